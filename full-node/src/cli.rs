@@ -64,9 +64,12 @@ pub struct CliOptionsRun {
     /// Chain to connect to ("Polkadot", "Kusama", "Westend", or a file path).
     #[arg(long, default_value = "polkadot")]
     pub chain: CliChain,
-    /// Output to stdout: auto, none, informant, logs, logs-json.
+    /// Output to stdout: auto, none, informant, informant-json, logs, logs-json.
     #[arg(long, default_value = "auto")]
     pub output: Output,
+    /// Number of seconds between two records printed when the output is `informant-json`.
+    #[arg(long, default_value = "5")]
+    pub informant_interval: u64,
     /// Log filter. Example: `foo=trace`
     #[arg(long)]
     pub log: Vec<String>,
@@ -85,6 +88,9 @@ pub struct CliOptionsRun {
     /// Bind point of the JSON-RPC server ("none" or `<ip>:<port>`).
     #[arg(long, default_value = "127.0.0.1:9944", value_parser = parse_json_rpc_address)]
     pub json_rpc_address: JsonRpcAddress,
+    /// Bind point of the HTTP JSON-RPC server ("none" or `<ip>:<port>`).
+    #[arg(long, default_value = "127.0.0.1:9933", value_parser = parse_json_rpc_address)]
+    pub json_rpc_http_address: JsonRpcAddress,
     /// List of secret phrases to insert in the keystore of the node. Used to author blocks.
     #[arg(long, value_parser = decode_sr25519_private_key)]
     // TODO: also automatically add the same keys through ed25519?
@@ -95,6 +101,17 @@ pub struct CliOptionsRun {
     /// Do not load or store anything on disk.
     #[arg(long)]
     pub tmp: bool,
+    /// Do not verify the signatures of the block authors. Only use with a chain whose blocks
+    /// are already known to be valid.
+    #[arg(long)]
+    pub skip_seal_verification: bool,
+    /// Maximum number of requests that require accessing the database that a single peer can
+    /// perform per second.
+    #[arg(long, default_value = "32")]
+    pub max_inbound_requests_per_peer_per_sec: u32,
+    /// Maximum number of bytes of responses to the requests of a single peer sent per second.
+    #[arg(long, default_value = "16777216")]
+    pub max_inbound_response_bytes_per_peer_per_sec: usize,
 }
 
 #[derive(Debug, clap::Parser)]
@@ -162,6 +179,9 @@ pub enum Output {
     Auto,
     None,
     Informant,
+    /// Prints on stdout a JSON record describing the state of the node at a regular interval.
+    /// Logs are printed on stderr, in the JSON format.
+    InformantJson,
     Logs,
     LogsJson,
 }
@@ -24,10 +24,14 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::run::{database_thread, jaeger_service, network_service};
+use crate::{database_thread, jaeger_service, network_service};
 
 use core::{num::NonZeroU32, ops};
-use futures::{lock::Mutex, prelude::*};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+};
 use hashbrown::HashSet;
 use smoldot::{
     author,
@@ -39,15 +43,24 @@ use smoldot::{
     libp2p,
     network::{self, protocol::BlockData},
     sync::all::{self, TrieEntryVersion},
+    verify,
 };
 use std::{
     collections::BTreeMap,
     iter,
     num::NonZeroU64,
     sync::Arc,
-    time::{Duration, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
+/// Maximum number of blocks passed to [`ConsensusService::import_block`] whose import is still
+/// in progress. Any additional block leads to [`ImportError::TooManyPending`].
+const MAX_PENDING_IMPORTS: usize = 64;
+
+/// Duration after which a block authored locally or passed to [`ConsensusService::import_block`]
+/// is discarded if it hasn't been imported, for example because its parent is never found.
+const LOCAL_BLOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
 /// Configuration for a [`ConsensusService`].
 pub struct Config<'a> {
     /// Closure that spawns background tasks.
@@ -65,6 +78,17 @@ pub struct Config<'a> {
     /// >           to compare against a known genesis hash and print a warning.
     pub genesis_block_hash: [u8; 32],
 
+    /// Hashes of blocks that are known to be invalid, as found in the chain specification.
+    pub bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed, as found in
+    /// the chain specification.
+    pub fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. See [`all::Config::skip_seal_verification`].
+    pub skip_seal_verification: bool,
+
     /// Stores of key to use for all block-production-related purposes.
     pub keystore: Arc<keystore::Keystore>,
 
@@ -109,12 +133,96 @@ pub struct SyncState {
     pub best_block_hash: [u8; 32],
     pub finalized_block_number: u64,
     pub finalized_block_hash: [u8; 32],
+    /// Specification version of the runtime of the best block. `None` if it isn't known yet.
+    pub best_block_runtime_spec_version: Option<u32>,
+}
+
+/// Block to pass to [`ConsensusService::import_block`].
+#[derive(Debug, Clone)]
+pub struct BlockToImport {
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+    /// List of SCALE-encoded extrinsics that compose the body of the block.
+    pub scale_encoded_extrinsics: Vec<Vec<u8>>,
+    /// List of justifications of the block, alongside with their consensus engine id. Can be
+    /// empty.
+    pub scale_encoded_justifications: Vec<([u8; 4], Vec<u8>)>,
+    /// `true` if the block is the best block of the node it has been received from, or if it
+    /// has been authored on top of the best block. Blocks are only considered as potential new
+    /// best blocks once they have been verified, whatever the value of this flag.
+    pub is_best: bool,
+}
+
+/// Successful outcome of [`ConsensusService::import_block`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ImportOutcome {
+    /// Block has been verified and added to the chain.
+    Imported {
+        /// `true` if the block has become the new best block.
+        is_new_best: bool,
+    },
+    /// Block was already part of the non-finalized chain. Nothing has been done.
+    AlreadyInChain,
+}
+
+/// Error potentially returned by [`ConsensusService::import_block`].
+#[derive(Debug, derive_more::Display)]
+pub enum ImportError {
+    /// Failed to decode the header of the block.
+    #[display(fmt = "Failed to decode header: {_0}")]
+    InvalidHeader(header::Error),
+    /// Block is older than the latest finalized block.
+    #[display(
+        fmt = "Block #{block_number} is older than the finalized block #{finalized_block_number}"
+    )]
+    TooOld {
+        /// Height of the block that was passed.
+        block_number: u64,
+        /// Height of the latest finalized block.
+        finalized_block_number: u64,
+    },
+    /// Block is known to not be a descendant of the latest finalized block.
+    #[display(fmt = "Block isn't a descendant of the finalized block")]
+    NotFinalizedChain,
+    /// Block can't be verified now and has been discarded by the syncing.
+    #[display(fmt = "Block has been discarded")]
+    Discarded,
+    /// The same block is already being imported.
+    #[display(fmt = "Block is already being imported")]
+    AlreadyInProgress,
+    /// Too many blocks are already being imported.
+    #[display(fmt = "Too many blocks are already being imported")]
+    TooManyPending,
+    /// The block couldn't be imported in time, for example because its parent is unknown.
+    #[display(fmt = "Timeout while importing the block")]
+    Timeout,
+    /// The header of the block has failed to verify.
+    #[display(fmt = "Header verification failed: {_0}")]
+    HeaderVerification(all::HeaderVerifyError),
+    /// The block has failed to verify.
+    #[display(fmt = "Block verification failed: {_0}")]
+    BlockVerification(all::BlockVerificationError),
+    /// The background task of the service has stopped.
+    #[display(fmt = "Consensus service has shut down")]
+    ServiceShutdown,
 }
 
 /// Background task that verifies blocks and emits requests.
 pub struct ConsensusService {
     /// State kept up-to-date with the background task.
     sync_state: Arc<Mutex<SyncState>>,
+
+    /// Channel to send messages to the background task.
+    to_background: mpsc::Sender<ToBackground>,
+}
+
+/// Message sent from the [`ConsensusService`] to the [`SyncBackground`].
+enum ToBackground {
+    /// See [`ConsensusService::import_block`].
+    ImportBlock {
+        block: BlockToImport,
+        result_tx: oneshot::Sender<Result<ImportOutcome, ImportError>>,
+    },
 }
 
 impl ConsensusService {
@@ -210,14 +318,18 @@ impl ConsensusService {
             best_block_hash,
             finalized_block_number,
             finalized_block_hash,
+            best_block_runtime_spec_version: None,
         }));
 
+        let (to_background, from_foreground) = mpsc::channel(8);
+
         // Spawn the background task that synchronizes blocks and updates the database.
         (config.tasks_executor)({
             let mut sync = all::AllSync::new(all::Config {
                 chain_information: finalized_chain_information,
                 block_number_bytes: config.block_number_bytes,
                 allow_unknown_consensus_engines: false,
+                skip_seal_verification: config.skip_seal_verification,
                 sources_capacity: 32,
                 blocks_capacity: {
                     // This is the maximum number of blocks between two consecutive justifications.
@@ -225,6 +337,14 @@ impl ConsensusService {
                 },
                 max_disjoint_headers: 1024,
                 max_requests_per_block: NonZeroU32::new(3).unwrap(),
+                bad_blocks: config.bad_blocks,
+                fork_blocks: config.fork_blocks,
+                clock: verify::Clock::new(|| {
+                    SystemTime::now()
+                        .duration_since(SystemTime::UNIX_EPOCH)
+                        .unwrap()
+                }),
+                clock_drift_tolerance: verify::DEFAULT_CLOCK_DRIFT_TOLERANCE,
                 download_ahead_blocks: {
                     // Assuming a verification speed of 1k blocks/sec and a 99th download time
                     // percentile of two second, the number of blocks to download ahead of time
@@ -233,6 +353,7 @@ impl ConsensusService {
                     // the chain and the machine of the user.
                     NonZeroU32::new(2000).unwrap()
                 },
+                warp_sync_min_distinct_sources: NonZeroU32::new(1).unwrap(),
                 full: Some(all::ConfigFull {
                     finalized_runtime: {
                         // Builds the runtime of the finalized block.
@@ -263,7 +384,8 @@ impl ConsensusService {
                 sync,
                 block_author_sync_source,
                 block_authoring: None,
-                authored_block: None,
+                local_blocks: LocalBlocks::new(),
+                from_foreground,
                 slot_duration_author_ratio: config.slot_duration_author_ratio,
                 keystore: config.keystore,
                 finalized_block_storage,
@@ -280,7 +402,10 @@ impl ConsensusService {
             Box::pin(background_sync.run())
         });
 
-        Arc::new(ConsensusService { sync_state })
+        Arc::new(ConsensusService {
+            sync_state,
+            to_background,
+        })
     }
 
     /// Returns a summary of the state of the service.
@@ -290,6 +415,31 @@ impl ConsensusService {
     pub async fn sync_state(&self) -> SyncState {
         self.sync_state.lock().await.clone()
     }
+
+    /// Submits a block, for example received through a gossiping protocol or authored by an
+    /// external block producer, for verification and import.
+    ///
+    /// The block goes through the same verification as blocks downloaded from the network. The
+    /// returned future yields once the block has been verified, or as soon as it is known that
+    /// it can't be imported.
+    ///
+    /// > **Note**: If the parent of the block isn't known yet, the block is kept aside and the
+    /// >           returned future only yields after the parent has been imported, or with
+    /// >           [`ImportError::Timeout`] if the block still hasn't been imported after
+    /// >           [`LOCAL_BLOCK_TIMEOUT`].
+    // TODO: not used by the node yet, will be used by block gossiping
+    #[allow(dead_code)]
+    pub async fn import_block(&self, block: BlockToImport) -> Result<ImportOutcome, ImportError> {
+        let (result_tx, result_rx) = oneshot::channel();
+
+        self.to_background
+            .clone()
+            .send(ToBackground::ImportBlock { block, result_tx })
+            .await
+            .map_err(|_| ImportError::ServiceShutdown)?;
+
+        result_rx.await.map_err(|_| ImportError::ServiceShutdown)?
+    }
 }
 
 struct SyncBackground {
@@ -330,10 +480,12 @@ struct SyncBackground {
     /// See [`Config::slot_duration_author_ratio`].
     slot_duration_author_ratio: u16,
 
-    /// After a block has been authored, it is inserted here while waiting for the `sync` to
-    /// import it. Contains the block height, the block hash, the SCALE-encoded block header, and
-    /// the list of SCALE-encoded extrinsics of the block.
-    authored_block: Option<(u64, [u8; 32], Vec<u8>, Vec<Vec<u8>>)>,
+    /// Blocks authored locally or passed to [`ConsensusService::import_block`] that are waiting
+    /// to be imported.
+    local_blocks: LocalBlocks,
+
+    /// Receiver for messages sent by the [`ConsensusService`].
+    from_foreground: mpsc::Receiver<ToBackground>,
 
     /// See [`Config::keystore`].
     keystore: Arc<keystore::Keystore>,
@@ -390,6 +542,131 @@ struct SyncBackground {
     jaeger_service: Arc<jaeger_service::JaegerService>,
 }
 
+/// Block waiting to be imported through [`SyncBackground::block_author_sync_source`].
+struct LocalBlock {
+    number: u64,
+    hash: [u8; 32],
+    scale_encoded_header: Vec<u8>,
+    scale_encoded_extrinsics: Vec<Vec<u8>>,
+    scale_encoded_justifications: Vec<([u8; 4], Vec<u8>)>,
+    /// Moment after which the block is discarded if it hasn't been requested by the `sync`.
+    deadline: Instant,
+}
+
+/// Blocks authored locally or passed to [`ConsensusService::import_block`].
+///
+/// After a block has been authored or passed to [`ConsensusService::import_block`], it is
+/// inserted here while waiting for the `sync` to request it from
+/// [`SyncBackground::block_author_sync_source`]. Blocks whose parent is unknown can stay here for
+/// a long time, which is why all the entries are discarded after [`LOCAL_BLOCK_TIMEOUT`].
+struct LocalBlocks {
+    /// Blocks not requested by the `sync` yet.
+    blocks: Vec<LocalBlock>,
+
+    /// For each block passed to [`ConsensusService::import_block`] and whose verification is
+    /// still pending, the sender to notify of the outcome and the moment when to give up.
+    waiters: hashbrown::HashMap<
+        [u8; 32],
+        (oneshot::Sender<Result<ImportOutcome, ImportError>>, Instant),
+        fnv::FnvBuildHasher,
+    >,
+}
+
+impl LocalBlocks {
+    fn new() -> Self {
+        LocalBlocks {
+            blocks: Vec::new(),
+            waiters: Default::default(),
+        }
+    }
+
+    /// Returns `true` if the block with the given hash is waiting to be imported.
+    fn contains(&self, hash: &[u8; 32]) -> bool {
+        self.waiters.contains_key(hash) || self.blocks.iter().any(|b| b.hash == *hash)
+    }
+
+    /// Inserts a locally-authored block.
+    fn insert_authored(&mut self, block: LocalBlock) {
+        self.blocks.push(block);
+    }
+
+    /// Returns `true` if [`MAX_PENDING_IMPORTS`] blocks passed to
+    /// [`ConsensusService::import_block`] are waiting to be imported.
+    fn is_full(&self) -> bool {
+        self.waiters.len() >= MAX_PENDING_IMPORTS
+    }
+
+    /// Inserts a block passed to [`ConsensusService::import_block`]. The outcome of the import
+    /// will be sent on `result_tx`.
+    ///
+    /// # Panic
+    ///
+    /// Panics if [`LocalBlocks::is_full`] returns `true`.
+    ///
+    fn insert_import(
+        &mut self,
+        block: LocalBlock,
+        result_tx: oneshot::Sender<Result<ImportOutcome, ImportError>>,
+    ) {
+        assert!(!self.is_full());
+        self.waiters.insert(block.hash, (result_tx, block.deadline));
+        self.blocks.push(block);
+    }
+
+    /// Returns `true` if the given request, targeting
+    /// [`SyncBackground::block_author_sync_source`], can be answered with one of the blocks.
+    fn has_matching(&self, request: &all::DesiredRequest) -> bool {
+        self.blocks
+            .iter()
+            .any(|block| local_block_matches(block, request))
+    }
+
+    /// Removes and returns the block that can answer the given request, if any.
+    fn take_matching(&mut self, request: &all::DesiredRequest) -> Option<LocalBlock> {
+        let index = self
+            .blocks
+            .iter()
+            .position(|block| local_block_matches(block, request))?;
+        Some(self.blocks.remove(index))
+    }
+
+    /// Removes the block with the given hash, and reports the given outcome to the caller of
+    /// [`ConsensusService::import_block`] if any.
+    fn finish(&mut self, hash: &[u8; 32], outcome: Result<ImportOutcome, ImportError>) {
+        self.blocks.retain(|block| block.hash != *hash);
+        if let Some((result_tx, _)) = self.waiters.remove(hash) {
+            let _ = result_tx.send(outcome);
+        }
+    }
+
+    /// Returns the earliest moment when [`LocalBlocks::expire`] has something to remove.
+    fn next_deadline(&self) -> Option<Instant> {
+        self.blocks
+            .iter()
+            .map(|block| block.deadline)
+            .chain(self.waiters.values().map(|(_, deadline)| *deadline))
+            .min()
+    }
+
+    /// Removes the blocks and waiters whose deadline is inferior or equal to `now`. The waiters
+    /// are notified with [`ImportError::Timeout`].
+    fn expire(&mut self, now: Instant) {
+        self.blocks.retain(|block| block.deadline > now);
+
+        let expired = self
+            .waiters
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(hash, _)| *hash)
+            .collect::<Vec<_>>();
+        for hash in expired {
+            log::debug!("import-block-timeout; hash={}", HashDisplay(&hash));
+            let (result_tx, _) = self.waiters.remove(&hash).unwrap();
+            let _ = result_tx.send(Err(ImportError::Timeout));
+        }
+    }
+}
+
 /// Information about a source in the sync state machine.
 #[derive(Debug, Clone)]
 struct NetworkSourceInfo {
@@ -411,6 +688,10 @@ impl SyncBackground {
                 let mut lock = self.sync_state.lock().await;
                 lock.best_block_hash = self.sync.best_block_hash();
                 lock.best_block_number = self.sync.best_block_number();
+                if let Some(storage) = self.sync.best_block_storage() {
+                    lock.best_block_runtime_spec_version =
+                        Some(storage.runtime().runtime_version().decode().spec_version);
+                }
             }
 
             // Creating the block authoring state and prepare a future that is ready when something
@@ -495,7 +776,21 @@ impl SyncBackground {
                 }
             };
 
+            // Future that is ready when a block authored locally or passed to
+            // `ConsensusService::import_block` must be discarded.
+            let mut local_blocks_expiration = match self.local_blocks.next_deadline() {
+                Some(deadline) => future::Either::Left(
+                    futures_timer::Delay::new(deadline.saturating_duration_since(Instant::now()))
+                        .fuse(),
+                ),
+                None => future::Either::Right(future::pending::<()>()),
+            };
+
             futures::select! {
+                () = local_blocks_expiration => {
+                    self.local_blocks.expire(Instant::now());
+                },
+
                 () = authoring_ready_future => {
                     // Ready to author a block. Call `author_block()`.
                     // While a block is being authored, the whole syncing state machine is
@@ -590,6 +885,14 @@ impl SyncBackground {
                     }
                 },
 
+                message = self.from_foreground.select_next_some() => {
+                    match message {
+                        ToBackground::ImportBlock { block, result_tx } => {
+                            self.import_block(block, result_tx);
+                        }
+                    }
+                },
+
                 (request_id, source_id, result) = self.block_requests_finished.select_next_some() => {
                     // `result` is an error if the block request got cancelled by the sync state
                     // machine.
@@ -856,13 +1159,92 @@ impl SyncBackground {
             | all::BlockAnnounceOutcome::InvalidHeader(_) => unreachable!(),
         }
 
-        debug_assert!(self.authored_block.is_none());
-        self.authored_block = Some((
-            parent_number + 1,
-            new_block_hash,
-            block.scale_encoded_header,
-            block.body,
-        ));
+        self.local_blocks.insert_authored(LocalBlock {
+            number: parent_number + 1,
+            hash: new_block_hash,
+            scale_encoded_header: block.scale_encoded_header,
+            scale_encoded_extrinsics: block.body,
+            scale_encoded_justifications: Vec::new(),
+            deadline: Instant::now() + LOCAL_BLOCK_TIMEOUT,
+        });
+    }
+
+    /// Starts importing a block passed to [`ConsensusService::import_block`]. The outcome is
+    /// sent on `result_tx` once known.
+    fn import_block(
+        &mut self,
+        block: BlockToImport,
+        result_tx: oneshot::Sender<Result<ImportOutcome, ImportError>>,
+    ) {
+        let number =
+            match header::decode(&block.scale_encoded_header, self.sync.block_number_bytes()) {
+                Ok(h) => h.number,
+                Err(err) => {
+                    let _ = result_tx.send(Err(ImportError::InvalidHeader(err)));
+                    return;
+                }
+            };
+        let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+
+        if self.local_blocks.contains(&hash) {
+            let _ = result_tx.send(Err(ImportError::AlreadyInProgress));
+            return;
+        }
+
+        if self.local_blocks.is_full() {
+            let _ = result_tx.send(Err(ImportError::TooManyPending));
+            return;
+        }
+
+        log::debug!(
+            "import-block; hash={}; height={}",
+            HashDisplay(&hash),
+            number
+        );
+
+        // Similar to locally-authored blocks, the block is imported by pretending that the local
+        // node is a source of blocks.
+        let outcome = match self.sync.block_announce(
+            self.block_author_sync_source,
+            block.scale_encoded_header.clone(),
+            block.is_best,
+        ) {
+            all::BlockAnnounceOutcome::HeaderVerify | all::BlockAnnounceOutcome::StoredForLater => {
+                None
+            }
+            all::BlockAnnounceOutcome::TooOld {
+                announce_block_height,
+                finalized_block_height,
+            } => Some(Err(ImportError::TooOld {
+                block_number: announce_block_height,
+                finalized_block_number: finalized_block_height,
+            })),
+            all::BlockAnnounceOutcome::AlreadyInChain => Some(Ok(ImportOutcome::AlreadyInChain)),
+            all::BlockAnnounceOutcome::NotFinalizedChain => {
+                Some(Err(ImportError::NotFinalizedChain))
+            }
+            all::BlockAnnounceOutcome::Discarded => Some(Err(ImportError::Discarded)),
+            all::BlockAnnounceOutcome::InvalidHeader(err) => {
+                Some(Err(ImportError::InvalidHeader(err)))
+            }
+        };
+
+        if let Some(outcome) = outcome {
+            let _ = result_tx.send(outcome);
+            return;
+        }
+
+        self.local_blocks.insert_import(
+            LocalBlock {
+                number,
+                hash,
+                scale_encoded_header: block.scale_encoded_header,
+                scale_encoded_extrinsics: block.scale_encoded_extrinsics,
+                scale_encoded_justifications: block.scale_encoded_justifications,
+                deadline: Instant::now() + LOCAL_BLOCK_TIMEOUT,
+            },
+            result_tx,
+        );
     }
 
     /// Starts all the new network requests that should be started.
@@ -884,30 +1266,8 @@ impl SyncBackground {
                         // Remote source.
                         self.sync.source_num_ongoing_requests(*source_id) == 0
                     } else {
-                        // Locally-authored blocks source.
-                        match (request_details, &self.authored_block) {
-                            (
-                                all::DesiredRequest::BlocksRequest {
-                                    first_block_hash: None,
-                                    first_block_height,
-                                    ..
-                                },
-                                Some((authored_height, _, _, _)),
-                            ) if first_block_height == authored_height => true,
-                            (
-                                all::DesiredRequest::BlocksRequest {
-                                    first_block_hash: Some(first_block_hash),
-                                    first_block_height,
-                                    ..
-                                },
-                                Some((authored_height, authored_hash, _, _)),
-                            ) if first_block_hash == authored_hash
-                                && first_block_height == authored_height =>
-                            {
-                                true
-                            }
-                            _ => false,
-                        }
+                        // Locally-provided blocks source.
+                        self.local_blocks.has_matching(request_details)
                     }
                 },
             ) {
@@ -923,12 +1283,11 @@ impl SyncBackground {
                 all::DesiredRequest::BlocksRequest { .. }
                     if source_id == self.block_author_sync_source =>
                 {
-                    log::debug!("queue-locally-authored-block-for-import");
+                    log::debug!("queue-local-block-for-import");
 
-                    let (_, block_hash, scale_encoded_header, scale_encoded_extrinsics) =
-                        self.authored_block.take().unwrap();
+                    let block = self.local_blocks.take_matching(&request_info).unwrap();
 
-                    let _jaeger_span = self.jaeger_service.block_import_queue_span(&block_hash);
+                    let _jaeger_span = self.jaeger_service.block_import_queue_span(&block.hash);
 
                     // Create a request that is immediately answered right below.
                     let request_id = self.sync.add_request(
@@ -941,9 +1300,9 @@ impl SyncBackground {
                     self.sync.blocks_request_response(
                         request_id,
                         Ok(iter::once(all::BlockRequestSuccessBlock {
-                            scale_encoded_header,
-                            scale_encoded_extrinsics,
-                            scale_encoded_justifications: Vec::new(),
+                            scale_encoded_header: block.scale_encoded_header,
+                            scale_encoded_extrinsics: block.scale_encoded_extrinsics,
+                            scale_encoded_justifications: block.scale_encoded_justifications,
                             user_data: (),
                         })),
                     );
@@ -1018,16 +1377,13 @@ impl SyncBackground {
         // If the state is one of the "verifying" states, perform the actual verification and
         // loop again until the sync is in an idle state.
         loop {
-            let unix_time = SystemTime::now()
-                .duration_since(SystemTime::UNIX_EPOCH)
-                .unwrap();
-
             match self.sync.process_one() {
                 all::ProcessOne::AllSync(idle) => {
                     self.sync = idle;
                     break;
                 }
                 all::ProcessOne::VerifyWarpSyncFragment(_)
+                | all::ProcessOne::WarpSyncBuildRuntime(_)
                 | all::ProcessOne::WarpSyncError { .. }
                 | all::ProcessOne::WarpSyncFinished { .. } => unreachable!(),
                 all::ProcessOne::VerifyBodyHeader(verify) => {
@@ -1037,7 +1393,7 @@ impl SyncBackground {
 
                     let _jaeger_span = self.jaeger_service.block_body_verify_span(&hash_to_verify);
 
-                    let mut verify = verify.start(unix_time, ());
+                    let mut verify = verify.start(());
                     // TODO: check this block against the chain spec's badBlocks
                     loop {
                         match verify {
@@ -1055,6 +1411,10 @@ impl SyncBackground {
                                     height_to_verify,
                                     error
                                 );
+                                self.local_blocks.finish(
+                                    &hash_to_verify,
+                                    Err(ImportError::BlockVerification(error)),
+                                );
                                 self.sync = sync_out;
                                 break;
                             }
@@ -1070,6 +1430,11 @@ impl SyncBackground {
 
                                 // Processing has made a step forward.
 
+                                self.local_blocks.finish(
+                                    &hash_to_verify,
+                                    Ok(ImportOutcome::Imported { is_new_best }),
+                                );
+
                                 if is_new_best {
                                     // Update the networking.
                                     let fut = self.network_service.set_local_best_block(
@@ -1157,6 +1522,12 @@ impl SyncBackground {
                                     .map(|(val, vers)| (&val[..], *vers));
                                 verify = req.inject_value(value);
                             }
+                            all::BlockVerification::FinalizedStorageNextKey(req)
+                                if req.child_trie().is_some() =>
+                            {
+                                // TODO: `finalized_block_storage` doesn't contain the child tries
+                                verify = req.inject_key(None::<&[u8]>);
+                            }
                             all::BlockVerification::FinalizedStorageNextKey(req) => {
                                 // TODO: to_vec() :-/ range() immediately calculates the range of keys so there's no borrowing issue, but the take_while needs to keep req borrowed, which isn't possible
                                 let req_key = req.key().as_ref().to_vec();
@@ -1170,6 +1541,12 @@ impl SyncBackground {
                                     .map(|(k, _)| k);
                                 verify = req.inject_key(next_key);
                             }
+                            all::BlockVerification::FinalizedStoragePrefixKeys(req)
+                                if req.child_trie().is_some() =>
+                            {
+                                // TODO: `finalized_block_storage` doesn't contain the child tries
+                                verify = req.inject_keys_ordered(iter::empty::<&[u8]>());
+                            }
                             all::BlockVerification::FinalizedStoragePrefixKeys(req) => {
                                 // TODO: to_vec() :-/ range() immediately calculates the range of keys so there's no borrowing issue, but the take_while needs to keep req borrowed, which isn't possible
                                 let prefix = req.prefix().as_ref().to_vec();
@@ -1183,6 +1560,14 @@ impl SyncBackground {
                                     .map(|(k, _)| k);
                                 verify = req.inject_keys_ordered(keys);
                             }
+                            all::BlockVerification::FinalizedChildStorageGet(req) => {
+                                // TODO: `finalized_block_storage` doesn't contain the child tries
+                                verify = req.inject_value(None);
+                            }
+                            all::BlockVerification::FinalizedChildStorageRoot(req) => {
+                                // TODO: `finalized_block_storage` doesn't contain the child tries
+                                verify = req.inject_keys_ordered(iter::empty::<&[u8]>());
+                            }
                             all::BlockVerification::RuntimeCompilation(rt) => {
                                 verify = rt.build();
                             }
@@ -1197,6 +1582,7 @@ impl SyncBackground {
                             all::FinalityProofVerifyOutcome::NewFinalized {
                                 finalized_blocks,
                                 updates_best_block,
+                                ..
                             },
                         ) => {
                             log::debug!("finality-proof-verification; outcome=success");
@@ -1293,7 +1679,7 @@ impl SyncBackground {
                         .jaeger_service
                         .block_header_verify_span(&hash_to_verify);
 
-                    match verify.perform(unix_time, ()) {
+                    match verify.perform(()) {
                         all::HeaderVerifyOutcome::Success { sync: sync_out, .. } => {
                             log::debug!(
                                 "header-verification; hash={}; height={}; outcome=success",
@@ -1312,6 +1698,11 @@ impl SyncBackground {
                             "header-verification; hash={}; height={}; outcome=failure; error={}",
                             HashDisplay(&hash_to_verify), height_to_verify, error
                         );
+                            // The block will never be requested from the local source.
+                            self.local_blocks.finish(
+                                &hash_to_verify,
+                                Err(ImportError::HeaderVerification(error)),
+                            );
                             self.sync = sync_out;
                             continue;
                         }
@@ -1324,6 +1715,24 @@ impl SyncBackground {
     }
 }
 
+/// Returns `true` if the given request, targeting [`SyncBackground::block_author_sync_source`],
+/// can be answered with the given block.
+fn local_block_matches(block: &LocalBlock, request: &all::DesiredRequest) -> bool {
+    match request {
+        all::DesiredRequest::BlocksRequest {
+            first_block_hash: None,
+            first_block_height,
+            ..
+        } => *first_block_height == block.number,
+        all::DesiredRequest::BlocksRequest {
+            first_block_hash: Some(first_block_hash),
+            first_block_height,
+            ..
+        } => *first_block_hash == block.hash && *first_block_height == block.number,
+        _ => false,
+    }
+}
+
 /// Writes blocks to the database
 async fn database_blocks(
     database: &database_thread::DatabaseThread,
@@ -1376,3 +1785,137 @@ async fn database_set_finalized(
         })
         .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        all, ImportError, ImportOutcome, LocalBlock, LocalBlocks, LOCAL_BLOCK_TIMEOUT,
+        MAX_PENDING_IMPORTS,
+    };
+    use core::num::NonZeroU64;
+    use futures::channel::oneshot;
+    use std::time::{Duration, Instant};
+
+    fn block(number: u64, hash: [u8; 32], deadline: Instant) -> LocalBlock {
+        LocalBlock {
+            number,
+            hash,
+            scale_encoded_header: Vec::new(),
+            scale_encoded_extrinsics: Vec::new(),
+            scale_encoded_justifications: Vec::new(),
+            deadline,
+        }
+    }
+
+    fn request(first_block_hash: Option<[u8; 32]>, first_block_height: u64) -> all::DesiredRequest {
+        all::DesiredRequest::BlocksRequest {
+            first_block_hash,
+            first_block_height,
+            ascending: false,
+            num_blocks: NonZeroU64::new(1).unwrap(),
+            request_headers: true,
+            request_bodies: true,
+            request_justification: true,
+        }
+    }
+
+    #[test]
+    fn import_finished() {
+        let mut local_blocks = LocalBlocks::new();
+        let deadline = Instant::now() + LOCAL_BLOCK_TIMEOUT;
+
+        let (tx, mut rx) = oneshot::channel();
+        local_blocks.insert_import(block(5, [5; 32], deadline), tx);
+        assert!(local_blocks.contains(&[5; 32]));
+        assert!(local_blocks.has_matching(&request(Some([5; 32]), 5)));
+        assert!(local_blocks.has_matching(&request(None, 5)));
+        assert!(!local_blocks.has_matching(&request(Some([6; 32]), 5)));
+        assert!(!local_blocks.has_matching(&request(None, 6)));
+
+        // The block is given to the sync but its waiter stays until the outcome is known.
+        let taken = local_blocks
+            .take_matching(&request(Some([5; 32]), 5))
+            .unwrap();
+        assert_eq!(taken.hash, [5; 32]);
+        assert!(!local_blocks.has_matching(&request(Some([5; 32]), 5)));
+        assert!(local_blocks.contains(&[5; 32]));
+        assert!(matches!(rx.try_recv(), Ok(None)));
+
+        local_blocks.finish(&[5; 32], Ok(ImportOutcome::Imported { is_new_best: false }));
+        assert!(!local_blocks.contains(&[5; 32]));
+        assert!(matches!(
+            rx.try_recv(),
+            Ok(Some(Ok(ImportOutcome::Imported { is_new_best: false })))
+        ));
+        assert!(local_blocks.next_deadline().is_none());
+    }
+
+    #[test]
+    fn orphan_blocks_time_out() {
+        let mut local_blocks = LocalBlocks::new();
+        let now = Instant::now();
+
+        let (tx, mut rx) = oneshot::channel();
+        local_blocks.insert_import(block(5, [5; 32], now + Duration::from_secs(10)), tx);
+        local_blocks.insert_authored(block(8, [8; 32], now + Duration::from_secs(20)));
+        assert_eq!(
+            local_blocks.next_deadline(),
+            Some(now + Duration::from_secs(10))
+        );
+
+        local_blocks.expire(now + Duration::from_secs(9));
+        assert!(local_blocks.contains(&[5; 32]));
+        assert!(matches!(rx.try_recv(), Ok(None)));
+
+        local_blocks.expire(now + Duration::from_secs(10));
+        assert!(!local_blocks.contains(&[5; 32]));
+        assert!(!local_blocks.has_matching(&request(None, 5)));
+        assert!(matches!(rx.try_recv(), Ok(Some(Err(ImportError::Timeout)))));
+        assert_eq!(
+            local_blocks.next_deadline(),
+            Some(now + Duration::from_secs(20))
+        );
+
+        local_blocks.expire(now + Duration::from_secs(20));
+        assert!(!local_blocks.contains(&[8; 32]));
+        assert!(local_blocks.next_deadline().is_none());
+    }
+
+    #[test]
+    fn waiter_of_block_given_to_sync_times_out() {
+        let mut local_blocks = LocalBlocks::new();
+        let now = Instant::now();
+
+        let (tx, mut rx) = oneshot::channel();
+        local_blocks.insert_import(block(5, [5; 32], now + Duration::from_secs(10)), tx);
+        assert!(local_blocks.take_matching(&request(None, 5)).is_some());
+
+        // The sync never reports the outcome of the verification.
+        local_blocks.expire(now + Duration::from_secs(10));
+        assert!(matches!(rx.try_recv(), Ok(Some(Err(ImportError::Timeout)))));
+        assert!(local_blocks.next_deadline().is_none());
+    }
+
+    #[test]
+    fn pending_imports_bounded() {
+        let mut local_blocks = LocalBlocks::new();
+        let deadline = Instant::now() + LOCAL_BLOCK_TIMEOUT;
+
+        let mut receivers = Vec::new();
+        for n in 0..MAX_PENDING_IMPORTS {
+            assert!(!local_blocks.is_full());
+            let (tx, rx) = oneshot::channel();
+            let hash = [u8::try_from(n).unwrap(); 32];
+            local_blocks.insert_import(block(1, hash, deadline), tx);
+            receivers.push(rx);
+        }
+        assert!(local_blocks.is_full());
+
+        // Locally-authored blocks don't count towards the limit.
+        local_blocks.insert_authored(block(2, [0xff; 32], deadline));
+        assert!(local_blocks.is_full());
+
+        local_blocks.finish(&[0; 32], Err(ImportError::Discarded));
+        assert!(!local_blocks.is_full());
+    }
+}
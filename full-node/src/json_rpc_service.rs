@@ -0,0 +1,238 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use futures::{channel::oneshot, prelude::*};
+use smoldot::json_rpc::{self, http_server, methods, websocket_server};
+use std::{io, net::SocketAddr};
+
+/// Configuration for a [`JsonRpcService`].
+pub struct Config<'a> {
+    /// Closure that spawns background tasks.
+    pub tasks_executor: &'a mut dyn FnMut(future::BoxFuture<'static, ()>),
+
+    /// Where to bind the WebSocket server. `None` if no WebSocket server should be started.
+    pub bind_address: Option<SocketAddr>,
+
+    /// Where to bind the HTTP server. `None` if no HTTP server should be started.
+    pub http_bind_address: Option<SocketAddr>,
+}
+
+/// Running JSON-RPC service. Holds a server open for as long as it is alive.
+pub struct JsonRpcService {
+    /// As long as this value is alive, the background server continues running.
+    _server_keep_alive: oneshot::Sender<()>,
+}
+
+impl JsonRpcService {
+    /// Initializes a new [`JsonRpcService`].
+    pub async fn new(config: Config<'_>) -> Result<Self, InitError> {
+        let server = if let Some(bind_address) = config.bind_address {
+            let result = websocket_server::WsServer::new(websocket_server::Config {
+                bind_address,
+                capacity: 1,
+                max_frame_size: 4096,
+                send_buffer_len: 16384,
+            })
+            .await;
+
+            match result {
+                Ok(server) => Some(server),
+                Err(error) => {
+                    return Err(InitError::ListenError {
+                        bind_address,
+                        error,
+                    })
+                }
+            }
+        } else {
+            None
+        };
+
+        let http_server = if let Some(bind_address) = config.http_bind_address {
+            let result = http_server::HttpServer::new(http_server::Config {
+                bind_address,
+                capacity: 1,
+                max_request_body_size: 1024 * 1024,
+            })
+            .await;
+
+            match result {
+                Ok(server) => Some(server),
+                Err(error) => {
+                    return Err(InitError::ListenError {
+                        bind_address,
+                        error,
+                    })
+                }
+            }
+        } else {
+            None
+        };
+
+        let (_server_keep_alive, client_still_alive) = oneshot::channel();
+
+        let background = JsonRpcBackground {
+            server,
+            http_server,
+            client_still_alive: client_still_alive.fuse(),
+        };
+
+        (config.tasks_executor)(async move { background.run().await }.boxed());
+        Ok(JsonRpcService { _server_keep_alive })
+    }
+}
+
+/// Error potentially returned by [`JsonRpcService::new`].
+#[derive(Debug, derive_more::Display)]
+pub enum InitError {
+    /// Failed to listen on the server address.
+    #[display(fmt = "Failed to listen on TCP address {bind_address}: {error}")]
+    ListenError {
+        /// Address that was attempted.
+        bind_address: SocketAddr,
+        /// Error returned by the operating system.
+        error: io::Error,
+    },
+}
+
+struct JsonRpcBackground {
+    /// State machine of the WebSocket server. Holds the TCP socket. `None` if disabled.
+    server: Option<websocket_server::WsServer<SocketAddr>>,
+
+    /// State machine of the HTTP server. Holds the TCP socket. `None` if disabled.
+    http_server: Option<http_server::HttpServer<SocketAddr>>,
+
+    /// As long as this channel is pending, the frontend of the JSON-RPC server is still alive.
+    client_still_alive: future::Fuse<oneshot::Receiver<()>>,
+}
+
+impl JsonRpcBackground {
+    async fn run(mut self) {
+        loop {
+            let ws_event = async {
+                match self.server.as_mut() {
+                    Some(server) => server.next_event().await,
+                    None => future::pending().await,
+                }
+            };
+
+            let http_event = async {
+                match self.http_server.as_mut() {
+                    Some(server) => server.next_event().await,
+                    None => future::pending().await,
+                }
+            };
+
+            futures::select! {
+                _ = &mut self.client_still_alive => return,
+                event = ws_event.fuse() => {
+                    match event {
+                        websocket_server::Event::ConnectionOpen { address, .. } => {
+                            log::debug!("incoming-connection; address={}", address);
+                            self.server.as_mut().unwrap().accept(address);
+                        }
+                        websocket_server::Event::ConnectionError {
+                            user_data: address, ..
+                        } => {
+                            log::debug!("connection-closed; address={}", address);
+                        }
+                        websocket_server::Event::TextFrame {
+                            connection_id,
+                            message,
+                            ..
+                        } => {
+                            let server = self.server.as_mut().unwrap();
+                            match handle_request(&message) {
+                                Some(response) => server.queue_send(connection_id, response),
+                                None => {
+                                    log::debug!("bad-request; message={:?}", message);
+                                    server.close(connection_id);
+                                }
+                            }
+                        }
+                    }
+                },
+                event = http_event.fuse() => {
+                    match event {
+                        http_server::Event::ConnectionOpen { address } => {
+                            log::debug!("incoming-http-connection; address={}", address);
+                            self.http_server.as_mut().unwrap().accept(address);
+                        }
+                        http_server::Event::ConnectionClosed { .. } => {}
+                        http_server::Event::Request {
+                            request_id, body, ..
+                        } => {
+                            let response = handle_request(&body).unwrap_or_else(|| {
+                                log::debug!("bad-request; message={:?}", body);
+                                json_rpc::parse::build_error_response(
+                                    "null",
+                                    json_rpc::parse::ErrorResponse::ParseError,
+                                    None,
+                                )
+                            });
+                            self.http_server.as_mut().unwrap().respond(request_id, response);
+                        }
+                    }
+                },
+            }
+        }
+    }
+}
+
+/// Processes a JSON-RPC request, which can be either a single call or a batch of calls, and
+/// returns the response to send back.
+///
+/// Returns `None` if the request is malformed.
+fn handle_request(request: &str) -> Option<String> {
+    match json_rpc::parse::parse_request(request) {
+        Ok(json_rpc::parse::Request::Single(call)) => handle_call(call),
+        Ok(json_rpc::parse::Request::Batch(calls)) if calls.is_empty() => None,
+        Ok(json_rpc::parse::Request::Batch(calls)) => Some(json_rpc::parse::build_batch_response(
+            calls.into_iter().map(|call| {
+                handle_call(call).unwrap_or_else(|| {
+                    json_rpc::parse::build_error_response(
+                        "null",
+                        json_rpc::parse::ErrorResponse::InvalidRequest,
+                        None,
+                    )
+                })
+            }),
+        )),
+        Err(_) => None,
+    }
+}
+
+/// Processes a single JSON-RPC call and returns the response to send back.
+///
+/// Returns `None` if the call is malformed.
+fn handle_call(call: &str) -> Option<String> {
+    let (request_id, _method) = match methods::parse_json_call(call) {
+        Ok(v) => v,
+        Err(error) => {
+            log::debug!("bad-call; error={:?}; call={:?}", error, call);
+            return None;
+        }
+    };
+
+    log::debug!("request; request_id={:?}; method={:?}", request_id, _method);
+
+    Some(json_rpc::parse::build_error_response(
+        request_id,
+        json_rpc::parse::ErrorResponse::ServerError(-32000, "Not implemented in smoldot yet"),
+        None,
+    ))
+}
@@ -0,0 +1,907 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Smoldot full node, usable as a library.
+//!
+//! Call [`FullNode::start`] with a [`Config`] in order to open the databases of the chain (and
+//! of its relay chain if it is a parachain), start the networking, the synchronization and the
+//! verification of blocks, and the JSON-RPC servers. All these subsystems run in the background
+//! for as long as the returned [`FullNode`] is alive.
+//!
+//! This is the same code as the one used by the smoldot full node binary. The binary only adds
+//! on top of it the command line parsing, the logging setup, and the informant.
+//!
+//! Programs that monitor the node can use [`FullNode::informant_records`] in order to
+//! periodically receive a machine-readable summary of its state.
+//!
+//! > **Note**: The full node doesn't maintain a transactions pool yet.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # async fn foo(chain_spec_json: &[u8]) {
+//! let threads_pool = futures::executor::ThreadPool::new().unwrap();
+//!
+//! let full_node = smoldot_full_node::FullNode::start(smoldot_full_node::Config {
+//!     chain: smoldot_full_node::ChainConfig {
+//!         chain_spec: smoldot::chain_spec::ChainSpec::from_json_bytes(chain_spec_json).unwrap(),
+//!         additional_bootnodes: Vec::new(),
+//!         keystore_memory: Vec::new(),
+//!         database_path: None,
+//!         keystore_path: None,
+//!         role: None,
+//!         skip_seal_verification: false,
+//!     },
+//!     relay_chain: None,
+//!     libp2p_key: Box::new(rand::random()),
+//!     listen_addresses: Vec::new(),
+//!     json_rpc_address: Some("127.0.0.1:9944".parse().unwrap()),
+//!     json_rpc_http_address: None,
+//!     jaeger_agent: None,
+//!     show_database_opening_progress: false,
+//!     max_inbound_requests_per_peer_per_sec: 32,
+//!     max_inbound_response_bytes_per_peer_per_sec: 16 * 1024 * 1024,
+//!     tasks_executor: &mut |task| threads_pool.spawn_ok(task),
+//! })
+//! .await
+//! .unwrap();
+//!
+//! println!("best block: #{}", full_node.sync_state().await.best_block_number);
+//! # }
+//! ```
+
+#![deny(rustdoc::broken_intra_doc_links)]
+
+use futures::{channel::oneshot, lock::Mutex, prelude::*};
+use smoldot::{
+    chain, chain_spec,
+    database::full_sqlite,
+    executor, header,
+    identity::keystore,
+    informant::HashDisplay,
+    libp2p::{connection, multiaddr::Multiaddr, peer_id, PeerId},
+};
+use std::{io, iter, mem, net::SocketAddr, path::PathBuf, sync::Arc, thread, time::Duration};
+
+mod consensus_service;
+mod database_thread;
+mod jaeger_service;
+mod json_rpc_service;
+mod network_service;
+
+pub use consensus_service::{BlockToImport, ImportError, ImportOutcome, SyncState};
+pub use smoldot::network::protocol::Role;
+
+/// Configuration for a [`FullNode`].
+pub struct Config<'a> {
+    /// Chain to connect to.
+    pub chain: ChainConfig,
+
+    /// Relay chain to connect to. Must be `Some` if and only if [`Config::chain`] is a
+    /// parachain, in which case this is the relay chain of that parachain.
+    pub relay_chain: Option<ChainConfig>,
+
+    /// Ed25519 private key of the network identity of the node.
+    pub libp2p_key: Box<[u8; 32]>,
+
+    /// Addresses to listen for incoming networking connections.
+    pub listen_addresses: Vec<Multiaddr>,
+
+    /// Where to bind the WebSocket JSON-RPC server. `None` if no WebSocket server should be
+    /// started.
+    pub json_rpc_address: Option<SocketAddr>,
+
+    /// Where to bind the HTTP JSON-RPC server. `None` if no HTTP server should be started.
+    pub json_rpc_http_address: Option<SocketAddr>,
+
+    /// Address of a Jaeger agent to send traces to. `None` to not send traces.
+    pub jaeger_agent: Option<SocketAddr>,
+
+    /// If `true`, a small progress indicator is printed on stderr while the databases are being
+    /// opened, which can take a long time.
+    pub show_database_opening_progress: bool,
+
+    /// Maximum number of incoming requests that require accessing the database, such as block
+    /// requests, that a single peer can perform per second. Requests above this limit are
+    /// refused.
+    pub max_inbound_requests_per_peer_per_sec: u32,
+
+    /// Maximum number of bytes of responses to incoming requests that can be sent to a single
+    /// peer per second. Requests received after this limit has been reached are refused.
+    pub max_inbound_response_bytes_per_peer_per_sec: usize,
+
+    /// Closure that spawns background tasks.
+    pub tasks_executor: &'a mut dyn FnMut(future::BoxFuture<'static, ()>),
+}
+
+/// Configuration of one chain of the [`FullNode`].
+pub struct ChainConfig {
+    /// Specification of the chain.
+    pub chain_spec: chain_spec::ChainSpec,
+
+    /// Nodes to try to connect to on startup, in addition to the ones found in the chain
+    /// specification.
+    pub additional_bootnodes: Vec<(PeerId, Multiaddr)>,
+
+    /// List of sr25519 private keys to insert in the in-memory keystore of the node. Used to
+    /// author blocks.
+    pub keystore_memory: Vec<[u8; 64]>,
+
+    /// Path to the directory of the database. `None` to store the database in memory, meaning
+    /// that everything is lost when the node stops.
+    pub database_path: Option<PathBuf>,
+
+    /// Path to the directory where the keys of the keystore are stored. `None` to not store any
+    /// key on disk.
+    pub keystore_path: Option<PathBuf>,
+
+    /// Role advertised to the peers of the chain, in particular in the block announces
+    /// handshakes. If `None`, [`Role::Authority`] is advertised if the keystore contains any
+    /// key, and [`Role::Full`] otherwise.
+    pub role: Option<Role>,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. The blocks are still executed.
+    /// See [`smoldot::sync::all::Config::skip_seal_verification`].
+    ///
+    /// > **Note**: This is meant to be used when benchmarking, or when importing a chain whose
+    /// >           blocks are already known to be valid. Passing `true` makes it possible for
+    /// >           anyone to author blocks that are considered as valid.
+    pub skip_seal_verification: bool,
+}
+
+/// Running full node. All the background tasks are stopped when it is destroyed.
+pub struct FullNode {
+    /// Identity of the node on the peer-to-peer network.
+    local_peer_id: PeerId,
+
+    /// Synchronization and block production of [`Config::chain`].
+    consensus_service: Arc<consensus_service::ConsensusService>,
+
+    /// Synchronization of [`Config::relay_chain`], if any.
+    relay_chain_consensus_service: Option<Arc<consensus_service::ConsensusService>>,
+
+    /// Networking of all the chains. The chain at index 0 is [`Config::chain`], and the
+    /// chain at index 1, if any, is [`Config::relay_chain`].
+    network_service: Arc<network_service::NetworkService>,
+
+    /// Highest block number of [`Config::chain`] reported by the peers of the network. Updated
+    /// by a background task.
+    network_known_best: Arc<Mutex<Option<u64>>>,
+
+    /// Name of [`Config::chain`], as found in its specification.
+    chain_name: Arc<str>,
+
+    /// Name of [`Config::relay_chain`], if any, as found in its specification.
+    relay_chain_name: Option<Arc<str>>,
+
+    /// JSON-RPC servers. Only need to be kept alive in order to function.
+    _json_rpc_service: Option<json_rpc_service::JsonRpcService>,
+}
+
+impl FullNode {
+    /// Starts all the subsystems of the full node.
+    ///
+    /// # Panic
+    ///
+    /// Panics if a database can't be opened or doesn't match its chain specification.
+    ///
+    pub async fn start(config: Config<'_>) -> Result<Self, StartError> {
+        // TODO: don't panic when opening the databases fails
+        let chain_spec = config.chain.chain_spec;
+        let chain_name = Arc::<str>::from(chain_spec.name());
+
+        let genesis_chain_information = chain_spec
+            .as_chain_information()
+            .map_err(StartError::InvalidChainSpec)?
+            .0;
+
+        // If the chain is a parachain, the specification of its relay chain must be provided.
+        let mut relay_chain = match (chain_spec.relay_chain(), config.relay_chain) {
+            (Some(_), Some(relay_chain)) => {
+                // Make sure we're not accidentally opening the same chain twice, otherwise weird
+                // interactions will happen.
+                if relay_chain.chain_spec.id() == chain_spec.id() {
+                    return Err(StartError::RelayChainSameAsChain);
+                }
+
+                let genesis_chain_information = relay_chain
+                    .chain_spec
+                    .as_chain_information()
+                    .map_err(StartError::InvalidRelayChainSpec)?
+                    .0;
+
+                Some((relay_chain, genesis_chain_information))
+            }
+            (None, None) => None,
+            (Some(_), None) => return Err(StartError::MissingRelayChain),
+            (None, Some(_)) => return Err(StartError::UnexpectedRelayChain),
+        };
+        let relay_chain_name = relay_chain
+            .as_ref()
+            .map(|(relay_chain, _)| Arc::<str>::from(relay_chain.chain_spec.name()));
+
+        let (database, database_existed) = {
+            let (db, existed) = open_database(
+                &chain_spec,
+                genesis_chain_information.as_ref(),
+                config.chain.database_path,
+                config.show_database_opening_progress,
+            )
+            .await;
+
+            (Arc::new(database_thread::DatabaseThread::from(db)), existed)
+        };
+
+        let relay_chain_database =
+            if let Some((relay_chain, relay_genesis_chain_information)) = &relay_chain {
+                Some(Arc::new(database_thread::DatabaseThread::from(
+                    open_database(
+                        &relay_chain.chain_spec,
+                        relay_genesis_chain_information.as_ref(),
+                        relay_chain.database_path.clone(),
+                        config.show_database_opening_progress,
+                    )
+                    .await
+                    .0,
+                )))
+            } else {
+                None
+            };
+
+        let database_finalized_block_hash = database
+            .with_database(|db| db.finalized_block_hash().unwrap())
+            .await;
+        let database_finalized_block_number = header::decode(
+            &database
+                .with_database(move |db| {
+                    db.block_scale_encoded_header(&database_finalized_block_hash)
+                        .unwrap()
+                        .unwrap()
+                })
+                .await,
+            chain_spec.block_number_bytes().into(),
+        )
+        .unwrap()
+        .number;
+
+        let noise_key = connection::NoiseKey::new(&config.libp2p_key);
+        let local_peer_id =
+            peer_id::PublicKey::Ed25519(*noise_key.libp2p_public_ed25519_key()).into_peer_id();
+
+        let genesis_block_hash = genesis_chain_information
+            .as_ref()
+            .finalized_block_header
+            .hash(chain_spec.block_number_bytes().into());
+
+        let jaeger_service = jaeger_service::JaegerService::new(jaeger_service::Config {
+            tasks_executor: &mut *config.tasks_executor,
+            service_name: local_peer_id.to_string(),
+            jaeger_agent: config.jaeger_agent,
+        })
+        .await
+        .map_err(StartError::JaegerInit)?;
+
+        let keystore =
+            open_keystore(config.chain.keystore_path, config.chain.keystore_memory).await?;
+        let relay_chain_keystore = if let Some((relay_chain, _)) = &mut relay_chain {
+            Some(
+                open_keystore(
+                    relay_chain.keystore_path.take(),
+                    mem::take(&mut relay_chain.keystore_memory),
+                )
+                .await?,
+            )
+        } else {
+            None
+        };
+
+        let (network_service, network_events_receivers) =
+            network_service::NetworkService::new(network_service::Config {
+                listen_addresses: config.listen_addresses,
+                num_events_receivers: 2 + if relay_chain.is_some() { 1 } else { 0 },
+                chains: iter::once(
+                    network_chain_config(
+                        &chain_spec,
+                        genesis_chain_information.as_ref(),
+                        &database,
+                        config.chain.additional_bootnodes,
+                        advertised_role(config.chain.role, &keystore).await,
+                    )
+                    .await?,
+                )
+                .chain(
+                    if let Some((relay_chain, relay_genesis_chain_information)) = &relay_chain {
+                        Some(
+                            network_chain_config(
+                                &relay_chain.chain_spec,
+                                relay_genesis_chain_information.as_ref(),
+                                relay_chain_database.as_ref().unwrap(),
+                                relay_chain.additional_bootnodes.clone(),
+                                advertised_role(
+                                    relay_chain.role,
+                                    relay_chain_keystore.as_ref().unwrap(),
+                                )
+                                .await,
+                            )
+                            .await?,
+                        )
+                    } else {
+                        None
+                    }
+                    .into_iter(),
+                )
+                .collect(),
+                noise_key,
+                tasks_executor: &mut *config.tasks_executor,
+                jaeger_service: jaeger_service.clone(),
+                max_inbound_requests_per_peer_per_sec: config.max_inbound_requests_per_peer_per_sec,
+                max_inbound_response_bytes_per_peer_per_sec: config
+                    .max_inbound_response_bytes_per_peer_per_sec,
+            })
+            .await
+            .map_err(StartError::NetworkInit)?;
+
+        let mut network_events_receivers = network_events_receivers.into_iter();
+
+        let consensus_service =
+            consensus_service::ConsensusService::new(consensus_service::Config {
+                tasks_executor: &mut *config.tasks_executor,
+                genesis_block_hash,
+                network_events_receiver: network_events_receivers.next().unwrap(),
+                network_service: (network_service.clone(), 0),
+                database,
+                block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+                bad_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec.fork_blocks().map(|(n, h)| (n, *h)).collect(),
+                skip_seal_verification: config.chain.skip_seal_verification,
+                keystore,
+                jaeger_service: jaeger_service.clone(),
+                slot_duration_author_ratio: 43691_u16,
+            })
+            .await;
+
+        let relay_chain_consensus_service =
+            if let Some((relay_chain, relay_genesis_chain_information)) = relay_chain {
+                let keystore = relay_chain_keystore.unwrap();
+                let block_number_bytes = usize::from(relay_chain.chain_spec.block_number_bytes());
+
+                Some(
+                    consensus_service::ConsensusService::new(consensus_service::Config {
+                        tasks_executor: &mut *config.tasks_executor,
+                        genesis_block_hash: relay_genesis_chain_information
+                            .as_ref()
+                            .finalized_block_header
+                            .hash(block_number_bytes),
+                        network_events_receiver: network_events_receivers.next().unwrap(),
+                        network_service: (network_service.clone(), 1),
+                        database: relay_chain_database.unwrap(),
+                        block_number_bytes,
+                        bad_blocks: relay_chain
+                            .chain_spec
+                            .bad_blocks_hashes()
+                            .copied()
+                            .collect(),
+                        fork_blocks: relay_chain
+                            .chain_spec
+                            .fork_blocks()
+                            .map(|(n, h)| (n, *h))
+                            .collect(),
+                        skip_seal_verification: relay_chain.skip_seal_verification,
+                        keystore,
+                        jaeger_service, // TODO: consider passing a different jaeger service with a different service name
+                        slot_duration_author_ratio: 43691_u16,
+                    })
+                    .await,
+                )
+            } else {
+                None
+            };
+
+        // Start the JSON-RPC service.
+        // It only needs to be kept alive in order to function.
+        let json_rpc_service =
+            if config.json_rpc_address.is_some() || config.json_rpc_http_address.is_some() {
+                Some(
+                    json_rpc_service::JsonRpcService::new(json_rpc_service::Config {
+                        tasks_executor: &mut *config.tasks_executor,
+                        bind_address: config.json_rpc_address,
+                        http_bind_address: config.json_rpc_http_address,
+                    })
+                    .await
+                    .map_err(StartError::JsonRpcServiceInit)?,
+                )
+            } else {
+                None
+            };
+
+        // Keep track of the highest block announced by the peers of the main chain.
+        let network_known_best = Arc::new(Mutex::new(None));
+        (config.tasks_executor)({
+            let network_known_best = network_known_best.clone();
+            let mut network_events_receiver = network_events_receivers.next().unwrap();
+            debug_assert!(network_events_receivers.next().is_none());
+
+            Box::pin(async move {
+                while let Some(network_event) = network_events_receiver.next().await {
+                    let number = match network_event {
+                        network_service::Event::BlockAnnounce {
+                            chain_index: 0,
+                            header,
+                            ..
+                        } => header.number,
+                        network_service::Event::Connected {
+                            chain_index: 0,
+                            best_block_number,
+                            ..
+                        } => best_block_number,
+                        _ => continue,
+                    };
+
+                    let mut network_known_best = network_known_best.lock().await;
+                    match *network_known_best {
+                        Some(n) if n >= number => {}
+                        _ => *network_known_best = Some(number),
+                    }
+                }
+            })
+        });
+
+        log::info!(
+            "successful-initialization; local_peer_id={}; database_is_new={:?}; \
+            finalized_block_hash={}; finalized_block_number={}",
+            local_peer_id,
+            !database_existed,
+            HashDisplay(&database_finalized_block_hash),
+            database_finalized_block_number,
+        );
+
+        Ok(FullNode {
+            local_peer_id,
+            consensus_service,
+            relay_chain_consensus_service,
+            network_service,
+            network_known_best,
+            chain_name,
+            relay_chain_name,
+            _json_rpc_service: json_rpc_service,
+        })
+    }
+
+    /// Returns the identity of the node on the peer-to-peer network.
+    pub fn local_peer_id(&self) -> &PeerId {
+        &self.local_peer_id
+    }
+
+    /// Returns a summary of the state of the synchronization of the chain.
+    pub async fn sync_state(&self) -> SyncState {
+        self.consensus_service.sync_state().await
+    }
+
+    /// Returns a summary of the state of the synchronization of the relay chain, or `None` if
+    /// the chain isn't a parachain.
+    pub async fn relay_chain_sync_state(&self) -> Option<SyncState> {
+        match &self.relay_chain_consensus_service {
+            Some(service) => Some(service.sync_state().await),
+            None => None,
+        }
+    }
+
+    /// Verifies the given block and adds it to the chain. See [`BlockToImport`].
+    pub async fn import_block(&self, block: BlockToImport) -> Result<ImportOutcome, ImportError> {
+        self.consensus_service.import_block(block).await
+    }
+
+    /// Returns the number of peers the node is connected to on the chain.
+    pub async fn num_peers(&self) -> usize {
+        self.network_service.num_peers(0).await
+    }
+
+    /// Returns the number of networking connections that are fully established, all chains
+    /// included.
+    pub async fn num_network_connections(&self) -> usize {
+        self.network_service.num_established_connections().await
+    }
+
+    /// Returns the total number of bytes received and sent on the network since the node has
+    /// started.
+    pub fn total_bytes_received_sent(&self) -> (u64, u64) {
+        self.network_service.total_bytes_received_sent()
+    }
+
+    /// Returns the highest block number of the chain reported by the peers of the node, or
+    /// `None` if no peer has reported anything yet.
+    pub async fn network_known_best(&self) -> Option<u64> {
+        *self.network_known_best.lock().await
+    }
+
+    /// Returns a stream that yields a summary of the state of the node every `interval`, starting
+    /// immediately.
+    ///
+    /// Each item is a single-line JSON object, as generated by
+    /// [`smoldot::informant::InformantRecord`]. The stream never ends, and keeps the background
+    /// services of the node alive for as long as it isn't destroyed.
+    pub fn informant_records(
+        &self,
+        interval: Duration,
+    ) -> impl Stream<Item = String> + Send + 'static {
+        let consensus_service = self.consensus_service.clone();
+        let relay_chain_consensus_service = self.relay_chain_consensus_service.clone();
+        let network_service = self.network_service.clone();
+        let network_known_best = self.network_known_best.clone();
+        let chain_name = self.chain_name.clone();
+        let relay_chain_name = self.relay_chain_name.clone();
+
+        stream::unfold(true, move |is_first| {
+            let consensus_service = consensus_service.clone();
+            let relay_chain_consensus_service = relay_chain_consensus_service.clone();
+            let network_service = network_service.clone();
+            let network_known_best = network_known_best.clone();
+            let chain_name = chain_name.clone();
+            let relay_chain_name = relay_chain_name.clone();
+
+            async move {
+                if !is_first {
+                    futures_timer::Delay::new(interval).await;
+                }
+
+                let sync_state = consensus_service.sync_state().await;
+                let relay_chain_best_number = match &relay_chain_consensus_service {
+                    Some(service) => Some(service.sync_state().await.best_block_number),
+                    None => None,
+                };
+                let (total_bytes_received, total_bytes_sent) =
+                    network_service.total_bytes_received_sent();
+
+                let record = smoldot::informant::InformantRecord {
+                    chain_name: &chain_name,
+                    relay_chain: relay_chain_name
+                        .as_deref()
+                        .zip(relay_chain_best_number)
+                        .map(|(chain_name, best_number)| smoldot::informant::RelayChain {
+                            chain_name,
+                            best_number,
+                        }),
+                    num_peers: u64::try_from(network_service.num_peers(0).await)
+                        .unwrap_or(u64::max_value()),
+                    num_network_connections: u64::try_from(
+                        network_service.num_established_connections().await,
+                    )
+                    .unwrap_or(u64::max_value()),
+                    network_known_best: *network_known_best.lock().await,
+                    best_number: sync_state.best_block_number,
+                    best_hash: &sync_state.best_block_hash,
+                    finalized_number: sync_state.finalized_block_number,
+                    finalized_hash: &sync_state.finalized_block_hash,
+                    total_bytes_received: Some(total_bytes_received),
+                    total_bytes_sent: Some(total_bytes_sent),
+                    runtime_spec_version: sync_state.best_block_runtime_spec_version,
+                }
+                .to_string();
+
+                Some((record, false))
+            }
+        })
+    }
+}
+
+/// Error potentially returned by [`FullNode::start`].
+#[derive(Debug, derive_more::Display)]
+pub enum StartError {
+    /// Failed to build the genesis block of the chain from its specification.
+    #[display(fmt = "Failed to build genesis of chain: {_0}")]
+    InvalidChainSpec(chain_spec::FromGenesisStorageError),
+    /// Failed to build the genesis block of the relay chain from its specification.
+    #[display(fmt = "Failed to build genesis of relay chain: {_0}")]
+    InvalidRelayChainSpec(chain_spec::FromGenesisStorageError),
+    /// The chain is a parachain but [`Config::relay_chain`] is `None`.
+    #[display(fmt = "Chain is a parachain but no relay chain has been provided")]
+    MissingRelayChain,
+    /// The chain isn't a parachain but [`Config::relay_chain`] is `Some`.
+    #[display(fmt = "Chain isn't a parachain but a relay chain has been provided")]
+    UnexpectedRelayChain,
+    /// The relay chain has the same identifier as the chain.
+    #[display(fmt = "Relay chain has the same identifier as the chain")]
+    RelayChainSameAsChain,
+    /// A bootnode in a chain specification couldn't be parsed.
+    #[display(fmt = "Failed to parse bootnode in chain specification: {_0}")]
+    InvalidBootnode(String),
+    /// Failed to initialize the Jaeger service.
+    #[display(fmt = "Failed to initialize Jaeger service: {_0}")]
+    JaegerInit(io::Error),
+    /// Failed to initialize the networking.
+    #[display(fmt = "Failed to initialize networking: {_0}")]
+    NetworkInit(network_service::InitError),
+    /// Failed to initialize the keystore.
+    #[display(fmt = "Failed to initialize keystore: {_0}")]
+    KeystoreInit(io::Error),
+    /// Failed to initialize the JSON-RPC servers.
+    #[display(fmt = "Failed to initialize JSON-RPC endpoint: {_0}")]
+    JsonRpcServiceInit(json_rpc_service::InitError),
+}
+
+/// Opens the keystore of a chain and inserts the given in-memory keys in it.
+async fn open_keystore(
+    keystore_path: Option<PathBuf>,
+    keystore_memory: Vec<[u8; 64]>,
+) -> Result<Arc<keystore::Keystore>, StartError> {
+    let mut keystore = keystore::Keystore::new(keystore_path, rand::random())
+        .await
+        .map_err(StartError::KeystoreInit)?;
+    for private_key in keystore_memory {
+        keystore.insert_sr25519_memory(keystore::KeyNamespace::all(), &private_key);
+    }
+    Ok(Arc::new(keystore))
+}
+
+/// Determines the role to advertise to the peers of a chain. See [`ChainConfig::role`].
+async fn advertised_role(role: Option<Role>, keystore: &keystore::Keystore) -> Role {
+    match role {
+        Some(role) => role,
+        None if keystore.keys().await.next().is_some() => Role::Authority,
+        None => Role::Full,
+    }
+}
+
+/// Builds the configuration of the given chain for the network service.
+async fn network_chain_config(
+    chain_spec: &chain_spec::ChainSpec,
+    genesis_chain_information: chain::chain_information::ChainInformationRef<'_>,
+    database: &Arc<database_thread::DatabaseThread>,
+    additional_bootnodes: Vec<(PeerId, Multiaddr)>,
+    role: Role,
+) -> Result<network_service::ChainConfig, StartError> {
+    let block_number_bytes = chain_spec.block_number_bytes();
+
+    let mut bootstrap_nodes =
+        Vec::with_capacity(chain_spec.boot_nodes().len() + additional_bootnodes.len());
+    for node in chain_spec.boot_nodes() {
+        match node {
+            chain_spec::Bootnode::UnrecognizedFormat(raw) => {
+                return Err(StartError::InvalidBootnode(raw.to_owned()))
+            }
+            chain_spec::Bootnode::Parsed { multiaddr, peer_id } => {
+                let multiaddr: Multiaddr = match multiaddr.parse() {
+                    Ok(a) => a,
+                    Err(_) => return Err(StartError::InvalidBootnode(multiaddr)),
+                };
+                let peer_id = PeerId::from_bytes(peer_id.to_vec()).unwrap();
+                bootstrap_nodes.push((peer_id, multiaddr));
+            }
+        }
+    }
+    bootstrap_nodes.extend(additional_bootnodes);
+
+    Ok(network_service::ChainConfig {
+        fork_id: chain_spec.fork_id().map(|n| n.to_owned()),
+        block_number_bytes: usize::from(block_number_bytes),
+        database: database.clone(),
+        has_grandpa_protocol: matches!(
+            genesis_chain_information.finality,
+            chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
+        ),
+        genesis_block_hash: genesis_chain_information
+            .finalized_block_header
+            .hash(usize::from(block_number_bytes)),
+        best_block: database
+            .with_database(move |database| {
+                let hash = database.finalized_block_hash().unwrap();
+                let header = database.block_scale_encoded_header(&hash).unwrap().unwrap();
+                let number = header::decode(&header, block_number_bytes.into())
+                    .unwrap()
+                    .number;
+                (number, hash)
+            })
+            .await,
+        bootstrap_nodes,
+        role,
+    })
+}
+/// Opens the database from the file system, or create a new database if none is found.
+///
+/// If `db_path` is `None`, open the database in memory instead.
+///
+/// The returned boolean is `true` if the database existed before.
+///
+/// # Panic
+///
+/// Panics if the database can't be open. This function is expected to be called from the `main`
+/// function.
+///
+async fn open_database(
+    chain_spec: &chain_spec::ChainSpec,
+    genesis_chain_information: chain::chain_information::ChainInformationRef<'_>,
+    db_path: Option<PathBuf>,
+    show_progress: bool,
+) -> (full_sqlite::SqliteFullDatabase, bool) {
+    // The `unwrap()` here can panic for example in case of access denied.
+    match background_open_database(
+        db_path.clone(),
+        chain_spec.block_number_bytes().into(),
+        show_progress,
+    )
+    .await
+    .unwrap()
+    {
+        // Database already exists and contains data.
+        full_sqlite::DatabaseOpen::Open(database) => {
+            if database.block_hash_by_number(0).unwrap().next().unwrap()
+                != genesis_chain_information
+                    .finalized_block_header
+                    .hash(chain_spec.block_number_bytes().into())
+            {
+                panic!("Mismatch between database and chain specification. Shutting down node.");
+            }
+
+            (database, true)
+        }
+
+        // The database doesn't exist or is empty.
+        full_sqlite::DatabaseOpen::Empty(empty) => {
+            let genesis_storage = chain_spec.genesis_storage().into_genesis_items().unwrap(); // TODO: return error instead
+
+            // In order to determine the state_version of the genesis block, we need to compile
+            // the runtime.
+            // TODO: return errors instead of panicking
+            let state_version = executor::host::HostVmPrototype::new(executor::host::Config {
+                module: genesis_storage.value(b":code").unwrap(),
+                heap_pages: executor::storage_heap_pages_to_value(
+                    genesis_storage.value(b":heappages"),
+                )
+                .unwrap(),
+                exec_hint: executor::vm::ExecHint::Oneshot,
+                allow_unresolved_imports: true,
+            })
+            .unwrap()
+            .runtime_version()
+            .decode()
+            .state_version
+            .map(u8::from)
+            .unwrap_or(0);
+
+            // The finalized block is the genesis block. As such, it has an empty body and
+            // no justification.
+            let database = empty
+                .initialize(
+                    genesis_chain_information,
+                    iter::empty(),
+                    None,
+                    genesis_storage.iter(),
+                    state_version,
+                )
+                .unwrap();
+            (database, false)
+        }
+    }
+}
+
+/// Since opening the database can take a long time, this utility function performs this operation
+/// in the background while showing a small progress bar to the user.
+///
+/// If `path` is `None`, the database is opened in memory.
+async fn background_open_database(
+    path: Option<PathBuf>,
+    block_number_bytes: usize,
+    show_progress: bool,
+) -> Result<full_sqlite::DatabaseOpen, full_sqlite::InternalError> {
+    let (tx, rx) = oneshot::channel();
+    let mut rx = rx.fuse();
+
+    let thread_spawn_result = thread::Builder::new().name("database-open".into()).spawn({
+        let path = path.clone();
+        move || {
+            let result = full_sqlite::open(full_sqlite::Config {
+                block_number_bytes,
+                ty: if let Some(path) = &path {
+                    full_sqlite::ConfigTy::Disk(path)
+                } else {
+                    full_sqlite::ConfigTy::Memory
+                },
+            });
+            let _ = tx.send(result);
+        }
+    });
+
+    // Fall back to opening the database on the same thread if the thread spawn failed.
+    if thread_spawn_result.is_err() {
+        return full_sqlite::open(full_sqlite::Config {
+            block_number_bytes,
+            ty: if let Some(path) = &path {
+                full_sqlite::ConfigTy::Disk(path)
+            } else {
+                full_sqlite::ConfigTy::Memory
+            },
+        });
+    }
+
+    let mut progress_timer = stream::unfold((), move |_| {
+        futures_timer::Delay::new(Duration::from_millis(200)).map(|_| Some(((), ())))
+    })
+    .map(|_| ());
+
+    let mut next_progress_icon = ['-', '\\', '|', '/'].iter().copied().cycle();
+
+    loop {
+        futures::select! {
+            res = rx => return res.unwrap(),
+            _ = progress_timer.next() => {
+                if show_progress {
+                    eprint!("    Opening database... {}\r", next_progress_icon.next().unwrap());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::prelude::*;
+    use std::time::Duration;
+
+    #[test]
+    fn informant_records() {
+        let threads_pool = futures::executor::ThreadPool::new().unwrap();
+
+        futures::executor::block_on(async {
+            let chain_spec = smoldot::chain_spec::ChainSpec::from_json_bytes(
+                &include_bytes!("../../demo-chain-specs/substrate-node-template.json")[..],
+            )
+            .unwrap();
+
+            let full_node = super::FullNode::start(super::Config {
+                chain: super::ChainConfig {
+                    chain_spec,
+                    additional_bootnodes: Vec::new(),
+                    keystore_memory: Vec::new(),
+                    database_path: None,
+                    keystore_path: None,
+                    role: None,
+                    skip_seal_verification: false,
+                },
+                relay_chain: None,
+                libp2p_key: Box::new([1; 32]),
+                listen_addresses: Vec::new(),
+                json_rpc_address: None,
+                json_rpc_http_address: None,
+                jaeger_agent: None,
+                show_database_opening_progress: false,
+                max_inbound_requests_per_peer_per_sec: 32,
+                max_inbound_response_bytes_per_peer_per_sec: 16 * 1024 * 1024,
+                tasks_executor: &mut |task| threads_pool.spawn_ok(task),
+            })
+            .await
+            .unwrap();
+
+            let records = full_node.informant_records(Duration::from_millis(10));
+            futures::pin_mut!(records);
+
+            // The node is destroyed before the records are read, which must not stop the stream.
+            drop(full_node);
+
+            for _ in 0..2 {
+                let record = records.next().await.unwrap();
+                assert!(!record.contains('\n'));
+                let record = serde_json::from_str::<serde_json::Value>(&record).unwrap();
+                assert_eq!(record["chain"], "Local Testnet");
+                assert_eq!(record["bestNumber"], 0);
+                assert_eq!(record["finalizedNumber"], 0);
+                assert_eq!(record["finalityLag"], 0);
+                assert!(record.get("relayChain").is_none());
+            }
+        });
+    }
+}
@@ -27,7 +27,7 @@
 // TODO: doc
 // TODO: re-review this once finished
 
-use crate::run::{database_thread, jaeger_service};
+use crate::{database_thread, jaeger_service};
 
 use core::{cmp, mem, task::Poll, time::Duration};
 use futures::{
@@ -53,11 +53,15 @@ use std::{
     net::{IpAddr, SocketAddr},
     num::NonZeroUsize,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     thread,
     time::Instant,
 };
 
+mod inbound_limiter;
 mod tasks;
 
 /// Configuration for a [`NetworkService`].
@@ -81,6 +85,14 @@ pub struct Config<'a> {
 
     /// Service to use to report traces.
     pub jaeger_service: Arc<jaeger_service::JaegerService>,
+
+    /// Maximum number of incoming requests that require accessing the database that a single
+    /// peer can perform per second. Requests above this limit are refused.
+    pub max_inbound_requests_per_peer_per_sec: u32,
+
+    /// Maximum number of bytes of responses to incoming requests that can be sent to a single
+    /// peer per second. Requests received after this limit has been reached are refused.
+    pub max_inbound_response_bytes_per_peer_per_sec: usize,
 }
 
 /// Configuration for one chain.
@@ -108,6 +120,9 @@ pub struct ChainConfig {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// Role of the local node sent to the peers of the chain.
+    pub role: protocol::Role,
 }
 
 /// Event generated by the events reporters returned by [`NetworkService::new`].
@@ -159,6 +174,12 @@ struct Inner {
 
     /// Service to use to report traces.
     jaeger_service: Arc<jaeger_service::JaegerService>,
+
+    /// Total number of bytes read from all the sockets since the service has started.
+    total_bytes_received: AtomicU64,
+
+    /// Total number of bytes written to all the sockets since the service has started.
+    total_bytes_sent: AtomicU64,
 }
 
 struct Guarded {
@@ -201,6 +222,9 @@ struct Guarded {
     /// List of Kademlia discovery operations that have been started but not finished yet.
     kademlia_discovery_operations:
         HashMap<service::KademliaOperationId, usize, fnv::FnvBuildHasher>,
+
+    /// Limits the rate of the requests sent by peers that require accessing the database.
+    inbound_requests_limiter: inbound_limiter::InboundRequestsLimiter,
 }
 
 impl NetworkService {
@@ -223,7 +247,7 @@ impl NetworkService {
                 best_hash: chain.best_block.1,
                 best_number: chain.best_block.0,
                 genesis_hash: chain.genesis_block_hash,
-                role: protocol::Role::Full,
+                role: chain.role,
                 grandpa_protocol_config: if chain.has_grandpa_protocol {
                     // TODO: dummy values
                     Some(service::GrandpaState {
@@ -276,6 +300,8 @@ impl NetworkService {
                 .into_peer_id(),
                 wake_up_main_background_task: event_listener::Event::new(),
                 databases,
+                total_bytes_received: AtomicU64::new(0),
+                total_bytes_sent: AtomicU64::new(0),
                 guarded: Mutex::new(Guarded {
                     num_pending_out_attempts: 0,
                     messages_from_connections_tx,
@@ -298,6 +324,10 @@ impl NetworkService {
                         4,
                         Default::default(),
                     ),
+                    inbound_requests_limiter: inbound_limiter::InboundRequestsLimiter::new(
+                        config.max_inbound_requests_per_peer_per_sec,
+                        config.max_inbound_response_bytes_per_peer_per_sec,
+                    ),
                 }),
                 jaeger_service: config.jaeger_service,
             })
@@ -504,6 +534,15 @@ impl NetworkService {
             .num_established_connections()
     }
 
+    /// Returns the total number of bytes received and sent over all the connections since the
+    /// service has started.
+    pub fn total_bytes_received_sent(&self) -> (u64, u64) {
+        (
+            self.inner.total_bytes_received.load(Ordering::Relaxed),
+            self.inner.total_bytes_sent.load(Ordering::Relaxed),
+        )
+    }
+
     /// Returns the number of peers we have a substream with.
     pub async fn num_peers(&self, chain_index: usize) -> usize {
         self.inner
@@ -736,6 +775,7 @@ async fn update_round(inner: &Arc<Inner>, event_senders: &mut [mpsc::Sender<Even
                     chain_indices,
                 } => {
                     log::debug!("disconnected; peer_id={}", peer_id);
+                    guarded.inbound_requests_limiter.remove_peer(&peer_id);
                     if !chain_indices.is_empty() {
                         debug_assert_eq!(chain_indices.len(), 1); // TODO: not implemented
                         break Event::Disconnected {
@@ -909,6 +949,19 @@ async fn update_round(inner: &Arc<Inner>, event_senders: &mut [mpsc::Sender<Even
                         peer_id,
                         chain_index
                     );
+
+                    if !guarded
+                        .inbound_requests_limiter
+                        .try_start_request(&peer_id, Instant::now())
+                    {
+                        log::debug!(
+                            "incoming-blocks-request-refused; peer_id={}; reason=rate-limit",
+                            peer_id
+                        );
+                        guarded.network.respond_blocks(request_id, None);
+                        continue;
+                    }
+
                     let mut _jaeger_span = inner.jaeger_service.incoming_block_request_span(
                         &inner.local_peer_id,
                         &peer_id,
@@ -929,6 +982,12 @@ async fn update_round(inner: &Arc<Inner>, event_senders: &mut [mpsc::Sender<Even
                         config,
                     )
                     .await;
+                    if let Ok(blocks) = &response {
+                        guarded.inbound_requests_limiter.report_response_size(
+                            &peer_id,
+                            blocks.iter().map(block_data_size).sum(),
+                        );
+                    }
                     guarded.network.respond_blocks(
                         request_id,
                         match response {
@@ -1022,7 +1081,10 @@ async fn update_round(inner: &Arc<Inner>, event_senders: &mut [mpsc::Sender<Even
                 })
                 .cloned();
 
-            let Some(peer_to_assign) = peer_to_assign else { break };
+            let peer_to_assign = match peer_to_assign {
+                Some(p) => p,
+                None => break,
+            };
             log::debug!(
                 "slot-assigned; peer_id={}; chain_index={}",
                 peer_to_assign,
@@ -1097,6 +1159,20 @@ impl Guarded {
     }
 }
 
+/// Returns the approximate number of bytes that the given block occupies in a response.
+fn block_data_size(block: &protocol::BlockData) -> usize {
+    block.hash.len()
+        + block.header.as_ref().map_or(0, |h| h.len())
+        + block
+            .body
+            .as_ref()
+            .map_or(0, |b| b.iter().map(|e| e.len()).sum())
+        + block
+            .justifications
+            .as_ref()
+            .map_or(0, |j| j.iter().map(|(e, j)| e.len() + j.len()).sum())
+}
+
 /// Builds the response to a block request by reading from the given database.
 async fn blocks_request_response(
     database: &database_thread::DatabaseThread,
@@ -0,0 +1,173 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Limits the rate at which the requests sent by peers and that require accessing the database
+//! are answered.
+//!
+//! All the kinds of requests that access the database must go through the same
+//! [`InboundRequestsLimiter`] and share the same quota, so that a peer can't bypass the limits by
+//! alternating between kinds of requests. At the moment, only block requests are answered.
+//! Storage proof and call proof requests are refused by the networking layer before reaching
+//! the node, as the database doesn't store the trie nodes necessary to build proofs.
+
+use core::time::Duration;
+use hashbrown::HashMap;
+use smoldot::libp2p::PeerId;
+use std::time::Instant;
+
+/// See [the module-level documentation](..).
+pub(super) struct InboundRequestsLimiter {
+    /// Maximum number of requests that a single peer can perform per second.
+    max_requests_per_sec: u32,
+
+    /// Maximum number of bytes of responses that can be sent to a single peer per second.
+    max_response_bytes_per_sec: usize,
+
+    /// For each peer that has recently sent requests, the amount of requests and response bytes
+    /// it has consumed during the current window.
+    ///
+    /// Entries are removed when [`InboundRequestsLimiter::remove_peer`] is called.
+    // TODO: use SipHasher
+    quotas: HashMap<PeerId, Quota, fnv::FnvBuildHasher>,
+}
+
+/// Usage by a peer of the requests that require accessing the database, within a window of one
+/// second.
+struct Quota {
+    /// Moment when the current window has started.
+    window_start: Instant,
+
+    /// Number of requests answered since [`Quota::window_start`].
+    num_requests: u32,
+
+    /// Number of bytes of responses sent since [`Quota::window_start`].
+    num_response_bytes: usize,
+}
+
+impl InboundRequestsLimiter {
+    /// Initializes a new limiter.
+    pub(super) fn new(max_requests_per_sec: u32, max_response_bytes_per_sec: usize) -> Self {
+        InboundRequestsLimiter {
+            max_requests_per_sec,
+            max_response_bytes_per_sec,
+            quotas: HashMap::with_capacity_and_hasher(
+                50, // TODO: ?
+                Default::default(),
+            ),
+        }
+    }
+
+    /// Checks whether the given peer is allowed to perform a request. If so, counts the request
+    /// towards the quota of this peer and returns `true`.
+    ///
+    /// Once the request has been answered, [`InboundRequestsLimiter::report_response_size`]
+    /// should be called.
+    pub(super) fn try_start_request(&mut self, peer_id: &PeerId, now: Instant) -> bool {
+        let quota = self.quotas.entry(peer_id.clone()).or_insert_with(|| Quota {
+            window_start: now,
+            num_requests: 0,
+            num_response_bytes: 0,
+        });
+
+        if now.saturating_duration_since(quota.window_start) >= Duration::from_secs(1) {
+            quota.window_start = now;
+            quota.num_requests = 0;
+            quota.num_response_bytes = 0;
+        }
+
+        if quota.num_requests >= self.max_requests_per_sec
+            || quota.num_response_bytes >= self.max_response_bytes_per_sec
+        {
+            return false;
+        }
+
+        quota.num_requests += 1;
+        true
+    }
+
+    /// Adds the given number of bytes to the quota of the given peer. See
+    /// [`InboundRequestsLimiter::try_start_request`].
+    pub(super) fn report_response_size(&mut self, peer_id: &PeerId, num_bytes: usize) {
+        if let Some(quota) = self.quotas.get_mut(peer_id) {
+            quota.num_response_bytes = quota.num_response_bytes.saturating_add(num_bytes);
+        }
+    }
+
+    /// Forgets the quota of the given peer, for example because it has disconnected.
+    pub(super) fn remove_peer(&mut self, peer_id: &PeerId) {
+        self.quotas.remove(peer_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::InboundRequestsLimiter;
+    use smoldot::libp2p::peer_id::{PeerId, PublicKey};
+    use std::time::{Duration, Instant};
+
+    fn peer(byte: u8) -> PeerId {
+        PeerId::from_public_key(&PublicKey::Ed25519([byte; 32]))
+    }
+
+    #[test]
+    fn requests_limit_reached() {
+        let mut limiter = InboundRequestsLimiter::new(3, usize::MAX);
+        let now = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.try_start_request(&peer(1), now));
+        }
+        assert!(!limiter.try_start_request(&peer(1), now));
+
+        // Other peers have their own quota.
+        assert!(limiter.try_start_request(&peer(2), now));
+    }
+
+    #[test]
+    fn response_bytes_limit_reached() {
+        let mut limiter = InboundRequestsLimiter::new(32, 1024);
+        let now = Instant::now();
+
+        assert!(limiter.try_start_request(&peer(1), now));
+        limiter.report_response_size(&peer(1), 1000);
+        assert!(limiter.try_start_request(&peer(1), now));
+        limiter.report_response_size(&peer(1), 1000);
+        assert!(!limiter.try_start_request(&peer(1), now));
+    }
+
+    #[test]
+    fn quota_reset_after_window() {
+        let mut limiter = InboundRequestsLimiter::new(1, 1024);
+        let now = Instant::now();
+
+        assert!(limiter.try_start_request(&peer(1), now));
+        limiter.report_response_size(&peer(1), 2048);
+        assert!(!limiter.try_start_request(&peer(1), now + Duration::from_millis(999)));
+        assert!(limiter.try_start_request(&peer(1), now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn removed_peer_quota_forgotten() {
+        let mut limiter = InboundRequestsLimiter::new(1, usize::MAX);
+        let now = Instant::now();
+
+        assert!(limiter.try_start_request(&peer(1), now));
+        assert!(!limiter.try_start_request(&peer(1), now));
+        limiter.remove_peer(&peer(1));
+        assert!(limiter.try_start_request(&peer(1), now));
+    }
+}
@@ -32,7 +32,7 @@ use std::{
     io,
     net::{IpAddr, SocketAddr},
     pin::Pin,
-    sync::Arc,
+    sync::{atomic::Ordering, Arc},
     time::Instant,
 };
 
@@ -208,6 +208,13 @@ pub(super) async fn established_connection_task(
 
             socket.advance(read_bytes, written_bytes);
 
+            inner
+                .total_bytes_received
+                .fetch_add(u64::try_from(read_bytes).unwrap(), Ordering::Relaxed);
+            inner
+                .total_bytes_sent
+                .fetch_add(u64::try_from(written_bytes).unwrap(), Ordering::Relaxed);
+
             if read_bytes != 0 || written_bytes != 0 {
                 continue;
             }
@@ -20,3 +20,4 @@
 pub mod aura;
 pub mod build;
 pub mod runtime;
+pub mod upgrade_dry_run;
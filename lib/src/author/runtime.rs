@@ -164,6 +164,8 @@ pub enum Error {
         /// Error returned by the runtime.
         error: TransactionValidityError,
     },
+    /// The runtime has accessed a child trie, which isn't supported when building a block.
+    ChildTriesNotSupported,
 }
 
 /// Start a block building process.
@@ -192,6 +194,7 @@ pub fn build_block(config: Config) -> BlockBuild {
         },
         main_trie_root_calculation_cache: config.main_trie_root_calculation_cache,
         storage_main_trie_changes: Default::default(),
+        storage_child_tries_changes: Default::default(),
         offchain_storage_changes: Default::default(),
         max_log_level: config.max_log_level,
     });
@@ -271,6 +274,23 @@ impl BlockBuild {
                 (Inner::Runtime(runtime_host::RuntimeHostVm::Finished(Err(err))), _) => {
                     return BlockBuild::Finished(Err(Error::WasmVm(err)))
                 }
+                (
+                    Inner::Runtime(
+                        runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                        | runtime_host::RuntimeHostVm::ChildStorageRoot(_),
+                    ),
+                    _,
+                ) => return BlockBuild::Finished(Err(Error::ChildTriesNotSupported)),
+                (Inner::Runtime(runtime_host::RuntimeHostVm::PrefixKeys(inner)), _)
+                    if inner.child_trie().is_some() =>
+                {
+                    return BlockBuild::Finished(Err(Error::ChildTriesNotSupported))
+                }
+                (Inner::Runtime(runtime_host::RuntimeHostVm::NextKey(inner)), _)
+                    if inner.child_trie().is_some() =>
+                {
+                    return BlockBuild::Finished(Err(Error::ChildTriesNotSupported))
+                }
                 (Inner::Runtime(runtime_host::RuntimeHostVm::StorageGet(inner)), _) => {
                     return BlockBuild::StorageGet(StorageGet(inner, shared))
                 }
@@ -331,6 +351,7 @@ impl BlockBuild {
                             success.main_trie_root_calculation_cache,
                         ),
                         storage_main_trie_changes: success.storage_main_trie_changes,
+                        storage_child_tries_changes: Default::default(),
                         offchain_storage_changes: success.offchain_storage_changes,
                         max_log_level: shared.max_log_level,
                     });
@@ -527,6 +548,7 @@ impl InherentExtrinsics {
             },
             main_trie_root_calculation_cache: Some(self.main_trie_root_calculation_cache),
             storage_main_trie_changes: self.storage_main_trie_changes,
+            storage_child_tries_changes: Default::default(),
             offchain_storage_changes: self.offchain_storage_changes,
             max_log_level: self.shared.max_log_level,
         });
@@ -561,6 +583,7 @@ impl ApplyExtrinsic {
             parameter: iter::once(&extrinsic),
             main_trie_root_calculation_cache: Some(self.main_trie_root_calculation_cache),
             storage_main_trie_changes: self.storage_main_trie_changes,
+            storage_child_tries_changes: Default::default(),
             offchain_storage_changes: self.offchain_storage_changes,
             max_log_level: self.shared.max_log_level,
         });
@@ -585,6 +608,7 @@ impl ApplyExtrinsic {
             parameter: iter::empty::<&[u8]>(),
             main_trie_root_calculation_cache: Some(self.main_trie_root_calculation_cache),
             storage_main_trie_changes: self.storage_main_trie_changes,
+            storage_child_tries_changes: Default::default(),
             offchain_storage_changes: self.offchain_storage_changes,
             max_log_level: self.shared.max_log_level,
         });
@@ -665,7 +689,7 @@ impl NextKey {
 //       `type OpaqueExtrinsic = Vec<u8>;` here, which happens to start with a length prefix
 //       containing its remaining size; this length prefix is fully part of the `Extrinsic` though.
 //       In other words, this function might succeed or fail depending on the Substrate chain.
-fn parse_inherent_extrinsics_output(output: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
+pub(super) fn parse_inherent_extrinsics_output(output: &[u8]) -> Result<Vec<Vec<u8>>, Error> {
     nom::combinator::all_consuming(nom::combinator::flat_map(
         crate::util::nom_scale_compact_usize,
         |num_elems| {
@@ -687,7 +711,7 @@ fn parse_inherent_extrinsics_output(output: &[u8]) -> Result<Vec<Vec<u8>>, Error
 }
 
 /// Analyzes the output of a call to `BlockBuilder_apply_extrinsic`.
-fn parse_apply_extrinsic_output(
+pub(super) fn parse_apply_extrinsic_output(
     output: &[u8],
 ) -> Result<Result<Result<(), DispatchError>, TransactionValidityError>, Error> {
     nom::combinator::all_consuming(apply_extrinsic_result)(output)
@@ -0,0 +1,668 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Comparison between the execution of a block by the current runtime of a chain and by a
+//! candidate runtime, in order to validate a runtime upgrade before it is enacted.
+//!
+//! # Detail
+//!
+//! The block is executed twice on top of the storage of its parent: once with the current
+//! runtime, then once with the candidate runtime. Because the candidate runtime finds in the
+//! storage a runtime version different from its own, it runs its storage migrations as part of
+//! `Core_initialize_block`, exactly like it would after the runtime upgrade has been enacted.
+//!
+//! Each execution consists in the same runtime calls as when building a block (see the
+//! [`runtime`](super::runtime) module): `Core_initialize_block`, then
+//! `BlockBuilder_apply_extrinsic` for each extrinsic, then `BlockBuilder_finalize_block`.
+//! Contrary to `Core_execute_block`, this doesn't verify the state root and extrinsics root
+//! found in the header of the block, which are expected to differ between the two runtimes.
+//!
+//! The block to execute can either be an existing block, in which case all its extrinsics are
+//! applied, or a synthetic empty block, in which case only the inherent extrinsics generated by
+//! each runtime are applied.
+//!
+//! Once both executions are finished, [`Success::storage_differences`] indicates the storage
+//! entries that have been modified differently, and [`Success::events_identical`] indicates
+//! whether the events emitted by the block are the same.
+
+use super::runtime;
+use crate::{
+    executor::{host, runtime_host, storage_diff},
+    header, util,
+    verify::inherents,
+};
+
+use alloc::{collections::BTreeSet, string::String, vec::Vec};
+use core::{iter, mem};
+
+pub use crate::transactions::events::SYSTEM_EVENTS_STORAGE_KEY;
+pub use runtime_host::TrieEntryVersion;
+
+/// Configuration for a dry run.
+pub struct Config<'a> {
+    /// Number of bytes used to encode block numbers in the header.
+    pub block_number_bytes: usize,
+
+    /// Runtime of the parent of the block. Must be built using the Wasm code found at the
+    /// `:code` key of the parent block storage.
+    pub current_runtime: host::HostVmPrototype,
+
+    /// Runtime to compare against [`Config::current_runtime`]. Typically built using a `:code`
+    /// blob that is intended to be used in a future runtime upgrade.
+    pub candidate_runtime: host::HostVmPrototype,
+
+    /// Block to execute.
+    pub block: ConfigBlock<'a>,
+
+    /// Maximum log level of the runtime.
+    ///
+    /// > **Note**: This value is opaque from the point of the view of the client, and the runtime
+    /// >           is free to interpret it the way it wants. However, usually values are: `0` for
+    /// >           "off", `1` for "error", `2` for "warn", `3` for "info", `4` for "debug",
+    /// >           and `5` for "trace".
+    pub max_log_level: u32,
+}
+
+/// Block to execute. See [`Config::block`].
+pub enum ConfigBlock<'a> {
+    /// Existing block. All the extrinsics of its body are applied, including the inherent
+    /// extrinsics.
+    Existing {
+        /// Header of the block. If the header contains a seal, it is ignored.
+        header: header::HeaderRef<'a>,
+        /// Body of the block.
+        body: Vec<Vec<u8>>,
+    },
+    /// Synthetic block that only contains the inherent extrinsics generated by each runtime.
+    Empty {
+        /// Hash of the parent of the block to execute.
+        parent_hash: &'a [u8; 32],
+        /// Height of the parent of the block to execute.
+        parent_number: u64,
+        /// Consensus-specific item to put in the digest of the header.
+        consensus_digest_log_item: runtime::ConfigPreRuntime<'a>,
+        /// Inherents passed to `BlockBuilder_inherent_extrinsics`.
+        inherents: inherents::InherentData,
+    },
+}
+
+/// Start a dry run.
+pub fn dry_run(config: Config) -> DryRun {
+    let (initialize_block_parameter, block_body) = match config.block {
+        ConfigBlock::Existing { mut header, body } => {
+            // Consensus engines add a seal at the end of the digest logs, which isn't part of
+            // the header passed to `Core_initialize_block`.
+            let _seal_log = header.digest.pop_seal();
+            (
+                concat(header.scale_encoding(config.block_number_bytes)),
+                BlockBody::Existing(body),
+            )
+        }
+        ConfigBlock::Empty {
+            parent_hash,
+            parent_number,
+            consensus_digest_log_item,
+            inherents,
+        } => {
+            let number = match parent_number.checked_add(1) {
+                Some(n) => n,
+                None => return DryRun::Finished(Err(Error::BlockHeightOverflow)),
+            };
+
+            let header = concat(
+                header::HeaderRef {
+                    parent_hash,
+                    number,
+                    extrinsics_root: &[0; 32],
+                    state_root: &[0; 32],
+                    digest: header::DigestRef::from_slice(&[match consensus_digest_log_item {
+                        runtime::ConfigPreRuntime::Aura(item) => {
+                            header::DigestItem::AuraPreDigest(item)
+                        }
+                        runtime::ConfigPreRuntime::Babe(item) => {
+                            header::DigestItem::BabePreDigest(item.into())
+                        }
+                    }])
+                    .unwrap(),
+                }
+                .scale_encoding(config.block_number_bytes),
+            );
+
+            // The `BlockBuilder_inherent_extrinsics` function expects a SCALE-encoded list of
+            // tuples containing an "inherent identifier" (`[u8; 8]`) and a value (`Vec<u8>`).
+            let inherents_parameter = {
+                let list = inherents.as_raw_list();
+                let mut out = util::encode_scale_compact_usize(list.len())
+                    .as_ref()
+                    .to_vec();
+                for (id, value) in list {
+                    out.extend_from_slice(&id);
+                    out.extend_from_slice(
+                        util::encode_scale_compact_usize(value.as_ref().len()).as_ref(),
+                    );
+                    out.extend_from_slice(value.as_ref());
+                }
+                out
+            };
+
+            (header, BlockBody::Empty(inherents_parameter))
+        }
+    };
+
+    let shared = Shared {
+        initialize_block_parameter,
+        block_body,
+        stage: Stage::InitializeBlock,
+        extrinsics: Vec::new(),
+        extrinsics_results: Vec::new(),
+        logs: String::new(),
+        candidate_runtime: Some(config.candidate_runtime),
+        current_outcome: None,
+        max_log_level: config.max_log_level,
+    };
+
+    shared.start_run(config.current_runtime)
+}
+
+/// Current state of the dry run.
+#[must_use]
+pub enum DryRun {
+    /// Dry run is over.
+    Finished(Result<Success, Error>),
+
+    /// Loading a storage value from the parent storage is required in order to continue.
+    StorageGet(StorageGet),
+
+    /// Fetching the list of keys with a given prefix from the parent storage is required in order
+    /// to continue.
+    PrefixKeys(PrefixKeys),
+
+    /// Fetching the key that follows a given one in the parent storage is required in order to
+    /// continue.
+    NextKey(NextKey),
+}
+
+impl DryRun {
+    fn from_inner(mut inner: runtime_host::RuntimeHostVm, mut shared: Shared) -> Self {
+        loop {
+            let success = match inner {
+                runtime_host::RuntimeHostVm::Finished(Ok(success)) => success,
+                runtime_host::RuntimeHostVm::Finished(Err(err)) => {
+                    return DryRun::Finished(Err(shared.run_error(runtime::Error::WasmVm(err))))
+                }
+                runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | runtime_host::RuntimeHostVm::ChildStorageRoot(_) => {
+                    return DryRun::Finished(Err(
+                        shared.run_error(runtime::Error::ChildTriesNotSupported)
+                    ))
+                }
+                runtime_host::RuntimeHostVm::PrefixKeys(inner) if inner.child_trie().is_some() => {
+                    return DryRun::Finished(Err(
+                        shared.run_error(runtime::Error::ChildTriesNotSupported)
+                    ))
+                }
+                runtime_host::RuntimeHostVm::NextKey(inner) if inner.child_trie().is_some() => {
+                    return DryRun::Finished(Err(
+                        shared.run_error(runtime::Error::ChildTriesNotSupported)
+                    ))
+                }
+                runtime_host::RuntimeHostVm::StorageGet(inner) => {
+                    return DryRun::StorageGet(StorageGet(inner, shared))
+                }
+                runtime_host::RuntimeHostVm::PrefixKeys(inner) => {
+                    return DryRun::PrefixKeys(PrefixKeys(inner, shared))
+                }
+                runtime_host::RuntimeHostVm::NextKey(inner) => {
+                    return DryRun::NextKey(NextKey(inner, shared))
+                }
+                runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    inner = sig.verify_and_resume();
+                    continue;
+                }
+            };
+
+            shared.logs.push_str(&success.logs);
+
+            match shared.stage {
+                Stage::InitializeBlock => {
+                    if !success.virtual_machine.value().as_ref().is_empty() {
+                        return DryRun::Finished(Err(
+                            shared.run_error(runtime::Error::InitializeBlockNonEmptyOutput)
+                        ));
+                    }
+
+                    shared.stage = match &shared.block_body {
+                        BlockBody::Existing(body) => {
+                            shared.extrinsics = body.clone();
+                            Stage::ApplyExtrinsic
+                        }
+                        BlockBody::Empty(_) => Stage::InherentExtrinsics,
+                    };
+                }
+                Stage::InherentExtrinsics => {
+                    match runtime::parse_inherent_extrinsics_output(
+                        success.virtual_machine.value().as_ref(),
+                    ) {
+                        Ok(extrinsics) => shared.extrinsics = extrinsics,
+                        Err(err) => return DryRun::Finished(Err(shared.run_error(err))),
+                    }
+                    shared.stage = Stage::ApplyExtrinsic;
+                }
+                Stage::ApplyExtrinsic => {
+                    match runtime::parse_apply_extrinsic_output(
+                        success.virtual_machine.value().as_ref(),
+                    ) {
+                        Ok(result) => shared.extrinsics_results.push(result),
+                        Err(err) => return DryRun::Finished(Err(shared.run_error(err))),
+                    }
+                }
+                Stage::FinalizeBlock => {
+                    let scale_encoded_header = success.virtual_machine.value().as_ref().to_vec();
+                    let outcome = RunOutcome {
+                        scale_encoded_header,
+                        runtime: success.virtual_machine.into_prototype(),
+                        extrinsics: mem::take(&mut shared.extrinsics),
+                        extrinsics_results: mem::take(&mut shared.extrinsics_results),
+                        storage_main_trie_changes: success.storage_main_trie_changes,
+                        state_trie_version: success.state_trie_version,
+                        logs: mem::take(&mut shared.logs),
+                    };
+
+                    // Switch to the candidate runtime, or finish if it has already been executed.
+                    match shared.candidate_runtime.take() {
+                        Some(candidate_runtime) => {
+                            shared.current_outcome = Some(outcome);
+                            return shared.start_run(candidate_runtime);
+                        }
+                        None => {
+                            return DryRun::Finished(Ok(Success {
+                                current: shared.current_outcome.take().unwrap(),
+                                candidate: outcome,
+                            }))
+                        }
+                    }
+                }
+            }
+
+            // Start the next call of the current run.
+            if matches!(shared.stage, Stage::ApplyExtrinsic)
+                && shared.extrinsics_results.len() == shared.extrinsics.len()
+            {
+                shared.stage = Stage::FinalizeBlock;
+            }
+
+            let (function_to_call, parameter) = match (&shared.stage, &shared.block_body) {
+                (Stage::InherentExtrinsics, BlockBody::Empty(inherents_parameter)) => {
+                    ("BlockBuilder_inherent_extrinsics", &inherents_parameter[..])
+                }
+                (Stage::ApplyExtrinsic, _) => (
+                    "BlockBuilder_apply_extrinsic",
+                    &shared.extrinsics[shared.extrinsics_results.len()][..],
+                ),
+                (Stage::FinalizeBlock, _) => ("BlockBuilder_finalize_block", &[][..]),
+                _ => unreachable!(),
+            };
+
+            inner = match runtime_host::run(runtime_host::Config {
+                virtual_machine: success.virtual_machine.into_prototype(),
+                function_to_call,
+                parameter: iter::once(parameter),
+                main_trie_root_calculation_cache: Some(success.main_trie_root_calculation_cache),
+                storage_main_trie_changes: success.storage_main_trie_changes,
+                storage_child_tries_changes: success.storage_child_tries_changes,
+                offchain_storage_changes: success.offchain_storage_changes,
+                max_log_level: shared.max_log_level,
+            }) {
+                Ok(vm) => vm,
+                Err((err, proto)) => {
+                    return DryRun::Finished(Err(
+                        shared.run_error(runtime::Error::VmInit(err, proto))
+                    ))
+                }
+            };
+        }
+    }
+}
+
+/// Dry run successfully finished.
+#[derive(Debug)]
+pub struct Success {
+    /// Outcome of the execution with [`Config::current_runtime`].
+    pub current: RunOutcome,
+    /// Outcome of the execution with [`Config::candidate_runtime`].
+    pub candidate: RunOutcome,
+}
+
+impl Success {
+    /// Returns the list of storage entries that have been modified by only one of the two
+    /// executions, or that have been modified differently. Ordered by key.
+    pub fn storage_differences(&self) -> Vec<StorageDifference> {
+        let keys = self
+            .current
+            .storage_main_trie_changes
+            .diff_iter_unordered()
+            .chain(
+                self.candidate
+                    .storage_main_trie_changes
+                    .diff_iter_unordered(),
+            )
+            .map(|(key, _, ())| key)
+            .collect::<BTreeSet<_>>();
+
+        keys.into_iter()
+            .filter_map(|key| {
+                let current = self.current.storage_main_trie_changes.diff_get(key);
+                let candidate = self.candidate.storage_main_trie_changes.diff_get(key);
+                if current == candidate {
+                    return None;
+                }
+
+                Some(StorageDifference {
+                    key: key.to_vec(),
+                    current: current.map(|(value, ())| value.map(|v| v.to_vec())),
+                    candidate: candidate.map(|(value, ())| value.map(|v| v.to_vec())),
+                })
+            })
+            .collect()
+    }
+
+    /// Returns `true` if both executions have emitted the same events.
+    ///
+    /// > **Note**: The events are compared as opaque SCALE-encoded blobs. If the candidate
+    /// >           runtime changes the layout of the events, they are always considered different.
+    pub fn events_identical(&self) -> bool {
+        self.current.events() == self.candidate.events()
+    }
+}
+
+/// Outcome of one of the two executions of the block.
+#[derive(Debug)]
+pub struct RunOutcome {
+    /// Runtime that has executed the block.
+    pub runtime: host::HostVmPrototype,
+    /// SCALE-encoded header returned by `BlockBuilder_finalize_block`.
+    pub scale_encoded_header: Vec<u8>,
+    /// Extrinsics that have been applied. If [`ConfigBlock::Empty`] was passed, contains the
+    /// inherent extrinsics generated by the runtime.
+    pub extrinsics: Vec<Vec<u8>>,
+    /// Result of applying each extrinsic of [`RunOutcome::extrinsics`].
+    pub extrinsics_results:
+        Vec<Result<Result<(), runtime::DispatchError>, runtime::TransactionValidityError>>,
+    /// List of changes to the storage main trie that the block performs.
+    pub storage_main_trie_changes: storage_diff::TrieDiff,
+    /// State trie version indicated by the runtime.
+    pub state_trie_version: TrieEntryVersion,
+    /// Concatenation of all the log messages printed by the runtime.
+    pub logs: String,
+}
+
+impl RunOutcome {
+    /// Returns the SCALE-encoded value of the `System.Events` storage entry at the end of the
+    /// block, or `None` if the block hasn't modified this storage entry.
+    pub fn events(&self) -> Option<Option<&[u8]>> {
+        self.storage_main_trie_changes
+            .diff_get(&SYSTEM_EVENTS_STORAGE_KEY)
+            .map(|(value, ())| value)
+    }
+}
+
+/// Storage entry modified differently by the two executions.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageDifference {
+    /// Key of the storage entry.
+    pub key: Vec<u8>,
+    /// Value written by the execution with [`Config::current_runtime`]. `None` if the storage
+    /// entry hasn't been modified, and `Some(None)` if it has been erased.
+    pub current: Option<Option<Vec<u8>>>,
+    /// Value written by the execution with [`Config::candidate_runtime`]. `None` if the storage
+    /// entry hasn't been modified, and `Some(None)` if it has been erased.
+    pub candidate: Option<Option<Vec<u8>>>,
+}
+
+/// Error that can happen during the dry run.
+#[derive(Debug, derive_more::Display)]
+pub enum Error {
+    /// Error during the execution with [`Config::current_runtime`].
+    #[display(fmt = "Error with the current runtime: {_0}")]
+    Current(runtime::Error),
+    /// Error during the execution with [`Config::candidate_runtime`].
+    #[display(fmt = "Error with the candidate runtime: {_0}")]
+    Candidate(runtime::Error),
+    /// Overflow when incrementing block height.
+    BlockHeightOverflow,
+}
+
+/// Loading a storage value from the parent storage is required in order to continue.
+#[must_use]
+pub struct StorageGet(runtime_host::StorageGet, Shared);
+
+impl StorageGet {
+    /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.key()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> DryRun {
+        DryRun::from_inner(self.0.inject_value(value), self.1)
+    }
+}
+
+/// Fetching the list of keys with a given prefix from the parent storage is required in order to
+/// continue.
+#[must_use]
+pub struct PrefixKeys(runtime_host::PrefixKeys, Shared);
+
+impl PrefixKeys {
+    /// Returns the prefix whose keys to load.
+    pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.prefix()
+    }
+
+    /// Injects the list of keys ordered lexicographically.
+    pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> DryRun {
+        DryRun::from_inner(self.0.inject_keys_ordered(keys), self.1)
+    }
+}
+
+/// Fetching the key that follows a given one in the parent storage is required in order to
+/// continue.
+#[must_use]
+pub struct NextKey(runtime_host::NextKey, Shared);
+
+impl NextKey {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.0.key()
+    }
+
+    /// Injects the key.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't strictly superior to the requested key.
+    ///
+    pub fn inject_key(self, key: Option<impl AsRef<[u8]>>) -> DryRun {
+        DryRun::from_inner(self.0.inject_key(key), self.1)
+    }
+}
+
+/// Extra information maintained in parallel of the [`runtime_host::RuntimeHostVm`].
+struct Shared {
+    /// SCALE-encoded header passed to `Core_initialize_block`.
+    initialize_block_parameter: Vec<u8>,
+    /// Extrinsics to apply.
+    block_body: BlockBody,
+    /// Each execution is separated into multiple stages.
+    stage: Stage,
+    /// Extrinsics applied by the current execution.
+    extrinsics: Vec<Vec<u8>>,
+    /// Result of applying the extrinsics of [`Shared::extrinsics`]. The next extrinsic to apply
+    /// is found at the index equal to the length of this list.
+    extrinsics_results:
+        Vec<Result<Result<(), runtime::DispatchError>, runtime::TransactionValidityError>>,
+    /// Concatenation of all logs produced by the calls of the current execution.
+    logs: String,
+    /// Value provided by [`Config::candidate_runtime`]. `None` if the candidate runtime is
+    /// currently being executed.
+    candidate_runtime: Option<host::HostVmPrototype>,
+    /// Outcome of the execution with the current runtime, if it is over.
+    current_outcome: Option<RunOutcome>,
+    /// Value provided by [`Config::max_log_level`].
+    max_log_level: u32,
+}
+
+impl Shared {
+    /// Starts executing the block with the given runtime.
+    fn start_run(mut self, runtime: host::HostVmPrototype) -> DryRun {
+        self.stage = Stage::InitializeBlock;
+
+        let init_result = runtime_host::run(runtime_host::Config {
+            virtual_machine: runtime,
+            function_to_call: "Core_initialize_block",
+            parameter: iter::once(&self.initialize_block_parameter),
+            main_trie_root_calculation_cache: None,
+            storage_main_trie_changes: Default::default(),
+            storage_child_tries_changes: Default::default(),
+            offchain_storage_changes: Default::default(),
+            max_log_level: self.max_log_level,
+        });
+
+        match init_result {
+            Ok(vm) => DryRun::from_inner(vm, self),
+            Err((err, proto)) => {
+                DryRun::Finished(Err(self.run_error(runtime::Error::VmInit(err, proto))))
+            }
+        }
+    }
+
+    /// Wraps around an error that happened during the current execution.
+    fn run_error(&self, error: runtime::Error) -> Error {
+        if self.candidate_runtime.is_some() {
+            Error::Current(error)
+        } else {
+            Error::Candidate(error)
+        }
+    }
+}
+
+/// See [`Shared::block_body`].
+enum BlockBody {
+    /// Body of an existing block.
+    Existing(Vec<Vec<u8>>),
+    /// Empty block. Contains the parameter to pass to `BlockBuilder_inherent_extrinsics`.
+    Empty(Vec<u8>),
+}
+
+/// Each execution is separated into multiple stages.
+enum Stage {
+    InitializeBlock,
+    InherentExtrinsics,
+    ApplyExtrinsic,
+    FinalizeBlock,
+}
+
+fn concat(buffers: impl Iterator<Item = impl AsRef<[u8]>>) -> Vec<u8> {
+    buffers.fold(Vec::new(), |mut a, b| {
+        a.extend_from_slice(b.as_ref());
+        a
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{util, verify::inherents};
+    use core::iter;
+
+    #[test]
+    fn system_events_storage_key() {
+        assert_eq!(
+            [util::twox_128(b"System"), util::twox_128(b"Events")].concat(),
+            super::SYSTEM_EVENTS_STORAGE_KEY
+        );
+    }
+
+    #[test]
+    fn identical_runtimes_have_no_difference() {
+        let chain_specs = crate::chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("runtime/example-chain-specs.json")[..],
+        )
+        .unwrap();
+        let genesis_storage = chain_specs.genesis_storage().into_genesis_items().unwrap();
+
+        let (chain_info, current_runtime) = chain_specs.as_chain_information().unwrap();
+        let (_, candidate_runtime) = chain_specs.as_chain_information().unwrap();
+        let genesis_hash = chain_info.as_ref().finalized_block_header.hash(4);
+
+        let mut dry_run = super::dry_run(super::Config {
+            block_number_bytes: 4,
+            current_runtime,
+            candidate_runtime,
+            block: super::ConfigBlock::Empty {
+                parent_hash: &genesis_hash,
+                parent_number: 0,
+                consensus_digest_log_item: crate::author::runtime::ConfigPreRuntime::Aura(
+                    crate::header::AuraPreDigest {
+                        slot_number: 1234u64,
+                    },
+                ),
+                inherents: inherents::InherentData { timestamp: 1234 },
+            },
+            max_log_level: 0,
+        });
+
+        loop {
+            match dry_run {
+                super::DryRun::Finished(Ok(success)) => {
+                    assert!(!success.current.extrinsics.is_empty());
+                    assert_eq!(success.current.extrinsics, success.candidate.extrinsics);
+                    assert_ne!(
+                        success
+                            .current
+                            .storage_main_trie_changes
+                            .diff_iter_unordered()
+                            .len(),
+                        0
+                    );
+                    assert!(success.storage_differences().is_empty());
+                    assert!(success.events_identical());
+                    break;
+                }
+                super::DryRun::Finished(Err(err)) => panic!("{}", err),
+                super::DryRun::StorageGet(get) => {
+                    let value = genesis_storage
+                        .iter()
+                        .find(|(k, _)| *k == get.key().as_ref())
+                        .map(|(_, v)| iter::once(v));
+                    dry_run = get.inject_value(value.map(|v| (v, super::TrieEntryVersion::V0)));
+                }
+                super::DryRun::NextKey(_) => unimplemented!(), // Not needed for this test.
+                super::DryRun::PrefixKeys(prefix) => {
+                    let p = prefix.prefix().as_ref().to_owned();
+                    let list = genesis_storage
+                        .iter()
+                        .filter(move |(k, _)| k.starts_with(&p))
+                        .map(|(k, _)| k);
+                    dry_run = prefix.inject_keys_ordered(list);
+                }
+            }
+        }
+    }
+}
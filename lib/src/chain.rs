@@ -52,3 +52,4 @@ pub mod async_tree;
 pub mod blocks_tree;
 pub mod chain_information;
 pub mod fork_tree;
+pub mod state_commitment;
@@ -64,14 +64,16 @@
 use crate::{
     chain::{chain_information, fork_tree},
     header,
+    verify::{babe, Clock},
 };
 
 use alloc::{boxed::Box, format, sync::Arc, vec::Vec};
 use core::{fmt, mem, num::NonZeroU64, time::Duration};
-use hashbrown::HashMap;
+use hashbrown::{HashMap, HashSet};
 
 mod best_block;
 mod finality;
+mod tests;
 mod verify;
 
 pub use self::finality::*;
@@ -98,6 +100,36 @@ pub struct Config {
     /// Consequently, both `true` and `false` guarantee that the number of authorable blocks over
     /// the network is bounded.
     pub allow_unknown_consensus_engines: bool,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. All the other checks, including the execution of the block when its body
+    /// is verified, are still performed.
+    ///
+    /// > **Note**: This is meant to be used when benchmarking, or when importing a chain whose
+    /// >           blocks are already known to be valid. Passing `true` makes it possible for
+    /// >           anyone to author blocks that are considered as valid.
+    pub skip_seal_verification: bool,
+
+    /// Hashes of blocks that are known to be invalid. Blocks whose hash is in this list, and
+    /// consequently all of their descendants, fail to verify.
+    pub bad_blocks: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed. Blocks at one
+    /// of these heights but with a different hash, and consequently all of their descendants,
+    /// fail to verify.
+    pub fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// Source of the current time. Used in order to verify that blocks don't pretend to come
+    /// from the future.
+    ///
+    /// Use [`Clock::fixed`] in order to replay historical blocks in a reproducible way.
+    pub clock: Clock,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block. See [`crate::verify::aura::VerifyConfig::clock_drift_tolerance`].
+    ///
+    /// Use [`crate::verify::DEFAULT_CLOCK_DRIFT_TOLERANCE`] if in doubt.
+    pub clock_drift_tolerance: Duration,
 }
 
 /// Holds state about the current state of the chain for the purpose of verifying headers.
@@ -173,6 +205,12 @@ impl<T> NonFinalizedTree<T> {
                 current_best: None,
                 block_number_bytes: config.block_number_bytes,
                 allow_unknown_consensus_engines: config.allow_unknown_consensus_engines,
+                skip_seal_verification: config.skip_seal_verification,
+                bad_blocks: config.bad_blocks,
+                fork_blocks: config.fork_blocks,
+                clock: config.clock,
+                clock_drift_tolerance: config.clock_drift_tolerance,
+                babe_vrf_cache: babe::VrfCache::new(BABE_VRF_CACHE_CAPACITY),
             })),
         }
     }
@@ -384,6 +422,58 @@ impl<T> NonFinalizedTree<T> {
         }
     }
 
+    /// Returns finality information about the current best block of the chain.
+    ///
+    /// In the case of GrandPa, the returned value contains the authorities set that must finalize
+    /// the children of the best block, and the change to this set that has been scheduled by the
+    /// best block or its ancestors but not triggered yet. Contrary to what the names of the
+    /// fields indicate, this scheduled change isn't necessarily finalized.
+    ///
+    /// See [`NonFinalizedTree::as_chain_information`] for the same information about the
+    /// finalized block.
+    pub fn best_block_finality(&self) -> chain_information::ChainInformationFinalityRef<'_> {
+        let inner = self.inner.as_ref().unwrap();
+        match (
+            &inner.finality,
+            inner
+                .current_best
+                .map(|idx| &inner.blocks.get(idx).unwrap().finality),
+        ) {
+            (Finality::Outsourced, _) => chain_information::ChainInformationFinalityRef::Outsourced,
+            (
+                Finality::Grandpa {
+                    after_finalized_block_authorities_set_id,
+                    finalized_triggered_authorities,
+                    finalized_scheduled_change,
+                },
+                None,
+            ) => chain_information::ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id: *after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities,
+                finalized_scheduled_change: finalized_scheduled_change
+                    .as_ref()
+                    .map(|(n, l)| (*n, &l[..])),
+            },
+            (
+                Finality::Grandpa { .. },
+                Some(BlockFinality::Grandpa {
+                    after_block_authorities_set_id,
+                    triggered_authorities,
+                    scheduled_change,
+                    ..
+                }),
+            ) => chain_information::ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id: *after_block_authorities_set_id,
+                finalized_triggered_authorities: triggered_authorities,
+                finalized_scheduled_change: scheduled_change.as_ref().map(|(n, l)| (*n, &l[..])),
+            },
+
+            // Any mismatch of finality engine between the finalized and best block is not
+            // supported at the moment.
+            _ => unreachable!(),
+        }
+    }
+
     /// Returns true if the block with the given hash is in the [`NonFinalizedTree`].
     pub fn contains_non_finalized_block(&self, hash: &[u8; 32]) -> bool {
         self.inner
@@ -474,8 +564,23 @@ struct NonFinalizedTreeInner<T> {
     block_number_bytes: usize,
     /// See [`Config::allow_unknown_consensus_engines`].
     allow_unknown_consensus_engines: bool,
+    /// See [`Config::skip_seal_verification`].
+    skip_seal_verification: bool,
+    /// See [`Config::bad_blocks`].
+    bad_blocks: HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    /// See [`Config::fork_blocks`].
+    fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+    /// See [`Config::clock`].
+    clock: Clock,
+    /// See [`Config::clock_drift_tolerance`].
+    clock_drift_tolerance: Duration,
+    /// Outcome of the VRF verifications of the Babe headers verified recently.
+    babe_vrf_cache: babe::VrfCache,
 }
 
+/// Number of entries in [`NonFinalizedTreeInner::babe_vrf_cache`].
+const BABE_VRF_CACHE_CAPACITY: usize = 256;
+
 /// State of the consensus of the finalized block.
 #[derive(Clone)]
 enum FinalizedConsensus {
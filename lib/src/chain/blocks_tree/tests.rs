@@ -0,0 +1,112 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+#![cfg(test)]
+
+use super::{chain_information, BodyVerifyStep1, Config, HeaderVerifyError, NonFinalizedTree};
+use crate::{header, verify::Clock};
+
+use alloc::vec::Vec;
+use core::time::Duration;
+
+const BLOCK_NUMBER_BYTES: usize = 4;
+
+fn finalized_header() -> header::Header {
+    header::Header {
+        parent_hash: [0; 32],
+        number: 0,
+        state_root: [1; 32],
+        extrinsics_root: [2; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+}
+
+/// Returns the SCALE encoding of a child of the finalized block. Different values of `variant`
+/// lead to different hashes.
+fn child_header(variant: u8) -> Vec<u8> {
+    header::Header {
+        parent_hash: finalized_header().hash(BLOCK_NUMBER_BYTES),
+        number: 1,
+        state_root: [variant; 32],
+        extrinsics_root: [2; 32],
+        digest: header::DigestRef::empty().into(),
+    }
+    .scale_encoding_vec(BLOCK_NUMBER_BYTES)
+}
+
+fn tree(bad_blocks: &[[u8; 32]], fork_blocks: &[(u64, [u8; 32])]) -> NonFinalizedTree<()> {
+    NonFinalizedTree::new(Config {
+        chain_information: chain_information::ChainInformation {
+            finalized_block_header: finalized_header(),
+            consensus: chain_information::ChainInformationConsensus::Unknown,
+            finality: chain_information::ChainInformationFinality::Outsourced,
+        }
+        .try_into()
+        .unwrap(),
+        block_number_bytes: BLOCK_NUMBER_BYTES,
+        blocks_capacity: 16,
+        allow_unknown_consensus_engines: false,
+        skip_seal_verification: false,
+        bad_blocks: bad_blocks.iter().copied().collect(),
+        fork_blocks: fork_blocks.iter().copied().collect(),
+        clock: Clock::fixed(Duration::from_secs(0)),
+        clock_drift_tolerance: Duration::from_secs(0),
+    })
+}
+
+#[test]
+fn bad_block_rejected() {
+    let bad_hash = header::hash_from_scale_encoded_header(child_header(3));
+    let mut tree = tree(&[bad_hash], &[]);
+
+    assert!(matches!(
+        tree.verify_header(child_header(3)),
+        Err(HeaderVerifyError::BadBlock)
+    ));
+    assert!(matches!(
+        tree.verify_body(child_header(3)),
+        BodyVerifyStep1::BadBlock(_)
+    ));
+}
+
+#[test]
+fn fork_block_mismatch_rejected() {
+    let expected = header::hash_from_scale_encoded_header(child_header(3));
+    let mut tree = tree(&[], &[(1, expected)]);
+
+    assert!(matches!(
+        tree.verify_header(child_header(4)),
+        Err(HeaderVerifyError::ForkBlockMismatch { expected_hash }) if expected_hash == expected
+    ));
+    assert!(matches!(
+        tree.verify_body(child_header(4)),
+        BodyVerifyStep1::ForkBlockMismatch { expected_hash, .. } if expected_hash == expected
+    ));
+}
+
+#[test]
+fn fork_block_match_accepted() {
+    let expected = header::hash_from_scale_encoded_header(child_header(3));
+    let tree = tree(&[], &[(1, expected)]);
+
+    // The block passes the checks against the bad and fork blocks, and its verification
+    // continues by requesting the runtime of its parent.
+    assert!(matches!(
+        tree.verify_body(child_header(3)),
+        BodyVerifyStep1::ParentRuntimeRequired(_)
+    ));
+}
@@ -33,7 +33,7 @@ use super::{
     FinalizedConsensus, NonFinalizedTree, NonFinalizedTreeInner, Vec,
 };
 
-use alloc::boxed::Box;
+use alloc::{boxed::Box, collections::BTreeMap};
 use core::cmp::Ordering;
 
 pub use verify::header_body::TrieEntryVersion;
@@ -47,15 +47,14 @@ impl<T> NonFinalizedTree<T> {
     /// If the verification succeeds, an [`HeaderInsert`] object might be returned which can be
     /// used to then insert the block in the chain.
     ///
-    /// Must be passed the current UNIX time in order to verify that the block doesn't pretend to
-    /// come from the future.
+    /// The current time, used in order to verify that the block doesn't pretend to come from the
+    /// future, is obtained from [`Config::clock`].
     pub fn verify_header(
         &mut self,
         scale_encoded_header: Vec<u8>,
-        now_from_unix_epoch: Duration,
     ) -> Result<HeaderVerifySuccess<T>, HeaderVerifyError> {
         let self_inner = self.inner.take().unwrap();
-        match self_inner.verify(scale_encoded_header, now_from_unix_epoch, false) {
+        match self_inner.verify(scale_encoded_header, false) {
             VerifyOut::HeaderErr(self_inner, err) => {
                 self.inner = Some(self_inner);
                 Err(err)
@@ -95,18 +94,10 @@ impl<T> NonFinalizedTree<T> {
     /// back. The state of the [`NonFinalizedTree`] isn't modified until [`BodyInsert::insert`] is
     /// called after the end of the verification.
     ///
-    /// Must be passed the current UNIX time in order to verify that the block doesn't pretend to
-    /// come from the future.
-    pub fn verify_body(
-        self,
-        scale_encoded_header: Vec<u8>,
-        now_from_unix_epoch: Duration,
-    ) -> BodyVerifyStep1<T> {
-        match self
-            .inner
-            .unwrap()
-            .verify(scale_encoded_header, now_from_unix_epoch, true)
-        {
+    /// The current time, used in order to verify that the block doesn't pretend to come from the
+    /// future and as the value of the timestamp inherent, is obtained from [`Config::clock`].
+    pub fn verify_body(self, scale_encoded_header: Vec<u8>) -> BodyVerifyStep1<T> {
+        match self.inner.unwrap().verify(scale_encoded_header, true) {
             VerifyOut::Body(step) => step,
             VerifyOut::HeaderDuplicate(..) | VerifyOut::HeaderOk(..) | VerifyOut::HeaderErr(..) => {
                 // Can't happen when asked for full verification.
@@ -119,12 +110,9 @@ impl<T> NonFinalizedTree<T> {
 impl<T> NonFinalizedTreeInner<T> {
     /// Common implementation for both [`NonFinalizedTree::verify_header`] and
     /// [`NonFinalizedTree::verify_body`].
-    fn verify(
-        self: Box<Self>,
-        scale_encoded_header: Vec<u8>,
-        now_from_unix_epoch: Duration,
-        full: bool,
-    ) -> VerifyOut<T> {
+    fn verify(self: Box<Self>, scale_encoded_header: Vec<u8>, full: bool) -> VerifyOut<T> {
+        let now_from_unix_epoch = self.clock.now_from_unix_epoch();
+
         let decoded_header = match header::decode(&scale_encoded_header, self.block_number_bytes) {
             Ok(h) => h,
             Err(err) => {
@@ -152,6 +140,33 @@ impl<T> NonFinalizedTreeInner<T> {
             };
         }
 
+        // Check whether the block has been explicitly rejected.
+        if self.bad_blocks.contains(&hash) {
+            return if full {
+                VerifyOut::Body(BodyVerifyStep1::BadBlock(NonFinalizedTree {
+                    inner: Some(self),
+                }))
+            } else {
+                VerifyOut::HeaderErr(self, HeaderVerifyError::BadBlock)
+            };
+        }
+        if let Some(expected_hash) = self.fork_blocks.get(&decoded_header.number) {
+            if *expected_hash != hash {
+                let expected_hash = *expected_hash;
+                return if full {
+                    VerifyOut::Body(BodyVerifyStep1::ForkBlockMismatch {
+                        chain: NonFinalizedTree { inner: Some(self) },
+                        expected_hash,
+                    })
+                } else {
+                    VerifyOut::HeaderErr(
+                        self,
+                        HeaderVerifyError::ForkBlockMismatch { expected_hash },
+                    )
+                };
+            }
+        }
+
         // Try to find the parent block in the tree of known blocks.
         // `Some` with an index of the parent within the tree of unfinalized blocks.
         // `None` means that the parent is the finalized block.
@@ -256,6 +271,7 @@ impl<T> NonFinalizedTreeInner<T> {
                         ),
                         now_from_unix_epoch,
                         slot_duration: *slot_duration,
+                        clock_drift_tolerance: context.chain.clock_drift_tolerance,
                     },
                     (
                         FinalizedConsensus::Babe {
@@ -270,6 +286,7 @@ impl<T> NonFinalizedTreeInner<T> {
                         parent_block_next_epoch: (&**next_epoch).into(),
                         slots_per_epoch: *slots_per_epoch,
                         now_from_unix_epoch,
+                        vrf_cache: Some(&mut context.chain.babe_vrf_cache),
                     },
                     (FinalizedConsensus::Unknown, None) => {
                         return VerifyOut::HeaderErr(
@@ -289,6 +306,7 @@ impl<T> NonFinalizedTreeInner<T> {
                     BlockFinality::Grandpa { .. } => verify::header_only::ConfigFinality::Grandpa,
                 },
                 allow_unknown_consensus_engines: context.chain.allow_unknown_consensus_engines,
+                skip_seal_verification: context.chain.skip_seal_verification,
                 block_header: (&*context.header).into(), // TODO: inefficiency ; in case of header only verify we do an extra allocation to build the context above
                 block_number_bytes: context.chain.block_number_bytes,
                 parent_block_header: parent_block_header.into(),
@@ -599,6 +617,7 @@ impl<T> VerifyContext<T> {
                     parent_runtime: success.parent_runtime,
                     new_runtime: success.new_runtime,
                     storage_main_trie_changes: success.storage_main_trie_changes,
+                    storage_child_tries_changes: success.storage_child_tries_changes,
                     state_trie_version: success.state_trie_version,
                     offchain_storage_changes: success.offchain_storage_changes,
                     main_trie_root_calculation_cache: success.main_trie_root_calculation_cache,
@@ -626,6 +645,18 @@ impl<T> VerifyContext<T> {
                     inner,
                 })
             }
+            verify::header_body::Verify::ChildStorageGet(inner) => {
+                BodyVerifyStep2::ChildStorageGet(ChildStorageGet {
+                    context: self,
+                    inner,
+                })
+            }
+            verify::header_body::Verify::ChildStorageRoot(inner) => {
+                BodyVerifyStep2::ChildStorageRoot(ChildStorageRoot {
+                    context: self,
+                    inner,
+                })
+            }
             verify::header_body::Verify::StorageNextKey(inner) => {
                 BodyVerifyStep2::StorageNextKey(StorageNextKey {
                     context: self,
@@ -667,6 +698,17 @@ pub enum BodyVerifyStep1<T> {
         parent_hash: [u8; 32],
     },
 
+    /// The hash of the block is in the list of bad blocks. See [`super::Config::bad_blocks`].
+    BadBlock(NonFinalizedTree<T>),
+
+    /// The height of the block is in the list of fork blocks, but the hash of the block
+    /// doesn't match. See [`super::Config::fork_blocks`].
+    ForkBlockMismatch {
+        chain: NonFinalizedTree<T>,
+        /// Hash that the block at this height is expected to have.
+        expected_hash: [u8; 32],
+    },
+
     /// Verification is pending. In order to continue, a [`host::HostVmPrototype`] of the
     /// runtime of the parent block must be provided.
     ParentRuntimeRequired(BodyVerifyRuntimeRequired<T>),
@@ -738,7 +780,7 @@ impl<T> BodyVerifyRuntimeRequired<T> {
     /// While `main_trie_root_calculation_cache` is optional, providing a value will considerably
     /// speed up the calculation.
     pub fn resume(
-        self,
+        mut self,
         parent_runtime: host::HostVmPrototype,
         block_body: impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone,
         main_trie_root_calculation_cache: Option<calculate_root::CalculationCache>,
@@ -774,6 +816,7 @@ impl<T> BodyVerifyRuntimeRequired<T> {
             ) => verify::header_body::ConfigConsensus::Aura {
                 current_authorities: header::AuraAuthoritiesIter::from_slice(authorities_list),
                 slot_duration: *slot_duration,
+                clock_drift_tolerance: self.context.chain.clock_drift_tolerance,
             },
             (
                 FinalizedConsensus::Babe {
@@ -787,6 +830,7 @@ impl<T> BodyVerifyRuntimeRequired<T> {
                 parent_block_epoch: current_epoch.as_ref().map(|v| (&**v).into()),
                 parent_block_next_epoch: (&**next_epoch).into(),
                 slots_per_epoch: *slots_per_epoch,
+                vrf_cache: Some(&mut self.context.chain.babe_vrf_cache),
             },
             _ => {
                 return BodyVerifyStep2::Error {
@@ -803,6 +847,7 @@ impl<T> BodyVerifyRuntimeRequired<T> {
             parent_runtime,
             consensus: config_consensus,
             allow_unknown_consensus_engines: self.context.chain.allow_unknown_consensus_engines,
+            skip_seal_verification: self.context.chain.skip_seal_verification,
             now_from_unix_epoch: self.now_from_unix_epoch,
             block_header: (&*self.context.header).into(),
             block_number_bytes: self.context.chain.block_number_bytes,
@@ -846,8 +891,12 @@ pub enum BodyVerifyStep2<T> {
         new_runtime: Option<host::HostVmPrototype>,
         /// List of changes to the storage main trie that the block performs.
         storage_main_trie_changes: storage_diff::TrieDiff,
+        /// List of changes to the default child tries that the block performs. Keys are the
+        /// names of the child tries, without the `:child_storage:default:` prefix.
+        storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
         /// State trie version indicated by the runtime. All the storage changes indicated by
-        /// [`BodyVerifyStep2::Finished::storage_main_trie_changes`] should store this version
+        /// [`BodyVerifyStep2::Finished::storage_main_trie_changes`] and
+        /// [`BodyVerifyStep2::Finished::storage_child_tries_changes`] should store this version
         /// alongside with them.
         state_trie_version: TrieEntryVersion,
         /// List of changes to the off-chain storage that this block performs.
@@ -874,6 +923,10 @@ pub enum BodyVerifyStep2<T> {
     StoragePrefixKeys(StoragePrefixKeys<T>),
     /// Fetching the key that follows a given one is required in order to continue.
     StorageNextKey(StorageNextKey<T>),
+    /// Loading a storage value of a child trie is required in order to continue.
+    ChildStorageGet(ChildStorageGet<T>),
+    /// Fetching the list of all the keys of a child trie is required in order to continue.
+    ChildStorageRoot(ChildStorageRoot<T>),
     /// A new runtime must be compiled.
     ///
     /// This variant doesn't require any specific input from the user, but is provided in order to
@@ -960,6 +1013,12 @@ pub struct StoragePrefixKeys<T> {
 }
 
 impl<T> StoragePrefixKeys<T> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix, or `None` if the keys of the main trie must be loaded.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the prefix whose keys to load.
     pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.prefix()
@@ -1019,6 +1078,12 @@ pub struct StorageNextKey<T> {
 }
 
 impl<T> StorageNextKey<T> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix, or `None` if it belongs to the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the key whose next key must be passed back.
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.key()
@@ -1072,6 +1137,131 @@ impl<T> StorageNextKey<T> {
     }
 }
 
+/// Loading a storage value of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageGet<T> {
+    inner: verify::header_body::ChildStorageGet,
+    context: VerifyContext<T>,
+}
+
+impl<T> ChildStorageGet<T> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Returns the key whose value must be passed to [`ChildStorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.key()
+    }
+
+    /// Access to the Nth ancestor's information and hierarchy. Returns `None` if `n` is too
+    /// large. A value of `0` for `n` corresponds to the parent block. A value of `1` corresponds
+    /// to the parent's parent. And so on.
+    pub fn nth_ancestor(&mut self, n: u64) -> Option<BlockAccess<T>> {
+        let parent_index = self.context.parent_tree_index?;
+        let n = usize::try_from(n).ok()?;
+        let ret = self
+            .context
+            .chain
+            .blocks
+            .node_to_root_path(parent_index)
+            .nth(n)?;
+        Some(BlockAccess {
+            tree: &mut self.context.chain,
+            node_index: ret,
+        })
+    }
+
+    /// Returns the number of non-finalized blocks in the tree that are ancestors to the block
+    /// being verified.
+    pub fn num_non_finalized_ancestors(&self) -> u64 {
+        let parent_index = match self.context.parent_tree_index {
+            Some(p) => p,
+            None => return 0,
+        };
+
+        u64::try_from(
+            self.context
+                .chain
+                .blocks
+                .node_to_root_path(parent_index)
+                .count(),
+        )
+        .unwrap()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> BodyVerifyStep2<T> {
+        let inner = self.inner.inject_value(value);
+        self.context.with_body_verify(inner)
+    }
+}
+
+/// Fetching the list of all the keys of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageRoot<T> {
+    inner: verify::header_body::ChildStorageRoot,
+    context: VerifyContext<T>,
+}
+
+impl<T> ChildStorageRoot<T> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Access to the Nth ancestor's information and hierarchy. Returns `None` if `n` is too
+    /// large. A value of `0` for `n` corresponds to the parent block. A value of `1` corresponds
+    /// to the parent's parent. And so on.
+    pub fn nth_ancestor(&mut self, n: u64) -> Option<BlockAccess<T>> {
+        let parent_index = self.context.parent_tree_index?;
+        let n = usize::try_from(n).ok()?;
+        let ret = self
+            .context
+            .chain
+            .blocks
+            .node_to_root_path(parent_index)
+            .nth(n)?;
+        Some(BlockAccess {
+            tree: &mut self.context.chain,
+            node_index: ret,
+        })
+    }
+
+    /// Returns the number of non-finalized blocks in the tree that are ancestors to the block
+    /// being verified.
+    pub fn num_non_finalized_ancestors(&self) -> u64 {
+        let parent_index = match self.context.parent_tree_index {
+            Some(p) => p,
+            None => return 0,
+        };
+
+        u64::try_from(
+            self.context
+                .chain
+                .blocks
+                .node_to_root_path(parent_index)
+                .count(),
+        )
+        .unwrap()
+    }
+
+    /// Injects the list of all the keys of the child trie, ordered lexicographically.
+    pub fn inject_keys_ordered(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> BodyVerifyStep2<T> {
+        let inner = self.inner.inject_keys_ordered(keys);
+        self.context.with_body_verify(inner)
+    }
+}
+
 /// A new runtime must be compiled.
 ///
 /// This variant doesn't require any specific input from the user, but is provided in order to
@@ -1200,6 +1390,16 @@ pub enum HeaderVerifyError {
         /// Hash of the parent block in question.
         parent_hash: [u8; 32],
     },
+    /// The hash of the block is in the list of bad blocks. See [`super::Config::bad_blocks`].
+    #[display(fmt = "The block is in the list of bad blocks.")]
+    BadBlock,
+    /// The height of the block is in the list of fork blocks, but the hash of the block doesn't
+    /// match. See [`super::Config::fork_blocks`].
+    #[display(fmt = "The hash of the block doesn't match the one in the list of fork blocks.")]
+    ForkBlockMismatch {
+        /// Hash that the block at this height is expected to have.
+        expected_hash: [u8; 32],
+    },
     /// The block verification has failed. The block is invalid and should be thrown away.
     #[display(fmt = "{_0}")]
     VerificationFailed(verify::header_only::Error),
@@ -0,0 +1,478 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Compact commitment to the state of a chain at a finalized block.
+//!
+//! A [`StateCommitment`] contains the hash, height, and state root of a finalized block, the
+//! GrandPa authorities set in charge of finalizing the children of this block, and the root of
+//! the Merkle Mountain Range (MMR) of the chain at this block if the chain has one. It can be
+//! turned into bytes with [`StateCommitment::encode`] in order to be persisted, for example as
+//! part of an audit trail, and decoded back with [`StateCommitment::decode`].
+//!
+//! The commitment isn't signed. Its encoding ends with a BLAKE2 hash of the rest of the encoded
+//! commitment, which makes it possible to detect corruptions but not malicious modifications.
+//! Instead, a commitment can be re-verified at a later point in time by comparing it with the
+//! header of the block (see [`StateCommitment::check_header`]) and with the content of its
+//! storage (see [`StateCommitment::check_storage`]).
+//!
+//! Determining the exact location of the GrandPa authorities and of the MMR root in the storage
+//! would require parsing the metadata provided by the runtime. Instead, this module assumes the
+//! layout used by the Substrate and Polkadot runtimes: the authorities are found under the
+//! `:grandpa_authorities` key, the identifier of the set under `Grandpa.CurrentSetId`, and the
+//! MMR root under `Mmr.RootHash`.
+
+use crate::{chain::chain_information, header, util};
+
+use alloc::vec::Vec;
+use core::num::NonZeroU64;
+
+/// See the module-level documentation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StateCommitment {
+    /// Hash of the block the commitment refers to.
+    pub block_hash: [u8; 32],
+    /// Height of the block the commitment refers to.
+    pub block_number: u64,
+    /// State trie root of the block the commitment refers to.
+    pub state_root: [u8; 32],
+    /// GrandPa authorities set that must finalize the children of the block. `None` if the
+    /// finality of the chain isn't handled by GrandPa, for example because it is a parachain.
+    pub grandpa_authorities: Option<GrandpaAuthoritiesSet>,
+    /// Root of the Merkle Mountain Range of the chain at the block. `None` if the chain doesn't
+    /// have any.
+    pub mmr_root: Option<[u8; 32]>,
+}
+
+/// See [`StateCommitment::grandpa_authorities`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GrandpaAuthoritiesSet {
+    /// Identifier of the authorities set, incremented by one every time the set changes.
+    pub set_id: u64,
+    /// List of authorities of the set.
+    pub authorities: Vec<header::GrandpaAuthority>,
+}
+
+impl StateCommitment {
+    /// Builds a commitment from the information about the finalized block of a chain.
+    ///
+    /// The root of the Merkle Mountain Range must be obtained separately, by reading the storage
+    /// entry whose key is [`mmr_root_storage_key`] and passing it through
+    /// [`decode_mmr_root`].
+    pub fn from_chain_information(
+        chain_information: chain_information::ChainInformationRef,
+        block_number_bytes: usize,
+        mmr_root: Option<[u8; 32]>,
+    ) -> Self {
+        StateCommitment {
+            block_hash: chain_information
+                .finalized_block_header
+                .hash(block_number_bytes),
+            block_number: chain_information.finalized_block_header.number,
+            state_root: *chain_information.finalized_block_header.state_root,
+            grandpa_authorities: match chain_information.finality {
+                chain_information::ChainInformationFinalityRef::Grandpa {
+                    after_finalized_block_authorities_set_id,
+                    finalized_triggered_authorities,
+                    ..
+                } => Some(GrandpaAuthoritiesSet {
+                    set_id: after_finalized_block_authorities_set_id,
+                    authorities: finalized_triggered_authorities.to_vec(),
+                }),
+                chain_information::ChainInformationFinalityRef::Outsourced => None,
+            },
+            mmr_root,
+        }
+    }
+
+    /// Turns the commitment into bytes. The output can be decoded with
+    /// [`StateCommitment::decode`].
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            1 + 32
+                + 8
+                + 32
+                + 1
+                + self
+                    .grandpa_authorities
+                    .as_ref()
+                    .map_or(0, |set| 8 + 5 + set.authorities.len() * 40)
+                + 1
+                + 32
+                + 32,
+        );
+
+        out.push(ENCODING_VERSION);
+        out.extend_from_slice(&self.block_hash);
+        out.extend_from_slice(&self.block_number.to_le_bytes());
+        out.extend_from_slice(&self.state_root);
+
+        match &self.grandpa_authorities {
+            Some(set) => {
+                out.push(1);
+                out.extend_from_slice(&set.set_id.to_le_bytes());
+                out.extend_from_slice(
+                    crate::util::encode_scale_compact_usize(set.authorities.len()).as_ref(),
+                );
+                for authority in &set.authorities {
+                    for buffer in authority.scale_encoding() {
+                        out.extend_from_slice(buffer.as_ref());
+                    }
+                }
+            }
+            None => out.push(0),
+        }
+
+        match &self.mmr_root {
+            Some(mmr_root) => {
+                out.push(1);
+                out.extend_from_slice(mmr_root);
+            }
+            None => out.push(0),
+        }
+
+        let checksum = blake2_rfc::blake2b::blake2b(32, &[], &out);
+        out.extend_from_slice(checksum.as_bytes());
+        out
+    }
+
+    /// Decodes a commitment previously encoded with [`StateCommitment::encode`].
+    pub fn decode(encoded: &[u8]) -> Result<Self, DecodeError> {
+        if encoded.len() < 32 {
+            return Err(DecodeError::InvalidFormat);
+        }
+
+        let (content, checksum) = encoded.split_at(encoded.len() - 32);
+        if blake2_rfc::blake2b::blake2b(32, &[], content).as_bytes() != checksum {
+            return Err(DecodeError::ChecksumMismatch);
+        }
+
+        match content.first() {
+            Some(&ENCODING_VERSION) => {}
+            Some(version) => return Err(DecodeError::UnsupportedVersion(*version)),
+            None => return Err(DecodeError::InvalidFormat),
+        }
+
+        let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+            nom::sequence::tuple((
+                nom::bytes::complete::take(32u32),
+                nom::number::complete::le_u64,
+                nom::bytes::complete::take(32u32),
+                crate::util::nom_option_decode(nom::combinator::map(
+                    nom::sequence::tuple((nom::number::complete::le_u64, grandpa_authorities_list)),
+                    |(set_id, authorities)| GrandpaAuthoritiesSet {
+                        set_id,
+                        authorities,
+                    },
+                )),
+                crate::util::nom_option_decode(nom::bytes::complete::take(32u32)),
+            )),
+            |(block_hash, block_number, state_root, grandpa_authorities, mmr_root)| {
+                StateCommitment {
+                    block_hash: <[u8; 32]>::try_from(block_hash).unwrap(),
+                    block_number,
+                    state_root: <[u8; 32]>::try_from(state_root).unwrap(),
+                    grandpa_authorities,
+                    mmr_root: mmr_root.map(|r: &[u8]| <[u8; 32]>::try_from(r).unwrap()),
+                }
+            },
+        ))(&content[1..]);
+
+        match result {
+            Ok((_, commitment)) => Ok(commitment),
+            Err(_) => Err(DecodeError::InvalidFormat),
+        }
+    }
+
+    /// Checks whether the given SCALE-encoded header is the header of the block the commitment
+    /// refers to.
+    ///
+    /// Since the hash of a block is the hash of its header, a successful check proves that the
+    /// block height and state root of the commitment are correct.
+    pub fn check_header(
+        &self,
+        scale_encoded_header: &[u8],
+        block_number_bytes: usize,
+    ) -> Result<(), CheckError> {
+        if header::hash_from_scale_encoded_header(scale_encoded_header) != self.block_hash {
+            return Err(CheckError::BlockHashMismatch);
+        }
+
+        let decoded = header::decode(scale_encoded_header, block_number_bytes)
+            .map_err(CheckError::InvalidHeader)?;
+        if decoded.number != self.block_number {
+            return Err(CheckError::BlockNumberMismatch);
+        }
+        if *decoded.state_root != self.state_root {
+            return Err(CheckError::StateRootMismatch);
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the storage of the block the commitment refers to matches the commitment.
+    ///
+    /// The parameters must be the storage values, as found in the storage of the block, whose
+    /// keys are respectively [`GRANDPA_AUTHORITIES_STORAGE_KEY`],
+    /// [`grandpa_current_set_id_storage_key`], and [`mmr_root_storage_key`]. They must have been
+    /// obtained through a proof verified against [`StateCommitment::state_root`].
+    ///
+    /// The GrandPa authorities aren't checked if [`StateCommitment::grandpa_authorities`] is
+    /// `None`.
+    pub fn check_storage(
+        &self,
+        grandpa_authorities: Option<&[u8]>,
+        grandpa_current_set_id: Option<&[u8]>,
+        mmr_root: Option<&[u8]>,
+    ) -> Result<(), CheckError> {
+        if let Some(set) = &self.grandpa_authorities {
+            let authorities = decode_grandpa_authorities(grandpa_authorities.unwrap_or_default())
+                .map_err(CheckError::GrandpaAuthoritiesDecode)?;
+            if authorities != set.authorities {
+                return Err(CheckError::GrandpaAuthoritiesMismatch);
+            }
+
+            let set_id = grandpa_current_set_id
+                .and_then(|value| <[u8; 8]>::try_from(value).ok())
+                .map(u64::from_le_bytes)
+                .ok_or(CheckError::GrandpaAuthoritiesDecode(
+                    DecodeError::InvalidFormat,
+                ))?;
+            if set_id != set.set_id {
+                return Err(CheckError::GrandpaSetIdMismatch);
+            }
+        }
+
+        let mmr_root = mmr_root
+            .map(decode_mmr_root)
+            .transpose()
+            .map_err(CheckError::MmrRootDecode)?;
+        if mmr_root != self.mmr_root {
+            return Err(CheckError::MmrRootMismatch);
+        }
+
+        Ok(())
+    }
+}
+
+/// Key of the storage entry containing the list of GrandPa authorities that must finalize the
+/// children of the block.
+pub const GRANDPA_AUTHORITIES_STORAGE_KEY: &[u8] = b":grandpa_authorities";
+
+/// Returns the key of the `Grandpa.CurrentSetId` storage entry.
+pub fn grandpa_current_set_id_storage_key() -> Vec<u8> {
+    storage_key(b"Grandpa", b"CurrentSetId")
+}
+
+/// Returns the key of the `Mmr.RootHash` storage entry.
+pub fn mmr_root_storage_key() -> Vec<u8> {
+    storage_key(b"Mmr", b"RootHash")
+}
+
+/// Attempt to decode the value of the `Mmr.RootHash` storage entry.
+pub fn decode_mmr_root(scale_encoded: &[u8]) -> Result<[u8; 32], DecodeError> {
+    <[u8; 32]>::try_from(scale_encoded).map_err(|_| DecodeError::InvalidFormat)
+}
+
+/// Attempt to decode the value of the storage entry whose key is
+/// [`GRANDPA_AUTHORITIES_STORAGE_KEY`].
+pub fn decode_grandpa_authorities(
+    scale_encoded: &[u8],
+) -> Result<Vec<header::GrandpaAuthority>, DecodeError> {
+    // The list of authorities is prefixed with a version number, which is always 1.
+    match scale_encoded.split_first() {
+        Some((1, list)) => {
+            let result: nom::IResult<_, _> =
+                nom::combinator::all_consuming(grandpa_authorities_list)(list);
+            result
+                .map(|(_, list)| list)
+                .map_err(|_| DecodeError::InvalidFormat)
+        }
+        Some((version, _)) => Err(DecodeError::UnsupportedVersion(*version)),
+        None => Err(DecodeError::InvalidFormat),
+    }
+}
+
+/// Error potentially returned by [`StateCommitment::decode`] and the other decoding functions
+/// of this module.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The encoded value has an invalid format.
+    InvalidFormat,
+    /// The encoded value uses a version of the format that isn't supported.
+    #[display(fmt = "Unsupported version: {_0}")]
+    UnsupportedVersion(u8),
+    /// The checksum at the end of the encoded commitment doesn't match its content.
+    ChecksumMismatch,
+}
+
+/// Error potentially returned by [`StateCommitment::check_header`] and
+/// [`StateCommitment::check_storage`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum CheckError {
+    /// The hash of the header doesn't match the hash of the block of the commitment.
+    BlockHashMismatch,
+    /// Failed to decode the header.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidHeader(header::Error),
+    /// The height of the block doesn't match the commitment.
+    BlockNumberMismatch,
+    /// The state root of the block doesn't match the commitment.
+    StateRootMismatch,
+    /// Failed to decode the GrandPa authorities found in the storage.
+    #[display(fmt = "Failed to decode GrandPa authorities: {_0}")]
+    GrandpaAuthoritiesDecode(DecodeError),
+    /// The GrandPa authorities found in the storage don't match the commitment.
+    GrandpaAuthoritiesMismatch,
+    /// The identifier of the GrandPa authorities set found in the storage doesn't match the
+    /// commitment.
+    GrandpaSetIdMismatch,
+    /// Failed to decode the MMR root found in the storage.
+    #[display(fmt = "Failed to decode MMR root: {_0}")]
+    MmrRootDecode(DecodeError),
+    /// The MMR root found in the storage doesn't match the commitment.
+    MmrRootMismatch,
+}
+
+/// Version of the format produced by [`StateCommitment::encode`].
+const ENCODING_VERSION: u8 = 0;
+
+fn grandpa_authorities_list<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], Vec<header::GrandpaAuthority>, E> {
+    nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+        nom::multi::many_m_n(
+            num_elems,
+            num_elems,
+            nom::combinator::map(
+                nom::sequence::tuple((
+                    nom::bytes::complete::take(32u32),
+                    nom::combinator::map_opt(nom::number::complete::le_u64, NonZeroU64::new),
+                )),
+                |(public_key, weight)| header::GrandpaAuthority {
+                    public_key: <[u8; 32]>::try_from(public_key).unwrap(),
+                    weight,
+                },
+            ),
+        )
+    })(bytes)
+}
+
+fn storage_key(pallet_name: &[u8], item_name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16 + 16);
+    key.extend_from_slice(&util::twox_128(pallet_name));
+    key.extend_from_slice(&util::twox_128(item_name));
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use core::num::NonZeroU64;
+
+    fn example() -> super::StateCommitment {
+        super::StateCommitment {
+            block_hash: [1; 32],
+            block_number: 12345,
+            state_root: [2; 32],
+            grandpa_authorities: Some(super::GrandpaAuthoritiesSet {
+                set_id: 7,
+                authorities: vec![
+                    crate::header::GrandpaAuthority {
+                        public_key: [3; 32],
+                        weight: NonZeroU64::new(1).unwrap(),
+                    },
+                    crate::header::GrandpaAuthority {
+                        public_key: [4; 32],
+                        weight: NonZeroU64::new(1).unwrap(),
+                    },
+                ],
+            }),
+            mmr_root: Some([5; 32]),
+        }
+    }
+
+    #[test]
+    fn encode_decode() {
+        let commitment = example();
+        let encoded = commitment.encode();
+        assert_eq!(
+            super::StateCommitment::decode(&encoded).unwrap(),
+            commitment
+        );
+
+        let commitment = super::StateCommitment {
+            grandpa_authorities: None,
+            mmr_root: None,
+            ..example()
+        };
+        let encoded = commitment.encode();
+        assert_eq!(
+            super::StateCommitment::decode(&encoded).unwrap(),
+            commitment
+        );
+    }
+
+    #[test]
+    fn corruption_detected() {
+        let mut encoded = example().encode();
+        encoded[10] ^= 1;
+        assert!(matches!(
+            super::StateCommitment::decode(&encoded),
+            Err(super::DecodeError::ChecksumMismatch)
+        ));
+        assert!(super::StateCommitment::decode(&[]).is_err());
+    }
+
+    #[test]
+    fn check_storage() {
+        let commitment = example();
+
+        let mut authorities = vec![1, 8];
+        authorities.extend_from_slice(&[3; 32]);
+        authorities.extend_from_slice(&1u64.to_le_bytes());
+        authorities.extend_from_slice(&[4; 32]);
+        authorities.extend_from_slice(&1u64.to_le_bytes());
+
+        commitment
+            .check_storage(
+                Some(&authorities),
+                Some(&7u64.to_le_bytes()),
+                Some(&[5; 32]),
+            )
+            .unwrap();
+
+        assert!(matches!(
+            commitment.check_storage(
+                Some(&authorities),
+                Some(&8u64.to_le_bytes()),
+                Some(&[5; 32])
+            ),
+            Err(super::CheckError::GrandpaSetIdMismatch)
+        ));
+        assert!(matches!(
+            commitment.check_storage(Some(&authorities), Some(&7u64.to_le_bytes()), None),
+            Err(super::CheckError::MmrRootMismatch)
+        ));
+    }
+
+    #[test]
+    fn grandpa_current_set_id_storage_key() {
+        assert_eq!(
+            hex::encode(super::grandpa_current_set_id_storage_key()),
+            "5f9cc45b7a00c5899361e1c6099678dc8a2d09463effcc78a22d75b9cb87dffc"
+        );
+    }
+}
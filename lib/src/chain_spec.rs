@@ -37,19 +37,28 @@
 use crate::{
     chain::chain_information::{
         build, BabeEpochInformation, ChainInformation, ChainInformationConsensus,
-        ChainInformationFinality, ValidChainInformation,
+        ChainInformationConsensusRef, ChainInformationFinality, ChainInformationRef,
+        ValidChainInformation,
     },
     executor, libp2p, trie,
 };
 
 use alloc::{
+    borrow::ToOwned,
     string::{String, ToString as _},
     vec::Vec,
 };
 use core::{iter, num::NonZeroU64};
 
+mod genesis_storage;
+mod incremental;
 mod light_sync_state;
 mod structs;
+mod validate;
+
+pub use genesis_storage::{GenesisStorageParseError, GenesisStorageParser, SeparateGenesisStorage};
+pub use incremental::IncrementalParser;
+pub use validate::{validate_detailed, ValidationWarning, MAX_RECOMMENDED_GENESIS_SIZE};
 
 /// A configuration of a chain. Can be used to build a genesis block.
 #[derive(Clone)]
@@ -59,11 +68,109 @@ pub struct ChainSpec {
 
 impl ChainSpec {
     /// Parse JSON content into a [`ChainSpec`].
+    ///
+    /// See also [`IncrementalParser`] in order to parse the JSON content chunk by chunk.
     pub fn from_json_bytes(json: impl AsRef<[u8]>) -> Result<Self, ParseError> {
         let client_spec: structs::ClientSpec = serde_json::from_slice(json.as_ref())
             .map_err(ParseErrorInner::Serde)
             .map_err(ParseError)?;
+        Self::from_client_spec(client_spec)
+    }
+
+    /// Builds a chain spec from its components.
+    ///
+    /// The chain spec can then be turned into JSON with [`ChainSpec::to_json`], for example in
+    /// order to be distributed to the nodes of a test network.
+    pub fn build(
+        config: BuildConfig<impl Iterator<Item = (impl AsRef<[u8]>, impl AsRef<[u8]>)>>,
+    ) -> Result<Self, BuildError> {
+        let genesis_storage = {
+            let mut builder = structs::RawStorageBuilder::default();
+            for (key, value) in config.genesis_storage {
+                builder.push(key.as_ref(), value.as_ref());
+            }
+            builder.build()
+        };
+
+        let properties = match config.properties {
+            Some(properties) => {
+                // Properties are always a JSON object.
+                if serde_json::from_str::<serde_json::Map<String, serde_json::Value>>(properties)
+                    .is_err()
+                {
+                    return Err(BuildError::InvalidProperties);
+                }
+                Some(
+                    serde_json::value::RawValue::from_string(properties.to_owned())
+                        .map_err(|_| BuildError::InvalidProperties)?,
+                )
+            }
+            None => None,
+        };
+
+        let client_spec = structs::ClientSpec {
+            name: config.name.to_owned(),
+            id: config.id.to_owned(),
+            chain_type: match config.chain_type {
+                "Development" => structs::ChainType::Development,
+                "Local" => structs::ChainType::Local,
+                "Live" => structs::ChainType::Live,
+                other => structs::ChainType::Custom(other.to_owned()),
+            },
+            code_substitutes: Default::default(),
+            boot_nodes: config.boot_nodes.iter().map(|b| (*b).to_owned()).collect(),
+            telemetry_endpoints: if config.telemetry_endpoints.is_empty() {
+                None
+            } else {
+                Some(
+                    config
+                        .telemetry_endpoints
+                        .iter()
+                        .map(|(url, verbosity)| ((*url).to_owned(), *verbosity))
+                        .collect(),
+                )
+            },
+            protocol_id: config.protocol_id.map(ToOwned::to_owned),
+            fork_id: config.fork_id.map(ToOwned::to_owned),
+            // The field is omitted if it is equal to its default value, in order to remain
+            // compatible with Substrate.
+            block_number_bytes: if config.block_number_bytes == 4 {
+                None
+            } else {
+                Some(config.block_number_bytes)
+            },
+            properties,
+            fork_blocks: None,
+            bad_blocks: None,
+            consensus_engine: (),
+            genesis: structs::Genesis::Raw(structs::RawGenesis {
+                top: genesis_storage,
+                children_default: Default::default(),
+            }),
+            light_sync_state: config.light_sync_state.map(|state| {
+                light_sync_state::LightSyncState::from_encoded(
+                    state.babe_epoch_changes,
+                    state.babe_finalized_block_weight,
+                    state.finalized_block_header,
+                    state.grandpa_authority_set,
+                )
+            }),
+            relay_chain: config.relay_chain.map(|(id, _)| id.to_owned()),
+            para_id: config.relay_chain.map(|(_, para_id)| para_id),
+        };
+
+        // The only verification that can fail is the decoding of the light sync state.
+        Self::from_client_spec(client_spec).map_err(|_| BuildError::InvalidLightSyncState)
+    }
+
+    /// Serializes the chain spec into JSON. The output can be parsed back using
+    /// [`ChainSpec::from_json_bytes`].
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&self.client_spec).unwrap()
+    }
 
+    /// Performs the verifications of the content of the chain spec that serde can't perform.
+    fn from_client_spec(client_spec: structs::ClientSpec) -> Result<Self, ParseError> {
         // TODO: we don't support child tries in the genesis block
         assert!(match &client_spec.genesis {
             structs::Genesis::Raw(genesis) => genesis.children_default.is_empty(),
@@ -91,6 +198,51 @@ impl ChainSpec {
     pub fn as_chain_information(
         &self,
     ) -> Result<(ValidChainInformation, executor::host::HostVmPrototype), FromGenesisStorageError>
+    {
+        self.as_chain_information_inner(None)
+    }
+
+    /// Similar to [`ChainSpec::as_chain_information`], but uses the given value as the hash of
+    /// the root of the trie of the genesis storage instead of calculating it.
+    ///
+    /// Calculating the trie root hash of the genesis storage is expensive. This function is
+    /// useful if this value is already known, or if the chain information is needed before it
+    /// could be calculated. The value isn't verified. Passing a wrong value leads to the header
+    /// of the genesis block in the returned [`ValidChainInformation`] being wrong.
+    ///
+    /// See also [`GenesisStorageItems::trie_root_hash_calculation`].
+    pub fn as_chain_information_with_state_root(
+        &self,
+        state_trie_root_hash: &[u8; 32],
+    ) -> Result<(ValidChainInformation, executor::host::HostVmPrototype), FromGenesisStorageError>
+    {
+        self.as_chain_information_inner(Some(state_trie_root_hash))
+    }
+
+    /// Similar to [`ChainSpec::as_chain_information`], but doesn't calculate the hash of the
+    /// root of the trie of the genesis storage.
+    ///
+    /// Calculating the trie root hash of the genesis storage is expensive. This function makes
+    /// it possible to verify the genesis storage and to obtain the state version needed by
+    /// [`GenesisStorageItems::trie_root_hash_calculation`], then to finish building the chain
+    /// information with [`ChainInformationWithoutStateRoot::with_state_root`] once the
+    /// calculation is over.
+    pub fn as_chain_information_without_state_root(
+        &self,
+    ) -> Result<ChainInformationWithoutStateRoot, FromGenesisStorageError> {
+        // The state root is only used in order to build the header of the finalized block, which
+        // is overwritten in `ChainInformationWithoutStateRoot::with_state_root`.
+        let (chain_information, runtime) = self.as_chain_information_inner(Some(&[0; 32]))?;
+        Ok(ChainInformationWithoutStateRoot {
+            chain_information: chain_information.into(),
+            runtime,
+        })
+    }
+
+    fn as_chain_information_inner(
+        &self,
+        state_trie_root_hash: Option<&[u8; 32]>,
+    ) -> Result<(ValidChainInformation, executor::host::HostVmPrototype), FromGenesisStorageError>
     {
         let genesis_storage = match self.genesis_storage() {
             GenesisStorage::Items(items) => items,
@@ -115,38 +267,21 @@ impl ChainSpec {
 
         let mut chain_information_build = build::ChainInformationBuild::new(build::Config {
             finalized_block_header: build::ConfigFinalizedBlockHeader::Genesis {
-                state_trie_root_hash: {
-                    let state_version = vm_prototype
-                        .runtime_version()
-                        .decode()
-                        .state_version
-                        .unwrap_or(trie::TrieEntryVersion::V0);
-
-                    match self.genesis_storage() {
-                        GenesisStorage::TrieRootHash(hash) => *hash,
-                        GenesisStorage::Items(genesis_storage) => {
-                            let mut calculation = trie::calculate_root::root_merkle_value(None);
-
-                            loop {
-                                match calculation {
-                                trie::calculate_root::RootMerkleValueCalculation::Finished {
-                                    hash,
-                                    ..
-                                } => break hash,
-                                trie::calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
-                                    calculation = keys.inject(
-                                        genesis_storage.iter().map(|(k, _)| k.iter().copied()),
-                                    );
-                                }
-                                trie::calculate_root::RootMerkleValueCalculation::StorageValue(
-                                    val,
-                                ) => {
-                                    let key: alloc::vec::Vec<u8> = val.key().collect();
-                                    let value = genesis_storage.value(&key[..]);
-                                    calculation = val.inject(value.map(move |v| (v, state_version)));
-                                }
-                            }
-                            }
+                state_trie_root_hash: match state_trie_root_hash {
+                    Some(hash) => *hash,
+                    None => {
+                        let state_version = vm_prototype
+                            .runtime_version()
+                            .decode()
+                            .state_version
+                            .unwrap_or(trie::TrieEntryVersion::V0);
+
+                        match genesis_storage
+                            .trie_root_hash_calculation(state_version)
+                            .advance(usize::MAX)
+                        {
+                            TrieRootHashCalculationStep::Finished(hash) => hash,
+                            TrieRootHashCalculationStep::InProgress(_) => unreachable!(),
                         }
                     }
                 },
@@ -228,6 +363,28 @@ impl ChainSpec {
             .map(|h| &h.0)
     }
 
+    /// Returns a list of block heights and hashes. The block found at each of these heights must
+    /// always be the one with the given hash. Blocks at these heights with a different hash
+    /// should be considered as invalid.
+    pub fn fork_blocks(&'_ self) -> impl Iterator<Item = (u64, &'_ [u8; 32])> + '_ {
+        self.client_spec
+            .fork_blocks
+            .as_ref()
+            .into_iter()
+            .flat_map(|l| l.iter())
+            .map(|(n, h)| (*n, &h.0))
+    }
+
+    /// Returns a list of block heights and runtime codes. The runtime code found at each of these
+    /// heights must be used instead of the `:code` found in the storage, starting from this block
+    /// and until the `spec_version` of the on-chain runtime changes.
+    pub fn code_substitutes(&'_ self) -> impl Iterator<Item = (u64, &'_ [u8])> + '_ {
+        self.client_spec
+            .code_substitutes
+            .iter()
+            .map(|(n, code)| (*n, &code.0[..]))
+    }
+
     /// Returns the list of bootnode addresses found in the chain spec.
     ///
     /// Bootnode addresses that have failed to be parsed are returned as well in the form of
@@ -299,6 +456,27 @@ impl ChainSpec {
         }
     }
 
+    /// Replaces the genesis storage of the chain specification with the given one.
+    ///
+    /// This is typically used when the chain specification only contains the hash of the root
+    /// of the trie of the genesis storage, and the genesis storage items are provided
+    /// separately, for example decoded using a [`GenesisStorageParser`].
+    ///
+    /// No verification is performed. If the chain specification contains a genesis trie root
+    /// hash, it is the responsibility of the caller to make sure that it matches the storage
+    /// items, otherwise the genesis block hash will not match the one of the rest of the network.
+    pub fn set_genesis_storage(&mut self, storage: SeparateGenesisStorage) {
+        let children_default = match &mut self.client_spec.genesis {
+            structs::Genesis::Raw(raw) => core::mem::take(&mut raw.children_default),
+            structs::Genesis::StateRootHash(_) => Default::default(),
+        };
+
+        self.client_spec.genesis = structs::Genesis::Raw(structs::RawGenesis {
+            top: storage.storage,
+            children_default,
+        });
+    }
+
     /// Returns a list of arbitrary properties contained in the chain specs, such as the name of
     /// the token or the number of decimals.
     ///
@@ -324,6 +502,65 @@ impl ChainSpec {
     }
 }
 
+/// Configuration for [`ChainSpec::build`].
+pub struct BuildConfig<'a, TGenesisStorage> {
+    /// Name of the chain. Meant to be displayed to the user. See [`ChainSpec::name`].
+    pub name: &'a str,
+
+    /// Identifier of the chain. See [`ChainSpec::id`].
+    pub id: &'a str,
+
+    /// Type of the chain. `"Development"`, `"Local"`, and `"Live"` are recognized, and any other
+    /// value is considered as a custom type. See [`ChainSpec::chain_type`].
+    pub chain_type: &'a str,
+
+    /// List of storage keys and values of the genesis block. If the same key is found multiple
+    /// times, the last value is kept.
+    pub genesis_storage: TGenesisStorage,
+
+    /// Multiaddresses of the bootnodes of the chain, including the trailing `/p2p/...`.
+    pub boot_nodes: &'a [&'a str],
+
+    /// Multiaddresses of the default telemetry servers of the chain, and the verbosity of the
+    /// telemetry sent to each of them.
+    pub telemetry_endpoints: &'a [(&'a str, u8)],
+
+    /// Network protocol id of the chain. See [`ChainSpec::protocol_id`].
+    pub protocol_id: Option<&'a str>,
+
+    /// Fork id of the chain. See [`ChainSpec::fork_id`].
+    pub fork_id: Option<&'a str>,
+
+    /// Number of bytes of the "block number" field of various data structures. Should be `4`
+    /// for chains based on Substrate.
+    pub block_number_bytes: u8,
+
+    /// JSON-formatted map of arbitrary properties, such as the name of the token or the number
+    /// of decimals. See [`ChainSpec::properties`].
+    pub properties: Option<&'a str>,
+
+    /// If the chain is a parachain, identifier of the relay chain and id of the parachain.
+    pub relay_chain: Option<(&'a str, u32)>,
+
+    /// Checkpoint allowing light clients to start syncing from a recent block.
+    pub light_sync_state: Option<BuildLightSyncState<'a>>,
+}
+
+/// See [`BuildConfig::light_sync_state`].
+///
+/// Each field contains the SCALE encoding of the corresponding Substrate data structure, as
+/// found in the `lightSyncState` field of the chain specs generated by Substrate.
+pub struct BuildLightSyncState<'a> {
+    /// SCALE-encoded header of the finalized block of the checkpoint.
+    pub finalized_block_header: &'a [u8],
+    /// SCALE-encoded Babe epoch changes.
+    pub babe_epoch_changes: &'a [u8],
+    /// Babe weight of the finalized block of the checkpoint.
+    pub babe_finalized_block_weight: u32,
+    /// SCALE-encoded GrandPa authority set.
+    pub grandpa_authority_set: &'a [u8],
+}
+
 /// See [`ChainSpec::boot_nodes`].
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Bootnode<'a> {
@@ -375,6 +612,55 @@ impl<'a> GenesisStorage<'a> {
     }
 }
 
+/// Chain information of the genesis block whose header doesn't contain the hash of the root of
+/// the trie of the genesis storage yet.
+///
+/// See [`ChainSpec::as_chain_information_without_state_root`].
+pub struct ChainInformationWithoutStateRoot {
+    /// Chain information whose finalized block header contains a placeholder state root.
+    chain_information: ChainInformation,
+    /// Runtime of the genesis block.
+    runtime: executor::host::HostVmPrototype,
+}
+
+impl ChainInformationWithoutStateRoot {
+    /// Returns the runtime of the genesis block.
+    pub fn runtime(&self) -> &executor::host::HostVmPrototype {
+        &self.runtime
+    }
+
+    /// Returns the consensus algorithm used by the chain at the genesis block.
+    pub fn consensus(&self) -> ChainInformationConsensusRef<'_> {
+        ChainInformationRef::from(&self.chain_information).consensus
+    }
+
+    /// Returns the state version to pass to
+    /// [`GenesisStorageItems::trie_root_hash_calculation`].
+    pub fn state_version(&self) -> trie::TrieEntryVersion {
+        self.runtime
+            .runtime_version()
+            .decode()
+            .state_version
+            .unwrap_or(trie::TrieEntryVersion::V0)
+    }
+
+    /// Finishes building the chain information using the given hash of the root of the trie of
+    /// the genesis storage.
+    ///
+    /// The value isn't verified. Passing a wrong value leads to the header of the genesis block
+    /// in the returned [`ValidChainInformation`] being wrong.
+    pub fn with_state_root(
+        self,
+        state_trie_root_hash: &[u8; 32],
+    ) -> (ValidChainInformation, executor::host::HostVmPrototype) {
+        let mut chain_information = self.chain_information;
+        chain_information.finalized_block_header.state_root = *state_trie_root_hash;
+        // The state root isn't verified when building a `ValidChainInformation`.
+        let chain_information = ValidChainInformation::try_from(chain_information).unwrap();
+        (chain_information, self.runtime)
+    }
+}
+
 /// See [`GenesisStorage`].
 pub struct GenesisStorageItems<'a> {
     raw: &'a structs::RawGenesis,
@@ -383,17 +669,114 @@ pub struct GenesisStorageItems<'a> {
 impl<'a> GenesisStorageItems<'a> {
     /// Returns the list of storage keys and values of the genesis block.
     pub fn iter(&self) -> impl ExactSizeIterator<Item = (&[u8], &[u8])> + Clone {
-        self.raw.top.iter().map(|(k, v)| (&k.0[..], &v.0[..]))
+        self.raw.top.iter()
     }
 
     /// Returns the genesis storage value for a specific key.
     ///
     /// Returns `None` if there is no value corresponding to that key.
     pub fn value(&self, key: &[u8]) -> Option<&[u8]> {
-        self.raw.top.get(key).map(|value| &value.0[..])
+        self.raw.top.get(key)
+    }
+
+    /// Returns a hash of the list of storage keys and values of the genesis block.
+    ///
+    /// This hash has no relationship with the trie root hash of the genesis storage, but is
+    /// considerably cheaper to calculate. Two genesis storages with the same items always have
+    /// the same hash.
+    pub fn content_hash(&self) -> [u8; 32] {
+        let mut hasher = blake2_rfc::blake2b::Blake2b::new(32);
+        for (key, value) in self.iter() {
+            hasher.update(&u64::try_from(key.len()).unwrap().to_le_bytes());
+            hasher.update(key);
+            hasher.update(&u64::try_from(value.len()).unwrap().to_le_bytes());
+            hasher.update(value);
+        }
+        <[u8; 32]>::try_from(hasher.finalize().as_bytes()).unwrap()
+    }
+
+    /// Encodes the list of storage keys and values of the genesis block in the binary format
+    /// decoded by [`GenesisStorageParser`].
+    pub fn to_binary(&self) -> Vec<u8> {
+        genesis_storage::encode(self.iter())
+    }
+
+    /// Starts calculating the hash of the root of the trie of the genesis storage.
+    ///
+    /// The state version to pass can be found in the runtime version of the runtime of the
+    /// genesis block.
+    pub fn trie_root_hash_calculation(
+        &self,
+        state_version: trie::TrieEntryVersion,
+    ) -> TrieRootHashCalculation<'a> {
+        TrieRootHashCalculation {
+            raw: self.raw,
+            state_version,
+            inner: trie::calculate_root::root_merkle_value(None),
+            num_values_injected: 0,
+        }
     }
 }
 
+/// Calculation of the hash of the root of the trie of the genesis storage, performed in
+/// multiple steps.
+///
+/// See [`GenesisStorageItems::trie_root_hash_calculation`].
+pub struct TrieRootHashCalculation<'a> {
+    raw: &'a structs::RawGenesis,
+    state_version: trie::TrieEntryVersion,
+    inner: trie::calculate_root::RootMerkleValueCalculation,
+    num_values_injected: usize,
+}
+
+impl<'a> TrieRootHashCalculation<'a> {
+    /// Returns the number of storage values that have been included in the calculation so far,
+    /// and the total number of storage values.
+    ///
+    /// The first call to [`TrieRootHashCalculation::advance`] builds the structure of the trie
+    /// and doesn't make this number progress.
+    pub fn progress(&self) -> (usize, usize) {
+        (self.num_values_injected, self.raw.top.iter().len())
+    }
+
+    /// Advances the calculation by including at most `max_values` storage values.
+    pub fn advance(mut self, max_values: usize) -> TrieRootHashCalculationStep<'a> {
+        let mut num_values = 0;
+
+        loop {
+            match self.inner {
+                trie::calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => {
+                    break TrieRootHashCalculationStep::Finished(hash)
+                }
+                trie::calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
+                    self.inner = keys.inject(self.raw.top.iter().map(|(k, _)| k.iter().copied()));
+                }
+                trie::calculate_root::RootMerkleValueCalculation::StorageValue(val)
+                    if num_values < max_values =>
+                {
+                    let key: Vec<u8> = val.key().collect();
+                    let value = self.raw.top.get(&key[..]);
+                    self.inner = val.inject(value.map(|v| (v, self.state_version)));
+                    num_values += 1;
+                    self.num_values_injected += 1;
+                }
+                inner @ trie::calculate_root::RootMerkleValueCalculation::StorageValue(_) => {
+                    self.inner = inner;
+                    break TrieRootHashCalculationStep::InProgress(self);
+                }
+            }
+        }
+    }
+}
+
+/// Outcome of [`TrieRootHashCalculation::advance`].
+pub enum TrieRootHashCalculationStep<'a> {
+    /// The calculation is finished. Contains the hash of the root of the trie.
+    Finished([u8; 32]),
+    /// The calculation must continue.
+    InProgress(TrieRootHashCalculation<'a>),
+}
+
 pub struct LightSyncState {
     inner: light_sync_state::DecodedLightSyncState,
 }
@@ -481,6 +864,15 @@ enum ParseErrorInner {
     Other,
 }
 
+/// Error potentially returned by [`ChainSpec::build`].
+#[derive(Debug, derive_more::Display)]
+pub enum BuildError {
+    /// The properties aren't a JSON-formatted map.
+    InvalidProperties,
+    /// Failed to decode the light sync state.
+    InvalidLightSyncState,
+}
+
 /// Error when building the chain information from the genesis storage.
 #[derive(Debug, derive_more::Display)]
 pub enum FromGenesisStorageError {
@@ -501,7 +893,11 @@ pub enum FromGenesisStorageError {
 
 #[cfg(test)]
 mod tests {
-    use super::{Bootnode, ChainSpec};
+    use super::{
+        Bootnode, BuildConfig, BuildError, ChainSpec, GenesisStorageParseError,
+        GenesisStorageParser, IncrementalParser, SeparateGenesisStorage,
+        TrieRootHashCalculationStep, ValidationWarning,
+    };
 
     #[test]
     fn can_decode_polkadot_genesis() {
@@ -512,6 +908,10 @@ mod tests {
         // code_substitutes field
         assert_eq!(specs.client_spec.code_substitutes.get(&1), None);
         assert!(specs.client_spec.code_substitutes.get(&5203203).is_some());
+        assert_eq!(
+            specs.code_substitutes().map(|(n, _)| n).collect::<Vec<_>>(),
+            vec![5203203]
+        );
 
         // bootnodes field
         assert_eq!(
@@ -626,4 +1026,341 @@ mod tests {
         )
         .is_err());
     }
+
+    #[test]
+    fn incremental_parser_matches_non_incremental() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let expected = ChainSpec::from_json_bytes(spec).unwrap();
+
+        for chunk_size in [1, 7, 4096] {
+            let mut parser = IncrementalParser::new();
+            for chunk in spec.chunks(chunk_size) {
+                parser.push_chunk(chunk).unwrap();
+            }
+            let parsed = parser.finish().unwrap();
+
+            assert_eq!(parsed.id(), expected.id());
+            assert_eq!(parsed.boot_nodes().len(), expected.boot_nodes().len());
+            assert!(parsed
+                .genesis_storage()
+                .into_genesis_items()
+                .unwrap()
+                .iter()
+                .eq(expected
+                    .genesis_storage()
+                    .into_genesis_items()
+                    .unwrap()
+                    .iter()));
+        }
+    }
+
+    #[test]
+    fn incremental_parser_escape_sequences() {
+        // Escape sequences, including ones split between chunks, in both the storage entries
+        // and the keys that lead to them.
+        let spec = r#"{
+            "name": "Test",
+            "id": "test",
+            "bootNodes": [],
+            "gen\u0065sis": {
+              "raw": {
+                "t\u006fp": {
+                  "\u0030x0102": "0x\u0061a",
+                  "0x\u00301": "0xbb",
+                  "0x03": "\u0030\u0078"
+                },
+                "childrenDefault": {}
+              }
+            }
+          }"#
+        .as_bytes();
+        let expected = ChainSpec::from_json_bytes(spec).unwrap();
+        let expected_items = expected
+            .genesis_storage()
+            .into_genesis_items()
+            .unwrap()
+            .iter()
+            .map(|(k, v)| (k.to_vec(), v.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(expected_items.len(), 3);
+
+        for chunk_size in 1..=spec.len() {
+            let mut parser = IncrementalParser::new();
+            for chunk in spec.chunks(chunk_size) {
+                parser.push_chunk(chunk).unwrap();
+            }
+            let parsed = parser.finish().unwrap();
+            assert!(parsed
+                .genesis_storage()
+                .into_genesis_items()
+                .unwrap()
+                .iter()
+                .map(|(k, v)| (k.to_vec(), v.to_vec()))
+                .eq(expected_items.iter().cloned()));
+        }
+    }
+
+    #[test]
+    fn incremental_parser_invalid_escape_sequences() {
+        for escape in [r"\x", r"\u00", r"\ud800", r"\ud800\u0030"] {
+            let spec = format!(
+                r#"{{
+                    "name": "Test",
+                    "id": "test",
+                    "bootNodes": [],
+                    "genesis": {{
+                      "raw": {{
+                        "top": {{ "0x01": "0x{escape}" }},
+                        "childrenDefault": {{}}
+                      }}
+                    }}
+                  }}"#
+            );
+            assert!(ChainSpec::from_json_bytes(spec.as_bytes()).is_err());
+            let mut parser = IncrementalParser::new();
+            let result = parser.push_chunk(spec.as_bytes());
+            assert!(result.is_err() || parser.finish().is_err());
+        }
+    }
+
+    #[test]
+    fn incremental_parser_rejects_truncated() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let mut parser = IncrementalParser::new();
+        parser.push_chunk(&spec[..spec.len() / 2]).unwrap();
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn genesis_storage_items() {
+        let spec = r#"{
+            "name": "Test",
+            "id": "test",
+            "bootNodes": [],
+            "genesis": {
+              "raw": {
+                "top": {
+                  "0x0102": "0xaa",
+                  "0x01": "0xbbbb",
+                  "0x03": "0x",
+                  "0x01": "0xcc"
+                },
+                "childrenDefault": {}
+              }
+            }
+          }
+          "#;
+
+        let non_incremental = ChainSpec::from_json_bytes(spec).unwrap();
+        let mut incremental = IncrementalParser::new();
+        incremental.push_chunk(spec.as_bytes()).unwrap();
+        let incremental = incremental.finish().unwrap();
+
+        for spec in [non_incremental, incremental] {
+            let items = spec.genesis_storage().into_genesis_items().unwrap();
+            assert_eq!(
+                items.iter().collect::<Vec<_>>(),
+                vec![
+                    (&[0x01][..], &[0xcc][..]),
+                    (&[0x01, 0x02][..], &[0xaa][..]),
+                    (&[0x03][..], &[][..])
+                ]
+            );
+            assert_eq!(items.value(&[0x01, 0x02]), Some(&[0xaa][..]));
+            assert_eq!(items.value(&[0x02]), None);
+        }
+    }
+
+    #[test]
+    fn trie_root_hash_calculation_in_steps() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let specs = ChainSpec::from_json_bytes(spec).unwrap();
+
+        let (chain_information, vm) = specs.as_chain_information().unwrap();
+        let state_version = vm
+            .runtime_version()
+            .decode()
+            .state_version
+            .unwrap_or(crate::trie::TrieEntryVersion::V0);
+
+        let items = specs.genesis_storage().into_genesis_items().unwrap();
+        let mut calculation = items.trie_root_hash_calculation(state_version);
+        let hash = loop {
+            let (before, total) = calculation.progress();
+            match calculation.advance(100) {
+                TrieRootHashCalculationStep::Finished(hash) => break hash,
+                TrieRootHashCalculationStep::InProgress(c) => {
+                    assert!(c.progress().0 <= before + 100);
+                    assert!(c.progress().0 <= total);
+                    calculation = c;
+                }
+            }
+        };
+
+        assert_eq!(
+            hash,
+            *chain_information.as_ref().finalized_block_header.state_root
+        );
+
+        let (with_state_root, _) = specs.as_chain_information_with_state_root(&hash).unwrap();
+        assert_eq!(
+            with_state_root.as_ref().finalized_block_header.hash(4),
+            chain_information.as_ref().finalized_block_header.hash(4)
+        );
+
+        let without_state_root = specs.as_chain_information_without_state_root().unwrap();
+        assert_eq!(without_state_root.state_version(), state_version);
+        let (with_state_root, _) = without_state_root.with_state_root(&hash);
+        assert_eq!(
+            with_state_root.as_ref().finalized_block_header.hash(4),
+            chain_information.as_ref().finalized_block_header.hash(4)
+        );
+    }
+
+    #[test]
+    fn build_and_serialize() {
+        let config = BuildConfig {
+            name: "Test network",
+            id: "test",
+            chain_type: "Local",
+            genesis_storage: [(&b"b"[..], &b"2"[..]), (b"a", b"1"), (b"b", b"3")].into_iter(),
+            boot_nodes: &["/dns4/example.com/tcp/30333/p2p/12D3KooWHEQXbvCzLYvc87obHV6HY4rruHz8BJ9Lw1Gg2csVfR6Z"],
+            telemetry_endpoints: &[],
+            protocol_id: Some("tst"),
+            fork_id: None,
+            block_number_bytes: 4,
+            properties: Some(r#"{"tokenSymbol":"TST"}"#),
+            relay_chain: Some(("rococo", 1000)),
+            light_sync_state: None,
+        };
+
+        let spec = ChainSpec::from_json_bytes(ChainSpec::build(config).unwrap().to_json()).unwrap();
+        assert_eq!(spec.name(), "Test network");
+        assert_eq!(spec.id(), "test");
+        assert_eq!(spec.chain_type(), "Local");
+        assert_eq!(spec.protocol_id(), "tst");
+        assert_eq!(spec.block_number_bytes(), 4);
+        assert_eq!(spec.properties(), r#"{"tokenSymbol":"TST"}"#);
+        assert_eq!(spec.relay_chain(), Some(("rococo", 1000)));
+        assert!(matches!(
+            spec.boot_nodes().next(),
+            Some(Bootnode::Parsed { .. })
+        ));
+        assert_eq!(
+            spec.genesis_storage()
+                .into_genesis_items()
+                .unwrap()
+                .iter()
+                .collect::<Vec<_>>(),
+            vec![(&b"a"[..], &b"1"[..]), (&b"b"[..], &b"3"[..])]
+        );
+    }
+
+    #[test]
+    fn build_rejects_invalid_properties() {
+        let config = BuildConfig {
+            name: "Test network",
+            id: "test",
+            chain_type: "Development",
+            genesis_storage: core::iter::empty::<(Vec<u8>, Vec<u8>)>(),
+            boot_nodes: &[],
+            telemetry_endpoints: &[],
+            protocol_id: None,
+            fork_id: None,
+            block_number_bytes: 4,
+            properties: Some("[1, 2]"),
+            relay_chain: None,
+            light_sync_state: None,
+        };
+
+        assert!(matches!(
+            ChainSpec::build(config),
+            Err(BuildError::InvalidProperties)
+        ));
+    }
+
+    #[test]
+    fn separate_genesis_storage_matches() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        let original = ChainSpec::from_json_bytes(spec).unwrap();
+        let original_items = original.genesis_storage().into_genesis_items().unwrap();
+        let binary = original_items.to_binary();
+
+        let mut parser = GenesisStorageParser::new();
+        for chunk in binary.chunks(1777) {
+            parser.push_chunk(chunk).unwrap();
+        }
+
+        let mut spec = ChainSpec::from_json_bytes(spec).unwrap();
+        spec.set_genesis_storage(SeparateGenesisStorage::from_binary(&[0x4, 1, 0x4, 2]).unwrap());
+        assert_eq!(
+            spec.genesis_storage()
+                .into_genesis_items()
+                .unwrap()
+                .iter()
+                .len(),
+            1
+        );
+        spec.set_genesis_storage(parser.finish().unwrap());
+
+        let items = spec.genesis_storage().into_genesis_items().unwrap();
+        assert!(items.iter().eq(original_items.iter()));
+        assert_eq!(items.content_hash(), original_items.content_hash());
+    }
+
+    #[test]
+    fn separate_genesis_storage_truncated() {
+        let mut parser = GenesisStorageParser::new();
+        parser.push_chunk(&[0x8, 1, 2, 0x4]).unwrap();
+        assert!(matches!(
+            parser.finish(),
+            Err(GenesisStorageParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn validate_detailed_example() {
+        let spec = &include_bytes!("chain_spec/example.json")[..];
+        assert_eq!(
+            super::validate_detailed(spec).unwrap(),
+            vec![
+                ValidationWarning::BootnodeNotReachableFromBrowser(
+                    "/dns4/p2p.cc1-0.polkadot.network/tcp/30100".into()
+                ),
+                ValidationWarning::BootnodeNotReachableFromBrowser(
+                    "/dns4/cc1-1.parity.tech/tcp/30333".into()
+                ),
+                ValidationWarning::UnrecognizedBootnode("/some/wrong/multiaddress".into()),
+                ValidationWarning::MissingLightSyncState,
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_detailed_without_runtime() {
+        let spec = ChainSpec::build(BuildConfig {
+            name: "Test network",
+            id: "test",
+            chain_type: "Live",
+            genesis_storage: core::iter::once((&b"foo"[..], &b"bar"[..])),
+            boot_nodes: &[],
+            telemetry_endpoints: &[],
+            protocol_id: None,
+            fork_id: None,
+            block_number_bytes: 4,
+            properties: None,
+            relay_chain: None,
+            light_sync_state: None,
+        })
+        .unwrap();
+
+        assert_eq!(
+            super::validate_detailed(spec.to_json()).unwrap(),
+            vec![
+                ValidationWarning::NoBootnode,
+                ValidationWarning::MissingLightSyncState,
+                ValidationWarning::UnknownConsensus,
+            ]
+        );
+    }
 }
@@ -0,0 +1,196 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Genesis storage provided separately from the chain specification.
+//!
+//! Rather than embedding the genesis storage in the JSON of the chain specification in the form
+//! of hexadecimal strings, the genesis storage can be provided as a separate binary blob. This
+//! halves the size of the data, avoids the cost of decoding hexadecimal and JSON, and makes it
+//! possible to host the genesis storage separately from the chain specification.
+//!
+//! The binary blob is a concatenation of storage items. Each storage item consists of the
+//! SCALE-compact-encoded length of the key, the key, the SCALE-compact-encoded length of the
+//! value, and the value. Items don't need to be ordered. If the same key is found multiple
+//! times, the latest value is kept.
+//!
+//! Use [`GenesisStorageParser`] to decode such a blob, then
+//! [`ChainSpec::set_genesis_storage`](super::ChainSpec::set_genesis_storage) to use it in place
+//! of the genesis storage of a chain specification. The blob can be generated from a chain
+//! specification using [`GenesisStorageItems::to_binary`](super::GenesisStorageItems::to_binary).
+
+use super::structs;
+use crate::util;
+
+use alloc::vec::Vec;
+
+/// Incremental parser for a genesis storage binary blob.
+///
+/// See [the module-level documentation](..).
+pub struct GenesisStorageParser {
+    /// Items decoded so far.
+    storage: structs::RawStorageBuilder,
+
+    /// Data pushed so far that belongs to an item that hasn't been fully received yet.
+    pending: Vec<u8>,
+}
+
+impl GenesisStorageParser {
+    /// Initializes a new parser.
+    pub fn new() -> Self {
+        GenesisStorageParser {
+            storage: structs::RawStorageBuilder::default(),
+            pending: Vec::new(),
+        }
+    }
+
+    /// Pushes the next chunk of the binary blob.
+    ///
+    /// Chunks are allowed to be split anywhere, including in the middle of a storage item.
+    /// Only the part of the chunk that belongs to an incomplete storage item is copied.
+    pub fn push_chunk(&mut self, chunk: &[u8]) -> Result<(), GenesisStorageParseError> {
+        if self.pending.is_empty() {
+            let consumed = decode_items(&mut self.storage, chunk)?;
+            self.pending.extend_from_slice(&chunk[consumed..]);
+        } else {
+            self.pending.extend_from_slice(chunk);
+            let consumed = decode_items(&mut self.storage, &self.pending)?;
+            self.pending.drain(..consumed);
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the parsing.
+    ///
+    /// Returns an error if the data pushed so far ends in the middle of a storage item.
+    pub fn finish(self) -> Result<SeparateGenesisStorage, GenesisStorageParseError> {
+        if !self.pending.is_empty() {
+            return Err(GenesisStorageParseError::UnexpectedEof);
+        }
+
+        Ok(SeparateGenesisStorage {
+            storage: self.storage.build(),
+        })
+    }
+}
+
+impl Default for GenesisStorageParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Genesis storage decoded from a binary blob.
+///
+/// See [`ChainSpec::set_genesis_storage`](super::ChainSpec::set_genesis_storage).
+#[derive(Debug, Clone)]
+pub struct SeparateGenesisStorage {
+    pub(super) storage: structs::RawStorage,
+}
+
+impl SeparateGenesisStorage {
+    /// Decodes the given binary blob all at once.
+    pub fn from_binary(data: &[u8]) -> Result<Self, GenesisStorageParseError> {
+        let mut parser = GenesisStorageParser::new();
+        parser.push_chunk(data)?;
+        parser.finish()
+    }
+
+    /// Returns the number of storage items.
+    pub fn len(&self) -> usize {
+        self.storage.iter().len()
+    }
+
+    /// Returns `true` if there isn't any storage item.
+    pub fn is_empty(&self) -> bool {
+        self.storage.is_empty()
+    }
+}
+
+/// Error potentially returned by [`GenesisStorageParser`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum GenesisStorageParseError {
+    /// The length of a key or value is invalid.
+    InvalidLength,
+    /// The data ends in the middle of a storage item.
+    UnexpectedEof,
+}
+
+/// Encodes the given storage items into the binary format decoded by [`GenesisStorageParser`].
+pub(super) fn encode<'a>(items: impl Iterator<Item = (&'a [u8], &'a [u8])>) -> Vec<u8> {
+    let mut out = Vec::new();
+    for (key, value) in items {
+        out.extend_from_slice(util::encode_scale_compact_usize(key.len()).as_ref());
+        out.extend_from_slice(key);
+        out.extend_from_slice(util::encode_scale_compact_usize(value.len()).as_ref());
+        out.extend_from_slice(value);
+    }
+    out
+}
+
+/// Decodes as many complete storage items as possible from `data` and pushes them to `storage`.
+/// Returns the number of bytes that have been consumed.
+fn decode_items(
+    storage: &mut structs::RawStorageBuilder,
+    data: &[u8],
+) -> Result<usize, GenesisStorageParseError> {
+    let mut consumed = 0;
+
+    loop {
+        let remaining = &data[consumed..];
+
+        let Some((key_len, after_key_len)) = decode_length(remaining)? else {
+            return Ok(consumed);
+        };
+        if after_key_len.len() < key_len {
+            return Ok(consumed);
+        }
+        let (key, after_key) = after_key_len.split_at(key_len);
+
+        let Some((value_len, after_value_len)) = decode_length(after_key)? else {
+            return Ok(consumed);
+        };
+        if after_value_len.len() < value_len {
+            return Ok(consumed);
+        }
+        let (value, after_value) = after_value_len.split_at(value_len);
+
+        storage.push(key, value);
+        consumed = data.len() - after_value.len();
+    }
+}
+
+/// Decodes a SCALE-compact-encoded length at the start of `data`. Returns `Ok(None)` if `data`
+/// is too short to contain the whole length.
+fn decode_length(data: &[u8]) -> Result<Option<(usize, &[u8])>, GenesisStorageParseError> {
+    let encoded_len = match data.first() {
+        None => return Ok(None),
+        Some(b) if b & 0b11 == 0b00 => 1,
+        Some(b) if b & 0b11 == 0b01 => 2,
+        Some(b) if b & 0b11 == 0b10 => 4,
+        Some(b) => usize::from(b >> 2) + 5,
+    };
+
+    if data.len() < encoded_len {
+        return Ok(None);
+    }
+
+    match util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(data) {
+        Ok((rest, len)) => Ok(Some((len, rest))),
+        Err(_) => Err(GenesisStorageParseError::InvalidLength),
+    }
+}
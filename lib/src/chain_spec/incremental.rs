@@ -0,0 +1,391 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Incremental parsing of chain specifications.
+//!
+//! The JSON of a chain specification is provided chunk by chunk. The content of the
+//! `genesis.raw.top` object, which is in practice the overwhelming majority of the size of a
+//! chain specification, is decoded progressively as the chunks are pushed. The rest of the
+//! chain specification is buffered and decoded all at once when the parsing finishes.
+//!
+//! This makes it possible for the amount of CPU time spent in each call to be proportional to
+//! the size of the chunk that is passed, rather than to the size of the whole chain
+//! specification.
+
+use super::{structs, ChainSpec, ParseError, ParseErrorInner};
+
+use alloc::{borrow::Cow, vec::Vec};
+
+/// Incremental parser for a chain specification.
+///
+/// See [the module-level documentation](..).
+pub struct IncrementalParser {
+    /// JSON of the chain specification pushed so far, except for the content of the
+    /// `genesis.raw.top` object, which is replaced with `{}`.
+    skeleton: Vec<u8>,
+
+    /// Entries of the `genesis.raw.top` object decoded so far.
+    top: structs::RawStorageBuilder,
+
+    /// List of objects and arrays the parser is currently in, from the outermost to the
+    /// innermost. Only covers the content of [`IncrementalParser::skeleton`].
+    stack: Vec<Frame>,
+
+    /// What the parser is currently doing.
+    state: State,
+}
+
+struct Frame {
+    /// `true` if this is an object, `false` if this is an array.
+    is_object: bool,
+    /// If this is an object, latest key that has been encountered, with JSON escape sequences
+    /// decoded.
+    key: Vec<u8>,
+    /// If this is an object, `true` if the next string is a key.
+    expect_key: bool,
+}
+
+enum State {
+    /// Not within a string, and not within the `genesis.raw.top` object.
+    Skeleton,
+    /// Within a string that is part of the skeleton.
+    SkeletonString {
+        /// `true` if the previous character was a non-escaped `\`.
+        escaped: bool,
+        /// If the string is an object key, contains the characters of the key so far.
+        key: Option<Vec<u8>>,
+    },
+    /// Within the `genesis.raw.top` object.
+    Top(TopState),
+}
+
+enum TopState {
+    /// Just after the opening `{`.
+    KeyOrEnd,
+    /// Just after a `,`.
+    Key,
+    /// Within the string of a key. The boolean is `true` if the previous character was a
+    /// non-escaped `\`.
+    InKey(Vec<u8>, bool),
+    /// Just after the string of a key.
+    Colon(Vec<u8>),
+    /// Just after the `:` that follows a key.
+    Value(Vec<u8>),
+    /// Within the string of a value. The boolean is `true` if the previous character was a
+    /// non-escaped `\`.
+    InValue(Vec<u8>, Vec<u8>, bool),
+    /// Just after the string of a value.
+    CommaOrEnd,
+}
+
+impl IncrementalParser {
+    /// Initializes a new parser.
+    pub fn new() -> Self {
+        IncrementalParser {
+            skeleton: Vec::new(),
+            top: structs::RawStorageBuilder::default(),
+            stack: Vec::with_capacity(8),
+            state: State::Skeleton,
+        }
+    }
+
+    /// Pushes the next chunk of the JSON of the chain specification.
+    ///
+    /// The amount of processing performed by this function is proportional to the size of the
+    /// chunk.
+    ///
+    /// Chunks are allowed to be split in the middle of a UTF-8 character. An error is returned
+    /// if the JSON is malformed. Some errors, however, can only be detected when
+    /// [`IncrementalParser::finish`] is called.
+    pub fn push_chunk(&mut self, chunk: &[u8]) -> Result<(), ParseError> {
+        let mut chunk = chunk;
+
+        while !chunk.is_empty() {
+            let consumed = match &mut self.state {
+                State::Skeleton => {
+                    // Copy everything up to the next interesting character.
+                    let len = chunk
+                        .iter()
+                        .position(|c| matches!(*c, b'"' | b'{' | b'}' | b'[' | b']' | b','))
+                        .unwrap_or(chunk.len());
+                    self.skeleton.extend_from_slice(&chunk[..len]);
+                    if len == chunk.len() {
+                        break;
+                    }
+
+                    match chunk[len] {
+                        b'"' => {
+                            let is_key =
+                                matches!(self.stack.last(), Some(f) if f.is_object && f.expect_key);
+                            self.state = State::SkeletonString {
+                                escaped: false,
+                                key: if is_key { Some(Vec::new()) } else { None },
+                            };
+                            self.skeleton.push(b'"');
+                        }
+                        b'{' if is_genesis_raw_top(&self.stack) => {
+                            self.state = State::Top(TopState::KeyOrEnd);
+                            self.skeleton.extend_from_slice(b"{}");
+                        }
+                        b'{' | b'[' => {
+                            self.stack.push(Frame {
+                                is_object: chunk[len] == b'{',
+                                key: Vec::new(),
+                                expect_key: chunk[len] == b'{',
+                            });
+                            self.skeleton.push(chunk[len]);
+                        }
+                        b'}' | b']' => {
+                            match self.stack.pop() {
+                                Some(f) if f.is_object == (chunk[len] == b'}') => {}
+                                _ => return Err(invalid_json("mismatched brackets")),
+                            }
+                            self.skeleton.push(chunk[len]);
+                        }
+                        b',' => {
+                            if let Some(frame) = self.stack.last_mut() {
+                                frame.expect_key = frame.is_object;
+                            }
+                            self.skeleton.push(b',');
+                        }
+                        _ => unreachable!(),
+                    }
+
+                    len + 1
+                }
+
+                State::SkeletonString { escaped, key } => {
+                    let mut len = 0;
+                    let mut string_end = false;
+                    for c in chunk {
+                        len += 1;
+                        if *escaped {
+                            *escaped = false;
+                        } else if *c == b'\\' {
+                            *escaped = true;
+                        } else if *c == b'"' {
+                            string_end = true;
+                            break;
+                        }
+                    }
+
+                    self.skeleton.extend_from_slice(&chunk[..len]);
+                    if let Some(key) = key {
+                        key.extend_from_slice(&chunk[..if string_end { len - 1 } else { len }]);
+                    }
+
+                    if string_end {
+                        if let Some(key) = key.take() {
+                            let frame = self.stack.last_mut().unwrap_or_else(|| unreachable!());
+                            frame.key = unescape(&key)?.into_owned();
+                            frame.expect_key = false;
+                        }
+                        self.state = State::Skeleton;
+                    }
+
+                    len
+                }
+
+                State::Top(top_state) => {
+                    let c = chunk[0];
+                    match top_state {
+                        TopState::InKey(..) | TopState::InValue(..) => {
+                            let (buffer, escaped) = match top_state {
+                                TopState::InKey(key, escaped) => (key, escaped),
+                                TopState::InValue(_, value, escaped) => (value, escaped),
+                                _ => unreachable!(),
+                            };
+
+                            // Escape sequences are kept as-is in the buffer and decoded only
+                            // once the string is complete, as they might be split between
+                            // multiple chunks.
+                            let mut len = 0;
+                            let mut string_end = false;
+                            for c in chunk {
+                                len += 1;
+                                if *escaped {
+                                    *escaped = false;
+                                } else if *c == b'\\' {
+                                    *escaped = true;
+                                } else if *c == b'"' {
+                                    string_end = true;
+                                    break;
+                                }
+                            }
+
+                            if !string_end {
+                                buffer.extend_from_slice(chunk);
+                                break;
+                            }
+                            buffer.extend_from_slice(&chunk[..len - 1]);
+
+                            *top_state = match top_state {
+                                TopState::InKey(key, _) => {
+                                    TopState::Colon(unescape(key)?.into_owned())
+                                }
+                                TopState::InValue(key, value, _) => {
+                                    self.top
+                                        .push_hex(key, &unescape(value)?)
+                                        .map_err(invalid_json)?;
+                                    TopState::CommaOrEnd
+                                }
+                                _ => unreachable!(),
+                            };
+
+                            len
+                        }
+                        _ if c.is_ascii_whitespace() => 1,
+                        TopState::KeyOrEnd | TopState::CommaOrEnd if c == b'}' => {
+                            self.state = State::Skeleton;
+                            1
+                        }
+                        TopState::KeyOrEnd | TopState::Key if c == b'"' => {
+                            *top_state = TopState::InKey(Vec::new(), false);
+                            1
+                        }
+                        TopState::Colon(key) if c == b':' => {
+                            *top_state = TopState::Value(core::mem::take(key));
+                            1
+                        }
+                        TopState::Value(key) if c == b'"' => {
+                            *top_state = TopState::InValue(core::mem::take(key), Vec::new(), false);
+                            1
+                        }
+                        TopState::CommaOrEnd if c == b',' => {
+                            *top_state = TopState::Key;
+                            1
+                        }
+                        _ => return Err(invalid_json("unexpected character in genesis storage")),
+                    }
+                }
+            };
+
+            chunk = &chunk[consumed..];
+        }
+
+        Ok(())
+    }
+
+    /// Finishes the parsing and returns the decoded chain specification.
+    pub fn finish(self) -> Result<ChainSpec, ParseError> {
+        if !matches!(self.state, State::Skeleton) || !self.stack.is_empty() {
+            return Err(invalid_json("unexpected end of chain specification"));
+        }
+
+        let mut client_spec: structs::ClientSpec = serde_json::from_slice(&self.skeleton)
+            .map_err(ParseErrorInner::Serde)
+            .map_err(ParseError)?;
+
+        // The `genesis.raw.top` object can only have been found if the genesis is indeed raw.
+        // Because the skeleton contains `{}` in place of its content, the map is empty here.
+        if let structs::Genesis::Raw(raw) = &mut client_spec.genesis {
+            debug_assert!(raw.top.is_empty());
+            raw.top = self.top.build();
+        }
+
+        ChainSpec::from_client_spec(client_spec)
+    }
+}
+
+impl Default for IncrementalParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Returns `true` if the next value to parse would be the one of the `genesis.raw.top` field.
+fn is_genesis_raw_top(stack: &[Frame]) -> bool {
+    if stack.len() != 3 {
+        return false;
+    }
+
+    stack
+        .iter()
+        .zip([&b"genesis"[..], &b"raw"[..], &b"top"[..]])
+        .all(|(frame, key)| frame.is_object && !frame.expect_key && frame.key == key)
+}
+
+/// Decodes the JSON escape sequences found in the content of a string.
+///
+/// Returns the input as-is if it doesn't contain any escape sequence.
+fn unescape(escaped: &[u8]) -> Result<Cow<'_, [u8]>, ParseError> {
+    if !escaped.contains(&b'\\') {
+        return Ok(Cow::Borrowed(escaped));
+    }
+
+    let mut out = Vec::with_capacity(escaped.len());
+    let mut iter = escaped.iter().copied();
+    while let Some(c) = iter.next() {
+        if c != b'\\' {
+            out.push(c);
+            continue;
+        }
+
+        let decoded = match iter.next() {
+            Some(b'"') => '"',
+            Some(b'\\') => '\\',
+            Some(b'/') => '/',
+            Some(b'b') => '\u{8}',
+            Some(b'f') => '\u{c}',
+            Some(b'n') => '\n',
+            Some(b'r') => '\r',
+            Some(b't') => '\t',
+            Some(b'u') => {
+                let high = unescape_hex4(&mut iter)?;
+                let code_point = if (0xd800..0xdc00).contains(&high) {
+                    // The UTF-16 high surrogate must be followed with a low surrogate.
+                    if iter.next() != Some(b'\\') || iter.next() != Some(b'u') {
+                        return Err(invalid_json("invalid escape sequence"));
+                    }
+                    let low = unescape_hex4(&mut iter)?;
+                    if !(0xdc00..0xe000).contains(&low) {
+                        return Err(invalid_json("invalid escape sequence"));
+                    }
+                    0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                } else {
+                    high
+                };
+                char::from_u32(code_point).ok_or_else(|| invalid_json("invalid escape sequence"))?
+            }
+            _ => return Err(invalid_json("invalid escape sequence")),
+        };
+
+        let mut utf8 = [0; 4];
+        out.extend_from_slice(decoded.encode_utf8(&mut utf8).as_bytes());
+    }
+
+    Ok(Cow::Owned(out))
+}
+
+/// Decodes the four hexadecimal digits that follow a `\u` escape sequence.
+fn unescape_hex4(iter: &mut impl Iterator<Item = u8>) -> Result<u32, ParseError> {
+    let mut value = 0;
+    for _ in 0..4 {
+        let digit = iter
+            .next()
+            .and_then(|c| char::from(c).to_digit(16))
+            .ok_or_else(|| invalid_json("invalid escape sequence"))?;
+        value = (value << 4) | digit;
+    }
+    Ok(value)
+}
+
+fn invalid_json(msg: &'static str) -> ParseError {
+    ParseError(ParseErrorInner::Serde(
+        <serde_json::Error as serde::de::Error>::custom(msg),
+    ))
+}
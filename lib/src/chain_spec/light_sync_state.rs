@@ -33,6 +33,24 @@ pub(super) struct LightSyncState {
 }
 
 impl LightSyncState {
+    /// Builds a [`LightSyncState`] from its SCALE-encoded fields.
+    ///
+    /// The fields aren't verified. Use [`LightSyncState::decode`] to make sure that they are
+    /// valid.
+    pub(super) fn from_encoded(
+        babe_epoch_changes: &[u8],
+        babe_finalized_block_weight: u32,
+        finalized_block_header: &[u8],
+        grandpa_authority_set: &[u8],
+    ) -> Self {
+        LightSyncState {
+            babe_epoch_changes: HexString(babe_epoch_changes.to_vec()),
+            babe_finalized_block_weight,
+            finalized_block_header: HexString(finalized_block_header.to_vec()),
+            grandpa_authority_set: HexString(grandpa_authority_set.to_vec()),
+        }
+    }
+
     pub(super) fn decode(
         &self,
         block_number_bytes: usize,
@@ -22,7 +22,8 @@
 
 use super::light_sync_state::LightSyncState;
 
-use alloc::{boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use alloc::{borrow::Cow, boxed::Box, collections::BTreeMap, format, string::String, vec::Vec};
+use core::fmt;
 use fnv::FnvBuildHasher;
 use hashbrown::{HashMap, HashSet};
 use serde::{Deserialize, Serialize};
@@ -43,7 +44,6 @@ pub(super) struct ClientSpec {
     /// the given block number until the `spec_version`
     /// ([`crate::executor::host::CoreVersionRef::spec_version`]) on chain changes.
     #[serde(default)]
-    // TODO: make use of this
     pub(super) code_substitutes: HashMap<u64, HexString, fnv::FnvBuildHasher>,
     pub(super) boot_nodes: Vec<String>,
     pub(super) telemetry_endpoints: Option<Vec<(String, u8)>>,
@@ -58,7 +58,6 @@ pub(super) struct ClientSpec {
     #[serde(default = "Default::default", skip_serializing_if = "Option::is_none")]
     pub(super) block_number_bytes: Option<u8>,
     pub(super) properties: Option<Box<serde_json::value::RawValue>>,
-    // TODO: make use of this
     pub(super) fork_blocks: Option<Vec<(u64, HashHexString)>>,
     pub(super) bad_blocks: Option<HashSet<HashHexString, FnvBuildHasher>>,
     // Unused but for some reason still part of the chain specs.
@@ -104,10 +103,215 @@ pub(super) enum Genesis {
 #[serde(rename_all = "camelCase")]
 #[serde(deny_unknown_fields)]
 pub(super) struct RawGenesis {
-    pub(super) top: BTreeMap<HexString, HexString>,
+    pub(super) top: RawStorage,
     pub(super) children_default: BTreeMap<HexString, ChildRawStorage>,
 }
 
+/// List of storage items of the genesis block.
+///
+/// Contrary to a `BTreeMap<HexString, HexString>`, all the keys and values are stored in a single
+/// buffer, and are decoded directly into this buffer without any intermediate allocation. This
+/// considerably reduces the memory overhead when the genesis storage contains a lot of items.
+#[derive(Debug, Clone, Default)]
+pub(super) struct RawStorage {
+    /// Concatenation of all the keys and values.
+    data: Vec<u8>,
+    /// For each item, contains the offset within [`RawStorage::data`] of the start of the key,
+    /// the start of the value (which is also the end of the key), and the end of the value.
+    /// Sorted by key, and never contains the same key twice.
+    entries: Vec<[usize; 3]>,
+}
+
+impl RawStorage {
+    /// Returns the value associated to the given key, if any.
+    pub(super) fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        let index = self
+            .entries
+            .binary_search_by(|e| self.data[e[0]..e[1]].cmp(key))
+            .ok()?;
+        let entry = &self.entries[index];
+        Some(&self.data[entry[1]..entry[2]])
+    }
+
+    /// Returns the list of all keys and values, ordered by key.
+    pub(super) fn iter(&self) -> impl ExactSizeIterator<Item = (&[u8], &[u8])> + Clone {
+        self.entries
+            .iter()
+            .map(|e| (&self.data[e[0]..e[1]], &self.data[e[1]..e[2]]))
+    }
+
+    /// Returns `true` if there isn't any item.
+    pub(super) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/// Builds a [`RawStorage`] one item at a time.
+#[derive(Default)]
+pub(super) struct RawStorageBuilder {
+    /// Storage being built. The entries aren't sorted yet.
+    storage: RawStorage,
+}
+
+impl RawStorageBuilder {
+    /// Adds the given key and value to the storage.
+    ///
+    /// If the same key is pushed multiple times, the latest value is kept.
+    pub(super) fn push(&mut self, key: &[u8], value: &[u8]) {
+        let key_start = self.storage.data.len();
+        self.storage.data.extend_from_slice(key);
+        let value_start = self.storage.data.len();
+        self.storage.data.extend_from_slice(value);
+        self.storage
+            .entries
+            .push([key_start, value_start, self.storage.data.len()]);
+    }
+
+    /// Decodes the given hexadecimal key and value and adds them to the storage.
+    ///
+    /// If the same key is pushed multiple times, the latest value is kept.
+    pub(super) fn push_hex(&mut self, key: &[u8], value: &[u8]) -> Result<(), &'static str> {
+        let key_start = self.storage.data.len();
+        let value_start = match self.decode_hex(key) {
+            Ok(offset) => offset,
+            Err(err) => {
+                self.storage.data.truncate(key_start);
+                return Err(err);
+            }
+        };
+        let value_end = match self.decode_hex(value) {
+            Ok(offset) => offset,
+            Err(err) => {
+                self.storage.data.truncate(key_start);
+                return Err(err);
+            }
+        };
+
+        self.storage
+            .entries
+            .push([key_start, value_start, value_end]);
+        Ok(())
+    }
+
+    /// Appends the decoded version of `hex` at the end of the data. Returns the new length of
+    /// the data.
+    fn decode_hex(&mut self, hex: &[u8]) -> Result<usize, &'static str> {
+        let hex = hex
+            .strip_prefix(b"0x")
+            .ok_or("hexadecimal string doesn't start with 0x")?;
+        if hex.len() % 2 != 0 {
+            return Err("hexadecimal string has an odd length");
+        }
+
+        let start = self.storage.data.len();
+        self.storage.data.resize(start + hex.len() / 2, 0);
+        hex::decode_to_slice(hex, &mut self.storage.data[start..])
+            .map_err(|_| "invalid hexadecimal string")?;
+        Ok(self.storage.data.len())
+    }
+
+    /// Finishes building the storage.
+    pub(super) fn build(self) -> RawStorage {
+        let RawStorage { data, mut entries } = self.storage;
+
+        // Sorting is stable, meaning that entries with the same key stay in the order in which
+        // they were pushed. Only the last one of them is kept.
+        entries.sort_by(|a, b| data[a[0]..a[1]].cmp(&data[b[0]..b[1]]));
+        let mut deduplicated = Vec::with_capacity(entries.len());
+        for (index, entry) in entries.iter().enumerate() {
+            if !matches!(entries.get(index + 1), Some(next) if data[next[0]..next[1]] == data[entry[0]..entry[1]])
+            {
+                deduplicated.push(*entry);
+            }
+        }
+
+        RawStorage {
+            data,
+            entries: deduplicated,
+        }
+    }
+}
+
+impl serde::Serialize for RawStorage {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.collect_map(self.iter().map(|(key, value)| {
+            (
+                format!("0x{}", hex::encode(key)),
+                format!("0x{}", hex::encode(value)),
+            )
+        }))
+    }
+}
+
+impl<'a> serde::Deserialize<'a> for RawStorage {
+    fn deserialize<D>(deserializer: D) -> Result<RawStorage, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        struct Visitor;
+        impl<'a> serde::de::Visitor<'a> for Visitor {
+            type Value = RawStorage;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map of hexadecimal keys and values")
+            }
+
+            fn visit_map<M>(self, mut map: M) -> Result<RawStorage, M::Error>
+            where
+                M: serde::de::MapAccess<'a>,
+            {
+                let mut builder = RawStorageBuilder::default();
+                while let Some((key, value)) =
+                    map.next_entry::<MaybeBorrowedStr, MaybeBorrowedStr>()?
+                {
+                    builder
+                        .push_hex(key.0.as_bytes(), value.0.as_bytes())
+                        .map_err(serde::de::Error::custom)?;
+                }
+                Ok(builder.build())
+            }
+        }
+
+        deserializer.deserialize_map(Visitor)
+    }
+}
+
+/// String that is borrowed from the JSON input if possible, in order to avoid an allocation.
+struct MaybeBorrowedStr<'a>(Cow<'a, str>);
+
+impl<'a> serde::Deserialize<'a> for MaybeBorrowedStr<'a> {
+    fn deserialize<D>(deserializer: D) -> Result<MaybeBorrowedStr<'a>, D::Error>
+    where
+        D: serde::Deserializer<'a>,
+    {
+        struct Visitor;
+        impl<'a> serde::de::Visitor<'a> for Visitor {
+            type Value = MaybeBorrowedStr<'a>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a string")
+            }
+
+            fn visit_borrowed_str<E>(self, v: &'a str) -> Result<Self::Value, E> {
+                Ok(MaybeBorrowedStr(Cow::Borrowed(v)))
+            }
+
+            fn visit_str<E>(self, v: &str) -> Result<Self::Value, E> {
+                Ok(MaybeBorrowedStr(Cow::Owned(v.into())))
+            }
+
+            fn visit_string<E>(self, v: String) -> Result<Self::Value, E> {
+                Ok(MaybeBorrowedStr(Cow::Owned(v)))
+            }
+        }
+
+        deserializer.deserialize_str(Visitor)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub(super) struct HexString(pub(super) Vec<u8>);
 
@@ -0,0 +1,148 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of the problems that make a chain specification unsuitable for light clients.
+//!
+//! A chain specification can be perfectly valid and still be difficult or impossible to use
+//! for a light client, for example because none of its bootnodes can be reached from a browser
+//! or because it doesn't contain any checkpoint. [`validate_detailed`] reports these problems in
+//! the form of a list of [`ValidationWarning`]s, in order for tooling to lint chain
+//! specifications before they are distributed.
+
+use super::{Bootnode, ChainSpec, GenesisStorage, ParseError};
+use crate::{chain::chain_information::ChainInformationConsensusRef, libp2p};
+
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+
+/// Size, in bytes, of the genesis storage above which [`ValidationWarning::OversizedGenesis`]
+/// is reported.
+///
+/// Light clients have to download the entire chain specification before they can start, which
+/// is slow in the case of a large genesis storage.
+pub const MAX_RECOMMENDED_GENESIS_SIZE: usize = 4 * 1024 * 1024;
+
+/// Problem found in a chain specification by [`validate_detailed`].
+#[derive(Debug, Clone, PartialEq, Eq, derive_more::Display)]
+pub enum ValidationWarning {
+    /// The chain is expected to have a live network, but the chain specification doesn't
+    /// contain any bootnode.
+    #[display(fmt = "No bootnode")]
+    NoBootnode,
+    /// The address of a bootnode couldn't be parsed, or doesn't end with `/p2p/...`.
+    #[display(fmt = "Bootnode address in an unrecognized format: {_0}")]
+    UnrecognizedBootnode(String),
+    /// The address of a bootnode uses neither WebSocket nor WebRTC, and can thus not be reached
+    /// by light clients running in a browser.
+    #[display(fmt = "Bootnode not reachable from a browser: {_0}")]
+    BootnodeNotReachableFromBrowser(String),
+    /// The chain specification of a chain that isn't a parachain doesn't contain any light sync
+    /// state. Light clients have to start syncing from the genesis block.
+    #[display(fmt = "No light sync state")]
+    MissingLightSyncState,
+    /// The genesis storage is larger than [`MAX_RECOMMENDED_GENESIS_SIZE`].
+    #[display(fmt = "Genesis storage of {size} bytes")]
+    OversizedGenesis {
+        /// Total size, in bytes, of the keys and values of the genesis storage.
+        size: usize,
+    },
+    /// The properties of the chain specification aren't a JSON object.
+    #[display(fmt = "Properties aren't a JSON object")]
+    InvalidProperties,
+    /// The consensus engine of the genesis block couldn't be determined, or isn't supported.
+    #[display(fmt = "Unknown consensus engine in the genesis block")]
+    UnknownConsensus,
+}
+
+/// Parses the given chain specification and reports the problems that make it unsuitable for
+/// light clients. See [the module-level documentation](self).
+///
+/// Returns an error if the chain specification can't be parsed at all.
+///
+/// > **Note**: Determining the consensus engine requires compiling the runtime of the genesis
+/// >           block, which is CPU-intensive. This is skipped if the chain specification
+/// >           contains a light sync state or doesn't contain the genesis storage items.
+pub fn validate_detailed(json: impl AsRef<[u8]>) -> Result<Vec<ValidationWarning>, ParseError> {
+    let chain_spec = ChainSpec::from_json_bytes(json)?;
+    let mut warnings = Vec::new();
+
+    if chain_spec.boot_nodes().len() == 0 && chain_spec.has_live_network() {
+        warnings.push(ValidationWarning::NoBootnode);
+    }
+
+    for bootnode in chain_spec.boot_nodes() {
+        match bootnode {
+            Bootnode::UnrecognizedFormat(addr) => {
+                warnings.push(ValidationWarning::UnrecognizedBootnode(addr.to_owned()));
+            }
+            Bootnode::Parsed { multiaddr, .. } => {
+                let reachable_from_browser = match multiaddr.parse::<libp2p::Multiaddr>() {
+                    Ok(addr) => addr.iter().any(|protocol| {
+                        matches!(
+                            protocol,
+                            libp2p::multiaddr::ProtocolRef::Ws
+                                | libp2p::multiaddr::ProtocolRef::Wss
+                                | libp2p::multiaddr::ProtocolRef::WebRtcDirect
+                        )
+                    }),
+                    Err(_) => false,
+                };
+                if !reachable_from_browser {
+                    warnings.push(ValidationWarning::BootnodeNotReachableFromBrowser(
+                        multiaddr,
+                    ));
+                }
+            }
+        }
+    }
+
+    if chain_spec.light_sync_state().is_none() && chain_spec.relay_chain().is_none() {
+        warnings.push(ValidationWarning::MissingLightSyncState);
+    }
+
+    if let GenesisStorage::Items(items) = chain_spec.genesis_storage() {
+        let size = items
+            .iter()
+            .map(|(key, value)| key.len() + value.len())
+            .sum::<usize>();
+        if size > MAX_RECOMMENDED_GENESIS_SIZE {
+            warnings.push(ValidationWarning::OversizedGenesis { size });
+        }
+    }
+
+    if !chain_spec.properties().trim_start().starts_with('{') {
+        warnings.push(ValidationWarning::InvalidProperties);
+    }
+
+    if chain_spec.light_sync_state().is_none()
+        && matches!(chain_spec.genesis_storage(), GenesisStorage::Items(_))
+    {
+        // The state trie root hash is only used to build the header of the genesis block, which
+        // isn't needed here, and its calculation is expensive.
+        let known_consensus = match chain_spec.as_chain_information_without_state_root() {
+            Ok(chain_information) => !matches!(
+                chain_information.consensus(),
+                ChainInformationConsensusRef::Unknown
+            ),
+            Err(_) => false,
+        };
+        if !known_consensus {
+            warnings.push(ValidationWarning::UnknownConsensus);
+        }
+    }
+
+    Ok(warnings)
+}
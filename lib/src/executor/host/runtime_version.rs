@@ -421,6 +421,46 @@ pub fn hash_api_name(api_name: &str) -> [u8; 8] {
     result.as_bytes().try_into().unwrap()
 }
 
+/// Names of the APIs commonly found in Substrate-based runtimes.
+///
+/// Runtimes only report the hash of the name of the APIs they support (see
+/// [`CoreVersionApi::name_hash`]). This list makes it possible to find back the name of the
+/// most common APIs. See [`api_name_from_hash`].
+pub const KNOWN_API_NAMES: &[&str] = &[
+    "AccountNonceApi",
+    "AssetsApi",
+    "AuraApi",
+    "AuthorityDiscoveryApi",
+    "BabeApi",
+    "BeefyApi",
+    "BeefyMmrApi",
+    "BlockBuilder",
+    "CollectCollationInfo",
+    "ContractsApi",
+    "Core",
+    "GenesisBuilder",
+    "GrandpaApi",
+    "Metadata",
+    "MmrApi",
+    "NominationPoolsApi",
+    "OffchainWorkerApi",
+    "ParachainHost",
+    "SessionKeys",
+    "StakingApi",
+    "TaggedTransactionQueue",
+    "TransactionPaymentApi",
+    "TransactionPaymentCallApi",
+];
+
+/// Returns the name of the API whose hash is the given one, if it is found in
+/// [`KNOWN_API_NAMES`].
+pub fn api_name_from_hash(api_name_hash: &[u8; 8]) -> Option<&'static str> {
+    KNOWN_API_NAMES
+        .iter()
+        .find(|name| hash_api_name(name) == *api_name_hash)
+        .copied()
+}
+
 /// One API that the runtime supports.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct CoreVersionApi {
@@ -435,6 +475,13 @@ pub struct CoreVersionApi {
     pub version: u32,
 }
 
+impl CoreVersionApi {
+    /// Returns the name of this API, if it is found in [`KNOWN_API_NAMES`].
+    pub fn name(&self) -> Option<&'static str> {
+        api_name_from_hash(&self.name_hash)
+    }
+}
+
 fn decode(scale_encoded: &[u8]) -> Result<CoreVersionRef, ()> {
     // See https://spec.polkadot.network/#defn-rt-core-version
     let result: nom::IResult<_, _> =
@@ -31,6 +31,9 @@
 //! - Keeps track of the logs generated by the call and concatenates them into a [`String`].
 //! - Automatically handles some externalities, such as calculating the Merkle root or storage
 //!   transactions.
+//! - Keeps track of the changes made to the default child tries, and stores the root of the
+//!   child tries that have been modified in the main trie whenever the root of the main trie is
+//!   calculated.
 //!
 //! These additional features considerably reduces the number of externals concepts to plug to
 //! the virtual machine.
@@ -43,7 +46,7 @@ use crate::{
     util,
 };
 
-use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+use alloc::{borrow::ToOwned as _, boxed::Box, collections::BTreeMap, string::String, vec::Vec};
 use core::fmt;
 use hashbrown::HashSet;
 
@@ -69,6 +72,10 @@ pub struct Config<'a, TParams> {
     /// execution will be pushed over the value in this field.
     pub storage_main_trie_changes: storage_diff::TrieDiff,
 
+    /// Initial state of [`Success::storage_child_tries_changes`]. The changes made during this
+    /// execution will be pushed over the value in this field.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
+
     /// Initial state of [`Success::offchain_storage_changes`]. The changes made during this
     /// execution will be pushed over the value in this field.
     pub offchain_storage_changes: storage_diff::TrieDiff,
@@ -99,6 +106,19 @@ pub fn run(
             .run_vectored(config.function_to_call, config.parameter)?
             .into(),
         main_trie_changes: config.storage_main_trie_changes,
+        child_tries: config
+            .storage_child_tries_changes
+            .into_iter()
+            .map(|(child_trie, changes)| {
+                (
+                    child_trie,
+                    ChildTrie {
+                        changes,
+                        root_calculation_cache: None,
+                    },
+                )
+            })
+            .collect(),
         state_trie_version,
         main_trie_transaction: Vec::new(),
         offchain_storage_changes: config.offchain_storage_changes,
@@ -120,6 +140,13 @@ pub struct Success {
     pub virtual_machine: SuccessVirtualMachine,
     /// List of changes to the storage main trie that the block performs.
     pub storage_main_trie_changes: storage_diff::TrieDiff,
+    /// List of changes to the default child tries that the block performs, indexed by the name
+    /// of the child trie (without the `:child_storage:default:` prefix).
+    ///
+    /// > **Note**: The roots of these child tries are stored in the main trie under the
+    /// >           `:child_storage:default:` prefix only when the root of the main trie is
+    /// >           calculated. In practice, runtimes always calculate it at the end of a block.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
     /// State trie version indicated by the runtime. All the storage changes indicated by
     /// [`Success::storage_main_trie_changes`] should store this version alongside with them.
     pub state_trie_version: TrieEntryVersion,
@@ -184,6 +211,11 @@ pub enum RuntimeHostVm {
     Finished(Result<Success, Error>),
     /// Loading a storage value is required in order to continue.
     StorageGet(StorageGet),
+    /// Loading a storage value of a child trie is required in order to continue.
+    ChildStorageGet(ChildStorageGet),
+    /// Fetching the list of all the keys of a child trie is required in order to calculate its
+    /// root.
+    ChildStorageRoot(ChildStorageRoot),
     /// Fetching the list of keys with a given prefix is required in order to continue.
     PrefixKeys(PrefixKeys),
     /// Fetching the key that follows a given one is required in order to continue.
@@ -199,6 +231,8 @@ impl RuntimeHostVm {
             RuntimeHostVm::Finished(Ok(inner)) => inner.virtual_machine.into_prototype(),
             RuntimeHostVm::Finished(Err(inner)) => inner.prototype,
             RuntimeHostVm::StorageGet(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::ChildStorageGet(inner) => inner.inner.vm.into_prototype(),
+            RuntimeHostVm::ChildStorageRoot(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::PrefixKeys(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::NextKey(inner) => inner.inner.vm.into_prototype(),
             RuntimeHostVm::SignatureVerification(inner) => inner.inner.vm.into_prototype(),
@@ -215,99 +249,73 @@ pub struct StorageGet {
 impl StorageGet {
     /// Returns the key whose value must be passed to [`StorageGet::inject_value`].
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
-        enum Three<A, B, C> {
-            A(A),
-            B(B),
-            C(C),
-        }
+        self.inner.storage_get_key()
+    }
 
-        impl<A: AsRef<[u8]>, B: AsRef<[u8]>, C: AsRef<[u8]>> AsRef<[u8]> for Three<A, B, C> {
-            fn as_ref(&self) -> &[u8] {
-                match self {
-                    Three::A(a) => a.as_ref(),
-                    Three::B(b) => b.as_ref(),
-                    Three::C(c) => c.as_ref(),
-                }
-            }
-        }
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> RuntimeHostVm {
+        self.inner.inject_storage_value(value)
+    }
+}
 
-        match &self.inner.vm {
-            host::HostVm::ExternalStorageGet(req) => Three::A(match req.key() {
-                // TODO: child tries are not implemented correctly
-                host::StorageKey::MainTrie { key } => key,
-                _ => unreachable!(),
-            }),
-            host::HostVm::ExternalStorageAppend(req) => Three::B(match req.key() {
-                // TODO: child tries are not implemented correctly
-                host::StorageKey::MainTrie { key } => key,
-                _ => unreachable!(),
-            }),
-            host::HostVm::ExternalStorageRoot(_) => {
-                if let calculate_root::RootMerkleValueCalculation::StorageValue(value_request) =
-                    self.inner.root_calculation.as_ref().unwrap()
-                {
-                    Three::C(value_request.key().collect::<Vec<_>>())
-                } else {
-                    // We only create a `StorageGet` if the state is `StorageValue`.
-                    panic!()
-                }
-            }
+/// Loading a storage value of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageGet {
+    inner: Inner,
+}
 
-            // We only create a `StorageGet` if the state is one of the above.
-            _ => unreachable!(),
-        }
+impl ChildStorageGet {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        // We only create a `ChildStorageGet` if the key belongs to a child trie.
+        self.inner.storage_get_child_trie().unwrap()
+    }
+
+    /// Returns the key whose value must be passed to [`ChildStorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.storage_get_key()
     }
 
     /// Injects the corresponding storage value.
     pub fn inject_value(
-        mut self,
+        self,
         value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
     ) -> RuntimeHostVm {
-        // TODO: update the implementation to not require the folding here
-        let value = value.map(|(value, version)| {
-            let value = value.fold(Vec::new(), |mut a, b| {
-                a.extend_from_slice(b.as_ref());
-                a
-            });
-            (value, version)
-        });
-
-        match self.inner.vm {
-            host::HostVm::ExternalStorageGet(req) => {
-                // TODO: should actually report the offset and max_size in the API
-                self.inner.vm = req.resume_full_value(value.as_ref().map(|(v, _)| &v[..]));
-            }
-            host::HostVm::ExternalStorageAppend(req) => {
-                match req.key() {
-                    host::StorageKey::MainTrie { key } => {
-                        // TODO: could be less overhead?
-                        let mut value = value.map(|(v, _)| v).unwrap_or_default();
-                        append_to_storage_value(&mut value, req.value().as_ref());
-                        self.inner
-                            .main_trie_changes
-                            .diff_insert(key.as_ref().to_vec(), value, ());
-                    }
-                    _ => unreachable!(),
-                }
+        self.inner.inject_storage_value(value)
+    }
+}
 
-                self.inner.vm = req.resume();
-            }
-            host::HostVm::ExternalStorageRoot(_) => {
-                if let calculate_root::RootMerkleValueCalculation::StorageValue(value_request) =
-                    self.inner.root_calculation.take().unwrap()
-                {
-                    self.inner.root_calculation = Some(value_request.inject(value));
-                } else {
-                    // We only create a `StorageGet` if the state is `StorageValue`.
-                    panic!()
-                }
-            }
+/// Fetching the list of all the keys of a child trie is required in order to calculate its root.
+#[must_use]
+pub struct ChildStorageRoot {
+    inner: Inner,
+}
 
-            // We only create a `StorageGet` if the state is one of the above.
+impl ChildStorageRoot {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match self.inner.root_calculation.as_deref() {
+            Some(RootCalculation {
+                calculation: calculate_root::RootMerkleValueCalculation::AllKeys(all_keys),
+                ..
+            }) => all_keys.child_trie().unwrap(),
+
+            // We only create a `ChildStorageRoot` if the state is `AllKeys`.
             _ => unreachable!(),
-        };
+        }
+    }
 
-        self.inner.run()
+    /// Injects the list of all the keys of the child trie, ordered lexicographically.
+    pub fn inject_keys_ordered(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> RuntimeHostVm {
+        self.inner.inject_root_calculation_keys(keys)
     }
 }
 
@@ -318,13 +326,27 @@ pub struct PrefixKeys {
 }
 
 impl PrefixKeys {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix, or `None` if the keys of the main trie must be loaded.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageClearPrefix(req) => match req.prefix() {
+                host::StorageKey::MainTrie { .. } => None,
+                host::StorageKey::ChildTrieDefault { child_trie, .. } => Some(child_trie),
+            },
+            host::HostVm::ExternalStorageRoot { .. } => None,
+
+            // We only create a `PrefixKeys` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
     /// Returns the prefix whose keys to load.
     pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
         match &self.inner.vm {
             host::HostVm::ExternalStorageClearPrefix(req) => either::Left(match req.prefix() {
-                // TODO: child tries are not implemented correctly
-                host::StorageKey::MainTrie { key } => key,
-                _ => unreachable!(),
+                host::StorageKey::MainTrie { key }
+                | host::StorageKey::ChildTrieDefault { key, .. } => key,
             }),
             host::HostVm::ExternalStorageRoot { .. } => either::Right(&[]),
 
@@ -348,14 +370,25 @@ impl PrefixKeys {
                 let max_keys_to_remove = req.max_keys_to_remove();
                 let mut keys_removed_so_far = 0u32;
 
-                let prefix = match req.prefix() {
-                    host::StorageKey::MainTrie { key } => key.as_ref().to_owned(),
-                    _ => unreachable!(),
+                let (child_trie, prefix) = match req.prefix() {
+                    host::StorageKey::MainTrie { key } => (None, key.as_ref().to_owned()),
+                    host::StorageKey::ChildTrieDefault { child_trie, key } => (
+                        Some(child_trie.as_ref().to_owned()),
+                        key.as_ref().to_owned(),
+                    ),
+                };
+
+                let empty_changes = storage_diff::TrieDiff::empty();
+                let changes = match &child_trie {
+                    None => &self.inner.main_trie_changes,
+                    Some(child_trie) => self
+                        .inner
+                        .child_tries
+                        .get(child_trie)
+                        .map_or(&empty_changes, |c| &c.changes),
                 };
 
-                let mut after_overlay = self
-                    .inner
-                    .main_trie_changes
+                let mut after_overlay = changes
                     .storage_prefix_keys_ordered(&prefix, keys)
                     .peekable();
 
@@ -384,47 +417,31 @@ impl PrefixKeys {
 
                 drop(after_overlay);
 
-                for key in keys_to_remove {
-                    self.inner
-                        .main_trie_root_calculation_cache
-                        .as_mut()
-                        .unwrap()
-                        .storage_value_update(&key, false);
-                    self.inner
-                        .main_trie_changes
-                        .diff_insert_erase(key.clone(), ());
+                if !keys_to_remove.is_empty() {
+                    let (changes, mut cache) = match child_trie {
+                        None => (
+                            &mut self.inner.main_trie_changes,
+                            self.inner.main_trie_root_calculation_cache.as_mut(),
+                        ),
+                        Some(child_trie) => {
+                            let child = self.inner.child_tries.entry(child_trie).or_default();
+                            (&mut child.changes, child.root_calculation_cache.as_mut())
+                        }
+                    };
+
+                    for key in keys_to_remove {
+                        if let Some(cache) = &mut cache {
+                            cache.storage_value_update(&key, false);
+                        }
+                        changes.diff_insert_erase(key, ());
+                    }
                 }
 
                 self.inner.vm = req.resume(keys_removed_so_far, some_keys_remain);
             }
 
             host::HostVm::ExternalStorageRoot { .. } => {
-                if let calculate_root::RootMerkleValueCalculation::AllKeys(all_keys) =
-                    self.inner.root_calculation.take().unwrap()
-                {
-                    // TODO: overhead
-                    let mut list = keys
-                        .filter(|v| {
-                            self.inner
-                                .main_trie_changes
-                                .diff_get(v.as_ref())
-                                .map_or(true, |(v, _)| v.is_some())
-                        })
-                        .map(|v| v.as_ref().to_vec())
-                        .collect::<HashSet<_, fnv::FnvBuildHasher>>();
-                    // TODO: slow to iterate over everything?
-                    for (key, value, ()) in self.inner.main_trie_changes.diff_iter_unordered() {
-                        if value.is_none() {
-                            continue;
-                        }
-                        list.insert(key.to_owned());
-                    }
-                    self.inner.root_calculation =
-                        Some(all_keys.inject(list.into_iter().map(|k| k.into_iter())));
-                } else {
-                    // We only create a `PrefixKeys` if the state is `AllKeys`.
-                    panic!()
-                }
+                return self.inner.inject_root_calculation_keys(keys);
             }
 
             // We only create a `PrefixKeys` if the state is one of the above.
@@ -446,6 +463,18 @@ pub struct NextKey {
 }
 
 impl NextKey {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix, or `None` if it belongs to the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.inner.vm {
+            host::HostVm::ExternalStorageNextKey(req) => match req.key() {
+                host::StorageKey::MainTrie { .. } => None,
+                host::StorageKey::ChildTrieDefault { child_trie, .. } => Some(child_trie),
+            },
+            _ => unreachable!(),
+        }
+    }
+
     /// Returns the key whose next key must be passed back.
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
         if let Some(key_overwrite) = &self.key_overwrite {
@@ -454,9 +483,8 @@ impl NextKey {
 
         match &self.inner.vm {
             host::HostVm::ExternalStorageNextKey(req) => either::Right(match req.key() {
-                // TODO: child tries are not implemented correctly
-                host::StorageKey::MainTrie { key } => key,
-                _ => unreachable!(),
+                host::StorageKey::MainTrie { key }
+                | host::StorageKey::ChildTrieDefault { key, .. } => key,
             }),
             _ => unreachable!(),
         }
@@ -470,22 +498,27 @@ impl NextKey {
     ///
     pub fn inject_key(mut self, key: Option<impl AsRef<[u8]>>) -> RuntimeHostVm {
         let key = key.as_ref().map(|k| k.as_ref());
+        let empty_changes = storage_diff::TrieDiff::empty();
 
         match self.inner.vm {
             host::HostVm::ExternalStorageNextKey(req) => {
                 let search = {
-                    let req_key = match req.key() {
-                        host::StorageKey::MainTrie { key } => key,
-                        _ => unreachable!(),
+                    let (changes, req_key) = match req.key() {
+                        host::StorageKey::MainTrie { key } => (&self.inner.main_trie_changes, key),
+                        host::StorageKey::ChildTrieDefault { child_trie, key } => (
+                            self.inner
+                                .child_tries
+                                .get(child_trie.as_ref())
+                                .map_or(&empty_changes, |c| &c.changes),
+                            key,
+                        ),
                     };
                     let requested_key = if let Some(key_overwrite) = &self.key_overwrite {
                         &key_overwrite[..]
                     } else {
                         req_key.as_ref()
                     };
-                    self.inner
-                        .main_trie_changes
-                        .storage_next_key(requested_key, key)
+                    changes.storage_next_key(requested_key, key)
                 };
 
                 match search {
@@ -604,14 +637,22 @@ struct Inner {
     /// Pending changes to the top storage trie that this execution performs.
     main_trie_changes: storage_diff::TrieDiff,
 
-    /// Contains a copy of [`Inner::main_trie_changes`] and
-    /// [`Inner::main_trie_root_calculation_cache`] at the time when the transaction started.
+    /// Default child tries that this execution modifies, indexed by their name (without the
+    /// `:child_storage:default:` prefix).
+    child_tries: BTreeMap<Vec<u8>, ChildTrie>,
+
+    /// Contains a copy of [`Inner::main_trie_changes`], [`Inner::main_trie_root_calculation_cache`]
+    /// and [`Inner::child_tries`] at the time when the transaction started.
     /// When the storage transaction ends, either the entry is silently discarded (to commit),
-    /// or is written over [`Inner::main_trie_changes`] and
-    /// [`Inner::main_trie_root_calculation_cache`] (to rollback).
+    /// or is written over [`Inner::main_trie_changes`],
+    /// [`Inner::main_trie_root_calculation_cache`] and [`Inner::child_tries`] (to rollback).
     ///
     /// Contains a `Vec` in case transactions are stacked.
-    main_trie_transaction: Vec<(storage_diff::TrieDiff, calculate_root::CalculationCache)>,
+    main_trie_transaction: Vec<(
+        storage_diff::TrieDiff,
+        calculate_root::CalculationCache,
+        BTreeMap<Vec<u8>, ChildTrie>,
+    )>,
 
     /// State trie version indicated by the runtime. All the storage changes that are performed
     /// use this version.
@@ -625,7 +666,7 @@ struct Inner {
     main_trie_root_calculation_cache: Option<calculate_root::CalculationCache>,
 
     /// Trie root calculation in progress.
-    root_calculation: Option<calculate_root::RootMerkleValueCalculation>,
+    root_calculation: Option<Box<RootCalculation>>,
 
     /// Concatenation of all the log messages generated by the runtime.
     logs: String,
@@ -634,6 +675,31 @@ struct Inner {
     max_log_level: u32,
 }
 
+/// See [`Inner::child_tries`].
+#[derive(Clone, Default)]
+struct ChildTrie {
+    /// Pending changes to the child trie that this execution performs.
+    changes: storage_diff::TrieDiff,
+
+    /// Cache of the calculation of the root of this child trie. `None` if the root of this child
+    /// trie hasn't been calculated yet during this execution, or while it is being calculated.
+    root_calculation_cache: Option<calculate_root::CalculationCache>,
+}
+
+/// See [`Inner::root_calculation`].
+struct RootCalculation {
+    /// Name of the child trie whose root is being calculated, or `None` for the main trie.
+    child_trie: Option<Vec<u8>>,
+
+    /// Calculation in progress.
+    calculation: calculate_root::RootMerkleValueCalculation,
+
+    /// List of child tries whose root must be calculated and stored in the main trie once
+    /// the current calculation is finished, and before the root of the main trie is calculated.
+    /// Always empty if the runtime has requested the root of a child trie.
+    next_child_tries: Vec<Vec<u8>>,
+}
+
 impl Inner {
     /// Continues the execution.
     fn run(mut self) -> RuntimeHostVm {
@@ -655,6 +721,11 @@ impl Inner {
                     return RuntimeHostVm::Finished(Ok(Success {
                         virtual_machine: SuccessVirtualMachine(finished),
                         storage_main_trie_changes: self.main_trie_changes,
+                        storage_child_tries_changes: self
+                            .child_tries
+                            .into_iter()
+                            .map(|(child_trie, child)| (child_trie, child.changes))
+                            .collect(),
                         state_trie_version: self.state_trie_version,
                         offchain_storage_changes: self.offchain_storage_changes,
                         main_trie_root_calculation_cache: self
@@ -665,39 +736,61 @@ impl Inner {
                 }
 
                 host::HostVm::ExternalStorageGet(req) => {
-                    let search = {
-                        let key = match req.key() {
-                            host::StorageKey::MainTrie { key } => key,
-                            v => {
-                                drop(v);
-                                // TODO: this is a dummy implementation and child tries are not implemented properly
-                                self.vm = req.resume(None);
-                                continue;
-                            }
-                        };
-                        self.main_trie_changes.diff_get(key.as_ref())
+                    let search = match req.key() {
+                        host::StorageKey::MainTrie { key } => self
+                            .main_trie_changes
+                            .diff_get(key.as_ref())
+                            .map(|(v, _)| v),
+                        host::StorageKey::ChildTrieDefault { child_trie, key } => self
+                            .child_tries
+                            .get(child_trie.as_ref())
+                            .and_then(|child| child.changes.diff_get(key.as_ref()))
+                            .map(|(v, _)| v),
                     };
 
-                    if let Some((overlay, _)) = search {
+                    if let Some(overlay) = search {
                         self.vm = req.resume_full_value(overlay);
                     } else {
+                        let is_main_trie = matches!(req.key(), host::StorageKey::MainTrie { .. });
                         self.vm = req.into();
-                        return RuntimeHostVm::StorageGet(StorageGet { inner: self });
+                        return if is_main_trie {
+                            RuntimeHostVm::StorageGet(StorageGet { inner: self })
+                        } else {
+                            RuntimeHostVm::ChildStorageGet(ChildStorageGet { inner: self })
+                        };
                     }
                 }
 
                 host::HostVm::ExternalStorageSet(req) => {
-                    // TODO: this is a dummy implementation and child tries are not implemented properly
-                    if let host::StorageKey::MainTrie { key } = req.key() {
-                        self.main_trie_root_calculation_cache
-                            .as_mut()
-                            .unwrap()
-                            .storage_value_update(key.as_ref(), req.value().is_some());
-                        if let Some(value) = req.value() {
-                            self.main_trie_changes
-                                .diff_insert(key.as_ref(), value.as_ref(), ());
-                        } else {
-                            self.main_trie_changes.diff_insert_erase(key.as_ref(), ());
+                    match req.key() {
+                        host::StorageKey::MainTrie { key } => {
+                            self.main_trie_root_calculation_cache
+                                .as_mut()
+                                .unwrap()
+                                .storage_value_update(key.as_ref(), req.value().is_some());
+                            if let Some(value) = req.value() {
+                                self.main_trie_changes.diff_insert(
+                                    key.as_ref(),
+                                    value.as_ref(),
+                                    (),
+                                );
+                            } else {
+                                self.main_trie_changes.diff_insert_erase(key.as_ref(), ());
+                            }
+                        }
+                        host::StorageKey::ChildTrieDefault { child_trie, key } => {
+                            let child = self
+                                .child_tries
+                                .entry(child_trie.as_ref().to_vec())
+                                .or_default();
+                            if let Some(cache) = &mut child.root_calculation_cache {
+                                cache.storage_value_update(key.as_ref(), req.value().is_some());
+                            }
+                            if let Some(value) = req.value() {
+                                child.changes.diff_insert(key.as_ref(), value.as_ref(), ());
+                            } else {
+                                child.changes.diff_insert_erase(key.as_ref(), ());
+                            }
                         }
                     }
 
@@ -705,115 +798,220 @@ impl Inner {
                 }
 
                 host::HostVm::ExternalStorageAppend(req) => {
-                    let key = match req.key() {
-                        host::StorageKey::MainTrie { key } => key,
-                        v => {
-                            drop(v);
-                            // TODO: this is a dummy implementation and child tries are not implemented properly
-                            self.vm = req.resume();
-                            continue;
+                    let (changes, cache) = match req.key() {
+                        host::StorageKey::MainTrie { .. } => (
+                            &mut self.main_trie_changes,
+                            self.main_trie_root_calculation_cache.as_mut(),
+                        ),
+                        host::StorageKey::ChildTrieDefault { child_trie, .. } => {
+                            let child = self
+                                .child_tries
+                                .entry(child_trie.as_ref().to_vec())
+                                .or_default();
+                            (&mut child.changes, child.root_calculation_cache.as_mut())
                         }
                     };
 
-                    self.main_trie_root_calculation_cache
-                        .as_mut()
-                        .unwrap()
-                        .storage_value_update(key.as_ref(), true);
+                    let key = match req.key() {
+                        host::StorageKey::MainTrie { key }
+                        | host::StorageKey::ChildTrieDefault { key, .. } => key,
+                    };
+
+                    if let Some(cache) = cache {
+                        cache.storage_value_update(key.as_ref(), true);
+                    }
 
-                    let current_value = self
-                        .main_trie_changes
-                        .diff_get(key.as_ref())
-                        .map(|(v, _)| v);
+                    let current_value = changes.diff_get(key.as_ref()).map(|(v, _)| v);
                     if let Some(current_value) = current_value {
                         let mut current_value = current_value.unwrap_or_default().to_vec();
                         append_to_storage_value(&mut current_value, req.value().as_ref());
-                        self.main_trie_changes.diff_insert(
-                            key.as_ref().to_vec(),
-                            current_value,
-                            (),
-                        );
+                        changes.diff_insert(key.as_ref().to_vec(), current_value, ());
                         drop(key);
                         self.vm = req.resume();
                     } else {
                         drop(key);
+                        let is_main_trie = matches!(req.key(), host::StorageKey::MainTrie { .. });
                         self.vm = req.into();
-                        return RuntimeHostVm::StorageGet(StorageGet { inner: self });
+                        return if is_main_trie {
+                            RuntimeHostVm::StorageGet(StorageGet { inner: self })
+                        } else {
+                            RuntimeHostVm::ChildStorageGet(ChildStorageGet { inner: self })
+                        };
                     }
                 }
 
                 host::HostVm::ExternalStorageClearPrefix(req) => {
-                    let is_main_trie = matches!(req.prefix(), host::StorageKey::MainTrie { .. });
-                    if is_main_trie {
-                        self.vm = req.into();
-                        return RuntimeHostVm::PrefixKeys(PrefixKeys { inner: self });
-                    } else {
-                        // TODO: this is a dummy implementation and child tries are not implemented properly
-                        self.vm = req.resume(0, false);
-                    }
+                    self.vm = req.into();
+                    return RuntimeHostVm::PrefixKeys(PrefixKeys { inner: self });
                 }
 
                 host::HostVm::ExternalStorageRoot(req) => {
-                    let is_main_trie = matches!(req.trie(), host::Trie::MainTrie);
-                    if !is_main_trie {
-                        // TODO: this is a dummy implementation and child tries are not implemented properly
-                        self.vm = req.resume(None);
-                        continue;
-                    }
-
-                    // For the main trie, whether the changes must be committed is a dummy value.
-                    debug_assert!(req.commit_changes());
-
                     if self.root_calculation.is_none() {
-                        self.root_calculation = Some(calculate_root::root_merkle_value(Some(
-                            self.main_trie_root_calculation_cache.take().unwrap(),
-                        )));
+                        let child_trie = match req.trie() {
+                            host::Trie::MainTrie => None,
+                            host::Trie::ChildTrieDefault { child_trie } => {
+                                Some(child_trie.as_ref().to_vec())
+                            }
+                        };
+
+                        match child_trie {
+                            None => {
+                                // For the main trie, whether the changes must be committed is a
+                                // dummy value.
+                                debug_assert!(req.commit_changes());
+
+                                // Before the root of the main trie can be calculated, the roots
+                                // of all the child tries that have been modified must be stored
+                                // in the main trie.
+                                let mut child_tries =
+                                    self.child_tries.keys().cloned().collect::<Vec<_>>();
+                                self.root_calculation = Some(match child_tries.pop() {
+                                    Some(child_trie) => child_trie_root_calculation(
+                                        &mut self.child_tries,
+                                        child_trie,
+                                        child_tries,
+                                    ),
+                                    None => main_trie_root_calculation(
+                                        self.main_trie_root_calculation_cache.take().unwrap(),
+                                    ),
+                                });
+                            }
+                            Some(child_trie) if !req.commit_changes() => {
+                                // The runtime is reading the `:child_storage:default:` entry of
+                                // the main trie, which contains the root of the child trie as
+                                // of the last time it has been committed.
+                                let key = child_trie_root_key(&child_trie);
+                                if let Some((value, ())) = self.main_trie_changes.diff_get(&key) {
+                                    let hash = value.and_then(|v| <[u8; 32]>::try_from(v).ok());
+                                    self.vm = req.resume(hash.as_ref());
+                                    continue;
+                                }
+
+                                self.vm = req.into();
+                                return RuntimeHostVm::StorageGet(StorageGet { inner: self });
+                            }
+                            Some(child_trie) => {
+                                self.root_calculation = Some(child_trie_root_calculation(
+                                    &mut self.child_tries,
+                                    child_trie,
+                                    Vec::new(),
+                                ));
+                            }
+                        }
                     }
 
-                    match self.root_calculation.take().unwrap() {
+                    let root_calculation = *self.root_calculation.take().unwrap();
+                    match root_calculation.calculation {
                         calculate_root::RootMerkleValueCalculation::Finished { hash, cache } => {
-                            self.main_trie_root_calculation_cache = Some(cache);
-                            self.vm = req.resume(Some(&hash));
+                            let child_trie = match root_calculation.child_trie {
+                                Some(child_trie) => child_trie,
+                                None => {
+                                    self.main_trie_root_calculation_cache = Some(cache);
+                                    self.vm = req.resume(Some(&hash));
+                                    continue;
+                                }
+                            };
+
+                            if let Some(child) = self.child_tries.get_mut(&child_trie) {
+                                child.root_calculation_cache = Some(cache);
+                            }
+
+                            // Store the root of the child trie in the main trie, or remove it if
+                            // the child trie is now empty.
+                            let key = child_trie_root_key(&child_trie);
+                            let value = if hash == trie::empty_trie_merkle_value() {
+                                None
+                            } else {
+                                Some(&hash[..])
+                            };
+                            if self.main_trie_changes.diff_get(&key).map(|(v, _)| v) != Some(value)
+                            {
+                                self.main_trie_root_calculation_cache
+                                    .as_mut()
+                                    .unwrap()
+                                    .storage_value_update(&key, value.is_some());
+                                if let Some(value) = value {
+                                    self.main_trie_changes.diff_insert(key, value, ());
+                                } else {
+                                    self.main_trie_changes.diff_insert_erase(key, ());
+                                }
+                            }
+
+                            let mut next_child_tries = root_calculation.next_child_tries;
+                            if let Some(child_trie) = next_child_tries.pop() {
+                                self.root_calculation = Some(child_trie_root_calculation(
+                                    &mut self.child_tries,
+                                    child_trie,
+                                    next_child_tries,
+                                ));
+                            } else if matches!(req.trie(), host::Trie::MainTrie) {
+                                self.root_calculation = Some(main_trie_root_calculation(
+                                    self.main_trie_root_calculation_cache.take().unwrap(),
+                                ));
+                            } else {
+                                self.vm = req.resume(Some(&hash));
+                                continue;
+                            }
+
+                            self.vm = req.into();
                         }
                         calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
                             self.vm = req.into();
-                            self.root_calculation =
-                                Some(calculate_root::RootMerkleValueCalculation::AllKeys(keys));
-                            return RuntimeHostVm::PrefixKeys(PrefixKeys { inner: self });
+                            let is_main_trie = root_calculation.child_trie.is_none();
+                            self.root_calculation = Some(Box::new(RootCalculation {
+                                calculation: calculate_root::RootMerkleValueCalculation::AllKeys(
+                                    keys,
+                                ),
+                                ..root_calculation
+                            }));
+                            return if is_main_trie {
+                                RuntimeHostVm::PrefixKeys(PrefixKeys { inner: self })
+                            } else {
+                                RuntimeHostVm::ChildStorageRoot(ChildStorageRoot { inner: self })
+                            };
                         }
                         calculate_root::RootMerkleValueCalculation::StorageValue(value_request) => {
                             self.vm = req.into();
                             // TODO: allocating a Vec, meh
-                            if let Some((overlay, ())) = self
-                                .main_trie_changes
-                                .diff_get(&value_request.key().collect::<Vec<_>>())
-                            {
-                                self.root_calculation = Some(
-                                    value_request
+                            let key = value_request.key().collect::<Vec<_>>();
+                            let overlay = match &root_calculation.child_trie {
+                                None => self.main_trie_changes.diff_get(&key),
+                                Some(child_trie) => self
+                                    .child_tries
+                                    .get(child_trie)
+                                    .and_then(|child| child.changes.diff_get(&key)),
+                            };
+                            if let Some((overlay, ())) = overlay {
+                                self.root_calculation = Some(Box::new(RootCalculation {
+                                    calculation: value_request
                                         .inject(overlay.map(|v| (v, self.state_trie_version))),
-                                );
+                                    ..root_calculation
+                                }));
                             } else {
-                                self.root_calculation =
-                                    Some(calculate_root::RootMerkleValueCalculation::StorageValue(
-                                        value_request,
-                                    ));
-                                return RuntimeHostVm::StorageGet(StorageGet { inner: self });
+                                let is_main_trie = root_calculation.child_trie.is_none();
+                                self.root_calculation = Some(Box::new(RootCalculation {
+                                    calculation:
+                                        calculate_root::RootMerkleValueCalculation::StorageValue(
+                                            value_request,
+                                        ),
+                                    ..root_calculation
+                                }));
+                                return if is_main_trie {
+                                    RuntimeHostVm::StorageGet(StorageGet { inner: self })
+                                } else {
+                                    RuntimeHostVm::ChildStorageGet(ChildStorageGet { inner: self })
+                                };
                             }
                         }
                     }
                 }
 
                 host::HostVm::ExternalStorageNextKey(req) => {
-                    let is_main_trie = matches!(req.key(), host::StorageKey::MainTrie { .. });
-                    if is_main_trie {
-                        self.vm = req.into();
-                        return RuntimeHostVm::NextKey(NextKey {
-                            inner: self,
-                            key_overwrite: None,
-                        });
-                    } else {
-                        // TODO: this is a dummy implementation and child tries are not implemented properly
-                        self.vm = req.resume(None);
-                    }
+                    self.vm = req.into();
+                    return RuntimeHostVm::NextKey(NextKey {
+                        inner: self,
+                        key_overwrite: None,
+                    });
                 }
 
                 host::HostVm::ExternalStorageNextChildTrie(req) => {
@@ -876,6 +1074,7 @@ impl Inner {
                             .as_ref()
                             .unwrap()
                             .clone(),
+                        self.child_tries.clone(),
                     ));
 
                     self.vm = tx.resume();
@@ -885,11 +1084,13 @@ impl Inner {
                     // The inner implementation guarantees that a storage transaction can only
                     // end if it has earlier been started.
                     debug_assert!(!self.main_trie_transaction.is_empty());
-                    let (rollback_diff, rollback_cache) = self.main_trie_transaction.pop().unwrap();
+                    let (rollback_diff, rollback_cache, rollback_child_tries) =
+                        self.main_trie_transaction.pop().unwrap();
 
                     if rollback {
                         self.main_trie_changes = rollback_diff;
                         self.main_trie_root_calculation_cache = Some(rollback_cache);
+                        self.child_tries = rollback_child_tries;
                     }
 
                     self.vm = resume.resume();
@@ -936,6 +1137,238 @@ impl Inner {
             }
         }
     }
+
+    /// Returns the key whose value is requested through a [`StorageGet`] or a
+    /// [`ChildStorageGet`].
+    fn storage_get_key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        match &self.vm {
+            host::HostVm::ExternalStorageGet(req) => Three::A(match req.key() {
+                host::StorageKey::MainTrie { key }
+                | host::StorageKey::ChildTrieDefault { key, .. } => key,
+            }),
+            host::HostVm::ExternalStorageAppend(req) => Three::B(match req.key() {
+                host::StorageKey::MainTrie { key }
+                | host::StorageKey::ChildTrieDefault { key, .. } => key,
+            }),
+            host::HostVm::ExternalStorageRoot(req) => match self.root_calculation.as_deref() {
+                Some(RootCalculation {
+                    calculation:
+                        calculate_root::RootMerkleValueCalculation::StorageValue(value_request),
+                    ..
+                }) => Three::C(value_request.key().collect::<Vec<_>>()),
+                // If no calculation is in progress, the runtime is reading the root of a child
+                // trie as stored in the main trie.
+                None => match req.trie() {
+                    host::Trie::ChildTrieDefault { child_trie } => {
+                        Three::C(child_trie_root_key(child_trie.as_ref()))
+                    }
+                    host::Trie::MainTrie => unreachable!(),
+                },
+                // We only create a `StorageGet` if the state is `StorageValue`.
+                _ => panic!(),
+            },
+
+            // We only create a `StorageGet` or `ChildStorageGet` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Returns the child trie of the value requested through a [`StorageGet`] or a
+    /// [`ChildStorageGet`], or `None` if it belongs to the main trie.
+    fn storage_get_child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        match &self.vm {
+            host::HostVm::ExternalStorageGet(req) => match req.key() {
+                host::StorageKey::MainTrie { .. } => None,
+                host::StorageKey::ChildTrieDefault { child_trie, .. } => Some(Three::A(child_trie)),
+            },
+            host::HostVm::ExternalStorageAppend(req) => match req.key() {
+                host::StorageKey::MainTrie { .. } => None,
+                host::StorageKey::ChildTrieDefault { child_trie, .. } => Some(Three::B(child_trie)),
+            },
+            host::HostVm::ExternalStorageRoot(_) => match self.root_calculation.as_deref() {
+                Some(RootCalculation {
+                    calculation:
+                        calculate_root::RootMerkleValueCalculation::StorageValue(value_request),
+                    ..
+                }) => value_request.child_trie().map(Three::C),
+                _ => None,
+            },
+
+            // We only create a `StorageGet` or `ChildStorageGet` if the state is one of the above.
+            _ => unreachable!(),
+        }
+    }
+
+    /// Injects the value requested through a [`StorageGet`] or a [`ChildStorageGet`] and
+    /// continues the execution.
+    fn inject_storage_value(
+        mut self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> RuntimeHostVm {
+        // TODO: update the implementation to not require the folding here
+        let value = value.map(|(value, version)| {
+            let value = value.fold(Vec::new(), |mut a, b| {
+                a.extend_from_slice(b.as_ref());
+                a
+            });
+            (value, version)
+        });
+
+        match self.vm {
+            host::HostVm::ExternalStorageGet(req) => {
+                // TODO: should actually report the offset and max_size in the API
+                self.vm = req.resume_full_value(value.as_ref().map(|(v, _)| &v[..]));
+            }
+            host::HostVm::ExternalStorageAppend(req) => {
+                // TODO: could be less overhead?
+                let mut value = value.map(|(v, _)| v).unwrap_or_default();
+                append_to_storage_value(&mut value, req.value().as_ref());
+
+                match req.key() {
+                    host::StorageKey::MainTrie { key } => {
+                        self.main_trie_changes
+                            .diff_insert(key.as_ref().to_vec(), value, ());
+                    }
+                    host::StorageKey::ChildTrieDefault { child_trie, key } => {
+                        self.child_tries
+                            .entry(child_trie.as_ref().to_vec())
+                            .or_default()
+                            .changes
+                            .diff_insert(key.as_ref().to_vec(), value, ());
+                    }
+                }
+
+                self.vm = req.resume();
+            }
+            host::HostVm::ExternalStorageRoot(req) if self.root_calculation.is_none() => {
+                // The runtime is reading the root of a child trie as stored in the main trie.
+                let hash = value.and_then(|(v, _)| <[u8; 32]>::try_from(&v[..]).ok());
+                self.vm = req.resume(hash.as_ref());
+            }
+            host::HostVm::ExternalStorageRoot(_) => {
+                let root_calculation = *self.root_calculation.take().unwrap();
+                if let calculate_root::RootMerkleValueCalculation::StorageValue(value_request) =
+                    root_calculation.calculation
+                {
+                    self.root_calculation = Some(Box::new(RootCalculation {
+                        calculation: value_request.inject(value),
+                        ..root_calculation
+                    }));
+                } else {
+                    // We only create a `StorageGet` if the state is `StorageValue`.
+                    panic!()
+                }
+            }
+
+            // We only create a `StorageGet` or `ChildStorageGet` if the state is one of the above.
+            _ => unreachable!(),
+        };
+
+        self.run()
+    }
+
+    /// Injects the list of keys requested by the root calculation in progress and continues the
+    /// execution.
+    fn inject_root_calculation_keys(
+        mut self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> RuntimeHostVm {
+        let root_calculation = *self.root_calculation.take().unwrap();
+        let all_keys = match root_calculation.calculation {
+            calculate_root::RootMerkleValueCalculation::AllKeys(all_keys) => all_keys,
+            // We only create a `PrefixKeys` or a `ChildStorageRoot` if the state is `AllKeys`.
+            _ => panic!(),
+        };
+
+        let empty_changes = storage_diff::TrieDiff::empty();
+        let changes = match &root_calculation.child_trie {
+            None => &self.main_trie_changes,
+            Some(child_trie) => self
+                .child_tries
+                .get(child_trie)
+                .map_or(&empty_changes, |child| &child.changes),
+        };
+
+        // TODO: overhead
+        let mut list = keys
+            .filter(|v| {
+                changes
+                    .diff_get(v.as_ref())
+                    .map_or(true, |(v, _)| v.is_some())
+            })
+            .map(|v| v.as_ref().to_vec())
+            .collect::<HashSet<_, fnv::FnvBuildHasher>>();
+        // TODO: slow to iterate over everything?
+        for (key, value, ()) in changes.diff_iter_unordered() {
+            if value.is_none() {
+                continue;
+            }
+            list.insert(key.to_owned());
+        }
+
+        self.root_calculation = Some(Box::new(RootCalculation {
+            calculation: all_keys.inject(list.into_iter().map(|k| k.into_iter())),
+            ..root_calculation
+        }));
+
+        self.run()
+    }
+}
+
+/// Starts calculating the root of the main trie.
+fn main_trie_root_calculation(cache: calculate_root::CalculationCache) -> Box<RootCalculation> {
+    Box::new(RootCalculation {
+        child_trie: None,
+        calculation: calculate_root::root_merkle_value(Some(cache)),
+        next_child_tries: Vec::new(),
+    })
+}
+
+/// Starts calculating the root of the given child trie, using the cache found in `child_tries`
+/// if any.
+fn child_trie_root_calculation(
+    child_tries: &mut BTreeMap<Vec<u8>, ChildTrie>,
+    child_trie: Vec<u8>,
+    next_child_tries: Vec<Vec<u8>>,
+) -> Box<RootCalculation> {
+    let cache = child_tries
+        .get_mut(&child_trie)
+        .and_then(|child| child.root_calculation_cache.take());
+    Box::new(RootCalculation {
+        calculation: calculate_root::child_trie_root_merkle_value(&child_trie, cache),
+        child_trie: Some(child_trie),
+        next_child_tries,
+    })
+}
+
+/// Returns the key of the main trie under which the root of the given default child trie is
+/// stored.
+fn child_trie_root_key(child_trie: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(DEFAULT_CHILD_STORAGE_SPECIAL_PREFIX.len() + child_trie.len());
+    key.extend_from_slice(DEFAULT_CHILD_STORAGE_SPECIAL_PREFIX);
+    key.extend_from_slice(child_trie);
+    key
+}
+
+/// Prefix of the keys of the main trie under which the roots of the default child tries are
+/// stored.
+const DEFAULT_CHILD_STORAGE_SPECIAL_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Implementation of `AsRef<[u8]>` for one of three types.
+enum Three<A, B, C> {
+    A(A),
+    B(B),
+    C(C),
+}
+
+impl<A: AsRef<[u8]>, B: AsRef<[u8]>, C: AsRef<[u8]>> AsRef<[u8]> for Three<A, B, C> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Three::A(a) => a.as_ref(),
+            Three::B(b) => b.as_ref(),
+            Three::C(c) => c.as_ref(),
+        }
+    }
 }
 
 /// Performs the action described by [`host::HostVm::ExternalStorageAppend`] on an
@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#![deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
+
 use alloc::vec::Vec;
 
 /// Attempt to decode the given SCALE-encoded Grandpa commit.
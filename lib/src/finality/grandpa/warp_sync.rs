@@ -17,12 +17,14 @@
 
 // TODO: really needs documentation
 
+#![deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
+
 use crate::chain::chain_information::{ChainInformationFinality, ChainInformationFinalityRef};
 use crate::finality;
 use crate::finality::justification::verify::{
     verify, Config as VerifyConfig, Error as VerifyError,
 };
-use crate::header::{self, DigestItemRef, GrandpaAuthority, GrandpaConsensusLogRef};
+use crate::header::{self, DigestItemRef, GrandpaAuthority, GrandpaConsensusLogRef, HeaderRef};
 use crate::informant::HashDisplay;
 
 use alloc::vec::Vec;
@@ -37,6 +39,7 @@ pub enum Error {
         header_hash: [u8; 32],
     },
     NonMinimalProof,
+    AuthoritiesSetIdOverflow,
     EmptyProof,
     InvalidHeader(header::Error),
     InvalidJustification(finality::justification::decode::Error),
@@ -64,6 +67,9 @@ impl fmt::Display for Error {
                 f,
                 "Warp sync proof fragment doesn't contain an authorities list change"
             ),
+            Error::AuthoritiesSetIdOverflow => {
+                write!(f, "Warp sync proof overflows the authorities set id")
+            }
             Error::EmptyProof => write!(f, "Warp sync proof is empty"),
             Error::InvalidHeader(_) => write!(f, "Failed to decode header"),
             Error::InvalidJustification(_) => write!(f, "Failed to decode justification"),
@@ -85,6 +91,11 @@ pub struct Verifier {
     fragments: Vec<WarpSyncFragment>,
     is_proof_complete: bool,
 
+    /// For each element in [`Verifier::fragments`], if the signatures of its justification have
+    /// already been verified, the authorities set id and list of authorities it has been
+    /// verified against.
+    verified_justifications: Vec<Option<(u64, Vec<GrandpaAuthority>)>>,
+
     block_number_bytes: usize,
 }
 
@@ -117,12 +128,99 @@ impl Verifier {
             index: 0,
             authorities_set_id,
             authorities_list,
+            verified_justifications: (0..warp_sync_response_fragments.len())
+                .map(|_| None)
+                .collect(),
             fragments: warp_sync_response_fragments,
             is_proof_complete,
             block_number_bytes,
         }
     }
 
+    /// Returns the hash of the block of the last fragment, or `None` if there isn't any
+    /// fragment.
+    ///
+    /// > **Note**: The fragments might not have been verified yet.
+    pub fn last_fragment_block_hash(&self) -> Option<[u8; 32]> {
+        self.fragments
+            .last()
+            .map(|f| header::hash_from_scale_encoded_header(&f.scale_encoded_header))
+    }
+
+    /// Returns the verifications of the justifications of up to `max` fragments that haven't
+    /// been verified yet, starting with the next fragment that [`Verifier::next`] will verify.
+    ///
+    /// Each [`JustificationVerify`] is independent from the others and from the [`Verifier`],
+    /// and can thus be performed concurrently, for example on multiple threads. The outcome of
+    /// a successful verification must then be passed to
+    /// [`Verifier::inject_justification_verified`], after which [`Verifier::next`] no longer
+    /// verifies the signatures of this justification.
+    ///
+    /// The authorities expected to have signed each justification are determined by decoding
+    /// the headers of the fragments that precede it, without verifying them. If the header of a
+    /// fragment can't be decoded, no verification is returned for the fragments that follow.
+    /// The error is instead reported when [`Verifier::next`] reaches this fragment.
+    pub fn justification_verifications(&self, max: usize) -> Vec<JustificationVerify> {
+        let mut out = Vec::new();
+        if self.wrong_chain_algorithm {
+            return out;
+        }
+
+        let mut authorities_set_id = self.authorities_set_id;
+        let mut authorities_list = self.authorities_list.clone();
+
+        for (fragment_index, fragment) in self.fragments.iter().enumerate().skip(self.index) {
+            if out.len() >= max {
+                break;
+            }
+
+            if self.verified_justifications[fragment_index].is_none() {
+                out.push(JustificationVerify {
+                    fragment_index,
+                    scale_encoded_justification: fragment.scale_encoded_justification.clone(),
+                    authorities_set_id,
+                    authorities_list: authorities_list.clone(),
+                    block_number_bytes: self.block_number_bytes,
+                });
+            }
+
+            let decoded_header =
+                match header::decode(&fragment.scale_encoded_header, self.block_number_bytes) {
+                    Ok(h) => h,
+                    Err(_) => break,
+                };
+
+            if let Some(next_authorities) = authorities_change(&decoded_header) {
+                authorities_list = next_authorities;
+                authorities_set_id = match authorities_set_id.checked_add(1) {
+                    Some(id) => id,
+                    None => break,
+                };
+            }
+        }
+
+        out
+    }
+
+    /// Notifies the [`Verifier`] that the justification of a fragment has been successfully
+    /// verified. See [`Verifier::justification_verifications`].
+    ///
+    /// Has no effect if the [`JustificationVerified`] was obtained from a different
+    /// [`Verifier`].
+    pub fn inject_justification_verified(&mut self, verified: JustificationVerified) {
+        let fragment = match self.fragments.get(verified.fragment_index) {
+            Some(f) => f,
+            None => return,
+        };
+
+        if fragment.scale_encoded_justification != verified.scale_encoded_justification {
+            return;
+        }
+
+        self.verified_justifications[verified.fragment_index] =
+            Some((verified.authorities_set_id, verified.authorities_list));
+    }
+
     pub fn next(mut self, randomness_seed: [u8; 32]) -> Result<Next, Error> {
         if self.wrong_chain_algorithm {
             return Err(Error::WrongChainAlgorithm);
@@ -153,37 +251,39 @@ impl Verifier {
             });
         }
 
-        verify(VerifyConfig {
-            justification,
-            block_number_bytes: self.block_number_bytes,
-            authorities_list: self.authorities_list.iter().map(|a| &a.public_key),
-            authorities_set_id: self.authorities_set_id,
-            randomness_seed,
-        })
-        .map_err(Error::Verify)?;
+        // The signatures don't need to be verified again if they have already been verified
+        // against the same authorities.
+        let already_verified = matches!(
+            &self.verified_justifications[self.index],
+            Some((set_id, list))
+                if *set_id == self.authorities_set_id && *list == self.authorities_list
+        );
 
-        let authorities_list =
-            header::decode(&fragment.scale_encoded_header, self.block_number_bytes)
-                .map_err(Error::InvalidHeader)?
-                .digest
-                .logs()
-                .find_map(|log_item| match log_item {
-                    DigestItemRef::GrandpaConsensus(grandpa_log_item) => match grandpa_log_item {
-                        GrandpaConsensusLogRef::ScheduledChange(change)
-                        | GrandpaConsensusLogRef::ForcedChange { change, .. } => {
-                            Some(change.next_authorities)
-                        }
-                        _ => None,
-                    },
-                    _ => None,
-                })
-                .map(|next_authorities| next_authorities.map(GrandpaAuthority::from).collect());
-
-        self.index += 1;
+        if !already_verified {
+            verify(VerifyConfig {
+                justification,
+                block_number_bytes: self.block_number_bytes,
+                authorities_list: self.authorities_list.iter().map(|a| &a.public_key),
+                authorities_set_id: self.authorities_set_id,
+                randomness_seed,
+            })
+            .map_err(Error::Verify)?;
+        }
+
+        let authorities_list = authorities_change(
+            &header::decode(&fragment.scale_encoded_header, self.block_number_bytes)
+                .map_err(Error::InvalidHeader)?,
+        );
+
+        // `self.index` is always lower than `self.fragments.len()`.
+        self.index = self.index.checked_add(1).unwrap();
 
         if let Some(authorities_list) = authorities_list {
             self.authorities_list = authorities_list;
-            self.authorities_set_id += 1;
+            self.authorities_set_id = self
+                .authorities_set_id
+                .checked_add(1)
+                .ok_or(Error::AuthoritiesSetIdOverflow)?;
         } else if !self.is_proof_complete || self.index != self.fragments.len() {
             return Err(Error::NonMinimalProof);
         }
@@ -196,6 +296,7 @@ impl Verifier {
                     finalized_triggered_authorities: self.authorities_list,
                     finalized_scheduled_change: None,
                 },
+                verified_fragments: self.fragments,
             })
         } else {
             Ok(Next::NotFinished(self))
@@ -209,11 +310,84 @@ pub enum Next {
     Success {
         scale_encoded_header: Vec<u8>,
         chain_information_finality: ChainInformationFinality,
+        /// All the fragments that were passed to [`Verifier::new`], all of which have now been
+        /// verified.
+        verified_fragments: Vec<WarpSyncFragment>,
     },
 }
 
+/// Verification of the signatures of the justification of a fragment.
+///
+/// See [`Verifier::justification_verifications`].
+#[derive(Debug, Clone)]
+pub struct JustificationVerify {
+    fragment_index: usize,
+    scale_encoded_justification: Vec<u8>,
+    authorities_set_id: u64,
+    authorities_list: Vec<GrandpaAuthority>,
+    block_number_bytes: usize,
+}
+
+impl JustificationVerify {
+    /// Performs the verification.
+    ///
+    /// Must be passed a randomly-generated value that is used by the verification process. Note
+    /// that the verification is still deterministic.
+    pub fn verify(self, randomness_seed: [u8; 32]) -> Result<JustificationVerified, Error> {
+        let justification = finality::justification::decode::decode_grandpa(
+            &self.scale_encoded_justification,
+            self.block_number_bytes,
+        )
+        .map_err(Error::InvalidJustification)?;
+
+        verify(VerifyConfig {
+            justification,
+            block_number_bytes: self.block_number_bytes,
+            authorities_list: self.authorities_list.iter().map(|a| &a.public_key),
+            authorities_set_id: self.authorities_set_id,
+            randomness_seed,
+        })
+        .map_err(Error::Verify)?;
+
+        Ok(JustificationVerified {
+            fragment_index: self.fragment_index,
+            scale_encoded_justification: self.scale_encoded_justification,
+            authorities_set_id: self.authorities_set_id,
+            authorities_list: self.authorities_list,
+        })
+    }
+}
+
+/// Successful outcome of [`JustificationVerify::verify`]. Must be passed to
+/// [`Verifier::inject_justification_verified`].
+#[derive(Debug, Clone)]
+pub struct JustificationVerified {
+    fragment_index: usize,
+    scale_encoded_justification: Vec<u8>,
+    authorities_set_id: u64,
+    authorities_list: Vec<GrandpaAuthority>,
+}
+
+/// Returns the list of authorities enacted by the given header, if any.
+fn authorities_change(header: &HeaderRef) -> Option<Vec<GrandpaAuthority>> {
+    header
+        .digest
+        .logs()
+        .find_map(|log_item| match log_item {
+            DigestItemRef::GrandpaConsensus(grandpa_log_item) => match grandpa_log_item {
+                GrandpaConsensusLogRef::ScheduledChange(change)
+                | GrandpaConsensusLogRef::ForcedChange { change, .. } => {
+                    Some(change.next_authorities)
+                }
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|next_authorities| next_authorities.map(GrandpaAuthority::from).collect())
+}
+
 /// Fragment to be verified.
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct WarpSyncFragment {
     /// Header of a block in the chain.
     pub scale_encoded_header: Vec<u8>,
@@ -221,3 +395,187 @@ pub struct WarpSyncFragment {
     /// Justification that proves the finality of [`WarpSyncFragment::scale_encoded_header`].
     pub scale_encoded_justification: Vec<u8>,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, JustificationVerified, Next, Verifier, WarpSyncFragment};
+    use crate::{chain::chain_information::ChainInformationFinalityRef, header};
+    use alloc::vec::Vec;
+    use core::num::NonZeroU64;
+
+    fn authorities() -> Vec<header::GrandpaAuthority> {
+        alloc::vec![header::GrandpaAuthority {
+            public_key: [7; 32],
+            weight: NonZeroU64::new(1).unwrap(),
+        }]
+    }
+
+    /// Builds a fragment whose justification has no signature and thus fails to verify.
+    fn fragment(number: u64) -> WarpSyncFragment {
+        fragment_with_digest(number, header::DigestRef::empty())
+    }
+
+    /// Same as [`fragment`], but with the given digest in the header.
+    fn fragment_with_digest(number: u64, digest: header::DigestRef) -> WarpSyncFragment {
+        let scale_encoded_header = header::HeaderRef {
+            parent_hash: &[0; 32],
+            number,
+            state_root: &[0; 32],
+            extrinsics_root: &[0; 32],
+            digest,
+        }
+        .scale_encoding_vec(4);
+
+        let mut scale_encoded_justification = Vec::new();
+        scale_encoded_justification.extend_from_slice(&1u64.to_le_bytes());
+        scale_encoded_justification.extend_from_slice(&header::hash_from_scale_encoded_header(
+            &scale_encoded_header,
+        ));
+        scale_encoded_justification
+            .extend_from_slice(&u32::try_from(number).unwrap().to_le_bytes());
+        scale_encoded_justification.extend_from_slice(&[0, 0]);
+
+        WarpSyncFragment {
+            scale_encoded_header,
+            scale_encoded_justification,
+        }
+    }
+
+    fn verifier(fragments: Vec<WarpSyncFragment>) -> Verifier {
+        let authorities = authorities();
+        Verifier::new(
+            ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id: 5,
+                finalized_triggered_authorities: &authorities,
+                finalized_scheduled_change: None,
+            },
+            4,
+            fragments,
+            true,
+        )
+    }
+
+    #[test]
+    fn justification_verifications_respects_max() {
+        let verifier = verifier((1..=3).map(fragment).collect());
+        let verifications = verifier.justification_verifications(2);
+        assert_eq!(verifications.len(), 2);
+        assert_eq!(verifications[0].fragment_index, 0);
+        assert_eq!(verifications[1].fragment_index, 1);
+        assert!(verifications
+            .iter()
+            .all(|v| v.authorities_set_id == 5 && v.authorities_list == authorities()));
+    }
+
+    #[test]
+    fn justification_verifications_stops_after_invalid_header() {
+        let mut fragments = (1..=3).map(fragment).collect::<Vec<_>>();
+        fragments[1].scale_encoded_header = alloc::vec![1, 2, 3];
+        let verifications = verifier(fragments).justification_verifications(10);
+        assert_eq!(verifications.len(), 2);
+    }
+
+    #[test]
+    fn justification_verifications_wrong_algorithm() {
+        let verifier = Verifier::new(
+            ChainInformationFinalityRef::Outsourced,
+            4,
+            alloc::vec![fragment(1)],
+            true,
+        );
+        assert!(verifier.justification_verifications(10).is_empty());
+    }
+
+    #[test]
+    fn invalid_justification_rejected() {
+        let mut fragments = alloc::vec![fragment(1)];
+        fragments[0].scale_encoded_justification = alloc::vec![0; 3];
+        let verifications = verifier(fragments).justification_verifications(1);
+        assert!(matches!(
+            verifications.into_iter().next().unwrap().verify([0; 32]),
+            Err(Error::InvalidJustification(_))
+        ));
+    }
+
+    #[test]
+    fn justification_without_signatures_fails() {
+        let verifier = verifier(alloc::vec![fragment(1)]);
+        let verification = verifier.justification_verifications(1).pop().unwrap();
+        assert!(matches!(
+            verification.verify([0; 32]),
+            Err(Error::Verify(_))
+        ));
+        assert!(matches!(verifier.next([0; 32]), Err(Error::Verify(_))));
+    }
+
+    #[test]
+    fn injected_verification_skips_signatures() {
+        let frag = fragment(1);
+        let mut verifier = verifier(alloc::vec![frag.clone()]);
+        verifier.inject_justification_verified(JustificationVerified {
+            fragment_index: 0,
+            scale_encoded_justification: frag.scale_encoded_justification,
+            authorities_set_id: 5,
+            authorities_list: authorities(),
+        });
+        assert!(matches!(verifier.next([0; 32]), Ok(Next::Success { .. })));
+    }
+
+    #[test]
+    fn injected_verification_against_other_authorities_ignored() {
+        let frag = fragment(1);
+        let mut verifier = verifier(alloc::vec![frag.clone()]);
+        verifier.inject_justification_verified(JustificationVerified {
+            fragment_index: 0,
+            scale_encoded_justification: frag.scale_encoded_justification,
+            authorities_set_id: 4,
+            authorities_list: authorities(),
+        });
+        assert!(matches!(verifier.next([0; 32]), Err(Error::Verify(_))));
+    }
+
+    #[test]
+    fn injected_verification_of_other_justification_ignored() {
+        let mut verifier = verifier(alloc::vec![fragment(1)]);
+        verifier.inject_justification_verified(JustificationVerified {
+            fragment_index: 0,
+            scale_encoded_justification: fragment(2).scale_encoded_justification,
+            authorities_set_id: 5,
+            authorities_list: authorities(),
+        });
+        assert!(matches!(verifier.next([0; 32]), Err(Error::Verify(_))));
+    }
+
+    #[test]
+    fn authorities_set_id_overflow_rejected() {
+        let digest_items = [header::DigestItem::GrandpaConsensus(
+            header::GrandpaConsensusLog::ScheduledChange(header::GrandpaScheduledChange {
+                next_authorities: authorities(),
+                delay: 0,
+            }),
+        )];
+        let frag = fragment_with_digest(1, header::DigestRef::from_slice(&digest_items).unwrap());
+
+        let authorities = authorities();
+        let mut verifier = Verifier::new(
+            ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id: u64::max_value(),
+                finalized_triggered_authorities: &authorities,
+                finalized_scheduled_change: None,
+            },
+            4,
+            alloc::vec![frag.clone()],
+            true,
+        );
+        verifier.inject_justification_verified(JustificationVerified {
+            fragment_index: 0,
+            scale_encoded_justification: frag.scale_encoded_justification,
+            authorities_set_id: u64::max_value(),
+            authorities_list: authorities.clone(),
+        });
+        assert!(matches!(
+            verifier.next([0; 32]),
+            Err(Error::AuthoritiesSetIdOverflow)
+        ));
+    }
+}
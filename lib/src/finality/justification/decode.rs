@@ -15,6 +15,8 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+#![deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
+
 use crate::header;
 
 use alloc::vec::Vec;
@@ -171,13 +173,10 @@ impl<'a> Iterator for PrecommitsRefIter<'a> {
                 pointer,
                 remaining_len,
             } => {
-                if *remaining_len == 0 {
-                    return None;
-                }
+                *remaining_len = remaining_len.checked_sub(1)?;
 
                 let (new_pointer, precommit) = precommit(*block_number_bytes)(pointer).unwrap();
                 *pointer = new_pointer;
-                *remaining_len -= 1;
 
                 Some(precommit)
             }
@@ -281,15 +280,12 @@ impl<'a> Iterator for VotesAncestriesIter<'a> {
     type Item = header::HeaderRef<'a>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.num == 0 {
-            return None;
-        }
+        self.num = self.num.checked_sub(1)?;
 
         // Validity is guaranteed when the `VotesAncestriesIter` is constructed.
         let (item, new_slice) =
             header::decode_partial(self.slice, self.block_number_bytes).unwrap();
         self.slice = new_slice;
-        self.num -= 1;
 
         Some(item)
     }
@@ -0,0 +1,309 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Fuzzing harnesses.
+//!
+//! This module is only available if the `fuzzing` feature is enabled.
+//!
+//! Each function of this module whose name ends with `_target` accepts arbitrary bytes and
+//! feeds them to a specific part of smoldot. These functions never panic unless a bug is found
+//! in smoldot, and are meant to be called from a fuzzer such as `cargo fuzz` or AFL. The
+//! `fuzz` directory at the root of the smoldot repository contains fuzzing targets that simply
+//! call these functions.
+//!
+//! The fuzzing targets interpret the first few bytes of their input as parameters. Fuzzers
+//! typically work better when they are provided with an initial corpus of valid inputs. The
+//! functions whose name ends with `_corpus_entry` build such inputs out of real-world data,
+//! such as the headers or proofs of a specific chain, making it possible to fuzz smoldot
+//! against the data formats of this chain.
+
+use crate::{
+    header, json_rpc, libp2p,
+    trie::{self, proof_decode},
+};
+
+use alloc::vec::Vec;
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use rand::{Rng as _, SeedableRng as _};
+
+/// Decodes a block header, then checks that re-encoding it produces something decodable.
+///
+/// The first byte of the input is the number of bytes used to encode block numbers, minus one.
+/// The rest of the input is the header.
+pub fn header_decode_target(data: &[u8]) {
+    let Some((block_number_bytes, header)) = data.split_first() else {
+        return;
+    };
+    let block_number_bytes = usize::from(*block_number_bytes) + 1;
+
+    if let Ok(decoded) = header::decode(header, block_number_bytes) {
+        let encoded = decoded.scale_encoding_vec(block_number_bytes);
+        assert!(header::decode(&encoded, block_number_bytes).is_ok());
+    }
+}
+
+/// Builds an input for [`header_decode_target`].
+///
+/// # Panic
+///
+/// Panics if `block_number_bytes` isn't between 1 and 256.
+///
+pub fn header_decode_corpus_entry(header: &[u8], block_number_bytes: usize) -> Vec<u8> {
+    assert!((1..=256).contains(&block_number_bytes));
+    let mut out = Vec::with_capacity(1 + header.len());
+    out.push(u8::try_from(block_number_bytes - 1).unwrap());
+    out.extend_from_slice(header);
+    out
+}
+
+/// Decodes a Merkle proof, both at once and in chunks, and checks that the outcomes match.
+///
+/// The first 32 bytes of the input are the hash of the trie root. The next byte is the size of
+/// the chunks passed to [`proof_decode::IncrementalDecoder`], minus one. The rest of the input
+/// is the proof.
+pub fn proof_decode_target(data: &[u8]) {
+    if data.len() < 33 {
+        return;
+    }
+    let trie_root_hash = <&[u8; 32]>::try_from(&data[..32]).unwrap();
+    let chunk_size = usize::from(data[32]) + 1;
+    let proof = &data[33..];
+
+    let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+        proof,
+        trie_root_hash,
+    });
+
+    let mut decoder = proof_decode::IncrementalDecoder::new();
+    let incremental = proof
+        .chunks(chunk_size)
+        .try_for_each(|chunk| decoder.feed(chunk))
+        .and_then(|()| decoder.finish(trie_root_hash));
+    assert_eq!(decoded.is_ok(), incremental.is_ok());
+
+    if let Ok(decoded) = decoded {
+        for (key, _) in decoded.iter_ordered() {
+            let _ = decoded.closest_ancestor(key);
+            let _ = decoded.closest_descendant_merkle_value(key);
+        }
+    }
+}
+
+/// Builds an input for [`proof_decode_target`].
+///
+/// # Panic
+///
+/// Panics if `chunk_size` isn't between 1 and 256.
+///
+pub fn proof_decode_corpus_entry(
+    proof: &[u8],
+    trie_root_hash: &[u8; 32],
+    chunk_size: usize,
+) -> Vec<u8> {
+    assert!((1..=256).contains(&chunk_size));
+    let mut out = Vec::with_capacity(33 + proof.len());
+    out.extend_from_slice(trie_root_hash);
+    out.push(u8::try_from(chunk_size - 1).unwrap());
+    out.extend_from_slice(proof);
+    out
+}
+
+/// Generates a random trie and checks that the trie-related modules of this crate behave the
+/// same way as the naive implementation found in [`crate::trie::reference`].
+///
+/// The first 32 bytes of the input are used as the seed of the random number generator. The
+/// rest of the input is ignored.
+pub fn trie_differential_target(data: &[u8]) {
+    let Some(seed) = data.get(..32) else {
+        return;
+    };
+    let mut rng = rand_chacha::ChaCha20Rng::from_seed(<[u8; 32]>::try_from(seed).unwrap());
+
+    let mut main_trie = trie::reference::random_entries(&mut rng);
+    for child_trie in 0..rng.gen_range(0..3u8) {
+        let child_entries = trie::reference::random_entries(&mut rng);
+        trie::reference::check(&child_entries, &mut rng).unwrap();
+        trie::reference::insert_child_trie(&mut main_trie, &[child_trie], &child_entries);
+    }
+    trie::reference::check(&main_trie, &mut rng).unwrap();
+}
+
+/// Feeds Yamux frames to a Yamux state machine. Incoming substreams are accepted, and the data
+/// sent back is silently discarded.
+///
+/// The lowest bit of the first byte of the input indicates whether the local side has initiated
+/// the connection. The rest of the input is the data received from the remote.
+pub fn yamux_frames_target(data: &[u8]) {
+    let Some((flags, mut data)) = data.split_first() else {
+        return;
+    };
+
+    let mut yamux = libp2p::connection::yamux::Yamux::new(libp2p::connection::yamux::Config {
+        is_initiator: (flags & 0x1) != 0,
+        capacity: 0,
+        randomness_seed: [0; 32],
+        max_out_data_frame_size: NonZeroU32::new(8192).unwrap(),
+        max_simultaneous_queued_pongs: NonZeroUsize::new(4).unwrap(),
+        max_simultaneous_rst_substreams: NonZeroUsize::new(1024).unwrap(),
+    });
+
+    loop {
+        let outcome = match yamux.incoming_data(data) {
+            Ok(outcome) => outcome,
+            Err(_) => return,
+        };
+
+        yamux = outcome.yamux;
+        data = &data[outcome.bytes_read..];
+
+        if let Some(libp2p::connection::yamux::IncomingDataDetail::IncomingSubstream) =
+            outcome.detail
+        {
+            yamux.accept_pending_substream(()).unwrap();
+        } else if outcome.detail.is_none() && outcome.bytes_read == 0 {
+            return;
+        }
+
+        let dead_substreams = yamux
+            .dead_substreams()
+            .map(|(id, _, _)| id)
+            .collect::<Vec<_>>();
+        for id in dead_substreams {
+            yamux.remove_dead_substream(id);
+        }
+
+        while yamux.extract_next(usize::MAX).is_some() {}
+    }
+}
+
+/// Builds an input for [`yamux_frames_target`].
+pub fn yamux_frames_corpus_entry(frames: &[u8], is_initiator: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + frames.len());
+    out.push(u8::from(is_initiator));
+    out.extend_from_slice(frames);
+    out
+}
+
+/// Performs a Noise handshake where the remote sends the input data. The data sent back is
+/// silently discarded.
+///
+/// The lowest bit of the first byte of the input indicates whether the local side has initiated
+/// the handshake. The rest of the input is the data received from the remote.
+///
+/// The local key is constant rather than being derived from the input, as the purpose of this
+/// target isn't to fuzz the cryptographic code but everything around it.
+pub fn noise_handshake_target(data: &[u8]) {
+    let Some((flags, mut data)) = data.split_first() else {
+        return;
+    };
+
+    let key = libp2p::connection::NoiseKey::new(&[0; 32]);
+    let mut handshake =
+        libp2p::connection::noise::HandshakeInProgress::new(libp2p::connection::noise::Config {
+            key: &key,
+            is_initiator: (flags & 0x1) != 0,
+            prologue: &[],
+        });
+
+    let mut out_buffer = alloc::vec![0; 4096];
+
+    loop {
+        let mut read_write = libp2p::read_write::ReadWrite {
+            now: Duration::new(0, 0),
+            incoming_buffer: Some(data),
+            outgoing_buffer: Some((&mut out_buffer, &mut [])),
+            read_bytes: 0,
+            written_bytes: 0,
+            wake_up_after: None,
+        };
+
+        let outcome = handshake.read_write(&mut read_write);
+        let (read_bytes, written_bytes) = (read_write.read_bytes, read_write.written_bytes);
+        data = &data[read_bytes..];
+
+        match outcome {
+            Ok(libp2p::connection::noise::NoiseHandshake::InProgress(h)) => handshake = h,
+            Ok(libp2p::connection::noise::NoiseHandshake::Success { .. }) | Err(_) => return,
+        }
+
+        if read_bytes == 0 && written_bytes == 0 {
+            return;
+        }
+    }
+}
+
+/// Builds an input for [`noise_handshake_target`].
+pub fn noise_handshake_corpus_entry(received_data: &[u8], is_initiator: bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(1 + received_data.len());
+    out.push(u8::from(is_initiator));
+    out.extend_from_slice(received_data);
+    out
+}
+
+/// Parses a JSON-RPC request, then checks that the list of parameters of known methods can be
+/// re-encoded and parsed again.
+///
+/// The input is the UTF-8 JSON-RPC request. Inputs that aren't valid UTF-8 are ignored.
+pub fn json_rpc_parse_target(data: &[u8]) {
+    let Ok(request) = core::str::from_utf8(data) else {
+        return;
+    };
+
+    let _ = json_rpc::parse::parse_call(request);
+    if let Ok((id, call)) = json_rpc::methods::parse_json_call(request) {
+        let rebuilt = json_rpc::methods::build_json_call_object_parameters(Some(id), call);
+        assert!(json_rpc::methods::parse_json_call(&rebuilt).is_ok());
+    }
+}
+
+/// Builds an input for [`json_rpc_parse_target`].
+pub fn json_rpc_parse_corpus_entry(request: &str) -> Vec<u8> {
+    request.as_bytes().to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn corpus_entries_accepted() {
+        super::header_decode_target(&super::header_decode_corpus_entry(
+            include_bytes!("./header/tests-header-polkadot-512271"),
+            4,
+        ));
+        super::yamux_frames_target(&super::yamux_frames_corpus_entry(
+            &[0, 1, 0, 1, 0, 0, 0, 1, 0, 0, 0, 0],
+            true,
+        ));
+        super::noise_handshake_target(&super::noise_handshake_corpus_entry(&[0; 64], false));
+        super::trie_differential_target(&[0; 32]);
+        super::json_rpc_parse_target(&super::json_rpc_parse_corpus_entry(
+            r#"{"jsonrpc":"2.0","id":1,"method":"system_name","params":[]}"#,
+        ));
+    }
+
+    #[test]
+    fn empty_inputs() {
+        super::header_decode_target(&[]);
+        super::proof_decode_target(&[]);
+        super::trie_differential_target(&[]);
+        super::yamux_frames_target(&[]);
+        super::noise_handshake_target(&[]);
+        super::json_rpc_parse_target(&[]);
+    }
+}
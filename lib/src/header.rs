@@ -73,6 +73,8 @@
 
 // TODO: consider rewriting the encoding into a more legible style
 
+#![deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
+
 use crate::{trie, util};
 
 use alloc::{vec, vec::Vec};
@@ -522,7 +524,7 @@ impl<'a> DigestRef<'a> {
         match &mut self.inner {
             DigestRefInner::Parsed(list) => {
                 debug_assert!(!list.is_empty());
-                debug_assert_eq!(seal_pos, list.len() - 1);
+                debug_assert_eq!(Some(seal_pos), list.len().checked_sub(1));
 
                 let item = &list[seal_pos];
                 *list = &list[..seal_pos];
@@ -539,7 +541,7 @@ impl<'a> DigestRef<'a> {
                 digest_logs_len,
                 block_number_bytes,
             } => {
-                debug_assert_eq!(seal_pos, *digest_logs_len - 1);
+                debug_assert_eq!(Some(seal_pos), digest_logs_len.checked_sub(1));
 
                 let mut iter = LogsIter {
                     inner: LogsIterInner::Undecoded {
@@ -559,8 +561,9 @@ impl<'a> DigestRef<'a> {
                     ..
                 } = iter.inner
                 {
-                    *digest_logs_len -= 1;
-                    *digest = &digest[..digest.len() - pointer.len()];
+                    *digest_logs_len = seal_pos;
+                    // `pointer` is always a suffix of `digest`.
+                    *digest = &digest[..digest.len().checked_sub(pointer.len()).unwrap()];
                     self.babe_seal_index = None;
                     debug_assert_eq!(remaining_len, 1);
                 } else {
@@ -594,6 +597,19 @@ impl<'a> DigestRef<'a> {
         }
     }
 
+    /// Returns an iterator to the log items in this digest whose consensus engine is the given
+    /// one. See [`DigestItemRef::engine_id`].
+    ///
+    /// This works both with the consensus engines that smoldot recognizes and with the ones it
+    /// doesn't recognize.
+    pub fn logs_with_engine(
+        &self,
+        engine_id: [u8; 4],
+    ) -> impl Iterator<Item = DigestItemRef<'a>> + Clone + 'a {
+        self.logs()
+            .filter(move |item| item.engine_id() == Some(engine_id))
+    }
+
     /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
     /// encoding of the digest items.
     pub fn scale_encoding(
@@ -650,13 +666,13 @@ impl<'a> DigestRef<'a> {
                 }
                 DigestItem::BabeConsensus(BabeConsensusLog::OnDisabled(_)) => {}
                 DigestItem::GrandpaConsensus(_) => {}
-                DigestItem::AuraSeal(_) if item_num == slice.len() - 1 => {
+                DigestItem::AuraSeal(_) if slice.len().checked_sub(1) == Some(item_num) => {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                     aura_seal_index = Some(item_num);
                 }
                 DigestItem::AuraSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItem::BabeSeal(_) if item_num == slice.len() - 1 => {
+                DigestItem::BabeSeal(_) if slice.len().checked_sub(1) == Some(item_num) => {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                     babe_seal_index = Some(item_num);
@@ -668,7 +684,7 @@ impl<'a> DigestRef<'a> {
                     has_runtime_environment_updated = true;
                 }
                 DigestItem::BabeSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItem::UnknownSeal { .. } if item_num == slice.len() - 1 => {
+                DigestItem::UnknownSeal { .. } if slice.len().checked_sub(1) == Some(item_num) => {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                 }
@@ -750,13 +766,13 @@ impl<'a> DigestRef<'a> {
                 }
                 DigestItemRef::BabeConsensus(BabeConsensusLogRef::OnDisabled(_)) => {}
                 DigestItemRef::GrandpaConsensus(_) => {}
-                DigestItemRef::AuraSeal(_) if item_num == digest_logs_len - 1 => {
+                DigestItemRef::AuraSeal(_) if digest_logs_len.checked_sub(1) == Some(item_num) => {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                     aura_seal_index = Some(item_num);
                 }
                 DigestItemRef::AuraSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItemRef::BabeSeal(_) if item_num == digest_logs_len - 1 => {
+                DigestItemRef::BabeSeal(_) if digest_logs_len.checked_sub(1) == Some(item_num) => {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                     babe_seal_index = Some(item_num);
@@ -768,7 +784,9 @@ impl<'a> DigestRef<'a> {
                     has_runtime_environment_updated = true;
                 }
                 DigestItemRef::BabeSeal(_) => return Err(Error::SealIsntLastItem),
-                DigestItemRef::UnknownSeal { .. } if item_num == digest_logs_len - 1 => {
+                DigestItemRef::UnknownSeal { .. }
+                    if digest_logs_len.checked_sub(1) == Some(item_num) =>
+                {
                     debug_assert!(aura_seal_index.is_none());
                     debug_assert!(babe_seal_index.is_none());
                 }
@@ -858,6 +876,15 @@ impl Digest {
         DigestRef::from(self).logs()
     }
 
+    /// Returns an iterator to the log items in this digest whose consensus engine is the given
+    /// one. See [`DigestRef::logs_with_engine`].
+    pub fn logs_with_engine(
+        &self,
+        engine_id: [u8; 4],
+    ) -> impl Iterator<Item = DigestItemRef> + Clone {
+        DigestRef::from(self).logs_with_engine(engine_id)
+    }
+
     /// Returns the Aura seal digest item, if any.
     pub fn aura_seal(&self) -> Option<&[u8; 64]> {
         DigestRef::from(self).aura_seal()
@@ -964,14 +991,11 @@ impl<'a> Iterator for LogsIter<'a> {
                 remaining_len,
                 block_number_bytes,
             } => {
-                if *remaining_len == 0 {
-                    return None;
-                }
+                *remaining_len = remaining_len.checked_sub(1)?;
 
                 // Validity is guaranteed when the `DigestRef` is constructed.
                 let (item, new_pointer) = decode_item(pointer, *block_number_bytes).unwrap();
                 *pointer = new_pointer;
-                *remaining_len -= 1;
 
                 Some(item)
             }
@@ -1069,6 +1093,27 @@ impl<'a> DigestItemRef<'a> {
         matches!(self, DigestItemRef::GrandpaConsensus(_))
     }
 
+    /// Returns the identifier of the consensus engine this item relates to, or `None` if the
+    /// item isn't related to any consensus engine.
+    ///
+    /// The identifiers of the consensus engines that smoldot recognizes are `aura`, `BABE`, and
+    /// `FRNK` (Grandpa).
+    pub fn engine_id(&self) -> Option<[u8; 4]> {
+        match self {
+            DigestItemRef::AuraPreDigest(_)
+            | DigestItemRef::AuraSeal(_)
+            | DigestItemRef::AuraConsensus(_) => Some(*b"aura"),
+            DigestItemRef::BabePreDigest(_)
+            | DigestItemRef::BabeConsensus(_)
+            | DigestItemRef::BabeSeal(_) => Some(*b"BABE"),
+            DigestItemRef::GrandpaConsensus(_) => Some(*b"FRNK"),
+            DigestItemRef::UnknownConsensus { engine, .. }
+            | DigestItemRef::UnknownPreRuntime { engine, .. }
+            | DigestItemRef::UnknownSeal { engine, .. } => Some(*engine),
+            DigestItemRef::Other(_) | DigestItemRef::RuntimeEnvironmentUpdated => None,
+        }
+    }
+
     /// Returns an iterator to list of buffers which, when concatenated, produces the SCALE
     /// encoding of that digest item.
     pub fn scale_encoding(
@@ -147,15 +147,17 @@ impl<'a> BabeNextEpochRef<'a> {
                 Err(_) => return Err(Error::TooShort),
             };
 
-        if slice.len() != authorities_len * 40 + 32 {
+        // The number of authorities is untrusted and the multiplication might overflow.
+        let authorities_bytes = authorities_len.checked_mul(40).ok_or(Error::TooShort)?;
+        if slice.len().checked_sub(authorities_bytes) != Some(32) {
             return Err(Error::TooShort);
         }
 
         Ok(BabeNextEpochRef {
             authorities: BabeAuthoritiesIter(BabeAuthoritiesIterInner::Raw(
-                slice[0..authorities_len * 40].chunks(40),
+                slice[0..authorities_bytes].chunks(40),
             )),
-            randomness: <&[u8; 32]>::try_from(&slice[authorities_len * 40..]).unwrap(),
+            randomness: <&[u8; 32]>::try_from(&slice[authorities_bytes..]).unwrap(),
         })
     }
 
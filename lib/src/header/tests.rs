@@ -78,3 +78,89 @@ fn regression_2336() {
         4,
     );
 }
+
+#[test]
+fn unknown_engines_reencode() {
+    // Header with a pre-runtime item, a consensus item, and a seal of an unknown consensus
+    // engine, plus an `Other` item.
+    let digest = [
+        super::DigestItem::UnknownPreRuntime {
+            engine: *b"test",
+            opaque: vec![1, 2, 3],
+        },
+        super::DigestItem::Other(vec![4, 5]),
+        super::DigestItem::UnknownConsensus {
+            engine: *b"test",
+            opaque: vec![],
+        },
+        super::DigestItem::UnknownSeal {
+            engine: *b"othr",
+            opaque: vec![6; 80],
+        },
+    ];
+
+    let encoded = super::HeaderRef {
+        parent_hash: &[1; 32],
+        number: 100,
+        state_root: &[2; 32],
+        extrinsics_root: &[3; 32],
+        digest: super::DigestRef::from_slice(&digest).unwrap(),
+    }
+    .scale_encoding_vec(4);
+
+    let decoded = super::decode(&encoded, 4).unwrap();
+    assert_eq!(decoded.scale_encoding_vec(4), encoded);
+
+    let test_items = decoded
+        .digest
+        .logs_with_engine(*b"test")
+        .collect::<Vec<_>>();
+    assert_eq!(test_items.len(), 2);
+    assert!(matches!(
+        test_items[0],
+        super::DigestItemRef::UnknownPreRuntime {
+            opaque: &[1, 2, 3],
+            ..
+        }
+    ));
+    assert!(matches!(
+        test_items[1],
+        super::DigestItemRef::UnknownConsensus { opaque: &[], .. }
+    ));
+
+    assert_eq!(decoded.digest.logs_with_engine(*b"othr").count(), 1);
+    assert_eq!(decoded.digest.logs_with_engine(*b"BABE").count(), 0);
+}
+
+#[test]
+fn babe_next_epoch_overflowing_authorities_len() {
+    // The number of authorities is chosen so that multiplying it by 40 wraps around to 24. The
+    // remaining data would then look like 24 bytes of authorities followed by the randomness.
+    let mut log = vec![1, 0b0001_0011];
+    log.extend_from_slice(&0x0666_6666_6666_6667u64.to_le_bytes());
+    log.extend_from_slice(&[0; 56]);
+    assert!(super::BabeConsensusLogRef::from_slice(&log).is_err());
+}
+
+#[test]
+fn corrupted_headers_dont_panic() {
+    // Decoding must return an error rather than panic no matter how the input is corrupted.
+    // The fuzzing targets in the `fuzz` directory explore this more thoroughly.
+    let header = include_bytes!("./tests-header-polkadot-512271");
+
+    for len in 0..header.len() {
+        let _ = super::decode(&header[..len], 4);
+    }
+
+    let mut corrupted = header.to_vec();
+    for index in 0..corrupted.len() {
+        for value in [0x00, 0xff] {
+            let original = corrupted[index];
+            corrupted[index] = value;
+            if let Ok(decoded) = super::decode(&corrupted, 4) {
+                let _ = decoded.scale_encoding_vec(4);
+            }
+            corrupted[index] = original;
+        }
+    }
+}
@@ -57,6 +57,8 @@
 //! The checksum is verified when the human-readable format is turned into a public key. Its
 //! presence of a checksum guarantees that simple copying mistakes will be caught.
 //!
+//! This format is implemented in the [`ss58`] module.
+//!
 //! ## Private keys
 //!
 //! Examples:
@@ -85,5 +87,4 @@
 
 pub mod keystore;
 pub mod seed_phrase;
-
-// TODO: implement ss58
+pub mod ss58;
@@ -0,0 +1,431 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Human-readable format for public keys.
+//!
+//! See [the module-level documentation of `identity`](..) for an explanation of the format.
+//!
+//! Only addresses that contain a 32 bytes public key are supported.
+//!
+//! # Network identifiers
+//!
+//! The list of network identifiers is maintained in the
+//! [SS58 registry](https://github.com/paritytech/ss58-registry). This module contains a copy of
+//! some of its entries in [`KNOWN_NETWORKS`], which [`network_by_prefix`] and
+//! [`network_by_name`] use in order to convert between network identifiers and network names.
+//!
+//! The network identifiers `46` and `47` are reserved and refused by [`decode`].
+
+use alloc::{string::String, vec::Vec};
+
+/// Decoded SS58 address.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// Network identifier found in the address. Indicates which chain the address is meant for.
+    pub chain_prefix: u16,
+
+    /// Public key found in the address.
+    pub public_key: [u8; 32],
+}
+
+/// Entry of the SS58 registry of network identifiers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KnownNetwork {
+    /// Network identifier of the addresses of this network.
+    pub prefix: u16,
+    /// Name of the network in the registry.
+    pub network: &'static str,
+    /// Human-readable name of the network.
+    pub display_name: &'static str,
+}
+
+/// Subset of the SS58 registry, ordered by network identifier.
+pub const KNOWN_NETWORKS: &[KnownNetwork] = &[
+    KnownNetwork {
+        prefix: 0,
+        network: "polkadot",
+        display_name: "Polkadot Relay Chain",
+    },
+    KnownNetwork {
+        prefix: 2,
+        network: "kusama",
+        display_name: "Kusama Relay Chain",
+    },
+    KnownNetwork {
+        prefix: 5,
+        network: "astar",
+        display_name: "Astar Network",
+    },
+    KnownNetwork {
+        prefix: 6,
+        network: "bifrost",
+        display_name: "Bifrost",
+    },
+    KnownNetwork {
+        prefix: 7,
+        network: "edgeware",
+        display_name: "Edgeware",
+    },
+    KnownNetwork {
+        prefix: 8,
+        network: "karura",
+        display_name: "Karura",
+    },
+    KnownNetwork {
+        prefix: 10,
+        network: "acala",
+        display_name: "Acala",
+    },
+    KnownNetwork {
+        prefix: 12,
+        network: "polymesh",
+        display_name: "Polymesh",
+    },
+    KnownNetwork {
+        prefix: 30,
+        network: "phala",
+        display_name: "Phala Network",
+    },
+    KnownNetwork {
+        prefix: 31,
+        network: "litentry",
+        display_name: "Litentry Network",
+    },
+    KnownNetwork {
+        prefix: 32,
+        network: "robonomics",
+        display_name: "Robonomics",
+    },
+    KnownNetwork {
+        prefix: 36,
+        network: "centrifuge",
+        display_name: "Centrifuge Chain",
+    },
+    KnownNetwork {
+        prefix: 37,
+        network: "nodle",
+        display_name: "Nodle Chain",
+    },
+    KnownNetwork {
+        prefix: 38,
+        network: "kilt",
+        display_name: "KILT Spiritnet",
+    },
+    KnownNetwork {
+        prefix: 42,
+        network: "substrate",
+        display_name: "Substrate",
+    },
+    KnownNetwork {
+        prefix: 172,
+        network: "parallel",
+        display_name: "Parallel",
+    },
+    KnownNetwork {
+        prefix: 1284,
+        network: "moonbeam",
+        display_name: "Moonbeam",
+    },
+    KnownNetwork {
+        prefix: 1285,
+        network: "moonriver",
+        display_name: "Moonriver",
+    },
+    KnownNetwork {
+        prefix: 2032,
+        network: "interlay",
+        display_name: "Interlay",
+    },
+    KnownNetwork {
+        prefix: 2092,
+        network: "kintsugi",
+        display_name: "Kintsugi",
+    },
+];
+
+/// Returns the entry of [`KNOWN_NETWORKS`] with the given network identifier, if any.
+pub fn network_by_prefix(prefix: u16) -> Option<&'static KnownNetwork> {
+    KNOWN_NETWORKS
+        .binary_search_by_key(&prefix, |n| n.prefix)
+        .ok()
+        .map(|index| &KNOWN_NETWORKS[index])
+}
+
+/// Returns the entry of [`KNOWN_NETWORKS`] with the given network name, if any. The comparison
+/// is case-insensitive.
+pub fn network_by_name(name: &str) -> Option<&'static KnownNetwork> {
+    KNOWN_NETWORKS
+        .iter()
+        .find(|n| n.network.eq_ignore_ascii_case(name))
+}
+
+/// Returns `true` if the given network identifier is reserved and must not be used.
+pub fn is_reserved_prefix(prefix: u16) -> bool {
+    matches!(prefix, 46 | 47)
+}
+
+/// Decodes an SS58 address and verifies its checksum.
+pub fn decode(encoded: &str) -> Result<Decoded, DecodeError> {
+    let bytes = bs58::decode(encoded)
+        .into_vec()
+        .map_err(|_| DecodeError::InvalidBs58)?;
+
+    let (chain_prefix, prefix_len) = match bytes.first() {
+        Some(b @ 0..=63) => (u16::from(*b), 1),
+        Some(b0 @ 64..=127) => {
+            let b1 = *bytes.get(1).ok_or(DecodeError::InvalidLength)?;
+            let lower = ((b0 & 0b0011_1111) << 2) | (b1 >> 6);
+            let upper = b1 & 0b0011_1111;
+            (u16::from(lower) | (u16::from(upper) << 8), 2)
+        }
+        Some(_) => return Err(DecodeError::InvalidPrefix),
+        None => return Err(DecodeError::InvalidLength),
+    };
+
+    if is_reserved_prefix(chain_prefix) {
+        return Err(DecodeError::InvalidPrefix);
+    }
+
+    if bytes.len() != prefix_len + 32 + 2 {
+        return Err(DecodeError::InvalidLength);
+    }
+
+    let (data, checksum) = bytes.split_at(prefix_len + 32);
+    if checksum != &calculate_checksum(data)[..2] {
+        return Err(DecodeError::InvalidChecksum);
+    }
+
+    Ok(Decoded {
+        chain_prefix,
+        public_key: <[u8; 32]>::try_from(&data[prefix_len..]).unwrap(),
+    })
+}
+
+/// Encodes a public key as an SS58 address.
+///
+/// # Panic
+///
+/// Panics if `chain_prefix` is superior or equal to `16384`, as the SS58 format can't encode
+/// such network identifiers.
+///
+pub fn encode(chain_prefix: u16, public_key: &[u8; 32]) -> String {
+    assert!(chain_prefix < 16384);
+
+    let mut bytes = Vec::with_capacity(2 + 32 + 2);
+    if chain_prefix < 64 {
+        bytes.push(u8::try_from(chain_prefix).unwrap());
+    } else {
+        bytes.push(0b0100_0000 | u8::try_from((chain_prefix & 0b1111_1100) >> 2).unwrap());
+        bytes.push(
+            u8::try_from(chain_prefix >> 8).unwrap()
+                | (u8::try_from(chain_prefix & 0b11).unwrap() << 6),
+        );
+    }
+    bytes.extend_from_slice(public_key);
+
+    let checksum = calculate_checksum(&bytes);
+    bytes.extend_from_slice(&checksum[..2]);
+
+    bs58::encode(bytes).into_string()
+}
+
+/// Address parsed by [`parse_address`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParsedAddress {
+    /// Network identifier found in the address. `None` if the address doesn't contain any,
+    /// which is the case for hexadecimal public keys.
+    pub chain_prefix: Option<u16>,
+
+    /// Public key found in the address.
+    pub public_key: [u8; 32],
+}
+
+/// Parses an address that is either in the SS58 format, or a public key in hexadecimal
+/// prefixed with `0x`.
+///
+/// If `expected_prefix` is `Some`, SS58 addresses whose network identifier is different are
+/// refused.
+pub fn parse_address(
+    address: &str,
+    expected_prefix: Option<u16>,
+) -> Result<ParsedAddress, ParseAddressError> {
+    if let Some(hex) = address.strip_prefix("0x") {
+        let mut public_key = [0; 32];
+        hex::decode_to_slice(hex, &mut public_key).map_err(|_| ParseAddressError::InvalidHex)?;
+        return Ok(ParsedAddress {
+            chain_prefix: None,
+            public_key,
+        });
+    }
+
+    let decoded = decode(address).map_err(ParseAddressError::Ss58)?;
+    match expected_prefix {
+        Some(expected) if expected != decoded.chain_prefix => {
+            Err(ParseAddressError::PrefixMismatch {
+                expected,
+                actual: decoded.chain_prefix,
+            })
+        }
+        _ => Ok(ParsedAddress {
+            chain_prefix: Some(decoded.chain_prefix),
+            public_key: decoded.public_key,
+        }),
+    }
+}
+
+/// Returns the hash whose first two bytes are the checksum of the given prefix and public key.
+fn calculate_checksum(data: &[u8]) -> [u8; 64] {
+    let mut hasher = blake2_rfc::blake2b::Blake2b::new(64);
+    hasher.update(b"SS58PRE");
+    hasher.update(data);
+    <[u8; 64]>::try_from(hasher.finalize().as_bytes()).unwrap()
+}
+
+/// Error potentially returned by [`decode`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The address isn't valid base58.
+    #[display(fmt = "Address isn't valid base58")]
+    InvalidBs58,
+    /// The address has a length that doesn't correspond to a 32 bytes public key.
+    #[display(fmt = "Invalid address length")]
+    InvalidLength,
+    /// The network identifier is reserved or invalid.
+    #[display(fmt = "Invalid address network identifier")]
+    InvalidPrefix,
+    /// The checksum of the address doesn't match its content.
+    #[display(fmt = "Invalid address checksum")]
+    InvalidChecksum,
+}
+
+/// Error potentially returned by [`parse_address`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum ParseAddressError {
+    /// The address starts with `0x` but isn't a 32 bytes public key in hexadecimal.
+    #[display(fmt = "Invalid hexadecimal public key")]
+    InvalidHex,
+    /// The address isn't a valid SS58 address.
+    #[display(fmt = "{_0}")]
+    Ss58(DecodeError),
+    /// The network identifier of the address isn't the one that was expected.
+    #[display(fmt = "Address is for network {actual} instead of {expected}")]
+    PrefixMismatch {
+        /// Network identifier that was passed to [`parse_address`].
+        expected: u16,
+        /// Network identifier found in the address.
+        actual: u16,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    const ALICE: [u8; 32] = [
+        212, 53, 147, 199, 21, 253, 211, 28, 97, 20, 26, 189, 4, 169, 159, 214, 130, 44, 133, 88,
+        133, 76, 205, 227, 154, 86, 132, 231, 165, 109, 162, 125,
+    ];
+
+    #[test]
+    fn decode_one_byte_prefix() {
+        let decoded = super::decode("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY").unwrap();
+        assert_eq!(decoded.chain_prefix, 42);
+        assert_eq!(decoded.public_key, ALICE);
+
+        let decoded = super::decode("15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5").unwrap();
+        assert_eq!(decoded.chain_prefix, 0);
+        assert_eq!(decoded.public_key, ALICE);
+    }
+
+    #[test]
+    fn decode_two_bytes_prefix() {
+        let decoded = super::decode("VdvKmYJfD4VXA9fzz1SbmCo2eYHSzUFbaDCZSuaNKJAe8YNg6").unwrap();
+        assert_eq!(decoded.chain_prefix, 1284);
+        assert_eq!(decoded.public_key, ALICE);
+    }
+
+    #[test]
+    fn encode_matches_decode() {
+        for prefix in [0, 2, 42, 63, 64, 1284, 16383] {
+            let encoded = super::encode(prefix, &ALICE);
+            let decoded = super::decode(&encoded).unwrap();
+            assert_eq!(decoded.chain_prefix, prefix);
+            assert_eq!(decoded.public_key, ALICE);
+        }
+
+        assert_eq!(
+            super::encode(2, &ALICE),
+            "HNZata7iMYWmk5RvZRTiAsSDhV8366zq2YGb3tLH5Upf74F"
+        );
+    }
+
+    #[test]
+    fn reserved_prefix_refused() {
+        for prefix in [46, 47] {
+            let encoded = super::encode(prefix, &ALICE);
+            assert!(matches!(
+                super::decode(&encoded),
+                Err(super::DecodeError::InvalidPrefix)
+            ));
+        }
+    }
+
+    #[test]
+    fn known_networks_sorted() {
+        assert!(super::KNOWN_NETWORKS
+            .windows(2)
+            .all(|w| w[0].prefix < w[1].prefix));
+        assert_eq!(super::network_by_prefix(2).unwrap().network, "kusama");
+        assert_eq!(super::network_by_name("Polkadot").unwrap().prefix, 0);
+        assert!(super::network_by_prefix(3).is_none());
+    }
+
+    #[test]
+    fn parse_address_formats() {
+        let parsed = super::parse_address(
+            "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d",
+            Some(0),
+        )
+        .unwrap();
+        assert_eq!(parsed.chain_prefix, None);
+        assert_eq!(parsed.public_key, ALICE);
+
+        let parsed =
+            super::parse_address("15oF4uVJwmo4TdGW7VfQxNLavjCXviqxT9S1MgbjMNHr6Sp5", Some(0))
+                .unwrap();
+        assert_eq!(parsed.chain_prefix, Some(0));
+        assert_eq!(parsed.public_key, ALICE);
+
+        assert!(matches!(
+            super::parse_address("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY", Some(0)),
+            Err(super::ParseAddressError::PrefixMismatch {
+                expected: 0,
+                actual: 42
+            })
+        ));
+        assert!(matches!(
+            super::parse_address("0xd435", None),
+            Err(super::ParseAddressError::InvalidHex)
+        ));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        assert!(matches!(
+            super::decode("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQZ"),
+            Err(super::DecodeError::InvalidChecksum)
+        ));
+    }
+}
@@ -44,8 +44,12 @@
 //!     network_known_best: Some(224),
 //! });
 //! ```
+//!
+//! Programs that monitor a node can instead use [`InformantRecord`], whose
+//! [`core::fmt::Display`] implementation writes out a single-line JSON object containing the
+//! same information in a machine-readable way.
 
-use alloc::format;
+use alloc::{format, string::String};
 use core::{cmp, fmt};
 
 /// Values used to build the informant line. Implements the [`core::fmt::Display`] trait.
@@ -189,6 +193,127 @@ impl<'a> fmt::Display for InformantLine<'a> {
     }
 }
 
+/// Machine-readable equivalent of [`InformantLine`].
+///
+/// Implements the [`core::fmt::Display`] trait, which writes out the record as a JSON object
+/// on a single line. Hashes are written in hexadecimal with a `0x` prefix, and fields whose
+/// value is `None` are omitted.
+///
+/// ```
+/// use smoldot::informant::InformantRecord;
+/// println!("{}", InformantRecord {
+///     chain_name: "My chain",
+///     relay_chain: None,
+///     num_peers: 8,
+///     num_network_connections: 12,
+///     best_number: 220,
+///     finalized_number: 217,
+///     best_hash: &[0x12, 0x34, 0x56, 0x76],
+///     finalized_hash: &[0xaa, 0xbb, 0xcc, 0xdd],
+///     network_known_best: Some(224),
+///     total_bytes_received: Some(1024),
+///     total_bytes_sent: Some(512),
+///     runtime_spec_version: Some(9370),
+/// });
+/// ```
+#[derive(Debug)]
+pub struct InformantRecord<'a> {
+    /// Name of the chain.
+    pub chain_name: &'a str,
+    /// Extra fields related to the relay chain.
+    pub relay_chain: Option<RelayChain<'a>>,
+    /// Number of gossiping substreams open with nodes of the same chain.
+    pub num_peers: u64,
+    /// Number of network connections we are having with the rest of the peer-to-peer network.
+    pub num_network_connections: u64,
+    /// Best block currently being propagated on the peer-to-peer. `None` if unknown.
+    pub network_known_best: Option<u64>,
+    /// Number of the best block that we have locally.
+    pub best_number: u64,
+    /// Hash of the best block that we have locally.
+    pub best_hash: &'a [u8],
+    /// Number of the latest finalized block we have locally.
+    pub finalized_number: u64,
+    /// Hash of the latest finalized block we have locally.
+    pub finalized_hash: &'a [u8],
+    /// Total number of bytes received from the network since the start. `None` if unknown.
+    pub total_bytes_received: Option<u64>,
+    /// Total number of bytes sent to the network since the start. `None` if unknown.
+    pub total_bytes_sent: Option<u64>,
+    /// Specification version of the runtime of the best block. `None` if unknown.
+    pub runtime_spec_version: Option<u32>,
+}
+
+impl<'a> fmt::Display for InformantRecord<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Record<'a> {
+            chain: &'a str,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            relay_chain: Option<RelayChainRecord<'a>>,
+            best_number: u64,
+            best_hash: String,
+            finalized_number: u64,
+            finalized_hash: String,
+            finality_lag: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            network_known_best: Option<u64>,
+            peers: u64,
+            connections: u64,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            total_bytes_received: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            total_bytes_sent: Option<u64>,
+            #[serde(skip_serializing_if = "Option::is_none")]
+            runtime_spec_version: Option<u32>,
+        }
+
+        #[derive(serde::Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct RelayChainRecord<'a> {
+            chain: &'a str,
+            best_number: u64,
+        }
+
+        let record = Record {
+            chain: self.chain_name,
+            relay_chain: self
+                .relay_chain
+                .as_ref()
+                .map(|relay_chain| RelayChainRecord {
+                    chain: relay_chain.chain_name,
+                    best_number: relay_chain.best_number,
+                }),
+            best_number: self.best_number,
+            best_hash: hex_string(self.best_hash),
+            finalized_number: self.finalized_number,
+            finalized_hash: hex_string(self.finalized_hash),
+            finality_lag: self.best_number.saturating_sub(self.finalized_number),
+            network_known_best: self.network_known_best,
+            peers: self.num_peers,
+            connections: self.num_network_connections,
+            total_bytes_received: self.total_bytes_received,
+            total_bytes_sent: self.total_bytes_sent,
+            runtime_spec_version: self.runtime_spec_version,
+        };
+
+        // Serializing this struct can't fail.
+        let json = serde_json::to_string(&record).unwrap();
+        f.write_str(&json)
+    }
+}
+
+/// Turns the given bytes into a hexadecimal string prefixed with `0x`.
+fn hex_string(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(2 + bytes.len() * 2);
+    out.push_str("0x");
+    for byte in bytes {
+        let _ = fmt::Write::write_fmt(&mut out, format_args!("{byte:02x}"));
+    }
+    out
+}
+
 /// Implements `fmt::Display` and displays hashes in a nice way.
 pub struct HashDisplay<'a>(pub &'a [u8]);
 
@@ -270,3 +395,37 @@ impl fmt::Display for BlockNumberDisplay {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{InformantRecord, RelayChain};
+
+    #[test]
+    fn record_json() {
+        let record = InformantRecord {
+            chain_name: "Foo",
+            relay_chain: Some(RelayChain {
+                chain_name: "Bar",
+                best_number: 1000,
+            }),
+            num_peers: 8,
+            num_network_connections: 12,
+            network_known_best: None,
+            best_number: 220,
+            best_hash: &[0x12, 0x34],
+            finalized_number: 217,
+            finalized_hash: &[0xaa, 0xbb],
+            total_bytes_received: Some(1024),
+            total_bytes_sent: None,
+            runtime_spec_version: Some(9370),
+        };
+
+        assert_eq!(
+            alloc::string::ToString::to_string(&record),
+            "{\"chain\":\"Foo\",\"relayChain\":{\"chain\":\"Bar\",\"bestNumber\":1000},\
+             \"bestNumber\":220,\"bestHash\":\"0x1234\",\"finalizedNumber\":217,\
+             \"finalizedHash\":\"0xaabb\",\"finalityLag\":3,\"peers\":8,\"connections\":12,\
+             \"totalBytesReceived\":1024,\"runtimeSpecVersion\":9370}"
+        );
+    }
+}
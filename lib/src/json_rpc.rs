@@ -61,8 +61,13 @@
 
 // TODO: write docs about usage ^
 
+pub mod account_info;
+pub mod ethereum;
+pub mod http_server;
 pub mod methods;
 pub mod parse;
 pub mod payment_info;
 pub mod requests_subscriptions;
+pub mod session_info;
+pub mod staking_info;
 pub mod websocket_server;
@@ -0,0 +1,523 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access to the `System.Account` storage entry, which contains the nonce and balance of an
+//! account.
+//!
+//! The format of this storage entry depends on the runtime. An [`AccountInfoDecoder`] is built
+//! from the metadata of the runtime, and determines where the nonce, the reference counters,
+//! and the balances are found in the storage value. The integers of the storage value are
+//! matched by the name of their field, which makes it possible to support both the older and
+//! the newer layouts of the Substrate and Polkadot runtimes, and integers of any size.
+
+use crate::{
+    metadata::{self, TypeDef, TypesRegistry},
+    util,
+};
+
+use alloc::vec::Vec;
+
+/// Returns the key of the `System.Account` storage entry of the given account.
+pub fn storage_key(account_id: &[u8; 32]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16 + 16 + 16 + 32);
+    key.extend_from_slice(&util::twox_128(b"System"));
+    key.extend_from_slice(&util::twox_128(b"Account"));
+    key.extend_from_slice(blake2_rfc::blake2b::blake2b(16, &[], account_id).as_bytes());
+    key.extend_from_slice(account_id);
+    key
+}
+
+/// Information about an account, as stored in the `System.Account` storage entry.
+///
+/// The [`Default`] value corresponds to an account that doesn't exist in the storage.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccountInfo {
+    /// Number of transactions that the account has emitted.
+    pub nonce: u32,
+    /// Number of other modules that currently depend on this account's existence. Named
+    /// `refcount` in older runtimes.
+    pub consumers: u32,
+    /// Number of other modules that allow this account to exist. Always `0` on older runtimes
+    /// which don't have this counter.
+    pub providers: u32,
+    /// Number of modules that allow this account to exist for their own purposes. Always `0` on
+    /// older runtimes which don't have this counter.
+    pub sufficients: u32,
+    /// Balance that can be freely transferred.
+    pub free: u128,
+    /// Balance that is reserved and can't be transferred.
+    pub reserved: u128,
+    /// Third field of the balance information. Named `misc_frozen` in older runtimes and `frozen`
+    /// in newer runtimes.
+    pub misc_frozen: u128,
+    /// Fourth field of the balance information. Named `fee_frozen` in older runtimes and `flags`
+    /// in newer runtimes.
+    pub fee_frozen: u128,
+}
+
+/// Decoder for the values of the `System.Account` storage entry of a specific runtime.
+#[derive(Debug, Clone)]
+pub struct AccountInfoDecoder {
+    /// List of the integers found in a storage value, in order, and the field of
+    /// [`AccountInfo`] each of them corresponds to, if any.
+    integers: Vec<(IntegerFormat, Option<Target>)>,
+}
+
+impl AccountInfoDecoder {
+    /// Extracts the format of the `System.Account` storage entry from the given metadata.
+    ///
+    /// The metadata is the output of the `Metadata_metadata` runtime call, after its length
+    /// prefix has been removed. Only versions 14 and 15 of the metadata format are supported.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, MetadataError> {
+        let metadata = metadata::decode(metadata).map_err(MetadataError::Decode)?;
+        let account_ty = metadata
+            .storage_entry_type("System", "Account")
+            .ok_or(MetadataError::AccountNotFound)?;
+
+        let mut integers = Vec::new();
+        flatten(&metadata.types, account_ty, None, 0, &mut integers)?;
+        Ok(AccountInfoDecoder { integers })
+    }
+
+    /// Attempt to decode the value of the `System.Account` storage entry.
+    ///
+    /// If the storage entry doesn't exist, [`AccountInfo::default`] should be used instead.
+    pub fn decode(&self, mut scale_encoded: &[u8]) -> Result<AccountInfo, DecodeError> {
+        let mut info = AccountInfo::default();
+
+        for (format, target) in &self.integers {
+            let value = match format {
+                IntegerFormat::Fixed(num_bytes) => {
+                    if scale_encoded.len() < *num_bytes {
+                        return Err(DecodeError::UnexpectedLength);
+                    }
+                    let (value, rest) = scale_encoded.split_at(*num_bytes);
+                    scale_encoded = rest;
+                    let mut buffer = [0; 16];
+                    buffer[..value.len()].copy_from_slice(value);
+                    u128::from_le_bytes(buffer)
+                }
+                IntegerFormat::Compact => {
+                    let (rest, value) =
+                        util::nom_scale_compact_u128::<nom::error::Error<&[u8]>>(scale_encoded)
+                            .map_err(|_| DecodeError::UnexpectedLength)?;
+                    scale_encoded = rest;
+                    value
+                }
+            };
+
+            let counter = || u32::try_from(value).map_err(|_| DecodeError::Overflow);
+            match target {
+                None => {}
+                Some(Target::Nonce) => info.nonce = counter()?,
+                Some(Target::Consumers) => info.consumers = counter()?,
+                Some(Target::Providers) => info.providers = counter()?,
+                Some(Target::Sufficients) => info.sufficients = counter()?,
+                Some(Target::Free) => info.free = value,
+                Some(Target::Reserved) => info.reserved = value,
+                Some(Target::MiscFrozen) => info.misc_frozen = value,
+                Some(Target::FeeFrozen) => info.fee_frozen = value,
+            }
+        }
+
+        if !scale_encoded.is_empty() {
+            return Err(DecodeError::UnexpectedLength);
+        }
+
+        Ok(info)
+    }
+}
+
+/// Error potentially returned by [`AccountInfoDecoder::from_metadata`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum MetadataError {
+    /// Failed to decode the metadata.
+    #[display(fmt = "{_0}")]
+    Decode(metadata::Error),
+    /// The metadata doesn't contain a `System.Account` storage entry.
+    AccountNotFound,
+    /// The type of the `System.Account` storage entry contains something else than structs and
+    /// unsigned integers.
+    UnsupportedAccountType,
+}
+
+/// Potential error when decoding the `System.Account` storage entry.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The storage value doesn't have the length indicated by the metadata.
+    #[display(fmt = "Unexpected length of the System.Account storage value")]
+    UnexpectedLength,
+    /// The nonce or one of the reference counters doesn't fit in a `u32`.
+    #[display(fmt = "Account nonce or reference counter too large")]
+    Overflow,
+}
+
+/// Maximum number of nested types that the `System.Account` storage entry can contain. Protects
+/// against recursive types.
+const MAX_TYPE_DEPTH: u32 = 8;
+
+/// How an integer is encoded.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum IntegerFormat {
+    /// Little endian integer of the given number of bytes.
+    Fixed(usize),
+    /// SCALE-compact integer.
+    Compact,
+}
+
+/// Field of [`AccountInfo`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Target {
+    Nonce,
+    Consumers,
+    Providers,
+    Sufficients,
+    Free,
+    Reserved,
+    MiscFrozen,
+    FeeFrozen,
+}
+
+/// Pushes to `out` the list of integers found in a value of the given type. `field_name` is the
+/// name of the field the type is found in, and is used to determine the [`Target`] of integers.
+fn flatten(
+    types: &TypesRegistry,
+    ty: u32,
+    field_name: Option<&str>,
+    depth: u32,
+    out: &mut Vec<(IntegerFormat, Option<Target>)>,
+) -> Result<(), MetadataError> {
+    if depth >= MAX_TYPE_DEPTH {
+        return Err(MetadataError::UnsupportedAccountType);
+    }
+
+    let format = match types.get(ty) {
+        Some(TypeDef::Composite(fields)) => {
+            // Unnamed fields, such as the one of `ExtraFlags(u128)`, inherit the name of the
+            // field their parent is found in.
+            for field in fields {
+                let name = field.name.as_deref().or(field_name);
+                flatten(types, field.ty, name, depth + 1, out)?;
+            }
+            return Ok(());
+        }
+        Some(TypeDef::Primitive(primitive @ 3..=7)) => IntegerFormat::Fixed(1 << (primitive - 3)),
+        Some(TypeDef::Compact) => IntegerFormat::Compact,
+        _ => return Err(MetadataError::UnsupportedAccountType),
+    };
+
+    let target = match field_name {
+        Some("nonce") => Some(Target::Nonce),
+        Some("consumers" | "refcount") => Some(Target::Consumers),
+        Some("providers") => Some(Target::Providers),
+        Some("sufficients") => Some(Target::Sufficients),
+        Some("free") => Some(Target::Free),
+        Some("reserved") => Some(Target::Reserved),
+        Some("misc_frozen" | "frozen") => Some(Target::MiscFrozen),
+        Some("fee_frozen" | "flags") => Some(Target::FeeFrozen),
+        _ => None,
+    };
+
+    out.push((format, target));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    const ALICE: [u8; 32] = [
+        212, 53, 147, 199, 21, 253, 211, 28, 97, 20, 26, 189, 4, 169, 159, 214, 130, 44, 133, 88,
+        133, 76, 205, 227, 154, 86, 132, 231, 165, 109, 162, 125,
+    ];
+
+    #[test]
+    fn storage_key_alice() {
+        let expected = [
+            0x26, 0xaa, 0x39, 0x4e, 0xea, 0x56, 0x30, 0xe0, 0x7c, 0x48, 0xae, 0x0c, 0x95, 0x58,
+            0xce, 0xf7, 0xb9, 0x9d, 0x88, 0x0e, 0xc6, 0x81, 0x79, 0x9c, 0x0c, 0xf3, 0x0e, 0x88,
+            0x86, 0x37, 0x1d, 0xa9, 0xde, 0x1e, 0x86, 0xa9, 0xa8, 0xc7, 0x39, 0x86, 0x4c, 0xf3,
+            0xcc, 0x5e, 0xc2, 0xbe, 0xa5, 0x9f,
+        ];
+
+        let key = super::storage_key(&ALICE);
+        assert_eq!(&key[..48], &expected[..]);
+        assert_eq!(&key[48..], &ALICE[..]);
+    }
+
+    fn compact(out: &mut Vec<u8>, n: usize) {
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(n).as_ref());
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        compact(out, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    /// Builds a composite type with the given name and type id of each field.
+    fn composite(fields: &[(Option<&str>, usize)]) -> Vec<u8> {
+        let mut out = vec![0];
+        compact(&mut out, fields.len());
+        for (name, ty) in fields {
+            match name {
+                Some(name) => {
+                    out.push(1);
+                    string(&mut out, name);
+                }
+                None => out.push(0),
+            }
+            compact(&mut out, *ty);
+            out.push(0); // Type name.
+            out.push(0); // Documentation.
+        }
+        out
+    }
+
+    /// Builds a metadata containing the given types, and a `System.Account` storage entry
+    /// whose values are of type `account_ty`.
+    fn metadata(types: &[Vec<u8>], account_ty: usize) -> Vec<u8> {
+        let mut metadata = b"meta".to_vec();
+        metadata.push(14);
+        compact(&mut metadata, types.len());
+        for (id, ty) in types.iter().enumerate() {
+            compact(&mut metadata, id);
+            metadata.push(0); // Path.
+            metadata.push(0); // Type parameters.
+            metadata.extend_from_slice(ty);
+            metadata.push(0); // Documentation.
+        }
+
+        compact(&mut metadata, 1);
+        string(&mut metadata, "System");
+        metadata.push(1); // Storage.
+        string(&mut metadata, "System");
+        compact(&mut metadata, 1);
+        string(&mut metadata, "Account");
+        metadata.push(1); // Modifier.
+        metadata.push(1); // Map.
+        metadata.extend_from_slice(&[1 << 2, 1]); // Hashers.
+        compact(&mut metadata, 0); // Key type.
+        compact(&mut metadata, account_ty);
+        metadata.push(0); // Default value.
+        metadata.push(0); // Documentation.
+        metadata.push(0); // Calls.
+        metadata.push(0); // Events.
+        metadata.push(0); // Constants.
+        metadata.push(0); // Errors.
+        metadata.push(0); // Index.
+        metadata
+    }
+
+    /// Metadata of a recent runtime, where the balances contain a `flags` field.
+    fn metadata_recent() -> Vec<u8> {
+        metadata(
+            &[
+                // 0: u32
+                vec![5, 5],
+                // 1: u128
+                vec![5, 7],
+                // 2: ExtraFlags
+                composite(&[(None, 1)]),
+                // 3: AccountData
+                composite(&[
+                    (Some("free"), 1),
+                    (Some("reserved"), 1),
+                    (Some("frozen"), 1),
+                    (Some("flags"), 2),
+                ]),
+                // 4: AccountInfo
+                composite(&[
+                    (Some("nonce"), 0),
+                    (Some("consumers"), 0),
+                    (Some("providers"), 0),
+                    (Some("sufficients"), 0),
+                    (Some("data"), 3),
+                ]),
+            ],
+            4,
+        )
+    }
+
+    fn encode(counters: &[u32], balances: &[u128]) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for counter in counters {
+            encoded.extend_from_slice(&counter.to_le_bytes());
+        }
+        for balance in balances {
+            encoded.extend_from_slice(&balance.to_le_bytes());
+        }
+        encoded
+    }
+
+    #[test]
+    fn decode_recent() {
+        let decoder = super::AccountInfoDecoder::from_metadata(&metadata_recent()).unwrap();
+        let info = decoder
+            .decode(&encode(&[7, 1, 2, 3], &[1_000_000_000_000, 50, 20, 10]))
+            .unwrap();
+        assert_eq!(
+            info,
+            super::AccountInfo {
+                nonce: 7,
+                consumers: 1,
+                providers: 2,
+                sufficients: 3,
+                free: 1_000_000_000_000,
+                reserved: 50,
+                misc_frozen: 20,
+                fee_frozen: 10,
+            }
+        );
+    }
+
+    #[test]
+    fn decode_without_sufficients() {
+        let metadata = metadata(
+            &[
+                // 0: u32
+                vec![5, 5],
+                // 1: u128
+                vec![5, 7],
+                // 2: AccountData
+                composite(&[
+                    (Some("free"), 1),
+                    (Some("reserved"), 1),
+                    (Some("misc_frozen"), 1),
+                    (Some("fee_frozen"), 1),
+                ]),
+                // 3: AccountInfo
+                composite(&[
+                    (Some("nonce"), 0),
+                    (Some("consumers"), 0),
+                    (Some("providers"), 0),
+                    (Some("data"), 2),
+                ]),
+            ],
+            3,
+        );
+
+        let decoder = super::AccountInfoDecoder::from_metadata(&metadata).unwrap();
+        let info = decoder
+            .decode(&encode(&[7, 1, 2], &[1_000_000_000_000, 50, 20, 10]))
+            .unwrap();
+        assert_eq!(info.nonce, 7);
+        assert_eq!(info.providers, 2);
+        assert_eq!(info.sufficients, 0);
+        assert_eq!(info.free, 1_000_000_000_000);
+        assert_eq!(info.misc_frozen, 20);
+        assert_eq!(info.fee_frozen, 10);
+    }
+
+    #[test]
+    fn decode_other_integer_types() {
+        // Nonce as a `u64`, balances as `u64`s, and an unknown compact field.
+        let metadata = metadata(
+            &[
+                // 0: u64
+                vec![5, 6],
+                // 1: Compact<u32>
+                vec![6, 2 << 2],
+                // 2: u32
+                vec![5, 5],
+                // 3: AccountData
+                composite(&[(Some("free"), 0), (Some("reserved"), 0)]),
+                // 4: AccountInfo
+                composite(&[(Some("nonce"), 0), (Some("unknown"), 1), (Some("data"), 3)]),
+            ],
+            4,
+        );
+
+        let decoder = super::AccountInfoDecoder::from_metadata(&metadata).unwrap();
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&5u64.to_le_bytes());
+        encoded.extend_from_slice(&[0b01, 0x10]); // Compact-encoded 1024.
+        encoded.extend_from_slice(&300u64.to_le_bytes());
+        encoded.extend_from_slice(&12u64.to_le_bytes());
+
+        let info = decoder.decode(&encoded).unwrap();
+        assert_eq!(info.nonce, 5);
+        assert_eq!(info.free, 300);
+        assert_eq!(info.reserved, 12);
+        assert_eq!(info.providers, 0);
+    }
+
+    #[test]
+    fn decode_nonce_overflow() {
+        let metadata = metadata(
+            &[
+                // 0: u64
+                vec![5, 6],
+                // 1: AccountInfo
+                composite(&[(Some("nonce"), 0)]),
+            ],
+            1,
+        );
+
+        let decoder = super::AccountInfoDecoder::from_metadata(&metadata).unwrap();
+        assert!(matches!(
+            decoder.decode(&(1u64 << 40).to_le_bytes()),
+            Err(super::DecodeError::Overflow)
+        ));
+    }
+
+    #[test]
+    fn decode_bad_length() {
+        let decoder = super::AccountInfoDecoder::from_metadata(&metadata_recent()).unwrap();
+        assert!(decoder.decode(&[0; 79]).is_err());
+        assert!(decoder.decode(&[0; 81]).is_err());
+        assert!(decoder.decode(&[0; 80]).is_ok());
+    }
+
+    #[test]
+    fn account_not_found() {
+        let mut metadata = metadata(&[vec![5, 5]], 0);
+        // Rename the storage entry.
+        let pos = metadata.windows(7).position(|w| w == b"Account").unwrap();
+        metadata[pos..pos + 7].copy_from_slice(b"Accounz");
+
+        assert!(matches!(
+            super::AccountInfoDecoder::from_metadata(&metadata),
+            Err(super::MetadataError::AccountNotFound)
+        ));
+    }
+
+    #[test]
+    fn unsupported_account_type() {
+        let metadata = metadata(
+            &[
+                // 0: Vec<u8>
+                vec![2, 1 << 2],
+                // 1: u8
+                vec![5, 3],
+                // 2: AccountInfo
+                composite(&[(Some("nonce"), 0)]),
+            ],
+            2,
+        );
+        assert!(matches!(
+            super::AccountInfoDecoder::from_metadata(&metadata),
+            Err(super::MetadataError::UnsupportedAccountType)
+        ));
+    }
+
+    #[test]
+    fn recursive_account_type() {
+        let metadata = metadata(&[composite(&[(Some("data"), 0)])], 0);
+        assert!(matches!(
+            super::AccountInfoDecoder::from_metadata(&metadata),
+            Err(super::MetadataError::UnsupportedAccountType)
+        ));
+    }
+}
@@ -0,0 +1,496 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Helpers for serving the Ethereum JSON-RPC functions (`eth_*`) of chains based on Frontier.
+//!
+//! Frontier is a set of Substrate pallets that make it possible for a Substrate chain to
+//! execute Ethereum transactions. The runtime of these chains exposes the
+//! `EthereumRuntimeRPCApi` and `ConvertTransactionRuntimeApi` runtime APIs, which are used
+//! to answer the Ethereum JSON-RPC functions.
+//!
+//! Determining the exact format of the types of these runtime APIs would require parsing the
+//! metadata provided by the runtime. Instead, this module assumes the layout of the types of
+//! the `ethereum` Rust library used by Frontier: 256-bits integers are encoded as 32 bytes in
+//! little endian, and transactions are encoded as the `TransactionV2` enum.
+
+use alloc::{format, string::String, vec::Vec};
+use tiny_keccak::Hasher as _;
+
+/// Name of the runtime API that must be supported in order to call
+/// [`ACCOUNT_BASIC_FUNCTION_NAME`] and [`CHAIN_ID_FUNCTION_NAME`].
+pub const ETHEREUM_RUNTIME_API: &str = "EthereumRuntimeRPCApi";
+
+/// Name of the runtime function to call in order to obtain the balance and nonce of an account.
+/// The parameter is the 20 bytes of the address of the account. See [`decode_account_basic`].
+pub const ACCOUNT_BASIC_FUNCTION_NAME: &str = "EthereumRuntimeRPCApi_account_basic";
+
+/// Name of the runtime function to call in order to obtain the Ethereum chain id of the chain.
+/// Returns a little endian `u64`.
+pub const CHAIN_ID_FUNCTION_NAME: &str = "EthereumRuntimeRPCApi_chain_id";
+
+/// Name of the runtime API that must be supported in order to call
+/// [`CONVERT_TRANSACTION_FUNCTION_NAME`].
+pub const CONVERT_TRANSACTION_RUNTIME_API: &str = "ConvertTransactionRuntimeApi";
+
+/// Name of the runtime function to call in order to wrap an Ethereum transaction into an
+/// extrinsic. The parameter is the output of [`transaction_to_scale`]. The runtime returns the
+/// extrinsic, which can be submitted as is.
+pub const CONVERT_TRANSACTION_FUNCTION_NAME: &str =
+    "ConvertTransactionRuntimeApi_convert_transaction";
+
+/// Balance and nonce of an Ethereum account.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AccountBasic {
+    /// Balance of the account, as a big endian 256 bits integer.
+    pub balance: [u8; 32],
+    /// Number of transactions that the account has emitted, as a big endian 256 bits integer.
+    pub nonce: [u8; 32],
+}
+
+/// Decodes the output of a call to [`ACCOUNT_BASIC_FUNCTION_NAME`].
+pub fn decode_account_basic(scale_encoded: &[u8]) -> Result<AccountBasic, DecodeError> {
+    if scale_encoded.len() != 64 {
+        return Err(DecodeError::UnexpectedLength);
+    }
+
+    let big_endian = |little_endian: &[u8]| {
+        let mut out = <[u8; 32]>::try_from(little_endian).unwrap();
+        out.reverse();
+        out
+    };
+
+    Ok(AccountBasic {
+        balance: big_endian(&scale_encoded[..32]),
+        nonce: big_endian(&scale_encoded[32..]),
+    })
+}
+
+/// Decodes the output of a call to [`CHAIN_ID_FUNCTION_NAME`].
+pub fn decode_chain_id(scale_encoded: &[u8]) -> Result<u64, DecodeError> {
+    <[u8; 8]>::try_from(scale_encoded)
+        .map(u64::from_le_bytes)
+        .map_err(|_| DecodeError::UnexpectedLength)
+}
+
+/// Potential error when decoding the output of a runtime call.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The output of the runtime call doesn't have the expected length.
+    #[display(fmt = "Unexpected length of the runtime call output")]
+    UnexpectedLength,
+}
+
+/// Encodes the given big endian unsigned integer as an Ethereum JSON-RPC "quantity", in other
+/// words a `0x`-prefixed hexadecimal string without leading zeroes.
+pub fn encode_quantity(big_endian: &[u8]) -> String {
+    let encoded = hex::encode(big_endian);
+    match encoded.trim_start_matches('0') {
+        "" => "0x0".into(),
+        trimmed => format!("0x{trimmed}"),
+    }
+}
+
+/// Returns the hash of the given raw Ethereum transaction, as used by Ethereum tools to
+/// designate the transaction.
+pub fn transaction_hash(raw_transaction: &[u8]) -> [u8; 32] {
+    let mut keccak = tiny_keccak::Keccak::v256();
+    keccak.update(raw_transaction);
+    let mut out = [0; 32];
+    keccak.finalize(&mut out);
+    out
+}
+
+/// Converts a raw Ethereum transaction, as passed to `eth_sendRawTransaction`, into the SCALE
+/// encoding of a Frontier `TransactionV2`.
+///
+/// Raw transactions are either the RLP encoding of a legacy transaction, or a one byte type
+/// followed with the RLP encoding of an EIP-2930 (type 1) or EIP-1559 (type 2) transaction.
+pub fn transaction_to_scale(raw_transaction: &[u8]) -> Result<Vec<u8>, TransactionDecodeError> {
+    let (variant, rlp) = match raw_transaction.first() {
+        Some(0xc0..=0xff) => (0, raw_transaction),
+        Some(1) => (1, &raw_transaction[1..]),
+        Some(2) => (2, &raw_transaction[1..]),
+        Some(_) => return Err(TransactionDecodeError::UnsupportedType),
+        None => return Err(TransactionDecodeError::Rlp),
+    };
+
+    let fields = match rlp_decode(rlp)? {
+        (RlpItem::List(payload), []) => {
+            let mut fields = Vec::new();
+            let mut remaining = payload;
+            while !remaining.is_empty() {
+                let (item, rest) = rlp_decode(remaining)?;
+                fields.push(item);
+                remaining = rest;
+            }
+            fields
+        }
+        _ => return Err(TransactionDecodeError::Rlp),
+    };
+
+    let mut out = Vec::with_capacity(raw_transaction.len() + 256);
+    out.push(variant);
+
+    match (variant, &fields[..]) {
+        (0, [nonce, gas_price, gas_limit, to, value, input, v, r, s]) => {
+            push_u256(&mut out, nonce)?;
+            push_u256(&mut out, gas_price)?;
+            push_u256(&mut out, gas_limit)?;
+            push_action(&mut out, to)?;
+            push_u256(&mut out, value)?;
+            push_bytes(&mut out, input)?;
+            push_u64(&mut out, v)?;
+            push_h256(&mut out, r)?;
+            push_h256(&mut out, s)?;
+        }
+        (
+            1,
+            [chain_id, nonce, gas_price, gas_limit, to, value, input, access_list, y_parity, r, s],
+        ) => {
+            push_u64(&mut out, chain_id)?;
+            push_u256(&mut out, nonce)?;
+            push_u256(&mut out, gas_price)?;
+            push_u256(&mut out, gas_limit)?;
+            push_action(&mut out, to)?;
+            push_u256(&mut out, value)?;
+            push_bytes(&mut out, input)?;
+            push_access_list(&mut out, access_list)?;
+            push_y_parity(&mut out, y_parity)?;
+            push_h256(&mut out, r)?;
+            push_h256(&mut out, s)?;
+        }
+        (
+            2,
+            [chain_id, nonce, max_priority_fee_per_gas, max_fee_per_gas, gas_limit, to, value, input, access_list, y_parity, r, s],
+        ) => {
+            push_u64(&mut out, chain_id)?;
+            push_u256(&mut out, nonce)?;
+            push_u256(&mut out, max_priority_fee_per_gas)?;
+            push_u256(&mut out, max_fee_per_gas)?;
+            push_u256(&mut out, gas_limit)?;
+            push_action(&mut out, to)?;
+            push_u256(&mut out, value)?;
+            push_bytes(&mut out, input)?;
+            push_access_list(&mut out, access_list)?;
+            push_y_parity(&mut out, y_parity)?;
+            push_h256(&mut out, r)?;
+            push_h256(&mut out, s)?;
+        }
+        _ => return Err(TransactionDecodeError::InvalidFields),
+    }
+
+    Ok(out)
+}
+
+/// Error potentially returned by [`transaction_to_scale`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum TransactionDecodeError {
+    /// The transaction isn't properly RLP-encoded.
+    #[display(fmt = "Invalid RLP encoding")]
+    Rlp,
+    /// The type of the transaction isn't supported.
+    #[display(fmt = "Unsupported transaction type")]
+    UnsupportedType,
+    /// The fields of the transaction don't match its type.
+    #[display(fmt = "Invalid transaction fields")]
+    InvalidFields,
+}
+
+/// Item of an RLP encoding.
+enum RlpItem<'a> {
+    /// Byte string.
+    Bytes(&'a [u8]),
+    /// List, containing the concatenation of the RLP encodings of its items.
+    List(&'a [u8]),
+}
+
+/// Decodes the RLP item at the beginning of `data`. Returns the item and the rest of `data`.
+fn rlp_decode(data: &[u8]) -> Result<(RlpItem<'_>, &[u8]), TransactionDecodeError> {
+    let (&first, rest) = data.split_first().ok_or(TransactionDecodeError::Rlp)?;
+
+    // Returns the payload of the given length at the beginning of `rest`, and what follows.
+    fn split(rest: &[u8], len: usize) -> Result<(&[u8], &[u8]), TransactionDecodeError> {
+        if rest.len() < len {
+            return Err(TransactionDecodeError::Rlp);
+        }
+        Ok(rest.split_at(len))
+    }
+
+    // Decodes a big endian length of `len_of_len` bytes at the beginning of `rest`.
+    fn long_len(rest: &[u8], len_of_len: usize) -> Result<(usize, &[u8]), TransactionDecodeError> {
+        let (len, rest) = split(rest, len_of_len)?;
+        if len.first() == Some(&0) {
+            return Err(TransactionDecodeError::Rlp);
+        }
+        let len = len
+            .iter()
+            .try_fold(0usize, |acc, b| {
+                acc.checked_mul(256)
+                    .and_then(|acc| acc.checked_add(usize::from(*b)))
+            })
+            .ok_or(TransactionDecodeError::Rlp)?;
+        Ok((len, rest))
+    }
+
+    match first {
+        0x00..=0x7f => Ok((RlpItem::Bytes(&data[..1]), rest)),
+        0x80..=0xb7 => {
+            let (payload, rest) = split(rest, usize::from(first - 0x80))?;
+            Ok((RlpItem::Bytes(payload), rest))
+        }
+        0xb8..=0xbf => {
+            let (len, rest) = long_len(rest, usize::from(first - 0xb7))?;
+            let (payload, rest) = split(rest, len)?;
+            Ok((RlpItem::Bytes(payload), rest))
+        }
+        0xc0..=0xf7 => {
+            let (payload, rest) = split(rest, usize::from(first - 0xc0))?;
+            Ok((RlpItem::List(payload), rest))
+        }
+        0xf8..=0xff => {
+            let (len, rest) = long_len(rest, usize::from(first - 0xf7))?;
+            let (payload, rest) = split(rest, len)?;
+            Ok((RlpItem::List(payload), rest))
+        }
+    }
+}
+
+/// Returns the content of the given item if it is a byte string.
+fn rlp_bytes<'a>(item: &RlpItem<'a>) -> Result<&'a [u8], TransactionDecodeError> {
+    match item {
+        RlpItem::Bytes(bytes) => Ok(bytes),
+        RlpItem::List(_) => Err(TransactionDecodeError::InvalidFields),
+    }
+}
+
+/// Pushes the given big endian integer as a SCALE-encoded `U256`.
+fn push_u256(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    let bytes = rlp_bytes(item)?;
+    if bytes.len() > 32 {
+        return Err(TransactionDecodeError::InvalidFields);
+    }
+    out.extend(bytes.iter().rev());
+    out.extend((bytes.len()..32).map(|_| 0));
+    Ok(())
+}
+
+/// Pushes the given big endian integer as a SCALE-encoded `u64`.
+fn push_u64(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    let bytes = rlp_bytes(item)?;
+    if bytes.len() > 8 {
+        return Err(TransactionDecodeError::InvalidFields);
+    }
+    out.extend(bytes.iter().rev());
+    out.extend((bytes.len()..8).map(|_| 0));
+    Ok(())
+}
+
+/// Pushes the given big endian integer, left-padded with zeroes, as a `H256`.
+fn push_h256(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    let bytes = rlp_bytes(item)?;
+    if bytes.len() > 32 {
+        return Err(TransactionDecodeError::InvalidFields);
+    }
+    out.extend((bytes.len()..32).map(|_| 0));
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Pushes the given byte string as a SCALE-encoded `Vec<u8>`.
+fn push_bytes(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    let bytes = rlp_bytes(item)?;
+    out.extend_from_slice(crate::util::encode_scale_compact_usize(bytes.len()).as_ref());
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Pushes the given destination address, or lack thereof, as a SCALE-encoded
+/// `TransactionAction`.
+fn push_action(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    match rlp_bytes(item)? {
+        [] => out.push(1),
+        address if address.len() == 20 => {
+            out.push(0);
+            out.extend_from_slice(address);
+        }
+        _ => return Err(TransactionDecodeError::InvalidFields),
+    }
+    Ok(())
+}
+
+/// Pushes the given y parity as a SCALE-encoded `bool`.
+fn push_y_parity(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    match rlp_bytes(item)? {
+        [] => out.push(0),
+        [1] => out.push(1),
+        _ => return Err(TransactionDecodeError::InvalidFields),
+    }
+    Ok(())
+}
+
+/// Pushes the given access list as a SCALE-encoded `Vec<AccessListItem>`.
+fn push_access_list(out: &mut Vec<u8>, item: &RlpItem) -> Result<(), TransactionDecodeError> {
+    let RlpItem::List(mut remaining) = item else {
+        return Err(TransactionDecodeError::InvalidFields);
+    };
+
+    let mut num_items = 0;
+    let mut encoded_items = Vec::new();
+
+    while !remaining.is_empty() {
+        let (access_list_item, rest) = rlp_decode(remaining)?;
+        remaining = rest;
+        num_items += 1;
+
+        let RlpItem::List(access_list_item) = access_list_item else {
+            return Err(TransactionDecodeError::InvalidFields);
+        };
+        let (address, rest) = rlp_decode(access_list_item)?;
+        let (storage_keys, rest) = rlp_decode(rest)?;
+        let (RlpItem::Bytes(address), RlpItem::List(mut storage_keys), []) =
+            (address, storage_keys, rest)
+        else {
+            return Err(TransactionDecodeError::InvalidFields);
+        };
+        if address.len() != 20 {
+            return Err(TransactionDecodeError::InvalidFields);
+        }
+        encoded_items.extend_from_slice(address);
+
+        let mut keys = Vec::new();
+        let mut num_keys = 0;
+        while !storage_keys.is_empty() {
+            let (key, rest) = rlp_decode(storage_keys)?;
+            storage_keys = rest;
+            num_keys += 1;
+            match key {
+                RlpItem::Bytes(key) if key.len() == 32 => keys.extend_from_slice(key),
+                _ => return Err(TransactionDecodeError::InvalidFields),
+            }
+        }
+        encoded_items.extend_from_slice(crate::util::encode_scale_compact_usize(num_keys).as_ref());
+        encoded_items.extend_from_slice(&keys);
+    }
+
+    out.extend_from_slice(crate::util::encode_scale_compact_usize(num_items).as_ref());
+    out.extend_from_slice(&encoded_items);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn encode_quantity() {
+        assert_eq!(super::encode_quantity(&[]), "0x0");
+        assert_eq!(super::encode_quantity(&[0, 0]), "0x0");
+        assert_eq!(super::encode_quantity(&[0, 0x01, 0xb4]), "0x1b4");
+        assert_eq!(super::encode_quantity(&[0x10, 0x00]), "0x1000");
+    }
+
+    #[test]
+    fn decode_account_basic() {
+        let mut encoded = [0; 64];
+        encoded[0] = 0xe8;
+        encoded[1] = 0x03;
+        encoded[32] = 7;
+
+        let account = super::decode_account_basic(&encoded).unwrap();
+        assert_eq!(super::encode_quantity(&account.balance), "0x3e8");
+        assert_eq!(super::encode_quantity(&account.nonce), "0x7");
+        assert!(super::decode_account_basic(&encoded[1..]).is_err());
+    }
+
+    #[test]
+    fn legacy_transaction() {
+        // Example transaction of EIP-155.
+        let raw = hex::decode(
+            "f86c098504a817c800825208943535353535353535353535353535353535353535880de0b6b3a76400008025a028ef61340bd939bc2195fe537567866003e1a15d3c71ff63e1590620aa636276a067cbe9d8997f761aecb703304b3800ccf555c9f3dc64214b297fb1966a3b6d83",
+        )
+        .unwrap();
+
+        let scale = super::transaction_to_scale(&raw).unwrap();
+
+        let mut expected = vec![0];
+        // Nonce.
+        expected.push(9);
+        expected.extend_from_slice(&[0; 31]);
+        // Gas price.
+        expected.extend_from_slice(&[0x00, 0xc8, 0x17, 0xa8, 0x04]);
+        expected.extend_from_slice(&[0; 27]);
+        // Gas limit.
+        expected.extend_from_slice(&[0x08, 0x52]);
+        expected.extend_from_slice(&[0; 30]);
+        // Action.
+        expected.push(0);
+        expected.extend_from_slice(&[0x35; 20]);
+        // Value.
+        expected.extend_from_slice(&[0x00, 0x00, 0x64, 0xa7, 0xb3, 0xb6, 0xe0, 0x0d]);
+        expected.extend_from_slice(&[0; 24]);
+        // Input.
+        expected.push(0);
+        // Signature.
+        expected.extend_from_slice(&[37, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&raw[raw.len() - 65..raw.len() - 33]);
+        expected.extend_from_slice(&raw[raw.len() - 32..]);
+
+        assert_eq!(scale, expected);
+        assert_eq!(
+            hex::encode(super::transaction_hash(&raw)),
+            "33469b22e9f636356c4160a87eb19df52b7412e8eac32a4a55ffe88ea8350788"
+        );
+    }
+
+    #[test]
+    fn eip1559_transaction() {
+        // Type 2 transaction with an empty access list, creating a contract.
+        let mut rlp = vec![
+            0xd3, 0x01, 0x02, 0x03, 0x04, 0x05, 0x80, 0x06, 0x82, 0xab, 0xcd,
+        ];
+        rlp.extend_from_slice(&[0xc0, 0x01, 0x81, 0xff, 0x81, 0xee]);
+        rlp[0] = 0xc0 + u8::try_from(rlp.len() - 1).unwrap();
+        let mut raw = vec![2];
+        raw.extend_from_slice(&rlp);
+
+        let scale = super::transaction_to_scale(&raw).unwrap();
+        assert_eq!(scale[0], 2);
+        assert_eq!(&scale[1..9], &[1, 0, 0, 0, 0, 0, 0, 0]);
+        // Nonce, max priority fee, max fee, gas limit.
+        for (n, value) in [2, 3, 4, 5].into_iter().enumerate() {
+            assert_eq!(scale[9 + n * 32], value);
+        }
+        // Action is `Create`.
+        assert_eq!(scale[9 + 4 * 32], 1);
+        let rest = &scale[9 + 4 * 32 + 1..];
+        // Value.
+        assert_eq!(rest[0], 6);
+        // Input, access list, y parity.
+        assert_eq!(&rest[32..36], &[8, 0xab, 0xcd, 0]);
+        assert_eq!(rest[36], 1);
+        // Signature.
+        assert_eq!(rest[37 + 31], 0xff);
+        assert_eq!(rest[37 + 63], 0xee);
+        assert_eq!(rest.len(), 37 + 64);
+    }
+
+    #[test]
+    fn invalid_transactions() {
+        assert!(super::transaction_to_scale(&[]).is_err());
+        assert!(super::transaction_to_scale(&[3, 0xc0]).is_err());
+        assert!(super::transaction_to_scale(&[0xc0]).is_err());
+        assert!(super::transaction_to_scale(&[0xc2, 0x01]).is_err());
+    }
+}
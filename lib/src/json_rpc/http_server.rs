@@ -0,0 +1,519 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! HTTP server that accepts JSON-RPC requests sent with `POST`.
+//!
+//! Many existing tools only know how to send JSON-RPC requests through HTTP rather than through
+//! a WebSocket connection. This module is the HTTP counterpart of the
+//! [`websocket_server`](super::websocket_server) module.
+//!
+//! Each TCP connection carries exactly one HTTP request, after which the connection is closed.
+//! Only `POST` requests are reported through the API. `OPTIONS` requests (CORS preflight
+//! requests) are answered automatically, and other HTTP methods are answered with an error.
+//! All responses contain an `Access-Control-Allow-Origin: *` header, so that browsers accept
+//! sending requests to the server from any web page.
+//!
+//! The body of the requests is reported as-is, and it is the responsibility of the API user to
+//! parse it, for example with [`parse_request`](super::parse::parse_request), which supports
+//! batches of calls.
+//!
+//! # Usage
+//!
+//! ```no_run
+//! # async fn foo() {
+//! use smoldot::json_rpc::http_server::{Config, Event, HttpServer};
+//!
+//! let mut server = HttpServer::new(Config {
+//!     bind_address: "127.0.0.1:9933".parse().unwrap(),
+//!     max_request_body_size: 1024 * 1024,
+//!     capacity: 16,
+//! })
+//! .await
+//! .unwrap();
+//!
+//! loop {
+//!     match server.next_event().await {
+//!         // Received a new connection.
+//!         Event::ConnectionOpen { address } => {
+//!             server.accept(address);
+//!         }
+//!
+//!         // Received a request. It must be answered.
+//!         Event::Request { request_id, body, .. } => {
+//!             println!("Received request: {:?}", body);
+//!             server.respond(request_id, "hello back!".to_string());
+//!         },
+//!
+//!         // Connection has been closed without any request to answer.
+//!         Event::ConnectionClosed { .. } => {},
+//!     }
+//! }
+//! # }
+//! ```
+//!
+//! # About performances
+//!
+//! Similar to the [`websocket_server`](super::websocket_server) module, the [`HttpServer`] is
+//! entirely single-threaded.
+
+#![cfg(all(feature = "std"))]
+#![cfg_attr(docsrs, doc(cfg(all(feature = "std"))))]
+
+#[cfg(test)]
+mod tests;
+
+use async_std::net::{TcpListener, TcpStream};
+use core::{fmt, ops, str};
+use futures::prelude::*;
+use std::{io, net::SocketAddr};
+
+/// Maximum size, in bytes, of the request line and headers of an HTTP request.
+const MAX_HEADERS_SIZE: usize = 8192;
+
+/// Configuration for an [`HttpServer`].
+pub struct Config {
+    /// IP address to try to bind to.
+    pub bind_address: SocketAddr,
+
+    /// Maximum size, in bytes, of the body of a request sent by the remote.
+    ///
+    /// Since the requests are entirely buffered before being returned, a maximum value is
+    /// necessary in order to prevent malicious clients from sending huge requests that would
+    /// occupy a lot of memory.
+    pub max_request_body_size: usize,
+
+    /// Pre-allocated capacity for the list of connections.
+    pub capacity: usize,
+}
+
+/// Identifier for a request with regard to an [`HttpServer`].
+///
+/// Since each connection carries exactly one request, this identifier also designates the
+/// connection the request has been received on.
+///
+/// After a request has been answered, its [`RequestId`] might be reused.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub struct RequestId(usize);
+
+/// Listening socket and list of open connections.
+pub struct HttpServer<T> {
+    /// Value passed through [`Config::max_request_body_size`].
+    max_request_body_size: usize,
+
+    /// Endpoint for incoming TCP sockets.
+    listener: TcpListener,
+
+    /// Pending incoming connection to accept. Accepted by calling [`HttpServer::accept`].
+    pending_incoming: Option<TcpStream>,
+
+    /// List of TCP connections whose request is being received.
+    reading: stream::FuturesUnordered<future::BoxFuture<'static, ReadingRequest>>,
+
+    /// Tasks dedicated to sending responses and closing the sockets.
+    writing: stream::FuturesUnordered<future::BoxFuture<'static, ()>>,
+
+    /// List of connections whose request is being received or hasn't been answered yet.
+    connections: slab::Slab<Connection<T>>,
+
+    /// Value of [`Connection::unique_id`] for the next connection.
+    next_unique_id: u64,
+}
+
+struct Connection<T> {
+    user_data: T,
+
+    /// Socket of the connection, once the request has been fully received. `None` while the
+    /// request is still being received.
+    socket: Option<TcpStream>,
+
+    /// Because [`RequestId`]s are reused, we need to make sure that received requests don't
+    /// correspond to old connections with the same ID. For this reason, we additionally compare
+    /// the expected unique ID with the actual one.
+    unique_id: u64,
+}
+
+struct ReadingRequest {
+    /// Identifier of the connection the request comes from.
+    request_id: RequestId,
+
+    /// Unique identifier for this connection. See [`Connection::unique_id`].
+    unique_id: u64,
+
+    /// Socket and body of the request, or `Err` if the connection has been closed without any
+    /// request to report.
+    outcome: Result<(TcpStream, String), ()>,
+}
+
+impl<T> HttpServer<T> {
+    /// Try opening a TCP listening socket.
+    ///
+    /// Returns an error if the listening socket fails to open.
+    pub async fn new(config: Config) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(config.bind_address).await?;
+
+        Ok(HttpServer {
+            max_request_body_size: config.max_request_body_size,
+            listener,
+            pending_incoming: None,
+            reading: stream::FuturesUnordered::new(),
+            writing: stream::FuturesUnordered::new(),
+            connections: slab::Slab::with_capacity(config.capacity),
+            next_unique_id: 0,
+        })
+    }
+
+    /// Address of the local TCP listening socket, as provided by the operating system.
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    /// Accepts the pending connection.
+    ///
+    /// Either [`HttpServer::accept`] or [`HttpServer::reject`] must be called after a
+    /// [`Event::ConnectionOpen`] event is returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no connection is pending.
+    ///
+    pub fn accept(&mut self, user_data: T) -> RequestId {
+        let socket = self.pending_incoming.take().expect("no pending socket");
+
+        let unique_id = {
+            let id = self.next_unique_id;
+            self.next_unique_id += 1;
+            id
+        };
+
+        let request_id = RequestId(self.connections.insert(Connection {
+            user_data,
+            socket: None,
+            unique_id,
+        }));
+
+        let max_request_body_size = self.max_request_body_size;
+        self.reading.push(Box::pin(async move {
+            ReadingRequest {
+                request_id,
+                unique_id,
+                outcome: read_request(socket, max_request_body_size).await,
+            }
+        }));
+
+        request_id
+    }
+
+    /// Reject the pending connection.
+    ///
+    /// Either [`HttpServer::accept`] or [`HttpServer::reject`] must be called after a
+    /// [`Event::ConnectionOpen`] event is returned.
+    ///
+    /// # Panic
+    ///
+    /// Panics if no connection is pending.
+    ///
+    pub fn reject(&mut self) {
+        let _ = self.pending_incoming.take().expect("no pending socket");
+    }
+
+    /// Returns `true` if there isn't any active connection.
+    pub fn is_empty(&self) -> bool {
+        self.connections.is_empty()
+    }
+
+    /// Returns the number of active connections.
+    pub fn len(&self) -> usize {
+        self.connections.len()
+    }
+
+    /// Sends back a response to the given request, then closes the connection. The body must
+    /// be JSON.
+    ///
+    /// The response will be sent in the background, but for API purposes this [`RequestId`] is
+    /// now no longer valid.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`RequestId`] is invalid or if no request has been received on this
+    /// connection yet.
+    ///
+    pub fn respond(&mut self, request_id: RequestId, body: String) -> T {
+        let connection = self.connections.remove(request_id.0);
+        let mut socket = connection.socket.expect("no request received");
+        let response = build_response("200 OK", &body);
+
+        self.writing.push(Box::pin(async move {
+            let _ = socket.write_all(response.as_bytes()).await;
+            let _ = socket.close().await;
+        }));
+
+        connection.user_data
+    }
+
+    /// Destroys a connection without sending back any response.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`RequestId`] is invalid.
+    ///
+    pub fn close(&mut self, request_id: RequestId) -> T {
+        self.connections.remove(request_id.0).user_data
+    }
+
+    /// Returns the next event happening on the server.
+    pub async fn next_event(&'_ mut self) -> Event<'_, T> {
+        loop {
+            futures::select! {
+                // Only try to fetch a new incoming connection if none is pending.
+                socket = {
+                    let listener = &self.listener;
+                    let has_pending = self.pending_incoming.is_some();
+                    async move {
+                        if !has_pending {
+                            listener.accept().await
+                        } else {
+                            loop { futures::pending!() }
+                        }
+                    }
+                }.fuse() => {
+                    let (socket, address) = match socket {
+                        Ok(s) => s,
+                        Err(_) => continue,
+                    };
+                    debug_assert!(self.pending_incoming.is_none());
+                    self.pending_incoming = Some(socket);
+                    return Event::ConnectionOpen { address };
+                },
+
+                reading = self.reading.select_next_some() => {
+                    // Make sure that what is in `self.connections` matches the request.
+                    // Otherwise, it means that the connection is already closed.
+                    if !self.connections.contains(reading.request_id.0) {
+                        continue;
+                    }
+                    if self.connections[reading.request_id.0].unique_id != reading.unique_id {
+                        continue;
+                    }
+
+                    let (socket, body) = match reading.outcome {
+                        Ok(r) => r,
+                        Err(()) => return Event::ConnectionClosed {
+                            request_id: reading.request_id,
+                            user_data: self.connections.remove(reading.request_id.0).user_data,
+                        },
+                    };
+
+                    let connection = &mut self.connections[reading.request_id.0];
+                    connection.socket = Some(socket);
+                    return Event::Request {
+                        request_id: reading.request_id,
+                        user_data: &mut connection.user_data,
+                        body,
+                    };
+                },
+
+                () = self.writing.select_next_some() => {}
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for HttpServer<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list()
+            .entries(
+                self.connections
+                    .iter()
+                    .map(|c| (RequestId(c.0), &c.1.user_data)),
+            )
+            .finish()
+    }
+}
+
+impl<T> ops::Index<RequestId> for HttpServer<T> {
+    type Output = T;
+
+    #[track_caller]
+    fn index(&self, id: RequestId) -> &T {
+        &self.connections.get(id.0).unwrap().user_data
+    }
+}
+
+impl<T> ops::IndexMut<RequestId> for HttpServer<T> {
+    #[track_caller]
+    fn index_mut(&mut self, id: RequestId) -> &mut T {
+        &mut self.connections.get_mut(id.0).unwrap().user_data
+    }
+}
+
+/// Event that has happened on an [`HttpServer`].
+#[derive(Debug)]
+pub enum Event<'a, T> {
+    /// A new TCP connection has arrived on the listening socket.
+    ///
+    /// The connection *must* be accepted or rejected using [`HttpServer::accept`] or
+    /// [`HttpServer::reject`].
+    /// No other [`Event::ConnectionOpen`] event will be generated until the current pending
+    /// connection has been either accepted or rejected.
+    ConnectionOpen {
+        /// Address of the remote, as provided by the operating system.
+        address: SocketAddr,
+    },
+
+    /// A connection has been closed without any request to report, for example because of an
+    /// error or because the remote sent a CORS preflight request. Its [`RequestId`] is now
+    /// invalid.
+    ConnectionClosed {
+        /// Identifier of the connection. This identifier might be reused by the [`HttpServer`]
+        /// for another connection.
+        request_id: RequestId,
+        /// User data associated with the connection.
+        user_data: T,
+    },
+
+    /// A `POST` request has been received on a connection. It must be answered with
+    /// [`HttpServer::respond`] or dropped with [`HttpServer::close`].
+    Request {
+        /// Identifier of the request.
+        request_id: RequestId,
+        /// User data associated with the connection.
+        user_data: &'a mut T,
+        /// Body of the request. Its content is entirely decided by the client, and nothing must
+        /// be assumed about the validity of this body.
+        body: String,
+    },
+}
+
+/// Reads an HTTP request from the given socket.
+///
+/// Returns the socket and the body of the request if the request is a `POST` request. Any other
+/// request is answered and `Err` is returned.
+async fn read_request(
+    mut socket: TcpStream,
+    max_request_body_size: usize,
+) -> Result<(TcpStream, String), ()> {
+    let mut buffer = Vec::with_capacity(1024);
+
+    // Read until the end of the headers.
+    let headers_len = loop {
+        if let Some(pos) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break pos + 4;
+        }
+
+        if buffer.len() >= MAX_HEADERS_SIZE {
+            let _ = send_error(&mut socket, "431 Request Header Fields Too Large").await;
+            return Err(());
+        }
+
+        let mut chunk = [0; 1024];
+        match socket.read(&mut chunk).await {
+            Ok(0) | Err(_) => return Err(()),
+            Ok(n) => buffer.extend_from_slice(&chunk[..n]),
+        }
+    };
+
+    let content_length = {
+        let mut headers = [httparse::EMPTY_HEADER; 32];
+        let mut request = httparse::Request::new(&mut headers);
+        match request.parse(&buffer[..headers_len]) {
+            Ok(httparse::Status::Complete(_)) => {}
+            Ok(httparse::Status::Partial) | Err(_) => {
+                let _ = send_error(&mut socket, "400 Bad Request").await;
+                return Err(());
+            }
+        }
+
+        match request.method {
+            Some("POST") => {}
+            Some("OPTIONS") => {
+                let _ = socket
+                    .write_all(
+                        "HTTP/1.1 204 No Content\r\n\
+                        Access-Control-Allow-Origin: *\r\n\
+                        Access-Control-Allow-Methods: POST, OPTIONS\r\n\
+                        Access-Control-Allow-Headers: Content-Type\r\n\
+                        Access-Control-Max-Age: 86400\r\n\
+                        Connection: close\r\n\r\n"
+                            .as_bytes(),
+                    )
+                    .await;
+                let _ = socket.close().await;
+                return Err(());
+            }
+            _ => {
+                let _ = send_error(&mut socket, "405 Method Not Allowed").await;
+                return Err(());
+            }
+        }
+
+        request
+            .headers
+            .iter()
+            .find(|h| h.name.eq_ignore_ascii_case("Content-Length"))
+            .and_then(|h| str::from_utf8(h.value).ok())
+            .and_then(|v| v.trim().parse::<usize>().ok())
+    };
+
+    let Some(content_length) = content_length else {
+        let _ = send_error(&mut socket, "411 Length Required").await;
+        return Err(());
+    };
+
+    if content_length > max_request_body_size {
+        let _ = send_error(&mut socket, "413 Payload Too Large").await;
+        return Err(());
+    }
+
+    // Read the rest of the body.
+    let mut body = buffer.split_off(headers_len);
+    body.truncate(content_length);
+    if body.len() < content_length {
+        let already_read = body.len();
+        body.resize(content_length, 0);
+        if socket.read_exact(&mut body[already_read..]).await.is_err() {
+            return Err(());
+        }
+    }
+
+    match String::from_utf8(body) {
+        Ok(body) => Ok((socket, body)),
+        Err(_) => {
+            let _ = send_error(&mut socket, "400 Bad Request").await;
+            Err(())
+        }
+    }
+}
+
+/// Sends back a response with the given status and an empty body, then closes the socket.
+async fn send_error(socket: &mut TcpStream, status: &str) -> Result<(), io::Error> {
+    socket
+        .write_all(build_response(status, "").as_bytes())
+        .await?;
+    socket.close().await
+}
+
+/// Builds an HTTP response containing the given JSON body.
+fn build_response(status: &str, body: &str) -> String {
+    format!(
+        "HTTP/1.1 {status}\r\n\
+        Content-Type: application/json; charset=utf-8\r\n\
+        Content-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\n\
+        Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+}
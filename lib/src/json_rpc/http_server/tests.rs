@@ -0,0 +1,144 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use super::{Config, Event, HttpServer};
+
+use futures::prelude::*;
+
+async fn new_server() -> HttpServer<i32> {
+    HttpServer::new(Config {
+        bind_address: "127.0.0.1:0".parse().unwrap(),
+        max_request_body_size: 1024,
+        capacity: 32,
+    })
+    .await
+    .unwrap()
+}
+
+/// Sends the given raw HTTP request to the server, and returns the raw response.
+fn send_request(
+    server: &HttpServer<i32>,
+    request: &'static str,
+) -> async_std::task::JoinHandle<String> {
+    let server_addr = server.local_addr().unwrap();
+    async_std::task::spawn(async move {
+        let mut socket = async_std::net::TcpStream::connect(server_addr)
+            .await
+            .unwrap();
+        socket.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+        response
+    })
+}
+
+#[test]
+fn basic_works() {
+    async_std::task::block_on(async move {
+        let mut server = new_server().await;
+
+        let client_task = send_request(
+            &server,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Type: application/json\r\n\
+            Content-Length: 12\r\n\r\nhello world!",
+        );
+
+        let id = match server.next_event().await {
+            Event::ConnectionOpen { .. } => server.accept(12),
+            _ => panic!(),
+        };
+
+        match server.next_event().await {
+            Event::Request {
+                request_id,
+                user_data,
+                body,
+            } => {
+                assert_eq!(request_id, id);
+                assert_eq!(*user_data, 12);
+                assert_eq!(body, "hello world!");
+            }
+            _ => panic!(),
+        };
+
+        assert_eq!(server.respond(id, "hello back".to_owned()), 12);
+        assert!(server.is_empty());
+
+        let response = {
+            let mut client_task = client_task.fuse();
+            futures::select! {
+                response = client_task => response,
+                _ = server.next_event().fuse() => panic!(),
+            }
+        };
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+        assert!(response.ends_with("\r\n\r\nhello back"));
+    });
+}
+
+#[test]
+fn preflight_answered() {
+    async_std::task::block_on(async move {
+        let mut server = new_server().await;
+
+        let client_task = send_request(
+            &server,
+            "OPTIONS / HTTP/1.1\r\nHost: localhost\r\nOrigin: http://example.com\r\n\r\n",
+        );
+
+        match server.next_event().await {
+            Event::ConnectionOpen { .. } => server.accept(5),
+            _ => panic!(),
+        };
+
+        match server.next_event().await {
+            Event::ConnectionClosed { user_data, .. } => assert_eq!(user_data, 5),
+            _ => panic!(),
+        };
+
+        let response = client_task.await;
+        assert!(response.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(response.contains("Access-Control-Allow-Origin: *\r\n"));
+    });
+}
+
+#[test]
+fn body_too_large() {
+    async_std::task::block_on(async move {
+        let mut server = new_server().await;
+
+        let client_task = send_request(
+            &server,
+            "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 4096\r\n\r\n",
+        );
+
+        match server.next_event().await {
+            Event::ConnectionOpen { .. } => server.accept(5),
+            _ => panic!(),
+        };
+
+        match server.next_event().await {
+            Event::ConnectionClosed { user_data, .. } => assert_eq!(user_data, 5),
+            _ => panic!(),
+        };
+
+        let response = client_task.await;
+        assert!(response.starts_with("HTTP/1.1 413 Payload Too Large\r\n"));
+    });
+}
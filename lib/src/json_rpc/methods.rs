@@ -385,6 +385,18 @@ define_methods! {
     childstate_getStorage() -> (), // TODO:
     childstate_getStorageHash() -> (), // TODO:
     childstate_getStorageSize() -> (), // TODO:
+    /// Returns, as an Ethereum quantity, the number of the current best block. Only for chains
+    /// based on Frontier.
+    eth_blockNumber() -> Cow<'a, str>,
+    /// Returns, as an Ethereum quantity, the Ethereum chain id of the chain. Only for chains
+    /// based on Frontier.
+    eth_chainId() -> Cow<'a, str>,
+    /// Returns, as an Ethereum quantity, the balance of the given 20 bytes address. Only for
+    /// chains based on Frontier.
+    eth_getBalance(address: HexString, block: Option<Cow<'a, str>>) -> Cow<'a, str>,
+    /// Submits a raw signed Ethereum transaction and returns its hash. Only for chains based on
+    /// Frontier.
+    eth_sendRawTransaction(transaction: HexString) -> HashHexString,
     grandpa_roundState() -> (), // TODO:
     offchain_localStorageGet() -> (), // TODO:
     offchain_localStorageSet() -> (), // TODO:
@@ -487,6 +499,42 @@ define_methods! {
     network_unstable_subscribeEvents() -> Cow<'a, str>,
     network_unstable_unsubscribeEvents(subscription: Cow<'a, str>) -> (),
     chainHead_unstable_finalizedDatabase(#[rename = "maxSizeBytes"] max_size_bytes: Option<u64>) -> Cow<'a, str>,
+    /// Indicates which extrinsics and storage items the JSON-RPC client is interested in, so
+    /// that the bodies and storage items of the new blocks reported by the given `follow`
+    /// subscription can be downloaded ahead of time. Replaces the previous hints of this
+    /// subscription.
+    chainHead_unstable_prefetchHint(
+        #[rename = "followSubscription"] follow_subscription: Cow<'a, str>,
+        #[rename = "extrinsicHashes"] extrinsic_hashes: Vec<HashHexString>,
+        #[rename = "storageKeys"] storage_keys: Vec<HexString>
+    ) -> (),
+    /// Returns information about the progress of the finality of the chain, or `null` if the
+    /// chain is a parachain.
+    sync_unstable_finalityDiagnostics() -> Option<FinalityDiagnostics>,
+    /// Executes again the given block, or only its extrinsics up to and including the one at
+    /// the given index, and returns the storage accesses and logs of each step of the execution.
+    state_unstable_traceBlock(
+        hash: HashHexString,
+        #[rename = "extrinsicIndex"] extrinsic_index: Option<u32>
+    ) -> BlockTrace,
+    /// Returns the list of APIs supported by the runtime of the given block, or of the best
+    /// block if no block is provided, with their names when known.
+    state_unstable_runtimeApis(at: Option<HashHexString>) -> Vec<RuntimeApi>,
+    /// Similar to `system_health`, but returns detailed information about the peers and the
+    /// requests of the chain, plus the list of problems that have been detected.
+    system_unstable_health() -> HealthDiagnostics,
+    /// Returns the GrandPa authorities set that must finalize the children of the current
+    /// finalized block, plus the change to this set that has been scheduled by a finalized block
+    /// but not triggered yet. Returns `null` if the chain doesn't use GrandPa or if the
+    /// finalized block isn't known yet.
+    grandpa_unstable_authoritySet() -> Option<GrandpaAuthoritySet>,
+    /// Returns the verified justification or GrandPa commit that proves the finality of the
+    /// given block. Returns `null` if no such proof has been retained, which is always the case
+    /// if the client has been configured to not retain any finality proof.
+    grandpa_unstable_finalityProof(hash: HashHexString) -> Option<FinalityProof>,
+    /// Returns the Babe epoch the current best block belongs to and the epoch that follows it.
+    /// Returns `null` if the chain doesn't use Babe or is a parachain.
+    babe_unstable_epochs() -> Option<BabeEpochs>,
 }
 
 define_methods! {
@@ -628,23 +676,10 @@ impl<'a> serde::Deserialize<'a> for AccountId {
         D: serde::Deserializer<'a>,
     {
         let string = <&str>::deserialize(deserializer)?;
-        let decoded = match bs58::decode(&string).into_vec() {
-            // TODO: don't use into_vec
-            Ok(d) => d,
-            Err(_) => return Err(serde::de::Error::custom("AccountId isn't in base58 format")),
-        };
-
-        // TODO: retrieve the actual prefix length of the current chain
-        if decoded.len() < 35 {
-            return Err(serde::de::Error::custom("unexpected length for AccountId"));
-        }
-
-        // TODO: finish implementing this properly ; must notably check checksum
-        // see https://github.com/paritytech/substrate/blob/74a50abd6cbaad1253daf3585d5cdaa4592e9184/primitives/core/src/crypto.rs#L228
-
-        // TODO: retrieve and use the actual prefix length of the current chain
-        let account_id =
-            <[u8; 32]>::try_from(&decoded[(decoded.len() - 34)..(decoded.len() - 2)]).unwrap();
+        // TODO: check the network identifier against the one of the current chain
+        let account_id = crate::identity::ss58::decode(string)
+            .map_err(|err| serde::de::Error::custom(format!("invalid AccountId: {err}")))?
+            .public_key;
 
         Ok(AccountId(account_id))
     }
@@ -755,6 +790,14 @@ pub enum TransactionWatchEvent<'a> {
         #[serde(rename = "block")]
         block: TransactionWatchEventBlock,
     },
+    /// Non-standard event specific to smoldot.
+    #[serde(rename = "inclusionProof")]
+    InclusionProof {
+        #[serde(rename = "block")]
+        block: TransactionWatchEventBlock,
+        #[serde(rename = "eventsProof")]
+        events_proof: HexString,
+    },
     #[serde(rename = "error")]
     Error { error: Cow<'a, str> },
     #[serde(rename = "invalid")]
@@ -990,6 +1033,10 @@ pub struct RuntimeSpec<'a> {
     pub transaction_version: Option<u32>,
     // TODO: add `state_version`? would need a JSON-RPC API interface spec change
     pub apis: HashMap<HexString, u32, fnv::FnvBuildHasher>,
+    /// Number of heap pages available to the runtime, as determined by the `:heappages` storage
+    /// item. Not part of the JSON-RPC API interface specification.
+    #[serde(rename = "heapPages", default, skip_serializing_if = "Option::is_none")]
+    pub heap_pages: Option<u32>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -1012,6 +1059,16 @@ pub struct RuntimeVersion<'a> {
     pub apis: Vec<(HexString, u32)>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct RuntimeApi {
+    /// Name of the API, or `None` if it isn't one of the well-known APIs.
+    pub name: Option<String>,
+    /// BLAKE2 hash of length 8 of the name of the API.
+    #[serde(rename = "nameHash")]
+    pub name_hash: HexString,
+    pub version: u32,
+}
+
 #[derive(Debug, Copy, Clone)]
 pub struct RuntimeDispatchInfo {
     pub weight: u64,
@@ -1032,11 +1089,189 @@ pub struct StorageChangeSet {
     pub changes: Vec<(HexString, Option<HexString>)>,
 }
 
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FinalityDiagnostics {
+    #[serde(rename = "bestBlockNumber")]
+    pub best_block_number: u64,
+    #[serde(rename = "finalizedBlockNumber")]
+    pub finalized_block_number: u64,
+    /// Number of milliseconds since the finalized block has last changed.
+    #[serde(rename = "msSinceLastFinalized")]
+    pub ms_since_last_finalized: u64,
+    pub peers: u64,
+    /// Number of peers whose finalized block is higher than the local finalized block.
+    #[serde(rename = "peersWithHigherFinalized")]
+    pub peers_with_higher_finalized: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct HealthDiagnostics {
+    pub peers: u64,
+    /// Number of peers whose best block is the local best block or one of its descendants.
+    /// `None` for parachains.
+    #[serde(rename = "bestChainCompatiblePeers")]
+    pub best_chain_compatible_peers: Option<u64>,
+    /// Number of peers capable of answering storage and call proof requests.
+    #[serde(rename = "proofServingPeers")]
+    pub proof_serving_peers: u64,
+    /// Number of milliseconds since a request of each type has last succeeded, or `None` if no
+    /// such request has ever succeeded.
+    #[serde(rename = "msSinceLastBlocksRequest")]
+    pub ms_since_last_blocks_request: Option<u64>,
+    #[serde(rename = "msSinceLastWarpSyncRequest")]
+    pub ms_since_last_warp_sync_request: Option<u64>,
+    #[serde(rename = "msSinceLastStorageProofRequest")]
+    pub ms_since_last_storage_proof_request: Option<u64>,
+    #[serde(rename = "msSinceLastCallProofRequest")]
+    pub ms_since_last_call_proof_request: Option<u64>,
+    /// List of problems that have been detected. Empty if the chain is healthy.
+    pub problems: Vec<HealthProblem>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub enum HealthProblem {
+    /// The node isn't connected to any peer.
+    #[serde(rename = "noPeers")]
+    NoPeers,
+    /// None of the peers is following the same best chain as the node, which indicates that
+    /// the node or all its peers are on a different fork.
+    #[serde(rename = "noBestChainCompatiblePeer")]
+    NoBestChainCompatiblePeer,
+    /// All the peers are light clients, and thus storage items can't be accessed.
+    #[serde(rename = "noProofServingPeer")]
+    NoProofServingPeer,
+    /// It is suspected that all the peers are controlled by the same entity.
+    #[serde(rename = "eclipseSuspected")]
+    EclipseSuspected,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BabeEpochs {
+    #[serde(rename = "bestBlockHash")]
+    pub best_block_hash: HashHexString,
+    #[serde(rename = "bestBlockNumber")]
+    pub best_block_number: u64,
+    #[serde(rename = "slotsPerEpoch")]
+    pub slots_per_epoch: u64,
+    /// `None` if the best block belongs to epoch #0.
+    #[serde(rename = "currentEpoch")]
+    pub current_epoch: Option<BabeEpoch>,
+    #[serde(rename = "nextEpoch")]
+    pub next_epoch: BabeEpoch,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BabeEpoch {
+    #[serde(rename = "epochIndex")]
+    pub epoch_index: u64,
+    /// `None` if and only if this is epoch #0 and its start slot isn't known yet.
+    #[serde(rename = "startSlotNumber")]
+    pub start_slot_number: Option<u64>,
+    pub authorities: Vec<BabeAuthority>,
+    pub randomness: HashHexString,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BabeAuthority {
+    /// Sr25519 public key of the authority.
+    #[serde(rename = "publicKey")]
+    pub public_key: HashHexString,
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaAuthoritySet {
+    #[serde(rename = "finalizedBlockHash")]
+    pub finalized_block_hash: HashHexString,
+    #[serde(rename = "finalizedBlockNumber")]
+    pub finalized_block_number: u64,
+    /// Identifier of the authorities set, incremented by one every time the set changes.
+    #[serde(rename = "setId")]
+    pub set_id: u64,
+    pub authorities: Vec<GrandpaAuthority>,
+    #[serde(rename = "scheduledChange")]
+    pub scheduled_change: Option<GrandpaScheduledChange>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaAuthority {
+    /// Ed25519 public key of the authority.
+    #[serde(rename = "publicKey")]
+    pub public_key: HashHexString,
+    pub weight: u64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct GrandpaScheduledChange {
+    /// Number of the block that triggers the change. The descendants of this block must be
+    /// finalized by the new authorities, and [`GrandpaAuthoritySet::set_id`] is incremented.
+    #[serde(rename = "triggerBlockNumber")]
+    pub trigger_block_number: u64,
+    pub authorities: Vec<GrandpaAuthority>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct FinalityProof {
+    /// Hash of the block whose finality is directly proven by [`FinalityProof::proof`]. Either
+    /// the requested block or one of its descendants.
+    #[serde(rename = "targetBlockHash")]
+    pub target_block_hash: HashHexString,
+    #[serde(rename = "targetBlockNumber")]
+    pub target_block_number: u64,
+    pub proof: FinalityProofContent,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(tag = "type")]
+pub enum FinalityProofContent {
+    #[serde(rename = "justification")]
+    Justification {
+        /// Identifier of the consensus engine, such as `0x46524e4b` (`FRNK`) for GrandPa.
+        #[serde(rename = "consensusEngine")]
+        consensus_engine: HexString,
+        /// SCALE-encoded justification.
+        justification: HexString,
+    },
+    #[serde(rename = "grandpaCommit")]
+    GrandpaCommit {
+        /// SCALE-encoded GrandPa commit message.
+        commit: HexString,
+    },
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTrace {
+    pub steps: Vec<BlockTraceStep>,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct BlockTraceStep {
+    /// Index within the body of the block of the extrinsic applied during this step, or `None`
+    /// if this step is the initialization of the block.
+    #[serde(rename = "extrinsicIndex")]
+    pub extrinsic_index: Option<u32>,
+    /// Storage items read from the storage of the parent of the block, in the order in which
+    /// they have been read. Items previously modified during the execution aren't included.
+    pub reads: Vec<(HexString, Option<HexString>)>,
+    /// Storage items modified during this step, ordered by key. `None` if the item has been
+    /// removed.
+    pub writes: Vec<(HexString, Option<HexString>)>,
+    /// Concatenation of all the log messages printed by the runtime during this step.
+    pub logs: String,
+    /// Value returned by the runtime, or `None` if the execution has failed.
+    pub output: Option<HexString>,
+    /// Reason why the execution has failed. If `Some`, this step is the last one of the trace.
+    pub error: Option<String>,
+}
+
 #[derive(Debug, Clone)]
 pub struct SystemHealth {
     pub is_syncing: bool,
     pub peers: u64,
     pub should_have_peers: bool,
+    /// Non-standard field. If `true`, it is suspected that all the peers are controlled by the
+    /// same entity and are hiding the latest blocks of the chain.
+    pub possibly_eclipsed: bool,
 }
 
 #[derive(Debug, Clone, serde::Serialize)]
@@ -1186,12 +1421,15 @@ impl serde::Serialize for SystemHealth {
             peers: u64,
             #[serde(rename = "shouldHavePeers")]
             should_have_peers: bool,
+            #[serde(rename = "possiblyEclipsed")]
+            possibly_eclipsed: bool,
         }
 
         SerdeSystemHealth {
             is_syncing: self.is_syncing,
             peers: self.peers,
             should_have_peers: self.should_have_peers,
+            possibly_eclipsed: self.possibly_eclipsed,
         }
         .serialize(serializer)
     }
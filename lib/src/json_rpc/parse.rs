@@ -17,7 +17,7 @@
 
 //! Parse JSON-RPC method calls and notifications, and build responses messages.
 
-use alloc::{borrow::Cow, string::String};
+use alloc::{borrow::Cow, string::String, vec::Vec};
 
 /// Parses a JSON-encoded RPC method call or notification.
 pub fn parse_call(call_json: &str) -> Result<Call, ParseError> {
@@ -45,6 +45,51 @@ pub fn parse_call(call_json: &str) -> Result<Call, ParseError> {
     })
 }
 
+/// Parses a JSON-encoded request, which is either a single call or a batch of calls.
+///
+/// Each call returned by this function is the JSON of that call and must then be passed to
+/// [`parse_call`].
+///
+/// # Example
+///
+/// ```
+/// # use smoldot::json_rpc::parse;
+/// let request = parse::parse_request(r#"[{"jsonrpc":"2.0","id":1,"method":"foo"}, 5]"#).unwrap();
+/// match request {
+///     parse::Request::Batch(calls) => {
+///         assert_eq!(calls, [r#"{"jsonrpc":"2.0","id":1,"method":"foo"}"#, "5"]);
+///     }
+///     parse::Request::Single(_) => unreachable!(),
+/// }
+/// ```
+///
+pub fn parse_request(request_json: &str) -> Result<Request<'_>, ParseError> {
+    let raw: &serde_json::value::RawValue =
+        serde_json::from_str(request_json).map_err(ParseError)?;
+
+    if raw.get().starts_with('[') {
+        let calls: Vec<&serde_json::value::RawValue> =
+            serde_json::from_str(raw.get()).map_err(ParseError)?;
+        Ok(Request::Batch(calls.into_iter().map(|c| c.get()).collect()))
+    } else {
+        Ok(Request::Single(raw.get()))
+    }
+}
+
+/// Request decoded by [`parse_request`].
+#[derive(Debug)]
+pub enum Request<'a> {
+    /// Request consists in a single call, whose JSON is provided.
+    Single(&'a str),
+
+    /// Request consists in a batch of calls, whose JSON is provided.
+    ///
+    /// According to the JSON-RPC specification, an empty batch must be answered with a single
+    /// [`ErrorResponse::InvalidRequest`] error, and the responses to the calls of a non-empty
+    /// batch must be grouped with [`build_batch_response`].
+    Batch(Vec<&'a str>),
+}
+
 /// Builds a JSON call.
 ///
 /// `method` must be the name of the method to call. `params_json` must be the JSON-formatted
@@ -169,6 +214,39 @@ pub fn build_error_response(
     .unwrap()
 }
 
+/// Builds the response to a batch of calls, given the JSON-formatted responses to each
+/// individual call.
+///
+/// Calls that don't have any response, such as notifications, must be omitted. According to
+/// the JSON-RPC specification, nothing at all must be sent back if none of the calls of a batch
+/// has a response.
+///
+/// # Example
+///
+/// ```
+/// # use smoldot::json_rpc::parse;
+/// let response = parse::build_batch_response(
+///     [parse::build_success_response("1", "true"), parse::build_success_response("2", "null")]
+///         .into_iter()
+/// );
+/// assert_eq!(
+///     response,
+///     r#"[{"jsonrpc":"2.0","id":1,"result":true},{"jsonrpc":"2.0","id":2,"result":null}]"#
+/// );
+/// ```
+///
+pub fn build_batch_response(responses: impl Iterator<Item = impl AsRef<str>>) -> String {
+    let mut out = String::from("[");
+    for (index, response) in responses.enumerate() {
+        if index != 0 {
+            out.push(',');
+        }
+        out.push_str(response.as_ref());
+    }
+    out.push(']');
+    out
+}
+
 /// Error that can be reported to the JSON-RPC client.
 #[derive(Debug)]
 pub enum ErrorResponse<'a> {
@@ -381,6 +459,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn parse_request_single() {
+        match super::parse_request(r#" {"jsonrpc":"2.0","id":5,"method":"foo"} "#).unwrap() {
+            super::Request::Single(call) => {
+                assert_eq!(call, r#"{"jsonrpc":"2.0","id":5,"method":"foo"}"#)
+            }
+            super::Request::Batch(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_request_batch() {
+        match super::parse_request(
+            r#"[{"jsonrpc":"2.0","id":5,"method":"foo"}, {"jsonrpc":"2.0","method":"bar"}]"#,
+        )
+        .unwrap()
+        {
+            super::Request::Batch(calls) => {
+                assert_eq!(calls.len(), 2);
+                assert_eq!(super::parse_call(calls[0]).unwrap().method, "foo");
+                assert_eq!(super::parse_call(calls[1]).unwrap().method, "bar");
+            }
+            super::Request::Single(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn parse_request_bad_json() {
+        assert!(super::parse_request(r#"[{"jsonrpc":"2.0","id":5,"#).is_err());
+    }
+
     #[test]
     fn parse_bad_id() {
         assert!(
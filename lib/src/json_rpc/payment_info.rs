@@ -16,6 +16,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use super::methods;
+use crate::transactions::{self, calls::Weight};
 
 /// Produces the input to pass to the `TransactionPaymentApi_query_info` runtime call.
 pub fn payment_info_parameters(
@@ -31,14 +32,65 @@ pub fn payment_info_parameters(
 /// Name of the runtime function to call in order to obtain the payment fees.
 pub const PAYMENT_FEES_FUNCTION_NAME: &str = "TransactionPaymentApi_query_info";
 
+/// Payment information of a transaction, as returned by the runtime, independently of the
+/// version of the `TransactionPaymentApi` API.
+#[derive(Debug, Copy, Clone)]
+pub struct PaymentInfo {
+    /// Computation time of the transaction, in picoseconds.
+    pub weight_ref_time: u64,
+    /// Size in bytes of the storage proof that a parachain collator must provide in order to
+    /// include the transaction. `None` if the runtime uses version 1 of the
+    /// `TransactionPaymentApi` API, which doesn't report this value.
+    pub weight_proof_size: Option<u64>,
+    /// Class of the transaction.
+    pub class: methods::DispatchClass,
+    /// Fee of the transaction, not including the tip.
+    pub partial_fee: u128,
+}
+
+impl From<PaymentInfo> for methods::RuntimeDispatchInfo {
+    fn from(info: PaymentInfo) -> Self {
+        methods::RuntimeDispatchInfo {
+            weight: info.weight_ref_time,
+            class: info.class,
+            partial_fee: info.partial_fee,
+        }
+    }
+}
+
+impl From<transactions::dispatch::DispatchClass> for methods::DispatchClass {
+    fn from(class: transactions::dispatch::DispatchClass) -> Self {
+        match class {
+            transactions::dispatch::DispatchClass::Normal => methods::DispatchClass::Normal,
+            transactions::dispatch::DispatchClass::Operational => {
+                methods::DispatchClass::Operational
+            }
+            transactions::dispatch::DispatchClass::Mandatory => methods::DispatchClass::Mandatory,
+        }
+    }
+}
+
 /// Attempt to decode the output of the runtime call.
 ///
 /// Must be passed the version of the `TransactionPaymentApi` API, according to the runtime
 /// specification.
+///
+/// Equivalent to calling [`decode`] then converting the result.
 pub fn decode_payment_info(
     scale_encoded: &'_ [u8],
     api_version: u32,
 ) -> Result<methods::RuntimeDispatchInfo, DecodeError> {
+    decode(scale_encoded, api_version).map(Into::into)
+}
+
+/// Attempt to decode the output of the runtime call.
+///
+/// Must be passed the version of the `TransactionPaymentApi` API, according to the runtime
+/// specification.
+///
+/// Version 1 of the API encodes the weight of the transaction as a single number, while version
+/// 2 encodes it as a computation time and a proof size.
+pub fn decode(scale_encoded: &'_ [u8], api_version: u32) -> Result<PaymentInfo, DecodeError> {
     let is_api_v2 = match api_version {
         1 => false,
         2 => true,
@@ -55,7 +107,7 @@ pub fn decode_payment_info(
 }
 
 /// Potential error when decoding payment information runtime output.
-#[derive(Debug, derive_more::Display)]
+#[derive(Debug, derive_more::Display, Clone)]
 pub enum DecodeError {
     /// Failed to parse the return value of `TransactionPaymentApi_query_info`.
     ParseError,
@@ -65,29 +117,20 @@ pub enum DecodeError {
 
 fn nom_decode_payment_info<'a, E: nom::error::ParseError<&'a [u8]>>(
     is_api_v2: bool,
-) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], methods::RuntimeDispatchInfo, E> {
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], PaymentInfo, E> {
     nom::combinator::map(
         nom::sequence::tuple((
-            move |bytes| {
-                if is_api_v2 {
-                    nom::number::complete::le_u64(bytes)
-                } else {
-                    nom::combinator::map(
-                        nom::sequence::tuple((
-                            crate::util::nom_scale_compact_u64,
-                            crate::util::nom_scale_compact_u64,
-                        )),
-                        |(ref_time, _proof_size)| ref_time,
-                    )(bytes)
+            nom::combinator::map(transactions::dispatch::nom_weight(is_api_v2), |weight| {
+                match weight {
+                    Weight::V1(ref_time) => (ref_time, None),
+                    Weight::V2 {
+                        ref_time,
+                        proof_size,
+                    } => (ref_time, Some(proof_size)),
                 }
-            },
-            nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
-                0 => Some(methods::DispatchClass::Normal),
-                1 => Some(methods::DispatchClass::Operational),
-                2 => Some(methods::DispatchClass::Mandatory),
-                _ => None,
             }),
-            |bytes| {
+            nom::combinator::map(transactions::dispatch::nom_dispatch_class, Into::into),
+            |bytes: &'a [u8]| {
                 // The exact format here is the SCALE encoding of the type `Balance`.
                 // Normally, determining the actual type of `Balance` would require parsing the
                 // metadata provided by the runtime. However, this is a pretty difficult to
@@ -98,38 +141,79 @@ fn nom_decode_payment_info<'a, E: nom::error::ParseError<&'a [u8]>>(
                 // number of bytes.
                 // If a field was to be added after the balance, this code would need to be
                 // modified.
+                if bytes.len() > 16 {
+                    return Err(nom::Err::Error(nom::error::make_error(
+                        bytes,
+                        nom::error::ErrorKind::Digit,
+                    )));
+                }
+
                 let mut num = 0u128;
-                let mut shift = 0u32;
-                for byte in <[u8]>::iter(bytes) {
-                    let shifted =
-                        u128::from(*byte)
-                            .checked_mul(1 << shift)
-                            .ok_or(nom::Err::Error(nom::error::make_error(
-                                bytes,
-                                nom::error::ErrorKind::Digit,
-                            )))?;
-                    num =
-                        num.checked_add(shifted)
-                            .ok_or(nom::Err::Error(nom::error::make_error(
-                                bytes,
-                                nom::error::ErrorKind::Digit,
-                            )))?;
-                    shift =
-                        shift
-                            .checked_add(16)
-                            .ok_or(nom::Err::Error(nom::error::make_error(
-                                bytes,
-                                nom::error::ErrorKind::Digit,
-                            )))?;
+                for (index, byte) in bytes.iter().enumerate() {
+                    num |= u128::from(*byte) << (8 * index);
                 }
 
                 Ok((&[][..], num))
             },
         )),
-        |(weight, class, partial_fee)| methods::RuntimeDispatchInfo {
-            weight,
+        |((weight_ref_time, weight_proof_size), class, partial_fee)| PaymentInfo {
+            weight_ref_time,
+            weight_proof_size,
             class,
             partial_fee,
         },
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::super::methods;
+
+    #[test]
+    fn decode_v1() {
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&123_456_789u64.to_le_bytes());
+        encoded.push(1);
+        encoded.extend_from_slice(&15_000_000_000u128.to_le_bytes());
+
+        let info = super::decode(&encoded, 1).unwrap();
+        assert_eq!(info.weight_ref_time, 123_456_789);
+        assert_eq!(info.weight_proof_size, None);
+        assert!(matches!(info.class, methods::DispatchClass::Operational));
+        assert_eq!(info.partial_fee, 15_000_000_000);
+    }
+
+    #[test]
+    fn decode_v2() {
+        // Compact encoding of 100_000_000 followed with the compact encoding of 3_593.
+        let mut encoded = vec![0x02, 0x84, 0xd7, 0x17, 0x25, 0x38];
+        encoded.push(0);
+        encoded.extend_from_slice(&15_000_000_000u128.to_le_bytes());
+
+        let info = super::decode(&encoded, 2).unwrap();
+        assert_eq!(info.weight_ref_time, 100_000_000);
+        assert_eq!(info.weight_proof_size, Some(3_593));
+        assert!(matches!(info.class, methods::DispatchClass::Normal));
+        assert_eq!(info.partial_fee, 15_000_000_000);
+    }
+
+    #[test]
+    fn decode_unknown_version() {
+        assert!(matches!(
+            super::decode(&[0; 25], 3),
+            Err(super::DecodeError::UnknownRuntimeVersion)
+        ));
+    }
+
+    #[test]
+    fn decode_balance_too_large() {
+        let mut encoded = Vec::new();
+        encoded.extend_from_slice(&0u64.to_le_bytes());
+        encoded.push(0);
+        encoded.extend_from_slice(&[0xff; 17]);
+        assert!(matches!(
+            super::decode(&encoded, 1),
+            Err(super::DecodeError::ParseError)
+        ));
+    }
+}
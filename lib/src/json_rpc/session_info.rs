@@ -0,0 +1,388 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access to the `Session.Validators` and `Session.QueuedKeys` storage entries, which contain
+//! the list of validators of the current session and the session keys of the validators of the
+//! next session.
+//!
+//! Validators are assumed to be identified by a 32 bytes account id, like in the Substrate and
+//! Polkadot runtimes.
+//!
+//! The session keys of a validator are a runtime-specific struct containing one public key per
+//! key type, for example one for GrandPa and one for Babe. A [`QueuedKeysDecoder`] is built from
+//! the metadata of the runtime, and determines the name and size of each of these public keys.
+
+use crate::{
+    metadata::{self, TypeDef},
+    util,
+};
+
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+
+/// Returns the key of the `Session.Validators` storage entry.
+pub fn validators_storage_key() -> Vec<u8> {
+    storage_key(b"Validators")
+}
+
+/// Returns the key of the `Session.QueuedKeys` storage entry.
+pub fn queued_keys_storage_key() -> Vec<u8> {
+    storage_key(b"QueuedKeys")
+}
+
+/// Attempt to decode the value of the `Session.Validators` storage entry.
+///
+/// If the storage entry doesn't exist, an empty list should be used instead.
+pub fn decode_validators(scale_encoded: &[u8]) -> Result<Vec<[u8; 32]>, DecodeError> {
+    let (num_validators, rest) = decode_length(scale_encoded)?;
+
+    let expected_len = num_validators
+        .checked_mul(32)
+        .ok_or(DecodeError::InvalidLength)?;
+    if rest.len() != expected_len {
+        return Err(DecodeError::InvalidLength);
+    }
+
+    Ok(rest
+        .chunks_exact(32)
+        .map(|account_id| <[u8; 32]>::try_from(account_id).unwrap())
+        .collect())
+}
+
+/// Session keys of a validator, as found in the `Session.QueuedKeys` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueuedKeys {
+    /// Account id of the validator.
+    pub validator: [u8; 32],
+    /// Public keys of the validator, one per key type, in the order in which they are found in
+    /// the session keys struct of the runtime.
+    pub session_keys: Vec<SessionKey>,
+}
+
+/// Public key of a validator for a specific key type.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SessionKey {
+    /// Name of the field of the session keys struct of the runtime, for example `grandpa` or
+    /// `babe`.
+    pub key_type: String,
+    /// SCALE encoding of the public key.
+    pub public_key: Vec<u8>,
+}
+
+/// Decoder for the values of the `Session.QueuedKeys` storage entry of a specific runtime.
+#[derive(Debug, Clone)]
+pub struct QueuedKeysDecoder {
+    /// Name and size of each field of the session keys struct.
+    keys: Vec<(String, usize)>,
+}
+
+impl QueuedKeysDecoder {
+    /// Extracts the format of the `Session.QueuedKeys` storage entry from the given metadata.
+    ///
+    /// The metadata is the output of the `Metadata_metadata` runtime call, after its length
+    /// prefix has been removed. Only versions 14 and 15 of the metadata format are supported.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, MetadataError> {
+        let metadata = metadata::decode(metadata).map_err(MetadataError::Decode)?;
+        let queued_keys_ty = metadata
+            .storage_entry_type("Session", "QueuedKeys")
+            .ok_or(MetadataError::QueuedKeysNotFound)?;
+        let types = &metadata.types;
+
+        // The storage entry is a list of tuples of a validator id and of its session keys.
+        let (validator_ty, keys_ty) = match types.get(queued_keys_ty) {
+            Some(TypeDef::Sequence(entry_ty)) => match types.get(*entry_ty) {
+                Some(TypeDef::Tuple(tys)) if tys.len() == 2 => (tys[0], tys[1]),
+                _ => return Err(MetadataError::UnsupportedQueuedKeysType),
+            },
+            _ => return Err(MetadataError::UnsupportedQueuedKeysType),
+        };
+
+        if types.fixed_size(validator_ty) != Some(32) {
+            return Err(MetadataError::UnsupportedQueuedKeysType);
+        }
+
+        let keys = match types.get(keys_ty) {
+            Some(TypeDef::Composite(fields)) => fields
+                .iter()
+                .map(|field| {
+                    let name = field
+                        .name
+                        .as_deref()
+                        .ok_or(MetadataError::UnsupportedQueuedKeysType)?;
+                    let size = types
+                        .fixed_size(field.ty)
+                        .ok_or(MetadataError::UnsupportedQueuedKeysType)?;
+                    Ok((name.to_owned(), size))
+                })
+                .collect::<Result<Vec<_>, _>>()?,
+            _ => return Err(MetadataError::UnsupportedQueuedKeysType),
+        };
+
+        Ok(QueuedKeysDecoder { keys })
+    }
+
+    /// Attempt to decode the value of the `Session.QueuedKeys` storage entry.
+    ///
+    /// If the storage entry doesn't exist, an empty list should be used instead.
+    pub fn decode(&self, scale_encoded: &[u8]) -> Result<Vec<QueuedKeys>, DecodeError> {
+        let (num_entries, mut rest) = decode_length(scale_encoded)?;
+
+        let entry_len = self
+            .keys
+            .iter()
+            .try_fold(32usize, |len, (_, size)| len.checked_add(*size))
+            .ok_or(DecodeError::InvalidLength)?;
+        if num_entries.checked_mul(entry_len) != Some(rest.len()) {
+            return Err(DecodeError::InvalidLength);
+        }
+
+        let mut entries = Vec::with_capacity(num_entries);
+        for _ in 0..num_entries {
+            let (validator, after) = rest.split_at(32);
+            rest = after;
+
+            let mut session_keys = Vec::with_capacity(self.keys.len());
+            for (key_type, size) in &self.keys {
+                let (public_key, after) = rest.split_at(*size);
+                rest = after;
+                session_keys.push(SessionKey {
+                    key_type: key_type.clone(),
+                    public_key: public_key.to_vec(),
+                });
+            }
+
+            entries.push(QueuedKeys {
+                validator: <[u8; 32]>::try_from(validator).unwrap(),
+                session_keys,
+            });
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Error potentially returned by [`QueuedKeysDecoder::from_metadata`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum MetadataError {
+    /// Failed to decode the metadata.
+    #[display(fmt = "{_0}")]
+    Decode(metadata::Error),
+    /// The metadata doesn't contain a `Session.QueuedKeys` storage entry.
+    QueuedKeysNotFound,
+    /// The type of the `Session.QueuedKeys` storage entry isn't a list of 32 bytes validator ids
+    /// and of session keys structs whose fields have a fixed size.
+    UnsupportedQueuedKeysType,
+}
+
+/// Potential error when decoding the `Session.Validators` or `Session.QueuedKeys` storage
+/// entries.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The number of items of the list couldn't be decoded.
+    #[display(fmt = "Invalid list length prefix")]
+    InvalidLengthPrefix,
+    /// The length of the storage value doesn't match the number of items of the list.
+    #[display(fmt = "Unexpected length of the storage value")]
+    InvalidLength,
+}
+
+fn decode_length(scale_encoded: &[u8]) -> Result<(usize, &[u8]), DecodeError> {
+    match crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(scale_encoded) {
+        Ok((rest, length)) => Ok((length, rest)),
+        Err(_) => Err(DecodeError::InvalidLengthPrefix),
+    }
+}
+
+fn storage_key(item_name: &[u8]) -> Vec<u8> {
+    let mut key = Vec::with_capacity(16 + 16);
+    key.extend_from_slice(&util::twox_128(b"Session"));
+    key.extend_from_slice(&util::twox_128(item_name));
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn validators_storage_key() {
+        assert_eq!(
+            hex::encode(super::validators_storage_key()),
+            "cec5070d609dd3497f72bde07fc96ba088dcde934c658227ee1dfafcd6e16903"
+        );
+    }
+
+    #[test]
+    fn decode_validators() {
+        let mut encoded = vec![8];
+        encoded.extend_from_slice(&[1; 32]);
+        encoded.extend_from_slice(&[2; 32]);
+
+        assert_eq!(
+            super::decode_validators(&encoded).unwrap(),
+            vec![[1; 32], [2; 32]]
+        );
+        assert!(super::decode_validators(&encoded[..encoded.len() - 1]).is_err());
+        assert!(super::decode_validators(&[]).is_err());
+    }
+
+    fn compact(out: &mut Vec<u8>, n: usize) {
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(n).as_ref());
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        compact(out, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    fn composite(out: &mut Vec<u8>, fields: &[(Option<&str>, usize)]) {
+        out.push(0);
+        compact(out, fields.len());
+        for (name, ty) in fields {
+            match name {
+                Some(name) => {
+                    out.push(1);
+                    string(out, name);
+                }
+                None => out.push(0),
+            }
+            compact(out, *ty);
+            out.push(0); // Type name.
+            out.push(0); // Documentation.
+        }
+    }
+
+    /// Builds a minimal metadata containing a `Session.QueuedKeys` storage entry whose session
+    /// keys struct is of the given type.
+    fn metadata(pallet_name: &str, session_keys: Vec<u8>) -> Vec<u8> {
+        let mut types = Vec::<Vec<u8>>::new();
+        // 0: u8
+        types.push(vec![5, 3]);
+        // 1: [u8; 32]
+        types.push(vec![3, 32, 0, 0, 0, 0]);
+        // 2: AccountId32
+        let mut ty = Vec::new();
+        composite(&mut ty, &[(None, 1)]);
+        types.push(ty);
+        // 3: SessionKeys
+        types.push(session_keys);
+        // 4: [u8; 33]
+        types.push(vec![3, 33, 0, 0, 0, 0]);
+        // 5: (AccountId32, SessionKeys)
+        types.push(vec![4, 2 << 2, 2 << 2, 3 << 2]);
+        // 6: Vec<(AccountId32, SessionKeys)>
+        types.push(vec![2, 5 << 2]);
+        // 7: Vec<u8>
+        types.push(vec![2, 0]);
+
+        let mut metadata = b"meta".to_vec();
+        metadata.push(14);
+        compact(&mut metadata, types.len());
+        for (id, ty) in types.iter().enumerate() {
+            compact(&mut metadata, id);
+            metadata.push(0); // Path.
+            metadata.push(0); // Type parameters.
+            metadata.extend_from_slice(ty);
+            metadata.push(0); // Documentation.
+        }
+
+        compact(&mut metadata, 1);
+        string(&mut metadata, pallet_name);
+        metadata.push(1); // Storage.
+        string(&mut metadata, pallet_name);
+        compact(&mut metadata, 1);
+        string(&mut metadata, "QueuedKeys");
+        metadata.push(1); // Modifier.
+        metadata.extend_from_slice(&[0, 6 << 2]); // Plain type.
+        metadata.extend_from_slice(&[1 << 2, 0]); // Default value.
+        metadata.push(0); // Documentation.
+        metadata.push(0); // Calls.
+        metadata.push(0); // Events.
+        metadata.push(0); // Constants.
+        metadata.push(0); // Errors.
+        metadata.push(0); // Index.
+        metadata
+    }
+
+    #[test]
+    fn decode_queued_keys() {
+        let mut session_keys = Vec::new();
+        composite(
+            &mut session_keys,
+            &[(Some("grandpa"), 1), (Some("beefy"), 4)],
+        );
+        let decoder =
+            super::QueuedKeysDecoder::from_metadata(&metadata("Session", session_keys)).unwrap();
+
+        let mut encoded = vec![8];
+        for n in 1..=2 {
+            encoded.extend_from_slice(&[n; 32]);
+            encoded.extend_from_slice(&[n + 10; 32]);
+            encoded.extend_from_slice(&[n + 20; 33]);
+        }
+
+        let decoded = decoder.decode(&encoded).unwrap();
+        assert_eq!(decoded.len(), 2);
+        assert_eq!(decoded[1].validator, [2; 32]);
+        assert_eq!(
+            decoded[1].session_keys,
+            vec![
+                super::SessionKey {
+                    key_type: "grandpa".into(),
+                    public_key: vec![12; 32]
+                },
+                super::SessionKey {
+                    key_type: "beefy".into(),
+                    public_key: vec![22; 33]
+                },
+            ]
+        );
+
+        assert!(decoder.decode(&[0]).unwrap().is_empty());
+        assert!(decoder.decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decoder.decode(&[0, 0]).is_err());
+    }
+
+    #[test]
+    fn queued_keys_not_found() {
+        let mut session_keys = Vec::new();
+        composite(&mut session_keys, &[(Some("grandpa"), 1)]);
+        assert!(matches!(
+            super::QueuedKeysDecoder::from_metadata(&metadata("Other", session_keys)),
+            Err(super::MetadataError::QueuedKeysNotFound)
+        ));
+    }
+
+    #[test]
+    fn unsupported_session_keys() {
+        // Public keys whose size isn't fixed.
+        let mut session_keys = Vec::new();
+        composite(
+            &mut session_keys,
+            &[(Some("grandpa"), 1), (Some("other"), 7)],
+        );
+        assert!(matches!(
+            super::QueuedKeysDecoder::from_metadata(&metadata("Session", session_keys)),
+            Err(super::MetadataError::UnsupportedQueuedKeysType)
+        ));
+
+        // Unnamed fields.
+        let mut session_keys = Vec::new();
+        composite(&mut session_keys, &[(None, 1)]);
+        assert!(matches!(
+            super::QueuedKeysDecoder::from_metadata(&metadata("Session", session_keys)),
+            Err(super::MetadataError::UnsupportedQueuedKeysType)
+        ));
+    }
+}
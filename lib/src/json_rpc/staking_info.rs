@@ -0,0 +1,314 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Access to the storage entries of the `Staking` pallet related to eras: the active era, the
+//! reward points earned by validators during an era, and the nominators backing each validator
+//! during an era.
+//!
+//! The exposure of a validator (i.e. the list of nominators backing it) is split in two kinds of
+//! storage entries: `Staking.ErasStakersOverview` contains a summary of the exposure, including
+//! the number of pages, and `Staking.ErasStakersPaged` contains each page of nominators. The
+//! legacy non-paged `Staking.ErasStakers` storage entry isn't supported.
+//!
+//! Similar to the other modules of this crate that access storage entries, the layout used by
+//! the Substrate and Polkadot runtimes is assumed: accounts are identified by a 32 bytes account
+//! id, and balances are 128 bits integers.
+
+use crate::util;
+
+use alloc::vec::Vec;
+
+/// Returns the key of the `Staking.ActiveEra` storage entry.
+pub fn active_era_storage_key() -> Vec<u8> {
+    storage_key(b"ActiveEra", &[])
+}
+
+/// Returns the key of the `Staking.ErasRewardPoints` storage entry of the given era.
+pub fn eras_reward_points_storage_key(era: u32) -> Vec<u8> {
+    storage_key(b"ErasRewardPoints", &[&era.to_le_bytes()])
+}
+
+/// Returns the key of the `Staking.ErasStakersOverview` storage entry of the given era and
+/// validator.
+pub fn eras_stakers_overview_storage_key(era: u32, validator: &[u8; 32]) -> Vec<u8> {
+    storage_key(b"ErasStakersOverview", &[&era.to_le_bytes(), validator])
+}
+
+/// Returns the key of the `Staking.ErasStakersPaged` storage entry of the given era, validator,
+/// and page.
+pub fn eras_stakers_paged_storage_key(era: u32, validator: &[u8; 32], page: u32) -> Vec<u8> {
+    storage_key(
+        b"ErasStakersPaged",
+        &[&era.to_le_bytes(), validator, &page.to_le_bytes()],
+    )
+}
+
+/// Decoded value of the `Staking.ActiveEra` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActiveEra {
+    /// Index of the era.
+    pub index: u32,
+    /// UNIX timestamp, in milliseconds, of the start of the era. `None` if the era has been
+    /// started in the current block and the timestamp isn't known yet.
+    pub start_timestamp_ms: Option<u64>,
+}
+
+/// Attempt to decode the value of the `Staking.ActiveEra` storage entry.
+pub fn decode_active_era(scale_encoded: &[u8]) -> Result<ActiveEra, DecodeError> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u32,
+            nom::branch::alt((
+                nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| None),
+                nom::combinator::map(
+                    nom::sequence::preceded(
+                        nom::bytes::complete::tag(&[1]),
+                        nom::number::complete::le_u64,
+                    ),
+                    Some,
+                ),
+            )),
+        )),
+        |(index, start_timestamp_ms)| ActiveEra {
+            index,
+            start_timestamp_ms,
+        },
+    ))(scale_encoded);
+
+    result.map(|(_, v)| v).map_err(|_| DecodeError)
+}
+
+/// Decoded value of a `Staking.ErasRewardPoints` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EraRewardPoints {
+    /// Sum of all the reward points of the era.
+    pub total: u32,
+    /// Validators and the reward points they have earned during the era.
+    pub individual: Vec<([u8; 32], u32)>,
+}
+
+/// Attempt to decode the value of a `Staking.ErasRewardPoints` storage entry.
+///
+/// If the storage entry doesn't exist, [`EraRewardPoints`] with a total of 0 and an empty list
+/// should be used instead.
+pub fn decode_era_reward_points(scale_encoded: &[u8]) -> Result<EraRewardPoints, DecodeError> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u32,
+            nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+                nom::multi::many_m_n(
+                    num_elems,
+                    num_elems,
+                    nom::sequence::tuple((account_id, nom::number::complete::le_u32)),
+                )
+            }),
+        )),
+        |(total, individual)| EraRewardPoints { total, individual },
+    ))(scale_encoded);
+
+    result.map(|(_, v)| v).map_err(|_| DecodeError)
+}
+
+/// Decoded value of a `Staking.ErasStakersOverview` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposureOverview {
+    /// Total amount of tokens backing the validator, including its own.
+    pub total: u128,
+    /// Amount of tokens the validator has bonded itself.
+    pub own: u128,
+    /// Number of nominators backing the validator.
+    pub nominator_count: u32,
+    /// Number of `Staking.ErasStakersPaged` entries the nominators are split into.
+    pub page_count: u32,
+}
+
+/// Attempt to decode the value of a `Staking.ErasStakersOverview` storage entry.
+///
+/// If the storage entry doesn't exist, the validator wasn't elected during the era.
+pub fn decode_exposure_overview(scale_encoded: &[u8]) -> Result<ExposureOverview, DecodeError> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_scale_compact_u128,
+            crate::util::nom_scale_compact_u128,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+        )),
+        |(total, own, nominator_count, page_count)| ExposureOverview {
+            total,
+            own,
+            nominator_count,
+            page_count,
+        },
+    ))(scale_encoded);
+
+    result.map(|(_, v)| v).map_err(|_| DecodeError)
+}
+
+/// Decoded value of a `Staking.ErasStakersPaged` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExposurePage {
+    /// Sum of the tokens of all the nominators of this page.
+    pub page_total: u128,
+    /// Nominators of this page and the amount of tokens they back the validator with.
+    pub others: Vec<IndividualExposure>,
+}
+
+/// Nominator backing a validator, as found in [`ExposurePage`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IndividualExposure {
+    /// Account id of the nominator.
+    pub who: [u8; 32],
+    /// Amount of tokens backing the validator.
+    pub value: u128,
+}
+
+/// Attempt to decode the value of a `Staking.ErasStakersPaged` storage entry.
+pub fn decode_exposure_page(scale_encoded: &[u8]) -> Result<ExposurePage, DecodeError> {
+    let result: nom::IResult<_, _> = nom::combinator::all_consuming(nom::combinator::map(
+        nom::sequence::tuple((
+            crate::util::nom_scale_compact_u128,
+            nom::combinator::flat_map(crate::util::nom_scale_compact_usize, |num_elems| {
+                nom::multi::many_m_n(
+                    num_elems,
+                    num_elems,
+                    nom::combinator::map(
+                        nom::sequence::tuple((account_id, crate::util::nom_scale_compact_u128)),
+                        |(who, value)| IndividualExposure { who, value },
+                    ),
+                )
+            }),
+        )),
+        |(page_total, others)| ExposurePage { page_total, others },
+    ))(scale_encoded);
+
+    result.map(|(_, v)| v).map_err(|_| DecodeError)
+}
+
+/// Potential error when decoding a storage entry of the `Staking` pallet.
+#[derive(Debug, derive_more::Display, Clone)]
+#[display(fmt = "Failed to decode staking storage value")]
+pub struct DecodeError;
+
+fn account_id(bytes: &[u8]) -> nom::IResult<&[u8], [u8; 32]> {
+    nom::combinator::map(nom::bytes::complete::take(32u32), |b: &[u8]| {
+        <[u8; 32]>::try_from(b).unwrap()
+    })(bytes)
+}
+
+/// Builds the key of a storage entry of the `Staking` pallet whose keys are all hashed using
+/// `Twox64Concat`.
+fn storage_key(item_name: &[u8], keys: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(16 + 16 + keys.iter().map(|k| 8 + k.len()).sum::<usize>());
+    out.extend_from_slice(&util::twox_128(b"Staking"));
+    out.extend_from_slice(&util::twox_128(item_name));
+    for key in keys {
+        out.extend_from_slice(&util::twox_64(key));
+        out.extend_from_slice(key);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn active_era_storage_key() {
+        assert_eq!(
+            hex::encode(super::active_era_storage_key()),
+            "5f3e4907f716ac89b6347d15ececedca487df464e44a534ba6b0cbb32407b587"
+        );
+    }
+
+    #[test]
+    fn eras_stakers_paged_storage_key() {
+        let key = super::eras_stakers_paged_storage_key(5, &[7; 32], 2);
+        let overview_key = super::eras_stakers_overview_storage_key(5, &[7; 32]);
+        assert_eq!(key.len(), 32 + (8 + 4) + (8 + 32) + (8 + 4));
+        assert_eq!(overview_key.len(), 32 + (8 + 4) + (8 + 32));
+        assert_eq!(&key[40..44], &[5, 0, 0, 0]);
+        assert_eq!(&key[52..84], &[7; 32]);
+        assert_eq!(&key[92..], &[2, 0, 0, 0]);
+        assert_eq!(&key[32..84], &overview_key[32..]);
+    }
+
+    #[test]
+    fn decode_active_era() {
+        assert_eq!(
+            super::decode_active_era(&[3, 1, 0, 0, 1, 0x10, 0x27, 0, 0, 0, 0, 0, 0]).unwrap(),
+            super::ActiveEra {
+                index: 259,
+                start_timestamp_ms: Some(10000),
+            }
+        );
+        assert_eq!(
+            super::decode_active_era(&[3, 0, 0, 0, 0]).unwrap(),
+            super::ActiveEra {
+                index: 3,
+                start_timestamp_ms: None,
+            }
+        );
+        assert!(super::decode_active_era(&[3, 0, 0, 0, 0, 0]).is_err());
+    }
+
+    #[test]
+    fn decode_era_reward_points() {
+        let mut encoded = vec![60, 0, 0, 0, 8];
+        encoded.extend_from_slice(&[1; 32]);
+        encoded.extend_from_slice(&[20, 0, 0, 0]);
+        encoded.extend_from_slice(&[2; 32]);
+        encoded.extend_from_slice(&[40, 0, 0, 0]);
+
+        assert_eq!(
+            super::decode_era_reward_points(&encoded).unwrap(),
+            super::EraRewardPoints {
+                total: 60,
+                individual: vec![([1; 32], 20), ([2; 32], 40)],
+            }
+        );
+        assert!(super::decode_era_reward_points(&encoded[..encoded.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn decode_exposure() {
+        // Compact encodings of 1000 (two bytes mode) and 10 (single byte mode).
+        let overview = [0xa1, 0x0f, 0x28, 3, 0, 0, 0, 1, 0, 0, 0];
+        assert_eq!(
+            super::decode_exposure_overview(&overview).unwrap(),
+            super::ExposureOverview {
+                total: 1000,
+                own: 10,
+                nominator_count: 3,
+                page_count: 1,
+            }
+        );
+
+        let mut page = vec![0xa1, 0x0f, 4];
+        page.extend_from_slice(&[9; 32]);
+        page.push(0xa1);
+        page.push(0x0f);
+        assert_eq!(
+            super::decode_exposure_page(&page).unwrap(),
+            super::ExposurePage {
+                page_total: 1000,
+                others: vec![super::IndividualExposure {
+                    who: [9; 32],
+                    value: 1000,
+                }],
+            }
+        );
+        assert!(super::decode_exposure_page(&page[..page.len() - 1]).is_err());
+    }
+}
@@ -202,15 +202,17 @@ pub mod chain_spec;
 pub mod database;
 pub mod executor;
 pub mod finality;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod fuzzing;
 pub mod header;
 pub mod identity;
 pub mod informant;
 pub mod json_rpc;
 pub mod libp2p;
+pub mod metadata;
 pub mod network;
 pub mod sync;
 pub mod transactions;
 pub mod trie;
+pub mod util;
 pub mod verify;
-
-mod util;
@@ -68,7 +68,7 @@ pub use super::peer_id::PeerId;
 pub use super::read_write::ReadWrite;
 pub use established::{
     ConfigNotifications, ConfigRequestResponse, ConfigRequestResponseIn, InboundError,
-    SubstreamFate,
+    SubstreamFate, SubstreamPriority,
 };
 pub use single_stream_handshake::HandshakeError;
 
@@ -144,6 +144,14 @@ pub struct NotificationProtocolConfig {
 
     /// Maximum size, in bytes, of a notification that can be received.
     pub max_notification_size: usize,
+
+    /// Maximum number of bytes of outgoing notifications that can be queued on each substream.
+    /// Notifications sent while this limit is reached are silently discarded.
+    pub max_queued_bytes: usize,
+
+    /// Priority of the substreams of this protocol when sending out data.
+    /// See [`ConfigNotifications::priority`].
+    pub priority: SubstreamPriority,
 }
 
 /// Identifier of a connection spawned by the [`Network`].
@@ -779,7 +787,9 @@ where
     /// Each substream maintains a queue of notifications to be sent to the remote. This method
     /// attempts to push a notification to this queue.
     ///
-    /// An error is also returned if the queue exceeds a certain size in bytes, for two reasons:
+    /// The size of this queue, in bytes, is bounded by
+    /// [`NotificationProtocolConfig::max_queued_bytes`]. Notifications that don't fit in
+    /// the queue are silently discarded, for two reasons:
     ///
     /// - Since the content of the queue is transferred at a limited rate, each notification
     /// pushed at the end of the queue will take more time than the previous one to reach the
@@ -793,6 +803,11 @@ where
     /// be designed in such a way that discarding notifications shouldn't have a too negative
     /// impact.
     ///
+    /// If `coalescable` is `true`, the notifications previously queued with `coalescable` set to
+    /// `true` and that haven't started being sent yet are discarded, as they are considered
+    /// superseded by the new notification. This is appropriate for example for block
+    /// announcements of a new best block.
+    ///
     /// Regardless of the success of this function, no guarantee exists about the successful
     /// delivery of notifications.
     ///
@@ -808,6 +823,7 @@ where
         &mut self,
         substream_id: SubstreamId,
         notification: impl Into<Vec<u8>>,
+        coalescable: bool,
     ) -> Result<(), QueueNotificationError> {
         let (connection_id, state) = match self.outgoing_notification_substreams.get(&substream_id)
         {
@@ -816,13 +832,15 @@ where
         };
         assert!(matches!(state, SubstreamState::Open));
 
-        //  TODO: add some back-pressure system and return a `QueueNotificationError` if full
-
+        // The limit to the size of the queue is enforced by the connection task, as it is the
+        // only one to know how much data is still waiting to be sent out.
+        // TODO: report back to the coordinator whether the notification was discarded
         self.messages_to_connections.push_back((
             *connection_id,
             CoordinatorToConnectionInner::QueueNotification {
                 substream_id,
                 notification: notification.into(),
+                coalescable,
             },
         ));
 
@@ -1692,6 +1710,7 @@ enum CoordinatorToConnectionInner<TNow> {
         /// This is **not** the same as the actual substream used in the connection.
         substream_id: SubstreamId,
         notification: Vec<u8>,
+        coalescable: bool,
     },
     AcceptInNotifications {
         substream_id: established::SubstreamId,
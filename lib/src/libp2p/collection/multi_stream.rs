@@ -164,6 +164,8 @@ where
                             name: net.config.protocol_name.clone(), // TODO: clone :-/
                             max_handshake_size: net.config.max_handshake_size,
                             max_notification_size: net.config.max_notification_size,
+                            max_queued_bytes: net.config.max_queued_bytes,
+                            priority: net.config.priority,
                         })
                         .collect(),
                     request_protocols: request_response_protocols.to_vec(), // TODO: overhead
@@ -437,6 +439,7 @@ where
                 CoordinatorToConnectionInner::QueueNotification {
                     substream_id,
                     notification,
+                    coalescable,
                 },
                 MultiStreamConnectionTaskInner::Established {
                     established,
@@ -452,7 +455,11 @@ where
                 // notification to not be sent. This is consistent with the guarantees about
                 // notifications delivered that are documented in the public API.
                 if let Some(inner_substream_id) = outbound_substreams_map.get(&substream_id) {
-                    established.write_notification_unbounded(*inner_substream_id, notification);
+                    let _ = established.write_notification(
+                        *inner_substream_id,
+                        notification,
+                        coalescable,
+                    );
                 }
             }
             (
@@ -79,6 +79,7 @@
 
 pub use noise::{NoiseKey, UnsignedNoiseKey};
 
+pub mod buffer_pool;
 pub mod established;
 pub mod multistream_select;
 pub mod noise;
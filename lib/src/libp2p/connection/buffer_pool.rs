@@ -0,0 +1,157 @@
+// Smoldot
+// Copyright (C) 2023  Pierre Krieger
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Global pool of buffers used by connections.
+//!
+//! Each connection needs a few buffers of a fixed size, for example in order to hold the data
+//! received from the socket before it is decrypted. Allocating these buffers every time a
+//! connection is opened and freeing them every time a connection is closed puts pressure on the
+//! memory allocator and, on platforms with a small heap such as 32 bits WebAssembly, leads to
+//! fragmentation of the heap.
+//!
+//! Instead, a [`Buffer`] is returned to a global pool when it is destroyed, and can then be
+//! reused by the next call to [`Buffer::with_capacity`] or [`Buffer::zeroed`].
+//!
+//! Only buffers whose capacity corresponds to one of the sizes used by the networking code are
+//! pooled. The capacity requested when building a [`Buffer`] is rounded up to the smallest of
+//! these sizes. Buffers larger than all these sizes are allocated and freed normally. The total
+//! size of the unused buffers kept in the pool is bounded.
+//!
+//! The content of a buffer is always cleared before it is put back in the pool. Data destined
+//! to a connection can thus never accidentally be read by another connection.
+
+use alloc::vec::Vec;
+use core::{fmt, ops};
+
+/// Capacities of the buffers that are pooled, in increasing order.
+const SIZE_CLASSES: [usize; 4] = [2048, 4096, 16384, 65536 + 2];
+
+/// Maximum number of bytes of unused buffers kept in each pool.
+const MAX_POOLED_BYTES_PER_CLASS: usize = 1024 * 1024;
+
+/// Unused buffers, one queue for each entry in [`SIZE_CLASSES`]. All the buffers in each queue
+/// are empty and have a capacity equal to the corresponding entry in [`SIZE_CLASSES`].
+static POOLS: [crossbeam_queue::SegQueue<Vec<u8>>; SIZE_CLASSES.len()] = [
+    crossbeam_queue::SegQueue::new(),
+    crossbeam_queue::SegQueue::new(),
+    crossbeam_queue::SegQueue::new(),
+    crossbeam_queue::SegQueue::new(),
+];
+
+/// Buffer of bytes that returns to the global pool when destroyed.
+///
+/// Dereferences to a `Vec<u8>`. The capacity of this `Vec` shouldn't be modified, as the buffer
+/// would otherwise not be returned to the pool.
+pub struct Buffer {
+    inner: Vec<u8>,
+    /// Index within [`SIZE_CLASSES`] of the pool this buffer belongs to, or `None` if it isn't
+    /// pooled.
+    size_class: Option<usize>,
+}
+
+impl Buffer {
+    /// Returns an empty buffer whose capacity is at least `capacity`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        let size_class = SIZE_CLASSES.iter().position(|size| *size >= capacity);
+
+        let inner = match size_class {
+            Some(size_class) => POOLS[size_class]
+                .pop()
+                .unwrap_or_else(|| Vec::with_capacity(SIZE_CLASSES[size_class])),
+            None => Vec::with_capacity(capacity),
+        };
+
+        debug_assert!(inner.is_empty());
+        Buffer { inner, size_class }
+    }
+
+    /// Returns a buffer whose length is `len` and filled with zeroes.
+    pub fn zeroed(len: usize) -> Self {
+        let mut buffer = Self::with_capacity(len);
+        buffer.inner.resize(len, 0);
+        buffer
+    }
+}
+
+impl ops::Deref for Buffer {
+    type Target = Vec<u8>;
+
+    fn deref(&self) -> &Vec<u8> {
+        &self.inner
+    }
+}
+
+impl ops::DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut Vec<u8> {
+        &mut self.inner
+    }
+}
+
+impl fmt::Debug for Buffer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&self.inner, f)
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        let Some(size_class) = self.size_class else {
+            return;
+        };
+
+        // The capacity might have been modified by the user, in which case the buffer is simply
+        // freed.
+        if self.inner.capacity() != SIZE_CLASSES[size_class] {
+            return;
+        }
+
+        // Note that the length of the queue might be modified by another thread between the
+        // moment it is checked and the moment the buffer is pushed. This is not a problem, as
+        // the limit is only approximate.
+        if POOLS[size_class].len() >= MAX_POOLED_BYTES_PER_CLASS / SIZE_CLASSES[size_class] {
+            return;
+        }
+
+        let mut inner = core::mem::take(&mut self.inner);
+        inner.clear();
+        POOLS[size_class].push(inner);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn capacity_rounded_up() {
+        let buffer = super::Buffer::with_capacity(3000);
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.capacity(), 4096);
+
+        let buffer = super::Buffer::with_capacity(1024 * 1024);
+        assert!(buffer.capacity() >= 1024 * 1024);
+    }
+
+    #[test]
+    fn reused_buffers_are_zeroed() {
+        for _ in 0..8 {
+            let mut buffer = super::Buffer::zeroed(2000);
+            assert_eq!(buffer.len(), 2000);
+            assert!(buffer.iter().all(|b| *b == 0));
+            buffer.iter_mut().for_each(|b| *b = 0xff);
+            buffer.extend_from_slice(&[0xff; 48]);
+        }
+    }
+}
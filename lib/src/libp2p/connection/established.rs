@@ -30,6 +30,7 @@ pub use substream::{
     InboundError, NotificationsInClosedErr, NotificationsOutErr, RequestError,
     RespondInRequestError,
 };
+pub use yamux::SubstreamPriority;
 
 /// Identifier of a request or a notifications substream.
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
@@ -228,6 +229,13 @@ pub struct ConfigRequestResponse {
 
     /// If true, incoming substreams are allowed to negotiate this protocol.
     pub inbound_allowed: bool,
+
+    /// Priority of the substreams of this protocol, compared to the other substreams of the
+    /// same connection, when sending out data.
+    ///
+    /// > **Note**: Only taken into account for single-stream connections, as the multiplexing
+    /// >           of multi-stream connections isn't performed by smoldot.
+    pub priority: SubstreamPriority,
 }
 
 /// See [`ConfigRequestResponse::inbound_config`].
@@ -265,6 +273,17 @@ pub struct ConfigNotifications {
 
     /// Maximum size, in bytes, of a notification that can be received.
     pub max_notification_size: usize,
+
+    /// Maximum number of bytes of outgoing notifications that can be queued on a substream.
+    /// Notifications sent while this limit is reached are silently discarded.
+    pub max_queued_bytes: usize,
+
+    /// Priority of the substreams of this protocol, compared to the other substreams of the
+    /// same connection, when sending out data.
+    ///
+    /// > **Note**: Only taken into account for single-stream connections, as the multiplexing
+    /// >           of multi-stream connections isn't performed by smoldot.
+    pub priority: SubstreamPriority,
 }
 
 /// Error potentially returned when starting a request.
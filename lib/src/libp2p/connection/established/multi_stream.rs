@@ -802,6 +802,7 @@ where
                 self.notifications_protocols[protocol_index].name.clone(), // TODO: clone :-/,
                 handshake,
                 max_handshake_size,
+                self.notifications_protocols[protocol_index].max_queued_bytes,
                 user_data,
             )),
             read_buffer: Vec::new(),
@@ -873,24 +874,27 @@ where
     ///
     /// # About back-pressure
     ///
-    /// This method unconditionally queues up data. You must be aware that the remote, however,
-    /// can decide to delay indefinitely the sending of that data, which can potentially lead to
-    /// an unbounded increase in memory.
+    /// The remote can decide to delay indefinitely the sending of the queued data. In order to
+    /// avoid an unbounded increase in memory, the notification is silently discarded and `false`
+    /// is returned if the amount of queued data (as determined by calling
+    /// [`MultiStream::notification_substream_queued_bytes`]) would exceed the
+    /// [`ConfigNotifications::max_queued_bytes`] of the protocol.
     ///
-    /// As such, you are encouraged to call this method only if the amount of queued data (as
-    /// determined by calling [`MultiStream::notification_substream_queued_bytes`]) is below a
-    /// certain threshold. If above, the notification should be silently discarded.
+    /// If `coalescable` is `true`, the notifications previously queued with `coalescable` set to
+    /// `true` and that haven't started being sent out yet are discarded, as they are considered
+    /// superseded by the new notification.
     ///
     /// # Panic
     ///
     /// Panics if the [`SubstreamId`] doesn't correspond to a notifications substream, or if the
     /// notifications substream isn't in the appropriate state.
     ///
-    pub fn write_notification_unbounded(
+    pub fn write_notification(
         &mut self,
         substream_id: SubstreamId,
         notification: Vec<u8>,
-    ) {
+        coalescable: bool,
+    ) -> bool {
         let substream_id = match substream_id.0 {
             SubstreamIdInner::MultiStream(id) => id,
             _ => panic!(),
@@ -904,12 +908,12 @@ where
             .inner
             .as_mut()
             .unwrap()
-            .write_notification_unbounded(notification);
+            .write_notification(notification, coalescable)
     }
 
     /// Returns the number of bytes waiting to be sent out on that substream.
     ///
-    /// See the documentation of [`MultiStream::write_notification_unbounded`] for context.
+    /// See the documentation of [`MultiStream::write_notification`] for context.
     ///
     /// # Panic
     ///
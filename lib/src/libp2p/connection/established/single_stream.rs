@@ -38,10 +38,10 @@
 //! Request-response protocols enforce a limit to the size of the request and response, again
 //! guaranteeing a bound on the memory consumption.
 //!
-//! In order to solve 3-, always use [`SingleStream::notification_substream_queued_bytes`] in order
-//! to check the current amount of buffered data before calling
-//! [`SingleStream::write_notification_unbounded`]. See the documentation of
-//! [`SingleStream::write_notification_unbounded`] for more details.
+//! In order to solve 3-, each notifications protocol has a maximum amount of buffered data
+//! (see [`ConfigNotifications::max_queued_bytes`]) above which [`SingleStream::write_notification`]
+//! discards notifications. See the documentation of [`SingleStream::write_notification`] for more
+//! details.
 //!
 //! In order to solve 5-, // TODO: .
 //!
@@ -51,13 +51,13 @@
 // TODO: consider implementing on top of multi_stream
 
 use super::{
-    super::{super::read_write::ReadWrite, noise, yamux},
+    super::{super::read_write::ReadWrite, buffer_pool, noise, yamux},
     substream::{self, RespondInRequestError},
     AddRequestError, Config, ConfigNotifications, ConfigRequestResponse, ConfigRequestResponseIn,
     Event, SubstreamId, SubstreamIdInner,
 };
 
-use alloc::{boxed::Box, string::String, vec, vec::Vec};
+use alloc::{string::String, vec::Vec};
 use core::{
     fmt,
     num::{NonZeroU32, NonZeroUsize},
@@ -133,9 +133,10 @@ struct Inner<TNow, TRqUd, TNotifUd> {
     /// While in theory this intermediary buffer could be shared between multiple different
     /// connections, since data present in this buffer isn't always zero-ed, it could be possible
     /// for a bug to cause data destined for connection A to be sent to connection B. Sharing this
-    /// buffer is too dangerous.
+    /// buffer is too dangerous. It is, however, obtained from the [`buffer_pool`], which clears
+    /// buffers before reusing them.
     // TODO: remove; needs a lot of refactoring of noise and yamux
-    intermediary_buffer: Box<[u8]>,
+    intermediary_buffer: buffer_pool::Buffer,
 }
 
 impl<TNow, TRqUd, TNotifUd> SingleStream<TNow, TRqUd, TNotifUd>
@@ -585,7 +586,7 @@ where
                     Some(&in_data[total_read..])
                 },
                 outgoing_buffer: if !write_is_closed {
-                    Some((&mut inner.intermediary_buffer, &mut []))
+                    Some((&mut inner.intermediary_buffer[..], &mut []))
                 } else {
                     None
                 },
@@ -635,6 +636,9 @@ where
 
                     if protocol == inner.ping_protocol {
                         substream.accept_inbound(substream::InboundTy::Ping);
+                        inner
+                            .yamux
+                            .set_priority(substream_id, yamux::SubstreamPriority::High);
                     } else if let Some(protocol_index) = inner
                         .request_protocols
                         .iter()
@@ -650,6 +654,10 @@ where
                                 None
                             },
                         });
+                        inner.yamux.set_priority(
+                            substream_id,
+                            inner.request_protocols[protocol_index].priority,
+                        );
                     } else if let Some(protocol_index) = inner
                         .notifications_protocols
                         .iter()
@@ -660,6 +668,10 @@ where
                             max_handshake_size: inner.notifications_protocols[protocol_index]
                                 .max_handshake_size,
                         });
+                        inner.yamux.set_priority(
+                            substream_id,
+                            inner.notifications_protocols[protocol_index].priority,
+                        );
                     } else {
                         substream.reject_inbound();
                     }
@@ -822,6 +834,10 @@ where
                 user_data,
             )))
             .unwrap(); // TODO: consider not panicking
+        self.inner.yamux.set_priority(
+            substream_id,
+            self.inner.request_protocols[protocol_index].priority,
+        );
 
         // TODO: we add some bytes due to the length prefix, this is a bit hacky as we should ask this information from the substream
         self.inner.yamux.add_remote_window_saturating(
@@ -900,9 +916,14 @@ where
                     .clone(), // TODO: clone :-/,
                 handshake,
                 max_handshake_size,
+                self.inner.notifications_protocols[protocol_index].max_queued_bytes,
                 user_data,
             )))
             .unwrap(); // TODO: consider not panicking
+        self.inner.yamux.set_priority(
+            substream,
+            self.inner.notifications_protocols[protocol_index].priority,
+        );
 
         SubstreamId(SubstreamIdInner::SingleStream(substream))
     }
@@ -960,24 +981,27 @@ where
     ///
     /// # About back-pressure
     ///
-    /// This method unconditionally queues up data. You must be aware that the remote, however,
-    /// can decide to delay indefinitely the sending of that data, which can potentially lead to
-    /// an unbounded increase in memory.
+    /// The remote can decide to delay indefinitely the sending of the queued data. In order to
+    /// avoid an unbounded increase in memory, the notification is silently discarded and `false`
+    /// is returned if the amount of queued data (as determined by calling
+    /// [`SingleStream::notification_substream_queued_bytes`]) would exceed the
+    /// [`ConfigNotifications::max_queued_bytes`] of the protocol.
     ///
-    /// As such, you are encouraged to call this method only if the amount of queued data (as
-    /// determined by calling [`SingleStream::notification_substream_queued_bytes`]) is below a
-    /// certain threshold. If above, the notification should be silently discarded.
+    /// If `coalescable` is `true`, the notifications previously queued with `coalescable` set to
+    /// `true` and that haven't started being sent out yet are discarded, as they are considered
+    /// superseded by the new notification.
     ///
     /// # Panic
     ///
     /// Panics if the [`SubstreamId`] doesn't correspond to a notifications substream, or if the
     /// notifications substream isn't in the appropriate state.
     ///
-    pub fn write_notification_unbounded(
+    pub fn write_notification(
         &mut self,
         substream_id: SubstreamId,
         notification: Vec<u8>,
-    ) {
+        coalescable: bool,
+    ) -> bool {
         let substream_id = match substream_id.0 {
             SubstreamIdInner::SingleStream(id) => id,
             _ => panic!(),
@@ -988,12 +1012,12 @@ where
             .user_data_mut(substream_id)
             .as_mut()
             .unwrap()
-            .write_notification_unbounded(notification);
+            .write_notification(notification, coalescable)
     }
 
     /// Returns the number of bytes waiting to be sent out on that substream.
     ///
-    /// See the documentation of [`SingleStream::write_notification_unbounded`] for context.
+    /// See the documentation of [`SingleStream::write_notification`] for context.
     ///
     /// # Panic
     ///
@@ -1142,6 +1166,8 @@ impl ConnectionPrototype {
             // Can only panic if a `GoAway` has been received, or if there are too many substreams
             // already open, which we know for sure can't happen here
             .unwrap_or_else(|_| panic!());
+        // Pings are small and their timing matters.
+        yamux.set_priority(outgoing_pings, yamux::SubstreamPriority::High);
 
         SingleStream {
             encryption: self.encryption,
@@ -1157,7 +1183,7 @@ impl ConnectionPrototype {
                 ping_protocol: config.ping_protocol,
                 ping_interval: config.ping_interval,
                 ping_timeout: config.ping_timeout,
-                intermediary_buffer: vec![0u8; 2048].into_boxed_slice(),
+                intermediary_buffer: buffer_pool::Buffer::zeroed(2048),
             },
         }
     }
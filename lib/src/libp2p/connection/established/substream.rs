@@ -66,14 +66,20 @@ enum SubstreamInner<TNow, TRqUd, TNotifUd> {
         handshake_in: leb128::FramedInProgress,
         /// Handshake payload to write out.
         handshake_out: VecDeque<u8>,
+        /// Value passed to [`Substream::notifications_out`].
+        max_queued_bytes: usize,
         /// Data passed by the user to [`Substream::notifications_out`].
         user_data: TNotifUd,
     },
     /// A notifications protocol has been negotiated, and the remote accepted it. Can now send
     /// notifications.
     NotificationsOut {
-        /// Notifications to write out.
+        /// Bytes of the notification that is currently being written out.
         notifications: VecDeque<u8>,
+        /// Notifications that have been queued but haven't started being written out yet.
+        queued_notifications: VecDeque<QueuedNotification>,
+        /// Value passed to [`Substream::notifications_out`].
+        max_queued_bytes: usize,
         /// Data passed by the user to [`Substream::notifications_out`].
         user_data: TNotifUd,
         /// If `true`, we have reported a [`Event::NotificationsOutCloseDemanded`] event in the
@@ -183,6 +189,14 @@ enum SubstreamInner<TNow, TRqUd, TNotifUd> {
     },
 }
 
+/// Notification queued in a [`SubstreamInner::NotificationsOut`] substream.
+struct QueuedNotification {
+    /// Notification, including its LEB128 length prefix.
+    framed: Vec<u8>,
+    /// Value passed to [`Substream::write_notification`].
+    coalescable: bool,
+}
+
 impl<TNow, TRqUd, TNotifUd> Substream<TNow, TRqUd, TNotifUd>
 where
     TNow: Clone + Ord,
@@ -227,16 +241,20 @@ where
     /// After the remote has sent back a handshake or after an error occurred, an
     /// [`Event::NotificationsOutResult`] event will be generated locally.
     ///
-    /// If this event contains an `Ok`, then [`Substream::write_notification_unbounded`],
+    /// If this event contains an `Ok`, then [`Substream::write_notification`],
     /// [`Substream::notification_substream_queued_bytes`] and
     /// [`Substream::close_notifications_substream`] can be used, and
     /// [`Event::NotificationsOutCloseDemanded`] and [`Event::NotificationsOutReset`] can be
     /// generated.
+    ///
+    /// `max_queued_bytes` is the maximum number of bytes of notifications that can be waiting
+    /// to be sent out. See [`Substream::write_notification`].
     pub fn notifications_out(
         timeout: TNow,
         requested_protocol: String,
         handshake: Vec<u8>,
         max_handshake_size: usize,
+        max_queued_bytes: usize,
         user_data: TNotifUd,
     ) -> Self {
         // TODO: check `handshake < max_handshake_size`?
@@ -258,6 +276,7 @@ where
                 negotiation: Some(negotiation),
                 handshake_in: leb128::FramedInProgress::new(max_handshake_size),
                 handshake_out,
+                max_queued_bytes,
                 user_data,
             },
         }
@@ -506,6 +525,7 @@ where
                 mut negotiation,
                 handshake_in,
                 mut handshake_out,
+                max_queued_bytes,
                 user_data,
             } => {
                 if timeout < read_write.now {
@@ -583,6 +603,7 @@ where
                                 negotiation,
                                 handshake_in,
                                 handshake_out,
+                                max_queued_bytes,
                                 user_data,
                             }),
                             None,
@@ -596,6 +617,8 @@ where
                             (
                                 Some(SubstreamInner::NotificationsOut {
                                     notifications: VecDeque::new(),
+                                    queued_notifications: VecDeque::new(),
+                                    max_queued_bytes,
                                     user_data,
                                     close_demanded_by_remote: false,
                                 }),
@@ -612,6 +635,7 @@ where
                                     negotiation,
                                     handshake_in,
                                     handshake_out,
+                                    max_queued_bytes,
                                     user_data,
                                 }),
                                 None,
@@ -634,6 +658,7 @@ where
                             negotiation,
                             handshake_in,
                             handshake_out,
+                            max_queued_bytes,
                             user_data,
                         }),
                         None,
@@ -642,12 +667,30 @@ where
             }
             SubstreamInner::NotificationsOut {
                 mut notifications,
+                mut queued_notifications,
+                max_queued_bytes,
                 user_data,
                 close_demanded_by_remote,
             } => {
                 // Receiving data on an outgoing substream is forbidden by the protocol.
                 read_write.discard_all_incoming();
-                read_write.write_from_vec_deque(&mut notifications);
+
+                // Notifications are only moved to `notifications` once the previous one has been
+                // entirely written out, so that queued notifications can still be coalesced.
+                loop {
+                    if notifications.is_empty() {
+                        match queued_notifications.pop_front() {
+                            Some(next) => notifications.extend(next.framed),
+                            None => break,
+                        }
+                    }
+
+                    let before = notifications.len();
+                    read_write.write_from_vec_deque(&mut notifications);
+                    if notifications.len() == before {
+                        break;
+                    }
+                }
 
                 // If this debug assertion fails, it means that `incoming_buffer` was `None` in
                 // the past then became `Some` again.
@@ -657,6 +700,8 @@ where
                     return (
                         Some(SubstreamInner::NotificationsOut {
                             notifications,
+                            queued_notifications,
+                            max_queued_bytes,
                             user_data,
                             close_demanded_by_remote: true,
                         }),
@@ -667,6 +712,8 @@ where
                 (
                     Some(SubstreamInner::NotificationsOut {
                         notifications,
+                        queued_notifications,
+                        max_queued_bytes,
                         user_data,
                         close_demanded_by_remote,
                     }),
@@ -1252,17 +1299,52 @@ where
 
     /// Queues a notification to be written out on the given substream.
     ///
+    /// If `coalescable` is `true`, any notification previously queued with `coalescable` set to
+    /// `true` and that hasn't started being written out yet is discarded, as it is considered
+    /// superseded by the new one.
+    ///
+    /// If the total size of the queued notifications would exceed the `max_queued_bytes` passed
+    /// to [`Substream::notifications_out`], the notification is silently discarded and `false`
+    /// is returned. A notification is always accepted if nothing is queued.
+    ///
     /// # Panic
     ///
     /// Panics if the substream isn't a notifications substream, or if the notifications substream
     /// isn't in the appropriate state.
     ///
-    pub fn write_notification_unbounded(&mut self, notification: Vec<u8>) {
+    pub fn write_notification(&mut self, notification: Vec<u8>, coalescable: bool) -> bool {
         match &mut self.inner {
-            SubstreamInner::NotificationsOut { notifications, .. } => {
+            SubstreamInner::NotificationsOut {
+                notifications,
+                queued_notifications,
+                max_queued_bytes,
+                ..
+            } => {
+                if coalescable {
+                    queued_notifications.retain(|n| !n.coalescable);
+                }
+
                 // TODO: expensive copying?
-                notifications.extend(leb128::encode_usize(notification.len()));
-                notifications.extend(notification.into_iter());
+                let mut framed = Vec::with_capacity(notification.len() + 10);
+                framed.extend(leb128::encode_usize(notification.len()));
+                framed.extend_from_slice(&notification);
+
+                let already_queued = notifications.len()
+                    + queued_notifications
+                        .iter()
+                        .map(|n| n.framed.len())
+                        .sum::<usize>();
+                if already_queued != 0
+                    && already_queued.saturating_add(framed.len()) > *max_queued_bytes
+                {
+                    return false;
+                }
+
+                queued_notifications.push_back(QueuedNotification {
+                    framed,
+                    coalescable,
+                });
+                true
             }
             _ => panic!(),
         }
@@ -1270,7 +1352,7 @@ where
 
     /// Returns the number of bytes waiting to be sent out on that substream.
     ///
-    /// See the documentation of [`Substream::write_notification_unbounded`] for context.
+    /// See the documentation of [`Substream::write_notification`] for context.
     ///
     /// # Panic
     ///
@@ -1279,7 +1361,17 @@ where
     ///
     pub fn notification_substream_queued_bytes(&self) -> usize {
         match &self.inner {
-            SubstreamInner::NotificationsOut { notifications, .. } => notifications.len(),
+            SubstreamInner::NotificationsOut {
+                notifications,
+                queued_notifications,
+                ..
+            } => {
+                notifications.len()
+                    + queued_notifications
+                        .iter()
+                        .map(|n| n.framed.len())
+                        .sum::<usize>()
+            }
             _ => panic!(),
         }
     }
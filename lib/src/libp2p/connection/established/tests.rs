@@ -19,7 +19,7 @@
 
 use super::{
     Config, ConfigNotifications, ConfigRequestResponse, ConfigRequestResponseIn, Event,
-    InboundError, NotificationsOutErr, RequestError, SingleStream,
+    InboundError, NotificationsOutErr, RequestError, SingleStream, SubstreamPriority,
 };
 use crate::libp2p::read_write::ReadWrite;
 use std::time::Duration;
@@ -268,6 +268,7 @@ fn successful_request() {
             inbound_config: ConfigRequestResponseIn::Payload { max_size: 128 },
             max_response_size: 1024,
             name: "test-request-protocol".to_owned(),
+            priority: SubstreamPriority::Normal,
         }],
         max_inbound_substreams: 64,
         ping_interval: Duration::from_secs(20),
@@ -320,6 +321,7 @@ fn refused_request() {
             inbound_config: ConfigRequestResponseIn::Payload { max_size: 128 },
             max_response_size: 1024,
             name: "test-request-protocol".to_owned(),
+            priority: SubstreamPriority::Normal,
         }],
         max_inbound_substreams: 64,
         ping_interval: Duration::from_secs(20),
@@ -372,6 +374,7 @@ fn request_protocol_not_supported() {
             inbound_config: ConfigRequestResponseIn::Payload { max_size: 128 },
             max_response_size: 1024,
             name: "test-request-protocol".to_owned(),
+            priority: SubstreamPriority::Normal,
         }],
         max_inbound_substreams: 64,
         ping_interval: Duration::from_secs(20),
@@ -413,6 +416,7 @@ fn request_timeout() {
             inbound_config: ConfigRequestResponseIn::Payload { max_size: 128 },
             max_response_size: 1024,
             name: "test-request-protocol".to_owned(),
+            priority: SubstreamPriority::Normal,
         }],
         max_inbound_substreams: 64,
         ping_interval: Duration::from_secs(20),
@@ -462,6 +466,8 @@ fn outbound_substream_works() {
             name: "test-notif-protocol".to_owned(),
             max_handshake_size: 1024,
             max_notification_size: 1024,
+            max_queued_bytes: 1024 * 1024,
+            priority: SubstreamPriority::Normal,
         }],
         request_protocols: Vec::new(),
         max_inbound_substreams: 64,
@@ -513,7 +519,7 @@ fn outbound_substream_works() {
             assert_eq!(id, substream_id);
             assert_eq!(handshake, b"hello back");
             for notif in notifications_to_send {
-                connections.alice.write_notification_unbounded(id, notif);
+                assert!(connections.alice.write_notification(id, notif, false));
             }
         }
         _ev => unreachable!("{:?}", _ev),
@@ -535,6 +541,81 @@ fn outbound_substream_works() {
     }
 }
 
+#[test]
+fn outbound_substream_queue_bounded_and_coalesced() {
+    let config = Config {
+        first_out_ping: Duration::new(60, 0),
+        notifications_protocols: vec![ConfigNotifications {
+            name: "test-notif-protocol".to_owned(),
+            max_handshake_size: 1024,
+            max_notification_size: 1024,
+            max_queued_bytes: 24,
+            priority: SubstreamPriority::Normal,
+        }],
+        request_protocols: Vec::new(),
+        max_inbound_substreams: 64,
+        ping_interval: Duration::from_secs(20),
+        ping_protocol: "ping".to_owned(),
+        ping_timeout: Duration::from_secs(20),
+        randomness_seed: [0; 32],
+    };
+
+    let mut connections = perform_handshake(256, 256, config.clone(), config);
+
+    let substream_id = connections.alice.open_notifications_substream(
+        0,
+        b"hello".to_vec(),
+        connections.now + Duration::from_secs(5),
+        (),
+    );
+
+    let (connections_update, event) = connections.run_until_event();
+    connections = connections_update;
+    match event {
+        either::Right(Event::NotificationsInOpen { id, .. }) => {
+            connections
+                .bob
+                .accept_in_notifications_substream(id, b"hello back".to_vec(), ());
+        }
+        _ev => unreachable!("{:?}", _ev),
+    }
+
+    let (connections_update, event) = connections.run_until_event();
+    connections = connections_update;
+    match event {
+        either::Left(Event::NotificationsOutResult { id, result: Ok(_) }) => {
+            assert_eq!(id, substream_id);
+            // Each notification is 8 bytes long once its length prefix is added.
+            assert!(connections
+                .alice
+                .write_notification(id, b"notif 1".to_vec(), true));
+            assert!(connections
+                .alice
+                .write_notification(id, b"notif 2".to_vec(), false));
+            assert!(connections
+                .alice
+                .write_notification(id, b"notif 3".to_vec(), true));
+            assert!(connections
+                .alice
+                .write_notification(id, b"notif 4".to_vec(), false));
+            assert!(!connections
+                .alice
+                .write_notification(id, b"notif 5".to_vec(), false));
+        }
+        _ev => unreachable!("{:?}", _ev),
+    }
+
+    for expected in [&b"notif 2"[..], &b"notif 3"[..], &b"notif 4"[..]] {
+        let (connections_update, event) = connections.run_until_event();
+        connections = connections_update;
+        match event {
+            either::Right(Event::NotificationIn { notification, .. }) => {
+                assert_eq!(notification, expected);
+            }
+            _ev => unreachable!("{:?}", _ev),
+        }
+    }
+}
 #[test]
 fn outbound_substream_open_timeout() {
     let config = Config {
@@ -543,6 +624,8 @@ fn outbound_substream_open_timeout() {
             name: "test-notif-protocol".to_owned(),
             max_handshake_size: 1024,
             max_notification_size: 1024,
+            max_queued_bytes: 1024 * 1024,
+            priority: SubstreamPriority::Normal,
         }],
         request_protocols: Vec::new(),
         max_inbound_substreams: 64,
@@ -595,6 +678,8 @@ fn outbound_substream_refuse() {
             name: "test-notif-protocol".to_owned(),
             max_handshake_size: 1024,
             max_notification_size: 1024,
+            max_queued_bytes: 1024 * 1024,
+            priority: SubstreamPriority::Normal,
         }],
         request_protocols: Vec::new(),
         max_inbound_substreams: 64,
@@ -648,6 +733,8 @@ fn outbound_substream_close_demanded() {
             name: "test-notif-protocol".to_owned(),
             max_handshake_size: 1024,
             max_notification_size: 1024,
+            max_queued_bytes: 1024 * 1024,
+            priority: SubstreamPriority::Normal,
         }],
         request_protocols: Vec::new(),
         max_inbound_substreams: 64,
@@ -693,7 +780,7 @@ fn outbound_substream_close_demanded() {
             assert_eq!(handshake, b"hello back");
             connections
                 .alice
-                .write_notification_unbounded(id, b"notif".to_vec());
+                .write_notification(id, b"notif".to_vec(), false);
         }
         _ev => unreachable!("{:?}", _ev),
     }
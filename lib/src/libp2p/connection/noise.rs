@@ -61,6 +61,7 @@
 //! [`Noise::inject_inbound_data`] when data is received.
 // TODO: review this last sentence, as this API might change after some experience with it
 
+use super::buffer_pool;
 use crate::{
     libp2p::{
         peer_id::{PeerId, PublicKey, SignatureVerifyFailed},
@@ -223,7 +224,7 @@ pub struct Noise {
     /// Buffer of data containing data received on the wire, before decryption. Always either
     /// empty or contains a partial frame (including the two bytes of length prefix). Frames,
     /// once full, are immediately decoded and moved to `rx_buffer_decrypted`.
-    rx_buffer_encrypted: Vec<u8>,
+    rx_buffer_encrypted: buffer_pool::Buffer,
 
     /// Buffer of data containing data received on the wire, after decryption.
     rx_buffer_decrypted: Vec<u8>,
@@ -420,7 +421,7 @@ impl Noise {
                 }
             } else {
                 debug_assert!(out_len <= destination.0.len() + destination.1.len());
-                let mut intermediary_buffer = vec![0; out_len];
+                let mut intermediary_buffer = buffer_pool::Buffer::zeroed(out_len);
                 let _written = self
                     .inner
                     .write_message(&payload[..in_len], &mut intermediary_buffer[2..])
@@ -511,7 +512,7 @@ struct HandshakeInProgressInner {
     rx_messages_remain: u8,
 
     /// Buffer of data containing data received on the wire, before decryption.
-    rx_buffer_encrypted: Vec<u8>,
+    rx_buffer_encrypted: buffer_pool::Buffer,
 
     /// Buffer of data containing data waiting to be sent on the wire, after encryption. Includes
     /// the length prefixes.
@@ -565,7 +566,7 @@ impl HandshakeInProgress {
             tx_payload,
             rx_payload,
             rx_messages_remain,
-            rx_buffer_encrypted: Vec::with_capacity(65536 + 2),
+            rx_buffer_encrypted: buffer_pool::Buffer::with_capacity(65536 + 2),
             tx_buffer_encrypted: VecDeque::new(),
         }));
 
@@ -40,6 +40,13 @@
 //! When [`Yamux::write`] is called, the buffer of data to send out is stored within the
 //! [`Yamux`] object. This data will then be progressively returned by [`Yamux::extract_next`].
 //!
+//! Each substream has a [`SubstreamPriority`], which can be modified with
+//! [`Yamux::set_priority`]. Data frames and window updates of substreams with a higher priority
+//! are always sent out before the ones of substreams with a lower priority. Use
+//! [`Config::max_out_data_frame_size`] in order to bound the time during which a
+//! higher-priority substream might have to wait for a data frame of a lower-priority substream
+//! that has already started being sent out.
+//!
 //! It is the responsibility of the user to enforce a bound to the amount of enqueued data, as
 //! the [`Yamux`] itself doesn't enforce any limit. Enforcing such a bound must be done based
 //! on the logic of the higher-level protocols. Failing to do so might lead to potential DoS
@@ -180,6 +187,8 @@ struct Substream<T> {
     state: SubstreamState,
     /// `true` if the substream has been opened by the remote.
     inbound: bool,
+    /// See [`Yamux::set_priority`].
+    priority: SubstreamPriority,
     /// Data chosen by the user.
     user_data: T,
 }
@@ -433,6 +442,7 @@ impl<T> Yamux<T> {
                     write_queue: write_queue::WriteQueue::new(),
                 },
                 inbound: false,
+                priority: SubstreamPriority::Normal,
                 user_data,
             },
         );
@@ -597,6 +607,25 @@ impl<T> Yamux<T> {
         }
     }
 
+    /// Modifies the priority of the given substream. Substreams have a priority of
+    /// [`SubstreamPriority::Normal`] when they are opened or accepted.
+    ///
+    /// Whenever multiple substreams have data to send out, the substreams with the highest
+    /// priority are always chosen first. This makes it possible for example to prevent
+    /// consensus-critical messages from being stuck behind large responses to requests.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`SubstreamId`] is invalid.
+    ///
+    pub fn set_priority(&mut self, substream_id: SubstreamId, priority: SubstreamPriority) {
+        self.inner
+            .substreams
+            .get_mut(&substream_id.0)
+            .unwrap_or_else(|| panic!())
+            .priority = priority;
+    }
+
     /// Returns the number of bytes queued for writing on this substream.
     ///
     /// Returns 0 if the substream is in a reset state.
@@ -962,7 +991,10 @@ impl<T> Yamux<T> {
                                 ..
                             },
                         ..
-                    }) = self.inner.substreams.get_mut(&substream_id.0) else { continue; };
+                    }) = self.inner.substreams.get_mut(&substream_id.0)
+                    else {
+                        continue;
+                    };
 
                     *remote_write_closed = true;
 
@@ -1067,8 +1099,7 @@ impl<T> Yamux<T> {
 
                     // Decode the header in `incoming_header`.
                     let decoded_header = {
-                        let Ok(full_header) = <&[u8; 12]>::try_from(&incoming_header[..])
-                        else {
+                        let Ok(full_header) = <&[u8; 12]>::try_from(&incoming_header[..]) else {
                             // Not enough data to finish receiving header. Nothing more can be
                             // done.
                             debug_assert!(data.is_empty());
@@ -1202,7 +1233,9 @@ impl<T> Yamux<T> {
                             // which we have sent a RST frame earlier. Considering that we don't
                             // always keep traces of old substreams, we have no way to know whether
                             // this is the case or not.
-                            let Some(s) = self.inner.substreams.get_mut(&stream_id) else { continue };
+                            let Some(s) = self.inner.substreams.get_mut(&stream_id) else {
+                                continue;
+                            };
                             if !matches!(s.state, SubstreamState::Healthy { .. }) {
                                 continue;
                             }
@@ -1666,10 +1699,22 @@ impl<T> Yamux<T> {
                     }
 
                     // Send either window update frames or data frames.
+                    // Substreams with the highest priority are chosen first. Amongst them, one
+                    // is chosen randomly.
+                    let highest_priority = self
+                        .inner
+                        .outgoing_req_substreams
+                        .iter()
+                        .map(|id| self.inner.substreams.get(id).unwrap().priority)
+                        .max();
                     if let Some(substream_id) = self
                         .inner
                         .outgoing_req_substreams
                         .iter()
+                        .filter(|id| {
+                            Some(self.inner.substreams.get(*id).unwrap().priority)
+                                == highest_priority
+                        })
                         .choose(&mut self.inner.randomness)
                         .cloned()
                     {
@@ -1685,7 +1730,10 @@ impl<T> Yamux<T> {
                             local_write_close: local_write,
                             allowed_window,
                             ..
-                        } = &mut sub.state else { unreachable!() };
+                        } = &mut sub.state
+                        else {
+                            unreachable!()
+                        };
 
                         let has_data_to_write = (*allowed_window != 0 && !write_queue.is_empty())
                             || matches!(local_write, SubstreamStateLocalWrite::FinDesired);
@@ -1820,6 +1868,7 @@ impl<T> Yamux<T> {
                             write_queue: write_queue::WriteQueue::new(),
                         },
                         inbound: true,
+                        priority: SubstreamPriority::Normal,
                         user_data,
                     },
                 );
@@ -1904,6 +1953,21 @@ where
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash, derive_more::From)]
 pub struct SubstreamId(NonZeroU32);
 
+/// Priority of a substream. See [`Yamux::set_priority`].
+///
+/// Values compare in the order `Low < Normal < High`.
+#[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
+pub enum SubstreamPriority {
+    /// Data is sent out only if no other substream has data to send. Appropriate for bulk
+    /// transfers, such as responses to block or storage requests.
+    Low,
+    /// Default priority of substreams.
+    Normal,
+    /// Data is sent out before the one of any other substream. Appropriate for small and
+    /// time-sensitive messages, such as GrandPa messages.
+    High,
+}
+
 impl SubstreamId {
     /// Returns the value that compares inferior or equal to all possible values.
     pub fn min_value() -> Self {
@@ -18,8 +18,8 @@
 #![cfg(test)]
 
 use super::{
-    CloseError, Config, Error, GoAwayErrorCode, IncomingDataDetail, OpenSubstreamError, WriteError,
-    Yamux,
+    CloseError, Config, Error, GoAwayErrorCode, IncomingDataDetail, OpenSubstreamError,
+    SubstreamPriority, WriteError, Yamux,
 };
 
 use core::{
@@ -121,6 +121,42 @@ fn max_out_data_frame_size_works() {
     assert_eq!(output.len(), 27);
 }
 
+#[test]
+fn high_priority_substreams_sent_first() {
+    let mut yamux = Yamux::new(Config {
+        capacity: 0,
+        is_initiator: true,
+        randomness_seed: [0; 32],
+        max_out_data_frame_size: NonZeroU32::new(2).unwrap(),
+        max_simultaneous_queued_pongs: NonZeroUsize::new(4).unwrap(),
+        max_simultaneous_rst_substreams: NonZeroUsize::new(1024).unwrap(),
+    });
+
+    let low_substream = yamux.open_substream(()).unwrap();
+    yamux.set_priority(low_substream, SubstreamPriority::Low);
+    yamux.write(low_substream, b"lowp".to_vec()).unwrap();
+
+    let high_substream = yamux.open_substream(()).unwrap();
+    yamux.set_priority(high_substream, SubstreamPriority::High);
+    yamux.write(high_substream, b"high".to_vec()).unwrap();
+
+    let mut output = Vec::new();
+    while let Some(out) = yamux.extract_next(usize::max_value()) {
+        output.extend_from_slice(out.as_ref());
+    }
+
+    // Each frame consists in a 12 bytes header followed with 2 bytes of data.
+    assert_eq!(output.len(), 4 * 14);
+    let frames = output
+        .chunks(14)
+        .map(|frame| (frame[7], &frame[12..]))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        frames,
+        [(3, &b"hi"[..]), (3, b"gh"), (1, b"lo"), (1, b"wp")]
+    );
+}
+
 #[test]
 fn extract_bytes_one_by_one() {
     let mut yamux = Yamux::new(Config {
@@ -61,7 +61,7 @@ pub use collection::{
     ConfigRequestResponse, ConfigRequestResponseIn, ConnectionId, ConnectionToCoordinator,
     CoordinatorToConnection, MultiStreamConnectionTask, NotificationProtocolConfig,
     NotificationsInClosedErr, NotificationsOutErr, ReadWrite, RequestError,
-    SingleStreamConnectionTask, StartRequestError, SubstreamId,
+    SingleStreamConnectionTask, StartRequestError, SubstreamId, SubstreamPriority,
 };
 
 /// Configuration for a [`Peers`].
@@ -1651,7 +1651,9 @@ where
     /// Each substream maintains a queue of notifications to be sent to the remote. This method
     /// attempts to push a notification to this queue.
     ///
-    /// An error is also returned if the queue exceeds a certain size in bytes, for two reasons:
+    /// The size of this queue, in bytes, is bounded by
+    /// [`collection::NotificationProtocolConfig::max_queued_bytes`]. Notifications that don't fit in
+    /// the queue are silently discarded, for two reasons:
     ///
     /// - Since the content of the queue is transferred at a limited rate, each notification
     /// pushed at the end of the queue will take more time than the previous one to reach the
@@ -1665,6 +1667,11 @@ where
     /// be designed in such a way that discarding notifications shouldn't have a too negative
     /// impact.
     ///
+    /// If `coalescable` is `true`, the notifications previously queued with `coalescable` set to
+    /// `true` and that haven't started being sent yet are discarded, as they are considered
+    /// superseded by the new notification. This is appropriate for example for block
+    /// announcements of a new best block.
+    ///
     /// Regardless of the success of this function, no guarantee exists about the successful
     /// delivery of notifications.
     ///
@@ -1681,6 +1688,7 @@ where
         target: &PeerId,
         notifications_protocol_index: usize,
         notification: impl Into<Vec<u8>>,
+        coalescable: bool,
     ) -> Result<(), QueueNotificationError> {
         let peer_index = *self.peer_indices.get(target).unwrap();
 
@@ -1700,7 +1708,9 @@ where
             Some(NotificationsOutOpenState::Open(s_id)) => s_id,
         };
 
-        let result = self.inner.queue_notification(*substream_id, notification);
+        let result = self
+            .inner
+            .queue_notification(*substream_id, notification, coalescable);
 
         match result {
             Ok(()) => Ok(()),
@@ -1752,6 +1762,7 @@ where
         &mut self,
         notifications_protocol_index: usize,
         notification: impl Into<Vec<u8>>,
+        coalescable: bool,
     ) {
         let notification = notification.into();
 
@@ -1762,9 +1773,9 @@ where
             }
 
             if let NotificationsOutOpenState::Open(substream_id) = &state.open {
-                let _ = self
-                    .inner
-                    .queue_notification(*substream_id, notification.clone());
+                let _ =
+                    self.inner
+                        .queue_notification(*substream_id, notification.clone(), coalescable);
             }
         }
     }
@@ -0,0 +1,663 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the metadata of a runtime.
+//!
+//! The metadata, returned by the `Metadata_metadata` runtime call, describes the pallets of the
+//! runtime and the types they use. It starts with a magic number and a version number, followed
+//! with a registry of all the types, then with the list of pallets, each referring to the types
+//! of the registry by their id.
+//!
+//! Only the parts of the metadata that the rest of this crate needs are decoded: the types
+//! registry, and for each pallet its name, its index, the types of the values of its storage
+//! entries, and the type of its errors. Only versions 14 and 15 of the metadata format are
+//! supported.
+
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+
+use crate::util;
+
+/// Maximum depth of the types while skipping over a value. Protects against stack overflows
+/// caused by recursive types.
+const MAX_TYPE_DEPTH: u32 = 64;
+
+/// Decoded metadata.
+#[derive(Debug, Clone)]
+pub(crate) struct Metadata {
+    /// All the types of the registry of the metadata.
+    pub(crate) types: TypesRegistry,
+    /// List of pallets of the runtime.
+    pub(crate) pallets: Vec<Pallet>,
+}
+
+/// Pallet found in the metadata.
+#[derive(Debug, Clone)]
+pub(crate) struct Pallet {
+    /// Name of the pallet, for example `Balances`.
+    pub(crate) name: String,
+    /// Index of the pallet, as found in events and errors.
+    pub(crate) index: u8,
+    /// Storage entries of the pallet: name and type of their values.
+    pub(crate) storage_entries: Vec<(String, u32)>,
+    /// Type of the errors of the pallet, if any.
+    pub(crate) error_ty: Option<u32>,
+}
+
+/// Registry of all the types of the metadata, indexed by type id.
+#[derive(Debug, Clone)]
+pub(crate) struct TypesRegistry {
+    types: hashbrown::HashMap<u32, TypeDef, fnv::FnvBuildHasher>,
+}
+
+/// Definition of a type of the registry.
+#[derive(Debug, Clone)]
+pub(crate) enum TypeDef {
+    Composite(Vec<Field>),
+    Variant(Vec<Variant>),
+    Sequence(u32),
+    /// Length and type of the elements.
+    Array(u32, u32),
+    Tuple(Vec<u32>),
+    /// Index of the primitive type, as found in the metadata.
+    Primitive(u8),
+    Compact,
+    /// Type used to store the bits.
+    BitSequence(u32),
+}
+
+/// Field of a composite type or of an enum variant.
+#[derive(Debug, Clone)]
+pub(crate) struct Field {
+    pub(crate) name: Option<String>,
+    pub(crate) ty: u32,
+}
+
+/// Variant of an enum type.
+#[derive(Debug, Clone)]
+pub(crate) struct Variant {
+    pub(crate) name: String,
+    pub(crate) index: u8,
+    pub(crate) fields: Vec<Field>,
+    /// Documentation of the variant, one entry per line.
+    pub(crate) docs: Vec<String>,
+}
+
+/// Decodes the given metadata.
+///
+/// The metadata is the output of the `Metadata_metadata` runtime call, after its length prefix
+/// has been removed.
+pub(crate) fn decode(metadata: &[u8]) -> Result<Metadata, Error> {
+    let Some(metadata) = metadata.strip_prefix(b"meta") else {
+        return Err(Error::InvalidPrefix);
+    };
+
+    let (version, metadata) = match metadata.split_first() {
+        Some((v @ (14 | 15), rest)) => (*v, rest),
+        Some((v, _)) => return Err(Error::UnsupportedVersion(*v)),
+        None => return Err(Error::ParseError),
+    };
+
+    // Only the types registry and the pallets are decoded. What follows them is ignored.
+    let (_, (types, pallets)) = nom::sequence::tuple((
+        nom_registry::<nom::error::Error<&[u8]>>,
+        nom_pallets(version >= 15),
+    ))(metadata)
+    .map_err(|_| Error::ParseError)?;
+
+    Ok(Metadata {
+        types: TypesRegistry { types },
+        pallets,
+    })
+}
+
+impl Metadata {
+    /// Returns the type of the values of the given storage entry, or `None` if the storage entry
+    /// isn't found.
+    pub(crate) fn storage_entry_type(&self, pallet_name: &str, entry_name: &str) -> Option<u32> {
+        self.pallets
+            .iter()
+            .find(|p| p.name == pallet_name)?
+            .storage_entries
+            .iter()
+            .find(|(name, _)| name == entry_name)
+            .map(|(_, ty)| *ty)
+    }
+}
+
+impl TypesRegistry {
+    /// Returns the definition of the given type, or `None` if it isn't in the registry.
+    pub(crate) fn get(&self, ty: u32) -> Option<&TypeDef> {
+        self.types.get(&ty)
+    }
+
+    /// Returns the variant of the given enum type whose name is `name`.
+    pub(crate) fn variant_by_name(&self, ty: u32, name: &str) -> Option<&Variant> {
+        match self.get(ty) {
+            Some(TypeDef::Variant(variants)) => variants.iter().find(|v| v.name == name),
+            _ => None,
+        }
+    }
+
+    /// Reads the index of a variant of the given enum type, and returns this variant and the
+    /// bytes after the index.
+    pub(crate) fn decode_variant_index<'a, 'b>(
+        &'a self,
+        ty: u32,
+        bytes: &'b [u8],
+    ) -> Option<(&'a Variant, &'b [u8])> {
+        let variants = match self.get(ty) {
+            Some(TypeDef::Variant(variants)) => variants,
+            _ => return None,
+        };
+        let (index, rest) = bytes.split_first()?;
+        let variant = variants.iter().find(|v| v.index == *index)?;
+        Some((variant, rest))
+    }
+
+    /// Skips over a SCALE-encoded value of the given type and returns the bytes after it.
+    ///
+    /// Returns `None` if the value is invalid or if the type is too deeply nested.
+    pub(crate) fn skip_value<'a>(&self, ty: u32, bytes: &'a [u8]) -> Option<&'a [u8]> {
+        self.skip(ty, bytes, 0)
+    }
+
+    /// Returns the number of bytes of the SCALE encoding of the values of the given type, or
+    /// `None` if this number depends on the value.
+    pub(crate) fn fixed_size(&self, ty: u32) -> Option<usize> {
+        self.fixed_size_inner(ty, 0)
+    }
+
+    fn fixed_size_inner(&self, ty: u32, depth: u32) -> Option<usize> {
+        if depth >= MAX_TYPE_DEPTH {
+            return None;
+        }
+
+        match self.get(ty)? {
+            TypeDef::Composite(fields) => fields.iter().try_fold(0usize, |size, field| {
+                size.checked_add(self.fixed_size_inner(field.ty, depth + 1)?)
+            }),
+            TypeDef::Array(len, elem_ty) => usize::try_from(*len)
+                .ok()?
+                .checked_mul(self.fixed_size_inner(*elem_ty, depth + 1)?),
+            TypeDef::Tuple(tys) => tys.iter().try_fold(0usize, |size, ty| {
+                size.checked_add(self.fixed_size_inner(*ty, depth + 1)?)
+            }),
+            TypeDef::Primitive(primitive) => match primitive {
+                0 | 3 | 9 => Some(1),
+                4 | 10 => Some(2),
+                1 | 5 | 11 => Some(4),
+                6 | 12 => Some(8),
+                7 | 13 => Some(16),
+                8 | 14 => Some(32),
+                _ => None,
+            },
+            TypeDef::Variant(_)
+            | TypeDef::Sequence(_)
+            | TypeDef::Compact
+            | TypeDef::BitSequence(_) => None,
+        }
+    }
+
+    fn skip<'a>(&self, ty: u32, bytes: &'a [u8], depth: u32) -> Option<&'a [u8]> {
+        if depth >= MAX_TYPE_DEPTH {
+            return None;
+        }
+
+        match self.get(ty)? {
+            TypeDef::Composite(fields) => fields
+                .iter()
+                .try_fold(bytes, |bytes, field| self.skip(field.ty, bytes, depth + 1)),
+            TypeDef::Variant(_) => {
+                let (variant, bytes) = self.decode_variant_index(ty, bytes)?;
+                variant
+                    .fields
+                    .iter()
+                    .try_fold(bytes, |bytes, field| self.skip(field.ty, bytes, depth + 1))
+            }
+            TypeDef::Sequence(elem_ty) => {
+                let (bytes, len) =
+                    util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(bytes).ok()?;
+                self.skip_repeated(*elem_ty, len, bytes, depth)
+            }
+            TypeDef::Array(len, elem_ty) => {
+                self.skip_repeated(*elem_ty, usize::try_from(*len).unwrap(), bytes, depth)
+            }
+            TypeDef::Tuple(tys) => tys
+                .iter()
+                .try_fold(bytes, |bytes, ty| self.skip(*ty, bytes, depth + 1)),
+            TypeDef::Primitive(primitive) => {
+                let len = match primitive {
+                    0 | 3 | 9 => 1,
+                    4 | 10 => 2,
+                    1 | 5 | 11 => 4,
+                    6 | 12 => 8,
+                    7 | 13 => 16,
+                    8 | 14 => 32,
+                    // String.
+                    2 => {
+                        let (bytes, _) =
+                            util::nom_bytes_decode::<nom::error::Error<&[u8]>>(bytes).ok()?;
+                        return Some(bytes);
+                    }
+                    _ => return None,
+                };
+                bytes.get(len..)
+            }
+            TypeDef::Compact => {
+                let first = *bytes.first()?;
+                let len = match first & 0b11 {
+                    0 => 1,
+                    1 => 2,
+                    2 => 4,
+                    _ => usize::from(first >> 2) + 5,
+                };
+                bytes.get(len..)
+            }
+            TypeDef::BitSequence(store_ty) => {
+                let store_bytes = match self.get(*store_ty) {
+                    Some(TypeDef::Primitive(3)) => 1,
+                    Some(TypeDef::Primitive(4)) => 2,
+                    Some(TypeDef::Primitive(5)) => 4,
+                    Some(TypeDef::Primitive(6)) => 8,
+                    _ => return None,
+                };
+                let (bytes, num_bits) =
+                    util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(bytes).ok()?;
+                let len =
+                    num_bits.checked_add(store_bytes * 8 - 1)? / (store_bytes * 8) * store_bytes;
+                bytes.get(len..)
+            }
+        }
+    }
+
+    fn skip_repeated<'a>(
+        &self,
+        elem_ty: u32,
+        len: usize,
+        mut bytes: &'a [u8],
+        depth: u32,
+    ) -> Option<&'a [u8]> {
+        for _ in 0..len {
+            let rest = self.skip(elem_ty, bytes, depth + 1)?;
+            // Elements of size zero are skipped in one go, in order to not loop for a long time.
+            if rest.len() == bytes.len() {
+                return Some(rest);
+            }
+            bytes = rest;
+        }
+        Some(bytes)
+    }
+}
+
+/// Error potentially returned when decoding the metadata.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// The metadata doesn't start with the expected magic number.
+    InvalidPrefix,
+    /// The version of the metadata format isn't supported.
+    #[display(fmt = "Unsupported metadata version: {_0}")]
+    UnsupportedVersion(u8),
+    /// Failed to parse the metadata.
+    ParseError,
+}
+
+/// Decodes the types registry found at the start of the metadata.
+fn nom_registry<
+    'a,
+    E: nom::error::ParseError<&'a [u8]>
+        + nom::error::FromExternalError<&'a [u8], core::str::Utf8Error>,
+>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], hashbrown::HashMap<u32, TypeDef, fnv::FnvBuildHasher>, E> {
+    nom::combinator::flat_map(util::nom_scale_compact_usize, |num_types| {
+        nom::multi::fold_many_m_n(
+            num_types,
+            num_types,
+            nom::sequence::tuple((
+                nom_compact_u32,
+                // Path.
+                nom_vec_skip(util::nom_string_decode),
+                // Type parameters.
+                nom_vec_skip(nom::sequence::tuple((
+                    util::nom_string_decode,
+                    util::nom_option_decode(nom_compact_u32),
+                ))),
+                nom_type_def,
+                // Documentation.
+                nom_vec_skip(util::nom_string_decode),
+            )),
+            || hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+            |mut acc, (type_id, (), (), type_def, ())| {
+                acc.insert(type_id, type_def);
+                acc
+            },
+        )
+    })(bytes)
+}
+
+/// Decodes the definition of a type of the registry.
+fn nom_type_def<
+    'a,
+    E: nom::error::ParseError<&'a [u8]>
+        + nom::error::FromExternalError<&'a [u8], core::str::Utf8Error>,
+>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], TypeDef, E> {
+    let (bytes, variant) = nom::number::complete::u8(bytes)?;
+    match variant {
+        0 => nom::combinator::map(nom_fields, TypeDef::Composite)(bytes),
+        1 => nom::combinator::map(
+            nom::combinator::flat_map(util::nom_scale_compact_usize, |num_variants| {
+                nom::multi::many_m_n(
+                    num_variants,
+                    num_variants,
+                    nom::combinator::map(
+                        nom::sequence::tuple((
+                            util::nom_string_decode,
+                            nom_fields,
+                            nom::number::complete::u8,
+                            nom::combinator::flat_map(util::nom_scale_compact_usize, |num_docs| {
+                                nom::multi::many_m_n(num_docs, num_docs, util::nom_string_decode)
+                            }),
+                        )),
+                        |(name, fields, index, docs)| Variant {
+                            name: name.to_owned(),
+                            index,
+                            fields,
+                            docs: docs.into_iter().map(|d| d.to_owned()).collect(),
+                        },
+                    ),
+                )
+            }),
+            TypeDef::Variant,
+        )(bytes),
+        2 => nom::combinator::map(nom_compact_u32, TypeDef::Sequence)(bytes),
+        3 => nom::combinator::map(
+            nom::sequence::tuple((nom::number::complete::le_u32, nom_compact_u32)),
+            |(len, ty)| TypeDef::Array(len, ty),
+        )(bytes),
+        4 => nom::combinator::map(
+            nom::combinator::flat_map(util::nom_scale_compact_usize, |num_elems| {
+                nom::multi::many_m_n(num_elems, num_elems, nom_compact_u32)
+            }),
+            TypeDef::Tuple,
+        )(bytes),
+        5 => nom::combinator::map(nom::number::complete::u8, TypeDef::Primitive)(bytes),
+        6 => nom::combinator::map(nom_compact_u32, |_| TypeDef::Compact)(bytes),
+        7 => nom::combinator::map(
+            nom::sequence::tuple((nom_compact_u32, nom_compact_u32)),
+            |(store_ty, _)| TypeDef::BitSequence(store_ty),
+        )(bytes),
+        _ => Err(nom::Err::Error(nom::error::make_error(
+            bytes,
+            nom::error::ErrorKind::Tag,
+        ))),
+    }
+}
+
+/// Decodes the fields of a composite type or of an enum variant.
+fn nom_fields<
+    'a,
+    E: nom::error::ParseError<&'a [u8]>
+        + nom::error::FromExternalError<&'a [u8], core::str::Utf8Error>,
+>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], Vec<Field>, E> {
+    nom::combinator::flat_map(util::nom_scale_compact_usize, |num_fields| {
+        nom::multi::many_m_n(
+            num_fields,
+            num_fields,
+            nom::combinator::map(
+                nom::sequence::tuple((
+                    util::nom_option_decode(util::nom_string_decode),
+                    nom_compact_u32,
+                    // Type name.
+                    util::nom_option_decode(util::nom_string_decode),
+                    // Documentation.
+                    nom_vec_skip(util::nom_string_decode),
+                )),
+                |(name, ty, _, ())| Field {
+                    name: name.map(|n| n.to_owned()),
+                    ty,
+                },
+            ),
+        )
+    })(bytes)
+}
+
+/// Decodes the list of pallets found after the types registry.
+fn nom_pallets<
+    'a,
+    E: nom::error::ParseError<&'a [u8]>
+        + nom::error::FromExternalError<&'a [u8], core::str::Utf8Error>,
+>(
+    has_docs: bool,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Vec<Pallet>, E> {
+    nom::combinator::flat_map(util::nom_scale_compact_usize, move |num_pallets| {
+        nom::multi::many_m_n(
+            num_pallets,
+            num_pallets,
+            nom::combinator::map(
+                nom::sequence::tuple((
+                    util::nom_string_decode,
+                    // Storage.
+                    util::nom_option_decode(nom::sequence::tuple((
+                        util::nom_string_decode,
+                        nom::combinator::flat_map(util::nom_scale_compact_usize, |num_entries| {
+                            nom::multi::many_m_n(num_entries, num_entries, nom_storage_entry)
+                        }),
+                    ))),
+                    // Calls.
+                    util::nom_option_decode(nom_compact_u32),
+                    // Events.
+                    util::nom_option_decode(nom_compact_u32),
+                    // Constants.
+                    nom_vec_skip(nom::sequence::tuple((
+                        util::nom_string_decode,
+                        nom_compact_u32,
+                        util::nom_bytes_decode,
+                        nom_vec_skip(util::nom_string_decode),
+                    ))),
+                    // Errors.
+                    util::nom_option_decode(nom_compact_u32),
+                    // Index.
+                    nom::number::complete::u8,
+                    // Documentation, only in version 15 and above.
+                    move |bytes| {
+                        if has_docs {
+                            nom_vec_skip(util::nom_string_decode)(bytes)
+                        } else {
+                            Ok((bytes, ()))
+                        }
+                    },
+                )),
+                |(name, storage, _, _, (), error_ty, index, ())| Pallet {
+                    name: name.to_owned(),
+                    index,
+                    storage_entries: storage
+                        .map(|(_, entries)| {
+                            entries
+                                .into_iter()
+                                .map(|(name, ty)| (name.to_owned(), ty))
+                                .collect()
+                        })
+                        .unwrap_or_default(),
+                    error_ty,
+                },
+            ),
+        )
+    })
+}
+
+/// Decodes a storage entry of a pallet. Returns its name and the type of its values.
+fn nom_storage_entry<
+    'a,
+    E: nom::error::ParseError<&'a [u8]>
+        + nom::error::FromExternalError<&'a [u8], core::str::Utf8Error>,
+>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], (&'a str, u32), E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_string_decode,
+            // Modifier.
+            nom::number::complete::u8,
+            nom::branch::alt((
+                nom::sequence::preceded(nom::bytes::complete::tag(&[0]), nom_compact_u32),
+                nom::combinator::map(
+                    nom::sequence::preceded(
+                        nom::bytes::complete::tag(&[1]),
+                        nom::sequence::tuple((
+                            // Hashers.
+                            nom_vec_skip(nom::number::complete::u8),
+                            // Key type.
+                            nom_compact_u32,
+                            nom_compact_u32,
+                        )),
+                    ),
+                    |(_, _, value_ty)| value_ty,
+                ),
+            )),
+            // Default value.
+            util::nom_bytes_decode,
+            nom_vec_skip(util::nom_string_decode),
+        )),
+        |(name, _, ty, _, ())| (name, ty),
+    )(bytes)
+}
+
+/// Decodes a SCALE-compact-encoded type id.
+pub(crate) fn nom_compact_u32<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], u32, E> {
+    nom::combinator::map_opt(util::nom_scale_compact_u64, |n| u32::try_from(n).ok())(bytes)
+}
+
+/// Decodes a SCALE-encoded vector whose items are decoded then discarded.
+pub(crate) fn nom_vec_skip<'a, O, E: nom::error::ParseError<&'a [u8]>>(
+    mut inner: impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], O, E>,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], (), E> {
+    move |bytes| {
+        let (mut bytes, num_elems) = util::nom_scale_compact_usize(bytes)?;
+        for _ in 0..num_elems {
+            bytes = inner(bytes)?.0;
+        }
+        Ok((bytes, ()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    /// Builds a metadata of the given version containing a `u32` type, an enum type with two
+    /// variants, and a `Test` pallet with a `Value` storage entry and errors.
+    fn metadata(version: u8) -> Vec<u8> {
+        let mut metadata = b"meta".to_vec();
+        metadata.push(version);
+
+        metadata.push(2 << 2); // Number of types.
+                               // 0: u32
+        metadata.extend_from_slice(&[0, 0, 0, 5, 5, 0]);
+        // 1: enum { First, Second(u32) }
+        metadata.extend_from_slice(&[1 << 2, 0, 0, 1, 2 << 2]);
+        metadata.extend_from_slice(&[5 << 2, b'F', b'i', b'r', b's', b't', 0, 0]);
+        metadata.extend_from_slice(&[1 << 2, 3 << 2, b'd', b'o', b'c']);
+        metadata.extend_from_slice(&[6 << 2, b'S', b'e', b'c', b'o', b'n', b'd']);
+        metadata.extend_from_slice(&[1 << 2, 0, 0, 0, 0, 1, 0]);
+        metadata.push(0); // Documentation.
+
+        metadata.push(1 << 2); // Number of pallets.
+        metadata.extend_from_slice(&[4 << 2, b'T', b'e', b's', b't']);
+        metadata.extend_from_slice(&[1, 4 << 2, b'T', b'e', b's', b't', 1 << 2]); // Storage.
+        metadata.extend_from_slice(&[5 << 2, b'V', b'a', b'l', b'u', b'e']);
+        metadata.extend_from_slice(&[0, 0, 0]); // Modifier and plain type.
+        metadata.extend_from_slice(&[4 << 2, 0, 0, 0, 0, 0]); // Default value and documentation.
+        metadata.extend_from_slice(&[0, 0, 0]); // Calls, events, and constants.
+        metadata.extend_from_slice(&[1, 1 << 2]); // Errors.
+        metadata.push(7); // Index.
+        if version >= 15 {
+            metadata.push(0); // Documentation.
+        }
+
+        // Rest of the metadata, which isn't parsed.
+        metadata.extend_from_slice(&[0xff; 8]);
+        metadata
+    }
+
+    #[test]
+    fn decode_v14_and_v15() {
+        for version in [14, 15] {
+            let metadata = super::decode(&metadata(version)).unwrap();
+            assert_eq!(metadata.pallets.len(), 1);
+            assert_eq!(metadata.pallets[0].name, "Test");
+            assert_eq!(metadata.pallets[0].index, 7);
+            assert_eq!(metadata.pallets[0].error_ty, Some(1));
+            assert_eq!(metadata.storage_entry_type("Test", "Value"), Some(0));
+            assert_eq!(metadata.storage_entry_type("Test", "Other"), None);
+            assert_eq!(metadata.storage_entry_type("Other", "Value"), None);
+
+            let first = metadata.types.variant_by_name(1, "First").unwrap();
+            assert_eq!(first.index, 0);
+            assert_eq!(first.docs, ["doc"]);
+        }
+    }
+
+    #[test]
+    fn invalid_prefix_and_version() {
+        let mut bad_prefix = metadata(14);
+        bad_prefix[0] = b'x';
+        assert_eq!(
+            super::decode(&bad_prefix).unwrap_err(),
+            super::Error::InvalidPrefix
+        );
+
+        assert_eq!(
+            super::decode(&metadata(13)).unwrap_err(),
+            super::Error::UnsupportedVersion(13)
+        );
+
+        assert_eq!(
+            super::decode(b"meta").unwrap_err(),
+            super::Error::ParseError
+        );
+    }
+
+    #[test]
+    fn skip_value() {
+        let metadata = super::decode(&metadata(14)).unwrap();
+        assert_eq!(
+            metadata.types.skip_value(0, &[1, 2, 3, 4, 5]),
+            Some(&[5][..])
+        );
+        assert_eq!(metadata.types.skip_value(1, &[0, 9]), Some(&[9][..]));
+        assert_eq!(
+            metadata.types.skip_value(1, &[1, 1, 2, 3, 4]),
+            Some(&[][..])
+        );
+        assert_eq!(metadata.types.skip_value(1, &[1, 1, 2]), None);
+        assert_eq!(metadata.types.skip_value(1, &[2]), None);
+    }
+
+    #[test]
+    fn fixed_size() {
+        let metadata = super::decode(&metadata(14)).unwrap();
+        assert_eq!(metadata.types.fixed_size(0), Some(4));
+        assert_eq!(metadata.types.fixed_size(1), None);
+        assert_eq!(metadata.types.fixed_size(2), None);
+    }
+}
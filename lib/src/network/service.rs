@@ -132,6 +132,19 @@ pub struct ChainConfig {
     pub role: protocol::Role,
 }
 
+/// Builds the name of a networking protocol of a chain, such as `block-announces/1` or
+/// `sync/2`, as found on the wire.
+///
+/// The fork id, if any, is inserted in the name right after the genesis hash. Nodes that use a
+/// different fork id, or no fork id, use different protocol names and thus never communicate
+/// with each other on this chain, even if the genesis hash is the same.
+pub fn protocol_name(genesis_hash: &[u8; 32], fork_id: Option<&str>, protocol: &str) -> String {
+    match fork_id {
+        Some(fork_id) => format!("/{}/{}/{}", hex::encode(genesis_hash), fork_id, protocol),
+        None => format!("/{}/{}", hex::encode(genesis_hash), protocol),
+    }
+}
+
 /// Identifier of a pending connection requested by the network through a [`StartConnect`].
 #[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct PendingId(usize);
@@ -376,6 +389,16 @@ where
         self.chains[chain_index].chain_config.block_number_bytes
     }
 
+    /// Returns the value passed as [`ChainConfig::fork_id`] for the given chain.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub fn fork_id(&self, chain_index: usize) -> Option<&str> {
+        self.chains[chain_index].chain_config.fork_id.as_deref()
+    }
+
     /// Returns the Noise key originally passed as [`Config::noise_key`].
     pub fn noise_key(&self) -> &connection::NoiseKey {
         self.inner.noise_key()
@@ -1309,3 +1332,21 @@ pub enum ProtocolError {
     #[display(fmt = "Error while decoding a received blocks request: {_0}")]
     BadBlocksRequest(protocol::DecodeBlockRequestError),
 }
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn protocol_name_with_and_without_fork_id() {
+        let genesis_hash = [0xab; 32];
+        let hex_hash = hex::encode(genesis_hash);
+
+        assert_eq!(
+            super::protocol_name(&genesis_hash, None, "block-announces/1"),
+            format!("/{hex_hash}/block-announces/1")
+        );
+        assert_eq!(
+            super::protocol_name(&genesis_hash, Some("fork"), "sync/2"),
+            format!("/{hex_hash}/fork/sync/2")
+        );
+    }
+}
@@ -62,32 +62,28 @@ pub(super) fn protocols<'a>(
     chains
         .flat_map(|chain| {
             iter::once(peers::NotificationProtocolConfig {
-                protocol_name: match &chain.fork_id {
-                    Some(fork_id) => {
-                        format!(
-                            "/{}/{}/block-announces/1",
-                            hex::encode(chain.genesis_hash),
-                            fork_id
-                        )
-                    }
-                    None => format!("/{}/block-announces/1", hex::encode(chain.genesis_hash)),
-                },
+                protocol_name: super::protocol_name(
+                    &chain.genesis_hash,
+                    chain.fork_id.as_deref(),
+                    "block-announces/1",
+                ),
                 max_handshake_size: 1024 * 1024, // TODO: arbitrary
                 max_notification_size: 1024 * 1024,
+                max_queued_bytes: 256 * 1024, // TODO: arbitrary
+                priority: peers::SubstreamPriority::High,
             })
             .chain(iter::once(peers::NotificationProtocolConfig {
-                protocol_name: match &chain.fork_id {
-                    Some(fork_id) => {
-                        format!(
-                            "/{}/{}/transactions/1",
-                            hex::encode(chain.genesis_hash),
-                            fork_id
-                        )
-                    }
-                    None => format!("/{}/transactions/1", hex::encode(chain.genesis_hash)),
-                },
+                protocol_name: super::protocol_name(
+                    &chain.genesis_hash,
+                    chain.fork_id.as_deref(),
+                    "transactions/1",
+                ),
                 max_handshake_size: 4,
                 max_notification_size: 16 * 1024 * 1024,
+                // Note that a notification is always accepted if the queue is empty, meaning
+                // that this doesn't prevent sending transactions larger than this limit.
+                max_queued_bytes: 1024 * 1024, // TODO: arbitrary
+                priority: peers::SubstreamPriority::Normal,
             }))
             .chain({
                 // The `has_grandpa_protocol` flag controls whether the chain uses GrandPa.
@@ -95,14 +91,15 @@ pub(super) fn protocols<'a>(
                 // chains, in order to make the rest of the code of this module more
                 // comprehensible.
                 iter::once(peers::NotificationProtocolConfig {
-                    protocol_name: match &chain.fork_id {
-                        Some(fork_id) => {
-                            format!("/{}/{}/grandpa/1", hex::encode(chain.genesis_hash), fork_id)
-                        }
-                        None => format!("/{}/grandpa/1", hex::encode(chain.genesis_hash)),
-                    },
+                    protocol_name: super::protocol_name(
+                        &chain.genesis_hash,
+                        chain.fork_id.as_deref(),
+                        "grandpa/1",
+                    ),
                     max_handshake_size: 4,
                     max_notification_size: 1024 * 1024,
+                    max_queued_bytes: 64 * 1024, // TODO: arbitrary
+                    priority: peers::SubstreamPriority::High,
                 })
             })
         })
@@ -247,10 +244,12 @@ where
                 debug_assert!(self
                     .inner
                     .can_queue_notification(&peer_id, notifications_protocol_index));
+                // Neighbor packets supersede each other.
                 let _ = self.inner.queue_notification(
                     &peer_id,
                     notifications_protocol_index,
                     notification,
+                    true,
                 );
 
                 None
@@ -680,8 +679,11 @@ where
         });
 
         // Now sending out.
-        self.inner
-            .broadcast_notification(chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2, packet);
+        self.inner.broadcast_notification(
+            chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 2,
+            packet,
+            true,
+        );
 
         // Update the locally-stored state, but only after the notification has been broadcasted.
         // This way, if the user cancels the future while `broadcast_notification` is executing,
@@ -720,10 +722,13 @@ where
             a
         });
 
+        // An announcement of a new best block supersedes the announcement of the previous best
+        // block if the latter hasn't been sent out yet.
         self.inner.queue_notification(
             target,
             chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN,
             notification,
+            is_best,
         )
     }
 
@@ -773,6 +778,7 @@ where
             target,
             chain_index * NOTIFICATIONS_PROTOCOLS_PER_CHAIN + 1,
             val,
+            false,
         )
     }
 }
@@ -25,7 +25,7 @@ use crate::network::{kademlia, protocol};
 
 use super::*;
 
-use alloc::{format, vec::Vec};
+use alloc::vec::Vec;
 use core::{
     fmt,
     hash::Hash,
@@ -61,65 +61,48 @@ pub(super) fn protocols<'a>(
         inbound_config: peers::ConfigRequestResponseIn::Empty,
         max_response_size: 4096,
         inbound_allowed: true,
+        priority: peers::SubstreamPriority::Normal,
     })
     .chain(chains.flat_map(|chain| {
         // TODO: limits are arbitrary
         iter::once(peers::ConfigRequestResponse {
-            name: match &chain.fork_id {
-                Some(fork_id) => {
-                    format!("/{}/{}/sync/2", hex::encode(chain.genesis_hash), fork_id)
-                }
-                None => format!("/{}/sync/2", hex::encode(chain.genesis_hash)),
-            },
+            name: super::protocol_name(&chain.genesis_hash, chain.fork_id.as_deref(), "sync/2"),
             inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 1024 },
             max_response_size: 16 * 1024 * 1024,
             inbound_allowed: chain.allow_inbound_block_requests,
+            // Responses can be multiple megabytes large, and must not delay the notifications
+            // sent on the same connection.
+            priority: peers::SubstreamPriority::Low,
         })
         .chain(iter::once(peers::ConfigRequestResponse {
-            name: match &chain.fork_id {
-                Some(fork_id) => {
-                    format!("/{}/{}/light/2", hex::encode(chain.genesis_hash), fork_id)
-                }
-                None => format!("/{}/light/2", hex::encode(chain.genesis_hash)),
-            },
+            name: super::protocol_name(&chain.genesis_hash, chain.fork_id.as_deref(), "light/2"),
             inbound_config: peers::ConfigRequestResponseIn::Payload {
                 max_size: 1024 * 512,
             },
             max_response_size: 10 * 1024 * 1024,
             // TODO: make this configurable
             inbound_allowed: false,
+            priority: peers::SubstreamPriority::Low,
         }))
         .chain(iter::once(peers::ConfigRequestResponse {
-            name: match &chain.fork_id {
-                Some(fork_id) => {
-                    format!("/{}/{}/kad", hex::encode(chain.genesis_hash), fork_id)
-                }
-                None => format!("/{}/kad", hex::encode(chain.genesis_hash)),
-            },
+            name: super::protocol_name(&chain.genesis_hash, chain.fork_id.as_deref(), "kad"),
             inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 1024 },
             max_response_size: 1024 * 1024,
             // TODO: `false` here means we don't insert ourselves in the DHT, which is the polite thing to do for as long as Kad isn't implemented
             inbound_allowed: false,
+            priority: peers::SubstreamPriority::Normal,
         }))
         .chain(iter::once(peers::ConfigRequestResponse {
-            name: match &chain.fork_id {
-                Some(fork_id) => {
-                    format!("/{}/{}/sync/warp", hex::encode(chain.genesis_hash), fork_id)
-                }
-                None => format!("/{}/sync/warp", hex::encode(chain.genesis_hash)),
-            },
+            name: super::protocol_name(&chain.genesis_hash, chain.fork_id.as_deref(), "sync/warp"),
             inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 32 },
             max_response_size: 16 * 1024 * 1024,
             // We don't support inbound warp sync requests (yet).
             inbound_allowed: false,
+            // Warp sync responses can be multiple megabytes large as well.
+            priority: peers::SubstreamPriority::Low,
         }))
         .chain(iter::once(peers::ConfigRequestResponse {
-            name: match &chain.fork_id {
-                Some(fork_id) => {
-                    format!("/{}/{}/state/2", hex::encode(chain.genesis_hash), fork_id)
-                }
-                None => format!("/{}/state/2", hex::encode(chain.genesis_hash)),
-            },
+            name: super::protocol_name(&chain.genesis_hash, chain.fork_id.as_deref(), "state/2"),
             inbound_config: peers::ConfigRequestResponseIn::Payload { max_size: 1024 },
             // The sender tries to cap the response to 2MiB. However, if one storage item
             // is larger than 2MiB, the response is allowed to be bigger, as otherwise it
@@ -127,6 +110,7 @@ pub(super) fn protocols<'a>(
             max_response_size: 16 * 1024 * 1024,
             // We don't support inbound state requests (yet).
             inbound_allowed: false,
+            priority: peers::SubstreamPriority::Low,
         }))
     }))
     .collect()
@@ -38,7 +38,7 @@ use crate::{
     verify,
 };
 
-use alloc::{borrow::Cow, vec::Vec};
+use alloc::{borrow::Cow, collections::BTreeMap, vec::Vec};
 use core::{
     cmp, iter, marker, mem,
     num::{NonZeroU32, NonZeroU64},
@@ -46,8 +46,12 @@ use core::{
     time::Duration,
 };
 
+pub use all_forks::ExternalFinalityProof;
 pub use optimistic::TrieEntryVersion;
-pub use warp_sync::{FragmentError as WarpSyncFragmentError, WarpSyncFragment};
+pub use warp_sync::{
+    FragmentError as WarpSyncFragmentError, JustificationVerified as WarpSyncJustificationVerified,
+    JustificationVerify as WarpSyncJustificationVerify, WarpSyncFragment,
+};
 
 /// Configuration for the [`AllSync`].
 // TODO: review these fields
@@ -69,6 +73,14 @@ pub struct Config {
     /// the network is bounded.
     pub allow_unknown_consensus_engines: bool,
 
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. All the other checks are still performed.
+    ///
+    /// > **Note**: This is meant to be used when benchmarking, or when importing a chain whose
+    /// >           blocks are already known to be valid. Passing `true` makes it possible for
+    /// >           anyone to author blocks that are considered as valid.
+    pub skip_seal_verification: bool,
+
     /// Pre-allocated capacity for the number of block sources.
     pub sources_capacity: usize,
 
@@ -88,6 +100,32 @@ pub struct Config {
     /// See [`all_forks::Config::max_requests_per_block`] for more information.
     pub max_requests_per_block: NonZeroU32,
 
+    /// Hashes of blocks that are known to be invalid. Blocks whose hash is in this list, and
+    /// consequently all of their descendants, are never considered as valid.
+    ///
+    /// The values of the `badBlocks` field of chain specifications should be passed here.
+    pub bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed. Blocks at one
+    /// of these heights but with a different hash, and consequently all of their descendants,
+    /// are never considered as valid.
+    ///
+    /// The values of the `forkBlocks` field of chain specifications should be passed here.
+    pub fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// Source of the current time. Used in order to verify that blocks don't pretend to come
+    /// from the future, and as the value of the timestamp inherent when verifying block bodies.
+    ///
+    /// Use [`verify::Clock::fixed`] in order to replay historical blocks in a reproducible way.
+    pub clock: verify::Clock,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block. Blocks that pretend to come from further in the future than this
+    /// tolerance are rejected.
+    ///
+    /// Use [`verify::DEFAULT_CLOCK_DRIFT_TOLERANCE`] if in doubt.
+    pub clock_drift_tolerance: Duration,
+
     /// Number of blocks to download ahead of the best verified block.
     ///
     /// Whenever the latest best block is updated, the state machine will start block
@@ -99,6 +137,12 @@ pub struct Config {
     /// block requests.
     pub download_ahead_blocks: NonZeroU32,
 
+    /// Minimum number of sources that must be known before a warp sync proof is requested.
+    /// Ignored if [`Config::full`] is `Some`.
+    ///
+    /// See [`warp_sync::Config::min_distinct_sources`].
+    pub warp_sync_min_distinct_sources: NonZeroU32,
+
     /// If `Some`, the block bodies and storage are also synchronized. Contains the extra
     /// configuration.
     pub full: Option<ConfigFull>,
@@ -176,6 +220,11 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                         block_number_bytes: config.block_number_bytes,
                         sources_capacity: config.sources_capacity,
                         blocks_capacity: config.blocks_capacity,
+                        skip_seal_verification: config.skip_seal_verification,
+                        bad_blocks: config.bad_blocks.clone(),
+                        fork_blocks: config.fork_blocks.clone(),
+                        clock: config.clock.clone(),
+                        clock_drift_tolerance: config.clock_drift_tolerance,
                         download_ahead_blocks: config.download_ahead_blocks,
                         full: Some(optimistic::ConfigFull {
                             finalized_runtime: config_full.finalized_runtime,
@@ -188,6 +237,7 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                     block_number_bytes: config.block_number_bytes,
                     sources_capacity: config.sources_capacity,
                     requests_capacity: config.sources_capacity, // TODO: ?! add as config?
+                    min_distinct_sources: config.warp_sync_min_distinct_sources,
                 }) {
                     Ok(inner) => AllSyncInner::GrandpaWarpSync { inner },
                     Err((
@@ -203,6 +253,11 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                                 block_number_bytes: config.block_number_bytes,
                                 sources_capacity: config.sources_capacity,
                                 blocks_capacity: config.blocks_capacity,
+                                skip_seal_verification: config.skip_seal_verification,
+                                bad_blocks: config.bad_blocks.clone(),
+                                fork_blocks: config.fork_blocks.clone(),
+                                clock: config.clock.clone(),
+                                clock_drift_tolerance: config.clock_drift_tolerance,
                                 download_ahead_blocks: config.download_ahead_blocks,
                                 full: None,
                             }),
@@ -220,6 +275,11 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
                 max_requests_per_block: config.max_requests_per_block,
                 block_number_bytes: config.block_number_bytes,
                 allow_unknown_consensus_engines: config.allow_unknown_consensus_engines,
+                skip_seal_verification: config.skip_seal_verification,
+                bad_blocks: config.bad_blocks,
+                fork_blocks: config.fork_blocks,
+                clock: config.clock,
+                clock_drift_tolerance: config.clock_drift_tolerance,
             },
         }
     }
@@ -339,9 +399,12 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
     /// Returns consensus information about the current best block of the chain.
     pub fn best_block_consensus(&self) -> chain_information::ChainInformationConsensusRef {
         match &self.inner {
-            AllSyncInner::AllForks(_) => todo!(), // TODO:
+            AllSyncInner::AllForks(sync) => sync.best_block_consensus(),
             AllSyncInner::Optimistic { inner } => inner.best_block_consensus(),
-            AllSyncInner::GrandpaWarpSync { .. } => todo!(), // TODO: ?!
+            // During the warp syncing, the best block is the finalized block.
+            AllSyncInner::GrandpaWarpSync { inner: sync } => {
+                sync.as_chain_information().as_ref().consensus
+            }
             AllSyncInner::Poisoned => unreachable!(),
         }
     }
@@ -1153,70 +1216,30 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
     /// [`AllSync`] is yielded back at the end of this process.
     pub fn process_one(mut self) -> ProcessOne<TRq, TSrc, TBl> {
         match self.inner {
-            AllSyncInner::GrandpaWarpSync { inner } => {
-                match inner.process_one() {
-                    warp_sync::ProcessOne::Idle(inner) => {
-                        self.inner = AllSyncInner::GrandpaWarpSync { inner };
-                        ProcessOne::AllSync(self)
-                    }
-                    warp_sync::ProcessOne::VerifyWarpSyncFragment(inner) => {
-                        ProcessOne::VerifyWarpSyncFragment(WarpSyncFragmentVerify {
-                            inner,
-                            shared: self.shared,
-                            marker: marker::PhantomData,
-                        })
-                    }
-                    warp_sync::ProcessOne::BuildRuntime(inner) => {
-                        // TODO: errors not reported to upper layer
-                        // TODO: make these parameters configurable
-                        match inner.build(ExecHint::CompileAheadOfTime, false).0 {
-                            warp_sync::WarpSync::InProgress(inner) => {
-                                self.inner = AllSyncInner::GrandpaWarpSync { inner };
-                                ProcessOne::AllSync(self)
-                            }
-                            warp_sync::WarpSync::Finished(success) => {
-                                let (
-                                    new_inner,
-                                    finalized_block_runtime,
-                                    finalized_storage_code,
-                                    finalized_storage_heap_pages,
-                                ) = self.shared.transition_grandpa_warp_sync_all_forks(success);
-                                self.inner = AllSyncInner::AllForks(new_inner);
-                                ProcessOne::WarpSyncFinished {
-                                    sync: self,
-                                    finalized_block_runtime,
-                                    finalized_storage_code,
-                                    finalized_storage_heap_pages,
-                                }
-                            }
-                        }
-                    }
-                    warp_sync::ProcessOne::BuildChainInformation(inner) => {
-                        match inner.build().0 {
-                            // TODO: errors not reported to upper layer
-                            warp_sync::WarpSync::InProgress(inner) => {
-                                self.inner = AllSyncInner::GrandpaWarpSync { inner };
-                                ProcessOne::AllSync(self)
-                            }
-                            warp_sync::WarpSync::Finished(success) => {
-                                let (
-                                    new_inner,
-                                    finalized_block_runtime,
-                                    finalized_storage_code,
-                                    finalized_storage_heap_pages,
-                                ) = self.shared.transition_grandpa_warp_sync_all_forks(success);
-                                self.inner = AllSyncInner::AllForks(new_inner);
-                                ProcessOne::WarpSyncFinished {
-                                    sync: self,
-                                    finalized_block_runtime,
-                                    finalized_storage_code,
-                                    finalized_storage_heap_pages,
-                                }
-                            }
-                        }
-                    }
+            AllSyncInner::GrandpaWarpSync { inner } => match inner.process_one() {
+                warp_sync::ProcessOne::Idle(inner) => {
+                    self.inner = AllSyncInner::GrandpaWarpSync { inner };
+                    ProcessOne::AllSync(self)
                 }
-            }
+                warp_sync::ProcessOne::VerifyWarpSyncFragment(inner) => {
+                    ProcessOne::VerifyWarpSyncFragment(WarpSyncFragmentVerify {
+                        inner,
+                        shared: self.shared,
+                        marker: marker::PhantomData,
+                    })
+                }
+                warp_sync::ProcessOne::BuildRuntime(inner) => {
+                    ProcessOne::WarpSyncBuildRuntime(WarpSyncBuildRuntime {
+                        inner,
+                        shared: self.shared,
+                        marker: marker::PhantomData,
+                    })
+                }
+                warp_sync::ProcessOne::BuildChainInformation(inner) => {
+                    let (warp_sync, error) = inner.build();
+                    self.shared.into_warp_sync_process_outcome(warp_sync, error)
+                }
+            },
             AllSyncInner::AllForks(sync) => match sync.process_one() {
                 all_forks::ProcessOne::AllSync { sync } => {
                     self.inner = AllSyncInner::AllForks(sync);
@@ -1371,6 +1394,33 @@ impl<TRq, TSrc, TBl> AllSync<TRq, TSrc, TBl> {
         }
     }
 
+    /// Verifies a finality proof obtained through other means than the sources of the state
+    /// machine, for example from a trusted archive, and applies it to the chain.
+    ///
+    /// Contrary to the finality proofs sent by sources, the proof is verified immediately. A
+    /// GrandPa commit that can't be verified yet, because its target block isn't known yet, is
+    /// discarded and [`FinalityProofVerifyOutcome::GrandpaCommitPending`] is returned. It can be
+    /// injected again later.
+    ///
+    /// Returns `None` if the state machine is in a mode where it can't verify finality proofs,
+    /// such as during the GrandPa warp syncing. The proof is then discarded.
+    ///
+    /// A randomness seed must be provided and will be used during the verification. Note that the
+    /// verification is nonetheless deterministic.
+    pub fn verify_external_finality_proof(
+        &mut self,
+        finality_proof: ExternalFinalityProof,
+        randomness_seed: [u8; 32],
+    ) -> Option<FinalityProofVerifyOutcome<TBl>> {
+        match &mut self.inner {
+            AllSyncInner::AllForks(sync) => Some(all_forks_finality_proof_outcome_convert(
+                sync.verify_external_finality_proof(finality_proof, randomness_seed),
+            )),
+            AllSyncInner::Optimistic { .. } | AllSyncInner::GrandpaWarpSync { .. } => None,
+            AllSyncInner::Poisoned => unreachable!(),
+        }
+    }
+
     /// Inject a response to a previously-emitted blocks request.
     ///
     /// # Panic
@@ -2044,6 +2094,11 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
 
         /// Storage value at the `:heappages` key of the finalized block.
         finalized_storage_heap_pages: Option<Vec<u8>>,
+
+        /// List of the warp sync fragments that have been verified in order to reach the newly
+        /// finalized block, in order. Each fragment contains a block header that enacts a change
+        /// in the list of GrandPa authorities, and a justification proving its finality.
+        verified_fragments: Vec<WarpSyncFragment>,
     },
 
     /// Ready to start verifying a header.
@@ -2057,6 +2112,9 @@ pub enum ProcessOne<TRq, TSrc, TBl> {
 
     /// Ready to start verifying a warp sync fragment.
     VerifyWarpSyncFragment(WarpSyncFragmentVerify<TRq, TSrc, TBl>),
+
+    /// Ready to build the runtime of the block that the warp sync has reached.
+    WarpSyncBuildRuntime(WarpSyncBuildRuntime<TRq, TSrc, TBl>),
 }
 
 /// Outcome of injecting a response in the [`AllSync`].
@@ -2119,8 +2177,13 @@ pub struct BlockFull {
     /// Changes to the storage made by this block compared to its parent.
     pub storage_main_trie_changes: storage_diff::TrieDiff,
 
+    /// Changes to the default child tries made by this block compared to its parent. Keys are
+    /// the names of the child tries, without the `:child_storage:default:` prefix.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
+
     /// State trie version indicated by the runtime. All the storage changes indicated by
-    /// [`BlockFull::storage_main_trie_changes`] should store this version alongside with them.
+    /// [`BlockFull::storage_main_trie_changes`] and [`BlockFull::storage_child_tries_changes`]
+    /// should store this version alongside with them.
     pub state_trie_version: TrieEntryVersion,
 
     /// List of changes to the off-chain storage that this block performs.
@@ -2154,17 +2217,15 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
     }
 
     /// Perform the verification.
-    pub fn perform(
-        self,
-        now_from_unix_epoch: Duration,
-        user_data: TBl,
-    ) -> HeaderVerifyOutcome<TRq, TSrc, TBl> {
+    ///
+    /// The current time is obtained from [`Config::clock`].
+    pub fn perform(self, user_data: TBl) -> HeaderVerifyOutcome<TRq, TSrc, TBl> {
         match self.inner {
             HeaderVerifyInner::AllForks(verify) => {
                 let verified_block_height = verify.height();
                 let verified_block_hash = *verify.hash();
 
-                match verify.perform(now_from_unix_epoch) {
+                match verify.perform() {
                     all_forks::HeaderVerifyOutcome::Success {
                         is_new_best,
                         mut sync,
@@ -2196,6 +2257,12 @@ impl<TRq, TSrc, TBl> HeaderVerify<TRq, TSrc, TBl> {
                                 all_forks::HeaderVerifyError::ConsensusMismatch => {
                                     HeaderVerifyError::ConsensusMismatch
                                 }
+                                all_forks::HeaderVerifyError::BadBlock => {
+                                    HeaderVerifyError::BadBlock
+                                }
+                                all_forks::HeaderVerifyError::ForkBlockMismatch {
+                                    expected_hash,
+                                } => HeaderVerifyError::ForkBlockMismatch { expected_hash },
                             },
                             user_data,
                         }
@@ -2234,6 +2301,16 @@ pub enum HeaderVerifyError {
     UnknownConsensusEngine,
     /// Block uses a different consensus than the rest of the chain.
     ConsensusMismatch,
+    /// The hash of the block is in the list of bad blocks. See [`Config::bad_blocks`].
+    #[display(fmt = "The block is in the list of bad blocks.")]
+    BadBlock,
+    /// The height of the block is in the list of fork blocks, but the hash of the block doesn't
+    /// match. See [`Config::fork_blocks`].
+    #[display(fmt = "The hash of the block doesn't match the one in the list of fork blocks.")]
+    ForkBlockMismatch {
+        /// Hash that the block at this height is expected to have.
+        expected_hash: [u8; 32],
+    },
     /// The block verification has failed. The block is invalid and should be thrown away.
     #[display(fmt = "{_0}")]
     VerificationFailed(verify::header_only::Error),
@@ -2273,41 +2350,8 @@ impl<TRq, TSrc, TBl> FinalityProofVerify<TRq, TSrc, TBl> {
     ) -> (AllSync<TRq, TSrc, TBl>, FinalityProofVerifyOutcome<TBl>) {
         match self.inner {
             FinalityProofVerifyInner::AllForks(verify) => {
-                let (sync, outcome) = match verify.perform(randomness_seed) {
-                    (
-                        sync,
-                        all_forks::FinalityProofVerifyOutcome::NewFinalized {
-                            finalized_blocks,
-                            updates_best_block,
-                        },
-                    ) => (
-                        sync,
-                        FinalityProofVerifyOutcome::NewFinalized {
-                            finalized_blocks: finalized_blocks
-                                .into_iter()
-                                .map(|b| Block {
-                                    full: None, // TODO: wrong
-                                    header: b.0,
-                                    justifications: Vec::new(), // TODO: wrong
-                                    user_data: b.1.unwrap(),
-                                })
-                                .collect(),
-                            updates_best_block,
-                        },
-                    ),
-                    (sync, all_forks::FinalityProofVerifyOutcome::AlreadyFinalized) => {
-                        (sync, FinalityProofVerifyOutcome::AlreadyFinalized)
-                    }
-                    (sync, all_forks::FinalityProofVerifyOutcome::GrandpaCommitPending) => {
-                        (sync, FinalityProofVerifyOutcome::GrandpaCommitPending)
-                    }
-                    (sync, all_forks::FinalityProofVerifyOutcome::JustificationError(error)) => {
-                        (sync, FinalityProofVerifyOutcome::JustificationError(error))
-                    }
-                    (sync, all_forks::FinalityProofVerifyOutcome::GrandpaCommitError(error)) => {
-                        (sync, FinalityProofVerifyOutcome::GrandpaCommitError(error))
-                    }
-                };
+                let (sync, outcome) = verify.perform(randomness_seed);
+                let outcome = all_forks_finality_proof_outcome_convert(outcome);
 
                 (
                     AllSync {
@@ -2318,7 +2362,14 @@ impl<TRq, TSrc, TBl> FinalityProofVerify<TRq, TSrc, TBl> {
                 )
             }
             FinalityProofVerifyInner::Optimistic(verify) => match verify.perform(randomness_seed) {
-                (inner, optimistic::JustificationVerification::Finalized { finalized_blocks }) => (
+                (
+                    inner,
+                    optimistic::JustificationVerification::Finalized {
+                        finalized_blocks,
+                        consensus_engine_id,
+                        scale_encoded_justification,
+                    },
+                ) => (
                     // TODO: transition to all_forks
                     AllSync {
                         inner: AllSyncInner::Optimistic { inner },
@@ -2335,11 +2386,16 @@ impl<TRq, TSrc, TBl> FinalityProofVerify<TRq, TSrc, TBl> {
                                     body: b.body,
                                     offchain_storage_changes: b.offchain_storage_changes,
                                     storage_main_trie_changes: b.storage_main_trie_changes,
+                                    storage_child_tries_changes: b.storage_child_tries_changes,
                                     state_trie_version: b.state_trie_version,
                                 }),
                             })
                             .collect(),
                         updates_best_block: false,
+                        finality_proof: ExternalFinalityProof::Justification {
+                            consensus_engine_id,
+                            scale_encoded_justification,
+                        },
                     },
                 ),
                 (inner, optimistic::JustificationVerification::Reset { error, .. }) => (
@@ -2354,6 +2410,42 @@ impl<TRq, TSrc, TBl> FinalityProofVerify<TRq, TSrc, TBl> {
     }
 }
 
+fn all_forks_finality_proof_outcome_convert<TBl>(
+    outcome: all_forks::FinalityProofVerifyOutcome<Option<TBl>>,
+) -> FinalityProofVerifyOutcome<TBl> {
+    match outcome {
+        all_forks::FinalityProofVerifyOutcome::NewFinalized {
+            finalized_blocks,
+            updates_best_block,
+            finality_proof,
+        } => FinalityProofVerifyOutcome::NewFinalized {
+            finalized_blocks: finalized_blocks
+                .into_iter()
+                .map(|b| Block {
+                    full: None, // TODO: wrong
+                    header: b.0,
+                    justifications: Vec::new(), // TODO: wrong
+                    user_data: b.1.unwrap(),
+                })
+                .collect(),
+            updates_best_block,
+            finality_proof,
+        },
+        all_forks::FinalityProofVerifyOutcome::AlreadyFinalized => {
+            FinalityProofVerifyOutcome::AlreadyFinalized
+        }
+        all_forks::FinalityProofVerifyOutcome::GrandpaCommitPending => {
+            FinalityProofVerifyOutcome::GrandpaCommitPending
+        }
+        all_forks::FinalityProofVerifyOutcome::JustificationError(error) => {
+            FinalityProofVerifyOutcome::JustificationError(error)
+        }
+        all_forks::FinalityProofVerifyOutcome::GrandpaCommitError(error) => {
+            FinalityProofVerifyOutcome::GrandpaCommitError(error)
+        }
+    }
+}
+
 /// Information about the outcome of verifying a finality proof.
 #[derive(Debug)]
 pub enum FinalityProofVerifyOutcome<TBl> {
@@ -2366,10 +2458,15 @@ pub enum FinalityProofVerifyOutcome<TBl> {
         /// This can happen if the previous best block isn't a descendant of the now finalized
         /// block.
         updates_best_block: bool,
+        /// Finality proof that has been verified. Proves the finality of the block with the
+        /// highest number in [`FinalityProofVerifyOutcome::NewFinalized::finalized_blocks`] and,
+        /// as such, of all the other ones.
+        finality_proof: ExternalFinalityProof,
     },
     /// Finality proof concerns block that was already finalized.
     AlreadyFinalized,
-    /// GrandPa commit cannot be verified yet and has been stored for later.
+    /// GrandPa commit cannot be verified yet and has been stored for later, or discarded if it
+    /// has been passed to [`AllSync::verify_external_finality_proof`].
     GrandpaCommitPending,
     /// Problem while verifying justification.
     JustificationError(blocks_tree::JustificationVerifyError),
@@ -2414,6 +2511,54 @@ impl<TRq, TSrc, TBl> WarpSyncFragmentVerify<TRq, TSrc, TBl> {
             error.map_or(Ok(()), Result::Err),
         )
     }
+
+    /// Returns the verifications of the signatures of the justifications of up to `max`
+    /// fragments that haven't been verified yet.
+    ///
+    /// These verifications are CPU-intensive and independent from each other, and can be
+    /// performed concurrently, for example on multiple threads. Their outcome must then be
+    /// passed to [`WarpSyncFragmentVerify::inject_justification_verified`] before calling
+    /// [`WarpSyncFragmentVerify::perform`], which no longer verifies these signatures.
+    pub fn justification_verifications(&self, max: usize) -> Vec<WarpSyncJustificationVerify> {
+        self.inner.justification_verifications(max)
+    }
+
+    /// Stores the outcome of a successful verification obtained through
+    /// [`WarpSyncFragmentVerify::justification_verifications`].
+    pub fn inject_justification_verified(&mut self, verified: WarpSyncJustificationVerified) {
+        self.inner.inject_justification_verified(verified)
+    }
+}
+
+/// Ready to build the runtime of the block that the warp sync has reached.
+///
+/// Building the runtime is a CPU-intensive operation that can take a long time. It is
+/// recommended to perform it in the background, for example on a different thread.
+#[must_use]
+pub struct WarpSyncBuildRuntime<TRq, TSrc, TBl> {
+    inner:
+        warp_sync::BuildRuntime<GrandpaWarpSyncSourceExtra<TSrc>, GrandpaWarpSyncRequestExtra<TRq>>,
+    shared: Shared<TRq>,
+    marker: marker::PhantomData<Vec<TBl>>,
+}
+
+impl<TRq, TSrc, TBl> WarpSyncBuildRuntime<TRq, TSrc, TBl> {
+    /// Builds the runtime.
+    ///
+    /// Must be passed parameters used for the construction of the runtime: a hint as to whether
+    /// the runtime is trusted and/or will be executed again, and whether unresolved function
+    /// imports are allowed.
+    ///
+    /// Returns either [`ProcessOne::AllSync`], [`ProcessOne::WarpSyncError`], or
+    /// [`ProcessOne::WarpSyncFinished`].
+    pub fn build(
+        self,
+        exec_hint: ExecHint,
+        allow_unresolved_imports: bool,
+    ) -> ProcessOne<TRq, TSrc, TBl> {
+        let (warp_sync, error) = self.inner.build(exec_hint, allow_unresolved_imports);
+        self.shared.into_warp_sync_process_outcome(warp_sync, error)
+    }
 }
 
 pub struct HeaderBodyVerify<TRq, TSrc, TBl> {
@@ -2450,17 +2595,13 @@ impl<TRq, TSrc, TBl> HeaderBodyVerify<TRq, TSrc, TBl> {
     }
 
     /// Start the verification process.
-    pub fn start(
-        self,
-        now_from_unix_epoch: Duration,
-        user_data: TBl,
-    ) -> BlockVerification<TRq, TSrc, TBl> {
+    ///
+    /// The current time is obtained from [`Config::clock`].
+    pub fn start(self, user_data: TBl) -> BlockVerification<TRq, TSrc, TBl> {
         match self.inner {
-            HeaderBodyVerifyInner::Optimistic(verify) => BlockVerification::from_inner(
-                verify.start(now_from_unix_epoch),
-                self.shared,
-                user_data,
-            ),
+            HeaderBodyVerifyInner::Optimistic(verify) => {
+                BlockVerification::from_inner(verify.start(), self.shared, user_data)
+            }
         }
     }
 }
@@ -2496,6 +2637,14 @@ pub enum BlockVerification<TRq, TSrc, TBl> {
     /// order to continue.
     FinalizedStorageNextKey(StorageNextKey<TRq, TSrc, TBl>),
 
+    /// Loading a storage value of a child trie of the finalized block is required in order to
+    /// continue.
+    FinalizedChildStorageGet(ChildStorageGet<TRq, TSrc, TBl>),
+
+    /// Fetching the list of all the keys of a child trie of the finalized block is required in
+    /// order to continue.
+    FinalizedChildStorageRoot(ChildStorageRoot<TRq, TSrc, TBl>),
+
     /// Compiling a runtime is required in order to continue.
     RuntimeCompilation(RuntimeCompilation<TRq, TSrc, TBl>),
 }
@@ -2580,6 +2729,20 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                     user_data,
                 })
             }
+            optimistic::BlockVerification::FinalizedChildStorageGet(inner) => {
+                BlockVerification::FinalizedChildStorageGet(ChildStorageGet {
+                    inner,
+                    shared,
+                    user_data,
+                })
+            }
+            optimistic::BlockVerification::FinalizedChildStorageRoot(inner) => {
+                BlockVerification::FinalizedChildStorageRoot(ChildStorageRoot {
+                    inner,
+                    shared,
+                    user_data,
+                })
+            }
             optimistic::BlockVerification::RuntimeCompilation(inner) => {
                 BlockVerification::RuntimeCompilation(RuntimeCompilation {
                     inner,
@@ -2628,6 +2791,12 @@ pub struct StoragePrefixKeys<TRq, TSrc, TBl> {
 }
 
 impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix, or `None` if the keys of the main trie must be loaded.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the prefix whose keys to load.
     pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.prefix()
@@ -2653,6 +2822,12 @@ pub struct StorageNextKey<TRq, TSrc, TBl> {
 }
 
 impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix, or `None` if it belongs to the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.key()
     }
@@ -2669,6 +2844,63 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
     }
 }
 
+/// Loading a storage value of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageGet<TRq, TSrc, TBl> {
+    inner:
+        optimistic::ChildStorageGet<OptimisticRequestExtra<TRq>, OptimisticSourceExtra<TSrc>, TBl>,
+    shared: Shared<TRq>,
+    user_data: TBl,
+}
+
+impl<TRq, TSrc, TBl> ChildStorageGet<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Returns the key whose value must be passed to [`ChildStorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.key()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(&[u8], TrieEntryVersion)>,
+    ) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_value(value);
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
+}
+
+/// Fetching the list of all the keys of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageRoot<TRq, TSrc, TBl> {
+    inner:
+        optimistic::ChildStorageRoot<OptimisticRequestExtra<TRq>, OptimisticSourceExtra<TSrc>, TBl>,
+    shared: Shared<TRq>,
+    user_data: TBl,
+}
+
+impl<TRq, TSrc, TBl> ChildStorageRoot<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Injects the list of all the keys of the child trie, ordered lexicographically.
+    pub fn inject_keys_ordered(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_keys_ordered(keys);
+        BlockVerification::from_inner(inner, self.shared, self.user_data)
+    }
+}
+
 /// Compiling a new runtime is necessary as part of the verification.
 #[must_use]
 pub struct RuntimeCompilation<TRq, TSrc, TBl> {
@@ -2762,9 +2994,61 @@ struct Shared<TRq> {
     block_number_bytes: usize,
     /// Value passed through [`Config::allow_unknown_consensus_engines`].
     allow_unknown_consensus_engines: bool,
+    /// Value passed through [`Config::skip_seal_verification`].
+    skip_seal_verification: bool,
+    /// Value passed through [`Config::bad_blocks`].
+    bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    /// Value passed through [`Config::fork_blocks`].
+    fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+    /// Value passed through [`Config::clock`].
+    clock: verify::Clock,
+    /// Value passed through [`Config::clock_drift_tolerance`].
+    clock_drift_tolerance: Duration,
 }
 
 impl<TRq> Shared<TRq> {
+    /// Builds the [`ProcessOne`] corresponding to the outcome of a step of the warp sync.
+    fn into_warp_sync_process_outcome<TSrc, TBl>(
+        mut self,
+        warp_sync: warp_sync::WarpSync<
+            GrandpaWarpSyncSourceExtra<TSrc>,
+            GrandpaWarpSyncRequestExtra<TRq>,
+        >,
+        error: Option<warp_sync::Error>,
+    ) -> ProcessOne<TRq, TSrc, TBl> {
+        match warp_sync {
+            warp_sync::WarpSync::InProgress(inner) => {
+                let sync = AllSync {
+                    inner: AllSyncInner::GrandpaWarpSync { inner },
+                    shared: self,
+                };
+                match error {
+                    Some(error) => ProcessOne::WarpSyncError { sync, error },
+                    None => ProcessOne::AllSync(sync),
+                }
+            }
+            warp_sync::WarpSync::Finished(success) => {
+                let (
+                    new_inner,
+                    finalized_block_runtime,
+                    finalized_storage_code,
+                    finalized_storage_heap_pages,
+                    verified_fragments,
+                ) = self.transition_grandpa_warp_sync_all_forks(success);
+                ProcessOne::WarpSyncFinished {
+                    sync: AllSync {
+                        inner: AllSyncInner::AllForks(new_inner),
+                        shared: self,
+                    },
+                    finalized_block_runtime,
+                    finalized_storage_code,
+                    finalized_storage_heap_pages,
+                    verified_fragments,
+                }
+            }
+        }
+    }
+
     /// Transitions the sync state machine from the grandpa warp strategy to the "all-forks"
     /// strategy.
     fn transition_grandpa_warp_sync_all_forks<TSrc, TBl>(
@@ -2778,6 +3062,7 @@ impl<TRq> Shared<TRq> {
         host::HostVmPrototype,
         Option<Vec<u8>>,
         Option<Vec<u8>>,
+        Vec<WarpSyncFragment>,
     ) {
         let mut all_forks = all_forks::AllForksSync::new(all_forks::Config {
             chain_information: grandpa.chain_information,
@@ -2787,6 +3072,11 @@ impl<TRq> Shared<TRq> {
             max_disjoint_headers: self.max_disjoint_headers,
             max_requests_per_block: self.max_requests_per_block,
             allow_unknown_consensus_engines: self.allow_unknown_consensus_engines,
+            skip_seal_verification: self.skip_seal_verification,
+            bad_blocks: self.bad_blocks.clone(),
+            fork_blocks: self.fork_blocks.clone(),
+            clock: self.clock.clone(),
+            clock_drift_tolerance: self.clock_drift_tolerance,
             full: false,
         });
 
@@ -2877,6 +3167,7 @@ impl<TRq> Shared<TRq> {
             grandpa.finalized_runtime,
             grandpa.finalized_storage_code,
             grandpa.finalized_storage_heap_pages,
+            grandpa.verified_fragments,
         )
     }
 }
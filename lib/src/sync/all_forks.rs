@@ -117,6 +117,12 @@ pub struct Config {
     /// the network is bounded.
     pub allow_unknown_consensus_engines: bool,
 
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified.
+    ///
+    /// See [`blocks_tree::Config::skip_seal_verification`] for more information.
+    pub skip_seal_verification: bool,
+
     /// Pre-allocated capacity for the number of block sources.
     pub sources_capacity: usize,
 
@@ -159,6 +165,27 @@ pub struct Config {
     /// The higher the value, the more bandwidth is potentially wasted.
     pub max_requests_per_block: NonZeroU32,
 
+    /// Hashes of blocks that are known to be invalid.
+    ///
+    /// See [`blocks_tree::Config::bad_blocks`] for more information.
+    pub bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed.
+    ///
+    /// See [`blocks_tree::Config::fork_blocks`] for more information.
+    pub fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// Source of the current time.
+    ///
+    /// See [`blocks_tree::Config::clock`] for more information.
+    pub clock: verify::Clock,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block.
+    ///
+    /// See [`blocks_tree::Config::clock_drift_tolerance`] for more information.
+    pub clock_drift_tolerance: Duration,
+
     /// If true, the block bodies and storage are also synchronized.
     pub full: bool,
 }
@@ -421,6 +448,11 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
             block_number_bytes: config.block_number_bytes,
             blocks_capacity: config.blocks_capacity,
             allow_unknown_consensus_engines: config.allow_unknown_consensus_engines,
+            skip_seal_verification: config.skip_seal_verification,
+            bad_blocks: config.bad_blocks,
+            fork_blocks: config.fork_blocks,
+            clock: config.clock,
+            clock_drift_tolerance: config.clock_drift_tolerance,
         });
 
         Self {
@@ -480,6 +512,11 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         self.chain.best_block_hash()
     }
 
+    /// Returns consensus information about the current best block of the chain.
+    pub fn best_block_consensus(&self) -> chain_information::ChainInformationConsensusRef<'_> {
+        self.chain.best_block_consensus()
+    }
+
     /// Returns the header of all known non-finalized blocks in the chain without any specific
     /// order.
     pub fn non_finalized_blocks_unordered(
@@ -940,6 +977,144 @@ impl<TBl, TRq, TSrc> AllForksSync<TBl, TRq, TSrc> {
         GrandpaCommitMessageOutcome::Queued
     }
 
+    /// Verifies a finality proof obtained through other means than the sources of the state
+    /// machine, for example from a trusted archive, and applies it to the chain.
+    ///
+    /// Contrary to the finality proofs sent by sources, the proof is verified immediately. A
+    /// GrandPa commit that can't be verified yet, because its target block isn't known yet, is
+    /// discarded and [`FinalityProofVerifyOutcome::GrandpaCommitPending`] is returned. It can be
+    /// injected again later.
+    ///
+    /// A randomness seed must be provided and will be used during the verification. Note that the
+    /// verification is nonetheless deterministic.
+    pub fn verify_external_finality_proof(
+        &mut self,
+        finality_proof: ExternalFinalityProof,
+        randomness_seed: [u8; 32],
+    ) -> FinalityProofVerifyOutcome<TBl> {
+        let finality_proof = match finality_proof {
+            ExternalFinalityProof::Justification {
+                consensus_engine_id,
+                scale_encoded_justification,
+            } => FinalityProof::Justification((consensus_engine_id, scale_encoded_justification)),
+            ExternalFinalityProof::GrandpaCommit(scale_encoded_commit) => {
+                FinalityProof::GrandpaCommit(scale_encoded_commit)
+            }
+        };
+
+        self.verify_finality_proof(None, finality_proof, randomness_seed)
+    }
+
+    /// Verifies the given finality proof and applies it to the chain.
+    ///
+    /// If `source_id` is `Some` and the proof is a commit that can't be verified yet, it is
+    /// stored in the source in order to be verified later. If `source_id` is `None`, it is
+    /// discarded.
+    fn verify_finality_proof(
+        &mut self,
+        source_id: Option<SourceId>,
+        finality_proof: FinalityProof,
+        randomness_seed: [u8; 32],
+    ) -> FinalityProofVerifyOutcome<TBl> {
+        match finality_proof {
+            FinalityProof::GrandpaCommit(scale_encoded_commit) => {
+                match self
+                    .chain
+                    .verify_grandpa_commit_message(&scale_encoded_commit, randomness_seed)
+                {
+                    Ok(success) => {
+                        // TODO: DRY
+                        let finalized_blocks_iter = success.apply();
+                        let updates_best_block = finalized_blocks_iter.updates_best_block();
+                        let finalized_blocks = finalized_blocks_iter
+                            .map(|b| (b.header, b.user_data))
+                            .collect::<Vec<_>>();
+                        let _finalized_blocks = self
+                            .inner
+                            .blocks
+                            .set_finalized_block_height(finalized_blocks.last().unwrap().0.number);
+                        FinalityProofVerifyOutcome::NewFinalized {
+                            finalized_blocks,
+                            updates_best_block,
+                            finality_proof: ExternalFinalityProof::GrandpaCommit(
+                                scale_encoded_commit,
+                            ),
+                        }
+                    }
+                    // In case where the commit message concerns a block older or equal to the
+                    // finalized block, the operation is silently considered successful.
+                    Err(blocks_tree::CommitVerifyError::FinalityVerify(
+                        blocks_tree::FinalityVerifyError::EqualToFinalized
+                        | blocks_tree::FinalityVerifyError::BelowFinalized,
+                    )) => FinalityProofVerifyOutcome::AlreadyFinalized,
+                    Err(
+                        blocks_tree::CommitVerifyError::FinalityVerify(
+                            blocks_tree::FinalityVerifyError::UnknownTargetBlock {
+                                block_number,
+                                ..
+                            },
+                        )
+                        | blocks_tree::CommitVerifyError::FinalityVerify(
+                            blocks_tree::FinalityVerifyError::TooFarAhead {
+                                justification_block_number: block_number,
+                                ..
+                            },
+                        )
+                        | blocks_tree::CommitVerifyError::NotEnoughKnownBlocks {
+                            target_block_number: block_number,
+                        },
+                    ) => {
+                        if let Some(source_id) = source_id {
+                            self.inner.blocks[source_id].pending_finality_proofs.insert(
+                                block_number,
+                                FinalityProofs::GrandpaCommit(scale_encoded_commit),
+                            );
+                        }
+                        FinalityProofVerifyOutcome::GrandpaCommitPending
+                    }
+                    Err(err) => FinalityProofVerifyOutcome::GrandpaCommitError(err),
+                }
+            }
+            FinalityProof::Justification((consensus_engine_id, scale_encoded_justification)) => {
+                match self.chain.verify_justification(
+                    consensus_engine_id,
+                    &scale_encoded_justification,
+                    randomness_seed,
+                ) {
+                    Ok(success) => {
+                        let finalized_blocks_iter = success.apply();
+                        let updates_best_block = finalized_blocks_iter.updates_best_block();
+                        let finalized_blocks = finalized_blocks_iter
+                            .map(|b| (b.header, b.user_data))
+                            .collect::<Vec<_>>();
+                        let _finalized_blocks = self
+                            .inner
+                            .blocks
+                            .set_finalized_block_height(finalized_blocks.last().unwrap().0.number);
+                        FinalityProofVerifyOutcome::NewFinalized {
+                            finalized_blocks,
+                            updates_best_block,
+                            finality_proof: ExternalFinalityProof::Justification {
+                                consensus_engine_id,
+                                scale_encoded_justification,
+                            },
+                        }
+                    }
+                    // In case where the commit message concerns a block older or equal to the
+                    // finalized block, the operation is silently considered successful.
+                    Err(blocks_tree::JustificationVerifyError::FinalityVerify(
+                        blocks_tree::FinalityVerifyError::EqualToFinalized
+                        | blocks_tree::FinalityVerifyError::BelowFinalized,
+                    )) => FinalityProofVerifyOutcome::AlreadyFinalized,
+
+                    // Note that, contrary to commits, there's no such thing as a justification
+                    // that can't be verified yet.
+                    Err(err) => FinalityProofVerifyOutcome::JustificationError(err),
+                }
+            }
+        }
+    }
+
     /// Process the next block in the queue of verification.
     ///
     /// This method takes ownership of the [`AllForksSync`] and starts a verification
@@ -1854,7 +2029,7 @@ impl<TBl, TRq, TSrc> HeaderVerify<TBl, TRq, TSrc> {
     }
 
     /// Perform the verification.
-    pub fn perform(mut self, now_from_unix_epoch: Duration) -> HeaderVerifyOutcome<TBl, TRq, TSrc> {
+    pub fn perform(mut self) -> HeaderVerifyOutcome<TBl, TRq, TSrc> {
         let to_verify_scale_encoded_header = self
             .parent
             .inner
@@ -1871,7 +2046,7 @@ impl<TBl, TRq, TSrc> HeaderVerify<TBl, TRq, TSrc> {
         let result = match self
             .parent
             .chain
-            .verify_header(to_verify_scale_encoded_header, now_from_unix_epoch)
+            .verify_header(to_verify_scale_encoded_header)
         {
             Ok(blocks_tree::HeaderVerifySuccess::Insert {
                 insert,
@@ -1935,6 +2110,24 @@ impl<TBl, TRq, TSrc> HeaderVerify<TBl, TRq, TSrc> {
 
                 Err(HeaderVerifyError::UnknownConsensusEngine)
             }
+            Err(blocks_tree::HeaderVerifyError::BadBlock) => {
+                // Remove the block from `pending_blocks`.
+                self.parent.inner.blocks.mark_unverified_block_as_bad(
+                    self.block_to_verify.block_number,
+                    &self.block_to_verify.block_hash,
+                );
+
+                Err(HeaderVerifyError::BadBlock)
+            }
+            Err(blocks_tree::HeaderVerifyError::ForkBlockMismatch { expected_hash }) => {
+                // Remove the block from `pending_blocks`.
+                self.parent.inner.blocks.mark_unverified_block_as_bad(
+                    self.block_to_verify.block_number,
+                    &self.block_to_verify.block_hash,
+                );
+
+                Err(HeaderVerifyError::ForkBlockMismatch { expected_hash })
+            }
             Ok(blocks_tree::HeaderVerifySuccess::Duplicate)
             | Err(
                 blocks_tree::HeaderVerifyError::BadParent { .. }
@@ -1983,97 +2176,11 @@ impl<TBl, TRq, TSrc> FinalityProofVerify<TBl, TRq, TSrc> {
         AllForksSync<TBl, TRq, TSrc>,
         FinalityProofVerifyOutcome<TBl>,
     ) {
-        let outcome = match self.finality_proof_to_verify {
-            FinalityProof::GrandpaCommit(scale_encoded_commit) => {
-                match self
-                    .parent
-                    .chain
-                    .verify_grandpa_commit_message(&scale_encoded_commit, randomness_seed)
-                {
-                    Ok(success) => {
-                        // TODO: DRY
-                        let finalized_blocks_iter = success.apply();
-                        let updates_best_block = finalized_blocks_iter.updates_best_block();
-                        let finalized_blocks = finalized_blocks_iter
-                            .map(|b| (b.header, b.user_data))
-                            .collect::<Vec<_>>();
-                        let _finalized_blocks =
-                            self.parent.inner.blocks.set_finalized_block_height(
-                                finalized_blocks.last().unwrap().0.number,
-                            );
-                        FinalityProofVerifyOutcome::NewFinalized {
-                            finalized_blocks,
-                            updates_best_block,
-                        }
-                    }
-                    // In case where the commit message concerns a block older or equal to the
-                    // finalized block, the operation is silently considered successful.
-                    Err(blocks_tree::CommitVerifyError::FinalityVerify(
-                        blocks_tree::FinalityVerifyError::EqualToFinalized
-                        | blocks_tree::FinalityVerifyError::BelowFinalized,
-                    )) => FinalityProofVerifyOutcome::AlreadyFinalized,
-                    Err(
-                        blocks_tree::CommitVerifyError::FinalityVerify(
-                            blocks_tree::FinalityVerifyError::UnknownTargetBlock {
-                                block_number,
-                                ..
-                            },
-                        )
-                        | blocks_tree::CommitVerifyError::FinalityVerify(
-                            blocks_tree::FinalityVerifyError::TooFarAhead {
-                                justification_block_number: block_number,
-                                ..
-                            },
-                        )
-                        | blocks_tree::CommitVerifyError::NotEnoughKnownBlocks {
-                            target_block_number: block_number,
-                        },
-                    ) => {
-                        self.parent.inner.blocks[self.source_id]
-                            .pending_finality_proofs
-                            .insert(
-                                block_number,
-                                FinalityProofs::GrandpaCommit(scale_encoded_commit),
-                            );
-                        FinalityProofVerifyOutcome::GrandpaCommitPending
-                    }
-                    Err(err) => FinalityProofVerifyOutcome::GrandpaCommitError(err),
-                }
-            }
-            FinalityProof::Justification((consensus_engine_id, scale_encoded_justification)) => {
-                match self.parent.chain.verify_justification(
-                    consensus_engine_id,
-                    &scale_encoded_justification,
-                    randomness_seed,
-                ) {
-                    Ok(success) => {
-                        let finalized_blocks_iter = success.apply();
-                        let updates_best_block = finalized_blocks_iter.updates_best_block();
-                        let finalized_blocks = finalized_blocks_iter
-                            .map(|b| (b.header, b.user_data))
-                            .collect::<Vec<_>>();
-                        let _finalized_blocks =
-                            self.parent.inner.blocks.set_finalized_block_height(
-                                finalized_blocks.last().unwrap().0.number,
-                            );
-                        FinalityProofVerifyOutcome::NewFinalized {
-                            finalized_blocks,
-                            updates_best_block,
-                        }
-                    }
-                    // In case where the commit message concerns a block older or equal to the
-                    // finalized block, the operation is silently considered successful.
-                    Err(blocks_tree::JustificationVerifyError::FinalityVerify(
-                        blocks_tree::FinalityVerifyError::EqualToFinalized
-                        | blocks_tree::FinalityVerifyError::BelowFinalized,
-                    )) => FinalityProofVerifyOutcome::AlreadyFinalized,
-
-                    // Note that, contrary to commits, there's no such thing as a justification
-                    // that can't be verified yet.
-                    Err(err) => FinalityProofVerifyOutcome::JustificationError(err),
-                }
-            }
-        };
+        let outcome = self.parent.verify_finality_proof(
+            Some(self.source_id),
+            self.finality_proof_to_verify,
+            randomness_seed,
+        );
 
         (self.parent, outcome)
     }
@@ -2084,6 +2191,21 @@ impl<TBl, TRq, TSrc> FinalityProofVerify<TBl, TRq, TSrc> {
     }
 }
 
+/// Finality proof passed to [`AllForksSync::verify_external_finality_proof`].
+#[derive(Debug, Clone)]
+pub enum ExternalFinalityProof {
+    /// SCALE-encoded justification of a block.
+    Justification {
+        /// Identifier of the consensus engine the justification corresponds to, such as
+        /// `*b"FRNK"` for GrandPa.
+        consensus_engine_id: [u8; 4],
+        /// SCALE-encoded justification.
+        scale_encoded_justification: Vec<u8>,
+    },
+    /// SCALE-encoded GrandPa commit message.
+    GrandpaCommit(Vec<u8>),
+}
+
 /// See [`AllForksSync::grandpa_commit_message`].
 #[derive(Debug, Clone)]
 pub enum GrandpaCommitMessageOutcome {
@@ -2138,6 +2260,16 @@ pub enum HeaderVerifyError {
     UnknownConsensusEngine,
     /// Block uses a different consensus than the rest of the chain.
     ConsensusMismatch,
+    /// The hash of the block is in the list of bad blocks. See [`Config::bad_blocks`].
+    #[display(fmt = "The block is in the list of bad blocks.")]
+    BadBlock,
+    /// The height of the block is in the list of fork blocks, but the hash of the block doesn't
+    /// match. See [`Config::fork_blocks`].
+    #[display(fmt = "The hash of the block doesn't match the one in the list of fork blocks.")]
+    ForkBlockMismatch {
+        /// Hash that the block at this height is expected to have.
+        expected_hash: [u8; 32],
+    },
     /// The block verification has failed. The block is invalid and should be thrown away.
     #[display(fmt = "{_0}")]
     VerificationFailed(verify::header_only::Error),
@@ -2156,10 +2288,15 @@ pub enum FinalityProofVerifyOutcome<TBl> {
         /// This can happen if the previous best block isn't a descendant of the now finalized
         /// block.
         updates_best_block: bool,
+        /// Finality proof that has been verified. Proves the finality of the block with the
+        /// highest number in [`FinalityProofVerifyOutcome::NewFinalized::finalized_blocks`] and,
+        /// as such, of all the other ones.
+        finality_proof: ExternalFinalityProof,
     },
     /// Finality proof concerns block that was already finalized.
     AlreadyFinalized,
-    /// GrandPa commit cannot be verified yet and has been stored for later.
+    /// GrandPa commit cannot be verified yet and has been stored for later, or discarded if it
+    /// has been passed to [`AllForksSync::verify_external_finality_proof`].
     GrandpaCommitPending,
     /// Problem while verifying justification.
     JustificationError(blocks_tree::JustificationVerifyError),
@@ -2209,3 +2346,106 @@ pub enum BlockBodyVerify<TBl, TRq, TSrc> {
     /// order to continue.
     FinalizedStorageNextKey(StorageNextKey<TBl, TRq, TSrc>),*/
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AddSource, AllForksSync, BlockAnnounceOutcome, Config, HeaderVerifyOutcome};
+    use crate::{chain::chain_information, header, verify};
+    use core::{num::NonZeroU32, num::NonZeroU64, time::Duration};
+
+    /// Builds an [`AllForksSync`] whose finalized block is the genesis block of an Aura chain
+    /// with a single authority, then verifies a child of this genesis block signed with
+    /// `signing_key`.
+    fn verify_child(skip_seal_verification: bool, signing_key: [u8; 32]) -> bool {
+        let authority = schnorrkel::MiniSecretKey::from_bytes(&[1; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519);
+
+        let genesis = header::Header {
+            parent_hash: [0; 32],
+            number: 0,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        };
+
+        let mut sync = AllForksSync::<(), (), ()>::new(Config {
+            chain_information: chain_information::ValidChainInformation::try_from(
+                chain_information::ChainInformation {
+                    finalized_block_header: genesis.clone(),
+                    consensus: chain_information::ChainInformationConsensus::Aura {
+                        finalized_authorities_list: vec![header::AuraAuthority {
+                            public_key: authority.public.to_bytes(),
+                        }],
+                        slot_duration: NonZeroU64::new(6000).unwrap(),
+                    },
+                    finality: chain_information::ChainInformationFinality::Outsourced,
+                },
+            )
+            .unwrap(),
+            block_number_bytes: 4,
+            allow_unknown_consensus_engines: false,
+            skip_seal_verification,
+            sources_capacity: 4,
+            blocks_capacity: 4,
+            max_disjoint_headers: 4,
+            max_requests_per_block: NonZeroU32::new(1).unwrap(),
+            bad_blocks: Default::default(),
+            fork_blocks: Default::default(),
+            clock: verify::Clock::fixed(Duration::from_secs(3600)),
+            clock_drift_tolerance: verify::DEFAULT_CLOCK_DRIFT_TOLERANCE,
+            full: false,
+        });
+
+        let mut child = header::Header {
+            parent_hash: genesis.hash(4),
+            number: 1,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::from_slice(&[header::DigestItem::AuraPreDigest(
+                header::AuraPreDigest { slot_number: 1 },
+            )])
+            .unwrap()
+            .into(),
+        };
+        let signature = schnorrkel::MiniSecretKey::from_bytes(&signing_key)
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519)
+            .sign_simple(b"substrate", &child.hash(4))
+            .to_bytes();
+        child.digest.push_aura_seal(signature).unwrap();
+
+        let source_id = match sync.prepare_add_source(0, genesis.hash(4)) {
+            AddSource::OldBestBlock(source) => source.add_source(()),
+            _ => unreachable!(),
+        };
+        match sync.block_announce(source_id, child.scale_encoding_vec(4), true) {
+            BlockAnnounceOutcome::Unknown(block) => block.insert_and_update_source(()),
+            _ => unreachable!(),
+        }
+
+        match sync.process_one() {
+            super::ProcessOne::HeaderVerify(verify) => match verify.perform() {
+                HeaderVerifyOutcome::Success { .. } => true,
+                HeaderVerifyOutcome::Error { .. } => false,
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn valid_seal_accepted() {
+        assert!(verify_child(false, [1; 32]));
+        assert!(verify_child(true, [1; 32]));
+    }
+
+    #[test]
+    fn invalid_seal_rejected() {
+        assert!(!verify_child(false, [2; 32]));
+    }
+
+    #[test]
+    fn invalid_seal_accepted_if_verification_skipped() {
+        assert!(verify_child(true, [2; 32]));
+    }
+}
@@ -50,12 +50,13 @@ use crate::{
     executor::{host, storage_diff},
     header,
     trie::calculate_root,
+    verify,
 };
 
 use alloc::{
     borrow::ToOwned as _,
     boxed::Box,
-    collections::BTreeSet,
+    collections::{BTreeMap, BTreeSet},
     vec::{self, Vec},
 };
 use core::{
@@ -89,6 +90,33 @@ pub struct Config {
     /// Should be set to the maximum number of block between two consecutive justifications.
     pub blocks_capacity: usize,
 
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified.
+    ///
+    /// See [`blocks_tree::Config::skip_seal_verification`] for more information.
+    pub skip_seal_verification: bool,
+
+    /// Hashes of blocks that are known to be invalid.
+    ///
+    /// See [`blocks_tree::Config::bad_blocks`] for more information.
+    pub bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed.
+    ///
+    /// See [`blocks_tree::Config::fork_blocks`] for more information.
+    pub fork_blocks: HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// Source of the current time.
+    ///
+    /// See [`blocks_tree::Config::clock`] for more information.
+    pub clock: verify::Clock,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block.
+    ///
+    /// See [`blocks_tree::Config::clock_drift_tolerance`] for more information.
+    pub clock_drift_tolerance: Duration,
+
     /// Number of blocks to download ahead of the best block.
     ///
     /// Whenever the latest best block is updated, the state machine will start block
@@ -162,6 +190,12 @@ struct OptimisticSyncInner<TRq, TSrc, TBl> {
     /// Each entry is associated with the state version of the runtime at the time of the write.
     best_to_finalized_storage_diff: storage_diff::TrieDiff<TrieEntryVersion>,
 
+    /// Same as [`OptimisticSyncInner::best_to_finalized_storage_diff`], but for the default child
+    /// tries. The `BTreeMap`'s keys are the names of the child tries, without the
+    /// `:child_storage:default:` prefix.
+    best_to_finalized_child_tries_storage_diff:
+        BTreeMap<Vec<u8>, storage_diff::TrieDiff<TrieEntryVersion>>,
+
     /// Compiled runtime code of the best block. `None` if it is the same as
     /// [`OptimisticSyncInner::finalized_runtime`].
     best_runtime: Option<host::HostVmPrototype>,
@@ -269,8 +303,13 @@ pub struct BlockFull {
     /// Changes to the storage made by this block compared to its parent.
     pub storage_main_trie_changes: storage_diff::TrieDiff,
 
+    /// Changes to the default child tries made by this block compared to its parent. Keys are
+    /// the names of the child tries, without the `:child_storage:default:` prefix.
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
+
     /// State trie version indicated by the runtime. All the storage changes indicated by
-    /// [`BlockFull::storage_main_trie_changes`] should store this version alongside with them.
+    /// [`BlockFull::storage_main_trie_changes`] and [`BlockFull::storage_child_tries_changes`]
+    /// should store this version alongside with them.
     pub state_trie_version: TrieEntryVersion,
 
     /// List of changes to the off-chain storage that this block performs.
@@ -290,6 +329,11 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
             // a malicious node could send non-finalized blocks. Accepting blocks with an
             // unrecognized consensus engine doesn't add any additional risk.
             allow_unknown_consensus_engines: true,
+            skip_seal_verification: config.skip_seal_verification,
+            bad_blocks: config.bad_blocks,
+            fork_blocks: config.fork_blocks,
+            clock: config.clock,
+            clock_drift_tolerance: config.clock_drift_tolerance,
         };
 
         let chain = blocks_tree::NonFinalizedTree::new(blocks_tree_config.clone());
@@ -301,6 +345,7 @@ impl<TRq, TSrc, TBl> OptimisticSync<TRq, TSrc, TBl> {
                 finalized_chain_information: blocks_tree_config,
                 finalized_runtime: config.full.map(|f| f.finalized_runtime),
                 best_to_finalized_storage_diff: storage_diff::TrieDiff::empty(),
+                best_to_finalized_child_tries_storage_diff: BTreeMap::new(),
                 best_runtime: None,
                 main_trie_root_calculation_cache: None,
                 sources: HashMap::with_capacity_and_hasher(
@@ -871,9 +916,8 @@ impl<TRq, TSrc, TBl> BlockVerify<TRq, TSrc, TBl> {
 
     /// Start the verification of the block.
     ///
-    /// Must be passed the current UNIX time in order to verify that the block doesn't pretend to
-    /// come from the future.
-    pub fn start(mut self, now_from_unix_epoch: Duration) -> BlockVerification<TRq, TSrc, TBl> {
+    /// The current time is obtained from [`Config::clock`].
+    pub fn start(mut self) -> BlockVerification<TRq, TSrc, TBl> {
         // Extract the block to process. We are guaranteed that a block is available because a
         // `Verify` is built only when that is the case.
         // Be aware that `source_id` might refer to an obsolete source.
@@ -894,10 +938,7 @@ impl<TRq, TSrc, TBl> BlockVerify<TRq, TSrc, TBl> {
 
         if self.inner.finalized_runtime.is_some() {
             BlockVerification::from(
-                Inner::Step1(
-                    self.chain
-                        .verify_body(block.scale_encoded_header, now_from_unix_epoch),
-                ),
+                Inner::Step1(self.chain.verify_body(block.scale_encoded_header)),
                 BlockVerificationShared {
                     inner: self.inner,
                     block_body: block.scale_encoded_extrinsics,
@@ -906,10 +947,7 @@ impl<TRq, TSrc, TBl> BlockVerify<TRq, TSrc, TBl> {
                 },
             )
         } else {
-            let error = match self
-                .chain
-                .verify_header(block.scale_encoded_header, now_from_unix_epoch)
-            {
+            let error = match self.chain.verify_header(block.scale_encoded_header) {
                 Ok(blocks_tree::HeaderVerifySuccess::Insert {
                     insert,
                     is_new_best: true,
@@ -947,6 +985,7 @@ impl<TRq, TSrc, TBl> BlockVerify<TRq, TSrc, TBl> {
 
                 self.inner.make_requests_obsolete(&self.chain);
                 self.inner.best_to_finalized_storage_diff = Default::default();
+                self.inner.best_to_finalized_child_tries_storage_diff = Default::default();
                 self.inner.best_runtime = None;
                 self.inner.main_trie_root_calculation_cache = None;
 
@@ -1017,6 +1056,14 @@ pub enum BlockVerification<TRq, TSrc, TBl> {
     /// order to continue.
     FinalizedStorageNextKey(StorageNextKey<TRq, TSrc, TBl>),
 
+    /// Loading a storage value of a child trie of the finalized block is required in order to
+    /// continue.
+    FinalizedChildStorageGet(ChildStorageGet<TRq, TSrc, TBl>),
+
+    /// Fetching the list of all the keys of a child trie of the finalized block is required in
+    /// order to continue.
+    FinalizedChildStorageRoot(ChildStorageRoot<TRq, TSrc, TBl>),
+
     /// Compiling a runtime is required in order to continue.
     RuntimeCompilation(RuntimeCompilation<TRq, TSrc, TBl>),
 }
@@ -1067,6 +1114,7 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
 
                 Inner::Step2(blocks_tree::BodyVerifyStep2::Finished {
                     storage_main_trie_changes,
+                    storage_child_tries_changes,
                     state_trie_version,
                     offchain_storage_changes,
                     main_trie_root_calculation_cache,
@@ -1109,6 +1157,14 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                         .inner
                         .best_to_finalized_storage_diff
                         .merge_map(&storage_main_trie_changes, |()| state_trie_version);
+                    for (child_trie, changes) in &storage_child_tries_changes {
+                        shared
+                            .inner
+                            .best_to_finalized_child_tries_storage_diff
+                            .entry(child_trie.clone())
+                            .or_default()
+                            .merge_map(changes, |()| state_trie_version);
+                    }
 
                     let chain = {
                         let header = insert.header().into();
@@ -1119,6 +1175,7 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                             full: Some(BlockFull {
                                 body: mem::take(&mut shared.block_body),
                                 storage_main_trie_changes,
+                                storage_child_tries_changes,
                                 offchain_storage_changes,
                                 state_trie_version,
                             }),
@@ -1170,6 +1227,39 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                     });
                 }
 
+                Inner::Step2(blocks_tree::BodyVerifyStep2::ChildStorageGet(req)) => {
+                    // Same as `StorageGet`, but the diff of the child trie is used.
+                    let value = shared
+                        .inner
+                        .best_to_finalized_child_tries_storage_diff
+                        .get(req.child_trie().as_ref())
+                        .and_then(|diff| diff.diff_get(req.key().as_ref()));
+                    if let Some((value, storage_trie_node_version)) = value {
+                        inner = Inner::Step2(
+                            req.inject_value(
+                                value
+                                    .as_ref()
+                                    .map(|v| (iter::once(&v[..]), *storage_trie_node_version)),
+                            ),
+                        );
+                        continue 'verif_steps;
+                    }
+
+                    break BlockVerification::FinalizedChildStorageGet(ChildStorageGet {
+                        inner: req,
+                        shared,
+                    });
+                }
+
+                Inner::Step2(blocks_tree::BodyVerifyStep2::ChildStorageRoot(req)) => {
+                    // The underlying verification process is asking for all the keys of a
+                    // child trie. The user is asked for the keys of the finalized block.
+                    break BlockVerification::FinalizedChildStorageRoot(ChildStorageRoot {
+                        inner: req,
+                        shared,
+                    });
+                }
+
                 Inner::Step2(blocks_tree::BodyVerifyStep2::StorageNextKey(req)) => {
                     // The underlying verification process is asking for the key that follows
                     // the requested one.
@@ -1207,7 +1297,31 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
                 // - `cancelling_requests` is set to true in order to cancel all ongoing requests.
                 // - `chain` is recreated using `finalized_chain_information`.
                 //
-                Inner::Step1(blocks_tree::BodyVerifyStep1::InvalidHeader(old_chain, error)) => {
+                Inner::Step1(
+                    step @ (blocks_tree::BodyVerifyStep1::InvalidHeader(..)
+                    | blocks_tree::BodyVerifyStep1::BadBlock(_)
+                    | blocks_tree::BodyVerifyStep1::ForkBlockMismatch { .. }),
+                ) => {
+                    let (old_chain, reason) = match step {
+                        blocks_tree::BodyVerifyStep1::InvalidHeader(old_chain, error) => {
+                            (old_chain, ResetCause::InvalidHeader(error))
+                        }
+                        blocks_tree::BodyVerifyStep1::BadBlock(old_chain) => (
+                            old_chain,
+                            ResetCause::HeaderError(blocks_tree::HeaderVerifyError::BadBlock),
+                        ),
+                        blocks_tree::BodyVerifyStep1::ForkBlockMismatch {
+                            chain: old_chain,
+                            expected_hash,
+                        } => (
+                            old_chain,
+                            ResetCause::HeaderError(
+                                blocks_tree::HeaderVerifyError::ForkBlockMismatch { expected_hash },
+                            ),
+                        ),
+                        _ => unreachable!(),
+                    };
+
                     if let Some(source) = shared.inner.sources.get_mut(&shared.source_id) {
                         source.banned = true;
                     }
@@ -1225,13 +1339,14 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
 
                     let mut inner = shared.inner.with_requests_obsoleted(&chain);
                     inner.best_to_finalized_storage_diff = Default::default();
+                    inner.best_to_finalized_child_tries_storage_diff = Default::default();
                     inner.best_runtime = None;
                     inner.main_trie_root_calculation_cache = None;
 
                     break BlockVerification::Reset {
                         previous_best_height: old_chain.best_block_header().number,
                         sync: OptimisticSync { chain, inner },
-                        reason: ResetCause::InvalidHeader(error),
+                        reason,
                     };
                 }
                 Inner::Step1(
@@ -1256,6 +1371,7 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
 
                     let mut inner = shared.inner.with_requests_obsoleted(&chain);
                     inner.best_to_finalized_storage_diff = Default::default();
+                    inner.best_to_finalized_child_tries_storage_diff = Default::default();
                     inner.best_runtime = None;
                     inner.main_trie_root_calculation_cache = None;
 
@@ -1289,6 +1405,7 @@ impl<TRq, TSrc, TBl> BlockVerification<TRq, TSrc, TBl> {
 
                     let mut inner = shared.inner.with_requests_obsoleted(&chain);
                     inner.best_to_finalized_storage_diff = Default::default();
+                    inner.best_to_finalized_child_tries_storage_diff = Default::default();
                     inner.best_runtime = None;
                     inner.main_trie_root_calculation_cache = None;
 
@@ -1348,6 +1465,7 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
 
                 let mut inner = self.inner.with_requests_obsoleted(&chain);
                 inner.best_to_finalized_storage_diff = Default::default();
+                inner.best_to_finalized_child_tries_storage_diff = Default::default();
                 inner.best_runtime = None;
                 inner.main_trie_root_calculation_cache = None;
 
@@ -1369,7 +1487,7 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
         apply
             .block_user_data()
             .justifications
-            .push((consensus_engine_id, justification));
+            .push((consensus_engine_id, justification.clone()));
 
         // Applying the finalization and iterating over the now-finalized block.
         // Since `apply()` returns the blocks in decreasing block number, we have
@@ -1388,6 +1506,9 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
         // diff.
         debug_assert!(self.chain.is_empty());
         self.inner.best_to_finalized_storage_diff.clear();
+        self.inner
+            .best_to_finalized_child_tries_storage_diff
+            .clear();
 
         if let Some(runtime) = self.inner.best_runtime.take() {
             self.inner.finalized_runtime = Some(runtime);
@@ -1401,7 +1522,11 @@ impl<TRq, TSrc, TBl> JustificationVerify<TRq, TSrc, TBl> {
                 chain: self.chain,
                 inner: self.inner,
             },
-            JustificationVerification::Finalized { finalized_blocks },
+            JustificationVerification::Finalized {
+                finalized_blocks,
+                consensus_engine_id,
+                scale_encoded_justification: justification,
+            },
         )
     }
 }
@@ -1424,6 +1549,11 @@ pub enum JustificationVerification<TBl> {
     Finalized {
         /// Blocks that have been finalized.
         finalized_blocks: Vec<Block<TBl>>,
+        /// Identifier of the consensus engine of the justification that has been verified.
+        consensus_engine_id: [u8; 4],
+        /// SCALE-encoded justification that has been verified. Proves the finality of the last
+        /// block of [`JustificationVerification::Finalized::finalized_blocks`].
+        scale_encoded_justification: Vec<u8>,
     },
 }
 
@@ -1460,6 +1590,12 @@ pub struct StoragePrefixKeys<TRq, TSrc, TBl> {
 }
 
 impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix, or `None` if the keys of the main trie must be loaded.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the prefix whose keys to load.
     pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.prefix()
@@ -1473,11 +1609,17 @@ impl<TRq, TSrc, TBl> StoragePrefixKeys<TRq, TSrc, TBl> {
         // We need to turn the prefix into a Vec, as otherwise the iterator would borrow
         // self.inner.
         let owned_prefix = self.inner.prefix().as_ref().to_owned();
-        let list_after_diff = self
-            .shared
-            .inner
-            .best_to_finalized_storage_diff
-            .storage_prefix_keys_ordered(&owned_prefix, keys);
+        let empty_diff = storage_diff::TrieDiff::empty();
+        let diff = match self.inner.child_trie() {
+            None => &self.shared.inner.best_to_finalized_storage_diff,
+            Some(child_trie) => self
+                .shared
+                .inner
+                .best_to_finalized_child_tries_storage_diff
+                .get(child_trie.as_ref())
+                .unwrap_or(&empty_diff),
+        };
+        let list_after_diff = diff.storage_prefix_keys_ordered(&owned_prefix, keys);
         let inner = self.inner.inject_keys_ordered(list_after_diff);
         BlockVerification::from(Inner::Step2(inner), self.shared)
     }
@@ -1495,6 +1637,12 @@ pub struct StorageNextKey<TRq, TSrc, TBl> {
 }
 
 impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix, or `None` if it belongs to the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
         if let Some(key_overwrite) = &self.key_overwrite {
             either::Left(key_overwrite)
@@ -1517,19 +1665,26 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
         // `best_to_finalized_storage_diff` needs to be taken into account in order to provide
         // the next key in the best block instead.
 
+        let empty_diff = storage_diff::TrieDiff::empty();
         let search = {
             let inner_key = self.inner.key();
-            self.shared
-                .inner
-                .best_to_finalized_storage_diff
-                .storage_next_key(
-                    if let Some(key_overwrite) = &self.key_overwrite {
-                        key_overwrite
-                    } else {
-                        inner_key.as_ref()
-                    },
-                    key,
-                )
+            let diff = match self.inner.child_trie() {
+                None => &self.shared.inner.best_to_finalized_storage_diff,
+                Some(child_trie) => self
+                    .shared
+                    .inner
+                    .best_to_finalized_child_tries_storage_diff
+                    .get(child_trie.as_ref())
+                    .unwrap_or(&empty_diff),
+            };
+            diff.storage_next_key(
+                if let Some(key_overwrite) = &self.key_overwrite {
+                    key_overwrite
+                } else {
+                    inner_key.as_ref()
+                },
+                key,
+            )
         };
 
         match search {
@@ -1549,6 +1704,70 @@ impl<TRq, TSrc, TBl> StorageNextKey<TRq, TSrc, TBl> {
     }
 }
 
+/// Loading a storage value of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageGet<TRq, TSrc, TBl> {
+    inner: blocks_tree::ChildStorageGet<Block<TBl>>,
+    shared: BlockVerificationShared<TRq, TSrc, TBl>,
+}
+
+impl<TRq, TSrc, TBl> ChildStorageGet<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Returns the key whose value must be passed to [`ChildStorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.key()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(&[u8], TrieEntryVersion)>,
+    ) -> BlockVerification<TRq, TSrc, TBl> {
+        let inner = self.inner.inject_value(
+            value.map(|(v, storage_trie_node_version)| (iter::once(v), storage_trie_node_version)),
+        );
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
+}
+
+/// Fetching the list of all the keys of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageRoot<TRq, TSrc, TBl> {
+    inner: blocks_tree::ChildStorageRoot<Block<TBl>>,
+    shared: BlockVerificationShared<TRq, TSrc, TBl>,
+}
+
+impl<TRq, TSrc, TBl> ChildStorageRoot<TRq, TSrc, TBl> {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Injects the list of all the keys of the child trie in the finalized block, ordered
+    /// lexicographically.
+    pub fn inject_keys_ordered(
+        self,
+        keys: impl Iterator<Item = impl AsRef<[u8]>>,
+    ) -> BlockVerification<TRq, TSrc, TBl> {
+        let empty_diff = storage_diff::TrieDiff::empty();
+        let diff = self
+            .shared
+            .inner
+            .best_to_finalized_child_tries_storage_diff
+            .get(self.inner.child_trie().as_ref())
+            .unwrap_or(&empty_diff);
+        let list_after_diff = diff.storage_prefix_keys_ordered(&[], keys);
+        let inner = self.inner.inject_keys_ordered(list_after_diff);
+        BlockVerification::from(Inner::Step2(inner), self.shared)
+    }
+}
+
 /// Compiling a new runtime is necessary as part of the verification.
 #[must_use]
 pub struct RuntimeCompilation<TRq, TSrc, TBl> {
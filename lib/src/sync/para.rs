@@ -32,6 +32,10 @@
 //! See the [`persisted_validation_data_parameters`] to obtain the input to pass to the runtime
 //! function. The first parameter is a `para_id` found in the chain specification of the
 //! parachain of parathread.
+//!
+//! See also the [`hrmp`] module in order to verify the messages exchanged between parachains.
+
+pub mod hrmp;
 
 /// Produces the input to pass to the `ParachainHost_persisted_validation_data` runtime call.
 pub fn persisted_validation_data_parameters(
@@ -0,0 +1,536 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Verification of the messages sent between parachains through HRMP channels.
+//!
+//! When a parachain sends a message to another parachain, this message is stored in the storage
+//! of the relay chain, in a channel dedicated to this pair of sender and recipient, until the
+//! recipient processes it. Each channel is made of two storage entries: `Hrmp.HrmpChannels`,
+//! which contains the characteristics of the channel, and `Hrmp.HrmpChannelContents`, which
+//! contains the list of messages waiting to be processed by the recipient.
+//!
+//! Because these entries are part of the storage of the relay chain, their content can be
+//! verified with a Merkle proof against the state trie root of a relay chain block header. Use
+//! [`channel_storage_key`] and [`channel_contents_storage_key`] to obtain the keys to request a
+//! proof of, then [`verify_channel`] to verify the proof and decode the channel.
+//!
+//! # Message queue chain
+//!
+//! Each channel contains a so-called "message queue chain" head (MQC head). Each message sent
+//! through the channel modifies this head by hashing the previous head together with the
+//! message, similar to a blockchain. Since messages are removed from the storage once they have
+//! been processed by the recipient, the MQC head makes it possible to confirm that a message has
+//! been sent even after its removal. See [`mqc_head_extend`].
+//!
+//! This module assumes the layout of the storage used by the Polkadot runtimes.
+
+use crate::{trie::proof_decode, util};
+
+use alloc::vec::Vec;
+
+/// Returns the key of the `Hrmp.HrmpChannels` storage entry of the given channel.
+pub fn channel_storage_key(sender_para_id: u32, recipient_para_id: u32) -> Vec<u8> {
+    storage_key(b"HrmpChannels", sender_para_id, recipient_para_id)
+}
+
+/// Returns the key of the `Hrmp.HrmpChannelContents` storage entry of the given channel.
+pub fn channel_contents_storage_key(sender_para_id: u32, recipient_para_id: u32) -> Vec<u8> {
+    storage_key(b"HrmpChannelContents", sender_para_id, recipient_para_id)
+}
+
+/// Characteristics of an HRMP channel, as stored in the `Hrmp.HrmpChannels` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HrmpChannel {
+    /// Maximum number of messages that can be pending in the channel at once.
+    pub max_capacity: u32,
+    /// Maximum total size, in bytes, of the messages that can be pending in the channel at once.
+    pub max_total_size: u32,
+    /// Maximum size of a single message, in bytes.
+    pub max_message_size: u32,
+    /// Number of messages currently pending in the channel.
+    pub msg_count: u32,
+    /// Total size, in bytes, of the messages currently pending in the channel.
+    pub total_size: u32,
+    /// Head of the message queue chain. `None` if no message has ever been sent through this
+    /// channel.
+    pub mqc_head: Option<[u8; 32]>,
+    /// Deposit reserved by the sender of the channel.
+    pub sender_deposit: u128,
+    /// Deposit reserved by the recipient of the channel.
+    pub recipient_deposit: u128,
+}
+
+/// Message pending in an HRMP channel, as found in the `Hrmp.HrmpChannelContents` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InboundHrmpMessage {
+    /// Height of the relay chain block at which the message has been sent.
+    pub sent_at: u64,
+    /// Opaque content of the message.
+    pub data: Vec<u8>,
+}
+
+/// Attempt to decode the value of the `Hrmp.HrmpChannels` storage entry.
+pub fn decode_channel(scale_encoded: &[u8]) -> Result<HrmpChannel, DecodeError> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> =
+        nom::combinator::all_consuming(channel)(scale_encoded);
+    match res {
+        Ok((_, channel)) => Ok(channel),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(DecodeError(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Attempt to decode the value of the `Hrmp.HrmpChannelContents` storage entry.
+///
+/// `block_number_bytes` is the number of bytes used to encode block numbers in the relay chain.
+pub fn decode_channel_contents(
+    scale_encoded: &[u8],
+    block_number_bytes: usize,
+) -> Result<Vec<InboundHrmpMessage>, DecodeError> {
+    let res: Result<_, nom::Err<nom::error::Error<_>>> = nom::combinator::all_consuming(
+        nom::combinator::flat_map(util::nom_scale_compact_usize, |num_messages| {
+            nom::multi::many_m_n(
+                num_messages,
+                num_messages,
+                inbound_message(block_number_bytes),
+            )
+        }),
+    )(scale_encoded);
+    match res {
+        Ok((_, messages)) => Ok(messages),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(DecodeError(err.code)),
+        Err(_) => unreachable!(),
+    }
+}
+
+/// Error that can happen during the decoding.
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Error decoding HRMP storage entry")]
+pub struct DecodeError(nom::error::ErrorKind);
+
+/// Calculates the new head of the message queue chain of a channel after the given message has
+/// been sent through it.
+///
+/// `previous_head` must be `None` if no message has been sent before through this channel.
+pub fn mqc_head_extend(
+    previous_head: Option<&[u8; 32]>,
+    message: &InboundHrmpMessage,
+    block_number_bytes: usize,
+) -> [u8; 32] {
+    let mut message_hash = blake2_rfc::blake2b::Blake2b::new(32);
+    message_hash.update(util::encode_scale_compact_usize(message.data.len()).as_ref());
+    message_hash.update(&message.data);
+
+    let mut sent_at = [0; 16];
+    sent_at[..8].copy_from_slice(&message.sent_at.to_le_bytes());
+
+    let mut new_head = blake2_rfc::blake2b::Blake2b::new(32);
+    new_head.update(previous_head.unwrap_or(&[0; 32]));
+    new_head.update(&sent_at[..block_number_bytes.min(sent_at.len())]);
+    new_head.update(message_hash.finalize().as_bytes());
+    <[u8; 32]>::try_from(new_head.finalize().as_bytes()).unwrap()
+}
+
+/// Configuration for [`verify_channel`].
+pub struct VerifyConfig<'a, P> {
+    /// State trie root of the relay chain block against which to verify the proof.
+    pub relay_chain_state_trie_root: &'a [u8; 32],
+
+    /// Merkle proof of the relay chain storage containing the entries whose keys are returned
+    /// by [`channel_storage_key`] and [`channel_contents_storage_key`].
+    pub proof: P,
+
+    /// Parachain id of the sender of the messages.
+    pub sender_para_id: u32,
+
+    /// Parachain id of the recipient of the messages.
+    pub recipient_para_id: u32,
+
+    /// Number of bytes used to encode block numbers in the relay chain.
+    pub block_number_bytes: usize,
+
+    /// If `Some`, head of the message queue chain before the first message currently pending
+    /// in the channel was sent, as known by the API user. In that case, the verification also
+    /// checks that the pending messages lead to the head of the message queue chain stored in
+    /// the channel.
+    ///
+    /// The inner `Option` must be `None` if the first message pending in the channel is the
+    /// first message ever sent through this channel.
+    pub mqc_head_before_pending: Option<Option<[u8; 32]>>,
+}
+
+/// HRMP channel whose content has been verified by [`verify_channel`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifiedChannel {
+    /// Characteristics of the channel.
+    pub channel: HrmpChannel,
+    /// Messages sent by the sender and not processed yet by the recipient, from oldest to
+    /// newest.
+    pub pending_messages: Vec<InboundHrmpMessage>,
+}
+
+impl VerifiedChannel {
+    /// Returns `true` if a message with the given content is pending in the channel.
+    pub fn contains_message(&self, data: &[u8]) -> bool {
+        self.pending_messages.iter().any(|msg| msg.data == data)
+    }
+}
+
+/// Verifies the given proof against the relay chain state trie root, and returns the content of
+/// the HRMP channel between the given sender and recipient.
+///
+/// Returns `Ok(None)` if the proof indicates that this channel doesn't exist.
+pub fn verify_channel<P: AsRef<[u8]>>(
+    config: VerifyConfig<P>,
+) -> Result<Option<VerifiedChannel>, VerifyError> {
+    let decoded_proof = proof_decode::decode_and_verify_proof(proof_decode::Config {
+        trie_root_hash: config.relay_chain_state_trie_root,
+        proof: config.proof,
+    })
+    .map_err(VerifyError::InvalidProof)?;
+
+    let channel = match decoded_proof
+        .storage_value(&channel_storage_key(
+            config.sender_para_id,
+            config.recipient_para_id,
+        ))
+        .ok_or(VerifyError::MissingProofEntry)?
+    {
+        Some((value, _)) => decode_channel(value).map_err(VerifyError::InvalidChannel)?,
+        None => return Ok(None),
+    };
+
+    // The storage entry is absent if no message is pending.
+    let pending_messages = match decoded_proof
+        .storage_value(&channel_contents_storage_key(
+            config.sender_para_id,
+            config.recipient_para_id,
+        ))
+        .ok_or(VerifyError::MissingProofEntry)?
+    {
+        Some((value, _)) => decode_channel_contents(value, config.block_number_bytes)
+            .map_err(VerifyError::InvalidContents)?,
+        None => Vec::new(),
+    };
+
+    if usize::try_from(channel.msg_count).map_or(true, |n| n != pending_messages.len()) {
+        return Err(VerifyError::MessageCountMismatch);
+    }
+
+    if pending_messages.iter().fold(0u64, |sum, msg| {
+        sum + u64::try_from(msg.data.len()).unwrap()
+    }) != u64::from(channel.total_size)
+    {
+        return Err(VerifyError::TotalSizeMismatch);
+    }
+
+    if let Some(mqc_head_before_pending) = config.mqc_head_before_pending {
+        let mut mqc_head = mqc_head_before_pending;
+        for message in &pending_messages {
+            mqc_head = Some(mqc_head_extend(
+                mqc_head.as_ref(),
+                message,
+                config.block_number_bytes,
+            ));
+        }
+
+        if mqc_head != channel.mqc_head {
+            return Err(VerifyError::MqcHeadMismatch);
+        }
+    }
+
+    Ok(Some(VerifiedChannel {
+        channel,
+        pending_messages,
+    }))
+}
+
+/// Error potentially returned by [`verify_channel`].
+#[derive(Debug, derive_more::Display)]
+pub enum VerifyError {
+    /// Failed to verify the Merkle proof.
+    #[display(fmt = "Invalid proof: {_0}")]
+    InvalidProof(proof_decode::Error),
+    /// The proof doesn't contain one of the necessary storage entries.
+    #[display(fmt = "Missing entry in the proof")]
+    MissingProofEntry,
+    /// Failed to decode the `Hrmp.HrmpChannels` storage entry.
+    #[display(fmt = "Failed to decode channel: {_0}")]
+    InvalidChannel(DecodeError),
+    /// Failed to decode the `Hrmp.HrmpChannelContents` storage entry.
+    #[display(fmt = "Failed to decode channel contents: {_0}")]
+    InvalidContents(DecodeError),
+    /// The number of pending messages doesn't match the one indicated by the channel.
+    #[display(fmt = "Number of pending messages doesn't match the channel")]
+    MessageCountMismatch,
+    /// The total size of the pending messages doesn't match the one indicated by the channel.
+    #[display(fmt = "Total size of pending messages doesn't match the channel")]
+    TotalSizeMismatch,
+    /// The pending messages don't lead to the head of the message queue chain of the channel.
+    #[display(fmt = "Pending messages don't match the message queue chain head")]
+    MqcHeadMismatch,
+}
+
+fn storage_key(item_name: &[u8], sender_para_id: u32, recipient_para_id: u32) -> Vec<u8> {
+    let mut channel_id = [0; 8];
+    channel_id[..4].copy_from_slice(&sender_para_id.to_le_bytes());
+    channel_id[4..].copy_from_slice(&recipient_para_id.to_le_bytes());
+
+    let mut key = Vec::with_capacity(16 + 16 + 8 + 8);
+    key.extend_from_slice(&util::twox_128(b"Hrmp"));
+    key.extend_from_slice(&util::twox_128(item_name));
+    key.extend_from_slice(&util::twox_64(&channel_id));
+    key.extend_from_slice(&channel_id);
+    key
+}
+
+/// `Nom` combinator that parses an [`HrmpChannel`].
+fn channel<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], HrmpChannel, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            nom::number::complete::le_u32,
+            util::nom_option_decode(nom::combinator::map(
+                nom::bytes::complete::take(32u32),
+                |hash| <[u8; 32]>::try_from(hash).unwrap(),
+            )),
+            nom::number::complete::le_u128,
+            nom::number::complete::le_u128,
+        )),
+        |(
+            max_capacity,
+            max_total_size,
+            max_message_size,
+            msg_count,
+            total_size,
+            mqc_head,
+            sender_deposit,
+            recipient_deposit,
+        )| HrmpChannel {
+            max_capacity,
+            max_total_size,
+            max_message_size,
+            msg_count,
+            total_size,
+            mqc_head,
+            sender_deposit,
+            recipient_deposit,
+        },
+    )(bytes)
+}
+
+/// `Nom` combinator that parses an [`InboundHrmpMessage`].
+fn inbound_message<'a, E: nom::error::ParseError<&'a [u8]>>(
+    block_number_bytes: usize,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], InboundHrmpMessage, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            util::nom_varsize_number_decode_u64(block_number_bytes),
+            util::nom_bytes_decode,
+        )),
+        |(sent_at, data)| InboundHrmpMessage {
+            sent_at,
+            data: data.to_vec(),
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::trie::{self, proof_encode, trie_node, trie_structure};
+    use alloc::vec::Vec;
+    use core::array;
+
+    /// Builds a proof containing all the given entries, and returns the trie root hash and the
+    /// proof.
+    fn build_proof(entries: &[(Vec<u8>, Vec<u8>)]) -> ([u8; 32], Vec<u8>) {
+        let mut trie = trie_structure::TrieStructure::new();
+        for (key, value) in entries {
+            match trie.node(trie::bytes_to_nibbles(key.iter().copied())) {
+                trie_structure::Entry::Vacant(e) => {
+                    e.insert_storage_value().insert(Some(value.clone()), None);
+                }
+                _ => unreachable!(),
+            }
+        }
+
+        let mut proof_builder = proof_encode::ProofBuilder::new();
+        for node_index in trie.iter_unordered().collect::<Vec<_>>() {
+            let mut node = trie.node_by_index(node_index).unwrap();
+            let storage_value = node.user_data().clone();
+            let key = node.full_key().collect::<Vec<_>>();
+            let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+                children: array::from_fn(|nibble| {
+                    let nibble = trie::Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+                    node.child_user_data(nibble).map(|_| &[][..])
+                }),
+                partial_key: node.partial_key().collect::<Vec<_>>().into_iter(),
+                storage_value: match &storage_value {
+                    Some(value) => trie_node::StorageValue::Unhashed(&value[..]),
+                    None => trie_node::StorageValue::None,
+                },
+            })
+            .unwrap();
+            proof_builder.set_node_value(&key, &node_value, None);
+        }
+
+        proof_builder.make_coherent();
+        (
+            proof_builder.trie_root_hash().unwrap(),
+            proof_builder.build_to_vec(),
+        )
+    }
+
+    fn encode_channel(channel: &super::HrmpChannel) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&channel.max_capacity.to_le_bytes());
+        out.extend_from_slice(&channel.max_total_size.to_le_bytes());
+        out.extend_from_slice(&channel.max_message_size.to_le_bytes());
+        out.extend_from_slice(&channel.msg_count.to_le_bytes());
+        out.extend_from_slice(&channel.total_size.to_le_bytes());
+        match &channel.mqc_head {
+            Some(head) => {
+                out.push(1);
+                out.extend_from_slice(head);
+            }
+            None => out.push(0),
+        }
+        out.extend_from_slice(&channel.sender_deposit.to_le_bytes());
+        out.extend_from_slice(&channel.recipient_deposit.to_le_bytes());
+        out
+    }
+
+    fn encode_messages(messages: &[super::InboundHrmpMessage]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(messages.len()).as_ref());
+        for message in messages {
+            out.extend_from_slice(&u32::try_from(message.sent_at).unwrap().to_le_bytes());
+            out.extend_from_slice(
+                crate::util::encode_scale_compact_usize(message.data.len()).as_ref(),
+            );
+            out.extend_from_slice(&message.data);
+        }
+        out
+    }
+
+    #[test]
+    fn verify_pending_messages() {
+        let messages = [
+            super::InboundHrmpMessage {
+                sent_at: 100,
+                data: b"hello".to_vec(),
+            },
+            super::InboundHrmpMessage {
+                sent_at: 102,
+                data: b"world!".to_vec(),
+            },
+        ];
+
+        let previous_head = [0x55; 32];
+        let mqc_head = super::mqc_head_extend(
+            Some(&super::mqc_head_extend(
+                Some(&previous_head),
+                &messages[0],
+                4,
+            )),
+            &messages[1],
+            4,
+        );
+
+        let channel = super::HrmpChannel {
+            max_capacity: 8,
+            max_total_size: 1024,
+            max_message_size: 512,
+            msg_count: 2,
+            total_size: 11,
+            mqc_head: Some(mqc_head),
+            sender_deposit: 5,
+            recipient_deposit: 7,
+        };
+
+        let (trie_root, proof) = build_proof(&[
+            (
+                super::channel_storage_key(1000, 2000),
+                encode_channel(&channel),
+            ),
+            (
+                super::channel_contents_storage_key(1000, 2000),
+                encode_messages(&messages),
+            ),
+        ]);
+
+        let verify = |mqc_head_before_pending| {
+            super::verify_channel(super::VerifyConfig {
+                relay_chain_state_trie_root: &trie_root,
+                proof: &proof,
+                sender_para_id: 1000,
+                recipient_para_id: 2000,
+                block_number_bytes: 4,
+                mqc_head_before_pending,
+            })
+        };
+
+        let verified = verify(Some(Some(previous_head))).unwrap().unwrap();
+        assert_eq!(verified.channel, channel);
+        assert_eq!(verified.pending_messages, messages);
+        assert!(verified.contains_message(b"world!"));
+        assert!(!verified.contains_message(b"foo"));
+
+        assert!(verify(None).unwrap().is_some());
+        assert!(matches!(
+            verify(Some(None)),
+            Err(super::VerifyError::MqcHeadMismatch)
+        ));
+    }
+
+    #[test]
+    fn verify_channel_absent() {
+        let (trie_root, proof) = build_proof(&[(
+            super::channel_storage_key(1000, 2000),
+            encode_channel(&super::HrmpChannel {
+                max_capacity: 8,
+                max_total_size: 1024,
+                max_message_size: 512,
+                msg_count: 0,
+                total_size: 0,
+                mqc_head: None,
+                sender_deposit: 0,
+                recipient_deposit: 0,
+            }),
+        )]);
+
+        let verify = |sender_para_id, recipient_para_id| {
+            super::verify_channel(super::VerifyConfig {
+                relay_chain_state_trie_root: &trie_root,
+                proof: &proof,
+                sender_para_id,
+                recipient_para_id,
+                block_number_bytes: 4,
+                mqc_head_before_pending: Some(None),
+            })
+        };
+
+        let verified = verify(1000, 2000).unwrap().unwrap();
+        assert!(verified.pending_messages.is_empty());
+        assert!(verify(2000, 1000).unwrap().is_none());
+    }
+}
@@ -90,6 +90,28 @@
 //! Use [`InProgressWarpSync::process_one`] in order to run verifications of the payloads that have
 //! previously been downloaded.
 //!
+//! # Eclipse attacks
+//!
+//! A client that is only connected to malicious nodes can't be tricked into accepting an invalid
+//! warp sync proof, but can be served a proof that stops at an older finalized block than the
+//! actual head of the chain, or no proof at all.
+//!
+//! In order to make this more difficult, a warp sync proof is requested from
+//! [`Config::min_distinct_sources`] different sources, and no proof is requested until at least
+//! that many sources have been added to the state machine. Each proof is verified as soon as it
+//! has been downloaded. A source that sends a proof that fails to verify is banned and is no
+//! longer counted or requested from, and a proof is requested from a different source instead.
+//!
+//! Once enough proofs have been successfully verified, they are compared with each other. The
+//! proofs agree if the fragments they contain that concern the same block height also concern
+//! the same block, in which case the warp syncing continues from the highest block. Two verified
+//! proofs can only disagree if the authorities of the chain have finalized two conflicting
+//! blocks. If that happens, they are all discarded and new proofs are requested, preferably from
+//! other sources.
+//!
+//! Sources are assumed by this module to be distinct from each other. It is the responsibility
+//! of the API user to not add multiple times the same source.
+//!
 
 use crate::{
     chain::chain_information::{
@@ -108,12 +130,15 @@ use crate::{
 
 use alloc::{
     borrow::{Cow, ToOwned as _},
+    collections::{btree_map, BTreeMap},
     vec,
     vec::Vec,
 };
-use core::{iter, mem, ops};
+use core::{iter, mem, num::NonZeroU32, ops};
 
-pub use warp_sync::{Error as FragmentError, WarpSyncFragment};
+pub use warp_sync::{
+    Error as FragmentError, JustificationVerified, JustificationVerify, WarpSyncFragment,
+};
 
 /// Problem encountered during a call to [`start_warp_sync()`].
 #[derive(Debug, derive_more::Display)]
@@ -155,6 +180,13 @@ pub struct Config {
 
     /// The initial capacity of the list of requests.
     pub requests_capacity: usize,
+
+    /// Number of sources that a warp sync proof is requested from, and minimum number of
+    /// sources that must have been added to the state machine before a warp sync proof is
+    /// requested. See the [module-level documentation](..) for more information.
+    ///
+    /// Use `1` in order to disable this requirement.
+    pub min_distinct_sources: NonZeroU32,
 }
 
 /// Initializes the warp sync state machine.
@@ -186,8 +218,11 @@ pub fn start_warp_sync<TSrc, TRq>(
     Ok(InProgressWarpSync {
         start_chain_information: config.start_chain_information,
         block_number_bytes: config.block_number_bytes,
+        min_distinct_sources: config.min_distinct_sources,
         sources: slab::Slab::with_capacity(config.sources_capacity),
         in_progress_requests: slab::Slab::with_capacity(config.requests_capacity),
+        verified_fragments: Vec::new(),
+        downloaded_proofs: Vec::new(),
         phase: Phase::DownloadFragments {
             previous_verifier_values: None,
         },
@@ -224,6 +259,14 @@ pub struct Success<TSrc, TRq> {
     /// Storage value at the `:heappages` key of the finalized block.
     pub finalized_storage_heap_pages: Option<Vec<u8>>,
 
+    /// List of the warp sync fragments that have been verified, in order. The first fragment
+    /// is signed by the authorities of the finalized block of
+    /// [`Config::start_chain_information`], and each subsequent fragment is signed by the
+    /// authorities enacted by the previous one.
+    ///
+    /// Empty if the starting point of the warp sync was already the head of the chain.
+    pub verified_fragments: Vec<WarpSyncFragment>,
+
     /// The list of sources that were added to the state machine.
     pub sources: Vec<TSrc>,
 
@@ -266,10 +309,47 @@ pub struct InProgressWarpSync<TSrc, TRq> {
     start_chain_information: ValidChainInformation,
     /// Number of bytes used to encode the block number in headers.
     block_number_bytes: usize,
+    /// See [`Config::min_distinct_sources`].
+    min_distinct_sources: NonZeroU32,
     /// List of requests that have been added using [`InProgressWarpSync::add_source`].
     sources: slab::Slab<Source<TSrc>>,
     /// List of requests that have been added using [`InProgressWarpSync::add_request`].
     in_progress_requests: slab::Slab<(SourceId, TRq, RequestDetail)>,
+    /// Fragments that have been successfully verified so far, in order.
+    verified_fragments: Vec<WarpSyncFragment>,
+    /// Warp sync proofs downloaded during the [`Phase::DownloadFragments`] phase when
+    /// [`Config::min_distinct_sources`] is superior to 1. They are verified, then wait for proofs
+    /// from other sources in order to be compared with them. Proofs whose starting point doesn't
+    /// match the current phase are obsolete.
+    downloaded_proofs: Vec<DownloadedProof>,
+}
+
+struct DownloadedProof {
+    /// Source the proof has been obtained from.
+    source_id: SourceId,
+    /// Hash of the block the proof starts from.
+    start_block_hash: [u8; 32],
+    /// `true` if the source has indicated that there is no more fragment afterwards.
+    final_set_of_fragments: bool,
+    /// Progress of the verification of the proof.
+    verification: ProofVerification,
+}
+
+enum ProofVerification {
+    /// The proof is being verified.
+    /// Always `Some`, but wrapped within an `Option` in order to permit extracting
+    /// temporarily.
+    InProgress(Option<warp_sync::Verifier>),
+    /// The proof has been successfully verified.
+    Verified {
+        /// Header of the last block of the proof, or of the starting point if the proof is
+        /// empty.
+        header: Header,
+        /// Information about the finality of the chain after the last block of the proof.
+        chain_information_finality: ChainInformationFinality,
+        /// Fragments contained in the proof, all of which have been verified.
+        fragments: Vec<WarpSyncFragment>,
+    },
 }
 
 enum Phase {
@@ -293,6 +373,10 @@ enum Phase {
         /// Always `Some`, but wrapped within an `Option` in order to permit extracting
         /// temporarily.
         verifier: Option<warp_sync::Verifier>,
+        /// Fragments that follow the ones being verified, downloaded while the verification is
+        /// in progress, the source they have been obtained from, and whether this is the final
+        /// set of fragments. Always `None` if `final_set_of_fragments` is `true`.
+        next_fragments: Option<(SourceId, Vec<WarpSyncFragment>, bool)>,
     },
     /// All warp sync fragments have been verified, and we are now downloading the runtime of the
     /// finalized block of the chain.
@@ -479,6 +563,7 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
         SourceId(self.sources.insert(Source {
             user_data,
             already_tried: false,
+            banned: false,
         }))
     }
 
@@ -496,6 +581,8 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
     ) -> (TSrc, impl Iterator<Item = (RequestId, TRq)> + '_) {
         debug_assert!(self.sources.contains(to_remove.0));
         let removed = self.sources.remove(to_remove.0).user_data;
+        self.downloaded_proofs
+            .retain(|proof| proof.source_id != to_remove);
 
         if let Phase::RuntimeDownload {
             warp_sync_source_id,
@@ -521,6 +608,7 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
         } else if let Phase::PendingVerify {
             previous_verifier_values,
             downloaded_source,
+            next_fragments,
             ..
         } = &mut self.phase
         {
@@ -532,6 +620,11 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
                 self.phase = Phase::DownloadFragments {
                     previous_verifier_values: previous_verifier_values.take(),
                 }
+            } else if next_fragments
+                .as_ref()
+                .map_or(false, |(src, _, _)| *src == to_remove)
+            {
+                *next_fragments = None;
             }
         }
 
@@ -558,37 +651,96 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
         &'_ self,
     ) -> impl Iterator<Item = (SourceId, &'_ TSrc, DesiredRequest)> + '_ {
         // If we are in the fragments download phase, return a fragments download request.
-        let warp_sync_request = if let Phase::DownloadFragments {
-            previous_verifier_values,
-        } = &self.phase
-        {
-            // TODO: it feels like a hack to try again sources that have failed in the past; also, this means that the already_tried mechanism only works once
-            let all_sources_already_tried = self.sources.iter().all(|(_, s)| s.already_tried);
-
-            let start_block_hash = match previous_verifier_values.as_ref() {
+        // If we are verifying fragments that aren't the final ones, the fragments that follow
+        // them are downloaded ahead of time in parallel of the verification, unless proofs from
+        // multiple sources must be compared with each other.
+        let start_block_hash = match &self.phase {
+            Phase::DownloadFragments {
+                previous_verifier_values,
+            } => Some(match previous_verifier_values.as_ref() {
                 Some((header, _)) => header.hash(self.block_number_bytes),
                 None => self
                     .start_chain_information
                     .as_ref()
                     .finalized_block_header
                     .hash(self.block_number_bytes),
+            }),
+            Phase::PendingVerify {
+                final_set_of_fragments: false,
+                verifier,
+                next_fragments: None,
+                ..
+            } if self.min_distinct_sources.get() == 1 => {
+                verifier.as_ref().unwrap().last_fragment_block_hash()
+            }
+            _ => None,
+        };
+
+        // No warp sync proof is requested until enough sources that aren't banned are
+        // available. See the module-level documentation.
+        // TODO: O(n)
+        let start_block_hash = start_block_hash.filter(|_| {
+            let num_sources = self.sources.iter().filter(|(_, s)| !s.banned).count();
+            u32::try_from(num_sources).unwrap_or(u32::max_value())
+                >= self.min_distinct_sources.get()
+        });
+
+        let warp_sync_request = if let Some(start_block_hash) = start_block_hash {
+            // Returns `true` if the given source has already sent back a proof starting from
+            // this block, or is currently being asked for one.
+            // TODO: O(n)
+            let has_proof_or_request = move |source_id: SourceId| {
+                self.downloaded_proofs.iter().any(|proof| {
+                    proof.source_id == source_id && proof.start_block_hash == start_block_hash
+                }) || self
+                    .in_progress_requests
+                    .iter()
+                    .any(|(_, (src, _, rq))| match rq {
+                        RequestDetail::WarpSyncRequest { block_hash }
+                            if *src == source_id && *block_hash == start_block_hash =>
+                        {
+                            true
+                        }
+                        _ => false,
+                    })
             };
 
+            // Number of proofs starting from this block that have been downloaded or are being
+            // downloaded.
             // TODO: O(n)
-            if !self
-                .in_progress_requests
+            let num_proofs = self
+                .downloaded_proofs
                 .iter()
-                .any(|(_, (_, _, rq))| match rq {
-                    RequestDetail::WarpSyncRequest { block_hash }
-                        if *block_hash == start_block_hash =>
-                    {
-                        true
-                    }
-                    _ => false,
-                })
+                .filter(|proof| proof.start_block_hash == start_block_hash)
+                .count()
+                + self
+                    .in_progress_requests
+                    .iter()
+                    .filter(|(_, (_, _, rq))| match rq {
+                        RequestDetail::WarpSyncRequest { block_hash } => {
+                            *block_hash == start_block_hash
+                        }
+                        _ => false,
+                    })
+                    .count();
+
+            // TODO: it feels like a hack to try again sources that have failed in the past; also, this means that the already_tried mechanism only works once
+            let all_sources_already_tried = self
+                .sources
+                .iter()
+                .filter(|(src_id, s)| !s.banned && !has_proof_or_request(SourceId(*src_id)))
+                .all(|(_, s)| s.already_tried);
+
+            if u32::try_from(num_proofs).unwrap_or(u32::max_value())
+                < self.min_distinct_sources.get()
             {
-                // Combine the request with every single available source.
+                // Combine the request with every single available source that hasn't been
+                // asked yet.
                 either::Left(self.sources.iter().filter_map(move |(src_id, src)| {
+                    if src.banned || has_proof_or_request(SourceId(src_id)) {
+                        return None;
+                    }
+
                     // TODO: also filter by source finalized block? so that we don't request from sources below us
                     if all_sources_already_tried || !src.already_tried {
                         Some((
@@ -940,15 +1092,48 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
                     ),
                 };
 
+                // If proofs from multiple sources are required, the proof is verified then
+                // compared with the proofs of the other sources. See the module-level
+                // documentation.
+                if self.min_distinct_sources.get() != 1 {
+                    self.downloaded_proofs.retain(|proof| {
+                        proof.start_block_hash == desired_block_hash
+                            && proof.source_id != rq_source_id
+                    });
+                    self.downloaded_proofs.push(DownloadedProof {
+                        source_id: rq_source_id,
+                        start_block_hash: desired_block_hash,
+                        final_set_of_fragments,
+                        verification: ProofVerification::InProgress(Some(verifier)),
+                    });
+                    return user_data;
+                }
+
                 self.phase = Phase::PendingVerify {
                     previous_verifier_values: previous_verifier_values.take(),
                     final_set_of_fragments,
                     downloaded_source: rq_source_id,
                     verifier: Some(verifier),
+                    next_fragments: None,
                 };
 
                 user_data
             }
+            (
+                (rq_source_id, user_data, RequestDetail::WarpSyncRequest { block_hash }),
+                Phase::PendingVerify {
+                    final_set_of_fragments: false,
+                    verifier,
+                    next_fragments: next_fragments @ None,
+                    ..
+                },
+            ) if verifier.as_ref().unwrap().last_fragment_block_hash() == Some(block_hash) => {
+                // Fragments that follow the ones being verified. They are verified once the
+                // verification of the current fragments has succeeded.
+                self.sources[rq_source_id.0].already_tried = true;
+                *next_fragments = Some((rq_source_id, fragments, final_set_of_fragments));
+                user_data
+            }
             ((_, user_data, RequestDetail::WarpSyncRequest { .. }), _) => {
                 // Uninteresting download. We simply ignore the response.
                 user_data
@@ -978,11 +1163,153 @@ impl<TSrc, TRq> InProgressWarpSync<TSrc, TRq> {
         }
 
         if let Phase::PendingVerify { .. } = &self.phase {
-            return ProcessOne::VerifyWarpSyncFragment(VerifyWarpSyncFragment { inner: self });
+            return ProcessOne::VerifyWarpSyncFragment(VerifyWarpSyncFragment {
+                inner: self,
+                downloaded_proof_index: None,
+            });
+        }
+
+        if let Phase::DownloadFragments { .. } = &self.phase {
+            if let Some(index) = self
+                .downloaded_proofs
+                .iter()
+                .position(|proof| matches!(proof.verification, ProofVerification::InProgress(_)))
+            {
+                return ProcessOne::VerifyWarpSyncFragment(VerifyWarpSyncFragment {
+                    inner: self,
+                    downloaded_proof_index: Some(index),
+                });
+            }
         }
 
         ProcessOne::Idle(self)
     }
+
+    /// Verifies one fragment of the proof found at the given index within
+    /// [`InProgressWarpSync::downloaded_proofs`].
+    ///
+    /// If the proof fails to verify, it is discarded and its source is banned.
+    fn verify_downloaded_proof(
+        mut self,
+        proof_index: usize,
+        randomness_seed: [u8; 32],
+    ) -> (Self, Option<FragmentError>) {
+        let proof = &mut self.downloaded_proofs[proof_index];
+        let verifier = match &mut proof.verification {
+            ProofVerification::InProgress(verifier) => verifier.take().unwrap(),
+            ProofVerification::Verified { .. } => unreachable!(),
+        };
+
+        let (header, chain_information_finality, fragments) = match verifier.next(randomness_seed) {
+            Ok(warp_sync::Next::NotFinished(next_verifier)) => {
+                proof.verification = ProofVerification::InProgress(Some(next_verifier));
+                return (self, None);
+            }
+            Ok(warp_sync::Next::EmptyProof) => match &self.phase {
+                Phase::DownloadFragments {
+                    previous_verifier_values: Some((header, chain_information_finality)),
+                } => (
+                    header.clone(),
+                    chain_information_finality.clone(),
+                    Vec::new(),
+                ),
+                Phase::DownloadFragments {
+                    previous_verifier_values: None,
+                } => (
+                    self.start_chain_information
+                        .as_ref()
+                        .finalized_block_header
+                        .into(),
+                    self.start_chain_information.as_ref().finality.into(),
+                    Vec::new(),
+                ),
+                _ => unreachable!(),
+            },
+            Ok(warp_sync::Next::Success {
+                scale_encoded_header,
+                chain_information_finality,
+                verified_fragments,
+            }) => {
+                // As the verification of the fragment has succeeded, we are sure that the header
+                // is valid and can decode it.
+                let header: Header = header::decode(&scale_encoded_header, self.block_number_bytes)
+                    .unwrap()
+                    .into();
+                (header, chain_information_finality, verified_fragments)
+            }
+            Err(error) => {
+                // A proof is requested from a different source instead.
+                let proof = self.downloaded_proofs.remove(proof_index);
+                self.sources[proof.source_id.0].banned = true;
+                return (self, Some(error));
+            }
+        };
+
+        self.downloaded_proofs[proof_index].verification = ProofVerification::Verified {
+            header,
+            chain_information_finality,
+            fragments,
+        };
+
+        self.compare_verified_proofs();
+        (self, None)
+    }
+
+    /// If enough proofs within [`InProgressWarpSync::downloaded_proofs`] have been verified,
+    /// compares them with each other and continues the warp syncing from the highest block if
+    /// they agree.
+    fn compare_verified_proofs(&mut self) {
+        let num_verified = self
+            .downloaded_proofs
+            .iter()
+            .filter(|proof| matches!(proof.verification, ProofVerification::Verified { .. }))
+            .count();
+        if u32::try_from(num_verified).unwrap_or(u32::max_value()) < self.min_distinct_sources.get()
+        {
+            return;
+        }
+
+        let DownloadedProof {
+            source_id,
+            final_set_of_fragments,
+            verification,
+            ..
+        } = match select_agreeing_proof(
+            mem::take(&mut self.downloaded_proofs),
+            self.block_number_bytes,
+        ) {
+            Ok(proof) => proof,
+            Err(()) => {
+                // The authorities of the chain have finalized conflicting blocks. The proofs are
+                // discarded, and new proofs will be requested, if possible from sources that
+                // haven't been tried yet.
+                return;
+            }
+        };
+
+        let (header, chain_information_finality, fragments) = match verification {
+            ProofVerification::Verified {
+                header,
+                chain_information_finality,
+                fragments,
+            } => (header, chain_information_finality, fragments),
+            ProofVerification::InProgress(_) => unreachable!(),
+        };
+
+        self.verified_fragments.extend(fragments);
+        self.phase = if final_set_of_fragments {
+            Phase::RuntimeDownload {
+                header,
+                chain_information_finality,
+                warp_sync_source_id: source_id,
+                downloaded_runtime: None,
+            }
+        } else {
+            Phase::DownloadFragments {
+                previous_verifier_values: Some((header, chain_information_finality)),
+            }
+        };
+    }
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -991,6 +1318,9 @@ struct Source<TSrc> {
     /// `true` if this source has been in a past warp sync request and we should try a different
     /// source.
     already_tried: bool,
+    /// `true` if this source has sent a warp sync proof that has failed to verify. Warp sync
+    /// proofs are no longer requested from this source.
+    banned: bool,
 }
 
 /// Information about a request that the warp sync state machine would like to start.
@@ -1072,23 +1402,27 @@ pub enum ProcessOne<TSrc, TRq> {
 /// Ready to verify a warp sync fragment.
 pub struct VerifyWarpSyncFragment<TSrc, TRq> {
     inner: InProgressWarpSync<TSrc, TRq>,
+    /// Index within [`InProgressWarpSync::downloaded_proofs`] of the proof to verify, or `None`
+    /// if the fragments to verify are the ones of [`Phase::PendingVerify`].
+    downloaded_proof_index: Option<usize>,
 }
 
 impl<TSrc, TRq> VerifyWarpSyncFragment<TSrc, TRq> {
     /// Returns the source that has sent the fragments that we are about to verify, and its user
     /// data.
     pub fn proof_sender(&self) -> (SourceId, &TSrc) {
-        if let Phase::PendingVerify {
-            downloaded_source, ..
-        } = &self.inner.phase
-        {
+        let source_id = match (self.downloaded_proof_index, &self.inner.phase) {
+            (Some(index), _) => self.inner.downloaded_proofs[index].source_id,
             (
-                *downloaded_source,
-                &self.inner.sources[downloaded_source.0].user_data,
-            )
-        } else {
-            unreachable!()
-        }
+                None,
+                Phase::PendingVerify {
+                    downloaded_source, ..
+                },
+            ) => *downloaded_source,
+            (None, _) => unreachable!(),
+        };
+
+        (source_id, &self.inner.sources[source_id.0].user_data)
     }
 
     /// Verify one warp sync fragment.
@@ -1100,11 +1434,16 @@ impl<TSrc, TRq> VerifyWarpSyncFragment<TSrc, TRq> {
         mut self,
         randomness_seed: [u8; 32],
     ) -> (InProgressWarpSync<TSrc, TRq>, Option<FragmentError>) {
+        if let Some(index) = self.downloaded_proof_index {
+            return self.inner.verify_downloaded_proof(index, randomness_seed);
+        }
+
         if let Phase::PendingVerify {
             previous_verifier_values,
             verifier,
             final_set_of_fragments,
             downloaded_source,
+            next_fragments,
         } = &mut self.inner.phase
         {
             match verifier.take().unwrap().next(randomness_seed) {
@@ -1132,7 +1471,10 @@ impl<TSrc, TRq> VerifyWarpSyncFragment<TSrc, TRq> {
                 Ok(warp_sync::Next::Success {
                     scale_encoded_header,
                     chain_information_finality,
+                    verified_fragments,
                 }) => {
+                    self.inner.verified_fragments.extend(verified_fragments);
+
                     // As the verification of the fragment has succeeded, we are sure that the header
                     // is valid and can decode it.
                     let header: Header =
@@ -1147,6 +1489,22 @@ impl<TSrc, TRq> VerifyWarpSyncFragment<TSrc, TRq> {
                             warp_sync_source_id: *downloaded_source,
                             downloaded_runtime: None,
                         };
+                    } else if let Some((next_source, next_fragments, next_final_set)) =
+                        next_fragments.take()
+                    {
+                        let verifier = warp_sync::Verifier::new(
+                            (&chain_information_finality).into(),
+                            self.inner.block_number_bytes,
+                            next_fragments,
+                            next_final_set,
+                        );
+                        self.inner.phase = Phase::PendingVerify {
+                            previous_verifier_values: Some((header, chain_information_finality)),
+                            final_set_of_fragments: next_final_set,
+                            downloaded_source: next_source,
+                            verifier: Some(verifier),
+                            next_fragments: None,
+                        };
                     } else {
                         self.inner.phase = Phase::DownloadFragments {
                             previous_verifier_values: Some((header, chain_information_finality)),
@@ -1166,6 +1524,45 @@ impl<TSrc, TRq> VerifyWarpSyncFragment<TSrc, TRq> {
             unreachable!()
         }
     }
+
+    /// Returns the verifications of the signatures of the justifications of up to `max`
+    /// fragments that haven't been verified yet.
+    ///
+    /// Since the signatures of the justifications make up for most of the verification time,
+    /// these verifications can be performed concurrently, for example on multiple threads, then
+    /// passed to [`VerifyWarpSyncFragment::inject_justification_verified`] before calling
+    /// [`VerifyWarpSyncFragment::verify`]. The outcome of [`VerifyWarpSyncFragment::verify`]
+    /// is the same as if this function wasn't called.
+    pub fn justification_verifications(&self, max: usize) -> Vec<JustificationVerify> {
+        let verifier = match (self.downloaded_proof_index, &self.inner.phase) {
+            (Some(index), _) => match &self.inner.downloaded_proofs[index].verification {
+                ProofVerification::InProgress(verifier) => verifier,
+                ProofVerification::Verified { .. } => unreachable!(),
+            },
+            (None, Phase::PendingVerify { verifier, .. }) => verifier,
+            (None, _) => unreachable!(),
+        };
+
+        verifier.as_ref().unwrap().justification_verifications(max)
+    }
+
+    /// Stores the outcome of a successful verification obtained through
+    /// [`VerifyWarpSyncFragment::justification_verifications`].
+    pub fn inject_justification_verified(&mut self, verified: JustificationVerified) {
+        let verifier = match (self.downloaded_proof_index, &mut self.inner.phase) {
+            (Some(index), _) => match &mut self.inner.downloaded_proofs[index].verification {
+                ProofVerification::InProgress(verifier) => verifier,
+                ProofVerification::Verified { .. } => unreachable!(),
+            },
+            (None, Phase::PendingVerify { verifier, .. }) => verifier,
+            (None, _) => unreachable!(),
+        };
+
+        verifier
+            .as_mut()
+            .unwrap()
+            .inject_justification_verified(verified);
+    }
 }
 
 /// Ready to build the runtime of the finalized chain.
@@ -1324,6 +1721,7 @@ impl<TSrc, TRq> BuildRuntime<TSrc, TRq> {
                             finalized_storage_code: Some(finalized_storage_code.to_owned()),
                             finalized_storage_heap_pages: finalized_storage_heappages
                                 .map(|v| v.to_vec()),
+                            verified_fragments: mem::take(&mut self.inner.verified_fragments),
                             sources: self
                                 .inner
                                 .sources
@@ -1475,6 +1873,9 @@ impl<TSrc, TRq> BuildChainInformation<TSrc, TRq> {
                                         finalized_storage_code: downloaded_runtime.storage_code,
                                         finalized_storage_heap_pages: downloaded_runtime
                                             .storage_heap_pages,
+                                        verified_fragments: mem::take(
+                                            &mut self.inner.verified_fragments,
+                                        ),
                                         sources: self
                                             .inner
                                             .sources
@@ -1536,6 +1937,54 @@ impl<TSrc, TRq> BuildChainInformation<TSrc, TRq> {
     }
 }
 
+/// Compares the given warp sync proofs, which must all start from the same block, with each other.
+/// Proofs that haven't been verified yet are ignored.
+///
+/// Returns the verified proof that reaches the highest block if all the fragments that concern
+/// the same block height also concern the same block. Returns an error if that is not the case,
+/// or if none of the proofs has been verified.
+fn select_agreeing_proof(
+    mut proofs: Vec<DownloadedProof>,
+    block_number_bytes: usize,
+) -> Result<DownloadedProof, ()> {
+    let mut blocks = BTreeMap::<u64, [u8; 32]>::new();
+    let mut best: Option<(usize, (u64, bool))> = None;
+
+    for (index, proof) in proofs.iter().enumerate() {
+        let (header, fragments) = match &proof.verification {
+            ProofVerification::Verified {
+                header, fragments, ..
+            } => (header, fragments),
+            ProofVerification::InProgress(_) => continue,
+        };
+
+        for fragment in fragments {
+            let number = header::decode(&fragment.scale_encoded_header, block_number_bytes)
+                .map_err(|_| ())?
+                .number;
+            let hash = header::hash_from_scale_encoded_header(&fragment.scale_encoded_header);
+
+            match blocks.entry(number) {
+                btree_map::Entry::Vacant(entry) => {
+                    entry.insert(hash);
+                }
+                btree_map::Entry::Occupied(entry) if *entry.get() == hash => {}
+                btree_map::Entry::Occupied(_) => return Err(()),
+            }
+        }
+
+        // In case of equality, prefer proofs that aren't final, as they indicate that more
+        // fragments can be downloaded.
+        let key = (header.number, !proof.final_set_of_fragments);
+        if best.map_or(true, |(_, best_key)| key > best_key) {
+            best = Some((index, key));
+        }
+    }
+
+    let (best_index, _) = best.ok_or(())?;
+    Ok(proofs.swap_remove(best_index))
+}
+
 /// Returns `true` if `a` and `b` are equal.
 fn parameters_equal(mut a: &[u8], b: impl Iterator<Item = impl AsRef<[u8]>>) -> bool {
     for slice in b {
@@ -1554,3 +2003,410 @@ fn parameters_equal(mut a: &[u8], b: impl Iterator<Item = impl AsRef<[u8]>>) ->
 
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        start_warp_sync, Config, DesiredRequest, InProgressWarpSync, Phase, ProcessOne,
+        RequestDetail, SourceId, WarpSyncFragment,
+    };
+    use crate::{chain::chain_information, header};
+    use alloc::vec::Vec;
+    use core::num::{NonZeroU32, NonZeroU64};
+
+    fn genesis() -> header::Header {
+        header::Header {
+            parent_hash: [0; 32],
+            number: 0,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::empty().into(),
+        }
+    }
+
+    /// Key of the single GrandPa authority of the test chain.
+    fn authority_key() -> ed25519_zebra::SigningKey {
+        ed25519_zebra::SigningKey::from([1; 32])
+    }
+
+    fn authority() -> header::GrandpaAuthority {
+        header::GrandpaAuthority {
+            public_key: ed25519_zebra::VerificationKey::from(&authority_key()).into(),
+            weight: NonZeroU64::new(1).unwrap(),
+        }
+    }
+
+    fn new_sync(min_distinct_sources: u32) -> InProgressWarpSync<(), ()> {
+        start_warp_sync(Config {
+            start_chain_information: chain_information::ValidChainInformation::try_from(
+                chain_information::ChainInformation {
+                    finalized_block_header: genesis(),
+                    consensus: chain_information::ChainInformationConsensus::Aura {
+                        finalized_authorities_list: Vec::new(),
+                        slot_duration: NonZeroU64::new(6000).unwrap(),
+                    },
+                    finality: chain_information::ChainInformationFinality::Grandpa {
+                        after_finalized_block_authorities_set_id: 0,
+                        finalized_triggered_authorities: vec![authority()],
+                        finalized_scheduled_change: None,
+                    },
+                },
+            )
+            .unwrap(),
+            block_number_bytes: 4,
+            sources_capacity: 4,
+            requests_capacity: 4,
+            min_distinct_sources: NonZeroU32::new(min_distinct_sources).unwrap(),
+        })
+        .map_err(|_| ())
+        .unwrap()
+    }
+
+    /// Builds a warp sync proof starting from the genesis block, containing one fragment for
+    /// each of the given block numbers. `variant` is put in the state root in order to build
+    /// different blocks with the same number.
+    ///
+    /// Each block schedules a change to the same authorities, and its justification is signed
+    /// by the authority of the test chain.
+    fn proof(numbers: &[u64], variant: u8) -> Vec<WarpSyncFragment> {
+        numbers
+            .iter()
+            .enumerate()
+            .map(|(authorities_set_id, number)| {
+                let digest = [header::DigestItem::GrandpaConsensus(
+                    header::GrandpaConsensusLog::ScheduledChange(header::GrandpaScheduledChange {
+                        next_authorities: vec![authority()],
+                        delay: 0,
+                    }),
+                )];
+                let scale_encoded_header = header::HeaderRef {
+                    parent_hash: &[0; 32],
+                    number: *number,
+                    state_root: &[variant; 32],
+                    extrinsics_root: &[0; 32],
+                    digest: header::DigestRef::from_slice(&digest).unwrap(),
+                }
+                .scale_encoding_vec(4);
+                let hash = header::hash_from_scale_encoded_header(&scale_encoded_header);
+                let number = u32::try_from(*number).unwrap().to_le_bytes();
+
+                let mut message = vec![1u8];
+                message.extend_from_slice(&hash);
+                message.extend_from_slice(&number);
+                message.extend_from_slice(&1u64.to_le_bytes());
+                message
+                    .extend_from_slice(&u64::try_from(authorities_set_id).unwrap().to_le_bytes());
+                let signature: [u8; 64] = authority_key().sign(&message).into();
+
+                let mut scale_encoded_justification = 1u64.to_le_bytes().to_vec();
+                scale_encoded_justification.extend_from_slice(&hash);
+                scale_encoded_justification.extend_from_slice(&number);
+                scale_encoded_justification.push(4); // One precommit.
+                scale_encoded_justification.extend_from_slice(&hash);
+                scale_encoded_justification.extend_from_slice(&number);
+                scale_encoded_justification.extend_from_slice(&signature);
+                scale_encoded_justification.extend_from_slice(&authority().public_key);
+                scale_encoded_justification.push(0); // No votes ancestry.
+
+                WarpSyncFragment {
+                    scale_encoded_header,
+                    scale_encoded_justification,
+                }
+            })
+            .collect()
+    }
+
+    /// Builds a warp sync proof similar to [`proof`] but whose signatures are invalid.
+    fn invalid_proof(numbers: &[u64]) -> Vec<WarpSyncFragment> {
+        let mut proof = proof(numbers, 0);
+        for fragment in &mut proof {
+            let len = fragment.scale_encoded_justification.len();
+            fragment.scale_encoded_justification[len - 40] ^= 0xff;
+        }
+        proof
+    }
+
+    /// Starts a warp sync request towards the first source returned by `desired_requests`.
+    fn start_request(
+        sync: &mut InProgressWarpSync<(), ()>,
+    ) -> Option<(SourceId, super::RequestId)> {
+        let (source_id, block_hash) = match sync.desired_requests().next()? {
+            (source_id, _, DesiredRequest::WarpSyncRequest { block_hash }) => {
+                (source_id, block_hash)
+            }
+            _ => unreachable!(),
+        };
+        let request_id =
+            sync.add_request(source_id, (), RequestDetail::WarpSyncRequest { block_hash });
+        Some((source_id, request_id))
+    }
+
+    /// Verifies everything that is ready to be verified. Returns the number of verifications
+    /// that have failed.
+    fn verify_all(sync: &mut InProgressWarpSync<(), ()>) -> usize {
+        let mut num_errors = 0;
+        let mut current = core::mem::replace(sync, new_sync(1));
+        loop {
+            match current.process_one() {
+                ProcessOne::VerifyWarpSyncFragment(verify) => {
+                    let (next, error) = verify.verify([0; 32]);
+                    current = next;
+                    num_errors += usize::from(error.is_some());
+                }
+                ProcessOne::Idle(idle) => {
+                    *sync = idle;
+                    return num_errors;
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    /// Returns the height of the finalized block the warp syncing has reached, and `true` if
+    /// the runtime is now being downloaded.
+    fn progress(sync: &InProgressWarpSync<(), ()>) -> (u64, bool) {
+        match &sync.phase {
+            Phase::DownloadFragments {
+                previous_verifier_values: Some((header, _)),
+            } => (header.number, false),
+            Phase::DownloadFragments {
+                previous_verifier_values: None,
+            } => (0, false),
+            Phase::RuntimeDownload { header, .. } => (header.number, true),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn no_request_below_min_sources() {
+        let mut sync = new_sync(3);
+        sync.add_source(());
+        sync.add_source(());
+        assert!(sync.desired_requests().next().is_none());
+        sync.add_source(());
+        assert_eq!(sync.desired_requests().count(), 3);
+    }
+
+    #[test]
+    fn requests_to_distinct_sources() {
+        let mut sync = new_sync(3);
+        for _ in 0..4 {
+            sync.add_source(());
+        }
+
+        let mut sources = Vec::new();
+        while let Some((source_id, _)) = start_request(&mut sync) {
+            sources.push(source_id);
+        }
+
+        sources.sort();
+        sources.dedup();
+        assert_eq!(sources.len(), 3);
+    }
+
+    #[test]
+    fn single_source_verified_immediately() {
+        let mut sync = new_sync(1);
+        sync.add_source(());
+        let (_, request_id) = start_request(&mut sync).unwrap();
+        assert!(start_request(&mut sync).is_none());
+        sync.warp_sync_request_success(request_id, proof(&[10], 0), true);
+        assert!(matches!(sync.phase, Phase::PendingVerify { .. }));
+        assert_eq!(verify_all(&mut sync), 0);
+        assert_eq!(progress(&sync), (10, true));
+    }
+
+    #[test]
+    fn waits_for_all_proofs() {
+        let mut sync = new_sync(2);
+        sync.add_source(());
+        sync.add_source(());
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (_, request2) = start_request(&mut sync).unwrap();
+
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        assert_eq!(verify_all(&mut sync), 0);
+        assert_eq!(progress(&sync), (0, false));
+        assert!(start_request(&mut sync).is_none());
+
+        sync.warp_sync_request_success(request2, proof(&[10], 0), true);
+        assert_eq!(verify_all(&mut sync), 0);
+        assert_eq!(progress(&sync), (10, true));
+        assert_eq!(sync.verified_fragments.len(), 1);
+    }
+
+    #[test]
+    fn highest_agreeing_proof_chosen() {
+        let mut sync = new_sync(3);
+        for _ in 0..3 {
+            sync.add_source(());
+        }
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (source2, request2) = start_request(&mut sync).unwrap();
+        let (_, request3) = start_request(&mut sync).unwrap();
+
+        // The first source pretends that the chain stops at block 10.
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        sync.warp_sync_request_success(request2, proof(&[10, 20], 0), true);
+        sync.warp_sync_request_success(request3, proof(&[10], 0), false);
+        assert_eq!(verify_all(&mut sync), 0);
+
+        match sync.phase {
+            Phase::RuntimeDownload {
+                ref header,
+                warp_sync_source_id,
+                ..
+            } => {
+                assert_eq!(header.number, 20);
+                assert_eq!(warp_sync_source_id, source2);
+            }
+            _ => panic!(),
+        }
+        assert_eq!(sync.verified_fragments.len(), 2);
+    }
+
+    #[test]
+    fn non_final_proof_preferred_on_equality() {
+        let mut sync = new_sync(2);
+        sync.add_source(());
+        sync.add_source(());
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (_, request2) = start_request(&mut sync).unwrap();
+
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        sync.warp_sync_request_success(request2, proof(&[10], 0), false);
+        assert_eq!(verify_all(&mut sync), 0);
+
+        // The warp syncing continues from block 10.
+        assert_eq!(progress(&sync), (10, false));
+    }
+
+    #[test]
+    fn disagreeing_proofs_discarded() {
+        let mut sync = new_sync(2);
+        for _ in 0..3 {
+            sync.add_source(());
+        }
+        let (source1, request1) = start_request(&mut sync).unwrap();
+        let (source2, request2) = start_request(&mut sync).unwrap();
+
+        // Both proofs are valid, but finalize different blocks.
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        sync.warp_sync_request_success(request2, proof(&[10, 20], 1), true);
+        assert_eq!(verify_all(&mut sync), 0);
+        assert_eq!(progress(&sync), (0, false));
+        assert!(sync.downloaded_proofs.is_empty());
+        assert!(sync.verified_fragments.is_empty());
+
+        // New proofs are requested, in priority from the source that hasn't been tried yet.
+        let (source3, _) = start_request(&mut sync).unwrap();
+        assert_ne!(source3, source1);
+        assert_ne!(source3, source2);
+    }
+
+    #[test]
+    fn undecodable_proof_source_banned() {
+        let mut sync = new_sync(2);
+        sync.add_source(());
+        sync.add_source(());
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (source2, request2) = start_request(&mut sync).unwrap();
+
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        sync.warp_sync_request_success(
+            request2,
+            vec![WarpSyncFragment {
+                scale_encoded_header: vec![1, 2, 3],
+                scale_encoded_justification: Vec::new(),
+            }],
+            true,
+        );
+        assert_eq!(verify_all(&mut sync), 1);
+        assert_eq!(progress(&sync), (0, false));
+        assert!(sync.sources[source2.0].banned);
+
+        // The banned source isn't counted, and only one source remains.
+        assert!(start_request(&mut sync).is_none());
+    }
+
+    #[test]
+    fn invalid_proof_doesnt_prevent_sync() {
+        let mut sync = new_sync(2);
+        for _ in 0..3 {
+            sync.add_source(());
+        }
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (source2, request2) = start_request(&mut sync).unwrap();
+
+        // The second source sends a proof that reaches a higher block but isn't signed by the
+        // authorities.
+        sync.warp_sync_request_success(request1, proof(&[10, 20], 0), true);
+        sync.warp_sync_request_success(request2, invalid_proof(&[10, 20, 30]), true);
+        assert_eq!(verify_all(&mut sync), 1);
+        assert_eq!(progress(&sync), (0, false));
+
+        // The proof of the first source is kept, and a proof is requested from the third source.
+        let (source3, request3) = start_request(&mut sync).unwrap();
+        assert_ne!(source3, source2);
+        assert!(start_request(&mut sync).is_none());
+
+        sync.warp_sync_request_success(request3, proof(&[10, 20], 0), true);
+        assert_eq!(verify_all(&mut sync), 0);
+        assert_eq!(progress(&sync), (20, true));
+    }
+
+    #[test]
+    fn failed_request_replaced() {
+        let mut sync = new_sync(2);
+        for _ in 0..3 {
+            sync.add_source(());
+        }
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let _ = start_request(&mut sync).unwrap();
+        assert!(start_request(&mut sync).is_none());
+
+        sync.fail_request(request1);
+        assert!(start_request(&mut sync).is_some());
+    }
+
+    #[test]
+    fn removed_source_proof_forgotten() {
+        let mut sync = new_sync(2);
+        for _ in 0..3 {
+            sync.add_source(());
+        }
+        let (source1, request1) = start_request(&mut sync).unwrap();
+        let (_, request2) = start_request(&mut sync).unwrap();
+
+        sync.warp_sync_request_success(request1, proof(&[10], 0), true);
+        assert_eq!(verify_all(&mut sync), 0);
+        let _ = sync.remove_source(source1);
+        sync.warp_sync_request_success(request2, proof(&[10], 0), true);
+        assert_eq!(verify_all(&mut sync), 0);
+
+        // Only one proof is available, and another one must be requested.
+        assert_eq!(progress(&sync), (0, false));
+        assert!(start_request(&mut sync).is_some());
+    }
+
+    #[test]
+    fn multiple_sources_requested_after_non_final_proofs() {
+        let mut sync = new_sync(2);
+        sync.add_source(());
+        sync.add_source(());
+        let (_, request1) = start_request(&mut sync).unwrap();
+        let (_, request2) = start_request(&mut sync).unwrap();
+        sync.warp_sync_request_success(request1, proof(&[10], 0), false);
+        sync.warp_sync_request_success(request2, proof(&[10], 0), false);
+        assert_eq!(verify_all(&mut sync), 0);
+
+        // The next proofs, starting from block 10, are again requested from both sources.
+        let block_10_hash =
+            header::hash_from_scale_encoded_header(&proof(&[10], 0)[0].scale_encoded_header);
+        assert_eq!(sync.desired_requests().count(), 2);
+        assert!(sync.desired_requests().all(|(_, _, rq)| matches!(
+            rq,
+            DesiredRequest::WarpSyncRequest { block_hash } if block_hash == block_10_hash
+        )));
+    }
+}
@@ -28,7 +28,8 @@
 //! generated. This can be done for example through a UI, through an off-chain worker, or other. A
 //! transaction can be either signed (i.e. have a signature attached to it) or unsigned, depending
 //! on the action to be performed. A balance transfer, for example, generally always requires a
-//! signature.
+//! signature. The [`calls`] module contains helpers for building some of the calls that
+//! transactions contain.
 //!
 //! - The transaction is then processed by a node, generally the node that belongs to the author
 //! of the transaction, where it is *validated* by passing it as parameter to a runtime entry
@@ -85,6 +86,10 @@
 //! certain block B, it will forever remain considered as invalid on any descendant of B, but a
 //! client also attempts to not cache that information for *too long* through heuristics.
 
+pub mod calls;
+pub mod dispatch;
+pub mod events;
+pub mod extrinsic;
 pub mod light_pool;
 pub mod pool;
 pub mod validate;
@@ -0,0 +1,332 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Construction of calls that wrap other calls.
+//!
+//! A *call* is the SCALE-encoded description of the action that a transaction performs. It starts
+//! with the index of the pallet and the index of the call within this pallet, followed with the
+//! parameters of the call.
+//!
+//! This module provides helpers to build the `Multisig.as_multi` and `Proxy.proxy` calls, which
+//! both contain another call as parameter.
+//!
+//! The indices of the pallets and calls, the encoding of account addresses, and the encoding of
+//! weights all depend on the runtime and must be determined by the API user by parsing the
+//! metadata of the runtime. This module doesn't parse the metadata.
+
+use alloc::vec::Vec;
+
+use crate::util;
+
+/// Index of a call in the runtime, as found in the metadata.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct CallIndex {
+    /// Index of the pallet within the runtime.
+    pub pallet_index: u8,
+    /// Index of the call within the pallet.
+    pub call_index: u8,
+}
+
+/// How account addresses are encoded in the parameters of calls.
+///
+/// Corresponds to the `Lookup::Source` type of the runtime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AddressEncoding {
+    /// Addresses are encoded as a 32 bytes account id.
+    AccountId,
+    /// Addresses are encoded as a `MultiAddress` enum whose variant is `Id`. This is the case in
+    /// the Polkadot and Kusama runtimes.
+    MultiAddress,
+}
+
+/// Weight passed as parameter of a call.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Weight {
+    /// Weight encoded as a single `u64`, used by older runtimes.
+    V1(u64),
+    /// Weight encoded as a computation time and a proof size, both SCALE-compact-encoded.
+    V2 {
+        /// Computation time, in picoseconds.
+        ref_time: u64,
+        /// Size of the storage proof, in bytes.
+        proof_size: u64,
+    },
+}
+
+/// Block height and index within the block body of the transaction that has started a
+/// multisignature operation.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Timepoint {
+    /// Height of the block that contains the transaction.
+    pub height: u32,
+    /// Index of the transaction within the block body.
+    pub index: u32,
+}
+
+/// Configuration for [`as_multi`].
+#[derive(Debug, Clone)]
+pub struct AsMultiConfig<'a, TSig> {
+    /// Index of the `Multisig.as_multi` call.
+    pub call_index: CallIndex,
+
+    /// Number of approvals necessary for the call to be dispatched. Must be at least 2.
+    pub threshold: u16,
+
+    /// Account of the signer of the transaction that will contain the call.
+    pub signer: [u8; 32],
+
+    /// List of all the accounts of the multisignature operation. May or may not contain
+    /// [`AsMultiConfig::signer`], and doesn't need to be ordered.
+    pub signatories: TSig,
+
+    /// `None` for the first approval of the operation. For the subsequent approvals, must be
+    /// the [`Timepoint`] of the first approval.
+    pub maybe_timepoint: Option<Timepoint>,
+
+    /// SCALE-encoded call to dispatch once enough approvals have been gathered.
+    pub call: &'a [u8],
+
+    /// Maximum weight of the execution of [`AsMultiConfig::call`].
+    pub max_weight: Weight,
+}
+
+/// Builds a `Multisig.as_multi` call.
+///
+/// The runtime requires the list of signatories other than the signer to be sorted and without
+/// duplicates. This function takes care of removing the signer from the list and sorting it.
+pub fn as_multi<'a>(
+    config: AsMultiConfig<'a, impl IntoIterator<Item = &'a [u8; 32]>>,
+) -> Result<Vec<u8>, MultisigError> {
+    if config.threshold < 2 {
+        return Err(MultisigError::ThresholdTooLow);
+    }
+
+    let other_signatories = other_signatories(&config.signer, config.signatories)?;
+    if other_signatories.len() + 1 < usize::from(config.threshold) {
+        return Err(MultisigError::NotEnoughSignatories);
+    }
+
+    let mut out = Vec::with_capacity(2 + 2 + 5 + other_signatories.len() * 32 + config.call.len());
+    out.push(config.call_index.pallet_index);
+    out.push(config.call_index.call_index);
+    out.extend_from_slice(&config.threshold.to_le_bytes());
+    out.extend_from_slice(util::encode_scale_compact_usize(other_signatories.len()).as_ref());
+    for signatory in &other_signatories {
+        out.extend_from_slice(&signatory[..]);
+    }
+    match config.maybe_timepoint {
+        None => out.push(0),
+        Some(timepoint) => {
+            out.push(1);
+            out.extend_from_slice(&timepoint.height.to_le_bytes());
+            out.extend_from_slice(&timepoint.index.to_le_bytes());
+        }
+    }
+    out.extend_from_slice(config.call);
+    encode_weight(&config.max_weight, &mut out);
+    Ok(out)
+}
+
+/// Returns the account of a multisignature operation, in other words the account the calls
+/// passed to [`as_multi`] are dispatched from.
+///
+/// `signatories` must contain all the accounts of the operation, including the signer, in any
+/// order.
+pub fn multisig_account_id<'a>(
+    signatories: impl IntoIterator<Item = &'a [u8; 32]>,
+    threshold: u16,
+) -> Result<[u8; 32], MultisigError> {
+    let mut signatories = signatories.into_iter().collect::<Vec<_>>();
+    signatories.sort_unstable();
+    if signatories.windows(2).any(|w| w[0] == w[1]) {
+        return Err(MultisigError::DuplicateSignatory);
+    }
+
+    let mut hasher = blake2_rfc::blake2b::Blake2b::new(32);
+    hasher.update(b"modlpy/utilisuba");
+    hasher.update(util::encode_scale_compact_usize(signatories.len()).as_ref());
+    for signatory in &signatories {
+        hasher.update(&signatory[..]);
+    }
+    hasher.update(&threshold.to_le_bytes());
+    Ok(<[u8; 32]>::try_from(hasher.finalize().as_bytes()).unwrap())
+}
+
+/// Builds a `Proxy.proxy` call.
+///
+/// `force_proxy_type` is the index of the variant of the `ProxyType` enum of the runtime, or
+/// `None` to let the runtime pick any proxy relationship between the signer and `real`.
+pub fn proxy(
+    call_index: CallIndex,
+    address_encoding: AddressEncoding,
+    real: &[u8; 32],
+    force_proxy_type: Option<u8>,
+    call: &[u8],
+) -> Vec<u8> {
+    let mut out = Vec::with_capacity(2 + 33 + 2 + call.len());
+    out.push(call_index.pallet_index);
+    out.push(call_index.call_index);
+    match address_encoding {
+        AddressEncoding::AccountId => {}
+        AddressEncoding::MultiAddress => out.push(0),
+    }
+    out.extend_from_slice(&real[..]);
+    match force_proxy_type {
+        None => out.push(0),
+        Some(proxy_type) => {
+            out.push(1);
+            out.push(proxy_type);
+        }
+    }
+    out.extend_from_slice(call);
+    out
+}
+
+/// Error potentially returned by [`as_multi`] or [`multisig_account_id`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum MultisigError {
+    /// Threshold must be at least 2. A threshold of 1 requires using the
+    /// `Multisig.as_multi_threshold_1` call instead.
+    #[display(fmt = "Multisig threshold must be at least 2")]
+    ThresholdTooLow,
+    /// The number of signatories is inferior to the threshold.
+    #[display(fmt = "Number of signatories is lower than the threshold")]
+    NotEnoughSignatories,
+    /// The same account has been passed multiple times as signatory.
+    #[display(fmt = "Duplicate signatory")]
+    DuplicateSignatory,
+}
+
+/// Returns the sorted list of signatories, excluding `signer`.
+fn other_signatories<'a>(
+    signer: &[u8; 32],
+    signatories: impl IntoIterator<Item = &'a [u8; 32]>,
+) -> Result<Vec<&'a [u8; 32]>, MultisigError> {
+    let mut list = signatories
+        .into_iter()
+        .filter(|s| *s != signer)
+        .collect::<Vec<_>>();
+    list.sort_unstable();
+    if list.windows(2).any(|w| w[0] == w[1]) {
+        return Err(MultisigError::DuplicateSignatory);
+    }
+    Ok(list)
+}
+
+fn encode_weight(weight: &Weight, out: &mut Vec<u8>) {
+    match *weight {
+        Weight::V1(weight) => out.extend_from_slice(&weight.to_le_bytes()),
+        Weight::V2 {
+            ref_time,
+            proof_size,
+        } => {
+            out.extend_from_slice(util::encode_scale_compact_u64(ref_time).as_ref());
+            out.extend_from_slice(util::encode_scale_compact_u64(proof_size).as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    const ALICE: [u8; 32] = [1; 32];
+    const BOB: [u8; 32] = [2; 32];
+    const CHARLIE: [u8; 32] = [3; 32];
+
+    const CALL_INDEX: super::CallIndex = super::CallIndex {
+        pallet_index: 30,
+        call_index: 1,
+    };
+
+    #[test]
+    fn as_multi_encoding() {
+        let encoded = super::as_multi(super::AsMultiConfig {
+            call_index: CALL_INDEX,
+            threshold: 2,
+            signer: BOB,
+            signatories: [&CHARLIE, &BOB, &ALICE],
+            maybe_timepoint: Some(super::Timepoint {
+                height: 5,
+                index: 1,
+            }),
+            call: &[0xaa, 0xbb],
+            max_weight: super::Weight::V2 {
+                ref_time: 100,
+                proof_size: 1,
+            },
+        })
+        .unwrap();
+
+        let mut expected = vec![30, 1, 2, 0, 2 << 2];
+        expected.extend_from_slice(&ALICE);
+        expected.extend_from_slice(&CHARLIE);
+        expected.extend_from_slice(&[1, 5, 0, 0, 0, 1, 0, 0, 0]);
+        expected.extend_from_slice(&[0xaa, 0xbb]);
+        expected.extend_from_slice(&[0x91, 0x01, 1 << 2]);
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn as_multi_errors() {
+        let config = |threshold, signatories: [&'static [u8; 32]; 2]| super::AsMultiConfig {
+            call_index: CALL_INDEX,
+            threshold,
+            signer: ALICE,
+            signatories,
+            maybe_timepoint: None,
+            call: &[],
+            max_weight: super::Weight::V1(0),
+        };
+
+        assert_eq!(
+            super::as_multi(config(1, [&ALICE, &BOB])),
+            Err(super::MultisigError::ThresholdTooLow)
+        );
+        assert_eq!(
+            super::as_multi(config(3, [&ALICE, &BOB])),
+            Err(super::MultisigError::NotEnoughSignatories)
+        );
+        assert_eq!(
+            super::as_multi(config(2, [&BOB, &BOB])),
+            Err(super::MultisigError::DuplicateSignatory)
+        );
+    }
+
+    #[test]
+    fn multisig_account_id_order_independent() {
+        let a = super::multisig_account_id([&ALICE, &BOB, &CHARLIE], 2).unwrap();
+        let b = super::multisig_account_id([&CHARLIE, &ALICE, &BOB], 2).unwrap();
+        let c = super::multisig_account_id([&CHARLIE, &ALICE, &BOB], 3).unwrap();
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+
+    #[test]
+    fn proxy_encoding() {
+        let encoded = super::proxy(
+            CALL_INDEX,
+            super::AddressEncoding::MultiAddress,
+            &ALICE,
+            Some(3),
+            &[0xaa],
+        );
+
+        let mut expected = vec![30, 1, 0];
+        expected.extend_from_slice(&ALICE);
+        expected.extend_from_slice(&[1, 3, 0xaa]);
+        assert_eq!(encoded, expected);
+    }
+}
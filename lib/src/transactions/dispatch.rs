@@ -0,0 +1,606 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the outcome of the dispatch of a call.
+//!
+//! When a transaction is applied, the runtime *dispatches* the call that the transaction
+//! contains. The outcome of this dispatch is reported in several places:
+//!
+//! - The output of `BlockBuilder_apply_extrinsic`, for example when performing a dry run of a
+//! transaction, contains either a [`DispatchError`] or a
+//! [`TransactionValidityError`](super::validate::TransactionValidityError). See
+//! [`decode_apply_extrinsic_result`].
+//! - The `System.ExtrinsicSuccess` and `System.ExtrinsicFailed` events contain a
+//! [`DispatchInfo`], plus a [`DispatchError`] in the case of a failure. See
+//! [`decode_dispatch_info`] and [`decode_extrinsic_failed`].
+//! - The output of `TransactionPaymentApi_query_info` contains the weight and class of the
+//! transaction, encoded the same way as in a [`DispatchInfo`].
+//!
+//! The exact encoding of these values has changed over time and depends on the runtime. See
+//! [`Format`].
+//!
+//! Errors that happen within a pallet are reported as a [`ModuleError`] containing the index of
+//! the pallet and the index of the error. Turning these indices into names requires parsing the
+//! metadata of the runtime, which [`ModuleErrors`] does.
+
+use alloc::{
+    format,
+    string::{String, ToString as _},
+    vec::Vec,
+};
+
+use super::{calls::Weight, validate};
+use crate::{metadata, util};
+
+/// Encoding of the values decoded by this module used by a specific runtime.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Format {
+    /// If `true`, weights are encoded as a computation time and a proof size, both
+    /// SCALE-compact-encoded. If `false`, weights are encoded as a single `u64`.
+    ///
+    /// Corresponds to version 2 of the `TransactionPaymentApi` API.
+    pub weight_v2: bool,
+
+    /// If `true`, the error of a [`ModuleError`] is encoded as 4 bytes. If `false`, it is encoded
+    /// as a single byte. Older runtimes use a single byte.
+    pub module_error_four_bytes: bool,
+}
+
+/// Information about the dispatch of a call, as found in the `System.ExtrinsicSuccess` and
+/// `System.ExtrinsicFailed` events.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct DispatchInfo {
+    /// Weight of the call.
+    pub weight: Weight,
+    /// Class of the call.
+    pub class: DispatchClass,
+    /// `true` if the sender of the transaction has paid fees for the call.
+    pub pays_fee: bool,
+}
+
+/// Class of a dispatched call.
+#[derive(Debug, derive_more::Display, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum DispatchClass {
+    /// Normal call, such as a balance transfer.
+    Normal,
+    /// Call that has an impact on the operations of the chain, such as a runtime upgrade.
+    Operational,
+    /// Call that must always be included in a block, such as an inherent.
+    Mandatory,
+}
+
+/// Reason why a dispatched call has failed.
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DispatchError {
+    /// Unspecified error.
+    Other,
+    /// Failed to lookup some data.
+    CannotLookup,
+    /// The origin of the call isn't allowed to perform it.
+    BadOrigin,
+    /// Error specific to a pallet.
+    #[display(fmt = "{_0}")]
+    Module(ModuleError),
+    /// At least one consumer is remaining so the account cannot be destroyed.
+    ConsumerRemaining,
+    /// There are no providers so the account cannot be created.
+    NoProviders,
+    /// There are too many consumers so the account cannot be created.
+    TooManyConsumers,
+    /// Error related to a token or an asset.
+    #[display(fmt = "Token error: {_0}")]
+    Token(TokenError),
+    /// Arithmetic error.
+    #[display(fmt = "Arithmetic error: {_0}")]
+    Arithmetic(ArithmeticError),
+    /// Error related to storage transactional layers.
+    #[display(fmt = "Transactional error: {_0}")]
+    Transactional(TransactionalError),
+    /// Resources are exhausted.
+    Exhausted,
+    /// The state of the chain is corrupt.
+    Corruption,
+    /// Some resource is unavailable.
+    Unavailable,
+    /// The root origin isn't allowed to perform the call.
+    RootNotAllowed,
+}
+
+/// Error specific to a pallet.
+#[derive(Debug, derive_more::Display, Copy, Clone, PartialEq, Eq, Hash)]
+#[display(fmt = "Error #{} in pallet #{}", "self.error_index()", index)]
+pub struct ModuleError {
+    /// Index of the pallet within the runtime, as found in the metadata.
+    pub index: u8,
+    /// Encoded error. The first byte is the index of the error within the pallet. The other
+    /// bytes are only present in the encoding if [`Format::module_error_four_bytes`] is `true`,
+    /// and are `0` otherwise.
+    pub error: [u8; 4],
+}
+
+impl ModuleError {
+    /// Returns the index of the error within the pallet, as found in the metadata.
+    pub fn error_index(&self) -> u8 {
+        self.error[0]
+    }
+}
+
+/// Error related to a token or an asset.
+#[derive(Debug, derive_more::Display, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TokenError {
+    /// Funds are unavailable.
+    FundsUnavailable,
+    /// Some part of the balance gives the only provider reference to the account and thus cannot
+    /// be (re)moved.
+    OnlyProvider,
+    /// The account cannot exist with the funds that would be given.
+    BelowMinimum,
+    /// The account cannot be created.
+    CannotCreate,
+    /// The asset in question is unknown.
+    UnknownAsset,
+    /// Funds exist but are frozen.
+    Frozen,
+    /// The operation isn't supported by the asset.
+    Unsupported,
+    /// The account cannot be created because of a hold.
+    CannotCreateHold,
+    /// Withdrawal would cause unwanted loss of the account.
+    NotExpendable,
+    /// The account cannot receive the assets.
+    Blocked,
+    /// Error unknown to this implementation.
+    #[display(fmt = "Unknown error #{_0}")]
+    Unknown(u8),
+}
+
+/// Arithmetic error.
+#[derive(Debug, derive_more::Display, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum ArithmeticError {
+    /// Underflow.
+    Underflow,
+    /// Overflow.
+    Overflow,
+    /// Division by zero.
+    DivisionByZero,
+}
+
+/// Error related to storage transactional layers.
+#[derive(Debug, derive_more::Display, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum TransactionalError {
+    /// Too many transactional layers have been spawned.
+    LimitReached,
+    /// A transactional layer was expected, but does not exist.
+    NoLayer,
+}
+
+/// Error that can happen during the decoding.
+#[derive(Debug, derive_more::Display, Clone)]
+pub struct DecodeError();
+
+/// Attempt to decode a SCALE-encoded [`DispatchInfo`], such as the data of the
+/// `System.ExtrinsicSuccess` event.
+pub fn decode_dispatch_info(
+    scale_encoded: &[u8],
+    format: Format,
+) -> Result<DispatchInfo, DecodeError> {
+    nom::combinator::all_consuming(nom_dispatch_info::<nom::error::Error<&[u8]>>(format))(
+        scale_encoded,
+    )
+    .map(|(_, info)| info)
+    .map_err(|_| DecodeError())
+}
+
+/// Attempt to decode a SCALE-encoded [`DispatchError`].
+pub fn decode_dispatch_error(
+    scale_encoded: &[u8],
+    format: Format,
+) -> Result<DispatchError, DecodeError> {
+    nom::combinator::all_consuming(nom_dispatch_error::<nom::error::Error<&[u8]>>(format))(
+        scale_encoded,
+    )
+    .map(|(_, error)| error)
+    .map_err(|_| DecodeError())
+}
+
+/// Attempt to decode a SCALE-encoded `DispatchResult`, in other words a
+/// `Result<(), DispatchError>`.
+pub fn decode_dispatch_result(
+    scale_encoded: &[u8],
+    format: Format,
+) -> Result<Result<(), DispatchError>, DecodeError> {
+    nom::combinator::all_consuming(nom_dispatch_result::<nom::error::Error<&[u8]>>(format))(
+        scale_encoded,
+    )
+    .map(|(_, result)| result)
+    .map_err(|_| DecodeError())
+}
+
+/// Attempt to decode the data of the `System.ExtrinsicFailed` event.
+pub fn decode_extrinsic_failed(
+    scale_encoded: &[u8],
+    format: Format,
+) -> Result<(DispatchError, DispatchInfo), DecodeError> {
+    nom::combinator::all_consuming(nom::sequence::tuple((
+        nom_dispatch_error::<nom::error::Error<&[u8]>>(format),
+        nom_dispatch_info(format),
+    )))(scale_encoded)
+    .map(|(_, event)| event)
+    .map_err(|_| DecodeError())
+}
+
+/// Attempt to decode the output of `BlockBuilder_apply_extrinsic`, such as the output of a dry
+/// run of a transaction.
+///
+/// The outer `Result` indicates whether the transaction is valid. The inner `Result` indicates
+/// whether the dispatch of its call has succeeded.
+#[allow(clippy::type_complexity)]
+pub fn decode_apply_extrinsic_result(
+    scale_encoded: &[u8],
+    format: Format,
+) -> Result<Result<Result<(), DispatchError>, validate::TransactionValidityError>, DecodeError> {
+    // The parsers of the `validate` module use the default error type of `nom`.
+    let (rest, result) = nom::branch::alt((
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[0]),
+                nom_dispatch_result::<nom::error::Error<&[u8]>>(format),
+            ),
+            Ok,
+        ),
+        nom::combinator::map(
+            nom::sequence::preceded(
+                nom::bytes::complete::tag(&[1]),
+                validate::transaction_validity_error,
+            ),
+            Err,
+        ),
+    ))(scale_encoded)
+    .map_err(|_| DecodeError())?;
+
+    if !rest.is_empty() {
+        return Err(DecodeError());
+    }
+
+    Ok(result)
+}
+
+/// Names of the errors of the pallets of a runtime, extracted from its metadata.
+#[derive(Debug, Clone)]
+pub struct ModuleErrors {
+    /// Keys are the index of the pallet and the index of the error within the pallet.
+    errors: hashbrown::HashMap<(u8, u8), ModuleErrorInfo, fnv::FnvBuildHasher>,
+}
+
+/// Information about an error of a pallet, as found in the metadata.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleErrorInfo {
+    /// Name of the pallet, for example `Balances`.
+    pub pallet_name: String,
+    /// Name of the error, for example `InsufficientBalance`.
+    pub error_name: String,
+    /// Documentation of the error, one entry per line.
+    pub docs: Vec<String>,
+}
+
+impl ModuleErrors {
+    /// Extracts the errors of the pallets from the given metadata.
+    ///
+    /// The metadata is the output of the `Metadata_metadata` runtime call, after its length
+    /// prefix has been removed. Only versions 14 and 15 of the metadata format are supported.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, metadata::Error> {
+        let metadata = metadata::decode(metadata)?;
+
+        let mut errors = hashbrown::HashMap::with_capacity_and_hasher(0, Default::default());
+        for pallet in &metadata.pallets {
+            let Some(error_ty) = pallet.error_ty else {
+                continue;
+            };
+            let Some(metadata::TypeDef::Variant(variants)) = metadata.types.get(error_ty) else {
+                return Err(metadata::Error::ParseError);
+            };
+            for variant in variants {
+                errors.insert(
+                    (pallet.index, variant.index),
+                    ModuleErrorInfo {
+                        pallet_name: pallet.name.clone(),
+                        error_name: variant.name.clone(),
+                        docs: variant.docs.clone(),
+                    },
+                );
+            }
+        }
+
+        Ok(ModuleErrors { errors })
+    }
+
+    /// Returns the information about the given error, or `None` if it isn't found in the
+    /// metadata.
+    pub fn get(&self, error: &ModuleError) -> Option<&ModuleErrorInfo> {
+        self.errors.get(&(error.index, error.error_index()))
+    }
+
+    /// Returns a human-readable description of the given error, such as
+    /// `Balances::InsufficientBalance`.
+    pub fn describe(&self, error: &DispatchError) -> String {
+        match error {
+            DispatchError::Module(module_error) => match self.get(module_error) {
+                Some(info) => format!("{}::{}", info.pallet_name, info.error_name),
+                None => module_error.to_string(),
+            },
+            error => error.to_string(),
+        }
+    }
+}
+
+fn nom_dispatch_info<'a, E: nom::error::ParseError<&'a [u8]>>(
+    format: Format,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], DispatchInfo, E> {
+    nom::combinator::map(
+        nom::sequence::tuple((
+            nom_weight(format.weight_v2),
+            nom_dispatch_class,
+            nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+                0 => Some(true),
+                1 => Some(false),
+                _ => None,
+            }),
+        )),
+        |(weight, class, pays_fee)| DispatchInfo {
+            weight,
+            class,
+            pays_fee,
+        },
+    )
+}
+
+/// Decodes a weight, either as a single `u64` or as a computation time and a proof size.
+pub(crate) fn nom_weight<'a, E: nom::error::ParseError<&'a [u8]>>(
+    weight_v2: bool,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Weight, E> {
+    move |bytes| {
+        if weight_v2 {
+            nom::combinator::map(
+                nom::sequence::tuple((util::nom_scale_compact_u64, util::nom_scale_compact_u64)),
+                |(ref_time, proof_size)| Weight::V2 {
+                    ref_time,
+                    proof_size,
+                },
+            )(bytes)
+        } else {
+            nom::combinator::map(nom::number::complete::le_u64, Weight::V1)(bytes)
+        }
+    }
+}
+
+/// Decodes a [`DispatchClass`].
+pub(crate) fn nom_dispatch_class<'a, E: nom::error::ParseError<&'a [u8]>>(
+    bytes: &'a [u8],
+) -> nom::IResult<&'a [u8], DispatchClass, E> {
+    nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+        0 => Some(DispatchClass::Normal),
+        1 => Some(DispatchClass::Operational),
+        2 => Some(DispatchClass::Mandatory),
+        _ => None,
+    })(bytes)
+}
+
+fn nom_dispatch_result<'a, E: nom::error::ParseError<&'a [u8]>>(
+    format: Format,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], Result<(), DispatchError>, E> {
+    nom::branch::alt((
+        nom::combinator::map(nom::bytes::complete::tag(&[0]), |_| Ok(())),
+        nom::combinator::map(
+            nom::sequence::preceded(nom::bytes::complete::tag(&[1]), nom_dispatch_error(format)),
+            Err,
+        ),
+    ))
+}
+
+fn nom_dispatch_error<'a, E: nom::error::ParseError<&'a [u8]>>(
+    format: Format,
+) -> impl FnMut(&'a [u8]) -> nom::IResult<&'a [u8], DispatchError, E> {
+    move |bytes| {
+        let (bytes, variant) = nom::number::complete::u8(bytes)?;
+        match variant {
+            0 => Ok((bytes, DispatchError::Other)),
+            1 => Ok((bytes, DispatchError::CannotLookup)),
+            2 => Ok((bytes, DispatchError::BadOrigin)),
+            3 => nom::combinator::map(
+                nom::sequence::tuple((
+                    nom::number::complete::u8,
+                    nom::bytes::complete::take(if format.module_error_four_bytes {
+                        4u32
+                    } else {
+                        1u32
+                    }),
+                )),
+                |(index, error_bytes): (u8, &[u8])| {
+                    let mut error = [0; 4];
+                    error[..error_bytes.len()].copy_from_slice(error_bytes);
+                    DispatchError::Module(ModuleError { index, error })
+                },
+            )(bytes),
+            4 => Ok((bytes, DispatchError::ConsumerRemaining)),
+            5 => Ok((bytes, DispatchError::NoProviders)),
+            6 => Ok((bytes, DispatchError::TooManyConsumers)),
+            7 => nom::combinator::map(nom::number::complete::u8, |n| {
+                DispatchError::Token(match n {
+                    0 => TokenError::FundsUnavailable,
+                    1 => TokenError::OnlyProvider,
+                    2 => TokenError::BelowMinimum,
+                    3 => TokenError::CannotCreate,
+                    4 => TokenError::UnknownAsset,
+                    5 => TokenError::Frozen,
+                    6 => TokenError::Unsupported,
+                    7 => TokenError::CannotCreateHold,
+                    8 => TokenError::NotExpendable,
+                    9 => TokenError::Blocked,
+                    n => TokenError::Unknown(n),
+                })
+            })(bytes),
+            8 => nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+                0 => Some(DispatchError::Arithmetic(ArithmeticError::Underflow)),
+                1 => Some(DispatchError::Arithmetic(ArithmeticError::Overflow)),
+                2 => Some(DispatchError::Arithmetic(ArithmeticError::DivisionByZero)),
+                _ => None,
+            })(bytes),
+            9 => nom::combinator::map_opt(nom::number::complete::u8, |n| match n {
+                0 => Some(DispatchError::Transactional(
+                    TransactionalError::LimitReached,
+                )),
+                1 => Some(DispatchError::Transactional(TransactionalError::NoLayer)),
+                _ => None,
+            })(bytes),
+            10 => Ok((bytes, DispatchError::Exhausted)),
+            11 => Ok((bytes, DispatchError::Corruption)),
+            12 => Ok((bytes, DispatchError::Unavailable)),
+            13 => Ok((bytes, DispatchError::RootNotAllowed)),
+            _ => Err(nom::Err::Error(nom::error::make_error(
+                bytes,
+                nom::error::ErrorKind::Tag,
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::calls::Weight;
+
+    const FORMAT: super::Format = super::Format {
+        weight_v2: true,
+        module_error_four_bytes: true,
+    };
+
+    #[test]
+    fn decode_extrinsic_failed() {
+        // Module error #2 in pallet #5, followed with a weight of (100_000_000, 3_593), the
+        // `Normal` class, and `Pays::Yes`.
+        let encoded = [3, 5, 2, 0, 0, 0, 0x02, 0x84, 0xd7, 0x17, 0x25, 0x38, 0, 0];
+        let (error, info) = super::decode_extrinsic_failed(&encoded, FORMAT).unwrap();
+        assert_eq!(
+            error,
+            super::DispatchError::Module(super::ModuleError {
+                index: 5,
+                error: [2, 0, 0, 0]
+            })
+        );
+        assert_eq!(
+            info.weight,
+            Weight::V2 {
+                ref_time: 100_000_000,
+                proof_size: 3_593
+            }
+        );
+        assert_eq!(info.class, super::DispatchClass::Normal);
+        assert!(info.pays_fee);
+    }
+
+    #[test]
+    fn decode_single_byte_module_error() {
+        let format = super::Format {
+            weight_v2: false,
+            module_error_four_bytes: false,
+        };
+        let error = super::decode_dispatch_error(&[3, 10, 4], format).unwrap();
+        assert_eq!(
+            error,
+            super::DispatchError::Module(super::ModuleError {
+                index: 10,
+                error: [4, 0, 0, 0]
+            })
+        );
+        assert!(super::decode_dispatch_error(&[3, 10, 4, 0, 0, 0], format).is_err());
+    }
+
+    #[test]
+    fn decode_apply_extrinsic_result() {
+        assert_eq!(
+            super::decode_apply_extrinsic_result(&[0, 0], FORMAT).unwrap(),
+            Ok(Ok(()))
+        );
+        assert_eq!(
+            super::decode_apply_extrinsic_result(&[0, 1, 8, 1], FORMAT).unwrap(),
+            Ok(Err(super::DispatchError::Arithmetic(
+                super::ArithmeticError::Overflow
+            )))
+        );
+        assert!(super::decode_apply_extrinsic_result(&[1, 0, 2], FORMAT)
+            .unwrap()
+            .is_err());
+        assert!(super::decode_apply_extrinsic_result(&[0, 0, 0], FORMAT).is_err());
+    }
+
+    #[test]
+    fn module_errors_from_metadata() {
+        fn string(out: &mut Vec<u8>, s: &str) {
+            out.push(u8::try_from(s.len() << 2).unwrap());
+            out.extend_from_slice(s.as_bytes());
+        }
+
+        let mut metadata = b"meta".to_vec();
+        metadata.push(14);
+
+        // Registry containing a single enum type with id 0.
+        metadata.push(1 << 2);
+        metadata.push(0);
+        metadata.push(0); // Path.
+        metadata.push(0); // Type parameters.
+        metadata.push(1); // Variant type.
+        metadata.push(1 << 2);
+        string(&mut metadata, "InsufficientBalance");
+        metadata.push(0); // Fields.
+        metadata.push(2); // Index.
+        metadata.push(1 << 2);
+        string(&mut metadata, "Balance too low.");
+        metadata.push(0); // Documentation of the type.
+
+        // A single pallet whose errors are the type with id 0.
+        metadata.push(1 << 2);
+        string(&mut metadata, "Balances");
+        metadata.push(0); // Storage.
+        metadata.push(0); // Calls.
+        metadata.push(0); // Events.
+        metadata.push(0); // Constants.
+        metadata.extend_from_slice(&[1, 0]); // Errors.
+        metadata.push(5); // Index.
+
+        // Rest of the metadata, which isn't parsed.
+        metadata.extend_from_slice(&[0xff; 8]);
+
+        let errors = super::ModuleErrors::from_metadata(&metadata).unwrap();
+        let error = super::ModuleError {
+            index: 5,
+            error: [2, 0, 0, 0],
+        };
+        let info = errors.get(&error).unwrap();
+        assert_eq!(info.pallet_name, "Balances");
+        assert_eq!(info.error_name, "InsufficientBalance");
+        assert_eq!(info.docs, vec!["Balance too low.".to_owned()]);
+        assert_eq!(
+            errors.describe(&super::DispatchError::Module(error)),
+            "Balances::InsufficientBalance"
+        );
+        assert!(errors
+            .get(&super::ModuleError {
+                index: 5,
+                error: [3, 0, 0, 0]
+            })
+            .is_none());
+    }
+}
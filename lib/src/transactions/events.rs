@@ -0,0 +1,638 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Decoding of the events of a block.
+//!
+//! The events generated while executing a block are stored in the `System.Events` storage
+//! entry, as a list of event records. Each record contains the phase during which the event has
+//! been generated (for example while applying a specific extrinsic), the event itself, and a
+//! list of topics.
+//!
+//! The encoding of the events depends on the runtime. An [`EventsDecoder`], built from the
+//! metadata of the runtime, is capable of splitting the storage value into individual
+//! [`EventRecord`]s, and of finding out from the `System.ExtrinsicSuccess` and
+//! `System.ExtrinsicFailed` events whether each extrinsic of the block has succeeded. See also
+//! the [`dispatch`](super::dispatch) module.
+//!
+//! The fields of the events are not decoded, and are instead provided in their SCALE-encoded
+//! form.
+
+use alloc::{vec, vec::Vec};
+
+use super::dispatch;
+use crate::{
+    metadata::{self, Field, TypeDef, TypesRegistry, Variant},
+    util,
+};
+
+/// Key of the `System.Events` storage entry, containing the events of a block.
+pub const SYSTEM_EVENTS_STORAGE_KEY: [u8; 32] = [
+    0x26, 0xaa, 0x39, 0x4e, 0xea, 0x56, 0x30, 0xe0, 0x7c, 0x48, 0xae, 0x0c, 0x95, 0x58, 0xce, 0xf7,
+    0x80, 0xd4, 0x1e, 0x5e, 0x16, 0x05, 0x67, 0x65, 0xbc, 0x84, 0x61, 0x85, 0x10, 0x72, 0xc9, 0xd7,
+];
+
+/// Decoder of the events of a specific runtime, extracted from its metadata.
+#[derive(Debug, Clone)]
+pub struct EventsDecoder {
+    /// All the types of the registry of the metadata.
+    types: TypesRegistry,
+    /// Fields of the type of the event records.
+    record_fields: Vec<Field>,
+    /// Format of the values of the `System.ExtrinsicSuccess` and `System.ExtrinsicFailed`
+    /// events.
+    dispatch_format: dispatch::Format,
+}
+
+/// Event found in the `System.Events` storage entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EventRecord<'a> {
+    /// Moment when the event has been generated.
+    pub phase: Phase,
+    /// Name of the pallet that has generated the event, for example `Balances`.
+    pub pallet_name: &'a str,
+    /// Index of the pallet that has generated the event.
+    pub pallet_index: u8,
+    /// Name of the event, for example `Transfer`.
+    pub event_name: &'a str,
+    /// Index of the event within the pallet.
+    pub event_index: u8,
+    /// SCALE-encoded fields of the event, one after the other.
+    pub fields: &'a [u8],
+}
+
+/// Moment when an event has been generated.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Phase {
+    /// Event has been generated while applying the extrinsic with the given index.
+    ApplyExtrinsic(u32),
+    /// Event has been generated while finalizing the block.
+    Finalization,
+    /// Event has been generated while initializing the block.
+    Initialization,
+}
+
+/// Outcome of the dispatch of an extrinsic, as found in the events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtrinsicOutcome {
+    /// The call of the extrinsic has been successfully dispatched.
+    Success(dispatch::DispatchInfo),
+    /// The call of the extrinsic has failed.
+    Failed {
+        /// Reason for the failure.
+        error: dispatch::DispatchError,
+        /// Information about the dispatch.
+        info: dispatch::DispatchInfo,
+    },
+}
+
+impl EventsDecoder {
+    /// Extracts the information necessary to decode events from the given metadata.
+    ///
+    /// The metadata is the output of the `Metadata_metadata` runtime call, after its length
+    /// prefix has been removed. Only versions 14 and 15 of the metadata format are supported.
+    pub fn from_metadata(metadata: &[u8]) -> Result<Self, MetadataError> {
+        let metadata = metadata::decode(metadata).map_err(MetadataError::Decode)?;
+        let events_ty = metadata
+            .storage_entry_type("System", "Events")
+            .ok_or(MetadataError::EventsNotFound)?;
+        let types = metadata.types;
+
+        // The `System.Events` storage entry must be a list of records that contain at least a
+        // phase and an event.
+        let record_fields = match types.get(events_ty) {
+            Some(TypeDef::Sequence(record_ty)) => match types.get(*record_ty) {
+                Some(TypeDef::Composite(fields)) => fields.clone(),
+                _ => return Err(MetadataError::UnsupportedEventsType),
+            },
+            _ => return Err(MetadataError::UnsupportedEventsType),
+        };
+        for name in ["phase", "event"] {
+            if !record_fields
+                .iter()
+                .any(|f| f.name.as_deref() == Some(name))
+            {
+                return Err(MetadataError::UnsupportedEventsType);
+            }
+        }
+
+        let mut decoder = EventsDecoder {
+            types,
+            record_fields,
+            dispatch_format: dispatch::Format {
+                weight_v2: false,
+                module_error_four_bytes: false,
+            },
+        };
+        decoder.dispatch_format = decoder.find_dispatch_format();
+        Ok(decoder)
+    }
+
+    /// Returns the format of the values of the `System.ExtrinsicSuccess` and
+    /// `System.ExtrinsicFailed` events, as determined from the metadata.
+    pub fn dispatch_format(&self) -> dispatch::Format {
+        self.dispatch_format
+    }
+
+    /// Splits the value of the `System.Events` storage entry into individual events.
+    pub fn decode<'a>(
+        &'a self,
+        scale_encoded_events: &'a [u8],
+    ) -> Result<Vec<EventRecord<'a>>, DecodeError> {
+        let (mut bytes, num_records) =
+            util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(scale_encoded_events)
+                .map_err(|_| DecodeError())?;
+
+        // Each record is at least one byte, which prevents allocating a huge vector.
+        let mut records = Vec::with_capacity(num_records.min(bytes.len()));
+
+        for _ in 0..num_records {
+            let mut phase = None;
+            let mut event = None;
+
+            for field in &self.record_fields {
+                match field.name.as_deref() {
+                    Some("phase") => {
+                        let (rest, p) = self.decode_phase(field.ty, bytes)?;
+                        phase = Some(p);
+                        bytes = rest;
+                    }
+                    Some("event") => {
+                        let (rest, e) = self.decode_event(field.ty, bytes)?;
+                        event = Some(e);
+                        bytes = rest;
+                    }
+                    _ => {
+                        bytes = self
+                            .types
+                            .skip_value(field.ty, bytes)
+                            .ok_or(DecodeError())?
+                    }
+                }
+            }
+
+            let (pallet_name, pallet_index, event_name, event_index, fields) = event.unwrap();
+            records.push(EventRecord {
+                phase: phase.unwrap(),
+                pallet_name,
+                pallet_index,
+                event_name,
+                event_index,
+                fields,
+            });
+        }
+
+        if !bytes.is_empty() {
+            return Err(DecodeError());
+        }
+
+        Ok(records)
+    }
+
+    /// Returns the outcome of each of the `num_extrinsics` extrinsics of a block, given the
+    /// events of this block.
+    ///
+    /// An entry is `None` if no `System.ExtrinsicSuccess` or `System.ExtrinsicFailed` event has
+    /// been found for the corresponding extrinsic.
+    pub fn extrinsics_outcomes(
+        &self,
+        records: &[EventRecord],
+        num_extrinsics: usize,
+    ) -> Result<Vec<Option<ExtrinsicOutcome>>, DecodeError> {
+        let mut outcomes = vec![None; num_extrinsics];
+
+        for record in records {
+            let extrinsic_index = match record.phase {
+                Phase::ApplyExtrinsic(index) => index,
+                _ => continue,
+            };
+
+            if record.pallet_name != "System" {
+                continue;
+            }
+
+            let outcome = match record.event_name {
+                "ExtrinsicSuccess" => ExtrinsicOutcome::Success(
+                    dispatch::decode_dispatch_info(record.fields, self.dispatch_format)
+                        .map_err(|_| DecodeError())?,
+                ),
+                "ExtrinsicFailed" => {
+                    let (error, info) =
+                        dispatch::decode_extrinsic_failed(record.fields, self.dispatch_format)
+                            .map_err(|_| DecodeError())?;
+                    ExtrinsicOutcome::Failed { error, info }
+                }
+                _ => continue,
+            };
+
+            if let Some(entry) = usize::try_from(extrinsic_index)
+                .ok()
+                .and_then(|idx| outcomes.get_mut(idx))
+            {
+                *entry = Some(outcome);
+            }
+        }
+
+        Ok(outcomes)
+    }
+
+    fn decode_phase<'a>(&self, ty: u32, bytes: &'a [u8]) -> Result<(&'a [u8], Phase), DecodeError> {
+        let (variant, bytes) = self.decode_variant_index(ty, bytes)?;
+        match variant.name.as_str() {
+            "ApplyExtrinsic" => {
+                let (index, rest) = bytes
+                    .get(..4)
+                    .map(|b| {
+                        (
+                            u32::from_le_bytes(<[u8; 4]>::try_from(b).unwrap()),
+                            &bytes[4..],
+                        )
+                    })
+                    .ok_or(DecodeError())?;
+                Ok((rest, Phase::ApplyExtrinsic(index)))
+            }
+            "Finalization" => Ok((bytes, Phase::Finalization)),
+            "Initialization" => Ok((bytes, Phase::Initialization)),
+            _ => Err(DecodeError()),
+        }
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn decode_event<'a>(
+        &'a self,
+        ty: u32,
+        bytes: &'a [u8],
+    ) -> Result<(&'a [u8], (&'a str, u8, &'a str, u8, &'a [u8])), DecodeError> {
+        // The outer event type contains one variant per pallet, each containing the event type
+        // of the pallet.
+        let (pallet, bytes) = self.decode_variant_index(ty, bytes)?;
+        let pallet_event_ty = match &pallet.fields[..] {
+            [field] => field.ty,
+            _ => return Err(DecodeError()),
+        };
+
+        let (event, fields_start) = self.decode_variant_index(pallet_event_ty, bytes)?;
+        let mut rest = fields_start;
+        for field in &event.fields {
+            rest = self.types.skip_value(field.ty, rest).ok_or(DecodeError())?;
+        }
+
+        let fields = &fields_start[..fields_start.len() - rest.len()];
+        Ok((
+            rest,
+            (&pallet.name, pallet.index, &event.name, event.index, fields),
+        ))
+    }
+
+    /// Reads the index of a variant of the given enum type.
+    fn decode_variant_index<'a, 'b>(
+        &'a self,
+        ty: u32,
+        bytes: &'b [u8],
+    ) -> Result<(&'a Variant, &'b [u8]), DecodeError> {
+        self.types
+            .decode_variant_index(ty, bytes)
+            .ok_or(DecodeError())
+    }
+
+    /// Determines the [`dispatch::Format`] from the types of the fields of the
+    /// `System.ExtrinsicFailed` event. Falls back to the format of older runtimes if these types
+    /// aren't found.
+    fn find_dispatch_format(&self) -> dispatch::Format {
+        let mut format = dispatch::Format {
+            weight_v2: false,
+            module_error_four_bytes: false,
+        };
+
+        let event_ty = self
+            .record_fields
+            .iter()
+            .find(|f| f.name.as_deref() == Some("event"))
+            .unwrap()
+            .ty;
+        let failed_fields = self
+            .types
+            .variant_by_name(event_ty, "System")
+            .and_then(|v| v.fields.first())
+            .and_then(|system_event| {
+                self.types
+                    .variant_by_name(system_event.ty, "ExtrinsicFailed")
+            })
+            .map(|v| &v.fields[..]);
+        let (error_ty, info_ty) = match failed_fields {
+            Some([error, info]) => (error.ty, info.ty),
+            _ => return format,
+        };
+
+        // The weight is either a `u64` or a composite containing a computation time and a
+        // proof size.
+        if let Some(TypeDef::Composite(info_fields)) = self.types.get(info_ty) {
+            if let Some(weight) = info_fields.first() {
+                format.weight_v2 = matches!(self.types.get(weight.ty), Some(TypeDef::Composite(f)) if f.len() == 2);
+            }
+        }
+
+        // The `Module` variant either directly contains an index and an error, or a
+        // `ModuleError` struct that does.
+        if let Some(module) = self.types.variant_by_name(error_ty, "Module") {
+            let fields = match &module.fields[..] {
+                [field] => match self.types.get(field.ty) {
+                    Some(TypeDef::Composite(fields)) => &fields[..],
+                    _ => &[],
+                },
+                fields => fields,
+            };
+            if let Some(error) = fields.iter().find(|f| f.name.as_deref() == Some("error")) {
+                format.module_error_four_bytes =
+                    matches!(self.types.get(error.ty), Some(TypeDef::Array(4, _)));
+            }
+        }
+
+        format
+    }
+}
+
+/// Error potentially returned by [`EventsDecoder::from_metadata`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum MetadataError {
+    /// Failed to decode the metadata.
+    #[display(fmt = "{_0}")]
+    Decode(metadata::Error),
+    /// The metadata doesn't contain a `System.Events` storage entry.
+    EventsNotFound,
+    /// The type of the `System.Events` storage entry isn't supported.
+    UnsupportedEventsType,
+}
+
+/// Error that can happen during the decoding.
+#[derive(Debug, derive_more::Display, Clone)]
+pub struct DecodeError();
+
+#[cfg(test)]
+mod tests {
+    use super::super::{calls::Weight, dispatch};
+
+    fn compact(out: &mut Vec<u8>, n: usize) {
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(n).as_ref());
+    }
+
+    fn string(out: &mut Vec<u8>, s: &str) {
+        compact(out, s.len());
+        out.extend_from_slice(s.as_bytes());
+    }
+
+    /// Name and type id of each field.
+    type Fields<'a> = &'a [(Option<&'a str>, usize)];
+
+    fn fields(out: &mut Vec<u8>, fields: Fields) {
+        compact(out, fields.len());
+        for (name, ty) in fields {
+            match name {
+                Some(name) => {
+                    out.push(1);
+                    string(out, name);
+                }
+                None => out.push(0),
+            }
+            compact(out, *ty);
+            out.push(0); // Type name.
+            out.push(0); // Documentation.
+        }
+    }
+
+    fn variants(out: &mut Vec<u8>, variants: &[(&str, Fields, u8)]) {
+        out.push(1);
+        compact(out, variants.len());
+        for (name, variant_fields, index) in variants {
+            string(out, name);
+            fields(out, variant_fields);
+            out.push(*index);
+            out.push(0); // Documentation.
+        }
+    }
+
+    fn composite(out: &mut Vec<u8>, composite_fields: Fields) {
+        out.push(0);
+        fields(out, composite_fields);
+    }
+
+    /// Builds a minimal metadata containing the types necessary to decode events.
+    fn metadata() -> Vec<u8> {
+        let mut types = Vec::<Vec<u8>>::new();
+        // 0: u8
+        types.push(vec![5, 3]);
+        // 1: u32
+        types.push(vec![5, 5]);
+        // 2: Phase
+        let mut ty = Vec::new();
+        variants(
+            &mut ty,
+            &[
+                ("ApplyExtrinsic", &[(None, 1)], 0),
+                ("Finalization", &[], 1),
+                ("Initialization", &[], 2),
+            ],
+        );
+        types.push(ty);
+        // 3: DispatchInfo
+        let mut ty = Vec::new();
+        composite(
+            &mut ty,
+            &[
+                (Some("weight"), 4),
+                (Some("class"), 0),
+                (Some("pays_fee"), 0),
+            ],
+        );
+        types.push(ty);
+        // 4: Weight
+        let mut ty = Vec::new();
+        composite(&mut ty, &[(Some("ref_time"), 5), (Some("proof_size"), 5)]);
+        types.push(ty);
+        // 5: Compact<u64>
+        types.push(vec![6, 6 << 2]);
+        // 6: u64
+        types.push(vec![5, 6]);
+        // 7: System event
+        let mut ty = Vec::new();
+        variants(
+            &mut ty,
+            &[
+                ("ExtrinsicSuccess", &[(Some("dispatch_info"), 3)], 0),
+                (
+                    "ExtrinsicFailed",
+                    &[(Some("dispatch_error"), 8), (Some("dispatch_info"), 3)],
+                    1,
+                ),
+            ],
+        );
+        types.push(ty);
+        // 8: DispatchError
+        let mut ty = Vec::new();
+        variants(&mut ty, &[("Other", &[], 0), ("Module", &[(None, 9)], 3)]);
+        types.push(ty);
+        // 9: ModuleError
+        let mut ty = Vec::new();
+        composite(&mut ty, &[(Some("index"), 0), (Some("error"), 10)]);
+        types.push(ty);
+        // 10: [u8; 4]
+        types.push(vec![3, 4, 0, 0, 0, 0]);
+        // 11: Runtime event
+        let mut ty = Vec::new();
+        variants(
+            &mut ty,
+            &[("System", &[(None, 7)], 0), ("Balances", &[(None, 12)], 5)],
+        );
+        types.push(ty);
+        // 12: Balances event
+        let mut ty = Vec::new();
+        variants(
+            &mut ty,
+            &[(
+                "Transfer",
+                &[(Some("from"), 13), (Some("to"), 13), (Some("amount"), 14)],
+                2,
+            )],
+        );
+        types.push(ty);
+        // 13: [u8; 32]
+        types.push(vec![3, 32, 0, 0, 0, 0]);
+        // 14: u128
+        types.push(vec![5, 7]);
+        // 15: EventRecord
+        let mut ty = Vec::new();
+        composite(
+            &mut ty,
+            &[
+                (Some("phase"), 2),
+                (Some("event"), 11),
+                (Some("topics"), 16),
+            ],
+        );
+        types.push(ty);
+        // 16: Vec<[u8; 32]>
+        types.push(vec![2, 13 << 2]);
+        // 17: Vec<EventRecord>
+        types.push(vec![2, 15 << 2]);
+
+        let mut metadata = b"meta".to_vec();
+        metadata.push(14);
+        compact(&mut metadata, types.len());
+        for (id, ty) in types.iter().enumerate() {
+            compact(&mut metadata, id);
+            metadata.push(0); // Path.
+            metadata.push(0); // Type parameters.
+            metadata.extend_from_slice(ty);
+            metadata.push(0); // Documentation.
+        }
+
+        compact(&mut metadata, 1);
+        string(&mut metadata, "System");
+        metadata.push(1); // Storage.
+        string(&mut metadata, "System");
+        compact(&mut metadata, 1);
+        string(&mut metadata, "Events");
+        metadata.push(1); // Modifier.
+        metadata.extend_from_slice(&[0, 17 << 2]); // Plain type.
+        metadata.extend_from_slice(&[1 << 2, 0]); // Default value.
+        metadata.push(0); // Documentation.
+        metadata.push(0); // Calls.
+        metadata.push(0); // Events.
+        metadata.push(0); // Constants.
+        metadata.push(0); // Errors.
+        metadata.push(0); // Index.
+
+        // Rest of the metadata, which isn't parsed.
+        metadata.extend_from_slice(&[0xff; 8]);
+        metadata
+    }
+
+    #[test]
+    fn decode_events() {
+        let decoder = super::EventsDecoder::from_metadata(&metadata()).unwrap();
+        assert_eq!(
+            decoder.dispatch_format(),
+            dispatch::Format {
+                weight_v2: true,
+                module_error_four_bytes: true,
+            }
+        );
+
+        let dispatch_info = [10 << 2, 1 << 2, 0, 0];
+
+        let mut events = Vec::new();
+        compact(&mut events, 4);
+        // Extrinsic 0 succeeds.
+        events.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0]);
+        events.extend_from_slice(&dispatch_info);
+        events.push(0);
+        // Extrinsic 1 performs a transfer then fails.
+        events.extend_from_slice(&[0, 1, 0, 0, 0, 5, 2]);
+        events.extend_from_slice(&[1; 32]);
+        events.extend_from_slice(&[2; 32]);
+        events.extend_from_slice(&100u128.to_le_bytes());
+        events.push(1 << 2);
+        events.extend_from_slice(&[0xff; 32]);
+        events.extend_from_slice(&[0, 1, 0, 0, 0, 0, 1, 3, 5, 2, 0, 0, 0]);
+        events.extend_from_slice(&dispatch_info);
+        events.push(0);
+        // Event during the initialization, which isn't associated with any extrinsic.
+        events.extend_from_slice(&[2, 0, 0]);
+        events.extend_from_slice(&dispatch_info);
+        events.push(0);
+
+        let records = decoder.decode(&events).unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[1].phase, super::Phase::ApplyExtrinsic(1));
+        assert_eq!(records[1].pallet_name, "Balances");
+        assert_eq!(records[1].pallet_index, 5);
+        assert_eq!(records[1].event_name, "Transfer");
+        assert_eq!(records[1].event_index, 2);
+        assert_eq!(records[1].fields.len(), 32 + 32 + 16);
+        assert_eq!(records[3].phase, super::Phase::Initialization);
+
+        let info = dispatch::DispatchInfo {
+            weight: Weight::V2 {
+                ref_time: 10,
+                proof_size: 1,
+            },
+            class: dispatch::DispatchClass::Normal,
+            pays_fee: true,
+        };
+        assert_eq!(
+            decoder.extrinsics_outcomes(&records, 3).unwrap(),
+            vec![
+                Some(super::ExtrinsicOutcome::Success(info)),
+                Some(super::ExtrinsicOutcome::Failed {
+                    error: dispatch::DispatchError::Module(dispatch::ModuleError {
+                        index: 5,
+                        error: [2, 0, 0, 0],
+                    }),
+                    info,
+                }),
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_trailing_bytes() {
+        let decoder = super::EventsDecoder::from_metadata(&metadata()).unwrap();
+        assert!(decoder.decode(&[0]).unwrap().is_empty());
+        assert!(decoder.decode(&[0, 0]).is_err());
+        assert!(decoder.decode(&[1 << 2, 0, 0, 0, 0, 0, 9]).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Inspection of the extrinsics found in block bodies.
+//!
+//! An extrinsic, as found in a block body, starts with a SCALE-compact-encoded length prefix,
+//! followed with a byte containing the version of the extrinsic format and whether the
+//! extrinsic is signed. Signed extrinsics then contain the address of the signer, the
+//! signature, the signed extensions, and finally the call. Unsigned extrinsics directly contain
+//! the call.
+//!
+//! Only version 4 of the extrinsic format is supported.
+//!
+//! Since the encoding of the signature and of the signed extensions depends on the runtime, this
+//! module can't find where the call starts in a signed extrinsic. It only decodes the address of
+//! the signer, and offers [`contains_account_id`] as a way to find the extrinsics that mention
+//! an account in their parameters.
+
+use super::calls::AddressEncoding;
+use crate::util;
+
+/// Signer of an extrinsic.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Signer {
+    /// Extrinsic is signed by the given account.
+    AccountId([u8; 32]),
+    /// Extrinsic is signed, but its signer is designated using a variant of the `MultiAddress`
+    /// enum other than `Id`, such as an index, and thus can't be turned into an account id
+    /// without accessing the storage.
+    Other,
+}
+
+/// Decodes the signer of the given extrinsic. Returns `None` if the extrinsic is unsigned.
+///
+/// `extrinsic` must include its length prefix, as found in block bodies.
+pub fn decode_signer(
+    extrinsic: &[u8],
+    address_encoding: AddressEncoding,
+) -> Result<Option<Signer>, DecodeError> {
+    let (rest, length) = util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(extrinsic)
+        .map_err(|_| DecodeError::InvalidLengthPrefix)?;
+    if rest.len() != length {
+        return Err(DecodeError::InvalidLengthPrefix);
+    }
+
+    let (version, rest) = match rest.split_first() {
+        Some((v, rest)) => (*v, rest),
+        None => return Err(DecodeError::Truncated),
+    };
+
+    if version & 0x7f != 4 {
+        return Err(DecodeError::UnsupportedVersion(version & 0x7f));
+    }
+    if version & 0x80 == 0 {
+        return Ok(None);
+    }
+
+    let account_id = match (address_encoding, rest.split_first()) {
+        (AddressEncoding::AccountId, _) => rest,
+        (AddressEncoding::MultiAddress, Some((0, rest))) => rest,
+        (AddressEncoding::MultiAddress, Some((1..=4, _))) => return Ok(Some(Signer::Other)),
+        (AddressEncoding::MultiAddress, Some(_)) => return Err(DecodeError::InvalidAddress),
+        (AddressEncoding::MultiAddress, None) => return Err(DecodeError::Truncated),
+    };
+
+    match account_id.get(..32) {
+        Some(account_id) => Ok(Some(Signer::AccountId(
+            <[u8; 32]>::try_from(account_id).unwrap(),
+        ))),
+        None => Err(DecodeError::Truncated),
+    }
+}
+
+/// Returns `true` if the given account id is found anywhere within the given extrinsic.
+///
+/// This includes the extrinsic being signed by this account, but also this account being passed
+/// as parameter of the call, for example as the destination of a transfer.
+///
+/// > **Note**: This function doesn't decode the extrinsic and simply searches for the bytes of
+/// >           the account id. Because account ids are 32 bytes long, false positives are
+/// >           extremely unlikely but not impossible.
+pub fn contains_account_id(extrinsic: &[u8], account_id: &[u8; 32]) -> bool {
+    extrinsic.windows(32).any(|w| w == &account_id[..])
+}
+
+/// Error potentially returned by [`decode_signer`].
+#[derive(Debug, derive_more::Display, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The length prefix of the extrinsic is invalid or doesn't match its length.
+    InvalidLengthPrefix,
+    /// The extrinsic is too short.
+    Truncated,
+    /// The version of the extrinsic format isn't supported.
+    #[display(fmt = "Unsupported extrinsic version: {_0}")]
+    UnsupportedVersion(u8),
+    /// The address of the signer uses an unknown variant of the `MultiAddress` enum.
+    InvalidAddress,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::calls::AddressEncoding;
+
+    const ALICE: [u8; 32] = [1; 32];
+    const BOB: [u8; 32] = [2; 32];
+
+    fn with_length_prefix(bytes: &[u8]) -> Vec<u8> {
+        let mut out = crate::util::encode_scale_compact_usize(bytes.len())
+            .as_ref()
+            .to_vec();
+        out.extend_from_slice(bytes);
+        out
+    }
+
+    #[test]
+    fn signed_multi_address() {
+        let mut extrinsic = vec![0x84, 0];
+        extrinsic.extend_from_slice(&ALICE);
+        extrinsic.extend_from_slice(&[0xaa; 70]);
+        let extrinsic = with_length_prefix(&extrinsic);
+
+        assert_eq!(
+            super::decode_signer(&extrinsic, AddressEncoding::MultiAddress),
+            Ok(Some(super::Signer::AccountId(ALICE)))
+        );
+    }
+
+    #[test]
+    fn signed_account_id() {
+        let mut extrinsic = vec![0x84];
+        extrinsic.extend_from_slice(&ALICE);
+        extrinsic.extend_from_slice(&[0xaa; 70]);
+        let extrinsic = with_length_prefix(&extrinsic);
+
+        assert_eq!(
+            super::decode_signer(&extrinsic, AddressEncoding::AccountId),
+            Ok(Some(super::Signer::AccountId(ALICE)))
+        );
+    }
+
+    #[test]
+    fn signed_by_index() {
+        let extrinsic = with_length_prefix(&[0x84, 1, 4, 0xaa, 0xaa]);
+        assert_eq!(
+            super::decode_signer(&extrinsic, AddressEncoding::MultiAddress),
+            Ok(Some(super::Signer::Other))
+        );
+    }
+
+    #[test]
+    fn unsigned() {
+        let extrinsic = with_length_prefix(&[0x04, 3, 0, 0xaa]);
+        assert_eq!(
+            super::decode_signer(&extrinsic, AddressEncoding::MultiAddress),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            super::decode_signer(&[8, 0x84], AddressEncoding::MultiAddress),
+            Err(super::DecodeError::InvalidLengthPrefix)
+        );
+        assert_eq!(
+            super::decode_signer(
+                &with_length_prefix(&[0x84, 0, 1, 2]),
+                AddressEncoding::MultiAddress
+            ),
+            Err(super::DecodeError::Truncated)
+        );
+        assert_eq!(
+            super::decode_signer(&with_length_prefix(&[0x83]), AddressEncoding::MultiAddress),
+            Err(super::DecodeError::UnsupportedVersion(3))
+        );
+        assert_eq!(
+            super::decode_signer(
+                &with_length_prefix(&[0x84, 9]),
+                AddressEncoding::MultiAddress
+            ),
+            Err(super::DecodeError::InvalidAddress)
+        );
+    }
+
+    #[test]
+    fn contains_account_id() {
+        let mut extrinsic = vec![0x84, 0];
+        extrinsic.extend_from_slice(&ALICE);
+        extrinsic.extend_from_slice(&[5, 0, 0]);
+        extrinsic.extend_from_slice(&BOB);
+        let extrinsic = with_length_prefix(&extrinsic);
+
+        assert!(super::contains_account_id(&extrinsic, &ALICE));
+        assert!(super::contains_account_id(&extrinsic, &BOB));
+        assert!(!super::contains_account_id(&extrinsic, &[3; 32]));
+    }
+}
@@ -213,6 +213,9 @@ pub enum Error {
     /// the runtime to always provide a non-empty list of tags. This error is consequently a bug
     /// in the runtime.
     EmptyProvidedTags,
+    /// The runtime has accessed a child trie, which isn't supported when validating a
+    /// transaction.
+    ChildTriesNotSupported,
 }
 
 /// Error that can happen during the decoding.
@@ -334,6 +337,7 @@ pub fn validate_transaction(
                 .scale_encoding(config.block_number_bytes),
                 main_trie_root_calculation_cache: None,
                 storage_main_trie_changes: storage_diff::TrieDiff::empty(),
+                storage_child_tries_changes: Default::default(),
                 offchain_storage_changes: storage_diff::TrieDiff::empty(),
                 max_log_level: config.max_log_level,
             });
@@ -371,6 +375,7 @@ pub fn validate_transaction(
                 ),
                 main_trie_root_calculation_cache: None,
                 storage_main_trie_changes: storage_diff::TrieDiff::empty(),
+                storage_child_tries_changes: Default::default(),
                 offchain_storage_changes: storage_diff::TrieDiff::empty(),
                 max_log_level: config.max_log_level,
             });
@@ -460,6 +465,7 @@ impl Query {
                             info.transaction_source,
                         ),
                         storage_main_trie_changes: success.storage_main_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         offchain_storage_changes: success.offchain_storage_changes,
                         main_trie_root_calculation_cache: Some(
                             success.main_trie_root_calculation_cache,
@@ -482,12 +488,31 @@ impl Query {
                 runtime_host::RuntimeHostVm::StorageGet(i) => {
                     Query::StorageGet(StorageGet(StorageGetInner::Stage1(i, info)))
                 }
+                runtime_host::RuntimeHostVm::PrefixKeys(i) if i.child_trie().is_some() => {
+                    Query::Finished {
+                        result: Err(Error::ChildTriesNotSupported),
+                        virtual_machine: runtime_host::RuntimeHostVm::PrefixKeys(i)
+                            .into_prototype(),
+                    }
+                }
+                runtime_host::RuntimeHostVm::NextKey(inner) if inner.child_trie().is_some() => {
+                    Query::Finished {
+                        result: Err(Error::ChildTriesNotSupported),
+                        virtual_machine: runtime_host::RuntimeHostVm::NextKey(inner)
+                            .into_prototype(),
+                    }
+                }
                 runtime_host::RuntimeHostVm::PrefixKeys(i) => {
                     Query::PrefixKeys(PrefixKeys(PrefixKeysInner::Stage1(i, info)))
                 }
                 runtime_host::RuntimeHostVm::NextKey(inner) => {
                     Query::NextKey(NextKey(NextKeyInner::Stage1(inner, info)))
                 }
+                other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => Query::Finished {
+                    result: Err(Error::ChildTriesNotSupported),
+                    virtual_machine: other.into_prototype(),
+                },
                 runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
                     inner = sig.verify_and_resume();
                     continue;
@@ -540,12 +565,31 @@ impl Query {
                 runtime_host::RuntimeHostVm::StorageGet(i) => {
                     Query::StorageGet(StorageGet(StorageGetInner::Stage2(i, info)))
                 }
+                runtime_host::RuntimeHostVm::PrefixKeys(i) if i.child_trie().is_some() => {
+                    Query::Finished {
+                        result: Err(Error::ChildTriesNotSupported),
+                        virtual_machine: runtime_host::RuntimeHostVm::PrefixKeys(i)
+                            .into_prototype(),
+                    }
+                }
+                runtime_host::RuntimeHostVm::NextKey(inner) if inner.child_trie().is_some() => {
+                    Query::Finished {
+                        result: Err(Error::ChildTriesNotSupported),
+                        virtual_machine: runtime_host::RuntimeHostVm::NextKey(inner)
+                            .into_prototype(),
+                    }
+                }
                 runtime_host::RuntimeHostVm::PrefixKeys(i) => {
                     Query::PrefixKeys(PrefixKeys(PrefixKeysInner::Stage2(i, info)))
                 }
                 runtime_host::RuntimeHostVm::NextKey(inner) => {
                     Query::NextKey(NextKey(NextKeyInner::Stage2(inner, info)))
                 }
+                other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => Query::Finished {
+                    result: Err(Error::ChildTriesNotSupported),
+                    virtual_machine: other.into_prototype(),
+                },
                 runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
                     inner = sig.verify_and_resume();
                     continue;
@@ -707,7 +751,9 @@ fn valid_transaction(bytes: &[u8]) -> nom::IResult<&[u8], ValidTransaction> {
     )(bytes)
 }
 
-fn transaction_validity_error(bytes: &[u8]) -> nom::IResult<&[u8], TransactionValidityError> {
+pub(super) fn transaction_validity_error(
+    bytes: &[u8],
+) -> nom::IResult<&[u8], TransactionValidityError> {
     nom::error::context(
         "transaction validity error",
         nom::branch::alt((
@@ -107,9 +107,13 @@ use core::iter;
 mod nibble;
 
 pub mod calculate_root;
+pub mod changes_proof;
+pub mod migration;
 pub mod prefix_proof;
 pub mod proof_decode;
 pub mod proof_encode;
+#[cfg(any(test, feature = "fuzzing"))]
+pub mod reference;
 pub mod trie_node;
 pub mod trie_structure;
 
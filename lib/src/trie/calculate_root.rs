@@ -63,12 +63,31 @@
 //!
 //! When using a cache, be careful to properly invalidate cache entries whenever you perform
 //! modifications on the trie associated to it.
+//!
+//! If the modifications are available in the form of a [`storage_diff::TrieDiff`], you can
+//! instead use [`root_merkle_value_after_diff`], which invalidates the cache entries and starts
+//! the calculation. Because the cache remembers the storage values (or their hashes), only the
+//! values of the keys modified by the diff are then requested.
+//!
+//! The cache can be turned into bytes with [`CalculationCache::encode`] and rebuilt with
+//! [`CalculationCache::decode`], which makes it possible to persist it, for example in a
+//! database, and avoid having to rebuild the entire trie structure after a restart.
+//!
+//! # Child tries
+//!
+//! The calculation of the root of a child trie is identical to the one of the main trie. Use
+//! [`child_trie_root_merkle_value`] instead of [`root_merkle_value`] in order to start it. The
+//! name of the child trie is then available through [`AllKeys::child_trie`] and
+//! [`StorageValue::child_trie`], which makes it possible to know, in situations where multiple
+//! tries are involved, which trie the requested keys and values belong to.
 
 use super::{
     nibble::{bytes_to_nibbles, Nibble},
     trie_node, trie_structure, TrieEntryVersion,
 };
+use crate::executor::storage_diff;
 
+use alloc::{vec, vec::Vec};
 use core::{fmt, iter};
 
 /// Cache containing intermediate calculation steps.
@@ -86,6 +105,19 @@ pub struct CalculationCache {
 #[derive(Default, Clone)]
 struct CacheEntry {
     merkle_value: Option<trie_node::MerkleValueOutput>,
+
+    /// Storage value of the node, as found in its encoding. `None` if the node doesn't have any
+    /// storage value, if it isn't known yet, or if it is too large to be kept in the cache.
+    storage_value: Option<CachedStorageValue>,
+}
+
+/// See [`CacheEntry::storage_value`].
+#[derive(Clone)]
+enum CachedStorageValue {
+    /// Storage value of at most 32 bytes, put as-is in the node value.
+    Unhashed(arrayvec::ArrayVec<u8, 32>),
+    /// Hash of the storage value.
+    Hashed([u8; 32]),
 }
 
 impl CalculationCache {
@@ -114,7 +146,18 @@ impl CalculationCache {
             (trie_structure::Entry::Vacant(entry), true) => {
                 match entry.insert_storage_value() {
                     trie_structure::PrepareInsert::One(insert) => {
-                        let inserted = insert.insert(Default::default());
+                        let mut inserted = insert.insert(Default::default());
+
+                        // The node might have been inserted in-between an existing node and its
+                        // parent, in which case the partial key of this existing node, and thus
+                        // its Merkle value, has changed.
+                        for idx in 0..16u8 {
+                            if let Some(mut child) = inserted.child(Nibble::try_from(idx).unwrap())
+                            {
+                                child.user_data().merkle_value = None;
+                            }
+                        }
+
                         match inserted.into_parent() {
                             Some(p) => p,
                             None => return,
@@ -143,10 +186,15 @@ impl CalculationCache {
             }
             (trie_structure::Entry::Vacant(_), false) => return,
             (trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(entry)), true) => {
-                let entry = entry.insert_storage_value();
+                let mut entry = entry.insert_storage_value();
+                entry.user_data().storage_value = None;
                 trie_structure::NodeAccess::Storage(entry)
             }
-            (trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(entry)), true) => {
+            (
+                trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(mut entry)),
+                true,
+            ) => {
+                entry.user_data().storage_value = None;
                 trie_structure::NodeAccess::Storage(entry)
             }
             (trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(_)), false) => {
@@ -156,7 +204,8 @@ impl CalculationCache {
                 trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(entry)),
                 false,
             ) => match entry.remove() {
-                trie_structure::Remove::StorageToBranch(node) => {
+                trie_structure::Remove::StorageToBranch(mut node) => {
+                    node.user_data().storage_value = None;
                     trie_structure::NodeAccess::Branch(node)
                 }
                 trie_structure::Remove::BranchAlsoRemoved { sibling, .. } => sibling,
@@ -180,6 +229,16 @@ impl CalculationCache {
         }
     }
 
+    /// Notify the cache of all the modifications contained in the given diff.
+    ///
+    /// This is equivalent to calling [`CalculationCache::storage_value_update`] for each entry
+    /// of the diff.
+    pub fn storage_diff_update<T>(&mut self, diff: &storage_diff::TrieDiff<T>) {
+        for (key, value, _) in diff.diff_iter_unordered() {
+            self.storage_value_update(key, value.is_some());
+        }
+    }
+
     /// Notify the cache that all the storage values whose key start with the given prefix have
     /// been removed.
     pub fn prefix_remove_update(&mut self, prefix: &[u8]) {
@@ -201,6 +260,240 @@ impl CalculationCache {
     }
 }
 
+impl CalculationCache {
+    /// Turns the cache into bytes. The output can be decoded with [`CalculationCache::decode`].
+    ///
+    /// The output contains the structure of the trie, the keys of the nodes that have a storage
+    /// value, and the Merkle values and storage values that have been calculated so far.
+    pub fn encode(&self) -> Vec<u8> {
+        let structure = match &self.structure {
+            Some(s) => s,
+            None => return vec![ENCODING_VERSION, 0],
+        };
+
+        let mut out = Vec::with_capacity(2 + 5 + structure.len() * 40);
+        out.push(ENCODING_VERSION);
+        out.push(1);
+        out.extend_from_slice(crate::util::encode_scale_compact_usize(structure.len()).as_ref());
+
+        for node_index in structure.iter_ordered() {
+            let has_storage_value = structure
+                .node_has_storage_value_by_index(node_index)
+                .unwrap();
+            let entry = &structure[node_index];
+
+            let mut flags = 0u8;
+            if has_storage_value {
+                flags |= ENCODING_FLAG_STORAGE_NODE;
+            }
+            if entry.merkle_value.is_some() {
+                flags |= ENCODING_FLAG_MERKLE_VALUE;
+            }
+            match entry.storage_value {
+                Some(CachedStorageValue::Unhashed(_)) => flags |= ENCODING_FLAG_UNHASHED_VALUE,
+                Some(CachedStorageValue::Hashed(_)) => flags |= ENCODING_FLAG_HASHED_VALUE,
+                None => {}
+            }
+            out.push(flags);
+
+            // Only the keys of the storage nodes are encoded, as the branch nodes are
+            // automatically re-created when inserting the storage nodes.
+            if has_storage_value {
+                let key = structure
+                    .node_full_key_by_index(node_index)
+                    .unwrap()
+                    .collect::<Vec<_>>();
+                out.extend_from_slice(crate::util::encode_scale_compact_usize(key.len()).as_ref());
+                for pair in key.chunks(2) {
+                    let high = u8::from(pair[0]) << 4;
+                    let low = pair.get(1).map_or(0, |n| u8::from(*n));
+                    out.push(high | low);
+                }
+            }
+
+            if let Some(merkle_value) = &entry.merkle_value {
+                let merkle_value = merkle_value.as_ref();
+                out.push(u8::try_from(merkle_value.len()).unwrap());
+                out.extend_from_slice(merkle_value);
+            }
+
+            match &entry.storage_value {
+                Some(CachedStorageValue::Unhashed(value)) => {
+                    out.push(u8::try_from(value.len()).unwrap());
+                    out.extend_from_slice(value);
+                }
+                Some(CachedStorageValue::Hashed(hash)) => out.extend_from_slice(hash),
+                None => {}
+            }
+        }
+
+        out
+    }
+
+    /// Rebuilds a cache from the output of [`CalculationCache::encode`].
+    ///
+    /// > **Note**: The Merkle values and storage values found in the encoded cache can't be
+    /// >           verified. Passing a cache that doesn't match the trie to a calculation leads
+    /// >           to an incorrect result.
+    pub fn decode(encoded: &[u8]) -> Result<Self, DecodeError> {
+        match encoded {
+            [ENCODING_VERSION, 0] => return Ok(CalculationCache::empty()),
+            [ENCODING_VERSION, 1, ..] => {}
+            [version, ..] if *version != ENCODING_VERSION => {
+                return Err(DecodeError::UnsupportedVersion(*version))
+            }
+            _ => return Err(DecodeError::InvalidFormat),
+        }
+
+        let (mut encoded, num_nodes) =
+            crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(&encoded[2..])
+                .map_err(|_| DecodeError::InvalidFormat)?;
+
+        // Each node takes at least one byte, which protects against excessive allocations.
+        if num_nodes > encoded.len() {
+            return Err(DecodeError::InvalidFormat);
+        }
+
+        let mut structure = trie_structure::TrieStructure::with_capacity(num_nodes);
+        let mut entries = Vec::with_capacity(num_nodes);
+        let mut previous_key = None::<Vec<Nibble>>;
+
+        for _ in 0..num_nodes {
+            let (&flags, rest) = encoded.split_first().ok_or(DecodeError::InvalidFormat)?;
+            encoded = rest;
+
+            let has_storage_value = (flags & ENCODING_FLAG_STORAGE_NODE) != 0;
+            if has_storage_value {
+                let (rest, num_nibbles) =
+                    crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(encoded)
+                        .map_err(|_| DecodeError::InvalidFormat)?;
+                let num_bytes = num_nibbles / 2 + num_nibbles % 2;
+                if rest.len() < num_bytes {
+                    return Err(DecodeError::InvalidFormat);
+                }
+                let (key_bytes, rest) = rest.split_at(num_bytes);
+                encoded = rest;
+
+                let key = key_bytes
+                    .iter()
+                    .flat_map(|b| [*b >> 4, *b & 0xf])
+                    .take(num_nibbles)
+                    .map(|n| Nibble::try_from(n).unwrap())
+                    .collect::<Vec<_>>();
+
+                // Keys must be provided in strictly increasing order, so that the storage nodes
+                // end up in the same order as the encoded entries.
+                if previous_key.as_ref().is_some_and(|prev| *prev >= key) {
+                    return Err(DecodeError::InvalidFormat);
+                }
+
+                match structure.node(key.iter().copied()) {
+                    trie_structure::Entry::Vacant(entry) => match entry.insert_storage_value() {
+                        trie_structure::PrepareInsert::One(insert) => {
+                            insert.insert(CacheEntry::default());
+                        }
+                        trie_structure::PrepareInsert::Two(insert) => {
+                            insert.insert(CacheEntry::default(), CacheEntry::default());
+                        }
+                    },
+                    trie_structure::Entry::Occupied(trie_structure::NodeAccess::Branch(entry)) => {
+                        entry.insert_storage_value();
+                    }
+                    trie_structure::Entry::Occupied(trie_structure::NodeAccess::Storage(_)) => {
+                        return Err(DecodeError::InvalidFormat)
+                    }
+                }
+
+                previous_key = Some(key);
+            }
+
+            let merkle_value = if (flags & ENCODING_FLAG_MERKLE_VALUE) != 0 {
+                let (value, rest) = decode_short_bytes(encoded)?;
+                encoded = rest;
+                Some(trie_node::MerkleValueOutput::from_bytes(value))
+            } else {
+                None
+            };
+
+            let storage_value = if (flags & ENCODING_FLAG_UNHASHED_VALUE) != 0 {
+                let (value, rest) = decode_short_bytes(encoded)?;
+                encoded = rest;
+                Some(CachedStorageValue::Unhashed(
+                    arrayvec::ArrayVec::try_from(value).unwrap(),
+                ))
+            } else if (flags & ENCODING_FLAG_HASHED_VALUE) != 0 {
+                if encoded.len() < 32 {
+                    return Err(DecodeError::InvalidFormat);
+                }
+                let (hash, rest) = encoded.split_at(32);
+                encoded = rest;
+                Some(CachedStorageValue::Hashed(
+                    <[u8; 32]>::try_from(hash).unwrap(),
+                ))
+            } else {
+                None
+            };
+
+            entries.push((
+                has_storage_value,
+                CacheEntry {
+                    merkle_value,
+                    storage_value,
+                },
+            ));
+        }
+
+        if !encoded.is_empty() || structure.len() != entries.len() {
+            return Err(DecodeError::InvalidFormat);
+        }
+
+        // Now that the structure has been rebuilt, assign to each node its cached values.
+        let node_indices = structure.iter_ordered().collect::<Vec<_>>();
+        for (node_index, (has_storage_value, entry)) in node_indices.into_iter().zip(entries) {
+            let mut node = structure.node_by_index(node_index).unwrap();
+            if node.has_storage_value() != has_storage_value {
+                return Err(DecodeError::InvalidFormat);
+            }
+            *node.user_data() = entry;
+        }
+
+        Ok(CalculationCache {
+            structure: Some(structure),
+        })
+    }
+}
+
+/// Error potentially returned by [`CalculationCache::decode`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum DecodeError {
+    /// The encoded cache has an invalid format.
+    InvalidFormat,
+    /// The encoded cache uses a version of the format that isn't supported.
+    #[display(fmt = "Unsupported version: {_0}")]
+    UnsupportedVersion(u8),
+}
+
+/// Version of the format produced by [`CalculationCache::encode`].
+const ENCODING_VERSION: u8 = 0;
+/// Flag indicating that the node has a storage value.
+const ENCODING_FLAG_STORAGE_NODE: u8 = 1 << 0;
+/// Flag indicating that the Merkle value of the node is present.
+const ENCODING_FLAG_MERKLE_VALUE: u8 = 1 << 1;
+/// Flag indicating that the storage value of the node is present as-is.
+const ENCODING_FLAG_UNHASHED_VALUE: u8 = 1 << 2;
+/// Flag indicating that the hash of the storage value of the node is present.
+const ENCODING_FLAG_HASHED_VALUE: u8 = 1 << 3;
+
+/// Decodes a buffer of at most 32 bytes prefixed with its length.
+fn decode_short_bytes(encoded: &[u8]) -> Result<(&[u8], &[u8]), DecodeError> {
+    let (&len, rest) = encoded.split_first().ok_or(DecodeError::InvalidFormat)?;
+    let len = usize::from(len);
+    if len > 32 || rest.len() < len {
+        return Err(DecodeError::InvalidFormat);
+    }
+    Ok(rest.split_at(len))
+}
+
 impl Default for CalculationCache {
     fn default() -> Self {
         Self::empty()
@@ -216,6 +509,28 @@ impl fmt::Debug for CalculationCache {
 
 /// Start calculating the Merkle value of the root node.
 pub fn root_merkle_value(cache: Option<CalculationCache>) -> RootMerkleValueCalculation {
+    start(None, cache)
+}
+
+/// Start calculating the Merkle value of the root node of the given child trie.
+///
+/// `child_trie` is the name of the child trie, without the `:child_storage:default:` prefix.
+/// It isn't used in the calculation itself, and is only reported back through
+/// [`AllKeys::child_trie`] and [`StorageValue::child_trie`].
+///
+/// The cache, if any, must have been used exclusively for this child trie.
+pub fn child_trie_root_merkle_value(
+    child_trie: &[u8],
+    cache: Option<CalculationCache>,
+) -> RootMerkleValueCalculation {
+    start(Some(child_trie.to_vec()), cache)
+}
+
+/// Common implementation of [`root_merkle_value`] and [`child_trie_root_merkle_value`].
+fn start(
+    child_trie: Option<Vec<u8>>,
+    cache: Option<CalculationCache>,
+) -> RootMerkleValueCalculation {
     // The calculation that we perform relies on storing values in the cache and reloading them
     // afterwards. If the user didn't pass any cache, we create a temporary one.
     let cache_or_temporary = if let Some(mut cache) = cache {
@@ -230,6 +545,7 @@ pub fn root_merkle_value(cache: Option<CalculationCache>) -> RootMerkleValueCalc
     };
 
     CalcInner {
+        child_trie,
         cache: cache_or_temporary,
         current: None,
         coming_from_child: false,
@@ -237,6 +553,23 @@ pub fn root_merkle_value(cache: Option<CalculationCache>) -> RootMerkleValueCalc
     .next()
 }
 
+/// Start calculating the Merkle value of the root node of a trie that has been modified by the
+/// given diff since the last time `cache` was used.
+///
+/// The cache entries corresponding to the keys of the diff are invalidated, and only the values
+/// of the keys that the diff inserts or modifies are requested, with the exception of storage
+/// values using [`TrieEntryVersion::V0`] and longer than 32 bytes, which are too large to be
+/// kept in the cache. If `cache` is empty, however, the calculation behaves in the same way as
+/// [`root_merkle_value`], and the keys passed to [`AllKeys::inject`] must include the
+/// modifications of the diff.
+pub fn root_merkle_value_after_diff<T>(
+    mut cache: CalculationCache,
+    diff: &storage_diff::TrieDiff<T>,
+) -> RootMerkleValueCalculation {
+    cache.storage_diff_update(diff);
+    root_merkle_value(Some(cache))
+}
+
 /// Current state of the [`RootMerkleValueCalculation`] and how to continue.
 #[must_use]
 pub enum RootMerkleValueCalculation {
@@ -270,6 +603,9 @@ pub enum RootMerkleValueCalculation {
 /// Due to this order of iteration, we traverse each node which lack a Merkle value twice, and
 /// the Merkle value is calculated that second time.
 struct CalcInner {
+    /// Name of the child trie whose root is calculated, or `None` for the main trie.
+    child_trie: Option<Vec<u8>>,
+
     /// Contains the intermediary steps of the calculation. `None` if the calculation is finished.
     cache: CalculationCache,
 
@@ -371,31 +707,49 @@ impl CalcInner {
             // If we reach this, we are ready to calculate `current`'s Merkle value.
             self.coming_from_child = true;
 
-            if !current.has_storage_value() {
-                // Calculate the Merkle value of the node.
-                // `calculate_merkle_value` returns an error if the node is invalid, which would
-                // indicate a bug in this module.
-                let merkle_value = trie_node::calculate_merkle_value(
-                    trie_node::Decoded {
-                        partial_key: current.partial_key(),
-                        children: core::array::from_fn(|child_idx| {
-                            current
-                                .child_user_data(
-                                    Nibble::try_from(u8::try_from(child_idx).unwrap()).unwrap(),
-                                )
-                                .map(|child| child.merkle_value.as_ref().unwrap())
-                        }),
-                        storage_value: trie_node::StorageValue::None,
-                    },
-                    current.is_root_node(),
-                )
-                .unwrap();
+            // If the node has a storage value that isn't in the cache, we need to ask the user
+            // for it.
+            let storage_value = if current.has_storage_value() {
+                match current.user_data().storage_value.clone() {
+                    Some(v) => Some(v),
+                    None => {
+                        return RootMerkleValueCalculation::StorageValue(StorageValue {
+                            calculation: self,
+                        })
+                    }
+                }
+            } else {
+                None
+            };
 
-                current.user_data().merkle_value = Some(merkle_value);
-                continue;
-            }
+            // Calculate the Merkle value of the node.
+            // `calculate_merkle_value` returns an error if the node is invalid, which would
+            // indicate a bug in this module.
+            let merkle_value = trie_node::calculate_merkle_value(
+                trie_node::Decoded {
+                    partial_key: current.partial_key(),
+                    children: core::array::from_fn(|child_idx| {
+                        current
+                            .child_user_data(
+                                Nibble::try_from(u8::try_from(child_idx).unwrap()).unwrap(),
+                            )
+                            .map(|child| child.merkle_value.as_ref().unwrap())
+                    }),
+                    storage_value: match &storage_value {
+                        None => trie_node::StorageValue::None,
+                        Some(CachedStorageValue::Unhashed(value)) => {
+                            trie_node::StorageValue::Unhashed(value)
+                        }
+                        Some(CachedStorageValue::Hashed(hash)) => {
+                            trie_node::StorageValue::Hashed(hash)
+                        }
+                    },
+                },
+                current.is_root_node(),
+            )
+            .unwrap();
 
-            return RootMerkleValueCalculation::StorageValue(StorageValue { calculation: self });
+            current.user_data().merkle_value = Some(merkle_value);
         }
     }
 }
@@ -408,6 +762,12 @@ pub struct AllKeys {
 }
 
 impl AllKeys {
+    /// Returns the name of the child trie whose keys are requested, or `None` if the keys of
+    /// the main trie are requested.
+    pub fn child_trie(&self) -> Option<&[u8]> {
+        self.calculation.child_trie.as_deref()
+    }
+
     /// Indicates the list of all keys of the trie and advances the calculation.
     pub fn inject(
         mut self,
@@ -438,6 +798,12 @@ pub struct StorageValue {
 }
 
 impl StorageValue {
+    /// Returns the name of the child trie the requested value belongs to, or `None` if it
+    /// belongs to the main trie.
+    pub fn child_trie(&self) -> Option<&[u8]> {
+        self.calculation.child_trie.as_deref()
+    }
+
     /// Returns the key whose value is being requested.
     pub fn key(&'_ self) -> impl Iterator<Item = u8> + '_ {
         let trie_structure = self.calculation.cache.structure.as_ref().unwrap();
@@ -503,6 +869,19 @@ impl StorageValue {
         .unwrap();
 
         current.user_data().merkle_value = Some(merkle_value);
+
+        // Keep the storage value in the cache, so that it doesn't need to be requested again if
+        // the Merkle value of the node is later invalidated because of one of its descendants.
+        current.user_data().storage_value = match (hashed_storage_value, stored_value) {
+            (Some(hash), _) => Some(CachedStorageValue::Hashed(
+                <[u8; 32]>::try_from(hash.as_bytes()).unwrap(),
+            )),
+            (None, Some((value, _))) => arrayvec::ArrayVec::try_from(value.as_ref())
+                .ok()
+                .map(CachedStorageValue::Unhashed),
+            (None, None) => unreachable!(),
+        };
+
         self.calculation.next()
     }
 }
@@ -560,6 +939,30 @@ mod tests {
         );
     }
 
+    #[test]
+    fn child_trie_reports_name() {
+        let mut trie = BTreeMap::new();
+        trie.insert(b"abcd".to_vec(), b"hello world".to_vec());
+
+        let mut calculation = super::child_trie_root_merkle_value(b"foo", None);
+        let root = loop {
+            match calculation {
+                super::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+                super::RootMerkleValueCalculation::AllKeys(keys) => {
+                    assert_eq!(keys.child_trie(), Some(&b"foo"[..]));
+                    calculation = keys.inject(trie.keys().map(|k| k.iter().cloned()));
+                }
+                super::RootMerkleValueCalculation::StorageValue(value) => {
+                    assert_eq!(value.child_trie(), Some(&b"foo"[..]));
+                    let key = value.key().collect::<Vec<u8>>();
+                    calculation = value.inject(trie.get(&key).map(|v| (v, TrieEntryVersion::V1)));
+                }
+            }
+        };
+
+        assert_eq!(root, calculate_root(TrieEntryVersion::V1, &trie));
+    }
+
     #[test]
     fn trie_root_single_tuple() {
         let mut trie = BTreeMap::new();
@@ -723,4 +1126,149 @@ mod tests {
             assert_eq!(root_no_cache, root_with_cache);
         }
     }
+
+    #[test]
+    fn after_diff_only_requests_modified_values() {
+        for _ in 0..500 {
+            // Generate a random trie, with values of random sizes in order to test both hashed
+            // and unhashed storage values.
+            let mut trie = BTreeMap::<Vec<u8>, Vec<u8>>::new();
+            for _ in 0..rand::thread_rng().gen_range::<u32, _>(5..400) {
+                let mut new_key = trie
+                    .keys()
+                    .choose(&mut rand::thread_rng())
+                    .map(|s| s.to_vec())
+                    .unwrap_or_default();
+                for _ in 0..rand::thread_rng().gen_range::<u32, _>(1..6) {
+                    new_key.push(rand::random::<u8>());
+                }
+                let mut new_value = vec![0u8; rand::thread_rng().gen_range(0..64)];
+                rand::thread_rng().fill(&mut new_value[..]);
+                trie.insert(new_key, new_value);
+            }
+
+            let mut calculation = super::root_merkle_value(None);
+            let cache = loop {
+                match calculation {
+                    super::RootMerkleValueCalculation::Finished { cache, .. } => break cache,
+                    super::RootMerkleValueCalculation::AllKeys(keys) => {
+                        calculation = keys.inject(trie.keys().map(|k| k.iter().cloned()));
+                    }
+                    super::RootMerkleValueCalculation::StorageValue(value) => {
+                        let key = value.key().collect::<Vec<u8>>();
+                        calculation =
+                            value.inject(trie.get(&key).map(|v| (v, TrieEntryVersion::V1)));
+                    }
+                }
+            };
+
+            // Build a diff that modifies, removes, or inserts a few keys.
+            let mut diff = crate::executor::storage_diff::TrieDiff::empty();
+            for _ in 0..rand::thread_rng().gen_range::<u32, _>(1..5) {
+                let mut key = trie
+                    .keys()
+                    .choose(&mut rand::thread_rng())
+                    .map(|s| s.to_vec())
+                    .unwrap_or_default();
+                match rand::thread_rng().gen_range(0..3) {
+                    0 => {
+                        diff.diff_insert_erase(key.clone(), ());
+                        trie.remove(&key);
+                    }
+                    n => {
+                        if n == 2 {
+                            key.push(rand::random::<u8>());
+                        }
+                        let mut new_value = vec![0u8; rand::thread_rng().gen_range(0..64)];
+                        rand::thread_rng().fill(&mut new_value[..]);
+                        diff.diff_insert(key.clone(), new_value.clone(), ());
+                        trie.insert(key, new_value);
+                    }
+                }
+            }
+
+            let mut calculation = super::root_merkle_value_after_diff(cache, &diff);
+            let root_with_diff = loop {
+                match calculation {
+                    super::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+                    super::RootMerkleValueCalculation::AllKeys(_) => panic!(),
+                    super::RootMerkleValueCalculation::StorageValue(value) => {
+                        let key = value.key().collect::<Vec<u8>>();
+                        assert!(matches!(diff.diff_get(&key), Some((Some(_), _))));
+                        calculation =
+                            value.inject(trie.get(&key).map(|v| (v, TrieEntryVersion::V1)));
+                    }
+                }
+            };
+
+            assert_eq!(calculate_root(TrieEntryVersion::V1, &trie), root_with_diff);
+        }
+    }
+
+    #[test]
+    fn cache_encode_decode_round_trip() {
+        for _ in 0..100 {
+            let mut trie = BTreeMap::<Vec<u8>, Vec<u8>>::new();
+            for _ in 0..rand::thread_rng().gen_range::<u32, _>(0..200) {
+                let mut new_key = trie
+                    .keys()
+                    .choose(&mut rand::thread_rng())
+                    .map(|s| s.to_vec())
+                    .unwrap_or_default();
+                for _ in 0..rand::thread_rng().gen_range::<u32, _>(1..6) {
+                    new_key.push(rand::random::<u8>());
+                }
+                let mut new_value = vec![0u8; rand::thread_rng().gen_range(0..64)];
+                rand::thread_rng().fill(&mut new_value[..]);
+                trie.insert(new_key, new_value);
+            }
+
+            let mut calculation = super::root_merkle_value(None);
+            let (expected_hash, cache) = loop {
+                match calculation {
+                    super::RootMerkleValueCalculation::Finished { hash, cache } => {
+                        break (hash, cache)
+                    }
+                    super::RootMerkleValueCalculation::AllKeys(keys) => {
+                        calculation = keys.inject(trie.keys().map(|k| k.iter().cloned()));
+                    }
+                    super::RootMerkleValueCalculation::StorageValue(value) => {
+                        let key = value.key().collect::<Vec<u8>>();
+                        calculation =
+                            value.inject(trie.get(&key).map(|v| (v, TrieEntryVersion::V1)));
+                    }
+                }
+            };
+
+            let encoded = cache.encode();
+            let decoded = super::CalculationCache::decode(&encoded).unwrap();
+            assert_eq!(decoded.encode(), encoded);
+
+            // All the Merkle values are in the cache, meaning that the calculation must finish
+            // immediately.
+            match super::root_merkle_value(Some(decoded)) {
+                super::RootMerkleValueCalculation::Finished { hash, .. } => {
+                    assert_eq!(hash, expected_hash)
+                }
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn cache_decode_empty_and_invalid() {
+        let empty = super::CalculationCache::empty().encode();
+        assert!(super::CalculationCache::decode(&empty).is_ok());
+
+        assert!(matches!(
+            super::CalculationCache::decode(&[5, 0]),
+            Err(super::DecodeError::UnsupportedVersion(5))
+        ));
+        assert!(super::CalculationCache::decode(&[]).is_err());
+        assert!(super::CalculationCache::decode(&[0, 1]).is_err());
+        // Announces one node but doesn't provide it.
+        assert!(super::CalculationCache::decode(&[0, 1, 4]).is_err());
+        // Trailing data.
+        assert!(super::CalculationCache::decode(&[0, 0, 0]).is_err());
+    }
 }
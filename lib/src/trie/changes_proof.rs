@@ -0,0 +1,476 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Determining, through trie proofs, which keys of a list have a different storage value in a
+//! "new" trie compared to an "old" trie.
+//!
+//! The Merkle value of a trie node depends on the storage values of all its descendants. If the
+//! closest descendant of a certain key has the same Merkle value in both tries, then none of the
+//! storage values below this key has changed. This module uses this property in order to avoid
+//! downloading the storage values of the old trie when possible.
+//!
+//! A proof of all the requested keys in the new trie must first be obtained. This proof contains
+//! the Merkle values of all the ancestors of the requested keys in the new trie. Then, storage
+//! proofs of the old trie are queried, going down the trie starting from the root. Each query
+//! is a storage proof of a key in the direction of an ancestor of the requested keys. If the
+//! closest descendant of this ancestor has the same Merkle value in both tries, then all the
+//! requested keys below this ancestor are known to be unchanged. Otherwise, the next query is
+//! in the direction of the next ancestor. The storage values of the old trie are only queried
+//! for the keys for which no ancestor has an identical Merkle value in both tries.
+//!
+//! The total number of storage proofs of the old trie required is at most equal to the depth
+//! of the new trie above the requested keys, plus one. Multiple steps can be verified with a
+//! single proof if the proof contains enough information.
+
+use super::{nibble, proof_decode, trie_node};
+
+use alloc::{collections::BTreeSet, vec::Vec};
+use core::fmt;
+
+/// Configuration to pass to [`changes_scan`].
+pub struct Config<'a, K> {
+    /// List of keys whose storage value must be compared. Can contain duplicates.
+    pub keys: K,
+
+    /// Merkle value (or node value) of the root node of the old trie.
+    pub old_trie_root_hash: [u8; 32],
+
+    /// Merkle value (or node value) of the root node of the new trie.
+    pub new_trie_root_hash: [u8; 32],
+
+    /// Proof of all the keys of [`Config::keys`] in the new trie.
+    pub new_trie_proof: &'a [u8],
+}
+
+/// Start a new scanning process.
+///
+/// Returns an error if `new_trie_proof` is invalid or doesn't contain all the keys.
+pub fn changes_scan(
+    config: Config<'_, impl Iterator<Item = impl AsRef<[u8]>>>,
+) -> Result<ResumeOutcome, Error> {
+    let keys = config.keys.map(|k| k.as_ref().to_vec()).collect::<Vec<_>>();
+
+    // If the trie root hashes are equal, then none of the storage values have changed.
+    if config.old_trie_root_hash == config.new_trie_root_hash {
+        return Ok(ResumeOutcome::Success {
+            changed: keys.iter().map(|_| false).collect(),
+        });
+    }
+
+    let new_trie_proof = proof_decode::decode_and_verify_proof(proof_decode::Config {
+        proof: config.new_trie_proof,
+        trie_root_hash: &config.new_trie_root_hash,
+    })
+    .map_err(Error::InvalidProof)?;
+
+    let mut pending = Vec::with_capacity(keys.len());
+    for (index, key) in keys.into_iter().enumerate() {
+        let new_value = new_trie_proof
+            .storage_value(&key)
+            .ok_or(Error::MissingProofEntry)?
+            .map(|(value, _)| value.to_vec());
+
+        // Build the list of ancestors of the key in the new trie, from the closest to the
+        // furthest, not including the root node as its Merkle value is known to be different.
+        // Because the proof is a proof of this key, it contains all the ancestors of the key.
+        let key_nibbles = nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+        let mut ancestors = Vec::new();
+        let mut iter = &key_nibbles[..];
+        while let Some(ancestor) = new_trie_proof
+            .closest_ancestor(iter)
+            .map_err(|_| Error::MissingProofEntry)?
+        {
+            let merkle_value = new_trie_proof
+                .closest_descendant_merkle_value(ancestor)
+                .map_err(|_| Error::MissingProofEntry)?
+                .unwrap_or_else(|| unreachable!());
+            if merkle_value.as_ref() == &config.new_trie_root_hash[..] {
+                break;
+            }
+
+            ancestors.push((ancestor.to_vec(), merkle_value));
+            iter = &key_nibbles[..ancestor.len()];
+        }
+
+        pending.push(PendingKey {
+            index,
+            key,
+            new_value,
+            ancestors,
+        });
+    }
+
+    let scan = ChangesScan {
+        old_trie_root_hash: config.old_trie_root_hash,
+        changed: pending.iter().map(|_| false).collect(),
+        pending,
+    };
+
+    if scan.pending.is_empty() {
+        return Ok(ResumeOutcome::Success {
+            changed: scan.changed,
+        });
+    }
+
+    Ok(ResumeOutcome::InProgress(scan))
+}
+
+/// Scan in progress.
+pub struct ChangesScan {
+    old_trie_root_hash: [u8; 32],
+    /// Keys whose storage value is still unknown to have changed or not.
+    pending: Vec<PendingKey>,
+    /// For each requested key, whether its storage value has changed. Only meaningful for the
+    /// keys that aren't in [`ChangesScan::pending`].
+    changed: Vec<bool>,
+}
+
+struct PendingKey {
+    /// Index of the key within [`Config::keys`].
+    index: usize,
+    /// The requested key.
+    key: Vec<u8>,
+    /// Storage value of the key in the new trie.
+    new_value: Option<Vec<u8>>,
+    /// Ancestors of the key in the new trie that remain to be compared, and their Merkle value
+    /// in the new trie. The next ancestor to compare is the last element.
+    ancestors: Vec<(Vec<nibble::Nibble>, trie_node::MerkleValueOutput)>,
+}
+
+/// Outcome of verifying the next step of a [`PendingKey`].
+enum Step {
+    /// The Merkle value of the closest descendant of the ancestor is different in both tries.
+    AncestorDifferent,
+    /// The storage value has been determined to have changed or not.
+    Finished { changed: bool },
+    /// The proof doesn't contain enough information.
+    Missing,
+}
+
+impl ChangesScan {
+    /// Returns the list of keys whose storage proof in the old trie must be queried.
+    pub fn requested_keys(&'_ self) -> impl Iterator<Item = Vec<u8>> + '_ {
+        // Keys that share an ancestor are all in the same direction, and can be de-duplicated.
+        self.pending
+            .iter()
+            .map(|pending| match pending.ancestors.last() {
+                Some((ancestor, _)) => {
+                    nibble::nibbles_to_bytes_suffix_extend(ancestor.iter().copied()).collect()
+                }
+                None => pending.key.clone(),
+            })
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+    }
+
+    /// Injects the proof of the old trie presumably containing the keys returned by
+    /// [`ChangesScan::requested_keys`].
+    ///
+    /// Returns an error if the proof is invalid or doesn't contain enough information to make
+    /// progress. In that case, `self` isn't modified.
+    pub fn resume(mut self, old_trie_proof: &[u8]) -> Result<ResumeOutcome, (Self, Error)> {
+        let decoded_proof = match proof_decode::decode_and_verify_proof(proof_decode::Config {
+            proof: old_trie_proof,
+            trie_root_hash: &self.old_trie_root_hash,
+        }) {
+            Ok(d) => d,
+            Err(err) => return Err((self, Error::InvalidProof(err))),
+        };
+
+        // Before modifying `self`, determine for each pending key the number of ancestors that
+        // are different in both tries and whether the value is known to have changed.
+        let mut outcomes = Vec::with_capacity(self.pending.len());
+        let mut missing_entry = false;
+        for pending in &self.pending {
+            let mut num_different_ancestors = 0;
+            let finished = loop {
+                let step = match pending.ancestors.iter().rev().nth(num_different_ancestors) {
+                    Some((ancestor, new_merkle_value)) => {
+                        match decoded_proof.closest_descendant_merkle_value(ancestor) {
+                            Ok(Some(old)) if old.as_ref() == new_merkle_value.as_ref() => {
+                                Step::Finished { changed: false }
+                            }
+                            Ok(Some(_)) => Step::AncestorDifferent,
+                            // The key has no ancestor in the old trie at this location, and
+                            // thus no storage value.
+                            Ok(None) => Step::Finished {
+                                changed: pending.new_value.is_some(),
+                            },
+                            Err(_) => Step::Missing,
+                        }
+                    }
+                    None => match decoded_proof.storage_value(&pending.key) {
+                        Some(old_value) => Step::Finished {
+                            changed: old_value.map(|(v, _)| v) != pending.new_value.as_deref(),
+                        },
+                        None => Step::Missing,
+                    },
+                };
+
+                match step {
+                    Step::AncestorDifferent => num_different_ancestors += 1,
+                    Step::Finished { changed } => break Some(changed),
+                    Step::Missing if num_different_ancestors == 0 => {
+                        missing_entry = true;
+                        break None;
+                    }
+                    Step::Missing => break None,
+                }
+            };
+
+            if missing_entry {
+                break;
+            }
+
+            outcomes.push((num_different_ancestors, finished));
+        }
+
+        if missing_entry {
+            return Err((self, Error::MissingProofEntry));
+        }
+
+        let mut outcomes = outcomes.into_iter();
+        self.pending.retain_mut(|pending| {
+            let (num_different_ancestors, finished) = outcomes.next().unwrap();
+            match finished {
+                Some(changed) => {
+                    self.changed[pending.index] = changed;
+                    false
+                }
+                None => {
+                    pending
+                        .ancestors
+                        .truncate(pending.ancestors.len() - num_different_ancestors);
+                    true
+                }
+            }
+        });
+
+        if self.pending.is_empty() {
+            Ok(ResumeOutcome::Success {
+                changed: self.changed,
+            })
+        } else {
+            Ok(ResumeOutcome::InProgress(self))
+        }
+    }
+}
+
+impl fmt::Debug for ChangesScan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ChangesScan").finish()
+    }
+}
+
+/// Outcome of calling [`changes_scan`] or [`ChangesScan::resume`].
+#[derive(Debug)]
+pub enum ResumeOutcome {
+    /// Scan must continue with the next storage proof query.
+    InProgress(ChangesScan),
+    /// Scan has succeeded.
+    Success {
+        /// For each key of [`Config::keys`], whether its storage value is different in the new
+        /// trie compared to the old trie.
+        changed: Vec<bool>,
+    },
+}
+
+/// Possible error returned by [`changes_scan`] or [`ChangesScan::resume`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum Error {
+    /// The proof has an invalid format.
+    #[display(fmt = "{_0}")]
+    InvalidProof(proof_decode::Error),
+    /// One or more entries are missing from the proof.
+    MissingProofEntry,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{proof_encode, TrieEntryVersion};
+    use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+    fn build_proof(entries: &BTreeMap<Vec<u8>, Vec<u8>>, keys: &[Vec<u8>]) -> (Vec<u8>, [u8; 32]) {
+        let mut build = proof_encode::build_from_storage(proof_encode::BuildFromStorageConfig {
+            keys: keys.iter(),
+        });
+
+        loop {
+            match build {
+                proof_encode::StorageProofBuild::Finished {
+                    proof,
+                    trie_root_hash,
+                } => return (proof, trie_root_hash),
+                proof_encode::StorageProofBuild::NextKey(req) => {
+                    let next = if req.or_equal() {
+                        entries.range(req.key().as_ref().to_vec()..).next()
+                    } else {
+                        entries
+                            .range((
+                                core::ops::Bound::Excluded(req.key().as_ref().to_vec()),
+                                core::ops::Bound::Unbounded,
+                            ))
+                            .next()
+                    };
+                    build = req.inject(next.map(|(k, _)| k));
+                }
+                proof_encode::StorageProofBuild::StorageValue(req) => {
+                    let value = entries
+                        .get(req.key().as_ref())
+                        .map(|v| (v, TrieEntryVersion::V1));
+                    build = req.inject(value);
+                }
+            }
+        }
+    }
+
+    /// Runs a full scan. Returns the result and the number of old trie proofs that were needed,
+    /// and the total number of storage values of the old trie that were requested.
+    fn run_scan(
+        old: &BTreeMap<Vec<u8>, Vec<u8>>,
+        new: &BTreeMap<Vec<u8>, Vec<u8>>,
+        keys: &[Vec<u8>],
+    ) -> (Vec<bool>, usize, usize) {
+        let (_, old_trie_root_hash) = build_proof(old, &[]);
+        let (new_trie_proof, new_trie_root_hash) = build_proof(new, keys);
+
+        let mut scan = super::changes_scan(super::Config {
+            keys: keys.iter(),
+            old_trie_root_hash,
+            new_trie_root_hash,
+            new_trie_proof: &new_trie_proof,
+        })
+        .unwrap();
+
+        let mut num_queries = 0;
+        let mut num_values_requested = 0;
+        loop {
+            match scan {
+                super::ResumeOutcome::Success { changed } => {
+                    return (changed, num_queries, num_values_requested)
+                }
+                super::ResumeOutcome::InProgress(in_progress) => {
+                    let requested = in_progress.requested_keys().collect::<Vec<_>>();
+                    num_values_requested +=
+                        requested.iter().filter(|k| old.contains_key(*k)).count();
+                    let (proof, _) = build_proof(old, &requested);
+                    num_queries += 1;
+                    scan = in_progress.resume(&proof).unwrap();
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn identical_roots() {
+        let mut entries = BTreeMap::new();
+        entries.insert(vec![1, 2], vec![3]);
+        let keys = [vec![1, 2], vec![5]];
+        assert_eq!(
+            run_scan(&entries, &entries, &keys),
+            (vec![false, false], 0, 0)
+        );
+    }
+
+    #[test]
+    fn detects_changes() {
+        let mut old = BTreeMap::new();
+        for n in 0..64u8 {
+            old.insert(vec![0xaa, n], vec![n; 40]);
+            old.insert(vec![0xbb, n], vec![n; 40]);
+        }
+
+        let mut new = old.clone();
+        new.insert(vec![0xbb, 3], vec![0xff; 40]);
+        new.remove(&[0xbb, 4][..]);
+        new.insert(vec![0xbb, 200], vec![1]);
+        // Value changed then restored.
+        new.insert(vec![0xbb, 5], vec![5; 40]);
+
+        let keys = [
+            vec![0xaa, 1],
+            vec![0xbb, 3],
+            vec![0xbb, 4],
+            vec![0xbb, 5],
+            vec![0xbb, 200],
+            vec![0xcc],
+            vec![0xbb, 3],
+        ];
+        let (changed, _, _) = run_scan(&old, &new, &keys);
+        assert_eq!(changed, vec![false, true, true, false, true, false, true]);
+    }
+
+    #[test]
+    fn unchanged_subtree_pruned() {
+        let mut old = BTreeMap::new();
+        for n in 0..64u8 {
+            old.insert(vec![0xaa, n], vec![n; 40]);
+            old.insert(vec![0xbb, n], vec![n; 40]);
+        }
+
+        let mut new = old.clone();
+        new.insert(vec![0xbb, 0], vec![0xff; 40]);
+
+        // All the keys below `0xaa` are unchanged. None of their values should be requested.
+        let keys = (0..64u8).map(|n| vec![0xaa, n]).collect::<Vec<_>>();
+        let (changed, num_queries, num_values_requested) = run_scan(&old, &new, &keys);
+        assert!(changed.iter().all(|c| !c));
+        assert_eq!(num_values_requested, 0);
+        assert_eq!(num_queries, 1);
+    }
+
+    #[test]
+    fn random_matches_direct_comparison() {
+        use rand::distributions::{Distribution as _, Uniform};
+
+        for _ in 0..200 {
+            let byte_range = Uniform::new_inclusive(0, 3);
+            let random_key = || {
+                let key_len = Uniform::new_inclusive(0, 4).sample(&mut rand::thread_rng());
+                (0..key_len)
+                    .map(|_| byte_range.sample(&mut rand::thread_rng()))
+                    .collect::<Vec<u8>>()
+            };
+            let random_value = || {
+                let value_len = if rand::random() { 1 } else { 40 };
+                vec![Uniform::new_inclusive(0, 1).sample(&mut rand::thread_rng()); value_len]
+            };
+
+            let old = (0..Uniform::new_inclusive(0, 24).sample(&mut rand::thread_rng()))
+                .map(|_| (random_key(), random_value()))
+                .collect::<BTreeMap<_, _>>();
+            let mut new = old.clone();
+            for _ in 0..Uniform::new_inclusive(0, 4).sample(&mut rand::thread_rng()) {
+                if rand::random() {
+                    new.insert(random_key(), random_value());
+                } else {
+                    new.remove(&random_key());
+                }
+            }
+
+            let keys = (0..Uniform::new_inclusive(0, 6).sample(&mut rand::thread_rng()))
+                .map(|_| random_key())
+                .chain(old.keys().take(2).cloned())
+                .collect::<Vec<_>>();
+
+            let (changed, _, _) = run_scan(&old, &new, &keys);
+            for (key, changed) in keys.iter().zip(changed) {
+                assert_eq!(changed, old.get(key) != new.get(key));
+            }
+        }
+    }
+}
@@ -0,0 +1,314 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Tracking of the migration of the entries of a trie from [`TrieEntryVersion::V0`] to
+//! [`TrieEntryVersion::V1`].
+//!
+//! Changing the state version of a chain doesn't modify the entries that are already in the
+//! trie. Each entry keeps the version it was written with until it is written again, which is
+//! typically done in batches by the `state_trie_migration` pallet.
+//!
+//! The [`MigrationProgress`] struct collects the entries of the trie, either from the local
+//! database (see [`MigrationProgress::inject_entry`]) or from Merkle proofs (see
+//! [`MigrationProgress::inject_proof`]), and reports how many of them still need to be migrated.
+//! [`MigrationProgress::next_batch`] can then be used in order to determine the keys to pass to
+//! the next migration extrinsic.
+//!
+//! # Unaffected entries
+//!
+//! The two versions differ only in the way storage values of 33 bytes or more are encoded: they
+//! are inlined in the trie node in [`TrieEntryVersion::V0`] and hashed in
+//! [`TrieEntryVersion::V1`]. Entries whose storage value is shorter are encoded identically no
+//! matter their version and thus don't need to be migrated. When reading a Merkle proof, it is
+//! impossible to know the version of these entries.
+//!
+//! # Example
+//!
+//! ```
+//! use smoldot::trie::{migration, TrieEntryVersion};
+//!
+//! let mut progress = migration::MigrationProgress::new();
+//! progress.inject_entry(b"foo", 64, TrieEntryVersion::V0);
+//! progress.inject_entry(b"bar", 64, TrieEntryVersion::V1);
+//! progress.inject_entry(b"baz", 4, TrieEntryVersion::V0);
+//!
+//! assert_eq!(progress.num_v0_entries(), 1);
+//! assert_eq!(progress.num_v1_entries(), 1);
+//! assert_eq!(progress.num_unaffected_entries(), 1);
+//!
+//! let batch = progress.next_batch(None, &migration::BatchLimits { max_items: 8, max_size: 1024 });
+//! assert_eq!(batch.keys, vec![&b"foo"[..]]);
+//! ```
+
+use super::{nibble, proof_decode, TrieEntryVersion};
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use core::ops;
+
+/// Storage values whose length is superior or equal to this value are encoded differently
+/// depending on the [`TrieEntryVersion`].
+const MIN_AFFECTED_VALUE_LEN: usize = 33;
+
+/// Progress of the migration of a trie. See the module-level documentation.
+#[derive(Debug, Clone, Default)]
+pub struct MigrationProgress {
+    /// List of all the entries that have been injected, indexed by key.
+    entries: BTreeMap<Vec<u8>, Entry>,
+
+    /// Number of entries in [`MigrationProgress::entries`] with [`EntryState::V0`].
+    num_v0: usize,
+    /// Number of entries in [`MigrationProgress::entries`] with [`EntryState::V1`].
+    num_v1: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Entry {
+    state: EntryState,
+    /// Length in bytes of the storage value of the entry.
+    value_len: usize,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum EntryState {
+    V0,
+    V1,
+    Unaffected,
+}
+
+impl MigrationProgress {
+    /// Initializes a new empty [`MigrationProgress`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds to the tracker an entry of the trie, such as found in the local database.
+    ///
+    /// If an entry with the same key has already been injected, it is overwritten. This can be
+    /// used in order to update the tracker after a migration batch has been applied.
+    pub fn inject_entry(&mut self, key: &[u8], value_len: usize, version: TrieEntryVersion) {
+        let state = match version {
+            _ if value_len < MIN_AFFECTED_VALUE_LEN => EntryState::Unaffected,
+            TrieEntryVersion::V0 => EntryState::V0,
+            TrieEntryVersion::V1 => EntryState::V1,
+        };
+
+        self.insert(key.to_vec(), Entry { state, value_len });
+    }
+
+    /// Adds to the tracker all the storage entries found in the given Merkle proof.
+    ///
+    /// The version of an entry is deduced from the way its storage value is encoded in the
+    /// proof. Entries whose storage value is hashed but missing from the proof are counted as
+    /// [`TrieEntryVersion::V1`], but their value is assumed to be 33 bytes long by
+    /// [`MigrationProgress::next_batch`].
+    ///
+    /// Entries whose key consists in an uneven number of nibbles can't be written by the runtime
+    /// and are ignored.
+    pub fn inject_proof<T: AsRef<[u8]>>(&mut self, proof: &proof_decode::DecodedTrieProof<T>) {
+        for (key, entry) in proof.iter_ordered() {
+            if key.len() % 2 != 0 {
+                continue;
+            }
+
+            let entry = match entry.trie_node_info.storage_value {
+                proof_decode::StorageValue::None => continue,
+                proof_decode::StorageValue::Known { value, inline } => Entry {
+                    state: if value.len() < MIN_AFFECTED_VALUE_LEN {
+                        EntryState::Unaffected
+                    } else if inline {
+                        EntryState::V0
+                    } else {
+                        EntryState::V1
+                    },
+                    value_len: value.len(),
+                },
+                proof_decode::StorageValue::HashKnownValueMissing(_) => Entry {
+                    state: EntryState::V1,
+                    value_len: MIN_AFFECTED_VALUE_LEN,
+                },
+            };
+
+            let key = nibble::nibbles_to_bytes_suffix_extend(key.iter().copied()).collect();
+            self.insert(key, entry);
+        }
+    }
+
+    /// Returns the number of entries that use [`TrieEntryVersion::V0`] and need to be migrated.
+    pub fn num_v0_entries(&self) -> usize {
+        self.num_v0
+    }
+
+    /// Returns the number of entries that use [`TrieEntryVersion::V1`].
+    pub fn num_v1_entries(&self) -> usize {
+        self.num_v1
+    }
+
+    /// Returns the number of entries whose storage value is too small to be affected by the
+    /// version of the entry. See the module-level documentation.
+    pub fn num_unaffected_entries(&self) -> usize {
+        self.entries.len() - self.num_v0 - self.num_v1
+    }
+
+    /// Returns `true` if none of the entries that have been injected need to be migrated.
+    pub fn is_complete(&self) -> bool {
+        self.num_v0 == 0
+    }
+
+    /// Returns the list of keys of the entries that need to be migrated, in lexicographic order.
+    pub fn pending_keys(&'_ self) -> impl Iterator<Item = &'_ [u8]> + '_ {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| entry.state == EntryState::V0)
+            .map(|(key, _)| &key[..])
+    }
+
+    /// Builds the next batch of keys to migrate, starting strictly after the given key, or at
+    /// the start of the trie if `None`.
+    ///
+    /// The size of an entry is the length of its key plus the length of its storage value. The
+    /// returned batch always contains at least one key if there is any key left to migrate, even
+    /// if the size of this entry alone exceeds [`BatchLimits::max_size`], as otherwise the
+    /// migration would be stuck.
+    pub fn next_batch(&'_ self, start_after: Option<&[u8]>, limits: &BatchLimits) -> Batch<'_> {
+        let lower_bound = match start_after {
+            Some(key) => ops::Bound::Excluded(key),
+            None => ops::Bound::Unbounded,
+        };
+
+        let mut batch = Batch {
+            keys: Vec::new(),
+            total_size: 0,
+        };
+
+        for (key, entry) in self
+            .entries
+            .range::<[u8], _>((lower_bound, ops::Bound::Unbounded))
+            .filter(|(_, entry)| entry.state == EntryState::V0)
+        {
+            if u32::try_from(batch.keys.len()).map_or(true, |n| n >= limits.max_items) {
+                break;
+            }
+
+            let entry_size = u64::try_from(key.len() + entry.value_len).unwrap();
+            if !batch.keys.is_empty() && batch.total_size + entry_size > u64::from(limits.max_size)
+            {
+                break;
+            }
+
+            batch.keys.push(&key[..]);
+            batch.total_size += entry_size;
+        }
+
+        batch
+    }
+
+    fn insert(&mut self, key: Vec<u8>, entry: Entry) {
+        match entry.state {
+            EntryState::V0 => self.num_v0 += 1,
+            EntryState::V1 => self.num_v1 += 1,
+            EntryState::Unaffected => {}
+        }
+
+        match self.entries.insert(key, entry).map(|e| e.state) {
+            Some(EntryState::V0) => self.num_v0 -= 1,
+            Some(EntryState::V1) => self.num_v1 -= 1,
+            Some(EntryState::Unaffected) | None => {}
+        }
+    }
+}
+
+/// Limits of a batch of keys to migrate. Mirrors the limits that the `state_trie_migration`
+/// pallet enforces.
+#[derive(Debug, Clone)]
+pub struct BatchLimits {
+    /// Maximum number of keys in the batch.
+    pub max_items: u32,
+    /// Maximum cumulated size in bytes of the keys and storage values of the batch.
+    pub max_size: u32,
+}
+
+/// Batch of keys to migrate. See [`MigrationProgress::next_batch`].
+#[derive(Debug, Clone)]
+pub struct Batch<'a> {
+    /// Keys to migrate, in lexicographic order. Empty if there is nothing left to migrate.
+    pub keys: Vec<&'a [u8]>,
+    /// Cumulated size in bytes of the keys and storage values of the batch.
+    pub total_size: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchLimits, MigrationProgress, TrieEntryVersion};
+
+    #[test]
+    fn overwrite_updates_counts() {
+        let mut progress = MigrationProgress::new();
+        progress.inject_entry(b"a", 100, TrieEntryVersion::V0);
+        progress.inject_entry(b"b", 100, TrieEntryVersion::V0);
+        assert_eq!(progress.num_v0_entries(), 2);
+        assert!(!progress.is_complete());
+
+        progress.inject_entry(b"a", 100, TrieEntryVersion::V1);
+        progress.inject_entry(b"b", 10, TrieEntryVersion::V1);
+        assert_eq!(progress.num_v0_entries(), 0);
+        assert_eq!(progress.num_v1_entries(), 1);
+        assert_eq!(progress.num_unaffected_entries(), 1);
+        assert!(progress.is_complete());
+    }
+
+    #[test]
+    fn batches_respect_limits() {
+        let mut progress = MigrationProgress::new();
+        for key in [&b"a"[..], b"b", b"c", b"d", b"e"] {
+            progress.inject_entry(key, 99, TrieEntryVersion::V0);
+        }
+        progress.inject_entry(b"bb", 99, TrieEntryVersion::V1);
+
+        let limits = BatchLimits {
+            max_items: 4,
+            max_size: 250,
+        };
+
+        let batch = progress.next_batch(None, &limits);
+        assert_eq!(batch.keys, vec![&b"a"[..], b"b"]);
+        assert_eq!(batch.total_size, 200);
+
+        let batch = progress.next_batch(Some(b"b"), &limits);
+        assert_eq!(batch.keys, vec![&b"c"[..], b"d"]);
+
+        let batch = progress.next_batch(Some(b"d"), &limits);
+        assert_eq!(batch.keys, vec![&b"e"[..]]);
+
+        assert!(progress.next_batch(Some(b"e"), &limits).keys.is_empty());
+    }
+
+    #[test]
+    fn oversized_entry_alone_in_batch() {
+        let mut progress = MigrationProgress::new();
+        progress.inject_entry(b"a", 1000, TrieEntryVersion::V0);
+        progress.inject_entry(b"b", 50, TrieEntryVersion::V0);
+
+        let batch = progress.next_batch(
+            None,
+            &BatchLimits {
+                max_items: 10,
+                max_size: 100,
+            },
+        );
+        assert_eq!(batch.keys, vec![&b"a"[..]]);
+    }
+}
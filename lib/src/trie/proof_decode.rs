@@ -38,6 +38,8 @@
 //! Once decoded, one can examine the content of the proof, in other words the list of storage
 //! items and values.
 
+#![deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
+
 use super::{nibble, trie_node, TrieEntryVersion};
 
 use alloc::{collections::BTreeMap, vec, vec::Vec};
@@ -106,18 +108,9 @@ where
                     )
                     .unwrap();
 
-                    let proof_entry_offset = if proof_entry.is_empty() {
-                        0
-                    } else {
-                        proof_entry.as_ptr() as usize - proof_as_ref.as_ptr() as usize
-                    };
-
                     (
                         hash,
-                        (
-                            proof_entry_num,
-                            proof_entry_offset..(proof_entry_offset + proof_entry.len()),
-                        ),
+                        (proof_entry_num, subslice_range(proof_as_ref, proof_entry)),
                     )
                 },
             )
@@ -133,10 +126,27 @@ where
         merkle_values
     };
 
+    verify_proof(config.proof, merkle_values, config.trie_root_hash)
+}
+
+/// Verifies whether a proof is correct, given the list of its entries indexed by their hash.
+///
+/// Each value of `merkle_values` contains the position of the entry within the proof and the
+/// range within `proof` where to find the entry.
+fn verify_proof<T>(
+    proof: T,
+    merkle_values: hashbrown::HashMap<[u8; 32], (usize, ops::Range<usize>), fnv::FnvBuildHasher>,
+    trie_root_hash: &[u8; 32],
+) -> Result<DecodedTrieProof<T>, Error>
+where
+    T: AsRef<[u8]>,
+{
+    let proof_as_ref = proof.as_ref();
+
     // Dummy empty proofs are always valid.
     if merkle_values.is_empty() {
         return Ok(DecodedTrieProof {
-            proof: config.proof,
+            proof,
             entries: BTreeMap::new(),
         });
     }
@@ -151,7 +161,7 @@ where
     // Find the expected trie root in the proof. This is the starting point of the verification.
     let mut remain_iterate = {
         let (root_position, root_range) = merkle_values
-            .get(&config.trie_root_hash[..])
+            .get(&trie_root_hash[..])
             .ok_or(Error::TrieRootNotFound)?
             .clone();
         let _ = unvisited_proof_entries.remove(&root_position);
@@ -176,7 +186,9 @@ where
             // Build the storage key of the node.
             let storage_key = {
                 let mut storage_key_after_partial = Vec::with_capacity(
-                    storage_key_before_partial.len() + decoded_node_value.partial_key.len(),
+                    storage_key_before_partial
+                        .len()
+                        .saturating_add(decoded_node_value.partial_key.len()),
                 );
                 storage_key_after_partial.extend_from_slice(&storage_key_before_partial);
                 storage_key_after_partial.extend(decoded_node_value.partial_key);
@@ -198,25 +210,19 @@ where
 
                 // Key of the child node before its partial key.
                 let mut child_storage_key_before_partial =
-                    Vec::with_capacity(storage_key.len() + 1);
+                    Vec::with_capacity(storage_key.len().saturating_add(1));
                 child_storage_key_before_partial.extend_from_slice(&storage_key);
                 child_storage_key_before_partial.push(child_nibble);
 
                 // The value of the child node is either directly inlined (if less than 32 bytes)
                 // or is a hash.
                 if child_node_value.len() < 32 {
-                    let offset = proof_entry_range.start
-                        + if !child_node_value.is_empty() {
-                            child_node_value.as_ptr() as usize - proof_entry.as_ptr() as usize
-                        } else {
-                            0
-                        };
-                    debug_assert!(offset == 0 || offset >= proof_entry_range.start);
-                    debug_assert!(offset <= (proof_entry_range.start + proof_entry.len()));
-                    remain_iterate.push((
-                        offset..(offset + child_node_value.len()),
-                        child_storage_key_before_partial,
-                    ));
+                    let child_range = subslice_range(proof_as_ref, child_node_value);
+                    debug_assert!(
+                        child_range.start == 0 || child_range.start >= proof_entry_range.start
+                    );
+                    debug_assert!(child_range.end <= proof_entry_range.end);
+                    remain_iterate.push((child_range, child_storage_key_before_partial));
                 } else {
                     // The decoding API guarantees that the child value is never larger than
                     // 32 bytes.
@@ -226,7 +232,7 @@ where
                     {
                         // If the node value of the child is less than 32 bytes long, it should
                         // have been inlined instead of given separately.
-                        if child_entry_range.end - child_entry_range.start < 32 {
+                        if child_entry_range.len() < 32 {
                             return Err(Error::UnexpectedHashedNode);
                         }
 
@@ -256,24 +262,19 @@ where
                             StorageValueInner::Known {
                                 is_inline: false,
                                 offset: value_entry_range.start,
-                                len: value_entry_range.end - value_entry_range.start,
+                                len: value_entry_range.len(),
                             }
                         } else {
-                            let offset =
-                                value_hash.as_ptr() as usize - proof_as_ref.as_ptr() as usize;
+                            let offset = subslice_range(proof_as_ref, &value_hash[..]).start;
                             debug_assert!(offset >= proof_entry_range.start);
-                            debug_assert!(offset <= (proof_entry_range.start + proof_entry.len()));
+                            debug_assert!(offset <= proof_entry_range.end);
                             StorageValueInner::HashKnownValueMissing { offset }
                         }
                     }
                     trie_node::StorageValue::Unhashed(v) => {
-                        let offset = if !v.is_empty() {
-                            v.as_ptr() as usize - proof_as_ref.as_ptr() as usize
-                        } else {
-                            0
-                        };
+                        let offset = subslice_range(proof_as_ref, v).start;
                         debug_assert!(offset == 0 || offset >= proof_entry_range.start);
-                        debug_assert!(offset <= (proof_entry_range.start + proof_entry.len()));
+                        debug_assert!(offset <= proof_entry_range.end);
                         StorageValueInner::Known {
                             is_inline: true,
                             offset,
@@ -298,10 +299,171 @@ where
         return Err(Error::UnusedProofEntry);
     }
 
-    Ok(DecodedTrieProof {
-        proof: config.proof,
-        entries,
-    })
+    Ok(DecodedTrieProof { proof, entries })
+}
+
+/// Returns the range of `outer` that `inner` points to. `inner` must be a sub-slice of `outer`.
+///
+/// Returns `0..0` if `inner` is empty, as the pointer of an empty slice is meaningless.
+fn subslice_range(outer: &[u8], inner: &[u8]) -> ops::Range<usize> {
+    if inner.is_empty() {
+        return 0..0;
+    }
+
+    let start = (inner.as_ptr() as usize)
+        .checked_sub(outer.as_ptr() as usize)
+        .unwrap();
+    let end = start.checked_add(inner.len()).unwrap();
+    debug_assert!(end <= outer.len());
+    start..end
+}
+
+/// Decodes and verifies a proof whose bytes are received progressively.
+///
+/// Contrary to [`decode_and_verify_proof`], the proof doesn't need to be entirely available
+/// before the decoding starts. Each entry of the proof is hashed as soon as it has been fully
+/// received, and format errors are reported as soon as they are detected, which makes it
+/// possible to interrupt the download of an invalid proof early.
+///
+/// The data passed to [`IncrementalDecoder::feed`] is copied into a single buffer that later
+/// becomes the proof of the [`DecodedTrieProof`]. The API user therefore doesn't need to keep
+/// its own copy of the proof.
+pub struct IncrementalDecoder {
+    /// Bytes of the proof received so far.
+    buffer: Vec<u8>,
+
+    /// Offset within [`IncrementalDecoder::buffer`] of the first byte that hasn't been decoded
+    /// yet.
+    decoded_offset: usize,
+
+    /// Number of entries in the proof, or `None` if the length prefix of the proof hasn't been
+    /// fully received yet.
+    num_entries: Option<usize>,
+
+    /// Entries of the proof that have been fully received, indexed by their hash. Each value
+    /// contains the position of the entry within the proof and the range within
+    /// [`IncrementalDecoder::buffer`] where to find the entry.
+    merkle_values: hashbrown::HashMap<[u8; 32], (usize, ops::Range<usize>), fnv::FnvBuildHasher>,
+}
+
+impl IncrementalDecoder {
+    /// Initializes a new decoder for a proof of which no byte has been received yet.
+    pub fn new() -> Self {
+        IncrementalDecoder {
+            buffer: Vec::new(),
+            decoded_offset: 0,
+            num_entries: None,
+            merkle_values: hashbrown::HashMap::with_capacity_and_hasher(0, Default::default()),
+        }
+    }
+
+    /// Adds data to the end of the proof received so far.
+    ///
+    /// Returns an error if the proof is known to be invalid, in which case the decoder should
+    /// be discarded.
+    pub fn feed(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.buffer.extend_from_slice(data);
+
+        loop {
+            let num_entries = match self.num_entries {
+                Some(n) => n,
+                None => match self.decode_length_prefix()? {
+                    Some(n) => {
+                        self.num_entries = Some(n);
+                        n
+                    }
+                    None => return Ok(()),
+                },
+            };
+
+            if self.merkle_values.len() == num_entries {
+                // All the entries have been received. Any additional byte makes the proof
+                // invalid.
+                if self.decoded_offset != self.buffer.len() {
+                    return Err(Error::InvalidFormat);
+                }
+                return Ok(());
+            }
+
+            let entry_offset_before_prefix = self.decoded_offset;
+            let entry_len = match self.decode_length_prefix()? {
+                Some(len) => len,
+                None => return Ok(()),
+            };
+
+            let entry_range = match self.decoded_offset.checked_add(entry_len) {
+                Some(entry_end) if entry_end <= self.buffer.len() => self.decoded_offset..entry_end,
+                _ => {
+                    // The entry hasn't been fully received yet. Rewind in order to decode the
+                    // length prefix again later.
+                    self.decoded_offset = entry_offset_before_prefix;
+                    return Ok(());
+                }
+            };
+            let hash = *<&[u8; 32]>::try_from(
+                blake2_rfc::blake2b::blake2b(32, &[], &self.buffer[entry_range.clone()]).as_bytes(),
+            )
+            .unwrap();
+
+            let entry_num = self.merkle_values.len();
+            match self.merkle_values.entry(hash) {
+                hashbrown::hash_map::Entry::Occupied(_) => return Err(Error::DuplicateProofEntry),
+                hashbrown::hash_map::Entry::Vacant(entry) => {
+                    entry.insert((entry_num, entry_range.clone()));
+                }
+            }
+
+            self.decoded_offset = entry_range.end;
+        }
+    }
+
+    /// Returns `true` if all the bytes of the proof have been received.
+    pub fn is_complete(&self) -> bool {
+        self.num_entries == Some(self.merkle_values.len())
+    }
+
+    /// Verifies the proof that has been received against the given trie root hash.
+    ///
+    /// Returns [`Error::InvalidFormat`] if the proof hasn't been fully received. See
+    /// [`IncrementalDecoder::is_complete`].
+    pub fn finish(self, trie_root_hash: &[u8; 32]) -> Result<DecodedTrieProof<Vec<u8>>, Error> {
+        if !self.is_complete() {
+            return Err(Error::InvalidFormat);
+        }
+
+        verify_proof(self.buffer, self.merkle_values, trie_root_hash)
+    }
+
+    /// Decodes the SCALE-compact number found at [`IncrementalDecoder::decoded_offset`] and
+    /// updates the offset. Returns `Ok(None)` if not enough bytes have been received yet.
+    fn decode_length_prefix(&mut self) -> Result<Option<usize>, Error> {
+        let to_decode = &self.buffer[self.decoded_offset..];
+        match crate::util::nom_scale_compact_usize::<nom::error::Error<&[u8]>>(to_decode) {
+            Ok((rest, value)) => {
+                // `rest` is always a suffix of the buffer.
+                self.decoded_offset = self.buffer.len().checked_sub(rest.len()).unwrap();
+                Ok(Some(value))
+            }
+            Err(nom::Err::Error(err)) if err.code == nom::error::ErrorKind::Eof => Ok(None),
+            Err(_) => Err(Error::InvalidFormat),
+        }
+    }
+}
+
+impl Default for IncrementalDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for IncrementalDecoder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncrementalDecoder")
+            .field("received_bytes", &self.buffer.len())
+            .field("num_entries", &self.num_entries)
+            .field("num_received_entries", &self.merkle_values.len())
+            .finish()
+    }
 }
 
 /// Equivalent to [`StorageValue`] but contains offsets indexing [`DecodedTrieProof::proof`].
@@ -593,14 +755,14 @@ impl<T: AsRef<[u8]>> DecodedTrieProof<T> {
                     if self
                         .entries
                         .range::<[nibble::Nibble], _>((
-                            ops::Bound::Included(&key[..found_key.len() + 1]),
+                            ops::Bound::Included(&key[..=found_key.len()]),
                             ops::Bound::Unbounded,
                         ))
                         .next()
-                        .map_or(false, |(k, _)| k.starts_with(&key[..found_key.len() + 1]))
+                        .map_or(false, |(k, _)| k.starts_with(&key[..=found_key.len()]))
                     {
                         // There exists at least one node in the proof that starts with
-                        // `key[..found_key.len() + 1]` but that isn't `key` and doesn't start
+                        // `key[..=found_key.len()]` but that isn't `key` and doesn't start
                         // with key, and there isn't any branch node at the common ancestor between
                         // this node and `key`, as otherwise would have found it when iterating
                         // earlier. This branch node can't be missing from the proof as otherwise
@@ -663,9 +825,341 @@ impl<T: AsRef<[u8]>> DecodedTrieProof<T> {
         }
     }
 
-    // TODO: add a ̀`next_key` and a `prefix_keys` function
+    /// Returns the key of the closest ancestor to the given key that can be found in the trie.
+    ///
+    /// An ancestor of a key is a node whose key is a strict prefix of the given key. In other
+    /// words, the given key itself is never returned.
+    ///
+    /// Returns `Ok(None)` if the given key is proven to have no ancestor in the trie, and an
+    /// error if the proof doesn't contain enough information to determine the closest ancestor.
+    pub fn closest_ancestor(
+        &'_ self,
+        key: &[nibble::Nibble],
+    ) -> Result<Option<&'_ [nibble::Nibble]>, IncompleteProofError> {
+        // If the proof is empty, then we have no information about the node whatsoever.
+        if self.entries.is_empty() {
+            return Err(IncompleteProofError());
+        }
+
+        // Because all the entries of the proof are connected to the root, all the ancestors of
+        // the entries of the proof are also in the proof. The closest ancestor found in the proof
+        // is thus the actual closest ancestor, unless there exists a deeper ancestor in a child
+        // that is absent from the proof.
+        let (ancestor_key, children_bitmap) = match self.closest_ancestor_in_proof(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let child_nibble = key[ancestor_key.len()];
+        if children_bitmap & (1 << u8::from(child_nibble)) == 0 {
+            return Ok(Some(ancestor_key));
+        }
+
+        // A node in the direction of the key exists in the trie. If it can't be found in the
+        // proof, it might be a deeper ancestor of the requested key, unless it is too long.
+        if key.len().saturating_sub(1) > ancestor_key.len()
+            && !self.has_entry_with_prefix(&key[..=ancestor_key.len()])
+        {
+            return Err(IncompleteProofError());
+        }
+
+        Ok(Some(ancestor_key))
+    }
+
+    /// Returns the Merkle value of the closest descendant of the given key, or of the node
+    /// whose key is the given key if it exists in the trie.
+    ///
+    /// This corresponds to the semantics of the `closestDescendantMerkleValue` storage query of
+    /// the `chainHead` JSON-RPC functions.
+    ///
+    /// Returns `Ok(None)` if the given key is proven to have no descendant in the trie, and an
+    /// error if the proof doesn't contain enough information to determine the closest
+    /// descendant.
+    pub fn closest_descendant_merkle_value(
+        &self,
+        key: &[nibble::Nibble],
+    ) -> Result<Option<trie_node::MerkleValueOutput>, IncompleteProofError> {
+        // If the proof is empty, then we have no information about the node whatsoever.
+        if self.entries.is_empty() {
+            return Err(IncompleteProofError());
+        }
+
+        // Because all the entries of the proof are connected to the root, if any entry of the
+        // proof starts with `key`, then the closest descendant is also in the proof, and it is
+        // the first entry in lexicographic order that starts with `key`.
+        if let Some((descendant_key, (_, node_value_range, _))) = self
+            .entries
+            .range::<[nibble::Nibble], _>((ops::Bound::Included(key), ops::Bound::Unbounded))
+            .next()
+            .filter(|(k, _)| k.starts_with(key))
+        {
+            let node_value = &self.proof.as_ref()[node_value_range.clone()];
+            let is_root_node = self.entries.keys().next() == Some(descendant_key);
+            return Ok(Some(if is_root_node || node_value.len() >= 32 {
+                trie_node::MerkleValueOutput::from_bytes(
+                    blake2_rfc::blake2b::blake2b(32, &[], node_value).as_bytes(),
+                )
+            } else {
+                trie_node::MerkleValueOutput::from_bytes(node_value)
+            }));
+        }
+
+        // No entry of the proof starts with `key`. The key might still have a descendant in a
+        // child that is absent from the proof.
+        let (ancestor_key, children_bitmap) = match self.closest_ancestor_in_proof(key) {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+
+        let child_nibble = key[ancestor_key.len()];
+        if children_bitmap & (1 << u8::from(child_nibble)) == 0
+            || self.has_entry_with_prefix(&key[..=ancestor_key.len()])
+        {
+            // Either the child doesn't exist, or it is in the proof and diverges from the
+            // requested key.
+            return Ok(None);
+        }
+
+        Err(IncompleteProofError())
+    }
+
+    /// Verifies that the proof contains all the storage items of the trie whose key is within
+    /// the given range, and returns the list of these storage items ordered by key in
+    /// lexicographic order.
+    ///
+    /// Contrary to [`DecodedTrieProof::iter_runtime_context_ordered`], the list returned by this
+    /// function is guaranteed to be exhaustive: if a key within the range isn't returned, then it
+    /// is proven that there is no storage value at this key. This makes it possible to iterate
+    /// over the storage of a trie, page after page, solely from proofs. For example, the next
+    /// page of keys that start with a certain prefix and that follow a certain key can be
+    /// obtained by passing this key as an excluded lower bound, then keeping the items that start
+    /// with the prefix.
+    ///
+    /// Storage values at keys that consist in an uneven number of nibbles are ignored, as they
+    /// can't exist in a trie that has only ever been used in the context of the runtime. See
+    /// [`DecodedTrieProof::iter_runtime_context_ordered`] for more details.
+    ///
+    /// Returns an error if the proof doesn't contain a node of the trie whose key or descendants
+    /// might be within the range, or if it doesn't contain a storage value within the range.
+    pub fn storage_values_range(
+        &'_ self,
+        start: ops::Bound<&[u8]>,
+        end: ops::Bound<&[u8]>,
+    ) -> Result<
+        impl Iterator<Item = (Vec<u8>, &'_ [u8], TrieEntryVersion)> + '_,
+        IncompleteProofError,
+    > {
+        // If the proof is empty, then we have no information about the trie whatsoever.
+        if self.entries.is_empty() {
+            return Err(IncompleteProofError());
+        }
+
+        let to_nibbles =
+            |key: &[u8]| nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+        let start = match start {
+            ops::Bound::Included(key) => ops::Bound::Included(to_nibbles(key)),
+            ops::Bound::Excluded(key) => ops::Bound::Excluded(to_nibbles(key)),
+            ops::Bound::Unbounded => ops::Bound::Unbounded,
+        };
+        let end = match end {
+            ops::Bound::Included(key) => ops::Bound::Included(to_nibbles(key)),
+            ops::Bound::Excluded(key) => ops::Bound::Excluded(to_nibbles(key)),
+            ops::Bound::Unbounded => ops::Bound::Unbounded,
+        };
+
+        // Returns `true` if the given key is inferior to the end of the range.
+        let before_end = |key: &[nibble::Nibble]| match &end {
+            ops::Bound::Included(end) => key <= &end[..],
+            ops::Bound::Excluded(end) => key < &end[..],
+            ops::Bound::Unbounded => true,
+        };
+
+        // Returns `true` if a node whose key starts with `prefix` might be within the range.
+        // Since `prefix` is the lowest key that starts with `prefix`, checking it against the end
+        // of the range is enough.
+        let subtree_in_range = |prefix: &[nibble::Nibble]| {
+            let after_start = match &start {
+                ops::Bound::Included(start) | ops::Bound::Excluded(start) => {
+                    prefix >= &start[..] || start.starts_with(prefix)
+                }
+                ops::Bound::Unbounded => true,
+            };
+            after_start && before_end(prefix)
+        };
+
+        // Because all the entries of the proof are connected to the root, the proof is
+        // incomplete if and only if one of its entries has a child that is absent from the proof
+        // and whose descendants might be within the range.
+        let mut child_prefix = Vec::new();
+        for (key, (_, _, children_bitmap)) in &self.entries {
+            for nibble in
+                nibble::all_nibbles().filter(|n| (children_bitmap & (1 << u8::from(*n))) != 0)
+            {
+                child_prefix.clear();
+                child_prefix.extend_from_slice(key);
+                child_prefix.push(nibble);
+                if subtree_in_range(&child_prefix) && !self.has_entry_with_prefix(&child_prefix) {
+                    return Err(IncompleteProofError());
+                }
+            }
+        }
+
+        // Note that `BTreeMap::range` panics if the end of the range is before its start, which
+        // is why the end of the range is checked separately.
+        let in_range = self
+            .entries
+            .range::<[nibble::Nibble], _>((
+                match &start {
+                    ops::Bound::Included(start) => ops::Bound::Included(&start[..]),
+                    ops::Bound::Excluded(start) => ops::Bound::Excluded(&start[..]),
+                    ops::Bound::Unbounded => ops::Bound::Unbounded,
+                },
+                ops::Bound::Unbounded,
+            ))
+            .take_while(|(key, _)| before_end(key))
+            .filter(|(key, _)| key.len() % 2 == 0);
+
+        let mut out = Vec::new();
+        for (key, (storage_value, _, _)) in in_range {
+            match storage_value {
+                StorageValueInner::Known {
+                    offset,
+                    len,
+                    is_inline,
+                } => out.push((
+                    nibble::nibbles_to_bytes_suffix_extend(key.iter().copied()).collect(),
+                    &self.proof.as_ref()[*offset..][..*len],
+                    if *is_inline {
+                        TrieEntryVersion::V0
+                    } else {
+                        TrieEntryVersion::V1
+                    },
+                )),
+                StorageValueInner::HashKnownValueMissing { .. } => {
+                    return Err(IncompleteProofError())
+                }
+                StorageValueInner::None => {}
+            }
+        }
+
+        Ok(out.into_iter())
+    }
+
+    /// Returns the first key of the trie that is strictly superior to `key` in lexicographic
+    /// order and that has a storage value, or `None` if it is proven that there is no such key.
+    ///
+    /// Keys that consist in an uneven number of nibbles are ignored, for the same reason as in
+    /// [`DecodedTrieProof::storage_values_range`].
+    ///
+    /// Returns an error if the proof doesn't contain enough information to determine the next
+    /// key.
+    pub fn next_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, IncompleteProofError> {
+        let key = nibble::bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+
+        // The trie is traversed depth-first, which visits the nodes in lexicographic order of
+        // their key. The last element of `to_visit` is the next node to visit, or `None` if this
+        // node is absent from the proof. Nodes absent from the proof only lead to an error if they
+        // are reached, as the next key might be found before them.
+        let mut to_visit = vec![Some(Vec::new())];
+
+        while let Some(node_key) = to_visit.pop() {
+            let node_key = node_key.ok_or(IncompleteProofError())?;
+            let node_info = self
+                .trie_node_info(&node_key)
+                .ok_or(IncompleteProofError())?;
+
+            if node_key > key
+                && node_key.len() % 2 == 0
+                && matches!(
+                    node_info.storage_value,
+                    StorageValue::Known { .. } | StorageValue::HashKnownValueMissing(_)
+                )
+            {
+                return Ok(Some(
+                    nibble::nibbles_to_bytes_suffix_extend(node_key.iter().copied()).collect(),
+                ));
+            }
+
+            for (nibble, child) in node_info.children.children().enumerate().rev() {
+                let nibble = nibble::Nibble::try_from(u8::try_from(nibble).unwrap()).unwrap();
+
+                // The keys of all the descendants of this child start with `child_prefix`. If
+                // `child_prefix` is inferior to `key` without being a prefix of it, these keys
+                // are all inferior to `key` and the child can be skipped.
+                let mut child_prefix = node_key.clone();
+                child_prefix.push(nibble);
+                let skip = child_prefix < key && !key.starts_with(&child_prefix);
+
+                match child {
+                    Child::NoChild => {}
+                    _ if skip => {}
+                    Child::AbsentFromProof => to_visit.push(None),
+                    Child::InProof { child_key } => to_visit.push(Some(child_key.to_vec())),
+                }
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Returns the key and children bitmap of the entry of the proof whose key is the longest
+    /// strict prefix of the given key.
+    fn closest_ancestor_in_proof(
+        &'_ self,
+        key: &[nibble::Nibble],
+    ) -> Option<(&'_ [nibble::Nibble], u16)> {
+        // Same algorithm as in `trie_node_info`. Since all the prefixes of `key` are inferior or
+        // equal to `key`, the entry right before `to_search` is either the deepest ancestor, or
+        // a node that diverges from `key`, in which case we continue searching starting from
+        // the common prefix.
+        let mut to_search = key;
+        loop {
+            let upper_bound = if to_search.len() == key.len() {
+                ops::Bound::Excluded(to_search)
+            } else {
+                ops::Bound::Included(to_search)
+            };
+
+            match self
+                .entries
+                .range::<[nibble::Nibble], _>((ops::Bound::Unbounded, upper_bound))
+                .next_back()
+            {
+                None => return None,
+                Some((found_key, (_, _, children_bitmap))) if key.starts_with(found_key) => {
+                    return Some((found_key, *children_bitmap))
+                }
+                Some((found_key, _)) => {
+                    let common_nibbles = found_key
+                        .iter()
+                        .zip(key.iter())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    debug_assert!(common_nibbles < to_search.len()); // Make sure we progress.
+                    to_search = &key[..common_nibbles];
+                }
+            }
+        }
+    }
+
+    /// Returns `true` if the proof contains an entry whose key starts with the given prefix.
+    fn has_entry_with_prefix(&self, prefix: &[nibble::Nibble]) -> bool {
+        self.entries
+            .range::<[nibble::Nibble], _>((ops::Bound::Included(prefix), ops::Bound::Unbounded))
+            .next()
+            .map_or(false, |(k, _)| k.starts_with(prefix))
+    }
+
+    // TODO: add a `prefix_keys` function
 }
 
+/// Error potentially returned by [`DecodedTrieProof::closest_ancestor`],
+/// [`DecodedTrieProof::closest_descendant_merkle_value`],
+/// [`DecodedTrieProof::storage_values_range`] and [`DecodedTrieProof::next_key`].
+#[derive(Debug, Clone, derive_more::Display)]
+#[display(fmt = "Proof doesn't contain enough information")]
+pub struct IncompleteProofError();
+
 /// Storage value of the node.
 #[derive(Copy, Clone)]
 pub enum StorageValue<'a> {
@@ -700,7 +1194,7 @@ impl<'a> fmt::Debug for StorageValue<'a> {
                     f,
                     "0x{}…{} ({} bytes{})",
                     hex::encode(&value[0..4]),
-                    hex::encode(&value[value.len() - 4..]),
+                    hex::encode(&value[value.len().saturating_sub(4)..]),
                     value.len(),
                     if *inline { ", inline" } else { "" }
                 )
@@ -840,10 +1334,8 @@ mod tests {
         .unwrap();
     }
 
-    #[test]
-    fn basic_works() {
-        // Key/value taken from the Polkadot genesis block.
-
+    /// Returns a proof and its trie root. Key/value taken from the Polkadot genesis block.
+    fn polkadot_genesis_proof() -> (Vec<u8>, [u8; 32]) {
         let proof = vec![
             24, 212, 125, 1, 84, 37, 150, 173, 176, 93, 97, 64, 193, 112, 172, 71, 158, 223, 124,
             253, 90, 163, 83, 87, 89, 10, 207, 229, 209, 26, 128, 77, 148, 78, 80, 13, 20, 86, 253,
@@ -948,6 +1440,13 @@ mod tests {
             <[u8; 32]>::try_from(&bytes[..]).unwrap()
         };
 
+        (proof, trie_root)
+    }
+
+    #[test]
+    fn basic_works() {
+        let (proof, trie_root) = polkadot_genesis_proof();
+
         let decoded = super::decode_and_verify_proof(super::Config {
             trie_root_hash: &trie_root,
             proof,
@@ -963,6 +1462,133 @@ mod tests {
         );
     }
 
+    #[test]
+    fn incremental_decoding_matches() {
+        let (proof, trie_root) = polkadot_genesis_proof();
+
+        for chunk_size in [1, 7, 64, proof.len()] {
+            let mut decoder = super::IncrementalDecoder::new();
+            for chunk in proof.chunks(chunk_size) {
+                assert!(!decoder.is_complete());
+                decoder.feed(chunk).unwrap();
+            }
+            assert!(decoder.is_complete());
+
+            let decoded = decoder.finish(&trie_root).unwrap();
+            let expected = super::decode_and_verify_proof(super::Config {
+                trie_root_hash: &trie_root,
+                proof: &proof,
+            })
+            .unwrap();
+
+            assert!(decoded
+                .iter_ordered()
+                .map(|(k, e)| (k, e.node_value))
+                .eq(expected.iter_ordered().map(|(k, e)| (k, e.node_value))));
+        }
+    }
+
+    #[test]
+    fn incremental_decoding_errors() {
+        let (mut proof, trie_root) = polkadot_genesis_proof();
+
+        // Incomplete proof.
+        let mut decoder = super::IncrementalDecoder::new();
+        decoder.feed(&proof[..proof.len() - 1]).unwrap();
+        assert!(matches!(
+            decoder.finish(&trie_root),
+            Err(super::Error::InvalidFormat)
+        ));
+
+        // Trailing data is detected as soon as it is received.
+        let mut decoder = super::IncrementalDecoder::new();
+        decoder.feed(&proof).unwrap();
+        assert!(matches!(
+            decoder.feed(&[0]),
+            Err(super::Error::InvalidFormat)
+        ));
+
+        // Wrong trie root.
+        let mut decoder = super::IncrementalDecoder::new();
+        decoder.feed(&proof).unwrap();
+        assert!(matches!(
+            decoder.finish(&[0; 32]),
+            Err(super::Error::TrieRootNotFound)
+        ));
+
+        // Invalid length prefix.
+        proof[0] = 0b01;
+        proof[1] = 0;
+        let mut decoder = super::IncrementalDecoder::new();
+        assert!(matches!(
+            decoder.feed(&proof[..2]),
+            Err(super::Error::InvalidFormat)
+        ));
+    }
+
+    #[test]
+    fn closest_descendant_and_ancestor() {
+        let (proof, trie_root) = polkadot_genesis_proof();
+        let decoded = super::decode_and_verify_proof(super::Config {
+            trie_root_hash: &trie_root,
+            proof,
+        })
+        .unwrap();
+
+        // The closest descendant of the empty key is the root node.
+        assert_eq!(
+            decoded
+                .closest_descendant_merkle_value(&[])
+                .unwrap()
+                .unwrap()
+                .as_ref(),
+            &trie_root[..]
+        );
+        assert!(decoded.closest_ancestor(&[]).unwrap().is_none());
+
+        let requested_key = hex::decode("9c5d795d0297be56027a4b2464e3339763e6d3c1fb15805edfd024172ea4817d7081542596adb05d6140c170ac479edf7cfd5aa35357590acfe5d11a804d944e").unwrap();
+        let requested_key =
+            crate::trie::bytes_to_nibbles(requested_key.into_iter()).collect::<Vec<_>>();
+
+        // The requested key is in the proof, and so are all of its ancestors.
+        assert!(decoded
+            .closest_descendant_merkle_value(&requested_key)
+            .unwrap()
+            .is_some());
+        let ancestor = decoded.closest_ancestor(&requested_key).unwrap().unwrap();
+        assert!(requested_key.starts_with(ancestor));
+        assert!(ancestor.len() < requested_key.len());
+
+        // Keys that diverge from the requested key right below the root are absent from the
+        // proof.
+        let mut other_key = requested_key.clone();
+        let root_key_len = decoded.iter_ordered().next().unwrap().0.len();
+        for nibble in crate::trie::all_nibbles() {
+            other_key.truncate(root_key_len);
+            other_key.push(nibble);
+            other_key.push(nibble);
+
+            let has_child = decoded
+                .iter_ordered()
+                .next()
+                .unwrap()
+                .1
+                .trie_node_info
+                .children
+                .has_child(nibble);
+            if nibble == requested_key[root_key_len] || !has_child {
+                continue;
+            }
+
+            assert!(decoded.closest_descendant_merkle_value(&other_key).is_err());
+            assert!(decoded.closest_ancestor(&other_key).is_err());
+            assert!(decoded
+                .closest_ancestor(&other_key[..root_key_len + 1])
+                .unwrap()
+                .is_some());
+        }
+    }
+
     #[test]
     fn node_values_smaller_than_32bytes() {
         let proof = vec![
@@ -1099,4 +1725,239 @@ mod tests {
         })
         .unwrap();
     }
+
+    #[test]
+    fn storage_values_range_complete_proof() {
+        // One root node with two identical inlined children, whose keys are `0x00` and `0x10`.
+        let decoded = super::decode_and_verify_proof(super::Config {
+            proof: &[
+                4, 60, 128, 3, 0, 20, 65, 0, 8, 104, 105, 20, 65, 0, 8, 104, 105,
+            ],
+            trie_root_hash: &[
+                15, 224, 134, 90, 11, 145, 174, 197, 185, 253, 233, 197, 95, 101, 197, 10, 78, 28,
+                137, 217, 102, 198, 242, 100, 90, 96, 9, 204, 213, 69, 174, 4,
+            ],
+        })
+        .unwrap();
+
+        let all = decoded
+            .storage_values_range(core::ops::Bound::Unbounded, core::ops::Bound::Unbounded)
+            .unwrap()
+            .map(|(key, value, _)| (key, value.to_vec()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            all,
+            vec![(vec![0x00], b"hi".to_vec()), (vec![0x10], b"hi".to_vec())]
+        );
+
+        let after_first = decoded
+            .storage_values_range(
+                core::ops::Bound::Excluded(&all[0].0),
+                core::ops::Bound::Unbounded,
+            )
+            .unwrap()
+            .map(|(key, _, _)| key)
+            .collect::<Vec<_>>();
+        assert_eq!(after_first, vec![all[1].0.clone()]);
+
+        // Start of the range after its end.
+        assert_eq!(
+            decoded
+                .storage_values_range(
+                    core::ops::Bound::Excluded(&all[1].0),
+                    core::ops::Bound::Excluded(&all[0].0),
+                )
+                .unwrap()
+                .count(),
+            0
+        );
+    }
+
+    #[test]
+    fn storage_values_range_partial_proof() {
+        let proof = vec![
+            12, 17, 1, 158, 195, 101, 195, 207, 89, 214, 113, 235, 114, 218, 14, 122, 65, 19, 196,
+            0, 3, 88, 95, 7, 141, 67, 77, 97, 37, 180, 4, 67, 254, 17, 253, 41, 45, 19, 164, 16, 2,
+            0, 0, 0, 104, 95, 15, 31, 5, 21, 244, 98, 205, 207, 132, 224, 241, 214, 4, 93, 252,
+            187, 32, 80, 82, 127, 41, 119, 1, 0, 0, 185, 5, 128, 175, 188, 128, 15, 126, 137, 9,
+            189, 204, 29, 117, 244, 124, 194, 9, 181, 214, 119, 106, 91, 55, 85, 146, 101, 112, 37,
+            46, 31, 42, 133, 72, 101, 38, 60, 66, 128, 28, 186, 118, 76, 106, 111, 232, 204, 106,
+            88, 52, 218, 113, 2, 76, 119, 132, 172, 202, 215, 130, 198, 184, 230, 206, 134, 44,
+            171, 25, 86, 243, 121, 128, 233, 10, 145, 50, 95, 100, 17, 213, 147, 28, 9, 142, 56,
+            95, 33, 40, 56, 9, 39, 3, 193, 79, 169, 207, 115, 80, 61, 217, 4, 106, 172, 152, 128,
+            12, 255, 241, 157, 249, 219, 101, 33, 139, 178, 174, 121, 165, 33, 175, 0, 232, 230,
+            129, 23, 89, 219, 21, 35, 23, 48, 18, 153, 124, 96, 81, 66, 128, 30, 174, 194, 227,
+            100, 149, 97, 237, 23, 238, 114, 178, 106, 158, 238, 48, 166, 82, 19, 210, 129, 122,
+            70, 165, 94, 186, 31, 28, 80, 29, 73, 252, 128, 16, 56, 19, 158, 188, 178, 192, 234,
+            12, 251, 221, 107, 119, 243, 74, 155, 111, 53, 36, 107, 183, 204, 174, 253, 183, 67,
+            77, 199, 47, 121, 185, 162, 128, 17, 217, 226, 195, 240, 113, 144, 201, 129, 184, 240,
+            237, 204, 79, 68, 191, 165, 29, 219, 170, 152, 134, 160, 153, 245, 38, 181, 131, 83,
+            209, 245, 194, 128, 137, 217, 3, 84, 1, 224, 52, 199, 112, 213, 150, 42, 51, 214, 103,
+            194, 225, 224, 210, 84, 84, 53, 31, 159, 82, 201, 3, 104, 118, 212, 110, 7, 128, 240,
+            251, 81, 190, 126, 80, 60, 139, 88, 152, 39, 153, 231, 178, 31, 184, 56, 44, 133, 31,
+            47, 98, 234, 107, 15, 248, 64, 78, 36, 89, 9, 149, 128, 233, 75, 238, 120, 212, 149,
+            223, 135, 48, 174, 211, 219, 223, 217, 20, 172, 212, 172, 3, 234, 54, 130, 55, 225, 63,
+            17, 255, 217, 150, 252, 93, 15, 128, 89, 54, 254, 99, 202, 80, 50, 27, 92, 48, 57, 174,
+            8, 211, 44, 58, 108, 207, 129, 245, 129, 80, 170, 57, 130, 80, 166, 250, 214, 40, 156,
+            181, 21, 1, 128, 65, 0, 128, 182, 204, 71, 61, 83, 76, 85, 166, 19, 22, 212, 242, 236,
+            229, 51, 88, 16, 191, 227, 125, 217, 54, 7, 31, 36, 176, 211, 111, 72, 220, 181, 241,
+            128, 149, 2, 12, 26, 95, 9, 193, 115, 207, 253, 90, 218, 0, 41, 140, 119, 189, 166,
+            101, 244, 74, 171, 53, 248, 82, 113, 79, 110, 25, 72, 62, 65,
+        ];
+
+        let trie_root = [
+            43, 100, 198, 174, 1, 66, 26, 95, 93, 119, 43, 242, 5, 176, 153, 134, 193, 74, 159,
+            215, 134, 15, 252, 135, 67, 129, 21, 16, 20, 211, 97, 217,
+        ];
+
+        let decoded = super::decode_and_verify_proof(super::Config {
+            trie_root_hash: &trie_root,
+            proof,
+        })
+        .unwrap();
+
+        // The proof only covers a single key.
+        assert!(decoded
+            .storage_values_range(core::ops::Bound::Unbounded, core::ops::Bound::Unbounded)
+            .is_err());
+
+        let requested_key =
+            hex::decode("f0c365c3cf59d671eb72da0e7a4113c49f1f0515f462cdcf84e0f1d6045dfcbb")
+                .unwrap();
+        let obtained = decoded
+            .storage_values_range(
+                core::ops::Bound::Included(&requested_key),
+                core::ops::Bound::Included(&requested_key),
+            )
+            .unwrap()
+            .collect::<Vec<_>>();
+        assert_eq!(obtained.len(), 1);
+        assert_eq!(obtained[0].0, requested_key);
+        assert_eq!(obtained[0].1, &[80, 82, 127, 41, 119, 1, 0, 0][..]);
+    }
+
+    /// The values are large enough for the trie nodes to never be inlined in their parent.
+    fn next_key_storage() -> alloc::collections::BTreeMap<Vec<u8>, Vec<u8>> {
+        [
+            &[0x01][..],
+            &[0x01, 0x00],
+            &[0x01, 0x02, 0x03],
+            &[0x12, 0x34],
+            &[0x12, 0x35],
+            &[0x80],
+            &[0xff, 0xff],
+        ]
+        .into_iter()
+        .map(|k| (k.to_vec(), vec![0xaa; 40]))
+        .collect()
+    }
+
+    /// Builds a proof of the given keys of the given storage.
+    fn build_proof(
+        entries: &alloc::collections::BTreeMap<Vec<u8>, Vec<u8>>,
+        keys: &[&[u8]],
+    ) -> super::DecodedTrieProof<Vec<u8>> {
+        let mut build = crate::trie::proof_encode::build_from_storage(
+            crate::trie::proof_encode::BuildFromStorageConfig { keys: keys.iter() },
+        );
+
+        loop {
+            match build {
+                crate::trie::proof_encode::StorageProofBuild::Finished {
+                    proof,
+                    trie_root_hash,
+                } => {
+                    return super::decode_and_verify_proof(super::Config {
+                        proof,
+                        trie_root_hash: &trie_root_hash,
+                    })
+                    .unwrap()
+                }
+                crate::trie::proof_encode::StorageProofBuild::NextKey(req) => {
+                    let next = if req.or_equal() {
+                        entries.range(req.key().as_ref().to_vec()..).next()
+                    } else {
+                        entries
+                            .range((
+                                core::ops::Bound::Excluded(req.key().as_ref().to_vec()),
+                                core::ops::Bound::Unbounded,
+                            ))
+                            .next()
+                    };
+                    build = req.inject(next.map(|(k, _)| k));
+                }
+                crate::trie::proof_encode::StorageProofBuild::StorageValue(req) => {
+                    let value = entries
+                        .get(req.key().as_ref())
+                        .map(|v| (v, super::TrieEntryVersion::V1));
+                    build = req.inject(value);
+                }
+            }
+        }
+    }
+
+    fn expected_next_key(
+        entries: &alloc::collections::BTreeMap<Vec<u8>, Vec<u8>>,
+        key: &[u8],
+    ) -> Option<Vec<u8>> {
+        entries
+            .range((
+                core::ops::Bound::Excluded(key.to_vec()),
+                core::ops::Bound::Unbounded,
+            ))
+            .next()
+            .map(|(k, _)| k.clone())
+    }
+
+    const NEXT_KEY_SEARCHED_KEYS: &[&[u8]] = &[
+        &[],
+        &[0x00],
+        &[0x01],
+        &[0x01, 0x00],
+        &[0x01, 0x01],
+        &[0x01, 0x02, 0x03],
+        &[0x12],
+        &[0x12, 0x34, 0x00],
+        &[0x12, 0x35],
+        &[0x7f, 0xff],
+        &[0xff, 0xff],
+        &[0xff, 0xff, 0x00],
+    ];
+
+    #[test]
+    fn next_key_full_proof() {
+        let entries = next_key_storage();
+        let all_keys = entries.keys().map(|k| &k[..]).collect::<Vec<_>>();
+        let proof = build_proof(&entries, &all_keys);
+
+        for key in NEXT_KEY_SEARCHED_KEYS {
+            assert_eq!(
+                proof.next_key(key).unwrap(),
+                expected_next_key(&entries, key),
+                "{key:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn next_key_minimal_proof() {
+        // A proof of the requested key and of the key that follows it is enough.
+        let entries = next_key_storage();
+        for key in NEXT_KEY_SEARCHED_KEYS {
+            let expected = expected_next_key(&entries, key);
+            let proof_keys = [*key]
+                .into_iter()
+                .chain(expected.as_deref())
+                .collect::<Vec<_>>();
+            let proof = build_proof(&entries, &proof_keys);
+            assert_eq!(proof.next_key(key).unwrap(), expected, "{key:?}");
+        }
+    }
+
+    #[test]
+    fn next_key_missing_from_proof() {
+        let entries = next_key_storage();
+        let proof = build_proof(&entries, &[&[0x01, 0x00]]);
+        assert!(proof.next_key(&[0x01, 0x00]).is_err());
+    }
 }
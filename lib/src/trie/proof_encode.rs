@@ -391,7 +391,9 @@ impl ProofBuilder {
                     .user_data()
                     .take()
                 // Ignore nodes whose value is missing.
-                else { return either::Right(iter::empty()); };
+                else {
+                    return either::Right(iter::empty());
+                };
 
                 // Nodes of length < 32 should have been inlined within their parent or ancestor.
                 // We thus skip them, unless they're the root node.
@@ -439,6 +441,322 @@ impl Default for ProofBuilder {
     }
 }
 
+/// Configuration for [`build_from_storage`].
+pub struct BuildFromStorageConfig<TKeys> {
+    /// List of keys whose storage value (or lack of storage value) must be provable using the
+    /// generated proof. Can contain duplicates and doesn't need to be ordered.
+    pub keys: TKeys,
+}
+
+/// Starts building a Merkle proof of the given keys, using the content of a storage.
+///
+/// Contrary to [`ProofBuilder`], which requires the node values of the trie to be known, this
+/// function only requires accessing the storage entries. The entries of the storage are
+/// requested one by one in lexicographic order, and the Merkle values of the trie nodes are
+/// calculated on the fly. Only the nodes that are an ancestor of one of the keys are kept in
+/// memory, in addition to the proof being built. This makes it possible to generate proofs
+/// without loading the entire trie in memory, at the cost of iterating over the entire storage.
+pub fn build_from_storage(
+    config: BuildFromStorageConfig<impl Iterator<Item = impl AsRef<[u8]>>>,
+) -> StorageProofBuild {
+    let mut requested_keys = config
+        .keys
+        .map(|key| nibble::bytes_to_nibbles(key.as_ref().iter().copied()).collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    requested_keys.sort_unstable();
+    requested_keys.dedup();
+
+    StorageProofBuild::NextKey(NextKey {
+        inner: BuildFromStorageInner {
+            requested_keys,
+            stack: Vec::new(),
+            proof_builder: ProofBuilder::new(),
+        },
+        key: Vec::new(),
+        or_equal: true,
+    })
+}
+
+/// Current state of the proof building and how to continue.
+#[must_use]
+pub enum StorageProofBuild {
+    /// The proof has been built.
+    Finished {
+        /// SCALE-encoded Merkle proof.
+        proof: Vec<u8>,
+        /// Hash of the root node of the trie, against which the proof can be verified.
+        trie_root_hash: [u8; 32],
+    },
+
+    /// Request the key of the storage that follows a certain key. Call [`NextKey::inject`] to
+    /// indicate the key.
+    NextKey(NextKey),
+
+    /// Request the storage value of a specific key. Call [`StorageValue::inject`] to indicate
+    /// the value.
+    StorageValue(StorageValue),
+}
+
+/// Request the key of the storage that follows a certain key. Call [`NextKey::inject`] to
+/// indicate the key.
+#[must_use]
+pub struct NextKey {
+    inner: BuildFromStorageInner,
+    key: Vec<u8>,
+    or_equal: bool,
+}
+
+impl NextKey {
+    /// Returns the key whose next key must be passed back.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        &self.key
+    }
+
+    /// If `true`, then the provided value must be the one superior or equal to the requested
+    /// key. If `false`, then the provided value must be strictly superior to the requested key.
+    pub fn or_equal(&self) -> bool {
+        self.or_equal
+    }
+
+    /// Indicates the key that follows the requested key, or `None` if there is no key after it,
+    /// and advances the proof building.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the key passed as parameter isn't superior (or equal, see
+    /// [`NextKey::or_equal`]) to the requested key.
+    ///
+    pub fn inject(mut self, key: Option<impl AsRef<[u8]>>) -> StorageProofBuild {
+        match key {
+            Some(key) => {
+                let key = key.as_ref();
+                assert!(key > &self.key[..] || (self.or_equal && key == &self.key[..]));
+                StorageProofBuild::StorageValue(StorageValue {
+                    inner: self.inner,
+                    key: key.to_owned(),
+                })
+            }
+            None => {
+                let trie_root_hash = self.inner.finish();
+                StorageProofBuild::Finished {
+                    proof: self.inner.proof_builder.build_to_vec(),
+                    trie_root_hash,
+                }
+            }
+        }
+    }
+}
+
+/// Request the storage value of a specific key. Call [`StorageValue::inject`] to indicate the
+/// value.
+#[must_use]
+pub struct StorageValue {
+    inner: BuildFromStorageInner,
+    key: Vec<u8>,
+}
+
+impl StorageValue {
+    /// Returns the key whose value is being requested.
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        &self.key
+    }
+
+    /// Indicates the storage value and advances the proof building.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `None` is passed, as [`StorageValue::key`] has previously been reported as
+    /// having a value.
+    ///
+    pub fn inject(
+        mut self,
+        stored_value: Option<(impl AsRef<[u8]>, super::TrieEntryVersion)>,
+    ) -> StorageProofBuild {
+        let (value, version) = match stored_value {
+            Some((value, version)) => (value.as_ref().to_owned(), version),
+            // API user misbehaved.
+            None => panic!("Injected no value when previously reported a value at this key"),
+        };
+
+        self.inner.push_entry(
+            nibble::bytes_to_nibbles(self.key.iter().copied()).collect(),
+            value,
+            version,
+        );
+
+        StorageProofBuild::NextKey(NextKey {
+            inner: self.inner,
+            key: self.key,
+            or_equal: false,
+        })
+    }
+}
+
+/// Shared by all the public-facing structs.
+///
+/// # Implementation notes
+///
+/// Storage entries are pushed in lexicographic order. [`BuildFromStorageInner::stack`] contains
+/// the trie nodes that are an ancestor of the last pushed entry (including the node of this
+/// entry), from the root to the leaf. When an entry is pushed, the nodes of the stack that
+/// aren't an ancestor of the new entry can no longer gain any child. Their Merkle value is thus
+/// calculated and stored within their parent, and they are removed from the stack.
+struct BuildFromStorageInner {
+    /// Keys requested by the user, ordered and de-duplicated.
+    requested_keys: Vec<Vec<Nibble>>,
+    /// See the implementation notes above.
+    stack: Vec<StackNode>,
+    /// Builder of the proof, into which the node values relevant for the proof are inserted.
+    proof_builder: ProofBuilder,
+}
+
+struct StackNode {
+    /// Full key of the node.
+    key: Vec<Nibble>,
+    /// Storage value of the node, if any.
+    storage_value: Option<(Vec<u8>, super::TrieEntryVersion)>,
+    /// Merkle values of the children that have already been calculated.
+    children: [Option<trie_node::MerkleValueOutput>; 16],
+}
+
+impl BuildFromStorageInner {
+    /// Pushes a new storage entry, whose key must be strictly superior to the previous one.
+    fn push_entry(&mut self, key: Vec<Nibble>, value: Vec<u8>, version: super::TrieEntryVersion) {
+        while let Some(top_node) = self.stack.last() {
+            if key.starts_with(&top_node.key) {
+                break;
+            }
+
+            let common_prefix_len = top_node
+                .key
+                .iter()
+                .zip(key.iter())
+                .take_while(|(a, b)| a == b)
+                .count();
+            let parent_key_len = self
+                .stack
+                .len()
+                .checked_sub(2)
+                .map(|idx| self.stack[idx].key.len());
+
+            let top_node = self.stack.pop().unwrap();
+
+            match parent_key_len {
+                Some(parent_key_len) if parent_key_len >= common_prefix_len => {
+                    // The top node is finished, and its parent is the next node on the stack.
+                    let child_nibble = top_node.key[parent_key_len];
+                    let merkle_value = self.close_node(top_node, Some(parent_key_len));
+                    self.stack.last_mut().unwrap().children[usize::from(u8::from(child_nibble))] =
+                        Some(merkle_value);
+                }
+                _ => {
+                    // The new entry and the top node diverge below the parent of the top node.
+                    // A branch node must be inserted at the point where they diverge.
+                    let child_nibble = top_node.key[common_prefix_len];
+                    let mut branch_node = StackNode {
+                        key: top_node.key[..common_prefix_len].to_vec(),
+                        storage_value: None,
+                        children: Default::default(),
+                    };
+                    branch_node.children[usize::from(u8::from(child_nibble))] =
+                        Some(self.close_node(top_node, Some(common_prefix_len)));
+                    self.stack.push(branch_node);
+                }
+            }
+        }
+
+        self.stack.push(StackNode {
+            key,
+            storage_value: Some((value, version)),
+            children: Default::default(),
+        });
+    }
+
+    /// Closes all the nodes of the stack and returns the hash of the root node of the trie.
+    fn finish(&mut self) -> [u8; 32] {
+        while let Some(top_node) = self.stack.pop() {
+            let parent_key_len = match self.stack.last() {
+                Some(parent) => parent.key.len(),
+                None => return self.close_node(top_node, None).into(),
+            };
+
+            let child_nibble = top_node.key[parent_key_len];
+            let merkle_value = self.close_node(top_node, Some(parent_key_len));
+            self.stack.last_mut().unwrap().children[usize::from(u8::from(child_nibble))] =
+                Some(merkle_value);
+        }
+
+        // The storage is empty.
+        let node_value = [0];
+        if !self.requested_keys.is_empty() {
+            self.proof_builder.set_node_value(&[], &node_value, None);
+        }
+        blake2_hash(&node_value)
+    }
+
+    /// Calculates the node value and Merkle value of the given node, and inserts it in the proof
+    /// if necessary.
+    ///
+    /// `parent_key_len` must be the length of the key of the parent of the node, or `None` if
+    /// the node is the root node.
+    fn close_node(
+        &mut self,
+        node: StackNode,
+        parent_key_len: Option<usize>,
+    ) -> trie_node::MerkleValueOutput {
+        let hashed_storage_value = match &node.storage_value {
+            Some((value, super::TrieEntryVersion::V1)) if value.len() >= 33 => {
+                Some(blake2_hash(value))
+            }
+            _ => None,
+        };
+
+        let partial_key_start = parent_key_len.map_or(0, |len| len + 1);
+
+        // `encode_to_vec` can only fail if the node has no child and no storage value, which
+        // can't happen here.
+        let node_value = trie_node::encode_to_vec(trie_node::Decoded {
+            partial_key: node.key[partial_key_start..].iter().copied(),
+            children: array::from_fn(|nibble| node.children[nibble].as_ref()),
+            storage_value: match (&node.storage_value, &hashed_storage_value) {
+                (Some(_), Some(hash)) => trie_node::StorageValue::Hashed(hash),
+                (Some((value, _)), None) => trie_node::StorageValue::Unhashed(value),
+                (None, _) => trie_node::StorageValue::None,
+            },
+        })
+        .unwrap();
+
+        // A node must be included in the proof if the nibbles of its key up to and including the
+        // nibble of its parent's child index are a prefix of one of the requested keys. This
+        // includes all the ancestors of the requested keys, plus the nodes that prove the
+        // absence of a requested key.
+        let proof_prefix = &node.key[..partial_key_start];
+        let first_candidate = self
+            .requested_keys
+            .partition_point(|k| &k[..] < proof_prefix);
+        if matches!(self.requested_keys.get(first_candidate), Some(k) if k.starts_with(proof_prefix))
+        {
+            let unhashed_storage_value = match (&node.storage_value, &hashed_storage_value) {
+                (Some((value, _)), Some(_))
+                    if self.requested_keys.binary_search(&node.key).is_ok() =>
+                {
+                    Some(&value[..])
+                }
+                _ => None,
+            };
+
+            self.proof_builder
+                .set_node_value(&node.key, &node_value, unhashed_storage_value);
+        }
+
+        if node_value.len() < 32 && parent_key_len.is_some() {
+            trie_node::MerkleValueOutput::from_bytes(&node_value)
+        } else {
+            trie_node::MerkleValueOutput::from_bytes(&blake2_hash(&node_value))
+        }
+    }
+}
+
 fn blake2_hash(data: &[u8]) -> [u8; 32] {
     <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
 }
@@ -665,4 +983,115 @@ mod tests {
         })
         .unwrap();
     }
+
+    fn build_from_storage(
+        entries: &alloc::collections::BTreeMap<Vec<u8>, Vec<u8>>,
+        version: super::super::TrieEntryVersion,
+        keys: &[Vec<u8>],
+    ) -> (Vec<u8>, [u8; 32]) {
+        let mut build =
+            super::build_from_storage(super::BuildFromStorageConfig { keys: keys.iter() });
+
+        loop {
+            match build {
+                super::StorageProofBuild::Finished {
+                    proof,
+                    trie_root_hash,
+                } => return (proof, trie_root_hash),
+                super::StorageProofBuild::NextKey(req) => {
+                    let next = if req.or_equal() {
+                        entries.range(req.key().as_ref().to_vec()..).next()
+                    } else {
+                        entries
+                            .range((
+                                core::ops::Bound::Excluded(req.key().as_ref().to_vec()),
+                                core::ops::Bound::Unbounded,
+                            ))
+                            .next()
+                    };
+                    build = req.inject(next.map(|(k, _)| k));
+                }
+                super::StorageProofBuild::StorageValue(req) => {
+                    let value = entries.get(req.key().as_ref()).map(|v| (v, version));
+                    build = req.inject(value);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn build_from_storage_empty_trie() {
+        let (proof, trie_root_hash) = build_from_storage(
+            &Default::default(),
+            super::super::TrieEntryVersion::V1,
+            &[vec![1, 2, 3]],
+        );
+
+        assert_eq!(
+            trie_root_hash,
+            super::super::trie_root(super::super::TrieEntryVersion::V1, &[] as &[(&[u8], &[u8])])
+        );
+
+        let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+            proof,
+            trie_root_hash: &trie_root_hash,
+        })
+        .unwrap();
+        assert_eq!(decoded.storage_value(&[1, 2, 3]), Some(None));
+    }
+
+    #[test]
+    fn build_from_storage_random() {
+        for _ in 0..500 {
+            let version = if rand::random() {
+                super::super::TrieEntryVersion::V0
+            } else {
+                super::super::TrieEntryVersion::V1
+            };
+
+            // Keys are made of few possible bytes in order to create branch nodes, and values
+            // are sometimes long enough to be hashed.
+            let byte_range = Uniform::new_inclusive(0, 3);
+            let entries = (0..Uniform::new_inclusive(0, 24).sample(&mut rand::thread_rng()))
+                .map(|_| {
+                    let key_len = Uniform::new_inclusive(0, 4).sample(&mut rand::thread_rng());
+                    let value_len = if rand::random() { 1 } else { 40 };
+                    let key = (0..key_len)
+                        .map(|_| byte_range.sample(&mut rand::thread_rng()))
+                        .collect::<Vec<u8>>();
+                    (key, vec![rand::random::<u8>(); value_len])
+                })
+                .collect::<alloc::collections::BTreeMap<_, _>>();
+
+            let requested_keys = (0..Uniform::new_inclusive(0, 4).sample(&mut rand::thread_rng()))
+                .map(|_| {
+                    let key_len = Uniform::new_inclusive(0, 4).sample(&mut rand::thread_rng());
+                    (0..key_len)
+                        .map(|_| byte_range.sample(&mut rand::thread_rng()))
+                        .collect::<Vec<u8>>()
+                })
+                .chain(entries.keys().take(2).cloned())
+                .collect::<Vec<_>>();
+
+            let (proof, trie_root_hash) = build_from_storage(&entries, version, &requested_keys);
+
+            assert_eq!(
+                trie_root_hash,
+                super::super::trie_root(version, &entries.iter().collect::<Vec<_>>())
+            );
+
+            let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+                proof,
+                trie_root_hash: &trie_root_hash,
+            })
+            .unwrap();
+
+            for key in &requested_keys {
+                assert_eq!(
+                    decoded.storage_value(key).unwrap().map(|(v, _)| v),
+                    entries.get(key).map(|v| &v[..])
+                );
+            }
+        }
+    }
 }
@@ -0,0 +1,412 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Naive reference implementation of the trie, for testing purposes.
+//!
+//! This module is only available if the `fuzzing` feature is enabled.
+//!
+//! The functions of this module build the node values of a trie recursively from the full list
+//! of its entries, without relying on any of the other trie-related modules of this crate. They
+//! are slow, but their simplicity makes them easy to review against the specification.
+//!
+//! [`check`] cross-checks [`super::calculate_root`], [`super::proof_encode`], and
+//! [`super::proof_decode`] against this reference implementation. Use [`random_entries`] and
+//! [`insert_child_trie`] in order to generate the tries to check.
+
+use super::{
+    bytes_to_nibbles, calculate_root, proof_decode, proof_encode, Nibble, TrieEntryVersion,
+};
+use crate::util;
+
+use alloc::{collections::BTreeMap, vec::Vec};
+use rand::{seq::IteratorRandom as _, Rng};
+
+/// Entries of a trie, indexed by key. Each entry contains a storage value and the version it was
+/// written with.
+pub type Entries = BTreeMap<Vec<u8>, (Vec<u8>, TrieEntryVersion)>;
+
+/// Prefix of the keys of the main trie under which the Merkle values of the child tries are
+/// stored.
+const CHILD_STORAGE_PREFIX: &[u8] = b":child_storage:default:";
+
+/// Node of a trie, as built by [`node_values`].
+#[derive(Debug, Clone)]
+pub struct NodeValue {
+    /// Full key of the node.
+    pub key: Vec<Nibble>,
+    /// Node value of the node.
+    pub node_value: Vec<u8>,
+    /// If the node value contains the hash of the storage value, contains the storage value.
+    pub unhashed_storage_value: Option<Vec<u8>>,
+}
+
+/// Returns the hash of the root of the trie containing the given entries.
+pub fn root_hash(entries: &Entries) -> [u8; 32] {
+    // The root node is always the last node returned by `node_values`.
+    let root_node_value = node_values(entries)
+        .pop()
+        .map_or_else(|| Vec::from([0]), |node| node.node_value);
+    blake2(&root_node_value)
+}
+
+/// Returns the list of all the nodes of the trie containing the given entries. Children are
+/// always before their parent, meaning that the root node is last. Returns an empty list if there
+/// is no entry.
+pub fn node_values(entries: &Entries) -> Vec<NodeValue> {
+    let entries = entries
+        .iter()
+        .map(|(key, (value, version))| {
+            (
+                bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>(),
+                &value[..],
+                *version,
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let mut out = Vec::new();
+    if !entries.is_empty() {
+        build_node(&entries, 0, &mut out);
+    }
+    out
+}
+
+/// Builds the node that is the closest common ancestor of all the given entries, whose keys all
+/// share their first `depth` nibbles, and its descendants. Returns the node value.
+fn build_node(
+    entries: &[(Vec<Nibble>, &[u8], TrieEntryVersion)],
+    depth: usize,
+    out: &mut Vec<NodeValue>,
+) -> Vec<u8> {
+    let first_key = &entries[0].0;
+    let key_len = entries.iter().fold(first_key.len(), |len, (key, _, _)| {
+        first_key[..len]
+            .iter()
+            .zip(key.iter())
+            .take_while(|(a, b)| a == b)
+            .count()
+    });
+    let key = &first_key[..key_len];
+
+    let storage_value = entries
+        .iter()
+        .find(|(k, _, _)| k.len() == key_len)
+        .map(|(_, value, version)| (*value, *version));
+    let hashed_storage_value = matches!(
+        storage_value,
+        Some((value, TrieEntryVersion::V1)) if value.len() >= 33
+    );
+
+    let mut children = Vec::new();
+    let mut children_bitmap = 0u16;
+    for nibble in 0..16u8 {
+        let child_entries = entries
+            .iter()
+            .filter(|(k, _, _)| k.len() > key_len && u8::from(k[key_len]) == nibble)
+            .cloned()
+            .collect::<Vec<_>>();
+        if child_entries.is_empty() {
+            continue;
+        }
+
+        children_bitmap |= 1 << nibble;
+        let child_node_value = build_node(&child_entries, key_len + 1, out);
+        children.push(if child_node_value.len() < 32 {
+            child_node_value
+        } else {
+            blake2(&child_node_value).to_vec()
+        });
+    }
+
+    let (header_prefix, header_bits) = match (children_bitmap != 0, storage_value) {
+        (_, Some(_)) if hashed_storage_value && children_bitmap != 0 => (0b0001_0000u8, 4),
+        (_, Some(_)) if hashed_storage_value => (0b0010_0000, 5),
+        (true, Some(_)) => (0b1100_0000, 6),
+        (false, Some(_)) => (0b0100_0000, 6),
+        (true, None) => (0b1000_0000, 6),
+        (false, None) => unreachable!(),
+    };
+
+    let mut node_value = Vec::new();
+
+    let partial_key = &key[depth..];
+    let max_in_header = (1usize << header_bits) - 1;
+    if partial_key.len() < max_in_header {
+        node_value.push(header_prefix | u8::try_from(partial_key.len()).unwrap());
+    } else {
+        node_value.push(header_prefix | u8::try_from(max_in_header).unwrap());
+        let mut remaining = partial_key.len() - max_in_header;
+        while remaining >= 255 {
+            node_value.push(255);
+            remaining -= 255;
+        }
+        node_value.push(u8::try_from(remaining).unwrap());
+    }
+
+    if partial_key.len() % 2 == 1 {
+        node_value.push(u8::from(partial_key[0]));
+    }
+    for pair in partial_key[partial_key.len() % 2..].chunks(2) {
+        node_value.push((u8::from(pair[0]) << 4) | u8::from(pair[1]));
+    }
+
+    if children_bitmap != 0 {
+        node_value.extend_from_slice(&children_bitmap.to_le_bytes());
+    }
+
+    if let Some((value, _)) = storage_value {
+        if hashed_storage_value {
+            node_value.extend_from_slice(&blake2(value));
+        } else {
+            node_value.extend_from_slice(util::encode_scale_compact_usize(value.len()).as_ref());
+            node_value.extend_from_slice(value);
+        }
+    }
+
+    for child in children {
+        node_value.extend_from_slice(util::encode_scale_compact_usize(child.len()).as_ref());
+        node_value.extend_from_slice(&child);
+    }
+
+    out.push(NodeValue {
+        key: key.to_vec(),
+        node_value: node_value.clone(),
+        unhashed_storage_value: storage_value
+            .filter(|_| hashed_storage_value)
+            .map(|(value, _)| value.to_vec()),
+    });
+
+    node_value
+}
+
+/// Inserts in `main_trie` the entry that stores the root hash of the child trie whose key is
+/// `child_trie` and whose entries are `child_entries`.
+pub fn insert_child_trie(main_trie: &mut Entries, child_trie: &[u8], child_entries: &Entries) {
+    let mut key = CHILD_STORAGE_PREFIX.to_vec();
+    key.extend_from_slice(child_trie);
+    main_trie.insert(
+        key,
+        (root_hash(child_entries).to_vec(), TrieEntryVersion::V1),
+    );
+}
+
+/// Generates a random list of entries.
+///
+/// The keys are generated in a way that makes them likely to share common prefixes. Some of the
+/// keys are long enough to require the extended encoding of the partial key length. The versions
+/// of the entries are randomly chosen between [`TrieEntryVersion::V0`] and
+/// [`TrieEntryVersion::V1`], and some of the storage values are long enough for the version to
+/// matter.
+pub fn random_entries(rng: &mut impl Rng) -> Entries {
+    let mut entries = Entries::new();
+
+    for _ in 0..rng.gen_range(0..32) {
+        let key_len = if rng.gen_ratio(1, 16) {
+            rng.gen_range(32..160)
+        } else {
+            rng.gen_range(0..6)
+        };
+        let key = (0..key_len)
+            .map(|_| rng.gen_range(0..4u8) * 0x11)
+            .collect::<Vec<_>>();
+
+        let value_len = rng.gen_range(0..64);
+        let value = (0..value_len).map(|_| rng.gen()).collect::<Vec<_>>();
+
+        let version = if rng.gen() {
+            TrieEntryVersion::V0
+        } else {
+            TrieEntryVersion::V1
+        };
+
+        entries.insert(key, (value, version));
+    }
+
+    entries
+}
+
+/// Error potentially returned by [`check`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum Mismatch {
+    /// [`calculate_root`] returned a root hash different from the reference.
+    #[display(fmt = "Root hash calculation mismatch")]
+    CalculateRoot,
+    /// [`proof_encode::ProofBuilder::trie_root_hash`] returned a root hash different from the
+    /// reference.
+    #[display(fmt = "Proof builder root hash mismatch")]
+    ProofBuilderRootHash,
+    /// Failed to decode a proof generated by [`proof_encode::ProofBuilder`].
+    #[display(fmt = "Failed to decode proof: {_0}")]
+    ProofDecode(proof_decode::Error),
+    /// The storage value found in a decoded proof doesn't match the reference.
+    #[display(fmt = "Storage value mismatch for key {key:?}")]
+    StorageValue {
+        /// Key whose storage value differs.
+        key: Vec<u8>,
+    },
+}
+
+/// Checks that [`calculate_root`], [`proof_encode`], and [`proof_decode`] behave the same way as
+/// the reference implementation for the trie containing the given entries.
+///
+/// A proof containing the entire trie is generated, then one proof for a randomly-chosen key.
+/// Both proofs are decoded and checked against the entries. `rng` is also used in order to
+/// generate keys that aren't in the trie.
+pub fn check(entries: &Entries, rng: &mut impl Rng) -> Result<(), Mismatch> {
+    let reference_root = root_hash(entries);
+
+    if calculate_root_hash(entries) != reference_root {
+        return Err(Mismatch::CalculateRoot);
+    }
+
+    // An empty trie can't be represented by the proof builder.
+    if entries.is_empty() {
+        return Ok(());
+    }
+
+    let nodes = node_values(entries);
+
+    // Proof containing all the nodes of the trie.
+    let full_proof = build_proof(nodes.iter(), &reference_root)?;
+    for (key, (value, _)) in entries {
+        if full_proof.storage_value(key).map(|v| v.map(|(v, _)| v)) != Some(Some(&value[..])) {
+            return Err(Mismatch::StorageValue { key: key.clone() });
+        }
+    }
+    for _ in 0..4 {
+        let key = (0..rng.gen_range(0..6))
+            .map(|_| rng.gen_range(0..5u8) * 0x11)
+            .collect::<Vec<_>>();
+        let expected = entries.get(&key).map(|(value, _)| &value[..]);
+        if full_proof.storage_value(&key).map(|v| v.map(|(v, _)| v)) != Some(expected) {
+            return Err(Mismatch::StorageValue { key });
+        }
+    }
+
+    // Proof containing only the nodes necessary to prove the storage value of one key.
+    let (key, (value, _)) = entries.iter().choose(rng).unwrap();
+    let key_nibbles = bytes_to_nibbles(key.iter().copied()).collect::<Vec<_>>();
+    let partial_proof = build_proof(
+        nodes
+            .iter()
+            .filter(|node| key_nibbles.starts_with(&node.key)),
+        &reference_root,
+    )?;
+    if partial_proof.storage_value(key).map(|v| v.map(|(v, _)| v)) != Some(Some(&value[..])) {
+        return Err(Mismatch::StorageValue { key: key.clone() });
+    }
+
+    Ok(())
+}
+
+fn calculate_root_hash(entries: &Entries) -> [u8; 32] {
+    let mut calculation = calculate_root::root_merkle_value(None);
+
+    loop {
+        match calculation {
+            calculate_root::RootMerkleValueCalculation::Finished { hash, .. } => break hash,
+            calculate_root::RootMerkleValueCalculation::AllKeys(keys) => {
+                calculation = keys.inject(entries.keys().map(|k| k.iter().copied()));
+            }
+            calculate_root::RootMerkleValueCalculation::StorageValue(value) => {
+                let key = value.key().collect::<Vec<_>>();
+                calculation = value.inject(entries.get(&key).map(|(v, version)| (v, *version)));
+            }
+        }
+    }
+}
+
+fn build_proof<'a>(
+    nodes: impl Iterator<Item = &'a NodeValue>,
+    reference_root: &[u8; 32],
+) -> Result<proof_decode::DecodedTrieProof<Vec<u8>>, Mismatch> {
+    let mut builder = proof_encode::ProofBuilder::new();
+    for node in nodes {
+        builder.set_node_value(
+            &node.key,
+            &node.node_value,
+            node.unhashed_storage_value.as_deref(),
+        );
+    }
+
+    if builder.trie_root_hash() != Some(*reference_root) {
+        return Err(Mismatch::ProofBuilderRootHash);
+    }
+
+    proof_decode::decode_and_verify_proof(proof_decode::Config {
+        trie_root_hash: reference_root,
+        proof: builder.build_to_vec(),
+    })
+    .map_err(Mismatch::ProofDecode)
+}
+
+fn blake2(data: &[u8]) -> [u8; 32] {
+    <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], data).as_bytes()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Entries, TrieEntryVersion};
+
+    #[test]
+    fn empty_trie_matches() {
+        assert_eq!(
+            super::root_hash(&Entries::new()),
+            super::super::empty_trie_merkle_value()
+        );
+    }
+
+    #[test]
+    fn random_tries_match() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..512 {
+            let entries = super::random_entries(&mut rng);
+            super::check(&entries, &mut rng).unwrap();
+        }
+    }
+
+    #[test]
+    fn random_tries_with_child_tries_match() {
+        let mut rng = rand::thread_rng();
+        for _ in 0..64 {
+            let mut main_trie = super::random_entries(&mut rng);
+            for child_trie in [&b"foo"[..], b"bar"] {
+                let child_entries = super::random_entries(&mut rng);
+                super::check(&child_entries, &mut rng).unwrap();
+                super::insert_child_trie(&mut main_trie, child_trie, &child_entries);
+            }
+            super::check(&main_trie, &mut rng).unwrap();
+        }
+    }
+
+    #[test]
+    fn versions_mix() {
+        let mut entries = Entries::new();
+        entries.insert(b"a".to_vec(), (vec![1; 40], TrieEntryVersion::V0));
+        entries.insert(b"ab".to_vec(), (vec![2; 40], TrieEntryVersion::V1));
+        entries.insert(b"abc".to_vec(), (vec![3; 8], TrieEntryVersion::V1));
+
+        let all_v0 = entries
+            .iter()
+            .map(|(k, (v, _))| (k.clone(), (v.clone(), TrieEntryVersion::V0)))
+            .collect::<Entries>();
+        assert_ne!(super::root_hash(&entries), super::root_hash(&all_v0));
+
+        super::check(&entries, &mut rand::thread_rng()).unwrap();
+        super::check(&all_v0, &mut rand::thread_rng()).unwrap();
+    }
+}
@@ -293,35 +293,36 @@ impl fmt::Debug for MerkleValueOutput {
 /// Decodes a node value found in a proof into its components.
 ///
 /// This can decode nodes no matter their version.
+#[deny(clippy::arithmetic_side_effects)] // Decodes untrusted data, which must never cause a panic.
 pub fn decode(mut node_value: &'_ [u8]) -> Result<Decoded<DecodedPartialKey<'_>, &'_ [u8]>, Error> {
     if node_value.is_empty() {
         return Err(Error::Empty);
     }
 
     // See https://spec.polkadot.network/#defn-node-header
-    let (has_children, storage_value_hashed, pk_len_first_byte_bits) = match node_value[0] >> 6 {
+    let (has_children, storage_value_hashed, pk_len_first_byte_mask) = match node_value[0] >> 6 {
         0b00 => {
             if (node_value[0] >> 5) == 0b001 {
-                (false, Some(true), 5)
+                (false, Some(true), 0b1_1111)
             } else if (node_value[0] >> 4) == 0b0001 {
-                (true, Some(true), 4)
+                (true, Some(true), 0b1111)
             } else if node_value[0] == 0 {
-                (false, None, 6)
+                (false, None, 0b11_1111)
             } else {
                 return Err(Error::InvalidHeaderBits);
             }
         }
-        0b10 => (true, None, 6),
-        0b01 => (false, Some(false), 6),
-        0b11 => (true, Some(false), 6),
+        0b10 => (true, None, 0b11_1111),
+        0b01 => (false, Some(false), 0b11_1111),
+        0b11 => (true, Some(false), 0b11_1111),
         _ => unreachable!(),
     };
 
     // Length of the partial key, in nibbles.
     let pk_len = {
-        let mut accumulator = usize::from(node_value[0] & ((1 << pk_len_first_byte_bits) - 1));
+        let mut accumulator = usize::from(node_value[0] & pk_len_first_byte_mask);
         node_value = &node_value[1..];
-        let mut continue_iter = accumulator == ((1 << pk_len_first_byte_bits) - 1);
+        let mut continue_iter = accumulator == usize::from(pk_len_first_byte_mask);
         while continue_iter {
             if node_value.is_empty() {
                 return Err(Error::PartialKeyLenTooShort);
@@ -343,12 +344,9 @@ pub fn decode(mut node_value: &'_ [u8]) -> Result<Decoded<DecodedPartialKey<'_>,
 
     // Iterator to the partial key found in the node value of `proof_iter`.
     let partial_key = {
-        // Length of the partial key, in bytes.
-        let pk_len_bytes = if pk_len == 0 {
-            0
-        } else {
-            1 + ((pk_len - 1) / 2)
-        };
+        // Length of the partial key, in bytes. Saturating is fine, as a partial key of this
+        // length can't fit in `node_value` anyway.
+        let pk_len_bytes = pk_len.saturating_add(1) / 2;
         if node_value.len() < pk_len_bytes {
             return Err(Error::PartialKeyTooShort);
         }
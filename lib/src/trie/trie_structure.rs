@@ -23,7 +23,7 @@
 use super::nibble::Nibble;
 
 use alloc::{borrow::ToOwned as _, vec, vec::Vec};
-use core::{fmt, iter, mem};
+use core::{fmt, iter, mem, ops};
 use either::Either;
 use slab::Slab;
 
@@ -166,6 +166,11 @@ impl<TUd> TrieStructure<TUd> {
         self.nodes.iter().map(|(k, _)| NodeIndex(k))
     }
 
+    /// Returns a list of all nodes in the structure, ordered by key in lexicographic order.
+    pub fn iter_ordered(&'_ self) -> impl Iterator<Item = NodeIndex> + '_ {
+        self.all_nodes_ordered().map(NodeIndex)
+    }
+
     /// Returns the root node of the trie, or `None` if the trie is empty.
     ///
     /// # Examples
@@ -682,6 +687,17 @@ impl<TUd> TrieStructure<TUd> {
         }
     }
 
+    /// Returns whether the node at the given index is a storage node, or `None` if no such node
+    /// exists.
+    ///
+    /// This method is a shortcut for [`TrieStructure::node_by_index`] followed with
+    /// [`NodeAccess::has_storage_value`].
+    pub fn node_has_storage_value_by_index(&self, node_index: NodeIndex) -> Option<bool> {
+        self.nodes
+            .get(node_index.0)
+            .map(|node| node.has_storage_value)
+    }
+
     /// Returns the key of the node at the given index, or `None` if no such node exists.
     ///
     /// This method is a shortcut for [`TrieStructure::node_by_index`] followed with
@@ -770,6 +786,20 @@ impl<TUd> TrieStructure<TUd> {
     }
 }
 
+impl<TUd> ops::Index<NodeIndex> for TrieStructure<TUd> {
+    type Output = TUd;
+
+    /// Returns the user data of the node at the given index.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `node_index` is not a valid index.
+    ///
+    fn index(&self, node_index: NodeIndex) -> &TUd {
+        &self.nodes[node_index.0].user_data
+    }
+}
+
 impl<TUd> Default for TrieStructure<TUd> {
     fn default() -> Self {
         Self::new()
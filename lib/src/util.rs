@@ -15,10 +15,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-//! Internal module. Contains functions that aren't Substrate/Polkadot-specific and should ideally
-//! be found in third party libraries, but that aren't worth a third-party library.
+//! Contains functions that aren't Substrate/Polkadot-specific and should ideally be found in
+//! third party libraries, but that aren't worth a third-party library.
 
-use core::{cmp, str};
+use core::{cmp, hash::Hasher as _, str};
 
 pub(crate) mod leb128;
 pub(crate) mod protobuf;
@@ -43,6 +43,28 @@ impl core::hash::BuildHasher for SipHasherBuild {
     }
 }
 
+/// Returns the `twox64` hash of the given data, as used by the `Twox64Concat` storage hasher of
+/// Substrate.
+pub(crate) fn twox_64(data: &[u8]) -> [u8; 8] {
+    let mut h0 = twox_hash::XxHash::with_seed(0);
+    h0.write(data);
+    h0.finish().to_le_bytes()
+}
+
+/// Returns the `twox128` hash of the given data, as used for the prefixes of the storage keys of
+/// the runtime pallets.
+pub(crate) fn twox_128(data: &[u8]) -> [u8; 16] {
+    let mut h0 = twox_hash::XxHash::with_seed(0);
+    let mut h1 = twox_hash::XxHash::with_seed(1);
+    h0.write(data);
+    h1.write(data);
+
+    let mut out = [0; 16];
+    out[..8].copy_from_slice(&h0.finish().to_le_bytes());
+    out[8..].copy_from_slice(&h1.finish().to_le_bytes());
+    out
+}
+
 /// Returns a parser that decodes a SCALE-encoded `Option`.
 ///
 /// > **Note**: When using this function outside of a `nom` "context", you might have to explicit
@@ -243,11 +265,12 @@ macro_rules! decode_scale_compact {
 
 decode_scale_compact!(nom_scale_compact_usize, usize);
 decode_scale_compact!(nom_scale_compact_u64, u64);
+decode_scale_compact!(nom_scale_compact_u128, u128);
 
 macro_rules! encode_scale_compact {
     ($fn_name:ident, $num_ty:ty) => {
         /// Returns a buffer containing the SCALE-compact encoding of the parameter.
-        pub(crate) fn $fn_name(mut value: $num_ty) -> impl AsRef<[u8]> + Clone {
+        pub fn $fn_name(mut value: $num_ty) -> impl AsRef<[u8]> + Clone {
             const MAX_BITS: usize = 1 + (<$num_ty>::BITS as usize) / 8;
             let mut array = arrayvec::ArrayVec::<u8, MAX_BITS>::new();
 
@@ -66,8 +66,56 @@
 //! happen, and many valid blocks don't get finalized.
 //!
 
+use alloc::sync::Arc;
+use core::{fmt, time::Duration};
+
 pub mod aura;
 pub mod babe;
+pub mod clock_skew;
 pub mod header_body;
 pub mod header_only;
 pub mod inherents;
+
+/// Default value for the maximum allowed difference between the local clock and the clock of
+/// the authority that created a block. See [`aura::VerifyConfig::clock_drift_tolerance`].
+pub const DEFAULT_CLOCK_DRIFT_TOLERANCE: Duration = Duration::from_secs(30);
+
+/// Source of the current time used when verifying blocks.
+///
+/// Verifying a block requires knowing the current time, in order to reject blocks whose slot is
+/// in the future, and in order to provide the value of the timestamp inherent when executing
+/// blocks. Rather than directly querying the time of the operating system, which isn't available
+/// in a `no_std` environment, the time is obtained from this object.
+///
+/// Use [`Clock::fixed`] in order to verify blocks in a reproducible way, for example when
+/// replaying historical blocks or in tests.
+#[derive(Clone)]
+pub struct Clock {
+    now_from_unix_epoch: Arc<dyn Fn() -> Duration + Send + Sync>,
+}
+
+impl Clock {
+    /// Builds a new [`Clock`] that calls the given function every time the current time is
+    /// needed. The function must return the time elapsed since the UNIX epoch.
+    pub fn new(now_from_unix_epoch: impl Fn() -> Duration + Send + Sync + 'static) -> Self {
+        Clock {
+            now_from_unix_epoch: Arc::new(now_from_unix_epoch),
+        }
+    }
+
+    /// Builds a new [`Clock`] that always returns the given time since the UNIX epoch.
+    pub fn fixed(now_from_unix_epoch: Duration) -> Self {
+        Self::new(move || now_from_unix_epoch)
+    }
+
+    /// Returns the time elapsed since the UNIX epoch.
+    pub fn now_from_unix_epoch(&self) -> Duration {
+        (self.now_from_unix_epoch)()
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Clock").finish()
+    }
+}
@@ -128,9 +128,18 @@
 //! height performing epoch transitions.
 //!
 //! See also the [`crate::chain::chain_information`] module for more help.
+//!
+//! # VRF cache
+//!
+//! Verifying the VRF output and proof of a block is the most CPU-intensive part of the
+//! verification of a header. Because the same header might be verified multiple times, for
+//! example when it is part of multiple forks being verified, a [`VrfCache`] can optionally be
+//! passed through [`VerifyConfig::vrf_cache`] in order to remember the outcome of the VRF
+//! verifications that have already been performed.
 
 use crate::{chain::chain_information, header};
 
+use alloc::collections::VecDeque;
 use core::{num::NonZeroU64, time::Duration};
 use num_traits::{cast::ToPrimitive as _, identities::One as _};
 
@@ -169,6 +178,87 @@ pub struct VerifyConfig<'a> {
     /// The [`chain_information::BabeEpochInformationRef::start_slot_number`] must be `None` if
     /// and only if the [`chain_information::BabeEpochInformationRef::epoch_index`] is `0`.
     pub parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+    /// Cache of the outcome of previous VRF verifications. If `None`, the VRF output and proof
+    /// of the block, if any, are always verified.
+    pub vrf_cache: Option<&'a mut VrfCache>,
+
+    /// If `true`, the signature found in the seal of the header and the VRF proof found in the
+    /// header aren't verified, and consequently primary slot claims aren't compared with the
+    /// primary slot claim threshold. All the other checks are still performed.
+    ///
+    /// > **Note**: This is meant to be used when benchmarking, or when importing blocks that are
+    /// >           already known to be valid. Passing `true` makes it possible for anyone to
+    /// >           author blocks that are considered as valid.
+    pub skip_seal_verification: bool,
+}
+
+/// Cache of the outcome of VRF verifications. See [the module-level documentation](..).
+///
+/// The cache has a fixed capacity. Once full, inserting a new entry removes the oldest one.
+#[derive(Debug, Clone)]
+pub struct VrfCache {
+    /// Outcome of each verification. Contains `None` if the VRF proof is invalid, or the first
+    /// bytes of the VRF output to compare with the primary slot claim threshold.
+    entries: hashbrown::HashMap<VrfCacheKey, Option<u128>, fnv::FnvBuildHasher>,
+    /// Keys of [`VrfCache::entries`], from the oldest to the most recently inserted.
+    insertion_order: VecDeque<VrfCacheKey>,
+    /// Maximum number of entries in the cache.
+    capacity: usize,
+}
+
+/// Everything the outcome of a VRF verification depends on.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct VrfCacheKey {
+    authority_public_key: [u8; 32],
+    slot_number: u64,
+    epoch_index: u64,
+    randomness: [u8; 32],
+    vrf_output: [u8; 32],
+    vrf_proof: [u8; 64],
+}
+
+impl VrfCache {
+    /// Builds a new empty cache that can contain up to `capacity` entries.
+    pub fn new(capacity: usize) -> Self {
+        VrfCache {
+            entries: hashbrown::HashMap::with_capacity_and_hasher(capacity, Default::default()),
+            insertion_order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns the number of entries in the cache.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Returns `true` if the cache is empty.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Removes all the entries of the cache.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.insertion_order.clear();
+    }
+
+    fn insert(&mut self, key: VrfCacheKey, outcome: Option<u128>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if self.entries.len() >= self.capacity {
+            if let Some(oldest) = self.insertion_order.pop_front() {
+                self.entries.remove(&oldest);
+            }
+        }
+
+        if self.entries.insert(key.clone(), outcome).is_none() {
+            self.insertion_order.push_back(key);
+        }
+    }
 }
 
 /// Information yielded back after successfully verifying a block.
@@ -410,32 +500,70 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
         schnorrkel::PublicKey::from_bytes(signing_authority.public_key).unwrap();
 
     // Now verifying the signature in the seal.
-    signing_public_key
-        .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
-        .map_err(|_| VerifyError::BadSignature)?;
+    if !config.skip_seal_verification {
+        signing_public_key
+            .verify_simple(b"substrate", &pre_seal_hash, &seal_signature)
+            .map_err(|_| VerifyError::BadSignature)?;
+    }
 
     // Now verify the VRF output and proof, if any.
     // The lack of VRF output/proof in the header is checked when we check whether the slot
     // type is allowed by the current configuration.
-    if let Some((vrf_output, vrf_proof)) = vrf_output_and_proof {
-        // In order to verify the VRF output, we first need to create a transcript containing all
-        // the data to verify the VRF against.
-        let transcript = {
-            let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
-            transcript.append_u64(b"slot number", slot_number);
-            transcript.append_u64(b"current epoch", block_epoch_info.epoch_index);
-            transcript.append_message(b"chain randomness", &block_epoch_info.randomness[..]);
-            transcript
+    if let (Some((vrf_output, vrf_proof)), false) =
+        (vrf_output_and_proof, config.skip_seal_verification)
+    {
+        let cache_key = VrfCacheKey {
+            authority_public_key: *signing_authority.public_key,
+            slot_number,
+            epoch_index: block_epoch_info.epoch_index,
+            randomness: *block_epoch_info.randomness,
+            vrf_output,
+            vrf_proof,
         };
 
-        // These `unwrap()`s can only panic if `vrf_output` or `vrf_proof` are of the wrong
-        // length, which we know can't happen as they're of types `[u8; 32]` and `[u8; 64]`.
-        let vrf_output = schnorrkel::vrf::VRFPreOut::from_bytes(&vrf_output[..]).unwrap();
-        let vrf_proof = schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]).unwrap();
+        let cached = config
+            .vrf_cache
+            .as_ref()
+            .and_then(|cache| cache.entries.get(&cache_key).copied());
+
+        let vrf_value = match cached {
+            Some(outcome) => outcome,
+            None => {
+                // In order to verify the VRF output, we first need to create a transcript
+                // containing all the data to verify the VRF against.
+                let transcript = {
+                    let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+                    transcript.append_u64(b"slot number", slot_number);
+                    transcript.append_u64(b"current epoch", block_epoch_info.epoch_index);
+                    transcript
+                        .append_message(b"chain randomness", &block_epoch_info.randomness[..]);
+                    transcript
+                };
+
+                // These `unwrap()`s can only panic if `vrf_output` or `vrf_proof` are of the
+                // wrong length, which we know can't happen as they're of types `[u8; 32]` and
+                // `[u8; 64]`.
+                let vrf_output = schnorrkel::vrf::VRFPreOut::from_bytes(&vrf_output[..]).unwrap();
+                let vrf_proof = schnorrkel::vrf::VRFProof::from_bytes(&vrf_proof[..]).unwrap();
+
+                let outcome = signing_public_key
+                    .vrf_verify(transcript, &vrf_output, &vrf_proof)
+                    .ok()
+                    .map(|(vrf_in_out, _)| {
+                        u128::from_le_bytes(
+                            vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"),
+                        )
+                    });
+
+                if let Some(cache) = config.vrf_cache {
+                    cache.insert(cache_key, outcome);
+                }
+
+                outcome
+            }
+        };
 
-        let (vrf_in_out, _) = signing_public_key
-            .vrf_verify(transcript, &vrf_output, &vrf_proof)
-            .map_err(|_| VerifyError::BadVrfProof)?;
+        let vrf_value = vrf_value.ok_or(VerifyError::BadVrfProof)?;
 
         // If this is a primary slot claim, we need to make sure that the VRF output is below
         // a certain threshold, otherwise all the authorities could claim all the slots.
@@ -445,14 +573,12 @@ pub fn verify_header(config: VerifyConfig) -> Result<VerifySuccess, VerifyError>
                 block_epoch_info.authorities.clone().map(|a| a.weight),
                 signing_authority.weight,
             );
-            if u128::from_le_bytes(vrf_in_out.make_bytes::<[u8; 16]>(b"substrate-babe-vrf"))
-                >= threshold
-            {
+            if vrf_value >= threshold {
                 return Err(VerifyError::OverPrimaryClaimThreshold);
             }
         }
     } else {
-        debug_assert!(!primary_slot_claim);
+        debug_assert!(!primary_slot_claim || config.skip_seal_verification);
     }
 
     // Each slot can be claimed by one specific authority in what is called a secondary slot
@@ -521,3 +647,176 @@ fn calculate_primary_threshold(
         .to_u128()
         .unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{verify_header, VerifyConfig, VerifyError, VrfCache};
+    use crate::{chain::chain_information, header};
+    use core::{num::NonZeroU64, time::Duration};
+
+    struct Fixture {
+        parent: header::Header,
+        child: header::Header,
+        epoch: chain_information::BabeEpochInformation,
+        next_epoch: chain_information::BabeEpochInformation,
+    }
+
+    /// Builds a block #2 that uses a secondary VRF slot claim, authored by the single authority
+    /// of the epoch. If `corrupt_vrf` is `true`, the VRF proof is replaced with one built against
+    /// a different slot number, and is thus invalid.
+    fn fixture(corrupt_vrf: bool) -> Fixture {
+        let authority = schnorrkel::MiniSecretKey::from_bytes(&[1; 32])
+            .unwrap()
+            .expand_to_keypair(schnorrkel::ExpansionMode::Ed25519);
+
+        let epoch = chain_information::BabeEpochInformation {
+            epoch_index: 0,
+            start_slot_number: Some(1),
+            authorities: vec![header::BabeAuthority {
+                public_key: authority.public.to_bytes(),
+                weight: 1,
+            }],
+            randomness: [7; 32],
+            c: (1, 4),
+            allowed_slots: header::BabeAllowedSlots::PrimaryAndSecondaryVrfSlots,
+        };
+        let next_epoch = chain_information::BabeEpochInformation {
+            epoch_index: 1,
+            start_slot_number: Some(101),
+            ..epoch.clone()
+        };
+
+        let parent = header::Header {
+            parent_hash: [0; 32],
+            number: 1,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::from_slice(&[header::DigestItem::BabePreDigest(
+                header::BabePreDigest::SecondaryPlain(header::BabeSecondaryPlainPreDigest {
+                    authority_index: 0,
+                    slot_number: 1,
+                }),
+            )])
+            .unwrap()
+            .into(),
+        };
+
+        let (vrf_in_out, vrf_proof, _) = authority.vrf_sign({
+            let mut transcript = merlin::Transcript::new(&b"BABE"[..]);
+            transcript.append_u64(b"slot number", if corrupt_vrf { 3 } else { 2 });
+            transcript.append_u64(b"current epoch", epoch.epoch_index);
+            transcript.append_message(b"chain randomness", &epoch.randomness[..]);
+            transcript
+        });
+
+        let mut child = header::Header {
+            parent_hash: parent.hash(4),
+            number: 2,
+            state_root: [0; 32],
+            extrinsics_root: [0; 32],
+            digest: header::DigestRef::from_slice(&[header::DigestItem::BabePreDigest(
+                header::BabePreDigest::SecondaryVRF(header::BabeSecondaryVRFPreDigest {
+                    authority_index: 0,
+                    slot_number: 2,
+                    vrf_output: vrf_in_out.to_preout().to_bytes(),
+                    vrf_proof: vrf_proof.to_bytes(),
+                }),
+            )])
+            .unwrap()
+            .into(),
+        };
+        let signature = authority
+            .sign_simple(b"substrate", &child.hash(4))
+            .to_bytes();
+        child.digest.push_babe_seal(signature).unwrap();
+
+        Fixture {
+            parent,
+            child,
+            epoch,
+            next_epoch,
+        }
+    }
+
+    fn verify(fixture: &Fixture, vrf_cache: Option<&mut VrfCache>) -> Result<(), VerifyError> {
+        verify_header(VerifyConfig {
+            header: (&fixture.child).into(),
+            block_number_bytes: 4,
+            parent_block_header: (&fixture.parent).into(),
+            now_from_unix_epoch: Duration::new(0, 0),
+            slots_per_epoch: NonZeroU64::new(100).unwrap(),
+            parent_block_epoch: Some((&fixture.epoch).into()),
+            parent_block_next_epoch: (&fixture.next_epoch).into(),
+            vrf_cache,
+            skip_seal_verification: false,
+        })
+        .map(|_| ())
+    }
+
+    #[test]
+    fn valid_vrf_without_cache() {
+        assert!(verify(&fixture(false), None).is_ok());
+    }
+
+    #[test]
+    fn invalid_vrf_without_cache() {
+        assert!(matches!(
+            verify(&fixture(true), None),
+            Err(VerifyError::BadVrfProof)
+        ));
+    }
+
+    #[test]
+    fn cache_miss_then_hit() {
+        let fixture = fixture(false);
+        let mut cache = VrfCache::new(4);
+
+        // Miss: the outcome is verified then inserted.
+        assert!(verify(&fixture, Some(&mut cache)).is_ok());
+        assert_eq!(cache.len(), 1);
+
+        // Hit: the cached outcome is used. Overwriting it with an invalid outcome proves that
+        // the VRF proof isn't verified again.
+        assert!(verify(&fixture, Some(&mut cache)).is_ok());
+        assert_eq!(cache.len(), 1);
+        for outcome in cache.entries.values_mut() {
+            *outcome = None;
+        }
+        assert!(matches!(
+            verify(&fixture, Some(&mut cache)),
+            Err(VerifyError::BadVrfProof)
+        ));
+    }
+
+    #[test]
+    fn invalid_vrf_cached() {
+        let fixture = fixture(true);
+        let mut cache = VrfCache::new(4);
+
+        assert!(matches!(
+            verify(&fixture, Some(&mut cache)),
+            Err(VerifyError::BadVrfProof)
+        ));
+        assert_eq!(cache.len(), 1);
+        assert!(cache.entries.values().all(|outcome| outcome.is_none()));
+
+        assert!(matches!(
+            verify(&fixture, Some(&mut cache)),
+            Err(VerifyError::BadVrfProof)
+        ));
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn cache_capacity() {
+        let mut cache = VrfCache::new(1);
+        assert!(verify(&fixture(false), Some(&mut cache)).is_ok());
+        assert!(verify(&fixture(true), Some(&mut cache)).is_err());
+        assert_eq!(cache.len(), 1);
+        assert!(cache.entries.values().all(|outcome| outcome.is_none()));
+
+        let mut cache = VrfCache::new(0);
+        assert!(verify(&fixture(false), Some(&mut cache)).is_ok());
+        assert!(cache.is_empty());
+    }
+}
@@ -0,0 +1,296 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Estimation of the offset of the local clock compared to the clock of the rest of the network.
+//!
+//! Verifying a block requires knowing the current time, in order to reject blocks whose slot is
+//! in the future. If the local clock is too far behind, valid blocks are considered as coming
+//! from the future and are rejected. This module makes it possible to estimate by how much the
+//! local clock is off without relying on an external time server.
+//!
+//! # Details
+//!
+//! The networking protocols don't carry any timestamp. Instead, the estimation is based on the
+//! slot numbers found in the headers of the blocks that peers announce, or that they report as
+//! their best block when connecting.
+//!
+//! A block is produced during its slot, and can only be received afterwards. As such, the
+//! difference between the start of the slot of a block and the local time at which this block
+//! is received is, ignoring the propagation delay, the offset of the local clock. Each source
+//! contributes the sample corresponding to the latest block it has announced.
+//!
+//! Sources are untrusted, and an attacker can easily create many sources. For this reason, an
+//! estimate is only reported if the samples corroborate each other: a majority of the samples,
+//! and at least [`Config::min_sources`] of them, must be within [`Config::max_spread`] of the
+//! median, and at least [`Config::min_trusted_sources`] of these samples must come from sources
+//! marked as trusted. The estimated offset is the median of these agreeing samples.
+//!
+//! Samples whose absolute value is greater than [`Config::max_offset`] are discarded. This
+//! makes it possible to ignore sources that are still syncing and announce old blocks, and
+//! bounds the influence that malicious sources can have.
+
+use alloc::vec::Vec;
+use core::{num::NonZeroU64, time::Duration};
+
+/// Configuration for a [`ClockSkewEstimator`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Maximum number of sources whose sample is kept. Samples of new sources are ignored once
+    /// this limit is reached.
+    pub max_sources: usize,
+
+    /// Minimum number of sources whose samples agree with each other before an estimate is
+    /// reported.
+    pub min_sources: usize,
+
+    /// Minimum number of trusted sources, among the sources whose samples agree with each other,
+    /// before an estimate is reported.
+    pub min_trusted_sources: usize,
+
+    /// Maximum difference between a sample and the median of all the samples for this sample to
+    /// be considered as agreeing with the others.
+    pub max_spread: Duration,
+
+    /// Samples whose absolute value is above this duration are discarded.
+    pub max_offset: Duration,
+}
+
+/// Collection of samples, one per source, of the offset of the local clock.
+///
+/// See [the module-level documentation](..).
+#[derive(Debug, Clone)]
+pub struct ClockSkewEstimator<TSrc> {
+    /// Latest sample of each source, in milliseconds, and whether the source is trusted.
+    /// Samples are positive if the local clock is behind.
+    samples: Vec<(TSrc, i64, bool)>,
+
+    /// See [`Config::max_sources`].
+    max_sources: usize,
+
+    /// See [`Config::min_sources`].
+    min_sources: usize,
+
+    /// See [`Config::min_trusted_sources`].
+    min_trusted_sources: usize,
+
+    /// See [`Config::max_spread`], in milliseconds.
+    max_spread_ms: i64,
+
+    /// See [`Config::max_offset`], in milliseconds.
+    max_offset_ms: i64,
+}
+
+impl<TSrc: PartialEq> ClockSkewEstimator<TSrc> {
+    /// Initializes a new estimator with no sample.
+    pub fn new(config: Config) -> Self {
+        ClockSkewEstimator {
+            samples: Vec::with_capacity(config.max_sources),
+            max_sources: config.max_sources,
+            min_sources: config.min_sources,
+            min_trusted_sources: config.min_trusted_sources,
+            max_spread_ms: i64::try_from(config.max_spread.as_millis()).unwrap_or(i64::MAX),
+            max_offset_ms: i64::try_from(config.max_offset.as_millis()).unwrap_or(i64::MAX),
+        }
+    }
+
+    /// Registers the fact that `source` has announced a block whose slot starts at
+    /// `slot_start_from_unix_epoch`, and that this announce has been received when the local
+    /// clock indicated `received_from_unix_epoch`.
+    ///
+    /// `is_trusted` indicates whether the source counts towards [`Config::min_trusted_sources`].
+    ///
+    /// Replaces the sample previously provided by the same source, if any.
+    pub fn insert_sample(
+        &mut self,
+        source: TSrc,
+        is_trusted: bool,
+        slot_start_from_unix_epoch: Duration,
+        received_from_unix_epoch: Duration,
+    ) {
+        let sample = i64::try_from(slot_start_from_unix_epoch.as_millis())
+            .unwrap_or(i64::MAX)
+            .saturating_sub(
+                i64::try_from(received_from_unix_epoch.as_millis()).unwrap_or(i64::MAX),
+            );
+
+        if sample.saturating_abs() > self.max_offset_ms {
+            return;
+        }
+
+        if let Some(entry) = self.samples.iter_mut().find(|(s, _, _)| *s == source) {
+            entry.1 = sample;
+            entry.2 = is_trusted;
+        } else if self.samples.len() < self.max_sources {
+            self.samples.push((source, sample, is_trusted));
+        }
+    }
+
+    /// Removes the sample provided by the given source, if any. Should be called when a source
+    /// disconnects.
+    pub fn remove_source(&mut self, source: &TSrc) {
+        self.samples.retain(|(s, _, _)| s != source);
+    }
+
+    /// Returns the estimated offset of the local clock, in milliseconds. Positive if the local
+    /// clock is behind, negative if it is ahead.
+    ///
+    /// Returns `None` if the samples don't corroborate each other. See
+    /// [the module-level documentation](..).
+    pub fn estimated_offset_ms(&self) -> Option<i64> {
+        if self.samples.is_empty() || self.samples.len() < self.min_sources {
+            return None;
+        }
+
+        let mut samples = self
+            .samples
+            .iter()
+            .map(|(_, s, trusted)| (*s, *trusted))
+            .collect::<Vec<_>>();
+        samples.sort_unstable_by_key(|(s, _)| *s);
+        let median = samples[samples.len() / 2].0;
+
+        samples.retain(|(s, _)| s.abs_diff(median) <= self.max_spread_ms.unsigned_abs());
+        if samples.len() < self.min_sources
+            || samples.len() * 2 <= self.samples.len()
+            || samples.iter().filter(|(_, trusted)| *trusted).count() < self.min_trusted_sources
+        {
+            return None;
+        }
+
+        Some(samples[samples.len() / 2].0)
+    }
+}
+
+/// Returns the time, since the Unix epoch, at which the given Aura slot starts.
+pub fn aura_slot_start(slot_number: u64, slot_duration: NonZeroU64) -> Duration {
+    Duration::from_millis(slot_number.saturating_mul(slot_duration.get()))
+}
+
+/// Applies an offset, as returned by [`ClockSkewEstimator::estimated_offset_ms`], to the given
+/// value of the local clock.
+pub fn apply_offset(local_now_from_unix_epoch: Duration, offset_ms: i64) -> Duration {
+    let offset = Duration::from_millis(offset_ms.unsigned_abs());
+    if offset_ms >= 0 {
+        local_now_from_unix_epoch.saturating_add(offset)
+    } else {
+        local_now_from_unix_epoch.saturating_sub(offset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    fn estimator() -> super::ClockSkewEstimator<u32> {
+        super::ClockSkewEstimator::new(super::Config {
+            max_sources: 8,
+            min_sources: 3,
+            min_trusted_sources: 0,
+            max_spread: Duration::from_secs(6),
+            max_offset: Duration::from_secs(120),
+        })
+    }
+
+    #[test]
+    fn median_ignores_outliers() {
+        let mut estimator = estimator();
+        let received = Duration::from_secs(1_000_000);
+
+        estimator.insert_sample(0, false, received + Duration::from_secs(60), received);
+        assert_eq!(estimator.estimated_offset_ms(), None);
+        estimator.insert_sample(1, false, received + Duration::from_secs(59), received);
+        estimator.insert_sample(2, false, received + Duration::from_secs(61), received);
+        assert_eq!(estimator.estimated_offset_ms(), Some(60_000));
+
+        // Source announcing an old block is ignored.
+        estimator.insert_sample(3, false, received - Duration::from_secs(3600), received);
+        assert_eq!(estimator.estimated_offset_ms(), Some(60_000));
+
+        // Source announcing a block from the future is present but doesn't agree.
+        estimator.insert_sample(4, false, received + Duration::from_secs(100), received);
+        assert_eq!(estimator.estimated_offset_ms(), Some(60_000));
+
+        estimator.remove_source(&2);
+        assert_eq!(estimator.estimated_offset_ms(), None);
+        estimator.insert_sample(5, false, received + Duration::from_secs(62), received);
+        assert_eq!(estimator.estimated_offset_ms(), Some(60_000));
+    }
+
+    #[test]
+    fn max_offset_bound() {
+        let mut estimator = estimator();
+        let received = Duration::from_secs(1_000_000);
+
+        for source in 0..5 {
+            estimator.insert_sample(source, false, received + Duration::from_secs(600), received);
+        }
+        assert_eq!(estimator.estimated_offset_ms(), None);
+    }
+
+    #[test]
+    fn no_majority() {
+        let mut estimator = estimator();
+        let received = Duration::from_secs(1_000_000);
+
+        // Two groups of three sources that disagree with each other.
+        for source in 0..3 {
+            estimator.insert_sample(source, false, received + Duration::from_secs(90), received);
+        }
+        assert_eq!(estimator.estimated_offset_ms(), Some(90_000));
+        for source in 3..6 {
+            estimator.insert_sample(source, false, received - Duration::from_secs(90), received);
+        }
+        assert_eq!(estimator.estimated_offset_ms(), None);
+    }
+
+    #[test]
+    fn trusted_sources_required() {
+        let mut estimator = super::ClockSkewEstimator::new(super::Config {
+            max_sources: 8,
+            min_sources: 3,
+            min_trusted_sources: 1,
+            max_spread: Duration::from_secs(6),
+            max_offset: Duration::from_secs(120),
+        });
+        let received = Duration::from_secs(1_000_000);
+
+        for source in 0..5 {
+            estimator.insert_sample(source, false, received + Duration::from_secs(90), received);
+        }
+        assert_eq!(estimator.estimated_offset_ms(), None);
+
+        // A trusted source that doesn't agree with the others doesn't count.
+        estimator.insert_sample(5, true, received + Duration::from_secs(30), received);
+        assert_eq!(estimator.estimated_offset_ms(), None);
+
+        estimator.insert_sample(5, true, received + Duration::from_secs(91), received);
+        assert_eq!(estimator.estimated_offset_ms(), Some(90_000));
+    }
+
+    #[test]
+    fn apply_offset() {
+        let now = Duration::from_secs(1000);
+        assert_eq!(
+            super::apply_offset(now, 2500),
+            Duration::from_millis(1_002_500)
+        );
+        assert_eq!(
+            super::apply_offset(now, -2500),
+            Duration::from_millis(997_500)
+        );
+    }
+}
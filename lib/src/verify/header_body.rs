@@ -24,7 +24,7 @@ use crate::{
     verify::{aura, babe, inherents},
 };
 
-use alloc::{string::String, vec::Vec};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
 use core::{iter, num::NonZeroU64, time::Duration};
 
 pub use runtime_host::TrieEntryVersion;
@@ -51,6 +51,11 @@ pub struct Config<'a, TBody> {
     /// the network is bounded.
     pub allow_unknown_consensus_engines: bool,
 
+    /// If `true`, the signatures and VRF proofs of the consensus engine aren't verified. The
+    /// block is still executed. See [`babe::VerifyConfig::skip_seal_verification`] and
+    /// [`aura::VerifyConfig::skip_seal_verification`].
+    pub skip_seal_verification: bool,
+
     /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
     /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
     pub now_from_unix_epoch: Duration,
@@ -93,6 +98,9 @@ pub enum ConfigConsensus<'a> {
         /// Duration of a slot in milliseconds.
         /// Can be found by calling the `AuraApi_slot_duration` runtime function.
         slot_duration: NonZeroU64,
+
+        /// See [`aura::VerifyConfig::clock_drift_tolerance`].
+        clock_drift_tolerance: Duration,
     },
 
     /// Chain is using the Babe consensus engine.
@@ -106,6 +114,9 @@ pub enum ConfigConsensus<'a> {
 
         /// Epoch that follows the epoch the parent block belongs to.
         parent_block_next_epoch: chain_information::BabeEpochInformationRef<'a>,
+
+        /// See [`babe::VerifyConfig::vrf_cache`].
+        vrf_cache: Option<&'a mut babe::VrfCache>,
     },
 }
 
@@ -125,8 +136,14 @@ pub struct Success {
     /// List of changes to the storage main trie that the block performs.
     pub storage_main_trie_changes: storage_diff::TrieDiff,
 
+    /// List of changes to the default child tries that the block performs, indexed by the name
+    /// of the child trie (without the `:child_storage:default:` prefix). The new roots of these
+    /// child tries are found in [`Success::storage_main_trie_changes`].
+    pub storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
+
     /// State trie version indicated by the runtime. All the storage changes indicated by
-    /// [`Success::storage_main_trie_changes`] should store this version alongside with them.
+    /// [`Success::storage_main_trie_changes`] and [`Success::storage_child_tries_changes`] should
+    /// store this version alongside with them.
     pub state_trie_version: TrieEntryVersion,
 
     /// List of changes to the off-chain storage that this block performs.
@@ -215,7 +232,7 @@ pub enum Error {
 
 /// Verifies whether a block is valid.
 pub fn verify(
-    config: Config<impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone>,
+    mut config: Config<impl ExactSizeIterator<Item = impl AsRef<[u8]> + Clone> + Clone>,
 ) -> Verify {
     // Fail verification if there is any digest log item with an unrecognized consensus engine.
     if !config.allow_unknown_consensus_engines {
@@ -238,10 +255,11 @@ pub fn verify(
     }
 
     // Start the consensus engine verification process.
-    let consensus_success = match &config.consensus {
+    let consensus_success = match &mut config.consensus {
         ConfigConsensus::Aura {
             current_authorities,
             slot_duration,
+            clock_drift_tolerance,
         } => {
             if config.block_header.digest.has_any_babe() {
                 return Verify::Finished(Err((
@@ -257,6 +275,8 @@ pub fn verify(
                 now_from_unix_epoch: config.now_from_unix_epoch,
                 current_authorities: current_authorities.clone(),
                 slot_duration: *slot_duration,
+                clock_drift_tolerance: *clock_drift_tolerance,
+                skip_seal_verification: config.skip_seal_verification,
             });
 
             match result {
@@ -275,6 +295,7 @@ pub fn verify(
             parent_block_epoch,
             parent_block_next_epoch,
             slots_per_epoch,
+            vrf_cache,
         } => {
             if config.block_header.digest.has_any_aura() {
                 return Verify::Finished(Err((
@@ -291,6 +312,8 @@ pub fn verify(
                 parent_block_epoch: parent_block_epoch.clone(),
                 slots_per_epoch: *slots_per_epoch,
                 now_from_unix_epoch: config.now_from_unix_epoch,
+                vrf_cache: vrf_cache.as_deref_mut(),
+                skip_seal_verification: config.skip_seal_verification,
             });
 
             match result {
@@ -371,6 +394,7 @@ pub fn verify(
             },
             main_trie_root_calculation_cache: config.main_trie_root_calculation_cache,
             storage_main_trie_changes: Default::default(),
+            storage_child_tries_changes: Default::default(),
             offchain_storage_changes: Default::default(),
             max_log_level: config.max_log_level,
         });
@@ -406,6 +430,11 @@ pub enum Verify {
     RuntimeCompilation(RuntimeCompilation),
     /// Loading a storage value is required in order to continue.
     StorageGet(StorageGet),
+    /// Loading a storage value of a child trie is required in order to continue.
+    ChildStorageGet(ChildStorageGet),
+    /// Fetching the list of all the keys of a child trie is required in order to calculate its
+    /// root.
+    ChildStorageRoot(ChildStorageRoot),
     /// Fetching the list of keys with a given prefix is required in order to continue.
     StoragePrefixKeys(StoragePrefixKeys),
     /// Fetching the key that follows a given one is required in order to continue.
@@ -451,6 +480,7 @@ impl VerifyInner {
                                 success.main_trie_root_calculation_cache,
                             ),
                             storage_main_trie_changes: success.storage_main_trie_changes,
+                            storage_child_tries_changes: success.storage_child_tries_changes,
                             offchain_storage_changes: success.offchain_storage_changes,
                             max_log_level: 0,
                         });
@@ -521,6 +551,7 @@ impl VerifyInner {
                                 logs: success.logs,
                                 offchain_storage_changes: success.offchain_storage_changes,
                                 storage_main_trie_changes: success.storage_main_trie_changes,
+                                storage_child_tries_changes: success.storage_child_tries_changes,
                                 state_trie_version: success.state_trie_version,
                                 main_trie_root_calculation_cache: success
                                     .main_trie_root_calculation_cache,
@@ -533,6 +564,7 @@ impl VerifyInner {
                         new_runtime: None,
                         consensus: self.consensus_success,
                         storage_main_trie_changes: success.storage_main_trie_changes,
+                        storage_child_tries_changes: success.storage_child_tries_changes,
                         state_trie_version: success.state_trie_version,
                         offchain_storage_changes: success.offchain_storage_changes,
                         main_trie_root_calculation_cache: success.main_trie_root_calculation_cache,
@@ -546,6 +578,20 @@ impl VerifyInner {
                         consensus_success: self.consensus_success,
                     })
                 }
+                runtime_host::RuntimeHostVm::ChildStorageGet(inner) => {
+                    break Verify::ChildStorageGet(ChildStorageGet {
+                        inner,
+                        execution_not_started: self.execution_not_started,
+                        consensus_success: self.consensus_success,
+                    })
+                }
+                runtime_host::RuntimeHostVm::ChildStorageRoot(inner) => {
+                    break Verify::ChildStorageRoot(ChildStorageRoot {
+                        inner,
+                        execution_not_started: self.execution_not_started,
+                        consensus_success: self.consensus_success,
+                    })
+                }
                 runtime_host::RuntimeHostVm::PrefixKeys(inner) => {
                     break Verify::StoragePrefixKeys(StoragePrefixKeys {
                         inner,
@@ -597,6 +643,68 @@ impl StorageGet {
     }
 }
 
+/// Loading a storage value of a child trie is required in order to continue.
+#[must_use]
+pub struct ChildStorageGet {
+    inner: runtime_host::ChildStorageGet,
+    /// See [`VerifyInner::execution_not_started`].
+    execution_not_started: Option<Vec<u8>>,
+    consensus_success: SuccessConsensus,
+}
+
+impl ChildStorageGet {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Returns the key whose value must be passed to [`ChildStorageGet::inject_value`].
+    pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.key()
+    }
+
+    /// Injects the corresponding storage value.
+    pub fn inject_value(
+        self,
+        value: Option<(impl Iterator<Item = impl AsRef<[u8]>>, TrieEntryVersion)>,
+    ) -> Verify {
+        VerifyInner {
+            inner: self.inner.inject_value(value),
+            execution_not_started: self.execution_not_started,
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
+}
+
+/// Fetching the list of all the keys of a child trie is required in order to calculate its root.
+#[must_use]
+pub struct ChildStorageRoot {
+    inner: runtime_host::ChildStorageRoot,
+    /// See [`VerifyInner::execution_not_started`].
+    execution_not_started: Option<Vec<u8>>,
+    consensus_success: SuccessConsensus,
+}
+
+impl ChildStorageRoot {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix.
+    pub fn child_trie(&'_ self) -> impl AsRef<[u8]> + '_ {
+        self.inner.child_trie()
+    }
+
+    /// Injects the list of all the keys of the child trie, ordered lexicographically.
+    pub fn inject_keys_ordered(self, keys: impl Iterator<Item = impl AsRef<[u8]>>) -> Verify {
+        VerifyInner {
+            inner: self.inner.inject_keys_ordered(keys),
+            execution_not_started: self.execution_not_started,
+            consensus_success: self.consensus_success,
+        }
+        .run()
+    }
+}
+
 /// Fetching the list of keys with a given prefix is required in order to continue.
 #[must_use]
 pub struct StoragePrefixKeys {
@@ -607,6 +715,12 @@ pub struct StoragePrefixKeys {
 }
 
 impl StoragePrefixKeys {
+    /// Returns the name of the child trie whose keys to load, without the
+    /// `:child_storage:default:` prefix, or `None` if the keys of the main trie must be loaded.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the prefix whose keys to load.
     pub fn prefix(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.prefix()
@@ -633,6 +747,12 @@ pub struct StorageNextKey {
 }
 
 impl StorageNextKey {
+    /// Returns the name of the child trie the key belongs to, without the
+    /// `:child_storage:default:` prefix, or `None` if it belongs to the main trie.
+    pub fn child_trie(&'_ self) -> Option<impl AsRef<[u8]> + '_> {
+        self.inner.child_trie()
+    }
+
     /// Returns the key whose next key must be passed back.
     pub fn key(&'_ self) -> impl AsRef<[u8]> + '_ {
         self.inner.key()
@@ -662,6 +782,7 @@ impl StorageNextKey {
 pub struct RuntimeCompilation {
     parent_runtime: host::HostVmPrototype,
     storage_main_trie_changes: storage_diff::TrieDiff,
+    storage_child_tries_changes: BTreeMap<Vec<u8>, storage_diff::TrieDiff>,
     state_trie_version: TrieEntryVersion,
     offchain_storage_changes: storage_diff::TrieDiff,
     main_trie_root_calculation_cache: calculate_root::CalculationCache,
@@ -702,6 +823,7 @@ impl RuntimeCompilation {
             new_runtime: Some(new_runtime),
             consensus: self.consensus_success,
             storage_main_trie_changes: self.storage_main_trie_changes,
+            storage_child_tries_changes: self.storage_child_tries_changes,
             state_trie_version: self.state_trie_version,
             offchain_storage_changes: self.offchain_storage_changes,
             main_trie_root_calculation_cache: self.main_trie_root_calculation_cache,
@@ -52,6 +52,11 @@ pub struct Config<'a> {
     /// Consequently, both `true` and `false` guarantee that the number of authorable blocks over
     /// the network is bounded.
     pub allow_unknown_consensus_engines: bool,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine aren't verified. See
+    /// [`babe::VerifyConfig::skip_seal_verification`] and
+    /// [`aura::VerifyConfig::skip_seal_verification`].
+    pub skip_seal_verification: bool,
 }
 
 /// Extra items of [`Config`] that are dependant on the consensus engine of the chain.
@@ -71,6 +76,9 @@ pub enum ConfigConsensus<'a> {
         /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
         /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
         now_from_unix_epoch: Duration,
+
+        /// See [`aura::VerifyConfig::clock_drift_tolerance`].
+        clock_drift_tolerance: Duration,
     },
 
     /// Chain is using the Babe consensus engine.
@@ -88,6 +96,9 @@ pub enum ConfigConsensus<'a> {
         /// Time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time) (i.e.
         /// 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
         now_from_unix_epoch: Duration,
+
+        /// See [`babe::VerifyConfig::vrf_cache`].
+        vrf_cache: Option<&'a mut babe::VrfCache>,
     },
 }
 
@@ -210,6 +221,7 @@ pub fn verify(config: Config) -> Result<Success, Error> {
             current_authorities,
             slot_duration,
             now_from_unix_epoch,
+            clock_drift_tolerance,
         } => {
             if config.block_header.digest.has_any_babe() {
                 return Err(Error::MultipleConsensusEngines);
@@ -222,6 +234,8 @@ pub fn verify(config: Config) -> Result<Success, Error> {
                 now_from_unix_epoch,
                 current_authorities,
                 slot_duration,
+                clock_drift_tolerance,
+                skip_seal_verification: config.skip_seal_verification,
             });
 
             match result {
@@ -236,6 +250,7 @@ pub fn verify(config: Config) -> Result<Success, Error> {
             parent_block_next_epoch,
             slots_per_epoch,
             now_from_unix_epoch,
+            vrf_cache,
         } => {
             if config.block_header.digest.has_any_aura() {
                 return Err(Error::MultipleConsensusEngines);
@@ -249,6 +264,8 @@ pub fn verify(config: Config) -> Result<Success, Error> {
                 parent_block_next_epoch,
                 slots_per_epoch,
                 now_from_unix_epoch,
+                vrf_cache,
+                skip_seal_verification: config.skip_seal_verification,
             });
 
             match result {
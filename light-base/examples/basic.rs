@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use core::iter;
+use core::{iter, num::NonZeroU32};
 
 fn main() {
     // The `smoldot_light` library uses the `log` crate to emit logs.
@@ -38,25 +38,56 @@ fn main() {
         }),
         system_name: env!("CARGO_PKG_NAME").into(),
         system_version: env!("CARGO_PKG_VERSION").into(),
+        // Maximum allowed difference between the local clock and the clock of the node that
+        // authored a block.
+        clock_drift_tolerance: std::time::Duration::from_secs(30),
+        // Maximum number of networking requests in progress at the same time towards each peer.
+        max_requests_per_peer: std::num::NonZeroUsize::new(3).unwrap(),
     });
 
     // Ask the client to connect to a chain.
     let smoldot_light::AddChainSuccess {
         chain_id,
         json_rpc_responses,
+        ..
     } = client
         .add_chain(smoldot_light::AddChainConfig {
             // The most important field of the configuration is the chain specification. This is a
             // JSON document containing all the information necessary for the client to connect to said
             // chain.
-            specification: include_str!("../../demo-chain-specs/polkadot.json"),
+            specification: smoldot_light::ChainSpecification::Json(include_str!(
+                "../chain-specs/polkadot.json"
+            )),
+
+            // The genesis storage can be provided separately from the chain specification, in
+            // which case it replaces the one found in the chain specification.
+            genesis_storage: None,
+
+            // The fork id inserted in the names of the networking protocols can be overridden,
+            // which is useful for chains that share the same genesis block as another chain.
+            fork_id: Default::default(),
 
             // If `true`, the chain will not be able to handle JSON-RPC requests. This can be used
             // to save up some resources.
             disable_json_rpc: false,
+            json_rpc_methods_filter: Default::default(),
+            ethereum_json_rpc: false,
+            block_announce_policy: smoldot_light::BlockAnnouncePolicy::Immediate,
+
+            // Number of peers the client must be connected to before warp syncing. Requiring
+            // more than one peer makes it more difficult to isolate the client from the rest of
+            // the network.
+            warp_sync_min_distinct_peers: NonZeroU32::new(3).unwrap(),
+            finality_proofs_window: 0,
+            skip_seal_verification: false,
+
+            // Limits of the pool of transactions submitted through the JSON-RPC interface, and
+            // what to do when they are reached.
+            transactions_pool: Default::default(),
 
             // This field is necessary only if adding a parachain.
             potential_relay_chains: iter::empty(),
+            auto_add_relay_chain: None,
 
             // After a chain has been added, it is possible to extract a "database" (in the form of a
             // simple string). This database can later be passed back the next time the same chain is
@@ -66,6 +97,10 @@ fn main() {
             // which is intentionally an invalid database content.
             database_content: "",
 
+            // The client can also periodically export such a database on its own once the chain
+            // is synchronized, through `AddChainSuccess::checkpoints`. This isn't used here.
+            checkpoint_refresh: None,
+
             // The client gives the possibility to insert an opaque "user data" alongside each chain.
             // This avoids having to create a separate `HashMap<ChainId, ...>` in parallel of the
             // client.
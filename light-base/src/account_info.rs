@@ -0,0 +1,234 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retrieval of the nonce and balance of an account.
+//!
+//! This module is a thin layer on top of [`sync_service::SyncService::storage_query`] that
+//! fetches the `System.Account` storage entry of an account and decodes it using
+//! [`smoldot::json_rpc::account_info`]. The format of the storage entry is determined from the
+//! metadata of the runtime of the queried block, which is obtained by calling
+//! `Metadata_metadata`. The decoders built from the metadata are kept in a [`DecodersCache`] in
+//! order to not repeat this call for every query.
+
+use crate::{block_bundle, error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::sync::Arc;
+use core::{
+    iter,
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use futures::{future, lock::Mutex};
+use smoldot::{
+    header,
+    identity::ss58,
+    json_rpc::account_info::{self, AccountInfo},
+};
+
+/// Number of runtimes whose [`account_info::AccountInfoDecoder`] is kept in a [`DecodersCache`].
+/// Runtime upgrades are rare, and only a few runtimes are in use at the same time.
+const DECODERS_CACHE_CAPACITY: usize = 4;
+
+/// Cache of the [`account_info::AccountInfoDecoder`]s of the runtimes of a chain, indexed by the
+/// `spec_version` of the runtime.
+pub(crate) struct DecodersCache {
+    decoders: Mutex<lru::LruCache<u32, Arc<account_info::AccountInfoDecoder>, fnv::FnvBuildHasher>>,
+}
+
+impl DecodersCache {
+    /// Creates a new empty cache.
+    pub(crate) fn new() -> Self {
+        DecodersCache {
+            decoders: Mutex::new(lru::LruCache::with_hasher(
+                NonZeroUsize::new(DECODERS_CACHE_CAPACITY).unwrap(),
+                Default::default(),
+            )),
+        }
+    }
+}
+
+/// Which block to query the account information from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccountInfoBlock {
+    /// Current best block of the chain.
+    Best,
+    /// Current finalized block of the chain.
+    Finalized,
+}
+
+/// Successful outcome of [`account_info`].
+#[derive(Debug, Clone)]
+pub struct AccountInfoAtBlock {
+    /// Hash of the block whose storage has been queried.
+    pub block_hash: [u8; 32],
+    /// Height of the block whose storage has been queried.
+    pub block_number: u64,
+    /// Public key of the account, as decoded from the address.
+    pub account_id: [u8; 32],
+    /// Information about the account. Equal to [`AccountInfo::default`] if the account doesn't
+    /// exist in the storage.
+    pub info: AccountInfo,
+}
+
+/// Decodes an address that is either in the SS58 format or a `0x`-prefixed hexadecimal public
+/// key. See [`ss58::parse_address`].
+///
+/// > **Note**: The network identifier of SS58 addresses isn't compared with the one of the chain.
+pub fn decode_address(address: &str) -> Result<[u8; 32], AccountInfoError> {
+    ss58::parse_address(address, None)
+        .map(|parsed| parsed.public_key)
+        .map_err(AccountInfoError::InvalidAddress)
+}
+
+/// Fetches and decodes the `System.Account` storage entry of the given account.
+pub async fn account_info<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    decoders: &DecodersCache,
+    account_id: [u8; 32],
+    block: AccountInfoBlock,
+) -> Result<AccountInfoAtBlock, AccountInfoError> {
+    // The subscription pins all the blocks that it reports, which guarantees that the runtime
+    // of the queried block can be accessed. Blocks are automatically unpinned when the
+    // subscription is destroyed.
+    let subscribe_all = runtime_service
+        .subscribe_all("account-info", 16, NonZeroUsize::new(32).unwrap())
+        .await;
+    let new_blocks = subscribe_all.new_blocks;
+    let block_header = match block {
+        AccountInfoBlock::Finalized => subscribe_all.finalized_block_scale_encoded_header,
+        AccountInfoBlock::Best => subscribe_all
+            .non_finalized_blocks_ancestry_order
+            .into_iter()
+            .find(|b| b.is_new_best)
+            .map_or(subscribe_all.finalized_block_scale_encoded_header, |b| {
+                b.scale_encoded_header
+            }),
+    };
+
+    let block_hash = header::hash_from_scale_encoded_header(&block_header);
+    let (block_number, state_root) =
+        match header::decode(&block_header, sync_service.block_number_bytes()) {
+            Ok(h) => (h.number, *h.state_root),
+            Err(err) => return Err(AccountInfoError::InvalidBlockHeader(err)),
+        };
+
+    // The storage value and the decoder are obtained in parallel.
+    let (value, decoder) = future::join(
+        sync_service.clone().storage_query(
+            block_number,
+            &block_hash,
+            &state_root,
+            iter::once(account_info::storage_key(&account_id)),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        ),
+        async {
+            let runtime_lock = runtime_service
+                .pinned_block_runtime_lock(new_blocks.id(), &block_hash)
+                .await
+                .map_err(|_| AccountInfoError::SubscriptionReset)?;
+
+            // If the runtime is invalid, the `Metadata_metadata` call below fails as well.
+            let spec_version = runtime_lock
+                .specification()
+                .ok()
+                .map(|spec| spec.decode().spec_version);
+            if let Some(spec_version) = spec_version {
+                if let Some(decoder) = decoders.decoders.lock().await.get(&spec_version) {
+                    return Ok(decoder.clone());
+                }
+            }
+
+            let metadata = block_bundle::metadata_call(&runtime_lock)
+                .await
+                .map_err(AccountInfoError::MetadataCall)?;
+            let decoder = Arc::new(
+                account_info::AccountInfoDecoder::from_metadata(&metadata)
+                    .map_err(AccountInfoError::Metadata)?,
+            );
+            if let Some(spec_version) = spec_version {
+                decoders
+                    .decoders
+                    .lock()
+                    .await
+                    .put(spec_version, decoder.clone());
+            }
+            Ok(decoder)
+        },
+    )
+    .await;
+    drop(new_blocks);
+
+    let value = value
+        .map_err(AccountInfoError::StorageQuery)?
+        .pop()
+        .unwrap();
+
+    let info = match value {
+        Some(value) => decoder?.decode(&value).map_err(AccountInfoError::Decode)?,
+        None => AccountInfo::default(),
+    };
+
+    Ok(AccountInfoAtBlock {
+        block_hash,
+        block_number,
+        account_id,
+        info,
+    })
+}
+
+/// Error potentially returned by [`account_info`] or [`decode_address`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum AccountInfoError {
+    /// The address couldn't be parsed.
+    #[display(fmt = "Invalid address: {_0}")]
+    InvalidAddress(ss58::ParseAddressError),
+    /// The header of the block to query is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage item from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// The runtime service has reset the subscription used to keep the queried block pinned.
+    #[display(fmt = "Block to query has been unpinned")]
+    SubscriptionReset,
+    /// Error while obtaining the metadata of the runtime of the queried block.
+    #[display(fmt = "{_0}")]
+    MetadataCall(block_bundle::MetadataCallError),
+    /// The format of the storage item can't be determined from the metadata.
+    #[display(fmt = "Unsupported metadata: {_0}")]
+    Metadata(account_info::MetadataError),
+    /// The storage item has been retrieved but couldn't be decoded.
+    #[display(fmt = "{_0}")]
+    Decode(account_info::DecodeError),
+}
+
+impl AccountInfoError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AccountInfoError::InvalidAddress(_) => ErrorKind::InvalidInput,
+            AccountInfoError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            AccountInfoError::StorageQuery(err) => err.kind(),
+            AccountInfoError::SubscriptionReset => ErrorKind::UnknownBlock,
+            AccountInfoError::MetadataCall(err) => err.kind(),
+            AccountInfoError::Metadata(_) | AccountInfoError::Decode(_) => ErrorKind::Unsupported,
+        }
+    }
+}
@@ -0,0 +1,456 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Watching of a set of accounts, without any private key.
+//!
+//! [`run`] follows the finalized blocks of the chain. For each newly-finalized block, it fetches
+//! the `System.Account` storage entry of each watched account and reports the accounts whose
+//! nonce or balance has changed, then downloads the body of the block and reports the
+//! extrinsics that are signed by or mention one of the watched accounts. See
+//! [`smoldot::transactions::extrinsic`].
+//!
+//! The format of the `System.Account` storage entry is determined from the metadata of the
+//! runtime, which is downloaded again every time the runtime changes.
+//!
+//! Only finalized blocks are inspected, in order to never report an event that is later
+//! reverted. If the runtime service isn't capable of reporting all the finalized blocks, for
+//! example after a warp sync, the blocks in between are skipped. The storage values reported
+//! afterwards are nonetheless always up to date.
+
+use crate::{block_bundle, error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use futures::{channel::mpsc, prelude::*};
+use smoldot::{
+    header,
+    informant::HashDisplay,
+    json_rpc::account_info::{self, AccountInfo, AccountInfoDecoder},
+    network::protocol,
+    transactions::{calls::AddressEncoding, extrinsic},
+};
+
+/// Configuration for [`run`].
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// List of account ids to watch.
+    pub accounts: Vec<[u8; 32]>,
+
+    /// How the runtime encodes the address of the signer of extrinsics. Used to determine
+    /// whether an extrinsic is signed by one of the watched accounts.
+    pub address_encoding: AddressEncoding,
+}
+
+/// Event generated by [`run`].
+#[derive(Debug, Clone)]
+pub enum AccountWatchEvent {
+    /// The nonce or balance of an account has changed.
+    ///
+    /// Also generated for each account when the first block is inspected, in order to report
+    /// its initial state.
+    InfoChanged {
+        /// Hash of the finalized block whose storage has been queried.
+        block_hash: [u8; 32],
+        /// Height of the finalized block whose storage has been queried.
+        block_number: u64,
+        /// Account whose information has changed.
+        account_id: [u8; 32],
+        /// New information about the account. Equal to [`AccountInfo::default`] if the account
+        /// doesn't exist in the storage.
+        info: AccountInfo,
+    },
+
+    /// An extrinsic of a finalized block is signed by or mentions one of the watched accounts.
+    ///
+    /// If an extrinsic concerns multiple watched accounts, one event is generated per account.
+    Extrinsic {
+        /// Hash of the finalized block containing the extrinsic.
+        block_hash: [u8; 32],
+        /// Height of the finalized block containing the extrinsic.
+        block_number: u64,
+        /// Account concerned by the extrinsic.
+        account_id: [u8; 32],
+        /// Index of the extrinsic within the body of the block.
+        extrinsic_index: usize,
+        /// SCALE-encoded extrinsic, as found in the body of the block.
+        extrinsic: Vec<u8>,
+        /// `true` if the extrinsic is signed by the account. `false` if the account is only
+        /// mentioned in the parameters of the call.
+        signed_by_account: bool,
+    },
+
+    /// Failed to inspect a finalized block. Some of the events concerning this block might be
+    /// missing.
+    BlockError {
+        /// Hash of the finalized block that couldn't be inspected.
+        block_hash: [u8; 32],
+        /// Problem that happened.
+        error: AccountWatchError,
+    },
+}
+
+/// Watches the accounts of the given [`Config`] and sends the corresponding events on
+/// `events_tx`.
+///
+/// Returns when `events_tx` is closed.
+pub async fn run<TPlat: Platform>(
+    sync_service: Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
+    config: Config,
+    mut events_tx: mpsc::Sender<AccountWatchEvent>,
+) {
+    // Information about the accounts found in the last successfully inspected block. Events
+    // are generated only when the information differs from this one.
+    let mut known_infos = hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+        config.accounts.len(),
+        Default::default(),
+    );
+    let mut last_inspected_block = None;
+
+    // Each iteration of this loop corresponds to one subscription. A new subscription is
+    // created if the previous one gets closed, which happens in case of a gap in the finality.
+    loop {
+        let subscribe_all = runtime_service
+            .subscribe_all("account-watch", 32, NonZeroUsize::new(32).unwrap())
+            .await;
+        let mut new_blocks = subscribe_all.new_blocks;
+
+        // Decoder of the `System.Account` storage entry corresponding to the runtime of the
+        // latest finalized block. `None` if it hasn't been downloaded yet.
+        let mut decoder = None;
+
+        // Headers of the non-finalized blocks, hashes of their parents, and whether their
+        // runtime differs from the one of their parent. The blocks are only inspected after
+        // they have been finalized.
+        // The blocks stay pinned until they have been inspected or pruned, as the metadata of
+        // their runtime might need to be downloaded. The current finalized block stays pinned
+        // as well.
+        let mut non_finalized_blocks =
+            hashbrown::HashMap::<_, _, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+                subscribe_all.non_finalized_blocks_ancestry_order.len(),
+                Default::default(),
+            );
+        for block in subscribe_all.non_finalized_blocks_ancestry_order {
+            let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+            non_finalized_blocks.insert(
+                hash,
+                (
+                    block.scale_encoded_header,
+                    block.parent_hash,
+                    block.new_runtime.is_some(),
+                ),
+            );
+        }
+
+        let mut finalized_block_hash = header::hash_from_scale_encoded_header(
+            &subscribe_all.finalized_block_scale_encoded_header,
+        );
+
+        if last_inspected_block != Some(finalized_block_hash) {
+            last_inspected_block = Some(finalized_block_hash);
+            let events = inspect_block(
+                &sync_service,
+                &runtime_service,
+                new_blocks.id(),
+                &config,
+                &mut decoder,
+                &mut known_infos,
+                &subscribe_all.finalized_block_scale_encoded_header,
+            )
+            .await;
+            for event in events {
+                if events_tx.send(event).await.is_err() {
+                    return;
+                }
+            }
+        }
+
+        loop {
+            match new_blocks.next().await {
+                None => break,
+                Some(runtime_service::Notification::Block(block)) => {
+                    let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
+                    non_finalized_blocks.insert(
+                        hash,
+                        (
+                            block.scale_encoded_header,
+                            block.parent_hash,
+                            block.new_runtime.is_some(),
+                        ),
+                    );
+                }
+                Some(runtime_service::Notification::Finalized {
+                    hash,
+                    pruned_blocks,
+                    ..
+                }) => {
+                    // Multiple blocks can be finalized at once. Walk back from the new finalized
+                    // block to the previous one in order to inspect all of them in order.
+                    let mut newly_finalized = Vec::new();
+                    let mut iter = hash;
+                    while iter != finalized_block_hash {
+                        let (header, parent_hash, runtime_changed) =
+                            non_finalized_blocks.remove(&iter).unwrap();
+                        newly_finalized.push((iter, header, runtime_changed));
+                        iter = parent_hash;
+                    }
+                    for pruned in pruned_blocks {
+                        non_finalized_blocks.remove(&pruned);
+                        new_blocks.unpin_block(&pruned).await;
+                    }
+                    last_inspected_block = Some(hash);
+
+                    for (block_hash, header, runtime_changed) in newly_finalized.into_iter().rev() {
+                        if runtime_changed {
+                            decoder = None;
+                        }
+
+                        let events = inspect_block(
+                            &sync_service,
+                            &runtime_service,
+                            new_blocks.id(),
+                            &config,
+                            &mut decoder,
+                            &mut known_infos,
+                            &header,
+                        )
+                        .await;
+
+                        // The previously-finalized block is no longer needed.
+                        new_blocks.unpin_block(&finalized_block_hash).await;
+                        finalized_block_hash = block_hash;
+
+                        for event in events {
+                            if events_tx.send(event).await.is_err() {
+                                return;
+                            }
+                        }
+                    }
+                }
+                Some(runtime_service::Notification::BestBlockChanged { .. }) => {}
+            }
+        }
+    }
+}
+
+/// Error potentially reported in [`AccountWatchEvent::BlockError`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum AccountWatchError {
+    /// The header of the block is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// The runtime service has reset the subscription used to keep the block pinned.
+    #[display(fmt = "Block has been unpinned")]
+    SubscriptionReset,
+    /// Error while obtaining the metadata of the runtime of the block.
+    #[display(fmt = "{_0}")]
+    MetadataCall(block_bundle::MetadataCallError),
+    /// The format of the storage items can't be determined from the metadata.
+    #[display(fmt = "Unsupported metadata: {_0}")]
+    Metadata(account_info::MetadataError),
+    /// The storage item of an account has been retrieved but couldn't be decoded.
+    #[display(fmt = "{_0}")]
+    Decode(account_info::DecodeError),
+    /// Failed to download the body of the block.
+    #[display(fmt = "Failed to download body of block {}", "HashDisplay(_0)")]
+    BodyQuery([u8; 32]),
+}
+
+impl AccountWatchError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            AccountWatchError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            AccountWatchError::StorageQuery(err) => err.kind(),
+            AccountWatchError::SubscriptionReset => ErrorKind::UnknownBlock,
+            AccountWatchError::MetadataCall(err) => err.kind(),
+            AccountWatchError::Metadata(_) | AccountWatchError::Decode(_) => ErrorKind::Unsupported,
+            AccountWatchError::BodyQuery(_) => ErrorKind::NetworkUnreachable,
+        }
+    }
+}
+
+/// Queries the storage and body of the given finalized block, and returns the corresponding
+/// events.
+///
+/// The block must be pinned by the given subscription. If `decoder` is `None`, it is filled
+/// with the decoder corresponding to the runtime of the block.
+async fn inspect_block<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    subscription_id: runtime_service::SubscriptionId,
+    config: &Config,
+    decoder: &mut Option<AccountInfoDecoder>,
+    known_infos: &mut hashbrown::HashMap<[u8; 32], AccountInfo, fnv::FnvBuildHasher>,
+    scale_encoded_header: &[u8],
+) -> Vec<AccountWatchEvent> {
+    let block_hash = header::hash_from_scale_encoded_header(scale_encoded_header);
+    let (block_number, state_root) =
+        match header::decode(scale_encoded_header, sync_service.block_number_bytes()) {
+            Ok(h) => (h.number, *h.state_root),
+            Err(err) => {
+                return vec![AccountWatchEvent::BlockError {
+                    block_hash,
+                    error: AccountWatchError::InvalidBlockHeader(err),
+                }]
+            }
+        };
+
+    let mut events = Vec::new();
+
+    let infos = match sync_service
+        .clone()
+        .storage_query(
+            block_number,
+            &block_hash,
+            &state_root,
+            config.accounts.iter().map(account_info::storage_key),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+    {
+        Ok(values) => {
+            decode_infos(
+                runtime_service,
+                subscription_id,
+                &block_hash,
+                decoder,
+                values,
+            )
+            .await
+        }
+        Err(err) => Err(AccountWatchError::StorageQuery(err)),
+    };
+
+    match infos {
+        Ok(infos) => {
+            for (account_id, info) in config.accounts.iter().zip(infos) {
+                if known_infos.get(account_id) == Some(&info) {
+                    continue;
+                }
+                known_infos.insert(*account_id, info.clone());
+                events.push(AccountWatchEvent::InfoChanged {
+                    block_hash,
+                    block_number,
+                    account_id: *account_id,
+                    info,
+                });
+            }
+        }
+        Err(error) => events.push(AccountWatchEvent::BlockError { block_hash, error }),
+    }
+
+    let body = sync_service
+        .clone()
+        .block_query(
+            block_number,
+            block_hash,
+            protocol::BlocksRequestFields {
+                header: true, // TODO: must be true in order for the body to be verified; fix the sync_service to not require that
+                body: true,
+                justifications: false,
+            },
+            3,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .ok()
+        .and_then(|block| block.body);
+
+    let body = match body {
+        Some(body) => body,
+        None => {
+            events.push(AccountWatchEvent::BlockError {
+                block_hash,
+                error: AccountWatchError::BodyQuery(block_hash),
+            });
+            return events;
+        }
+    };
+
+    for (extrinsic_index, extrinsic) in body.into_iter().enumerate() {
+        // Extrinsics that can't be decoded are still inspected for mentions of the accounts.
+        let signer = extrinsic::decode_signer(&extrinsic, config.address_encoding)
+            .ok()
+            .flatten();
+
+        for account_id in &config.accounts {
+            let signed_by_account = signer == Some(extrinsic::Signer::AccountId(*account_id));
+            if !signed_by_account && !extrinsic::contains_account_id(&extrinsic, account_id) {
+                continue;
+            }
+
+            events.push(AccountWatchEvent::Extrinsic {
+                block_hash,
+                block_number,
+                account_id: *account_id,
+                extrinsic_index,
+                extrinsic: extrinsic.clone(),
+                signed_by_account,
+            });
+        }
+    }
+
+    events
+}
+
+/// Decodes the given values of the `System.Account` storage entry.
+///
+/// If `decoder` is `None` and at least one value needs to be decoded, the metadata of the
+/// runtime of the given block is downloaded and `decoder` is filled. The block must be pinned by
+/// the given subscription.
+async fn decode_infos<TPlat: Platform>(
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    subscription_id: runtime_service::SubscriptionId,
+    block_hash: &[u8; 32],
+    decoder: &mut Option<AccountInfoDecoder>,
+    values: Vec<Option<Vec<u8>>>,
+) -> Result<Vec<AccountInfo>, AccountWatchError> {
+    if decoder.is_none() && values.iter().any(|value| value.is_some()) {
+        let runtime_lock = runtime_service
+            .pinned_block_runtime_lock(subscription_id, block_hash)
+            .await
+            .map_err(|_| AccountWatchError::SubscriptionReset)?;
+        let metadata = block_bundle::metadata_call(&runtime_lock)
+            .await
+            .map_err(AccountWatchError::MetadataCall)?;
+        *decoder = Some(
+            AccountInfoDecoder::from_metadata(&metadata).map_err(AccountWatchError::Metadata)?,
+        );
+    }
+
+    values
+        .into_iter()
+        .map(|value| match value {
+            Some(value) => decoder
+                .as_ref()
+                .unwrap()
+                .decode(&value)
+                .map_err(AccountWatchError::Decode),
+            None => Ok(AccountInfo::default()),
+        })
+        .collect()
+}
@@ -0,0 +1,358 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retrieval of everything a block explorer displays about a block.
+//!
+//! [`block_bundle`] gathers the header, the body, the events, and the outcome of each extrinsic
+//! of a block. All this information is verified: the body is checked against the extrinsics
+//! root of the header, and the events are obtained through a storage proof checked against the
+//! state root of the header.
+//!
+//! Splitting the events requires the metadata of the runtime of the block, which is obtained by
+//! calling `Metadata_metadata` and decoded with [`events::EventsDecoder`].
+
+use crate::{error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{borrow::ToOwned as _, string::String, sync::Arc, vec::Vec};
+use core::{
+    iter,
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use futures::future;
+use smoldot::{
+    executor::{host, runtime_host},
+    header,
+    informant::HashDisplay,
+    json_rpc::methods,
+    network::protocol,
+    transactions::events::{self, ExtrinsicOutcome, Phase},
+};
+
+/// Information about a block. See [`block_bundle`].
+#[derive(Debug, Clone)]
+pub struct BlockBundle {
+    /// Hash of the block.
+    pub block_hash: [u8; 32],
+    /// Height of the block.
+    pub block_number: u64,
+    /// SCALE-encoded header of the block.
+    pub scale_encoded_header: Vec<u8>,
+    /// List of SCALE-encoded extrinsics of the block.
+    pub body: Vec<Vec<u8>>,
+    /// Events generated by the block, in the order in which they have been generated.
+    pub events: Vec<BlockBundleEvent>,
+    /// Outcome of each extrinsic of [`BlockBundle::body`]. An entry is `None` if the events
+    /// don't indicate whether the extrinsic has succeeded, which is normally never the case.
+    pub extrinsics_outcomes: Vec<Option<ExtrinsicOutcome>>,
+}
+
+/// Event found in a [`BlockBundle`]. See [`events::EventRecord`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockBundleEvent {
+    /// Moment when the event has been generated.
+    pub phase: Phase,
+    /// Name of the pallet that has generated the event, for example `Balances`.
+    pub pallet_name: String,
+    /// Index of the pallet that has generated the event.
+    pub pallet_index: u8,
+    /// Name of the event, for example `Transfer`.
+    pub event_name: String,
+    /// Index of the event within the pallet.
+    pub event_index: u8,
+    /// SCALE-encoded fields of the event, one after the other.
+    pub fields: Vec<u8>,
+}
+
+/// Gathers the header, body, events, and outcome of each extrinsic of the given block.
+///
+/// The block must be either the current finalized block or one of its non-finalized
+/// descendants.
+pub async fn block_bundle<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    block_hash: [u8; 32],
+) -> Result<BlockBundle, BlockBundleError> {
+    // The subscription pins all the blocks that it reports, which guarantees that the runtime
+    // of the target block can be accessed. Blocks are automatically unpinned when the
+    // subscription is destroyed.
+    let subscribe_all = runtime_service
+        .subscribe_all("block-bundle", 16, NonZeroUsize::new(32).unwrap())
+        .await;
+    let new_blocks = subscribe_all.new_blocks;
+
+    let scale_encoded_header = if block_hash
+        == header::hash_from_scale_encoded_header(
+            &subscribe_all.finalized_block_scale_encoded_header,
+        ) {
+        subscribe_all.finalized_block_scale_encoded_header
+    } else {
+        subscribe_all
+            .non_finalized_blocks_ancestry_order
+            .into_iter()
+            .find(|b| header::hash_from_scale_encoded_header(&b.scale_encoded_header) == block_hash)
+            .ok_or(BlockBundleError::UnknownBlock(block_hash))?
+            .scale_encoded_header
+    };
+
+    let (block_number, state_root) =
+        match header::decode(&scale_encoded_header, sync_service.block_number_bytes()) {
+            Ok(h) => (h.number, *h.state_root),
+            Err(err) => return Err(BlockBundleError::InvalidBlockHeader(err)),
+        };
+
+    // The body, the events, and the metadata are all downloaded in parallel.
+    let (body, events, metadata) = future::join3(
+        sync_service.clone().block_query(
+            block_number,
+            block_hash,
+            protocol::BlocksRequestFields {
+                header: true, // TODO: must be true in order for the body to be verified; fix the sync_service to not require that
+                body: true,
+                justifications: false,
+            },
+            3,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        ),
+        sync_service.clone().storage_query(
+            block_number,
+            &block_hash,
+            &state_root,
+            iter::once(&events::SYSTEM_EVENTS_STORAGE_KEY[..]),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        ),
+        async {
+            let runtime_lock = runtime_service
+                .pinned_block_runtime_lock(new_blocks.id(), &block_hash)
+                .await
+                .map_err(|_| BlockBundleError::UnknownBlock(block_hash))?;
+            metadata_call(&runtime_lock)
+                .await
+                .map_err(BlockBundleError::MetadataCall)
+        },
+    )
+    .await;
+    drop(new_blocks);
+
+    let metadata = metadata?;
+    let body = body
+        .ok()
+        .and_then(|block| block.body)
+        .ok_or(BlockBundleError::BodyQuery)?;
+    let events = events
+        .map_err(BlockBundleError::StorageQuery)?
+        .pop()
+        .unwrap()
+        .unwrap_or_default();
+
+    let decoder =
+        events::EventsDecoder::from_metadata(&metadata).map_err(BlockBundleError::Metadata)?;
+
+    // A block that doesn't modify the events has an empty list of events.
+    let records = if events.is_empty() {
+        Vec::new()
+    } else {
+        decoder
+            .decode(&events)
+            .map_err(BlockBundleError::EventsDecode)?
+    };
+    let extrinsics_outcomes = decoder
+        .extrinsics_outcomes(&records, body.len())
+        .map_err(BlockBundleError::EventsDecode)?;
+
+    Ok(BlockBundle {
+        block_hash,
+        block_number,
+        scale_encoded_header,
+        body,
+        events: records
+            .into_iter()
+            .map(|record| BlockBundleEvent {
+                phase: record.phase,
+                pallet_name: record.pallet_name.to_owned(),
+                pallet_index: record.pallet_index,
+                event_name: record.event_name.to_owned(),
+                event_index: record.event_index,
+                fields: record.fields.to_vec(),
+            })
+            .collect(),
+        extrinsics_outcomes,
+    })
+}
+
+/// Error potentially returned by [`block_bundle`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum BlockBundleError {
+    /// The block isn't the current finalized block or one of its non-finalized descendants.
+    #[display(fmt = "Unknown block: {}", "HashDisplay(_0)")]
+    UnknownBlock([u8; 32]),
+    /// The header of the block is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Failed to download the body of the block.
+    #[display(fmt = "Failed to download block body")]
+    BodyQuery,
+    /// Error while retrieving the events from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// Error while obtaining the metadata of the runtime.
+    #[display(fmt = "{_0}")]
+    MetadataCall(MetadataCallError),
+    /// The metadata of the runtime isn't supported.
+    #[display(fmt = "Unsupported metadata: {_0}")]
+    Metadata(events::MetadataError),
+    /// Failed to decode the events of the block.
+    #[display(fmt = "Failed to decode events: {_0}")]
+    EventsDecode(events::DecodeError),
+}
+
+impl BlockBundleError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            BlockBundleError::UnknownBlock(_) => ErrorKind::UnknownBlock,
+            BlockBundleError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            BlockBundleError::BodyQuery => ErrorKind::NetworkUnreachable,
+            BlockBundleError::StorageQuery(err) => err.kind(),
+            BlockBundleError::MetadataCall(err) => err.kind(),
+            BlockBundleError::Metadata(_) | BlockBundleError::EventsDecode(_) => {
+                ErrorKind::Unsupported
+            }
+        }
+    }
+}
+
+/// Error potentially returned when obtaining the metadata of a runtime.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum MetadataCallError {
+    /// Error while performing the call proof request or accessing the runtime.
+    #[display(fmt = "{_0}")]
+    Call(runtime_service::RuntimeCallError),
+    /// Failed to start the `Metadata_metadata` runtime call.
+    #[display(fmt = "Failed to start runtime call: {_0}")]
+    StartError(host::StartErr),
+    /// The `Metadata_metadata` runtime call has failed.
+    #[display(fmt = "Runtime call failed: {_0}")]
+    RuntimeError(runtime_host::ErrorDetail),
+    /// The runtime has tried to enumerate storage keys, which isn't supported.
+    #[display(fmt = "Runtime call has accessed unsupported host functions")]
+    ForbiddenHostFunction,
+    /// The output of the `Metadata_metadata` runtime call has an invalid length prefix.
+    #[display(fmt = "Invalid metadata length prefix")]
+    InvalidMetadata,
+}
+
+impl MetadataCallError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            MetadataCallError::Call(err) => err.kind(),
+            MetadataCallError::StartError(_) | MetadataCallError::RuntimeError(_) => {
+                ErrorKind::RuntimeTrap
+            }
+            MetadataCallError::ForbiddenHostFunction | MetadataCallError::InvalidMetadata => {
+                ErrorKind::Unsupported
+            }
+        }
+    }
+}
+
+/// Performs the `Metadata_metadata` runtime call and returns its output, after its length prefix
+/// has been removed.
+pub(crate) async fn metadata_call<TPlat: Platform>(
+    runtime_lock: &runtime_service::RuntimeLock<TPlat>,
+) -> Result<Vec<u8>, MetadataCallError> {
+    let (runtime_call_lock, virtual_machine) = runtime_lock
+        .start(
+            "Metadata_metadata",
+            iter::empty::<Vec<u8>>(),
+            3,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .map_err(MetadataCallError::Call)?;
+
+    let mut runtime_call = match runtime_host::run(runtime_host::Config {
+        virtual_machine,
+        function_to_call: "Metadata_metadata",
+        parameter: iter::empty::<Vec<u8>>(),
+        main_trie_root_calculation_cache: None,
+        storage_main_trie_changes: Default::default(),
+        storage_child_tries_changes: Default::default(),
+        offchain_storage_changes: Default::default(),
+        max_log_level: 0,
+    }) {
+        Ok(vm) => vm,
+        Err((err, prototype)) => {
+            runtime_call_lock.unlock(prototype);
+            return Err(MetadataCallError::StartError(err));
+        }
+    };
+
+    loop {
+        match runtime_call {
+            runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                let output = methods::remove_metadata_length_prefix(
+                    success.virtual_machine.value().as_ref(),
+                )
+                .map(|metadata| metadata.to_vec())
+                .map_err(|_| MetadataCallError::InvalidMetadata);
+                runtime_call_lock.unlock(success.virtual_machine.into_prototype());
+                break output;
+            }
+            runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                runtime_call_lock.unlock(error.prototype);
+                break Err(MetadataCallError::RuntimeError(error.detail));
+            }
+            runtime_host::RuntimeHostVm::StorageGet(get) => {
+                let storage_value = runtime_call_lock.storage_entry(get.key().as_ref());
+                let storage_value = match storage_value {
+                    Ok(v) => v,
+                    Err(err) => {
+                        runtime_call_lock
+                            .unlock(runtime_host::RuntimeHostVm::StorageGet(get).into_prototype());
+                        break Err(MetadataCallError::Call(err));
+                    }
+                };
+                runtime_call =
+                    get.inject_value(storage_value.map(|(val, vers)| (iter::once(val), vers)));
+            }
+            runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                runtime_call = sig.verify_and_resume();
+            }
+            runtime_host::RuntimeHostVm::NextKey(nk) => {
+                runtime_call_lock.unlock(runtime_host::RuntimeHostVm::NextKey(nk).into_prototype());
+                break Err(MetadataCallError::ForbiddenHostFunction);
+            }
+            runtime_host::RuntimeHostVm::PrefixKeys(pk) => {
+                runtime_call_lock
+                    .unlock(runtime_host::RuntimeHostVm::PrefixKeys(pk).into_prototype());
+                break Err(MetadataCallError::ForbiddenHostFunction);
+            }
+            other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+            | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                // TODO: child tries aren't supported by the runtime call lock
+                runtime_call_lock.unlock(other.into_prototype());
+                break Err(MetadataCallError::ForbiddenHostFunction);
+            }
+        }
+    }
+}
@@ -0,0 +1,393 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Runner of JSON-RPC conformance vectors against an in-process client.
+//!
+//! A [`Vector`] consists of a JSON-RPC function call and of the response that the client is
+//! expected to send back. [`run`] sends the vectors one by one to a [`JsonRpcEndpoint`] and
+//! reports which ones the client has answered as expected. This lets chain teams verify that
+//! their chain works with smoldot before shipping it.
+//!
+//! [`spec_vectors`] builds the vectors that can be answered without any networking, from the
+//! chain specification alone. These are the only vectors that the tests of this module run.
+//!
+//! This module doesn't include any simulated network. API users can add their own vectors that
+//! depend on the content of the chain, such as storage queries or runtime calls, but the client
+//! must then be able to reach actual peers of the chain through the [`Platform`] that it uses,
+//! and the outcome of these vectors depends on the state of the live chain.
+//!
+//! This module is only available if the `conformance` feature is enabled.
+
+use crate::{platform::Platform, HandleRpcError, JsonRpcEndpoint};
+
+use alloc::{
+    borrow::ToOwned as _,
+    format,
+    string::{String, ToString as _},
+    vec::Vec,
+};
+use core::time::Duration;
+use futures::prelude::*;
+use smoldot::chain_spec;
+
+/// JSON-RPC function call and its expected response.
+#[derive(Debug, Clone)]
+pub struct Vector {
+    /// Name of the vector, for reporting purposes.
+    pub name: String,
+    /// Name of the JSON-RPC function to call.
+    pub method: String,
+    /// JSON-encoded parameters of the call. Typically an array.
+    pub params_json: String,
+    /// Response that the client is expected to send back.
+    pub expected: Expectation,
+}
+
+/// See [`Vector::expected`].
+#[derive(Debug, Clone)]
+pub enum Expectation {
+    /// Any successful response is expected.
+    Success,
+    /// A successful response whose result is equal to the given value is expected.
+    Result(serde_json::Value),
+    /// An error response with the given error code is expected.
+    Error(i64),
+}
+
+/// Outcome of [`run`].
+#[derive(Debug, Clone)]
+pub struct Report {
+    /// Outcome of each vector, in the same order as they have been passed to [`run`].
+    pub outcomes: Vec<VectorOutcome>,
+}
+
+impl Report {
+    /// Returns `true` if all the vectors have succeeded.
+    pub fn is_success(&self) -> bool {
+        self.outcomes.iter().all(|outcome| outcome.result.is_ok())
+    }
+}
+
+/// Outcome of a single [`Vector`].
+#[derive(Debug, Clone)]
+pub struct VectorOutcome {
+    /// Value of [`Vector::name`].
+    pub name: String,
+    /// `Ok` if the client has answered as expected.
+    pub result: Result<(), VectorFailure>,
+}
+
+/// Reason why a [`Vector`] has failed.
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum VectorFailure {
+    /// The client has refused the request.
+    #[display(fmt = "Request refused: {_0}")]
+    Refused(String),
+    /// The client hasn't answered before the timeout.
+    #[display(fmt = "No response before the timeout")]
+    Timeout,
+    /// The chain has been removed while waiting for the response.
+    #[display(fmt = "Chain removed")]
+    ChainRemoved,
+    /// The response doesn't match [`Vector::expected`].
+    #[display(fmt = "Unexpected response: {response}")]
+    UnexpectedResponse {
+        /// Response sent back by the client.
+        response: String,
+    },
+}
+
+/// Builds the vectors whose expected responses can be determined from the chain specification,
+/// and that the client can answer without any networking.
+pub fn spec_vectors(chain_spec_json: &str) -> Result<Vec<Vector>, chain_spec::ParseError> {
+    let chain_spec = chain_spec::ChainSpec::from_json_bytes(chain_spec_json)?;
+    let properties = serde_json::from_str::<serde_json::Value>(chain_spec.properties())
+        .unwrap_or(serde_json::Value::Null);
+
+    let vector = |name: &str, method: &str, params_json: &str, expected| Vector {
+        name: name.to_owned(),
+        method: method.to_owned(),
+        params_json: params_json.to_owned(),
+        expected,
+    };
+
+    Ok(alloc::vec![
+        vector("rpc-methods", "rpc_methods", "[]", Expectation::Success),
+        vector(
+            "system-chain",
+            "system_chain",
+            "[]",
+            Expectation::Result(chain_spec.name().into())
+        ),
+        vector(
+            "system-chain-type",
+            "system_chainType",
+            "[]",
+            Expectation::Result(chain_spec.chain_type().into())
+        ),
+        vector(
+            "system-properties",
+            "system_properties",
+            "[]",
+            Expectation::Result(properties.clone())
+        ),
+        vector("system-name", "system_name", "[]", Expectation::Success),
+        vector(
+            "system-version",
+            "system_version",
+            "[]",
+            Expectation::Success
+        ),
+        vector(
+            "chain-spec-chain-name",
+            "chainSpec_unstable_chainName",
+            "[]",
+            Expectation::Result(chain_spec.name().into())
+        ),
+        vector(
+            "chain-spec-properties",
+            "chainSpec_unstable_properties",
+            "[]",
+            Expectation::Result(properties)
+        ),
+        vector(
+            "chain-spec-genesis-hash",
+            "chainSpec_unstable_genesisHash",
+            "[]",
+            Expectation::Success
+        ),
+        vector(
+            "unknown-method",
+            "conformance_unknownMethod",
+            "[]",
+            Expectation::Error(-32601)
+        ),
+        vector(
+            "missing-parameters",
+            "author_submitExtrinsic",
+            "[]",
+            Expectation::Error(-32602)
+        ),
+    ])
+}
+
+/// Sends the given vectors one by one to the given endpoint, and compares the responses with
+/// the expected ones.
+///
+/// `timeout` is the maximum duration to wait for the response to each vector. The notifications
+/// that the endpoint might send back, for example because of subscriptions started before this
+/// function is called, are ignored.
+pub async fn run<TPlat: Platform>(
+    endpoint: &mut JsonRpcEndpoint,
+    vectors: impl IntoIterator<Item = Vector>,
+    timeout: Duration,
+) -> Report {
+    let mut outcomes = Vec::new();
+
+    for (index, vector) in vectors.into_iter().enumerate() {
+        let request_id = format!("conformance-{index}");
+        let result = run_one::<TPlat>(endpoint, &request_id, &vector, timeout).await;
+
+        if let Err(err) = &result {
+            log::debug!(target: "conformance", "Vector {} failed: {}", vector.name, err);
+        }
+
+        outcomes.push(VectorOutcome {
+            name: vector.name,
+            result,
+        });
+    }
+
+    Report { outcomes }
+}
+
+async fn run_one<TPlat: Platform>(
+    endpoint: &mut JsonRpcEndpoint,
+    request_id: &str,
+    vector: &Vector,
+    timeout: Duration,
+) -> Result<(), VectorFailure> {
+    let request = format!(
+        r#"{{"jsonrpc":"2.0","id":{},"method":{},"params":{}}}"#,
+        serde_json::Value::from(request_id),
+        serde_json::Value::from(&vector.method[..]),
+        vector.params_json
+    );
+
+    endpoint
+        .request(request)
+        .map_err(|err: HandleRpcError| VectorFailure::Refused(err.to_string()))?;
+
+    let mut timeout = TPlat::sleep(timeout).fuse();
+
+    loop {
+        let response = futures::select! {
+            response = endpoint.next_response().fuse() => response,
+            () = timeout => return Err(VectorFailure::Timeout),
+        };
+
+        let Some(response) = response else {
+            return Err(VectorFailure::ChainRemoved);
+        };
+
+        let Ok(decoded) = serde_json::from_str::<serde_json::Value>(&response) else {
+            return Err(VectorFailure::UnexpectedResponse { response });
+        };
+
+        // Notifications and responses to other requests are ignored.
+        if decoded.get("id").and_then(|id| id.as_str()) != Some(request_id) {
+            continue;
+        }
+
+        let matches = match (
+            &vector.expected,
+            decoded.get("result"),
+            decoded.get("error"),
+        ) {
+            (Expectation::Success, Some(_), None) => true,
+            (Expectation::Result(expected), Some(result), None) => result == expected,
+            (Expectation::Error(expected), None, Some(error)) => {
+                error.get("code").and_then(|code| code.as_i64()) == Some(*expected)
+            }
+            _ => false,
+        };
+
+        return if matches {
+            Ok(())
+        } else {
+            Err(VectorFailure::UnexpectedResponse { response })
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, spec_vectors, Expectation, Vector, VectorFailure};
+    use crate::platform::async_std::AsyncStdTcpWebSocket;
+    use core::time::Duration;
+
+    /// Chain specification of the tests, without any bootnode so that the client never connects
+    /// to the network.
+    fn test_chain_spec() -> String {
+        let mut spec = serde_json::from_slice::<serde_json::Value>(
+            &include_bytes!("../../lib/src/chain_spec/example.json")[..],
+        )
+        .unwrap();
+        spec["bootNodes"] = serde_json::json!([]);
+        spec.to_string()
+    }
+
+    async fn run_against_client(chain_spec: &str, vectors: Vec<Vector>) -> super::Report {
+        let mut client = crate::Client::<AsyncStdTcpWebSocket>::new(crate::ClientConfig {
+            tasks_spawner: Box::new(|_, task| {
+                async_std::task::spawn(task);
+            }),
+            system_name: "test".into(),
+            system_version: "0".into(),
+            clock_drift_tolerance: Duration::from_secs(30),
+            max_requests_per_peer: core::num::NonZeroUsize::new(3).unwrap(),
+        });
+
+        let chain_id = client
+            .add_chain(crate::AddChainConfig {
+                user_data: (),
+                specification: crate::ChainSpecification::Json(chain_spec),
+                genesis_storage: None,
+                fork_id: Default::default(),
+                database_content: "",
+                potential_relay_chains: core::iter::empty(),
+                auto_add_relay_chain: None,
+                disable_json_rpc: false,
+                json_rpc_methods_filter: Default::default(),
+                ethereum_json_rpc: false,
+                block_announce_policy: crate::BlockAnnouncePolicy::Immediate,
+                warp_sync_min_distinct_peers: core::num::NonZeroU32::new(1).unwrap(),
+                finality_proofs_window: 0,
+                skip_seal_verification: false,
+                transactions_pool: Default::default(),
+                checkpoint_refresh: None,
+            })
+            .unwrap()
+            .chain_id;
+
+        let mut endpoint = client.json_rpc_endpoint(chain_id).await.unwrap();
+        run::<AsyncStdTcpWebSocket>(&mut endpoint, vectors, Duration::from_secs(10)).await
+    }
+
+    #[test]
+    fn spec_vectors_pass() {
+        let chain_spec = test_chain_spec();
+        let vectors = spec_vectors(&chain_spec).unwrap();
+        let num_vectors = vectors.len();
+
+        let report = async_std::task::block_on(run_against_client(&chain_spec, vectors));
+        assert_eq!(report.outcomes.len(), num_vectors);
+        for outcome in &report.outcomes {
+            assert!(
+                outcome.result.is_ok(),
+                "{}: {:?}",
+                outcome.name,
+                outcome.result
+            );
+        }
+        assert!(report.is_success());
+    }
+
+    #[test]
+    fn mismatching_responses_reported() {
+        let chain_spec = test_chain_spec();
+        let vector = |name: &str, method: &str, expected| Vector {
+            name: name.to_owned(),
+            method: method.to_owned(),
+            params_json: "[]".to_owned(),
+            expected,
+        };
+
+        let report = async_std::task::block_on(run_against_client(
+            &chain_spec,
+            vec![
+                vector(
+                    "wrong-result",
+                    "system_chain",
+                    Expectation::Result("Not the chain name".into()),
+                ),
+                vector("wrong-error", "system_chain", Expectation::Error(-32601)),
+                vector(
+                    "unexpected-error",
+                    "conformance_unknownMethod",
+                    Expectation::Success,
+                ),
+                vector("success", "system_name", Expectation::Success),
+            ],
+        ));
+
+        assert!(!report.is_success());
+        assert_eq!(report.outcomes.len(), 4);
+        for outcome in &report.outcomes[..3] {
+            assert!(
+                matches!(
+                    outcome.result,
+                    Err(VectorFailure::UnexpectedResponse { .. })
+                ),
+                "{}: {:?}",
+                outcome.name,
+                outcome.result
+            );
+        }
+        assert!(report.outcomes[3].result.is_ok());
+    }
+}
@@ -40,7 +40,7 @@ use smoldot::{
     libp2p::{multiaddr, PeerId},
 };
 
-use crate::{network_service, platform, sync_service};
+use crate::{network_service, platform, sync_service, transactions_service};
 
 /// A decoded database.
 pub struct DatabaseContent {
@@ -51,6 +51,15 @@ pub struct DatabaseContent {
     /// List of nodes that were known to be part of the peer-to-peer network when the database
     /// was encoded.
     pub known_nodes: Vec<(PeerId, Vec<multiaddr::Multiaddr>)>,
+    /// Hash of the genesis storage and trie root hash of this genesis storage, as provided to
+    /// [`encode_database`].
+    ///
+    /// See [`smoldot::chain_spec::GenesisStorageItems::content_hash`].
+    pub genesis_state_root: Option<([u8; 32], [u8; 32])>,
+    /// Transactions that were pending in the transactions service when the database was encoded.
+    ///
+    /// See [`transactions_service::TransactionsService::pending_transactions`].
+    pub pending_transactions: Vec<transactions_service::SavedTransaction>,
 }
 
 /// Serializes the finalized state of the chain, using the given services.
@@ -60,12 +69,20 @@ pub struct DatabaseContent {
 pub async fn encode_database<TPlat: platform::Platform>(
     network_service: &network_service::NetworkService<TPlat>,
     sync_service: &sync_service::SyncService<TPlat>,
+    transactions_service: &transactions_service::TransactionsService<TPlat>,
     genesis_block_hash: &[u8; 32],
+    genesis_state_root: Option<&([u8; 32], [u8; 32])>,
     max_size: usize,
 ) -> String {
     // Craft the structure containing all the data that we would like to include.
     let mut database_draft = SerdeDatabase {
         genesis_hash: hex::encode(genesis_block_hash),
+        genesis_state_root: genesis_state_root.map(|(storage_hash, state_root)| {
+            SerdeGenesisStateRoot {
+                storage_hash: hex::encode(storage_hash),
+                state_root: hex::encode(state_root),
+            }
+        }),
         chain: match sync_service.serialize_chain_information().await {
             Some(ci) => {
                 let encoded =
@@ -93,6 +110,12 @@ pub async fn encode_database<TPlat: platform::Platform>(
                 )
             })
             .collect(),
+        transactions: transactions_service
+            .pending_transactions()
+            .await
+            .iter()
+            .map(SerdeTransaction::from_saved)
+            .collect(),
     };
 
     // Cap the database length to the maximum size.
@@ -103,7 +126,7 @@ pub async fn encode_database<TPlat: platform::Platform>(
             return serialized;
         }
 
-        if database_draft.nodes.is_empty() {
+        if database_draft.nodes.is_empty() && database_draft.transactions.is_empty() {
             // Can't shrink the database anymore. Return the string `"<too-large>"` which will
             // fail to decode but will indicate what is wrong.
             let dummy_message = "<too-large>";
@@ -116,6 +139,13 @@ pub async fn encode_database<TPlat: platform::Platform>(
 
         // Try to reduce the size of the database.
 
+        // The transactions are only removed once all the nodes have been removed, as losing
+        // a pending transaction is more problematic than losing a node.
+        if database_draft.nodes.is_empty() {
+            database_draft.transactions.pop();
+            continue;
+        }
+
         // Remove half of the nodes.
         // Which nodes are removed doesn't really matter.
         let mut nodes_to_remove = cmp::max(1, database_draft.nodes.len() / 2);
@@ -165,10 +195,27 @@ pub fn decode_database(encoded: &str, block_number_bytes: usize) -> Result<Datab
         })
         .collect::<Vec<_>>();
 
+    // Since the genesis state root is only a cache, failing to decode it isn't an error.
+    let genesis_state_root = decoded.genesis_state_root.as_ref().and_then(|r| {
+        let storage_hash = <[u8; 32]>::try_from(hex::decode(&r.storage_hash).ok()?).ok()?;
+        let state_root = <[u8; 32]>::try_from(hex::decode(&r.state_root).ok()?).ok()?;
+        Some((storage_hash, state_root))
+    });
+
+    // Transactions that fail to decode, or whose hash doesn't match their content, are
+    // ignored.
+    let pending_transactions = decoded
+        .transactions
+        .iter()
+        .filter_map(SerdeTransaction::to_saved)
+        .collect();
+
     Ok(DatabaseContent {
         genesis_block_hash,
         chain_information,
         known_nodes,
+        genesis_state_root,
+        pending_transactions,
     })
 }
 
@@ -177,6 +224,245 @@ struct SerdeDatabase {
     /// Hexadecimal-encoded hash of the genesis block header. Has no `0x` prefix.
     #[serde(rename = "genesisHash")]
     genesis_hash: String,
+    /// Cache of the trie root hash of the genesis storage, as calculating it is expensive.
+    #[serde(
+        rename = "genesisStateRoot",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    genesis_state_root: Option<SerdeGenesisStateRoot>,
     chain: Box<serde_json::value::RawValue>,
     nodes: hashbrown::HashMap<String, Vec<String>, fnv::FnvBuildHasher>,
+    /// Transactions that were pending when the database was encoded.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    transactions: Vec<SerdeTransaction>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeTransaction {
+    /// Hexadecimal-encoded BLAKE2 hash of the transaction. Has no `0x` prefix.
+    hash: String,
+    /// Hexadecimal-encoded SCALE encoding of the transaction. Has no `0x` prefix.
+    transaction: String,
+    /// See [`transactions_service::SavedTransaction::submitted_block_number`].
+    #[serde(rename = "submittedBlock", default)]
+    submitted_block: u64,
+    /// See [`transactions_service::SavedTransaction::valid_until_block_number`].
+    #[serde(
+        rename = "validUntilBlock",
+        default,
+        skip_serializing_if = "Option::is_none"
+    )]
+    valid_until_block: Option<u64>,
+    /// See [`transactions_service::SavedTransaction::latest_status`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status: Option<SerdeTransactionStatus>,
+}
+
+impl SerdeTransaction {
+    fn from_saved(transaction: &transactions_service::SavedTransaction) -> Self {
+        SerdeTransaction {
+            hash: hex::encode(
+                blake2_rfc::blake2b::blake2b(32, &[], &transaction.scale_encoded).as_bytes(),
+            ),
+            transaction: hex::encode(&transaction.scale_encoded),
+            submitted_block: transaction.submitted_block_number,
+            valid_until_block: transaction.valid_until_block_number,
+            status: match &transaction.latest_status {
+                Some(transactions_service::TransactionStatus::Broadcast(peers)) => {
+                    Some(SerdeTransactionStatus::Broadcast {
+                        peers: peers.iter().map(|p| p.to_base58()).collect(),
+                    })
+                }
+                Some(transactions_service::TransactionStatus::IncludedBlockUpdate {
+                    block_hash: Some((block_hash, index)),
+                }) => Some(SerdeTransactionStatus::InBlock {
+                    block_hash: hex::encode(block_hash),
+                    index: *index,
+                }),
+                Some(transactions_service::TransactionStatus::IncludedBlockUpdate {
+                    block_hash: None,
+                }) => Some(SerdeTransactionStatus::NotInBlock),
+                _ => None,
+            },
+        }
+    }
+
+    /// Returns `None` if the transaction fails to decode or if its hash doesn't match its
+    /// content. A status that fails to decode is ignored.
+    fn to_saved(&self) -> Option<transactions_service::SavedTransaction> {
+        let scale_encoded = hex::decode(&self.transaction).ok()?;
+        let hash = hex::decode(&self.hash).ok()?;
+        if *hash != *blake2_rfc::blake2b::blake2b(32, &[], &scale_encoded).as_bytes() {
+            return None;
+        }
+
+        let latest_status = match &self.status {
+            Some(SerdeTransactionStatus::Broadcast { peers }) => {
+                Some(transactions_service::TransactionStatus::Broadcast(
+                    peers
+                        .iter()
+                        .filter_map(|p| p.parse::<PeerId>().ok())
+                        .collect(),
+                ))
+            }
+            Some(SerdeTransactionStatus::InBlock { block_hash, index }) => hex::decode(block_hash)
+                .ok()
+                .and_then(|h| <[u8; 32]>::try_from(h).ok())
+                .map(
+                    |block_hash| transactions_service::TransactionStatus::IncludedBlockUpdate {
+                        block_hash: Some((block_hash, *index)),
+                    },
+                ),
+            Some(SerdeTransactionStatus::NotInBlock) => Some(
+                transactions_service::TransactionStatus::IncludedBlockUpdate { block_hash: None },
+            ),
+            None => None,
+        };
+
+        Some(transactions_service::SavedTransaction {
+            scale_encoded,
+            submitted_block_number: self.submitted_block,
+            valid_until_block_number: self.valid_until_block,
+            latest_status,
+        })
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+#[serde(tag = "type")]
+enum SerdeTransactionStatus {
+    #[serde(rename = "broadcast")]
+    Broadcast {
+        /// Base58-encoded identities of the peers the transaction has been sent to.
+        peers: Vec<String>,
+    },
+    #[serde(rename = "inBlock")]
+    InBlock {
+        /// Hexadecimal-encoded hash of the block. Has no `0x` prefix.
+        #[serde(rename = "blockHash")]
+        block_hash: String,
+        /// Index of the transaction within the body of the block.
+        index: u32,
+    },
+    #[serde(rename = "notInBlock")]
+    NotInBlock,
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct SerdeGenesisStateRoot {
+    /// Hexadecimal-encoded hash of the genesis storage. Has no `0x` prefix.
+    #[serde(rename = "storageHash")]
+    storage_hash: String,
+    /// Hexadecimal-encoded trie root hash of the genesis storage. Has no `0x` prefix.
+    #[serde(rename = "stateRoot")]
+    state_root: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SerdeTransaction, SerdeTransactionStatus};
+    use crate::transactions_service::{SavedTransaction, TransactionStatus};
+    use smoldot::libp2p::peer_id;
+
+    fn round_trip(transaction: &SavedTransaction) -> SavedTransaction {
+        let encoded = serde_json::to_string(&SerdeTransaction::from_saved(transaction)).unwrap();
+        serde_json::from_str::<SerdeTransaction>(&encoded)
+            .unwrap()
+            .to_saved()
+            .unwrap()
+    }
+
+    #[test]
+    fn transaction_round_trip() {
+        let peer_id = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+
+        for latest_status in [
+            None,
+            Some(TransactionStatus::Broadcast(vec![peer_id.clone()])),
+            Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: Some(([5; 32], 3)),
+            }),
+            Some(TransactionStatus::IncludedBlockUpdate { block_hash: None }),
+        ] {
+            let transaction = SavedTransaction {
+                scale_encoded: vec![1, 2, 3, 4],
+                submitted_block_number: 1000,
+                valid_until_block_number: Some(1064),
+                latest_status: latest_status.clone(),
+            };
+
+            let decoded = round_trip(&transaction);
+            assert_eq!(decoded.scale_encoded, transaction.scale_encoded);
+            assert_eq!(decoded.submitted_block_number, 1000);
+            assert_eq!(decoded.valid_until_block_number, Some(1064));
+            match (decoded.latest_status, latest_status) {
+                (None, None) => {}
+                (
+                    Some(TransactionStatus::Broadcast(decoded)),
+                    Some(TransactionStatus::Broadcast(expected)),
+                ) => assert_eq!(decoded, expected),
+                (
+                    Some(TransactionStatus::IncludedBlockUpdate {
+                        block_hash: decoded,
+                    }),
+                    Some(TransactionStatus::IncludedBlockUpdate {
+                        block_hash: expected,
+                    }),
+                ) => assert_eq!(decoded, expected),
+                _ => panic!(),
+            }
+        }
+    }
+
+    #[test]
+    fn transaction_without_window_and_status_accepted() {
+        // Format of the transactions in databases encoded before the status and the window of
+        // blocks were stored.
+        let transaction = [1, 2, 3, 4];
+        let encoded = format!(
+            r#"{{"hash":"{}","transaction":"{}"}}"#,
+            hex::encode(blake2_rfc::blake2b::blake2b(32, &[], &transaction).as_bytes()),
+            hex::encode(transaction)
+        );
+
+        let decoded = serde_json::from_str::<SerdeTransaction>(&encoded)
+            .unwrap()
+            .to_saved()
+            .unwrap();
+        assert_eq!(decoded.scale_encoded, transaction);
+        assert_eq!(decoded.submitted_block_number, 0);
+        assert!(decoded.valid_until_block_number.is_none());
+        assert!(decoded.latest_status.is_none());
+    }
+
+    #[test]
+    fn transaction_with_wrong_hash_ignored() {
+        let mut encoded = SerdeTransaction::from_saved(&SavedTransaction {
+            scale_encoded: vec![1, 2, 3, 4],
+            submitted_block_number: 0,
+            valid_until_block_number: None,
+            latest_status: None,
+        });
+        encoded.hash = hex::encode([0; 32]);
+        assert!(encoded.to_saved().is_none());
+    }
+
+    #[test]
+    fn invalid_status_ignored() {
+        let mut encoded = SerdeTransaction::from_saved(&SavedTransaction {
+            scale_encoded: vec![1, 2, 3, 4],
+            submitted_block_number: 10,
+            valid_until_block_number: None,
+            latest_status: None,
+        });
+        encoded.status = Some(SerdeTransactionStatus::InBlock {
+            block_hash: "not hex".into(),
+            index: 0,
+        });
+
+        let decoded = encoded.to_saved().unwrap();
+        assert_eq!(decoded.submitted_block_number, 10);
+        assert!(decoded.latest_status.is_none());
+    }
 }
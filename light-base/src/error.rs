@@ -0,0 +1,89 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Categories of errors shared by all the services of the light client.
+//!
+//! Every error type that can be returned by the services provides a `kind()` method returning
+//! an [`ErrorKind`]. While the error types themselves contain precise information about what
+//! happened, the [`ErrorKind`] is meant to be matched on by API users that need to react
+//! differently depending on the cause of the problem, and is used to determine the JSON-RPC
+//! error code reported to JSON-RPC clients.
+
+use smoldot::json_rpc;
+
+/// Category of an error. See [the module-level documentation](self).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, derive_more::Display)]
+pub enum ErrorKind {
+    /// Not connected to any node capable of answering the request, or the nodes have failed to
+    /// answer.
+    #[display(fmt = "Network unreachable")]
+    NetworkUnreachable,
+    /// A proof sent by a node is invalid or doesn't contain the requested information.
+    #[display(fmt = "Invalid proof")]
+    InvalidProof,
+    /// A node has sent data that doesn't conform to the networking protocol.
+    #[display(fmt = "Peer misbehavior")]
+    PeerMisbehavior,
+    /// The runtime has failed to execute, or has returned an error.
+    #[display(fmt = "Runtime trap")]
+    RuntimeTrap,
+    /// A limit has been reached, such as the maximum number of subscriptions or the maximum
+    /// size of a request.
+    #[display(fmt = "Limit exceeded")]
+    LimitExceeded,
+    /// The requested block isn't known, or has been pruned.
+    #[display(fmt = "Unknown block")]
+    UnknownBlock,
+    /// The parameters provided by the API user are invalid.
+    #[display(fmt = "Invalid input")]
+    InvalidInput,
+    /// The chain or its runtime relies on a feature that isn't supported.
+    #[display(fmt = "Unsupported")]
+    Unsupported,
+}
+
+impl ErrorKind {
+    /// Returns the JSON-RPC error code corresponding to this kind of error.
+    ///
+    /// All the error codes are in the range reserved for implementation-defined server errors,
+    /// except for [`ErrorKind::InvalidInput`] which corresponds to the standard "Invalid params"
+    /// error code.
+    pub fn json_rpc_error_code(&self) -> i64 {
+        match self {
+            ErrorKind::NetworkUnreachable => -32000,
+            ErrorKind::InvalidProof => -32001,
+            ErrorKind::PeerMisbehavior => -32002,
+            ErrorKind::RuntimeTrap => -32003,
+            ErrorKind::LimitExceeded => -32004,
+            ErrorKind::UnknownBlock => -32005,
+            ErrorKind::Unsupported => -32006,
+            ErrorKind::InvalidInput => -32602,
+        }
+    }
+
+    /// Builds the JSON-RPC error object corresponding to this kind of error, using the given
+    /// message.
+    pub(crate) fn json_rpc_error<'a>(
+        &self,
+        message: &'a str,
+    ) -> json_rpc::parse::ErrorResponse<'a> {
+        match self {
+            ErrorKind::InvalidInput => json_rpc::parse::ErrorResponse::InvalidParams,
+            _ => json_rpc::parse::ErrorResponse::ServerError(self.json_rpc_error_code(), message),
+        }
+    }
+}
@@ -0,0 +1,304 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Estimation of the fees of transactions.
+//!
+//! The [`FeeEstimationService`] calls the `TransactionPaymentApi_query_info` runtime function
+//! against a block of the chain, and decodes its output no matter which version of the
+//! `TransactionPaymentApi` API the runtime implements.
+//!
+//! Contrary to the other services, the [`FeeEstimationService`] doesn't run any background task.
+//! Each call to [`FeeEstimationService::estimate_fee`] subscribes to the
+//! [`runtime_service::RuntimeService`] in order to pin the block to perform the call against,
+//! then drops this subscription once the call is finished.
+
+use crate::{error::ErrorKind, platform::Platform, runtime_service};
+
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use core::{
+    iter,
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use smoldot::{
+    executor::{host, runtime_host},
+    header,
+    informant::HashDisplay,
+    json_rpc::payment_info,
+};
+
+/// Configuration for a [`FeeEstimationService`].
+pub struct Config<TPlat: Platform> {
+    /// Name of the chain, for logging purposes.
+    ///
+    /// > **Note**: This name will be directly printed out. Any special character should already
+    /// >           have been filtered out from this name.
+    pub log_name: String,
+
+    /// Service responsible for tracking the runtime of the chain.
+    pub runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
+}
+
+/// See [the module-level documentation](..).
+pub struct FeeEstimationService<TPlat: Platform> {
+    /// Target to use for the logs.
+    log_target: String,
+
+    /// See [`Config::runtime_service`].
+    runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
+}
+
+impl<TPlat: Platform> FeeEstimationService<TPlat> {
+    /// Builds a new service.
+    pub fn new(config: Config<TPlat>) -> Self {
+        FeeEstimationService {
+            log_target: format!("fee-estimation-{}", config.log_name),
+            runtime_service: config.runtime_service,
+        }
+    }
+
+    /// Estimates the fees that the given SCALE-encoded transaction would cost if it was included
+    /// on top of the given block.
+    ///
+    /// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+    /// either the current finalized block or one of its non-finalized descendants.
+    pub async fn estimate_fee(
+        &self,
+        transaction: &[u8],
+        block_hash: Option<[u8; 32]>,
+    ) -> Result<FeeEstimate, EstimateFeeError> {
+        // The subscription pins all the blocks that it reports, which guarantees that the
+        // runtime of the target block can be accessed. Blocks are automatically unpinned when
+        // the subscription is destroyed at the end of this function.
+        let subscribe_all = self
+            .runtime_service
+            .subscribe_all("fee-estimation", 16, NonZeroUsize::new(32).unwrap())
+            .await;
+
+        let block_hash = {
+            let finalized_block_hash = header::hash_from_scale_encoded_header(
+                &subscribe_all.finalized_block_scale_encoded_header,
+            );
+            let mut non_finalized_hashes = subscribe_all
+                .non_finalized_blocks_ancestry_order
+                .iter()
+                .map(|b| {
+                    (
+                        header::hash_from_scale_encoded_header(&b.scale_encoded_header),
+                        b,
+                    )
+                });
+
+            match block_hash {
+                Some(hash) if hash == finalized_block_hash => hash,
+                Some(hash) => {
+                    if !non_finalized_hashes.any(|(h, _)| h == hash) {
+                        return Err(EstimateFeeError::UnknownBlock);
+                    }
+                    hash
+                }
+                None => non_finalized_hashes
+                    .find(|(_, b)| b.is_new_best)
+                    .map_or(finalized_block_hash, |(h, _)| h),
+            }
+        };
+
+        let runtime_lock = self
+            .runtime_service
+            .pinned_block_runtime_lock(subscribe_all.new_blocks.id(), &block_hash)
+            .await
+            .map_err(|_| EstimateFeeError::UnknownBlock)?;
+
+        let api_version = match runtime_lock.specification() {
+            Ok(spec) => spec.decode().apis.find_version("TransactionPaymentApi"),
+            Err(error) => {
+                return Err(EstimateFeeError::Call(
+                    runtime_service::RuntimeCallError::InvalidRuntime(error),
+                ))
+            }
+        };
+        let api_version = match api_version {
+            None => return Err(EstimateFeeError::ApiNotFound),
+            Some(v @ 1..=2) => v,
+            Some(v) => return Err(EstimateFeeError::ApiVersionUnknown { actual_version: v }),
+        };
+
+        let return_value = self.runtime_call(&runtime_lock, transaction).await?;
+        drop(subscribe_all);
+
+        let payment_info =
+            payment_info::decode(&return_value, api_version).map_err(EstimateFeeError::Decode)?;
+
+        log::debug!(
+            target: &self.log_target,
+            "Estimated fee of transaction at block {}: {}",
+            HashDisplay(&block_hash),
+            payment_info.partial_fee
+        );
+
+        Ok(FeeEstimate {
+            block_hash,
+            payment_info,
+        })
+    }
+
+    /// Performs the `TransactionPaymentApi_query_info` runtime call and returns its output.
+    async fn runtime_call(
+        &self,
+        runtime_lock: &runtime_service::RuntimeLock<TPlat>,
+        transaction: &[u8],
+    ) -> Result<Vec<u8>, EstimateFeeError> {
+        let call_parameters = payment_info::payment_info_parameters(transaction);
+
+        let (runtime_call_lock, virtual_machine) = runtime_lock
+            .start(
+                payment_info::PAYMENT_FEES_FUNCTION_NAME,
+                call_parameters.clone(),
+                4,
+                Duration::from_secs(4),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await
+            .map_err(EstimateFeeError::Call)?;
+
+        let mut runtime_call = match runtime_host::run(runtime_host::Config {
+            virtual_machine,
+            function_to_call: payment_info::PAYMENT_FEES_FUNCTION_NAME,
+            parameter: call_parameters,
+            main_trie_root_calculation_cache: None,
+            storage_main_trie_changes: Default::default(),
+            storage_child_tries_changes: Default::default(),
+            offchain_storage_changes: Default::default(),
+            max_log_level: 0,
+        }) {
+            Ok(vm) => vm,
+            Err((err, prototype)) => {
+                runtime_call_lock.unlock(prototype);
+                return Err(EstimateFeeError::StartError(err));
+            }
+        };
+
+        loop {
+            match runtime_call {
+                runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                    let output = success.virtual_machine.value().as_ref().to_vec();
+                    runtime_call_lock.unlock(success.virtual_machine.into_prototype());
+                    break Ok(output);
+                }
+                runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                    runtime_call_lock.unlock(error.prototype);
+                    break Err(EstimateFeeError::RuntimeError(error.detail));
+                }
+                runtime_host::RuntimeHostVm::StorageGet(get) => {
+                    let storage_value = runtime_call_lock.storage_entry(get.key().as_ref());
+                    let storage_value = match storage_value {
+                        Ok(v) => v,
+                        Err(err) => {
+                            runtime_call_lock.unlock(
+                                runtime_host::RuntimeHostVm::StorageGet(get).into_prototype(),
+                            );
+                            break Err(EstimateFeeError::Call(err));
+                        }
+                    };
+                    runtime_call =
+                        get.inject_value(storage_value.map(|(val, vers)| (iter::once(val), vers)));
+                }
+                runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    runtime_call = sig.verify_and_resume();
+                }
+                runtime_host::RuntimeHostVm::NextKey(nk) => {
+                    runtime_call_lock
+                        .unlock(runtime_host::RuntimeHostVm::NextKey(nk).into_prototype());
+                    break Err(EstimateFeeError::ForbiddenHostFunction);
+                }
+                runtime_host::RuntimeHostVm::PrefixKeys(pk) => {
+                    runtime_call_lock
+                        .unlock(runtime_host::RuntimeHostVm::PrefixKeys(pk).into_prototype());
+                    break Err(EstimateFeeError::ForbiddenHostFunction);
+                }
+                other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                    // TODO: child tries aren't supported by the runtime call lock
+                    runtime_call_lock.unlock(other.into_prototype());
+                    break Err(EstimateFeeError::ForbiddenHostFunction);
+                }
+            }
+        }
+    }
+}
+
+/// Successful outcome of [`FeeEstimationService::estimate_fee`].
+#[derive(Debug, Clone)]
+pub struct FeeEstimate {
+    /// Hash of the block the estimation has been performed against.
+    pub block_hash: [u8; 32],
+
+    /// Information returned by the runtime, normalized to not depend on the version of the
+    /// `TransactionPaymentApi` API.
+    pub payment_info: payment_info::PaymentInfo,
+}
+
+/// Error potentially returned by [`FeeEstimationService::estimate_fee`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum EstimateFeeError {
+    /// The requested block is neither the current finalized block nor one of its non-finalized
+    /// descendants.
+    #[display(fmt = "Unknown or obsolete block")]
+    UnknownBlock,
+    /// Error while performing the call proof request or accessing the runtime.
+    #[display(fmt = "{_0}")]
+    Call(runtime_service::RuntimeCallError),
+    /// Failed to start the runtime call.
+    #[display(fmt = "Failed to start runtime call: {_0}")]
+    StartError(host::StartErr),
+    /// The runtime call has failed.
+    #[display(fmt = "Runtime call failed: {_0}")]
+    RuntimeError(runtime_host::ErrorDetail),
+    /// The runtime has tried to enumerate storage keys, which isn't supported.
+    #[display(fmt = "Runtime call has accessed unsupported host functions")]
+    ForbiddenHostFunction,
+    /// The runtime doesn't implement the `TransactionPaymentApi` API.
+    #[display(fmt = "Runtime doesn't support TransactionPaymentApi")]
+    ApiNotFound,
+    /// The runtime implements a version of the `TransactionPaymentApi` API that isn't
+    /// supported.
+    #[display(fmt = "Unsupported TransactionPaymentApi version: {actual_version}")]
+    ApiVersionUnknown {
+        /// Version that the runtime supports.
+        actual_version: u32,
+    },
+    /// Failed to decode the output of the runtime call.
+    #[display(fmt = "Failed to decode runtime output: {_0}")]
+    Decode(payment_info::DecodeError),
+}
+
+impl EstimateFeeError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            EstimateFeeError::UnknownBlock => ErrorKind::UnknownBlock,
+            EstimateFeeError::Call(err) => err.kind(),
+            EstimateFeeError::StartError(_) | EstimateFeeError::RuntimeError(_) => {
+                ErrorKind::RuntimeTrap
+            }
+            EstimateFeeError::ForbiddenHostFunction
+            | EstimateFeeError::ApiNotFound
+            | EstimateFeeError::ApiVersionUnknown { .. }
+            | EstimateFeeError::Decode(_) => ErrorKind::Unsupported,
+        }
+    }
+}
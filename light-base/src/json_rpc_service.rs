@@ -38,14 +38,18 @@
 // TODO: re-review this once finished
 
 mod background;
+mod sub_utils;
+
+pub mod storage_subscriptions;
 
 use crate::{
-    network_service, platform::Platform, runtime_service, sync_service, transactions_service,
+    error::ErrorKind, metrics, network_service, platform::Platform, runtime_service, sync_service,
+    transactions_service,
 };
 
 use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
 use core::num::NonZeroU32;
-use futures::prelude::*;
+use futures::{channel::mpsc, prelude::*};
 use smoldot::{
     chain_spec,
     json_rpc::{self, requests_subscriptions},
@@ -60,15 +64,19 @@ pub struct Config {
     /// >           have been filtered out from this name.
     pub log_name: String,
 
+    /// Maximum number of clients that can be connected to the service simultaneously, including
+    /// the client of the [`Frontend`] returned by [`service`]. See [`Frontend::add_client`].
+    pub max_clients: NonZeroU32,
+
     /// Maximum number of JSON-RPC requests that can be added to a queue if it is not ready to be
     /// processed immediately. Any additional request will be immediately rejected.
     ///
     /// This parameter is necessary in order to prevent users from using up too much memory within
-    /// the client.
+    /// the client. Applies to each client individually.
     pub max_pending_requests: NonZeroU32,
 
     /// Maximum number of active subscriptions. Any additional subscription will be immediately
-    /// rejected.
+    /// rejected. Applies to each client individually.
     ///
     /// This parameter is necessary in order to prevent users from using up too much memory within
     /// the client.
@@ -85,6 +93,46 @@ pub struct Config {
     /// In combination with [`Config::max_parallel_requests`], this can increase or decrease
     /// the priority of updating subscriptions compared to answering requests.
     pub max_parallel_subscription_updates: NonZeroU32,
+
+    /// Restriction on the JSON-RPC methods that the service serves. Calling a method that isn't
+    /// allowed results in the same error as calling a method that doesn't exist.
+    pub methods_filter: MethodsFilter,
+}
+
+/// Restriction on the JSON-RPC methods that a JSON-RPC service serves.
+///
+/// Each pattern is either the exact name of a method, or a prefix followed with `*`. For
+/// example, `author_*` matches all the methods whose name starts with `author_`. Aliases are
+/// matched using the name of the method they are an alias of.
+#[derive(Debug, Clone, Default)]
+pub enum MethodsFilter {
+    /// All the methods are served.
+    #[default]
+    AllowAll,
+    /// Only the methods matching at least one of the patterns are served.
+    Allow(Vec<String>),
+    /// All the methods are served except for the ones matching at least one of the patterns.
+    Deny(Vec<String>),
+}
+
+impl MethodsFilter {
+    /// Returns `true` if the method with the given name is allowed by this filter.
+    pub fn is_allowed(&self, method_name: &str) -> bool {
+        let matches = |patterns: &[String]| {
+            patterns
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => method_name.starts_with(prefix),
+                    None => method_name == pattern,
+                })
+        };
+
+        match self {
+            MethodsFilter::AllowAll => true,
+            MethodsFilter::Allow(patterns) => matches(patterns),
+            MethodsFilter::Deny(patterns) => !matches(patterns),
+        }
+    }
 }
 
 /// Creates a new JSON-RPC service with the given configuration.
@@ -96,7 +144,7 @@ pub struct Config {
 pub fn service(config: Config) -> (Frontend, ServicePrototype) {
     let mut requests_subscriptions =
         requests_subscriptions::RequestsSubscriptions::new(requests_subscriptions::Config {
-            max_clients: 1,
+            max_clients: config.max_clients.get(),
             max_requests_per_client: config.max_pending_requests,
             max_subscriptions_per_client: config.max_subscriptions,
         });
@@ -112,7 +160,7 @@ pub fn service(config: Config) -> (Frontend, ServicePrototype) {
     // This calculation must be in sync with the part of the code that spawns the tasks. Assertions
     // are there in order to make sure that this is the case.
     let num_handles =
-        config.max_parallel_requests.get() + config.max_parallel_subscription_updates.get() + 1;
+        config.max_parallel_requests.get() + config.max_parallel_subscription_updates.get() + 2;
 
     let mut background_aborts = Vec::with_capacity(usize::try_from(num_handles).unwrap());
     let mut background_abort_registrations = Vec::with_capacity(background_aborts.capacity());
@@ -122,11 +170,14 @@ pub fn service(config: Config) -> (Frontend, ServicePrototype) {
         background_abort_registrations.push(reg);
     }
 
+    let (trim_caches_tx, trim_caches_rx) = mpsc::channel(0);
+
     let frontend = Frontend {
         log_target: log_target.clone(),
         requests_subscriptions: requests_subscriptions.clone(),
         client_id,
         background_aborts: Arc::from(background_aborts),
+        trim_caches_tx,
     };
 
     let prototype = ServicePrototype {
@@ -135,6 +186,8 @@ pub fn service(config: Config) -> (Frontend, ServicePrototype) {
         requests_subscriptions,
         max_parallel_requests: config.max_parallel_requests,
         max_parallel_subscription_updates: config.max_parallel_subscription_updates,
+        methods_filter: config.methods_filter,
+        trim_caches_rx,
     };
 
     (frontend, prototype)
@@ -163,6 +216,10 @@ pub struct Frontend {
     /// Handles to abort the background tasks that hold and process the
     /// [`Frontend::requests_subscriptions`].
     background_aborts: Arc<[future::AbortHandle]>,
+
+    /// Sending side of the channel used to ask the background to empty its caches. See
+    /// [`Frontend::trim_caches`].
+    trim_caches_tx: mpsc::Sender<()>,
 }
 
 impl Frontend {
@@ -212,6 +269,43 @@ impl Frontend {
         }
     }
 
+    /// Adds a new client to the service, and returns a [`Frontend`] that refers to this client.
+    ///
+    /// Each client has its own requests and subscriptions, and the limits passed in the
+    /// [`Config`] apply to each client individually. Requests sent through the returned
+    /// [`Frontend`] are responded to only through the returned [`Frontend`] and its clones.
+    ///
+    /// The client must later be removed using [`Frontend::remove_client`], otherwise it continues
+    /// counting towards [`Config::max_clients`] until the service is shut down.
+    pub async fn add_client(&self) -> Result<Frontend, requests_subscriptions::AddClientError> {
+        let client_id = self.requests_subscriptions.add_client().await?;
+        Ok(Frontend {
+            requests_subscriptions: self.requests_subscriptions.clone(),
+            client_id,
+            log_target: self.log_target.clone(),
+            background_aborts: self.background_aborts.clone(),
+            trim_caches_tx: self.trim_caches_tx.clone(),
+        })
+    }
+
+    /// Asks the service to shrink the caches that it maintains in order to reduce the number of
+    /// network requests, such as the runtimes of old blocks that JSON-RPC calls have targeted.
+    /// Only their most recently used entries are kept.
+    ///
+    /// The caches are shrunk in the background. Does nothing if a previous request hasn't been
+    /// processed yet.
+    pub fn trim_caches(&self) {
+        let _ = self.trim_caches_tx.clone().try_send(());
+    }
+
+    /// Removes the client this [`Frontend`] refers to from the service. All its requests and
+    /// subscriptions are cancelled, and the clones of this [`Frontend`] must no longer be used.
+    pub async fn remove_client(self) {
+        self.requests_subscriptions
+            .remove_client(&self.client_id)
+            .await;
+    }
+
     /// Waits until a JSON-RPC response has been generated, then returns it.
     ///
     /// If this function is called multiple times in parallel, the order in which the calls are
@@ -264,9 +358,15 @@ pub struct ServicePrototype {
     /// Value obtained through [`Config::max_parallel_subscription_updates`].
     max_parallel_subscription_updates: NonZeroU32,
 
+    /// Value obtained through [`Config::methods_filter`].
+    methods_filter: MethodsFilter,
+
     /// List of abort handles. When tasks are spawned, each handle is associated with a task, so
     /// that they can all be aborted. See [`Frontend::background_aborts`].
     background_abort_registrations: Vec<future::AbortRegistration>,
+
+    /// Receiving side of [`Frontend::trim_caches_tx`].
+    trim_caches_rx: mpsc::Receiver<()>,
 }
 
 /// Configuration for a JSON-RPC service.
@@ -287,6 +387,9 @@ pub struct StartConfig<'a, TPlat: Platform> {
     /// Service that provides a ready-to-be-called runtime for the current best block.
     pub runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
 
+    /// Storage subscriptions shared with the other JSON-RPC services of the same chain.
+    pub storage_subscriptions: Arc<storage_subscriptions::StorageSubscriptions<TPlat>>,
+
     /// Specification of the chain.
     pub chain_spec: &'a chain_spec::ChainSpec,
 
@@ -318,19 +421,27 @@ pub struct StartConfig<'a, TPlat: Platform> {
     /// >           expensive. We prefer to require this value from the upper layer instead, as
     /// >           it is most likely needed anyway.
     pub genesis_block_state_root: [u8; 32],
+
+    /// Hash of the content of the genesis storage, if the chain specification contains it. See
+    /// [`chain_spec::GenesisStorageItems::content_hash`].
+    ///
+    /// Stored in the database alongside with [`StartConfig::genesis_block_state_root`] in order
+    /// to not have to calculate the latter again.
+    pub genesis_storage_hash: Option<[u8; 32]>,
+
+    /// Metrics of the chain, where the durations of the JSON-RPC requests are recorded.
+    pub metrics: Arc<metrics::ChainMetrics>,
+
+    /// If `true`, the service also serves the `eth_*` JSON-RPC functions of chains based on
+    /// Frontier. Calling these functions otherwise results in the same error as calling a
+    /// function that doesn't exist.
+    pub ethereum_json_rpc: bool,
 }
 
 impl ServicePrototype {
     /// Consumes this prototype and starts the service through [`StartConfig::tasks_executor`].
     pub fn start<TPlat: Platform>(self, config: StartConfig<'_, TPlat>) {
-        background::start(
-            self.log_target.clone(),
-            self.requests_subscriptions.clone(),
-            config,
-            self.max_parallel_requests,
-            self.max_parallel_subscription_updates,
-            self.background_abort_registrations,
-        )
+        background::start(self, config)
     }
 }
 
@@ -351,6 +462,14 @@ pub enum HandleRpcError {
 }
 
 impl HandleRpcError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            HandleRpcError::Overloaded { .. } => ErrorKind::LimitExceeded,
+            HandleRpcError::MalformedJsonRpc(_) => ErrorKind::InvalidInput,
+        }
+    }
+
     /// Builds the JSON-RPC error string corresponding to this error.
     ///
     /// Returns `None` if the JSON-RPC requests isn't valid JSON-RPC or if the call was a
@@ -366,7 +485,7 @@ impl HandleRpcError {
                 id_json: Some(id), ..
             }) => Some(json_rpc::parse::build_error_response(
                 id,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, "Too busy"),
+                ErrorKind::LimitExceeded.json_rpc_error("Too busy"),
                 None,
             )),
             Ok(json_rpc::parse::Call { id_json: None, .. }) | Err(_) => None,
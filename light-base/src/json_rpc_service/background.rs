@@ -16,10 +16,11 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 use crate::{
-    network_service, platform::Platform, runtime_service, sync_service, transactions_service,
+    error::ErrorKind, metrics, network_service, platform::Platform, runtime_service, sync_service,
+    transactions_service,
 };
 
-use super::StartConfig;
+use super::{storage_subscriptions, sub_utils, StartConfig};
 
 use alloc::{
     borrow::ToOwned as _,
@@ -37,14 +38,17 @@ use core::{
 };
 use futures::{lock::Mutex, prelude::*};
 use smoldot::{
-    executor::{host, runtime_host},
+    executor::{host, runtime_host, storage_diff},
     header,
+    informant::HashDisplay,
     json_rpc::{self, methods, requests_subscriptions},
     libp2p::{multiaddr, PeerId},
     network::protocol,
+    util,
 };
 
 mod chain_head;
+mod ethereum;
 mod getters;
 mod state_chain;
 mod transactions;
@@ -84,6 +88,8 @@ struct Background<TPlat: Platform> {
     runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
     /// See [`StartConfig::transactions_service`].
     transactions_service: Arc<transactions_service::TransactionsService<TPlat>>,
+    /// See [`StartConfig::storage_subscriptions`].
+    storage_subscriptions: Arc<storage_subscriptions::StorageSubscriptions<TPlat>>,
 
     /// Various information caches about blocks, to potentially reduce the number of network
     /// requests to perform.
@@ -94,9 +100,22 @@ struct Background<TPlat: Platform> {
     /// transaction signatures, and must therefore be queried by upper-level UIs.
     genesis_block_hash: [u8; 32],
 
+    /// Hash of the genesis storage and trie root hash of the genesis storage, if the chain
+    /// specification contains the genesis storage. Stored in the database.
+    genesis_state_root: Option<([u8; 32], [u8; 32])>,
+
     /// If `true`, we have already printed a warning about usage of the legacy JSON-RPC API. This
     /// flag prevents printing this message multiple times.
     printed_legacy_json_rpc_warning: atomic::AtomicBool,
+
+    /// See [`super::Config::methods_filter`].
+    methods_filter: super::MethodsFilter,
+
+    /// See [`super::StartConfig::ethereum_json_rpc`].
+    ethereum_json_rpc: bool,
+
+    /// See [`super::StartConfig::metrics`].
+    metrics: Arc<metrics::ChainMetrics>,
 }
 
 pub(super) enum SubscriptionMessage {
@@ -137,6 +156,11 @@ pub(super) enum SubscriptionMessage {
         hash: methods::HashHexString,
         unpin_request_id: (String, requests_subscriptions::RequestId),
     },
+    ChainHeadFollowPrefetchHint {
+        extrinsic_hashes: Vec<[u8; 32]>,
+        storage_keys: Vec<Vec<u8>>,
+        hint_request_id: (String, requests_subscriptions::RequestId),
+    },
     ChainHeadHeader {
         hash: methods::HashHexString,
         get_request_id: (String, requests_subscriptions::RequestId),
@@ -210,16 +234,31 @@ struct Cache {
     /// The keys are `(block_hash, prefix)` and values are list of keys.
     state_get_keys_paged:
         lru::LruCache<([u8; 32], Option<methods::HexString>), Vec<Vec<u8>>, fnv::FnvBuildHasher>,
+
+    /// Runtimes of blocks that were not in [`Cache::recent_pinned_blocks`], and whose runtime
+    /// had to be downloaded from the network. The runtimes are kept pinned in the runtime
+    /// service, and are unpinned when removed from the cache.
+    ///
+    /// Downloading a runtime is expensive, and when a JSON-RPC client performs a call on an old
+    /// block it is common for it to perform several calls on that same block.
+    historical_runtimes:
+        lru::LruCache<[u8; 32], runtime_service::PinnedRuntimeId, fnv::FnvBuildHasher>,
 }
 
 pub(super) fn start<TPlat: Platform>(
-    log_target: String,
-    requests_subscriptions: Arc<requests_subscriptions::RequestsSubscriptions<SubscriptionMessage>>,
+    prototype: super::ServicePrototype,
     mut config: StartConfig<'_, TPlat>,
-    max_parallel_requests: NonZeroU32,
-    max_parallel_subscription_updates: NonZeroU32,
-    background_abort_registrations: Vec<future::AbortRegistration>,
 ) {
+    let super::ServicePrototype {
+        log_target,
+        requests_subscriptions,
+        max_parallel_requests,
+        max_parallel_subscription_updates,
+        methods_filter,
+        background_abort_registrations,
+        mut trim_caches_rx,
+    } = prototype;
+
     let me = Arc::new(Background {
         log_target,
         requests_subscriptions,
@@ -234,6 +273,7 @@ pub(super) fn start<TPlat: Platform>(
         sync_service: config.sync_service.clone(),
         runtime_service: config.runtime_service.clone(),
         transactions_service: config.transactions_service.clone(),
+        storage_subscriptions: config.storage_subscriptions.clone(),
         cache: Mutex::new(Cache {
             recent_pinned_blocks: lru::LruCache::with_hasher(
                 NonZeroUsize::new(32).unwrap(),
@@ -248,9 +288,19 @@ pub(super) fn start<TPlat: Platform>(
                 NonZeroUsize::new(2).unwrap(),
                 Default::default(),
             ),
+            historical_runtimes: lru::LruCache::with_hasher(
+                NonZeroUsize::new(4).unwrap(),
+                Default::default(),
+            ),
         }),
         genesis_block_hash: config.genesis_block_hash,
+        genesis_state_root: config
+            .genesis_storage_hash
+            .map(|storage_hash| (storage_hash, config.genesis_block_state_root)),
         printed_legacy_json_rpc_warning: atomic::AtomicBool::new(false),
+        methods_filter,
+        ethereum_json_rpc: config.ethereum_json_rpc,
+        metrics: config.metrics.clone(),
     });
 
     let mut background_abort_registrations = background_abort_registrations.into_iter();
@@ -303,6 +353,21 @@ pub(super) fn start<TPlat: Platform>(
         );
     }
 
+    // Spawn one task dedicated to emptying the `Cache` when requested by the frontend.
+    (config.tasks_executor)(format!("{}-cache-trim", me.log_target), {
+        let me = me.clone();
+        future::Abortable::new(
+            async move {
+                while trim_caches_rx.next().await.is_some() {
+                    me.trim_cache().await;
+                }
+            },
+            background_abort_registrations.next().unwrap(),
+        )
+        .map(|_: Result<(), _>| ())
+        .boxed()
+    });
+
     // Spawn one task dedicated to filling the `Cache` with new blocks from the runtime
     // service.
     // TODO: this is actually racy, as a block subscription task could report a new block to a client, and then client can query it, before this block has been been added to the cache
@@ -399,6 +464,51 @@ impl<TPlat: Platform> Background<TPlat> {
     async fn handle_request(self: &Arc<Self>) {
         let (json_rpc_request, state_machine_request_id) =
             self.requests_subscriptions.next_request().await;
+
+        let before_processing = TPlat::now();
+        self.process_request(json_rpc_request, state_machine_request_id)
+            .await;
+        self.metrics
+            .json_rpc_requests
+            .record(TPlat::now() - before_processing);
+    }
+
+    /// Evicts the least recently used entries of the caches of [`Background::cache`] that only
+    /// serve to reduce the number of network requests, and unpins the runtimes evicted from
+    /// [`Cache::historical_runtimes`].
+    ///
+    /// Only the most recently used entries are kept, so that a JSON-RPC client that resumes its
+    /// activity doesn't have to download again the block or runtime it was working with.
+    /// [`Cache::recent_pinned_blocks`] is left untouched, as it mirrors the blocks pinned in the
+    /// runtime service.
+    async fn trim_cache(&self) {
+        let evicted_runtimes = {
+            let mut cache = self.cache.lock().await;
+            while cache.block_state_root_hashes_numbers.len() > 4 {
+                cache.block_state_root_hashes_numbers.pop_lru();
+            }
+            while cache.state_get_keys_paged.len() > 1 {
+                cache.state_get_keys_paged.pop_lru();
+            }
+            let mut runtimes = Vec::new();
+            while cache.historical_runtimes.len() > 1 {
+                let (_, runtime) = cache.historical_runtimes.pop_lru().unwrap();
+                runtimes.push(runtime);
+            }
+            runtimes
+        };
+
+        for runtime in evicted_runtimes {
+            self.runtime_service.unpin_runtime(runtime).await;
+        }
+    }
+
+    /// Processes a request pulled from the inner state machine.
+    async fn process_request(
+        self: &Arc<Self>,
+        json_rpc_request: String,
+        state_machine_request_id: requests_subscriptions::RequestId,
+    ) {
         log::debug!(target: &self.log_target, "PendingRequestsQueue => {}",
             crate::util::truncated_str(
                 json_rpc_request.chars().filter(|c| !c.is_control()),
@@ -426,6 +536,24 @@ impl<TPlat: Platform> Background<TPlat> {
             }
         };
 
+        // Methods that aren't allowed are treated the same way as methods that don't exist.
+        // The same goes for the Ethereum methods if they aren't enabled.
+        if !self.methods_filter.is_allowed(call.name())
+            || (!self.ethereum_json_rpc && call.name().starts_with("eth_"))
+        {
+            log::debug!(
+                target: &self.log_target,
+                "Refused call to {} due to the methods filter", call.name()
+            );
+            self.requests_subscriptions
+                .respond(
+                    &state_machine_request_id,
+                    methods::MethodError::UnknownMethod(call.name()).to_json_error(request_id),
+                )
+                .await;
+            return;
+        }
+
         // Print a warning for legacy JSON-RPC functions.
         match call {
             methods::MethodCall::account_nextIndex { .. }
@@ -453,6 +581,10 @@ impl<TPlat: Platform> Background<TPlat> {
             | methods::MethodCall::childstate_getStorage { .. }
             | methods::MethodCall::childstate_getStorageHash { .. }
             | methods::MethodCall::childstate_getStorageSize { .. }
+            | methods::MethodCall::eth_blockNumber { .. }
+            | methods::MethodCall::eth_chainId { .. }
+            | methods::MethodCall::eth_getBalance { .. }
+            | methods::MethodCall::eth_sendRawTransaction { .. }
             | methods::MethodCall::grandpa_roundState { .. }
             | methods::MethodCall::offchain_localStorageGet { .. }
             | methods::MethodCall::offchain_localStorageSet { .. }
@@ -526,7 +658,15 @@ impl<TPlat: Platform> Background<TPlat> {
             | methods::MethodCall::transaction_unstable_unwatch { .. }
             | methods::MethodCall::network_unstable_subscribeEvents { .. }
             | methods::MethodCall::network_unstable_unsubscribeEvents { .. }
-            | methods::MethodCall::chainHead_unstable_finalizedDatabase { .. } => {}
+            | methods::MethodCall::chainHead_unstable_finalizedDatabase { .. }
+            | methods::MethodCall::chainHead_unstable_prefetchHint { .. }
+            | methods::MethodCall::sync_unstable_finalityDiagnostics { .. }
+            | methods::MethodCall::grandpa_unstable_authoritySet { .. }
+            | methods::MethodCall::grandpa_unstable_finalityProof { .. }
+            | methods::MethodCall::babe_unstable_epochs { .. }
+            | methods::MethodCall::state_unstable_traceBlock { .. }
+            | methods::MethodCall::state_unstable_runtimeApis { .. }
+            | methods::MethodCall::system_unstable_health { .. } => {}
         }
 
         // Each call is handled in a separate method.
@@ -603,6 +743,29 @@ impl<TPlat: Platform> Background<TPlat> {
                 )
                 .await;
             }
+            methods::MethodCall::eth_blockNumber {} => {
+                self.eth_block_number((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::eth_chainId {} => {
+                self.eth_chain_id((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::eth_getBalance { address, block } => {
+                self.eth_get_balance(
+                    (request_id, &state_machine_request_id),
+                    &address.0,
+                    block.as_deref(),
+                )
+                .await;
+            }
+            methods::MethodCall::eth_sendRawTransaction { transaction } => {
+                self.eth_send_raw_transaction(
+                    (request_id, &state_machine_request_id),
+                    &transaction.0,
+                )
+                .await;
+            }
             methods::MethodCall::payment_queryInfo { extrinsic, hash } => {
                 self.payment_query_info(
                     (request_id, &state_machine_request_id),
@@ -847,6 +1010,57 @@ impl<TPlat: Platform> Background<TPlat> {
                 )
                 .await;
             }
+            methods::MethodCall::chainHead_unstable_prefetchHint {
+                follow_subscription,
+                extrinsic_hashes,
+                storage_keys,
+            } => {
+                self.chain_head_unstable_prefetch_hint(
+                    (request_id, &state_machine_request_id),
+                    &follow_subscription,
+                    extrinsic_hashes,
+                    storage_keys,
+                )
+                .await;
+            }
+            methods::MethodCall::babe_unstable_epochs {} => {
+                self.babe_unstable_epochs((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::grandpa_unstable_authoritySet {} => {
+                self.grandpa_unstable_authority_set((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::grandpa_unstable_finalityProof { hash } => {
+                self.grandpa_unstable_finality_proof((request_id, &state_machine_request_id), hash)
+                    .await;
+            }
+            methods::MethodCall::sync_unstable_finalityDiagnostics {} => {
+                self.sync_unstable_finality_diagnostics((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::system_unstable_health {} => {
+                self.system_unstable_health((request_id, &state_machine_request_id))
+                    .await;
+            }
+            methods::MethodCall::state_unstable_traceBlock {
+                hash,
+                extrinsic_index,
+            } => {
+                self.state_unstable_trace_block(
+                    (request_id, &state_machine_request_id),
+                    hash.0,
+                    extrinsic_index,
+                )
+                .await;
+            }
+            methods::MethodCall::state_unstable_runtimeApis { at } => {
+                self.state_unstable_runtime_apis(
+                    (request_id, &state_machine_request_id),
+                    at.as_ref().map(|h| &h.0),
+                )
+                .await;
+            }
             methods::MethodCall::chainSpec_unstable_chainName {} => {
                 self.chain_spec_unstable_chain_name((request_id, &state_machine_request_id))
                     .await;
@@ -918,10 +1132,7 @@ impl<TPlat: Platform> Background<TPlat> {
                         &state_machine_request_id,
                         json_rpc::parse::build_error_response(
                             request_id,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Not implemented in smoldot yet",
-                            ),
+                            ErrorKind::Unsupported.json_rpc_error("Not implemented in smoldot yet"),
                             None,
                         ),
                     )
@@ -982,6 +1193,41 @@ impl<TPlat: Platform> Background<TPlat> {
             .await;
     }
 
+    /// Handles a call to [`methods::MethodCall::state_unstable_traceBlock`].
+    async fn state_unstable_trace_block(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        block_hash: [u8; 32],
+        extrinsic_index: Option<u32>,
+    ) {
+        let response = match self.trace_block(&block_hash, extrinsic_index).await {
+            Ok(trace) => {
+                methods::Response::state_unstable_traceBlock(trace).to_json_response(request_id.0)
+            }
+            Err(TraceBlockError::ExtrinsicIndexOutOfRange) => {
+                json_rpc::parse::build_error_response(
+                    request_id.0,
+                    json_rpc::parse::ErrorResponse::InvalidParams,
+                    Some(
+                        &serde_json::to_string(
+                            &TraceBlockError::ExtrinsicIndexOutOfRange.to_string(),
+                        )
+                        .unwrap(),
+                    ),
+                )
+            }
+            Err(error) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+        };
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
     /// Obtain the state trie root hash and number of the given block, and make sure to put it
     /// in cache.
     async fn state_trie_root_hash(
@@ -1104,6 +1350,61 @@ impl<TPlat: Platform> Background<TPlat> {
         Ok(result)
     }
 
+    /// Similar to [`Background::storage_query`], but performs the query against the current best
+    /// block. Returns the hash of the block the storage values have been obtained from.
+    ///
+    /// If the query fails and the best block has changed in the meantime, the query is started
+    /// again against the new best block. This covers the situation where the block has been
+    /// pruned by the peers while the query was in progress, which the JSON-RPC client can't do
+    /// anything about as it didn't choose the block.
+    async fn storage_query_best_block(
+        &self,
+        keys: impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+        total_attempts: u32,
+        timeout_per_request: Duration,
+        max_parallel: NonZeroU32,
+    ) -> Result<([u8; 32], Vec<Option<Vec<u8>>>), StorageQueryError> {
+        let mut block_hash = header::hash_from_scale_encoded_header(
+            sub_utils::subscribe_best(&self.runtime_service).await.0,
+        );
+        let mut redispatches_remaining = MAX_STORAGE_QUERY_REDISPATCHES;
+
+        loop {
+            let error = match self
+                .storage_query(
+                    keys.clone(),
+                    &block_hash,
+                    total_attempts,
+                    timeout_per_request,
+                    max_parallel,
+                )
+                .await
+            {
+                Ok(values) => return Ok((block_hash, values)),
+                Err(error) => error,
+            };
+
+            let new_best_block_hash = header::hash_from_scale_encoded_header(
+                sub_utils::subscribe_best(&self.runtime_service).await.0,
+            );
+            if new_best_block_hash == block_hash || redispatches_remaining == 0 {
+                return Err(error);
+            }
+
+            log::debug!(
+                target: &self.log_target,
+                "Storage query against block {} failed ({}). Trying again against new best \
+                block {}.",
+                HashDisplay(&block_hash),
+                error,
+                HashDisplay(&new_best_block_hash)
+            );
+
+            block_hash = new_best_block_hash;
+            redispatches_remaining -= 1;
+        }
+    }
+
     /// Obtain a lock to the runtime of the given block against the runtime service.
     // TODO: return better error?
     async fn runtime_lock(
@@ -1131,9 +1432,8 @@ impl<TPlat: Platform> Background<TPlat> {
             // Second situation: the block is not in the cache of recent blocks. This isn't great.
             drop::<futures::lock::MutexGuard<_>>(cache_lock);
 
-            // The only solution is to download the runtime of the block in question from the network.
-
-            // TODO: considering caching the runtime code the same way as the state trie root hash
+            // The only solution is to download the runtime of the block in question from the
+            // network, unless it has been downloaded recently.
 
             // In order to grab the runtime code and perform the call network request, we need
             // to know the state trie root hash and the height of the block.
@@ -1142,6 +1442,26 @@ impl<TPlat: Platform> Background<TPlat> {
                 .await
                 .map_err(RuntimeCallError::FindStorageRootHashError)?;
 
+            // Look in the cache of runtimes of old blocks.
+            let cached_runtime = self
+                .cache
+                .lock()
+                .await
+                .historical_runtimes
+                .get(block_hash)
+                .cloned();
+            if let Some(pinned_runtime_id) = cached_runtime {
+                return Ok(self
+                    .runtime_service
+                    .pinned_runtime_lock(
+                        pinned_runtime_id,
+                        *block_hash,
+                        block_number,
+                        state_trie_root_hash,
+                    )
+                    .await);
+            }
+
             // Download the runtime of this block. This takes a long time as the runtime is rather
             // big (around 1MiB in general).
             let (storage_code, storage_heap_pages) = {
@@ -1182,8 +1502,17 @@ impl<TPlat: Platform> Background<TPlat> {
                 )
                 .await;
 
-            // TODO: consider keeping pinned runtimes in a cache instead
-            self.runtime_service.unpin_runtime(pinned_runtime_id).await;
+            // Keep the runtime pinned in the cache, and unpin the runtime that gets evicted, if
+            // any.
+            let evicted = self
+                .cache
+                .lock()
+                .await
+                .historical_runtimes
+                .push(*block_hash, pinned_runtime_id);
+            if let Some((_, evicted)) = evicted {
+                self.runtime_service.unpin_runtime(evicted).await;
+            }
 
             precall
         })
@@ -1298,6 +1627,7 @@ impl<TPlat: Platform> Background<TPlat> {
             parameter: call_parameters,
             main_trie_root_calculation_cache: None,
             storage_main_trie_changes: Default::default(),
+            storage_child_tries_changes: Default::default(),
             offchain_storage_changes: Default::default(),
             max_log_level: 0,
         }) {
@@ -1348,11 +1678,292 @@ impl<TPlat: Platform> Background<TPlat> {
                         .unlock(runtime_host::RuntimeHostVm::PrefixKeys(pk).into_prototype());
                     break Err(RuntimeCallError::PrefixKeysForbidden);
                 }
+                other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                    // TODO:
+                    runtime_call_lock.unlock(other.into_prototype());
+                    break Err(RuntimeCallError::ChildTriesForbidden);
+                }
             }
         }
     }
 }
 
+impl<TPlat: Platform> Background<TPlat> {
+    /// Executes again the block with the given hash on top of the storage of its parent, and
+    /// returns the storage accesses and logs of each step of the execution.
+    ///
+    /// Rather than a single call to `Core_execute_block`, the block is executed as a call to
+    /// `Core_initialize_block` followed with one call to `BlockBuilder_apply_extrinsic` per
+    /// extrinsic, so that each access can be attributed to the extrinsic that performs it. If
+    /// `up_to_extrinsic` is `Some`, the execution stops after the extrinsic at this index, and
+    /// only the step of this extrinsic is reported.
+    ///
+    /// The storage items are read from a call proof of `Core_execute_block`. The call to
+    /// `BlockBuilder_finalize_block` is never performed, as it requires calculating the storage
+    /// trie root, which can't be done with a proof that only contains the storage items accessed
+    /// during the execution.
+    async fn trace_block(
+        self: &Arc<Self>,
+        block_hash: &[u8; 32],
+        up_to_extrinsic: Option<u32>,
+    ) -> Result<methods::BlockTrace, TraceBlockError> {
+        let block = self
+            .sync_service
+            .clone()
+            .block_query_unknown_number(
+                *block_hash,
+                protocol::BlocksRequestFields {
+                    header: true,
+                    body: true,
+                    justifications: false,
+                },
+                3,
+                Duration::from_secs(8),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .map_err(|()| TraceBlockError::BlockQuery)?;
+
+        // The `block_query` function guarantees that the header and body are present and
+        // are correct.
+        let scale_encoded_header = block.header.unwrap();
+        let body = block.body.unwrap();
+        let decoded_header = header::decode(
+            &scale_encoded_header,
+            self.sync_service.block_number_bytes(),
+        )
+        .unwrap();
+
+        if decoded_header.number == 0 {
+            return Err(TraceBlockError::GenesisBlock);
+        }
+
+        let num_extrinsics = match up_to_extrinsic {
+            Some(index) if usize::try_from(index).map_or(true, |i| i >= body.len()) => {
+                return Err(TraceBlockError::ExtrinsicIndexOutOfRange)
+            }
+            Some(index) => usize::try_from(index).unwrap() + 1,
+            None => body.len(),
+        };
+
+        // Consensus engines add a seal at the end of the digest logs. This seal must be removed
+        // before the header can be passed to the runtime.
+        let unsealed_header = {
+            let mut unsealed_header = decoded_header.clone();
+            let _seal_log = unsealed_header.digest.pop_seal();
+            unsealed_header
+                .scale_encoding(self.sync_service.block_number_bytes())
+                .fold(Vec::new(), |mut a, b| {
+                    a.extend_from_slice(b.as_ref());
+                    a
+                })
+        };
+
+        // The call proof is requested for `Core_execute_block`, as full nodes are expected to
+        // access the same storage items as the sequence of calls performed below.
+        let execute_block_parameter = {
+            let mut param = unsealed_header.clone();
+            param.extend_from_slice(util::encode_scale_compact_usize(body.len()).as_ref());
+            for extrinsic in &body {
+                param.extend_from_slice(extrinsic);
+            }
+            param
+        };
+
+        let precall = self
+            .runtime_lock(decoded_header.parent_hash)
+            .await
+            .map_err(TraceBlockError::RuntimeLock)?;
+        let (runtime_call_lock, mut virtual_machine) = precall
+            .start(
+                "Core_execute_block",
+                iter::once(&execute_block_parameter),
+                3,
+                Duration::from_secs(20),
+                NonZeroU32::new(3).unwrap(),
+            )
+            .await
+            .map_err(TraceBlockError::Call)?;
+
+        let calls = iter::once(("Core_initialize_block", &unsealed_header, None)).chain(
+            body.iter()
+                .take(num_extrinsics)
+                .enumerate()
+                .map(|(index, extrinsic)| {
+                    (
+                        "BlockBuilder_apply_extrinsic",
+                        extrinsic,
+                        Some(u32::try_from(index).unwrap()),
+                    )
+                }),
+        );
+
+        let mut storage_main_trie_changes = storage_diff::TrieDiff::empty();
+        let mut offchain_storage_changes = storage_diff::TrieDiff::empty();
+        let mut steps = Vec::with_capacity(num_extrinsics + 1);
+
+        for (function_to_call, parameter, extrinsic_index) in calls {
+            let mut step = methods::BlockTraceStep {
+                extrinsic_index,
+                reads: Vec::new(),
+                writes: Vec::new(),
+                logs: String::new(),
+                output: None,
+                error: None,
+            };
+
+            let previous_changes = storage_main_trie_changes.clone();
+            let mut runtime_call = match runtime_host::run(runtime_host::Config {
+                virtual_machine,
+                function_to_call,
+                parameter: iter::once(parameter),
+                main_trie_root_calculation_cache: None,
+                storage_main_trie_changes,
+                storage_child_tries_changes: Default::default(),
+                offchain_storage_changes,
+                max_log_level: 5,
+            }) {
+                Ok(vm) => vm,
+                Err((error, prototype)) => {
+                    virtual_machine = prototype;
+                    step.error = Some(error.to_string());
+                    steps.push(step);
+                    break;
+                }
+            };
+
+            let (prototype, success) = loop {
+                match runtime_call {
+                    runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                        step.output = Some(methods::HexString(
+                            success.virtual_machine.value().as_ref().to_vec(),
+                        ));
+                        step.logs = success.logs;
+                        break (
+                            success.virtual_machine.into_prototype(),
+                            Some((
+                                success.storage_main_trie_changes,
+                                success.offchain_storage_changes,
+                            )),
+                        );
+                    }
+                    runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                        step.error = Some(error.detail.to_string());
+                        break (error.prototype, None);
+                    }
+                    runtime_host::RuntimeHostVm::StorageGet(get) => {
+                        let storage_value = runtime_call_lock.storage_entry(get.key().as_ref());
+                        let storage_value = match storage_value {
+                            Ok(v) => v,
+                            Err(error) => {
+                                step.error = Some(error.to_string());
+                                break (
+                                    runtime_host::RuntimeHostVm::StorageGet(get).into_prototype(),
+                                    None,
+                                );
+                            }
+                        };
+                        step.reads.push((
+                            methods::HexString(get.key().as_ref().to_vec()),
+                            storage_value.map(|(value, _)| methods::HexString(value.to_vec())),
+                        ));
+                        runtime_call = get
+                            .inject_value(storage_value.map(|(val, vers)| (iter::once(val), vers)));
+                    }
+                    runtime_host::RuntimeHostVm::PrefixKeys(pk) => {
+                        let prefix = pk.prefix().as_ref().to_vec();
+                        let keys = match runtime_call_lock.storage_prefix_keys_ordered(&prefix) {
+                            Ok(keys) => keys,
+                            Err(error) => {
+                                step.error = Some(error.to_string());
+                                break (
+                                    runtime_host::RuntimeHostVm::PrefixKeys(pk).into_prototype(),
+                                    None,
+                                );
+                            }
+                        };
+                        runtime_call = pk.inject_keys_ordered(keys);
+                    }
+                    runtime_host::RuntimeHostVm::NextKey(nk) if nk.child_trie().is_some() => {
+                        // TODO: child tries aren't supported by the runtime call lock
+                        step.error = Some(RuntimeCallError::ChildTriesForbidden.to_string());
+                        break (
+                            runtime_host::RuntimeHostVm::NextKey(nk).into_prototype(),
+                            None,
+                        );
+                    }
+                    runtime_host::RuntimeHostVm::NextKey(nk) => {
+                        let next_key = runtime_call_lock.storage_next_key(nk.key().as_ref());
+                        let next_key = match next_key {
+                            Ok(k) => k,
+                            Err(error) => {
+                                step.error = Some(error.to_string());
+                                break (
+                                    runtime_host::RuntimeHostVm::NextKey(nk).into_prototype(),
+                                    None,
+                                );
+                            }
+                        };
+                        runtime_call = nk.inject_key(next_key);
+                    }
+                    other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                    | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                        // TODO: implement somehow
+                        step.error = Some(RuntimeCallError::ChildTriesForbidden.to_string());
+                        break (other.into_prototype(), None);
+                    }
+                    runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                        runtime_call = sig.verify_and_resume();
+                    }
+                }
+            };
+
+            virtual_machine = prototype;
+
+            let (main_trie_changes, offchain_changes) = match success {
+                Some(s) => s,
+                None => {
+                    steps.push(step);
+                    break;
+                }
+            };
+
+            step.writes = main_trie_changes
+                .diff_iter_unordered()
+                .filter(|(key, value, ())| {
+                    previous_changes.diff_get(key).map(|(v, ())| v) != Some(*value)
+                })
+                .map(|(key, value, ())| {
+                    (
+                        methods::HexString(key.to_vec()),
+                        value.map(|v| methods::HexString(v.to_vec())),
+                    )
+                })
+                .collect();
+            step.writes.sort_unstable_by(|(a, _), (b, _)| a.0.cmp(&b.0));
+
+            storage_main_trie_changes = main_trie_changes;
+            offchain_storage_changes = offchain_changes;
+            steps.push(step);
+        }
+
+        runtime_call_lock.unlock(virtual_machine);
+
+        if up_to_extrinsic.is_some() {
+            let last = steps.pop().unwrap();
+            steps.clear();
+            steps.push(last);
+        }
+
+        Ok(methods::BlockTrace { steps })
+    }
+}
+
+/// Maximum number of times [`Background::storage_query_best_block`] starts a query again after
+/// the best block has changed.
+const MAX_STORAGE_QUERY_REDISPATCHES: u32 = 3;
+
 #[derive(Debug, derive_more::Display)]
 enum StorageQueryError {
     /// Error while finding the storage root hash of the requested block.
@@ -1363,6 +1974,15 @@ enum StorageQueryError {
     StorageRetrieval(sync_service::StorageQueryError),
 }
 
+impl StorageQueryError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            StorageQueryError::FindStorageRootHashError(err) => err.kind(),
+            StorageQueryError::StorageRetrieval(err) => err.kind(),
+        }
+    }
+}
+
 // TODO: doc and properly derive Display
 #[derive(Debug, derive_more::Display, Clone)]
 enum RuntimeCallError {
@@ -1374,6 +1994,7 @@ enum RuntimeCallError {
     RuntimeError(runtime_host::ErrorDetail),
     NextKeyForbidden,
     PrefixKeysForbidden,
+    ChildTriesForbidden,
     /// Required runtime API isn't supported by the runtime.
     ApiNotFound,
     /// Version requirement of runtime API isn't supported.
@@ -1383,6 +2004,57 @@ enum RuntimeCallError {
     },
 }
 
+impl RuntimeCallError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            RuntimeCallError::FindStorageRootHashError(err) => err.kind(),
+            RuntimeCallError::Call(err) => err.kind(),
+            RuntimeCallError::StartError(_) | RuntimeCallError::RuntimeError(_) => {
+                ErrorKind::RuntimeTrap
+            }
+            RuntimeCallError::NextKeyForbidden
+            | RuntimeCallError::PrefixKeysForbidden
+            | RuntimeCallError::ChildTriesForbidden
+            | RuntimeCallError::ApiNotFound
+            | RuntimeCallError::ApiVersionUnknown { .. } => ErrorKind::Unsupported,
+        }
+    }
+}
+
+/// Error potentially returned by [`Background::trace_block`].
+#[derive(Debug, derive_more::Display, Clone)]
+enum TraceBlockError {
+    /// Failed to download the header and body of the block.
+    #[display(fmt = "Failed to download the block")]
+    BlockQuery,
+    /// The genesis block can't be executed, as it doesn't have a parent.
+    #[display(fmt = "The genesis block can't be traced")]
+    GenesisBlock,
+    /// The requested extrinsic index is superior or equal to the number of extrinsics in the
+    /// block.
+    #[display(fmt = "Extrinsic index out of range")]
+    ExtrinsicIndexOutOfRange,
+    /// Error while obtaining the runtime of the parent of the block.
+    #[display(fmt = "{_0}")]
+    RuntimeLock(RuntimeCallError),
+    /// Error while starting the execution of the block.
+    #[display(fmt = "{_0}")]
+    Call(runtime_service::RuntimeCallError),
+}
+
+impl TraceBlockError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            TraceBlockError::BlockQuery => ErrorKind::NetworkUnreachable,
+            TraceBlockError::GenesisBlock | TraceBlockError::ExtrinsicIndexOutOfRange => {
+                ErrorKind::InvalidInput
+            }
+            TraceBlockError::RuntimeLock(err) => err.kind(),
+            TraceBlockError::Call(err) => err.kind(),
+        }
+    }
+}
+
 /// Error potentially returned by [`Background::state_trie_root_hash`].
 #[derive(Debug, derive_more::Display, Clone)]
 enum StateTrieRootHashError {
@@ -1392,6 +2064,15 @@ enum StateTrieRootHashError {
     NetworkQueryError,
 }
 
+impl StateTrieRootHashError {
+    fn kind(&self) -> ErrorKind {
+        match self {
+            StateTrieRootHashError::HeaderDecodeError(_) => ErrorKind::PeerMisbehavior,
+            StateTrieRootHashError::NetworkQueryError => ErrorKind::NetworkUnreachable,
+        }
+    }
+}
+
 #[derive(Debug)]
 struct RuntimeCallResult {
     return_value: Vec<u8>,
@@ -19,7 +19,7 @@
 
 use super::{Background, SubscriptionMessage};
 
-use crate::{platform::Platform, runtime_service, sync_service};
+use crate::{error::ErrorKind, platform::Platform, runtime_service, sync_service};
 
 use alloc::{
     borrow::ToOwned as _,
@@ -38,8 +38,9 @@ use futures::{channel::mpsc, prelude::*};
 use hashbrown::HashMap;
 use smoldot::{
     chain::fork_tree,
-    executor::{self, runtime_host},
+    executor::runtime_host,
     header,
+    informant::HashDisplay,
     json_rpc::{self, methods, requests_subscriptions},
     network::protocol,
 };
@@ -113,10 +114,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -321,6 +320,9 @@ impl<TPlat: Platform> Background<TPlat> {
                 log_target,
                 runtime_service,
                 sync_service,
+                prefetch_extrinsic_hashes: Vec::new(),
+                prefetch_storage_keys: Vec::new(),
+                prefetches: stream::FuturesUnordered::new(),
             }
             .run(
                 requests_subscriptions,
@@ -640,6 +642,45 @@ impl<TPlat: Platform> Background<TPlat> {
         }
     }
 
+    /// Handles a call to [`methods::MethodCall::chainHead_unstable_prefetchHint`].
+    pub(super) async fn chain_head_unstable_prefetch_hint(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        follow_subscription: &str,
+        extrinsic_hashes: Vec<methods::HashHexString>,
+        storage_keys: Vec<methods::HexString>,
+    ) {
+        // This is implemented by sending a message to the notifications task.
+        // The task dedicated to this subscription will receive the message and send a response to
+        // the JSON-RPC client.
+        let message_received = self
+            .requests_subscriptions
+            .subscription_send(
+                request_id.1,
+                follow_subscription,
+                SubscriptionMessage::ChainHeadFollowPrefetchHint {
+                    extrinsic_hashes: extrinsic_hashes.into_iter().map(|h| h.0).collect(),
+                    storage_keys: storage_keys.into_iter().map(|k| k.0).collect(),
+                    hint_request_id: (request_id.0.to_owned(), request_id.1.clone()),
+                },
+            )
+            .await;
+
+        // Send back a response manually if the task doesn't exist.
+        if message_received.is_err() {
+            self.requests_subscriptions
+                .respond(
+                    request_id.1,
+                    json_rpc::parse::build_error_response(
+                        request_id.0,
+                        json_rpc::parse::ErrorResponse::InvalidParams,
+                        None,
+                    ),
+                )
+                .await;
+        }
+    }
+
     /// Handles a call to [`methods::MethodCall::chainHead_unstable_finalizedDatabase`].
     pub(super) async fn chain_head_unstable_finalized_database(
         self: &Arc<Self>,
@@ -649,7 +690,9 @@ impl<TPlat: Platform> Background<TPlat> {
         let response = crate::database::encode_database(
             &self.network_service.0,
             &self.sync_service,
+            &self.transactions_service,
             &self.genesis_block_hash,
+            self.genesis_state_root.as_ref(),
             usize::try_from(max_size_bytes.unwrap_or(u64::max_value()))
                 .unwrap_or(usize::max_value()),
         )
@@ -666,11 +709,11 @@ impl<TPlat: Platform> Background<TPlat> {
 }
 
 fn convert_runtime_spec(
-    runtime: &Result<executor::CoreVersion, runtime_service::RuntimeError>,
+    runtime: &Result<runtime_service::RuntimeDescriptor, runtime_service::RuntimeError>,
 ) -> methods::MaybeRuntimeSpec {
     match &runtime {
-        Ok(runtime) => {
-            let runtime = runtime.decode();
+        Ok(descriptor) => {
+            let runtime = descriptor.spec.decode();
             methods::MaybeRuntimeSpec::Valid {
                 spec: methods::RuntimeSpec {
                     impl_name: runtime.impl_name.into(),
@@ -683,6 +726,7 @@ fn convert_runtime_spec(
                         .apis
                         .map(|api| (methods::HexString(api.name_hash.to_vec()), api.version))
                         .collect(),
+                    heap_pages: Some(u32::from(descriptor.heap_pages)),
                 },
             }
         }
@@ -704,6 +748,22 @@ struct ChainHeadFollowTask<TPlat: Platform> {
     log_target: String,
     runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
     sync_service: Arc<sync_service::SyncService<TPlat>>,
+
+    /// Hashes of the extrinsics passed to the latest `chainHead_unstable_prefetchHint` and that
+    /// haven't been found in the body of a block yet. As long as this list isn't empty, the body
+    /// of every new block is downloaded ahead of time, as it isn't possible to know whether a
+    /// block contains an extrinsic without downloading its body.
+    prefetch_extrinsic_hashes: Vec<[u8; 32]>,
+
+    /// Storage keys passed to the latest `chainHead_unstable_prefetchHint`. The values of these
+    /// keys are downloaded ahead of time for every new block.
+    prefetch_storage_keys: Vec<Vec<u8>>,
+
+    /// Downloads started because of [`ChainHeadFollowTask::prefetch_extrinsic_hashes`] or
+    /// [`ChainHeadFollowTask::prefetch_storage_keys`]. The results of these downloads are kept
+    /// in the caches of the sync service. Each future yields the elements of
+    /// [`ChainHeadFollowTask::prefetch_extrinsic_hashes`] that have been found in a block body.
+    prefetches: stream::FuturesUnordered<future::BoxFuture<'static, Vec<[u8; 32]>>>,
 }
 
 enum Subscription<TPlat: Platform> {
@@ -758,12 +818,28 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                     }
                 };
                 let next_message = messages_rx.next();
+                let prefetches = &mut self.prefetches;
+                let next_prefetch = async move {
+                    if prefetches.is_empty() {
+                        future::pending().await
+                    } else {
+                        prefetches.next().await.unwrap()
+                    }
+                };
                 futures::pin_mut!(next_message);
                 futures::pin_mut!(next_block);
+                futures::pin_mut!(next_prefetch);
 
-                match future::select(next_block, next_message).await {
-                    future::Either::Left((v, _)) => either::Left(v),
-                    future::Either::Right((v, _)) => either::Right(v),
+                match future::select(future::select(next_block, next_message), next_prefetch).await
+                {
+                    future::Either::Left((future::Either::Left((v, _)), _)) => either::Left(v),
+                    future::Either::Left((future::Either::Right((v, _)), _)) => either::Right(v),
+                    future::Either::Right((found_extrinsics, _)) => {
+                        // The extrinsics that have been found no longer need to be looked for.
+                        self.prefetch_extrinsic_hashes
+                            .retain(|h| !found_extrinsics.contains(h));
+                        continue;
+                    }
                 }
             };
 
@@ -865,6 +941,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                         .insert(hash, block.scale_encoded_header);
                     debug_assert!(_was_in.is_none());
 
+                    self.start_prefetch(hash);
+
                     // TODO: check if it matches current finalized block
                     // TODO: O(n)
                     let parent_node_index =
@@ -922,6 +1000,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                         .insert(hash, block.scale_encoded_header);
                     debug_assert!(_was_in.is_none());
 
+                    self.start_prefetch(hash);
+
                     // TODO: check if it matches current finalized block
                     // TODO: O(n)
                     let parent_node_index =
@@ -1192,6 +1272,24 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
 
                 ops::ControlFlow::Continue(())
             }
+            SubscriptionMessage::ChainHeadFollowPrefetchHint {
+                extrinsic_hashes,
+                storage_keys,
+                hint_request_id,
+            } => {
+                self.prefetch_extrinsic_hashes = extrinsic_hashes;
+                self.prefetch_storage_keys = storage_keys;
+
+                requests_subscriptions
+                    .respond(
+                        &hint_request_id.1,
+                        methods::Response::chainHead_unstable_prefetchHint(())
+                            .to_json_response(&hint_request_id.0),
+                    )
+                    .await;
+                confirmation_sender.send();
+                ops::ControlFlow::Continue(())
+            }
             _ => {
                 // Any other message.
                 // Silently discard the confirmation sender.
@@ -1200,6 +1298,103 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
         }
     }
 
+    /// Starts downloading the body and storage items of the given pinned block according to
+    /// [`ChainHeadFollowTask::prefetch_extrinsic_hashes`] and
+    /// [`ChainHeadFollowTask::prefetch_storage_keys`].
+    fn start_prefetch(&mut self, hash: [u8; 32]) {
+        if self.prefetch_extrinsic_hashes.is_empty() && self.prefetch_storage_keys.is_empty() {
+            return;
+        }
+
+        let (block_number, state_root) = match self
+            .pinned_blocks_headers
+            .get(&hash)
+            .and_then(|h| header::decode(h, self.sync_service.block_number_bytes()).ok())
+        {
+            Some(header) => (header.number, *header.state_root),
+            None => return,
+        };
+
+        if !self.prefetch_extrinsic_hashes.is_empty() {
+            let sync_service = self.sync_service.clone();
+            let extrinsic_hashes = self.prefetch_extrinsic_hashes.clone();
+            let log_target = self.log_target.clone();
+            self.prefetches.push(
+                async move {
+                    // The same fields as `chainHead_unstable_body` are requested, so that the
+                    // result can be used to answer it.
+                    let body = match sync_service
+                        .block_query(
+                            block_number,
+                            hash,
+                            protocol::BlocksRequestFields {
+                                header: true,
+                                body: true,
+                                justifications: false,
+                            },
+                            3,
+                            Duration::from_secs(8),
+                            NonZeroU32::new(1).unwrap(),
+                        )
+                        .await
+                    {
+                        Ok(block) => block.body.unwrap_or_default(),
+                        Err(()) => {
+                            log::debug!(
+                                target: &log_target,
+                                "Failed to prefetch body of block {}",
+                                HashDisplay(&hash)
+                            );
+                            return Vec::new();
+                        }
+                    };
+
+                    body.iter()
+                        .map(|extrinsic| {
+                            <[u8; 32]>::try_from(
+                                blake2_rfc::blake2b::blake2b(32, &[], extrinsic).as_bytes(),
+                            )
+                            .unwrap()
+                        })
+                        .filter(|h| extrinsic_hashes.contains(h))
+                        .collect()
+                }
+                .boxed(),
+            );
+        }
+
+        if !self.prefetch_storage_keys.is_empty() {
+            let sync_service = self.sync_service.clone();
+            let storage_keys = self.prefetch_storage_keys.clone();
+            let log_target = self.log_target.clone();
+            self.prefetches.push(
+                async move {
+                    if let Err(error) = sync_service
+                        .storage_query(
+                            block_number,
+                            &hash,
+                            &state_root,
+                            storage_keys.iter(),
+                            3,
+                            Duration::from_secs(8),
+                            NonZeroU32::new(1).unwrap(),
+                        )
+                        .await
+                    {
+                        log::debug!(
+                            target: &log_target,
+                            "Failed to prefetch storage of block {}: {}",
+                            HashDisplay(&hash),
+                            error
+                        );
+                    }
+                    Vec::new()
+                }
+                .boxed(),
+            );
+        }
+    }
+
     async fn start_chain_head_body(
         &mut self,
         requests_subscriptions: &Arc<
@@ -1221,10 +1416,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -1382,10 +1575,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                     request_id.1,
                     json_rpc::parse::build_error_response(
                         request_id.0,
-                        json_rpc::parse::ErrorResponse::ServerError(
-                            -32000,
-                            "Child key storage queries not supported yet",
-                        ),
+                        ErrorKind::Unsupported
+                            .json_rpc_error("Child key storage queries not supported yet"),
                         None,
                     ),
                 )
@@ -1409,10 +1600,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -1590,10 +1779,8 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -1616,6 +1803,39 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                     )
                     .await;
 
+                // If all the storage values needed by the call are found in the cache, the call
+                // is performed locally and no call proof is requested from the network.
+                let local_outcome = match &pre_runtime_call {
+                    Some(pre_runtime_call) => {
+                        pre_runtime_call
+                            .try_call_locally(&function_to_call, iter::once(&call_parameters.0))
+                            .await
+                    }
+                    None => None,
+                };
+                if let Some(local_outcome) = local_outcome {
+                    let result = match local_outcome {
+                        Ok(output) => methods::ChainHeadCallEvent::Done {
+                            output: methods::HexString(output),
+                        },
+                        Err(error) => methods::ChainHeadCallEvent::Error {
+                            error: error.to_string().into(),
+                        },
+                    };
+                    requests_subscriptions
+                        .push_notification(
+                            &request_id.1,
+                            &subscription_id,
+                            methods::ServerToClient::chainHead_unstable_callEvent {
+                                subscription: (&subscription_id).into(),
+                                result,
+                            }
+                            .to_json_call_object_parameters(None),
+                        )
+                        .await;
+                    return;
+                }
+
                 let (pre_runtime_call, requests_subscriptions) = if let Some(pre_runtime_call) = &pre_runtime_call {
                     let call_future = pre_runtime_call.start(
                         &function_to_call,
@@ -1688,6 +1908,7 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                             main_trie_root_calculation_cache: None,
                             offchain_storage_changes: Default::default(),
                             storage_main_trie_changes: Default::default(),
+                            storage_child_tries_changes: Default::default(),
                             max_log_level: 0,
                         }) {
                             Err((error, prototype)) => {
@@ -1781,6 +2002,18 @@ impl<TPlat: Platform> ChainHeadFollowTask<TPlat> {
                                                 }
                                                 .to_json_call_object_parameters(None);
                                         }
+                                        other @ (runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                                        | runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                                            // TODO: implement somehow
+                                            runtime_call_lock.unlock(other.into_prototype());
+                                            break methods::ServerToClient::chainHead_unstable_callEvent {
+                                                    subscription: (&subscription_id).into(),
+                                                    result: methods::ChainHeadCallEvent::Inaccessible {
+                                                        error: "accessing child tries not implemented".into(),
+                                                    },
+                                                }
+                                                .to_json_call_object_parameters(None);
+                                        }
                                         runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
                                             runtime_call = sig.verify_and_resume();
                                         }
@@ -0,0 +1,256 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ethereum JSON-RPC functions of chains based on Frontier.
+//!
+//! Frontier creates one Ethereum block per Substrate block, with the same number. The functions
+//! below therefore answer using the Substrate blocks directly.
+
+use super::{super::sub_utils, Background, Platform};
+
+use crate::error::ErrorKind;
+
+use alloc::{format, string::ToString as _, sync::Arc};
+use core::{iter, num::NonZeroU32, num::NonZeroUsize, time::Duration};
+use smoldot::{
+    header,
+    json_rpc::{self, ethereum, methods, requests_subscriptions},
+};
+
+impl<TPlat: Platform> Background<TPlat> {
+    /// Handles a call to [`methods::MethodCall::eth_blockNumber`].
+    pub(super) async fn eth_block_number(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let best_block_header = sub_utils::subscribe_best(&self.runtime_service).await.0;
+
+        // Headers reported by the runtime service have already been verified.
+        let best_block_number =
+            header::decode(&best_block_header, self.sync_service.block_number_bytes())
+                .unwrap()
+                .number;
+
+        let response = methods::Response::eth_blockNumber(
+            ethereum::encode_quantity(&best_block_number.to_be_bytes()).into(),
+        )
+        .to_json_response(request_id.0);
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::eth_chainId`].
+    pub(super) async fn eth_chain_id(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let block_hash = header::hash_from_scale_encoded_header(
+            sub_utils::subscribe_best(&self.runtime_service).await.0,
+        );
+
+        let result = self
+            .runtime_call(
+                &block_hash,
+                ethereum::ETHEREUM_RUNTIME_API,
+                1..,
+                ethereum::CHAIN_ID_FUNCTION_NAME,
+                iter::empty::<&[u8]>(),
+                4,
+                Duration::from_secs(4),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await;
+
+        let response = match result {
+            Ok(result) => match ethereum::decode_chain_id(&result.return_value) {
+                Ok(chain_id) => methods::Response::eth_chainId(
+                    ethereum::encode_quantity(&chain_id.to_be_bytes()).into(),
+                )
+                .to_json_response(request_id.0),
+                Err(error) => json_rpc::parse::build_error_response(
+                    request_id.0,
+                    ErrorKind::Unsupported
+                        .json_rpc_error(&format!("Failed to decode runtime output: {error}")),
+                    None,
+                ),
+            },
+            Err(error) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+        };
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::eth_getBalance`].
+    pub(super) async fn eth_get_balance(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        address: &[u8],
+        block: Option<&str>,
+    ) {
+        if address.len() != 20 {
+            self.requests_subscriptions
+                .respond(
+                    request_id.1,
+                    json_rpc::parse::build_error_response(
+                        request_id.0,
+                        ErrorKind::InvalidInput.json_rpc_error("Address must be 20 bytes"),
+                        None,
+                    ),
+                )
+                .await;
+            return;
+        }
+
+        // Only block tags are supported, as looking up an Ethereum block by number or hash would
+        // require indexing the Ethereum blocks.
+        let block_hash = match block {
+            None | Some("latest") | Some("pending") => header::hash_from_scale_encoded_header(
+                sub_utils::subscribe_best(&self.runtime_service).await.0,
+            ),
+            Some("finalized") | Some("safe") => header::hash_from_scale_encoded_header(
+                self.runtime_service
+                    .subscribe_all("eth_getBalance", 16, NonZeroUsize::new(24).unwrap())
+                    .await
+                    .finalized_block_scale_encoded_header,
+            ),
+            Some("earliest") => self.genesis_block_hash,
+            Some(block) => {
+                self.requests_subscriptions
+                    .respond(
+                        request_id.1,
+                        json_rpc::parse::build_error_response(
+                            request_id.0,
+                            ErrorKind::Unsupported
+                                .json_rpc_error(&format!("Unsupported block parameter: {block}")),
+                            None,
+                        ),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        let result = self
+            .runtime_call(
+                &block_hash,
+                ethereum::ETHEREUM_RUNTIME_API,
+                1..,
+                ethereum::ACCOUNT_BASIC_FUNCTION_NAME,
+                iter::once(address),
+                4,
+                Duration::from_secs(4),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await;
+
+        let response = match result {
+            Ok(result) => match ethereum::decode_account_basic(&result.return_value) {
+                Ok(account) => methods::Response::eth_getBalance(
+                    ethereum::encode_quantity(&account.balance).into(),
+                )
+                .to_json_response(request_id.0),
+                Err(error) => json_rpc::parse::build_error_response(
+                    request_id.0,
+                    ErrorKind::Unsupported
+                        .json_rpc_error(&format!("Failed to decode runtime output: {error}")),
+                    None,
+                ),
+            },
+            Err(error) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+        };
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::eth_sendRawTransaction`].
+    pub(super) async fn eth_send_raw_transaction(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        raw_transaction: &[u8],
+    ) {
+        let transaction = match ethereum::transaction_to_scale(raw_transaction) {
+            Ok(t) => t,
+            Err(error) => {
+                self.requests_subscriptions
+                    .respond(
+                        request_id.1,
+                        json_rpc::parse::build_error_response(
+                            request_id.0,
+                            ErrorKind::InvalidInput.json_rpc_error(&error.to_string()),
+                            None,
+                        ),
+                    )
+                    .await;
+                return;
+            }
+        };
+
+        let block_hash = header::hash_from_scale_encoded_header(
+            sub_utils::subscribe_best(&self.runtime_service).await.0,
+        );
+
+        // The runtime wraps the Ethereum transaction into an extrinsic, which is then submitted
+        // like any other transaction.
+        let result = self
+            .runtime_call(
+                &block_hash,
+                ethereum::CONVERT_TRANSACTION_RUNTIME_API,
+                2..,
+                ethereum::CONVERT_TRANSACTION_FUNCTION_NAME,
+                iter::once(&transaction),
+                4,
+                Duration::from_secs(4),
+                NonZeroU32::new(2).unwrap(),
+            )
+            .await;
+
+        let response = match result {
+            Ok(result) => {
+                self.transactions_service
+                    .submit_transaction(result.return_value)
+                    .await;
+                methods::Response::eth_sendRawTransaction(methods::HashHexString(
+                    ethereum::transaction_hash(raw_transaction),
+                ))
+                .to_json_response(request_id.0)
+            }
+            Err(error) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+        };
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+}
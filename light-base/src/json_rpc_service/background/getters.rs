@@ -22,9 +22,10 @@ use super::{Background, Platform};
 use alloc::{borrow::Cow, format, string::ToString as _, sync::Arc, vec::Vec};
 use core::num::NonZeroUsize;
 use smoldot::{
-    header,
+    chain, header,
     json_rpc::{methods, requests_subscriptions},
     network::protocol,
+    sync,
 };
 
 impl<TPlat: Platform> Background<TPlat> {
@@ -121,6 +122,8 @@ impl<TPlat: Platform> Background<TPlat> {
                 request_id.1,
                 methods::Response::rpc_methods(methods::RpcMethods {
                     methods: methods::MethodCall::method_names()
+                        .filter(|n| self.methods_filter.is_allowed(n))
+                        .filter(|n| self.ethereum_json_rpc || !n.starts_with("eth_"))
                         .map(|n| n.into())
                         .collect(),
                 })
@@ -186,6 +189,211 @@ impl<TPlat: Platform> Background<TPlat> {
             peers: u64::try_from(self.sync_service.syncing_peers().await.len())
                 .unwrap_or(u64::max_value()),
             should_have_peers: self.chain_is_live,
+            possibly_eclipsed: self.sync_service.is_eclipse_suspected_heuristic().await,
+        })
+        .to_json_response(request_id.0);
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::sync_unstable_finalityDiagnostics`].
+    pub(super) async fn sync_unstable_finality_diagnostics(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let diagnostics = self.sync_service.finality_diagnostics().await;
+        let response = methods::Response::sync_unstable_finalityDiagnostics(diagnostics.map(|d| {
+            methods::FinalityDiagnostics {
+                best_block_number: d.best_block_number,
+                finalized_block_number: d.finalized_block_number,
+                ms_since_last_finalized: u64::try_from(d.time_since_last_finalized.as_millis())
+                    .unwrap_or(u64::max_value()),
+                peers: u64::try_from(d.num_peers).unwrap_or(u64::max_value()),
+                peers_with_higher_finalized: u64::try_from(d.num_peers_higher_finalized)
+                    .unwrap_or(u64::max_value()),
+            }
+        }))
+        .to_json_response(request_id.0);
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::grandpa_unstable_authoritySet`].
+    pub(super) async fn grandpa_unstable_authority_set(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let chain_information = self.sync_service.serialize_chain_information().await;
+
+        let authority_set = chain_information.and_then(|chain_information| {
+            let chain_information = chain_information.as_ref();
+            let chain::chain_information::ChainInformationFinalityRef::Grandpa {
+                after_finalized_block_authorities_set_id,
+                finalized_triggered_authorities,
+                finalized_scheduled_change,
+            } = chain_information.finality
+            else {
+                return None;
+            };
+
+            let convert_list = |list: &[header::GrandpaAuthority]| {
+                list.iter()
+                    .map(|authority| methods::GrandpaAuthority {
+                        public_key: methods::HashHexString(authority.public_key),
+                        weight: authority.weight.get(),
+                    })
+                    .collect::<Vec<_>>()
+            };
+
+            Some(methods::GrandpaAuthoritySet {
+                finalized_block_hash: methods::HashHexString(
+                    chain_information
+                        .finalized_block_header
+                        .hash(self.sync_service.block_number_bytes()),
+                ),
+                finalized_block_number: chain_information.finalized_block_header.number,
+                set_id: after_finalized_block_authorities_set_id,
+                authorities: convert_list(finalized_triggered_authorities),
+                scheduled_change: finalized_scheduled_change.map(|(trigger_block_number, list)| {
+                    methods::GrandpaScheduledChange {
+                        trigger_block_number,
+                        authorities: convert_list(list),
+                    }
+                }),
+            })
+        });
+
+        let response = methods::Response::grandpa_unstable_authoritySet(authority_set)
+            .to_json_response(request_id.0);
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::grandpa_unstable_finalityProof`].
+    pub(super) async fn grandpa_unstable_finality_proof(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        hash: methods::HashHexString,
+    ) {
+        let finality_proof = self
+            .sync_service
+            .finality_proof(hash.0)
+            .await
+            .map(|retained| methods::FinalityProof {
+                target_block_hash: methods::HashHexString(retained.target_block_hash),
+                target_block_number: retained.target_block_number,
+                proof: match retained.proof {
+                    sync::all::ExternalFinalityProof::Justification {
+                        consensus_engine_id,
+                        scale_encoded_justification,
+                    } => methods::FinalityProofContent::Justification {
+                        consensus_engine: methods::HexString(consensus_engine_id.to_vec()),
+                        justification: methods::HexString(scale_encoded_justification),
+                    },
+                    sync::all::ExternalFinalityProof::GrandpaCommit(commit) => {
+                        methods::FinalityProofContent::GrandpaCommit {
+                            commit: methods::HexString(commit),
+                        }
+                    }
+                },
+            });
+
+        let response = methods::Response::grandpa_unstable_finalityProof(finality_proof)
+            .to_json_response(request_id.0);
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::babe_unstable_epochs`].
+    pub(super) async fn babe_unstable_epochs(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let convert_epoch =
+            |epoch: chain::chain_information::BabeEpochInformation| methods::BabeEpoch {
+                epoch_index: epoch.epoch_index,
+                start_slot_number: epoch.start_slot_number,
+                authorities: epoch
+                    .authorities
+                    .into_iter()
+                    .map(|authority| methods::BabeAuthority {
+                        public_key: methods::HashHexString(authority.public_key),
+                        weight: authority.weight,
+                    })
+                    .collect(),
+                randomness: methods::HashHexString(epoch.randomness),
+            };
+
+        let epochs = self
+            .sync_service
+            .babe_epochs()
+            .await
+            .map(|epochs| methods::BabeEpochs {
+                best_block_hash: methods::HashHexString(epochs.best_block_hash),
+                best_block_number: epochs.best_block_number,
+                slots_per_epoch: epochs.slots_per_epoch.get(),
+                current_epoch: epochs.current_epoch.map(convert_epoch),
+                next_epoch: convert_epoch(epochs.next_epoch),
+            });
+
+        let response =
+            methods::Response::babe_unstable_epochs(epochs).to_json_response(request_id.0);
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::system_unstable_health`].
+    pub(super) async fn system_unstable_health(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+    ) {
+        let diagnostics = self.sync_service.health_diagnostics().await;
+        let last_successful_requests = self
+            .network_service
+            .0
+            .last_successful_requests(self.network_service.1)
+            .await;
+        let eclipse_suspected = self.sync_service.is_eclipse_suspected_heuristic().await;
+
+        let now = TPlat::now();
+        let ms_since = |instant: Option<TPlat::Instant>| {
+            instant.map(|instant| {
+                u64::try_from((now.clone() - instant).as_millis()).unwrap_or(u64::MAX)
+            })
+        };
+
+        let mut problems = Vec::new();
+        if diagnostics.num_peers == 0 {
+            problems.push(methods::HealthProblem::NoPeers);
+        } else {
+            if diagnostics.num_peers_best_chain_compatible == Some(0) {
+                problems.push(methods::HealthProblem::NoBestChainCompatiblePeer);
+            }
+            if diagnostics.num_proof_serving_peers == 0 {
+                problems.push(methods::HealthProblem::NoProofServingPeer);
+            }
+        }
+        if eclipse_suspected {
+            problems.push(methods::HealthProblem::EclipseSuspected);
+        }
+
+        let response = methods::Response::system_unstable_health(methods::HealthDiagnostics {
+            peers: u64::try_from(diagnostics.num_peers).unwrap_or(u64::MAX),
+            best_chain_compatible_peers: diagnostics
+                .num_peers_best_chain_compatible
+                .map(|n| u64::try_from(n).unwrap_or(u64::MAX)),
+            proof_serving_peers: u64::try_from(diagnostics.num_proof_serving_peers)
+                .unwrap_or(u64::MAX),
+            ms_since_last_blocks_request: ms_since(last_successful_requests.blocks),
+            ms_since_last_warp_sync_request: ms_since(last_successful_requests.grandpa_warp_sync),
+            ms_since_last_storage_proof_request: ms_since(last_successful_requests.storage_proof),
+            ms_since_last_call_proof_request: ms_since(last_successful_requests.call_proof),
+            problems,
         })
         .to_json_response(request_id.0);
         self.requests_subscriptions
@@ -17,9 +17,9 @@
 
 //! All legacy JSON-RPC method handlers that relate to the chain or the storage.
 
-use super::{Background, Platform, SubscriptionMessage};
+use super::{super::sub_utils, Background, Platform, SubscriptionMessage};
 
-use crate::runtime_service;
+use crate::{error::ErrorKind, runtime_service};
 
 use alloc::{
     borrow::ToOwned as _,
@@ -42,8 +42,6 @@ use smoldot::{
     network::protocol,
 };
 
-mod sub_utils;
-
 impl<TPlat: Platform> Background<TPlat> {
     /// Handles a call to [`methods::MethodCall::system_accountNextIndex`].
     pub(super) async fn account_next_index(
@@ -86,7 +84,7 @@ impl<TPlat: Platform> Background<TPlat> {
                 );
                 json_rpc::parse::build_error_response(
                     request_id.0,
-                    json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                    error.kind().json_rpc_error(&error.to_string()),
                     None,
                 )
             }
@@ -335,10 +333,8 @@ impl<TPlat: Platform> Background<TPlat> {
                     }
                     Err(error) => json_rpc::parse::build_error_response(
                         request_id.0,
-                        json_rpc::parse::ErrorResponse::ServerError(
-                            -32000,
-                            &format!("Failed to decode header: {error}"),
-                        ),
+                        ErrorKind::PeerMisbehavior
+                            .json_rpc_error(&format!("Failed to decode header: {error}")),
                         None,
                     ),
                 }
@@ -372,10 +368,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -554,10 +548,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -678,7 +670,7 @@ impl<TPlat: Platform> Background<TPlat> {
     ) {
         let (subscription_id, mut messages_rx, subscription_start) = match self
             .requests_subscriptions
-            .start_subscription(request_id.1, 1)
+            .start_subscription(request_id.1, 16)
             .await
         {
             Ok(v) => v,
@@ -688,10 +680,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -700,10 +690,18 @@ impl<TPlat: Platform> Background<TPlat> {
             }
         };
 
+        // Every block that joins the best chain is reported, in order, so that clients can
+        // follow the best chain block by block. The legacy API has no way to notify of blocks
+        // that leave the best chain, and re-orgs are thus only visible through the parent hash
+        // of the reported headers.
         let mut blocks_list = {
             let (block_header, blocks_subscription) =
-                sub_utils::subscribe_best(&self.runtime_service).await;
-            stream::once(future::ready(block_header)).chain(blocks_subscription)
+                sub_utils::subscribe_best_reorgs(&self.runtime_service).await;
+            stream::once(future::ready(sub_utils::BestBlockUpdate {
+                retracted: Vec::new(),
+                enacted: vec![block_header],
+            }))
+            .chain(blocks_subscription)
         };
 
         subscription_start.start({
@@ -747,38 +745,49 @@ impl<TPlat: Platform> Background<TPlat> {
                             // Stream returned by `subscribe_best` is always unlimited.
                             unreachable!()
                         }
-                        either::Left(Some(header)) => {
-                            let header = match methods::Header::from_scale_encoded_header(
-                                &header,
-                                sync_service.block_number_bytes(),
-                            ) {
-                                Ok(h) => h,
-                                Err(error) => {
-                                    log::warn!(
-                                        target: &log_target,
-                                        "`chain_subscribeNewHeads` subscription has skipped block \
-                                        due to undecodable header. Hash: {}. Error: {}",
-                                        HashDisplay(&header::hash_from_scale_encoded_header(
-                                            &header
-                                        )),
-                                        error,
-                                    );
-                                    continue;
-                                }
-                            };
+                        either::Left(Some(update)) => {
+                            if !update.retracted.is_empty() {
+                                log::debug!(
+                                    target: &log_target,
+                                    "`chain_subscribeNewHeads` subscription re-org. Retracted: {}. \
+                                    Enacted: {}",
+                                    update.retracted.len(),
+                                    update.enacted.len(),
+                                );
+                            }
 
-                            requests_subscriptions
-                                .set_queued_notification(
-                                    &request_id.1,
-                                    &subscription_id,
-                                    0,
-                                    methods::ServerToClient::chain_newHead {
-                                        subscription: (&subscription_id).into(),
-                                        result: header,
+                            for header in update.enacted {
+                                let header = match methods::Header::from_scale_encoded_header(
+                                    &header,
+                                    sync_service.block_number_bytes(),
+                                ) {
+                                    Ok(h) => h,
+                                    Err(error) => {
+                                        log::warn!(
+                                            target: &log_target,
+                                            "`chain_subscribeNewHeads` subscription has skipped \
+                                            block due to undecodable header. Hash: {}. Error: {}",
+                                            HashDisplay(&header::hash_from_scale_encoded_header(
+                                                &header
+                                            )),
+                                            error,
+                                        );
+                                        continue;
                                     }
-                                    .to_json_call_object_parameters(None),
-                                )
-                                .await;
+                                };
+
+                                requests_subscriptions
+                                    .push_notification(
+                                        &request_id.1,
+                                        &subscription_id,
+                                        methods::ServerToClient::chain_newHead {
+                                            subscription: (&subscription_id).into(),
+                                            result: header,
+                                        }
+                                        .to_json_call_object_parameters(None),
+                                    )
+                                    .await;
+                            }
                         }
                         either::Right((
                             SubscriptionMessage::StopIfNewHeads { stop_request_id },
@@ -944,10 +953,8 @@ impl<TPlat: Platform> Background<TPlat> {
                 }
                 Err(error) => json_rpc::parse::build_error_response(
                     request_id.0,
-                    json_rpc::parse::ErrorResponse::ServerError(
-                        -32000,
-                        &format!("Failed to decode runtime output: {error}"),
-                    ),
+                    ErrorKind::Unsupported
+                        .json_rpc_error(&format!("Failed to decode runtime output: {error}")),
                     None,
                 ),
             },
@@ -960,7 +967,7 @@ impl<TPlat: Platform> Background<TPlat> {
                 );
                 json_rpc::parse::build_error_response(
                     request_id.0,
-                    json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                    error.kind().json_rpc_error(&error.to_string()),
                     None,
                 )
             }
@@ -1003,7 +1010,7 @@ impl<TPlat: Platform> Background<TPlat> {
                 .to_json_response(request_id.0),
             Err(error) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
         };
@@ -1038,10 +1045,9 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                &format!("Failed to fetch block information: {err}"),
-                            ),
+                            err.kind().json_rpc_error(&format!(
+                                "Failed to fetch block information: {err}"
+                            )),
                             None,
                         ),
                     )
@@ -1071,7 +1077,7 @@ impl<TPlat: Platform> Background<TPlat> {
             }
             Err(error) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
         };
@@ -1135,10 +1141,9 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                &format!("Failed to fetch block information: {err}"),
-                            ),
+                            err.kind().json_rpc_error(&format!(
+                                "Failed to fetch block information: {err}"
+                            )),
                             None,
                         ),
                     )
@@ -1187,7 +1192,7 @@ impl<TPlat: Platform> Background<TPlat> {
             }
             Err(error) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
         };
@@ -1234,10 +1239,9 @@ impl<TPlat: Platform> Background<TPlat> {
             }
             Ok(Err(error)) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(
-                    -32000,
-                    &format!("Failed to decode metadata from runtime. Error: {error}"),
-                ),
+                ErrorKind::Unsupported.json_rpc_error(&format!(
+                    "Failed to decode metadata from runtime. Error: {error}"
+                )),
                 None,
             ),
             Err(error) => {
@@ -1248,7 +1252,7 @@ impl<TPlat: Platform> Background<TPlat> {
                 );
                 json_rpc::parse::build_error_response(
                     request_id.0,
-                    json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                    error.kind().json_rpc_error(&error.to_string()),
                     None,
                 )
             }
@@ -1296,12 +1300,53 @@ impl<TPlat: Platform> Background<TPlat> {
             }
             Ok(Err(error)) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+            Err(error) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
+                None,
+            ),
+        };
+
+        self.requests_subscriptions
+            .respond(request_id.1, response)
+            .await;
+    }
+
+    /// Handles a call to [`methods::MethodCall::state_unstable_runtimeApis`].
+    pub(super) async fn state_unstable_runtime_apis(
+        self: &Arc<Self>,
+        request_id: (&str, &requests_subscriptions::RequestId),
+        block_hash: Option<&[u8; 32]>,
+    ) {
+        let block_hash = match block_hash {
+            Some(h) => *h,
+            None => header::hash_from_scale_encoded_header(
+                sub_utils::subscribe_best(&self.runtime_service).await.0,
+            ),
+        };
+
+        let response = match self.runtime_lock(&block_hash).await.map(|l| l.apis()) {
+            Ok(Ok(apis)) => methods::Response::state_unstable_runtimeApis(
+                apis.into_iter()
+                    .map(|api| methods::RuntimeApi {
+                        name: api.name.map(|n| n.to_owned()),
+                        name_hash: methods::HexString(api.name_hash.to_vec()),
+                        version: api.version,
+                    })
+                    .collect(),
+            )
+            .to_json_response(request_id.0),
+            Ok(Err(error)) => json_rpc::parse::build_error_response(
+                request_id.0,
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
             Err(error) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
         };
@@ -1318,28 +1363,35 @@ impl<TPlat: Platform> Background<TPlat> {
         key: methods::HexString,
         hash: Option<methods::HashHexString>,
     ) {
-        let hash = hash
-            .as_ref()
-            .map(|h| h.0)
-            .unwrap_or(header::hash_from_scale_encoded_header(
-                sub_utils::subscribe_best(&self.runtime_service).await.0,
-            ));
-
-        let fut = self.storage_query(
-            iter::once(&key.0),
-            &hash,
-            3,
-            Duration::from_secs(12),
-            NonZeroU32::new(1).unwrap(),
-        );
-        let response = fut.await;
+        // If no block has been provided, we use the best block, in which case the query is
+        // started again if the best block changes while the query fails.
+        let response = if let Some(hash) = hash {
+            self.storage_query(
+                iter::once(&key.0),
+                &hash.0,
+                3,
+                Duration::from_secs(12),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+        } else {
+            self.storage_query_best_block(
+                iter::once(&key.0),
+                3,
+                Duration::from_secs(12),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await
+            .map(|(_, values)| values)
+        };
+
         let response = match response.map(|mut r| r.pop().unwrap()) {
             Ok(Some(value)) => methods::Response::state_getStorage(methods::HexString(value))
                 .to_json_response(request_id.0),
             Ok(None) => json_rpc::parse::build_success_response(request_id.0, "null"),
             Err(error) => json_rpc::parse::build_error_response(
                 request_id.0,
-                json_rpc::parse::ErrorResponse::ServerError(-32000, &error.to_string()),
+                error.kind().json_rpc_error(&error.to_string()),
                 None,
             ),
         };
@@ -1356,30 +1408,45 @@ impl<TPlat: Platform> Background<TPlat> {
         keys: Vec<methods::HexString>,
         at: Option<methods::HashHexString>,
     ) {
-        let best_block = header::hash_from_scale_encoded_header(
-            &sub_utils::subscribe_best(&self.runtime_service).await.0,
-        );
-
-        let cache = self.cache.lock().await;
-
-        let at = at.as_ref().map(|h| h.0).unwrap_or(best_block);
+        // If no block has been provided, we use the best block, in which case the query is
+        // started again if the best block changes while the query fails.
+        let (block_hash, outcome) = if let Some(at) = at {
+            let outcome = self
+                .storage_query(
+                    keys.iter(),
+                    &at.0,
+                    3,
+                    Duration::from_secs(12),
+                    NonZeroU32::new(1).unwrap(),
+                )
+                .await;
+            (at.0, outcome)
+        } else {
+            match self
+                .storage_query_best_block(
+                    keys.iter(),
+                    3,
+                    Duration::from_secs(12),
+                    NonZeroU32::new(1).unwrap(),
+                )
+                .await
+            {
+                Ok((block_hash, values)) => (block_hash, Ok(values)),
+                Err(error) => (
+                    header::hash_from_scale_encoded_header(
+                        &sub_utils::subscribe_best(&self.runtime_service).await.0,
+                    ),
+                    Err(error),
+                ),
+            }
+        };
 
         let mut out = methods::StorageChangeSet {
-            block: methods::HashHexString(best_block),
+            block: methods::HashHexString(block_hash),
             changes: Vec::new(),
         };
 
-        drop(cache);
-
-        let fut = self.storage_query(
-            keys.iter(),
-            &at,
-            3,
-            Duration::from_secs(12),
-            NonZeroU32::new(1).unwrap(),
-        );
-
-        if let Ok(values) = fut.await {
+        if let Ok(values) = outcome {
             for (value, key) in values.into_iter().zip(keys) {
                 out.changes.push((key, value.map(methods::HexString)));
             }
@@ -1410,10 +1477,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -1555,10 +1620,8 @@ impl<TPlat: Platform> Background<TPlat> {
                     request_id.1,
                     json_rpc::parse::build_error_response(
                         request_id.0,
-                        json_rpc::parse::ErrorResponse::ServerError(
-                            -32000,
-                            "Subscribing to all storage changes isn't supported",
-                        ),
+                        ErrorKind::Unsupported
+                            .json_rpc_error("Subscribing to all storage changes isn't supported"),
                         None,
                     ),
                 )
@@ -1578,10 +1641,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -1591,96 +1652,59 @@ impl<TPlat: Platform> Background<TPlat> {
         };
 
         // Build a stream of `methods::StorageChangeSet` items to send back to the user.
+        // The storage of the best block is watched by the storage subscriptions service, which
+        // is shared with the other JSON-RPC clients of this chain.
         let storage_updates = {
-            let known_values = (0..list.len()).map(|_| None).collect::<Vec<_>>();
-            let runtime_service = self.runtime_service.clone();
-            let sync_service = self.sync_service.clone();
-            let log_target = self.log_target.clone();
+            let keys = list.into_iter().map(|key| key.0).collect::<Vec<_>>();
+            let known_values = (0..keys.len()).map(|_| None).collect::<Vec<_>>();
+            let storage_subscriptions = self.storage_subscriptions.clone();
 
             stream::unfold(
-                (None, list, known_values),
-                move |(mut blocks_stream, list, mut known_values)| {
-                    let sync_service = sync_service.clone();
-                    let runtime_service = runtime_service.clone();
-                    let log_target = log_target.clone();
+                (None, keys, known_values),
+                move |(mut changes_rx, keys, mut known_values)| {
+                    let storage_subscriptions = storage_subscriptions.clone();
                     async move {
                         loop {
-                            if blocks_stream.is_none() {
-                                // TODO: why is this done against the runtime_service and not the sync_service? clarify
-                                let (block_header, blocks_subscription) =
-                                    sub_utils::subscribe_best(&runtime_service).await;
-                                blocks_stream = Some(
-                                    stream::once(future::ready(block_header))
-                                        .chain(blocks_subscription),
-                                );
+                            if changes_rx.is_none() {
+                                changes_rx =
+                                    Some(storage_subscriptions.subscribe(keys.clone(), 4).await);
                             }
 
-                            let block = match blocks_stream.as_mut().unwrap().next().await {
-                                Some(b) => b,
+                            // The channel is closed if we don't process its items quickly
+                            // enough, in which case we simply subscribe again.
+                            let changes = match changes_rx.as_mut().unwrap().next().await {
+                                Some(c) => c,
                                 None => {
-                                    blocks_stream = None;
+                                    changes_rx = None;
                                     continue;
                                 }
                             };
 
-                            let block_hash = header::hash_from_scale_encoded_header(&block);
-                            let (state_trie_root, block_number) = {
-                                let decoded =
-                                    header::decode(&block, sync_service.block_number_bytes())
-                                        .unwrap();
-                                (decoded.state_root, decoded.number)
-                            };
-
                             let mut out = methods::StorageChangeSet {
-                                block: methods::HashHexString(block_hash),
+                                block: methods::HashHexString(changes.block_hash),
                                 changes: Vec::new(),
                             };
 
-                            for (key_index, key) in list.iter().enumerate() {
-                                // TODO: parallelism?
-                                match sync_service
-                                    .clone()
-                                    .storage_query(
-                                        block_number,
-                                        &block_hash,
-                                        state_trie_root,
-                                        iter::once(&key.0),
-                                        4,
-                                        Duration::from_secs(12),
-                                        NonZeroU32::new(2).unwrap(),
-                                    )
-                                    .await
-                                {
-                                    Ok(mut values) => {
-                                        let value = values.pop().unwrap();
-                                        match &mut known_values[key_index] {
-                                            Some(v) if *v == value => {}
-                                            v => {
-                                                *v = Some(value.clone());
-                                                out.changes.push((
-                                                    key.clone(),
-                                                    value.map(methods::HexString),
-                                                ));
-                                            }
-                                        }
-                                    }
-                                    Err(error) => {
-                                        log::log!(
-                                            target: &log_target,
-                                            if error.is_network_problem() {
-                                                log::Level::Debug
-                                            } else {
-                                                log::Level::Warn
-                                            },
-                                            "state_subscribeStorage changes check failed: {}",
-                                            error
-                                        );
+                            for (key, value) in changes.changes {
+                                let key_index = match keys.iter().position(|k| *k == key) {
+                                    Some(i) => i,
+                                    None => continue,
+                                };
+
+                                match &mut known_values[key_index] {
+                                    Some(v) if *v == value => {}
+                                    v => {
+                                        *v = Some(value.clone());
+                                        out.changes.push((
+                                            methods::HexString(key),
+                                            value.map(methods::HexString),
+                                        ));
                                     }
                                 }
                             }
 
                             if !out.changes.is_empty() {
-                                return Some((out, (blocks_stream, list, known_values)));
+                                return Some((out, (changes_rx, keys, known_values)));
                             }
                         }
                     }
@@ -19,7 +19,7 @@
 
 use super::{Background, Platform, SubscriptionMessage};
 
-use crate::transactions_service;
+use crate::{error::ErrorKind, transactions_service};
 
 use alloc::{borrow::ToOwned as _, str, string::ToString as _, sync::Arc, vec::Vec};
 use futures::prelude::*;
@@ -133,10 +133,8 @@ impl<TPlat: Platform> Background<TPlat> {
                         request_id.1,
                         json_rpc::parse::build_error_response(
                             request_id.0,
-                            json_rpc::parse::ErrorResponse::ServerError(
-                                -32000,
-                                "Too many active subscriptions",
-                            ),
+                            ErrorKind::LimitExceeded
+                                .json_rpc_error("Too many active subscriptions"),
                             None,
                         ),
                     )
@@ -355,6 +353,29 @@ impl<TPlat: Platform> Background<TPlat> {
                         }
                         .to_json_call_object_parameters(None),
 
+                        (transactions_service::TransactionStatus::InclusionProof { .. }, true) => {
+                            // The legacy API has no equivalent.
+                            continue;
+                        }
+                        (
+                            transactions_service::TransactionStatus::InclusionProof {
+                                block_hash,
+                                index,
+                                events_proof,
+                            },
+                            false,
+                        ) => methods::ServerToClient::transaction_unstable_watchEvent {
+                            subscription: (&subscription_id).into(),
+                            result: methods::TransactionWatchEvent::InclusionProof {
+                                block: methods::TransactionWatchEventBlock {
+                                    hash: methods::HashHexString(block_hash),
+                                    index: methods::NumberAsString(index),
+                                },
+                                events_proof: methods::HexString(events_proof),
+                            },
+                        }
+                        .to_json_call_object_parameters(None),
+
                         (
                             transactions_service::TransactionStatus::Dropped(
                                 transactions_service::DropReason::GapInChain,
@@ -367,6 +388,24 @@ impl<TPlat: Platform> Background<TPlat> {
                             ),
                             true,
                         )
+                        | (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::MaxPendingTransactionsBytesReached,
+                            ),
+                            true,
+                        )
+                        | (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::MaxPendingTransactionsPerSenderReached,
+                            ),
+                            true,
+                        )
+                        | (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::Banned,
+                            ),
+                            true,
+                        )
                         | (
                             transactions_service::TransactionStatus::Dropped(
                                 transactions_service::DropReason::Invalid(_),
@@ -409,6 +448,45 @@ impl<TPlat: Platform> Background<TPlat> {
                             },
                         }
                         .to_json_call_object_parameters(None),
+                        (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::MaxPendingTransactionsBytesReached,
+                            ),
+                            false,
+                        ) => methods::ServerToClient::transaction_unstable_watchEvent {
+                            subscription: (&subscription_id).into(),
+                            result: methods::TransactionWatchEvent::Dropped {
+                                error: "transactions pool size limit reached".into(),
+                                broadcasted: num_broadcasted_peers != 0,
+                            },
+                        }
+                        .to_json_call_object_parameters(None),
+                        (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::MaxPendingTransactionsPerSenderReached,
+                            ),
+                            false,
+                        ) => methods::ServerToClient::transaction_unstable_watchEvent {
+                            subscription: (&subscription_id).into(),
+                            result: methods::TransactionWatchEvent::Dropped {
+                                error: "too many pending transactions from the same sender".into(),
+                                broadcasted: num_broadcasted_peers != 0,
+                            },
+                        }
+                        .to_json_call_object_parameters(None),
+                        (
+                            transactions_service::TransactionStatus::Dropped(
+                                transactions_service::DropReason::Banned,
+                            ),
+                            false,
+                        ) => methods::ServerToClient::transaction_unstable_watchEvent {
+                            subscription: (&subscription_id).into(),
+                            result: methods::TransactionWatchEvent::Dropped {
+                                error: "transaction banned".into(),
+                                broadcasted: num_broadcasted_peers != 0,
+                            },
+                        }
+                        .to_json_call_object_parameters(None),
                         (
                             transactions_service::TransactionStatus::Dropped(
                                 transactions_service::DropReason::Invalid(error),
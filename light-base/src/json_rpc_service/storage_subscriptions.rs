@@ -0,0 +1,439 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Storage subscriptions shared between all the JSON-RPC clients of a chain.
+//!
+//! It is very common for multiple JSON-RPC clients connected to the same chain, or for a single
+//! JSON-RPC client through multiple subscriptions, to watch the same storage items. Rather than
+//! downloading the value of these storage items once per subscription, the
+//! [`StorageSubscriptions`] multiplexes all the subscriptions.
+//!
+//! A single background task follows the best block of the chain. Every time the best block
+//! changes, it downloads a single storage proof containing all the keys watched by at least one
+//! subscription, compares the values with the ones of the previous best block, then reports the
+//! changes to every subscription watching the modified keys. If the new best block has the same
+//! storage trie root as the previous one, its storage is identical and nothing is downloaded.
+//!
+//! If the channel returned by [`StorageSubscriptions::subscribe`] is full, it will automatically
+//! be closed so as to not block the other subscriptions if the receiver is too slow to be
+//! processed.
+
+use super::sub_utils;
+use crate::{platform::Platform, runtime_service, sync_service};
+
+use alloc::{boxed::Box, format, string::String, sync::Arc, vec::Vec};
+use core::{marker::PhantomData, num::NonZeroU32, time::Duration};
+use futures::{channel::mpsc, lock::Mutex, prelude::*};
+use smoldot::header;
+
+/// Configuration for a [`StorageSubscriptions`].
+pub struct Config<TPlat: Platform> {
+    /// Name of the chain, for logging purposes.
+    ///
+    /// > **Note**: This name will be directly printed out. Any special character should already
+    /// >           have been filtered out from this name.
+    pub log_name: String,
+
+    /// Closure that spawns background tasks.
+    pub tasks_executor: Box<dyn FnMut(String, future::BoxFuture<'static, ()>) + Send>,
+
+    /// Service responsible for synchronizing the chain.
+    pub sync_service: Arc<sync_service::SyncService<TPlat>>,
+
+    /// Service that provides the best block of the chain.
+    pub runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
+}
+
+/// See [the module-level documentation](..).
+pub struct StorageSubscriptions<TPlat> {
+    /// Sending messages to the background task.
+    to_background: Mutex<mpsc::Sender<ToBackground>>,
+
+    platform: PhantomData<fn() -> TPlat>,
+}
+
+impl<TPlat: Platform> StorageSubscriptions<TPlat> {
+    /// Builds a new [`StorageSubscriptions`] and spawns its background task.
+    pub fn new(mut config: Config<TPlat>) -> Self {
+        let (to_background, from_foreground) = mpsc::channel(8);
+
+        (config.tasks_executor)(
+            format!("storage-subscriptions-{}", config.log_name),
+            Box::pin(background_task(
+                format!("json-rpc-{}", config.log_name),
+                config.sync_service,
+                config.runtime_service,
+                from_foreground,
+            )),
+        );
+
+        StorageSubscriptions {
+            to_background: Mutex::new(to_background),
+            platform: PhantomData,
+        }
+    }
+
+    /// Starts watching the given list of storage keys.
+    ///
+    /// The first item of the returned channel contains the values of all the keys in the best
+    /// block. Each item afterwards contains the keys whose value is different from the one in
+    /// the previous item.
+    ///
+    /// The channel is closed if it becomes full. Watching the keys again afterwards yields a
+    /// channel whose first item contains the values of all the keys, which lets the receiver
+    /// catch up.
+    pub async fn subscribe(
+        &self,
+        keys: Vec<Vec<u8>>,
+        channel_size: usize,
+    ) -> mpsc::Receiver<StorageChanges> {
+        let (sender, rx) = mpsc::channel(channel_size);
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::Subscribe { keys, sender })
+            .await
+            .unwrap();
+
+        rx
+    }
+}
+
+/// Item of the channel returned by [`StorageSubscriptions::subscribe`].
+#[derive(Debug, Clone)]
+pub struct StorageChanges {
+    /// Hash of the block the values are found in.
+    pub block_hash: [u8; 32],
+
+    /// List of keys and their value, or `None` if the key has no value.
+    pub changes: Vec<(Vec<u8>, Option<Vec<u8>>)>,
+}
+
+enum ToBackground {
+    Subscribe {
+        keys: Vec<Vec<u8>>,
+        sender: mpsc::Sender<StorageChanges>,
+    },
+}
+
+struct Subscription {
+    /// Keys watched by this subscription, in the order in which they have been passed to
+    /// [`StorageSubscriptions::subscribe`].
+    keys: Vec<Vec<u8>>,
+
+    /// Sender of the channel returned by [`StorageSubscriptions::subscribe`].
+    sender: mpsc::Sender<StorageChanges>,
+
+    /// `true` if the values of all the keys have already been sent to the subscription.
+    initial_values_sent: bool,
+}
+
+struct KeyState {
+    /// Number of entries in [`Subscription::keys`] of all the subscriptions that are equal to
+    /// this key. The key is removed when this reaches zero.
+    num_subscriptions: usize,
+
+    /// Value of this key that has been reported to the subscriptions, or `None` if the value
+    /// hasn't been downloaded yet.
+    value: Option<Option<Vec<u8>>>,
+
+    /// `true` if [`KeyState::value`] is the value of this key in the current best block.
+    up_to_date: bool,
+
+    /// `true` if the value of this key in the current best block must be downloaded. Set to
+    /// `false` at the start of the download, even if the download later fails.
+    needs_query: bool,
+}
+
+/// Outcome of a storage query, alongside with the storage trie root of the block and the keys
+/// that have been queried.
+type QueryOutcome = (
+    [u8; 32],
+    Vec<Vec<u8>>,
+    Result<Vec<Option<Vec<u8>>>, sync_service::StorageQueryError>,
+);
+
+async fn background_task<TPlat: Platform>(
+    log_target: String,
+    sync_service: Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
+    mut from_foreground: mpsc::Receiver<ToBackground>,
+) {
+    let mut subscriptions = slab::Slab::<Subscription>::new();
+    let mut keys =
+        hashbrown::HashMap::<Vec<u8>, KeyState, fnv::FnvBuildHasher>::with_capacity_and_hasher(
+            16,
+            Default::default(),
+        );
+
+    // Stream of headers of the best block. `None` if there isn't any subscription.
+    let mut best_blocks: Option<stream::BoxStream<'static, Vec<u8>>> = None;
+    // Hash, number, and state trie root of the current best block.
+    let mut current_best: Option<([u8; 32], u64, [u8; 32])> = None;
+    // Storage query currently in progress, if any.
+    let mut query_in_progress: Option<future::BoxFuture<'static, QueryOutcome>> = None;
+
+    loop {
+        // Remove the subscriptions whose receiver has been dropped.
+        let closed = subscriptions
+            .iter()
+            .filter(|(_, subscription)| subscription.sender.is_closed())
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        for subscription_id in closed {
+            remove_subscription(&mut subscriptions, &mut keys, subscription_id);
+        }
+
+        // Start a storage query for all the keys whose value in the best block is needed.
+        if let (None, Some((block_hash, block_number, state_trie_root))) =
+            (&query_in_progress, &current_best)
+        {
+            let to_query = keys
+                .iter_mut()
+                .filter(|(_, state)| state.needs_query)
+                .map(|(key, state)| {
+                    state.needs_query = false;
+                    key.clone()
+                })
+                .collect::<Vec<_>>();
+
+            if !to_query.is_empty() {
+                let sync_service = sync_service.clone();
+                let (block_hash, block_number, state_trie_root) =
+                    (*block_hash, *block_number, *state_trie_root);
+                query_in_progress = Some(Box::pin(async move {
+                    let outcome = sync_service
+                        .storage_query(
+                            block_number,
+                            &block_hash,
+                            &state_trie_root,
+                            to_query.iter(),
+                            4,
+                            Duration::from_secs(12),
+                            NonZeroU32::new(2).unwrap(),
+                        )
+                        .await;
+                    (state_trie_root, to_query, outcome)
+                }));
+            }
+        }
+
+        // Send the values of all the keys to the subscriptions that haven't received them yet.
+        let mut to_remove = Vec::new();
+        if let Some((block_hash, ..)) = &current_best {
+            for (subscription_id, subscription) in subscriptions.iter_mut() {
+                if subscription.initial_values_sent
+                    || !subscription
+                        .keys
+                        .iter()
+                        .all(|key| keys.get(key).unwrap().up_to_date)
+                {
+                    continue;
+                }
+
+                subscription.initial_values_sent = true;
+                let changes = subscription
+                    .keys
+                    .iter()
+                    .map(|key| {
+                        let value = keys.get(key).unwrap().value.clone().unwrap();
+                        (key.clone(), value)
+                    })
+                    .collect();
+                if subscription
+                    .sender
+                    .try_send(StorageChanges {
+                        block_hash: *block_hash,
+                        changes,
+                    })
+                    .is_err()
+                {
+                    to_remove.push(subscription_id);
+                }
+            }
+        }
+        for subscription_id in to_remove {
+            remove_subscription(&mut subscriptions, &mut keys, subscription_id);
+        }
+
+        // Stop following the best block if nobody is interested.
+        if subscriptions.is_empty() {
+            best_blocks = None;
+            current_best = None;
+            query_in_progress = None;
+        } else if best_blocks.is_none() {
+            let runtime_service = runtime_service.clone();
+            best_blocks = Some(
+                stream::once(async move { sub_utils::subscribe_best(&runtime_service).await })
+                    .flat_map(|(current, next)| stream::once(future::ready(current)).chain(next))
+                    .boxed(),
+            );
+        }
+
+        futures::select! {
+            message = from_foreground.next() => {
+                match message {
+                    Some(ToBackground::Subscribe { keys: subscription_keys, sender }) => {
+                        for key in &subscription_keys {
+                            keys.entry(key.clone())
+                                .or_insert(KeyState {
+                                    num_subscriptions: 0,
+                                    value: None,
+                                    up_to_date: false,
+                                    needs_query: true,
+                                })
+                                .num_subscriptions += 1;
+                        }
+
+                        subscriptions.insert(Subscription {
+                            keys: subscription_keys,
+                            sender,
+                            initial_values_sent: false,
+                        });
+                    }
+                    None => {
+                        // The frontend has been destroyed.
+                        return;
+                    }
+                }
+            },
+
+            block = async {
+                match best_blocks.as_mut() {
+                    Some(s) => s.next().await,
+                    None => future::pending().await,
+                }
+            }.fuse() => {
+                let Some(block) = block else {
+                    // The stream is supposed to be infinite. Rebuild it.
+                    best_blocks = None;
+                    continue;
+                };
+
+                let block_hash = header::hash_from_scale_encoded_header(&block);
+                if matches!(current_best, Some((h, ..)) if h == block_hash) {
+                    continue;
+                }
+
+                let decoded = header::decode(&block, sync_service.block_number_bytes()).unwrap();
+                let same_storage = matches!(current_best, Some((.., r)) if r == *decoded.state_root);
+                current_best = Some((block_hash, decoded.number, *decoded.state_root));
+
+                // If the storage trie root is the same as the one of the previous best block,
+                // then the storage is identical and the values don't need to be queried again.
+                if same_storage {
+                    continue;
+                }
+
+                for state in keys.values_mut() {
+                    state.up_to_date = false;
+                    state.needs_query = true;
+                }
+            },
+
+            (state_trie_root, queried_keys, outcome) = async {
+                match query_in_progress.as_mut() {
+                    Some(q) => q.await,
+                    None => future::pending().await,
+                }
+            }.fuse() => {
+                query_in_progress = None;
+
+                // Values of an older best block are discarded, as all the keys need to be
+                // queried again anyway.
+                let block_hash = match current_best {
+                    Some((h, _, r)) if r == state_trie_root => h,
+                    _ => continue,
+                };
+
+                let values = match outcome {
+                    Ok(values) => values,
+                    Err(error) => {
+                        log::log!(
+                            target: &log_target,
+                            if error.is_network_problem() {
+                                log::Level::Debug
+                            } else {
+                                log::Level::Warn
+                            },
+                            "state_subscribeStorage changes check failed: {}",
+                            error
+                        );
+                        continue;
+                    }
+                };
+
+                // Compare the values with the previous ones.
+                let mut changed_keys =
+                    hashbrown::HashSet::<Vec<u8>, fnv::FnvBuildHasher>::with_hasher(Default::default());
+                for (key, value) in queried_keys.into_iter().zip(values) {
+                    // The key might have stopped being watched while the query was in
+                    // progress.
+                    let Some(state) = keys.get_mut(&key) else { continue };
+                    state.up_to_date = true;
+                    if state.value.as_ref() != Some(&value) {
+                        state.value = Some(value);
+                        changed_keys.insert(key);
+                    }
+                }
+
+                // Report the changes to the subscriptions that have already received the initial
+                // values. The other subscriptions are handled at the next iteration.
+                let mut to_remove = Vec::new();
+                for (subscription_id, subscription) in subscriptions.iter_mut() {
+                    if !subscription.initial_values_sent {
+                        continue;
+                    }
+
+                    let changes = subscription
+                        .keys
+                        .iter()
+                        .filter(|key| changed_keys.contains(&key[..]))
+                        .map(|key| (key.clone(), keys.get(key).unwrap().value.clone().unwrap()))
+                        .collect::<Vec<_>>();
+                    if changes.is_empty() {
+                        continue;
+                    }
+
+                    if subscription.sender.try_send(StorageChanges { block_hash, changes }).is_err() {
+                        to_remove.push(subscription_id);
+                    }
+                }
+                for subscription_id in to_remove {
+                    remove_subscription(&mut subscriptions, &mut keys, subscription_id);
+                }
+            },
+        }
+    }
+}
+
+/// Removes a subscription, and the keys that are no longer watched by any subscription.
+fn remove_subscription(
+    subscriptions: &mut slab::Slab<Subscription>,
+    keys: &mut hashbrown::HashMap<Vec<u8>, KeyState, fnv::FnvBuildHasher>,
+    subscription_id: usize,
+) {
+    let subscription = subscriptions.remove(subscription_id);
+    for key in subscription.keys {
+        let state = keys.get_mut(&key).unwrap();
+        state.num_subscriptions -= 1;
+        if state.num_subscriptions == 0 {
+            keys.remove(&key);
+        }
+    }
+}
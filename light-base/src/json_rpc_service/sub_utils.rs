@@ -23,7 +23,7 @@ use crate::{
     runtime_service::{Notification, RuntimeError, RuntimeService},
 };
 
-use alloc::{sync::Arc, vec::Vec};
+use alloc::{sync::Arc, vec, vec::Vec};
 use core::num::NonZeroUsize;
 use futures::prelude::*;
 use smoldot::{executor, header};
@@ -66,7 +66,7 @@ pub async fn subscribe_runtime_version<TPlat: Platform>(
 
         headers.insert(
             current_finalized_hash,
-            Arc::new(subscribe_all.finalized_block_runtime),
+            Arc::new(subscribe_all.finalized_block_runtime.map(|rt| rt.spec)),
         );
 
         let mut current_best = None;
@@ -75,7 +75,7 @@ pub async fn subscribe_runtime_version<TPlat: Platform>(
             subscribe_all.new_blocks.unpin_block(&hash).await;
 
             if let Some(new_runtime) = block.new_runtime {
-                headers.insert(hash, Arc::new(new_runtime));
+                headers.insert(hash, Arc::new(new_runtime.map(|rt| rt.spec)));
             } else {
                 let parent_runtime = headers
                     .get(&block.parent_hash)
@@ -114,7 +114,7 @@ pub async fn subscribe_runtime_version<TPlat: Platform>(
                             new_blocks.unpin_block(&hash).await;
 
                             if let Some(new_runtime) = block.new_runtime {
-                                headers.insert(hash, Arc::new(new_runtime));
+                                headers.insert(hash, Arc::new(new_runtime.map(|rt| rt.spec)));
                             } else {
                                 let parent_runtime = headers
                                     .get(&block.parent_hash)
@@ -309,6 +309,39 @@ pub async fn subscribe_finalized<TPlat: Platform>(
 pub async fn subscribe_best<TPlat: Platform>(
     runtime_service: &Arc<RuntimeService<TPlat>>,
 ) -> (Vec<u8>, stream::BoxStream<'static, Vec<u8>>) {
+    let (first_value, stream) = subscribe_best_reorgs(runtime_service).await;
+    let stream = stream
+        .map(|mut update| update.enacted.pop().unwrap())
+        .boxed();
+    (first_value, stream)
+}
+
+/// Change of best block reported by [`subscribe_best_reorgs`].
+#[derive(Debug, Clone)]
+pub struct BestBlockUpdate {
+    /// BLAKE2 hashes of the headers of the blocks that were part of the best chain and no longer
+    /// are, ordered from the former best block to the child of the common ancestor of the former
+    /// and new best blocks. Empty if the new best block is a descendant of the former one.
+    pub retracted: Vec<[u8; 32]>,
+
+    /// SCALE-encoded headers of the blocks that are now part of the best chain and weren't
+    /// before, in ancestry order. The last element is the header of the new best block. Never
+    /// empty.
+    pub enacted: Vec<Vec<u8>>,
+}
+
+/// Returns the SCALE-encoded header of the current best block, plus an unlimited stream that
+/// produces one item every time the best block is changed.
+///
+/// Contrary to [`subscribe_best`], each item of the stream contains the list of blocks that
+/// leave the best chain and the list of blocks that join the best chain, which makes it
+/// possible to follow the best chain block by block, including during re-orgs.
+///
+/// This function only returns once the runtime of the current best block is known. This might
+/// take a long time.
+pub async fn subscribe_best_reorgs<TPlat: Platform>(
+    runtime_service: &Arc<RuntimeService<TPlat>>,
+) -> (Vec<u8>, stream::BoxStream<'static, BestBlockUpdate>) {
     let mut master_stream = stream::unfold(runtime_service.clone(), |runtime_service| async move {
         let subscribe_all = runtime_service
             .subscribe_all("subscribe-best", 16, NonZeroUsize::new(32).unwrap())
@@ -322,6 +355,13 @@ pub async fn subscribe_best<TPlat: Platform>(
                 Default::default(),
             );
 
+        // Map of parent block hashes by hash. Contains all non-finalized blocks.
+        let mut parents =
+            hashbrown::HashMap::<[u8; 32], [u8; 32], fnv::FnvBuildHasher>::with_capacity_and_hasher(
+                16,
+                Default::default(),
+            );
+
         let current_finalized_hash = header::hash_from_scale_encoded_header(
             &subscribe_all.finalized_block_scale_encoded_header,
         );
@@ -341,6 +381,7 @@ pub async fn subscribe_best<TPlat: Platform>(
             let hash = header::hash_from_scale_encoded_header(&block.scale_encoded_header);
             subscribe_all.new_blocks.unpin_block(&hash).await;
             headers.insert(hash, block.scale_encoded_header);
+            parents.insert(hash, block.parent_hash);
 
             if block.is_new_best {
                 debug_assert!(current_best.is_none());
@@ -350,17 +391,19 @@ pub async fn subscribe_best<TPlat: Platform>(
         let current_best = current_best.unwrap_or(current_finalized_hash);
         let current_best_header = headers.get(&current_best).unwrap().clone();
 
-        // Turns `subscribe_all.new_blocks` into a stream of headers.
+        // Turns `subscribe_all.new_blocks` into a stream of updates.
         let substream = stream::unfold(
             (
                 subscribe_all.new_blocks,
                 headers,
+                parents,
                 current_finalized_hash,
                 current_best,
             ),
             |(
                 mut new_blocks,
                 mut headers,
+                mut parents,
                 mut current_finalized_hash,
                 mut current_best,
             )| async move {
@@ -371,16 +414,23 @@ pub async fn subscribe_best<TPlat: Platform>(
                                 header::hash_from_scale_encoded_header(&block.scale_encoded_header);
                             new_blocks.unpin_block(&hash).await;
                             headers.insert(hash, block.scale_encoded_header);
+                            parents.insert(hash, block.parent_hash);
 
                             if block.is_new_best {
+                                let update = best_block_update(
+                                    &headers,
+                                    &parents,
+                                    &current_finalized_hash,
+                                    &current_best,
+                                    &hash,
+                                );
                                 current_best = hash;
-                                let header =
-                                    headers.get(&current_best).unwrap().clone();
                                 break Some((
-                                    header,
+                                    update,
                                     (
                                         new_blocks,
                                         headers,
+                                        parents,
                                         current_finalized_hash,
                                         current_best,
                                     ),
@@ -392,26 +442,39 @@ pub async fn subscribe_best<TPlat: Platform>(
                             pruned_blocks,
                             best_block_hash,
                         } => {
+                            // The update must be calculated before the pruned blocks are
+                            // removed, as the former best block might be one of them.
+                            let update = if best_block_hash != current_best {
+                                Some(best_block_update(
+                                    &headers,
+                                    &parents,
+                                    &current_finalized_hash,
+                                    &current_best,
+                                    &best_block_hash,
+                                ))
+                            } else {
+                                None
+                            };
+
                             // Clean up the headers we won't need anymore.
                             for pruned_block in pruned_blocks {
                                 let _was_in = headers.remove(&pruned_block);
                                 debug_assert!(_was_in.is_some());
+                                parents.remove(&pruned_block);
                             }
 
-                            let _ = headers
-                                .remove(&current_finalized_hash)
-                                .unwrap();
+                            let _ = headers.remove(&current_finalized_hash).unwrap();
+                            parents.remove(&hash);
                             current_finalized_hash = hash;
+                            current_best = best_block_hash;
 
-                            if best_block_hash != current_best {
-                                current_best = best_block_hash;
-                                let header =
-                                    headers.get(&current_best).unwrap().clone();
+                            if let Some(update) = update {
                                 break Some((
-                                    header,
+                                    update,
                                     (
                                         new_blocks,
                                         headers,
+                                        parents,
                                         current_finalized_hash,
                                         current_best,
                                     ),
@@ -420,14 +483,20 @@ pub async fn subscribe_best<TPlat: Platform>(
                         }
                         Notification::BestBlockChanged { hash } => {
                             if hash != current_best {
+                                let update = best_block_update(
+                                    &headers,
+                                    &parents,
+                                    &current_finalized_hash,
+                                    &current_best,
+                                    &hash,
+                                );
                                 current_best = hash;
-                                let header =
-                                    headers.get(&current_best).unwrap().clone();
                                 break Some((
-                                    header,
+                                    update,
                                     (
                                         new_blocks,
                                         headers,
+                                        parents,
                                         current_finalized_hash,
                                         current_best,
                                     ),
@@ -440,7 +509,11 @@ pub async fn subscribe_best<TPlat: Platform>(
         );
 
         // Prepend the current best block to the stream.
-        let substream = stream::once(future::ready(current_best_header)).chain(substream);
+        let substream = stream::once(future::ready(BestBlockUpdate {
+            retracted: Vec::new(),
+            enacted: vec![current_best_header],
+        }))
+        .chain(substream);
         Some((substream, runtime_service))
     })
     .flatten()
@@ -449,6 +522,46 @@ pub async fn subscribe_best<TPlat: Platform>(
     // TODO: we don't dedup blocks; in other words the stream can produce the same block twice if the inner subscription drops
 
     // Now that we have a stream, extract the first element to be the first value.
-    let first_value = master_stream.next().await.unwrap();
+    let first_value = master_stream.next().await.unwrap().enacted.pop().unwrap();
     (first_value, master_stream)
 }
+
+/// Builds the [`BestBlockUpdate`] corresponding to the best block switching from `former_best`
+/// to `new_best`.
+///
+/// Both `former_best` and `new_best` must be equal to or descend from `finalized`.
+fn best_block_update(
+    headers: &hashbrown::HashMap<[u8; 32], Vec<u8>, fnv::FnvBuildHasher>,
+    parents: &hashbrown::HashMap<[u8; 32], [u8; 32], fnv::FnvBuildHasher>,
+    finalized: &[u8; 32],
+    former_best: &[u8; 32],
+    new_best: &[u8; 32],
+) -> BestBlockUpdate {
+    // List of the new best block and its ancestors, up to either the former best block or
+    // the finalized block.
+    let mut new_branch = vec![*new_best];
+    while new_branch.last().unwrap() != former_best && new_branch.last().unwrap() != finalized {
+        new_branch.push(*parents.get(new_branch.last().unwrap()).unwrap());
+    }
+
+    // Walk up from the former best block until reaching one of the blocks of `new_branch`,
+    // which is then the common ancestor.
+    let mut retracted = Vec::new();
+    let mut iter = *former_best;
+    let common_ancestor_index = loop {
+        if let Some(index) = new_branch.iter().position(|b| *b == iter) {
+            break index;
+        }
+        retracted.push(iter);
+        iter = *parents.get(&iter).unwrap();
+    };
+
+    BestBlockUpdate {
+        retracted,
+        enacted: new_branch[..common_ancestor_index]
+            .iter()
+            .rev()
+            .map(|hash| headers.get(hash).unwrap().clone())
+            .collect(),
+    }
+}
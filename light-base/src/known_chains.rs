@@ -0,0 +1,78 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Well-known chains whose specification can be embedded in the client.
+//!
+//! If the `known-chains` feature is enabled, the specifications of the well-known chains found
+//! in the `chain-specs` directory of this crate are embedded in the client. They can then be
+//! added by passing their identifier to [`crate::ChainSpecification::WellKnown`], similar to
+//! what the `@substrate/connect` JavaScript library does. See [`crate::well_known_chains`].
+
+/// A well-known chain.
+struct KnownChain {
+    /// Identifier of the chain, as found in its chain specification. Also used by
+    /// `@substrate/connect` to designate the chain.
+    id: &'static str,
+    /// JSON specification of the chain.
+    #[cfg(feature = "known-chains")]
+    chain_spec: &'static str,
+}
+
+/// List of the well-known chains.
+const KNOWN_CHAINS: &[KnownChain] = &[
+    KnownChain {
+        id: "polkadot",
+        #[cfg(feature = "known-chains")]
+        chain_spec: include_str!("../chain-specs/polkadot.json"),
+    },
+    KnownChain {
+        id: "ksmcc3",
+        #[cfg(feature = "known-chains")]
+        chain_spec: include_str!("../chain-specs/kusama.json"),
+    },
+    KnownChain {
+        id: "westend2",
+        #[cfg(feature = "known-chains")]
+        chain_spec: include_str!("../chain-specs/westend.json"),
+    },
+];
+
+/// Returns the identifiers of the well-known chains whose specification is embedded in the
+/// client. Empty if the `known-chains` feature is disabled.
+pub(crate) fn chain_ids() -> impl Iterator<Item = &'static str> {
+    KNOWN_CHAINS
+        .iter()
+        .filter(|_| cfg!(feature = "known-chains"))
+        .map(|chain| chain.id)
+}
+
+/// Returns the JSON specification of the well-known chain with the given identifier, or `None`
+/// if there is no such chain or if the `known-chains` feature is disabled.
+pub(crate) fn chain_spec(id: &str) -> Option<&'static str> {
+    #[cfg(feature = "known-chains")]
+    {
+        KNOWN_CHAINS
+            .iter()
+            .find(|chain| chain.id == id)
+            .map(|chain| chain.chain_spec)
+    }
+    #[cfg(not(feature = "known-chains"))]
+    {
+        let _ = id;
+        None
+    }
+}
@@ -73,8 +73,16 @@
 extern crate alloc;
 
 use alloc::{borrow::ToOwned as _, boxed::Box, format, string::String, sync::Arc, vec, vec::Vec};
-use core::{num::NonZeroU32, pin::Pin};
-use futures::{channel::oneshot, prelude::*};
+use core::{
+    fmt,
+    num::{NonZeroU32, NonZeroUsize},
+    pin::Pin,
+    time::Duration,
+};
+use futures::{
+    channel::{mpsc, oneshot},
+    prelude::*,
+};
 use hashbrown::{hash_map::Entry, HashMap};
 use itertools::Itertools as _;
 use smoldot::{
@@ -84,18 +92,53 @@ use smoldot::{
     libp2p::{connection, multiaddr, peer_id},
 };
 
+mod account_info;
+mod account_watch;
+mod block_bundle;
 mod database;
+mod error;
+mod fee_estimation_service;
 mod json_rpc_service;
+mod known_chains;
+mod metrics;
 mod network_service;
+#[cfg(feature = "sqlite-cache")]
+mod persistent_cache;
 mod runtime_service;
+mod scheduler;
+mod session_info;
+mod staking_info;
+mod state_commitment;
+mod storage_changes;
+mod storage_snapshot;
 mod sync_service;
 mod transactions_service;
 mod util;
 
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod log_filter;
 pub mod platform;
-
-pub use json_rpc_service::HandleRpcError;
+mod spans;
+
+pub use account_info::{AccountInfoAtBlock, AccountInfoBlock, AccountInfoError};
+pub use account_watch::{AccountWatchError, AccountWatchEvent};
+pub use block_bundle::{BlockBundle, BlockBundleError, BlockBundleEvent, MetadataCallError};
+pub use error::ErrorKind;
+pub use fee_estimation_service::{EstimateFeeError, FeeEstimate};
+pub use json_rpc_service::{HandleRpcError, MethodsFilter as JsonRpcMethodsFilter};
+pub use metrics::{LatencyPercentiles, MetricsSnapshot};
 pub use peer_id::PeerId;
+#[cfg(feature = "sqlite-cache")]
+pub use persistent_cache::{PersistentCache, PersistentCacheOpenError};
+pub use session_info::{SessionValidators, SessionValidatorsError};
+pub use smoldot::chain::state_commitment::StateCommitment;
+pub use staking_info::{StakingAtBlock, StakingQueryError, ValidatorExposure};
+pub use state_commitment::StateCommitmentError;
+pub use storage_changes::StorageChangesError;
+pub use storage_snapshot::{StorageSnapshot, StorageSnapshotError};
+pub use sync_service::{BlockAnnouncePolicy, InjectFinalityProofError, RetainedFinalityProof};
+pub use transactions_service::{EvictionPolicy as TransactionsEvictionPolicy, TransactionBan};
 
 /// Configuration for a client.
 ///
@@ -113,6 +156,23 @@ pub struct ClientConfig {
     /// Value returned when a JSON-RPC client requests the version of the client. Reasonable value
     /// is `env!("CARGO_PKG_VERSION")`.
     pub system_version: String,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block. Blocks that pretend to come from further in the future than this
+    /// tolerance are considered invalid.
+    ///
+    /// Increasing this value makes it possible to follow chains on devices whose clock is
+    /// skewed. Reasonable value is [`smoldot::verify::DEFAULT_CLOCK_DRIFT_TOLERANCE`].
+    pub clock_drift_tolerance: Duration,
+
+    /// Maximum number of networking requests that can be in progress at the same time towards
+    /// a single peer. Additional requests wait for a previous one to finish, and are started in
+    /// order of priority: requests necessary to finalize blocks first, and requests downloading
+    /// block bodies and storage proofs last.
+    ///
+    /// A low value keeps the finality lag low when the bandwidth is constrained, while a high
+    /// value makes it possible to download more data in parallel. Reasonable value is `3`.
+    pub max_requests_per_peer: NonZeroUsize,
 }
 
 /// See [`Client::add_chain`].
@@ -121,8 +181,26 @@ pub struct AddChainConfig<'a, TChain, TRelays> {
     /// Opaque user data that the [`Client`] will hold for this chain.
     pub user_data: TChain,
 
-    /// JSON text containing the specification of the chain (the so-called "chain spec").
-    pub specification: &'a str,
+    /// Specification of the chain (the so-called "chain spec").
+    pub specification: ChainSpecification<'a>,
+
+    /// Storage of the genesis block, provided separately from the chain specification.
+    ///
+    /// If `Some`, replaces the genesis storage found in the chain specification, if any. This
+    /// makes it possible to use a chain specification that only contains the hash of the root of
+    /// the genesis storage trie, and to host the potentially large genesis storage separately.
+    ///
+    /// If `None`, the genesis storage found in the chain specification is used.
+    pub genesis_storage: Option<GenesisStorage<'a>>,
+
+    /// Fork id to insert in the names of the networking protocols of the chain.
+    ///
+    /// Chains that share the same genesis block, for example because one has been forked from
+    /// the other, are distinguished by their fork id. Nodes only communicate with nodes that use
+    /// the same fork id.
+    ///
+    /// Use `ForkIdOverride::default()` in order to use the fork id of the chain specification.
+    pub fork_id: ForkIdOverride<'a>,
 
     /// Opaque data containing the database content that was retrieved by calling
     /// the `chainHead_unstable_finalizedDatabase` JSON-RPC function in the past.
@@ -146,9 +224,219 @@ pub struct AddChainConfig<'a, TChain, TRelays> {
     /// be wrong to connect to the "Kusama" created by user A.
     pub potential_relay_chains: TRelays,
 
+    /// If `Some` and the chain is a parachain whose relay chain can't be found in
+    /// [`AddChainConfig::potential_relay_chains`], the relay chain is added automatically,
+    /// provided that it is one of the [`well_known_chains`], with this value as its user data.
+    ///
+    /// The relay chain is added without any database and without any JSON-RPC service, and uses
+    /// the same [`AddChainConfig::block_announce_policy`] and
+    /// [`AddChainConfig::warp_sync_min_distinct_peers`] as the parachain. Its identifier is
+    /// reported in [`AddChainSuccess::relay_chain_id`], and it must be removed with
+    /// [`Client::remove_chain`] like any other chain.
+    ///
+    /// Ignored if the chain isn't a parachain or if a relay chain has been found.
+    pub auto_add_relay_chain: Option<TChain>,
+
     /// If `true`, then no JSON-RPC service is started for this chain. This saves up a lot of
     /// resources, but will cause all JSON-RPC requests targeting this chain to fail.
     pub disable_json_rpc: bool,
+
+    /// Restriction on the JSON-RPC methods that the JSON-RPC service of this chain serves.
+    /// Ignored if [`AddChainConfig::disable_json_rpc`] is `true`.
+    ///
+    /// Use `JsonRpcMethodsFilter::default()` in order to serve all the methods.
+    pub json_rpc_methods_filter: JsonRpcMethodsFilter,
+
+    /// If `true`, the JSON-RPC service of this chain also serves a minimal subset of the
+    /// Ethereum JSON-RPC functions (`eth_blockNumber`, `eth_chainId`, `eth_getBalance`, and
+    /// `eth_sendRawTransaction`), by calling the runtime APIs provided by Frontier. Only
+    /// meaningful for chains based on Frontier. Ignored if [`AddChainConfig::disable_json_rpc`]
+    /// is `true`.
+    ///
+    /// If `false`, calling these functions results in the same error as calling a function that
+    /// doesn't exist.
+    pub ethereum_json_rpc: bool,
+
+    /// How to react to block announces received from the network. Ignored if the chain is a
+    /// parachain.
+    ///
+    /// Use [`BlockAnnouncePolicy::Immediate`] if in doubt.
+    pub block_announce_policy: BlockAnnouncePolicy,
+
+    /// Number of distinct peers that a warp sync proof is requested from. The client waits until
+    /// it is connected to that many peers before requesting the proofs, and only uses them if
+    /// they agree with each other. Ignored if the chain is a parachain.
+    ///
+    /// Requiring multiple peers makes it more difficult for an attacker that controls the
+    /// network access of the client to feed it with a proof that stops at an older block than
+    /// the actual head of the chain. Use `1` in order to disable this requirement.
+    ///
+    /// Peers are considered as distinct if their identity is different. Nothing prevents an
+    /// attacker from running multiple nodes with different identities.
+    ///
+    /// > **Note**: A chain whose network contains fewer nodes than this value, such as a test
+    /// >           network with a single node, never finishes warp syncing.
+    pub warp_sync_min_distinct_peers: NonZeroU32,
+
+    /// Number of most recent finalized blocks whose finality proof (justification or GrandPa
+    /// commit) is kept in memory after having been verified, and can be retrieved with
+    /// [`Client::finality_proof`]. This is useful in order to keep an audit trail of the blocks
+    /// that have been finalized. Ignored if the chain is a parachain.
+    ///
+    /// Use `0` in order to not retain any finality proof.
+    pub finality_proofs_window: u32,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. See [`smoldot::sync::all::Config::skip_seal_verification`]. Ignored if
+    /// the chain is a parachain.
+    ///
+    /// > **Note**: This is meant to be used when benchmarking, or against a chain whose blocks
+    /// >           are already known to be valid, such as a local test network. Passing `true`
+    /// >           makes it possible for any peer to make the client consider as valid blocks
+    /// >           that weren't authored by a legitimate authority.
+    pub skip_seal_verification: bool,
+
+    /// Configuration of the pool of transactions that have been submitted through the JSON-RPC
+    /// interface and that aren't included in the finalized chain yet.
+    ///
+    /// Use `TransactionsPoolConfig::default()` if in doubt.
+    pub transactions_pool: TransactionsPoolConfig,
+
+    /// If `Some`, a background task periodically exports the latest finalized state of the
+    /// chain, in the same format as the `chainHead_unstable_finalizedDatabase` JSON-RPC
+    /// function, and sends it to [`AddChainSuccess::checkpoints`]. The exported value can later
+    /// be passed back as [`AddChainConfig::database_content`].
+    ///
+    /// This saves the API user from having to implement its own schedule in order to always
+    /// persist a fresh checkpoint.
+    pub checkpoint_refresh: Option<CheckpointRefreshConfig>,
+}
+
+/// See [`AddChainConfig::checkpoint_refresh`].
+#[derive(Debug, Clone)]
+pub struct CheckpointRefreshConfig {
+    /// Minimum time between two consecutive checkpoints.
+    ///
+    /// No checkpoint is exported while the chain isn't synchronized, for example during the
+    /// initial warp sync.
+    pub period: Duration,
+
+    /// Maximum size, in bytes, of each checkpoint. A checkpoint is intentionally truncated or
+    /// invalid if this value is too low to fit all the information.
+    pub max_size: usize,
+}
+
+/// See [`AddChainConfig::transactions_pool`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct TransactionsPoolConfig {
+    /// Maximum number of transactions in the pool.
+    pub max_transactions: NonZeroU32,
+
+    /// Maximum sum of the sizes, in bytes, of the SCALE-encoded transactions in the pool.
+    pub max_bytes: NonZeroUsize,
+
+    /// Maximum number of transactions in the pool that are signed by the same account.
+    ///
+    /// Only transactions whose sender can be determined are subject to this limit.
+    pub max_transactions_per_sender: NonZeroU32,
+
+    /// What to do when submitting a transaction would exceed one of the limits above.
+    pub eviction_policy: TransactionsEvictionPolicy,
+
+    /// Maximum number of peers that a transaction is sent to every time it is announced. The
+    /// peers are chosen randomly amongst the ones the client is connected to.
+    ///
+    /// A low value reduces the bandwidth usage on networks where every node is connected to
+    /// every other node, while a high value increases the chances of a transaction reaching a
+    /// block author when the client is poorly connected.
+    pub max_broadcast_peers: NonZeroUsize,
+
+    /// Duration between two announcements of the same transaction, as long as it isn't
+    /// included in the best chain.
+    pub reannounce_interval: Duration,
+}
+
+impl Default for TransactionsPoolConfig {
+    fn default() -> Self {
+        TransactionsPoolConfig {
+            max_transactions: NonZeroU32::new(64).unwrap(),
+            max_bytes: NonZeroUsize::new(8 * 1024 * 1024).unwrap(),
+            max_transactions_per_sender: NonZeroU32::new(16).unwrap(),
+            eviction_policy: TransactionsEvictionPolicy::RejectNew,
+            max_broadcast_peers: NonZeroUsize::new(usize::max_value()).unwrap(),
+            reannounce_interval: Duration::from_secs(5),
+        }
+    }
+}
+
+/// See [`AddChainConfig::specification`].
+#[derive(Clone)]
+pub enum ChainSpecification<'a> {
+    /// JSON text containing the chain spec.
+    Json(&'a str),
+    /// Chain spec that has already been decoded, for example by using a
+    /// [`chain_spec::IncrementalParser`] in order to spread the decoding over time.
+    Parsed(chain_spec::ChainSpec),
+    /// Identifier of a well-known chain whose specification is embedded in the client, such as
+    /// `polkadot`, `ksmcc3` (Kusama), or `westend2`. See [`well_known_chains`].
+    ///
+    /// [`AddChainError::UnknownWellKnownChain`] is returned if the chain isn't known, which is
+    /// always the case if the `known-chains` feature is disabled.
+    WellKnown(&'a str),
+}
+
+/// See [`AddChainConfig::fork_id`].
+#[derive(Debug, Copy, Clone, Default)]
+pub enum ForkIdOverride<'a> {
+    /// Use the fork id found in the chain specification, if any.
+    #[default]
+    ChainSpecification,
+    /// Don't use any fork id, even if the chain specification contains one.
+    Disabled,
+    /// Use the given fork id, whatever the chain specification contains.
+    Custom(&'a str),
+}
+
+/// Returns the identifiers of the chains that can be passed to
+/// [`ChainSpecification::WellKnown`].
+///
+/// The specifications of these chains are embedded in the client only if the `known-chains`
+/// feature is enabled. This function returns an empty list otherwise.
+pub fn well_known_chains() -> impl Iterator<Item = &'static str> {
+    known_chains::chain_ids()
+}
+
+/// See [`AddChainConfig::genesis_storage`].
+#[derive(Clone)]
+pub enum GenesisStorage<'a> {
+    /// Binary blob in the format decoded by [`chain_spec::GenesisStorageParser`].
+    Binary(&'a [u8]),
+    /// Genesis storage that has already been decoded, for example by using a
+    /// [`chain_spec::GenesisStorageParser`] in order to spread the decoding over time.
+    Parsed(chain_spec::SeparateGenesisStorage),
+}
+
+impl<'a> fmt::Debug for ChainSpecification<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChainSpecification::Json(json) => f.debug_tuple("Json").field(json).finish(),
+            ChainSpecification::Parsed(chain_spec) => {
+                f.debug_tuple("Parsed").field(&chain_spec.id()).finish()
+            }
+            ChainSpecification::WellKnown(id) => f.debug_tuple("WellKnown").field(id).finish(),
+        }
+    }
+}
+
+impl<'a> fmt::Debug for GenesisStorage<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisStorage::Binary(data) => f.debug_tuple("Binary").field(&data.len()).finish(),
+            GenesisStorage::Parsed(storage) => {
+                f.debug_tuple("Parsed").field(&storage.len()).finish()
+            }
+        }
+    }
 }
 
 /// Chain registered in a [`Client`].
@@ -178,6 +466,9 @@ pub struct Client<TPlat: platform::Platform, TChain = ()> {
     // TODO: use SipHasher
     chains_by_key: HashMap<ChainKey, RunningChain<TPlat>, fnv::FnvBuildHasher>,
 
+    /// Value to put in the next [`ChainKeyGenesis::PendingBlockHash`].
+    next_pending_block_hash_id: u64,
+
     /// Value to return when the `system_name` RPC is called. Should be set to the name of the
     /// final executable.
     system_name: String,
@@ -185,6 +476,16 @@ pub struct Client<TPlat: platform::Platform, TChain = ()> {
     /// Value to return when the `system_version` RPC is called. Should be set to the version of
     /// the final executable.
     system_version: String,
+
+    /// See [`ClientConfig::clock_drift_tolerance`].
+    clock_drift_tolerance: Duration,
+
+    /// See [`ClientConfig::max_requests_per_peer`].
+    max_requests_per_peer: NonZeroUsize,
+
+    /// See [`Client::set_persistent_cache`].
+    #[cfg(feature = "sqlite-cache")]
+    persistent_cache: Option<Arc<PersistentCache>>,
 }
 
 struct PublicApiChain<TChain> {
@@ -206,8 +507,37 @@ struct PublicApiChain<TChain> {
     /// Dummy channel. Nothing is ever sent on it, but the receiving side is stored in the
     /// [`JsonRpcResponses`] in order to detect when the chain has been removed.
     _public_api_chain_destroyed_tx: oneshot::Sender<()>,
+
+    /// Same as [`PublicApiChain::_public_api_chain_destroyed_tx`], but for each
+    /// [`JsonRpcEndpoint`] created with [`Client::json_rpc_endpoint`].
+    json_rpc_endpoints_destroyed_tx: Vec<oneshot::Sender<()>>,
+
+    /// Same as [`PublicApiChain::_public_api_chain_destroyed_tx`], but for each task started
+    /// with [`Client::watch_accounts`].
+    account_watches_destroyed_tx: Vec<oneshot::Sender<()>>,
+
+    /// Dummy channel. Nothing is ever sent on it, but the receiving side is held by the task
+    /// that exports checkpoints in order to detect when the chain has been removed. `None` iff
+    /// [`AddChainConfig::checkpoint_refresh`] was `None` when adding the chain.
+    _checkpoints_task_stop_tx: Option<oneshot::Sender<()>>,
+
+    /// Same as [`PublicApiChain::_checkpoints_task_stop_tx`], but for the task that writes to
+    /// the persistent cache. `None` iff no persistent cache was set when adding the chain.
+    _persistent_cache_task_stop_tx: Option<oneshot::Sender<()>>,
+
+    /// `true` if [`Client::pause_chain`] has been called and [`Client::resume_chain`] hasn't
+    /// been called afterwards.
+    paused: bool,
 }
 
+/// Time between two consecutive writes of a chain to the persistent cache.
+#[cfg(feature = "sqlite-cache")]
+const PERSISTENT_CACHE_REFRESH_PERIOD: Duration = Duration::from_secs(60);
+
+/// Maximum size, in bytes, of the database of a chain written to the persistent cache.
+#[cfg(feature = "sqlite-cache")]
+const PERSISTENT_CACHE_DATABASE_MAX_SIZE: usize = 1024 * 1024;
+
 /// Identifies a chain, so that multiple identical chains are de-duplicated.
 ///
 /// This struct serves as the key in a `HashMap<ChainKey, ChainServices>`. It must contain all the
@@ -216,17 +546,61 @@ struct PublicApiChain<TChain> {
 /// [`ChainServices`], which has security consequences.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct ChainKey {
-    /// Hash of the genesis block of the chain.
-    genesis_block_hash: [u8; 32],
+    /// Identifies the genesis block of the chain.
+    genesis: ChainKeyGenesis,
 
     // TODO: what about light checkpoints?
-    // TODO: must also contain forkBlocks, and badBlocks fields
     /// If the chain is a parachain, contains the relay chain and the "para ID" on this relay
     /// chain.
     relay_chain: Option<(Box<ChainKey>, u32)>,
 
-    /// Networking fork id, found in the chain specification.
+    /// Networking fork id, found in the chain specification or overridden with
+    /// [`AddChainConfig::fork_id`].
     fork_id: Option<String>,
+
+    /// List of hashes of blocks that are known to be invalid, found in the chain specification.
+    /// Sorted in order to not depend on the order in the chain specification.
+    bad_blocks: Vec<[u8; 32]>,
+
+    /// List of block heights and hashes imposed by the chain specification. Sorted in order to
+    /// not depend on the order in the chain specification.
+    fork_blocks: Vec<(u64, [u8; 32])>,
+
+    /// List of block heights and BLAKE2 hashes of the runtime code substitutes found in the
+    /// chain specification. Sorted in order to not depend on the order in the chain
+    /// specification.
+    code_substitutes: Vec<(u64, [u8; 32])>,
+
+    /// See [`AddChainConfig::block_announce_policy`].
+    block_announce_policy: BlockAnnouncePolicy,
+
+    /// See [`AddChainConfig::warp_sync_min_distinct_peers`].
+    warp_sync_min_distinct_peers: NonZeroU32,
+
+    /// See [`AddChainConfig::finality_proofs_window`].
+    finality_proofs_window: u32,
+
+    /// See [`AddChainConfig::skip_seal_verification`].
+    skip_seal_verification: bool,
+
+    /// See [`AddChainConfig::transactions_pool`].
+    transactions_pool: TransactionsPoolConfig,
+}
+
+/// See [`ChainKey::genesis`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum ChainKeyGenesis {
+    /// Hash of the genesis block.
+    BlockHash([u8; 32]),
+
+    /// The chain specification contains the storage of the genesis block, but the trie root hash
+    /// of this storage, and thus the hash of the genesis block, isn't known yet and is being
+    /// calculated in the background. Contains a value that is unique to each chain in that
+    /// situation.
+    ///
+    /// Once the calculation is over, chains added later whose genesis block has the same hash
+    /// re-use the services of this chain. See [`Client::add_chain`].
+    PendingBlockHash(u64),
 }
 
 struct RunningChain<TPlat: platform::Platform> {
@@ -241,6 +615,16 @@ struct RunningChain<TPlat: platform::Platform> {
     /// Number of elements in [`Client::public_api_chains`] that reference this chain. If this
     /// number reaches `0`, the [`RunningChain`] should be destroyed.
     num_references: NonZeroU32,
+
+    /// Time spent executing the background tasks of the services of this chain.
+    cpu_time: scheduler::CpuTime,
+
+    /// Durations of the operations of the services of this chain. Shared with these services.
+    metrics: Arc<metrics::ChainMetrics>,
+
+    /// Makes it possible to pause the background tasks of the services of this chain. See
+    /// [`Client::pause_chain`].
+    pause_switch: scheduler::PauseSwitch,
 }
 
 struct ChainServices<TPlat: platform::Platform> {
@@ -249,8 +633,21 @@ struct ChainServices<TPlat: platform::Platform> {
     sync_service: Arc<sync_service::SyncService<TPlat>>,
     runtime_service: Arc<runtime_service::RuntimeService<TPlat>>,
     transactions_service: Arc<transactions_service::TransactionsService<TPlat>>,
+    storage_subscriptions:
+        Arc<json_rpc_service::storage_subscriptions::StorageSubscriptions<TPlat>>,
+    fee_estimation_service: Arc<fee_estimation_service::FeeEstimationService<TPlat>>,
+    metrics: Arc<metrics::ChainMetrics>,
+    /// See [`account_info::DecodersCache`].
+    account_info_decoders: Arc<account_info::DecodersCache>,
     // TODO: can be grabbed from the sync service instead
     block_number_bytes: usize,
+    /// Hash of the genesis block of the chain.
+    genesis_block_hash: [u8; 32],
+    /// Hash of the root of the storage trie of the genesis block of the chain.
+    genesis_block_state_root: [u8; 32],
+    /// Hash of the genesis storage found in the chain specification, if any. See
+    /// [`chain_spec::GenesisStorageItems::content_hash`].
+    genesis_storage_hash: Option<[u8; 32]>,
 }
 
 impl<TPlat: platform::Platform> Clone for ChainServices<TPlat> {
@@ -261,7 +658,14 @@ impl<TPlat: platform::Platform> Clone for ChainServices<TPlat> {
             sync_service: self.sync_service.clone(),
             runtime_service: self.runtime_service.clone(),
             transactions_service: self.transactions_service.clone(),
+            storage_subscriptions: self.storage_subscriptions.clone(),
+            fee_estimation_service: self.fee_estimation_service.clone(),
+            metrics: self.metrics.clone(),
+            account_info_decoders: self.account_info_decoders.clone(),
             block_number_bytes: self.block_number_bytes,
+            genesis_block_hash: self.genesis_block_hash,
+            genesis_block_state_root: self.genesis_block_state_root,
+            genesis_storage_hash: self.genesis_storage_hash,
         }
     }
 }
@@ -276,6 +680,38 @@ pub struct AddChainSuccess {
     /// Is always `Some` if [`AddChainConfig::disable_json_rpc`] was `false`, and `None` if it was
     /// `true`. In other words, you can unwrap this `Option` if you passed `false`.
     pub json_rpc_responses: Option<JsonRpcResponses>,
+
+    /// Stream of checkpoints of the chain.
+    ///
+    /// Is always `Some` if [`AddChainConfig::checkpoint_refresh`] was `Some`, and `None` if it
+    /// was `None`.
+    pub checkpoints: Option<Checkpoints>,
+
+    /// Identifier of the relay chain that has been automatically added because of
+    /// [`AddChainConfig::auto_add_relay_chain`]. `None` if no relay chain has been added.
+    pub relay_chain_id: Option<ChainId>,
+}
+
+/// Stream of checkpoints of a chain.
+///
+/// See [`AddChainSuccess::checkpoints`].
+pub struct Checkpoints {
+    /// Sending side of the requests for a checkpoint. The receiving side is held by the task
+    /// that exports the checkpoints, and is destroyed when the chain is removed.
+    requests_tx: mpsc::Sender<oneshot::Sender<String>>,
+}
+
+impl Checkpoints {
+    /// Returns the next checkpoint, or `None` if the chain has been removed.
+    ///
+    /// The checkpoint is generated after this function has been called, and never earlier than
+    /// [`CheckpointRefreshConfig::period`] after the previous checkpoint, in order to never return
+    /// a stale checkpoint.
+    pub async fn next(&mut self) -> Option<String> {
+        let (response_tx, response_rx) = oneshot::channel();
+        self.requests_tx.send(response_tx).await.ok()?;
+        response_rx.await.ok()
+    }
 }
 
 /// Stream of JSON-RPC responses or notifications.
@@ -314,6 +750,86 @@ impl JsonRpcResponses {
     }
 }
 
+/// Stream of events concerning the accounts watched with [`Client::watch_accounts`].
+///
+/// Destroying this object stops the watching.
+pub struct AccountWatch {
+    /// Receiving side of the events. The sending side is held by the task that watches the
+    /// accounts, and is destroyed when the chain is removed.
+    rx: mpsc::Receiver<AccountWatchEvent>,
+}
+
+impl AccountWatch {
+    /// Returns the next event, or `None` if the chain has been removed.
+    pub async fn next(&mut self) -> Option<AccountWatchEvent> {
+        self.rx.next().await
+    }
+}
+
+/// Additional JSON-RPC endpoint of a chain.
+///
+/// See [`Client::json_rpc_endpoint`].
+pub struct JsonRpcEndpoint {
+    /// Handle to the JSON-RPC service, referring to the client dedicated to this endpoint. Set
+    /// to `None` once the chain has been removed.
+    inner: Option<json_rpc_service::Frontend>,
+
+    /// Dummy channel. Nothing is ever sent on it, but the sending side is stored in the
+    /// [`PublicApiChain`] in order to detect when the chain has been removed.
+    public_api_chain_destroyed_rx: oneshot::Receiver<()>,
+
+    /// See [`Client::spawn_new_task`]. Used to remove the client from the JSON-RPC service when
+    /// the endpoint is destroyed.
+    spawn_new_task: Arc<dyn Fn(String, future::BoxFuture<'static, ()>) + Send + Sync>,
+}
+
+impl JsonRpcEndpoint {
+    /// Enqueues a JSON-RPC request towards the chain. The response will be returned by
+    /// [`JsonRpcEndpoint::next_response`] of this endpoint.
+    ///
+    /// Returns an error if this endpoint is overloaded, or if the request could not be parsed as
+    /// a valid JSON-RPC request. See [`Client::json_rpc_request`].
+    ///
+    /// If the chain has been removed, the request is silently discarded.
+    pub fn request(&self, json_rpc_request: impl Into<String>) -> Result<(), HandleRpcError> {
+        match &self.inner {
+            Some(frontend) => frontend.queue_rpc_request(json_rpc_request.into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the next response or notification destined to this endpoint, or `None` if the
+    /// chain has been removed.
+    pub async fn next_response(&mut self) -> Option<String> {
+        if let Some(frontend) = self.inner.as_mut() {
+            let response_fut = frontend.next_json_rpc_response();
+            futures::pin_mut!(response_fut);
+            match future::select(response_fut, &mut self.public_api_chain_destroyed_rx).await {
+                future::Either::Left((response, _)) => return Some(response),
+                future::Either::Right((_result, _)) => {
+                    debug_assert!(_result.is_err());
+                }
+            }
+        }
+
+        self.inner = None;
+        None
+    }
+}
+
+impl Drop for JsonRpcEndpoint {
+    fn drop(&mut self) {
+        // Cancel the requests and subscriptions of this endpoint, and free its slot in the
+        // JSON-RPC service.
+        if let Some(frontend) = self.inner.take() {
+            (self.spawn_new_task)(
+                "json-rpc-endpoint-remove".to_owned(),
+                frontend.remove_client().boxed(),
+            );
+        }
+    }
+}
+
 impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
     /// Initializes the smoldot client.
     pub fn new(config: ClientConfig) -> Self {
@@ -322,11 +838,27 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
             spawn_new_task: config.tasks_spawner.into(),
             public_api_chains: slab::Slab::with_capacity(expected_chains),
             chains_by_key: HashMap::with_capacity_and_hasher(expected_chains, Default::default()),
+            next_pending_block_hash_id: 0,
             system_name: config.system_name,
             system_version: config.system_version,
+            clock_drift_tolerance: config.clock_drift_tolerance,
+            max_requests_per_peer: config.max_requests_per_peer,
+            #[cfg(feature = "sqlite-cache")]
+            persistent_cache: None,
         }
     }
 
+    /// Sets the cache where the client automatically loads and stores the database of each
+    /// chain, and the storage values it has downloaded. Only applies to the chains that are
+    /// added afterwards.
+    ///
+    /// When a chain is added with an empty or invalid [`AddChainConfig::database_content`], the
+    /// database found in the cache is used instead, if any.
+    #[cfg(feature = "sqlite-cache")]
+    pub fn set_persistent_cache(&mut self, cache: PersistentCache) {
+        self.persistent_cache = Some(Arc::new(cache));
+    }
+
     /// Adds a new chain to the list of chains smoldot tries to synchronize.
     ///
     /// Returns an error in case something is wrong with the configuration.
@@ -335,145 +867,209 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
         config: AddChainConfig<'_, TChain, impl Iterator<Item = ChainId>>,
     ) -> Result<AddChainSuccess, AddChainError> {
         // Decode the chain specification.
-        let chain_spec = match chain_spec::ChainSpec::from_json_bytes(config.specification) {
-            Ok(cs) => cs,
-            Err(err) => {
-                return Err(AddChainError::ChainSpecParseError(err));
+        let chain_spec = match config.specification {
+            ChainSpecification::Json(json) => match chain_spec::ChainSpec::from_json_bytes(json) {
+                Ok(cs) => cs,
+                Err(err) => {
+                    return Err(AddChainError::ChainSpecParseError(err));
+                }
+            },
+            ChainSpecification::Parsed(cs) => cs,
+            ChainSpecification::WellKnown(id) => {
+                let Some(json) = known_chains::chain_spec(id) else {
+                    return Err(AddChainError::UnknownWellKnownChain);
+                };
+                match chain_spec::ChainSpec::from_json_bytes(json) {
+                    Ok(cs) => cs,
+                    Err(err) => {
+                        return Err(AddChainError::ChainSpecParseError(err));
+                    }
+                }
+            }
+        };
+
+        // Replace the genesis storage of the chain specification if it is provided separately.
+        // If the chain specification contains the trie root hash of the genesis storage, the
+        // genesis storage provided separately is verified against it below.
+        let mut expected_genesis_state_root = None;
+        let chain_spec = match config.genesis_storage {
+            None => chain_spec,
+            Some(genesis_storage) => {
+                let genesis_storage = match genesis_storage {
+                    GenesisStorage::Binary(data) => {
+                        match chain_spec::SeparateGenesisStorage::from_binary(data) {
+                            Ok(storage) => storage,
+                            Err(err) => return Err(AddChainError::GenesisStorageParseError(err)),
+                        }
+                    }
+                    GenesisStorage::Parsed(storage) => storage,
+                };
+
+                let mut chain_spec = chain_spec;
+                expected_genesis_state_root =
+                    chain_spec.genesis_storage().into_trie_root_hash().copied();
+                chain_spec.set_genesis_storage(genesis_storage);
+                chain_spec
             }
         };
 
+        let mut database_content = database::decode_database(
+            config.database_content,
+            chain_spec.block_number_bytes().into(),
+        )
+        .ok();
+
+        // If no valid database has been provided, use the one found in the persistent cache.
+        #[cfg(feature = "sqlite-cache")]
+        if database_content.is_none() {
+            database_content = self
+                .persistent_cache
+                .as_ref()
+                .and_then(|cache| cache.chain_database(chain_spec.id()))
+                .and_then(|stored| {
+                    database::decode_database(&stored, chain_spec.block_number_bytes().into()).ok()
+                });
+        }
+
+        // The transactions that were pending when the database was encoded are submitted again
+        // after the chain has been added, but only if the database concerns the same chain.
+        // This is verified once the hash of the genesis block is known.
+        let database_pending_transactions = database_content.as_mut().map(|db| {
+            (
+                db.genesis_block_hash,
+                core::mem::take(&mut db.pending_transactions),
+            )
+        });
+
         // Load the information about the chain from the chain spec. If a light sync state (also
         // known as a checkpoint) is present in the chain spec, it is possible to start syncing at
         // the finalized block it describes.
-        // TODO: clean up that block
-        let (chain_information, genesis_block_header, checkpoint_nodes) = {
-            match (
-                chain_spec.as_chain_information().map(|(ci, _)| ci), // TODO: don't just throw away the runtime
-                chain_spec.light_sync_state().map(|s| {
-                    chain::chain_information::ValidChainInformation::try_from(
-                        s.as_chain_information(),
-                    )
-                }),
-                database::decode_database(
-                    config.database_content,
+        let checkpoint = match chain_spec.light_sync_state().map(|s| {
+            chain::chain_information::ValidChainInformation::try_from(s.as_chain_information())
+        }) {
+            Some(Ok(checkpoint)) => Some(checkpoint),
+            Some(Err(err)) => return Err(AddChainError::InvalidCheckpoint(err)),
+            None => None,
+        };
+
+        // If the chain specification contains the genesis storage, the trie root hash of this
+        // storage is needed in order to build the header of the genesis block. Calculating this
+        // trie root hash is expensive. It is therefore cached in the database alongside with the
+        // hash of the genesis storage it has been calculated from, and if it isn't available it
+        // is calculated later in the background.
+        // `genesis_storage_hash` is `Some` if the hash of the genesis storage has been
+        // calculated. Since this is also a relatively expensive operation, it is only done here
+        // if the database contains a cached trie root hash.
+        let mut genesis_storage_hash = None;
+        // Nodes found in the database, if the database concerns the same chain. If the genesis
+        // block hash isn't known yet, they are instead added to the network service once it is.
+        let mut checkpoint_nodes = Vec::new();
+        let starting_point = match chain_spec.genesis_storage() {
+            chain_spec::GenesisStorage::TrieRootHash(state_root) => {
+                let genesis_block_header = header::Header {
+                    parent_hash: [0; 32],
+                    number: 0,
+                    state_root: *state_root,
+                    extrinsics_root: smoldot::trie::empty_trie_merkle_value(),
+                    digest: header::DigestRef::empty().into(),
+                };
+
+                let (chain_information, known_nodes) = choose_starting_point(
+                    &genesis_block_header,
+                    None,
+                    checkpoint,
+                    database_content,
                     chain_spec.block_number_bytes().into(),
-                ),
-            ) {
-                // Use the database if it contains a more recent block than the chain spec checkpoint.
-                (Ok(genesis_ci), checkpoint, Ok(database_content))
-                    if database_content.genesis_block_hash
-                        == genesis_ci
-                            .as_ref()
-                            .finalized_block_header
-                            .hash(chain_spec.block_number_bytes().into())
-                        && checkpoint.as_ref().and_then(|r| r.as_ref().ok()).map_or(
-                            true,
-                            |cp| {
-                                cp.as_ref().finalized_block_header.number
-                                    < database_content
-                                        .chain_information
-                                        .as_ref()
-                                        .finalized_block_header
-                                        .number
-                            },
-                        ) =>
-                {
-                    let genesis_header = genesis_ci.as_ref().finalized_block_header.clone();
-                    (
-                        database_content.chain_information,
-                        genesis_header.into(),
-                        database_content.known_nodes,
-                    )
+                )
+                // TODO: we can in theory support chain specs that have neither a checkpoint nor the genesis storage, but it's complicated
+                // TODO: is this relevant for parachains?
+                .ok_or(AddChainError::ChainSpecNeitherGenesisStorageNorCheckpoint)?;
+
+                checkpoint_nodes = known_nodes;
+                StartingPoint::Known {
+                    chain_information: Box::new(chain_information),
+                    genesis_block_header: Box::new(genesis_block_header),
                 }
+            }
+            chain_spec::GenesisStorage::Items(genesis_storage) => {
+                let genesis_chain_information = chain_spec
+                    .as_chain_information_without_state_root()
+                    .map_err(AddChainError::InvalidGenesisStorage)?; // TODO: don't just throw away the runtime
 
-                // Use the database if it contains a more recent block than the chain spec checkpoint.
-                (
-                    Err(chain_spec::FromGenesisStorageError::UnknownStorageItems),
-                    checkpoint,
-                    Ok(database_content),
-                ) if checkpoint
+                let mut cached_state_root = database_content
                     .as_ref()
-                    .and_then(|r| r.as_ref().ok())
-                    .map_or(true, |cp| {
-                        cp.as_ref().finalized_block_header.number
-                            < database_content
-                                .chain_information
-                                .as_ref()
-                                .finalized_block_header
-                                .number
-                    }) =>
-                {
-                    let genesis_header = header::Header {
-                        parent_hash: [0; 32],
-                        number: 0,
-                        state_root: *chain_spec.genesis_storage().into_trie_root_hash().unwrap(),
-                        extrinsics_root: smoldot::trie::empty_trie_merkle_value(),
-                        digest: header::DigestRef::empty().into(),
-                    };
+                    .and_then(|db| db.genesis_state_root)
+                    .and_then(|(storage_hash, state_root)| {
+                        let hash = genesis_storage.content_hash();
+                        genesis_storage_hash = Some(hash);
+                        if storage_hash == hash {
+                            Some(state_root)
+                        } else {
+                            None
+                        }
+                    });
 
-                    if database_content.genesis_block_hash
-                        == genesis_header.hash(chain_spec.block_number_bytes().into())
-                    {
-                        (
-                            database_content.chain_information,
-                            genesis_header,
-                            database_content.known_nodes,
+                // If the genesis storage has been provided separately from a chain
+                // specification that contains its trie root hash, make sure that they match.
+                // Contrary to when the trie root hash is unknown, the calculation is done
+                // synchronously, in order to be able to return an error.
+                if let Some(expected_state_root) = expected_genesis_state_root {
+                    let state_root = cached_state_root.unwrap_or_else(|| {
+                        genesis_state_root_sync(
+                            genesis_storage,
+                            genesis_chain_information.state_version(),
                         )
-                    } else if let Some(Ok(checkpoint)) = checkpoint {
-                        // Database is incorrect.
-                        (checkpoint, genesis_header, database_content.known_nodes)
-                    } else {
-                        // TODO: we can in theory support chain specs that have neither a checkpoint nor the genesis storage, but it's complicated
-                        // TODO: is this relevant for parachains?
-                        return Err(AddChainError::ChainSpecNeitherGenesisStorageNorCheckpoint);
+                    });
+                    if state_root != expected_state_root {
+                        return Err(AddChainError::GenesisStorageStateRootMismatch);
                     }
+                    cached_state_root = Some(state_root);
                 }
 
-                (Err(chain_spec::FromGenesisStorageError::UnknownStorageItems), None, _) => {
-                    // TODO: we can in theory support chain specs that have neither a checkpoint nor the genesis storage, but it's complicated
-                    // TODO: is this relevant for parachains?
-                    return Err(AddChainError::ChainSpecNeitherGenesisStorageNorCheckpoint);
-                }
-
-                (
-                    Err(chain_spec::FromGenesisStorageError::UnknownStorageItems),
-                    Some(Ok(checkpoint)),
-                    _,
-                ) => {
-                    let genesis_header = header::Header {
-                        parent_hash: [0; 32],
-                        number: 0,
-                        state_root: *chain_spec.genesis_storage().into_trie_root_hash().unwrap(),
-                        extrinsics_root: smoldot::trie::empty_trie_merkle_value(),
-                        digest: header::DigestRef::empty().into(),
-                    };
-
-                    (checkpoint, genesis_header, Default::default())
-                }
-
-                (Err(err), _, _) => return Err(AddChainError::InvalidGenesisStorage(err)),
-
-                (_, Some(Err(err)), _) => {
-                    return Err(AddChainError::InvalidCheckpoint(err));
-                }
-
-                (Ok(genesis_ci), Some(Ok(checkpoint)), _) => {
-                    let genesis_header = genesis_ci.as_ref().finalized_block_header.clone();
-                    (checkpoint, genesis_header.into(), Default::default())
-                }
+                if let Some(state_root) = cached_state_root {
+                    let (genesis_chain_information, _) =
+                        genesis_chain_information.with_state_root(&state_root);
+                    let genesis_block_header = header::Header::from(
+                        genesis_chain_information
+                            .as_ref()
+                            .finalized_block_header
+                            .clone(),
+                    );
+                    // Can't fail, as the genesis chain information is provided.
+                    let (chain_information, known_nodes) = choose_starting_point(
+                        &genesis_block_header,
+                        Some(genesis_chain_information),
+                        checkpoint,
+                        database_content,
+                        chain_spec.block_number_bytes().into(),
+                    )
+                    .unwrap_or_else(|| unreachable!());
 
-                (Ok(genesis_ci), None, _) => {
-                    let genesis_header =
-                        header::Header::from(genesis_ci.as_ref().finalized_block_header.clone());
-                    (genesis_ci, genesis_header, Default::default())
+                    checkpoint_nodes = known_nodes;
+                    StartingPoint::Known {
+                        chain_information: Box::new(chain_information),
+                        genesis_block_header: Box::new(genesis_block_header),
+                    }
+                } else {
+                    // Note that the database, including one encoded by a version of smoldot that
+                    // doesn't cache the trie root hash of the genesis storage, is still used if
+                    // it concerns the same genesis block.
+                    StartingPoint::PendingGenesisStateRoot {
+                        genesis_chain_information: Box::new(genesis_chain_information),
+                        checkpoint: checkpoint.map(Box::new),
+                        database_content: database_content.map(Box::new),
+                    }
                 }
             }
         };
 
         // If the chain specification specifies a parachain, find the corresponding relay chain
         // in the list of potential relay chains passed by the user.
-        // If no relay chain can be found, the chain creation fails. Exactly one matching relay
-        // chain must be found. If there are multiple ones, the creation fails as well.
+        // If no relay chain can be found, the chain creation fails, unless the relay chain is
+        // well-known and the API user has asked for it to be added automatically. Exactly one
+        // matching relay chain must be found. If there are multiple ones, the creation fails as
+        // well.
+        let mut auto_added_relay_chain_id = None;
         let relay_chain_id = if let Some((relay_chain_id, _para_id)) = chain_spec.relay_chain() {
             let chain = config
                 .potential_relay_chains
@@ -489,12 +1085,45 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                 Err(mut iter) => {
                     // `iter` here is identical to the iterator above before `exactly_one` is
                     // called. This lets us know what failed.
-                    return Err(if iter.next().is_none() {
-                        AddChainError::NoRelayChainFound
-                    } else {
+                    if iter.next().is_some() {
                         debug_assert!(iter.next().is_some());
-                        AddChainError::MultipleRelayChains
-                    });
+                        return Err(AddChainError::MultipleRelayChains);
+                    }
+                    drop(iter);
+
+                    let (Some(user_data), Some(_)) = (
+                        config.auto_add_relay_chain,
+                        known_chains::chain_spec(relay_chain_id),
+                    ) else {
+                        return Err(AddChainError::NoRelayChainFound);
+                    };
+
+                    log::info!(
+                        target: "smoldot",
+                        "Automatically adding relay chain {relay_chain_id}"
+                    );
+
+                    let relay_chain = self.add_chain(AddChainConfig {
+                        user_data,
+                        specification: ChainSpecification::WellKnown(relay_chain_id),
+                        genesis_storage: None,
+                        fork_id: ForkIdOverride::ChainSpecification,
+                        database_content: "",
+                        potential_relay_chains: core::iter::empty(),
+                        auto_add_relay_chain: None,
+                        disable_json_rpc: true,
+                        json_rpc_methods_filter: JsonRpcMethodsFilter::default(),
+                        ethereum_json_rpc: false,
+                        block_announce_policy: config.block_announce_policy.clone(),
+                        warp_sync_min_distinct_peers: config.warp_sync_min_distinct_peers,
+                        finality_proofs_window: 0,
+                        skip_seal_verification: false,
+                        transactions_pool: TransactionsPoolConfig::default(),
+                        checkpoint_refresh: None,
+                    })?;
+
+                    auto_added_relay_chain_id = Some(relay_chain.chain_id);
+                    Some(relay_chain.chain_id)
                 }
             }
         } else {
@@ -537,8 +1166,6 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
         // Grab a couple of fields from the chain specification for later, as the chain
         // specification is consumed below.
         let chain_spec_chain_id = chain_spec.id().to_owned();
-        let genesis_block_hash = genesis_block_header.hash(chain_spec.block_number_bytes().into());
-        let genesis_block_state_root = genesis_block_header.state_root;
 
         // The key generated here uniquely identifies this chain within smoldot. Mutiple chains
         // having the same key will use the same services.
@@ -547,14 +1174,88 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
         // identical chains to be de-duplicated, but security issues would arise if two chains
         // were considered identical while they're in reality not identical.
         let new_chain_key = ChainKey {
-            genesis_block_hash,
+            genesis: match &starting_point {
+                StartingPoint::Known {
+                    genesis_block_header,
+                    ..
+                } => ChainKeyGenesis::BlockHash(
+                    genesis_block_header.hash(chain_spec.block_number_bytes().into()),
+                ),
+                StartingPoint::PendingGenesisStateRoot { .. } => {
+                    let id = self.next_pending_block_hash_id;
+                    self.next_pending_block_hash_id += 1;
+                    ChainKeyGenesis::PendingBlockHash(id)
+                }
+            },
             relay_chain: relay_chain_id.map(|ck| {
                 (
                     Box::new(self.public_api_chains.get(ck.0).unwrap().key.clone()),
                     chain_spec.relay_chain().unwrap().1,
                 )
             }),
-            fork_id: chain_spec.fork_id().map(|f| f.to_owned()),
+            fork_id: match config.fork_id {
+                ForkIdOverride::ChainSpecification => chain_spec.fork_id().map(|f| f.to_owned()),
+                ForkIdOverride::Disabled => None,
+                ForkIdOverride::Custom(fork_id) => Some(fork_id.to_owned()),
+            },
+            bad_blocks: {
+                let mut list = chain_spec.bad_blocks_hashes().copied().collect::<Vec<_>>();
+                list.sort_unstable();
+                list
+            },
+            fork_blocks: {
+                let mut list = chain_spec
+                    .fork_blocks()
+                    .map(|(n, h)| (n, *h))
+                    .collect::<Vec<_>>();
+                list.sort_unstable();
+                list.dedup();
+                list
+            },
+            code_substitutes: {
+                let mut list = chain_spec
+                    .code_substitutes()
+                    .map(|(n, code)| {
+                        let hash = blake2_rfc::blake2b::blake2b(32, &[], code);
+                        (n, <[u8; 32]>::try_from(hash.as_bytes()).unwrap())
+                    })
+                    .collect::<Vec<_>>();
+                list.sort_unstable();
+                list
+            },
+            block_announce_policy: config.block_announce_policy.clone(),
+            warp_sync_min_distinct_peers: config.warp_sync_min_distinct_peers,
+            finality_proofs_window: config.finality_proofs_window,
+            skip_seal_verification: config.skip_seal_verification,
+            transactions_pool: config.transactions_pool.clone(),
+        };
+
+        // A chain whose genesis block hash was unknown when it was added is keyed with a
+        // `ChainKeyGenesis::PendingBlockHash`. If the hash of its genesis block has been
+        // calculated in the meanwhile and is the same as the one of the chain being added, use
+        // the key of this existing chain instead.
+        let new_chain_key = match &new_chain_key.genesis {
+            ChainKeyGenesis::BlockHash(genesis_block_hash) => self
+                .chains_by_key
+                .iter()
+                .find(|(key, chain)| {
+                    let resolved_genesis_block_hash = match &chain.services {
+                        future::MaybeDone::Done(services) => Some(services.genesis_block_hash),
+                        future::MaybeDone::Future(services) => {
+                            services.peek().map(|s| s.genesis_block_hash)
+                        }
+                        future::MaybeDone::Gone => None,
+                    };
+                    matches!(key.genesis, ChainKeyGenesis::PendingBlockHash(_))
+                        && resolved_genesis_block_hash == Some(*genesis_block_hash)
+                        && ChainKey {
+                            genesis: ChainKeyGenesis::BlockHash(*genesis_block_hash),
+                            ..(*key).clone()
+                        } == new_chain_key
+                })
+                .map(|(key, _)| key.clone())
+                .unwrap_or(new_chain_key),
+            ChainKeyGenesis::PendingBlockHash(_) => new_chain_key,
         };
 
         // If the chain we are adding is a parachain, grab the services of the relay chain.
@@ -634,14 +1335,88 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                 // peer-to-peer network.
                 let network_noise_key = connection::NoiseKey::new(&rand::random());
 
+                // The tasks of the services of the chain are grouped together in order to
+                // prevent them from starving the tasks of the other chains.
+                let (task_group, task_group_spawner) = scheduler::TaskGroup::<TPlat>::new();
+                let cpu_time = task_group.cpu_time();
+                let pause_switch = task_group.pause_switch();
+                let metrics = Arc::new(metrics::ChainMetrics::default());
+                (self.spawn_new_task)(format!("{log_name}-tasks"), task_group.boxed());
+
                 // Spawn a background task that initializes the services of the new chain and
                 // yields a `ChainServices`.
                 let running_chain_init_future: future::RemoteHandle<ChainServices<TPlat>> = {
-                    let spawn_new_task = self.spawn_new_task.clone();
+                    let spawn_new_task: Arc<dyn Fn(_, _) + Send + Sync> =
+                        Arc::new(move |name, task| task_group_spawner.spawn(name, task));
                     let chain_spec = chain_spec.clone(); // TODO: quite expensive
                     let log_name = log_name.clone();
+                    let fork_id = new_chain_key.fork_id.clone();
+                    let block_announce_policy = new_chain_key.block_announce_policy.clone();
+                    let warp_sync_min_distinct_peers = new_chain_key.warp_sync_min_distinct_peers;
+                    let finality_proofs_window = new_chain_key.finality_proofs_window;
+                    let skip_seal_verification = new_chain_key.skip_seal_verification;
+                    let transactions_pool = new_chain_key.transactions_pool.clone();
+                    let clock_drift_tolerance = self.clock_drift_tolerance;
+                    let max_requests_per_peer = self.max_requests_per_peer;
+                    let metrics = metrics.clone();
 
                     let future = async move {
+                        // Calculate the trie root hash of the genesis storage, if necessary, then
+                        // choose the block to start syncing from.
+                        let (chain_information, genesis_block_header, database_nodes) =
+                            match starting_point {
+                                StartingPoint::Known {
+                                    chain_information,
+                                    genesis_block_header,
+                                } => (*chain_information, *genesis_block_header, Vec::new()),
+                                StartingPoint::PendingGenesisStateRoot {
+                                    genesis_chain_information,
+                                    checkpoint,
+                                    database_content,
+                                } => {
+                                    let state_root = calculate_genesis_state_root::<TPlat>(
+                                        &log_name,
+                                        chain_spec.genesis_storage().into_genesis_items().unwrap(),
+                                        genesis_chain_information.state_version(),
+                                    )
+                                    .await;
+
+                                    let (genesis_chain_information, _) =
+                                        genesis_chain_information.with_state_root(&state_root);
+                                    let genesis_block_header = header::Header::from(
+                                        genesis_chain_information
+                                            .as_ref()
+                                            .finalized_block_header
+                                            .clone(),
+                                    );
+                                    // Can't fail, as the genesis chain information is provided.
+                                    let (chain_information, database_nodes) =
+                                        choose_starting_point(
+                                            &genesis_block_header,
+                                            Some(genesis_chain_information),
+                                            checkpoint.map(|cp| *cp),
+                                            database_content.map(|db| *db),
+                                            chain_spec.block_number_bytes().into(),
+                                        )
+                                        .unwrap_or_else(|| unreachable!());
+                                    (chain_information, genesis_block_header, database_nodes)
+                                }
+                            };
+
+                        // The hash of the genesis storage is stored in databases alongside with
+                        // its trie root hash. See above.
+                        let genesis_storage_hash = match genesis_storage_hash {
+                            Some(hash) => Some(hash),
+                            None => match chain_spec.genesis_storage().into_genesis_items() {
+                                Some(genesis_storage) => {
+                                    let hash = genesis_storage.content_hash();
+                                    TPlat::yield_after_cpu_intensive().await;
+                                    Some(hash)
+                                }
+                                None => None,
+                            },
+                        };
+
                         // Wait until the relay chain has finished initializing, if necessary.
                         let relay_chain =
                             if let Some((mut relay_chain_ready_future, relay_chain_log_name)) =
@@ -665,7 +1440,6 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                             .as_ref()
                             .finalized_block_header
                             .hash(chain_spec.block_number_bytes().into());
-                        let has_bad_blocks = chain_spec.bad_blocks_hashes().count() != 0;
 
                         let running_chain = start_services(
                             log_name.clone(),
@@ -673,12 +1447,27 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                             chain_information,
                             genesis_block_header
                                 .scale_encoding_vec(chain_spec.block_number_bytes().into()),
+                            genesis_storage_hash,
                             chain_spec,
+                            fork_id,
                             relay_chain.as_ref().map(|(r, _)| r),
                             network_noise_key,
+                            block_announce_policy,
+                            warp_sync_min_distinct_peers,
+                            finality_proofs_window,
+                            skip_seal_verification,
+                            transactions_pool,
+                            clock_drift_tolerance,
+                            max_requests_per_peer,
+                            metrics,
                         )
                         .await;
 
+                        running_chain
+                            .network_service
+                            .discover(&TPlat::now(), 0, database_nodes, false)
+                            .await;
+
                         // Note that the chain name is printed through the `Debug` trait (rather
                         // than `Display`) because it is an untrusted user input.
                         //
@@ -692,8 +1481,8 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                                 chain: {} (id: {})",
                                 log_name,
                                 chain_name,
-                                HashDisplay(&genesis_block_hash),
-                                hex::encode(genesis_block_state_root),
+                                HashDisplay(&running_chain.genesis_block_hash),
+                                hex::encode(running_chain.genesis_block_state_root),
                                 running_chain.network_identity,
                                 relay_chain_log_name,
                                 relay_chain_para_id.unwrap(),
@@ -706,30 +1495,14 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                                 specification or database starting at: {} (#{})",
                                 log_name,
                                 chain_name,
-                                HashDisplay(&genesis_block_hash),
-                                hex::encode(genesis_block_state_root),
+                                HashDisplay(&running_chain.genesis_block_hash),
+                                hex::encode(running_chain.genesis_block_state_root),
                                 running_chain.network_identity,
                                 HashDisplay(&starting_block_hash),
                                 starting_block_number
                             );
                         }
 
-                        // TODO: remove after https://github.com/paritytech/smoldot/issues/2584
-                        if has_bad_blocks {
-                            log::warn!(
-                                target: "smoldot",
-                                "Chain specification of {} contains a list of bad blocks. Bad \
-                                blocks are not implemented in the light client. An appropriate \
-                                way to silence this warning is to remove the bad blocks from the \
-                                chain specification, which can safely be done:\n\
-                                - For relay chains: if the chain specification contains a \
-                                checkpoint and that the bad blocks have a block number inferior \
-                                to this checkpoint.\n\
-                                - For parachains: if the bad blocks have a block number inferior \
-                                to the current parachain finalized block.", log_name
-                            );
-                        }
-
                         running_chain
                     };
 
@@ -745,6 +1518,9 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                     services: future::maybe_done(running_chain_init_future.shared()),
                     log_name,
                     num_references: NonZeroU32::new(1).unwrap(),
+                    cpu_time,
+                    metrics,
+                    pause_switch,
                 });
 
                 (&mut entry.services, &entry.log_name)
@@ -806,27 +1582,210 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
             .boxed()
         });
 
-        // JSON-RPC service initialization. This is done every time `add_chain` is called, even
-        // if a similar chain already existed.
-        let json_rpc_frontend = if !config.disable_json_rpc {
-            // Clone `running_chain_init`.
-            let mut running_chain_init = match services_init {
-                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
-                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
-                future::MaybeDone::Gone => unreachable!(),
-            };
+        // Submit again the transactions that were pending when the database was encoded. Just
+        // like for the initial topology, this is done in a short-lived task that waits for the
+        // chain initialization to finish. The transactions are only submitted once the chain is
+        // near its head, as the transactions service drops all its pending transactions when
+        // there is a gap in the chain, which is the case after a warp sync.
+        // The last known status of each transaction is restored as well. If a JSON-RPC client
+        // submits one of these transactions again, for example after a page reload, it is
+        // attached to the already-pending transaction and immediately receives this status.
+        if let Some((database_genesis_block_hash, transactions)) =
+            database_pending_transactions.filter(|(_, transactions)| !transactions.is_empty())
+        {
+            (self.spawn_new_task)("transactions-service-restore".to_owned(), {
+                let mut running_chain_init = match services_init {
+                    future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                    future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                    future::MaybeDone::Gone => unreachable!(),
+                };
 
-            let (frontend, service_starter) = json_rpc_service::service(json_rpc_service::Config {
-                log_name: log_name.clone(), // TODO: add a way to differentiate multiple different json-rpc services under the same chain
-                max_pending_requests: NonZeroU32::new(128).unwrap(),
-                max_subscriptions: 1024, // Note: the PolkadotJS UI is very heavy in terms of subscriptions.
-                max_parallel_requests: NonZeroU32::new(24).unwrap(),
-                max_parallel_subscription_updates: NonZeroU32::new(8).unwrap(),
-            });
+                async move {
+                    (&mut running_chain_init).await;
+                    let running_chain = Pin::new(&mut running_chain_init).take_output().unwrap();
+
+                    // The database might concern a different chain.
+                    if running_chain.genesis_block_hash != database_genesis_block_hash {
+                        return;
+                    }
+
+                    while !running_chain
+                        .runtime_service
+                        .is_near_head_of_chain_heuristic()
+                        .await
+                    {
+                        TPlat::sleep(core::time::Duration::from_secs(5)).await;
+                    }
+
+                    for transaction in transactions {
+                        running_chain
+                            .transactions_service
+                            .restore_transaction(transaction)
+                            .await;
+                    }
+                }
+                .boxed()
+            });
+        }
+
+        // Checkpoints exporting task, if requested. Just like the JSON-RPC service, this is done
+        // every time `add_chain` is called, even if a similar chain already existed.
+        let (checkpoints, checkpoints_task_stop_tx) = if let Some(checkpoint_refresh) =
+            config.checkpoint_refresh
+        {
+            let mut running_chain_init = match services_init {
+                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                future::MaybeDone::Gone => unreachable!(),
+            };
+
+            // Checkpoints are generated on demand, in order to never hand over a checkpoint that
+            // has been generated a long time before the API user asked for it.
+            let (requests_tx, requests_rx) = mpsc::channel(0);
+            let (stop_tx, stop_rx) = oneshot::channel::<()>();
+
+            (self.spawn_new_task)("checkpoint-refresh".to_owned(), {
+                let task = async move {
+                    (&mut running_chain_init).await;
+                    let running_chain = Pin::new(&mut running_chain_init).take_output().unwrap();
+                    let genesis_state_root = running_chain
+                        .genesis_storage_hash
+                        .map(|storage_hash| (storage_hash, running_chain.genesis_block_state_root));
+
+                    checkpoints_task::<TPlat, _>(requests_rx, checkpoint_refresh.period, || async {
+                        // Exporting a checkpoint while the chain is still syncing would give a
+                        // checkpoint older than necessary, or even older than the one the chain
+                        // was started from.
+                        while !running_chain
+                            .runtime_service
+                            .is_near_head_of_chain_heuristic()
+                            .await
+                        {
+                            TPlat::sleep(Duration::from_secs(5)).await;
+                        }
+
+                        database::encode_database(
+                            &running_chain.network_service,
+                            &running_chain.sync_service,
+                            &running_chain.transactions_service,
+                            &running_chain.genesis_block_hash,
+                            genesis_state_root.as_ref(),
+                            checkpoint_refresh.max_size,
+                        )
+                        .await
+                    })
+                    .await
+                };
+
+                async move {
+                    // The task is interrupted when the chain is removed.
+                    futures::pin_mut!(task);
+                    let _ = future::select(task, stop_rx).await;
+                }
+                .boxed()
+            });
+
+            (Some(Checkpoints { requests_tx }), Some(stop_tx))
+        } else {
+            (None, None)
+        };
+
+        // Persistent cache task, if a cache has been set. The task restores the storage values
+        // found in the cache, then periodically writes back the database of the chain and the
+        // storage values downloaded from the network.
+        #[cfg(feature = "sqlite-cache")]
+        let persistent_cache_task_stop_tx = if let Some(cache) = self.persistent_cache.clone() {
+            let mut running_chain_init = match services_init {
+                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                future::MaybeDone::Gone => unreachable!(),
+            };
+
+            let (stop_tx, stop_rx) = oneshot::channel::<()>();
+            let chain_spec_id = chain_spec.id().to_owned();
+
+            (self.spawn_new_task)("persistent-cache-refresh".to_owned(), {
+                let task = async move {
+                    (&mut running_chain_init).await;
+                    let running_chain = Pin::new(&mut running_chain_init).take_output().unwrap();
+                    let genesis_state_root = running_chain
+                        .genesis_storage_hash
+                        .map(|storage_hash| (storage_hash, running_chain.genesis_block_state_root));
+
+                    running_chain
+                        .sync_service
+                        .storage_cache_insert(cache.storage_cache(&chain_spec_id).into_iter())
+                        .await;
+
+                    loop {
+                        // Same as for the checkpoints, storing the database while the chain is
+                        // still syncing would overwrite a potentially more recent one.
+                        while !running_chain
+                            .runtime_service
+                            .is_near_head_of_chain_heuristic()
+                            .await
+                        {
+                            TPlat::sleep(Duration::from_secs(5)).await;
+                        }
+
+                        let database_content = database::encode_database(
+                            &running_chain.network_service,
+                            &running_chain.sync_service,
+                            &running_chain.transactions_service,
+                            &running_chain.genesis_block_hash,
+                            genesis_state_root.as_ref(),
+                            PERSISTENT_CACHE_DATABASE_MAX_SIZE,
+                        )
+                        .await;
+                        cache.set_chain_database(&chain_spec_id, &database_content);
+
+                        let storage_entries =
+                            running_chain.sync_service.storage_cache_entries().await;
+                        cache.set_storage_cache(&chain_spec_id, storage_entries.into_iter());
+
+                        TPlat::sleep(PERSISTENT_CACHE_REFRESH_PERIOD).await;
+                    }
+                };
+
+                async move {
+                    // The task is interrupted when the chain is removed.
+                    futures::pin_mut!(task);
+                    let _ = future::select(task, stop_rx).await;
+                }
+                .boxed()
+            });
+
+            Some(stop_tx)
+        } else {
+            None
+        };
+        #[cfg(not(feature = "sqlite-cache"))]
+        let persistent_cache_task_stop_tx = None;
+
+        // JSON-RPC service initialization. This is done every time `add_chain` is called, even
+        // if a similar chain already existed.
+        let json_rpc_frontend = if !config.disable_json_rpc {
+            // Clone `running_chain_init`.
+            let mut running_chain_init = match services_init {
+                future::MaybeDone::Done(d) => future::MaybeDone::Done(d.clone()),
+                future::MaybeDone::Future(d) => future::MaybeDone::Future(d.clone()),
+                future::MaybeDone::Gone => unreachable!(),
+            };
+
+            let (frontend, service_starter) = json_rpc_service::service(json_rpc_service::Config {
+                log_name: log_name.clone(), // TODO: add a way to differentiate multiple different json-rpc services under the same chain
+                max_pending_requests: NonZeroU32::new(128).unwrap(),
+                max_subscriptions: 1024, // Note: the PolkadotJS UI is very heavy in terms of subscriptions.
+                max_parallel_requests: NonZeroU32::new(24).unwrap(),
+                max_parallel_subscription_updates: NonZeroU32::new(8).unwrap(),
+                max_clients: NonZeroU32::new(16).unwrap(), // One for `json_rpc_responses`, the rest for `json_rpc_endpoint`.
+                methods_filter: config.json_rpc_methods_filter.clone(),
+            });
 
             let spawn_new_task = self.spawn_new_task.clone();
             let system_name = self.system_name.clone();
             let system_version = self.system_version.clone();
+            let ethereum_json_rpc = config.ethereum_json_rpc;
 
             let init_future = async move {
                 // Wait for the chain to finish initializing before starting the JSON-RPC service.
@@ -839,12 +1798,16 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
                     network_service: (running_chain.network_service, 0), // TODO: 0?
                     transactions_service: running_chain.transactions_service,
                     runtime_service: running_chain.runtime_service,
+                    storage_subscriptions: running_chain.storage_subscriptions,
                     chain_spec: &chain_spec,
                     peer_id: &running_chain.network_identity,
                     system_name,
                     system_version,
-                    genesis_block_hash,
-                    genesis_block_state_root,
+                    genesis_block_hash: running_chain.genesis_block_hash,
+                    genesis_block_state_root: running_chain.genesis_block_state_root,
+                    genesis_storage_hash: running_chain.genesis_storage_hash,
+                    metrics: running_chain.metrics,
+                    ethereum_json_rpc,
                 })
             };
 
@@ -863,13 +1826,25 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
             chain_spec_chain_id,
             json_rpc_frontend: json_rpc_frontend.clone(),
             _public_api_chain_destroyed_tx: public_api_chain_destroyed_tx,
+            json_rpc_endpoints_destroyed_tx: Vec::new(),
+            account_watches_destroyed_tx: Vec::new(),
+            _checkpoints_task_stop_tx: checkpoints_task_stop_tx,
+            _persistent_cache_task_stop_tx: persistent_cache_task_stop_tx,
+            paused: false,
         });
+
+        // If the new chain is a parachain whose relay chain is paused, the relay chain must be
+        // resumed.
+        self.update_paused_chains();
+
         Ok(AddChainSuccess {
             chain_id: new_chain_id,
             json_rpc_responses: json_rpc_frontend.map(|f| JsonRpcResponses {
                 inner: Some(f),
                 public_api_chain_destroyed_rx,
             }),
+            checkpoints,
+            relay_chain_id: auto_added_relay_chain_id,
         })
     }
 
@@ -900,9 +1875,130 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
 
         self.public_api_chains.shrink_to_fit();
 
+        // If the removed chain was a parachain, its relay chain might now be pausable.
+        self.update_paused_chains();
+
         removed_chain.user_data
     }
 
+    /// Suspends the networking, synchronization, and all the other background activities of the
+    /// given chain, while keeping its state in memory.
+    ///
+    /// This is a cheaper alternative to removing the chain then adding it back later. Once
+    /// [`Client::resume_chain`] is called, the chain continues where it left off.
+    ///
+    /// > **Note**: The connections to the peers of the chain aren't closed, but they are no
+    /// >           longer processed while the chain is paused. Peers are likely to close these
+    /// >           connections after a while, in which case new connections are opened after
+    /// >           the chain is resumed. In any case, the chain will likely need to catch up
+    /// >           with the head of the chain after being resumed.
+    ///
+    /// The JSON-RPC service of the chain continues to accept requests, but the requests that
+    /// need to access the network or that wait for new blocks won't make progress until the
+    /// chain is resumed.
+    ///
+    /// Chains whose specification is identical share the same background tasks. The background
+    /// tasks are only actually paused if all these chains are paused. Similarly, the relay chain
+    /// of a parachain that isn't paused continues to run even if it has been paused.
+    ///
+    /// Does nothing if the chain is already paused.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn pause_chain(&mut self, chain_id: ChainId) {
+        self.public_api_chains.get_mut(chain_id.0).unwrap().paused = true;
+        self.update_paused_chains();
+    }
+
+    /// Resumes a chain previously paused with [`Client::pause_chain`].
+    ///
+    /// Does nothing if the chain isn't paused.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn resume_chain(&mut self, chain_id: ChainId) {
+        self.public_api_chains.get_mut(chain_id.0).unwrap().paused = false;
+        self.update_paused_chains();
+    }
+
+    /// Pauses or resumes the background tasks of each chain of [`Client::chains_by_key`]
+    /// depending on whether they are still needed.
+    ///
+    /// A chain is needed if at least one of the chains of [`Client::public_api_chains`] that
+    /// reference it isn't paused, or if it is the relay chain of a parachain that is needed.
+    fn update_paused_chains(&mut self) {
+        let mut needed = self
+            .public_api_chains
+            .iter()
+            .filter(|(_, chain)| !chain.paused)
+            .map(|(_, chain)| &chain.key)
+            .collect::<Vec<_>>();
+
+        let mut index = 0;
+        while let Some(key) = needed.get(index) {
+            if let Some((relay_chain, _)) = &key.relay_chain {
+                needed.push(relay_chain);
+            }
+            index += 1;
+        }
+
+        for (key, running_chain) in &self.chains_by_key {
+            let paused = !needed.contains(&key);
+            if running_chain.pause_switch.is_paused() == paused {
+                continue;
+            }
+
+            if paused {
+                log::info!(target: "smoldot", "Pausing chain {}", running_chain.log_name);
+            } else {
+                log::info!(target: "smoldot", "Resuming chain {}", running_chain.log_name);
+            }
+
+            running_chain.pause_switch.set_paused(paused);
+        }
+    }
+
+    /// Shrinks the caches that the chains maintain in order to reduce the number of network
+    /// requests, such as the cache of storage values and the runtimes of old blocks that
+    /// JSON-RPC calls have targeted.
+    ///
+    /// The least recently used entries of each cache are evicted until only a small fraction of
+    /// its capacity remains in use. The most recently used entries are kept, so that a JSON-RPC
+    /// client resuming its activity doesn't have to download everything again.
+    ///
+    /// Calling this function is useful on platforms where memory is scarce, for example after
+    /// the client has been idle for a while or when memory usage is high. Calling it too often
+    /// defeats the purpose of the caches.
+    ///
+    /// The caches are shrunk in the background or when the returned future is polled. It can
+    /// safely be dropped, in which case the cache of storage values is left untouched.
+    pub fn trim_caches(&self) -> impl Future<Output = ()> + Send + 'static {
+        for (_, chain) in &self.public_api_chains {
+            if let Some(json_rpc_frontend) = &chain.json_rpc_frontend {
+                json_rpc_frontend.trim_caches();
+            }
+        }
+
+        let sync_services = self
+            .chains_by_key
+            .values()
+            .filter_map(|chain| match &chain.services {
+                future::MaybeDone::Done(services) => Some(services.sync_service.clone()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        async move {
+            for sync_service in sync_services {
+                sync_service.storage_cache_trim().await;
+            }
+        }
+    }
+
     /// Returns the user data associated to the given chain.
     ///
     /// # Panic
@@ -917,6 +2013,43 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
             .user_data
     }
 
+    /// Returns the total amount of time that has been spent executing the background tasks of
+    /// the given chain since it has been added.
+    ///
+    /// This makes it possible, when multiple chains are running, to determine which chain is
+    /// responsible for most of the CPU usage, and potentially remove it.
+    ///
+    /// Note that chains whose specification is identical share the same background tasks, and
+    /// thus the same value. Operations that the [`platform::Platform`] executes on a different
+    /// thread through [`platform::Platform::run_cpu_intensive`] aren't included.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn chain_cpu_time(&self, chain_id: ChainId) -> Duration {
+        let key = &self.public_api_chains.get(chain_id.0).unwrap().key;
+        self.chains_by_key.get(key).unwrap().cpu_time.get()
+    }
+
+    /// Returns the percentiles of the durations of the network requests and of the JSON-RPC
+    /// requests of the given chain since it has been added.
+    ///
+    /// This makes it possible for the API user to track the performance of the client over time,
+    /// and notably to detect regressions.
+    ///
+    /// Note that chains whose specification is identical share the same services, and thus the
+    /// same metrics.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn metrics_snapshot(&self, chain_id: ChainId) -> MetricsSnapshot {
+        let key = &self.public_api_chains.get(chain_id.0).unwrap().key;
+        self.chains_by_key.get(key).unwrap().metrics.snapshot()
+    }
+
     /// Enqueues a JSON-RPC request towards the given chain.
     ///
     /// Since most JSON-RPC requests can only be answered asynchronously, the request is only
@@ -959,6 +2092,684 @@ impl<TPlat: platform::Platform, TChain> Client<TPlat, TChain> {
 
         json_rpc_sender.queue_rpc_request(json_rpc_request)
     }
+
+    /// Creates a new JSON-RPC endpoint for the given chain.
+    ///
+    /// Each endpoint behaves as a separate JSON-RPC client: it has its own requests and
+    /// subscriptions, and the limits on the number of pending requests and active subscriptions
+    /// apply to each endpoint individually. Responses to the requests sent through an endpoint
+    /// are only ever returned by this endpoint. This makes it possible to safely give separate
+    /// endpoints to separate users of the same chain.
+    ///
+    /// Destroying the [`JsonRpcEndpoint`] cancels all its requests and subscriptions.
+    ///
+    /// The returned future can safely be dropped, and stays valid even if the chain is removed in
+    /// the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid, or if [`AddChainConfig::disable_json_rpc`] was
+    /// `true` when adding the chain.
+    ///
+    pub fn json_rpc_endpoint(
+        &mut self,
+        chain_id: ChainId,
+    ) -> impl Future<Output = Result<JsonRpcEndpoint, JsonRpcEndpointError>> + Send + 'static {
+        let chain = self.public_api_chains.get_mut(chain_id.0).unwrap();
+        let frontend = chain.json_rpc_frontend.clone().unwrap();
+
+        let (public_api_chain_destroyed_tx, public_api_chain_destroyed_rx) = oneshot::channel();
+        chain
+            .json_rpc_endpoints_destroyed_tx
+            .retain(|tx| !tx.is_canceled());
+        chain
+            .json_rpc_endpoints_destroyed_tx
+            .push(public_api_chain_destroyed_tx);
+
+        let spawn_new_task = self.spawn_new_task.clone();
+
+        async move {
+            let inner = frontend
+                .add_client()
+                .await
+                .map_err(|_| JsonRpcEndpointError::LimitReached)?;
+            Ok(JsonRpcEndpoint {
+                inner: Some(inner),
+                public_api_chain_destroyed_rx,
+                spawn_new_task,
+            })
+        }
+    }
+
+    /// Estimates the fees that the given SCALE-encoded transaction would cost if it was included
+    /// in a child of the given block of the given chain.
+    ///
+    /// If `block_hash` is `None`, the current best block of the chain is used. Otherwise, the
+    /// block must be either the current finalized block or one of its non-finalized descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn estimate_fee(
+        &self,
+        chain_id: ChainId,
+        transaction: Vec<u8>,
+        block_hash: Option<[u8; 32]>,
+    ) -> impl Future<Output = Result<FeeEstimate, EstimateFeeError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services
+                .fee_estimation_service
+                .estimate_fee(&transaction, block_hash)
+                .await
+        }
+    }
+
+    /// Fetches the nonce and balance of the given account of the given chain, by downloading
+    /// and decoding its `System.Account` storage entry.
+    ///
+    /// The address can be either in the SS58 format or a `0x`-prefixed hexadecimal public key.
+    /// The network identifier of SS58 addresses isn't compared with the one of the chain.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn account_info(
+        &self,
+        chain_id: ChainId,
+        address: &str,
+        block: AccountInfoBlock,
+    ) -> impl Future<Output = Result<AccountInfoAtBlock, AccountInfoError>> + Send + 'static {
+        let account_id = account_info::decode_address(address);
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let account_id = account_id?;
+            let services = services.await;
+            account_info::account_info(
+                &services.sync_service,
+                &services.runtime_service,
+                &services.account_info_decoders,
+                account_id,
+                block,
+            )
+            .await
+        }
+    }
+
+    /// Starts watching the given accounts of the given chain.
+    ///
+    /// Every time a block is finalized, the nonce and balance of the accounts are fetched, and
+    /// the body of the block is downloaded in order to find the extrinsics that are signed by or
+    /// mention one of the accounts. The corresponding events are returned by the
+    /// [`AccountWatch`].
+    ///
+    /// `address_encoding` indicates how the runtime of the chain encodes the address of the
+    /// signer of extrinsics.
+    ///
+    /// The watching starts once the chain has finished initializing, and stops when the
+    /// [`AccountWatch`] is destroyed or the chain is removed.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn watch_accounts(
+        &mut self,
+        chain_id: ChainId,
+        accounts: Vec<[u8; 32]>,
+        address_encoding: smoldot::transactions::calls::AddressEncoding,
+    ) -> AccountWatch {
+        let services = self.chain_services(chain_id);
+
+        let chain = self.public_api_chains.get_mut(chain_id.0).unwrap();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        chain
+            .account_watches_destroyed_tx
+            .retain(|tx| !tx.is_canceled());
+        chain.account_watches_destroyed_tx.push(stop_tx);
+
+        let (events_tx, events_rx) = mpsc::channel(32);
+
+        (self.spawn_new_task)("account-watch".to_owned(), {
+            let task = async move {
+                let services = services.await;
+                account_watch::run(
+                    services.sync_service,
+                    services.runtime_service,
+                    account_watch::Config {
+                        accounts,
+                        address_encoding,
+                    },
+                    events_tx,
+                )
+                .await
+            };
+
+            async move {
+                // The task is interrupted when the chain is removed.
+                futures::pin_mut!(task);
+                let _ = future::select(task, stop_rx).await;
+            }
+            .boxed()
+        });
+
+        AccountWatch { rx: events_rx }
+    }
+
+    /// Fetches and decodes the list of validators of the current session of the given chain,
+    /// and the session keys of the validators of the next session, by downloading the
+    /// `Session.Validators` and `Session.QueuedKeys` storage entries.
+    ///
+    /// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+    /// either the current finalized block or one of its non-finalized descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn session_validators(
+        &self,
+        chain_id: ChainId,
+        block_hash: Option<[u8; 32]>,
+    ) -> impl Future<Output = Result<SessionValidators, SessionValidatorsError>> + Send + 'static
+    {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            session_info::session_validators(
+                &services.sync_service,
+                &services.runtime_service,
+                block_hash,
+            )
+            .await
+        }
+    }
+
+    /// Fetches and decodes the `Staking.ActiveEra` storage entry of the given chain. The value
+    /// is `None` if the chain doesn't have a `Staking` pallet or if no era has started yet.
+    ///
+    /// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+    /// either the current finalized block or one of its non-finalized descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn staking_active_era(
+        &self,
+        chain_id: ChainId,
+        block_hash: Option<[u8; 32]>,
+    ) -> impl Future<
+        Output = Result<
+            StakingAtBlock<Option<smoldot::json_rpc::staking_info::ActiveEra>>,
+            StakingQueryError,
+        >,
+    > + Send
+           + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            staking_info::active_era(
+                &services.sync_service,
+                &services.runtime_service,
+                block_hash,
+            )
+            .await
+        }
+    }
+
+    /// Fetches and decodes the reward points earned by the validators of the given chain during
+    /// the given era, by downloading the `Staking.ErasRewardPoints` storage entry.
+    ///
+    /// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+    /// either the current finalized block or one of its non-finalized descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn staking_era_reward_points(
+        &self,
+        chain_id: ChainId,
+        era: u32,
+        block_hash: Option<[u8; 32]>,
+    ) -> impl Future<
+        Output = Result<
+            StakingAtBlock<smoldot::json_rpc::staking_info::EraRewardPoints>,
+            StakingQueryError,
+        >,
+    > + Send
+           + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            staking_info::era_reward_points(
+                &services.sync_service,
+                &services.runtime_service,
+                era,
+                block_hash,
+            )
+            .await
+        }
+    }
+
+    /// Fetches and decodes the nominators backing each of the given validators of the given
+    /// chain during the given era, by downloading the `Staking.ErasStakersOverview` and
+    /// `Staking.ErasStakersPaged` storage entries.
+    ///
+    /// The storage entries are downloaded in batches, each batch being retrieved with a single
+    /// storage proof, in order to avoid one network request per validator and per page.
+    ///
+    /// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+    /// either the current finalized block or one of its non-finalized descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn staking_exposures(
+        &self,
+        chain_id: ChainId,
+        era: u32,
+        validators: Vec<[u8; 32]>,
+        block_hash: Option<[u8; 32]>,
+    ) -> impl Future<Output = Result<StakingAtBlock<Vec<ValidatorExposure>>, StakingQueryError>>
+           + Send
+           + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            staking_info::exposures(
+                &services.sync_service,
+                &services.runtime_service,
+                era,
+                validators,
+                block_hash,
+            )
+            .await
+        }
+    }
+
+    /// Determines which of the given storage `keys` of the given chain have a different value in
+    /// the block whose hash is `new_block_hash` compared to the block whose hash is
+    /// `old_block_hash`. Returns the keys that have changed, in the same order as in `keys`.
+    ///
+    /// Both blocks must be either the current finalized block of the chain or one of its
+    /// non-finalized descendants. If the storage trie roots of both blocks are identical, no
+    /// network request is performed. Otherwise, the storage values that have recently been
+    /// downloaded aren't downloaded again.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn storage_changes(
+        &self,
+        chain_id: ChainId,
+        old_block_hash: [u8; 32],
+        new_block_hash: [u8; 32],
+        keys: Vec<Vec<u8>>,
+    ) -> impl Future<Output = Result<Vec<Vec<u8>>, StorageChangesError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            storage_changes::storage_changes(
+                &services.sync_service,
+                &services.runtime_service,
+                old_block_hash,
+                new_block_hash,
+                keys,
+            )
+            .await
+        }
+    }
+
+    /// Gathers the header, body, events, and outcome of each extrinsic of the given block of
+    /// the given chain. All the information is verified against the header of the block.
+    ///
+    /// The block must be either the current finalized block or one of its non-finalized
+    /// descendants.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn block_bundle(
+        &self,
+        chain_id: ChainId,
+        block_hash: [u8; 32],
+    ) -> impl Future<Output = Result<BlockBundle, BlockBundleError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            block_bundle::block_bundle(
+                &services.sync_service,
+                &services.runtime_service,
+                block_hash,
+            )
+            .await
+        }
+    }
+
+    /// Starts downloading a snapshot of the storage entries of the given chain whose key starts
+    /// with one of the given `prefixes`. The snapshot is pinned to the current finalized block.
+    ///
+    /// The returned [`StorageSnapshot`] is initially empty and must be filled by calling
+    /// [`Client::storage_snapshot_next_page`] until [`StorageSnapshot::is_complete`] returns
+    /// `true`. This makes it possible, for example, to run checks against the state of a live
+    /// chain without access to an archive node.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn storage_snapshot_start(
+        &self,
+        chain_id: ChainId,
+        prefixes: Vec<Vec<u8>>,
+    ) -> impl Future<Output = Result<StorageSnapshot, StorageSnapshotError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            storage_snapshot::start(&services.sync_service, &services.runtime_service, prefixes)
+                .await
+        }
+    }
+
+    /// Performs the next step of downloading the given [`StorageSnapshot`] of the given chain,
+    /// and returns the updated snapshot.
+    ///
+    /// Each call either lists the keys of one of the prefixes, or downloads the values of up to
+    /// `page_size` keys using a single Merkle proof. Everything is verified against the state
+    /// trie root of the block the snapshot is pinned to.
+    ///
+    /// On error, the snapshot is returned unmodified alongside with the error, and the download
+    /// can be resumed by calling this function again. Because [`StorageSnapshot`] contains the
+    /// entire state of the download, it can also be persisted and resumed later.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn storage_snapshot_next_page(
+        &self,
+        chain_id: ChainId,
+        snapshot: StorageSnapshot,
+        page_size: NonZeroUsize,
+    ) -> impl Future<Output = Result<StorageSnapshot, (StorageSnapshot, StorageSnapshotError)>>
+           + Send
+           + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            storage_snapshot::next_page(&services.sync_service, snapshot, page_size).await
+        }
+    }
+
+    /// Verifies a justification or GrandPa commit of the given chain that has been obtained
+    /// through other means than the peer-to-peer network, for example from a trusted archive,
+    /// and applies it to the chain.
+    ///
+    /// This can be used to accelerate the finality of chains whose peers rarely send
+    /// justifications. The proof is verified in the same way as the ones sent by peers, and as
+    /// such doesn't need to come from a trusted source.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn inject_finality_proof(
+        &self,
+        chain_id: ChainId,
+        finality_proof: smoldot::sync::all::ExternalFinalityProof,
+    ) -> impl Future<Output = Result<(), InjectFinalityProofError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services
+                .sync_service
+                .inject_finality_proof(finality_proof)
+                .await
+        }
+    }
+
+    /// Returns the list of GrandPa warp sync fragments that have been verified when the given
+    /// chain has been warp synced.
+    ///
+    /// Each fragment contains the header of a block that changes the list of GrandPa
+    /// authorities of the chain, and a justification of this block signed by the previous list
+    /// of authorities. Starting from the chain information found in the chain specification or
+    /// database that the chain has been added with, these fragments prove the finality of the
+    /// block found in the last fragment.
+    ///
+    /// Returns an empty list if the warp syncing hasn't finished yet, if no warp syncing was
+    /// necessary, or if the chain is a parachain.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn warp_sync_fragments(
+        &self,
+        chain_id: ChainId,
+    ) -> impl Future<Output = Vec<smoldot::sync::warp_sync::WarpSyncFragment>> + Send + 'static
+    {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services.sync_service.warp_sync_fragments().await
+        }
+    }
+
+    /// Returns the justification or GrandPa commit that has been verified and that proves the
+    /// finality of the block of the given chain with the given hash.
+    ///
+    /// Only the proofs of the latest [`AddChainConfig::finality_proofs_window`] finalized blocks
+    /// are kept. Returns `None` if no proof is known for this block, for example because the
+    /// block has been finalized as part of the warp syncing, or if the chain is a parachain.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn finality_proof(
+        &self,
+        chain_id: ChainId,
+        block_hash: [u8; 32],
+    ) -> impl Future<Output = Option<RetainedFinalityProof>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services.sync_service.finality_proof(block_hash).await
+        }
+    }
+
+    /// Builds a commitment to the state of the current finalized block of the given chain: its
+    /// hash, height, state root, GrandPa authorities set, and Merkle Mountain Range root if the
+    /// chain has one.
+    ///
+    /// The commitment can be persisted with [`StateCommitment::encode`], then later decoded and
+    /// verified against a different instance of the client with
+    /// [`Client::verify_state_commitment`].
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn export_state_commitment(
+        &self,
+        chain_id: ChainId,
+    ) -> impl Future<Output = Result<StateCommitment, StateCommitmentError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            state_commitment::export(&services.sync_service).await
+        }
+    }
+
+    /// Verifies that the given commitment, previously obtained with
+    /// [`Client::export_state_commitment`], matches the header and storage of the block of the
+    /// given chain that it refers to. The header and storage are downloaded from the network.
+    ///
+    /// A successful verification proves that the commitment accurately reflects the state of
+    /// the block, but not that the block is finalized.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn verify_state_commitment(
+        &self,
+        chain_id: ChainId,
+        commitment: StateCommitment,
+    ) -> impl Future<Output = Result<(), StateCommitmentError>> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            state_commitment::verify(&services.sync_service, &commitment).await
+        }
+    }
+
+    /// Bans the transactions of the given chain that match the given [`TransactionBan`], for
+    /// example in order to mitigate spam.
+    ///
+    /// The transactions that match and that have been submitted through the JSON-RPC interface
+    /// are dropped and are no longer gossiped to peers, and the ones submitted later are
+    /// immediately dropped. Note that chains whose specification is identical share the same
+    /// transactions pool, and thus the same bans.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn ban_transactions(
+        &self,
+        chain_id: ChainId,
+        ban: TransactionBan,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services.transactions_service.ban(ban).await
+        }
+    }
+
+    /// Reverts a previous call to [`Client::ban_transactions`]. Has no effect if this ban
+    /// doesn't exist.
+    ///
+    /// The returned future waits for the chain to finish initializing if necessary. It can
+    /// safely be dropped, and stays valid even if the chain is removed in the meanwhile.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    pub fn unban_transactions(
+        &self,
+        chain_id: ChainId,
+        ban: TransactionBan,
+    ) -> impl Future<Output = ()> + Send + 'static {
+        let services = self.chain_services(chain_id);
+
+        async move {
+            let services = services.await;
+            services.transactions_service.unban(ban).await
+        }
+    }
+
+    /// Returns a future that yields the services of the given chain, once it has finished
+    /// initializing.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the [`ChainId`] is invalid.
+    ///
+    fn chain_services(
+        &self,
+        chain_id: ChainId,
+    ) -> impl Future<Output = ChainServices<TPlat>> + Send + 'static {
+        let key = &self.public_api_chains.get(chain_id.0).unwrap().key;
+        match &self.chains_by_key.get(key).unwrap().services {
+            future::MaybeDone::Done(services) => either::Left(future::ready(services.clone())),
+            future::MaybeDone::Future(services) => either::Right(services.clone()),
+            future::MaybeDone::Gone => unreachable!(),
+        }
+    }
+}
+
+/// Error potentially returned by [`Client::json_rpc_endpoint`].
+#[derive(Debug, derive_more::Display)]
+pub enum JsonRpcEndpointError {
+    /// The maximum number of JSON-RPC endpoints of this chain has been reached.
+    #[display(fmt = "Maximum number of JSON-RPC endpoints reached")]
+    LimitReached,
 }
 
 /// Error potentially returned by [`Client::add_chain`].
@@ -967,6 +2778,19 @@ pub enum AddChainError {
     /// Failed to decode the specification of the chain.
     #[display(fmt = "Failed to decode chain specification: {_0}")]
     ChainSpecParseError(chain_spec::ParseError),
+    /// [`ChainSpecification::WellKnown`] doesn't designate a chain found in
+    /// [`well_known_chains`].
+    #[display(fmt = "Unknown well-known chain")]
+    UnknownWellKnownChain,
+    /// Failed to decode the genesis storage provided separately from the chain specification.
+    #[display(fmt = "Failed to decode genesis storage: {_0}")]
+    GenesisStorageParseError(chain_spec::GenesisStorageParseError),
+    /// The trie root hash of the genesis storage provided separately from the chain
+    /// specification doesn't match the one found in the chain specification.
+    #[display(
+        fmt = "Genesis storage doesn't match the state root hash of the chain specification"
+    )]
+    GenesisStorageStateRootMismatch,
     /// The chain specification must contain either the storage of the genesis block, or a
     /// checkpoint. Neither was provided.
     #[display(fmt = "Either a checkpoint or the genesis storage must be provided")]
@@ -999,10 +2823,29 @@ async fn start_services<TPlat: platform::Platform>(
     >,
     chain_information: chain::chain_information::ValidChainInformation,
     genesis_block_scale_encoded_header: Vec<u8>,
+    genesis_storage_hash: Option<[u8; 32]>,
     chain_spec: chain_spec::ChainSpec,
+    fork_id: Option<String>,
     relay_chain: Option<&ChainServices<TPlat>>,
     network_noise_key: connection::NoiseKey,
+    block_announce_policy: sync_service::BlockAnnouncePolicy,
+    warp_sync_min_distinct_peers: NonZeroU32,
+    finality_proofs_window: u32,
+    skip_seal_verification: bool,
+    transactions_pool: TransactionsPoolConfig,
+    clock_drift_tolerance: Duration,
+    max_requests_per_peer: NonZeroUsize,
+    metrics: Arc<metrics::ChainMetrics>,
 ) -> ChainServices<TPlat> {
+    let genesis_block_hash =
+        header::hash_from_scale_encoded_header(&genesis_block_scale_encoded_header);
+    let genesis_block_state_root = *header::decode(
+        &genesis_block_scale_encoded_header,
+        usize::from(chain_spec.block_number_bytes()),
+    )
+    .unwrap()
+    .state_root;
+
     // Since `network_noise_key` is moved out below, use it to build the network identity ahead
     // of the network service starting.
     let network_identity =
@@ -1017,15 +2860,14 @@ async fn start_services<TPlat: platform::Platform>(
             }),
             num_events_receivers: 1, // Configures the length of `network_event_receivers`
             noise_key: network_noise_key,
+            max_requests_per_peer,
             chains: vec![network_service::ConfigChain {
                 log_name: log_name.clone(),
                 has_grandpa_protocol: matches!(
                     chain_information.as_ref().finality,
                     chain::chain_information::ChainInformationFinalityRef::Grandpa { .. }
                 ),
-                genesis_block_hash: header::hash_from_scale_encoded_header(
-                    &genesis_block_scale_encoded_header,
-                ),
+                genesis_block_hash,
                 finalized_block_height: chain_information.as_ref().finalized_block_header.number,
                 best_block: (
                     chain_information.as_ref().finalized_block_header.number,
@@ -1034,8 +2876,9 @@ async fn start_services<TPlat: platform::Platform>(
                         .finalized_block_header
                         .hash(chain_spec.block_number_bytes().into()),
                 ),
-                fork_id: chain_spec.fork_id().map(|n| n.to_owned()),
+                fork_id,
                 block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+                metrics: metrics.clone(),
             }],
         })
         .await;
@@ -1051,6 +2894,14 @@ async fn start_services<TPlat: platform::Platform>(
                 log_name: log_name.clone(),
                 chain_information: chain_information.clone(),
                 block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+                bad_blocks: Default::default(),
+                fork_blocks: Default::default(),
+                clock_drift_tolerance,
+                block_announce_policy,
+                warp_sync_min_distinct_peers,
+                finality_proofs_window,
+                skip_seal_verification,
+                bootnodes: Vec::new(),
                 tasks_executor: Box::new({
                     let spawn_new_task = spawn_new_task.clone();
                     move |name, fut| spawn_new_task(name, fut)
@@ -1077,6 +2928,10 @@ async fn start_services<TPlat: platform::Platform>(
                 }),
                 sync_service: sync_service.clone(),
                 genesis_block_scale_encoded_header,
+                code_substitutes: chain_spec
+                    .code_substitutes()
+                    .map(|(n, code)| (n, code.to_vec()))
+                    .collect(),
             })
             .await,
         );
@@ -1093,6 +2948,22 @@ async fn start_services<TPlat: platform::Platform>(
                 log_name: log_name.clone(),
                 chain_information: chain_information.clone(),
                 block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+                bad_blocks: chain_spec.bad_blocks_hashes().copied().collect(),
+                fork_blocks: chain_spec.fork_blocks().map(|(n, h)| (n, *h)).collect(),
+                clock_drift_tolerance,
+                block_announce_policy,
+                warp_sync_min_distinct_peers,
+                finality_proofs_window,
+                skip_seal_verification,
+                bootnodes: chain_spec
+                    .boot_nodes()
+                    .filter_map(|bootnode| match bootnode {
+                        chain_spec::Bootnode::Parsed { peer_id, .. } => {
+                            peer_id::PeerId::from_bytes(peer_id).ok()
+                        }
+                        chain_spec::Bootnode::UnrecognizedFormat(_) => None,
+                    })
+                    .collect(),
                 tasks_executor: Box::new({
                     let spawn_new_task = spawn_new_task.clone();
                     move |name, fut| spawn_new_task(name, fut)
@@ -1115,6 +2986,10 @@ async fn start_services<TPlat: platform::Platform>(
                 }),
                 sync_service: sync_service.clone(),
                 genesis_block_scale_encoded_header,
+                code_substitutes: chain_spec
+                    .code_substitutes()
+                    .map(|(n, code)| (n, code.to_vec()))
+                    .collect(),
             })
             .await,
         );
@@ -1122,6 +2997,29 @@ async fn start_services<TPlat: platform::Platform>(
         (sync_service, runtime_service)
     };
 
+    // The storage subscriptions are shared between all the JSON-RPC services of the chain, so
+    // that identical subscriptions don't lead to identical storage proofs being downloaded.
+    let storage_subscriptions = Arc::new(
+        json_rpc_service::storage_subscriptions::StorageSubscriptions::new(
+            json_rpc_service::storage_subscriptions::Config {
+                log_name: log_name.clone(),
+                tasks_executor: Box::new({
+                    let spawn_new_task = spawn_new_task.clone();
+                    move |name, fut| spawn_new_task(name, fut)
+                }),
+                sync_service: sync_service.clone(),
+                runtime_service: runtime_service.clone(),
+            },
+        ),
+    );
+
+    let fee_estimation_service = Arc::new(fee_estimation_service::FeeEstimationService::new(
+        fee_estimation_service::Config {
+            log_name: log_name.clone(),
+            runtime_service: runtime_service.clone(),
+        },
+    ));
+
     // The transactions service lets one send transactions to the peer-to-peer network and watch
     // them being included in the chain.
     // While this service is in principle not needed if it is known ahead of time that no
@@ -1133,7 +3031,12 @@ async fn start_services<TPlat: platform::Platform>(
             sync_service: sync_service.clone(),
             runtime_service: runtime_service.clone(),
             network_service: (network_service.clone(), 0),
-            max_pending_transactions: NonZeroU32::new(64).unwrap(),
+            max_pending_transactions: transactions_pool.max_transactions,
+            max_pending_transactions_bytes: transactions_pool.max_bytes,
+            max_pending_transactions_per_sender: transactions_pool.max_transactions_per_sender,
+            eviction_policy: transactions_pool.eviction_policy,
+            max_broadcast_peers: transactions_pool.max_broadcast_peers,
+            reannounce_interval: transactions_pool.reannounce_interval,
             max_concurrent_downloads: NonZeroU32::new(3).unwrap(),
             max_concurrent_validations: NonZeroU32::new(2).unwrap(),
         })
@@ -1146,6 +3049,448 @@ async fn start_services<TPlat: platform::Platform>(
         runtime_service,
         sync_service,
         transactions_service,
+        storage_subscriptions,
+        fee_estimation_service,
+        metrics,
+        account_info_decoders: Arc::new(account_info::DecodersCache::new()),
         block_number_bytes: usize::from(chain_spec.block_number_bytes()),
+        genesis_block_hash,
+        genesis_block_state_root,
+        genesis_storage_hash,
+    }
+}
+
+/// Block from which a chain starts syncing. See [`Client::add_chain`].
+enum StartingPoint {
+    /// The header of the genesis block is known.
+    Known {
+        /// Information about the block to start syncing from.
+        chain_information: Box<chain::chain_information::ValidChainInformation>,
+        /// Header of the genesis block of the chain.
+        genesis_block_header: Box<header::Header>,
+    },
+
+    /// The trie root hash of the genesis storage, and thus the header of the genesis block,
+    /// must first be calculated. The block to start syncing from is chosen afterwards.
+    PendingGenesisStateRoot {
+        /// Information about the genesis block, missing the trie root hash.
+        genesis_chain_information: Box<chain_spec::ChainInformationWithoutStateRoot>,
+        /// Checkpoint found in the chain specification, if any.
+        checkpoint: Option<Box<chain::chain_information::ValidChainInformation>>,
+        /// Database passed by the API user, if any. Might concern a different chain.
+        database_content: Option<Box<database::DatabaseContent>>,
+    },
+}
+
+/// List of nodes of the peer-to-peer network of a chain, and their addresses.
+type KnownNodes = Vec<(PeerId, Vec<multiaddr::Multiaddr>)>;
+
+/// Chooses the block to start syncing from between the genesis block, the checkpoint found in
+/// the chain specification, and the database, and returns it alongside with the nodes found in
+/// the database.
+///
+/// The database is used only if it concerns the chain whose genesis block header is passed, and
+/// if it contains a more recent block than the checkpoint.
+///
+/// Returns `None` if neither `genesis_chain_information` nor `checkpoint` are provided and the
+/// database can't be used.
+fn choose_starting_point(
+    genesis_block_header: &header::Header,
+    genesis_chain_information: Option<chain::chain_information::ValidChainInformation>,
+    checkpoint: Option<chain::chain_information::ValidChainInformation>,
+    database_content: Option<database::DatabaseContent>,
+    block_number_bytes: usize,
+) -> Option<(chain::chain_information::ValidChainInformation, KnownNodes)> {
+    let genesis_block_hash = genesis_block_header.hash(block_number_bytes);
+    let database_content =
+        database_content.filter(|db| db.genesis_block_hash == genesis_block_hash);
+
+    match (database_content, checkpoint) {
+        (Some(database_content), checkpoint)
+            if checkpoint.as_ref().map_or(true, |cp| {
+                cp.as_ref().finalized_block_header.number
+                    < database_content
+                        .chain_information
+                        .as_ref()
+                        .finalized_block_header
+                        .number
+            }) =>
+        {
+            Some((
+                database_content.chain_information,
+                database_content.known_nodes,
+            ))
+        }
+        (database_content, Some(checkpoint)) => Some((
+            checkpoint,
+            database_content
+                .map(|db| db.known_nodes)
+                .unwrap_or_default(),
+        )),
+        (database_content, None) => genesis_chain_information.map(|genesis| {
+            (
+                genesis,
+                database_content
+                    .map(|db| db.known_nodes)
+                    .unwrap_or_default(),
+            )
+        }),
+    }
+}
+
+/// Calculates the hash of the root of the trie of the given genesis storage, without yielding.
+fn genesis_state_root_sync(
+    genesis_storage: chain_spec::GenesisStorageItems<'_>,
+    state_version: smoldot::trie::TrieEntryVersion,
+) -> [u8; 32] {
+    match genesis_storage
+        .trie_root_hash_calculation(state_version)
+        .advance(usize::MAX)
+    {
+        chain_spec::TrieRootHashCalculationStep::Finished(hash) => hash,
+        chain_spec::TrieRootHashCalculationStep::InProgress(_) => unreachable!(),
+    }
+}
+
+/// Calculates the hash of the root of the trie of the given genesis storage.
+///
+/// The calculation is split in multiple steps, between which control is yielded back. Its
+/// progress is regularly printed in the logs.
+async fn calculate_genesis_state_root<TPlat: platform::Platform>(
+    log_name: &str,
+    genesis_storage: chain_spec::GenesisStorageItems<'_>,
+    state_version: smoldot::trie::TrieEntryVersion,
+) -> [u8; 32] {
+    // Number of storage values to include in the calculation between two yields.
+    const VALUES_PER_STEP: usize = 2048;
+
+    let mut calculation = genesis_storage.trie_root_hash_calculation(state_version);
+    let mut last_printed_percent = None;
+
+    loop {
+        match calculation.advance(VALUES_PER_STEP) {
+            chain_spec::TrieRootHashCalculationStep::Finished(hash) => return hash,
+            chain_spec::TrieRootHashCalculationStep::InProgress(c) => {
+                let (num_done, num_total) = c.progress();
+                let percent = num_done * 100 / num_total;
+                match last_printed_percent {
+                    Some(printed) if percent < printed + 10 => {}
+                    _ => {
+                        log::info!(
+                            target: "smoldot",
+                            "Calculating the genesis state root hash of {}: {}%",
+                            log_name, percent
+                        );
+                        last_printed_percent = Some(percent);
+                    }
+                }
+                calculation = c;
+            }
+        }
+
+        // As explained in the documentation of `yield_after_cpu_intensive`, we should yield
+        // after a CPU-intensive operation.
+        TPlat::yield_after_cpu_intensive().await;
+    }
+}
+
+/// Answers the requests for a checkpoint received on `requests_rx` by calling `generate`, but
+/// without generating two checkpoints less than `period` apart. Returns when all the senders of
+/// `requests_rx` have been destroyed.
+async fn checkpoints_task<TPlat: platform::Platform, TFut: Future<Output = String>>(
+    mut requests_rx: mpsc::Receiver<oneshot::Sender<String>>,
+    period: Duration,
+    mut generate: impl FnMut() -> TFut,
+) {
+    let mut next_checkpoint_earliest = None;
+
+    while let Some(response_tx) = requests_rx.next().await {
+        if let Some(when) = next_checkpoint_earliest.take() {
+            TPlat::sleep_until(when).await;
+        }
+
+        // The API user might have stopped waiting for the checkpoint in the meanwhile.
+        if response_tx.is_canceled() {
+            continue;
+        }
+
+        let checkpoint = generate().await;
+        next_checkpoint_earliest = Some(TPlat::now() + period);
+        let _ = response_tx.send(checkpoint);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{choose_starting_point, database, header, peer_id};
+    use smoldot::{chain_spec, database::finalized_serialize};
+
+    #[test]
+    fn legacy_database_without_genesis_state_root_accepted() {
+        let chain_spec = chain_spec::ChainSpec::from_json_bytes(
+            &include_bytes!("../../lib/src/chain_spec/example.json")[..],
+        )
+        .unwrap();
+        let (genesis_chain_information, _) = chain_spec.as_chain_information().unwrap();
+        let genesis_block_header = header::Header::from(
+            genesis_chain_information
+                .as_ref()
+                .finalized_block_header
+                .clone(),
+        );
+        let genesis_block_hash = genesis_block_header.hash(4);
+
+        // Database encoded before the trie root hash of the genesis storage was cached.
+        let peer_id = peer_id::PeerId::from_public_key(&peer_id::PublicKey::Ed25519([1; 32]));
+        let legacy_database = |genesis_block_hash: &[u8; 32]| {
+            format!(
+                r#"{{"genesisHash":"{}","chain":{},"nodes":{{"{}":["/ip4/1.2.3.4/tcp/30333"]}}}}"#,
+                hex::encode(genesis_block_hash),
+                finalized_serialize::encode_chain(&genesis_chain_information, 4),
+                peer_id.to_base58()
+            )
+        };
+
+        let database_content =
+            database::decode_database(&legacy_database(&genesis_block_hash), 4).unwrap();
+        assert!(database_content.genesis_state_root.is_none());
+        assert_eq!(database_content.genesis_block_hash, genesis_block_hash);
+
+        let (_, known_nodes) = choose_starting_point(
+            &genesis_block_header,
+            Some(genesis_chain_information.clone()),
+            None,
+            Some(database_content),
+            4,
+        )
+        .unwrap();
+        assert_eq!(known_nodes.len(), 1);
+        assert_eq!(known_nodes[0].0, peer_id);
+
+        // A database of a different chain is ignored.
+        let database_content = database::decode_database(&legacy_database(&[0xff; 32]), 4).unwrap();
+        let (_, known_nodes) = choose_starting_point(
+            &genesis_block_header,
+            Some(genesis_chain_information),
+            None,
+            Some(database_content),
+            4,
+        )
+        .unwrap();
+        assert!(known_nodes.is_empty());
+    }
+
+    #[test]
+    fn neither_genesis_nor_checkpoint() {
+        let genesis_block_header = header::Header {
+            parent_hash: [0; 32],
+            number: 0,
+            state_root: [1; 32],
+            extrinsics_root: smoldot::trie::empty_trie_merkle_value(),
+            digest: header::DigestRef::empty().into(),
+        };
+        assert!(choose_starting_point(&genesis_block_header, None, None, None, 4).is_none());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn separate_genesis_storage_verified_against_state_root() {
+        let spec_json = &include_bytes!("../../lib/src/chain_spec/example.json")[..];
+        let chain_spec = chain_spec::ChainSpec::from_json_bytes(spec_json).unwrap();
+        let genesis_storage = chain_spec
+            .genesis_storage()
+            .into_genesis_items()
+            .unwrap()
+            .to_binary();
+        let (genesis_chain_information, _) = chain_spec.as_chain_information().unwrap();
+        let state_root = genesis_chain_information
+            .as_ref()
+            .finalized_block_header
+            .state_root;
+
+        let spec_with_state_root = |state_root: &[u8; 32]| {
+            let mut spec = serde_json::from_slice::<serde_json::Value>(spec_json).unwrap();
+            spec["genesis"] = serde_json::json!({
+                "stateRootHash": format!("0x{}", hex::encode(state_root))
+            });
+            spec.to_string()
+        };
+
+        let mut client = super::Client::<super::platform::async_std::AsyncStdTcpWebSocket>::new(
+            super::ClientConfig {
+                tasks_spawner: Box::new(|_, task| {
+                    async_std::task::spawn(task);
+                }),
+                system_name: "test".into(),
+                system_version: "0".into(),
+                clock_drift_tolerance: core::time::Duration::from_secs(30),
+                max_requests_per_peer: core::num::NonZeroUsize::new(3).unwrap(),
+            },
+        );
+
+        let mut add_chain = |specification: &str| {
+            client.add_chain(super::AddChainConfig {
+                user_data: (),
+                specification: super::ChainSpecification::Json(specification),
+                genesis_storage: Some(super::GenesisStorage::Binary(&genesis_storage)),
+                fork_id: Default::default(),
+                database_content: "",
+                potential_relay_chains: core::iter::empty(),
+                auto_add_relay_chain: None,
+                disable_json_rpc: true,
+                json_rpc_methods_filter: Default::default(),
+                ethereum_json_rpc: false,
+                block_announce_policy: super::BlockAnnouncePolicy::Immediate,
+                warp_sync_min_distinct_peers: core::num::NonZeroU32::new(1).unwrap(),
+                finality_proofs_window: 0,
+                skip_seal_verification: false,
+                transactions_pool: Default::default(),
+                checkpoint_refresh: None,
+            })
+        };
+
+        assert!(matches!(
+            add_chain(&spec_with_state_root(&[0xaa; 32])),
+            Err(super::AddChainError::GenesisStorageStateRootMismatch)
+        ));
+        assert!(add_chain(&spec_with_state_root(state_root)).is_ok());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn chains_sharing_services_paused_together() {
+        let spec_json = &include_bytes!("../../lib/src/chain_spec/example.json")[..];
+        let chain_spec = chain_spec::ChainSpec::from_json_bytes(spec_json).unwrap();
+        let genesis_storage = chain_spec
+            .genesis_storage()
+            .into_genesis_items()
+            .unwrap()
+            .to_binary();
+        let (genesis_chain_information, _) = chain_spec.as_chain_information().unwrap();
+        let mut spec = serde_json::from_slice::<serde_json::Value>(spec_json).unwrap();
+        spec["genesis"] = serde_json::json!({
+            "stateRootHash": format!(
+                "0x{}",
+                hex::encode(
+                    genesis_chain_information
+                        .as_ref()
+                        .finalized_block_header
+                        .state_root
+                )
+            )
+        });
+        let spec = spec.to_string();
+
+        let mut client = super::Client::<super::platform::async_std::AsyncStdTcpWebSocket>::new(
+            super::ClientConfig {
+                tasks_spawner: Box::new(|_, task| {
+                    async_std::task::spawn(task);
+                }),
+                system_name: "test".into(),
+                system_version: "0".into(),
+                clock_drift_tolerance: core::time::Duration::from_secs(30),
+                max_requests_per_peer: core::num::NonZeroUsize::new(3).unwrap(),
+            },
+        );
+
+        let mut add_chain = || {
+            client
+                .add_chain(super::AddChainConfig {
+                    user_data: (),
+                    specification: super::ChainSpecification::Json(&spec),
+                    genesis_storage: Some(super::GenesisStorage::Binary(&genesis_storage)),
+                    fork_id: Default::default(),
+                    database_content: "",
+                    potential_relay_chains: core::iter::empty(),
+                    auto_add_relay_chain: None,
+                    disable_json_rpc: true,
+                    json_rpc_methods_filter: Default::default(),
+                    ethereum_json_rpc: false,
+                    block_announce_policy: super::BlockAnnouncePolicy::Immediate,
+                    warp_sync_min_distinct_peers: core::num::NonZeroU32::new(1).unwrap(),
+                    finality_proofs_window: 0,
+                    skip_seal_verification: false,
+                    transactions_pool: Default::default(),
+                    checkpoint_refresh: None,
+                })
+                .unwrap()
+                .chain_id
+        };
+
+        let chain1 = add_chain();
+        let chain2 = add_chain();
+        assert_eq!(client.chains_by_key.len(), 1);
+        let is_paused = |client: &super::Client<_>| {
+            client
+                .chains_by_key
+                .values()
+                .next()
+                .unwrap()
+                .pause_switch
+                .is_paused()
+        };
+
+        // The services are only paused once both chains are paused.
+        client.pause_chain(chain1);
+        assert!(!is_paused(&client));
+        client.pause_chain(chain2);
+        assert!(is_paused(&client));
+        client.pause_chain(chain2);
+        assert!(is_paused(&client));
+
+        client.resume_chain(chain1);
+        assert!(!is_paused(&client));
+
+        // Removing the only chain that isn't paused pauses the services.
+        client.remove_chain(chain1);
+        assert!(is_paused(&client));
+        client.resume_chain(chain2);
+        assert!(!is_paused(&client));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn checkpoints_generated_on_demand() {
+        use super::{checkpoints_task, platform::async_std::AsyncStdTcpWebSocket, Checkpoints};
+        use core::{cell::Cell, time::Duration};
+        use futures::{channel::mpsc, future};
+        use std::time::Instant;
+
+        async_std::task::block_on(async {
+            let num_generated = Cell::new(0);
+            let (requests_tx, requests_rx) = mpsc::channel(0);
+            let mut checkpoints = Checkpoints { requests_tx };
+
+            let task = checkpoints_task::<AsyncStdTcpWebSocket, _>(
+                requests_rx,
+                Duration::from_millis(200),
+                || {
+                    let n = num_generated.get();
+                    num_generated.set(n + 1);
+                    future::ready(n.to_string())
+                },
+            );
+
+            let test = async {
+                // Nothing is generated before a checkpoint is requested.
+                async_std::task::sleep(Duration::from_millis(50)).await;
+                assert_eq!(num_generated.get(), 0);
+
+                let start = Instant::now();
+                assert_eq!(checkpoints.next().await.unwrap(), "0");
+                assert_eq!(checkpoints.next().await.unwrap(), "1");
+                assert!(start.elapsed() >= Duration::from_millis(200));
+
+                // No checkpoint is generated ahead of time, even once the period has elapsed.
+                async_std::task::sleep(Duration::from_millis(300)).await;
+                assert_eq!(num_generated.get(), 2);
+                assert_eq!(checkpoints.next().await.unwrap(), "2");
+
+                // Destroying the `Checkpoints` stops the task.
+                drop(checkpoints);
+            };
+
+            futures::join!(task, test);
+        });
     }
 }
@@ -0,0 +1,253 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Filtering of log messages depending on their target.
+//!
+//! The client emits log messages through the [`log`] crate. Each message has a *target* that
+//! indicates which component has emitted it. The targets currently in use are `smoldot`,
+//! `network` and `connections`, plus `sync-service-<chain>`, `runtime-<chain>`,
+//! `json-rpc-<chain>` and `tx-service-<chain>`, where `<chain>` is the name of the chain as
+//! printed in the logs.
+//!
+//! A [`LogFilter`] contains a default maximum log level, plus a list of targets each associated
+//! with its own maximum log level. A target in the filter applies to the messages whose target is
+//! either identical, or starts with the target of the filter followed by `-`. For example,
+//! the target `sync` applies to the sync services of all the chains, while `sync-service-polkadot`
+//! only applies to the sync service of the chain named `polkadot`. When multiple targets of the
+//! filter apply to a message, the longest one is used.
+//!
+//! The filter can be modified at any time. Because the [`log`] crate discards messages whose level
+//! is above [`log::max_level`] before they reach the logger, [`log::set_max_level`] must be called
+//! with the value of [`LogFilter::max_level`] after every modification.
+//!
+//! With the `std` feature enabled, the [`FilteredLogger`] struct wraps around another logger and
+//! takes care of this.
+
+use alloc::{borrow::ToOwned as _, string::String, vec::Vec};
+
+/// See [the module-level documentation](..).
+#[derive(Debug, Clone)]
+pub struct LogFilter {
+    /// Maximum level of the messages whose target doesn't match any entry in
+    /// [`LogFilter::targets`].
+    default_level: log::LevelFilter,
+
+    /// List of targets and their maximum levels. Never contains the same target twice.
+    targets: Vec<(String, log::LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Builds a new filter where all targets use the given level.
+    pub const fn new(default_level: log::LevelFilter) -> Self {
+        LogFilter {
+            default_level,
+            targets: Vec::new(),
+        }
+    }
+
+    /// Returns the maximum level of the messages whose target isn't in the filter.
+    pub fn default_level(&self) -> log::LevelFilter {
+        self.default_level
+    }
+
+    /// Sets the maximum level of the messages whose target isn't in the filter.
+    pub fn set_default_level(&mut self, level: log::LevelFilter) {
+        self.default_level = level;
+    }
+
+    /// Sets the maximum level of the messages of the given target, overwriting any previous
+    /// value for this target.
+    pub fn set_target_level(&mut self, target: &str, level: log::LevelFilter) {
+        if let Some((_, l)) = self.targets.iter_mut().find(|(t, _)| t == target) {
+            *l = level;
+        } else {
+            self.targets.push((target.to_owned(), level));
+        }
+    }
+
+    /// Removes the given target from the filter. Its messages now use the level of a shorter
+    /// target of the filter, or the default level.
+    pub fn remove_target(&mut self, target: &str) {
+        self.targets.retain(|(t, _)| t != target);
+    }
+
+    /// Modifies the filter according to a list of directives separated by `,`.
+    ///
+    /// Each directive is either `<target>=<level>`, which calls [`LogFilter::set_target_level`],
+    /// or `<level>`, which calls [`LogFilter::set_default_level`]. A level is one of `off`,
+    /// `error`, `warn`, `info`, `debug`, `trace`, or a number between `0` (off) and `5` (trace).
+    /// Example: `info,sync=debug,network=trace`.
+    ///
+    /// If an error is returned, the filter is left unmodified.
+    pub fn apply_directives(&mut self, directives: &str) -> Result<(), ParseError> {
+        let mut new_filter = self.clone();
+
+        for directive in directives.split(',').map(|d| d.trim()) {
+            if directive.is_empty() {
+                continue;
+            }
+
+            if let Some((target, level)) = directive.split_once('=') {
+                let target = target.trim();
+                if target.is_empty() {
+                    return Err(ParseError::EmptyTarget);
+                }
+                new_filter.set_target_level(target, parse_level(level.trim())?);
+            } else {
+                new_filter.set_default_level(parse_level(directive)?);
+            }
+        }
+
+        *self = new_filter;
+        Ok(())
+    }
+
+    /// Returns `true` if a message with the given target and level should be printed.
+    pub fn enabled(&self, target: &str, level: log::Level) -> bool {
+        level <= self.level_of(target)
+    }
+
+    /// Returns the maximum level of the messages of the given target.
+    pub fn level_of(&self, target: &str) -> log::LevelFilter {
+        self.targets
+            .iter()
+            .filter(|(t, _)| {
+                matches!(target.strip_prefix(&t[..]), Some(rest) if rest.is_empty() || rest.starts_with('-'))
+            })
+            .max_by_key(|(t, _)| t.len())
+            .map_or(self.default_level, |(_, level)| *level)
+    }
+
+    /// Returns the highest level among all the targets of the filter and the default level.
+    ///
+    /// This is the value to pass to [`log::set_max_level`].
+    pub fn max_level(&self) -> log::LevelFilter {
+        self.targets
+            .iter()
+            .map(|(_, level)| *level)
+            .fold(self.default_level, core::cmp::max)
+    }
+}
+
+/// Error potentially returned by [`LogFilter::apply_directives`].
+#[derive(Debug, derive_more::Display)]
+pub enum ParseError {
+    /// A directive of the form `=<level>` has been found.
+    #[display(fmt = "Empty target in log filter directive")]
+    EmptyTarget,
+    /// A level couldn't be parsed.
+    #[display(fmt = "Invalid log level: {_0:?}")]
+    InvalidLevel(String),
+}
+
+fn parse_level(level: &str) -> Result<log::LevelFilter, ParseError> {
+    Ok(match level {
+        "0" => log::LevelFilter::Off,
+        "1" => log::LevelFilter::Error,
+        "2" => log::LevelFilter::Warn,
+        "3" => log::LevelFilter::Info,
+        "4" => log::LevelFilter::Debug,
+        "5" => log::LevelFilter::Trace,
+        _ => level
+            .parse()
+            .map_err(|_| ParseError::InvalidLevel(level.to_owned()))?,
+    })
+}
+
+/// Implementation of [`log::Log`] that forwards the messages allowed by a [`LogFilter`] to
+/// another logger.
+///
+/// The filter can be modified at any time through a [`LogFilterHandle`].
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub struct FilteredLogger<L> {
+    inner: L,
+    filter: alloc::sync::Arc<parking_lot::RwLock<LogFilter>>,
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl<L: log::Log + 'static> FilteredLogger<L> {
+    /// Builds a new [`FilteredLogger`] that forwards messages to `inner`.
+    ///
+    /// `inner` doesn't need to perform any filtering of its own.
+    pub fn new(inner: L, filter: LogFilter) -> Self {
+        FilteredLogger {
+            inner,
+            filter: alloc::sync::Arc::new(parking_lot::RwLock::new(filter)),
+        }
+    }
+
+    /// Returns a handle that can be used to modify the filter.
+    pub fn handle(&self) -> LogFilterHandle {
+        LogFilterHandle(self.filter.clone())
+    }
+
+    /// Sets this logger as the global logger of the [`log`] crate, and sets the maximum log
+    /// level accordingly.
+    pub fn init(self) -> Result<LogFilterHandle, log::SetLoggerError> {
+        let handle = self.handle();
+        let max_level = self.filter.read().max_level();
+        log::set_logger(alloc::boxed::Box::leak(alloc::boxed::Box::new(self)))?;
+        log::set_max_level(max_level);
+        Ok(handle)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<L: log::Log> log::Log for FilteredLogger<L> {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.filter
+            .read()
+            .enabled(metadata.target(), metadata.level())
+            && self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.filter.read().enabled(record.target(), record.level()) {
+            self.inner.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+/// Makes it possible to modify the filter of a [`FilteredLogger`].
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Clone)]
+pub struct LogFilterHandle(alloc::sync::Arc<parking_lot::RwLock<LogFilter>>);
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl LogFilterHandle {
+    /// Modifies the filter using the given closure, then updates the maximum log level of the
+    /// [`log`] crate to match the new filter.
+    pub fn update<R>(&self, modify: impl FnOnce(&mut LogFilter) -> R) -> R {
+        let mut filter = self.0.write();
+        let outcome = modify(&mut filter);
+        log::set_max_level(filter.max_level());
+        outcome
+    }
+
+    /// Shortcut for calling [`LogFilter::apply_directives`] through [`LogFilterHandle::update`].
+    pub fn apply_directives(&self, directives: &str) -> Result<(), ParseError> {
+        self.update(|filter| filter.apply_directives(directives))
+    }
+}
@@ -0,0 +1,173 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Measurements of the duration of the operations performed by the services of a chain.
+//!
+//! Each chain owns a [`ChainMetrics`], shared between its services, which record the duration
+//! of their operations in [`LatencyHistogram`]s. The API user can obtain a [`MetricsSnapshot`]
+//! through [`crate::Client::metrics_snapshot`].
+//!
+//! In order to not use a lot of memory, the durations aren't stored individually. Instead, each
+//! duration is counted in a bucket. Buckets are distributed logarithmically, with four buckets
+//! per power of two microseconds, meaning that the percentiles that are reported are
+//! overestimated by at most 25%.
+
+use core::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of bits of the durations, in microseconds, that are stored. Durations longer than
+/// this are counted in the last bucket.
+const MAX_BITS: u32 = 40;
+
+/// Number of buckets of a [`LatencyHistogram`]. Four buckets per power of two microseconds.
+const NUM_BUCKETS: usize = 4 * (MAX_BITS as usize - 1);
+
+/// Metrics of all the services of a chain.
+#[derive(Default)]
+pub(crate) struct ChainMetrics {
+    /// Successful blocks requests sent to the peers of the chain.
+    pub(crate) blocks_requests: LatencyHistogram,
+    /// Successful storage proof requests sent to the peers of the chain.
+    pub(crate) storage_proof_requests: LatencyHistogram,
+    /// Successful call proof requests sent to the peers of the chain.
+    pub(crate) call_proof_requests: LatencyHistogram,
+    /// JSON-RPC requests processed by the JSON-RPC services of the chain.
+    pub(crate) json_rpc_requests: LatencyHistogram,
+}
+
+impl ChainMetrics {
+    /// Builds a [`MetricsSnapshot`] of the current state of the metrics.
+    pub(crate) fn snapshot(&self) -> MetricsSnapshot {
+        MetricsSnapshot {
+            blocks_requests: self.blocks_requests.percentiles(),
+            storage_proof_requests: self.storage_proof_requests.percentiles(),
+            call_proof_requests: self.call_proof_requests.percentiles(),
+            json_rpc_requests: self.json_rpc_requests.percentiles(),
+        }
+    }
+}
+
+/// Counts durations in logarithmic buckets. See [the module-level documentation](self).
+pub(crate) struct LatencyHistogram {
+    buckets: [AtomicU64; NUM_BUCKETS],
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        LatencyHistogram {
+            buckets: [(); NUM_BUCKETS].map(|()| AtomicU64::new(0)),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    /// Adds a duration to the histogram.
+    pub(crate) fn record(&self, duration: Duration) {
+        let micros = u64::try_from(duration.as_micros()).unwrap_or(u64::MAX);
+        let micros = micros.min((1 << MAX_BITS) - 1);
+
+        let index = if micros < 4 {
+            micros as usize
+        } else {
+            let msb = 63 - micros.leading_zeros();
+            let sub_bucket = (micros >> (msb - 2)) & 0b11;
+            4 * (msb as usize - 1) + sub_bucket as usize
+        };
+
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Returns the number of durations that have been recorded and their percentiles.
+    pub(crate) fn percentiles(&self) -> LatencyPercentiles {
+        let counts = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect::<alloc::vec::Vec<_>>();
+        let count = counts.iter().sum::<u64>();
+
+        let percentile = |percent: u64| {
+            if count == 0 {
+                return None;
+            }
+
+            let rank = (count * percent).div_ceil(100);
+            let mut cumulated = 0;
+            let index = counts
+                .iter()
+                .position(|bucket| {
+                    cumulated += *bucket;
+                    cumulated >= rank
+                })
+                .unwrap();
+            Some(Duration::from_micros(bucket_upper_bound(index)))
+        };
+
+        LatencyPercentiles {
+            count,
+            p50: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+        }
+    }
+}
+
+/// Returns the exclusive upper bound, in microseconds, of the durations counted in the bucket
+/// with the given index.
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < 4 {
+        index as u64 + 1
+    } else {
+        let shift = index / 4 - 1;
+        let sub_bucket = (index % 4) as u64;
+        (5 + sub_bucket) << shift
+    }
+}
+
+/// See [`crate::Client::metrics_snapshot`].
+#[derive(Debug, Clone)]
+pub struct MetricsSnapshot {
+    /// Duration of the successful blocks requests sent to the peers of the chain, from the
+    /// moment the request is sent until the moment the response is received.
+    pub blocks_requests: LatencyPercentiles,
+    /// Duration of the successful storage proof requests sent to the peers of the chain.
+    pub storage_proof_requests: LatencyPercentiles,
+    /// Duration of the successful call proof requests sent to the peers of the chain.
+    pub call_proof_requests: LatencyPercentiles,
+    /// Duration of the processing of the JSON-RPC requests, from the moment the JSON-RPC
+    /// service starts processing the request until the moment the response has been queued.
+    /// The time the request spends in the queue of pending requests isn't included.
+    pub json_rpc_requests: LatencyPercentiles,
+}
+
+/// Percentiles of a list of durations.
+///
+/// The percentiles are overestimated by at most 25%. They are `None` if no duration has been
+/// recorded.
+#[derive(Debug, Clone)]
+pub struct LatencyPercentiles {
+    /// Number of durations that have been recorded.
+    pub count: u64,
+    /// Median duration.
+    pub p50: Option<Duration>,
+    /// 90th percentile.
+    pub p90: Option<Duration>,
+    /// 99th percentile.
+    pub p99: Option<Duration>,
+}
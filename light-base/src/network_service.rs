@@ -36,7 +36,7 @@
 //! [`NetworkService::new`]. These channels inform the foreground about updates to the network
 //! connectivity.
 
-use crate::platform::Platform;
+use crate::{error::ErrorKind, metrics, platform::Platform, spans};
 
 use alloc::{
     boxed::Box,
@@ -77,6 +77,10 @@ pub struct Config {
 
     /// List of chains to connect to. Chains are later referred to by their index in this list.
     pub chains: Vec<ConfigChain>,
+
+    /// Maximum number of requests that can be in progress towards a single peer. Additional
+    /// requests wait for a previous one to finish, and are started in order of priority.
+    pub max_requests_per_peer: NonZeroUsize,
 }
 
 /// See [`Config::chains`].
@@ -111,6 +115,9 @@ pub struct ConfigChain {
 
     /// If true, the chain uses the GrandPa networking protocol.
     pub has_grandpa_protocol: bool,
+
+    /// Metrics of the chain, where the durations of the requests are recorded.
+    pub metrics: Arc<metrics::ChainMetrics>,
 }
 
 pub struct NetworkService<TPlat: Platform> {
@@ -130,12 +137,23 @@ struct Shared<TPlat: Platform> {
     /// purposes.
     log_chain_names: Vec<String>,
 
+    /// Metrics of the various chains the network service connects to. Indices are chain
+    /// indices.
+    chains_metrics: Vec<Arc<metrics::ChainMetrics>>,
+
     /// Event to notify when the background task needs to be waken up.
     ///
     /// Waking up this event guarantees a full loop of the background task. In other words,
     /// if the event is notified while the background task is already awake, the background task
     /// will do an additional loop.
     wake_up_main_background_task: event_listener::Event,
+
+    /// Sending side of [`SharedGuarded::released_request_slots_rx`]. Used by the [`RequestSlot`]s
+    /// when they are destroyed.
+    released_request_slots_tx: mpsc::UnboundedSender<PeerId>,
+
+    /// See [`Config::max_requests_per_peer`].
+    max_requests_per_peer: usize,
 }
 
 struct SharedGuarded<TPlat: Platform> {
@@ -195,6 +213,106 @@ struct SharedGuarded<TPlat: Platform> {
 
     kademlia_discovery_operations:
         HashMap<service::KademliaOperationId, usize, fnv::FnvBuildHasher>,
+
+    /// For each peer, number of requests currently in progress towards this peer and list of
+    /// requests waiting for one of them to finish. Peers with no request in progress and no
+    /// request waiting aren't in this list.
+    // TODO: use SipHasher
+    request_slots: HashMap<PeerId, PeerRequestSlots<RequestSlot<TPlat>>, fnv::FnvBuildHasher>,
+
+    /// Value to assign to [`QueuedRequest::order`] of the next request that is queued.
+    next_queued_request_order: u64,
+
+    /// Receives the peers whose [`RequestSlot`] has been destroyed.
+    released_request_slots_rx: mpsc::UnboundedReceiver<PeerId>,
+
+    /// For each chain, moments when the requests of each type have last succeeded. Indices
+    /// are chain indices.
+    last_successful_requests: Vec<LastSuccessfulRequests<TPlat::Instant>>,
+}
+
+/// See [`SharedGuarded::request_slots`].
+///
+/// The `TSlot` parameter is the type of the object sent to a queued request when it can start.
+/// It is always [`RequestSlot`], except in unit tests.
+struct PeerRequestSlots<TSlot> {
+    /// Number of [`RequestSlot`]s that are alive.
+    in_progress: usize,
+    /// Requests waiting for a [`RequestSlot`]. Apart from requests whose future has been
+    /// dropped, only ever non-empty if [`PeerRequestSlots::in_progress`] is equal to
+    /// [`Shared::max_requests_per_peer`].
+    queued: Vec<QueuedRequest<TSlot>>,
+}
+
+/// See [`PeerRequestSlots::queued`].
+struct QueuedRequest<TSlot> {
+    priority: RequestPriority,
+    /// Requests of the same priority are started in increasing order of this value.
+    order: u64,
+    /// Sender to use in order to start the request.
+    slot_tx: oneshot::Sender<TSlot>,
+}
+
+impl<TSlot> PeerRequestSlots<TSlot> {
+    /// If fewer than `max_in_progress` requests are in progress, removes from the queue the
+    /// request with the highest priority, counts it as in progress, and returns the sender to
+    /// use in order to start it.
+    ///
+    /// Requests whose receiver has been dropped are discarded.
+    fn next_to_start(&mut self, max_in_progress: usize) -> Option<oneshot::Sender<TSlot>> {
+        // Requests whose future has been dropped are no longer interested in a slot.
+        self.queued.retain(|rq| !rq.slot_tx.is_canceled());
+
+        if self.in_progress >= max_in_progress {
+            return None;
+        }
+
+        let index = self
+            .queued
+            .iter()
+            .enumerate()
+            .max_by_key(|(_, rq)| (rq.priority, cmp::Reverse(rq.order)))
+            .map(|(index, _)| index)?;
+
+        self.in_progress += 1;
+        Some(self.queued.remove(index).slot_tx)
+    }
+}
+
+/// Priority of an outgoing request.
+///
+/// When the maximum number of requests in progress towards a peer has been reached, the requests
+/// waiting for a slot are started in order of priority. This guarantees that, when bandwidth is
+/// constrained, requests that are critical to finalizing blocks aren't stuck behind requests
+/// that download large amounts of data.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum RequestPriority {
+    /// Requests that are likely to download a large amount of data, such as block bodies and
+    /// storage proofs.
+    Bulk,
+    /// Requests that don't fall in the other categories.
+    Normal,
+    /// Requests necessary in order to make progress in the finalization of the chain, such as
+    /// justifications and warp sync fragments.
+    Finality,
+}
+
+/// Authorization to have a request in progress towards a peer. See [`SharedGuarded::request_slots`].
+///
+/// The slot is released when this object is destroyed.
+struct RequestSlot<TPlat: Platform> {
+    shared: Arc<Shared<TPlat>>,
+    peer_id: PeerId,
+}
+
+impl<TPlat: Platform> Drop for RequestSlot<TPlat> {
+    fn drop(&mut self) {
+        let _ = self
+            .shared
+            .released_request_slots_tx
+            .unbounded_send(self.peer_id.clone());
+        self.shared.wake_up_main_background_task.notify(1);
+    }
 }
 
 impl<TPlat: Platform> NetworkService<TPlat> {
@@ -211,6 +329,7 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         let num_chains = config.chains.len();
         let mut chains = Vec::with_capacity(num_chains);
         let mut log_chain_names = Vec::with_capacity(num_chains);
+        let mut chains_metrics = Vec::with_capacity(num_chains);
 
         for chain in config.chains {
             chains.push(service::ChainConfig {
@@ -236,11 +355,13 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             });
 
             log_chain_names.push(chain.log_name);
+            chains_metrics.push(chain.metrics);
         }
 
         let mut abort_handles = Vec::new();
 
         let (messages_from_connections_tx, messages_from_connections_rx) = mpsc::channel(32);
+        let (released_request_slots_tx, released_request_slots_rx) = mpsc::unbounded();
 
         let shared = Arc::new(Shared {
             guarded: Mutex::new(SharedGuarded {
@@ -271,9 +392,23 @@ impl<TPlat: Platform> NetworkService<TPlat> {
                     2,
                     Default::default(),
                 ),
+                request_slots: HashMap::with_capacity_and_hasher(8, Default::default()),
+                next_queued_request_order: 0,
+                released_request_slots_rx,
+                last_successful_requests: (0..num_chains)
+                    .map(|_| LastSuccessfulRequests {
+                        blocks: None,
+                        grandpa_warp_sync: None,
+                        storage_proof: None,
+                        call_proof: None,
+                    })
+                    .collect(),
             }),
             log_chain_names,
+            chains_metrics,
             wake_up_main_background_task: event_listener::Event::new(),
+            released_request_slots_tx,
+            max_requests_per_peer: config.max_requests_per_peer.get(),
         });
 
         // Spawn main task that processes the network service.
@@ -357,6 +492,16 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         config: protocol::BlocksRequestConfig,
         timeout: Duration,
     ) -> Result<Vec<protocol::BlockData>, BlocksRequestError> {
+        let priority = if config.fields.justifications {
+            RequestPriority::Finality
+        } else if config.fields.body {
+            RequestPriority::Bulk
+        } else {
+            RequestPriority::Normal
+        };
+        let _slot = self.request_slot(&target, priority).await;
+        let request_start = TPlat::now();
+
         let rx = {
             let mut guarded = self.shared.guarded.lock().await;
 
@@ -449,6 +594,15 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             }
         }
 
+        if result.is_ok() {
+            let now = TPlat::now();
+            self.shared.chains_metrics[chain_index]
+                .blocks_requests
+                .record(now.clone() - request_start);
+            self.shared.guarded.lock().await.last_successful_requests[chain_index].blocks =
+                Some(now);
+        }
+
         result.map_err(BlocksRequestError::Request)
     }
 
@@ -461,6 +615,8 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         begin_hash: [u8; 32],
         timeout: Duration,
     ) -> Result<service::EncodedGrandpaWarpSyncResponse, GrandpaWarpSyncRequestError> {
+        let _slot = self.request_slot(&target, RequestPriority::Finality).await;
+
         let rx = {
             let mut guarded = self.shared.guarded.lock().await;
 
@@ -516,6 +672,11 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             }
         }
 
+        if result.is_ok() {
+            self.shared.guarded.lock().await.last_successful_requests[chain_index]
+                .grandpa_warp_sync = Some(TPlat::now());
+        }
+
         result.map_err(GrandpaWarpSyncRequestError::Request)
     }
 
@@ -565,6 +726,9 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         config: protocol::StorageProofRequestConfig<impl Iterator<Item = impl AsRef<[u8]> + Clone>>,
         timeout: Duration,
     ) -> Result<service::EncodedMerkleProof, StorageProofRequestError> {
+        let _slot = self.request_slot(&target, RequestPriority::Bulk).await;
+        let request_start = TPlat::now();
+
         let rx = {
             let mut guarded = self.shared.guarded.lock().await;
 
@@ -627,6 +791,15 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             }
         }
 
+        if result.is_ok() {
+            let now = TPlat::now();
+            self.shared.chains_metrics[chain_index]
+                .storage_proof_requests
+                .record(now.clone() - request_start);
+            self.shared.guarded.lock().await.last_successful_requests[chain_index].storage_proof =
+                Some(now);
+        }
+
         result.map_err(StorageProofRequestError::Request)
     }
 
@@ -641,6 +814,9 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         config: protocol::CallProofRequestConfig<'_, impl Iterator<Item = impl AsRef<[u8]>>>,
         timeout: Duration,
     ) -> Result<EncodedMerkleProof, CallProofRequestError> {
+        let _slot = self.request_slot(&target, RequestPriority::Normal).await;
+        let request_start = TPlat::now();
+
         let rx = {
             let mut guarded = self.shared.guarded.lock().await;
 
@@ -702,10 +878,54 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             }
         }
 
+        if result.is_ok() {
+            let now = TPlat::now();
+            self.shared.chains_metrics[chain_index]
+                .call_proof_requests
+                .record(now.clone() - request_start);
+            self.shared.guarded.lock().await.last_successful_requests[chain_index].call_proof =
+                Some(now);
+        }
+
         result.map_err(CallProofRequestError::Request)
     }
 
-    /// Announces transaction to the peers we are connected to.
+    /// Waits until a request of the given priority can be started towards `target`.
+    ///
+    /// The returned [`RequestSlot`] must be kept alive as long as the request is in progress.
+    async fn request_slot(&self, target: &PeerId, priority: RequestPriority) -> RequestSlot<TPlat> {
+        let slot_rx = {
+            let mut guarded = self.shared.guarded.lock().await;
+            let guarded = &mut *guarded;
+
+            let (slot_tx, slot_rx) = oneshot::channel();
+            let order = guarded.next_queued_request_order;
+            guarded.next_queued_request_order += 1;
+            guarded
+                .request_slots
+                .entry(target.clone())
+                .or_insert_with(|| PeerRequestSlots {
+                    in_progress: 0,
+                    queued: Vec::new(),
+                })
+                .queued
+                .push(QueuedRequest {
+                    priority,
+                    order,
+                    slot_tx,
+                });
+
+            // If a slot is available, it is immediately assigned.
+            assign_request_slots(&self.shared, guarded, target);
+            slot_rx
+        };
+
+        // The sender is never dropped as long as the receiver is alive.
+        slot_rx.await.unwrap()
+    }
+
+    /// Announces transaction to at most `max_peers` randomly-chosen peers amongst the ones we
+    /// are connected to.
     ///
     /// Returns a list of peers that we have sent the transaction to. Can return an empty `Vec`
     /// if we didn't send the transaction to any peer.
@@ -718,20 +938,24 @@ impl<TPlat: Platform> NetworkService<TPlat> {
         self: Arc<Self>,
         chain_index: usize,
         transaction: &[u8],
+        max_peers: NonZeroUsize,
     ) -> Vec<PeerId> {
-        let mut sent_peers = Vec::with_capacity(16); // TODO: capacity?
+        let mut sent_peers = Vec::with_capacity(cmp::min(max_peers.get(), 16));
 
         // TODO: keep track of which peer knows about which transaction, and don't send it again
 
         let mut guarded = self.shared.guarded.lock().await;
 
-        // TODO: collecting in a Vec :-/
-        for peer in guarded
-            .network
-            .opened_transactions_substream(chain_index)
-            .cloned()
-            .collect::<Vec<_>>()
-        {
+        let targets = rand::seq::IteratorRandom::choose_multiple(
+            guarded
+                .network
+                .opened_transactions_substream(chain_index)
+                .cloned(),
+            &mut rand::thread_rng(),
+            max_peers.get(),
+        );
+
+        for peer in targets {
             if guarded
                 .network
                 .announce_transaction(&peer, chain_index, transaction)
@@ -822,6 +1046,20 @@ impl<TPlat: Platform> NetworkService<TPlat> {
             .into_iter()
     }
 
+    /// Returns the moments when the requests of each type towards the given chain have last
+    /// succeeded.
+    ///
+    /// # Panic
+    ///
+    /// Panics if `chain_index` is out of range.
+    ///
+    pub async fn last_successful_requests(
+        &self,
+        chain_index: usize,
+    ) -> LastSuccessfulRequests<TPlat::Instant> {
+        self.shared.guarded.lock().await.last_successful_requests[chain_index].clone()
+    }
+
     /// Returns an iterator to the list of [`PeerId`]s that we have an established connection
     /// with.
     pub async fn peers_list(&self) -> impl Iterator<Item = PeerId> {
@@ -845,6 +1083,19 @@ impl<TPlat: Platform> Drop for NetworkService<TPlat> {
     }
 }
 
+/// See [`NetworkService::last_successful_requests`].
+#[derive(Debug, Clone)]
+pub struct LastSuccessfulRequests<TInstant> {
+    /// Moment when a blocks request has last succeeded, or `None` if none ever has.
+    pub blocks: Option<TInstant>,
+    /// Moment when a GrandPa warp sync request has last succeeded, or `None` if none ever has.
+    pub grandpa_warp_sync: Option<TInstant>,
+    /// Moment when a storage proof request has last succeeded, or `None` if none ever has.
+    pub storage_proof: Option<TInstant>,
+    /// Moment when a call proof request has last succeeded, or `None` if none ever has.
+    pub call_proof: Option<TInstant>,
+}
+
 /// Event that can happen on the network service.
 #[derive(Debug, Clone)]
 pub enum Event {
@@ -870,6 +1121,13 @@ pub enum Event {
         chain_index: usize,
         message: service::EncodedGrandpaCommitMessage,
     },
+    /// Received a GrandPa neighbor packet from the network.
+    GrandpaNeighborPacket {
+        peer_id: PeerId,
+        chain_index: usize,
+        /// Height of the latest block the peer considers as finalized.
+        finalized_block_height: u64,
+    },
 }
 
 /// Error returned by [`NetworkService::blocks_request`].
@@ -916,6 +1174,23 @@ pub enum CallProofRequestError {
     Request(service::CallProofRequestError),
 }
 
+impl StorageProofRequestError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StorageProofRequestError::NoConnection
+            | StorageProofRequestError::Request(
+                service::StorageProofRequestError::Request(_)
+                | service::StorageProofRequestError::RemoteCouldntAnswer,
+            ) => ErrorKind::NetworkUnreachable,
+            StorageProofRequestError::RequestTooLarge => ErrorKind::LimitExceeded,
+            StorageProofRequestError::Request(service::StorageProofRequestError::Decode(_)) => {
+                ErrorKind::PeerMisbehavior
+            }
+        }
+    }
+}
+
 impl CallProofRequestError {
     /// Returns `true` if this is caused by networking issues, as opposed to a consensus-related
     /// issue.
@@ -926,6 +1201,18 @@ impl CallProofRequestError {
             CallProofRequestError::NoConnection => true,
         }
     }
+
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            CallProofRequestError::Request(err) if err.is_network_problem() => {
+                ErrorKind::NetworkUnreachable
+            }
+            CallProofRequestError::Request(_) => ErrorKind::PeerMisbehavior,
+            CallProofRequestError::RequestTooLarge => ErrorKind::LimitExceeded,
+            CallProofRequestError::NoConnection => ErrorKind::NetworkUnreachable,
+        }
+    }
 }
 
 /// Error returned by [`NetworkService::send_block_announce`].
@@ -938,6 +1225,32 @@ pub enum QueueNotificationError {
     Queue(peers::QueueNotificationError),
 }
 
+/// Assigns the free request slots of `peer_id` to the queued requests with the highest priority,
+/// then removes `peer_id` from [`SharedGuarded::request_slots`] if it has no request left.
+fn assign_request_slots<TPlat: Platform>(
+    shared: &Arc<Shared<TPlat>>,
+    guarded: &mut SharedGuarded<TPlat>,
+    peer_id: &PeerId,
+) {
+    let slots = match guarded.request_slots.get_mut(peer_id) {
+        Some(s) => s,
+        None => return,
+    };
+
+    while let Some(slot_tx) = slots.next_to_start(shared.max_requests_per_peer) {
+        // If the receiver has been dropped in the meanwhile, the slot is destroyed and thus
+        // immediately released.
+        let _ = slot_tx.send(RequestSlot {
+            shared: shared.clone(),
+            peer_id: peer_id.clone(),
+        });
+    }
+
+    if slots.in_progress == 0 && slots.queued.is_empty() {
+        guarded.request_slots.remove(peer_id);
+    }
+}
+
 async fn background_task<TPlat: Platform>(
     shared: Arc<Shared<TPlat>>,
     mut event_senders: Vec<mpsc::Sender<Event>>,
@@ -961,6 +1274,7 @@ async fn update_round<TPlat: Platform>(
 
     // Inject in the coordinator the messages that the connections have generated.
     loop {
+        let _span = spans::enter::<TPlat>("network-inject-connection-message", "network");
         let (connection_id, message) =
             match guarded.messages_from_connections_rx.next().now_or_never() {
                 Some(Some(v)) => v,
@@ -972,9 +1286,24 @@ async fn update_round<TPlat: Platform>(
             .inject_connection_message(connection_id, message);
     }
 
+    // Release the request slots that have been destroyed, and assign them to the requests that
+    // are waiting.
+    loop {
+        let peer_id = match guarded.released_request_slots_rx.next().now_or_never() {
+            Some(Some(p)) => p,
+            _ => break,
+        };
+
+        if let Some(slots) = guarded.request_slots.get_mut(&peer_id) {
+            slots.in_progress -= 1;
+        }
+        assign_request_slots(shared, &mut guarded, &peer_id);
+    }
+
     // Process the events that the coordinator has generated.
     'events_loop: loop {
         let event = loop {
+            let _span = spans::enter::<TPlat>("network-process-event", "network");
             let inner_event = match guarded.network.next_event(TPlat::now()) {
                 Some(ev) => ev,
                 None => break 'events_loop,
@@ -1243,6 +1572,11 @@ async fn update_round<TPlat: Platform>(
                         state.set_id,
                         state.commit_finalized_height,
                     );
+                    break Event::GrandpaNeighborPacket {
+                        chain_index,
+                        peer_id,
+                        finalized_block_height: state.commit_finalized_height,
+                    };
                 }
                 service::Event::GrandpaCommitMessage {
                     chain_index,
@@ -1407,3 +1741,115 @@ impl<TPlat: Platform> SharedGuarded<TPlat> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{PeerRequestSlots, QueuedRequest, RequestPriority};
+    use alloc::vec::Vec;
+    use futures::channel::oneshot;
+
+    fn queue(
+        slots: &mut PeerRequestSlots<()>,
+        priority: RequestPriority,
+        order: u64,
+    ) -> oneshot::Receiver<()> {
+        let (slot_tx, slot_rx) = oneshot::channel();
+        slots.queued.push(QueuedRequest {
+            priority,
+            order,
+            slot_tx,
+        });
+        slot_rx
+    }
+
+    fn start_all(slots: &mut PeerRequestSlots<()>, max: usize) -> usize {
+        let mut num = 0;
+        while let Some(slot_tx) = slots.next_to_start(max) {
+            slot_tx.send(()).unwrap();
+            num += 1;
+        }
+        num
+    }
+
+    #[test]
+    fn highest_priority_first() {
+        let mut slots = PeerRequestSlots {
+            in_progress: 0,
+            queued: Vec::new(),
+        };
+
+        let mut bulk = queue(&mut slots, RequestPriority::Bulk, 0);
+        let mut normal = queue(&mut slots, RequestPriority::Normal, 1);
+        let mut finality1 = queue(&mut slots, RequestPriority::Finality, 2);
+        let mut finality2 = queue(&mut slots, RequestPriority::Finality, 3);
+
+        assert_eq!(start_all(&mut slots, 2), 2);
+        assert_eq!(slots.in_progress, 2);
+        assert!(matches!(finality1.try_recv(), Ok(Some(()))));
+        assert!(matches!(finality2.try_recv(), Ok(Some(()))));
+        assert!(matches!(normal.try_recv(), Ok(None)));
+        assert!(matches!(bulk.try_recv(), Ok(None)));
+
+        // Releasing a slot starts the request with the next highest priority.
+        slots.in_progress -= 1;
+        assert_eq!(start_all(&mut slots, 2), 1);
+        assert!(matches!(normal.try_recv(), Ok(Some(()))));
+        assert!(matches!(bulk.try_recv(), Ok(None)));
+
+        slots.in_progress -= 1;
+        assert_eq!(start_all(&mut slots, 2), 1);
+        assert!(matches!(bulk.try_recv(), Ok(Some(()))));
+        assert!(slots.queued.is_empty());
+    }
+
+    #[test]
+    fn same_priority_in_order() {
+        let mut slots = PeerRequestSlots {
+            in_progress: 0,
+            queued: Vec::new(),
+        };
+
+        let mut second = queue(&mut slots, RequestPriority::Normal, 8);
+        let mut first = queue(&mut slots, RequestPriority::Normal, 5);
+
+        assert_eq!(start_all(&mut slots, 1), 1);
+        assert!(matches!(first.try_recv(), Ok(Some(()))));
+        assert!(matches!(second.try_recv(), Ok(None)));
+    }
+
+    #[test]
+    fn limit_respected() {
+        let mut slots = PeerRequestSlots {
+            in_progress: 0,
+            queued: Vec::new(),
+        };
+
+        let _receivers = (0..10)
+            .map(|n| queue(&mut slots, RequestPriority::Normal, n))
+            .collect::<Vec<_>>();
+
+        assert_eq!(start_all(&mut slots, 7), 7);
+        assert_eq!(slots.in_progress, 7);
+        assert_eq!(slots.queued.len(), 3);
+        assert_eq!(start_all(&mut slots, 7), 0);
+    }
+
+    #[test]
+    fn canceled_requests_skipped() {
+        let mut slots = PeerRequestSlots {
+            in_progress: 1,
+            queued: Vec::new(),
+        };
+
+        drop(queue(&mut slots, RequestPriority::Finality, 0));
+        let mut normal = queue(&mut slots, RequestPriority::Normal, 1);
+
+        // No slot is available, but the canceled request is nonetheless discarded.
+        assert_eq!(start_all(&mut slots, 1), 0);
+        assert_eq!(slots.queued.len(), 1);
+
+        slots.in_progress = 0;
+        assert_eq!(start_all(&mut slots, 1), 1);
+        assert!(matches!(normal.try_recv(), Ok(Some(()))));
+    }
+}
@@ -18,11 +18,11 @@
 use super::Shared;
 use crate::platform::{Platform, PlatformConnection, PlatformSubstreamDirection, ReadBuffer};
 
-use alloc::{string::ToString as _, sync::Arc, vec, vec::Vec};
+use alloc::{string::ToString as _, sync::Arc, vec::Vec};
 use core::{cmp, iter, pin::Pin};
 use futures::{channel::mpsc, prelude::*};
 use smoldot::{
-    libp2p::{collection::SubstreamFate, read_write::ReadWrite},
+    libp2p::{collection::SubstreamFate, connection::buffer_pool, read_write::ReadWrite},
     network::service,
 };
 
@@ -211,8 +211,10 @@ async fn single_stream_connection_task<TPlat: Platform>(
     // from this slice the data to send. Consequently, the write buffer is held locally. This is
     // suboptimal compared to writing to a write buffer provided by the platform, but it is easier
     // to implement it this way.
+    // The buffer is obtained from a pool shared between all connections, in order to not
+    // allocate a new buffer for every connection.
     // Switched to `None` after the connection closes its writing side.
-    let mut write_buffer = Some(vec![0; 4096]);
+    let mut write_buffer = Some(buffer_pool::Buffer::zeroed(4096));
 
     // The main loop is as follows:
     // - Update the state machine.
@@ -429,7 +431,7 @@ async fn webrtc_multi_stream_connection_task<TPlat: Platform>(
     // to implement it this way.
     // The write buffer is limited to 16kiB, as this is the maximum amount of data a single
     // WebRTC frame can have.
-    let mut write_buffer = vec![0; 16384];
+    let mut write_buffer = buffer_pool::Buffer::zeroed(16384);
 
     loop {
         // Start opening new outbound substreams, if needed.
@@ -0,0 +1,231 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Persistent cache of the light client, stored in an SQLite database on disk.
+//!
+//! Instead of retrieving the database of each chain through the
+//! `chainHead_unstable_finalizedDatabase` JSON-RPC function or [`crate::AddChainSuccess::checkpoints`]
+//! and passing it back through [`crate::AddChainConfig::database_content`], the API user can
+//! open a [`PersistentCache`] and pass it to [`crate::Client::set_persistent_cache`]. The client
+//! then automatically loads the database of each chain when it is added, and periodically
+//! stores it back.
+//!
+//! In addition to the database of each chain, the cache also contains the storage values that
+//! have been downloaded from the network and that are kept in memory, so that they don't need
+//! to be downloaded again after a restart.
+//!
+//! The compiled runtimes aren't stored, as the executor doesn't support exporting the result
+//! of a compilation. The runtime of the finalized block is instead downloaded and compiled
+//! again after a restart, like when no cache is used.
+//!
+//! Entries are indexed by the identifier found in the chain specification of the chain. Since
+//! the database of a chain contains the hash of its genesis block, a database that belongs to a
+//! different chain that happens to have the same identifier is ignored when loaded.
+
+use alloc::{string::String, vec::Vec};
+use parking_lot::Mutex;
+use std::{fs, path::Path};
+
+/// Persistent cache of the light client. See [the module-level documentation](self).
+pub struct PersistentCache {
+    /// The SQLite connection.
+    database: Mutex<sqlite::Connection>,
+}
+
+impl PersistentCache {
+    /// Opens the cache found in the given directory, or creates a new empty cache if the
+    /// directory doesn't contain any.
+    pub fn open(path: &Path) -> Result<Self, PersistentCacheOpenError> {
+        // We put a `/v1/` behind the path in case we change the schema.
+        let path = path.join("v1");
+        // Ignoring errors in `create_dir_all`, in order to avoid making the API of this function
+        // more complex. If `create_dir_all` fails, opening the database will most likely fail
+        // too.
+        let _ = fs::create_dir_all(&path);
+        Self::open_inner(path.join("light-client.sqlite"))
+    }
+
+    /// Creates a new empty cache that is kept in memory. Mostly useful for testing purposes.
+    pub fn open_in_memory() -> Result<Self, PersistentCacheOpenError> {
+        Self::open_inner(":memory:")
+    }
+
+    fn open_inner(path: impl AsRef<Path>) -> Result<Self, PersistentCacheOpenError> {
+        let flags = sqlite::OpenFlags::new()
+            .set_create()
+            .set_read_write()
+            // The connection is put behind a `Mutex`, which makes it safe to open SQLite in
+            // "multi-threaded" mode. See https://www.sqlite.org/threadsafe.html
+            .set_no_mutex();
+
+        let database =
+            sqlite::Connection::open_with_flags(path, flags).map_err(PersistentCacheOpenError)?;
+
+        database
+            .execute(
+                r#"
+-- See https://sqlite.org/pragma.html and https://www.sqlite.org/wal.html
+PRAGMA journal_mode = WAL;
+PRAGMA synchronous = NORMAL;
+PRAGMA locking_mode = EXCLUSIVE;
+PRAGMA encoding = 'UTF-8';
+PRAGMA trusted_schema = false;
+
+/*
+Database of each chain, in the same format as `chainHead_unstable_finalizedDatabase`.
+*/
+CREATE TABLE IF NOT EXISTS chains(
+    chain_spec_id STRING NOT NULL PRIMARY KEY,
+    database_content STRING NOT NULL
+);
+
+/*
+Storage values obtained from the network, indexed by the Merkle value of the root of the storage
+trie they belong to. `value` is NULL if the storage doesn't contain any value for this key.
+*/
+CREATE TABLE IF NOT EXISTS storage_cache(
+    chain_spec_id STRING NOT NULL,
+    state_root BLOB NOT NULL,
+    key BLOB NOT NULL,
+    value BLOB,
+    UNIQUE(chain_spec_id, state_root, key),
+    CHECK(length(state_root) == 32)
+);
+    "#,
+            )
+            .map_err(PersistentCacheOpenError)?;
+
+        Ok(PersistentCache {
+            database: Mutex::new(database),
+        })
+    }
+
+    /// Returns the database of the given chain that has last been stored with
+    /// [`PersistentCache::set_chain_database`], if any.
+    pub(crate) fn chain_database(&self, chain_spec_id: &str) -> Option<String> {
+        let connection = self.database.lock();
+
+        let mut statement = connection
+            .prepare(r#"SELECT database_content FROM chains WHERE chain_spec_id = ?"#)
+            .unwrap()
+            .bind(1, chain_spec_id)
+            .unwrap();
+
+        if !matches!(statement.next().ok()?, sqlite::State::Row) {
+            return None;
+        }
+
+        statement.read::<String>(0).ok()
+    }
+
+    /// Stores the database of the given chain, overwriting the previous one if any.
+    pub(crate) fn set_chain_database(&self, chain_spec_id: &str, database_content: &str) {
+        let connection = self.database.lock();
+
+        let mut statement = connection
+            .prepare(
+                r#"INSERT OR REPLACE INTO chains(chain_spec_id, database_content) VALUES(?, ?)"#,
+            )
+            .unwrap()
+            .bind(1, chain_spec_id)
+            .unwrap()
+            .bind(2, database_content)
+            .unwrap();
+
+        if let Err(err) = statement.next() {
+            log::warn!(target: "smoldot", "Failed to write persistent cache: {}", err);
+        }
+    }
+
+    /// Returns the storage values of the given chain that have last been stored with
+    /// [`PersistentCache::set_storage_cache`].
+    pub(crate) fn storage_cache(&self, chain_spec_id: &str) -> Vec<StorageCacheEntry> {
+        let connection = self.database.lock();
+
+        let mut statement = connection
+            .prepare(r#"SELECT state_root, key, value FROM storage_cache WHERE chain_spec_id = ?"#)
+            .unwrap()
+            .bind(1, chain_spec_id)
+            .unwrap();
+
+        let mut out = Vec::new();
+        while matches!(statement.next(), Ok(sqlite::State::Row)) {
+            let state_root = match statement
+                .read::<Vec<u8>>(0)
+                .ok()
+                .and_then(|r| <[u8; 32]>::try_from(&r[..]).ok())
+            {
+                Some(r) => r,
+                None => continue,
+            };
+            let key = match statement.read::<Vec<u8>>(1) {
+                Ok(k) => k,
+                Err(_) => continue,
+            };
+            let value = statement.read::<Option<Vec<u8>>>(2).unwrap_or(None);
+            out.push((state_root, key, value));
+        }
+        out
+    }
+
+    /// Replaces the storage values of the given chain with the given entries.
+    pub(crate) fn set_storage_cache(
+        &self,
+        chain_spec_id: &str,
+        entries: impl Iterator<Item = StorageCacheEntry>,
+    ) {
+        let connection = self.database.lock();
+
+        let result = (|| -> Result<(), sqlite::Error> {
+            connection.execute("BEGIN TRANSACTION")?;
+
+            connection
+                .prepare("DELETE FROM storage_cache WHERE chain_spec_id = ?")?
+                .bind(1, chain_spec_id)?
+                .next()?;
+
+            let mut statement = connection.prepare(
+                "INSERT OR REPLACE INTO storage_cache(chain_spec_id, state_root, key, value) VALUES(?, ?, ?, ?)",
+            )?;
+            for (state_root, key, value) in entries {
+                statement = statement
+                    .bind(1, chain_spec_id)?
+                    .bind(2, &state_root[..])?
+                    .bind(3, &key[..])?
+                    .bind(4, value.as_deref())?;
+                statement.next()?;
+                statement = statement.reset()?;
+            }
+
+            connection.execute("COMMIT")
+        })();
+
+        if let Err(err) = result {
+            let _ = connection.execute("ROLLBACK");
+            log::warn!(target: "smoldot", "Failed to write persistent cache: {}", err);
+        }
+    }
+}
+
+/// Storage value found in the cache, in the format `(state_root, key, value)`.
+type StorageCacheEntry = ([u8; 32], Vec<u8>, Option<Vec<u8>>);
+
+/// Error potentially returned by [`PersistentCache::open`] or
+/// [`PersistentCache::open_in_memory`].
+#[derive(Debug, derive_more::Display)]
+#[display(fmt = "Failed to open persistent cache: {_0}")]
+pub struct PersistentCacheOpenError(sqlite::Error);
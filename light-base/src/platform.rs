@@ -58,6 +58,7 @@ pub trait Platform: Send + 'static {
         + Unpin
         + Send
         + 'a;
+    type CpuIntensiveFuture<T: Send + 'static>: Future<Output = T> + Unpin + Send + 'static;
 
     /// Returns the time elapsed since [the Unix Epoch](https://en.wikipedia.org/wiki/Unix_time)
     /// (i.e. 00:00:00 UTC on 1 January 1970), ignoring leap seconds.
@@ -83,6 +84,42 @@ pub trait Platform: Send + 'static {
     /// This function can be implemented as no-op on platforms where this is irrelevant.
     fn yield_after_cpu_intensive() -> Self::Yield;
 
+    /// Runs the given CPU-intensive operation, such as compiling a runtime or verifying
+    /// signatures, and returns a future that yields its output.
+    ///
+    /// Platforms that support threads can run the operation in the background on a different
+    /// thread. Other platforms can simply run the operation immediately.
+    fn run_cpu_intensive<T: Send + 'static>(
+        operation: impl FnOnce() -> T + Send + 'static,
+    ) -> Self::CpuIntensiveFuture<T>;
+
+    /// Called when a service enters a CPU-heavy section of code, such as verifying a block or
+    /// compiling a runtime. Only ever called if the `spans` feature is enabled.
+    ///
+    /// Sections of code never contain `await` points. They are always exited on the same thread
+    /// as the one they have been entered on, and are always properly nested. This makes it
+    /// possible to forward them to, for example, the `tracing` crate in order to generate
+    /// flamegraphs.
+    ///
+    /// `name` is a constant string identifying the section of code, such as
+    /// `sync-verify-header`. `target` is the same value as the target of the log messages
+    /// emitted by the service, and typically contains the name of the chain.
+    ///
+    /// The default implementation does nothing.
+    #[cfg(feature = "spans")]
+    fn enter_span(name: &'static str, target: &str) {
+        let _ = (name, target);
+    }
+
+    /// Called when the section of code most recently entered on the current thread with
+    /// [`Platform::enter_span`] is exited.
+    ///
+    /// The default implementation does nothing.
+    #[cfg(feature = "spans")]
+    fn exit_span(name: &'static str) {
+        let _ = name;
+    }
+
     /// Starts a connection attempt to the given multiaddress.
     ///
     /// The multiaddress is passed as a string. If the string can't be parsed, an error should be
@@ -49,6 +49,7 @@ impl Platform for AsyncStdTcpWebSocket {
     type StreamUpdateFuture<'a> = future::BoxFuture<'a, ()>;
     type NextSubstreamFuture<'a> =
         future::Pending<Option<(Self::Stream, PlatformSubstreamDirection)>>;
+    type CpuIntensiveFuture<T: Send + 'static> = async_std::task::JoinHandle<T>;
 
     fn now_from_unix_epoch() -> Duration {
         // Intentionally panic if the time is configured earlier than the UNIX EPOCH.
@@ -73,6 +74,12 @@ impl Platform for AsyncStdTcpWebSocket {
         future::ready(())
     }
 
+    fn run_cpu_intensive<T: Send + 'static>(
+        operation: impl FnOnce() -> T + Send + 'static,
+    ) -> Self::CpuIntensiveFuture<T> {
+        async_std::task::spawn_blocking(operation)
+    }
+
     fn connect(multiaddr: &str) -> Self::ConnectFuture {
         // We simply copy the address to own it. We could be more zero-cost here, but doing so
         // would considerably complicate the implementation.
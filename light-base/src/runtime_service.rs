@@ -54,7 +54,7 @@
 //! large, the subscription is force-killed by the [`RuntimeService`].
 //!
 
-use crate::{platform::Platform, sync_service};
+use crate::{error::ErrorKind, platform::Platform, spans, sync_service};
 
 use alloc::{
     borrow::ToOwned as _,
@@ -101,6 +101,12 @@ pub struct Config<TPlat: Platform> {
 
     /// Header of the genesis block of the chain, in SCALE encoding.
     pub genesis_block_scale_encoded_header: Vec<u8>,
+
+    /// List of block heights and runtime codes found in the chain specification. The runtime code
+    /// is used instead of the `:code` found in the storage of the block at this height and of its
+    /// descendants, as long as the `spec_version` of the on-chain runtime is the same as the one
+    /// of the substitute.
+    pub code_substitutes: BTreeMap<u64, Vec<u8>>,
 }
 
 /// Identifies a runtime currently pinned within a [`RuntimeService`].
@@ -167,8 +173,9 @@ impl<TPlat: Platform> RuntimeService<TPlat> {
         (config.tasks_executor)(log_target.clone(), {
             let sync_service = config.sync_service.clone();
             let guarded = guarded.clone();
+            let code_substitutes = Arc::new(config.code_substitutes);
             let (abortable, abort) = future::abortable(async move {
-                run_background(log_target, sync_service, guarded).await;
+                run_background(log_target, sync_service, guarded, code_substitutes).await;
             });
             background_task_abort = abort;
             abortable.map(|_| ()).boxed()
@@ -325,13 +332,7 @@ impl<TPlat: Platform> RuntimeService<TPlat> {
                 parent_hash,
                 scale_encoded_header: block.user_data.scale_encoded_header.clone(),
                 new_runtime: if !Arc::ptr_eq(&runtime, &parent_runtime) {
-                    Some(
-                        runtime
-                            .runtime
-                            .as_ref()
-                            .map(|rt| rt.runtime_spec.clone())
-                            .map_err(|err| err.clone()),
-                    )
+                    Some(runtime.descriptor())
                 } else {
                     None
                 },
@@ -353,12 +354,7 @@ impl<TPlat: Platform> RuntimeService<TPlat> {
 
         SubscribeAll {
             finalized_block_scale_encoded_header: finalized_block.scale_encoded_header.clone(),
-            finalized_block_runtime: tree
-                .finalized_async_user_data()
-                .runtime
-                .as_ref()
-                .map(|rt| rt.runtime_spec.clone())
-                .map_err(|err| err.clone()),
+            finalized_block_runtime: tree.finalized_async_user_data().descriptor(),
             non_finalized_blocks_ancestry_order,
             new_blocks: Subscription {
                 subscription_id,
@@ -576,7 +572,7 @@ pub struct SubscribeAll<TPlat: Platform> {
     pub finalized_block_scale_encoded_header: Vec<u8>,
 
     /// If the runtime of the finalized block is known, contains the information about it.
-    pub finalized_block_runtime: Result<executor::CoreVersion, RuntimeError>,
+    pub finalized_block_runtime: Result<RuntimeDescriptor, RuntimeError>,
 
     /// List of all known non-finalized blocks at the time of subscription.
     ///
@@ -710,7 +706,53 @@ pub struct BlockNotification {
 
     /// If the runtime of the block is different from its parent, contains the information about
     /// the new runtime.
-    pub new_runtime: Option<Result<executor::CoreVersion, RuntimeError>>,
+    ///
+    /// A runtime is considered as different if either its code or its number of heap pages is
+    /// different. Consequently, the [`RuntimeDescriptor::spec`] of the new runtime might be
+    /// identical to the one of the parent.
+    pub new_runtime: Option<Result<RuntimeDescriptor, RuntimeError>>,
+}
+
+/// Information about a runtime.
+///
+/// See [`RuntimeService::subscribe_all`].
+#[derive(Debug, Clone)]
+pub struct RuntimeDescriptor {
+    /// Specification of the runtime, as reported by the runtime itself.
+    pub spec: executor::CoreVersion,
+
+    /// Number of heap pages available to the runtime, decoded from the `:heappages` storage
+    /// item.
+    pub heap_pages: executor::vm::HeapPages,
+}
+
+/// API supported by a runtime.
+///
+/// Services can use this list in order to find out whether a feature is available without
+/// having to try calling the corresponding runtime function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeApi {
+    /// Name of the API, or `None` if the name isn't one of the well-known API names found in
+    /// [`executor::host::runtime_version::KNOWN_API_NAMES`].
+    pub name: Option<&'static str>,
+
+    /// BLAKE2 hash of length 8 of the name of the API.
+    pub name_hash: [u8; 8],
+
+    /// Version of the API.
+    pub version: u32,
+}
+
+/// Returns the list of APIs found in the given runtime specification.
+fn decode_runtime_apis(spec: &executor::CoreVersion) -> Vec<RuntimeApi> {
+    spec.decode()
+        .apis
+        .map(|api| RuntimeApi {
+            name: api.name(),
+            name_hash: api.name_hash,
+            version: api.version,
+        })
+        .collect()
 }
 
 async fn is_near_head_of_chain_heuristic<TPlat: Platform>(
@@ -768,6 +810,104 @@ impl<TPlat: Platform> RuntimeLock<TPlat> {
         }
     }
 
+    /// Returns the list of APIs supported by the given runtime.
+    pub fn apis(&self) -> Result<Vec<RuntimeApi>, RuntimeError> {
+        match self.runtime.runtime.as_ref() {
+            Ok(r) => Ok(decode_runtime_apis(&r.runtime_spec)),
+            Err(err) => Err(err.clone()),
+        }
+    }
+
+    /// Tries to perform a runtime call without any network request, by reading the storage
+    /// values from the cache of the sync service (see
+    /// [`sync_service::SyncService::storage_cache_get`]).
+    ///
+    /// Returns `None` if the call accesses a storage value that isn't in the cache, or needs to
+    /// enumerate storage keys. [`RuntimeLock::start`] must then be used instead.
+    ///
+    /// The values in the cache have been verified against the state root of the block, and as
+    /// such the outcome of the call is the same as if it was performed with
+    /// [`RuntimeLock::start`].
+    pub async fn try_call_locally(
+        &self,
+        method: &str,
+        parameter_vectored: impl Iterator<Item = impl AsRef<[u8]>> + Clone,
+    ) -> Option<Result<Vec<u8>, LocalCallError>> {
+        let runtime = match self.runtime.runtime.as_ref() {
+            Ok(r) => r,
+            Err(err) => return Some(Err(LocalCallError::InvalidRuntime(err.clone()))),
+        };
+
+        let state_version = runtime
+            .runtime_spec
+            .decode()
+            .state_version
+            .unwrap_or(TrieEntryVersion::V0);
+
+        let mut guarded = runtime.virtual_machine.lock().await;
+
+        let mut call = match executor::runtime_host::run(executor::runtime_host::Config {
+            virtual_machine: guarded.take().unwrap(),
+            function_to_call: method,
+            parameter: parameter_vectored,
+            main_trie_root_calculation_cache: None,
+            offchain_storage_changes: Default::default(),
+            storage_main_trie_changes: Default::default(),
+            storage_child_tries_changes: Default::default(),
+            max_log_level: 0,
+        }) {
+            Ok(call) => call,
+            Err((error, prototype)) => {
+                *guarded = Some(prototype);
+                return Some(Err(LocalCallError::StartError(error)));
+            }
+        };
+
+        loop {
+            match call {
+                executor::runtime_host::RuntimeHostVm::Finished(Ok(success)) => {
+                    let output = success.virtual_machine.value().as_ref().to_vec();
+                    *guarded = Some(success.virtual_machine.into_prototype());
+                    return Some(Ok(output));
+                }
+                executor::runtime_host::RuntimeHostVm::Finished(Err(error)) => {
+                    *guarded = Some(error.prototype);
+                    return Some(Err(LocalCallError::Execution(error.detail)));
+                }
+                executor::runtime_host::RuntimeHostVm::StorageGet(get) => {
+                    let value = self
+                        .sync_service
+                        .storage_cache_get(&self.block_state_root_hash, get.key().as_ref())
+                        .await;
+                    match value {
+                        Some(value) => {
+                            call = get.inject_value(
+                                value.as_ref().map(|v| (iter::once(v), state_version)),
+                            );
+                        }
+                        None => {
+                            *guarded = Some(
+                                executor::runtime_host::RuntimeHostVm::StorageGet(get)
+                                    .into_prototype(),
+                            );
+                            return None;
+                        }
+                    }
+                }
+                executor::runtime_host::RuntimeHostVm::SignatureVerification(sig) => {
+                    call = sig.verify_and_resume();
+                }
+                other @ (executor::runtime_host::RuntimeHostVm::NextKey(_)
+                | executor::runtime_host::RuntimeHostVm::PrefixKeys(_)
+                | executor::runtime_host::RuntimeHostVm::ChildStorageGet(_)
+                | executor::runtime_host::RuntimeHostVm::ChildStorageRoot(_)) => {
+                    *guarded = Some(other.into_prototype());
+                    return None;
+                }
+            }
+        }
+    }
+
     pub async fn start<'b>(
         &'b self,
         method: &'b str,
@@ -800,6 +940,7 @@ impl<TPlat: Platform> RuntimeLock<TPlat> {
             .map_err(RuntimeCallError::CallProof);
 
         let call_proof = call_proof.and_then(|call_proof| {
+            let _span = spans::enter::<TPlat>("runtime-verify-call-proof", "runtime");
             proof_decode::decode_and_verify_proof(proof_decode::Config {
                 proof: call_proof.decode().to_owned(), // TODO: to_owned() inefficiency, need some help from the networking to obtain the owned data
                 trie_root_hash: &self.block_state_root_hash,
@@ -919,6 +1060,25 @@ impl<'a> RuntimeCallLock<'a> {
         Ok(output.into_iter())
     }
 
+    /// Finds in the call proof the first key that is strictly superior to `key` in lexicographic
+    /// order, or `None` if there is no such key.
+    ///
+    /// Returns an error if the proof doesn't contain enough information, meaning that the proof
+    /// is invalid.
+    // TODO: if proof is invalid, we should give the option to fetch another call proof
+    pub fn storage_next_key(&self, key: &[u8]) -> Result<Option<Vec<u8>>, RuntimeCallError> {
+        match &self.call_proof {
+            Ok(call_proof) => {
+                call_proof
+                    .next_key(key)
+                    .map_err(|proof_decode::IncompleteProofError()| {
+                        RuntimeCallError::MissingProofEntry
+                    })
+            }
+            Err(err) => Err(err.clone()),
+        }
+    }
+
     /// End the runtime call.
     ///
     /// This method **must** be called.
@@ -958,6 +1118,20 @@ pub enum RuntimeCallError {
     StorageQuery(sync_service::StorageQueryError),
 }
 
+/// Error that can happen when calling [`RuntimeLock::try_call_locally`].
+#[derive(Debug, Clone, derive_more::Display)]
+pub enum LocalCallError {
+    /// Runtime of the block isn't valid.
+    #[display(fmt = "Runtime of the block isn't valid: {_0}")]
+    InvalidRuntime(RuntimeError),
+    /// Error while starting the execution of the runtime.
+    #[display(fmt = "{_0}")]
+    StartError(executor::host::StartErr),
+    /// Error while executing the runtime.
+    #[display(fmt = "{_0}")]
+    Execution(executor::runtime_host::ErrorDetail),
+}
+
 impl RuntimeCallError {
     /// Returns `true` if this is caused by networking issues, as opposed to a consensus-related
     /// issue.
@@ -970,6 +1144,18 @@ impl RuntimeCallError {
             RuntimeCallError::StorageQuery(err) => err.is_network_problem(),
         }
     }
+
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            RuntimeCallError::InvalidRuntime(err) => err.kind(),
+            RuntimeCallError::StorageRetrieval(_) | RuntimeCallError::MissingProofEntry => {
+                ErrorKind::InvalidProof
+            }
+            RuntimeCallError::CallProof(err) => err.kind(),
+            RuntimeCallError::StorageQuery(err) => err.kind(),
+        }
+    }
 }
 
 /// Error when analyzing the runtime.
@@ -985,6 +1171,15 @@ pub enum RuntimeError {
     Build(executor::host::NewErr),
 }
 
+impl RuntimeError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        // The runtime of a block is part of the storage of the chain, and has thus been
+        // verified. Being unable to use it indicates a problem with the runtime itself.
+        ErrorKind::RuntimeTrap
+    }
+}
+
 struct Guarded<TPlat: Platform> {
     /// Identifier of the next subscription for
     /// [`GuardedInner::FinalizedBlockRuntimeKnown::all_blocks_subscriptions`].
@@ -1099,6 +1294,7 @@ async fn run_background<TPlat: Platform>(
     log_target: String,
     sync_service: Arc<sync_service::SyncService<TPlat>>,
     guarded: Arc<Mutex<Guarded<TPlat>>>,
+    code_substitutes: Arc<BTreeMap<u64, Vec<u8>>>,
 ) {
     loop {
         // The buffer size should be large enough so that, if the CPU is busy, it doesn't
@@ -1125,22 +1321,13 @@ async fn run_background<TPlat: Platform>(
         //
         // Additionally, the situation where a subscription is killed but the finalized block
         // didn't change should be extremely rare anyway.
-        {
-            let mut lock = guarded.lock().await;
-            let lock = &mut *lock; // Solves borrow checking issues.
-
-            // TODO: restore
-            /*lock.best_near_head_of_chain =
-            is_near_head_of_chain_heuristic(&sync_service, &guarded).await;*/
-
-            lock.runtimes = slab::Slab::with_capacity(2); // TODO: hardcoded capacity
-
-            // TODO: DRY below
-            if let Some(finalized_block_runtime) = subscription.finalized_block_runtime {
-                let finalized_block_hash = header::hash_from_scale_encoded_header(
-                    &subscription.finalized_block_scale_encoded_header,
-                );
+        guarded.lock().await.runtimes = slab::Slab::with_capacity(2); // TODO: hardcoded capacity
 
+        // The runtime provided by the sync service is the on-chain one, and a code substitute
+        // might apply to it. This is determined before locking `guarded` again, as it might
+        // require compiling the substitute.
+        let finalized_block_runtime = match subscription.finalized_block_runtime {
+            Some(finalized_block_runtime) => {
                 let storage_code_len = u64::try_from(
                     finalized_block_runtime
                         .storage_code
@@ -1157,10 +1344,47 @@ async fn run_background<TPlat: Platform>(
                             .virtual_machine
                             .runtime_version()
                             .clone(),
+                        heap_pages: finalized_block_runtime.virtual_machine.heap_pages(),
                         virtual_machine: Mutex::new(Some(finalized_block_runtime.virtual_machine)),
                     }),
                 });
 
+                let runtime = match header::decode(
+                    &subscription.finalized_block_scale_encoded_header,
+                    sync_service.block_number_bytes(),
+                ) {
+                    Ok(decoded) => {
+                        apply_code_substitute::<TPlat>(
+                            &log_target,
+                            &code_substitutes,
+                            &guarded,
+                            decoded.number,
+                            runtime,
+                        )
+                        .await
+                    }
+                    Err(_) => runtime,
+                };
+
+                Some((runtime, storage_code_len))
+            }
+            None => None,
+        };
+
+        {
+            let mut lock = guarded.lock().await;
+            let lock = &mut *lock; // Solves borrow checking issues.
+
+            // TODO: restore
+            /*lock.best_near_head_of_chain =
+            is_near_head_of_chain_heuristic(&sync_service, &guarded).await;*/
+
+            // TODO: DRY below
+            if let Some((runtime, storage_code_len)) = finalized_block_runtime {
+                let finalized_block_hash = header::hash_from_scale_encoded_header(
+                    &subscription.finalized_block_scale_encoded_header,
+                );
+
                 match &runtime.runtime {
                     Ok(runtime) => {
                         log::info!(
@@ -1224,6 +1448,7 @@ async fn run_background<TPlat: Platform>(
                             let same_runtime_as_parent = same_runtime_as_parent(
                                 &block.scale_encoded_header,
                                 sync_service.block_number_bytes(),
+                                &code_substitutes,
                             );
                             let _ = tree.input_insert_block(
                                 Block {
@@ -1278,6 +1503,7 @@ async fn run_background<TPlat: Platform>(
                             let same_runtime_as_parent = same_runtime_as_parent(
                                 &block.scale_encoded_header,
                                 sync_service.block_number_bytes(),
+                                &code_substitutes,
                             );
                             let _ = tree.input_insert_block(
                                 Block {
@@ -1306,6 +1532,7 @@ async fn run_background<TPlat: Platform>(
             blocks_stream: subscription.new_blocks.boxed(),
             wake_up_new_necessary_download: future::pending().boxed().fuse(),
             runtime_downloads: stream::FuturesUnordered::new(),
+            code_substitutes: code_substitutes.clone(),
         };
 
         background.start_necessary_downloads().await;
@@ -1337,7 +1564,7 @@ async fn run_background<TPlat: Platform>(
                                 guarded.best_near_head_of_chain = near_head_of_chain;
                             }
 
-                            let same_runtime_as_parent = same_runtime_as_parent(&new_block.scale_encoded_header, sync_service.block_number_bytes());
+                            let same_runtime_as_parent = same_runtime_as_parent(&new_block.scale_encoded_header, sync_service.block_number_bytes(), &code_substitutes);
 
                             match &mut guarded.tree {
                                 GuardedInner::FinalizedBlockRuntimeKnown {
@@ -1420,7 +1647,7 @@ async fn run_background<TPlat: Platform>(
                     }.format_with(", ", |block, fmt| fmt(&HashDisplay(&block.hash))).to_string();
 
                     match download_result {
-                        Ok((storage_code, storage_heap_pages)) => {
+                        Ok((block_number, storage_code, storage_heap_pages)) => {
                             log::debug!(
                                 target: &log_target,
                                 "Worker <= SuccessfulDownload(blocks=[{}])",
@@ -1431,7 +1658,7 @@ async fn run_background<TPlat: Platform>(
                             guarded.best_near_head_of_chain = true;
                             drop(guarded);
 
-                            background.runtime_download_finished(async_op_id, storage_code, storage_heap_pages).await;
+                            background.runtime_download_finished(async_op_id, block_number, storage_code, storage_heap_pages).await;
                         }
                         Err(error) => {
                             log::debug!(
@@ -1501,18 +1728,21 @@ struct Background<TPlat: Platform> {
     blocks_stream: Pin<Box<dyn Stream<Item = sync_service::Notification> + Send>>,
 
     /// List of runtimes currently being downloaded from the network.
-    /// For each item, the download id, storage value of `:code`, and storage value of
-    /// `:heappages`.
+    /// For each item, the download id, height of the block whose storage has been queried,
+    /// storage value of `:code`, and storage value of `:heappages`.
     runtime_downloads: stream::FuturesUnordered<
         future::BoxFuture<
             'static,
             (
                 async_tree::AsyncOpId,
-                Result<(Option<Vec<u8>>, Option<Vec<u8>>), RuntimeDownloadError>,
+                Result<(u64, Option<Vec<u8>>, Option<Vec<u8>>), RuntimeDownloadError>,
             ),
         >,
     >,
 
+    /// See [`Config::code_substitutes`].
+    code_substitutes: Arc<BTreeMap<u64, Vec<u8>>>,
+
     /// Future that wakes up when a new download to start is potentially ready.
     wake_up_new_necessary_download: future::Fuse<future::BoxFuture<'static, ()>>,
 }
@@ -1522,21 +1752,24 @@ impl<TPlat: Platform> Background<TPlat> {
     async fn runtime_download_finished(
         &mut self,
         async_op_id: async_tree::AsyncOpId,
+        block_number: u64,
         storage_code: Option<Vec<u8>>,
         storage_heap_pages: Option<Vec<u8>>,
     ) {
-        let mut guarded = self.guarded.lock().await;
-
         // Try to find an existing runtime identical to the one that has just been downloaded.
         // This loop is `O(n)`, but given that we expect this list to very small (at most 1 or
         // 2 elements), this is not a problem.
-        let existing_runtime = guarded
+        let existing_runtime = self
+            .guarded
+            .lock()
+            .await
             .runtimes
             .iter()
             .filter_map(|(_, rt)| rt.upgrade())
             .find(|rt| rt.runtime_code == storage_code && rt.heap_pages == storage_heap_pages);
 
-        // If no identical runtime was found, try compiling the runtime.
+        // If no identical runtime was found, try compiling the runtime. This is done without
+        // locking `guarded`, in order to not block the API users during the compilation.
         let runtime = if let Some(existing_runtime) = existing_runtime {
             existing_runtime
         } else {
@@ -1568,10 +1801,25 @@ impl<TPlat: Platform> Background<TPlat> {
                 runtime,
             });
 
-            guarded.runtimes.insert(Arc::downgrade(&runtime));
+            self.guarded
+                .lock()
+                .await
+                .runtimes
+                .insert(Arc::downgrade(&runtime));
             runtime
         };
 
+        let runtime = apply_code_substitute::<TPlat>(
+            &self.log_target,
+            &self.code_substitutes,
+            &self.guarded,
+            block_number,
+            runtime,
+        )
+        .await;
+
+        let mut guarded = self.guarded.lock().await;
+
         // Insert the runtime into the tree.
         match &mut guarded.tree {
             GuardedInner::FinalizedBlockRuntimeKnown { tree, .. } => {
@@ -1702,13 +1950,7 @@ impl<TPlat: Platform> Background<TPlat> {
                             is_new_best,
                             scale_encoded_header,
                             new_runtime: if !Arc::ptr_eq(&parent_runtime, &block_runtime) {
-                                Some(
-                                    block_runtime
-                                        .runtime
-                                        .as_ref()
-                                        .map(|rt| rt.runtime_spec.clone())
-                                        .map_err(|err| err.clone()),
-                                )
+                                Some(block_runtime.descriptor())
                             } else {
                                 None
                             },
@@ -1908,7 +2150,7 @@ impl<TPlat: Platform> Background<TPlat> {
                                 Ok(mut c) => {
                                     let heap_pages = c.pop().unwrap();
                                     let code = c.pop().unwrap();
-                                    Ok((code, heap_pages))
+                                    Ok((block_number, code, heap_pages))
                                 }
                                 Err(error) => Err(RuntimeDownloadError::StorageQuery(error)),
                             };
@@ -2000,10 +2242,26 @@ struct Runtime {
     heap_pages: Option<Vec<u8>>,
 }
 
+impl Runtime {
+    /// Returns the information about this runtime reported to the API user.
+    fn descriptor(&self) -> Result<RuntimeDescriptor, RuntimeError> {
+        match &self.runtime {
+            Ok(rt) => Ok(RuntimeDescriptor {
+                spec: rt.runtime_spec.clone(),
+                heap_pages: rt.heap_pages,
+            }),
+            Err(err) => Err(err.clone()),
+        }
+    }
+}
+
 struct SuccessfulRuntime {
     /// Runtime specs extracted from the runtime.
     runtime_spec: executor::CoreVersion,
 
+    /// Number of heap pages of the runtime, decoded from [`Runtime::heap_pages`].
+    heap_pages: executor::vm::HeapPages,
+
     /// Virtual machine itself, to perform additional calls.
     ///
     /// Always `Some`, except for temporary extractions necessary to execute the VM.
@@ -2019,9 +2277,18 @@ impl SuccessfulRuntime {
         TPlat::yield_after_cpu_intensive().await;
 
         // Parameters for `HostVmPrototype::new`.
-        let module = code.as_ref().ok_or(RuntimeError::CodeNotFound)?;
+        let module = code.as_ref().ok_or(RuntimeError::CodeNotFound)?.clone();
         let heap_pages = executor::storage_heap_pages_to_value(heap_pages.as_deref())
             .map_err(RuntimeError::InvalidHeapPages)?;
+
+        TPlat::run_cpu_intensive(move || {
+            let _span = spans::enter::<TPlat>("runtime-compile", "runtime");
+            Self::compile(&module, heap_pages)
+        })
+        .await
+    }
+
+    fn compile(module: &[u8], heap_pages: executor::vm::HeapPages) -> Result<Self, RuntimeError> {
         let exec_hint = executor::vm::ExecHint::CompileAheadOfTime;
 
         // We try once with `allow_unresolved_imports: false`. If this fails due to unresolved
@@ -2038,6 +2305,7 @@ impl SuccessfulRuntime {
             Ok(vm) => {
                 return Ok(SuccessfulRuntime {
                     runtime_spec: vm.runtime_version().clone(),
+                    heap_pages,
                     virtual_machine: Mutex::new(Some(vm)),
                 })
             }
@@ -2064,6 +2332,7 @@ impl SuccessfulRuntime {
 
                         Ok(SuccessfulRuntime {
                             runtime_spec: vm.runtime_version().clone(),
+                            heap_pages,
                             virtual_machine: Mutex::new(Some(vm)),
                         })
                     }
@@ -2083,9 +2352,277 @@ impl SuccessfulRuntime {
 }
 
 /// Returns `true` if the block can be assumed to have the same runtime as its parent.
-fn same_runtime_as_parent(header: &[u8], block_number_bytes: usize) -> bool {
+///
+/// The runtime of a block that modifies `:code` or `:heappages` is indicated by a digest item.
+/// The runtime of a block whose height is found in `code_substitutes` is also considered as
+/// potentially different, as the code substitute might start applying at this block.
+fn same_runtime_as_parent(
+    header: &[u8],
+    block_number_bytes: usize,
+    code_substitutes: &BTreeMap<u64, Vec<u8>>,
+) -> bool {
     match header::decode(header, block_number_bytes) {
-        Ok(h) => !h.digest.has_runtime_environment_updated(),
+        Ok(h) => {
+            !h.digest.has_runtime_environment_updated() && !code_substitutes.contains_key(&h.number)
+        }
         Err(_) => false,
     }
 }
+
+/// Returns the runtime to use for the block at the given height, given the runtime built from
+/// the `:code` and `:heappages` found in its storage.
+///
+/// If a code substitute applies to this block, the substitute is searched in
+/// [`Guarded::runtimes`] or compiled and added to [`Guarded::runtimes`]. `guarded` is only locked
+/// for the duration of these two operations, and not while compiling.
+async fn apply_code_substitute<TPlat: Platform>(
+    log_target: &str,
+    code_substitutes: &BTreeMap<u64, Vec<u8>>,
+    guarded: &Mutex<Guarded<TPlat>>,
+    block_number: u64,
+    on_chain_runtime: Arc<Runtime>,
+) -> Arc<Runtime> {
+    // Only the substitute with the highest height inferior or equal to the block can apply.
+    let (substitute_block_number, substitute_code) =
+        match code_substitutes.range(..=block_number).next_back() {
+            Some(s) => s,
+            None => return on_chain_runtime,
+        };
+
+    // The substitute only applies if the on-chain runtime has the same `spec_version`, which
+    // can't be determined if it fails to compile.
+    let on_chain_spec_version = match &on_chain_runtime.runtime {
+        Ok(rt) => rt.runtime_spec.decode().spec_version,
+        Err(_) => return on_chain_runtime,
+    };
+
+    if on_chain_runtime.runtime_code.as_deref() == Some(&substitute_code[..]) {
+        return on_chain_runtime;
+    }
+
+    let existing_runtime = guarded
+        .lock()
+        .await
+        .runtimes
+        .iter()
+        .filter_map(|(_, rt)| rt.upgrade())
+        .find(|rt| {
+            rt.runtime_code.as_deref() == Some(&substitute_code[..])
+                && rt.heap_pages == on_chain_runtime.heap_pages
+        });
+
+    let substitute = if let Some(existing_runtime) = existing_runtime {
+        existing_runtime
+    } else {
+        let substitute_code = Some(substitute_code.clone());
+        let runtime = SuccessfulRuntime::from_storage::<TPlat>(
+            &substitute_code,
+            &on_chain_runtime.heap_pages,
+        )
+        .await;
+        let runtime = Arc::new(Runtime {
+            heap_pages: on_chain_runtime.heap_pages.clone(),
+            runtime_code: substitute_code,
+            runtime,
+        });
+        guarded
+            .lock()
+            .await
+            .runtimes
+            .insert(Arc::downgrade(&runtime));
+        runtime
+    };
+
+    match &substitute.runtime {
+        Ok(rt) if rt.runtime_spec.decode().spec_version == on_chain_spec_version => {
+            log::debug!(
+                target: log_target,
+                "Using code substitute of block #{} for block #{}. Spec version: {}.",
+                substitute_block_number,
+                block_number,
+                on_chain_spec_version
+            );
+            substitute
+        }
+        _ => on_chain_runtime,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        apply_code_substitute, async_tree, decode_runtime_apis, Guarded, GuardedInner, Runtime,
+        RuntimeApi, RuntimeError, SuccessfulRuntime,
+    };
+    use crate::platform::async_std::AsyncStdTcpWebSocket;
+    use alloc::{collections::BTreeMap, sync::Arc, vec, vec::Vec};
+    use core::time::Duration;
+    use futures::lock::Mutex;
+    use smoldot::executor::host::runtime_version::hash_api_name;
+
+    /// Runtime whose `spec_version` is 9300.
+    const WESTEND_RUNTIME: &[u8] =
+        include_bytes!("../../lib/src/executor/host/westend-runtime-v9300.wasm");
+
+    /// Runtime whose `spec_version` is 9160.
+    const POLKADOT_RUNTIME: &[u8] =
+        include_bytes!("../../lib/src/executor/vm/test-polkadot-runtime-v9160.wasm");
+
+    /// Returns [`WESTEND_RUNTIME`] with an additional empty custom section, in other words a
+    /// different code with the same `spec_version`.
+    fn westend_runtime_substitute() -> Vec<u8> {
+        let mut code = WESTEND_RUNTIME.to_vec();
+        code.extend_from_slice(&[0, 5, 4, b't', b'e', b's', b't']);
+        code
+    }
+
+    fn guarded() -> Mutex<Guarded<AsyncStdTcpWebSocket>> {
+        Mutex::new(Guarded {
+            next_subscription_id: 0,
+            best_near_head_of_chain: false,
+            runtimes: slab::Slab::new(),
+            tree: GuardedInner::FinalizedBlockRuntimeUnknown {
+                when_known: event_listener::Event::new(),
+                tree: async_tree::AsyncTree::new(async_tree::Config {
+                    finalized_async_user_data: None,
+                    retry_after_failed: Duration::from_secs(10),
+                    blocks_capacity: 32,
+                }),
+            },
+        })
+    }
+
+    async fn runtime(code: &[u8]) -> Arc<Runtime> {
+        let code = Some(code.to_vec());
+        Arc::new(Runtime {
+            runtime: SuccessfulRuntime::from_storage::<AsyncStdTcpWebSocket>(&code, &None).await,
+            runtime_code: code,
+            heap_pages: None,
+        })
+    }
+
+    #[test]
+    fn runtime_apis_decoded() {
+        let mut encoded = Vec::new();
+        for name in ["test", "test-impl"] {
+            encoded.push(u8::try_from(name.len()).unwrap() << 2);
+            encoded.extend_from_slice(name.as_bytes());
+        }
+        for version in [1u32, 100, 2] {
+            encoded.extend_from_slice(&version.to_le_bytes());
+        }
+        encoded.push(3 << 2);
+        for (name_hash, version) in [
+            (hash_api_name("Core"), 4u32),
+            (hash_api_name("Metadata"), 1),
+            ([1, 2, 3, 4, 5, 6, 7, 8], 2),
+        ] {
+            encoded.extend_from_slice(&name_hash);
+            encoded.extend_from_slice(&version.to_le_bytes());
+        }
+        encoded.extend_from_slice(&1u32.to_le_bytes()); // Transaction version.
+        encoded.push(1); // State version.
+
+        let spec = smoldot::executor::CoreVersion::from_slice(encoded).unwrap();
+        assert_eq!(
+            decode_runtime_apis(&spec),
+            vec![
+                RuntimeApi {
+                    name: Some("Core"),
+                    name_hash: hash_api_name("Core"),
+                    version: 4,
+                },
+                RuntimeApi {
+                    name: Some("Metadata"),
+                    name_hash: hash_api_name("Metadata"),
+                    version: 1,
+                },
+                RuntimeApi {
+                    name: None,
+                    name_hash: [1, 2, 3, 4, 5, 6, 7, 8],
+                    version: 2,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn code_substitute_not_applicable() {
+        async_std::task::block_on(async {
+            let guarded = guarded();
+            let on_chain = runtime(WESTEND_RUNTIME).await;
+
+            // The substitute starts applying after the block.
+            let substitutes = [(10, westend_runtime_substitute())].into_iter().collect();
+            let runtime =
+                apply_code_substitute("", &substitutes, &guarded, 9, on_chain.clone()).await;
+            assert!(Arc::ptr_eq(&runtime, &on_chain));
+
+            // The substitute is identical to the on-chain code.
+            let substitutes = [(10, WESTEND_RUNTIME.to_vec())].into_iter().collect();
+            let runtime =
+                apply_code_substitute("", &substitutes, &guarded, 12, on_chain.clone()).await;
+            assert!(Arc::ptr_eq(&runtime, &on_chain));
+
+            // The substitute has a different `spec_version`.
+            let substitutes = [(10, POLKADOT_RUNTIME.to_vec())].into_iter().collect();
+            let runtime =
+                apply_code_substitute("", &substitutes, &guarded, 12, on_chain.clone()).await;
+            assert!(Arc::ptr_eq(&runtime, &on_chain));
+
+            // The on-chain runtime has failed to compile.
+            let failed = Arc::new(Runtime {
+                runtime: Err(RuntimeError::CodeNotFound),
+                runtime_code: None,
+                heap_pages: None,
+            });
+            let substitutes = [(10, westend_runtime_substitute())].into_iter().collect();
+            let runtime =
+                apply_code_substitute("", &substitutes, &guarded, 12, failed.clone()).await;
+            assert!(Arc::ptr_eq(&runtime, &failed));
+        });
+    }
+
+    #[test]
+    fn code_substitute_applied_and_reused() {
+        async_std::task::block_on(async {
+            let guarded = guarded();
+            let on_chain = runtime(WESTEND_RUNTIME).await;
+            let substitutes = [(10, westend_runtime_substitute())]
+                .into_iter()
+                .collect::<BTreeMap<_, _>>();
+
+            let first =
+                apply_code_substitute("", &substitutes, &guarded, 10, on_chain.clone()).await;
+            assert!(!Arc::ptr_eq(&first, &on_chain));
+            assert_eq!(first.runtime_code, Some(westend_runtime_substitute()));
+            assert_eq!(guarded.lock().await.runtimes.len(), 1);
+
+            // The substitute also applies to the descendants, and isn't compiled again.
+            let second =
+                apply_code_substitute("", &substitutes, &guarded, 20, on_chain.clone()).await;
+            assert!(Arc::ptr_eq(&first, &second));
+            assert_eq!(guarded.lock().await.runtimes.len(), 1);
+        });
+    }
+
+    #[test]
+    fn code_substitute_compiled_without_lock() {
+        async_std::task::block_on(async {
+            let guarded = guarded();
+            let on_chain = runtime(WESTEND_RUNTIME).await;
+            let substitutes = [(10, westend_runtime_substitute())].into_iter().collect();
+
+            let apply = apply_code_substitute("", &substitutes, &guarded, 10, on_chain.clone());
+            futures::pin_mut!(apply);
+
+            // The compilation happens in the background, during which `guarded` must be
+            // available.
+            assert!(futures::poll!(&mut apply).is_pending());
+            assert!(guarded.try_lock().is_some());
+
+            let runtime = apply.await;
+            assert!(!Arc::ptr_eq(&runtime, &on_chain));
+        });
+    }
+}
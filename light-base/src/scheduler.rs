@@ -0,0 +1,389 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Cooperative scheduling of the background tasks of a chain.
+//!
+//! The [`crate::Client`] spawns its background tasks through the function provided by the API
+//! user in [`crate::ClientConfig::tasks_spawner`]. In some environments, such as browsers, all
+//! these tasks are executed by a single future that polls the tasks in turn. If all the tasks of
+//! all the chains were individually spawned there, a chain with a lot of work to do, for example
+//! because it is syncing, could monopolize this future and make the other chains, and notably
+//! their JSON-RPC services, unresponsive.
+//!
+//! Instead, the tasks of each chain are grouped in a [`TaskGroup`]. A [`TaskGroup`] is itself a
+//! single future, spawned through [`crate::ClientConfig::tasks_spawner`], that executes the
+//! tasks of its chain. After having polled [`POLLS_PER_TURN`] tasks, or after having spent
+//! [`TIME_PER_TURN`] polling them, it yields back to the executor, giving the other chains the
+//! opportunity to run. The time limit guarantees that a chain whose tasks take a long time to
+//! poll, for example because they verify blocks, can't make the other chains wait for
+//! [`POLLS_PER_TURN`] of these polls.
+//!
+//! The [`TaskGroup`] also measures the time spent polling its tasks, making it possible to know
+//! which chain consumes the most CPU. See [`CpuTime`].
+//!
+//! Finally, a [`TaskGroup`] can be paused through its [`PauseSwitch`]. While paused, none of its
+//! tasks are polled, but they are kept in memory and resume where they left off once the
+//! [`TaskGroup`] is unpaused.
+
+use crate::platform::Platform;
+
+use alloc::{string::String, sync::Arc};
+use core::{
+    marker,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::Duration,
+};
+use futures::{
+    channel::mpsc,
+    future::BoxFuture,
+    prelude::*,
+    task::{ArcWake, AtomicWaker},
+};
+
+/// Maximum number of times the tasks of a [`TaskGroup`] are polled before the [`TaskGroup`]
+/// yields back to the executor.
+const POLLS_PER_TURN: usize = 16;
+
+/// Time spent polling the tasks of a [`TaskGroup`] after which the [`TaskGroup`] yields back to
+/// the executor, even if it has polled fewer than [`POLLS_PER_TURN`] tasks.
+///
+/// A single poll of a task is never interrupted, and can exceed this duration.
+const TIME_PER_TURN: Duration = Duration::from_millis(5);
+
+/// Future that executes a group of tasks. Finishes when all the [`TaskGroupSpawner`]s have been
+/// dropped and all the tasks have finished.
+pub(crate) struct TaskGroup<TPlat> {
+    /// Receiving side of [`TaskGroupSpawner`].
+    new_tasks_rx: mpsc::UnboundedReceiver<(String, BoxFuture<'static, ()>)>,
+    /// `true` if [`TaskGroup::new_tasks_rx`] has returned `None`.
+    new_tasks_finished: bool,
+    /// Cloned into the wakers of the tasks. Never closed, as a copy is kept here.
+    ready_tx: mpsc::UnboundedSender<usize>,
+    /// Indices within [`TaskGroup::tasks`] of the tasks that have been woken up, in the order in
+    /// which they should be polled. Can contain indices of tasks that no longer exist.
+    ready_rx: mpsc::UnboundedReceiver<usize>,
+    /// List of tasks that haven't finished yet.
+    tasks: slab::Slab<(BoxFuture<'static, ()>, Arc<TaskWaker>)>,
+    /// Time spent polling the tasks.
+    cpu_time: CpuTime,
+    /// Whether the tasks must currently not be polled.
+    pause_switch: PauseSwitch,
+    /// The platform is only used to obtain the current time.
+    marker: marker::PhantomData<fn() -> TPlat>,
+}
+
+/// Makes it possible to add tasks to a [`TaskGroup`].
+#[derive(Clone)]
+pub(crate) struct TaskGroupSpawner {
+    new_tasks_tx: mpsc::UnboundedSender<(String, BoxFuture<'static, ()>)>,
+}
+
+/// Total time spent polling the tasks of a [`TaskGroup`]. Can be cloned in order to read the
+/// value while the [`TaskGroup`] is running.
+///
+/// Since the tasks are polled on a single thread, this value corresponds to the CPU time
+/// consumed by the tasks, with the exception of the operations that they offload through
+/// [`Platform::run_cpu_intensive`].
+#[derive(Clone, Default)]
+pub(crate) struct CpuTime(Arc<AtomicU64>);
+
+impl CpuTime {
+    /// Returns the total time spent polling the tasks so far.
+    pub(crate) fn get(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+
+    fn add(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.0.fetch_add(nanos, Ordering::Relaxed);
+    }
+}
+
+/// Makes it possible to pause and unpause a [`TaskGroup`]. Can be cloned.
+#[derive(Clone, Default)]
+pub(crate) struct PauseSwitch(Arc<PauseSwitchInner>);
+
+#[derive(Default)]
+struct PauseSwitchInner {
+    paused: AtomicBool,
+    /// Waker of the [`TaskGroup`], registered while it is paused.
+    waker: AtomicWaker,
+}
+
+impl PauseSwitch {
+    /// Returns `true` if the [`TaskGroup`] is currently paused.
+    pub(crate) fn is_paused(&self) -> bool {
+        self.0.paused.load(Ordering::Acquire)
+    }
+
+    /// Pauses or unpauses the [`TaskGroup`]. Does nothing if it is already in the requested state.
+    pub(crate) fn set_paused(&self, paused: bool) {
+        if self.0.paused.swap(paused, Ordering::AcqRel) && !paused {
+            self.0.waker.wake();
+        }
+    }
+}
+
+impl<TPlat: Platform> TaskGroup<TPlat> {
+    /// Creates a new empty [`TaskGroup`] and the [`TaskGroupSpawner`] to use to add tasks to it.
+    pub(crate) fn new() -> (Self, TaskGroupSpawner) {
+        let (new_tasks_tx, new_tasks_rx) = mpsc::unbounded();
+        let (ready_tx, ready_rx) = mpsc::unbounded();
+
+        let group = TaskGroup {
+            new_tasks_rx,
+            new_tasks_finished: false,
+            ready_tx,
+            ready_rx,
+            tasks: slab::Slab::new(),
+            cpu_time: CpuTime::default(),
+            pause_switch: PauseSwitch::default(),
+            marker: marker::PhantomData,
+        };
+
+        (group, TaskGroupSpawner { new_tasks_tx })
+    }
+
+    /// Returns the object that tracks the time spent polling the tasks of this group.
+    pub(crate) fn cpu_time(&self) -> CpuTime {
+        self.cpu_time.clone()
+    }
+
+    /// Returns the object that makes it possible to pause and unpause this group.
+    pub(crate) fn pause_switch(&self) -> PauseSwitch {
+        self.pause_switch.clone()
+    }
+}
+
+impl TaskGroupSpawner {
+    /// Adds a task to the [`TaskGroup`].
+    ///
+    /// The name of the task is accepted for consistency with
+    /// [`crate::ClientConfig::tasks_spawner`], but is currently unused.
+    ///
+    /// Does nothing if the [`TaskGroup`] has been destroyed.
+    pub(crate) fn spawn(&self, name: String, task: BoxFuture<'static, ()>) {
+        let _ = self.new_tasks_tx.unbounded_send((name, task));
+    }
+}
+
+impl<TPlat: Platform> Future for TaskGroup<TPlat> {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<()> {
+        let this = &mut *self;
+
+        // The waker is registered before checking the flag, in order to not miss an unpausing
+        // that happens in between.
+        this.pause_switch.0.waker.register(cx.waker());
+        if this.pause_switch.is_paused() {
+            return Poll::Pending;
+        }
+
+        while !this.new_tasks_finished {
+            match this.new_tasks_rx.poll_next_unpin(cx) {
+                Poll::Ready(Some((_name, task))) => {
+                    let entry = this.tasks.vacant_entry();
+                    let waker = Arc::new(TaskWaker {
+                        index: entry.key(),
+                        queued: AtomicBool::new(false),
+                        ready_tx: this.ready_tx.clone(),
+                    });
+                    ArcWake::wake_by_ref(&waker);
+                    entry.insert((task, waker));
+                }
+                Poll::Ready(None) => this.new_tasks_finished = true,
+                Poll::Pending => break,
+            }
+        }
+
+        let mut turn_time = Duration::new(0, 0);
+        for _ in 0..POLLS_PER_TURN {
+            if turn_time >= TIME_PER_TURN {
+                break;
+            }
+
+            if this.new_tasks_finished && this.tasks.is_empty() {
+                return Poll::Ready(());
+            }
+
+            let index = match this.ready_rx.poll_next_unpin(cx) {
+                Poll::Ready(Some(index)) => index,
+                Poll::Ready(None) => unreachable!(),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            let Some((task, waker)) = this.tasks.get_mut(index) else {
+                // The task has finished after having been woken up.
+                continue;
+            };
+
+            waker.queued.store(false, Ordering::Release);
+            let waker = futures::task::waker_ref(waker);
+            let before_polling = TPlat::now();
+            let poll_outcome = task.poll_unpin(&mut Context::from_waker(&waker));
+            let poll_time = TPlat::now() - before_polling;
+            this.cpu_time.add(poll_time);
+            turn_time += poll_time;
+            if poll_outcome.is_ready() {
+                let _ = this.tasks.remove(index);
+            }
+        }
+
+        // The budget of this turn, in number of polls or in time, has been exhausted. Wake up
+        // immediately in order to continue after the other futures of the executor have had the
+        // opportunity to run.
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+/// Waker of an individual task of a [`TaskGroup`].
+struct TaskWaker {
+    /// Index of the task within [`TaskGroup::tasks`].
+    index: usize,
+    /// `true` if [`TaskWaker::index`] has been sent on [`TaskWaker::ready_tx`] and the task
+    /// hasn't been polled since.
+    queued: AtomicBool,
+    /// See [`TaskGroup::ready_tx`].
+    ready_tx: mpsc::UnboundedSender<usize>,
+}
+
+impl ArcWake for TaskWaker {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.queued.swap(true, Ordering::AcqRel) {
+            let _ = arc_self.ready_tx.unbounded_send(arc_self.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TaskGroup, POLLS_PER_TURN, TIME_PER_TURN};
+    use crate::platform::async_std::AsyncStdTcpWebSocket;
+    use alloc::sync::Arc;
+    use core::{
+        sync::atomic::{AtomicUsize, Ordering},
+        task::{Context, Poll},
+        time::Duration,
+    };
+    use futures::{channel::oneshot, prelude::*, task::LocalSpawnExt as _};
+
+    /// Task that never finishes, and that blocks the thread for `poll_duration` every time it
+    /// is polled then immediately wakes itself up.
+    fn busy_task(
+        poll_duration: Duration,
+        num_polls: Arc<AtomicUsize>,
+    ) -> future::BoxFuture<'static, ()> {
+        future::poll_fn(move |cx: &mut Context| {
+            num_polls.fetch_add(1, Ordering::Relaxed);
+            std::thread::sleep(poll_duration);
+            cx.waker().wake_by_ref();
+            Poll::<()>::Pending
+        })
+        .boxed()
+    }
+
+    #[test]
+    fn yields_after_time_budget() {
+        let (mut group, spawner) = TaskGroup::<AsyncStdTcpWebSocket>::new();
+        let num_polls = Arc::new(AtomicUsize::new(0));
+        spawner.spawn(
+            "busy".into(),
+            busy_task(TIME_PER_TURN / 2, num_polls.clone()),
+        );
+
+        let waker = futures::task::noop_waker();
+        assert!(group
+            .poll_unpin(&mut Context::from_waker(&waker))
+            .is_pending());
+
+        // The turn stops as soon as the time spent polling reaches the budget. Sleeping can
+        // take longer than requested, in which case the budget is reached after a single poll.
+        let num_polls = num_polls.load(Ordering::Relaxed);
+        assert!((1..=2).contains(&num_polls), "{num_polls}");
+    }
+
+    #[test]
+    fn yields_after_polls_budget() {
+        let (mut group, spawner) = TaskGroup::<AsyncStdTcpWebSocket>::new();
+        let num_polls = Arc::new(AtomicUsize::new(0));
+        spawner.spawn(
+            "busy".into(),
+            busy_task(Duration::new(0, 0), num_polls.clone()),
+        );
+
+        let waker = futures::task::noop_waker();
+        assert!(group
+            .poll_unpin(&mut Context::from_waker(&waker))
+            .is_pending());
+        assert_eq!(num_polls.load(Ordering::Relaxed), POLLS_PER_TURN);
+    }
+
+    #[test]
+    fn busy_group_does_not_starve_other_group() {
+        let mut executor = futures::executor::LocalPool::new();
+
+        // The first group contains several tasks that never finish and block the thread for
+        // a long time every time they are polled.
+        let (busy_group, busy_spawner) = TaskGroup::<AsyncStdTcpWebSocket>::new();
+        let busy_polls = Arc::new(AtomicUsize::new(0));
+        for _ in 0..4 {
+            busy_spawner.spawn(
+                "busy".into(),
+                busy_task(TIME_PER_TURN * 2, busy_polls.clone()),
+            );
+        }
+        executor.spawner().spawn_local(busy_group).unwrap();
+
+        // The second group contains a task that needs to be polled several times in order to
+        // finish.
+        let (light_group, light_spawner) = TaskGroup::<AsyncStdTcpWebSocket>::new();
+        let (done_tx, done_rx) = oneshot::channel();
+        light_spawner.spawn(
+            "light".into(),
+            async move {
+                for _ in 0..10 {
+                    let mut yielded = false;
+                    future::poll_fn(|cx| {
+                        if yielded {
+                            return Poll::Ready(());
+                        }
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        Poll::Pending
+                    })
+                    .await;
+                }
+                let _ = done_tx.send(());
+            }
+            .boxed(),
+        );
+        drop(light_spawner);
+        executor.spawner().spawn_local(light_group).unwrap();
+
+        executor.run_until(done_rx).unwrap();
+
+        // Each turn of the busy group consists of a single poll, because each poll exceeds the
+        // time budget. The light group has been polled in between each of these turns, and thus
+        // finished after approximately as many polls of the busy group as it needed polls.
+        let busy_polls = busy_polls.load(Ordering::Relaxed);
+        assert!(busy_polls <= 12, "{busy_polls}");
+    }
+}
@@ -0,0 +1,195 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retrieval of the validators of the current session and of the session keys of the next one.
+//!
+//! This module is a thin layer on top of [`sync_service::SyncService::storage_query`] that
+//! fetches the `Session.Validators` and `Session.QueuedKeys` storage entries in a single query
+//! and decodes them using [`smoldot::json_rpc::session_info`]. The format of the session keys is
+//! determined from the metadata of the runtime of the queried block, which is obtained by calling
+//! `Metadata_metadata`.
+
+use crate::{block_bundle, error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use smoldot::{
+    header,
+    informant::HashDisplay,
+    json_rpc::session_info::{self, QueuedKeys},
+};
+
+/// Successful outcome of [`session_validators`].
+#[derive(Debug, Clone)]
+pub struct SessionValidators {
+    /// Hash of the block whose storage has been queried.
+    pub block_hash: [u8; 32],
+    /// Height of the block whose storage has been queried.
+    pub block_number: u64,
+    /// Account ids of the validators of the current session. Empty if the chain doesn't have a
+    /// `Session` pallet.
+    pub validators: Vec<[u8; 32]>,
+    /// Validators of the next session and their session keys. Empty if the chain doesn't have a
+    /// `Session` pallet.
+    pub queued_keys: Vec<QueuedKeys>,
+}
+
+/// Fetches and decodes the `Session.Validators` and `Session.QueuedKeys` storage entries.
+///
+/// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+/// either the current finalized block or one of its non-finalized descendants.
+pub async fn session_validators<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    block_hash: Option<[u8; 32]>,
+) -> Result<SessionValidators, SessionValidatorsError> {
+    // The subscription pins all the blocks that it reports, which guarantees that the runtime
+    // of the queried block can be accessed. Blocks are automatically unpinned when the
+    // subscription is destroyed.
+    let subscribe_all = runtime_service
+        .subscribe_all("session-validators", 16, NonZeroUsize::new(32).unwrap())
+        .await;
+    let new_blocks = subscribe_all.new_blocks;
+    let block_header = {
+        let finalized_block_header = subscribe_all.finalized_block_scale_encoded_header;
+        let mut non_finalized_blocks = subscribe_all
+            .non_finalized_blocks_ancestry_order
+            .into_iter();
+
+        match block_hash {
+            None => non_finalized_blocks
+                .find(|b| b.is_new_best)
+                .map_or(finalized_block_header, |b| b.scale_encoded_header),
+            Some(hash)
+                if hash == header::hash_from_scale_encoded_header(&finalized_block_header) =>
+            {
+                finalized_block_header
+            }
+            Some(hash) => {
+                non_finalized_blocks
+                    .find(|b| {
+                        header::hash_from_scale_encoded_header(&b.scale_encoded_header) == hash
+                    })
+                    .ok_or(SessionValidatorsError::UnknownBlock(hash))?
+                    .scale_encoded_header
+            }
+        }
+    };
+
+    let block_hash = header::hash_from_scale_encoded_header(&block_header);
+    let (block_number, state_root) =
+        match header::decode(&block_header, sync_service.block_number_bytes()) {
+            Ok(h) => (h.number, *h.state_root),
+            Err(err) => return Err(SessionValidatorsError::InvalidBlockHeader(err)),
+        };
+
+    // Both entries are fetched with a single storage proof.
+    let mut values = sync_service
+        .clone()
+        .storage_query(
+            block_number,
+            &block_hash,
+            &state_root,
+            [
+                session_info::validators_storage_key(),
+                session_info::queued_keys_storage_key(),
+            ]
+            .into_iter(),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .map_err(SessionValidatorsError::StorageQuery)?
+        .into_iter();
+
+    let validators = match values.next().unwrap() {
+        Some(value) => {
+            session_info::decode_validators(&value).map_err(SessionValidatorsError::Decode)?
+        }
+        None => Vec::new(),
+    };
+    let queued_keys = match values.next().unwrap() {
+        Some(value) => {
+            let runtime_lock = runtime_service
+                .pinned_block_runtime_lock(new_blocks.id(), &block_hash)
+                .await
+                .map_err(|_| SessionValidatorsError::SubscriptionReset)?;
+            let metadata = block_bundle::metadata_call(&runtime_lock)
+                .await
+                .map_err(SessionValidatorsError::MetadataCall)?;
+            session_info::QueuedKeysDecoder::from_metadata(&metadata)
+                .map_err(SessionValidatorsError::Metadata)?
+                .decode(&value)
+                .map_err(SessionValidatorsError::Decode)?
+        }
+        None => Vec::new(),
+    };
+    drop(new_blocks);
+
+    Ok(SessionValidators {
+        block_hash,
+        block_number,
+        validators,
+        queued_keys,
+    })
+}
+
+/// Error potentially returned by [`session_validators`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum SessionValidatorsError {
+    /// The block isn't the current finalized block or one of its non-finalized descendants.
+    #[display(fmt = "Unknown block: {}", "HashDisplay(_0)")]
+    UnknownBlock([u8; 32]),
+    /// The header of the block to query is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// The runtime service has reset the subscription used to keep the queried block pinned.
+    #[display(fmt = "Block to query has been unpinned")]
+    SubscriptionReset,
+    /// Error while obtaining the metadata of the runtime of the queried block.
+    #[display(fmt = "{_0}")]
+    MetadataCall(block_bundle::MetadataCallError),
+    /// The format of the session keys can't be determined from the metadata.
+    #[display(fmt = "Unsupported metadata: {_0}")]
+    Metadata(session_info::MetadataError),
+    /// The storage items have been retrieved but couldn't be decoded.
+    #[display(fmt = "{_0}")]
+    Decode(session_info::DecodeError),
+}
+
+impl SessionValidatorsError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            SessionValidatorsError::UnknownBlock(_) => ErrorKind::UnknownBlock,
+            SessionValidatorsError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            SessionValidatorsError::StorageQuery(err) => err.kind(),
+            SessionValidatorsError::SubscriptionReset => ErrorKind::UnknownBlock,
+            SessionValidatorsError::MetadataCall(err) => err.kind(),
+            SessionValidatorsError::Metadata(_) | SessionValidatorsError::Decode(_) => {
+                ErrorKind::Unsupported
+            }
+        }
+    }
+}
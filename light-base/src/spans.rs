@@ -0,0 +1,66 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Instrumentation of the CPU-heavy sections of the services.
+//!
+//! The sync, network, and runtime services enter a *span* at the beginning of the synchronous
+//! sections of code that are likely to consume a lot of CPU (verifying a block, compiling a
+//! runtime, processing a networking event, etc.) and exit it at the end of the section. Spans
+//! never cover an `await` point, meaning that they are always entered and exited on the same
+//! thread, and that the spans entered on a given thread are always properly nested.
+//!
+//! If the `spans` feature is enabled, entering and exiting a span calls
+//! `Platform::enter_span` and `Platform::exit_span`. Platforms can implement these functions
+//! for example in order to forward the spans to the `tracing` crate and generate flamegraphs.
+//!
+//! If the `spans` feature is disabled, entering a span does nothing and is optimized away by
+//! the compiler.
+
+use crate::platform::Platform;
+
+use core::marker::PhantomData;
+
+/// Enters a span. The span is exited when the returned guard is destroyed.
+///
+/// The guard must not be kept alive across an `await` point.
+pub(crate) fn enter<TPlat: Platform>(name: &'static str, target: &str) -> SpanGuard<TPlat> {
+    #[cfg(feature = "spans")]
+    TPlat::enter_span(name, target);
+    #[cfg(not(feature = "spans"))]
+    let _ = target;
+
+    SpanGuard {
+        name,
+        marker: PhantomData,
+    }
+}
+
+/// Returned by [`enter`]. Exits the span when destroyed.
+#[must_use]
+pub(crate) struct SpanGuard<TPlat: Platform> {
+    /// Name of the span, passed back to the platform when the span is exited.
+    #[cfg_attr(not(feature = "spans"), allow(unused))]
+    name: &'static str,
+    marker: PhantomData<fn() -> TPlat>,
+}
+
+#[cfg(feature = "spans")]
+impl<TPlat: Platform> Drop for SpanGuard<TPlat> {
+    fn drop(&mut self) {
+        TPlat::exit_span(self.name);
+    }
+}
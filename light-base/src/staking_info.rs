@@ -0,0 +1,314 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Retrieval of the era-related storage entries of the `Staking` pallet.
+//!
+//! The storage entries are decoded using [`smoldot::json_rpc::staking_info`]. Retrieving the
+//! exposures of a set of validators requires downloading one overview entry per validator,
+//! then one entry per page of nominators. Rather than performing one network request per
+//! storage entry, the keys are grouped into batches that are each retrieved with a single
+//! storage proof.
+
+use crate::{error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{sync::Arc, vec, vec::Vec};
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use futures::future;
+use smoldot::{
+    header,
+    informant::HashDisplay,
+    json_rpc::staking_info::{self, ActiveEra, EraRewardPoints, ExposureOverview, ExposurePage},
+};
+
+/// Maximum number of storage keys requested within a single storage proof. Limits the size of
+/// the proofs that full nodes have to generate and send back.
+const MAX_KEYS_PER_QUERY: usize = 64;
+
+/// Value decoded from the storage of a block.
+#[derive(Debug, Clone)]
+pub struct StakingAtBlock<T> {
+    /// Hash of the block whose storage has been queried.
+    pub block_hash: [u8; 32],
+    /// Height of the block whose storage has been queried.
+    pub block_number: u64,
+    /// Decoded value.
+    pub value: T,
+}
+
+/// Nominators backing a validator during an era.
+#[derive(Debug, Clone)]
+pub struct ValidatorExposure {
+    /// Account id of the validator.
+    pub validator: [u8; 32],
+    /// Summary of the exposure. `None` if the validator wasn't elected during this era.
+    pub overview: Option<ExposureOverview>,
+    /// Pages of nominators. Empty if `overview` is `None`.
+    pub pages: Vec<ExposurePage>,
+}
+
+/// Fetches and decodes the `Staking.ActiveEra` storage entry. Contains `None` if the chain
+/// doesn't have a `Staking` pallet or if no era has started yet.
+///
+/// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+/// either the current finalized block or one of its non-finalized descendants.
+pub async fn active_era<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    block_hash: Option<[u8; 32]>,
+) -> Result<StakingAtBlock<Option<ActiveEra>>, StakingQueryError> {
+    let block = StorageBlock::find(sync_service, runtime_service, block_hash).await?;
+    let value = block
+        .query(sync_service, vec![staking_info::active_era_storage_key()])
+        .await?
+        .remove(0)
+        .map(|value| staking_info::decode_active_era(&value))
+        .transpose()
+        .map_err(StakingQueryError::Decode)?;
+    Ok(block.with_value(value))
+}
+
+/// Fetches and decodes the `Staking.ErasRewardPoints` storage entry of the given era.
+///
+/// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+/// either the current finalized block or one of its non-finalized descendants.
+pub async fn era_reward_points<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    era: u32,
+    block_hash: Option<[u8; 32]>,
+) -> Result<StakingAtBlock<EraRewardPoints>, StakingQueryError> {
+    let block = StorageBlock::find(sync_service, runtime_service, block_hash).await?;
+    let value = match block
+        .query(
+            sync_service,
+            vec![staking_info::eras_reward_points_storage_key(era)],
+        )
+        .await?
+        .remove(0)
+    {
+        Some(value) => {
+            staking_info::decode_era_reward_points(&value).map_err(StakingQueryError::Decode)?
+        }
+        None => EraRewardPoints {
+            total: 0,
+            individual: Vec::new(),
+        },
+    };
+    Ok(block.with_value(value))
+}
+
+/// Fetches and decodes the exposures of the given validators during the given era.
+///
+/// The overviews of all the validators are downloaded first, then all the pages of nominators
+/// they indicate. Each of these two steps is split into as few storage proofs as possible.
+///
+/// If `block_hash` is `None`, the current best block is used. Otherwise, the block must be
+/// either the current finalized block or one of its non-finalized descendants.
+pub async fn exposures<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    era: u32,
+    validators: Vec<[u8; 32]>,
+    block_hash: Option<[u8; 32]>,
+) -> Result<StakingAtBlock<Vec<ValidatorExposure>>, StakingQueryError> {
+    let block = StorageBlock::find(sync_service, runtime_service, block_hash).await?;
+
+    let overviews = block
+        .query(
+            sync_service,
+            validators
+                .iter()
+                .map(|validator| staking_info::eras_stakers_overview_storage_key(era, validator))
+                .collect(),
+        )
+        .await?
+        .into_iter()
+        .map(|value| {
+            value
+                .map(|value| staking_info::decode_exposure_overview(&value))
+                .transpose()
+        })
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(StakingQueryError::Decode)?;
+
+    // Each page is requested in the same order as the validators, which makes it possible to
+    // distribute the results by iterating over the validators again.
+    let mut pages = block
+        .query(
+            sync_service,
+            validators
+                .iter()
+                .zip(overviews.iter())
+                .flat_map(|(validator, overview)| {
+                    let page_count = overview.as_ref().map_or(0, |o| o.page_count);
+                    (0..page_count).map(move |page| {
+                        staking_info::eras_stakers_paged_storage_key(era, validator, page)
+                    })
+                })
+                .collect(),
+        )
+        .await?
+        .into_iter();
+
+    let mut exposures = Vec::with_capacity(validators.len());
+    for (validator, overview) in validators.into_iter().zip(overviews) {
+        let page_count = overview.as_ref().map_or(0, |o| o.page_count);
+        let pages = (&mut pages)
+            .take(usize::try_from(page_count).unwrap())
+            .map(|page| match page {
+                Some(page) => {
+                    staking_info::decode_exposure_page(&page).map_err(StakingQueryError::Decode)
+                }
+                None => Err(StakingQueryError::MissingExposurePage),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        exposures.push(ValidatorExposure {
+            validator,
+            overview,
+            pages,
+        });
+    }
+
+    Ok(block.with_value(exposures))
+}
+
+/// Error potentially returned by the functions of this module.
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum StakingQueryError {
+    /// The block isn't the current finalized block or one of its non-finalized descendants.
+    #[display(fmt = "Unknown block: {}", "HashDisplay(_0)")]
+    UnknownBlock([u8; 32]),
+    /// The header of the block to query is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// The storage items have been retrieved but couldn't be decoded.
+    #[display(fmt = "{_0}")]
+    Decode(staking_info::DecodeError),
+    /// A page of nominators indicated by the exposure overview of a validator is missing from
+    /// the storage.
+    #[display(fmt = "Missing page of nominators")]
+    MissingExposurePage,
+}
+
+impl StakingQueryError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StakingQueryError::UnknownBlock(_) => ErrorKind::UnknownBlock,
+            StakingQueryError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            StakingQueryError::StorageQuery(err) => err.kind(),
+            StakingQueryError::Decode(_) | StakingQueryError::MissingExposurePage => {
+                ErrorKind::Unsupported
+            }
+        }
+    }
+}
+
+/// Block whose storage is queried.
+struct StorageBlock {
+    hash: [u8; 32],
+    number: u64,
+    state_root: [u8; 32],
+}
+
+impl StorageBlock {
+    /// Finds the header of the given block, or of the current best block if `None`.
+    async fn find<TPlat: Platform>(
+        sync_service: &Arc<sync_service::SyncService<TPlat>>,
+        runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+        block_hash: Option<[u8; 32]>,
+    ) -> Result<Self, StakingQueryError> {
+        // The subscription is only used to obtain the header of the desired block, then
+        // immediately destroyed.
+        let block_header = {
+            let subscribe_all = runtime_service
+                .subscribe_all("staking-info", 16, NonZeroUsize::new(32).unwrap())
+                .await;
+
+            let finalized_block_header = subscribe_all.finalized_block_scale_encoded_header;
+            let mut non_finalized_blocks = subscribe_all
+                .non_finalized_blocks_ancestry_order
+                .into_iter();
+
+            match block_hash {
+                None => non_finalized_blocks
+                    .find(|b| b.is_new_best)
+                    .map_or(finalized_block_header, |b| b.scale_encoded_header),
+                Some(hash)
+                    if hash == header::hash_from_scale_encoded_header(&finalized_block_header) =>
+                {
+                    finalized_block_header
+                }
+                Some(hash) => {
+                    non_finalized_blocks
+                        .find(|b| {
+                            header::hash_from_scale_encoded_header(&b.scale_encoded_header) == hash
+                        })
+                        .ok_or(StakingQueryError::UnknownBlock(hash))?
+                        .scale_encoded_header
+                }
+            }
+        };
+
+        match header::decode(&block_header, sync_service.block_number_bytes()) {
+            Ok(h) => Ok(StorageBlock {
+                hash: header::hash_from_scale_encoded_header(&block_header),
+                number: h.number,
+                state_root: *h.state_root,
+            }),
+            Err(err) => Err(StakingQueryError::InvalidBlockHeader(err)),
+        }
+    }
+
+    /// Queries the values of the given keys, in batches of [`MAX_KEYS_PER_QUERY`] keys that are
+    /// requested in parallel. The values are returned in the same order as the keys.
+    async fn query<TPlat: Platform>(
+        &self,
+        sync_service: &Arc<sync_service::SyncService<TPlat>>,
+        keys: Vec<Vec<u8>>,
+    ) -> Result<Vec<Option<Vec<u8>>>, StakingQueryError> {
+        let batches = future::try_join_all(keys.chunks(MAX_KEYS_PER_QUERY).map(|batch| {
+            sync_service.clone().storage_query(
+                self.number,
+                &self.hash,
+                &self.state_root,
+                batch.iter(),
+                4,
+                Duration::from_secs(8),
+                NonZeroU32::new(1).unwrap(),
+            )
+        }))
+        .await
+        .map_err(StakingQueryError::StorageQuery)?;
+
+        Ok(batches.into_iter().flatten().collect())
+    }
+
+    fn with_value<T>(&self, value: T) -> StakingAtBlock<T> {
+        StakingAtBlock {
+            block_hash: self.hash,
+            block_number: self.number,
+            value,
+        }
+    }
+}
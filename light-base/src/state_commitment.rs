@@ -0,0 +1,170 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Export and verification of commitments to the state of a chain.
+//!
+//! [`export`] builds a [`StateCommitment`] from the current finalized block, and [`verify`]
+//! checks a commitment previously exported, possibly by a different instance of the client,
+//! against the header and storage of the block it refers to, downloaded from the network.
+//! See [`smoldot::chain::state_commitment`] for more information.
+
+use crate::{error::ErrorKind, platform::Platform, sync_service};
+
+use alloc::sync::Arc;
+use core::{iter, num::NonZeroU32, time::Duration};
+use smoldot::{
+    chain::state_commitment::{self, StateCommitment},
+    network::protocol,
+};
+
+/// Builds a commitment to the state of the current finalized block of the chain.
+pub async fn export<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+) -> Result<StateCommitment, StateCommitmentError> {
+    let chain_information = sync_service
+        .serialize_chain_information()
+        .await
+        .ok_or(StateCommitmentError::NotSynced)?;
+    let finalized_block_header = &chain_information.as_ref().finalized_block_header;
+    let block_hash = finalized_block_header.hash(sync_service.block_number_bytes());
+
+    let mmr_root = sync_service
+        .clone()
+        .storage_query(
+            finalized_block_header.number,
+            &block_hash,
+            finalized_block_header.state_root,
+            iter::once(state_commitment::mmr_root_storage_key()),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .map_err(StateCommitmentError::StorageQuery)?
+        .pop()
+        .unwrap()
+        .map(|value| state_commitment::decode_mmr_root(&value))
+        .transpose()
+        .map_err(StateCommitmentError::MmrRootDecode)?;
+
+    Ok(StateCommitment::from_chain_information(
+        chain_information.as_ref(),
+        sync_service.block_number_bytes(),
+        mmr_root,
+    ))
+}
+
+/// Verifies that the given commitment matches the header and the storage of the block it refers
+/// to.
+///
+/// The header and storage are downloaded from the peers of the chain. Since full nodes normally
+/// don't keep the storage of old blocks, verifying a commitment to an old block might require
+/// being connected to an archive node.
+///
+/// > **Note**: A successful verification proves that the block exists and that the commitment
+/// >           accurately reflects its state, but doesn't prove that the block is finalized.
+pub async fn verify<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    commitment: &StateCommitment,
+) -> Result<(), StateCommitmentError> {
+    let block = sync_service
+        .clone()
+        .block_query(
+            commitment.block_number,
+            commitment.block_hash,
+            protocol::BlocksRequestFields {
+                header: true,
+                body: false,
+                justifications: false,
+            },
+            3,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .map_err(|()| StateCommitmentError::HeaderQuery)?;
+    let scale_encoded_header = block.header.ok_or(StateCommitmentError::HeaderQuery)?;
+    commitment
+        .check_header(&scale_encoded_header, sync_service.block_number_bytes())
+        .map_err(StateCommitmentError::Mismatch)?;
+
+    // All the entries are fetched with a single storage proof.
+    let mut values = sync_service
+        .clone()
+        .storage_query(
+            commitment.block_number,
+            &commitment.block_hash,
+            &commitment.state_root,
+            [
+                state_commitment::GRANDPA_AUTHORITIES_STORAGE_KEY.to_vec(),
+                state_commitment::grandpa_current_set_id_storage_key(),
+                state_commitment::mmr_root_storage_key(),
+            ]
+            .into_iter(),
+            4,
+            Duration::from_secs(8),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await
+        .map_err(StateCommitmentError::StorageQuery)?
+        .into_iter();
+
+    let grandpa_authorities = values.next().unwrap();
+    let grandpa_current_set_id = values.next().unwrap();
+    let mmr_root = values.next().unwrap();
+
+    commitment
+        .check_storage(
+            grandpa_authorities.as_deref(),
+            grandpa_current_set_id.as_deref(),
+            mmr_root.as_deref(),
+        )
+        .map_err(StateCommitmentError::Mismatch)
+}
+
+/// Error potentially returned by [`export`] or [`verify`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum StateCommitmentError {
+    /// Not enough is known about the chain yet, for example because it is still warp syncing.
+    #[display(fmt = "Chain isn't synchronized yet")]
+    NotSynced,
+    /// Failed to download the header of the block of the commitment.
+    #[display(fmt = "Failed to download block header")]
+    HeaderQuery,
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+    /// The MMR root found in the storage of the finalized block couldn't be decoded.
+    #[display(fmt = "Failed to decode MMR root: {_0}")]
+    MmrRootDecode(state_commitment::DecodeError),
+    /// The commitment doesn't match the block it refers to.
+    #[display(fmt = "Commitment mismatch: {_0}")]
+    Mismatch(state_commitment::CheckError),
+}
+
+impl StateCommitmentError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StateCommitmentError::NotSynced => ErrorKind::UnknownBlock,
+            StateCommitmentError::HeaderQuery => ErrorKind::NetworkUnreachable,
+            StateCommitmentError::StorageQuery(err) => err.kind(),
+            StateCommitmentError::MmrRootDecode(_) => ErrorKind::Unsupported,
+            StateCommitmentError::Mismatch(_) => ErrorKind::InvalidInput,
+        }
+    }
+}
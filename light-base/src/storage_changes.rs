@@ -0,0 +1,121 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Detection of the storage items that have been modified between two blocks.
+//!
+//! This module is a thin layer on top of [`sync_service::SyncService::storage_changes_query`]
+//! that finds the headers of the two blocks amongst the blocks pinned by the runtime service.
+
+use crate::{error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{sync::Arc, vec::Vec};
+use core::{num::NonZeroUsize, time::Duration};
+use smoldot::{header, informant::HashDisplay};
+
+/// Determines which of the given `keys` have a different storage value in `new_block_hash`
+/// compared to `old_block_hash`.
+///
+/// Both blocks must be either the current finalized block or one of its non-finalized
+/// descendants. Returns the keys that have changed, in the same order as in `keys`.
+pub async fn storage_changes<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    old_block_hash: [u8; 32],
+    new_block_hash: [u8; 32],
+    keys: Vec<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>, StorageChangesError> {
+    if old_block_hash == new_block_hash {
+        return Ok(Vec::new());
+    }
+
+    // The subscription is only used to obtain the headers of the desired blocks, then
+    // immediately destroyed.
+    let (old_block, new_block) = {
+        let subscribe_all = runtime_service
+            .subscribe_all("storage-changes", 16, NonZeroUsize::new(32).unwrap())
+            .await;
+
+        let mut headers = Some(subscribe_all.finalized_block_scale_encoded_header)
+            .into_iter()
+            .chain(
+                subscribe_all
+                    .non_finalized_blocks_ancestry_order
+                    .into_iter()
+                    .map(|b| b.scale_encoded_header),
+            )
+            .map(|h| (header::hash_from_scale_encoded_header(&h), h))
+            .collect::<hashbrown::HashMap<_, _, fnv::FnvBuildHasher>>();
+
+        let mut find = |hash: &[u8; 32]| {
+            let header = headers
+                .remove(hash)
+                .ok_or(StorageChangesError::UnknownBlock(*hash))?;
+            let decoded = header::decode(&header, sync_service.block_number_bytes())
+                .map_err(StorageChangesError::InvalidBlockHeader)?;
+            Ok(sync_service::StorageChangesBlock {
+                number: decoded.number,
+                hash: *hash,
+                storage_trie_root: *decoded.state_root,
+            })
+        };
+
+        (find(&old_block_hash)?, find(&new_block_hash)?)
+    };
+
+    let changed = sync_service
+        .clone()
+        .storage_changes_query(
+            old_block,
+            new_block,
+            keys.iter(),
+            4,
+            Duration::from_secs(12),
+        )
+        .await
+        .map_err(StorageChangesError::StorageQuery)?;
+
+    Ok(keys
+        .into_iter()
+        .zip(changed)
+        .filter_map(|(key, changed)| if changed { Some(key) } else { None })
+        .collect())
+}
+
+/// Error potentially returned by [`storage_changes`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum StorageChangesError {
+    /// The block isn't the current finalized block or one of its non-finalized descendants.
+    #[display(fmt = "Unknown block: {}", "HashDisplay(_0)")]
+    UnknownBlock([u8; 32]),
+    /// The header of one of the blocks is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+}
+
+impl StorageChangesError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StorageChangesError::UnknownBlock(_) => ErrorKind::UnknownBlock,
+            StorageChangesError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            StorageChangesError::StorageQuery(err) => err.kind(),
+        }
+    }
+}
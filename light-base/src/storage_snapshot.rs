@@ -0,0 +1,183 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Download of a snapshot of the storage of a finalized block, restricted to a list of prefixes.
+//!
+//! A [`StorageSnapshot`] is pinned to the block that was finalized when it was created. It is
+//! filled page by page using [`next_page`]. Each page either lists the keys of one of the
+//! prefixes, or downloads the values of a certain number of keys, and all the information is
+//! verified against the state trie root of the block using Merkle proofs.
+//!
+//! Because the [`StorageSnapshot`] contains the entire state of the download, the download can
+//! be paused at any point and later resumed, as long as the full nodes are still capable of
+//! generating proofs for this block.
+
+use crate::{error::ErrorKind, platform::Platform, runtime_service, sync_service};
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::{
+    num::{NonZeroU32, NonZeroUsize},
+    time::Duration,
+};
+use smoldot::header;
+
+/// Storage of a finalized block being downloaded.
+///
+/// All the fields are public in order to make it possible to persist a download in progress.
+#[derive(Debug, Clone)]
+pub struct StorageSnapshot {
+    /// Hash of the block whose storage is downloaded.
+    pub block_hash: [u8; 32],
+    /// Height of the block whose storage is downloaded.
+    pub block_number: u64,
+    /// State trie root of the block whose storage is downloaded.
+    pub state_root: [u8; 32],
+    /// Storage entries downloaded so far.
+    pub entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// Prefixes whose keys haven't been listed yet.
+    pub pending_prefixes: Vec<Vec<u8>>,
+    /// Keys that have been listed but whose value hasn't been downloaded yet.
+    pub pending_keys: Vec<Vec<u8>>,
+}
+
+impl StorageSnapshot {
+    /// Returns `true` if all the storage entries of all the prefixes have been downloaded.
+    pub fn is_complete(&self) -> bool {
+        self.pending_prefixes.is_empty() && self.pending_keys.is_empty()
+    }
+}
+
+/// Creates a new empty [`StorageSnapshot`] pinned to the current finalized block.
+pub async fn start<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    runtime_service: &Arc<runtime_service::RuntimeService<TPlat>>,
+    prefixes: Vec<Vec<u8>>,
+) -> Result<StorageSnapshot, StorageSnapshotError> {
+    // The subscription is only used to obtain the header of the finalized block, then
+    // immediately destroyed.
+    let finalized_block_header = runtime_service
+        .subscribe_all("storage-snapshot", 16, NonZeroUsize::new(32).unwrap())
+        .await
+        .finalized_block_scale_encoded_header;
+
+    let decoded = header::decode(&finalized_block_header, sync_service.block_number_bytes())
+        .map_err(StorageSnapshotError::InvalidBlockHeader)?;
+
+    Ok(StorageSnapshot {
+        block_hash: header::hash_from_scale_encoded_header(&finalized_block_header),
+        block_number: decoded.number,
+        state_root: *decoded.state_root,
+        entries: BTreeMap::new(),
+        pending_prefixes: prefixes,
+        pending_keys: Vec::new(),
+    })
+}
+
+/// Performs the next step of the download of the given [`StorageSnapshot`].
+///
+/// If the snapshot has keys whose value hasn't been downloaded yet, downloads the values of up
+/// to `page_size` of these keys using a single storage proof. Otherwise, lists the keys of the
+/// next prefix. Does nothing if the snapshot is already complete.
+///
+/// On error, the snapshot is returned unmodified and the download can be resumed by calling
+/// this function again.
+pub async fn next_page<TPlat: Platform>(
+    sync_service: &Arc<sync_service::SyncService<TPlat>>,
+    mut snapshot: StorageSnapshot,
+    page_size: NonZeroUsize,
+) -> Result<StorageSnapshot, (StorageSnapshot, StorageSnapshotError)> {
+    if snapshot.pending_keys.is_empty() {
+        let prefix = match snapshot.pending_prefixes.last() {
+            Some(p) => p,
+            None => return Ok(snapshot),
+        };
+
+        let result = sync_service
+            .clone()
+            .storage_prefix_keys_query(
+                snapshot.block_number,
+                &snapshot.block_hash,
+                prefix,
+                &snapshot.state_root,
+                4,
+                Duration::from_secs(12),
+                NonZeroU32::new(1).unwrap(),
+            )
+            .await;
+
+        return match result {
+            Ok(mut keys) => {
+                snapshot.pending_prefixes.pop();
+                // Keys are downloaded starting from the end of the list.
+                keys.reverse();
+                snapshot.pending_keys = keys;
+                Ok(snapshot)
+            }
+            Err(err) => Err((snapshot, StorageSnapshotError::StorageQuery(err))),
+        };
+    }
+
+    let page_start = snapshot.pending_keys.len().saturating_sub(page_size.get());
+
+    let result = sync_service
+        .clone()
+        .storage_query(
+            snapshot.block_number,
+            &snapshot.block_hash,
+            &snapshot.state_root,
+            snapshot.pending_keys[page_start..].iter(),
+            4,
+            Duration::from_secs(12),
+            NonZeroU32::new(1).unwrap(),
+        )
+        .await;
+
+    match result {
+        Ok(values) => {
+            for (key, value) in snapshot.pending_keys.drain(page_start..).zip(values) {
+                // The keys have been obtained from a proof of the same block, and thus always
+                // have a value.
+                if let Some(value) = value {
+                    snapshot.entries.insert(key, value);
+                }
+            }
+            Ok(snapshot)
+        }
+        Err(err) => Err((snapshot, StorageSnapshotError::StorageQuery(err))),
+    }
+}
+
+/// Error potentially returned by [`start`] or [`next_page`].
+#[derive(Debug, derive_more::Display, Clone)]
+pub enum StorageSnapshotError {
+    /// The header of the finalized block is invalid.
+    #[display(fmt = "Failed to decode block header: {_0}")]
+    InvalidBlockHeader(header::Error),
+    /// Error while retrieving the storage items from other nodes.
+    #[display(fmt = "{_0}")]
+    StorageQuery(sync_service::StorageQueryError),
+}
+
+impl StorageSnapshotError {
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StorageSnapshotError::InvalidBlockHeader(_) => ErrorKind::PeerMisbehavior,
+            StorageSnapshotError::StorageQuery(err) => err.kind(),
+        }
+    }
+}
@@ -26,20 +26,34 @@
 //!
 //! Use [`SyncService::subscribe_all`] to get notified about updates to the state of the chain.
 
-use crate::{network_service, platform::Platform, runtime_service};
-
-use alloc::{borrow::ToOwned as _, boxed::Box, format, string::String, sync::Arc, vec::Vec};
-use core::{fmt, num::NonZeroU32, time::Duration};
+use crate::{error::ErrorKind, network_service, platform::Platform, runtime_service};
+
+use alloc::{
+    borrow::ToOwned as _,
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    format,
+    string::String,
+    sync::Arc,
+    vec,
+    vec::Vec,
+};
+use core::{
+    fmt,
+    num::{NonZeroU32, NonZeroU64, NonZeroUsize},
+    time::Duration,
+};
 use futures::{
     channel::{mpsc, oneshot},
     lock::Mutex,
     prelude::*,
 };
 use smoldot::{
-    chain,
+    chain::{self, blocks_tree},
     executor::host,
     libp2p::PeerId,
     network::{protocol, service},
+    sync,
     trie::{self, prefix_proof, proof_decode},
 };
 
@@ -60,6 +74,63 @@ pub struct Config<TPlat: Platform> {
     /// Number of bytes of the block number in the networking protocol.
     pub block_number_bytes: usize,
 
+    /// Hashes of blocks that are known to be invalid, as found in the chain specification.
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the blocks of parachains are validated by
+    /// the relay chain.
+    pub bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// List of block heights for which the hash of the canonical block is imposed, as found in
+    /// the chain specification.
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the blocks of parachains are validated by
+    /// the relay chain.
+    pub fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+
+    /// Maximum allowed difference between the local clock and the clock of the authority that
+    /// created a block. See [`smoldot::sync::all::Config::clock_drift_tolerance`].
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the blocks of parachains are validated by
+    /// the relay chain.
+    pub clock_drift_tolerance: Duration,
+
+    /// How to react to block announces received from the network.
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the blocks of parachains are obtained from
+    /// the relay chain.
+    pub block_announce_policy: BlockAnnouncePolicy,
+
+    /// Number of distinct peers that a warp sync proof is requested from, and that the chain must
+    /// be connected to before these proofs are requested.
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as parachains aren't warp synced.
+    pub warp_sync_min_distinct_peers: NonZeroU32,
+
+    /// Number of most recent finalized blocks whose finality proof is retained in memory after
+    /// having been verified, and can be retrieved with [`SyncService::finality_proof`]. A
+    /// finality proof is discarded once the block whose finality it proves is more than
+    /// `finality_proofs_window` blocks below the current finalized block. `0` disables the
+    /// retention.
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the finality of parachains is determined
+    /// by their relay chain.
+    pub finality_proofs_window: u32,
+
+    /// If `true`, the signatures and VRF proofs of the consensus engine found in block headers
+    /// aren't verified. See [`smoldot::sync::all::Config::skip_seal_verification`].
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`, as the blocks of parachains are verified by
+    /// their relay chain.
+    pub skip_seal_verification: bool,
+
+    /// Identities of the bootnodes found in the chain specification.
+    ///
+    /// Used in order to detect when all the peers the sync service is connected to might be
+    /// controlled by the same attacker. See [`SyncService::is_eclipse_suspected_heuristic`].
+    ///
+    /// Ignored if [`Config::parachain`] is `Some`.
+    pub bootnodes: Vec<PeerId>,
+
     /// Closure that spawns background tasks.
     pub tasks_executor: Box<dyn FnMut(String, future::BoxFuture<'static, ()>) + Send>,
 
@@ -94,6 +165,44 @@ pub struct ConfigParachain<TPlat: Platform> {
     pub parachain_id: u32,
 }
 
+/// See [`Config::block_announce_policy`].
+///
+/// Each block announce that is processed might lead to the header of the announced block and
+/// of its ancestors being downloaded and verified. On low-power devices, it might be desirable
+/// to trade some responsiveness to new blocks in exchange for less CPU usage.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum BlockAnnouncePolicy {
+    /// Block announces are processed as soon as they are received.
+    Immediate,
+
+    /// Block announces are buffered and processed all at once when `interval` has elapsed since
+    /// the first buffered announce. Identical announces from the same peer are only processed
+    /// once, and announces from peers that have disconnected in the meanwhile are discarded.
+    Batched {
+        /// Maximum duration between the moment a block announce is received and the moment it
+        /// is processed.
+        interval: Duration,
+    },
+
+    /// Block announces are processed as soon as they are received, unless the height of the
+    /// announced block is more than `max_fork_depth` below the height of the local best block,
+    /// in which case the announce is ignored.
+    ///
+    /// Announces of blocks that are far below the current best block are typically forks that
+    /// will never become canonical, and downloading them is a waste of resources.
+    IgnoreDeepForks {
+        /// Maximum difference between the height of the local best block and the height of an
+        /// announced block for the announce to be processed.
+        max_fork_depth: u64,
+    },
+}
+
+impl Default for BlockAnnouncePolicy {
+    fn default() -> Self {
+        BlockAnnouncePolicy::Immediate
+    }
+}
+
 /// Identifier for a blocks request to be performed.
 #[derive(Debug, Copy, Clone, Ord, PartialOrd, Eq, PartialEq, Hash)]
 pub struct BlocksRequestId(usize);
@@ -108,14 +217,367 @@ pub struct SyncService<TPlat: Platform> {
     network_chain_index: usize,
     /// See [`Config::block_number_bytes`].
     block_number_bytes: usize,
+
+    /// Storage values that have been obtained through [`SyncService::storage_query`], indexed
+    /// by the Merkle value of the root of the storage trie and the key. Since the root of the
+    /// trie is a hash of the whole storage, the values in this cache never become invalid.
+    ///
+    /// Values whose size exceeds [`STORAGE_CACHE_MAX_VALUE_LEN`] aren't included, in order to
+    /// bound the memory usage of the cache.
+    storage_cache: Mutex<StorageCache>,
+
+    /// List of network requests started by [`SyncService::storage_query`] that might still be
+    /// in progress. Used to avoid requesting the same keys multiple times in parallel, and to
+    /// batch the keys of concurrent calls targeting the same block into a single request.
+    storage_requests_in_progress: StorageRequestsInProgress,
+
+    /// List of block requests that might still be in progress. Shared with the background task,
+    /// so that the requests started by [`SyncService::block_query`] and
+    /// [`SyncService::block_query_unknown_number`] and the ones started by the syncing itself
+    /// are deduplicated.
+    block_requests_in_progress: Arc<BlockRequestsInProgress>,
+
+    /// Blocks that have recently been obtained through [`SyncService::block_query`] or
+    /// [`SyncService::block_query_unknown_number`], indexed by hash, together with the fields
+    /// that were requested. Makes it possible for example to prefetch the body of a block before
+    /// it is actually requested by a JSON-RPC client.
+    ///
+    /// Blocks whose size exceeds [`RECENT_BLOCKS_CACHE_MAX_BLOCK_LEN`] aren't included, in
+    /// order to bound the memory usage of the cache.
+    recent_blocks: Mutex<RecentBlocksCache>,
+}
+
+/// See [`SyncService::storage_requests_in_progress`].
+#[derive(Default)]
+struct StorageRequestsInProgress {
+    list: Mutex<Vec<StorageRequestInProgress>>,
+}
+
+/// See [`StorageRequestsInProgress`].
+struct StorageRequestInProgress {
+    /// Merkle value of the root of the storage trie of the block the request concerns.
+    storage_trie_root: [u8; 32],
+    /// Keys of the request. Shared with the request itself, which reads them once
+    /// [`STORAGE_REQUESTS_BATCH_DELAY`] has elapsed.
+    batch: Arc<Mutex<StorageRequestBatch>>,
+    /// Outcome of the request. If `Ok`, contains the values of the keys of
+    /// [`StorageRequestInProgress::batch`]. The [`future::Shared`] is owned by the calls of
+    /// [`SyncService::storage_query`] waiting for this request, and this weak reference becomes
+    /// invalid when they have all been dropped.
+    outcome: future::WeakShared<StorageRequestOutcome>,
+}
+
+/// See [`StorageRequestInProgress::batch`].
+struct StorageRequestBatch {
+    /// Keys being requested.
+    keys: BTreeSet<Vec<u8>>,
+    /// If `false`, the network request hasn't started yet and keys can still be added to
+    /// [`StorageRequestBatch::keys`].
+    sent: bool,
+}
+
+/// See [`StorageRequestInProgress::outcome`].
+type StorageRequestOutcome =
+    future::BoxFuture<'static, Result<Arc<BTreeMap<Vec<u8>, Option<Vec<u8>>>>, StorageQueryError>>;
+
+/// Future returned by the function that [`StorageRequestsInProgress::query`] calls in order to
+/// start a network request, resolving to the values of the requested keys.
+type StorageNetworkRequest =
+    future::BoxFuture<'static, Result<Vec<Option<Vec<u8>>>, StorageQueryError>>;
+
+/// Duration between the moment when a storage request is created and the moment when it is
+/// sent to the network. Concurrent calls to [`SyncService::storage_query`] targeting the same
+/// block during this period add their keys to the same request.
+const STORAGE_REQUESTS_BATCH_DELAY: Duration = Duration::from_millis(5);
+
+impl StorageRequestsInProgress {
+    /// Returns the storage values of `keys` in the storage whose trie root is
+    /// `storage_trie_root`, in the same order as `keys`.
+    ///
+    /// Keys that are part of a request in progress targeting the same trie root are not
+    /// requested again, and the outcome of this other request is used instead. The other keys
+    /// are added to a request targeting the same trie root that hasn't been sent yet. If there
+    /// is no such request, a new one is created, and `new_request` is called with the keys to
+    /// request after [`STORAGE_REQUESTS_BATCH_DELAY`]. Later calls can add their keys to this
+    /// request until then.
+    ///
+    /// `new_request` must return the values of the keys passed to it, in the same order.
+    async fn query<TPlat: Platform>(
+        &self,
+        storage_trie_root: [u8; 32],
+        keys: &[Vec<u8>],
+        new_request: impl FnOnce(Vec<Vec<u8>>) -> StorageNetworkRequest + Send + 'static,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        // List of requests whose outcome to wait for, and for each element of `keys` the index
+        // within `requests` of the request that contains it.
+        let mut requests = Vec::new();
+        let mut sources = Vec::with_capacity(keys.len());
+
+        {
+            let mut list = self.list.lock().await;
+
+            // Clean up the requests that have finished or that nobody is waiting for anymore.
+            list.retain(|rq| matches!(rq.outcome.upgrade(), Some(o) if o.peek().is_none()));
+
+            let mut list_to_requests = vec![None; list.len()];
+            let mut keys_to_request = BTreeSet::new();
+            for key in keys {
+                // Find a request that contains the key, or else add the key to a request that
+                // hasn't been sent yet. The lock on the keys of the request is held between
+                // checking whether it has been sent and adding the key, as the request can be
+                // sent concurrently.
+                let mut found = None;
+                for (rq_index, rq) in list.iter().enumerate() {
+                    if rq.storage_trie_root == storage_trie_root
+                        && rq.batch.lock().await.keys.contains(key)
+                    {
+                        found = Some(rq_index);
+                        break;
+                    }
+                }
+                if found.is_none() {
+                    for (rq_index, rq) in list.iter().enumerate() {
+                        if rq.storage_trie_root != storage_trie_root {
+                            continue;
+                        }
+
+                        let mut batch = rq.batch.lock().await;
+                        if !batch.sent {
+                            batch.keys.insert(key.clone());
+                            found = Some(rq_index);
+                            break;
+                        }
+                    }
+                }
+
+                match found {
+                    Some(rq_index) => {
+                        let request_index = *list_to_requests[rq_index].get_or_insert_with(|| {
+                            requests.push(list[rq_index].outcome.upgrade().unwrap());
+                            requests.len() - 1
+                        });
+                        sources.push(Some(request_index));
+                    }
+                    None => {
+                        keys_to_request.insert(key.clone());
+                        sources.push(None);
+                    }
+                }
+            }
+
+            // Create a new request for the keys that haven't been found.
+            if !keys_to_request.is_empty() {
+                for source in &mut sources {
+                    if source.is_none() {
+                        *source = Some(requests.len());
+                    }
+                }
+
+                let batch = Arc::new(Mutex::new(StorageRequestBatch {
+                    keys: keys_to_request,
+                    sent: false,
+                }));
+
+                let outcome = {
+                    let batch = batch.clone();
+                    async move {
+                        // Give the other calls targeting the same block the opportunity to add
+                        // their keys to this request.
+                        TPlat::sleep(STORAGE_REQUESTS_BATCH_DELAY).await;
+
+                        let keys = {
+                            let mut batch = batch.lock().await;
+                            batch.sent = true;
+                            batch.keys.iter().cloned().collect::<Vec<_>>()
+                        };
+
+                        let values = new_request(keys.clone()).await?;
+                        debug_assert_eq!(keys.len(), values.len());
+                        Ok(Arc::new(keys.into_iter().zip(values).collect()))
+                    }
+                    .boxed()
+                    .shared()
+                };
+
+                list.push(StorageRequestInProgress {
+                    storage_trie_root,
+                    batch,
+                    outcome: outcome.downgrade().unwrap(),
+                });
+                requests.push(outcome);
+            }
+        }
+
+        let outcomes = future::join_all(requests).await;
+
+        let mut values = Vec::with_capacity(keys.len());
+        for (key, source) in keys.iter().zip(sources) {
+            match &outcomes[source.unwrap()] {
+                Ok(outcome) => values.push(outcome.get(key).unwrap().clone()),
+                Err(err) => return Err(err.clone()),
+            }
+        }
+        Ok(values)
+    }
+}
+
+/// See [`SyncService::block_requests_in_progress`].
+#[derive(Default)]
+struct BlockRequestsInProgress {
+    list: Mutex<Vec<BlockRequestInProgress>>,
+}
+
+/// See [`BlockRequestsInProgress`].
+struct BlockRequestInProgress {
+    /// Hash of the block being requested.
+    hash: [u8; 32],
+    /// Peer the request is sent to, or `None` if it can be sent to any peer.
+    target: Option<PeerId>,
+    /// Fields of the block being requested.
+    fields: protocol::BlocksRequestFields,
+    /// Outcome of the request. The [`future::Shared`] is owned by the callers waiting for this
+    /// request, and this weak reference becomes invalid when they have all been dropped.
+    outcome: future::WeakShared<BlockRequestOutcome>,
+}
+
+/// See [`BlockRequestInProgress::outcome`].
+type BlockRequestOutcome = future::BoxFuture<'static, Result<Arc<protocol::BlockData>, ()>>;
+
+impl BlockRequestsInProgress {
+    /// Returns the block whose hash is `hash`, with exactly the fields indicated by `fields`.
+    ///
+    /// `target` must be the peer that `new_request` sends the request to, or `None` if the
+    /// request can be sent to any peer.
+    ///
+    /// If a request for this block that covers at least `fields` is already in progress, waits
+    /// for its outcome instead of starting a new request. If `target` is `Some`, only requests
+    /// towards this same peer can be joined, while if `target` is `None` any request can be
+    /// joined. If there is no such request, or if it fails, calls `new_request` to start a new
+    /// request, which later calls can join.
+    async fn query(
+        &self,
+        hash: [u8; 32],
+        target: Option<&PeerId>,
+        fields: &protocol::BlocksRequestFields,
+        new_request: impl FnOnce() -> future::BoxFuture<'static, Result<protocol::BlockData, ()>>,
+    ) -> Result<protocol::BlockData, ()> {
+        let mut new_request = Some(new_request);
+        let mut may_join = true;
+
+        loop {
+            let (outcome, is_joined) = {
+                let mut list = self.list.lock().await;
+
+                // Clean up the requests that have finished or that nobody is waiting for anymore.
+                list.retain(|rq| matches!(rq.outcome.upgrade(), Some(o) if o.peek().is_none()));
+
+                let existing = if may_join {
+                    list.iter()
+                        .filter(|rq| {
+                            rq.hash == hash
+                                && target.map_or(true, |t| rq.target.as_ref() == Some(t))
+                                && fields_cover(&rq.fields, fields)
+                        })
+                        .find_map(|rq| rq.outcome.upgrade())
+                } else {
+                    None
+                };
+
+                match existing {
+                    Some(outcome) => (outcome, true),
+                    None => {
+                        let outcome = (new_request.take().unwrap())()
+                            .map(|result| result.map(Arc::new))
+                            .boxed()
+                            .shared();
+                        list.push(BlockRequestInProgress {
+                            hash,
+                            target: target.cloned(),
+                            fields: fields.clone(),
+                            outcome: outcome.downgrade().unwrap(),
+                        });
+                        (outcome, false)
+                    }
+                }
+            };
+
+            match outcome.await {
+                Ok(block) => {
+                    // The request that has been joined might contain more fields than desired.
+                    return Ok(block_data_subset(&block, fields));
+                }
+                // A request started by someone else has failed. Try again with a new request,
+                // as the other request might have been performed with a smaller number of
+                // attempts or a shorter timeout.
+                Err(()) if is_joined => may_join = false,
+                Err(()) => return Err(()),
+            }
+        }
+    }
 }
 
+/// Returns `true` if a block request with the fields `available` provides all the fields of
+/// `requested`.
+fn fields_cover(
+    available: &protocol::BlocksRequestFields,
+    requested: &protocol::BlocksRequestFields,
+) -> bool {
+    (available.header || !requested.header)
+        && (available.body || !requested.body)
+        && (available.justifications || !requested.justifications)
+}
+
+/// Returns a copy of `block` that only contains the given `fields`.
+fn block_data_subset(
+    block: &protocol::BlockData,
+    fields: &protocol::BlocksRequestFields,
+) -> protocol::BlockData {
+    protocol::BlockData {
+        hash: block.hash,
+        header: block.header.clone().filter(|_| fields.header),
+        body: block.body.clone().filter(|_| fields.body),
+        justifications: block
+            .justifications
+            .clone()
+            .filter(|_| fields.justifications),
+    }
+}
+
+/// See [`SyncService::recent_blocks`].
+type RecentBlocksCache = lru::LruCache<
+    [u8; 32],
+    (protocol::BlocksRequestFields, Arc<protocol::BlockData>),
+    fnv::FnvBuildHasher,
+>;
+
+/// Maximum number of entries in [`SyncService::recent_blocks`].
+const RECENT_BLOCKS_CACHE_CAPACITY: usize = 16;
+
+/// Maximum size in bytes of the header and body of a block stored in
+/// [`SyncService::recent_blocks`].
+const RECENT_BLOCKS_CACHE_MAX_BLOCK_LEN: usize = 1024 * 1024;
+
+/// See [`SyncService::storage_cache`].
+type StorageCache = lru::LruCache<([u8; 32], Vec<u8>), Option<Vec<u8>>, fnv::FnvBuildHasher>;
+
+/// Maximum number of entries in [`SyncService::storage_cache`].
+const STORAGE_CACHE_CAPACITY: usize = 512;
+
+/// Number of entries that [`SyncService::storage_cache_trim`] keeps in
+/// [`SyncService::storage_cache`].
+const STORAGE_CACHE_TRIMMED_LEN: usize = STORAGE_CACHE_CAPACITY / 8;
+
+/// Maximum size in bytes of a storage value stored in [`SyncService::storage_cache`].
+const STORAGE_CACHE_MAX_VALUE_LEN: usize = 16 * 1024;
+
 impl<TPlat: Platform> SyncService<TPlat> {
     pub async fn new(mut config: Config<TPlat>) -> Self {
         let (to_background, from_foreground) = mpsc::channel(16);
 
         let log_target = format!("sync-service-{}", config.log_name);
 
+        let block_requests_in_progress = Arc::new(BlockRequestsInProgress::default());
+
         if let Some(config_parachain) = config.parachain {
             (config.tasks_executor)(
                 log_target.clone(),
@@ -138,6 +600,15 @@ impl<TPlat: Platform> SyncService<TPlat> {
                     log_target,
                     config.chain_information,
                     config.block_number_bytes,
+                    config.bad_blocks,
+                    config.fork_blocks,
+                    config.clock_drift_tolerance,
+                    config.block_announce_policy,
+                    config.warp_sync_min_distinct_peers,
+                    config.finality_proofs_window,
+                    config.skip_seal_verification,
+                    config.bootnodes,
+                    block_requests_in_progress.clone(),
                     from_foreground,
                     config.network_service.0.clone(),
                     config.network_service.1,
@@ -151,6 +622,16 @@ impl<TPlat: Platform> SyncService<TPlat> {
             network_service: config.network_service.0,
             network_chain_index: config.network_service.1,
             block_number_bytes: config.block_number_bytes,
+            storage_cache: Mutex::new(lru::LruCache::with_hasher(
+                NonZeroUsize::new(STORAGE_CACHE_CAPACITY).unwrap(),
+                Default::default(),
+            )),
+            storage_requests_in_progress: StorageRequestsInProgress::default(),
+            block_requests_in_progress,
+            recent_blocks: Mutex::new(lru::LruCache::with_hasher(
+                NonZeroUsize::new(RECENT_BLOCKS_CACHE_CAPACITY).unwrap(),
+                Default::default(),
+            )),
         }
     }
 
@@ -179,6 +660,85 @@ impl<TPlat: Platform> SyncService<TPlat> {
         rx.await.unwrap()
     }
 
+    /// Returns the list of GrandPa warp sync fragments that have been verified in order to reach
+    /// the finalized block the chain has been warp synced to.
+    ///
+    /// Each fragment contains the header of a block that enacts a change in the list of GrandPa
+    /// authorities, and a justification proving its finality signed by the previous list of
+    /// authorities. The first fragment is signed by the authorities of the finalized block found
+    /// in the chain information the chain was initialized with. Together, these fragments form a
+    /// proof of finality of the last one that can be verified by third parties.
+    ///
+    /// Returns an empty list if the warp syncing hasn't finished yet, if the chain was
+    /// initialized at the head of the chain, or if this is a parachain.
+    pub async fn warp_sync_fragments(&self) -> Vec<sync::warp_sync::WarpSyncFragment> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::WarpSyncFragments { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Verifies a finality proof of the chain that has been obtained through other means than
+    /// the peer-to-peer network, such as from a trusted archive, and applies it.
+    ///
+    /// This makes it possible to accelerate the finality of the chain when the justifications
+    /// and GrandPa commits sent by peers are scarce. The proof is verified similarly to the
+    /// proofs sent by peers, and as such doesn't need to come from a trusted source.
+    ///
+    /// A GrandPa commit can only be verified if its target block and the blocks that it votes for
+    /// are known. If that's not the case, [`InjectFinalityProofError::NotVerifiableYet`] is
+    /// returned and the commit can be injected again later.
+    pub async fn inject_finality_proof(
+        &self,
+        finality_proof: sync::all::ExternalFinalityProof,
+    ) -> Result<(), InjectFinalityProofError> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::InjectFinalityProof {
+                finality_proof,
+                send_back,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the finality proof, retained in memory, that proves the finality of the block
+    /// with the given hash. See [`Config::finality_proofs_window`].
+    ///
+    /// The proof either concerns the requested block itself or one of its descendants, in
+    /// which case it proves the finality of the requested block as well. Only the blocks that
+    /// are finalized by a proof while the service is running are covered. The blocks finalized
+    /// as part of the warp syncing are not.
+    ///
+    /// Returns `None` if no such proof is known, if it has been discarded, or if this is a
+    /// parachain.
+    pub async fn finality_proof(&self, block_hash: [u8; 32]) -> Option<RetainedFinalityProof> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::FinalityProof {
+                block_hash,
+                send_back,
+            })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
     /// Subscribes to the state of the chain: the current state and the new blocks.
     ///
     /// All new blocks are reported. Only up to `buffer_size` block notifications are buffered
@@ -230,6 +790,82 @@ impl<TPlat: Platform> SyncService<TPlat> {
         rx.await.unwrap()
     }
 
+    /// Returns true if it is suspected that all the peers that are used to synchronize blocks are
+    /// controlled by the same entity, and that this entity is hiding the latest blocks of the
+    /// chain. This is called an eclipse attack.
+    ///
+    /// This is suspected when none of the peers is a bootnode of the chain, all the peers report
+    /// the same best block, and the finalized block hasn't changed for a long time.
+    ///
+    /// The way this method is implemented is opaque and cannot be relied on. The return value
+    /// should only ever be shown to the user and not used for any meaningful logic.
+    pub async fn is_eclipse_suspected_heuristic(&self) -> bool {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::IsEclipseSuspectedHeuristic { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns information about the progress of the finality of the chain, or `None` if the
+    /// chain is a parachain, as the finality of parachains is determined by their relay chain.
+    ///
+    /// The information returned by this function makes it possible to distinguish between a
+    /// chain whose finality has stalled and a local node that fails to follow the finality of
+    /// the chain.
+    pub async fn finality_diagnostics(&self) -> Option<FinalityDiagnostics> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::FinalityDiagnostics { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns the current and next Babe epochs of the current best block, or `None` if the
+    /// chain doesn't use Babe or is a parachain.
+    ///
+    /// The start slot of the next epoch makes it possible to predict the next session boundary.
+    ///
+    /// > **Note**: The epochs are those of the best block, and might be reverted if the best
+    /// >           block is reverted.
+    pub async fn babe_epochs(&self) -> Option<BabeEpochs> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::BabeEpochs { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Returns information about the peers that are used to synchronize blocks, in order to
+    /// determine whether the chain is in a healthy state.
+    pub async fn health_diagnostics(&self) -> HealthDiagnostics {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::HealthDiagnostics { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
     /// Returns the list of peers from the [`network_service::NetworkService`] that are used to
     /// synchronize blocks.
     ///
@@ -296,64 +932,109 @@ impl<TPlat: Platform> SyncService<TPlat> {
         _max_parallel: NonZeroU32,
     ) -> Result<protocol::BlockData, ()> {
         // TODO: better error?
-        let request_config = protocol::BlocksRequestConfig {
-            start: protocol::BlocksRequestConfigStart::Hash(hash),
-            desired_count: NonZeroU32::new(1).unwrap(),
-            direction: protocol::BlocksRequestDirection::Ascending,
-            fields: fields.clone(),
-        };
+        // TODO: handle max_parallel
+        self.block_query_cached(
+            Some(block_number),
+            hash,
+            fields,
+            total_attempts,
+            timeout_per_request,
+        )
+        .await
+    }
 
+    // TODO: doc; explain the guarantees
+    pub async fn block_query_unknown_number(
+        self: Arc<Self>,
+        hash: [u8; 32],
+        fields: protocol::BlocksRequestFields,
+        total_attempts: u32,
+        timeout_per_request: Duration,
+        _max_parallel: NonZeroU32,
+    ) -> Result<protocol::BlockData, ()> {
+        // TODO: better error?
         // TODO: handle max_parallel
-        // TODO: better peers selection ; don't just take the first 3
-        for target in self
-            .peers_assumed_know_blocks(block_number, &hash)
+        self.block_query_cached(None, hash, fields, total_attempts, timeout_per_request)
             .await
-            .take(usize::try_from(total_attempts).unwrap_or(usize::max_value()))
-        {
-            let mut result = match self
-                .network_service
-                .clone()
-                .blocks_request(
-                    target,
-                    self.network_chain_index,
-                    request_config.clone(),
-                    timeout_per_request,
-                )
-                .await
-            {
-                Ok(b) => b,
-                Err(_) => continue,
-            };
+    }
 
-            return Ok(result.remove(0));
+    /// Returns the given block from [`SyncService::recent_blocks`] if possible, or otherwise
+    /// obtains it through [`SyncService::block_requests_in_progress`] then inserts it in
+    /// [`SyncService::recent_blocks`].
+    async fn block_query_cached(
+        self: Arc<Self>,
+        block_number: Option<u64>,
+        hash: [u8; 32],
+        fields: protocol::BlocksRequestFields,
+        total_attempts: u32,
+        timeout_per_request: Duration,
+    ) -> Result<protocol::BlockData, ()> {
+        if let Some((cached_fields, block)) = self.recent_blocks.lock().await.get(&hash) {
+            if fields_cover(cached_fields, &fields) {
+                return Ok(block_data_subset(block, &fields));
+            }
         }
 
-        Err(())
+        let block = self
+            .block_requests_in_progress
+            .query(hash, None, &fields, || {
+                self.clone()
+                    .block_query_network(
+                        block_number,
+                        hash,
+                        fields.clone(),
+                        total_attempts,
+                        timeout_per_request,
+                    )
+                    .boxed()
+            })
+            .await?;
+
+        let block_len = block.header.as_ref().map_or(0, |h| h.len())
+            + block
+                .body
+                .as_ref()
+                .map_or(0, |b| b.iter().map(|e| e.len()).sum::<usize>());
+        if block_len <= RECENT_BLOCKS_CACHE_MAX_BLOCK_LEN {
+            self.recent_blocks
+                .lock()
+                .await
+                .put(hash, (fields, Arc::new(block.clone())));
+        }
+
+        Ok(block)
     }
 
-    // TODO: doc; explain the guarantees
-    pub async fn block_query_unknown_number(
+    /// Performs block requests towards the peers in order to obtain the given block, without
+    /// going through [`SyncService::block_requests_in_progress`].
+    ///
+    /// If `block_number` is `None`, the requests are sent to any peer.
+    async fn block_query_network(
         self: Arc<Self>,
+        block_number: Option<u64>,
         hash: [u8; 32],
         fields: protocol::BlocksRequestFields,
         total_attempts: u32,
         timeout_per_request: Duration,
-        _max_parallel: NonZeroU32,
     ) -> Result<protocol::BlockData, ()> {
-        // TODO: better error?
         let request_config = protocol::BlocksRequestConfig {
             start: protocol::BlocksRequestConfigStart::Hash(hash),
             desired_count: NonZeroU32::new(1).unwrap(),
             direction: protocol::BlocksRequestDirection::Ascending,
-            fields: fields.clone(),
+            fields,
         };
 
-        // TODO: handle max_parallel
-        // TODO: better peers selection ; don't just take the first
-        for target in self
-            .network_service
-            .peers_list()
-            .await
+        // TODO: better peers selection ; don't just take the first 3
+        let targets = match block_number {
+            Some(block_number) => self
+                .peers_assumed_know_blocks(block_number, &hash)
+                .await
+                .collect::<Vec<_>>(),
+            None => self.network_service.peers_list().await.collect::<Vec<_>>(),
+        };
+
+        for target in targets
+            .into_iter()
             .take(usize::try_from(total_attempts).unwrap_or(usize::max_value()))
         {
             let mut result = match self
@@ -377,6 +1058,62 @@ impl<TPlat: Platform> SyncService<TPlat> {
         Err(())
     }
 
+    /// Returns the storage value of the given key found in the cache of
+    /// [`SyncService::storage_query`], without performing any network request.
+    ///
+    /// Returns `None` if the value isn't in the cache, and `Some(None)` if the cache indicates
+    /// that there is no storage value associated to this key.
+    pub async fn storage_cache_get(
+        &self,
+        storage_trie_root: &[u8; 32],
+        key: &[u8],
+    ) -> Option<Option<Vec<u8>>> {
+        let mut cache = self.storage_cache.lock().await;
+        cache.get(&(*storage_trie_root, key.to_vec())).cloned()
+    }
+
+    /// Evicts the least recently used entries of the cache of [`SyncService::storage_query`]
+    /// until only a small fraction of its capacity remains in use.
+    pub async fn storage_cache_trim(&self) {
+        let mut cache = self.storage_cache.lock().await;
+        while cache.len() > STORAGE_CACHE_TRIMMED_LEN {
+            cache.pop_lru();
+        }
+    }
+
+    /// Returns all the entries of the cache of [`SyncService::storage_query`], in the format
+    /// `(storage_trie_root, key, value)`.
+    #[cfg(feature = "sqlite-cache")]
+    pub async fn storage_cache_entries(&self) -> Vec<([u8; 32], Vec<u8>, Option<Vec<u8>>)> {
+        let cache = self.storage_cache.lock().await;
+        cache
+            .iter()
+            .map(|((root, key), value)| (*root, key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Inserts entries in the cache of [`SyncService::storage_query`], in the format
+    /// `(storage_trie_root, key, value)`.
+    ///
+    /// The entries aren't verified. They must have been obtained through
+    /// [`SyncService::storage_cache_entries`].
+    #[cfg(feature = "sqlite-cache")]
+    pub async fn storage_cache_insert(
+        &self,
+        entries: impl Iterator<Item = ([u8; 32], Vec<u8>, Option<Vec<u8>>)>,
+    ) {
+        let mut cache = self.storage_cache.lock().await;
+        for (root, key, value) in entries {
+            if value
+                .as_ref()
+                .is_some_and(|v| v.len() > STORAGE_CACHE_MAX_VALUE_LEN)
+            {
+                continue;
+            }
+            cache.put((root, key), value);
+        }
+    }
+
     /// Performs one or more storage proof requests in order to find the value of the given
     /// `requested_keys`.
     ///
@@ -393,6 +1130,19 @@ impl<TPlat: Platform> SyncService<TPlat> {
     /// [`network_service::NetworkService::storage_proof_request`] and verifying the proof,
     /// potentially multiple times until it succeeds. The number of attempts and the selection of
     /// peers is done through reasonable heuristics.
+    ///
+    /// The values that have recently been obtained are kept in a cache. Keys whose value is found
+    /// in the cache aren't requested from the network. If all the values are in the cache, no
+    /// network request is performed at all.
+    ///
+    /// Similarly, keys that are already being requested by another call to this function
+    /// targeting the same storage trie root aren't requested again. This call instead waits for
+    /// the other call's network request to finish, and uses its result. As a consequence, an
+    /// error can be returned if that other request fails.
+    ///
+    /// Network requests are started after a short delay. The keys of the calls to this function
+    /// that target the same storage trie root during this delay are requested together in a
+    /// single network request.
     pub async fn storage_query(
         self: Arc<Self>,
         block_number: u64,
@@ -402,16 +1152,211 @@ impl<TPlat: Platform> SyncService<TPlat> {
         total_attempts: u32,
         timeout_per_request: Duration,
         _max_parallel: NonZeroU32,
+    ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
+        // Values of `requested_keys` found in the cache, or `None` if not in the cache, plus the
+        // list of keys that aren't in the cache.
+        let mut values = Vec::with_capacity(requested_keys.clone().count());
+        let mut missing_keys = Vec::new();
+        {
+            let mut cache = self.storage_cache.lock().await;
+            for key in requested_keys.clone() {
+                let cached = cache.get(&(*storage_trie_root, key.as_ref().to_vec()));
+                if cached.is_none() {
+                    missing_keys.push(key);
+                }
+                values.push(cached.cloned());
+            }
+        }
+
+        if !missing_keys.is_empty() {
+            let missing_keys = missing_keys
+                .into_iter()
+                .map(|key| key.as_ref().to_vec())
+                .collect::<Vec<_>>();
+
+            let new_request = {
+                let this = self.clone();
+                let block_hash = *block_hash;
+                let storage_trie_root = *storage_trie_root;
+                move |keys: Vec<Vec<u8>>| {
+                    async move {
+                        this.storage_query_network(
+                            block_number,
+                            &block_hash,
+                            &storage_trie_root,
+                            keys.iter(),
+                            total_attempts,
+                            timeout_per_request,
+                        )
+                        .await
+                    }
+                    .boxed()
+                }
+            };
+
+            let missing_values = self
+                .storage_requests_in_progress
+                .query::<TPlat>(*storage_trie_root, &missing_keys, new_request)
+                .await?;
+
+            let mut cache = self.storage_cache.lock().await;
+            let mut missing_values = missing_keys.into_iter().zip(missing_values);
+            for value in values.iter_mut() {
+                if value.is_some() {
+                    continue;
+                }
+
+                let (key, missing_value) = missing_values.next().unwrap();
+                if missing_value.as_ref().map_or(0, |v| v.len()) <= STORAGE_CACHE_MAX_VALUE_LEN {
+                    cache.put((*storage_trie_root, key), missing_value.clone());
+                }
+                *value = Some(missing_value);
+            }
+        }
+
+        Ok(values.into_iter().map(|v| v.unwrap()).collect())
+    }
+
+    /// Determines which of the given `requested_keys` have a different storage value in
+    /// `new_block` compared to `old_block`.
+    ///
+    /// Returns, for each element of `requested_keys`, `true` if its value has changed. If `Ok`,
+    /// the `Vec` is guaranteed to have the same number of elements as `requested_keys`.
+    ///
+    /// If the storage trie roots of both blocks are equal, the storage is identical and no
+    /// network request is performed. Otherwise, a proof of all the keys is downloaded from
+    /// `new_block`, and the values of `new_block` are inserted in the cache, so that calling
+    /// [`SyncService::storage_query`] afterwards on the keys that have changed doesn't perform
+    /// any network request.
+    ///
+    /// The values of `old_block` found in the cache, which is typically the case when this
+    /// function is called on successive blocks, are compared directly. For the other keys, the
+    /// Merkle values of their ancestors in the storage trie are compared between both blocks
+    /// through proofs, and the storage values of `old_block` are only downloaded for the keys
+    /// that aren't below an unchanged subtree. See [`trie::changes_proof`].
+    pub async fn storage_changes_query(
+        self: Arc<Self>,
+        old_block: StorageChangesBlock,
+        new_block: StorageChangesBlock,
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+        total_attempts: u32,
+        timeout_per_request: Duration,
+    ) -> Result<Vec<bool>, StorageQueryError> {
+        if old_block.storage_trie_root == new_block.storage_trie_root {
+            return Ok(vec![false; requested_keys.count()]);
+        }
+
+        let requested_keys = requested_keys
+            .map(|key| key.as_ref().to_vec())
+            .collect::<Vec<_>>();
+
+        let new_block_proof = self
+            .clone()
+            .storage_proof_query(
+                new_block.number,
+                &new_block.hash,
+                &new_block.storage_trie_root,
+                requested_keys.iter(),
+                total_attempts,
+                timeout_per_request,
+            )
+            .await?;
+
+        // For each requested key, `Some` if the value at `old_block` is found in the cache.
+        let mut changed = Vec::with_capacity(requested_keys.len());
+        {
+            // The proof has already been verified by `storage_proof_query`.
+            let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+                proof: &new_block_proof,
+                trie_root_hash: &new_block.storage_trie_root,
+            })
+            .unwrap();
+
+            let mut cache = self.storage_cache.lock().await;
+            for key in &requested_keys {
+                let new_value = decoded.storage_value(key).unwrap().map(|(v, _)| v);
+                if new_value.map_or(0, |v| v.len()) <= STORAGE_CACHE_MAX_VALUE_LEN {
+                    cache.put(
+                        (new_block.storage_trie_root, key.clone()),
+                        new_value.map(|v| v.to_vec()),
+                    );
+                }
+
+                changed.push(
+                    cache
+                        .get(&(old_block.storage_trie_root, key.clone()))
+                        .map(|old_value| old_value.as_deref() != new_value),
+                );
+            }
+        }
+
+        let mut scan = trie::changes_proof::changes_scan(trie::changes_proof::Config {
+            keys: requested_keys
+                .iter()
+                .zip(changed.iter())
+                .filter(|(_, changed)| changed.is_none())
+                .map(|(key, _)| key),
+            old_trie_root_hash: old_block.storage_trie_root,
+            new_trie_root_hash: new_block.storage_trie_root,
+            new_trie_proof: &new_block_proof,
+        })
+        .map_err(|err| StorageQueryError {
+            errors: vec![StorageQueryErrorDetail::from_changes_proof_error(err)],
+        })?;
+
+        let scan_outcome = loop {
+            match scan {
+                trie::changes_proof::ResumeOutcome::Success { changed } => break changed,
+                trie::changes_proof::ResumeOutcome::InProgress(in_progress) => {
+                    let keys = in_progress.requested_keys().collect::<Vec<_>>();
+                    let old_block_proof = self
+                        .clone()
+                        .storage_proof_query(
+                            old_block.number,
+                            &old_block.hash,
+                            &old_block.storage_trie_root,
+                            keys.iter(),
+                            total_attempts,
+                            timeout_per_request,
+                        )
+                        .await?;
+
+                    scan = in_progress.resume(&old_block_proof).map_err(|(_, err)| {
+                        StorageQueryError {
+                            errors: vec![StorageQueryErrorDetail::from_changes_proof_error(err)],
+                        }
+                    })?;
+                }
+            }
+        };
+
+        let mut scan_outcome = scan_outcome.into_iter();
+        Ok(changed
+            .into_iter()
+            .map(|changed| changed.unwrap_or_else(|| scan_outcome.next().unwrap()))
+            .collect())
+    }
+
+    /// Performs the network requests of [`SyncService::storage_query`], without consulting the
+    /// cache.
+    async fn storage_query_network(
+        &self,
+        block_number: u64,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+        total_attempts: u32,
+        timeout_per_request: Duration,
     ) -> Result<Vec<Option<Vec<u8>>>, StorageQueryError> {
         let mut outcome_errors =
-            Vec::with_capacity(usize::try_from(total_attempts).unwrap_or(usize::max_value()));
+            Vec::with_capacity(usize::try_from(total_attempts).unwrap_or(usize::MAX));
 
         // TODO: better peers selection ; don't just take the first
         // TODO: handle max_parallel
         for target in self
             .peers_assumed_know_blocks(block_number, block_hash)
             .await
-            .take(usize::try_from(total_attempts).unwrap_or(usize::max_value()))
+            .take(usize::try_from(total_attempts).unwrap_or(usize::MAX))
         {
             let result = self
                 .network_service
@@ -461,6 +1406,76 @@ impl<TPlat: Platform> SyncService<TPlat> {
         })
     }
 
+    /// Performs one or more storage proof requests in order to obtain a Merkle proof of the
+    /// given `requested_keys`, and returns the SCALE-encoded proof.
+    ///
+    /// Contrary to [`SyncService::storage_query`], the proof itself is returned rather than the
+    /// storage values, so that it can be passed to a third party that doesn't trust this node.
+    /// The proof is verified against `storage_trie_root` before being returned, and is
+    /// guaranteed to contain an entry for each of the `requested_keys`. The cache of
+    /// [`SyncService::storage_query`] isn't used.
+    pub async fn storage_proof_query(
+        self: Arc<Self>,
+        block_number: u64,
+        block_hash: &[u8; 32],
+        storage_trie_root: &[u8; 32],
+        requested_keys: impl Iterator<Item = impl AsRef<[u8]> + Clone> + Clone,
+        total_attempts: u32,
+        timeout_per_request: Duration,
+    ) -> Result<Vec<u8>, StorageQueryError> {
+        let mut outcome_errors =
+            Vec::with_capacity(usize::try_from(total_attempts).unwrap_or(usize::MAX));
+
+        // TODO: better peers selection ; don't just take the first
+        for target in self
+            .peers_assumed_know_blocks(block_number, block_hash)
+            .await
+            .take(usize::try_from(total_attempts).unwrap_or(usize::MAX))
+        {
+            let result = self
+                .network_service
+                .clone()
+                .storage_proof_request(
+                    self.network_chain_index,
+                    target,
+                    protocol::StorageProofRequestConfig {
+                        block_hash: *block_hash,
+                        keys: requested_keys.clone(),
+                    },
+                    timeout_per_request,
+                )
+                .await
+                .map_err(StorageQueryErrorDetail::Network)
+                .and_then(|outcome| {
+                    let proof = outcome.decode();
+                    let decoded = proof_decode::decode_and_verify_proof(proof_decode::Config {
+                        proof,
+                        trie_root_hash: storage_trie_root,
+                    })
+                    .map_err(StorageQueryErrorDetail::ProofVerification)?;
+
+                    for key in requested_keys.clone() {
+                        if decoded.storage_value(key.as_ref()).is_none() {
+                            return Err(StorageQueryErrorDetail::MissingProofEntry);
+                        }
+                    }
+
+                    Ok(proof.to_vec())
+                });
+
+            match result {
+                Ok(proof) => return Ok(proof),
+                Err(err) => {
+                    outcome_errors.push(err);
+                }
+            }
+        }
+
+        Err(StorageQueryError {
+            errors: outcome_errors,
+        })
+    }
+
     pub async fn storage_prefix_keys_query(
         self: Arc<Self>,
         block_number: u64,
@@ -597,6 +1612,18 @@ impl<TPlat: Platform> SyncService<TPlat> {
     }
 }
 
+/// Block passed to [`SyncService::storage_changes_query`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct StorageChangesBlock {
+    /// Number of the block.
+    pub number: u64,
+    /// Hash of the block.
+    pub hash: [u8; 32],
+    /// Merkle value of the root node of the storage trie of the block, as found in the
+    /// [`smoldot::header::HeaderRef::state_root`] field.
+    pub storage_trie_root: [u8; 32],
+}
+
 /// Error that can happen when calling [`SyncService::storage_query`].
 #[derive(Debug, Clone)]
 pub struct StorageQueryError {
@@ -629,6 +1656,18 @@ impl StorageQueryError {
             | StorageQueryErrorDetail::MissingProofEntry => false,
         })
     }
+
+    /// Returns the category of this error.
+    ///
+    /// If the errors of the individual peers are of different categories, the category of the
+    /// first error that isn't a networking issue is returned.
+    pub fn kind(&self) -> ErrorKind {
+        self.errors
+            .iter()
+            .map(|err| err.kind())
+            .find(|kind| *kind != ErrorKind::NetworkUnreachable)
+            .unwrap_or(ErrorKind::NetworkUnreachable)
+    }
 }
 
 impl fmt::Display for StorageQueryError {
@@ -645,6 +1684,94 @@ impl fmt::Display for StorageQueryError {
     }
 }
 
+/// See [`SyncService::babe_epochs`].
+#[derive(Debug, Clone)]
+pub struct BabeEpochs {
+    /// Hash of the best block the epochs belong to.
+    pub best_block_hash: [u8; 32],
+    /// Height of the best block the epochs belong to.
+    pub best_block_number: u64,
+    /// Number of slots in each epoch.
+    pub slots_per_epoch: NonZeroU64,
+    /// Epoch the best block belongs to. `None` if the best block belongs to epoch #0.
+    pub current_epoch: Option<chain::chain_information::BabeEpochInformation>,
+    /// Epoch that follows the epoch the best block belongs to.
+    pub next_epoch: chain::chain_information::BabeEpochInformation,
+}
+
+/// See [`SyncService::finality_diagnostics`].
+#[derive(Debug, Clone)]
+pub struct FinalityDiagnostics {
+    /// Height of the current best block.
+    pub best_block_number: u64,
+    /// Height of the current finalized block.
+    pub finalized_block_number: u64,
+    /// Time elapsed since the finalized block has last changed, or since the sync service has
+    /// started if it hasn't changed yet.
+    pub time_since_last_finalized: Duration,
+    /// Number of peers that are used to synchronize blocks.
+    pub num_peers: usize,
+    /// Number of peers that have indicated, through a GrandPa neighbor packet, that their
+    /// finalized block is higher than the local finalized block.
+    ///
+    /// If this number is high, the local node is likely failing to follow the finality of the
+    /// chain. If it is zero while the finalized block hasn't changed for a long time, the
+    /// finality of the chain has likely stalled.
+    pub num_peers_higher_finalized: usize,
+}
+
+/// See [`SyncService::finality_proof`].
+#[derive(Debug, Clone)]
+pub struct RetainedFinalityProof {
+    /// Hash of the block whose finality is directly proven by
+    /// [`RetainedFinalityProof::proof`]. Either the requested block or one of its descendants.
+    pub target_block_hash: [u8; 32],
+    /// Height of the block whose hash is [`RetainedFinalityProof::target_block_hash`].
+    pub target_block_number: u64,
+    /// Justification or GrandPa commit that has been verified.
+    pub proof: sync::all::ExternalFinalityProof,
+}
+
+/// See [`SyncService::health_diagnostics`].
+#[derive(Debug, Clone)]
+pub struct HealthDiagnostics {
+    /// Number of peers that are used to synchronize blocks.
+    pub num_peers: usize,
+    /// Number of peers whose best block is the local best block or one of its descendants.
+    ///
+    /// If the local best block is also the local finalized block, this is the number of peers
+    /// whose best block is higher than or equal to the local finalized block, as the sync
+    /// service doesn't track the ancestry of the blocks of its peers below its non-finalized
+    /// blocks.
+    ///
+    /// `None` if the chain is a parachain, as the best block of parachains is determined by
+    /// their relay chain.
+    pub num_peers_best_chain_compatible: Option<usize>,
+    /// Number of peers that are full nodes or authorities, and thus capable of answering
+    /// storage and call proof requests, as opposed to light clients.
+    pub num_proof_serving_peers: usize,
+}
+
+/// Error that can happen when calling [`SyncService::inject_finality_proof`].
+#[derive(Debug, derive_more::Display)]
+pub enum InjectFinalityProofError {
+    /// The chain is a parachain. The finality of parachains is determined by their relay chain.
+    #[display(fmt = "Finality proofs can't be injected in parachains")]
+    Parachain,
+    /// The chain is still warp syncing and can't verify finality proofs yet.
+    #[display(fmt = "Chain is still warp syncing")]
+    WarpSyncInProgress,
+    /// The target of the GrandPa commit or one of the blocks it votes for isn't known yet.
+    #[display(fmt = "GrandPa commit can't be verified yet")]
+    NotVerifiableYet,
+    /// Error while verifying the justification.
+    #[display(fmt = "Error while verifying justification: {_0}")]
+    Justification(blocks_tree::JustificationVerifyError),
+    /// Error while verifying the GrandPa commit.
+    #[display(fmt = "Error while verifying GrandPa commit: {_0}")]
+    GrandpaCommit(blocks_tree::CommitVerifyError),
+}
+
 /// See [`StorageQueryError`].
 #[derive(Debug, derive_more::Display, Clone)]
 pub enum StorageQueryErrorDetail {
@@ -658,6 +1785,28 @@ pub enum StorageQueryErrorDetail {
     MissingProofEntry,
 }
 
+impl StorageQueryErrorDetail {
+    fn from_changes_proof_error(err: trie::changes_proof::Error) -> Self {
+        match err {
+            trie::changes_proof::Error::InvalidProof(err) => {
+                StorageQueryErrorDetail::ProofVerification(err)
+            }
+            trie::changes_proof::Error::MissingProofEntry => {
+                StorageQueryErrorDetail::MissingProofEntry
+            }
+        }
+    }
+
+    /// Returns the category of this error.
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            StorageQueryErrorDetail::Network(err) => err.kind(),
+            StorageQueryErrorDetail::ProofVerification(_)
+            | StorageQueryErrorDetail::MissingProofEntry => ErrorKind::InvalidProof,
+        }
+    }
+}
+
 /// Error that can happen when calling [`SyncService::call_proof_query`].
 #[derive(Debug, Clone)]
 pub struct CallProofQueryError {
@@ -672,6 +1821,18 @@ impl CallProofQueryError {
     pub fn is_network_problem(&self) -> bool {
         self.errors.iter().all(|err| err.is_network_problem())
     }
+
+    /// Returns the category of this error.
+    ///
+    /// If the errors of the individual peers are of different categories, the category of the
+    /// first error that isn't a networking issue is returned.
+    pub fn kind(&self) -> ErrorKind {
+        self.errors
+            .iter()
+            .map(|err| err.kind())
+            .find(|kind| *kind != ErrorKind::NetworkUnreachable)
+            .unwrap_or(ErrorKind::NetworkUnreachable)
+    }
 }
 
 impl fmt::Display for CallProofQueryError {
@@ -803,6 +1964,20 @@ pub struct BlockNotification {
 enum ToBackground {
     /// See [`SyncService::is_near_head_of_chain_heuristic`].
     IsNearHeadOfChainHeuristic { send_back: oneshot::Sender<bool> },
+    /// See [`SyncService::is_eclipse_suspected_heuristic`].
+    IsEclipseSuspectedHeuristic { send_back: oneshot::Sender<bool> },
+    /// See [`SyncService::finality_diagnostics`].
+    FinalityDiagnostics {
+        send_back: oneshot::Sender<Option<FinalityDiagnostics>>,
+    },
+    /// See [`SyncService::babe_epochs`].
+    BabeEpochs {
+        send_back: oneshot::Sender<Option<BabeEpochs>>,
+    },
+    /// See [`SyncService::health_diagnostics`].
+    HealthDiagnostics {
+        send_back: oneshot::Sender<HealthDiagnostics>,
+    },
     /// See [`SyncService::subscribe_all`].
     SubscribeAll {
         send_back: oneshot::Sender<SubscribeAll>,
@@ -823,4 +1998,245 @@ enum ToBackground {
     SerializeChainInformation {
         send_back: oneshot::Sender<Option<chain::chain_information::ValidChainInformation>>,
     },
+    /// See [`SyncService::inject_finality_proof`].
+    InjectFinalityProof {
+        finality_proof: sync::all::ExternalFinalityProof,
+        send_back: oneshot::Sender<Result<(), InjectFinalityProofError>>,
+    },
+    /// See [`SyncService::warp_sync_fragments`].
+    WarpSyncFragments {
+        send_back: oneshot::Sender<Vec<sync::warp_sync::WarpSyncFragment>>,
+    },
+    /// See [`SyncService::finality_proof`].
+    FinalityProof {
+        block_hash: [u8; 32],
+        send_back: oneshot::Sender<Option<RetainedFinalityProof>>,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BlockRequestsInProgress, StorageNetworkRequest, StorageRequestsInProgress};
+    use crate::platform::async_std::AsyncStdTcpWebSocket;
+    use alloc::{boxed::Box, sync::Arc, vec, vec::Vec};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use futures::{channel::oneshot, prelude::*};
+    use smoldot::{libp2p::peer_id, network::protocol};
+
+    fn fields() -> protocol::BlocksRequestFields {
+        protocol::BlocksRequestFields {
+            header: true,
+            body: false,
+            justifications: false,
+        }
+    }
+
+    #[test]
+    fn query_deduplicated_per_target() {
+        async_std::task::block_on(async {
+            let requests = BlockRequestsInProgress::default();
+            let fields = fields();
+            let peer_a = peer_id::PublicKey::Ed25519([1; 32]).into_peer_id();
+            let peer_b = peer_id::PublicKey::Ed25519([2; 32]).into_peer_id();
+            let num_started = AtomicUsize::new(0);
+
+            // Starts a request that completes when something is sent on the returned sender.
+            let new_request = || {
+                num_started.fetch_add(1, Ordering::SeqCst);
+                let (tx, rx) = oneshot::channel::<protocol::BlockData>();
+                (tx, move || rx.map_err(|_| ()).boxed())
+            };
+
+            let (tx_a, request_a) = new_request();
+            let mut query_a = Box::pin(requests.query([0; 32], Some(&peer_a), &fields, request_a));
+            assert!(futures::poll!(&mut query_a).is_pending());
+
+            // A request towards a different peer doesn't join the request towards `peer_a`.
+            let (_tx_b, request_b) = new_request();
+            let mut query_b = Box::pin(requests.query([0; 32], Some(&peer_b), &fields, request_b));
+            assert!(futures::poll!(&mut query_b).is_pending());
+            assert_eq!(num_started.load(Ordering::SeqCst), 2);
+
+            // Requests towards the same peer and requests towards any peer join the request.
+            let mut query_a2 = Box::pin(requests.query(
+                [0; 32],
+                Some(&peer_a),
+                &fields,
+                || -> future::BoxFuture<'static, _> { unreachable!() },
+            ));
+            let mut query_any = Box::pin(requests.query(
+                [0; 32],
+                None,
+                &fields,
+                || -> future::BoxFuture<'static, _> { unreachable!() },
+            ));
+            assert!(futures::poll!(&mut query_a2).is_pending());
+            assert!(futures::poll!(&mut query_any).is_pending());
+
+            let block = protocol::BlockData {
+                hash: [0; 32],
+                header: Some(Vec::from(&b"header"[..])),
+                body: None,
+                justifications: None,
+            };
+            tx_a.send(block).unwrap();
+            assert_eq!(query_a.await.unwrap().header.unwrap(), b"header");
+            assert_eq!(query_a2.await.unwrap().header.unwrap(), b"header");
+            assert_eq!(query_any.await.unwrap().header.unwrap(), b"header");
+            assert!(futures::poll!(&mut query_b).is_pending());
+            assert_eq!(num_started.load(Ordering::SeqCst), 2);
+        });
+    }
+
+    #[test]
+    fn targeted_query_does_not_join_untargeted() {
+        async_std::task::block_on(async {
+            let requests = BlockRequestsInProgress::default();
+            let fields = fields();
+            let peer = peer_id::PublicKey::Ed25519([1; 32]).into_peer_id();
+
+            let (_tx_any, rx_any) = oneshot::channel::<protocol::BlockData>();
+            let mut query_any = Box::pin(requests.query([0; 32], None, &fields, move || {
+                rx_any.map_err(|_| ()).boxed()
+            }));
+            assert!(futures::poll!(&mut query_any).is_pending());
+
+            let mut started = false;
+            let (_tx_peer, rx_peer) = oneshot::channel::<protocol::BlockData>();
+            let mut query_peer = Box::pin(requests.query([0; 32], Some(&peer), &fields, || {
+                started = true;
+                rx_peer.map_err(|_| ()).boxed()
+            }));
+            assert!(futures::poll!(&mut query_peer).is_pending());
+            drop(query_peer);
+            assert!(started);
+        });
+    }
+
+    /// Returns a closure to pass to [`StorageRequestsInProgress::query`] that records the keys
+    /// it is called with in `started`, and whose values are the keys reversed.
+    fn storage_request(
+        started: &Arc<std::sync::Mutex<Vec<Vec<Vec<u8>>>>>,
+    ) -> impl FnOnce(Vec<Vec<u8>>) -> StorageNetworkRequest + Send + 'static {
+        let started = started.clone();
+        move |keys| {
+            started.lock().unwrap().push(keys.clone());
+            let values = keys
+                .into_iter()
+                .map(|key| Some(key.into_iter().rev().collect()))
+                .collect();
+            future::ready(Ok(values)).boxed()
+        }
+    }
+
+    #[test]
+    fn concurrent_storage_queries_batched() {
+        async_std::task::block_on(async {
+            let requests = StorageRequestsInProgress::default();
+            let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+            let (values_a, values_b) = future::join(
+                requests.query::<AsyncStdTcpWebSocket>(
+                    [0; 32],
+                    &[b"ab".to_vec(), b"ef".to_vec()],
+                    storage_request(&started),
+                ),
+                requests.query::<AsyncStdTcpWebSocket>(
+                    [0; 32],
+                    &[b"cd".to_vec(), b"ab".to_vec()],
+                    storage_request(&started),
+                ),
+            )
+            .await;
+
+            assert_eq!(
+                values_a.unwrap(),
+                vec![Some(b"ba".to_vec()), Some(b"fe".to_vec())]
+            );
+            assert_eq!(
+                values_b.unwrap(),
+                vec![Some(b"dc".to_vec()), Some(b"ba".to_vec())]
+            );
+            assert_eq!(
+                *started.lock().unwrap(),
+                vec![vec![b"ab".to_vec(), b"cd".to_vec(), b"ef".to_vec()]]
+            );
+        });
+    }
+
+    #[test]
+    fn storage_queries_different_roots_not_batched() {
+        async_std::task::block_on(async {
+            let requests = StorageRequestsInProgress::default();
+            let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+            let (values_a, values_b) = future::join(
+                requests.query::<AsyncStdTcpWebSocket>(
+                    [0; 32],
+                    &[b"ab".to_vec()],
+                    storage_request(&started),
+                ),
+                requests.query::<AsyncStdTcpWebSocket>(
+                    [1; 32],
+                    &[b"cd".to_vec()],
+                    storage_request(&started),
+                ),
+            )
+            .await;
+
+            assert_eq!(values_a.unwrap(), vec![Some(b"ba".to_vec())]);
+            assert_eq!(values_b.unwrap(), vec![Some(b"dc".to_vec())]);
+            assert_eq!(started.lock().unwrap().len(), 2);
+        });
+    }
+
+    #[test]
+    fn storage_query_joins_sent_request() {
+        async_std::task::block_on(async {
+            let requests = StorageRequestsInProgress::default();
+            let started = Arc::new(std::sync::Mutex::new(Vec::new()));
+            let keys_a = [b"ab".to_vec()];
+            let keys_b = [b"ab".to_vec(), b"cd".to_vec()];
+
+            // The first request only finishes when something is sent on `tx`.
+            let (tx, rx) = oneshot::channel::<()>();
+            let mut query_a = Box::pin(requests.query::<AsyncStdTcpWebSocket>([0; 32], &keys_a, {
+                let request = storage_request(&started);
+                move |keys| {
+                    let request = request(keys);
+                    async move {
+                        let _ = rx.await;
+                        request.await
+                    }
+                    .boxed()
+                }
+            }));
+            while started.lock().unwrap().is_empty() {
+                assert!(futures::poll!(&mut query_a).is_pending());
+                async_std::task::sleep(core::time::Duration::from_millis(1)).await;
+            }
+
+            // The first request has been sent. Its key is reused, but other keys can't be added
+            // to it anymore.
+            let mut query_b = Box::pin(requests.query::<AsyncStdTcpWebSocket>(
+                [0; 32],
+                &keys_b,
+                storage_request(&started),
+            ));
+            while started.lock().unwrap().len() < 2 {
+                assert!(futures::poll!(&mut query_b).is_pending());
+                async_std::task::sleep(core::time::Duration::from_millis(1)).await;
+            }
+            assert_eq!(started.lock().unwrap()[1], vec![b"cd".to_vec()]);
+            assert!(futures::poll!(&mut query_b).is_pending());
+
+            tx.send(()).unwrap();
+            assert_eq!(query_a.await.unwrap(), vec![Some(b"ba".to_vec())]);
+            assert_eq!(
+                query_b.await.unwrap(),
+                vec![Some(b"ba".to_vec()), Some(b"dc".to_vec())]
+            );
+            assert_eq!(started.lock().unwrap().len(), 2);
+        });
+    }
 }
@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::ToBackground;
+use super::{HealthDiagnostics, InjectFinalityProofError, ToBackground};
 use crate::{network_service, platform::Platform, runtime_service};
 
 use alloc::{borrow::ToOwned as _, string::String, sync::Arc, vec::Vec};
@@ -498,6 +498,30 @@ impl<TPlat: Platform> ParachainBackgroundTask<TPlat> {
 
                 let _ = send_back.send(list);
             }
+            (ToBackground::IsEclipseSuspectedHeuristic { send_back }, _) => {
+                // Parachain blocks are only ever considered if they are referenced by the relay
+                // chain. Eclipse attacks are detected by the sync service of the relay chain.
+                let _ = send_back.send(false);
+            }
+            (ToBackground::FinalityDiagnostics { send_back }, _) => {
+                let _ = send_back.send(None);
+            }
+            (ToBackground::BabeEpochs { send_back }, _) => {
+                let _ = send_back.send(None);
+            }
+            (ToBackground::HealthDiagnostics { send_back }, _) => {
+                let _ = send_back.send(HealthDiagnostics {
+                    num_peers: self.sync_sources.len(),
+                    num_peers_best_chain_compatible: None,
+                    num_proof_serving_peers: self
+                        .sync_sources
+                        .keys()
+                        .filter(|local_id| {
+                            !matches!(self.sync_sources[*local_id].1, protocol::Role::Light)
+                        })
+                        .count(),
+                });
+            }
             (ToBackground::SyncingPeers { send_back }, _) => {
                 let _ = send_back.send(
                     self.sync_sources
@@ -513,6 +537,15 @@ impl<TPlat: Platform> ParachainBackgroundTask<TPlat> {
             (ToBackground::SerializeChainInformation { send_back }, _) => {
                 let _ = send_back.send(None);
             }
+            (ToBackground::InjectFinalityProof { send_back, .. }, _) => {
+                let _ = send_back.send(Err(InjectFinalityProofError::Parachain));
+            }
+            (ToBackground::WarpSyncFragments { send_back }, _) => {
+                let _ = send_back.send(Vec::new());
+            }
+            (ToBackground::FinalityProof { send_back, .. }, _) => {
+                let _ = send_back.send(None);
+            }
         }
     }
 
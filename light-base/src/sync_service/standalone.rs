@@ -15,24 +15,30 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use super::{BlockNotification, FinalizedBlockRuntime, Notification, SubscribeAll, ToBackground};
-use crate::{network_service, platform::Platform};
+use super::{
+    BabeEpochs, BlockAnnouncePolicy, BlockNotification, BlockRequestsInProgress,
+    FinalityDiagnostics, FinalizedBlockRuntime, HealthDiagnostics, InjectFinalityProofError,
+    Notification, RetainedFinalityProof, SubscribeAll, ToBackground,
+};
+use crate::{network_service, platform::Platform, spans};
 
-use alloc::{borrow::ToOwned as _, string::String, sync::Arc, vec::Vec};
+use alloc::{borrow::ToOwned as _, collections::VecDeque, string::String, sync::Arc, vec::Vec};
 use core::{
     iter,
     marker::PhantomData,
     num::{NonZeroU32, NonZeroU64},
+    sync::atomic,
     time::Duration,
 };
 use futures::{channel::mpsc, prelude::*};
 use hashbrown::{HashMap, HashSet};
 use smoldot::{
-    chain, header,
+    chain, executor, header,
     informant::HashDisplay,
     libp2p,
     network::{self, protocol},
     sync::all,
+    verify,
 };
 
 /// Starts a sync service background task to synchronize a standalone chain (relay chain or not).
@@ -40,16 +46,40 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
     log_target: String,
     chain_information: chain::chain_information::ValidChainInformation,
     block_number_bytes: usize,
+    bad_blocks: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+    fork_blocks: hashbrown::HashMap<u64, [u8; 32], fnv::FnvBuildHasher>,
+    clock_drift_tolerance: Duration,
+    block_announce_policy: BlockAnnouncePolicy,
+    warp_sync_min_distinct_peers: NonZeroU32,
+    finality_proofs_window: u32,
+    skip_seal_verification: bool,
+    bootnodes: Vec<libp2p::PeerId>,
+    block_requests_in_progress: Arc<BlockRequestsInProgress>,
     mut from_foreground: mpsc::Receiver<ToBackground>,
     network_service: Arc<network_service::NetworkService<TPlat>>,
     network_chain_index: usize,
     from_network_service: stream::BoxStream<'static, network_service::Event>,
 ) {
+    // Slot duration used to estimate the offset of the local clock. See `Task::clock_skew`.
+    // Chains using Babe don't verify whether blocks come from the future, and thus aren't
+    // affected by the local clock being off.
+    let aura_slot_duration = match chain_information.as_ref().consensus {
+        chain::chain_information::ChainInformationConsensusRef::Aura { slot_duration, .. } => {
+            Some(slot_duration)
+        }
+        _ => None,
+    };
+
+    // Offset, in milliseconds, applied to the local clock when verifying blocks. Updated
+    // whenever the estimate of `Task::clock_skew` changes.
+    let clock_offset_ms = Arc::new(atomic::AtomicI64::new(0));
+
     let mut task = Task {
         sync: all::AllSync::new(all::Config {
             chain_information,
             block_number_bytes,
             allow_unknown_consensus_engines: true,
+            skip_seal_verification,
             sources_capacity: 32,
             blocks_capacity: {
                 // This is the maximum number of blocks between two consecutive justifications.
@@ -57,6 +87,18 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
             },
             max_disjoint_headers: 1024,
             max_requests_per_block: NonZeroU32::new(3).unwrap(),
+            bad_blocks,
+            fork_blocks,
+            clock: verify::Clock::new({
+                let clock_offset_ms = clock_offset_ms.clone();
+                move || {
+                    verify::clock_skew::apply_offset(
+                        TPlat::now_from_unix_epoch(),
+                        clock_offset_ms.load(atomic::Ordering::Relaxed),
+                    )
+                }
+            }),
+            clock_drift_tolerance,
             download_ahead_blocks: {
                 // Verifying a block mostly consists in:
                 //
@@ -73,11 +115,16 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
                 // is 5k.
                 NonZeroU32::new(5000).unwrap()
             },
+            // Each source corresponds to a different peer. See `peers_source_id_map`.
+            warp_sync_min_distinct_sources: warp_sync_min_distinct_peers,
             full: None,
         }),
         network_up_to_date_best: true,
         network_up_to_date_finalized: true,
         known_finalized_runtime: None,
+        verified_warp_sync_fragments: Vec::new(),
+        finality_proofs_window,
+        retained_finality_proofs: VecDeque::new(),
         pending_block_requests: stream::FuturesUnordered::new(),
         pending_grandpa_requests: stream::FuturesUnordered::new(),
         pending_storage_requests: stream::FuturesUnordered::new(),
@@ -86,11 +133,34 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
             Duration::from_secs(10),
         ))
         .fuse(),
+        block_announce_policy,
+        bootnodes: bootnodes.into_iter().collect(),
+        last_finalized_block_update: TPlat::now(),
+        eclipse_suspected: false,
+        eclipse_check: TPlat::sleep(ECLIPSE_CHECK_INTERVAL).fuse(),
+        block_requests_in_progress,
+        pending_block_announces: Vec::new(),
+        pending_block_announces_flush: future::Either::Right(future::pending()).fuse(),
         all_notifications: Vec::<mpsc::Sender<Notification>>::new(),
         log_target,
         network_service,
         network_chain_index,
         peers_source_id_map: HashMap::with_capacity_and_hasher(0, Default::default()),
+        peers_finalized_block_height: HashMap::with_capacity_and_hasher(0, Default::default()),
+        clock_skew: verify::clock_skew::ClockSkewEstimator::new(verify::clock_skew::Config {
+            max_sources: 32,
+            min_sources: 5,
+            // Bootnodes are chosen by the authors of the chain specification and are thus
+            // trusted.
+            min_trusted_sources: 1,
+            // Blocks are announced at most one slot after the start of their slot.
+            max_spread: aura_slot_duration
+                .map_or(Duration::from_secs(6), |d| Duration::from_millis(d.get())),
+            max_offset: CLOCK_SKEW_MAX_OFFSET,
+        }),
+        pending_handshake_clock_samples: HashMap::with_capacity_and_hasher(0, Default::default()),
+        aura_slot_duration,
+        clock_offset_ms,
         platform: PhantomData,
     };
 
@@ -187,6 +257,7 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
             }
 
             task.network_up_to_date_finalized = true;
+            task.last_finalized_block_update = TPlat::now();
         }
 
         // Now waiting for some event to happen: a network event, a request from the frontend
@@ -219,10 +290,16 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
                 // `result` is an error if the block request got cancelled by the sync state
                 // machine.
                 if let Ok(result) = result {
+                    if let Ok(blocks) = &result {
+                        task.update_clock_skew_from_downloaded_headers(
+                            blocks.iter().filter_map(|b| b.header.as_deref())
+                        );
+                    }
+
                     // Inject the result of the request into the sync state machine.
                     task.sync.blocks_request_response(
                         request_id,
-                        result.map_err(|_| ()).map(|v| {
+                        result.map(|v| {
                             v.into_iter().filter_map(|block| {
                                 Some(all::BlockRequestSuccessBlock {
                                     scale_encoded_header: block.header?,
@@ -336,6 +413,24 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
                 continue;
             },
 
+            () = &mut task.eclipse_check => {
+                task.update_eclipse_suspected();
+                task.eclipse_check = TPlat::sleep(ECLIPSE_CHECK_INTERVAL).fuse();
+                continue;
+            },
+
+            () = &mut task.pending_block_announces_flush => {
+                // Only ever ready if `block_announce_policy` is `Batched`.
+                task.pending_block_announces_flush =
+                    future::Either::Right(future::pending()).fuse();
+                for (peer_id, scale_encoded_header, is_best) in
+                    core::mem::take(&mut task.pending_block_announces)
+                {
+                    task.process_block_announce(peer_id, scale_encoded_header, is_best);
+                }
+                continue;
+            },
+
             // If the list of CPU-heavy operations to perform is potentially non-empty, then we
             // wait for a future that is always instantly ready, in order to loop again and
             // perform the next CPU-heavy operation.
@@ -361,6 +456,54 @@ pub(super) async fn start_standalone_chain<TPlat: Platform>(
     }
 }
 
+/// Heuristic used by [`Task::update_eclipse_suspected`].
+///
+/// `sources` contains, for each source, whether it is a bootnode and the hash of its best block.
+/// `finalized_block_age` is the time elapsed since the finalized block has last changed.
+fn is_eclipse_suspected<'a>(
+    warp_sync_finished: bool,
+    sources: impl Iterator<Item = (bool, &'a [u8; 32])>,
+    finalized_block_age: Duration,
+) -> bool {
+    // Eclipse attacks are only detected after the warp syncing has finished. If the warp
+    // syncing doesn't progress, a warning is already printed.
+    if !warp_sync_finished || finalized_block_age < ECLIPSE_FINALIZED_BLOCK_STALE {
+        return false;
+    }
+
+    let mut first_best = None;
+    for (is_bootnode, best) in sources {
+        // Being connected to at least one bootnode is considered as a guarantee that we aren't
+        // eclipsed, as the bootnodes are chosen by the authors of the chain specification.
+        if is_bootnode {
+            return false;
+        }
+
+        match first_best {
+            None => first_best = Some(best),
+            Some(first_best) if first_best != best => return false,
+            Some(_) => {}
+        }
+    }
+
+    first_best.is_some()
+}
+
+/// Interval at which [`Task::update_eclipse_suspected`] is called.
+const ECLIPSE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Maximum offset of the local clock that [`Task::clock_skew`] can compensate for.
+const CLOCK_SKEW_MAX_OFFSET: Duration = Duration::from_secs(2 * 60);
+
+/// Duration after which, if the finalized block hasn't changed, the finalized block is
+/// considered as stale. Finality can legitimately stall for some time, for example during
+/// network upgrades, and this value is intentionally large.
+const ECLIPSE_FINALIZED_BLOCK_STALE: Duration = Duration::from_secs(180);
+
+/// Maximum number of warp sync fragments whose justification signatures are verified
+/// concurrently through [`Platform::run_cpu_intensive`].
+const MAX_CONCURRENT_WARP_SYNC_VERIFICATIONS: usize = 4;
+
 struct Task<TPlat: Platform> {
     /// Log target to use for all logs that are emitted.
     log_target: String,
@@ -375,10 +518,27 @@ struct Task<TPlat: Platform> {
     /// If `Some`, contains the runtime of the current finalized block.
     known_finalized_runtime: Option<FinalizedBlockRuntime>,
 
+    /// Warp sync fragments that have been verified when the warp syncing has finished. Empty if
+    /// the warp syncing hasn't finished yet.
+    verified_warp_sync_fragments: Vec<all::WarpSyncFragment>,
+
+    /// See [`super::Config::finality_proofs_window`].
+    finality_proofs_window: u32,
+
+    /// Finality proofs that have been verified, ordered by increasing target block height. Each
+    /// proof is accompanied with the hashes of all the blocks whose finality it has caused.
+    /// Always empty if [`Task::finality_proofs_window`] is 0.
+    retained_finality_proofs: VecDeque<(Vec<[u8; 32]>, RetainedFinalityProof)>,
+
     /// For each networking peer, the index of the corresponding peer within the [`Task::sync`].
     // TODO: use SipHasher
     peers_source_id_map: HashMap<libp2p::PeerId, all::SourceId, fnv::FnvBuildHasher>,
 
+    /// For each networking peer that has sent a GrandPa neighbor packet, the height of the
+    /// finalized block indicated in the latest of these packets. Always a subset of the keys
+    /// of [`Task::peers_source_id_map`].
+    peers_finalized_block_height: HashMap<libp2p::PeerId, u64, fnv::FnvBuildHasher>,
+
     /// `false` after the best block in the [`Task::sync`] has changed. Set back to `true`
     /// after the networking has been notified of this change.
     network_up_to_date_best: bool,
@@ -395,22 +555,48 @@ struct Task<TPlat: Platform> {
     warp_sync_taking_long_time_warning:
         future::Fuse<future::Either<TPlat::Delay, future::Pending<()>>>,
 
+    /// See [`super::Config::block_announce_policy`].
+    block_announce_policy: BlockAnnouncePolicy,
+
+    /// See [`super::Config::bootnodes`].
+    bootnodes: HashSet<libp2p::PeerId, fnv::FnvBuildHasher>,
+
+    /// Moment when the finalized block of [`Task::sync`] has last changed, or when the task
+    /// has started if it hasn't changed yet.
+    last_finalized_block_update: TPlat::Instant,
+
+    /// See [`super::SyncService::is_eclipse_suspected_heuristic`]. Updated every
+    /// [`ECLIPSE_CHECK_INTERVAL`] by [`Task::update_eclipse_suspected`].
+    eclipse_suspected: bool,
+
+    /// Future that becomes ready when [`Task::eclipse_suspected`] must be updated.
+    eclipse_check: future::Fuse<TPlat::Delay>,
+
+    /// If [`Task::block_announce_policy`] is [`BlockAnnouncePolicy::Batched`], contains the block
+    /// announces that have been received but not processed yet, in the order in which they have
+    /// been received. Always empty otherwise.
+    pending_block_announces: Vec<(libp2p::PeerId, Vec<u8>, bool)>,
+
+    /// Future that becomes ready when [`Task::pending_block_announces`] must be processed. Set
+    /// to `Pending` when [`Task::pending_block_announces`] is empty.
+    pending_block_announces_flush: future::Fuse<future::Either<TPlat::Delay, future::Pending<()>>>,
+
     /// Network service. Used to send out requests to peers.
     network_service: Arc<network_service::NetworkService<TPlat>>,
     /// Index within the network service of the chain we are interested in. Must be indicated to
     /// the network service whenever a request is started.
     network_chain_index: usize,
 
+    /// See [`super::SyncService::block_requests_in_progress`].
+    block_requests_in_progress: Arc<BlockRequestsInProgress>,
+
     /// List of block requests currently in progress.
     pending_block_requests: stream::FuturesUnordered<
         future::BoxFuture<
             'static,
             (
                 all::RequestId,
-                Result<
-                    Result<Vec<protocol::BlockData>, network_service::BlocksRequestError>,
-                    future::Aborted,
-                >,
+                Result<Result<Vec<protocol::BlockData>, ()>, future::Aborted>,
             ),
         >,
     >,
@@ -448,6 +634,24 @@ struct Task<TPlat: Platform> {
         >,
     >,
 
+    /// Estimate of the offset of the local clock, based on the slot numbers of the blocks
+    /// announced by peers. Only filled if [`Task::aura_slot_duration`] is `Some`.
+    clock_skew: verify::clock_skew::ClockSkewEstimator<libp2p::PeerId>,
+
+    /// For each connected peer that hasn't announced any block yet, the best block hash that it
+    /// has reported in its handshake and the value of the local clock when the handshake has
+    /// been received. Once the header of this block is downloaded, a sample is inserted in
+    /// [`Task::clock_skew`]. Only filled if [`Task::aura_slot_duration`] is `Some`.
+    pending_handshake_clock_samples:
+        HashMap<libp2p::PeerId, ([u8; 32], Duration), fnv::FnvBuildHasher>,
+
+    /// Duration of a slot, in milliseconds, if the chain uses Aura.
+    aura_slot_duration: Option<NonZeroU64>,
+
+    /// Offset, in milliseconds, applied to the local clock when verifying blocks. Shared with
+    /// the [`verify::Clock`] passed to [`Task::sync`].
+    clock_offset_ms: Arc<atomic::AtomicI64>,
+
     platform: PhantomData<fn() -> TPlat>,
 }
 
@@ -488,8 +692,14 @@ impl<TPlat: Platform> Task<TPlat> {
             } => {
                 let peer_id = self.sync[source_id].0.clone(); // TODO: why does this require cloning? weird borrow chk issue
 
+                let fields = network::protocol::BlocksRequestFields {
+                    header: request_headers,
+                    body: request_bodies,
+                    justifications: request_justification,
+                };
+
                 let block_request = self.network_service.clone().blocks_request(
-                    peer_id,
+                    peer_id.clone(),
                     self.network_chain_index,
                     network::protocol::BlocksRequestConfig {
                         start: if let Some(first_block_hash) = first_block_hash {
@@ -506,15 +716,42 @@ impl<TPlat: Platform> Task<TPlat> {
                         } else {
                             network::protocol::BlocksRequestDirection::Descending
                         },
-                        fields: network::protocol::BlocksRequestFields {
-                            header: request_headers,
-                            body: request_bodies,
-                            justifications: request_justification,
-                        },
+                        fields: fields.clone(),
                     },
                     Duration::from_secs(10),
                 );
 
+                // Requests targeting a single block by hash go through the list of requests in
+                // progress, in order to be deduplicated with the other requests towards the same
+                // peer. The requests started by the foreground can join this request, but this
+                // request can't join the requests of the foreground, as the response must come
+                // from `peer_id`.
+                let block_request = match first_block_hash {
+                    Some(hash) if num_blocks.get() == 1 => {
+                        let block_requests_in_progress = self.block_requests_in_progress.clone();
+                        async move {
+                            block_requests_in_progress
+                                .query(hash, Some(&peer_id), &fields, move || {
+                                    block_request
+                                        .map(|result| {
+                                            result.map_err(|_| ()).and_then(|mut blocks| {
+                                                if blocks.is_empty() {
+                                                    Err(())
+                                                } else {
+                                                    Ok(blocks.remove(0))
+                                                }
+                                            })
+                                        })
+                                        .boxed()
+                                })
+                                .await
+                                .map(|block| alloc::vec![block])
+                        }
+                        .boxed()
+                    }
+                    _ => block_request.map(|result| result.map_err(|_| ())).boxed(),
+                };
+
                 let (block_request, abort) = future::abortable(block_request);
                 let request_id = self
                     .sync
@@ -634,7 +871,22 @@ impl<TPlat: Platform> Task<TPlat> {
     async fn process_one_verification_queue(mut self) -> (Self, bool) {
         // Note that `process_one` moves out of `sync` and provides the value back in its
         // return value.
-        match self.sync.process_one() {
+        let process_one = match self.sync.process_one() {
+            all::ProcessOne::WarpSyncBuildRuntime(build_runtime) => {
+                // Compiling the runtime is CPU-intensive and can take several hundreds of
+                // milliseconds. It is thus performed through the platform, which can do so on a
+                // different thread.
+                let log_target = self.log_target.clone();
+                TPlat::run_cpu_intensive(move || {
+                    let _span = spans::enter::<TPlat>("sync-warp-sync-build-runtime", &log_target);
+                    build_runtime.build(executor::vm::ExecHint::CompileAheadOfTime, false)
+                })
+                .await
+            }
+            other => other,
+        };
+
+        match process_one {
             all::ProcessOne::AllSync(sync) => {
                 // Nothing to do. Queue is empty.
                 self.sync = sync;
@@ -656,6 +908,7 @@ impl<TPlat: Platform> Task<TPlat> {
                 finalized_block_runtime,
                 finalized_storage_code,
                 finalized_storage_heap_pages,
+                verified_fragments,
             } => {
                 self.sync = sync;
 
@@ -677,6 +930,8 @@ impl<TPlat: Platform> Task<TPlat> {
                     storage_heap_pages: finalized_storage_heap_pages,
                 });
 
+                self.verified_warp_sync_fragments = verified_fragments;
+
                 self.network_up_to_date_finalized = false;
                 self.network_up_to_date_best = false;
                 // Since there is a gap in the blocks, all active notifications to all blocks
@@ -686,11 +941,42 @@ impl<TPlat: Platform> Task<TPlat> {
                 return (self, true);
             }
 
-            all::ProcessOne::VerifyWarpSyncFragment(verify) => {
+            all::ProcessOne::VerifyWarpSyncFragment(mut verify) => {
                 // Grandpa warp sync fragment to verify.
                 let sender_peer_id = verify.proof_sender().1 .0.clone(); // TODO: unnecessary cloning most of the time
 
-                let (sync, result) = verify.perform(rand::random());
+                // Verifying the signatures of the justifications is CPU-intensive and is thus
+                // performed through the platform, which can do so on a different thread. The
+                // signatures of multiple fragments are verified concurrently, which platforms
+                // that support multiple threads can do in parallel.
+                let verifications = verify
+                    .justification_verifications(MAX_CONCURRENT_WARP_SYNC_VERIFICATIONS)
+                    .into_iter()
+                    .map(|verification| {
+                        let randomness_seed = rand::random();
+                        let log_target = self.log_target.clone();
+                        TPlat::run_cpu_intensive(move || {
+                            let _span = spans::enter::<TPlat>(
+                                "sync-verify-warp-sync-justification",
+                                &log_target,
+                            );
+                            verification.verify(randomness_seed)
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                // Failed verifications are ignored here, and reported below by `perform`.
+                for verified in future::join_all(verifications).await.into_iter().flatten() {
+                    verify.inject_justification_verified(verified);
+                }
+
+                let randomness_seed = rand::random();
+                let log_target = self.log_target.clone();
+                let (sync, result) = TPlat::run_cpu_intensive(move || {
+                    let _span =
+                        spans::enter::<TPlat>("sync-verify-warp-sync-fragment", &log_target);
+                    verify.perform(randomness_seed)
+                })
+                .await;
                 self.sync = sync;
 
                 if let Err(err) = result {
@@ -713,7 +999,11 @@ impl<TPlat: Platform> Task<TPlat> {
                 // Header to verify.
                 let verified_hash = verify.hash();
                 let verified_height = verify.height();
-                match verify.perform(TPlat::now_from_unix_epoch(), ()) {
+                let outcome = {
+                    let _span = spans::enter::<TPlat>("sync-verify-header", &self.log_target);
+                    verify.perform(())
+                };
+                match outcome {
                     all::HeaderVerifyOutcome::Success {
                         sync, is_new_best, ..
                     } => {
@@ -856,42 +1146,22 @@ impl<TPlat: Platform> Task<TPlat> {
 
             all::ProcessOne::VerifyFinalityProof(verify) => {
                 // Finality proof to verify.
+                let _span = spans::enter::<TPlat>("sync-verify-finality-proof", &self.log_target);
                 match verify.perform(rand::random()) {
                     (
                         sync,
                         all::FinalityProofVerifyOutcome::NewFinalized {
                             updates_best_block,
                             finalized_blocks,
-                            ..
+                            finality_proof,
                         },
                     ) => {
                         self.sync = sync;
-
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => FinalityProofVerified(finalized_blocks={})",
-                            finalized_blocks.len(),
+                        self.on_new_finalized(
+                            &finalized_blocks,
+                            updates_best_block,
+                            finality_proof,
                         );
-
-                        if updates_best_block {
-                            self.network_up_to_date_best = false;
-                        }
-                        self.network_up_to_date_finalized = false;
-                        // Invalidate the cache of the runtime of the finalized blocks if any
-                        // of the finalized blocks indicates that a runtime update happened.
-                        if finalized_blocks
-                            .iter()
-                            .any(|b| b.header.digest.has_runtime_environment_updated())
-                        {
-                            self.known_finalized_runtime = None;
-                        }
-                        self.dispatch_all_subscribers(Notification::Finalized {
-                            hash: self
-                                .sync
-                                .finalized_block_header()
-                                .hash(self.sync.block_number_bytes()),
-                            best_block_hash: self.sync.best_block_hash(),
-                        });
                     }
 
                     (
@@ -939,12 +1209,219 @@ impl<TPlat: Platform> Task<TPlat> {
             }
 
             // Can't verify header and body in non-full mode.
-            all::ProcessOne::VerifyBodyHeader(_) => unreachable!(),
+            all::ProcessOne::VerifyBodyHeader(_) | all::ProcessOne::WarpSyncBuildRuntime(_) => {
+                unreachable!()
+            }
         }
 
         (self, true)
     }
 
+    /// Updates the task after a finality proof has been successfully verified.
+    fn on_new_finalized(
+        &mut self,
+        finalized_blocks: &[all::Block<()>],
+        updates_best_block: bool,
+        finality_proof: all::ExternalFinalityProof,
+    ) {
+        log::debug!(
+            target: &self.log_target,
+            "Sync => FinalityProofVerified(finalized_blocks={})",
+            finalized_blocks.len(),
+        );
+
+        if updates_best_block {
+            self.network_up_to_date_best = false;
+        }
+        self.network_up_to_date_finalized = false;
+        // Invalidate the cache of the runtime of the finalized blocks if any
+        // of the finalized blocks indicates that a runtime update happened.
+        if finalized_blocks
+            .iter()
+            .any(|b| b.header.digest.has_runtime_environment_updated())
+        {
+            self.known_finalized_runtime = None;
+        }
+        self.retain_finality_proof(finalized_blocks, finality_proof);
+        self.dispatch_all_subscribers(Notification::Finalized {
+            hash: self
+                .sync
+                .finalized_block_header()
+                .hash(self.sync.block_number_bytes()),
+            best_block_hash: self.sync.best_block_hash(),
+        });
+    }
+
+    /// Stores the given finality proof in [`Task::retained_finality_proofs`] and discards the
+    /// proofs that are now out of the window.
+    fn retain_finality_proof(
+        &mut self,
+        finalized_blocks: &[all::Block<()>],
+        finality_proof: all::ExternalFinalityProof,
+    ) {
+        if self.finality_proofs_window == 0 {
+            return;
+        }
+
+        let block_number_bytes = self.sync.block_number_bytes();
+        let hashes = finalized_blocks
+            .iter()
+            .map(|b| b.header.hash(block_number_bytes))
+            .collect::<Vec<_>>();
+
+        // The proof proves the finality of the block with the highest number, and thus of all
+        // its ancestors.
+        if let Some(target) = finalized_blocks.iter().max_by_key(|b| b.header.number) {
+            self.retained_finality_proofs.push_back((
+                hashes,
+                RetainedFinalityProof {
+                    target_block_hash: target.header.hash(block_number_bytes),
+                    target_block_number: target.header.number,
+                    proof: finality_proof,
+                },
+            ));
+        }
+
+        let finalized_block_number = self.sync.finalized_block_header().number;
+        while let Some((_, proof)) = self.retained_finality_proofs.front() {
+            if proof
+                .target_block_number
+                .saturating_add(u64::from(self.finality_proofs_window))
+                > finalized_block_number
+            {
+                break;
+            }
+            self.retained_finality_proofs.pop_front();
+        }
+    }
+
+    /// Updates [`Task::eclipse_suspected`], and prints a log message if it has changed.
+    fn update_eclipse_suspected(&mut self) {
+        let eclipse_suspected = is_eclipse_suspected(
+            matches!(self.sync.status(), all::Status::Sync),
+            self.sync.sources().map(|source_id| {
+                (
+                    self.bootnodes.contains(&self.sync[source_id].0),
+                    self.sync.source_best_block(source_id).1,
+                )
+            }),
+            TPlat::now() - self.last_finalized_block_update.clone(),
+        );
+
+        if eclipse_suspected == self.eclipse_suspected {
+            return;
+        }
+
+        self.eclipse_suspected = eclipse_suspected;
+
+        if eclipse_suspected {
+            log::warn!(
+                target: &self.log_target,
+                "None of the {} peers is a bootnode, they all report the same best block, and \
+                the finalized block #{} hasn't changed in {}s. These peers might be \
+                controlled by the same entity and be hiding the latest blocks of the chain.",
+                self.sync.sources().count(),
+                self.sync.finalized_block_header().number,
+                ECLIPSE_FINALIZED_BLOCK_STALE.as_secs(),
+            );
+        } else {
+            log::info!(
+                target: &self.log_target,
+                "No longer suspecting that the peers are hiding the latest blocks of the chain"
+            );
+        }
+    }
+
+    /// Inserts in [`Task::clock_skew`] the sample corresponding to the given header announced
+    /// by the given peer, then updates the offset applied to the local clock.
+    fn update_clock_skew(&mut self, peer_id: &libp2p::PeerId, scale_encoded_header: &[u8]) {
+        // The announce supersedes the best block reported in the handshake.
+        self.pending_handshake_clock_samples.remove(peer_id);
+
+        self.insert_clock_skew_sample(peer_id, scale_encoded_header, TPlat::now_from_unix_epoch());
+    }
+
+    /// Inserts in [`Task::clock_skew`] the samples of the peers in
+    /// [`Task::pending_handshake_clock_samples`] whose handshake best block is one of the given
+    /// downloaded headers.
+    fn update_clock_skew_from_downloaded_headers<'a>(
+        &mut self,
+        scale_encoded_headers: impl Iterator<Item = &'a [u8]>,
+    ) {
+        if self.pending_handshake_clock_samples.is_empty() {
+            return;
+        }
+
+        for scale_encoded_header in scale_encoded_headers {
+            let hash = header::hash_from_scale_encoded_header(scale_encoded_header);
+            let peers = self
+                .pending_handshake_clock_samples
+                .iter()
+                .filter(|(_, (best_hash, _))| *best_hash == hash)
+                .map(|(peer_id, (_, received))| (peer_id.clone(), *received))
+                .collect::<Vec<_>>();
+
+            for (peer_id, received) in peers {
+                self.pending_handshake_clock_samples.remove(&peer_id);
+                self.insert_clock_skew_sample(&peer_id, scale_encoded_header, received);
+            }
+        }
+    }
+
+    /// Inserts in [`Task::clock_skew`] the sample corresponding to the given header, received
+    /// from the given peer at the given time, then updates the offset applied to the local
+    /// clock.
+    fn insert_clock_skew_sample(
+        &mut self,
+        peer_id: &libp2p::PeerId,
+        scale_encoded_header: &[u8],
+        received_from_unix_epoch: Duration,
+    ) {
+        let slot_duration = match self.aura_slot_duration {
+            Some(d) => d,
+            None => return,
+        };
+
+        // Headers that fail to decode are reported when they are processed.
+        let slot_number = match header::decode(scale_encoded_header, self.sync.block_number_bytes())
+            .ok()
+            .and_then(|h| h.digest.aura_pre_runtime())
+        {
+            Some(pre_digest) => pre_digest.slot_number,
+            None => return,
+        };
+
+        // Only connected peers contribute to the estimate.
+        if !self.peers_source_id_map.contains_key(peer_id) {
+            return;
+        }
+
+        self.clock_skew.insert_sample(
+            peer_id.clone(),
+            self.bootnodes.contains(peer_id),
+            verify::clock_skew::aura_slot_start(slot_number, slot_duration),
+            received_from_unix_epoch,
+        );
+        self.refresh_clock_offset();
+    }
+
+    /// Copies the estimate of [`Task::clock_skew`] to [`Task::clock_offset_ms`], and prints a
+    /// log message if it has changed.
+    fn refresh_clock_offset(&mut self) {
+        let new_offset_ms = self.clock_skew.estimated_offset_ms().unwrap_or(0);
+        let old_offset_ms = self
+            .clock_offset_ms
+            .swap(new_offset_ms, atomic::Ordering::Relaxed);
+
+        if old_offset_ms != new_offset_ms {
+            log::debug!(
+                target: &self.log_target,
+                "Sync => ClockSkewEstimateUpdated(offset_ms={})",
+                new_offset_ms
+            );
+        }
+    }
+
     /// Process a request coming from the foreground service.
     fn process_foreground_message(&mut self, message: ToBackground) {
         match message {
@@ -952,6 +1429,78 @@ impl<TPlat: Platform> Task<TPlat> {
                 let _ = send_back.send(self.sync.is_near_head_of_chain_heuristic());
             }
 
+            ToBackground::IsEclipseSuspectedHeuristic { send_back } => {
+                let _ = send_back.send(self.eclipse_suspected);
+            }
+
+            ToBackground::FinalityDiagnostics { send_back } => {
+                let finalized_block_number = self.sync.finalized_block_header().number;
+                let _ = send_back.send(Some(FinalityDiagnostics {
+                    best_block_number: self.sync.best_block_number(),
+                    finalized_block_number,
+                    time_since_last_finalized: TPlat::now()
+                        - self.last_finalized_block_update.clone(),
+                    num_peers: self.peers_source_id_map.len(),
+                    num_peers_higher_finalized: self
+                        .peers_finalized_block_height
+                        .values()
+                        .filter(|height| **height > finalized_block_number)
+                        .count(),
+                }));
+            }
+
+            ToBackground::BabeEpochs { send_back } => {
+                let epochs = match self.sync.best_block_consensus() {
+                    chain::chain_information::ChainInformationConsensusRef::Babe {
+                        slots_per_epoch,
+                        finalized_block_epoch_information,
+                        finalized_next_epoch_transition,
+                    } => Some(BabeEpochs {
+                        best_block_hash: self.sync.best_block_hash(),
+                        best_block_number: self.sync.best_block_number(),
+                        slots_per_epoch,
+                        current_epoch: finalized_block_epoch_information.map(From::from),
+                        next_epoch: finalized_next_epoch_transition.into(),
+                    }),
+                    _ => None,
+                };
+                let _ = send_back.send(epochs);
+            }
+
+            ToBackground::HealthDiagnostics { send_back } => {
+                let finalized_block_number = self.sync.finalized_block_header().number;
+                let best_block_number = self.sync.best_block_number();
+                let best_block_hash = self.sync.best_block_hash();
+
+                let _ = send_back.send(HealthDiagnostics {
+                    num_peers: self.peers_source_id_map.len(),
+                    num_peers_best_chain_compatible: Some(
+                        self.sync
+                            .sources()
+                            .filter(|source_id| {
+                                if best_block_number > finalized_block_number {
+                                    self.sync.source_knows_non_finalized_block(
+                                        *source_id,
+                                        best_block_number,
+                                        &best_block_hash,
+                                    )
+                                } else {
+                                    self.sync.source_best_block(*source_id).0
+                                        >= finalized_block_number
+                                }
+                            })
+                            .count(),
+                    ),
+                    num_proof_serving_peers: self
+                        .sync
+                        .sources()
+                        .filter(|source_id| {
+                            !matches!(self.sync[*source_id].1, protocol::Role::Light)
+                        })
+                        .count(),
+                });
+            }
+
             ToBackground::SubscribeAll {
                 send_back,
                 buffer_size,
@@ -1036,11 +1585,193 @@ impl<TPlat: Platform> Task<TPlat> {
             ToBackground::SerializeChainInformation { send_back } => {
                 let _ = send_back.send(Some(self.sync.as_chain_information().into()));
             }
+            ToBackground::InjectFinalityProof {
+                finality_proof,
+                send_back,
+            } => {
+                let result = match self
+                    .sync
+                    .verify_external_finality_proof(finality_proof, rand::random())
+                {
+                    None => Err(InjectFinalityProofError::WarpSyncInProgress),
+                    Some(all::FinalityProofVerifyOutcome::NewFinalized {
+                        finalized_blocks,
+                        updates_best_block,
+                        finality_proof,
+                    }) => {
+                        self.on_new_finalized(
+                            &finalized_blocks,
+                            updates_best_block,
+                            finality_proof,
+                        );
+                        Ok(())
+                    }
+                    Some(all::FinalityProofVerifyOutcome::AlreadyFinalized) => Ok(()),
+                    Some(all::FinalityProofVerifyOutcome::GrandpaCommitPending) => {
+                        Err(InjectFinalityProofError::NotVerifiableYet)
+                    }
+                    Some(all::FinalityProofVerifyOutcome::JustificationError(error)) => {
+                        Err(InjectFinalityProofError::Justification(error))
+                    }
+                    Some(all::FinalityProofVerifyOutcome::GrandpaCommitError(error)) => {
+                        Err(InjectFinalityProofError::GrandpaCommit(error))
+                    }
+                };
+
+                let _ = send_back.send(result);
+            }
+            ToBackground::WarpSyncFragments { send_back } => {
+                let _ = send_back.send(self.verified_warp_sync_fragments.clone());
+            }
+            ToBackground::FinalityProof {
+                block_hash,
+                send_back,
+            } => {
+                let proof = self
+                    .retained_finality_proofs
+                    .iter()
+                    .find(|(hashes, _)| hashes.contains(&block_hash))
+                    .map(|(_, proof)| proof.clone());
+                let _ = send_back.send(proof);
+            }
+        }
+    }
+
+    /// Processes a block announce received from the network.
+    ///
+    /// Called either immediately when the announce is received, or later if block announces are
+    /// batched.
+    fn process_block_announce(
+        &mut self,
+        peer_id: libp2p::PeerId,
+        scale_encoded_header: Vec<u8>,
+        is_best: bool,
+    ) {
+        let announced_block_number = match header::decode(
+            &scale_encoded_header,
+            self.sync.block_number_bytes(),
+        ) {
+            Ok(decoded_header) => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync <= BlockAnnounce(sender={}, hash={}, is_best={}, parent_hash={})",
+                    peer_id,
+                    HashDisplay(&header::hash_from_scale_encoded_header(&scale_encoded_header)),
+                    is_best,
+                    HashDisplay(decoded_header.parent_hash)
+                );
+                Some(decoded_header.number)
+            }
+            Err(error) => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync <= BlockAnnounce(sender={}, hash={}, is_best={}, parent_hash=<unknown>)",
+                    peer_id,
+                    HashDisplay(&header::hash_from_scale_encoded_header(&scale_encoded_header)),
+                    is_best,
+                );
+
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => InvalidBlockHeader(error={})",
+                    error
+                );
+
+                log::warn!(
+                    target: &self.log_target,
+                    "Failed to decode header in block announce received from {}. Error: {}",
+                    peer_id, error,
+                );
+                None
+            }
+        };
+
+        if let (
+            BlockAnnouncePolicy::IgnoreDeepForks { max_fork_depth },
+            Some(announced_block_number),
+        ) = (&self.block_announce_policy, announced_block_number)
+        {
+            if self
+                .sync
+                .best_block_number()
+                .saturating_sub(announced_block_number)
+                > *max_fork_depth
+            {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => IgnoredDeepFork"
+                );
+                return;
+            }
+        }
+
+        // The peer might have disconnected since the announce has been received, in
+        // case where the announce has been buffered.
+        let sync_source_id = match self.peers_source_id_map.get(&peer_id) {
+            Some(id) => *id,
+            None => return,
+        };
+
+        match self
+            .sync
+            .block_announce(sync_source_id, scale_encoded_header, is_best)
+        {
+            all::BlockAnnounceOutcome::HeaderVerify | all::BlockAnnounceOutcome::AlreadyInChain => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => Ok"
+                );
+            }
+            all::BlockAnnounceOutcome::Discarded => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => Discarded"
+                );
+            }
+            all::BlockAnnounceOutcome::StoredForLater {} => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => StoredForLater"
+                );
+            }
+            all::BlockAnnounceOutcome::TooOld {
+                announce_block_height,
+                ..
+            } => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => TooOld"
+                );
+
+                log::warn!(
+                    target: &self.log_target,
+                    "Block announce header height (#{}) from {} is below finalized block",
+                    announce_block_height,
+                    peer_id
+                );
+            }
+            all::BlockAnnounceOutcome::NotFinalizedChain => {
+                log::debug!(
+                    target: &self.log_target,
+                    "Sync => NotFinalized"
+                );
+
+                log::warn!(
+                    target: &self.log_target,
+                    "Block announce from {} isn't part of finalized chain",
+                    peer_id
+                );
+            }
+            all::BlockAnnounceOutcome::InvalidHeader(_) => {
+                // Log messages are already printed above.
+            }
         }
     }
 
     /// Updates the task with a new event coming from the network service.
     fn inject_network_event(&mut self, network_event: network_service::Event) {
+        let _span = spans::enter::<TPlat>("sync-network-event", &self.log_target);
+
         match network_event {
             network_service::Event::Connected {
                 peer_id,
@@ -1049,6 +1780,16 @@ impl<TPlat: Platform> Task<TPlat> {
                 best_block_number,
                 best_block_hash,
             } if chain_index == self.network_chain_index => {
+                // The best block reported in the handshake has been produced at most one slot
+                // before the handshake has been received. Its header is downloaded by the sync
+                // state machine, at which point a sample is inserted in `clock_skew`.
+                if self.aura_slot_duration.is_some() {
+                    self.pending_handshake_clock_samples.insert(
+                        peer_id.clone(),
+                        (best_block_hash, TPlat::now_from_unix_epoch()),
+                    );
+                }
+
                 self.peers_source_id_map.insert(
                     peer_id.clone(),
                     self.sync
@@ -1061,6 +1802,10 @@ impl<TPlat: Platform> Task<TPlat> {
                 chain_index,
             } if chain_index == self.network_chain_index => {
                 let sync_source_id = self.peers_source_id_map.remove(&peer_id).unwrap();
+                self.peers_finalized_block_height.remove(&peer_id);
+                self.clock_skew.remove_source(&peer_id);
+                self.pending_handshake_clock_samples.remove(&peer_id);
+                self.refresh_clock_offset();
                 let (_, requests) = self.sync.remove_source(sync_source_id);
 
                 // The `Disconnect` network event indicates that the main notifications substream
@@ -1077,97 +1822,43 @@ impl<TPlat: Platform> Task<TPlat> {
                 peer_id,
                 announce,
             } if chain_index == self.network_chain_index => {
-                let sync_source_id = *self.peers_source_id_map.get(&peer_id).unwrap();
                 let decoded = announce.decode();
 
-                match header::decode(decoded.scale_encoded_header, self.sync.block_number_bytes()) {
-                    Ok(decoded_header) => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync <= BlockAnnounce(sender={}, hash={}, is_best={}, parent_hash={})",
-                            peer_id,
-                            HashDisplay(&header::hash_from_scale_encoded_header(decoded.scale_encoded_header)),
-                            decoded.is_best,
-                            HashDisplay(decoded_header.parent_hash)
-                        );
-                    }
-                    Err(error) => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync <= BlockAnnounce(sender={}, hash={}, is_best={}, parent_hash=<unknown>)",
-                            peer_id,
-                            HashDisplay(&header::hash_from_scale_encoded_header(decoded.scale_encoded_header)),
-                            decoded.is_best,
-                        );
-
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => InvalidBlockHeader(error={})",
-                            error
-                        );
-
-                        log::warn!(
-                            target: &self.log_target,
-                            "Failed to decode header in block announce received from {}. Error: {}",
-                            peer_id, error,
-                        )
-                    }
+                // The sample must be taken when the announce is received rather than when it is
+                // processed, as processing might be delayed by the block announce policy.
+                if decoded.is_best {
+                    self.update_clock_skew(&peer_id, decoded.scale_encoded_header);
                 }
 
-                match self.sync.block_announce(
-                    sync_source_id,
-                    decoded.scale_encoded_header.to_owned(),
-                    decoded.is_best,
-                ) {
-                    all::BlockAnnounceOutcome::HeaderVerify
-                    | all::BlockAnnounceOutcome::AlreadyInChain => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => Ok"
-                        );
-                    }
-                    all::BlockAnnounceOutcome::Discarded => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => Discarded"
-                        );
-                    }
-                    all::BlockAnnounceOutcome::StoredForLater {} => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => StoredForLater"
-                        );
-                    }
-                    all::BlockAnnounceOutcome::TooOld {
-                        announce_block_height,
-                        ..
-                    } => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => TooOld"
-                        );
-
-                        log::warn!(
-                            target: &self.log_target,
-                            "Block announce header height (#{}) from {} is below finalized block",
-                            announce_block_height,
-                            peer_id
+                match self.block_announce_policy {
+                    BlockAnnouncePolicy::Immediate
+                    | BlockAnnouncePolicy::IgnoreDeepForks { .. } => {
+                        self.process_block_announce(
+                            peer_id,
+                            decoded.scale_encoded_header.to_owned(),
+                            decoded.is_best,
                         );
                     }
-                    all::BlockAnnounceOutcome::NotFinalizedChain => {
-                        log::debug!(
-                            target: &self.log_target,
-                            "Sync => NotFinalized"
-                        );
+                    BlockAnnouncePolicy::Batched { interval } => {
+                        let is_duplicate =
+                            self.pending_block_announces.iter().any(|(p, h, is_best)| {
+                                *p == peer_id
+                                    && *h == decoded.scale_encoded_header
+                                    && *is_best == decoded.is_best
+                            });
+
+                        if !is_duplicate {
+                            if self.pending_block_announces.is_empty() {
+                                self.pending_block_announces_flush =
+                                    future::Either::Left(TPlat::sleep(interval)).fuse();
+                            }
 
-                        log::warn!(
-                            target: &self.log_target,
-                            "Block announce from {} isn't part of finalized chain",
-                            peer_id
-                        );
-                    }
-                    all::BlockAnnounceOutcome::InvalidHeader(_) => {
-                        // Log messages are already printed above.
+                            self.pending_block_announces.push((
+                                peer_id,
+                                decoded.scale_encoded_header.to_owned(),
+                                decoded.is_best,
+                            ));
+                        }
                     }
                 }
             }
@@ -1198,6 +1889,19 @@ impl<TPlat: Platform> Task<TPlat> {
                 }
             }
 
+            network_service::Event::GrandpaNeighborPacket {
+                chain_index,
+                peer_id,
+                finalized_block_height,
+            } if chain_index == self.network_chain_index => {
+                // Neighbor packets might be received from peers that aren't used for syncing,
+                // in which case they are ignored.
+                if self.peers_source_id_map.contains_key(&peer_id) {
+                    self.peers_finalized_block_height
+                        .insert(peer_id, finalized_block_height);
+                }
+            }
+
             _ => {
                 // Different chain index.
             }
@@ -1218,3 +1922,50 @@ impl<TPlat: Platform> Task<TPlat> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{is_eclipse_suspected, ECLIPSE_FINALIZED_BLOCK_STALE};
+    use core::time::Duration;
+
+    const STALE: Duration = ECLIPSE_FINALIZED_BLOCK_STALE;
+
+    #[test]
+    fn eclipse_suspected() {
+        let sources = [(false, &[1; 32]), (false, &[1; 32]), (false, &[1; 32])];
+        assert!(is_eclipse_suspected(true, sources.into_iter(), STALE));
+    }
+
+    #[test]
+    fn eclipse_not_suspected_during_warp_sync() {
+        let sources = [(false, &[1; 32]), (false, &[1; 32])];
+        assert!(!is_eclipse_suspected(false, sources.into_iter(), STALE));
+    }
+
+    #[test]
+    fn eclipse_not_suspected_if_finality_recent() {
+        let sources = [(false, &[1; 32]), (false, &[1; 32])];
+        assert!(!is_eclipse_suspected(
+            true,
+            sources.into_iter(),
+            STALE - Duration::from_secs(1)
+        ));
+    }
+
+    #[test]
+    fn eclipse_not_suspected_if_connected_to_bootnode() {
+        let sources = [(false, &[1; 32]), (true, &[1; 32]), (false, &[1; 32])];
+        assert!(!is_eclipse_suspected(true, sources.into_iter(), STALE));
+    }
+
+    #[test]
+    fn eclipse_not_suspected_if_best_blocks_differ() {
+        let sources = [(false, &[1; 32]), (false, &[1; 32]), (false, &[2; 32])];
+        assert!(!is_eclipse_suspected(true, sources.into_iter(), STALE));
+    }
+
+    #[test]
+    fn eclipse_not_suspected_without_sources() {
+        assert!(!is_eclipse_suspected(true, [].into_iter(), STALE));
+    }
+}
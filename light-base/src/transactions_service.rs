@@ -83,7 +83,12 @@ use core::{
     num::{NonZeroU32, NonZeroUsize},
     time::Duration,
 };
-use futures::{channel::mpsc, lock::Mutex, prelude::*, stream::FuturesUnordered};
+use futures::{
+    channel::{mpsc, oneshot},
+    lock::Mutex,
+    prelude::*,
+    stream::FuturesUnordered,
+};
 use itertools::Itertools as _;
 use smoldot::{
     header,
@@ -119,6 +124,33 @@ pub struct Config<TPlat: Platform> {
     /// Any extra transaction will lead to [`DropReason::MaxPendingTransactionsReached`].
     pub max_pending_transactions: NonZeroU32,
 
+    /// Maximum total size, in bytes, of the SCALE encoding of the pending transactions.
+    ///
+    /// Any extra transaction will lead to [`DropReason::MaxPendingTransactionsBytesReached`].
+    pub max_pending_transactions_bytes: NonZeroUsize,
+
+    /// Maximum number of pending transactions signed by the same account.
+    ///
+    /// Any extra transaction will lead to [`DropReason::MaxPendingTransactionsPerSenderReached`].
+    ///
+    /// > **Note**: The signer of a transaction can only be determined if the transaction uses
+    /// >           the format of the Substrate-based chains. Transactions whose signer can't be
+    /// >           determined aren't subject to this limit. Transactions signed with a
+    /// >           `MultiAddress::Index` are counted separately from the transactions signed with
+    /// >           the account id that this index designates.
+    pub max_pending_transactions_per_sender: NonZeroU32,
+
+    /// What to do when submitting a transaction would exceed one of the limits above.
+    pub eviction_policy: EvictionPolicy,
+
+    /// Maximum number of peers that a transaction is sent to every time it is announced. The
+    /// peers are chosen randomly amongst the ones we are connected to.
+    pub max_broadcast_peers: NonZeroUsize,
+
+    /// Duration between two announcements of the same transaction, as long as it isn't
+    /// included in the best chain.
+    pub reannounce_interval: Duration,
+
     /// Maximum number of block body downloads that can be performed in parallel.
     ///
     /// > **Note**: This is the maximum number of *blocks* whose body is being download, not the
@@ -156,6 +188,12 @@ impl<TPlat: Platform> TransactionsService<TPlat> {
                     .unwrap_or(usize::max_value()),
                 usize::try_from(config.max_pending_transactions.get())
                     .unwrap_or(usize::max_value()),
+                config.max_pending_transactions_bytes.get(),
+                usize::try_from(config.max_pending_transactions_per_sender.get())
+                    .unwrap_or(usize::max_value()),
+                config.eviction_policy,
+                config.max_broadcast_peers,
+                config.reannounce_interval,
                 usize::try_from(config.max_concurrent_validations.get())
                     .unwrap_or(usize::max_value()),
             )),
@@ -214,6 +252,120 @@ impl<TPlat: Platform> TransactionsService<TPlat> {
             .await
             .unwrap();
     }
+
+    /// Returns the state of all the transactions that are currently pending in the service, in
+    /// other words that have been submitted but haven't been finalized or dropped yet.
+    ///
+    /// This can be used in order to pass these transactions to
+    /// [`TransactionsService::restore_transaction`] after a restart.
+    pub async fn pending_transactions(&self) -> Vec<SavedTransaction> {
+        let (send_back, rx) = oneshot::channel();
+
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::PendingTransactions { send_back })
+            .await
+            .unwrap();
+
+        rx.await.unwrap()
+    }
+
+    /// Adds back to the service a transaction that was returned by
+    /// [`TransactionsService::pending_transactions`], for example before a restart.
+    ///
+    /// The transaction is validated again and its status is tracked as if it had been submitted
+    /// with [`TransactionsService::submit_transaction`]. The channels later returned by
+    /// [`TransactionsService::submit_and_watch_transaction`] for this transaction immediately
+    /// receive [`SavedTransaction::latest_status`], and the channels that were already
+    /// watching the same transaction receive it as well if no status is known yet.
+    ///
+    /// The transaction is ignored if it can't be included in a block anymore according to
+    /// [`SavedTransaction::valid_until_block_number`].
+    pub async fn restore_transaction(&self, transaction: SavedTransaction) {
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::RestoreTransaction { transaction })
+            .await
+            .unwrap();
+    }
+
+    /// Bans the transactions that match the given [`TransactionBan`].
+    ///
+    /// The pending transactions that match are immediately dropped with
+    /// [`DropReason::Banned`] and are no longer gossiped to peers. Transactions that match and
+    /// that are submitted later are immediately dropped as well.
+    pub async fn ban(&self, ban: TransactionBan) {
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::Ban { ban })
+            .await
+            .unwrap();
+    }
+
+    /// Reverts a previous call to [`TransactionsService::ban`]. Has no effect if this ban
+    /// doesn't exist.
+    ///
+    /// The transactions that have been dropped because of the ban aren't submitted again.
+    pub async fn unban(&self, ban: TransactionBan) {
+        self.to_background
+            .lock()
+            .await
+            .send(ToBackground::Unban { ban })
+            .await
+            .unwrap();
+    }
+}
+
+/// See [`TransactionsService::ban`].
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum TransactionBan {
+    /// Bans the transaction whose BLAKE2 hash of the SCALE encoding is the given value.
+    TransactionHash([u8; 32]),
+    /// Bans all the transactions signed by the given account.
+    ///
+    /// Transactions whose signer is a `MultiAddress::Id` or a `MultiAddress::Address32` equal to
+    /// the given account are banned.
+    ///
+    /// > **Note**: The signer of a transaction can only be determined if the transaction uses
+    /// >           the format of the Substrate-based chains. Transactions whose signer can't be
+    /// >           determined are never banned through this variant. This includes the
+    /// >           transactions whose signer is a `MultiAddress::Index`, as finding the account
+    /// >           that corresponds to an index requires accessing the storage of the chain.
+    Sender([u8; 32]),
+}
+
+/// State of a transaction pending in the service. See
+/// [`TransactionsService::pending_transactions`].
+#[derive(Debug, Clone)]
+pub struct SavedTransaction {
+    /// SCALE encoding of the transaction.
+    pub scale_encoded: Vec<u8>,
+
+    /// Height of the best block at the time when the transaction was submitted. The transaction
+    /// can't be included in a block below this height.
+    pub submitted_block_number: u64,
+
+    /// Height of the last block the transaction can be included in, according to its latest
+    /// successful validation. `None` if the transaction hasn't been successfully validated.
+    pub valid_until_block_number: Option<u64>,
+
+    /// Latest status of the transaction. Always `None`, [`TransactionStatus::Broadcast`], or
+    /// [`TransactionStatus::IncludedBlockUpdate`].
+    pub latest_status: Option<TransactionStatus>,
+}
+
+/// See [`Config::eviction_policy`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum EvictionPolicy {
+    /// The newly-submitted transaction is refused.
+    RejectNew,
+    /// The transactions that have been submitted the earliest are removed from the service in
+    /// order to make room for the newly-submitted transaction. If the per-sender limit is
+    /// reached, only the transactions of the same sender are removed.
+    EvictOldest,
 }
 
 /// Update on the state of a transaction in the service.
@@ -236,6 +388,24 @@ pub enum TransactionStatus {
         block_hash: Option<([u8; 32], u32)>,
     },
 
+    /// Transaction is included in the block of the best chain with the given hash and at the
+    /// given index, and the Merkle proof of the events of this block has been downloaded.
+    ///
+    /// Always follows a [`TransactionStatus::IncludedBlockUpdate`] with the same block hash and
+    /// index, unless the download of the proof has failed, in which case no
+    /// [`TransactionStatus::InclusionProof`] is sent. Contrary to the other variants, this
+    /// status isn't sent to the channels that start watching the transaction afterwards.
+    InclusionProof {
+        /// Hash of the block the transaction is included in.
+        block_hash: [u8; 32],
+        /// Index of the transaction within the body of the block.
+        index: u32,
+        /// SCALE-encoded Merkle proof of the `System.Events` storage item of the block, verified
+        /// against the state trie root found in the header of the block. The events whose phase
+        /// is `ApplyExtrinsic(index)` are the ones emitted by the transaction.
+        events_proof: Vec<u8>,
+    },
+
     /// Transaction has been removed from the pool.
     ///
     /// This is always the last message sent back by the channel reporting the status.
@@ -256,8 +426,23 @@ pub enum DropReason {
 
     /// Transaction has been dropped because the maximum number of transactions in the pool has
     /// been reached.
+    ///
+    /// Depending on [`Config::eviction_policy`], the transaction has either been refused or has
+    /// been removed in order to make room for a more recent transaction. This also applies to the
+    /// other limits below.
     MaxPendingTransactionsReached,
 
+    /// Transaction has been dropped because the maximum total size of the transactions in the
+    /// pool has been reached.
+    MaxPendingTransactionsBytesReached,
+
+    /// Transaction has been dropped because the maximum number of transactions signed by the
+    /// same account has been reached.
+    MaxPendingTransactionsPerSenderReached,
+
+    /// Transaction has been dropped because it matches a ban. See [`TransactionsService::ban`].
+    Banned,
+
     /// Transaction has been dropped because it is invalid.
     Invalid(validate::TransactionValidityError),
 
@@ -297,6 +482,18 @@ enum ToBackground {
         transaction_bytes: Vec<u8>,
         updates_report: Option<mpsc::Sender<TransactionStatus>>,
     },
+    PendingTransactions {
+        send_back: oneshot::Sender<Vec<SavedTransaction>>,
+    },
+    RestoreTransaction {
+        transaction: SavedTransaction,
+    },
+    Ban {
+        ban: TransactionBan,
+    },
+    Unban {
+        ban: TransactionBan,
+    },
 }
 
 /// Background task running in parallel of the front service.
@@ -309,6 +506,11 @@ async fn background_task<TPlat: Platform>(
     mut from_foreground: mpsc::Receiver<ToBackground>,
     max_concurrent_downloads: usize,
     max_pending_transactions: usize,
+    max_pending_transactions_bytes: usize,
+    max_pending_transactions_per_sender: usize,
+    eviction_policy: EvictionPolicy,
+    max_broadcast_peers: NonZeroUsize,
+    reannounce_interval: Duration,
     max_concurrent_validations: usize,
 ) {
     let transactions_capacity = cmp::min(8, max_pending_transactions);
@@ -329,6 +531,15 @@ async fn background_task<TPlat: Platform>(
         next_reannounce: FuturesUnordered::new(),
         max_concurrent_downloads,
         max_pending_transactions,
+        max_pending_transactions_bytes,
+        max_pending_transactions_per_sender,
+        eviction_policy,
+        max_broadcast_peers,
+        reannounce_interval,
+        inclusion_proof_downloads: FuturesUnordered::new(),
+        banned_transactions: hashbrown::HashSet::with_hasher(Default::default()),
+        banned_senders: hashbrown::HashSet::with_hasher(Default::default()),
+        finalized_block_number: 0,
     };
 
     // TODO: must periodically re-send transactions that aren't included in block yet
@@ -354,6 +565,12 @@ async fn background_task<TPlat: Platform>(
         let initial_finalized_block_hash = header::hash_from_scale_encoded_header(
             &subscribe_all.finalized_block_scale_encoded_header,
         );
+        worker.finalized_block_number = header::decode(
+            &subscribe_all.finalized_block_scale_encoded_header,
+            worker.sync_service.block_number_bytes(),
+        )
+        .unwrap()
+        .number;
 
         // Drop all pending transactions of the pool.
         for (_, pending) in worker.pending_transactions.transactions_iter_mut() {
@@ -395,6 +612,7 @@ async fn background_task<TPlat: Platform>(
         worker.block_downloads.clear();
         worker.validations_in_progress.clear();
         worker.next_reannounce.clear();
+        worker.inclusion_proof_downloads.clear();
 
         log::debug!(
             target: &log_target,
@@ -638,6 +856,14 @@ async fn background_task<TPlat: Platform>(
                         },
                         Some(runtime_service::Notification::Finalized { hash, best_block_hash, .. }) => {
                             worker.set_best_block(&log_target, &best_block_hash);
+                            if let Some(block) = worker.pending_transactions.block_user_data(&hash) {
+                                worker.finalized_block_number = header::decode(
+                                    &block.scale_encoded_header,
+                                    worker.sync_service.block_number_bytes(),
+                                )
+                                .unwrap()
+                                .number;
+                            }
                             for pruned in worker
                                 .pending_transactions
                                 .set_finalized_block(&hash)
@@ -698,6 +924,7 @@ async fn background_task<TPlat: Platform>(
                             // We assume that there's no more than 2<<32 transactions per block.
                             let body_index = u32::try_from(body_index).unwrap();
                             tx.update_status(TransactionStatus::IncludedBlockUpdate { block_hash: Some((block_hash, body_index)) });
+                            worker.start_inclusion_proof_download(tx_id, block_hash, body_index);
                         }
 
                     } else {
@@ -709,6 +936,40 @@ async fn background_task<TPlat: Platform>(
                     }
                 },
 
+                (tx_id, block_hash, index, result) = worker.inclusion_proof_downloads.select_next_some() => {
+                    // The transaction might have been removed from the pool, or might no longer
+                    // be included in this block, in which case the proof is simply discarded.
+                    let tx = match worker.pending_transactions.transaction_user_data_mut(tx_id) {
+                        Some(tx) => tx,
+                        None => continue,
+                    };
+                    if !matches!(
+                        tx.latest_status,
+                        Some(TransactionStatus::IncludedBlockUpdate { block_hash: Some((h, i)) })
+                            if h == block_hash && i == index
+                    ) {
+                        continue;
+                    }
+
+                    match result {
+                        Ok(events_proof) => {
+                            tx.notify_status(&TransactionStatus::InclusionProof {
+                                block_hash,
+                                index,
+                                events_proof,
+                            });
+                        }
+                        Err(error) => {
+                            log::debug!(
+                                target: &log_target,
+                                "InclusionProofDownloads => Failed(block={}, error={})",
+                                HashDisplay(&block_hash),
+                                error
+                            );
+                        }
+                    }
+                },
+
                 maybe_reannounce_tx_id = worker.next_reannounce.select_next_some() => {
                     // A transaction reannounce future has finished. This doesn't necessarily mean
                     // that a validation actually needs to be reannounced. The provided
@@ -739,9 +1000,10 @@ async fn background_task<TPlat: Platform>(
                     // TODO: only announce if propagate is true
 
                     // Update transaction state for the next re-announce.
-                    tx.when_reannounce = now + Duration::from_secs(5);
+                    let reannounce_interval = worker.reannounce_interval;
+                    tx.when_reannounce = now + reannounce_interval;
                     worker.next_reannounce.push(async move {
-                        TPlat::sleep(Duration::from_secs(5)).await;
+                        TPlat::sleep(reannounce_interval).await;
                         maybe_reannounce_tx_id
                     }.boxed());
 
@@ -750,7 +1012,8 @@ async fn background_task<TPlat: Platform>(
                         .clone()
                         .announce_transaction(
                             worker.network_chain_index,
-                            worker.pending_transactions.scale_encoding(maybe_reannounce_tx_id).unwrap()
+                            worker.pending_transactions.scale_encoding(maybe_reannounce_tx_id).unwrap(),
+                            worker.max_broadcast_peers,
                         )
                         .await;
                     log::debug!(
@@ -826,6 +1089,19 @@ async fn background_task<TPlat: Platform>(
                                 maybe_validated_tx_id
                             }.boxed());
 
+                            // Update the window of blocks the transaction can be included in.
+                            let validated_block_number = header::decode(
+                                &worker.pending_transactions.block_user_data(&block_hash).unwrap().scale_encoded_header,
+                                worker.sync_service.block_number_bytes(),
+                            )
+                            .unwrap()
+                            .number;
+                            worker.pending_transactions
+                                .transaction_user_data_mut(maybe_validated_tx_id)
+                                .unwrap()
+                                .valid_until_block_number =
+                                    Some(validated_block_number.saturating_add(result.longevity.get()));
+
                             Ok(result)
                         }
                         Err(ValidationError::ObsoleteSubscription) => {
@@ -890,6 +1166,15 @@ async fn background_task<TPlat: Platform>(
                             transaction_bytes,
                             updates_report,
                         } => {
+                            // Transactions that match a ban are immediately dropped.
+                            let sender = transaction_sender(&transaction_bytes);
+                            if worker.is_banned(&blake2_hash(&transaction_bytes), sender.as_ref()) {
+                                if let Some(mut updates_report) = updates_report {
+                                    let _ = updates_report.try_send(TransactionStatus::Dropped(DropReason::Banned));
+                                }
+                                continue;
+                            }
+
                             // Handle the situation where the same transaction has already been
                             // submitted in the pool before.
                             let existing_tx_id = worker.pending_transactions
@@ -905,19 +1190,25 @@ async fn background_task<TPlat: Platform>(
                                 continue;
                             }
 
-                            // We intentionally limit the number of transactions in the pool,
-                            // and immediately drop new transactions of this limit is reached.
-                            if worker.pending_transactions.num_transactions() >= worker.max_pending_transactions {
+                            // We intentionally limit the size of the pool. Depending on the
+                            // eviction policy, either the new transaction is dropped or older
+                            // transactions are dropped in order to make room for it.
+                            if let Err(reason) = worker.make_room_for_transaction(&log_target, transaction_bytes.len(), sender.as_ref()) {
                                 if let Some(mut updates_report) = updates_report {
-                                    let _ = updates_report.try_send(TransactionStatus::Dropped(DropReason::MaxPendingTransactionsReached));
+                                    let _ = updates_report.try_send(TransactionStatus::Dropped(reason));
                                 }
                                 continue;
                             }
 
                             // Success path. Inserting in pool.
+                            let submitted_block_number = worker.best_block_number();
                             worker
                                 .pending_transactions
                                 .add_unvalidated(transaction_bytes, PendingTransaction {
+                                    submitted_at: TPlat::now(),
+                                    sender,
+                                    submitted_block_number,
+                                    valid_until_block_number: None,
                                     when_reannounce: TPlat::now(),
                                     status_update: {
                                         let mut vec = Vec::with_capacity(1);
@@ -930,6 +1221,110 @@ async fn background_task<TPlat: Platform>(
                                     validation_in_progress: None,
                                 });
                         }
+                        ToBackground::PendingTransactions { send_back } => {
+                            let _ = send_back.send(
+                                worker.pending_transactions
+                                    .transactions_iter()
+                                    .map(|(tx_id, tx)| SavedTransaction {
+                                        scale_encoded: worker.pending_transactions.scale_encoding(tx_id).unwrap().to_vec(),
+                                        submitted_block_number: tx.submitted_block_number,
+                                        valid_until_block_number: tx.valid_until_block_number,
+                                        latest_status: tx.latest_status.clone(),
+                                    })
+                                    .collect()
+                            );
+                        }
+                        ToBackground::RestoreTransaction { transaction } => {
+                            let tx_hash = blake2_hash(&transaction.scale_encoded);
+                            let sender = transaction_sender(&transaction.scale_encoded);
+                            if worker.is_banned(&tx_hash, sender.as_ref()) {
+                                continue;
+                            }
+
+                            if is_expired(&transaction, worker.finalized_block_number) {
+                                log::debug!(
+                                    target: &log_target,
+                                    "Restore => Expired(tx_hash={}, valid_until={:?}, finalized={})",
+                                    HashDisplay(&tx_hash),
+                                    transaction.valid_until_block_number,
+                                    worker.finalized_block_number
+                                );
+                                continue;
+                            }
+
+                            // Only the statuses that describe a pending transaction are restored.
+                            let latest_status = transaction.latest_status.filter(|status| matches!(
+                                status,
+                                TransactionStatus::Broadcast(_) | TransactionStatus::IncludedBlockUpdate { .. }
+                            ));
+
+                            // The same transaction might have been submitted again in the
+                            // meanwhile, for example by a JSON-RPC client that resumes watching
+                            // it. In that situation, the saved state is merged in the existing
+                            // transaction and reported to the channels watching it.
+                            let existing_tx_id = worker.pending_transactions
+                                .find_transaction(&transaction.scale_encoded)
+                                .next();
+                            if let Some(existing_tx_id) = existing_tx_id {
+                                let existing_tx = worker.pending_transactions
+                                    .transaction_user_data_mut(existing_tx_id)
+                                    .unwrap();
+                                existing_tx.restore(transaction.submitted_block_number, transaction.valid_until_block_number, latest_status);
+                                continue;
+                            }
+
+                            if let Err(reason) = worker.make_room_for_transaction(&log_target, transaction.scale_encoded.len(), sender.as_ref()) {
+                                log::debug!(
+                                    target: &log_target,
+                                    "Restore => Refused(tx_hash={}, reason={:?})",
+                                    HashDisplay(&tx_hash),
+                                    reason
+                                );
+                                continue;
+                            }
+
+                            log::debug!(
+                                target: &log_target,
+                                "Restore => Success(tx_hash={})",
+                                HashDisplay(&tx_hash)
+                            );
+
+                            // The saved `valid_until_block_number` is kept until the transaction
+                            // has been validated again.
+                            worker
+                                .pending_transactions
+                                .add_unvalidated(transaction.scale_encoded, PendingTransaction {
+                                    submitted_at: TPlat::now(),
+                                    sender,
+                                    submitted_block_number: transaction.submitted_block_number,
+                                    valid_until_block_number: transaction.valid_until_block_number,
+                                    when_reannounce: TPlat::now(),
+                                    status_update: Vec::new(),
+                                    latest_status,
+                                    validation_in_progress: None,
+                                });
+                        }
+                        ToBackground::Ban { ban } => {
+                            match ban {
+                                TransactionBan::TransactionHash(hash) => {
+                                    worker.banned_transactions.insert(hash);
+                                }
+                                TransactionBan::Sender(sender) => {
+                                    worker.banned_senders.insert(sender);
+                                }
+                            }
+                            worker.drop_banned_transactions(&log_target);
+                        }
+                        ToBackground::Unban { ban } => {
+                            match ban {
+                                TransactionBan::TransactionHash(hash) => {
+                                    worker.banned_transactions.remove(&hash);
+                                }
+                                TransactionBan::Sender(sender) => {
+                                    worker.banned_senders.remove(&sender);
+                                }
+                            }
+                        }
                     }
                 }
             }
@@ -968,6 +1363,42 @@ struct Worker<TPlat: Platform> {
     /// See [`Config::max_pending_transactions`].
     max_pending_transactions: usize,
 
+    /// See [`Config::max_pending_transactions_bytes`].
+    max_pending_transactions_bytes: usize,
+
+    /// See [`Config::max_pending_transactions_per_sender`].
+    max_pending_transactions_per_sender: usize,
+
+    /// See [`Config::eviction_policy`].
+    eviction_policy: EvictionPolicy,
+
+    /// See [`Config::max_broadcast_peers`].
+    max_broadcast_peers: NonZeroUsize,
+
+    /// See [`Config::reannounce_interval`].
+    reannounce_interval: Duration,
+
+    /// List of ongoing downloads of the proof of the events of a block a transaction is included
+    /// in. The output of the future contains the transaction, block hash and index that the
+    /// download has been started for.
+    inclusion_proof_downloads: FuturesUnordered<
+        future::BoxFuture<
+            'static,
+            (
+                light_pool::TransactionId,
+                [u8; 32],
+                u32,
+                Result<Vec<u8>, sync_service::StorageQueryError>,
+            ),
+        >,
+    >,
+
+    /// Hashes of the transactions banned with [`TransactionBan::TransactionHash`].
+    banned_transactions: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
+    /// Accounts banned with [`TransactionBan::Sender`].
+    banned_senders: hashbrown::HashSet<[u8; 32], fnv::FnvBuildHasher>,
+
     /// List of ongoing block body downloads.
     /// The output of the future is a block hash and a block body.
     block_downloads:
@@ -993,9 +1424,192 @@ struct Worker<TPlat: Platform> {
     /// See [`Config::max_concurrent_downloads`]. Maximum number of elements in
     /// [`Worker::block_downloads`].
     max_concurrent_downloads: usize,
+
+    /// Height of the current finalized block.
+    finalized_block_number: u64,
 }
 
 impl<TPlat: Platform> Worker<TPlat> {
+    /// Returns the height of the current best block.
+    fn best_block_number(&self) -> u64 {
+        match self
+            .pending_transactions
+            .block_user_data(self.pending_transactions.best_block_hash())
+        {
+            Some(block) => {
+                header::decode(
+                    &block.scale_encoded_header,
+                    self.sync_service.block_number_bytes(),
+                )
+                .unwrap()
+                .number
+            }
+            // The best block is the finalized block, which isn't stored in the pool.
+            None => self.finalized_block_number,
+        }
+    }
+
+    /// Starts downloading the proof of the events of the given block, in order to later send a
+    /// [`TransactionStatus::InclusionProof`] concerning the given transaction.
+    ///
+    /// Does nothing if nobody is watching the transaction.
+    fn start_inclusion_proof_download(
+        &mut self,
+        tx_id: light_pool::TransactionId,
+        block_hash: [u8; 32],
+        index: u32,
+    ) {
+        if self
+            .pending_transactions
+            .transaction_user_data(tx_id)
+            .map_or(true, |tx| tx.status_update.is_empty())
+        {
+            return;
+        }
+
+        let (block_number, state_root) = match self
+            .pending_transactions
+            .block_user_data(&block_hash)
+            .and_then(|block| {
+                header::decode(
+                    &block.scale_encoded_header,
+                    self.sync_service.block_number_bytes(),
+                )
+                .ok()
+            }) {
+            Some(header) => (header.number, *header.state_root),
+            None => return,
+        };
+
+        let sync_service = self.sync_service.clone();
+        self.inclusion_proof_downloads.push(
+            async move {
+                let result = sync_service
+                    .storage_proof_query(
+                        block_number,
+                        &block_hash,
+                        &state_root,
+                        iter::once(&SYSTEM_EVENTS_STORAGE_KEY[..]),
+                        3,
+                        Duration::from_secs(8),
+                    )
+                    .await;
+                (tx_id, block_hash, index, result)
+            }
+            .boxed(),
+        );
+    }
+
+    /// Returns `true` if a transaction with the given hash and sender matches one of the bans.
+    fn is_banned(&self, transaction_hash: &[u8; 32], sender: Option<&TransactionSender>) -> bool {
+        if self.banned_transactions.contains(transaction_hash) {
+            return true;
+        }
+
+        match sender {
+            Some(TransactionSender::AccountId(account_id)) => {
+                self.banned_senders.contains(account_id)
+            }
+            _ => false,
+        }
+    }
+
+    /// Removes from [`Worker::pending_transactions`] all the transactions that match one of the
+    /// bans.
+    fn drop_banned_transactions(&mut self, log_target: &str) {
+        let to_remove = self
+            .pending_transactions
+            .transactions_iter()
+            .filter(|(tx_id, tx)| {
+                let hash = blake2_hash(self.pending_transactions.scale_encoding(*tx_id).unwrap());
+                self.is_banned(&hash, tx.sender.as_ref())
+            })
+            .map(|(tx_id, _)| tx_id)
+            .collect::<Vec<_>>();
+
+        for tx_id in to_remove {
+            let (tx_body, mut tx) = self.pending_transactions.remove_transaction(tx_id);
+            log::debug!(
+                target: log_target,
+                "Banned(tx_hash={})",
+                HashDisplay(&blake2_hash(&tx_body))
+            );
+            tx.update_status(TransactionStatus::Dropped(DropReason::Banned));
+        }
+    }
+
+    /// Removes transactions from [`Worker::pending_transactions`], if allowed by the eviction
+    /// policy, so that a new transaction of the given size and sender fits within the limits of
+    /// the pool.
+    ///
+    /// Returns an error containing the reason why the new transaction must be dropped if it
+    /// doesn't fit.
+    fn make_room_for_transaction(
+        &mut self,
+        log_target: &str,
+        new_tx_size: usize,
+        new_tx_sender: Option<&TransactionSender>,
+    ) -> Result<(), DropReason> {
+        // A transaction that is larger than the pool itself is always refused, as there is no
+        // point in evicting transactions to make room for it.
+        if new_tx_size > self.max_pending_transactions_bytes {
+            return Err(DropReason::MaxPendingTransactionsBytesReached);
+        }
+
+        loop {
+            // Determine which limit, if any, the new transaction would exceed.
+            let num_same_sender = new_tx_sender.map_or(0, |sender| {
+                self.pending_transactions
+                    .transactions_iter()
+                    .filter(|(_, tx)| tx.sender.as_ref() == Some(sender))
+                    .count()
+            });
+            let total_bytes = self
+                .pending_transactions
+                .transactions_iter()
+                .map(|(id, _)| self.pending_transactions.scale_encoding(id).unwrap().len())
+                .sum::<usize>();
+
+            let (reason, only_same_sender) = if num_same_sender
+                >= self.max_pending_transactions_per_sender
+            {
+                (DropReason::MaxPendingTransactionsPerSenderReached, true)
+            } else if self.pending_transactions.num_transactions() >= self.max_pending_transactions
+            {
+                (DropReason::MaxPendingTransactionsReached, false)
+            } else if total_bytes.saturating_add(new_tx_size) > self.max_pending_transactions_bytes
+            {
+                (DropReason::MaxPendingTransactionsBytesReached, false)
+            } else {
+                return Ok(());
+            };
+
+            if self.eviction_policy == EvictionPolicy::RejectNew {
+                return Err(reason);
+            }
+
+            // Evict the transaction that has been submitted the earliest.
+            let Some(to_evict) = self
+                .pending_transactions
+                .transactions_iter()
+                .filter(|(_, tx)| !only_same_sender || tx.sender.as_ref() == new_tx_sender)
+                .min_by_key(|(_, tx)| tx.submitted_at.clone())
+                .map(|(id, _)| id)
+            else {
+                return Err(reason);
+            };
+
+            let (tx_body, mut tx) = self.pending_transactions.remove_transaction(to_evict);
+            log::debug!(
+                target: log_target,
+                "Evicted(tx_hash={}, reason={:?})",
+                HashDisplay(&blake2_hash(&tx_body)),
+                reason
+            );
+            tx.update_status(TransactionStatus::Dropped(reason));
+        }
+    }
+
     /// Update the best block. Must have been previously inserted with
     /// [`light_pool::LightPool::add_block`].
     fn set_best_block(&mut self, log_target: &str, new_best_block_hash: &[u8; 32]) {
@@ -1039,6 +1653,7 @@ impl<TPlat: Platform> Worker<TPlat> {
             tx.update_status(TransactionStatus::IncludedBlockUpdate {
                 block_hash: Some((block_hash, block_body_index)),
             });
+            self.start_inclusion_proof_download(tx_id, block_hash, block_body_index);
         }
     }
 }
@@ -1055,6 +1670,19 @@ struct Block {
 }
 
 struct PendingTransaction<TPlat: Platform> {
+    /// Moment when the transaction has been submitted.
+    submitted_at: TPlat::Instant,
+
+    /// Account that has signed the transaction, if it could be determined. See
+    /// [`transaction_sender`].
+    sender: Option<TransactionSender>,
+
+    /// See [`SavedTransaction::submitted_block_number`].
+    submitted_block_number: u64,
+
+    /// See [`SavedTransaction::valid_until_block_number`].
+    valid_until_block_number: Option<u64>,
+
     /// Earliest moment when to gossip the transaction on the network again.
     ///
     /// This should be interpreted as the moment before which to not reannounce, rather than the
@@ -1091,15 +1719,41 @@ impl<TPlat: Platform> PendingTransaction<TPlat> {
         self.status_update.push(channel);
     }
 
+    /// Merges the state of a [`SavedTransaction`] of the same transaction in this transaction.
+    ///
+    /// The saved status is reported to the channels watching the transaction, unless a status
+    /// is already known.
+    fn restore(
+        &mut self,
+        submitted_block_number: u64,
+        valid_until_block_number: Option<u64>,
+        latest_status: Option<TransactionStatus>,
+    ) {
+        self.submitted_block_number = cmp::min(self.submitted_block_number, submitted_block_number);
+        if self.valid_until_block_number.is_none() {
+            self.valid_until_block_number = valid_until_block_number;
+        }
+        if self.latest_status.is_none() {
+            if let Some(latest_status) = latest_status {
+                self.update_status(latest_status);
+            }
+        }
+    }
+
     fn update_status(&mut self, status: TransactionStatus) {
+        self.notify_status(&status);
+        self.latest_status = Some(status);
+    }
+
+    /// Sends the given status to the channels of [`PendingTransaction::status_update`] without
+    /// modifying [`PendingTransaction::latest_status`].
+    fn notify_status(&mut self, status: &TransactionStatus) {
         for n in 0..self.status_update.len() {
             let mut channel = self.status_update.swap_remove(n);
             if channel.try_send(status.clone()).is_ok() {
                 self.status_update.push(channel);
             }
         }
-
-        self.latest_status = Some(status);
     }
 }
 
@@ -1237,7 +1891,250 @@ async fn validate_transaction<TPlat: Platform>(
     }
 }
 
+/// Returns `true` if the given transaction can't be included in any block that is a descendant
+/// of the finalized block with the given height.
+fn is_expired(transaction: &SavedTransaction, finalized_block_number: u64) -> bool {
+    transaction
+        .valid_until_block_number
+        .map_or(false, |n| n <= finalized_block_number)
+}
+
+/// Signer of a transaction. See [`transaction_sender`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TransactionSender {
+    /// `MultiAddress::Id` or `MultiAddress::Address32`. Both designate an account id.
+    AccountId([u8; 32]),
+    /// `MultiAddress::Index`. The account that corresponds to this index can't be determined
+    /// without accessing the storage of the chain.
+    Index(u64),
+    /// `MultiAddress::Raw`.
+    Raw(Vec<u8>),
+    /// `MultiAddress::Address20`.
+    Address20([u8; 20]),
+}
+
+/// Returns the signer of the given SCALE-encoded transaction, or `None` if the transaction isn't
+/// signed or if its format isn't recognized.
+///
+/// Only the format of the transactions of the Substrate-based chains that use a `MultiAddress`
+/// to identify the signer is recognized. In this format, the transaction starts with its length
+/// as a SCALE-compact, followed with a version byte whose highest bit indicates whether the
+/// transaction is signed, followed with the `MultiAddress` of the signer.
+fn transaction_sender(scale_encoded: &[u8]) -> Option<TransactionSender> {
+    // Skip the length prefix.
+    let (_, rest) = decode_scale_compact(scale_encoded)?;
+
+    // Only version 4 of the format is recognized.
+    if *rest.first()? != 0b1000_0100 {
+        return None;
+    }
+
+    let (variant, rest) = rest.get(1..)?.split_first()?;
+    match variant {
+        0 | 3 => Some(TransactionSender::AccountId(
+            <[u8; 32]>::try_from(rest.get(..32)?).unwrap(),
+        )),
+        1 => Some(TransactionSender::Index(decode_scale_compact(rest)?.0)),
+        2 => {
+            let (len, rest) = decode_scale_compact(rest)?;
+            let len = usize::try_from(len).ok()?;
+            Some(TransactionSender::Raw(rest.get(..len)?.to_vec()))
+        }
+        4 => Some(TransactionSender::Address20(
+            <[u8; 20]>::try_from(rest.get(..20)?).unwrap(),
+        )),
+        _ => None,
+    }
+}
+
+/// Decodes a SCALE-compact number that fits in a `u64` at the start of the given bytes. Returns
+/// the number and the bytes that follow it, or `None` if the encoding is invalid.
+fn decode_scale_compact(bytes: &[u8]) -> Option<(u64, &[u8])> {
+    let first = *bytes.first()?;
+    let (size, value) = match first & 0b11 {
+        0b00 => (1, u64::from(first >> 2)),
+        0b01 => (
+            2,
+            u64::from(u16::from_le_bytes(<[u8; 2]>::try_from(bytes.get(..2)?).unwrap()) >> 2),
+        ),
+        0b10 => (
+            4,
+            u64::from(u32::from_le_bytes(<[u8; 4]>::try_from(bytes.get(..4)?).unwrap()) >> 2),
+        ),
+        _ => {
+            let num_bytes = usize::from(first >> 2) + 4;
+            if num_bytes > 8 {
+                return None;
+            }
+            let mut value = [0; 8];
+            value[..num_bytes].copy_from_slice(bytes.get(1..1 + num_bytes)?);
+            (1 + num_bytes, u64::from_le_bytes(value))
+        }
+    };
+    Some((value, &bytes[size..]))
+}
+
+/// Key of the `System.Events` storage item, in other words the concatenation of
+/// `twox128("System")` and `twox128("Events")`.
+const SYSTEM_EVENTS_STORAGE_KEY: [u8; 32] = [
+    0x26, 0xaa, 0x39, 0x4e, 0xea, 0x56, 0x30, 0xe0, 0x7c, 0x48, 0xae, 0x0c, 0x95, 0x58, 0xce, 0xf7,
+    0x80, 0xd4, 0x1e, 0x5e, 0x16, 0x05, 0x67, 0x65, 0xbc, 0x84, 0x61, 0x85, 0x10, 0x72, 0xc9, 0xd7,
+];
+
 /// Utility. Calculates the BLAKE2 hash of the given bytes.
 fn blake2_hash(bytes: &[u8]) -> [u8; 32] {
     <[u8; 32]>::try_from(blake2_rfc::blake2b::blake2b(32, &[], bytes).as_bytes()).unwrap()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        is_expired, transaction_sender, PendingTransaction, SavedTransaction, TransactionSender,
+        TransactionStatus,
+    };
+    use crate::platform::{async_std::AsyncStdTcpWebSocket, Platform as _};
+    use futures::channel::mpsc;
+
+    fn pending_transaction(
+        latest_status: Option<TransactionStatus>,
+    ) -> PendingTransaction<AsyncStdTcpWebSocket> {
+        PendingTransaction {
+            submitted_at: AsyncStdTcpWebSocket::now(),
+            sender: None,
+            submitted_block_number: 100,
+            valid_until_block_number: None,
+            when_reannounce: AsyncStdTcpWebSocket::now(),
+            status_update: Vec::new(),
+            latest_status,
+            validation_in_progress: None,
+        }
+    }
+
+    #[test]
+    fn expired_transaction() {
+        let transaction = |valid_until_block_number| SavedTransaction {
+            scale_encoded: vec![1, 2, 3],
+            submitted_block_number: 10,
+            valid_until_block_number,
+            latest_status: None,
+        };
+
+        assert!(!is_expired(&transaction(None), 1_000_000));
+        assert!(!is_expired(&transaction(Some(74)), 73));
+        assert!(is_expired(&transaction(Some(74)), 74));
+        assert!(is_expired(&transaction(Some(74)), 75));
+    }
+
+    #[test]
+    fn restore_resumes_watchers() {
+        // A channel is watching the transaction, which has been submitted again before being
+        // restored and has no status yet.
+        let mut tx = pending_transaction(None);
+        let (watcher, mut rx) = mpsc::channel(8);
+        tx.add_status_update(watcher);
+        assert!(rx.try_next().is_err());
+
+        tx.restore(
+            50,
+            Some(114),
+            Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: Some(([1; 32], 2)),
+            }),
+        );
+
+        assert_eq!(tx.submitted_block_number, 50);
+        assert_eq!(tx.valid_until_block_number, Some(114));
+        assert!(matches!(
+            rx.try_next(),
+            Ok(Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: Some((h, 2))
+            })) if h == [1; 32]
+        ));
+
+        // Channels that start watching afterwards immediately receive the restored status.
+        let (watcher, mut rx) = mpsc::channel(8);
+        tx.add_status_update(watcher);
+        assert!(matches!(
+            rx.try_next(),
+            Ok(Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: Some((h, 2))
+            })) if h == [1; 32]
+        ));
+    }
+
+    #[test]
+    fn restore_keeps_more_recent_status() {
+        let mut tx = pending_transaction(Some(TransactionStatus::IncludedBlockUpdate {
+            block_hash: None,
+        }));
+        tx.valid_until_block_number = Some(200);
+        let (watcher, mut rx) = mpsc::channel(8);
+        tx.add_status_update(watcher);
+        assert!(matches!(
+            rx.try_next(),
+            Ok(Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: None
+            }))
+        ));
+
+        tx.restore(
+            150,
+            Some(114),
+            Some(TransactionStatus::IncludedBlockUpdate {
+                block_hash: Some(([1; 32], 2)),
+            }),
+        );
+
+        assert_eq!(tx.submitted_block_number, 100);
+        assert_eq!(tx.valid_until_block_number, Some(200));
+        assert!(rx.try_next().is_err());
+    }
+
+    #[test]
+    fn transaction_sender_multi_address() {
+        // Length prefix, version byte of a signed transaction, then the `MultiAddress`. The
+        // rest of the transaction isn't looked at.
+        let tx = |address: &[u8]| {
+            let mut tx = vec![0, 0b1000_0100];
+            tx.extend_from_slice(address);
+            tx.extend_from_slice(&[0xff; 8]);
+            tx
+        };
+
+        let mut id = vec![0];
+        id.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            transaction_sender(&tx(&id)),
+            Some(TransactionSender::AccountId([7; 32]))
+        );
+
+        let mut address32 = vec![3];
+        address32.extend_from_slice(&[7; 32]);
+        assert_eq!(
+            transaction_sender(&tx(&address32)),
+            Some(TransactionSender::AccountId([7; 32]))
+        );
+
+        assert_eq!(
+            transaction_sender(&tx(&[1, 0b0001_0001, 0b0000_0001])),
+            Some(TransactionSender::Index(68))
+        );
+
+        assert_eq!(
+            transaction_sender(&tx(&[2, 3 << 2, 1, 2, 3])),
+            Some(TransactionSender::Raw(vec![1, 2, 3]))
+        );
+
+        let mut address20 = vec![4];
+        address20.extend_from_slice(&[9; 20]);
+        assert_eq!(
+            transaction_sender(&tx(&address20)),
+            Some(TransactionSender::Address20([9; 20]))
+        );
+
+        // Truncated address, unknown variant, and unsigned transaction.
+        assert_eq!(transaction_sender(&[0, 0b1000_0100, 0, 7, 7]), None);
+        assert_eq!(transaction_sender(&tx(&[5])), None);
+        assert_eq!(transaction_sender(&[0, 0b0000_0100, 0, 7]), None);
+    }
+}
@@ -25,12 +25,12 @@ import * as fs from 'node:fs';
 // List of files containing chains available to the user.
 // The first item has a specific role in that we always connect to it at initialization.
 const chainSpecsFiles = [
-    //'../../demo-chain-specs/westend.json',
+    //'../../light-base/chain-specs/westend.json',
     //'../../demo-chain-specs/westend-westmint.json',
-    '../../demo-chain-specs/polkadot.json',
+    '../../light-base/chain-specs/polkadot.json',
     '../../demo-chain-specs/astar.json',
     //'../../demo-chain-specs/polkadot-acala.json',
-    //'../../demo-chain-specs/kusama.json',
+    //'../../light-base/chain-specs/kusama.json',
     //'../../demo-chain-specs/kusama-statemine.json',
     //'../../demo-chain-specs/kusama-karura.json',
     //'../../demo-chain-specs/rococo.json',
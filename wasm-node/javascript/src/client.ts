@@ -15,7 +15,7 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-import { MalformedJsonRpcError, PlatformBindings, start as startInstance } from './instance/instance.js';
+import { JsonRpcMethodsFilter, MalformedJsonRpcError, PlatformBindings, start as startInstance } from './instance/instance.js';
 
 export { MalformedJsonRpcError, QueueFullError, CrashError } from './instance/instance.js';
 
@@ -83,6 +83,31 @@ export interface Client {
    */
   addChain(options: AddChainOptions): Promise<Chain>;
 
+  /**
+   * Modifies which log messages the client reports through the log callback, for example in
+   * order to temporarily obtain more details about a specific component while diagnosing an
+   * issue.
+   *
+   * `directives` is a list of directives separated by `,`. Each directive is either
+   * `<target>=<level>` or `<level>`, where `<level>` is a number between `0` and `5` with the same
+   * meaning as {@link ClientOptions.maxLogLevel}, or one of `off`, `error`, `warn`, `info`,
+   * `debug`, `trace`. A directive without a target modifies the level of all the targets not
+   * mentioned in any directive, and defaults to {@link ClientOptions.maxLogLevel}.
+   *
+   * A `<target>` applies to the log messages whose target is either identical or starts with
+   * `<target>-`. For example, `sync=5` enables all the logs related to the synchronization of all
+   * chains, while `json-rpc-polkadot=4` enables the debug logs of the JSON-RPC service of the
+   * chain whose logs are prefixed with `polkadot`. Other targets include `network` and
+   * `runtime`.
+   *
+   * Directives are cumulative with the ones passed during previous calls.
+   *
+   * @throws {@link AlreadyDestroyedError} If the client has been terminated earlier.
+   * @throws {@link CrashError} If the background client has crashed.
+   * @throws {Error} If the directives are invalid, in which case the filter is left unmodified.
+   */
+  setLogFilter(directives: string): Promise<void>;
+
   /**
    * Terminates the client.
    *
@@ -174,6 +199,46 @@ export interface Chain {
    * @throws {@link CrashError} If the background client has crashed.
    */
   remove(): void;
+
+  /**
+   * Returns the number of milliseconds that have been spent executing the background tasks of
+   * this chain since it has been added.
+   *
+   * When multiple chains are running, this makes it possible to determine which chain is
+   * responsible for most of the CPU usage, and potentially remove it. Chains whose specification
+   * is identical share the same background tasks, and thus report the same value.
+   *
+   * @throws {@link AlreadyDestroyedError} If the chain has been removed or the client has been terminated.
+   * @throws {@link CrashError} If the background client has crashed.
+   */
+  cpuTimeMs(): number;
+
+  /**
+   * Suspends the networking and synchronization of this chain, while keeping its state in
+   * memory, until {@link Chain.resume} is called.
+   *
+   * This is a cheaper alternative to removing the chain and adding it back later, for example
+   * for chains that are temporarily not shown to the user. JSON-RPC requests continue to be
+   * accepted, but the requests that need to access the network don't make progress while the
+   * chain is paused. The relay chain of a parachain that isn't paused continues to run, and
+   * chains whose specification is identical are only paused if they are all paused.
+   *
+   * Does nothing if the chain is already paused.
+   *
+   * @throws {@link AlreadyDestroyedError} If the chain has been removed or the client has been terminated.
+   * @throws {@link CrashError} If the background client has crashed.
+   */
+  pause(): void;
+
+  /**
+   * Resumes the chain after it has been paused with {@link Chain.pause}.
+   *
+   * Does nothing if the chain isn't paused.
+   *
+   * @throws {@link AlreadyDestroyedError} If the chain has been removed or the client has been terminated.
+   * @throws {@link CrashError} If the background client has crashed.
+   */
+  resume(): void;
 }
 
 /**
@@ -197,7 +262,8 @@ export interface ClientOptions {
   logCallback?: LogCallback;
 
   /**
-   * The client will never call the log callback with a value of `level` superior to this value.
+   * The client will never call the log callback with a value of `level` superior to this value,
+   * unless more logs are enabled with {@link Client.setLogFilter}.
    * Defaults to 3.
    *
    * While this filtering could be done manually in the `logCallback`, passing a maximum log level
@@ -219,6 +285,17 @@ export interface ClientOptions {
    */
   cpuRateLimit?: number;
 
+  /**
+   * Maximum allowed difference, in milliseconds, between the local clock and the clock of the
+   * node that authored a block.
+   *
+   * Blocks that pretend to come from further in the future than this tolerance are considered
+   * invalid. Increasing this value makes it possible to follow chains on devices whose clock is
+   * behind.
+   * Defaults to `30000` if no value is provided.
+   */
+  clockDriftToleranceMs?: number;
+
   /**
    * If `true`, then the client will never open any TCP connection.
    * Defaults to `false`.
@@ -296,6 +373,20 @@ export interface AddChainOptions {
    */
   chainSpec: string;
 
+  /**
+   * Storage of the genesis block of the chain, provided separately from the chain specification.
+   *
+   * If provided, replaces the genesis storage found in the chain specification, if any. This
+   * makes it possible to use a chain specification that only contains the hash of the root of
+   * the genesis storage trie (`genesis.stateRootHash`), and to host the genesis storage, which
+   * can be very large, as a separate asset.
+   *
+   * The genesis storage is a binary blob that consists in a concatenation of storage items. Each
+   * storage item consists of the SCALE-compact-encoded length of the key, the key, the
+   * SCALE-compact-encoded length of the value, and the value.
+   */
+  genesisStorage?: Uint8Array;
+
   /**
    * Content of the database of this chain.
    *
@@ -324,6 +415,38 @@ export interface AddChainOptions {
    */
   databaseContent?: string;
 
+  /**
+   * If provided, smoldot periodically exports the latest finalized state of the chain, in the
+   * same format as the `chainHead_unstable_finalizedDatabase` JSON-RPC function, and passes it to
+   * `callback`. The value can then be passed as {@link AddChainOptions.databaseContent} the next
+   * time the chain is added.
+   *
+   * No checkpoint is exported while the chain is syncing, for example during the initial warp
+   * sync. The next checkpoint is generated `periodMs` milliseconds after the previous one.
+   *
+   * `maxSizeBytes` is the maximum size of each checkpoint, and defaults to the largest possible
+   * value. Just like for `chainHead_unstable_finalizedDatabase`, a truncated or invalid checkpoint
+   * is intentionally passed if this limit is too low to fit all the information.
+   */
+  checkpointRefresh?: {
+    periodMs: number,
+    maxSizeBytes?: number,
+    callback: (checkpoint: string) => void,
+  };
+
+  /**
+   * Number of different peers that a warp sync proof is requested from. Smoldot waits until it
+   * is connected to that many peers before requesting the proofs, and only uses them if they
+   * agree with each other. This makes it more difficult for an attacker that controls the
+   * network access of smoldot to make it warp sync to an old block.
+   *
+   * A chain whose network contains fewer nodes than this value, such as a test network with a
+   * single node, never finishes warp syncing. Set this value to `1` for such chains.
+   *
+   * Defaults to `3`. Ignored if the chain is a parachain.
+   */
+  warpSyncMinDistinctPeers?: number;
+
   /**
    * If `chainSpec` concerns a parachain, contains the list of chains whose `id` smoldot will try
    * to match with the parachain's `relayChain`.
@@ -365,6 +488,20 @@ export interface AddChainOptions {
    * this chain.
    */
   disableJsonRpc?: boolean,
+
+  /**
+   * Restricts the JSON-RPC methods that this chain serves.
+   *
+   * If `allow` is provided, only the methods matching one of the patterns are served. If `deny`
+   * is provided, all the methods are served except for the ones matching one of the patterns.
+   * Each pattern is either the name of a method or a prefix followed with `*`, for example
+   * `author_*` or `chainHead_unstable_follow`. Calling a method that isn't served results in the
+   * same error as calling a method that doesn't exist.
+   *
+   * Defaults to serving all the methods. Ignored if {@link AddChainOptions.disableJsonRpc} is
+   * `true`.
+   */
+  jsonRpcMethodsFilter?: { allow: string[] } | { deny: string[] },
 }
 
 // This function is similar to the `start` function found in `index.ts`, except with an extra
@@ -407,6 +544,7 @@ export function start(options: ClientOptions, platformBindings: PlatformBindings
     // the moment, we enable it all the time, except if the user has logging disabled altogether.
     enableCurrentTask: options.maxLogLevel ? options.maxLogLevel >= 1 : true,
     cpuRateLimit: options.cpuRateLimit || 1.0,
+    clockDriftToleranceMs: options.clockDriftToleranceMs !== undefined ? options.clockDriftToleranceMs : 30000,
   }, platformBindings);
 
   return {
@@ -431,7 +569,25 @@ export function start(options: ClientOptions, platformBindings: PlatformBindings
         }
       }
 
-      const outcome = await instance.addChain(options.chainSpec, typeof options.databaseContent === 'string' ? options.databaseContent : "", potentialRelayChainsIds, !!options.disableJsonRpc);
+      if (options.genesisStorage !== undefined && !(options.genesisStorage instanceof Uint8Array))
+        throw new Error("Genesis storage must be a Uint8Array");
+
+      let jsonRpcMethodsFilter: JsonRpcMethodsFilter = { mode: 0, patterns: [] };
+      if (options.jsonRpcMethodsFilter) {
+        jsonRpcMethodsFilter = 'allow' in options.jsonRpcMethodsFilter ?
+          { mode: 1, patterns: options.jsonRpcMethodsFilter.allow } :
+          { mode: 2, patterns: options.jsonRpcMethodsFilter.deny };
+        for (const pattern of jsonRpcMethodsFilter.patterns) {
+          if (typeof pattern !== 'string' || pattern.includes(','))
+            throw new Error("Invalid JSON-RPC methods filter pattern: " + pattern);
+        }
+      }
+
+      const outcome = await instance.addChain(options.chainSpec, options.genesisStorage, typeof options.databaseContent === 'string' ? options.databaseContent : "", potentialRelayChainsIds, !!options.disableJsonRpc, jsonRpcMethodsFilter, options.checkpointRefresh ? {
+        periodMs: options.checkpointRefresh.periodMs,
+        maxSizeBytes: options.checkpointRefresh.maxSizeBytes !== undefined ? options.checkpointRefresh.maxSizeBytes : 0xffffffff,
+        callback: options.checkpointRefresh.callback,
+      } : undefined, options.warpSyncMinDistinctPeers !== undefined ? Math.max(1, options.warpSyncMinDistinctPeers) : 3);
 
       if (!outcome.success)
         throw new AddChainError(outcome.error);
@@ -473,11 +629,38 @@ export function start(options: ClientOptions, platformBindings: PlatformBindings
           chainIds.delete(newChain);
           instance.removeChain(chainId);
         },
+        cpuTimeMs: () => {
+          if (alreadyDestroyedError)
+            throw alreadyDestroyedError;
+          if (wasDestroyed.destroyed)
+            throw new AlreadyDestroyedError();
+          return instance.chainCpuTimeMs(chainId);
+        },
+        pause: () => {
+          if (alreadyDestroyedError)
+            throw alreadyDestroyedError;
+          if (wasDestroyed.destroyed)
+            throw new AlreadyDestroyedError();
+          instance.setChainPaused(chainId, true);
+        },
+        resume: () => {
+          if (alreadyDestroyedError)
+            throw alreadyDestroyedError;
+          if (wasDestroyed.destroyed)
+            throw new AlreadyDestroyedError();
+          instance.setChainPaused(chainId, false);
+        },
       };
 
       chainIds.set(newChain, chainId);
       return newChain;
     },
+    setLogFilter: async (directives: string) => {
+      if (alreadyDestroyedError)
+        throw alreadyDestroyedError;
+      if (!(await instance.setLogFilter(directives)))
+        throw new Error("Invalid log filter directives: " + directives);
+    },
     terminate: async () => {
       if (alreadyDestroyedError)
         throw alreadyDestroyedError
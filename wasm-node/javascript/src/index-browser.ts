@@ -18,7 +18,7 @@
 /// <reference lib="dom" />
 
 import { Client, ClientOptions, start as innerStart } from './client.js'
-import { Connection, ConnectionError, ConnectionConfig } from './instance/instance.js';
+import { Connection, ConnectionError, ConnectionConfig, workerThreadMain } from './instance/instance.js';
 import { classicDecode, multibaseBase64Decode } from './base64.js'
 import { inflate } from 'pako';
 
@@ -74,10 +74,30 @@ export function start(options?: ClientOptions): Client {
         options?.forbidWss || false,
         options?.forbidWebRtc || false
       )
+    },
+    spawnWorkerThread: (typeof Worker === 'undefined') ? undefined : (data, onMessage) => {
+      const worker = new Worker(URL.createObjectURL(new Blob([workerThreadSource], { type: 'text/javascript' })));
+      worker.onmessage = (event) => onMessage(event.data);
+      worker.postMessage(data);
     }
   })
 }
 
+/**
+ * Source code of the worker threads spawned when the Wasm module has been compiled with the
+ * `threads` feature.
+ */
+const workerThreadSource = `
+  self.onmessage = (event) => {
+    self.onmessage = null;
+    (${workerThreadMain.toString()})(
+      event.data,
+      (message) => self.postMessage(message),
+      (buffer) => self.crypto.getRandomValues(buffer)
+    );
+  };
+`;
+
 /**
  * Tries to open a new connection using the given configuration.
  *
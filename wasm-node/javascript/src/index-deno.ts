@@ -16,7 +16,7 @@
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
 import { Client, ClientOptions, start as innerStart } from './client.js'
-import { Connection, ConnectionError, ConnectionConfig } from './instance/instance.js';
+import { Connection, ConnectionError, ConnectionConfig, workerThreadMain } from './instance/instance.js';
 
 export {
     AddChainError,
@@ -84,10 +84,34 @@ export function start(options?: ClientOptions): Client {
         },
         connect: (config) => {
             return connect(config, options?.forbidTcp || false, options?.forbidWs || false, options?.forbidNonLocalWs || false, options?.forbidWss || false)
+        },
+        spawnWorkerThread: (data, onMessage) => {
+            // Deno only supports workers of type `module`.
+            const worker = new Worker(
+                URL.createObjectURL(new Blob([workerThreadSource], { type: 'text/javascript' })),
+                { type: 'module' }
+            );
+            worker.onmessage = (event) => onMessage(event.data);
+            worker.postMessage(data);
         }
     })
 }
 
+/**
+ * Source code of the worker threads spawned when the Wasm module has been compiled with the
+ * `threads` feature.
+ */
+const workerThreadSource = `
+    self.onmessage = (event) => {
+        self.onmessage = null;
+        (${workerThreadMain.toString()})(
+            event.data,
+            (message) => self.postMessage(message),
+            (buffer) => crypto.getRandomValues(buffer)
+        );
+    };
+`;
+
 /**
  * Decodes a base64 string.
  *
@@ -502,3 +526,18 @@ declare class DecompressionStream {
     readonly readable: ReadableStream<Uint8Array>;
     readonly writable: WritableStream<Uint8Array>;
 }
+
+// Original can be found here: https://github.com/denoland/deno/blob/main/ext/web/lib.deno_web.d.ts
+/**
+ * A background thread that executes the script found at the given URL, and that communicates
+ * with the current thread through messages.
+ *
+ * Only the parts of the API used by this file are declared.
+ */
+declare class Worker {
+    constructor(specifier: string | URL, options?: { type?: 'module', name?: string });
+
+    onmessage: ((event: MessageEvent) => any) | null;
+    postMessage(message: any): void;
+    terminate(): void;
+}
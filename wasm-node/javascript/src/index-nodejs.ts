@@ -20,7 +20,7 @@
 // with both at the same time.
 
 import { Client, ClientOptions, start as innerStart } from './client.js'
-import { Connection, ConnectionError, ConnectionConfig } from './instance/instance.js';
+import { Connection, ConnectionError, ConnectionConfig, workerThreadMain } from './instance/instance.js';
 
 import { WebSocket } from 'ws';
 import { inflate } from 'pako';
@@ -28,6 +28,7 @@ import { inflate } from 'pako';
 import { performance } from 'node:perf_hooks';
 import { createConnection as nodeCreateConnection } from 'node:net';
 import { randomFillSync } from 'node:crypto';
+import { Worker } from 'node:worker_threads';
 
 export {
   AddChainError,
@@ -70,10 +71,31 @@ export function start(options?: ClientOptions): Client {
     },
     connect: (config) => {
       return connect(config, options?.forbidTcp || false, options?.forbidWs || false, options?.forbidNonLocalWs || false, options?.forbidWss || false)
+    },
+    spawnWorkerThread: (data, onMessage) => {
+      const worker = new Worker(workerThreadSource, { eval: true, workerData: data });
+      worker.on('message', onMessage);
+      // The worker threads never stop by themselves, and must not prevent the process from
+      // exiting.
+      worker.unref();
     }
   })
 }
 
+/**
+ * Source code of the worker threads spawned when the Wasm module has been compiled with the
+ * `threads` feature.
+ */
+const workerThreadSource = `
+  const { parentPort, workerData } = require('node:worker_threads');
+  const { randomFillSync } = require('node:crypto');
+  (${workerThreadMain.toString()})(
+    workerData,
+    (message) => parentPort.postMessage(message),
+    (buffer) => randomFillSync(buffer)
+  );
+`;
+
 /**
  * Tries to open a new connection using the given configuration.
  *
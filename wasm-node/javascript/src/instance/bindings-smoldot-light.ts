@@ -45,6 +45,12 @@ export interface Config {
      * @throws {@link ConnectionError} If the multiaddress couldn't be parsed or contains an invalid protocol.
      */
     connect(config: ConnectionConfig): Connection;
+
+    /**
+     * Spawns threads that share the memory of the Wasm instance and call its
+     * `worker_thread_run` export. If `undefined`, no thread is spawned.
+     */
+    spawnWorkerThreads?: (numThreads: number) => void,
     
     /**
      * Closure to call when the Wasm instance calls `panic`.
@@ -55,6 +61,7 @@ export interface Config {
     
     logCallback: (level: number, target: string, message: string) => void,
     jsonRpcResponsesNonEmptyCallback: (chainId: number) => void,
+    checkpointRefreshedCallback: (chainId: number, checkpoint: string) => void,
     currentTaskCallback?: (taskName: string | null) => void,
 }
 
@@ -281,6 +288,19 @@ export default function (config: Config): { imports: WebAssembly.ModuleImports,
             config.jsonRpcResponsesNonEmptyCallback(chainId);
         },
 
+        // Used by the Rust side to notify that a new checkpoint of a chain is available.
+        checkpoint_refreshed: (chainId: number, ptr: number, len: number) => {
+            if (killedTracked.killed) return;
+
+            const instance = config.instance!;
+
+            ptr >>>= 0;
+            len >>>= 0;
+
+            const checkpoint = buffer.utf8BytesToString(new Uint8Array(instance.exports.memory.buffer), ptr, len);
+            config.checkpointRefreshedCallback(chainId, checkpoint);
+        },
+
         // Used by the Rust side to emit a log entry.
         // See also the `max_log_level` parameter in the configuration.
         log: (level: number, targetPtr: number, targetLen: number, messagePtr: number, messageLen: number) => {
@@ -505,6 +525,18 @@ export default function (config: Config): { imports: WebAssembly.ModuleImports,
             if (killedTracked.killed) return;
             if (config.currentTaskCallback)
                 config.currentTaskCallback(null);
+        },
+
+        spawn_worker_threads: (numThreads: number) => {
+            if (killedTracked.killed) return;
+            if (config.spawnWorkerThreads)
+                config.spawnWorkerThreads(numThreads >>> 0);
+        },
+
+        // Only ever called by worker threads, which use different bindings. See the
+        // `worker-thread.js` file.
+        wake_up_main_thread: () => {
+            throw new Error("wake_up_main_thread called on the main thread");
         }
     };
 
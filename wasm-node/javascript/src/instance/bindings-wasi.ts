@@ -65,12 +65,20 @@ export default (config: Config): WebAssembly.ModuleImports => {
             ptr >>>= 0;
             len >>>= 0;
 
-            const baseBuffer = new Uint8Array(instance.exports.memory.buffer)
-                .subarray(ptr, ptr + len);
+            const memory = new Uint8Array(instance.exports.memory.buffer);
+
+            // `getRandomValues` refuses views of a `SharedArrayBuffer`, which is the case when
+            // the module has been compiled with the `threads` feature. The random bytes are
+            // then generated in a temporary buffer and copied afterwards.
+            const isShared = typeof SharedArrayBuffer !== 'undefined' &&
+                memory.buffer instanceof SharedArrayBuffer;
+            const baseBuffer = isShared ? new Uint8Array(len) : memory.subarray(ptr, ptr + len);
             for (let iter = 0; iter < len; iter += 65536) {
                 // `baseBuffer.subarray` automatically saturates at the end of the buffer
                 config.getRandomValues(baseBuffer.subarray(iter, iter + 65536))
             }
+            if (isShared)
+                memory.set(baseBuffer, ptr);
 
             return 0;
         },
@@ -23,17 +23,25 @@
  */
 export interface SmoldotWasmExports extends WebAssembly.Exports {
     memory: WebAssembly.Memory,
-    init: (maxLogLevel: number, enableCurrentTask: number, cpuRateLimit: number, periodicallyYield: number) => void,
+    init: (maxLogLevel: number, enableCurrentTask: number, cpuRateLimit: number, periodicallyYield: number, clockDriftToleranceMs: number) => void,
     set_periodically_yield: (periodicallyYield: number) => void,
+    set_log_filter: (bufferIndex: number) => number,
     start_shutdown: () => void,
-    add_chain: (chainSpecBufferIndex: number, databaseContentBufferIndex: number, jsonRpcRunning: number, potentialRelayChainsBufferIndex: number) => number;
+    chain_spec_upload_start: () => number,
+    chain_spec_upload_push: (uploadId: number, bufferIndex: number) => void,
+    genesis_storage_upload_start: () => number,
+    genesis_storage_upload_push: (uploadId: number, bufferIndex: number) => void,
+    add_chain: (chainSpecUploadId: number, genesisStorageUploadId: number, databaseContentBufferIndex: number, jsonRpcRunning: number, jsonRpcMethodsFilterMode: number, jsonRpcMethodsFilterBufferIndex: number, potentialRelayChainsBufferIndex: number, checkpointRefreshPeriodMs: number, checkpointMaxSize: number, warpSyncMinDistinctPeers: number) => number;
     remove_chain: (chainId: number) => void,
     chain_is_ok: (chainId: number) => number,
     chain_error_len: (chainId: number) => number,
     chain_error_ptr: (chainId: number) => number,
+    chain_cpu_time_ms: (chainId: number) => number,
+    chain_pause: (chainId: number) => void,
+    chain_resume: (chainId: number) => void,
     json_rpc_send: (textBufferIndex: number, chainId: number) => number,
-    json_rpc_responses_peek: (chainId: number) => number,
-    json_rpc_responses_pop: (chainId: number) => void,
+    json_rpc_responses_ring: (chainId: number) => number,
+    json_rpc_responses_ring_release: (chainId: number, readOffset: number) => void,
     timer_finished: (timerId: number) => void,
     connection_open_single_stream: (connectionId: number, handshakeTy: number, initialWritableBytes: number, writeClosable: number) => void,
     connection_open_multi_stream: (connectionId: number, handshakeTyBufferIndex: number) => void,
@@ -42,6 +50,9 @@ export interface SmoldotWasmExports extends WebAssembly.Exports {
     connection_stream_opened: (connectionId: number, streamId: number, outbound: number, initialWritableBytes: number) => void,
     connection_reset: (connectionId: number, bufferIndex: number) => void,
     stream_reset: (connectionId: number, streamId: number) => void,
+    // Only exported if the module has been compiled with the `threads` feature.
+    main_thread_wake_up?: () => void,
+    worker_thread_run?: () => void,
 }
 
 export interface SmoldotWasmInstance extends WebAssembly.Instance {
@@ -23,6 +23,24 @@ export function utf8BytesToString(buffer: Uint8Array, offset: number, length: nu
     return new TextDecoder().decode(buffer.slice(offset, offset + length))
 }
 
+/**
+ * Same as `utf8BytesToString`, except that the bytes are decoded in place rather than copied
+ * beforehand.
+ *
+ * The decoding is done synchronously, so it is fine to pass a view of memory that is later
+ * modified.
+ *
+ * `TextDecoder` refuses views of a `SharedArrayBuffer`, which is the case of the memory of the
+ * WebAssembly virtual machine when it is shared between multiple threads. In that situation, the
+ * bytes are copied out of the shared memory before being decoded.
+ */
+export function utf8BytesToStringNoCopy(buffer: Uint8Array, offset: number, length: number): string {
+    checkRange(buffer, offset, length)
+    if (typeof SharedArrayBuffer !== 'undefined' && buffer.buffer instanceof SharedArrayBuffer)
+        return new TextDecoder().decode(buffer.slice(offset, offset + length))
+    return new TextDecoder().decode(buffer.subarray(offset, offset + length))
+}
+
 export function readUInt32LE(buffer: Uint8Array, offset: number): number {
     checkRange(buffer, offset, 4)
     return (buffer[offset]! | (buffer[offset + 1]! << 8) | (buffer[offset + 2]! << 16)) + (buffer[offset + 3]! * 0x1000000)
@@ -21,6 +21,7 @@ import { SmoldotWasmInstance } from './bindings.js';
 import { AlreadyDestroyedError } from '../client.js';
 
 export { PlatformBindings, ConnectionError, ConnectionConfig, Connection } from './raw-instance.js';
+export { WorkerThreadData, WorkerThreadMessage, workerThreadMain } from './raw-instance.js';
 
 /**
  * Thrown in case the underlying client encounters an unexpected crash.
@@ -59,16 +60,45 @@ export interface Config {
   maxLogLevel: number;
   enableCurrentTask: boolean;
   cpuRateLimit: number,
+  clockDriftToleranceMs: number,
 }
 
 export interface Instance {
   request: (request: string, chainId: number) => void
   nextJsonRpcResponse: (chainId: number) => Promise<string>
-  addChain: (chainSpec: string, databaseContent: string, potentialRelayChains: number[], disableJsonRpc: boolean) => Promise<{ success: true, chainId: number } | { success: false, error: string }>
+  addChain: (chainSpec: string, genesisStorage: Uint8Array | undefined, databaseContent: string, potentialRelayChains: number[], disableJsonRpc: boolean, jsonRpcMethodsFilter: JsonRpcMethodsFilter, checkpointRefresh: CheckpointRefresh | undefined, warpSyncMinDistinctPeers: number) => Promise<{ success: true, chainId: number } | { success: false, error: string }>
   removeChain: (chainId: number) => void
+  chainCpuTimeMs: (chainId: number) => number
+  setChainPaused: (chainId: number, paused: boolean) => void
+  setLogFilter: (directives: string) => Promise<boolean>
   startShutdown: () => void
 }
 
+/**
+ * See {@link Instance.addChain}.
+ *
+ * `mode` is 0 to serve all the methods, 1 to serve only the methods matching `patterns`, and 2
+ * to serve all the methods except for the ones matching `patterns`.
+ */
+export interface JsonRpcMethodsFilter {
+  mode: 0 | 1 | 2,
+  patterns: string[],
+}
+
+/**
+ * See {@link Instance.addChain}.
+ */
+export interface CheckpointRefresh {
+  periodMs: number,
+  maxSizeBytes: number,
+  callback: (checkpoint: string) => void,
+}
+
+/**
+ * Size in bytes of the chunks the chain specification is split into when passed to smoldot.
+ */
+const CHAIN_SPEC_UPLOAD_CHUNK_SIZE = 1024 * 1024;
+
 export function start(configMessage: Config, platformBindings: instance.PlatformBindings): Instance {
 
   // This variable represents the state of the instance, and serves two different purposes:
@@ -87,6 +117,7 @@ export function start(configMessage: Config, platformBindings: instance.Platform
   // Contains the information of each chain that is currently alive.
   let chains: Map<number, {
     jsonRpcResponsesPromises: JsonRpcResponsesPromise[],
+    checkpointCallback?: (checkpoint: string) => void,
   }> = new Map();
 
   // Start initialization of the Wasm VM.
@@ -120,6 +151,13 @@ export function start(configMessage: Config, platformBindings: instance.Platform
         promises.shift()!.resolve();
       }
     },
+    checkpointRefreshedCallback: (chainId, checkpoint) => {
+      // The callback is invoked from within the Wasm VM. It is called asynchronously in order
+      // for the user to be able to call functions of the client from within the callback.
+      const callback = chains.get(chainId)?.checkpointCallback;
+      if (callback)
+        setTimeout(() => callback(checkpoint), 0);
+    },
     currentTaskCallback: (taskName) => {
       currentTask.name = taskName
     },
@@ -137,6 +175,12 @@ export function start(configMessage: Config, platformBindings: instance.Platform
       if (cpuRateLimit > 4294967295) cpuRateLimit = 4294967295;
       if (!Number.isFinite(cpuRateLimit)) cpuRateLimit = 4294967295; // User might have passed NaN
 
+      // The clock drift tolerance is passed as a number of milliseconds that must fit in a `u32`.
+      let clockDriftToleranceMs = Math.round(configMessage.clockDriftToleranceMs);
+      if (clockDriftToleranceMs < 0) clockDriftToleranceMs = 0;
+      if (clockDriftToleranceMs > 4294967295) clockDriftToleranceMs = 4294967295;
+      if (!Number.isFinite(clockDriftToleranceMs)) clockDriftToleranceMs = 30000; // User might have passed NaN
+
       // Smoldot requires an initial call to the `init` function in order to do its internal
       // configuration.
       const [periodicallyYield, unregisterCallback] = platformBindings.registerShouldPeriodicallyYield((newValue) => {
@@ -146,7 +190,7 @@ export function start(configMessage: Config, platformBindings: instance.Platform
           } catch(_error) {}
         }
       });
-      instance.exports.init(configMessage.maxLogLevel, configMessage.enableCurrentTask ? 1 : 0, cpuRateLimit, periodicallyYield ? 1 : 0);
+      instance.exports.init(configMessage.maxLogLevel, configMessage.enableCurrentTask ? 1 : 0, cpuRateLimit, periodicallyYield ? 1 : 0, clockDriftToleranceMs);
 
       state = { initialized: true, instance, bufferIndices, unregisterCallback };
       return [instance, bufferIndices];
@@ -207,16 +251,21 @@ export function start(configMessage: Config, platformBindings: instance.Platform
 
         // Try to pop a message from the queue.
         try {
+          const ringInfo = state.instance.exports.json_rpc_responses_ring(chainId) >>> 0;
           const mem = new Uint8Array(state.instance.exports.memory.buffer);
-          const responseInfo = state.instance.exports.json_rpc_responses_peek(chainId) >>> 0;
-          const ptr = buffer.readUInt32LE(mem, responseInfo) >>> 0;
-          const len = buffer.readUInt32LE(mem, responseInfo + 4) >>> 0;
+          const ringPtr = buffer.readUInt32LE(mem, ringInfo) >>> 0;
+          const ringLen = buffer.readUInt32LE(mem, ringInfo + 4) >>> 0;
+          const readOffset = buffer.readUInt32LE(mem, ringInfo + 8) >>> 0;
+          const writeOffset = buffer.readUInt32LE(mem, ringInfo + 12) >>> 0;
 
-          // `len === 0` means "queue is empty" according to the API.
+          // `readOffset === writeOffset` means "queue is empty" according to the API.
           // In that situation, queue the resolve/reject.
-          if (len !== 0) {
-            const message = buffer.utf8BytesToString(mem, ptr, len);
-            state.instance.exports.json_rpc_responses_pop(chainId);
+          if (readOffset !== writeOffset) {
+            // Each entry of the ring buffer indicates where the response is located in memory.
+            const ptr = buffer.readUInt32LE(mem, ringPtr + readOffset) >>> 0;
+            const len = buffer.readUInt32LE(mem, ringPtr + readOffset + 4) >>> 0;
+            const message = buffer.utf8BytesToStringNoCopy(mem, ptr, len);
+            state.instance.exports.json_rpc_responses_ring_release(chainId, (readOffset + 8) % ringLen);
             return message;
           }
         } catch (_error) {
@@ -231,7 +280,75 @@ export function start(configMessage: Config, platformBindings: instance.Platform
       }
     },
 
-    addChain: (chainSpec: string, databaseContent: string, potentialRelayChains: number[], disableJsonRpc: boolean): Promise<{ success: true, chainId: number } | { success: false, error: string }> => {
+    addChain: async (chainSpec: string, genesisStorage: Uint8Array | undefined, databaseContent: string, potentialRelayChains: number[], disableJsonRpc: boolean, jsonRpcMethodsFilter: JsonRpcMethodsFilter, checkpointRefresh: CheckpointRefresh | undefined, warpSyncMinDistinctPeers: number): Promise<{ success: true, chainId: number } | { success: false, error: string }> => {
+      // The chain specification is uploaded in multiple chunks, and we yield back control
+      // between each chunk. Chain specifications can be very large, and the time it takes for
+      // smoldot to process a chunk is proportional to its size. Doing this avoids freezing the
+      // thread for long periods of time.
+      const chainSpecEncoded = new TextEncoder().encode(chainSpec);
+      const uploadId = await queueOperation((instance) => {
+        if (crashError.error)
+          throw crashError.error;
+        try {
+          return instance.exports.chain_spec_upload_start() >>> 0;
+        } catch (_error) {
+          console.assert(crashError.error);
+          throw crashError.error
+        }
+      });
+
+      for (let offset = 0; offset < chainSpecEncoded.length; offset += CHAIN_SPEC_UPLOAD_CHUNK_SIZE) {
+        if (offset !== 0)
+          await new Promise((resolve) => setTimeout(resolve, 0));
+
+        await queueOperation((instance, bufferIndices) => {
+          if (crashError.error)
+            throw crashError.error;
+          try {
+            bufferIndices[0] = chainSpecEncoded.subarray(offset, offset + CHAIN_SPEC_UPLOAD_CHUNK_SIZE);
+            instance.exports.chain_spec_upload_push(uploadId, 0);
+            delete bufferIndices[0]
+          } catch (_error) {
+            console.assert(crashError.error);
+            throw crashError.error
+          }
+        });
+      }
+
+      // The genesis storage, if any, is uploaded in multiple chunks as well, for the same reason.
+      let genesisStorageUploadId = 0xffffffff;
+      if (genesisStorage !== undefined) {
+        const genesisStorageEncoded = genesisStorage;
+        genesisStorageUploadId = await queueOperation((instance) => {
+          if (crashError.error)
+            throw crashError.error;
+          try {
+            return instance.exports.genesis_storage_upload_start() >>> 0;
+          } catch (_error) {
+            console.assert(crashError.error);
+            throw crashError.error
+          }
+        });
+
+        for (let offset = 0; offset < genesisStorageEncoded.length; offset += CHAIN_SPEC_UPLOAD_CHUNK_SIZE) {
+          if (offset !== 0)
+            await new Promise((resolve) => setTimeout(resolve, 0));
+
+          await queueOperation((instance, bufferIndices) => {
+            if (crashError.error)
+              throw crashError.error;
+            try {
+              bufferIndices[0] = genesisStorageEncoded.subarray(offset, offset + CHAIN_SPEC_UPLOAD_CHUNK_SIZE);
+              instance.exports.genesis_storage_upload_push(genesisStorageUploadId, 0);
+              delete bufferIndices[0]
+            } catch (_error) {
+              console.assert(crashError.error);
+              throw crashError.error
+            }
+          });
+        }
+      }
+
       return queueOperation((instance, bufferIndices) => {
         if (crashError.error)
           throw crashError.error;
@@ -241,23 +358,24 @@ export function start(configMessage: Config, platformBindings: instance.Platform
           // id will refer to an *erroneous* chain. `chain_is_ok` is used below to determine whether it
           // has succeeeded or not.
           // Note that `add_chain` properly de-allocates buffers even if it failed.
-          bufferIndices[0] = new TextEncoder().encode(chainSpec)
           bufferIndices[1] = new TextEncoder().encode(databaseContent)
           const potentialRelayChainsEncoded = new Uint8Array(potentialRelayChains.length * 4)
           for (let idx = 0; idx < potentialRelayChains.length; ++idx) {
             buffer.writeUInt32LE(potentialRelayChainsEncoded, idx * 4, potentialRelayChains[idx]!);
           }
           bufferIndices[2] = potentialRelayChainsEncoded
-          const chainId = instance.exports.add_chain(0, 1, disableJsonRpc ? 0 : 1, 2);
+          bufferIndices[3] = new TextEncoder().encode(jsonRpcMethodsFilter.patterns.join(','))
+          const chainId = instance.exports.add_chain(uploadId, genesisStorageUploadId, 1, disableJsonRpc ? 0 : 1, jsonRpcMethodsFilter.mode, 3, 2, checkpointRefresh ? Math.max(1, checkpointRefresh.periodMs) : 0, checkpointRefresh ? checkpointRefresh.maxSizeBytes : 0, warpSyncMinDistinctPeers);
 
-          delete bufferIndices[0]
           delete bufferIndices[1]
           delete bufferIndices[2]
+          delete bufferIndices[3]
 
           if (instance.exports.chain_is_ok(chainId) != 0) {
             console.assert(!chains.has(chainId));
             chains.set(chainId, {
-              jsonRpcResponsesPromises: new Array()
+              jsonRpcResponsesPromises: new Array(),
+              checkpointCallback: checkpointRefresh?.callback,
             });
             return { success: true, chainId };
           } else {
@@ -299,6 +417,57 @@ export function start(configMessage: Config, platformBindings: instance.Platform
       }
     },
 
+    chainCpuTimeMs: (chainId: number): number => {
+      // Same remark as in `removeChain`.
+      if (!state.initialized)
+        throw new Error("Internal error");
+      if (crashError.error)
+        throw crashError.error;
+
+      console.assert(chains.has(chainId));
+      try {
+        return state.instance.exports.chain_cpu_time_ms(chainId);
+      } catch (_error) {
+        console.assert(crashError.error);
+        throw crashError.error
+      }
+    },
+
+    setChainPaused: (chainId: number, paused: boolean) => {
+      // Same remark as in `removeChain`.
+      if (!state.initialized)
+        throw new Error("Internal error");
+      if (crashError.error)
+        throw crashError.error;
+
+      console.assert(chains.has(chainId));
+      try {
+        if (paused)
+          state.instance.exports.chain_pause(chainId);
+        else
+          state.instance.exports.chain_resume(chainId);
+      } catch (_error) {
+        console.assert(crashError.error);
+        throw crashError.error
+      }
+    },
+
+    setLogFilter: (directives: string): Promise<boolean> => {
+      return queueOperation((instance, bufferIndices) => {
+        if (crashError.error)
+          throw crashError.error;
+        try {
+          bufferIndices[0] = new TextEncoder().encode(directives)
+          const retVal = instance.exports.set_log_filter(0) >>> 0;
+          delete bufferIndices[0]
+          return retVal === 0;
+        } catch (_error) {
+          console.assert(crashError.error);
+          throw crashError.error
+        }
+      })
+    },
+
     startShutdown: () => {
       return queueOperation((instance) => {
         // `startShutdown` is a bit special in its handling of crashes.
@@ -21,8 +21,10 @@ import { Config as WasiConfig, default as wasiBindingsBuilder } from './bindings
 import { default as wasmBase64 } from './autogen/wasm.js';
 
 import { SmoldotWasmInstance } from './bindings.js';
+import { WorkerThreadData, WorkerThreadMessage, importedMemoryLimits } from './worker-thread.js';
 
 export { ConnectionConfig, ConnectionError, Connection } from './bindings-smoldot-light.js';
+export { WorkerThreadData, WorkerThreadMessage, workerThreadMain } from './worker-thread.js';
 
 export interface Config {
     /**
@@ -39,6 +41,7 @@ export interface Config {
     onWasmPanic: (message: string) => void,
     logCallback: (level: number, target: string, message: string) => void,
     jsonRpcResponsesNonEmptyCallback: (chainId: number) => void,
+    checkpointRefreshedCallback: (chainId: number, checkpoint: string) => void,
     currentTaskCallback?: (taskName: string | null) => void,
     cpuRateLimit: number,
 }
@@ -89,6 +92,17 @@ export interface PlatformBindings {
      * @throws {@link ConnectionError} If the multiaddress couldn't be parsed or contains an invalid protocol.
      */
      connect(config: ConnectionConfig): Connection;
+
+    /**
+     * Spawns a thread (typically a `Worker`) that calls {@link workerThreadMain} with the given
+     * data, and calls `onMessage` on the current thread whenever the worker thread posts a
+     * message.
+     *
+     * Only ever called if the Wasm module has been compiled with the `threads` feature. Can be
+     * left `undefined` otherwise, or if the platform doesn't support threads, in which case all
+     * the CPU-intensive operations are executed on the current thread.
+     */
+    spawnWorkerThread?: (data: WorkerThreadData, onMessage: (message: WorkerThreadMessage) => void) => void,
 }
 
 export async function startInstance(config: Config, platformBindings: PlatformBindings): Promise<[SmoldotWasmInstance, Array<Uint8Array>]> {
@@ -98,8 +112,20 @@ export async function startInstance(config: Config, platformBindings: PlatformBi
     // cross-platform cross-bundler approach.
     const wasmBytecode = await platformBindings.trustedBase64DecodeAndZlibInflate(wasmBase64)
 
+    const module = await WebAssembly.compile(wasmBytecode);
+
+    // When the module has been compiled with the `threads` feature, its memory is imported
+    // rather than created by the module itself, so that it can be shared with the worker threads.
+    const memoryImport = WebAssembly.Module.imports(module).find((i) => i.kind === 'memory');
+    const memory = memoryImport ?
+        new WebAssembly.Memory({ ...importedMemoryLimits(wasmBytecode), shared: true } as WebAssembly.MemoryDescriptor) :
+        undefined;
+
     let killAll: () => void;
 
+    // Set to `true` by `killAll`. Messages sent by worker threads are ignored afterwards.
+    let killed = false;
+
     const bufferIndices = new Array;
 
     // Used to bind with the smoldot-light bindings. See the `bindings-smoldot-light.js` file.
@@ -107,6 +133,33 @@ export async function startInstance(config: Config, platformBindings: PlatformBi
         bufferIndices,
         performanceNow: platformBindings.performanceNow,
         connect: platformBindings.connect,
+        // Worker threads can only be spawned if the memory can be shared with them.
+        spawnWorkerThreads: (platformBindings.spawnWorkerThread && memory) ? (numThreads) => {
+            const data: WorkerThreadData = { module, memory };
+            for (let i = 0; i < numThreads; ++i) {
+                platformBindings.spawnWorkerThread!(data, (message) => {
+                    if (killed)
+                        return;
+                    switch (message.kind) {
+                        case 'wake-up': {
+                            try {
+                                smoldotJsConfig.instance!.exports.main_thread_wake_up!();
+                            } catch(_error) {}
+                            break;
+                        }
+                        case 'log': {
+                            config.logCallback(message.level, message.target, message.message);
+                            break;
+                        }
+                        case 'panic': {
+                            killAll();
+                            config.onWasmPanic(message.message);
+                            break;
+                        }
+                    }
+                });
+            }
+        } : undefined,
         onPanic: (message) => {
             killAll();
             config.onWasmPanic(message);
@@ -129,19 +182,26 @@ export async function startInstance(config: Config, platformBindings: PlatformBi
     const { imports: smoldotBindings, killAll: smoldotBindingsKillAll } =
         smoldotLightBindingsBuilder(smoldotJsConfig);
 
-    killAll = smoldotBindingsKillAll;
+    killAll = () => {
+        killed = true;
+        smoldotBindingsKillAll();
+    };
 
     // Start the Wasm virtual machine.
     // The Rust code defines a list of imports that must be fulfilled by the environment. The second
     // parameter provides their implementations.
-    const result = await WebAssembly.instantiate(wasmBytecode, {
+    const imports: WebAssembly.Imports = {
         // The functions with the "smoldot" prefix are specific to smoldot.
         "smoldot": smoldotBindings,
         // As the Rust code is compiled for wasi, some more wasi-specific imports exist.
         "wasi_snapshot_preview1": wasiBindingsBuilder(wasiConfig),
-    });
+    };
+    if (memoryImport) {
+        const moduleImports = imports[memoryImport.module] || (imports[memoryImport.module] = {});
+        moduleImports[memoryImport.name] = memory!;
+    }
 
-    const instance = result.instance as SmoldotWasmInstance;
+    const instance = await WebAssembly.instantiate(module, imports) as SmoldotWasmInstance;
     smoldotJsConfig.instance = instance;
     wasiConfig.instance = instance;
     return [instance, bufferIndices];
@@ -0,0 +1,210 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Code executed by the worker threads spawned when the Wasm module has been compiled with the
+//! `threads` feature.
+//!
+//! Each worker thread instantiates the same Wasm module as the main thread, with the same shared
+//! memory, and calls its `worker_thread_run` export. This function never returns, and executes
+//! the CPU-intensive operations queued by the main thread.
+
+/**
+ * Data that must be passed to {@link workerThreadMain}.
+ */
+export interface WorkerThreadData {
+    module: WebAssembly.Module,
+    memory: WebAssembly.Memory,
+}
+
+/**
+ * Message sent by a worker thread to the main thread.
+ */
+export type WorkerThreadMessage =
+    // The worker thread has finished executing an operation. The main thread must call the
+    // `main_thread_wake_up` export of its instance.
+    { kind: 'wake-up' } |
+    { kind: 'log', level: number, target: string, message: string } |
+    // The Wasm module has panicked. The main thread must stop its execution.
+    { kind: 'panic', message: string };
+
+/**
+ * Instantiates the Wasm module with the given shared memory, then calls its `worker_thread_run`
+ * export. Only returns if `worker_thread_run` returns or throws, which normally never happens.
+ *
+ * This function is meant to be converted to a string with `toString()` and executed as the body
+ * of a worker. As such, it must not reference anything outside of its own body.
+ *
+ * @param data Module and memory shared with the main thread.
+ * @param postMessage Sends a message to the main thread.
+ * @param getRandomValues Fills the given buffer with randomly-generated bytes.
+ */
+export function workerThreadMain(
+    data: WorkerThreadData,
+    postMessage: (message: WorkerThreadMessage) => void,
+    getRandomValues: (buffer: Uint8Array) => void
+): void {
+    // `TextDecoder` refuses views of a `SharedArrayBuffer`, and the bytes are thus copied
+    // beforehand.
+    const readString = (ptr: number, len: number): string => {
+        const mem = new Uint8Array(data.memory.buffer);
+        return new TextDecoder().decode(mem.slice(ptr >>> 0, (ptr >>> 0) + (len >>> 0)));
+    };
+
+    const writeUInt32LE = (ptr: number, value: number) => {
+        new DataView(data.memory.buffer).setUint32(ptr >>> 0, value >>> 0, true);
+    };
+
+    const functions: { [name: string]: Function } = {
+        "smoldot.panic": (ptr: number, len: number) => {
+            postMessage({ kind: 'panic', message: readString(ptr, len) });
+            throw new Error();
+        },
+        "smoldot.log": (level: number, targetPtr: number, targetLen: number, messagePtr: number, messageLen: number) => {
+            postMessage({
+                kind: 'log',
+                level,
+                target: readString(targetPtr, targetLen),
+                message: readString(messagePtr, messageLen)
+            });
+        },
+        "smoldot.wake_up_main_thread": () => {
+            postMessage({ kind: 'wake-up' });
+        },
+        "wasi_snapshot_preview1.random_get": (ptr: number, len: number) => {
+            // `getRandomValues` refuses views of a `SharedArrayBuffer`, and also refuses buffers
+            // larger than 65536 bytes.
+            const tmp = new Uint8Array(len >>> 0);
+            for (let iter = 0; iter < tmp.length; iter += 65536)
+                getRandomValues(tmp.subarray(iter, iter + 65536));
+            new Uint8Array(data.memory.buffer).set(tmp, ptr >>> 0);
+            return 0;
+        },
+        "wasi_snapshot_preview1.clock_time_get": (clockId: number, _precision: number, outPtr: number) => {
+            // Clock 0 is the real time clock. The others are monotonic clocks.
+            const nanos = (clockId === 0 ? Date.now() : performance.now()) * 1000000;
+            writeUInt32LE(outPtr, nanos % 0x100000000);
+            writeUInt32LE(outPtr + 4, Math.floor(nanos / 0x100000000));
+            return 0;
+        },
+        "wasi_snapshot_preview1.fd_write": (_fd: number, addr: number, num: number, outPtr: number) => {
+            // Whatever is printed by worker threads is discarded.
+            const view = new DataView(data.memory.buffer);
+            let totalLength = 0;
+            for (let i = 0; i < num; i++)
+                totalLength += view.getUint32((addr >>> 0) + 4 * (i * 2 + 1), true);
+            writeUInt32LE(outPtr, totalLength);
+            return 0;
+        },
+        "wasi_snapshot_preview1.environ_sizes_get": (argcOut: number, argvBufSizeOut: number) => {
+            writeUInt32LE(argcOut, 0);
+            writeUInt32LE(argvBufSizeOut, 0);
+            return 0;
+        },
+        "wasi_snapshot_preview1.environ_get": () => 0,
+        "wasi_snapshot_preview1.sched_yield": () => 0,
+        "wasi_snapshot_preview1.proc_exit": (retCode: number) => {
+            postMessage({ kind: 'panic', message: "proc_exit called: " + retCode });
+            throw new Error();
+        },
+    };
+
+    // Build the imports of the module. Functions that aren't supposed to be called from a worker
+    // thread throw an exception.
+    const imports: WebAssembly.Imports = {};
+    const descriptors = WebAssembly.Module.imports(data.module);
+    for (let i = 0; i < descriptors.length; ++i) {
+        const descriptor = descriptors[i]!;
+        const moduleImports = imports[descriptor.module] || (imports[descriptor.module] = {});
+        if (descriptor.kind === 'memory') {
+            moduleImports[descriptor.name] = data.memory;
+        } else {
+            const fullName = descriptor.module + "." + descriptor.name;
+            moduleImports[descriptor.name] = functions[fullName] || (() => {
+                throw new Error("Function not available on worker threads: " + fullName);
+            });
+        }
+    }
+
+    const instance = new WebAssembly.Instance(data.module, imports);
+    (instance.exports["worker_thread_run"] as Function)();
+}
+
+/**
+ * Returns the limits, in number of pages, of the memory imported by the given Wasm module.
+ *
+ * The JavaScript API doesn't provide any way to obtain these limits. They are necessary in order
+ * to create the shared memory of a module compiled with the `threads` feature.
+ *
+ * @throws {Error} If the module doesn't import a memory.
+ */
+export function importedMemoryLimits(bytecode: Uint8Array): { initial: number, maximum?: number } {
+    // Skip the magic number and the version.
+    let offset = 8;
+
+    const readByte = (): number => {
+        if (offset >= bytecode.length)
+            throw new Error("Invalid Wasm module");
+        return bytecode[offset++]!;
+    };
+    const readLeb128 = (): number => {
+        let result = 0;
+        let multiplier = 1;
+        while (true) {
+            const byte = readByte();
+            result += (byte & 0x7f) * multiplier;
+            multiplier *= 128;
+            if ((byte & 0x80) === 0)
+                return result;
+        }
+    };
+    const readLimits = (): { initial: number, maximum?: number } => {
+        const flags = readByte();
+        const initial = readLeb128();
+        return (flags & 0x1) ? { initial, maximum: readLeb128() } : { initial };
+    };
+
+    while (offset < bytecode.length) {
+        const sectionId = readByte();
+        const sectionLen = readLeb128();
+        if (sectionId !== 2) {
+            offset += sectionLen;
+            continue;
+        }
+
+        // Import section.
+        const numImports = readLeb128();
+        for (let i = 0; i < numImports; ++i) {
+            // Module name and field name. Note that `offset += readLeb128()` would be wrong, as
+            // `offset` would be read before being updated by `readLeb128`.
+            for (let j = 0; j < 2; ++j) {
+                const nameLen = readLeb128();
+                offset += nameLen;
+            }
+
+            switch (readByte()) {
+                case 0: readLeb128(); break;  // Function: type index.
+                case 1: readByte(); readLimits(); break;  // Table: reference type and limits.
+                case 2: return readLimits();  // Memory.
+                case 3: readByte(); readByte(); break;  // Global: value type and mutability.
+                default: throw new Error("Invalid Wasm module");
+            }
+        }
+        break;
+    }
+
+    throw new Error("Wasm module doesn't import a memory");
+}
@@ -0,0 +1,44 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+import test from 'ava';
+import { utf8BytesToString, utf8BytesToStringNoCopy } from "../dist/mjs/instance/buffer.js";
+
+const message = "{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":\"é\"}";
+const messageLen = new TextEncoder().encode(message).length;
+
+function fill(memory) {
+  const view = new Uint8Array(memory);
+  view.set(new TextEncoder().encode(message), 5);
+  return view;
+}
+
+test('decode from non-shared memory', t => {
+  const view = fill(new ArrayBuffer(64));
+  t.is(utf8BytesToStringNoCopy(view, 5, messageLen), message);
+  t.is(utf8BytesToString(view, 5, messageLen), message);
+});
+
+test('decode from shared memory', t => {
+  const view = fill(new SharedArrayBuffer(64));
+  t.is(utf8BytesToStringNoCopy(view, 5, messageLen), message);
+  t.is(utf8BytesToString(view, 5, messageLen), message);
+});
+
+test('out of range', t => {
+  const view = fill(new ArrayBuffer(64));
+  t.throws(() => utf8BytesToStringNoCopy(view, 60, 5), { instanceOf: RangeError });
+});
@@ -0,0 +1,62 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+import test from 'ava';
+import { importedMemoryLimits, workerThreadMain } from "../dist/mjs/instance/worker-thread.js";
+
+// Hand-assembled Wasm module that imports a shared memory and `smoldot.wake_up_main_thread`, and
+// whose `worker_thread_run` export calls `wake_up_main_thread` then writes a byte in memory.
+function buildModule(memoryLimits) {
+  const name = (str) => [str.length, ...new TextEncoder().encode(str)];
+  const section = (id, content) => [id, content.length, ...content];
+  return new Uint8Array([
+    0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+    // Type section: `() -> ()`.
+    ...section(1, [1, 0x60, 0, 0]),
+    // Import section.
+    ...section(2, [
+      2,
+      ...name("env"), ...name("memory"), 2, ...memoryLimits,
+      ...name("smoldot"), ...name("wake_up_main_thread"), 0, 0,
+    ]),
+    // Function section.
+    ...section(3, [1, 0]),
+    // Export section.
+    ...section(7, [1, ...name("worker_thread_run"), 0, 1]),
+    // Code section: `call 0; i32.const 8; i32.const 42; i32.store8; end`.
+    ...section(10, [1, 11, 0, 0x10, 0, 0x41, 8, 0x41, 42, 0x3a, 0, 0, 0x0b]),
+  ]);
+}
+
+test('imported memory limits', t => {
+  t.deepEqual(importedMemoryLimits(buildModule([3, 1, 2])), { initial: 1, maximum: 2 });
+  t.deepEqual(importedMemoryLimits(buildModule([0, 0x80, 0x01])), { initial: 128 });
+});
+
+test('no imported memory', t => {
+  const bytecode = new Uint8Array([0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00]);
+  t.throws(() => importedMemoryLimits(bytecode));
+});
+
+test('worker thread main', t => {
+  const module = new WebAssembly.Module(buildModule([3, 1, 2]));
+  const memory = new WebAssembly.Memory({ initial: 1, maximum: 2, shared: true });
+  const messages = [];
+  workerThreadMain({ module, memory }, (message) => messages.push(message), () => {});
+  t.deepEqual(messages, [{ kind: 'wake-up' }]);
+  t.is(new Uint8Array(memory.buffer)[8], 42);
+});
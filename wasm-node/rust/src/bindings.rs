@@ -115,14 +115,24 @@ extern "C" {
 
     /// The queue of JSON-RPC responses of the given chain is no longer empty.
     ///
-    /// Use [`json_rpc_responses_peek`] in order to obtain information about the responses in the
-    /// queue.
+    /// Use [`json_rpc_responses_ring`] in order to obtain the responses in the queue.
     ///
     /// This function might be called even when the queue wasn't empty before, however this
     /// behavior must not be relied upon. The queue must be emptied by calling
-    /// [`json_rpc_responses_pop`] in order to have the guarantee that this function gets called.
+    /// [`json_rpc_responses_ring_release`] in order to have the guarantee that this function gets
+    /// called.
     pub fn json_rpc_responses_non_empty(chain_id: u32);
 
+    /// A new checkpoint of the given chain is available.
+    ///
+    /// Only ever called for chains that have been added with a non-zero
+    /// `checkpoint_refresh_period_ms`. See [`add_chain`].
+    ///
+    /// The checkpoint is a UTF-8 string found in the memory of the WebAssembly virtual machine
+    /// at offset `ptr` and with length `len`. It can later be passed back as the database content
+    /// when adding the same chain again.
+    pub fn checkpoint_refreshed(chain_id: u32, ptr: u32, len: u32);
+
     /// Client is emitting a log entry.
     ///
     /// Each log entry is made of a log level (`1 = Error, 2 = Warn, 3 = Info, 4 = Debug,
@@ -314,6 +324,31 @@ extern "C" {
     ///
     /// This function is called only if `enable_current_task` was non-zero when calling [`init`].
     pub fn current_task_exit();
+
+    /// Must spawn `num_threads` new threads sharing the memory of this WebAssembly virtual
+    /// machine, and call [`worker_thread_run`] on each of them.
+    ///
+    /// > **Note**: In a browser or NodeJS environment, each thread is typically a `Worker` that
+    /// >           instantiates the same WebAssembly module with the same `SharedArrayBuffer`
+    /// >           memory.
+    ///
+    /// It is acceptable to spawn fewer threads than requested, or even none at all. Smoldot
+    /// continues to function normally, but uses fewer CPU cores.
+    ///
+    /// This function is only ever called if smoldot has been compiled with the `threads`
+    /// feature, in which case the WebAssembly module requires its memory to be shared.
+    #[cfg(feature = "threads")]
+    pub fn spawn_worker_threads(num_threads: u32);
+
+    /// Called by a worker thread, after it has finished executing an operation, in order to
+    /// notify the main thread. In response, the host must call [`main_thread_wake_up`] on the
+    /// main thread, typically after a message has been sent from the `Worker` to the main
+    /// thread.
+    ///
+    /// This function is only ever called if smoldot has been compiled with the `threads`
+    /// feature, and only from within [`worker_thread_run`].
+    #[cfg(feature = "threads")]
+    pub fn wake_up_main_thread();
 }
 
 /// Initializes the client.
@@ -335,22 +370,77 @@ extern "C" {
 ///
 /// `periodically_yield` represents the initial value of the setting described in the
 /// documentation of [`set_periodically_yield`].
+///
+/// `clock_drift_tolerance_ms` is the maximum allowed difference, in milliseconds, between the
+/// local clock and the clock of the authority that created a block. Blocks that pretend to come
+/// from further in the future than this tolerance are considered invalid.
 #[no_mangle]
 pub extern "C" fn init(
     max_log_level: u32,
     enable_current_task: u32,
     cpu_rate_limit: u32,
     periodically_yield: u32,
+    clock_drift_tolerance_ms: u32,
 ) {
     crate::init(
         max_log_level,
         enable_current_task,
         cpu_rate_limit,
         periodically_yield,
+        clock_drift_tolerance_ms,
     );
     super::advance_execution();
 }
 
+/// Must be called on each thread spawned in response to [`spawn_worker_threads`]. Never
+/// returns.
+///
+/// The thread executes CPU-intensive operations, such as compiling runtimes or verifying
+/// signatures, on behalf of the thread that has called [`init`]. Apart from [`panic()`], [`log()`],
+/// [`wake_up_main_thread`], and the functions of the wasi ABI, it doesn't call any imported
+/// function.
+///
+/// This function is only available if smoldot has been compiled with the `threads` feature.
+#[cfg(feature = "threads")]
+#[no_mangle]
+pub extern "C" fn worker_thread_run() {
+    crate::threads::workers::run()
+}
+
+/// Must be called on the main thread in response to [`wake_up_main_thread`] being called on a
+/// worker thread.
+///
+/// This function is only available if smoldot has been compiled with the `threads` feature.
+#[cfg(feature = "threads")]
+#[no_mangle]
+pub extern "C" fn main_thread_wake_up() {
+    super::advance_execution();
+}
+
+/// Modifies the filter applied to the log messages, in order for example to print more details
+/// about a specific component.
+///
+/// Assign a so-called "buffer index" (a `u32`) representing a UTF-8 string containing a list
+/// of directives separated by `,`, then provide this buffer index to the function. The Rust code
+/// will call [`buffer_size`] and [`buffer_copy`] in order to obtain the content of this buffer.
+/// The buffer index can be de-assigned and buffer destroyed once this function returns.
+///
+/// Each directive is either `<target>=<level>` or `<level>`, where `<level>` is a number between
+/// `0` and `5` with the same meaning as `max_log_level` in [`init`], or one of `off`, `error`,
+/// `warn`, `info`, `debug`, `trace`. A directive without a target modifies the level of all the
+/// targets that aren't mentioned in any other directive. A `<target>` also applies to all the
+/// targets that start with `<target>-`. For example, `sync=5` enables all the logs of the syncing
+/// of all chains, and `json-rpc-polkadot=4` enables the debug logs of the JSON-RPC service of
+/// the chain whose logs are prefixed with `polkadot`. Directives are cumulative with the ones
+/// passed during previous calls.
+///
+/// Returns 0 on success, or 1 if the directives are invalid, in which case the filter is left
+/// unmodified.
+#[no_mangle]
+pub extern "C" fn set_log_filter(buffer_index: u32) -> u32 {
+    super::set_log_filter(get_buffer(buffer_index))
+}
+
 /// Sets whether the smoldot client must periodically yield back control by setting up a timer
 /// using [`start_timer`] with a delay of 0.
 ///
@@ -381,15 +471,87 @@ pub extern "C" fn start_shutdown() {
     super::advance_execution();
 }
 
+/// Starts the upload of a chain specification, in preparation of a call to [`add_chain`].
+///
+/// Returns an identifier for this upload. The content of the chain specification must then be
+/// provided by calling [`chain_spec_upload_push`] one or more times with this identifier.
+///
+/// Chain specifications can be very large (multiple megabytes). Providing them in multiple
+/// chunks makes it possible for the host to yield back control between chunks, as the processing
+/// of each chunk takes an amount of time proportional to its size.
+#[no_mangle]
+pub extern "C" fn chain_spec_upload_start() -> u32 {
+    super::chain_spec_upload_start()
+}
+
+/// Pushes the next chunk of the chain specification whose upload has been started with
+/// [`chain_spec_upload_start`].
+///
+/// Assign a so-called "buffer index" (a `u32`) representing the chunk, then provide this buffer
+/// index to the function. The Rust code will call [`buffer_size`] and [`buffer_copy`] in order to
+/// obtain the content of this buffer. The buffer index can be de-assigned and buffer destroyed
+/// once this function returns.
+///
+/// The concatenation of all the chunks must be UTF-8, but individual chunks are allowed to end in
+/// the middle of a UTF-8 character.
+///
+/// If the chunk is invalid, the error is reported when [`add_chain`] is called.
+#[no_mangle]
+pub extern "C" fn chain_spec_upload_push(upload_id: u32, buffer_index: u32) {
+    super::chain_spec_upload_push(upload_id, get_buffer(buffer_index));
+    super::advance_execution();
+}
+
+/// Starts the upload of the storage of the genesis block of a chain, in preparation of a call
+/// to [`add_chain`].
+///
+/// Returns an identifier for this upload. The content of the genesis storage must then be
+/// provided by calling [`genesis_storage_upload_push`] one or more times with this identifier.
+///
+/// The genesis storage is a binary blob that consists in a concatenation of storage items. Each
+/// storage item consists of the SCALE-compact-encoded length of the key, the key, the
+/// SCALE-compact-encoded length of the value, and the value.
+#[no_mangle]
+pub extern "C" fn genesis_storage_upload_start() -> u32 {
+    super::genesis_storage_upload_start()
+}
+
+/// Pushes the next chunk of the genesis storage whose upload has been started with
+/// [`genesis_storage_upload_start`].
+///
+/// Assign a so-called "buffer index" (a `u32`) representing the chunk, then provide this buffer
+/// index to the function. The Rust code will call [`buffer_size`] and [`buffer_copy`] in order to
+/// obtain the content of this buffer. The buffer index can be de-assigned and buffer destroyed
+/// once this function returns.
+///
+/// Chunks are allowed to end in the middle of a storage item.
+///
+/// If the chunk is invalid, the error is reported when [`add_chain`] is called.
+#[no_mangle]
+pub extern "C" fn genesis_storage_upload_push(upload_id: u32, buffer_index: u32) {
+    super::genesis_storage_upload_push(upload_id, get_buffer(buffer_index));
+    super::advance_execution();
+}
+
 /// Adds a chain to the client. The client will try to stay connected and synchronize this chain.
 ///
-/// Assign a so-called "buffer index" (a `u32`) representing the chain specification, database
-/// content, and list of potential relay chains, then provide these buffer indices to the function.
+/// The chain specification must have previously been provided using [`chain_spec_upload_start`]
+/// and [`chain_spec_upload_push`]. `chain_spec_upload_id` is the value that was returned by
+/// [`chain_spec_upload_start`]. The upload identifier is no longer valid once this function
+/// returns.
+///
+/// If `genesis_storage_upload_id` isn't equal to `0xffffffff`, it must be a value that was
+/// returned by [`genesis_storage_upload_start`]. The genesis storage that was uploaded then
+/// replaces the genesis storage found in the chain specification, if any. The upload identifier
+/// is no longer valid once this function returns.
+///
+/// Assign a so-called "buffer index" (a `u32`) representing the database content and list of
+/// potential relay chains, then provide these buffer indices to the function.
 /// The Rust code will call [`buffer_size`] and [`buffer_copy`] in order to obtain the content of
 /// these buffers. The buffer indices can be de-assigned and buffers destroyed once this function
 /// returns.
 ///
-/// The content of the chain specification and database content must be in UTF-8.
+/// The content of the database content must be in UTF-8.
 ///
 /// > **Note**: The database content is an opaque string that can be obtained by calling
 /// >           the `chainHead_unstable_finalizedDatabase` JSON-RPC function.
@@ -401,6 +563,22 @@ pub extern "C" fn start_shutdown() {
 /// If `json_rpc_running` is 0, then no JSON-RPC service will be started and it is forbidden to
 /// send JSON-RPC requests targeting this chain. This can be used to save up resources.
 ///
+/// `json_rpc_methods_filter_mode` restricts the JSON-RPC methods served for this chain. If it is
+/// 0, all the methods are served. If it is 1, only the methods matching one of the patterns are
+/// served. If it is 2, all the methods are served except for the ones matching one of the
+/// patterns. The patterns are provided through `json_rpc_methods_filter_buffer_index`, which is a
+/// buffer index (similar to the database content) containing an UTF-8 list of patterns separated
+/// by `,`. Each pattern is either the name of a method or a prefix followed with `*`.
+///
+/// If `checkpoint_refresh_period_ms` isn't 0, then once the chain is synchronized the client
+/// periodically exports its finalized state and reports it using [`checkpoint_refreshed`]. The
+/// next checkpoint is generated `checkpoint_refresh_period_ms` milliseconds after the previous
+/// one. Each checkpoint doesn't exceed `checkpoint_max_size` bytes.
+///
+/// `warp_sync_min_distinct_peers` is the number of distinct peers that a warp sync proof is
+/// requested from. Warp sync proofs are only requested once the client is connected to that many
+/// peers, and are only used if they agree with each other. A value of 0 is treated as 1.
+///
 /// If an error happens during the creation of the chain, a chain id will be allocated
 /// nonetheless, and must later be de-allocated by calling [`remove_chain`]. This allocated chain,
 /// however, will be in an erroneous state. Use [`chain_is_ok`] to determine whether this function
@@ -408,16 +586,28 @@ pub extern "C" fn start_shutdown() {
 /// message.
 #[no_mangle]
 pub extern "C" fn add_chain(
-    chain_spec_buffer_index: u32,
+    chain_spec_upload_id: u32,
+    genesis_storage_upload_id: u32,
     database_content_buffer_index: u32,
     json_rpc_running: u32,
+    json_rpc_methods_filter_mode: u32,
+    json_rpc_methods_filter_buffer_index: u32,
     potential_relay_chains_buffer_index: u32,
+    checkpoint_refresh_period_ms: u32,
+    checkpoint_max_size: u32,
+    warp_sync_min_distinct_peers: u32,
 ) -> u32 {
     let success_code = super::add_chain(
-        get_buffer(chain_spec_buffer_index),
+        chain_spec_upload_id,
+        genesis_storage_upload_id,
         get_buffer(database_content_buffer_index),
         json_rpc_running,
+        json_rpc_methods_filter_mode,
+        get_buffer(json_rpc_methods_filter_buffer_index),
         get_buffer(potential_relay_chains_buffer_index),
+        checkpoint_refresh_period_ms,
+        checkpoint_max_size,
+        warp_sync_min_distinct_peers,
     );
     super::advance_execution();
     success_code
@@ -463,6 +653,37 @@ pub extern "C" fn chain_error_ptr(chain_id: u32) -> u32 {
     super::chain_error_ptr(chain_id)
 }
 
+/// Returns the number of milliseconds that have been spent executing the background tasks of
+/// this chain since it has been added.
+///
+/// This makes it possible, when multiple chains are running, to determine which chain is
+/// responsible for most of the CPU usage. Chains whose specification is identical share the same
+/// background tasks and thus report the same value. Returns `0.0` for erroneous chains.
+#[no_mangle]
+pub extern "C" fn chain_cpu_time_ms(chain_id: u32) -> f64 {
+    super::chain_cpu_time_ms(chain_id)
+}
+
+/// Suspends the networking and synchronization of the given chain, while keeping its state in
+/// memory, until [`chain_resume`] is called.
+///
+/// JSON-RPC requests continue to be accepted, but the requests that need to access the network
+/// don't make progress while the chain is paused. Chains whose specification is identical share
+/// the same background tasks, which are only paused if all these chains are paused. Does nothing
+/// if the chain is erroneous or already paused.
+#[no_mangle]
+pub extern "C" fn chain_pause(chain_id: u32) {
+    super::chain_set_paused(chain_id, true)
+}
+
+/// Resumes a chain previously paused with [`chain_pause`].
+///
+/// Does nothing if the chain is erroneous or isn't paused.
+#[no_mangle]
+pub extern "C" fn chain_resume(chain_id: u32) {
+    super::chain_set_paused(chain_id, false)
+}
+
 /// Emit a JSON-RPC request or notification towards the given chain previously added using
 /// [`add_chain`].
 ///
@@ -476,7 +697,7 @@ pub extern "C" fn chain_error_ptr(chain_id: u32) -> u32 {
 /// index can be de-assigned and buffer destroyed once this function returns.
 ///
 /// Responses and notifications are notified using [`json_rpc_responses_non_empty`], and can
-/// be read with [`json_rpc_responses_peek`].
+/// be read with [`json_rpc_responses_ring`].
 ///
 /// It is forbidden to call this function on an erroneous chain or a chain that was created with
 /// `json_rpc_running` equal to 0.
@@ -494,45 +715,67 @@ pub extern "C" fn json_rpc_send(text_buffer_index: u32, chain_id: u32) -> u32 {
     success_code
 }
 
-/// Obtains information about the first response in the queue of JSON-RPC responses.
+/// Obtains information about the ring buffer containing the JSON-RPC responses and notifications
+/// of the given chain, and fills this ring buffer with as many pending responses as possible.
 ///
 /// This function returns a pointer within the memory of the WebAssembly virtual machine where is
-/// stored a struct of type [`JsonRpcResponseInfo`]. This pointer remains valid until
-/// [`json_rpc_responses_pop`] or [`remove_chain`] is called with the same `chain_id`.
-///
-/// The response or notification is a UTF-8 string found in the memory of the WebAssembly
-/// virtual machine at offset `ptr` and with length `len`, where `ptr` and `len` are found in the
-/// [`JsonRpcResponseInfo`].
+/// stored a struct of type [`JsonRpcResponsesRingInfo`]. This pointer remains valid until
+/// [`remove_chain`] is called with the same `chain_id`. The values within this struct, however,
+/// are only valid until the next call to any function of these bindings.
+///
+/// The ring buffer is found in the memory of the WebAssembly virtual machine at offset
+/// `buffer_ptr` and with length `buffer_len`. The region between `read_offset` and
+/// `write_offset`, wrapping around the end of the buffer, contains entries of responses that
+/// haven't been read yet. If `read_offset` is equal to `write_offset`, then the queue of JSON-RPC
+/// responses is empty.
+///
+/// Each entry is 8 bytes long and made of a 32-bits-little-endian pointer followed with a
+/// 32-bits-little-endian length. The pointer and length indicate where the UTF-8 response or
+/// notification can be found in the memory of the WebAssembly virtual machine. The length of the
+/// ring buffer is always a multiple of 8, and the entry following the one at the end of the
+/// buffer is the one at offset 0.
+///
+/// The JavaScript can decode the responses directly from the memory of the virtual machine.
+/// Once it has done so, it must call [`json_rpc_responses_ring_release`] in order to notify of
+/// its new read offset.
 ///
-/// If `len` is equal to 0, this indicates that the queue of JSON-RPC responses is empty.
-///
-/// After having read the response or notification, use [`json_rpc_responses_pop`] to remove it
-/// from the queue. You can then call [`json_rpc_responses_peek`] again to read the next response.
+/// It is forbidden to call this function on an erroneous chain or a chain that was created with
+/// `json_rpc_running` equal to 0.
 #[no_mangle]
-pub extern "C" fn json_rpc_responses_peek(chain_id: u32) -> u32 {
-    super::json_rpc_responses_peek(chain_id)
+pub extern "C" fn json_rpc_responses_ring(chain_id: u32) -> u32 {
+    super::json_rpc_responses_ring(chain_id)
 }
 
-/// See [`json_rpc_responses_peek`].
+/// See [`json_rpc_responses_ring`].
 #[repr(C)]
-pub struct JsonRpcResponseInfo {
-    /// Pointer in memory where the JSON-RPC response can be found.
-    pub ptr: u32,
-    /// Length of the JSON-RPC response in bytes. If 0, indicates that the queue is empty.
-    pub len: u32,
+pub struct JsonRpcResponsesRingInfo {
+    /// Pointer in memory where the ring buffer can be found.
+    pub buffer_ptr: u32,
+    /// Length of the ring buffer in bytes.
+    pub buffer_len: u32,
+    /// Offset within the ring buffer of the next response to read.
+    pub read_offset: u32,
+    /// Offset within the ring buffer where the next response will be written. If equal to
+    /// `read_offset`, indicates that the queue is empty.
+    pub write_offset: u32,
 }
 
-/// Removes the first response from the queue of JSON-RPC responses. This is the response whose
-/// information can be retrieved using [`json_rpc_responses_peek`].
+/// Indicates that the responses found in the ring buffer (see [`json_rpc_responses_ring`])
+/// up to `read_offset` have been read and that their space can be reused.
+///
+/// `read_offset` must be an offset previously found between the `read_offset` and
+/// `write_offset` returned by [`json_rpc_responses_ring`], and must point to the start of an
+/// entry. The responses of the entries that have been released are freed, and must no longer
+/// be accessed.
 ///
-/// Calling this function invalidates the pointer previously returned by a call to
-/// [`json_rpc_responses_peek`] with the same `chain_id`.
+/// Calling this function invalidates the values previously obtained through the pointer returned
+/// by [`json_rpc_responses_ring`].
 ///
 /// It is forbidden to call this function on an erroneous chain or a chain that was created with
 /// `json_rpc_running` equal to 0.
 #[no_mangle]
-pub extern "C" fn json_rpc_responses_pop(chain_id: u32) {
-    super::json_rpc_responses_pop(chain_id);
+pub extern "C" fn json_rpc_responses_ring_release(chain_id: u32, read_offset: u32) {
+    super::json_rpc_responses_ring_release(chain_id, read_offset);
     super::advance_execution();
 }
 
@@ -15,12 +15,17 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use crate::{alloc, bindings, cpu_rate_limiter, platform, timers::Delay};
+use crate::{alloc, bindings, cpu_rate_limiter, json_rpc_ring, platform, timers::Delay};
 
-use core::{future::Future, pin::Pin, time::Duration};
+use core::{future::Future, num::NonZeroUsize, pin::Pin, time::Duration};
 use futures::{channel::mpsc, prelude::*};
-use smoldot::informant::BytesDisplay;
-use std::{panic, sync::atomic::Ordering, task};
+use smoldot::{chain_spec, informant::BytesDisplay};
+use smoldot_light::log_filter;
+use std::{
+    panic,
+    sync::{atomic::Ordering, Mutex},
+    task,
+};
 
 pub(crate) struct Client<TPlat: smoldot_light::platform::Platform, TChain> {
     pub(crate) smoldot: smoldot_light::Client<TPlat, TChain>,
@@ -28,8 +33,26 @@ pub(crate) struct Client<TPlat: smoldot_light::platform::Platform, TChain> {
     /// List of all chains that have been added by the user.
     pub(crate) chains: slab::Slab<Chain>,
 
+    /// List of chain specifications whose upload has been started by the user and that haven't
+    /// been passed to `add_chain` yet. Contains an error if one of the chunks was invalid.
+    pub(crate) chain_spec_uploads:
+        slab::Slab<Result<chain_spec::IncrementalParser, chain_spec::ParseError>>,
+
+    /// List of genesis storages whose upload has been started by the user and that haven't
+    /// been passed to `add_chain` yet. Contains an error if one of the chunks was invalid.
+    pub(crate) genesis_storage_uploads:
+        slab::Slab<Result<chain_spec::GenesisStorageParser, chain_spec::GenesisStorageParseError>>,
+
+    /// Sending side of the channel used to spawn background tasks. Tasks sent on this channel
+    /// are executed as part of [`Client::main_task`].
+    pub(crate) new_tasks_tx: mpsc::UnboundedSender<(String, future::BoxFuture<'static, ()>)>,
+
     pub(crate) periodically_yield: bool,
 
+    /// Moment when the API user has last sent a JSON-RPC request or added a chain, or `None` if
+    /// the memory has been trimmed since then. See `trim_memory`.
+    pub(crate) last_activity: Option<crate::Instant>,
+
     /// Infinite-running task that must be executed in order to drive the execution of the client.
     pub(crate) main_task: future::BoxFuture<'static, core::convert::Infallible>, // TODO: use `!` once stable
 }
@@ -38,20 +61,22 @@ pub(crate) enum Chain {
     Healthy {
         smoldot_chain_id: smoldot_light::ChainId,
 
-        /// JSON-RPC responses that is at the front of the queue according to the API. If `Some`,
-        /// a pointer to the string is referenced to within
-        /// [`Chain::Healthy::json_rpc_response_info`].
+        /// JSON-RPC response that has been pulled from
+        /// [`Chain::Healthy::json_rpc_responses_rx`] but didn't fit in
+        /// [`Chain::Healthy::json_rpc_responses_ring`].
         json_rpc_response: Option<String>,
-        /// Information about [`Chain::Healthy::json_rpc_response`]. A pointer to this struct is
-        /// sent over the FFI layer to the JavaScript. As such, the pointer must never be
-        /// invalidated.
-        json_rpc_response_info: Box<bindings::JsonRpcResponseInfo>,
+        /// Ring buffer shared with the JavaScript and containing the JSON-RPC responses that
+        /// haven't been read yet.
+        json_rpc_responses_ring: json_rpc_ring::JsonRpcResponsesRing,
         /// Receiver for JSON-RPC responses sent by the client. `None` if JSON-RPC requests are
         /// disabled on this chain.
         /// While this could in principle be a [`smoldot_light::JsonRpcResponses`], we wrap it
         /// within a [`futures::Stream`] in order to guarantee that the `waker` that we register
         /// doesn't get cleaned up.
         json_rpc_responses_rx: Option<stream::BoxStream<'static, String>>,
+        /// Handle to the task that reports the checkpoints of the chain to the JavaScript code.
+        /// `None` if no checkpoint has been requested.
+        checkpoints_abort: Option<future::AbortHandle>,
     },
     Erroneous {
         error: String,
@@ -63,17 +88,20 @@ pub(crate) fn init<TPlat: smoldot_light::platform::Platform, TChain>(
     enable_current_task: bool,
     cpu_rate_limit: u32,
     periodically_yield: bool,
+    clock_drift_tolerance: Duration,
 ) -> Client<TPlat, TChain> {
     // Try initialize the logging and the panic hook.
     let _ = log::set_boxed_logger(Box::new(Logger)).map(|()| {
-        log::set_max_level(match max_log_level {
+        let default_level = match max_log_level {
             0 => log::LevelFilter::Off,
             1 => log::LevelFilter::Error,
             2 => log::LevelFilter::Warn,
             3 => log::LevelFilter::Info,
             4 => log::LevelFilter::Debug,
             _ => log::LevelFilter::Trace,
-        })
+        };
+        LOG_FILTER.lock().unwrap().set_default_level(default_level);
+        log::set_max_level(default_level)
     });
     panic::set_hook(Box::new(|info| {
         panic(info.to_string());
@@ -91,6 +119,9 @@ pub(crate) fn init<TPlat: smoldot_light::platform::Platform, TChain>(
     assert_ne!(rand::random::<u64>(), 0);
     assert_ne!(rand::random::<u64>(), rand::random::<u64>());
 
+    // Spawn the threads that CPU-intensive operations are offloaded to, if enabled.
+    crate::threads::init();
+
     // A channel needs to be passed to the client in order for it to spawn background tasks.
     // Since "spawning a task" isn't really something that a browser or Node environment can do
     // efficiently, we instead combine all the asynchronous tasks into one `FuturesUnordered`
@@ -200,17 +231,24 @@ pub(crate) fn init<TPlat: smoldot_light::platform::Platform, TChain>(
         .unwrap();
 
     let client = smoldot_light::Client::new(smoldot_light::ClientConfig {
-        tasks_spawner: Box::new(move |name, task| {
-            new_task_tx.unbounded_send((name, task)).unwrap()
+        tasks_spawner: Box::new({
+            let new_task_tx = new_task_tx.clone();
+            move |name, task| new_task_tx.unbounded_send((name, task)).unwrap()
         }),
         system_name: env!("CARGO_PKG_NAME").into(),
         system_version: env!("CARGO_PKG_VERSION").into(),
+        clock_drift_tolerance,
+        max_requests_per_peer: NonZeroUsize::new(3).unwrap(),
     });
 
     Client {
         smoldot: client,
         chains: slab::Slab::with_capacity(8),
+        chain_spec_uploads: slab::Slab::with_capacity(1),
+        genesis_storage_uploads: slab::Slab::new(),
+        new_tasks_tx: new_task_tx,
         periodically_yield,
+        last_activity: Some(crate::Instant::now()),
         main_task,
     }
 }
@@ -233,15 +271,35 @@ fn panic(message: String) -> ! {
     }
 }
 
+/// Modifies the filter applied to the logs according to the given directives. See
+/// [`log_filter::LogFilter::apply_directives`].
+pub(crate) fn set_log_filter(directives: &str) -> Result<(), log_filter::ParseError> {
+    let mut filter = LOG_FILTER.lock().unwrap();
+    filter.apply_directives(directives)?;
+    log::set_max_level(filter.max_level());
+    Ok(())
+}
+
+/// Filter applied by the [`Logger`]. Its default level is set during the initialization.
+static LOG_FILTER: Mutex<log_filter::LogFilter> =
+    Mutex::new(log_filter::LogFilter::new(log::LevelFilter::Off));
+
 /// Implementation of [`log::Log`] that sends out logs to the FFI.
 struct Logger;
 
 impl log::Log for Logger {
-    fn enabled(&self, _: &log::Metadata) -> bool {
-        true
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        LOG_FILTER
+            .lock()
+            .unwrap()
+            .enabled(metadata.target(), metadata.level())
     }
 
     fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
         let target = record.target();
         let message = format!("{}", record.args());
 
@@ -0,0 +1,194 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Ring buffer of JSON-RPC responses shared between the Rust and the JavaScript.
+//!
+//! The ring buffer doesn't contain the responses themselves, but the location of each response
+//! in the memory of the WebAssembly virtual machine. Responses are thus handed over to the
+//! JavaScript without being copied, and the JavaScript decodes them directly from the memory of
+//! the virtual machine. Each response is kept alive until the JavaScript indicates that it has
+//! read it. See the documentation of [`bindings::json_rpc_responses_ring`] for the format of the
+//! buffer.
+
+use crate::bindings;
+
+use std::collections::VecDeque;
+
+/// Maximum number of responses that can be in the ring buffer at the same time, plus one.
+///
+/// One entry always remains unused, as `read_offset == write_offset` means that the ring buffer
+/// is empty.
+const NUM_ENTRIES: usize = 64;
+
+/// Length in bytes of an entry of the ring buffer: a 32-bits-little-endian pointer followed with
+/// a 32-bits-little-endian length.
+const ENTRY_LEN: usize = 8;
+
+pub(crate) struct JsonRpcResponsesRing {
+    /// Memory where the entries are stored. Its content is described in the documentation of
+    /// [`bindings::json_rpc_responses_ring`].
+    buffer: Box<[u8; NUM_ENTRIES * ENTRY_LEN]>,
+
+    /// Responses referred to by the entries between the read offset and the write offset, in
+    /// order. Must never be modified, as the JavaScript reads them directly.
+    responses: VecDeque<String>,
+
+    /// Information about [`JsonRpcResponsesRing::buffer`]. A pointer to this struct is sent over
+    /// the FFI layer to the JavaScript. As such, the pointer must never be invalidated.
+    info: Box<bindings::JsonRpcResponsesRingInfo>,
+}
+
+impl JsonRpcResponsesRing {
+    /// Creates a new empty ring buffer.
+    pub(crate) fn new() -> Self {
+        let buffer = Box::new([0; NUM_ENTRIES * ENTRY_LEN]);
+        let info = Box::new(bindings::JsonRpcResponsesRingInfo {
+            buffer_ptr: buffer.as_ptr() as usize as u32,
+            buffer_len: u32::try_from(buffer.len()).unwrap(),
+            read_offset: 0,
+            write_offset: 0,
+        });
+
+        JsonRpcResponsesRing {
+            buffer,
+            responses: VecDeque::with_capacity(NUM_ENTRIES - 1),
+            info,
+        }
+    }
+
+    /// Returns a pointer to the [`bindings::JsonRpcResponsesRingInfo`] that describes this ring
+    /// buffer. The pointer remains valid as long as `self` is alive.
+    pub(crate) fn info_ptr(&self) -> u32 {
+        (&*self.info) as *const bindings::JsonRpcResponsesRingInfo as usize as u32
+    }
+
+    /// Updates the offset where the reader will read the next entry, and frees the responses
+    /// that have been read.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the offset is out of range or doesn't point to the start of an entry.
+    ///
+    pub(crate) fn set_read_offset(&mut self, read_offset: u32) {
+        let read_offset = usize::try_from(read_offset).unwrap();
+        assert!(read_offset < self.buffer.len());
+        assert_eq!(read_offset % ENTRY_LEN, 0);
+
+        let num_read = (read_offset + self.buffer.len()
+            - usize::try_from(self.info.read_offset).unwrap())
+            % self.buffer.len()
+            / ENTRY_LEN;
+        assert!(num_read <= self.responses.len());
+
+        self.responses.drain(..num_read);
+        self.info.read_offset = u32::try_from(read_offset).unwrap();
+    }
+
+    /// If the ring buffer is empty, frees the memory used to keep track of the responses beyond
+    /// what is necessary.
+    pub(crate) fn shrink_if_empty(&mut self) {
+        if self.responses.is_empty() {
+            self.responses.shrink_to(NUM_ENTRIES - 1);
+        }
+    }
+
+    /// Tries to append a response at the end of the ring buffer. Returns back the response if
+    /// the ring buffer is full, in which case the response must be pushed again later, once the
+    /// reader has made progress.
+    pub(crate) fn try_push(&mut self, response: String) -> Result<(), String> {
+        if self.responses.len() >= NUM_ENTRIES - 1 {
+            return Err(response);
+        }
+
+        let write = usize::try_from(self.info.write_offset).unwrap();
+        self.buffer[write..write + 4]
+            .copy_from_slice(&(response.as_ptr() as usize as u32).to_le_bytes());
+        self.buffer[write + 4..write + 8]
+            .copy_from_slice(&u32::try_from(response.len()).unwrap().to_le_bytes());
+        self.info.write_offset = u32::try_from((write + ENTRY_LEN) % self.buffer.len()).unwrap();
+
+        // Moving the `String` doesn't move its content.
+        self.responses.push_back(response);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonRpcResponsesRing, ENTRY_LEN, NUM_ENTRIES};
+
+    /// Reads the entry at the given offset, and returns the pointer and length it contains.
+    fn entry(ring: &JsonRpcResponsesRing, offset: u32) -> (u32, u32) {
+        let offset = usize::try_from(offset).unwrap();
+        let ptr = u32::from_le_bytes(<[u8; 4]>::try_from(&ring.buffer[offset..][..4]).unwrap());
+        let len = u32::from_le_bytes(<[u8; 4]>::try_from(&ring.buffer[offset + 4..][..4]).unwrap());
+        (ptr, len)
+    }
+
+    #[test]
+    fn responses_not_copied() {
+        let mut ring = JsonRpcResponsesRing::new();
+        assert_eq!(ring.info.read_offset, ring.info.write_offset);
+
+        let response = String::from("{\"jsonrpc\":\"2.0\",\"id\":1,\"result\":null}");
+        let expected = (response.as_ptr() as usize as u32, response.len() as u32);
+        ring.try_push(response).unwrap();
+
+        assert_eq!(entry(&ring, ring.info.read_offset), expected);
+        assert_eq!(ring.info.write_offset, ENTRY_LEN as u32);
+        assert_eq!(ring.responses[0].as_ptr() as usize as u32, expected.0);
+    }
+
+    #[test]
+    fn full_ring_returns_response() {
+        let mut ring = JsonRpcResponsesRing::new();
+        for n in 0..NUM_ENTRIES - 1 {
+            ring.try_push(n.to_string()).unwrap();
+        }
+        assert_eq!(ring.try_push("foo".into()), Err("foo".into()));
+
+        // Releasing one entry makes space for one more.
+        ring.set_read_offset(ENTRY_LEN as u32);
+        assert_eq!(ring.responses.len(), NUM_ENTRIES - 2);
+        assert_eq!(ring.responses[0], "1");
+        ring.try_push("foo".into()).unwrap();
+        assert_eq!(ring.info.write_offset, 0);
+        assert_eq!(ring.try_push("bar".into()), Err("bar".into()));
+    }
+
+    #[test]
+    fn wraps_around() {
+        let mut ring = JsonRpcResponsesRing::new();
+        for n in 0..NUM_ENTRIES * 3 {
+            let response = n.to_string();
+            let expected = (response.as_ptr() as usize as u32, response.len() as u32);
+            ring.try_push(response).unwrap();
+            assert_eq!(entry(&ring, ring.info.read_offset), expected);
+            ring.set_read_offset(ring.info.write_offset);
+            assert!(ring.responses.is_empty());
+            ring.shrink_if_empty();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn release_too_many() {
+        let mut ring = JsonRpcResponsesRing::new();
+        ring.try_push("foo".into()).unwrap();
+        ring.set_read_offset(2 * ENTRY_LEN as u32);
+    }
+}
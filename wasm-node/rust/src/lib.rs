@@ -22,6 +22,7 @@
 
 use core::{
     cmp::Ordering,
+    num::NonZeroU32,
     ops::{Add, Sub},
     pin::Pin,
     str,
@@ -29,6 +30,7 @@ use core::{
     time::Duration,
 };
 use futures::prelude::*;
+use smoldot::chain_spec;
 use smoldot_light::HandleRpcError;
 use std::{
     sync::{Arc, Mutex},
@@ -40,7 +42,9 @@ pub mod bindings;
 mod alloc;
 mod cpu_rate_limiter;
 mod init;
+mod json_rpc_ring;
 mod platform;
+mod threads;
 mod timers;
 
 /// Uses the environment to invoke `closure` after at least `duration` has elapsed.
@@ -126,40 +130,202 @@ impl Sub<Instant> for Instant {
 
 static CLIENT: Mutex<Option<init::Client<platform::Platform, ()>>> = Mutex::new(None);
 
+/// Interval between two calls to [`trim_memory`].
+const MEMORY_TRIM_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Duration without any JSON-RPC request or new chain after which [`trim_memory`] considers
+/// the client as idle and trims the memory.
+const MEMORY_TRIM_IDLE_DURATION: Duration = Duration::from_secs(5 * 60);
+
+/// Number of allocated bytes above which [`trim_memory`] trims the memory even if the client
+/// isn't idle. The threshold is completely empirical.
+const MEMORY_TRIM_PRESSURE_THRESHOLD: usize = 1024 * 1024 * 1024;
+
 fn init(
     max_log_level: u32,
     enable_current_task: u32,
     cpu_rate_limit: u32,
     periodically_yield: u32,
+    clock_drift_tolerance_ms: u32,
 ) {
     let init_out = init::init(
         max_log_level,
         enable_current_task != 0,
         cpu_rate_limit,
         periodically_yield != 0,
+        Duration::from_millis(u64::from(clock_drift_tolerance_ms)),
     );
 
     let mut client_lock = crate::CLIENT.lock().unwrap();
     assert!(client_lock.is_none());
     *client_lock = Some(init_out);
+
+    start_timer_wrap(MEMORY_TRIM_CHECK_INTERVAL, trim_memory);
+}
+
+/// If the client has been idle for [`MEMORY_TRIM_IDLE_DURATION`] or is using more than
+/// [`MEMORY_TRIM_PRESSURE_THRESHOLD`] bytes of memory, shrinks the buffers that have grown
+/// larger than necessary and the caches of the chains. Then schedules the next call.
+///
+/// The memory of the WebAssembly virtual machine can only grow and is never given back to the
+/// environment. However, shrinking the buffers that are no longer in use lets the allocator
+/// reuse their memory for other purposes rather than growing the memory further. Buffers that
+/// are in use, such as the JSON-RPC responses ring buffer of a chain whose responses haven't
+/// all been read yet, are left untouched.
+///
+/// The caches of the chains, such as the cache of storage values and the runtimes of old blocks
+/// downloaded for JSON-RPC calls, are shrunk through [`smoldot_light::Client::trim_caches`].
+/// Because this discards entries that would otherwise save network requests and runtime
+/// compilations, this is done only once per period of idleness, unless memory is scarce.
+fn trim_memory() {
+    {
+        let mut client_lock = CLIENT.lock().unwrap();
+        let client = client_lock.as_mut().unwrap();
+
+        let is_idle = client.last_activity.map_or(false, |last| {
+            Instant::now() - last >= MEMORY_TRIM_IDLE_DURATION
+        });
+        let under_pressure = alloc::total_alloc_bytes() >= MEMORY_TRIM_PRESSURE_THRESHOLD;
+
+        if is_idle || under_pressure {
+            client.last_activity = None;
+
+            for (_, chain) in &mut client.chains {
+                if let init::Chain::Healthy {
+                    json_rpc_response: None,
+                    json_rpc_responses_ring,
+                    ..
+                } = chain
+                {
+                    json_rpc_responses_ring.shrink_if_empty();
+                }
+            }
+
+            let trim_caches = client.smoldot.trim_caches();
+            let _ = client
+                .new_tasks_tx
+                .unbounded_send(("trim-caches".to_owned(), trim_caches.boxed()));
+
+            client.chains.shrink_to_fit();
+            client.chain_spec_uploads.shrink_to_fit();
+            client.genesis_storage_uploads.shrink_to_fit();
+        }
+    }
+
+    start_timer_wrap(MEMORY_TRIM_CHECK_INTERVAL, trim_memory);
 }
 
 fn set_periodically_yield(periodically_yield: u32) {
     CLIENT.lock().unwrap().as_mut().unwrap().periodically_yield = periodically_yield != 0;
 }
 
+fn set_log_filter(directives: Vec<u8>) -> u32 {
+    let Ok(directives) = str::from_utf8(&directives) else {
+        return 1;
+    };
+
+    match init::set_log_filter(directives) {
+        Ok(()) => 0,
+        Err(_) => 1,
+    }
+}
+
 fn start_shutdown() {
     // TODO: do this in a clean way
     std::process::exit(0)
 }
 
+fn chain_spec_upload_start() -> u32 {
+    let mut client_lock = CLIENT.lock().unwrap();
+    let upload_id = client_lock
+        .as_mut()
+        .unwrap()
+        .chain_spec_uploads
+        .insert(Ok(chain_spec::IncrementalParser::new()));
+    u32::try_from(upload_id).unwrap()
+}
+
+fn chain_spec_upload_push(upload_id: u32, chunk: Vec<u8>) {
+    let mut client_lock = CLIENT.lock().unwrap();
+    let upload = client_lock
+        .as_mut()
+        .unwrap()
+        .chain_spec_uploads
+        .get_mut(usize::try_from(upload_id).unwrap())
+        .unwrap();
+
+    // Errors are reported only when the chain is added.
+    if let Ok(parser) = upload {
+        if let Err(error) = parser.push_chunk(&chunk) {
+            *upload = Err(error);
+        }
+    }
+}
+
+fn genesis_storage_upload_start() -> u32 {
+    let mut client_lock = CLIENT.lock().unwrap();
+    let upload_id = client_lock
+        .as_mut()
+        .unwrap()
+        .genesis_storage_uploads
+        .insert(Ok(chain_spec::GenesisStorageParser::new()));
+    u32::try_from(upload_id).unwrap()
+}
+
+fn genesis_storage_upload_push(upload_id: u32, chunk: Vec<u8>) {
+    let mut client_lock = CLIENT.lock().unwrap();
+    let upload = client_lock
+        .as_mut()
+        .unwrap()
+        .genesis_storage_uploads
+        .get_mut(usize::try_from(upload_id).unwrap())
+        .unwrap();
+
+    // Errors are reported only when the chain is added.
+    if let Ok(parser) = upload {
+        if let Err(error) = parser.push_chunk(&chunk) {
+            *upload = Err(error);
+        }
+    }
+}
+
 fn add_chain(
-    chain_spec: Vec<u8>,
+    chain_spec_upload_id: u32,
+    genesis_storage_upload_id: u32,
     database_content: Vec<u8>,
     json_rpc_running: u32,
+    json_rpc_methods_filter_mode: u32,
+    json_rpc_methods_filter_patterns: Vec<u8>,
     potential_relay_chains: Vec<u8>,
+    checkpoint_refresh_period_ms: u32,
+    checkpoint_max_size: u32,
+    warp_sync_min_distinct_peers: u32,
 ) -> u32 {
     let mut client_lock = CLIENT.lock().unwrap();
+    client_lock.as_mut().unwrap().last_activity = Some(Instant::now());
+
+    // Finish parsing the chain specification. The upload is destroyed no matter whether the
+    // chain is successfully added.
+    let chain_spec = client_lock
+        .as_mut()
+        .unwrap()
+        .chain_spec_uploads
+        .remove(usize::try_from(chain_spec_upload_id).unwrap())
+        .and_then(|parser| parser.finish());
+
+    // Same for the genesis storage, if any.
+    let genesis_storage = if genesis_storage_upload_id != u32::max_value() {
+        Some(
+            client_lock
+                .as_mut()
+                .unwrap()
+                .genesis_storage_uploads
+                .remove(usize::try_from(genesis_storage_upload_id).unwrap())
+                .and_then(|parser| parser.finish()),
+        )
+    } else {
+        None
+    };
 
     // Fail any new chain initialization if we're running low on memory space, which can
     // realistically happen as Wasm is a 32 bits platform. This avoids potentially running into
@@ -202,22 +368,90 @@ fn add_chain(
             .collect()
     };
 
+    // Retrieve the JSON-RPC methods filter passed through the FFI layer.
+    let json_rpc_methods_filter = {
+        let patterns = str::from_utf8(&json_rpc_methods_filter_patterns)
+            .unwrap_or_else(|_| panic!("non-utf8 JSON-RPC methods filter"))
+            .split(',')
+            .filter(|pattern| !pattern.is_empty())
+            .map(|pattern| pattern.to_owned())
+            .collect();
+        match json_rpc_methods_filter_mode {
+            0 => smoldot_light::JsonRpcMethodsFilter::AllowAll,
+            1 => smoldot_light::JsonRpcMethodsFilter::Allow(patterns),
+            2 => smoldot_light::JsonRpcMethodsFilter::Deny(patterns),
+            _ => panic!("invalid JSON-RPC methods filter mode"),
+        }
+    };
+
+    let chain_spec = match chain_spec {
+        Ok(cs) => cs,
+        Err(error) => {
+            let chain_id = client_lock
+                .as_mut()
+                .unwrap()
+                .chains
+                .insert(init::Chain::Erroneous {
+                    error: smoldot_light::AddChainError::ChainSpecParseError(error).to_string(),
+                });
+
+            return u32::try_from(chain_id).unwrap();
+        }
+    };
+
+    let genesis_storage = match genesis_storage {
+        None => None,
+        Some(Ok(storage)) => Some(smoldot_light::GenesisStorage::Parsed(storage)),
+        Some(Err(error)) => {
+            let chain_id = client_lock
+                .as_mut()
+                .unwrap()
+                .chains
+                .insert(init::Chain::Erroneous {
+                    error: smoldot_light::AddChainError::GenesisStorageParseError(error)
+                        .to_string(),
+                });
+
+            return u32::try_from(chain_id).unwrap();
+        }
+    };
+
     // Insert the chain in the client.
     let smoldot_light::AddChainSuccess {
         chain_id: smoldot_chain_id,
         json_rpc_responses,
+        checkpoints,
+        ..
     } = match client_lock
         .as_mut()
         .unwrap()
         .smoldot
         .add_chain(smoldot_light::AddChainConfig {
             user_data: (),
-            specification: str::from_utf8(&chain_spec)
-                .unwrap_or_else(|_| panic!("non-utf8 chain spec")),
+            specification: smoldot_light::ChainSpecification::Parsed(chain_spec),
+            genesis_storage,
+            fork_id: Default::default(),
             database_content: str::from_utf8(&database_content)
                 .unwrap_or_else(|_| panic!("non-utf8 database content")),
             disable_json_rpc: json_rpc_running == 0,
+            json_rpc_methods_filter,
+            ethereum_json_rpc: false,
+            block_announce_policy: smoldot_light::BlockAnnouncePolicy::Immediate,
+            warp_sync_min_distinct_peers: NonZeroU32::new(warp_sync_min_distinct_peers)
+                .unwrap_or(NonZeroU32::new(1).unwrap()),
+            finality_proofs_window: 0,
+            skip_seal_verification: false,
+            transactions_pool: Default::default(),
             potential_relay_chains: potential_relay_chains.into_iter(),
+            auto_add_relay_chain: None,
+            checkpoint_refresh: if checkpoint_refresh_period_ms != 0 {
+                Some(smoldot_light::CheckpointRefreshConfig {
+                    period: Duration::from_millis(u64::from(checkpoint_refresh_period_ms)),
+                    max_size: usize::try_from(checkpoint_max_size).unwrap_or(usize::max_value()),
+                })
+            } else {
+                None
+            },
         }) {
         Ok(c) => c,
         Err(error) => {
@@ -240,11 +474,38 @@ fn add_chain(
         .insert(init::Chain::Healthy {
             smoldot_chain_id,
             json_rpc_response: None,
-            json_rpc_response_info: Box::new(bindings::JsonRpcResponseInfo { ptr: 0, len: 0 }),
+            json_rpc_responses_ring: json_rpc_ring::JsonRpcResponsesRing::new(),
             json_rpc_responses_rx: None,
+            checkpoints_abort: None,
         });
     let outer_chain_id_u32 = u32::try_from(outer_chain_id).unwrap();
 
+    // Forward the checkpoints to the JavaScript code, if requested. The forwarding task is
+    // aborted when the chain is removed, in order to guarantee that no checkpoint is reported
+    // for a chain that no longer exists.
+    let checkpoints_abort = checkpoints.map(|mut checkpoints| {
+        let (task, abort_handle) = future::abortable(async move {
+            while let Some(checkpoint) = checkpoints.next().await {
+                unsafe {
+                    bindings::checkpoint_refreshed(
+                        outer_chain_id_u32,
+                        u32::try_from(checkpoint.as_bytes().as_ptr() as usize).unwrap(),
+                        u32::try_from(checkpoint.as_bytes().len()).unwrap(),
+                    )
+                }
+            }
+        });
+
+        client_lock
+            .as_mut()
+            .unwrap()
+            .new_tasks_tx
+            .unbounded_send(("checkpoints-forward".to_owned(), task.map(|_| ()).boxed()))
+            .unwrap();
+
+        abort_handle
+    });
+
     // We wrap the JSON-RPC responses stream into a proper stream in order to be able to guarantee
     // that `poll_next()` always operates on the same future.
     let mut json_rpc_responses = json_rpc_responses.map(|json_rpc_responses| {
@@ -272,6 +533,7 @@ fn add_chain(
 
     if let init::Chain::Healthy {
         json_rpc_responses_rx,
+        checkpoints_abort: checkpoints_abort_slot,
         ..
     } = client_lock
         .as_mut()
@@ -281,6 +543,7 @@ fn add_chain(
         .unwrap()
     {
         *json_rpc_responses_rx = json_rpc_responses;
+        *checkpoints_abort_slot = checkpoints_abort;
     }
 
     outer_chain_id_u32
@@ -298,6 +561,7 @@ fn remove_chain(chain_id: u32) {
         init::Chain::Healthy {
             smoldot_chain_id,
             json_rpc_responses_rx,
+            checkpoints_abort,
             ..
         } => {
             // We've polled the JSON-RPC receiver with a waker that calls
@@ -311,6 +575,10 @@ fn remove_chain(chain_id: u32) {
                 );
             }
 
+            if let Some(checkpoints_abort) = checkpoints_abort {
+                checkpoints_abort.abort();
+            }
+
             let () = client_lock
                 .as_mut()
                 .unwrap()
@@ -368,12 +636,57 @@ fn chain_error_ptr(chain_id: u32) -> u32 {
     }
 }
 
+fn chain_cpu_time_ms(chain_id: u32) -> f64 {
+    let client_lock = CLIENT.lock().unwrap();
+    let client_lock = client_lock.as_ref().unwrap();
+    match client_lock
+        .chains
+        .get(usize::try_from(chain_id).unwrap())
+        .unwrap()
+    {
+        init::Chain::Healthy {
+            smoldot_chain_id, ..
+        } => {
+            client_lock
+                .smoldot
+                .chain_cpu_time(*smoldot_chain_id)
+                .as_secs_f64()
+                * 1000.0
+        }
+        init::Chain::Erroneous { .. } => 0.0,
+    }
+}
+
+fn chain_set_paused(chain_id: u32, paused: bool) {
+    let mut client_lock = CLIENT.lock().unwrap();
+    let client_lock = client_lock.as_mut().unwrap();
+    match client_lock
+        .chains
+        .get(usize::try_from(chain_id).unwrap())
+        .unwrap()
+    {
+        init::Chain::Healthy {
+            smoldot_chain_id, ..
+        } => {
+            let smoldot_chain_id = *smoldot_chain_id;
+            if paused {
+                client_lock.smoldot.pause_chain(smoldot_chain_id);
+            } else {
+                client_lock.smoldot.resume_chain(smoldot_chain_id);
+            }
+        }
+        init::Chain::Erroneous { .. } => {}
+    }
+}
+
 fn json_rpc_send(json_rpc_request: Vec<u8>, chain_id: u32) -> u32 {
     // As mentioned in the documentation, the bytes *must* be valid UTF-8.
     let json_rpc_request: String = String::from_utf8(json_rpc_request.into())
         .unwrap_or_else(|_| panic!("non-UTF-8 JSON-RPC request"));
 
     let mut client_lock = CLIENT.lock().unwrap();
+    client_lock.as_mut().unwrap().last_activity = Some(Instant::now());
+
     let client_chain_id = match client_lock
         .as_ref()
         .unwrap()
@@ -399,7 +712,7 @@ fn json_rpc_send(json_rpc_request: Vec<u8>, chain_id: u32) -> u32 {
     }
 }
 
-fn json_rpc_responses_peek(chain_id: u32) -> u32 {
+fn json_rpc_responses_ring(chain_id: u32) -> u32 {
     let mut client_lock = CLIENT.lock().unwrap();
     match client_lock
         .as_mut()
@@ -411,63 +724,47 @@ fn json_rpc_responses_peek(chain_id: u32) -> u32 {
         init::Chain::Healthy {
             json_rpc_response,
             json_rpc_responses_rx,
-            json_rpc_response_info,
+            json_rpc_responses_ring,
             ..
         } => {
-            if json_rpc_response.is_none() {
-                if let Some(json_rpc_responses_rx) = json_rpc_responses_rx.as_mut() {
-                    loop {
-                        match Pin::new(&mut *json_rpc_responses_rx).poll_next(
+            if let Some(json_rpc_responses_rx) = json_rpc_responses_rx.as_mut() {
+                loop {
+                    let response = match json_rpc_response.take() {
+                        Some(rp) => rp,
+                        None => match Pin::new(&mut *json_rpc_responses_rx).poll_next(
                             &mut task::Context::from_waker(
                                 &Arc::new(JsonRpcResponsesNonEmptyWaker { chain_id }).into(),
                             ),
                         ) {
-                            task::Poll::Ready(Some(response)) if response.is_empty() => {
-                                // The API of `json_rpc_responses_peek` says that a length of 0
-                                // indicates that the queue is empty. For this reason, we skip
-                                // this response.
-                                // This is a pretty niche situation, but at least we handle it
-                                // properly.
-                            }
-                            task::Poll::Ready(Some(response)) => {
-                                debug_assert!(!response.is_empty());
-                                *json_rpc_response = Some(response);
-                                break;
-                            }
+                            task::Poll::Ready(Some(response)) => response,
                             task::Poll::Ready(None) => unreachable!(),
                             task::Poll::Pending => break,
-                        }
+                        },
+                    };
+
+                    // If the ring buffer is full, the response is kept aside and will be pushed
+                    // the next time this function is called.
+                    if let Err(response) = json_rpc_responses_ring.try_push(response) {
+                        *json_rpc_response = Some(response);
+                        break;
                     }
                 }
             }
 
-            // Note that we might be returning the last item in the queue. In principle, this means
-            // that the next time an entry is added to the queue, `json_rpc_responses_non_empty`
-            // should be called. Due to the way the implementation works, this will not happen
-            // until the user calls `json_rpc_responses_peek`. However, this is not a problem:
-            // it is impossible for the user to observe that the queue is empty, and as such there
-            // is simply not correct implementation of the API that can't work because of this
-            // property.
-
-            match &json_rpc_response {
-                Some(rp) => {
-                    debug_assert!(!rp.is_empty());
-                    json_rpc_response_info.ptr = rp.as_bytes().as_ptr() as u32;
-                    json_rpc_response_info.len = rp.as_bytes().len() as u32;
-                }
-                None => {
-                    json_rpc_response_info.ptr = 0;
-                    json_rpc_response_info.len = 0;
-                }
-            }
+            // Note that the ring buffer might be full while the stream of responses is still
+            // non-empty. In that situation, `json_rpc_responses_non_empty` will not be called
+            // until the user releases some space and calls `json_rpc_responses_ring` again.
+            // However, this is not a problem: it is impossible for the user to observe that the
+            // queue is empty, and as such there is simply not correct implementation of the API
+            // that can't work because of this property.
 
-            (&**json_rpc_response_info) as *const bindings::JsonRpcResponseInfo as usize as u32
+            json_rpc_responses_ring.info_ptr()
         }
         _ => panic!(),
     }
 }
 
-fn json_rpc_responses_pop(chain_id: u32) {
+fn json_rpc_responses_ring_release(chain_id: u32, read_offset: u32) {
     let mut client_lock = CLIENT.lock().unwrap();
     match client_lock
         .as_mut()
@@ -477,8 +774,9 @@ fn json_rpc_responses_pop(chain_id: u32) {
         .unwrap()
     {
         init::Chain::Healthy {
-            json_rpc_response, ..
-        } => *json_rpc_response = None,
+            json_rpc_responses_ring,
+            ..
+        } => json_rpc_responses_ring.set_read_offset(read_offset),
         _ => panic!(),
     }
 }
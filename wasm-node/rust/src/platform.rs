@@ -43,6 +43,7 @@ pub(crate) struct Platform;
 impl smoldot_light::platform::Platform for Platform {
     type Delay = Delay;
     type Yield = Yield;
+    type CpuIntensiveFuture<T: Send + 'static> = crate::threads::CpuIntensiveFuture<T>;
     type Instant = crate::Instant;
     type Connection = ConnectionWrapper; // Entry in the ̀`CONNECTIONS` map.
     type Stream = StreamWrapper; // Entry in the ̀`STREAMS` map and a read buffer.
@@ -100,6 +101,12 @@ impl smoldot_light::platform::Platform for Platform {
         }
     }
 
+    fn run_cpu_intensive<T: Send + 'static>(
+        operation: impl FnOnce() -> T + Send + 'static,
+    ) -> Self::CpuIntensiveFuture<T> {
+        crate::threads::run_cpu_intensive(operation)
+    }
+
     fn connect(url: &str) -> Self::ConnectFuture {
         let mut lock = STATE.try_lock().unwrap();
 
@@ -0,0 +1,199 @@
+// Smoldot
+// Copyright (C) 2019-2022  Parity Technologies (UK) Ltd.
+// SPDX-License-Identifier: GPL-3.0-or-later WITH Classpath-exception-2.0
+
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+//! Execution of CPU-intensive operations, such as compiling runtimes or verifying signatures.
+//!
+//! By default, the operations are simply executed immediately on the current thread.
+//!
+//! If the `threads` feature is enabled, the Wasm module must be compiled with support for
+//! atomics and its memory must be shared. The client then asks the environment to spawn worker
+//! threads by calling [`bindings::spawn_worker_threads`]. Each worker thread must call
+//! [`bindings::worker_thread_run`], which waits for CPU-intensive operations to be queued and
+//! executes them. As long as no worker thread has started, the operations continue to be
+//! executed on the current thread.
+//!
+//! Since browsers forbid blocking the main thread, the main thread never waits for the worker
+//! threads. It instead queues the operation, and the worker thread that has executed it calls
+//! [`bindings::wake_up_main_thread`], as the main thread only resumes its execution when it is
+//! called by the host. The host then calls [`bindings::main_thread_wake_up`] on the main thread.
+
+use core::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use futures::prelude::*;
+
+#[cfg(feature = "threads")]
+use crate::bindings;
+
+/// Number of worker threads that the environment is asked to spawn.
+#[cfg(feature = "threads")]
+const NUM_WORKER_THREADS: u32 = 2;
+
+/// Asks the environment to spawn the worker threads, if the `threads` feature is enabled. Does
+/// nothing otherwise.
+pub(crate) fn init() {
+    #[cfg(feature = "threads")]
+    unsafe {
+        bindings::spawn_worker_threads(NUM_WORKER_THREADS)
+    }
+}
+
+/// Runs the given CPU-intensive operation and returns a future that yields its output.
+pub(crate) fn run_cpu_intensive<T: Send + 'static>(
+    operation: impl FnOnce() -> T + Send + 'static,
+) -> CpuIntensiveFuture<T> {
+    #[cfg(feature = "threads")]
+    if workers::NUM_RUNNING.load(core::sync::atomic::Ordering::Acquire) != 0 {
+        let (tx, rx) = futures::channel::oneshot::channel();
+        workers::queue(Box::new(move || {
+            let _ = tx.send(operation());
+        }));
+        return CpuIntensiveFuture(CpuIntensiveFutureInner::Queued(rx));
+    }
+
+    CpuIntensiveFuture(CpuIntensiveFutureInner::Finished(Some(operation())))
+}
+
+/// Future returned by [`run_cpu_intensive`].
+pub(crate) struct CpuIntensiveFuture<T>(CpuIntensiveFutureInner<T>);
+
+enum CpuIntensiveFutureInner<T> {
+    Finished(Option<T>),
+    #[cfg(feature = "threads")]
+    Queued(futures::channel::oneshot::Receiver<T>),
+}
+
+impl<T> Unpin for CpuIntensiveFuture<T> {}
+
+impl<T> Future for CpuIntensiveFuture<T> {
+    type Output = T;
+
+    fn poll(mut self: Pin<&mut Self>, _cx: &mut Context) -> Poll<T> {
+        match &mut self.0 {
+            CpuIntensiveFutureInner::Finished(output) => Poll::Ready(output.take().unwrap()),
+            // The sending side is only ever dropped after having sent the output, as worker
+            // threads never abandon an operation. Sending the output wakes up the task.
+            #[cfg(feature = "threads")]
+            CpuIntensiveFutureInner::Queued(rx) => rx.poll_unpin(_cx).map(|out| out.unwrap()),
+        }
+    }
+}
+
+#[cfg(feature = "threads")]
+pub(crate) mod workers {
+    use std::{
+        collections::VecDeque,
+        sync::{atomic, Condvar, Mutex, TryLockError},
+    };
+
+    /// Number of worker threads that have called [`run`].
+    pub(super) static NUM_RUNNING: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+
+    /// Operations waiting to be picked by a worker thread.
+    static QUEUE: Mutex<VecDeque<Box<dyn FnOnce() + Send>>> = Mutex::new(VecDeque::new());
+
+    /// Notified whenever an operation is pushed to [`QUEUE`].
+    static QUEUE_NOTIFY: Condvar = Condvar::new();
+
+    /// Adds an operation to the queue and wakes up a worker thread.
+    pub(super) fn queue(operation: Box<dyn FnOnce() + Send>) {
+        // The main thread isn't allowed to block, and thus spins until the lock is available.
+        // The lock is only ever held for a very short time.
+        let mut queue = loop {
+            match QUEUE.try_lock() {
+                Ok(queue) => break queue,
+                Err(TryLockError::WouldBlock) => core::hint::spin_loop(),
+                Err(TryLockError::Poisoned(_)) => unreachable!(),
+            }
+        };
+        queue.push_back(operation);
+        drop(queue);
+        QUEUE_NOTIFY.notify_one();
+    }
+
+    /// Executes queued operations. Never returns.
+    pub(crate) fn run() -> ! {
+        NUM_RUNNING.fetch_add(1, atomic::Ordering::AcqRel);
+
+        loop {
+            let operation = {
+                let mut queue = QUEUE.lock().unwrap();
+                loop {
+                    if let Some(operation) = queue.pop_front() {
+                        break operation;
+                    }
+                    queue = QUEUE_NOTIFY.wait(queue).unwrap();
+                }
+            };
+
+            operation();
+
+            // The output of the operation has been sent, which has woken up the task waiting
+            // for it. The main thread must now resume its execution in order to poll that task.
+            wake_up_main_thread();
+        }
+    }
+
+    #[cfg(not(test))]
+    fn wake_up_main_thread() {
+        unsafe { crate::bindings::wake_up_main_thread() }
+    }
+
+    #[cfg(test)]
+    fn wake_up_main_thread() {
+        NUM_WAKE_UPS.fetch_add(1, atomic::Ordering::AcqRel);
+    }
+
+    /// Number of times the main thread has been woken up.
+    #[cfg(test)]
+    pub(super) static NUM_WAKE_UPS: atomic::AtomicU32 = atomic::AtomicU32::new(0);
+}
+
+#[cfg(all(test, feature = "threads"))]
+mod tests {
+    use core::sync::atomic;
+    use futures::prelude::*;
+
+    #[test]
+    fn operations_executed_on_worker_thread() {
+        // Without any worker thread, the operation is executed immediately.
+        let mut future = super::run_cpu_intensive(|| std::thread::current().id());
+        assert_eq!(
+            (&mut future).now_or_never(),
+            Some(std::thread::current().id())
+        );
+
+        let worker = std::thread::spawn(|| super::workers::run());
+        while super::workers::NUM_RUNNING.load(atomic::Ordering::Acquire) == 0 {
+            std::thread::yield_now();
+        }
+
+        let wake_ups_before = super::workers::NUM_WAKE_UPS.load(atomic::Ordering::Acquire);
+        let (tx, rx) = std::sync::mpsc::channel::<()>();
+        let future = super::run_cpu_intensive(move || {
+            rx.recv().unwrap();
+            std::thread::current().id()
+        });
+
+        // `block_on` only polls the future again once it has been woken up.
+        let outcome = std::thread::spawn(move || futures::executor::block_on(future));
+        tx.send(()).unwrap();
+        assert_eq!(outcome.join().unwrap(), worker.thread().id());
+        assert!(super::workers::NUM_WAKE_UPS.load(atomic::Ordering::Acquire) > wake_ups_before);
+    }
+}
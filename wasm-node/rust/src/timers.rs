@@ -18,19 +18,31 @@
 //! This module provides the `Delay` struct, which implement `Future` and becomes ready after a
 //! certain time.
 //!
-//! In order to optimize performances, we avoid invoking the FFI once per timer. Instead, the FFI
-//! is only used in order to wake up when the earliest timer finishes, then restarted for the next
-//! timer.
+//! In order to optimize performances, we avoid invoking the FFI once per timer. Instead, the
+//! timers are stored in a hierarchical timer wheel, and the FFI is only used in order to wake up
+//! when the earliest slot of the wheel expires.
+//!
+//! Time is divided in ticks of [`TICK`]. The moment when a timer finishes is rounded up to the
+//! next tick, so that all the timers finishing within the same tick are processed together and
+//! share the same call to the FFI. Since the FFI doesn't support cancelling a timer, the
+//! list of the deadlines of the calls in progress is tracked in order to never start two calls
+//! for the same tick.
+//!
+//! The timer wheel consists in [`NUM_LEVELS`] levels of [`SLOTS_PER_LEVEL`] slots each. Each
+//! slot of level `N` covers `SLOTS_PER_LEVEL.pow(N)` ticks. A timer is inserted in the lowest
+//! level whose slots are precise enough to distinguish it from the current tick. When the
+//! current tick reaches the beginning of a slot of a level above 0, the timers of this slot are
+//! moved to a lower level. This makes inserting a timer `O(1)`, no matter how many timers are
+//! pending.
 
 use core::{
-    cmp::{Eq, Ord, Ordering, PartialEq, PartialOrd},
     future::Future,
     pin::Pin,
     task::{Context, Poll, Waker},
     time::Duration,
 };
 use futures::{lock::Mutex, prelude::*};
-use std::collections::BinaryHeap;
+use std::collections::BTreeSet;
 
 pub(crate) fn timer_finished(timer_id: u32) {
     let callback = {
@@ -43,6 +55,21 @@ pub(crate) fn timer_finished(timer_id: u32) {
 
 use super::Instant;
 
+/// Duration of a tick of the timer wheel. Timers can finish up to this amount of time later
+/// than requested.
+const TICK: Duration = Duration::from_millis(4);
+
+/// Number of bits of a tick number used to determine a slot within a level.
+const BITS_PER_LEVEL: u32 = 6;
+
+/// Number of slots of each level of the timer wheel.
+const SLOTS_PER_LEVEL: usize = 1 << BITS_PER_LEVEL;
+
+/// Number of levels of the timer wheel. With 4 milliseconds ticks, the top level covers
+/// approximately 8.7 years. Timers that finish later than that are moved down the wheel
+/// multiple times.
+const NUM_LEVELS: usize = 6;
+
 /// `Future` that automatically wakes up after a certain amount of time has elapsed.
 pub struct Delay {
     /// Index in `TIMERS::timers`. Guaranteed to have `is_obsolete` equal to `false`.
@@ -68,26 +95,28 @@ impl Delay {
 
         // Because we're in a single-threaded environment, `try_lock()` should always succeed.
         let mut lock = TIMERS.try_lock().unwrap();
+        let lock = &mut *lock;
+
+        // Round up to the next tick, so that the timer never finishes too early.
+        let when_tick = {
+            let when_from_time_zero = (when - lock.time_zero).as_nanos();
+            let tick = TICK.as_nanos();
+            u64::try_from(when_from_time_zero.div_ceil(tick)).unwrap_or(u64::MAX)
+        };
 
         let timer_id = lock.timers.insert(Timer {
+            when_tick,
             is_finished: false,
             is_obsolete: false,
             waker: None,
         });
 
-        let when_from_time_zero = when - lock.time_zero;
-        lock.timers_queue.push(QueuedTimer {
-            when_from_time_zero,
-            timer_id,
-        });
+        // Because `elapsed_tick` is never past the current time, `when_tick` is strictly
+        // superior to it.
+        debug_assert!(when_tick > lock.wheel.elapsed_tick);
+        lock.wheel.insert(timer_id, when_tick);
 
-        // If the timer that has just been inserted is the one that ends the soonest, then
-        // actually start the callback that will process timers.
-        // Ideally we would instead cancel or update the deadline of the previous call to
-        // `start_timer_wrap`, but this isn't possible.
-        if lock.timers_queue.peek().unwrap().timer_id == timer_id {
-            super::start_timer_wrap(when - now, process_timers);
-        }
+        lock.start_host_timer_if_necessary(now);
 
         Delay {
             timer_id: Some(timer_id),
@@ -148,26 +177,58 @@ impl Drop for Delay {
 
 lazy_static::lazy_static! {
     static ref TIMERS: Mutex<Timers> = Mutex::new(Timers {
-        timers_queue: BinaryHeap::new(),
+        wheel: Wheel::new(),
         timers: slab::Slab::new(),
+        host_timers: BTreeSet::new(),
         time_zero: Instant::now(),
     });
 }
 
 struct Timers {
-    /// Same entries as `timer`, but ordered based on when they're finished. Items are only ever
-    /// removed from [`process_timers`], even if the corresponding [`Delay`] is destroyed.
-    timers_queue: BinaryHeap<QueuedTimer>,
+    /// Contains the same entries as `timers`, except for the ones that have finished. Items are
+    /// only ever removed from [`process_timers`], even if the corresponding [`Delay`] is
+    /// destroyed.
+    wheel: Wheel,
 
     /// List of all timers.
     timers: slab::Slab<Timer>,
 
+    /// Ticks at which a call to [`process_timers`] has been scheduled through the FFI and hasn't
+    /// happened yet.
+    host_timers: BTreeSet<u64>,
+
     /// Arbitrary point in time set at initialization and that never changes. All moments in time
-    /// are represented by `Duration`s relative to this value.
+    /// are represented by a number of [`TICK`]s since this value.
     time_zero: Instant,
 }
 
+impl Timers {
+    /// Schedules a call to [`process_timers`] at the next expiration of the wheel, unless a call
+    /// at or before this moment is already scheduled.
+    fn start_host_timer_if_necessary(&mut self, now: Instant) {
+        let Some((_, _, next_expiration)) = self.wheel.next_expiration() else {
+            return;
+        };
+
+        if matches!(self.host_timers.first(), Some(first) if *first <= next_expiration) {
+            return;
+        }
+
+        self.host_timers.insert(next_expiration);
+        let deadline = self.time_zero
+            + Duration::from_nanos(next_expiration.saturating_mul(TICK.as_nanos() as u64));
+        let delay = if deadline > now {
+            deadline - now
+        } else {
+            Duration::new(0, 0)
+        };
+        super::start_timer_wrap(delay, move || process_timers(next_expiration));
+    }
+}
+
 struct Timer {
+    /// Tick at which the timer finishes.
+    when_tick: u64,
     /// If `true`, then this timer has elapsed.
     is_finished: bool,
     /// If `true`, then the corresponding `Delay` has been destroyed or no longer points to this
@@ -177,87 +238,343 @@ struct Timer {
     waker: Option<Waker>,
 }
 
-struct QueuedTimer {
-    when_from_time_zero: Duration,
+/// Hierarchical timer wheel. See [the module-level documentation](self).
+struct Wheel {
+    /// Tick up to which all the timers of the wheel have been processed. All the timers in the
+    /// wheel finish strictly after this tick.
+    elapsed_tick: u64,
 
-    // Entry in `TIMERS::timers`. Guaranteed to always have `is_finished` equal to `false`.
-    timer_id: usize,
+    /// Levels of the wheel, from the most precise to the least precise.
+    levels: [Level; NUM_LEVELS],
 }
 
-impl PartialEq for QueuedTimer {
-    fn eq(&self, other: &Self) -> bool {
-        self.when_from_time_zero == other.when_from_time_zero
-    }
+struct Level {
+    /// Bit `N` is set if `slots[N]` is non-empty.
+    occupied: u64,
+
+    /// Indices within `Timers::timers` of the timers of each slot.
+    slots: [Vec<usize>; SLOTS_PER_LEVEL],
 }
 
-impl Eq for QueuedTimer {}
+impl Wheel {
+    fn new() -> Self {
+        Wheel {
+            elapsed_tick: 0,
+            levels: core::array::from_fn(|_| Level {
+                occupied: 0,
+                slots: core::array::from_fn(|_| Vec::new()),
+            }),
+        }
+    }
+
+    /// Inserts a timer in the wheel.
+    ///
+    /// `when_tick` must be strictly superior to [`Wheel::elapsed_tick`].
+    fn insert(&mut self, timer_id: usize, when_tick: u64) {
+        debug_assert!(when_tick > self.elapsed_tick);
+
+        // Timers that finish after the range covered by the wheel are inserted at the end of
+        // this range, and are moved again once this point is reached.
+        let max_tick = self
+            .elapsed_tick
+            .saturating_add((1 << (BITS_PER_LEVEL * NUM_LEVELS as u32)) - 1);
+        let when_tick = when_tick.min(max_tick);
+
+        // The level is determined by the most significant bit that differs between the
+        // current tick and the tick of the timer.
+        let level = {
+            let significant_bit = 63
+                - ((self.elapsed_tick ^ when_tick) | (SLOTS_PER_LEVEL as u64 - 1)).leading_zeros();
+            usize::try_from(significant_bit / BITS_PER_LEVEL)
+                .unwrap()
+                .min(NUM_LEVELS - 1)
+        };
 
-impl PartialOrd for QueuedTimer {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(Ord::cmp(self, other))
+        let slot = slot_of(when_tick, level);
+        self.levels[level].slots[slot].push(timer_id);
+        self.levels[level].occupied |= 1 << slot;
+    }
+
+    /// Returns the level and slot of the next slot of the wheel that expires, and the tick at
+    /// which it expires. Returns `None` if the wheel is empty.
+    ///
+    /// The returned tick is always strictly superior to [`Wheel::elapsed_tick`].
+    fn next_expiration(&self) -> Option<(usize, usize, u64)> {
+        // Timers in lower levels always expire before timers in higher levels.
+        for (level_num, level) in self.levels.iter().enumerate() {
+            if level.occupied == 0 {
+                continue;
+            }
+
+            let slot_range = 1u64 << (BITS_PER_LEVEL * level_num as u32);
+            let level_range = slot_range << BITS_PER_LEVEL;
+
+            // Find the first occupied slot after the slot of the current tick. The slot of the
+            // current tick is searched last, as it can only be occupied in the top level, by
+            // timers that are a full rotation of the top level away.
+            let first_slot = (slot_of(self.elapsed_tick, level_num) + 1) % SLOTS_PER_LEVEL;
+            let slot = (usize::try_from(
+                level
+                    .occupied
+                    .rotate_right(first_slot as u32)
+                    .trailing_zeros(),
+            )
+            .unwrap()
+                + first_slot)
+                % SLOTS_PER_LEVEL;
+
+            let level_start = self.elapsed_tick & !(level_range - 1);
+            let mut deadline = level_start + slot as u64 * slot_range;
+            if deadline <= self.elapsed_tick {
+                // The slots of the top level wrap around, and this slot is actually in the
+                // next rotation of the top level.
+                debug_assert_eq!(level_num, NUM_LEVELS - 1);
+                deadline = deadline.saturating_add(level_range);
+            }
+
+            return Some((level_num, slot, deadline));
+        }
+
+        None
     }
-}
 
-impl Ord for QueuedTimer {
-    fn cmp(&self, other: &Self) -> Ordering {
-        // Since the `BinaryHeap` puts the highest item first, we reverse the ordering so that
-        // the minimum `when_from_time_zero` is actually first.
-        self.when_from_time_zero
-            .cmp(&other.when_from_time_zero)
-            .reverse()
+    /// Removes from the wheel the timers of the next slot that expires at or before
+    /// `now_tick`, and updates [`Wheel::elapsed_tick`] to the moment of this expiration.
+    ///
+    /// Returns `None` if no slot expires at or before `now_tick`, in which case
+    /// [`Wheel::elapsed_tick`] is set to `now_tick`.
+    fn pop_expired_slot(&mut self, now_tick: u64) -> Option<Vec<usize>> {
+        match self.next_expiration() {
+            Some((level, slot, deadline)) if deadline <= now_tick => {
+                self.elapsed_tick = deadline;
+                self.levels[level].occupied &= !(1 << slot);
+                Some(core::mem::take(&mut self.levels[level].slots[slot]))
+            }
+            _ => {
+                self.elapsed_tick = self.elapsed_tick.max(now_tick);
+                None
+            }
+        }
     }
 }
 
+/// Returns the slot within the given level that contains the given tick.
+fn slot_of(tick: u64, level: usize) -> usize {
+    usize::try_from((tick >> (BITS_PER_LEVEL * level as u32)) & (SLOTS_PER_LEVEL as u64 - 1))
+        .unwrap()
+}
+
 /// Marks as ready all the timers in `TIMERS` that are finished.
-fn process_timers() {
+///
+/// `scheduled_tick` is the tick at which this call was scheduled.
+fn process_timers(scheduled_tick: u64) {
     // Because we're in a single-threaded environment, `try_lock()` should always succeed.
     let mut lock = TIMERS.try_lock().unwrap();
+    let lock = &mut *lock;
     let now = Instant::now();
 
-    // Note that this function can be called spuriously.
-    // For example, `process_timers` can be scheduled twice from two different timers, and the
-    // first call leads to both timers being finished, after which the second call will be
-    // spurious.
-
-    // Figure out the next time (relative to `time_zero`) we should call `process_timers`.
-    //
-    // This iterates through all the elements in `timers_queue` until a valid one is found.
-    let next_wakeup: Option<Duration> = loop {
-        let next_timer = match lock.timers_queue.peek() {
-            Some(t) => t,
-            None => break None,
-        };
+    // Note that this function can be called spuriously, as calls are scheduled at the
+    // beginning of slots that might only contain obsolete timers or timers that are moved to a
+    // lower level.
+    lock.host_timers.remove(&scheduled_tick);
 
-        // The `Delay` corresponding to the iterated timer has been destroyed. Removing it and
-        // `continue`.
-        if lock.timers[next_timer.timer_id].is_obsolete {
-            let next_timer_id = next_timer.timer_id;
-            lock.timers.remove(next_timer_id);
-            lock.timers_queue.pop().unwrap();
-            continue;
-        }
+    let now_tick = {
+        let now_from_time_zero = (now - lock.time_zero).as_nanos();
+        u64::try_from(now_from_time_zero / TICK.as_nanos()).unwrap_or(u64::MAX)
+    };
+
+    while let Some(expired) = lock.wheel.pop_expired_slot(now_tick) {
+        for timer_id in expired {
+            // The `Delay` corresponding to the iterated timer has been destroyed.
+            if lock.timers[timer_id].is_obsolete {
+                lock.timers.remove(timer_id);
+                continue;
+            }
 
-        // Iterated timer is ready. Wake up the `Waker`, remove from the queue, and `continue`.
-        if lock.time_zero + next_timer.when_from_time_zero <= now {
-            let next_timer_id = next_timer.timer_id;
-            debug_assert!(!lock.timers[next_timer_id].is_obsolete);
-            lock.timers[next_timer_id].is_finished = true;
-            if let Some(waker) = lock.timers[next_timer_id].waker.take() {
+            // Slots of the levels above 0 contain timers that finish later than the start of
+            // the slot. They are inserted again in a lower level.
+            let when_tick = lock.timers[timer_id].when_tick;
+            if when_tick > lock.wheel.elapsed_tick {
+                lock.wheel.insert(timer_id, when_tick);
+                continue;
+            }
+
+            // Iterated timer is ready.
+            lock.timers[timer_id].is_finished = true;
+            if let Some(waker) = lock.timers[timer_id].waker.take() {
                 waker.wake();
             }
-            let _ = lock.timers_queue.pop().unwrap();
-            continue;
         }
+    }
 
-        // Iterated timer is not ready.
-        break Some(next_timer.when_from_time_zero);
-    };
-
-    if let Some(next_wakeup) = next_wakeup {
-        super::start_timer_wrap(lock.time_zero + next_wakeup - now, process_timers);
+    if lock.wheel.next_expiration().is_some() {
+        lock.start_host_timer_if_necessary(now);
     } else {
         // Clean up memory a bit. Hopefully this doesn't impact performances too much.
-        lock.timers_queue.shrink_to_fit();
         lock.timers.shrink_to_fit();
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{slot_of, Wheel, BITS_PER_LEVEL, NUM_LEVELS};
+
+    /// Pops the expired slots of the wheel and moves the timers to lower levels the same way as
+    /// [`super::process_timers`] does. Returns the timers that have finished, in order, alongside
+    /// with the tick at which they have been found finished.
+    fn advance(wheel: &mut Wheel, when_ticks: &[u64], now_tick: u64) -> Vec<(usize, u64)> {
+        let mut finished = Vec::new();
+        while let Some(expired) = wheel.pop_expired_slot(now_tick) {
+            for timer_id in expired {
+                if when_ticks[timer_id] > wheel.elapsed_tick {
+                    wheel.insert(timer_id, when_ticks[timer_id]);
+                } else {
+                    finished.push((timer_id, wheel.elapsed_tick));
+                }
+            }
+        }
+        finished
+    }
+
+    /// Returns the level and slot where the given timer is found.
+    fn position(wheel: &Wheel, timer_id: usize) -> (usize, usize) {
+        for (level_num, level) in wheel.levels.iter().enumerate() {
+            for (slot_num, slot) in level.slots.iter().enumerate() {
+                if slot.contains(&timer_id) {
+                    assert_ne!(level.occupied & (1 << slot_num), 0);
+                    return (level_num, slot_num);
+                }
+            }
+        }
+        panic!()
+    }
+
+    #[test]
+    fn slots_of_ticks() {
+        assert_eq!(slot_of(0x41, 0), 1);
+        assert_eq!(slot_of(0x41, 1), 1);
+        assert_eq!(slot_of(3 << 12, 2), 3);
+        assert_eq!(slot_of(u64::MAX, NUM_LEVELS - 1), 63);
+    }
+
+    #[test]
+    fn inserted_in_precise_enough_level() {
+        let mut wheel = Wheel::new();
+        assert!(wheel.next_expiration().is_none());
+
+        wheel.insert(0, 5);
+        wheel.insert(1, 64);
+        wheel.insert(2, (1 << 12) + 3);
+        assert_eq!(position(&wheel, 0), (0, 5));
+        assert_eq!(position(&wheel, 1), (1, 1));
+        assert_eq!(position(&wheel, 2), (2, 1));
+        assert_eq!(wheel.next_expiration(), Some((0, 5, 5)));
+    }
+
+    #[test]
+    fn cascades_across_levels() {
+        let when_tick = (1 << 12) + (2 << 6) + 7;
+        let mut wheel = Wheel::new();
+        wheel.insert(0, when_tick);
+        assert_eq!(position(&wheel, 0), (2, 1));
+        assert_eq!(wheel.next_expiration(), Some((2, 1, 1 << 12)));
+
+        // Reaching the start of the slot of level 2 moves the timer to level 1.
+        assert!(advance(&mut wheel, &[when_tick], 1 << 12).is_empty());
+        assert_eq!(position(&wheel, 0), (1, 2));
+        assert_eq!(wheel.next_expiration(), Some((1, 2, (1 << 12) + (2 << 6))));
+
+        // Then to level 0.
+        assert!(advance(&mut wheel, &[when_tick], (1 << 12) + (2 << 6)).is_empty());
+        assert_eq!(position(&wheel, 0), (0, 7));
+        assert_eq!(wheel.next_expiration(), Some((0, 7, when_tick)));
+
+        assert!(advance(&mut wheel, &[when_tick], when_tick - 1).is_empty());
+        assert_eq!(
+            advance(&mut wheel, &[when_tick], when_tick),
+            vec![(0, when_tick)]
+        );
+        assert!(wheel.next_expiration().is_none());
+    }
+
+    #[test]
+    fn expirations_in_order() {
+        let when_ticks = [300, 5, 70, 5000, 64, 1, 4096, 299, 1 << 20, 6];
+        let mut wheel = Wheel::new();
+        for (timer_id, when_tick) in when_ticks.iter().enumerate() {
+            wheel.insert(timer_id, *when_tick);
+        }
+
+        let finished = advance(&mut wheel, &when_ticks, u64::MAX);
+        let mut expected = when_ticks
+            .iter()
+            .enumerate()
+            .map(|(timer_id, when_tick)| (timer_id, *when_tick))
+            .collect::<Vec<_>>();
+        expected.sort_by_key(|(_, when_tick)| *when_tick);
+        assert_eq!(finished, expected);
+    }
+
+    #[test]
+    fn expirations_while_advancing_step_by_step() {
+        let when_ticks = [10, 10, 100, 4100, 70];
+        let mut wheel = Wheel::new();
+        for (timer_id, when_tick) in when_ticks.iter().enumerate() {
+            wheel.insert(timer_id, *when_tick);
+        }
+
+        let mut finished = Vec::new();
+        for now_tick in (0..5000).step_by(3) {
+            for (timer_id, tick) in advance(&mut wheel, &when_ticks, now_tick) {
+                // Timers are never found finished before their tick.
+                assert!(tick >= when_ticks[timer_id]);
+                assert!(now_tick >= when_ticks[timer_id]);
+                finished.push(timer_id);
+            }
+        }
+        assert_eq!(finished, vec![0, 1, 4, 2, 3]);
+    }
+
+    #[test]
+    fn timer_beyond_top_level() {
+        let wheel_range = 1u64 << (BITS_PER_LEVEL * NUM_LEVELS as u32);
+
+        for when_tick in [wheel_range + 10, 3 * wheel_range + 5] {
+            let mut wheel = Wheel::new();
+            wheel.insert(0, when_tick);
+            let (level, _) = position(&wheel, 0);
+            assert_eq!(level, NUM_LEVELS - 1);
+
+            assert!(advance(&mut wheel, &[when_tick], wheel_range - 1).is_empty());
+            assert!(advance(&mut wheel, &[when_tick], when_tick - 1).is_empty());
+            assert_eq!(
+                advance(&mut wheel, &[when_tick], when_tick),
+                vec![(0, when_tick)]
+            );
+            assert!(wheel.next_expiration().is_none());
+        }
+    }
+
+    #[test]
+    fn top_level_wraps_around() {
+        let wheel_range = 1u64 << (BITS_PER_LEVEL * NUM_LEVELS as u32);
+        let top_slot_range = wheel_range >> BITS_PER_LEVEL;
+
+        // Move the current tick to the middle of the last slot of the top level, then insert a
+        // timer that falls in the first slot of the next rotation.
+        let mut wheel = Wheel::new();
+        let start = 63 * top_slot_range + 5;
+        assert!(advance(&mut wheel, &[], start).is_empty());
+        let when_tick = wheel_range + top_slot_range + 1;
+        wheel.insert(0, when_tick);
+        assert_eq!(position(&wheel, 0), (NUM_LEVELS - 1, 1));
+
+        let (_, _, deadline) = wheel.next_expiration().unwrap();
+        assert!(deadline > start);
+        assert!(deadline <= when_tick);
+        assert_eq!(
+            advance(&mut wheel, &[when_tick], u64::MAX),
+            vec![(0, when_tick)]
+        );
+    }
+}